@@ -21,7 +21,11 @@ use http::{Request, StatusCode};
 use mas_data_model::{Client, JwksOrJwksUri};
 use mas_http::RequestBuilderExt;
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
-use mas_jose::{jwk::PublicJsonWebKeySet, jwt::Jwt};
+use mas_jose::{
+    claims::{self, TimeOptions},
+    jwk::PublicJsonWebKeySet,
+    jwt::Jwt,
+};
 use mas_keystore::Encrypter;
 use mas_storage::{oauth2::OAuth2ClientRepository, RepositoryAccess};
 use oauth2_types::errors::{ClientError, ClientErrorCode};
@@ -95,6 +99,9 @@ impl Credentials {
 
     /// Verify credentials presented by the client for authentication
     ///
+    /// The time-based claims of JWT assertions are checked against the given
+    /// time options, which carry the allowed clock skew.
+    ///
     /// # Errors
     ///
     /// Returns an error if the credentials are invalid.
@@ -105,6 +112,7 @@ impl Credentials {
         encrypter: &Encrypter,
         method: &OAuthClientAuthenticationMethod,
         client: &Client,
+        time_options: &TimeOptions,
     ) -> Result<(), CredentialsVerificationError> {
         match (self, method) {
             (Credentials::None { .. }, OAuthClientAuthenticationMethod::None) => {}
@@ -173,10 +181,36 @@ impl Credentials {
                 return Err(CredentialsVerificationError::AuthenticationMethodMismatch);
             }
         };
+
+        if let Credentials::ClientAssertionJwtBearer { jwt, .. } = self {
+            verify_assertion_time(jwt, time_options)?;
+        }
+
         Ok(())
     }
 }
 
+/// Check that a JWT assertion has not expired and is already valid, within the
+/// allowed clock skew
+fn verify_assertion_time(
+    jwt: &Jwt<'_, HashMap<String, Value>>,
+    time_options: &TimeOptions,
+) -> Result<(), CredentialsVerificationError> {
+    let mut claims = jwt.payload().clone();
+
+    claims::EXP
+        .extract_required_with_options(&mut claims, time_options)
+        .map_err(|_| CredentialsVerificationError::InvalidAssertionTime)?;
+    claims::NBF
+        .extract_optional_with_options(&mut claims, time_options)
+        .map_err(|_| CredentialsVerificationError::InvalidAssertionTime)?;
+    claims::IAT
+        .extract_optional_with_options(&mut claims, time_options)
+        .map_err(|_| CredentialsVerificationError::InvalidAssertionTime)?;
+
+    Ok(())
+}
+
 async fn fetch_jwks(
     http_client: &reqwest::Client,
     jwks: &JwksOrJwksUri,
@@ -214,6 +248,9 @@ pub enum CredentialsVerificationError {
     #[error("invalid assertion signature")]
     InvalidAssertionSignature,
 
+    #[error("assertion expired or not valid yet")]
+    InvalidAssertionTime,
+
     #[error("failed to fetch jwks")]
     JwksFetchFailed,
 }
@@ -650,5 +687,30 @@ mod tests {
         assert_eq!(client_id, "client-id");
         jwt.verify_with_shared_secret(b"client-secret".to_vec())
             .unwrap();
+
+        // The assertion was issued at 1516239022 and expires 5 minutes later
+        let issued_at = chrono::DateTime::from_timestamp(1_516_239_022, 0).unwrap();
+        let time_options = TimeOptions::new(issued_at + chrono::Duration::minutes(1));
+        verify_assertion_time(&jwt, &time_options).unwrap();
+
+        // Past its expiration, but within the allowed clock skew
+        let time_options = TimeOptions::new(issued_at + chrono::Duration::minutes(8));
+        verify_assertion_time(&jwt, &time_options).unwrap();
+
+        // Too late, unless the allowed clock skew is larger
+        let time_options = TimeOptions::new(issued_at + chrono::Duration::minutes(15));
+        assert!(matches!(
+            verify_assertion_time(&jwt, &time_options),
+            Err(CredentialsVerificationError::InvalidAssertionTime)
+        ));
+        let time_options = time_options.leeway(chrono::Duration::minutes(15));
+        verify_assertion_time(&jwt, &time_options).unwrap();
+
+        // Too early
+        let time_options = TimeOptions::new(issued_at - chrono::Duration::minutes(10));
+        assert!(matches!(
+            verify_assertion_time(&jwt, &time_options),
+            Err(CredentialsVerificationError::InvalidAssertionTime)
+        ));
     }
 }
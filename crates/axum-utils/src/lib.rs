@@ -14,6 +14,7 @@ pub mod error_wrapper;
 pub mod fancy_error;
 pub mod jwt;
 pub mod language_detection;
+pub mod security_headers;
 pub mod sentry;
 pub mod session;
 pub mod user_authorization;
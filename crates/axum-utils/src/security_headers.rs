@@ -0,0 +1,489 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Layer setting security-related response headers, like the
+//! `Content-Security-Policy`, with support for per-response nonces
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use data_encoding::BASE64;
+use http::{
+    header::{
+        CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY, REFERRER_POLICY,
+        STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    request::Parts,
+    HeaderMap, HeaderValue, Request, Response,
+};
+use pin_project_lite::pin_project;
+use rand::RngCore;
+use tower::{Layer, Service};
+
+/// The placeholder which gets replaced by the per-response nonce in
+/// Content-Security-Policy directive sources
+pub const NONCE_PLACEHOLDER: &str = "'nonce'";
+
+/// A per-response nonce, to be used in the `nonce` attribute of inline
+/// `<script>` and `<style>` tags
+///
+/// It is generated by the [`SecurityHeadersLayer`] and stored in the request
+/// extensions. If the layer is not installed, a fresh nonce is generated, which
+/// is harmless since no CSP header will be set on the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(Arc<str>);
+
+impl CspNonce {
+    /// Generate a new random nonce
+    #[must_use]
+    pub fn generate(mut rng: impl RngCore) -> Self {
+        let mut bytes = [0u8; 16];
+        rng.fill_bytes(&mut bytes);
+        Self(BASE64.encode(&bytes).into())
+    }
+
+    /// Get the nonce value, as used in the `nonce` HTML attribute
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for CspNonce {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CspNonce
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(nonce) = parts.extensions.get::<CspNonce>() {
+            return Ok(nonce.clone());
+        }
+
+        // This rng is only used to generate a nonce which won't be enforced
+        #[allow(clippy::disallowed_methods)]
+        let nonce = CspNonce::generate(rand::thread_rng());
+        Ok(nonce)
+    }
+}
+
+/// A `Content-Security-Policy`, as a list of directives
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Create a new, empty policy
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strict policy for HTML pages: only allow resources from the same origin,
+    /// and inline scripts which carry the per-response nonce
+    #[must_use]
+    pub fn html() -> Self {
+        Self::new()
+            .with_directive("default-src", ["'self'"])
+            .with_directive("script-src", ["'self'", NONCE_PLACEHOLDER])
+            .with_directive("style-src", ["'self'", "'unsafe-inline'"])
+            .with_directive("img-src", ["'self'", "data:", "https:"])
+            .with_directive("connect-src", ["'self'"])
+            .with_directive("object-src", ["'none'"])
+            .with_directive("base-uri", ["'none'"])
+            .with_directive("form-action", ["*"])
+            .with_directive("frame-ancestors", ["'none'"])
+    }
+
+    /// Policy for API responses, which should never load any resource
+    #[must_use]
+    pub fn api() -> Self {
+        Self::new()
+            .with_directive("default-src", ["'none'"])
+            .with_directive("frame-ancestors", ["'none'"])
+    }
+
+    /// Set a directive, replacing any previous value
+    #[must_use]
+    pub fn with_directive<I, T>(mut self, name: &str, sources: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let sources = sources.into_iter().map(Into::into).collect();
+        if let Some((_, existing)) = self.directives.iter_mut().find(|(n, _)| n == name) {
+            *existing = sources;
+        } else {
+            self.directives.push((name.to_owned(), sources));
+        }
+        self
+    }
+
+    /// Add sources to an existing directive, or create it if it doesn't exist
+    #[must_use]
+    pub fn with_extra_sources<I, T>(mut self, name: &str, sources: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let sources = sources.into_iter().map(Into::into);
+        if let Some((_, existing)) = self.directives.iter_mut().find(|(n, _)| n == name) {
+            for source in sources {
+                if !existing.contains(&source) {
+                    existing.push(source);
+                }
+            }
+        } else {
+            self.directives.push((name.to_owned(), sources.collect()));
+        }
+        self
+    }
+
+    /// Only report violations instead of enforcing the policy
+    #[must_use]
+    pub fn report_only(mut self, report_only: bool) -> Self {
+        self.report_only = report_only;
+        self
+    }
+
+    /// Whether the policy references the per-response nonce
+    fn uses_nonce(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|(_, sources)| sources.iter().any(|s| s == NONCE_PLACEHOLDER))
+    }
+
+    /// Get the sources of the `frame-ancestors` directive, if any
+    fn frame_ancestors(&self) -> Option<&[String]> {
+        self.directives
+            .iter()
+            .find(|(name, _)| name == "frame-ancestors")
+            .map(|(_, sources)| &sources[..])
+    }
+
+    /// Render the policy to a header value, replacing the nonce placeholder
+    fn render(&self, nonce: Option<&CspNonce>) -> Option<HeaderValue> {
+        let mut out = String::new();
+        for (name, sources) in &self.directives {
+            if !out.is_empty() {
+                out.push_str("; ");
+            }
+            out.push_str(name);
+            for source in sources {
+                match (source.as_str(), nonce) {
+                    (NONCE_PLACEHOLDER, Some(nonce)) => {
+                        out.push_str(" 'nonce-");
+                        out.push_str(nonce.as_str());
+                        out.push('\'');
+                    }
+                    // Drop the placeholder if we don't have a nonce
+                    (NONCE_PLACEHOLDER, None) => {}
+                    (source, _) => {
+                        out.push(' ');
+                        out.push_str(source);
+                    }
+                }
+            }
+        }
+
+        HeaderValue::from_str(&out).ok()
+    }
+}
+
+/// Settings for the `Strict-Transport-Security` header
+#[derive(Debug, Clone, Copy)]
+pub struct StrictTransportSecurity {
+    max_age: Duration,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl StrictTransportSecurity {
+    /// Create a new HSTS setting with the given `max-age`
+    #[must_use]
+    pub const fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// Set the `includeSubDomains` directive
+    #[must_use]
+    pub const fn include_subdomains(mut self, include_subdomains: bool) -> Self {
+        self.include_subdomains = include_subdomains;
+        self
+    }
+
+    /// Set the `preload` directive
+    #[must_use]
+    pub const fn preload(mut self, preload: bool) -> Self {
+        self.preload = preload;
+        self
+    }
+
+    fn to_header_value(self) -> HeaderValue {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        HeaderValue::try_from(value).expect("valid header value")
+    }
+}
+
+/// The set of security headers to apply on a group of routes
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    content_security_policy: Option<ContentSecurityPolicy>,
+    strict_transport_security: Option<HeaderValue>,
+    referrer_policy: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// Create an empty set of security headers
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `Content-Security-Policy`
+    #[must_use]
+    pub fn with_content_security_policy(mut self, policy: ContentSecurityPolicy) -> Self {
+        self.content_security_policy = Some(policy);
+        self
+    }
+
+    /// Set the `Strict-Transport-Security` header
+    #[must_use]
+    pub fn with_strict_transport_security(mut self, hsts: StrictTransportSecurity) -> Self {
+        self.strict_transport_security = Some(hsts.to_header_value());
+        self
+    }
+
+    /// Set the `Referrer-Policy` header
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy is not a valid header value
+    #[must_use]
+    pub fn with_referrer_policy(mut self, policy: &str) -> Self {
+        self.referrer_policy = Some(HeaderValue::from_str(policy).expect("valid referrer policy"));
+        self
+    }
+
+    /// Apply the headers on a response, without overriding headers already
+    /// set by the handler
+    fn apply(&self, headers: &mut HeaderMap, nonce: Option<&CspNonce>) {
+        headers
+            .entry(X_CONTENT_TYPE_OPTIONS)
+            .or_insert(HeaderValue::from_static("nosniff"));
+
+        if let Some(value) = &self.referrer_policy {
+            headers.entry(REFERRER_POLICY).or_insert(value.clone());
+        }
+
+        if let Some(value) = &self.strict_transport_security {
+            headers
+                .entry(STRICT_TRANSPORT_SECURITY)
+                .or_insert(value.clone());
+        }
+
+        if let Some(policy) = &self.content_security_policy {
+            // Legacy browsers don't understand `frame-ancestors`
+            if policy
+                .frame_ancestors()
+                .is_some_and(|sources| sources == ["'none'"])
+            {
+                headers
+                    .entry(X_FRAME_OPTIONS)
+                    .or_insert(HeaderValue::from_static("DENY"));
+            }
+
+            let name = if policy.report_only {
+                CONTENT_SECURITY_POLICY_REPORT_ONLY
+            } else {
+                CONTENT_SECURITY_POLICY
+            };
+
+            if !headers.contains_key(&name) {
+                if let Some(value) = policy.render(nonce) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+/// A [`Layer`] which sets security headers on responses, and generates a
+/// [`CspNonce`] for each request if the policy needs one
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersLayer {
+    headers: Arc<SecurityHeaders>,
+}
+
+impl SecurityHeadersLayer {
+    /// Create a new layer applying the given headers
+    #[must_use]
+    pub fn new(headers: SecurityHeaders) -> Self {
+        Self {
+            headers: Arc::new(headers),
+        }
+    }
+}
+
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecurityHeadersService {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+/// Service created by the [`SecurityHeadersLayer`]
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersService<S> {
+    inner: S,
+    headers: Arc<SecurityHeaders>,
+}
+
+pin_project! {
+    /// The future returned by [`SecurityHeadersService`]
+    pub struct SecurityHeadersFuture<F> {
+        #[pin]
+        inner: F,
+        headers: Arc<SecurityHeaders>,
+        nonce: Option<CspNonce>,
+    }
+}
+
+impl<F, B, E> Future for SecurityHeadersFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = std::task::ready!(this.inner.poll(cx))?;
+        this.headers
+            .apply(response.headers_mut(), this.nonce.as_ref());
+        Poll::Ready(Ok(response))
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for SecurityHeadersService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = SecurityHeadersFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let uses_nonce = self
+            .headers
+            .content_security_policy
+            .as_ref()
+            .is_some_and(ContentSecurityPolicy::uses_nonce);
+
+        let nonce = if uses_nonce {
+            #[allow(clippy::disallowed_methods)]
+            let nonce = CspNonce::generate(rand::thread_rng());
+            req.extensions_mut().insert(nonce.clone());
+            Some(nonce)
+        } else {
+            None
+        };
+
+        SecurityHeadersFuture {
+            inner: self.inner.call(req),
+            headers: self.headers.clone(),
+            nonce,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_render_policy() {
+        let rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let nonce = CspNonce::generate(rng);
+
+        let policy = ContentSecurityPolicy::html()
+            .with_extra_sources("script-src", ["https://www.google.com/recaptcha/"]);
+
+        let value = policy.render(Some(&nonce)).unwrap();
+        let value = value.to_str().unwrap();
+        assert!(value.starts_with("default-src 'self'; script-src 'self' 'nonce-"));
+        assert!(value.contains(&format!(
+            "'nonce-{nonce}' https://www.google.com/recaptcha/;"
+        )));
+        assert!(value.ends_with("frame-ancestors 'none'"));
+
+        // Without a nonce, the placeholder is dropped
+        let value = policy.render(None).unwrap();
+        assert!(!value.to_str().unwrap().contains("nonce"));
+    }
+
+    #[test]
+    fn test_apply_headers() {
+        let headers = SecurityHeaders::new()
+            .with_content_security_policy(ContentSecurityPolicy::api())
+            .with_strict_transport_security(
+                StrictTransportSecurity::new(Duration::from_secs(3600)).include_subdomains(true),
+            )
+            .with_referrer_policy("no-referrer");
+
+        let mut map = HeaderMap::new();
+        // Headers set by the handler are kept
+        map.insert(REFERRER_POLICY, HeaderValue::from_static("origin"));
+        headers.apply(&mut map, None);
+
+        assert_eq!(map[REFERRER_POLICY], "origin");
+        assert_eq!(
+            map[STRICT_TRANSPORT_SECURITY],
+            "max-age=3600; includeSubDomains"
+        );
+        assert_eq!(
+            map[CONTENT_SECURITY_POLICY],
+            "default-src 'none'; frame-ancestors 'none'"
+        );
+        assert_eq!(map[X_FRAME_OPTIONS], "DENY");
+        assert_eq!(map[X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
@@ -13,18 +13,20 @@ use axum::{
 use ipnetwork::IpNetwork;
 use mas_data_model::SiteConfig;
 use mas_handlers::{
-    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, CookieManager, ErrorWrapper,
-    GraphQLSchema, Limiter, MetadataCache, RequesterFingerprint,
+    passwords::PasswordManager, ActivityTracker, AttestationChecker, BoundActivityTracker,
+    ClientCertificate, CookieManager, DeviceProofReplayCache, ErrorWrapper, GraphQLSchema,
+    HomeserverDiscovery, Limiter, MetadataCache, NetworkPolicy, RequestOrigin,
+    RequesterFingerprint, RiskAssessor,
 };
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, Keystore};
-use mas_matrix::BoxHomeserverConnection;
-use mas_matrix_synapse::SynapseConnection;
+use mas_matrix::{BoxHomeserverConnection, CircuitBreaker, ResilientHomeserverConnection};
 use mas_policy::{Policy, PolicyFactory};
 use mas_router::UrlBuilder;
-use mas_storage::{BoxClock, BoxRepository, BoxRng, SystemClock};
-use mas_storage_pg::PgRepository;
+use mas_storage::{BoxClock, BoxRepository, BoxRng, Clock};
+use mas_storage_pg::{PgRepository, TokenHasher};
 use mas_templates::Templates;
+use mas_tower::AccessLogContext;
 use opentelemetry::{
     metrics::{Histogram, MetricsError},
     KeyValue,
@@ -32,6 +34,8 @@ use opentelemetry::{
 use rand::SeedableRng;
 use sqlx::PgPool;
 
+use crate::util::HomeserverBackend;
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
@@ -39,18 +43,27 @@ pub struct AppState {
     pub key_store: Keystore,
     pub cookie_manager: CookieManager,
     pub encrypter: Encrypter,
+    pub token_hasher: TokenHasher,
     pub url_builder: UrlBuilder,
-    pub homeserver_connection: SynapseConnection,
+    pub homeserver_connection: ResilientHomeserverConnection<HomeserverBackend>,
     pub policy_factory: Arc<PolicyFactory>,
     pub graphql_schema: GraphQLSchema,
     pub http_client: reqwest::Client,
+    pub homeserver_discovery: HomeserverDiscovery,
     pub password_manager: PasswordManager,
     pub metadata_cache: MetadataCache,
     pub site_config: SiteConfig,
     pub activity_tracker: ActivityTracker,
     pub trusted_proxies: Vec<IpNetwork>,
     pub limiter: Limiter,
+    pub risk_assessor: RiskAssessor,
+    pub attestation_checker: AttestationChecker,
+    pub device_proof_replay_cache: DeviceProofReplayCache,
+    pub client_certificate_login: bool,
+    pub network_policy: NetworkPolicy,
+    pub email_webhook_secret: Option<String>,
     pub conn_acquisition_histogram: Option<Histogram<u64>>,
+    pub clock: Arc<dyn Clock + Send + Sync>,
 }
 
 impl AppState {
@@ -80,20 +93,35 @@ impl AppState {
             .with_unit("{connection}")
             .init();
 
+        let utilization = meter
+            .f64_observable_gauge("db.client.connections.utilization")
+            .with_description("The fraction of the maximum number of connections currently used. Requests start waiting for a connection when this reaches 1.")
+            .with_unit("1")
+            .init();
+
         // Observe the number of active and idle connections in the pool
-        meter.register_callback(&[usage.as_any(), max.as_any()], move |observer| {
-            let idle = u32::try_from(pool.num_idle()).unwrap_or(u32::MAX);
-            let used = pool.size() - idle;
-            let max_conn = pool.options().get_max_connections();
-            observer.observe_i64(&usage, i64::from(idle), &[KeyValue::new("state", "idle")]);
-            observer.observe_i64(&usage, i64::from(used), &[KeyValue::new("state", "used")]);
-            observer.observe_i64(&max, i64::from(max_conn), &[]);
-        })?;
-
-        // Track the connection acquisition time
+        meter.register_callback(
+            &[usage.as_any(), max.as_any(), utilization.as_any()],
+            move |observer| {
+                let idle = u32::try_from(pool.num_idle()).unwrap_or(u32::MAX);
+                let used = pool.size().saturating_sub(idle);
+                let max_conn = pool.options().get_max_connections();
+                observer.observe_i64(&usage, i64::from(idle), &[KeyValue::new("state", "idle")]);
+                observer.observe_i64(&usage, i64::from(used), &[KeyValue::new("state", "used")]);
+                observer.observe_i64(&max, i64::from(max_conn), &[]);
+                if max_conn > 0 {
+                    observer.observe_f64(&utilization, f64::from(used) / f64::from(max_conn), &[]);
+                }
+            },
+        )?;
+
+        // Track the connection acquisition time, which grows when the pool is
+        // saturated
         let histogram = meter
             .u64_histogram("db.client.connections.create_time")
-            .with_description("The time it took to create a new connection.")
+            .with_description(
+                "The time it took to get a connection from the pool and start a transaction.",
+            )
             .with_unit("ms")
             .init();
         self.conn_acquisition_histogram = Some(histogram);
@@ -175,6 +203,12 @@ impl FromRef<AppState> for reqwest::Client {
     }
 }
 
+impl FromRef<AppState> for HomeserverDiscovery {
+    fn from_ref(input: &AppState) -> Self {
+        input.homeserver_discovery.clone()
+    }
+}
+
 impl FromRef<AppState> for PasswordManager {
     fn from_ref(input: &AppState) -> Self {
         input.password_manager.clone()
@@ -205,22 +239,51 @@ impl FromRef<AppState> for Limiter {
     }
 }
 
+impl FromRef<AppState> for RiskAssessor {
+    fn from_ref(input: &AppState) -> Self {
+        input.risk_assessor.clone()
+    }
+}
+
+impl FromRef<AppState> for DeviceProofReplayCache {
+    fn from_ref(input: &AppState) -> Self {
+        input.device_proof_replay_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for AttestationChecker {
+    fn from_ref(input: &AppState) -> Self {
+        input.attestation_checker.clone()
+    }
+}
+
+impl FromRef<AppState> for NetworkPolicy {
+    fn from_ref(input: &AppState) -> Self {
+        input.network_policy.clone()
+    }
+}
+
 impl FromRef<AppState> for BoxHomeserverConnection {
     fn from_ref(input: &AppState) -> Self {
         Box::new(input.homeserver_connection.clone())
     }
 }
 
+impl FromRef<AppState> for CircuitBreaker {
+    fn from_ref(input: &AppState) -> Self {
+        input.homeserver_connection.circuit_breaker().clone()
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for BoxClock {
     type Rejection = Infallible;
 
     async fn from_request_parts(
         _parts: &mut axum::http::request::Parts,
-        _state: &AppState,
+        state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let clock = SystemClock::default();
-        Ok(Box::new(clock))
+        Ok(Box::new(state.clock.clone()))
     }
 }
 
@@ -324,7 +387,12 @@ impl FromRequestParts<AppState> for BoundActivityTracker {
         // TODO: we may infer the IP twice, for the activity tracker and the limiter
         let ip = infer_client_ip(parts, &state.trusted_proxies);
         tracing::debug!(ip = ?ip, "Inferred client IP address");
-        Ok(state.activity_tracker.clone().bind(ip))
+        let access_log = parts.extensions.get::<AccessLogContext>().cloned();
+        Ok(state
+            .activity_tracker
+            .clone()
+            .bind(ip)
+            .with_access_log(access_log))
     }
 }
 
@@ -350,6 +418,40 @@ impl FromRequestParts<AppState> for RequesterFingerprint {
     }
 }
 
+#[async_trait]
+impl FromRequestParts<AppState> for RequestOrigin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ip = infer_client_ip(parts, &state.trusted_proxies);
+        Ok(state.network_policy.origin(ip, &parts.headers))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientCertificate {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // The certificate chain was already verified during the TLS handshake
+        let certificate = parts
+            .extensions
+            .get::<mas_listener::ConnectionInfo>()
+            .and_then(mas_listener::ConnectionInfo::get_tls_ref)
+            .and_then(|tls| tls.peer_certificates.as_ref())
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| certificate.to_vec());
+
+        Ok(ClientCertificate::new(certificate).on_listener(state.client_certificate_login))
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for BoxRepository {
     type Rejection = ErrorWrapper<mas_storage_pg::DatabaseError>;
@@ -359,7 +461,9 @@ impl FromRequestParts<AppState> for BoxRepository {
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         let start = Instant::now();
-        let repo = PgRepository::from_pool(&state.pool).await?;
+        let repo = PgRepository::from_pool(&state.pool)
+            .await?
+            .with_token_hasher(state.token_hasher.clone());
 
         // Measure the time it took to create the connection
         let duration = start.elapsed();
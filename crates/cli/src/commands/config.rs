@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::process::ExitCode;
+use std::{fmt::Write as _, process::ExitCode};
 
 use anyhow::Context;
 use camino::Utf8PathBuf;
@@ -15,7 +15,7 @@ use mas_storage::SystemClock;
 use mas_storage_pg::MIGRATOR;
 use rand::SeedableRng;
 use tokio::io::AsyncWriteExt;
-use tracing::{info, info_span, Instrument};
+use tracing::{info, info_span, warn, Instrument};
 
 use crate::util::database_connection_from_config;
 
@@ -36,9 +36,26 @@ enum Subcommand {
         output: Option<Utf8PathBuf>,
     },
 
+    /// Show the effective configuration, with the defaults applied and the
+    /// secrets redacted, and where each value comes from
+    Show {
+        /// Print the values as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
     /// Check a config file
     Check,
 
+    /// Output the JSON Schema of the configuration file
+    Schema {
+        /// The path to the schema file to write
+        ///
+        /// If not specified, the schema will be written to stdout
+        #[clap(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+
     /// Generate a new config file
     Generate {
         /// The path to the config file to generate
@@ -62,6 +79,7 @@ enum Subcommand {
 }
 
 impl Options {
+    #[allow(clippy::too_many_lines)]
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
         use Subcommand as SC;
         match self.subcommand {
@@ -81,13 +99,69 @@ impl Options {
                 }
             }
 
+            SC::Show { json } => {
+                let _span = info_span!("cli.config.show").entered();
+
+                let values = mas_config::effective_config(figment)?;
+
+                let mut output = String::new();
+                if json {
+                    let values: Vec<_> = values
+                        .into_iter()
+                        .map(|value| {
+                            serde_json::json!({
+                                "path": value.path,
+                                "value": value.value,
+                                "source": value.source.to_string(),
+                            })
+                        })
+                        .collect();
+                    output = serde_json::to_string_pretty(&values)?;
+                    output.push('\n');
+                } else {
+                    for value in values {
+                        let _ = writeln!(
+                            output,
+                            "{}: {} # {}",
+                            value.path, value.value, value.source
+                        );
+                    }
+                }
+
+                tokio::io::stdout().write_all(output.as_bytes()).await?;
+            }
+
             SC::Check => {
                 let _span = info_span!("cli.config.check").entered();
 
                 let _config = RootConfig::extract(figment)?;
+
+                // Unknown keys are only rejected in strict mode, as they could be
+                // options from a more recent version
+                let unknown_keys = mas_config::unknown_keys(figment)?;
+                for unknown_key in &unknown_keys {
+                    warn!("{unknown_key}");
+                }
+
                 info!("Configuration file looks good");
             }
 
+            SC::Schema { output } => {
+                let _span = info_span!("cli.config.schema").entered();
+
+                let schema = mas_config::json_schema();
+                let schema = serde_json::to_string_pretty(&schema)?;
+
+                if let Some(output) = output {
+                    info!("Writing configuration schema to {output:?}");
+                    let mut file = tokio::fs::File::create(output).await?;
+                    file.write_all(schema.as_bytes()).await?;
+                } else {
+                    info!("Writing configuration schema to standard output");
+                    tokio::io::stdout().write_all(schema.as_bytes()).await?;
+                }
+            }
+
             SC::Generate { output } => {
                 let _span = info_span!("cli.config.generate").entered();
 
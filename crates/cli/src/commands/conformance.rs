@@ -0,0 +1,196 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Support for running the OpenID Foundation conformance suite against the
+//! service.
+//!
+//! This is only available when the `conformance` feature is enabled, and must
+//! never be used in production.
+
+use std::{num::NonZeroU32, process::ExitCode};
+
+use anyhow::Context;
+use clap::Parser;
+use figment::Figment;
+use mas_config::{
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, HttpConfig, PasswordsConfig,
+    RateLimitingConfig, SecretsConfig,
+};
+use mas_data_model::Ulid;
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    oauth2::OAuth2ClientRepository,
+    user::{UserEmailRepository, UserPasswordRepository, UserRepository},
+    RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::PgRepository;
+use rand::SeedableRng;
+use serde_json::json;
+use sqlx::Acquire;
+use tracing::{info, info_span};
+use url::Url;
+
+use crate::util::{database_connection_from_config, password_manager_from_config};
+
+/// The IDs of the clients seeded for the conformance suite, which needs two
+/// of them
+const CLIENT_IDS: [Ulid; 2] = [Ulid::from_parts(0, 1), Ulid::from_parts(0, 2)];
+
+#[derive(Parser, Debug)]
+pub(super) struct Options {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Parser, Debug)]
+enum Subcommand {
+    /// Create the clients and the user used by the conformance suite, and
+    /// print the matching configuration for the suite
+    ///
+    /// This can be run multiple times, and always seeds the same clients and
+    /// user.
+    Seed {
+        /// The redirect URI of the conformance suite, to allow on the clients
+        #[arg(
+            long,
+            default_value = "https://localhost.emobix.co.uk:8443/test/a/mas/callback"
+        )]
+        redirect_uri: Url,
+
+        /// The secret of the seeded clients
+        #[arg(long, default_value = "conformance-client-secret")]
+        client_secret: String,
+
+        /// The username of the seeded user
+        #[arg(long, default_value = "conformance")]
+        username: String,
+
+        /// The password of the seeded user
+        #[arg(long, default_value = "conformance-password")]
+        password: String,
+    },
+}
+
+impl Options {
+    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+        use Subcommand as SC;
+        let clock = SystemClock::default();
+        // XXX: we should disallow SeedableRng::from_entropy
+        let mut rng = rand_chacha::ChaChaRng::from_entropy();
+
+        match self.subcommand {
+            SC::Seed {
+                redirect_uri,
+                client_secret,
+                username,
+                password,
+            } => {
+                let _span = info_span!("cli.conformance.seed").entered();
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let passwords_config = PasswordsConfig::extract_or_default(figment)?;
+                let http_config = HttpConfig::extract_or_default(figment)?;
+                let secrets_config = SecretsConfig::extract(figment)?;
+
+                let encrypter = secrets_config.encrypter();
+                let password_manager = password_manager_from_config(&passwords_config).await?;
+
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let encrypted_client_secret =
+                    encrypter.encrypt_to_string(client_secret.as_bytes())?;
+                for client_id in CLIENT_IDS {
+                    repo.oauth2_client()
+                        .upsert_static(
+                            client_id,
+                            OAuthClientAuthenticationMethod::ClientSecretBasic,
+                            Some(encrypted_client_secret.clone()),
+                            None,
+                            None,
+                            vec![redirect_uri.clone()],
+                        )
+                        .await?;
+                    info!(%client_id, "Seeded client");
+                }
+
+                let existing = repo.user().find_by_username(&username).await?;
+                let user = if let Some(user) = existing {
+                    user
+                } else {
+                    let user = repo.user().add(&mut rng, &clock, username.clone()).await?;
+                    let email = repo
+                        .user_email()
+                        .add(&mut rng, &clock, &user, format!("{username}@example.com"))
+                        .await?;
+                    let email = repo.user_email().mark_as_verified(&clock, email).await?;
+                    repo.user_email().set_as_primary(&email).await?;
+                    user
+                };
+
+                let (version, hashed_password) = password_manager
+                    .hash(&mut rng, password.clone().into_bytes().into())
+                    .await?;
+                repo.user_password()
+                    .add(&mut rng, &clock, &user, version, hashed_password, None)
+                    .await?;
+                info!(%user.id, %user.username, "Seeded user");
+
+                repo.into_inner()
+                    .commit()
+                    .await
+                    .context("could not commit the seeded data")?;
+
+                let url_builder =
+                    UrlBuilder::new(http_config.public_base, http_config.issuer, None);
+                let [client, client2] = CLIENT_IDS.map(|client_id| {
+                    json!({
+                        "client_id": client_id.to_string(),
+                        "client_secret": client_secret,
+                    })
+                });
+
+                let suite_config = json!({
+                    "alias": "mas",
+                    "description": "matrix-authentication-service",
+                    "server": {
+                        "discoveryUrl": url_builder.oidc_discovery(),
+                    },
+                    "client": client,
+                    "client2": client2,
+                    "resource": {
+                        "resourceUrl": url_builder.oidc_userinfo_endpoint(),
+                    },
+                    "browser": [],
+                    "override": {},
+                });
+
+                serde_json::to_writer_pretty(std::io::stdout(), &suite_config)?;
+                println!();
+
+                Ok(ExitCode::SUCCESS)
+            }
+        }
+    }
+}
+
+/// Relax the rate limits, so that the conformance suite doesn't get rate
+/// limited when running many tests in a row
+pub fn relax_rate_limits(config: &mut RateLimitingConfig) {
+    for limiter in [
+        &mut config.account_recovery.per_ip,
+        &mut config.account_recovery.per_address,
+        &mut config.login.per_ip,
+        &mut config.login.per_account,
+        &mut config.registration,
+        &mut config.device_code_entry,
+        &mut config.form_submissions,
+        &mut config.matrix_id_login,
+    ] {
+        limiter.burst = NonZeroU32::MAX;
+        limiter.per_second = 1000.0;
+    }
+}
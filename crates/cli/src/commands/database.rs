@@ -10,8 +10,13 @@ use anyhow::Context;
 use clap::Parser;
 use figment::Figment;
 use mas_config::{ConfigurationSectionExt, DatabaseConfig};
-use mas_storage_pg::MIGRATOR;
-use tracing::{info_span, Instrument};
+use mas_storage_pg::{
+    backfill::list_progress,
+    migrations::{down_migrations_to, lock_impact, pending_migrations, revert_to},
+    MIGRATOR,
+};
+use sqlx::PgConnection;
+use tracing::{info, info_span, warn, Instrument};
 
 use crate::util::database_connection_from_config;
 
@@ -24,22 +29,155 @@ pub(super) struct Options {
 #[derive(Parser, Debug)]
 enum Subcommand {
     /// Run database migrations
-    Migrate,
+    Migrate {
+        /// Print the SQL of the pending migrations instead of running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Revert the migrations applied after the given version
+    ///
+    /// Only the migrations added since the last release can be reverted.
+    Revert {
+        /// The version of the last migration to keep
+        #[arg(long)]
+        to: i64,
+
+        /// Print the SQL of the migrations to revert instead of running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show the progress of the backfills running in the background
+    Backfills,
 }
 
 impl Options {
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
-        let _span = info_span!("cli.database.migrate").entered();
+        use Subcommand as SC;
         let config = DatabaseConfig::extract_or_default(figment)?;
-        let mut conn = database_connection_from_config(&config).await?;
 
-        // Run pending migrations
-        MIGRATOR
-            .run(&mut conn)
-            .instrument(info_span!("db.migrate"))
-            .await
-            .context("could not run migrations")?;
+        match self.subcommand {
+            SC::Migrate { dry_run } => {
+                let _span = info_span!("cli.database.migrate").entered();
+                let mut conn = database_connection_from_config(&config).await?;
+
+                // Warn about the migrations which could lock large tables for a while
+                let pending = pending_migrations(&mut conn).await?;
+                if pending.is_empty() {
+                    info!("No pending migrations");
+                    return Ok(ExitCode::SUCCESS);
+                }
+
+                for migration in &pending {
+                    let impacts = lock_impact(&mut conn, &migration.sql).await?;
+
+                    if dry_run {
+                        println!(
+                            "-- Migration {} ({})",
+                            migration.version, migration.description
+                        );
+                        for impact in &impacts {
+                            println!(
+                                "-- {} ({} rows, {} bytes): {}",
+                                impact.table,
+                                impact.estimated_rows,
+                                impact.total_bytes,
+                                impact.kind.description(),
+                            );
+                        }
+                        println!("{}\n", migration.sql.trim());
+                    }
+
+                    for impact in impacts.iter().filter(|impact| impact.is_concerning()) {
+                        warn!(
+                            version = migration.version,
+                            table = %impact.table,
+                            estimated_rows = impact.estimated_rows,
+                            total_bytes = impact.total_bytes,
+                            "Migration {:?} will hold a lock on a large table: {}",
+                            migration.description,
+                            impact.kind.description(),
+                        );
+                    }
+                }
+
+                if dry_run {
+                    return Ok(ExitCode::SUCCESS);
+                }
+
+                // Run pending migrations
+                MIGRATOR
+                    .run(&mut conn)
+                    .instrument(info_span!("db.migrate"))
+                    .await
+                    .context("could not run migrations")?;
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::Revert { to, dry_run } => {
+                let _span = info_span!("cli.database.revert").entered();
+                let mut conn = database_connection_from_config(&config).await?;
+
+                if dry_run {
+                    print_down_migrations(&mut conn, to).await?;
+                    return Ok(ExitCode::SUCCESS);
+                }
+
+                let reverted = revert_to(&mut conn, to)
+                    .instrument(info_span!("db.revert"))
+                    .await
+                    .context("could not revert migrations")?;
 
-        Ok(ExitCode::SUCCESS)
+                info!("Reverted {} migrations", reverted.len());
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::Backfills => {
+                let _span = info_span!("cli.database.backfills").entered();
+                let mut conn = database_connection_from_config(&config).await?;
+
+                let progress = list_progress(&mut conn).await?;
+                if progress.is_empty() {
+                    info!("No backfill started yet");
+                }
+
+                for backfill in progress {
+                    let status = if let Some(completed_at) = backfill.completed_at {
+                        format!("completed at {completed_at}")
+                    } else {
+                        format!("last batch at {}", backfill.updated_at)
+                    };
+
+                    println!(
+                        "{}: {:.1}% ({} rows updated), {status}",
+                        backfill.name,
+                        backfill.percent(),
+                        backfill.processed_rows,
+                    );
+                }
+
+                Ok(ExitCode::SUCCESS)
+            }
+        }
     }
 }
+
+async fn print_down_migrations(conn: &mut PgConnection, to: i64) -> anyhow::Result<()> {
+    let downs = down_migrations_to(conn, to).await?;
+    if downs.is_empty() {
+        info!("No migrations to revert");
+    }
+
+    for down in downs {
+        println!(
+            "-- Revert migration {} ({})",
+            down.version, down.description
+        );
+        println!("{}\n", down.sql.trim());
+    }
+
+    Ok(())
+}
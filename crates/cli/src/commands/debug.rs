@@ -4,14 +4,37 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::process::ExitCode;
+use std::{fmt::Write as _, io::Write, process::ExitCode};
 
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Duration, Utc};
 use clap::Parser;
 use figment::Figment;
-use mas_config::{ConfigurationSectionExt, PolicyConfig};
+use mas_config::{
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, PasswordsConfig, PolicyConfig,
+    SecretsConfig,
+};
+use mas_data_model::{Device, TokenType, Ulid};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_storage::{
+    oauth2::OAuth2ClientRepository, user::UserRepository, Clock, RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::{PgRepository, TokenHasher};
+use rand::{Rng, SeedableRng};
+use sqlx::PgConnection;
 use tracing::{info, info_span};
+use zeroize::Zeroizing;
 
-use crate::util::policy_factory_from_config;
+use crate::util::{
+    database_pool_from_config, password_manager_from_config, policy_factory_from_config,
+};
+
+/// The ID of the client owning the sessions generated by `generate-load-data`
+const LOAD_DATA_CLIENT_ID: Ulid = Ulid::from_parts(0, 0x10AD);
+
+/// How long the access tokens generated by `generate-load-data` are valid for
+const LOAD_DATA_ACCESS_TOKEN_TTL: Duration = Duration::days(30);
 
 #[derive(Parser, Debug)]
 pub(super) struct Options {
@@ -23,6 +46,52 @@ pub(super) struct Options {
 enum Subcommand {
     /// Check that the policies compile
     Policy,
+
+    /// Dump the active sessions, tokens and upstream links of a user as a
+    /// JSON document, for security incident investigations
+    ///
+    /// The value of the tokens is redacted.
+    Snapshot {
+        /// The ID or the username of the user
+        #[arg(long)]
+        user: String,
+    },
+
+    /// Bulk-insert synthetic users, sessions and tokens, to benchmark the
+    /// service before migrating a large deployment
+    ///
+    /// All the users share the same password, which is only hashed once. Each
+    /// user gets a browser session, and OAuth 2.0 sessions with an access and
+    /// a refresh token on a dedicated static client. The tokens are saved as
+    /// a hash keyed with the encryption secret of the configuration.
+    ///
+    /// This must never be run against a production database.
+    GenerateLoadData {
+        /// How many users to generate. Accepts the `k` and `M` suffixes
+        #[arg(long, value_parser = parse_count, default_value = "1k")]
+        users: u64,
+
+        /// How many OAuth 2.0 sessions to generate for each user
+        #[arg(long, default_value_t = 1)]
+        sessions_per_user: u32,
+
+        /// The prefix of the generated usernames, followed by a number
+        #[arg(long, default_value = "load-")]
+        username_prefix: String,
+
+        /// The password set on all the generated users
+        #[arg(long, default_value = "load-password")]
+        password: String,
+
+        /// How many users to insert in each transaction
+        #[arg(long, default_value_t = 10_000)]
+        batch_size: u64,
+
+        /// Write the generated access tokens to this file, one per line, to
+        /// feed them to a benchmarking tool
+        #[arg(long)]
+        tokens_output: Option<Utf8PathBuf>,
+    },
 }
 
 impl Options {
@@ -38,8 +107,276 @@ impl Options {
 
                 let _instance = policy_factory.instantiate().await?;
             }
+
+            SC::Snapshot { user } => {
+                let _span = info_span!("cli.debug.snapshot", user).entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let pool = database_pool_from_config(&config).await?;
+                let mut repo = PgRepository::from_pool(&pool).await?.boxed();
+                let clock = SystemClock::default();
+
+                let found = match user.parse::<Ulid>() {
+                    Ok(id) => repo.user().lookup(id).await?,
+                    Err(_) => repo.user().find_by_username(&user).await?,
+                };
+                let found = found.context("User not found")?;
+
+                let snapshot = mas_handlers::user_snapshot(&mut repo, &clock, &found).await?;
+                repo.cancel().await?;
+
+                let mut stdout = std::io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &snapshot)?;
+                writeln!(stdout)?;
+            }
+
+            SC::GenerateLoadData {
+                users,
+                sessions_per_user,
+                username_prefix,
+                password,
+                batch_size,
+                tokens_output,
+            } => {
+                let _span = info_span!("cli.debug.generate_load_data").entered();
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let passwords_config = PasswordsConfig::extract_or_default(figment)?;
+                let secrets_config = SecretsConfig::extract(figment)?;
+
+                // The tokens are saved as a hash keyed with the encryption secret, so it
+                // has to be the one the server uses for them to be valid
+                let token_hasher = TokenHasher::new(&secrets_config.encryption);
+
+                let pool = database_pool_from_config(&database_config).await?;
+                let password_manager = password_manager_from_config(&passwords_config).await?;
+                let clock = SystemClock::default();
+                // XXX: we should disallow SeedableRng::from_entropy
+                let mut rng = rand_chacha::ChaChaRng::from_entropy();
+
+                // Hashing the password is the expensive part, so do it only once
+                let (version, hashed_password) = password_manager
+                    .hash(&mut rng, Zeroizing::new(password.into_bytes()))
+                    .await?;
+
+                let mut repo = PgRepository::from_pool(&pool).await?;
+                repo.oauth2_client()
+                    .upsert_static(
+                        LOAD_DATA_CLIENT_ID,
+                        OAuthClientAuthenticationMethod::None,
+                        None,
+                        None,
+                        None,
+                        Vec::new(),
+                    )
+                    .await?;
+                repo.into_inner().commit().await?;
+
+                let mut tokens_output = match tokens_output {
+                    Some(path) => Some(std::io::BufWriter::new(
+                        std::fs::File::create(&path)
+                            .with_context(|| format!("could not create {path}"))?,
+                    )),
+                    None => None,
+                };
+
+                let mut start = 0;
+                while start < users {
+                    let end = users.min(start + batch_size);
+                    let batch = LoadDataBatch::generate(
+                        &mut rng,
+                        &token_hasher,
+                        clock.now(),
+                        &username_prefix,
+                        start..end,
+                        sessions_per_user,
+                        i32::from(version),
+                        &hashed_password,
+                    );
+
+                    let mut txn = pool.begin().await?;
+                    batch.copy_in(&mut txn).await?;
+                    txn.commit().await?;
+
+                    if let Some(output) = &mut tokens_output {
+                        output.write_all(batch.access_tokens.as_bytes())?;
+                    }
+
+                    info!(inserted = end, total = users, "Inserted a batch of users");
+                    start = end;
+                }
+
+                if let Some(output) = &mut tokens_output {
+                    output.flush()?;
+                }
+            }
         }
 
         Ok(ExitCode::SUCCESS)
     }
 }
+
+/// Parse a count, with an optional `k` or `M` suffix
+fn parse_count(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 1_000),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1_000_000),
+        _ => (value, 1),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|e| format!("invalid count {value:?}: {e}"))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("count {value:?} is too large"))
+}
+
+/// A batch of synthetic rows, in the text format of the `COPY` command
+#[derive(Default)]
+struct LoadDataBatch {
+    users: String,
+    user_passwords: String,
+    user_sessions: String,
+    oauth2_sessions: String,
+    oauth2_access_tokens: String,
+    oauth2_refresh_tokens: String,
+
+    /// The generated access tokens, one per line
+    access_tokens: String,
+}
+
+impl LoadDataBatch {
+    #[allow(clippy::too_many_arguments)]
+    fn generate(
+        rng: &mut impl Rng,
+        token_hasher: &TokenHasher,
+        now: DateTime<Utc>,
+        username_prefix: &str,
+        range: std::ops::Range<u64>,
+        sessions_per_user: u32,
+        version: i32,
+        hashed_password: &str,
+    ) -> Self {
+        // `COPY` accepts UUIDs as 32 hexadecimal digits
+        fn uuid(id: Ulid) -> String {
+            format!("{:032x}", u128::from(id))
+        }
+
+        // `COPY` expects `BYTEA` values in hexadecimal, with an escaped backslash
+        let token_hash = |token: &str| -> String {
+            token_hasher
+                .hash(token)
+                .iter()
+                .fold(String::from("\\\\x"), |mut out, byte| {
+                    let _ = write!(out, "{byte:02x}");
+                    out
+                })
+        };
+
+        let created_at = now.to_rfc3339();
+        let expires_at = (now + LOAD_DATA_ACCESS_TOKEN_TTL).to_rfc3339();
+        let client_id = uuid(LOAD_DATA_CLIENT_ID);
+        let mut batch = Self::default();
+
+        for n in range {
+            let user_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+            let password_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+            let user_session_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+
+            let _ = writeln!(batch.users, "{user_id}\t{username_prefix}{n}\t{created_at}");
+            let _ = writeln!(
+                batch.user_passwords,
+                "{password_id}\t{user_id}\t{hashed_password}\t{version}\t{created_at}"
+            );
+            let _ = writeln!(
+                batch.user_sessions,
+                "{user_session_id}\t{user_id}\t{created_at}"
+            );
+
+            for _ in 0..sessions_per_user {
+                let session_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+                let access_token_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+                let refresh_token_id = uuid(Ulid::from_datetime_with_source(now.into(), rng));
+                let device = Device::generate(rng);
+                let access_token = TokenType::AccessToken.generate(rng);
+                let refresh_token = TokenType::RefreshToken.generate(rng);
+
+                let _ = writeln!(
+                    batch.oauth2_sessions,
+                    "{session_id}\t{user_id}\t{user_session_id}\t{client_id}\t\
+                     {{openid,urn:matrix:org.matrix.msc2967.client:api:*,{device}}}\t{created_at}",
+                    device = device.to_scope_token(),
+                );
+                let _ = writeln!(
+                    batch.oauth2_access_tokens,
+                    "{access_token_id}\t{session_id}\t{access_token_hash}\t{created_at}\t{expires_at}",
+                    access_token_hash = token_hash(&access_token),
+                );
+                let _ = writeln!(
+                    batch.oauth2_refresh_tokens,
+                    "{refresh_token_id}\t{session_id}\t{access_token_id}\t{refresh_token_hash}\t{created_at}",
+                    refresh_token_hash = token_hash(&refresh_token),
+                );
+                let _ = writeln!(batch.access_tokens, "{access_token}");
+            }
+        }
+
+        batch
+    }
+
+    /// Insert the batch, in an order which satisfies the foreign keys
+    async fn copy_in(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        let copies = [
+            (
+                "COPY users (user_id, username, created_at) FROM STDIN",
+                &self.users,
+            ),
+            (
+                "COPY user_passwords (user_password_id, user_id, hashed_password, version, created_at) FROM STDIN",
+                &self.user_passwords,
+            ),
+            (
+                "COPY user_sessions (user_session_id, user_id, created_at) FROM STDIN",
+                &self.user_sessions,
+            ),
+            (
+                "COPY oauth2_sessions (oauth2_session_id, user_id, user_session_id, oauth2_client_id, scope_list, created_at) FROM STDIN",
+                &self.oauth2_sessions,
+            ),
+            (
+                "COPY oauth2_access_tokens (oauth2_access_token_id, oauth2_session_id, access_token_hash, created_at, expires_at) FROM STDIN",
+                &self.oauth2_access_tokens,
+            ),
+            (
+                "COPY oauth2_refresh_tokens (oauth2_refresh_token_id, oauth2_session_id, oauth2_access_token_id, refresh_token_hash, created_at) FROM STDIN",
+                &self.oauth2_refresh_tokens,
+            ),
+        ];
+
+        for (statement, data) in copies {
+            if data.is_empty() {
+                continue;
+            }
+
+            let mut copy = conn.copy_in_raw(statement).await?;
+            copy.send(data.as_bytes()).await?;
+            copy.finish().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_count;
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("42"), Ok(42));
+        assert_eq!(parse_count("10k"), Ok(10_000));
+        assert_eq!(parse_count("1M"), Ok(1_000_000));
+        assert!(parse_count("M").is_err());
+        assert!(parse_count("1G").is_err());
+        assert!(parse_count("99999999999999999M").is_err());
+    }
+}
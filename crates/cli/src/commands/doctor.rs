@@ -9,32 +9,141 @@
 //! The code is quite repetitive for now, but we can refactor later with a
 //! better check abstraction
 
-use std::process::ExitCode;
+use std::{process::ExitCode, time::Duration};
 
 use anyhow::Context;
-use clap::Parser;
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
 use figment::Figment;
-use mas_config::{ConfigurationSection, RootConfig};
+use mas_config::{
+    ConfigurationSection, ConfigurationSectionExt, HashingCost, HomeserverKind, PasswordsConfig,
+    RootConfig, UpstreamOAuth2DiscoveryMode,
+};
+use mas_email::Address;
+use mas_handlers::passwords::Hasher;
 use mas_http::RequestBuilderExt;
+use mas_keystore::PrivateKey;
+use mas_matrix::HomeserverConnection;
+use mas_matrix_synapse::{SynapseConnection, SynapseFeature};
+use mas_storage::{Clock, SystemClock};
+use mas_storage_pg::migrations::pending_migrations;
+use rand::thread_rng;
 use tracing::{error, info, info_span, warn};
 use url::{Host, Url};
+use x509_cert::{der::Decode, Certificate};
+
+use crate::util::{
+    database_connection_from_config, homeserver_backend_from_config, http_client_from_config,
+    PASSWORD_HASHING_TARGET,
+};
+
+mod email;
 
 /// Base URL for the human-readable documentation
 const DOCS_BASE: &str = "https://element-hq.github.io/matrix-authentication-service";
 
+/// How many hashes are measured for each set of parameters, keeping the median
+const BENCH_SAMPLES: usize = 5;
+
+/// Clock differences above this are reported, as they make tokens look
+/// expired or not yet valid to other services
+const MAX_CLOCK_SKEW_SECONDS: i64 = 30;
+
+/// Certificates expiring sooner than this are reported
+const CERTIFICATE_EXPIRY_WARNING_DAYS: i64 = 14;
+
 #[derive(Parser, Debug)]
-pub(super) struct Options {}
+pub(super) struct Options {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(Parser, Debug)]
+enum Subcommand {
+    /// Benchmark the password hashing algorithms on this host, and suggest
+    /// cost parameters hashing a password in the given time
+    HashBench {
+        /// How long hashing a password should take, in milliseconds
+        #[arg(long, default_value_t = u64::try_from(PASSWORD_HASHING_TARGET.as_millis()).unwrap_or(250))]
+        target: u64,
+
+        /// The hashing algorithm to tune
+        #[arg(long, value_enum, default_value_t = BenchAlgorithm::Argon2id)]
+        algorithm: BenchAlgorithm,
+
+        /// The maximum memory used by a single argon2id hash, in MiB.
+        ///
+        /// Keep in mind that each concurrent login uses this much memory.
+        #[arg(long, default_value_t = 256)]
+        max_memory: u32,
+
+        /// Write a configuration file adding the tuned hashing scheme, to load
+        /// along the main configuration file
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+
+    /// Check the email configuration: the connection to the SMTP relay, and
+    /// the SPF, DKIM and DMARC records of the sender domain
+    Email {
+        /// Send a test email to this address
+        #[arg(long)]
+        to: Option<Address>,
+
+        /// DNS-over-HTTPS resolver used to look up the DNS records. It must
+        /// support the JSON API
+        #[arg(long, default_value = "https://cloudflare-dns.com/dns-query")]
+        dns_resolver: Url,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BenchAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
 
 impl Options {
-    #[allow(clippy::too_many_lines)]
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+        match self.subcommand {
+            None => Self::diagnose(figment).await,
+            Some(Subcommand::HashBench {
+                target,
+                algorithm,
+                max_memory,
+                output,
+            }) => {
+                hash_bench(
+                    figment,
+                    Duration::from_millis(target),
+                    algorithm,
+                    max_memory.saturating_mul(1024),
+                    output,
+                )
+                .await
+            }
+            Some(Subcommand::Email {
+                to,
+                dns_resolver,
+                json,
+            }) => email::diagnose(figment, to, dns_resolver, json).await,
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn diagnose(figment: &Figment) -> anyhow::Result<ExitCode> {
         let _span = info_span!("cli.doctor").entered();
         info!("💡 Running diagnostics, make sure that both MAS and Synapse are running, and that MAS is using the same configuration files as this tool.");
 
         let config = RootConfig::extract(figment)?;
 
         // We'll need an HTTP client
-        let http_client = mas_http::reqwest_client();
+        let http_client = http_client_from_config(&config.http_client)?;
         let base_url = config.http.public_base.as_str();
         let issuer = config.http.issuer.as_ref().map(url::Url::as_str);
         let issuer = issuer.unwrap_or(base_url);
@@ -42,8 +151,13 @@ impl Options {
             r"The homeserver host in the config (`matrix.homeserver`) is not a valid domain.
 See {DOCS_BASE}/setup/homeserver.html",
         )?;
-        let hs_api = config.matrix.endpoint;
-        let admin_token = config.matrix.secret;
+        let hs_api = config.matrix.endpoint.clone();
+        let admin_token = config.matrix.secret.clone();
+        let clock = SystemClock::default();
+
+        check_database(&config).await;
+        check_keys(&config).await;
+        check_certificates(&config, &clock);
 
         if !issuer.starts_with("https://") {
             warn!(
@@ -52,6 +166,8 @@ This means some clients will refuse to use it."#
             );
         }
 
+        check_discovery(&http_client, &clock, issuer).await;
+
         let well_known_uri = format!("https://{matrix_domain}/.well-known/matrix/client");
         let result = http_client.get(&well_known_uri).send_traced().await;
 
@@ -352,6 +468,54 @@ Error details: {e}
 "#
                 ),
             }
+
+            // Probe the features supported by the homeserver
+            if config.matrix.kind == HomeserverKind::Synapse {
+                let connection = SynapseConnection::new(
+                    config.matrix.homeserver.clone(),
+                    hs_api.clone(),
+                    admin_token.clone(),
+                    http_client.clone(),
+                );
+                match connection.probe().await {
+                    Ok(capabilities) => {
+                        let version = capabilities.server_version().unwrap_or("(unknown)");
+                        info!(r#"✅ The homeserver runs Synapse "{version}"."#);
+                        for feature in SynapseFeature::ALL {
+                            let name = feature.as_str();
+                            if capabilities.supports(feature) {
+                                info!("✅ Synapse admin API feature `{name}` is supported.");
+                            } else {
+                                let (major, minor) = feature.min_version();
+                                warn!(
+                                    r#"⚠️ Synapse admin API feature `{name}` is not supported by this version of Synapse.
+Upgrade Synapse to {major}.{minor} or later to use it."#
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => error!(
+                        r#"❌ Can't probe the features supported by the homeserver.
+MAS will assume that it supports all of them.
+
+Error details: {e:#}
+"#
+                    ),
+                }
+            } else {
+                #[allow(clippy::disallowed_methods)]
+                let backend = homeserver_backend_from_config(
+                    &config.matrix,
+                    http_client.clone(),
+                    &mut thread_rng(),
+                )?;
+                for feature in backend.capabilities().unsupported() {
+                    warn!(
+                        "⚠️ The {kind:?} homeserver doesn't support the `{feature}` feature, it will be skipped or fail.",
+                        kind = config.matrix.kind,
+                    );
+                }
+            }
         }
 
         let external_cs_api_endpoint = discovered_cs_api.as_ref().unwrap_or(&hs_api);
@@ -422,6 +586,459 @@ Error details: {e}"#
             ),
         }
 
+        check_upstream_providers(&config, &http_client).await;
+
         Ok(ExitCode::SUCCESS)
     }
 }
+
+/// Check that the database is reachable, and that all the migrations ran
+async fn check_database(config: &RootConfig) {
+    let mut conn = match database_connection_from_config(&config.database).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(
+                r"❌ Can't connect to the database.
+Make sure the database is running, and check the `database` section of the config.
+
+See {DOCS_BASE}/setup/database.html
+
+Error details: {e:#}
+"
+            );
+            return;
+        }
+    };
+
+    match pending_migrations(&mut conn).await {
+        Ok(pending) if pending.is_empty() => {
+            info!("✅ The database is reachable, and its schema is up to date.");
+        }
+        Ok(pending) => {
+            let count = pending.len();
+            error!(
+                r"❌ The database has {count} pending migrations.
+MAS runs them on startup, unless it was started with `--no-migrate`.
+Run them with:
+
+  mas-cli database migrate
+"
+            );
+        }
+        Err(e) => error!(
+            r"❌ Can't list the migrations applied to the database.
+Make sure the database user can read the `_sqlx_migrations` table.
+
+Error details: {e:#}
+"
+        ),
+    }
+}
+
+/// Check that the signing keys can be loaded, and that there is an RSA key, as
+/// RS256 is the only algorithm all clients support
+async fn check_keys(config: &RootConfig) {
+    match config.secrets.key_store().await {
+        Ok(keystore) if keystore.is_empty() => error!(
+            r"❌ There are no signing keys in the config.
+Generate some with `mas-cli config generate`, and copy the `secrets.keys` section.
+"
+        ),
+        Ok(keystore) => {
+            let has_rsa = keystore
+                .iter()
+                .any(|key| matches!(key.params(), PrivateKey::Rsa(_)));
+            if has_rsa {
+                info!(
+                    "✅ The {count} signing keys in the config are valid.",
+                    count = keystore.len()
+                );
+            } else {
+                warn!(
+                    r"⚠️ There is no RSA key in `secrets.keys`.
+Clients requiring ID tokens signed with RS256, which is the default algorithm, won't work.
+"
+                );
+            }
+        }
+        Err(e) => error!(
+            r"❌ Can't load the signing keys in `secrets.keys`.
+
+Error details: {e:#}
+"
+        ),
+    }
+}
+
+/// Check the validity period of the TLS certificates of the HTTP listeners
+fn check_certificates(config: &RootConfig, clock: &dyn Clock) {
+    let now = clock.now();
+
+    for listener in &config.http.listeners {
+        let name = listener.name.as_deref().unwrap_or("(unnamed)");
+        let Some(tls) = &listener.tls else {
+            continue;
+        };
+
+        let leaf = tls
+            .load()
+            .and_then(|(_, chain)| {
+                let leaf = chain.first().context("empty certificate chain")?;
+                Ok(Certificate::from_der(leaf)?)
+            })
+            .map(|certificate: Certificate| {
+                let validity = certificate.tbs_certificate.validity;
+                let not_before = DateTime::<Utc>::from(validity.not_before.to_system_time());
+                let not_after = DateTime::<Utc>::from(validity.not_after.to_system_time());
+                (not_before, not_after)
+            });
+
+        match leaf {
+            Ok((not_before, _)) if not_before > now => error!(
+                r"❌ The TLS certificate of the listener {name:?} is not valid before {not_before}.
+Check the clock of this host, and the certificate."
+            ),
+            Ok((_, not_after)) if not_after < now => error!(
+                r"❌ The TLS certificate of the listener {name:?} expired on {not_after}.
+Renew the certificate, and restart MAS."
+            ),
+            Ok((_, not_after))
+                if not_after - now
+                    < chrono::Duration::try_days(CERTIFICATE_EXPIRY_WARNING_DAYS).unwrap() =>
+            {
+                warn!(
+                    r"⚠️ The TLS certificate of the listener {name:?} expires on {not_after}.
+Renew the certificate soon."
+                );
+            }
+            Ok((_, not_after)) => {
+                info!(
+                    "✅ The TLS certificate of the listener {name:?} is valid until {not_after}."
+                );
+            }
+            Err(e) => error!(
+                r"❌ Can't load the TLS certificate of the listener {name:?}.
+
+Error details: {e:#}
+"
+            ),
+        }
+    }
+}
+
+/// Check that the discovery document is reachable on the public URL and
+/// advertises the configured issuer, and compare the clock of the server with
+/// the local one
+async fn check_discovery(http_client: &reqwest::Client, clock: &dyn Clock, issuer: &str) {
+    let discovery_url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+
+    let response = match http_client.get(&discovery_url).send_traced().await {
+        Ok(response) => response,
+        Err(e) => {
+            error!(
+                r#"❌ Can't fetch the discovery document at "{discovery_url}".
+Make sure MAS is running, and that `http.public_base` is the URL at which it is publicly reachable.
+Check your reverse proxy settings.
+
+See {DOCS_BASE}/setup/reverse-proxy.html
+
+Error details: {e}
+"#
+            );
+            return;
+        }
+    };
+
+    if let Some(date) = response
+        .headers()
+        .get("date")
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+    {
+        let skew = (clock.now() - date.with_timezone(&Utc)).num_seconds().abs();
+        if skew > MAX_CLOCK_SKEW_SECONDS {
+            warn!(
+                r"⚠️ The clock of this host and the one of the server at {discovery_url:?} differ by {skew} seconds.
+Tokens issued by MAS might look expired or not yet valid to clients and to the homeserver.
+Make sure the clocks of all hosts are synchronized, for example with NTP."
+            );
+        } else {
+            info!("✅ The clock of this host is in sync with the server.");
+        }
+    }
+
+    let status = response.status();
+    if !status.is_success() {
+        error!(
+            r#"❌ The discovery document at "{discovery_url}" replied with {status}.
+Make sure MAS is running, and check your reverse proxy settings.
+
+See {DOCS_BASE}/setup/reverse-proxy.html
+"#
+        );
+        return;
+    }
+
+    let advertised = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body.get("issuer")?.as_str().map(ToOwned::to_owned));
+
+    match advertised {
+        Some(advertised) if advertised == issuer => {
+            info!(
+                r#"✅ The discovery document at "{discovery_url}" advertises the issuer {issuer:?}."#
+            );
+        }
+        Some(advertised) => error!(
+            r#"❌ The discovery document at "{discovery_url}" advertises the issuer {advertised:?}, but the config expects {issuer:?}.
+This can happen if another MAS instance, or another provider, is served at this URL.
+Make sure `http.public_base` and `http.issuer` match how MAS is publicly reachable.
+"#
+        ),
+        None => error!(
+            r#"❌ The document at "{discovery_url}" is not a valid discovery document.
+Make sure this URL is handled by MAS, and check your reverse proxy settings.
+
+See {DOCS_BASE}/setup/reverse-proxy.html
+"#
+        ),
+    }
+}
+
+/// Check that the discovery documents of the upstream providers are reachable
+async fn check_upstream_providers(config: &RootConfig, http_client: &reqwest::Client) {
+    for provider in &config.upstream_oauth2.providers {
+        if !provider.enabled
+            || matches!(
+                provider.discovery_mode,
+                UpstreamOAuth2DiscoveryMode::Disabled
+            )
+        {
+            continue;
+        }
+
+        let issuer = provider.issuer.as_str();
+        let name = provider.human_name.as_deref().unwrap_or(issuer);
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+
+        let result = async {
+            let body = http_client
+                .get(&discovery_url)
+                .send_traced()
+                .await?
+                .error_for_status()?
+                .json::<serde_json::Value>()
+                .await?;
+            anyhow::Ok(
+                body.get("issuer")
+                    .and_then(|i| i.as_str())
+                    .map(ToOwned::to_owned),
+            )
+        }
+        .await;
+
+        match result {
+            Ok(Some(advertised)) if advertised == issuer => {
+                info!(r#"✅ The upstream provider "{name}" is reachable."#);
+            }
+            Ok(Some(advertised))
+                if matches!(
+                    provider.discovery_mode,
+                    UpstreamOAuth2DiscoveryMode::Insecure
+                ) =>
+            {
+                warn!(
+                    r#"⚠️ The upstream provider "{name}" advertises the issuer {advertised:?} instead of {issuer:?}.
+This is tolerated with the `insecure` discovery mode."#
+                );
+            }
+            Ok(Some(advertised)) => error!(
+                r#"❌ The upstream provider "{name}" advertises the issuer {advertised:?} instead of {issuer:?}.
+Users won't be able to log in with it.
+Fix its `issuer`, or set its `discovery_mode` to `insecure` if the provider is known to advertise a different issuer.
+
+See {DOCS_BASE}/setup/sso.html
+"#
+            ),
+            Ok(None) => error!(
+                r#"❌ The document at "{discovery_url}" is not a valid discovery document.
+Check the `issuer` of the upstream provider "{name}".
+
+See {DOCS_BASE}/setup/sso.html
+"#
+            ),
+            Err(e) => error!(
+                r#"❌ Can't fetch the discovery document of the upstream provider "{name}" at "{discovery_url}".
+Users won't be able to log in with it.
+Make sure the provider is reachable from this host, and check its `issuer`.
+
+Error details: {e:#}
+"#
+            ),
+        }
+    }
+}
+
+async fn hash_bench(
+    figment: &Figment,
+    target: Duration,
+    algorithm: BenchAlgorithm,
+    max_memory_cost: u32,
+    output: Option<Utf8PathBuf>,
+) -> anyhow::Result<ExitCode> {
+    let _span = info_span!("cli.doctor.hash_bench").entered();
+    let config = PasswordsConfig::extract_or_default(figment)?;
+    let schemes = config.load().await?;
+
+    // Measure the current scheme first, to compare
+    if let Some((version, current_algorithm, cost, secret)) = schemes.first().cloned() {
+        let hasher = crate::util::hasher(current_algorithm, cost, secret);
+        let elapsed = tokio::task::spawn_blocking(move || median_hash_time(&hasher)).await??;
+        info!(
+            "The current hashing scheme (version {version}, {current_algorithm:?}) hashes a password in {elapsed:?}"
+        );
+    }
+
+    info!("Tuning {algorithm:?} to hash a password in {target:?}, this can take a while...");
+    let (cost, elapsed) = tokio::task::spawn_blocking(move || match algorithm {
+        BenchAlgorithm::Argon2id => tune_argon2id(target, max_memory_cost),
+        BenchAlgorithm::Bcrypt => tune_bcrypt(target),
+    })
+    .await??;
+
+    let version = schemes
+        .iter()
+        .map(|(version, ..)| *version)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let mut scheme = serde_json::json!({
+        "version": version,
+        "algorithm": match algorithm {
+            BenchAlgorithm::Argon2id => "argon2id",
+            BenchAlgorithm::Bcrypt => "bcrypt",
+        },
+    });
+    for (key, value) in [
+        ("cost", cost.cost),
+        ("memory_cost", cost.memory_cost),
+        ("time_cost", cost.time_cost),
+        ("parallelism", cost.parallelism),
+    ] {
+        if let Some(value) = value {
+            scheme[key] = value.into();
+        }
+    }
+    let fragment = serde_json::json!({ "passwords": { "schemes": [scheme] } });
+    let fragment = serde_yaml::to_string(&fragment)?;
+
+    info!("The tuned parameters hash a password in {elapsed:?}:\n{fragment}");
+
+    if elapsed > target * 2 {
+        warn!("Even the cheapest parameters are slower than the target on this host");
+    }
+
+    if schemes.iter().any(|(_, _, _, secret)| secret.is_some()) {
+        warn!("The current hashing schemes use a secret, add the same `secret` or `secret_file` to the new scheme");
+    }
+
+    if let Some(output) = output {
+        tokio::fs::write(&output, fragment).await?;
+        info!(
+            "Wrote the new hashing scheme to {output}. Load it after the main configuration file, with `--config` or an `include` directive. Existing passwords are upgraded to it on the next login of their users."
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Measure how long hashing a password takes, keeping the median of a few
+/// samples to smooth out the noise
+fn median_hash_time(hasher: &Hasher) -> anyhow::Result<Duration> {
+    let mut samples = (0..BENCH_SAMPLES)
+        .map(|_| hasher.benchmark_blocking())
+        .collect::<Result<Vec<_>, _>>()?;
+    samples.sort_unstable();
+    Ok(samples[samples.len() / 2])
+}
+
+/// Scale a cost parameter linearly so that hashing takes the target time
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn scale(value: u32, elapsed: Duration, target: Duration) -> u32 {
+    let factor = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+    (f64::from(value) * factor).clamp(1.0, f64::from(u32::MAX)) as u32
+}
+
+/// Find argon2id parameters hashing a password in the target time: first by
+/// raising the memory cost, up to the given maximum, then by adding
+/// iterations. The parallelism is kept to 1, as hashes already run in
+/// parallel across logins.
+fn tune_argon2id(
+    target: Duration,
+    max_memory_cost: u32,
+) -> anyhow::Result<(HashingCost, Duration)> {
+    // Start from the OWASP recommendation
+    const MIN_MEMORY_COST: u32 = 19 * 1024;
+    let max_memory_cost = max_memory_cost.max(MIN_MEMORY_COST);
+
+    let measure = |memory_cost, time_cost| {
+        let cost = HashingCost {
+            memory_cost: Some(memory_cost),
+            time_cost: Some(time_cost),
+            parallelism: Some(1),
+            ..HashingCost::default()
+        };
+        let hasher = Hasher::argon2id_with_cost(Some(memory_cost), Some(time_cost), Some(1), None);
+        Ok::<_, anyhow::Error>((cost, median_hash_time(&hasher)?))
+    };
+
+    let time_cost = 2;
+    let (_, elapsed) = measure(MIN_MEMORY_COST, time_cost)?;
+
+    // The time grows linearly with the memory cost
+    let memory_cost =
+        scale(MIN_MEMORY_COST, elapsed, target).clamp(MIN_MEMORY_COST, max_memory_cost);
+    let (cost, elapsed) = measure(memory_cost, time_cost)?;
+    if elapsed >= target || memory_cost < max_memory_cost {
+        return Ok((cost, elapsed));
+    }
+
+    // The memory budget is not enough to reach the target, add iterations
+    let time_cost = scale(time_cost, elapsed, target).max(time_cost);
+    measure(memory_cost, time_cost)
+}
+
+/// Find the bcrypt cost hashing a password in the target time. Each increment
+/// of the cost doubles the time.
+fn tune_bcrypt(target: Duration) -> anyhow::Result<(HashingCost, Duration)> {
+    const BASE_COST: u32 = 10;
+
+    let measure = |cost| {
+        let hasher = Hasher::bcrypt(Some(cost), None);
+        let cost = HashingCost {
+            cost: Some(cost),
+            ..HashingCost::default()
+        };
+        Ok::<_, anyhow::Error>((cost, median_hash_time(&hasher)?))
+    };
+
+    let (_, elapsed) = measure(BASE_COST)?;
+    let ratio = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+
+    #[allow(clippy::cast_possible_truncation)]
+    let increment = ratio.log2().round() as i64;
+    let cost = (i64::from(BASE_COST) + increment).clamp(4, 31);
+    measure(u32::try_from(cost)?)
+}
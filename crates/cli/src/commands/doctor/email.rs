@@ -0,0 +1,491 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Diagnose the email configuration: the connection to the relay, the SPF,
+//! DKIM and DMARC records of the sender domain, and the delivery of a test
+//! email
+
+use std::{fmt::Write as _, process::ExitCode};
+
+use anyhow::Context;
+use figment::Figment;
+use mas_config::{ConfigurationSection, EmailSmtpMode, EmailTransportKind, RootConfig};
+use mas_email::{Address, Mailbox};
+use mas_http::RequestBuilderExt;
+use mas_router::UrlBuilder;
+use tracing::{info, info_span};
+use url::Url;
+
+use crate::util::{
+    http_client_from_config, mailer_from_config, site_config_from_config, templates_from_config,
+};
+
+/// The outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+            Self::Skipped => "skipped",
+        }
+    }
+
+    fn emoji(self) -> &'static str {
+        match self {
+            Self::Pass => "✅",
+            Self::Warn => "⚠️",
+            Self::Fail => "❌",
+            Self::Skipped => "⏭️",
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    details: String,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, name: &'static str, status: Status, details: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            details: details.into(),
+        });
+    }
+
+    fn has_failures(&self) -> bool {
+        self.checks.iter().any(|check| check.status == Status::Fail)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let checks: Vec<_> = self
+            .checks
+            .iter()
+            .map(|check| {
+                serde_json::json!({
+                    "name": check.name,
+                    "status": check.status.as_str(),
+                    "details": check.details,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "ok": !self.has_failures(),
+            "checks": checks,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        let width = self
+            .checks
+            .iter()
+            .map(|check| check.name.len())
+            .max()
+            .unwrap_or_default();
+
+        let mut out = String::new();
+        for check in &self.checks {
+            let _ = writeln!(
+                out,
+                "{} {:width$}  {}",
+                check.status.emoji(),
+                check.name,
+                check.details,
+            );
+        }
+        out
+    }
+}
+
+/// Resolves TXT records through a DNS-over-HTTPS resolver supporting the JSON
+/// API
+struct TxtResolver {
+    http_client: reqwest::Client,
+    endpoint: Url,
+}
+
+impl TxtResolver {
+    /// Lookup the TXT records of a name. Returns an empty list if the name
+    /// does not exist.
+    async fn lookup(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let response = self
+            .http_client
+            .get(self.endpoint.clone())
+            .query(&[("name", name), ("type", "TXT")])
+            .header("accept", "application/dns-json")
+            .send_traced()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+
+        // 0 is NOERROR, 3 is NXDOMAIN
+        match response.get("Status").and_then(serde_json::Value::as_u64) {
+            Some(0 | 3) => {}
+            status => anyhow::bail!("the DNS lookup of {name} failed with status {status:?}"),
+        }
+
+        let records = response
+            .get("Answer")
+            .and_then(serde_json::Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            // Only keep TXT records, not the CNAMEs leading to them
+            .filter(|answer| answer.get("type").and_then(serde_json::Value::as_u64) == Some(16))
+            .filter_map(|answer| answer.get("data").and_then(serde_json::Value::as_str))
+            .map(join_txt_strings)
+            .collect();
+
+        Ok(records)
+    }
+}
+
+/// TXT records longer than 255 characters are split in multiple quoted
+/// strings, which have to be concatenated
+fn join_txt_strings(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_owned();
+    }
+
+    data.split('"')
+        .enumerate()
+        // Odd parts are inside quotes
+        .filter(|(index, _)| index % 2 == 1)
+        .map(|(_, part)| part)
+        .collect()
+}
+
+/// Whether two domains are aligned in the DMARC relaxed mode. This
+/// approximates the organizational domain by considering a domain aligned with
+/// its subdomains.
+fn relaxed_aligned(a: &str, b: &str) -> bool {
+    let a = a.trim_end_matches('.').to_ascii_lowercase();
+    let b = b.trim_end_matches('.').to_ascii_lowercase();
+    a == b || a.ends_with(&format!(".{b}")) || b.ends_with(&format!(".{a}"))
+}
+
+/// Find the value of a tag in a DKIM or DMARC record, like `p=reject`
+fn tag<'a>(record: &'a str, name: &str) -> Option<&'a str> {
+    record.split(';').find_map(|part| {
+        let (key, value) = part.split_once('=')?;
+        (key.trim() == name).then_some(value.trim())
+    })
+}
+
+async fn check_spf(report: &mut Report, resolver: &TxtResolver, domain: &str) {
+    let records = match resolver.lookup(domain).await {
+        Ok(records) => records,
+        Err(e) => {
+            report.push("SPF", Status::Fail, format!("{e:#}"));
+            return;
+        }
+    };
+
+    let spf: Vec<_> = records
+        .iter()
+        .filter(|record| record.starts_with("v=spf1"))
+        .collect();
+
+    match spf.as_slice() {
+        [] => report.push(
+            "SPF",
+            Status::Fail,
+            format!(
+                "No SPF record found on {domain}. Publish one listing the servers sending emails for this domain"
+            ),
+        ),
+        [record] if record.ends_with("+all") => report.push(
+            "SPF",
+            Status::Warn,
+            format!("The SPF record of {domain} allows any server to send emails: {record}"),
+        ),
+        [record] => report.push(
+            "SPF",
+            Status::Pass,
+            format!(
+                "{record} (make sure it covers the servers relaying the emails of MAS)"
+            ),
+        ),
+        _ => report.push(
+            "SPF",
+            Status::Fail,
+            format!("{domain} has multiple SPF records, which makes SPF fail; merge them into one"),
+        ),
+    }
+}
+
+async fn check_dkim(
+    report: &mut Report,
+    resolver: &TxtResolver,
+    config: &mas_config::EmailConfig,
+    from_domain: &str,
+) {
+    let Some(dkim) = config.dkim() else {
+        report.push(
+            "DKIM",
+            Status::Warn,
+            format!(
+                "MAS does not sign emails (`email.dkim` is not set); make sure the relay signs them with a key of {from_domain}"
+            ),
+        );
+        return;
+    };
+
+    let domain = dkim.domain.as_deref().unwrap_or(from_domain);
+    if !relaxed_aligned(domain, from_domain) {
+        report.push(
+            "DKIM",
+            Status::Fail,
+            format!(
+                "The signing domain {domain} is not aligned with the sender domain {from_domain}, so the signature does not count for DMARC"
+            ),
+        );
+        return;
+    }
+
+    let name = format!("{}._domainkey.{domain}", dkim.selector);
+    match resolver.lookup(&name).await {
+        Ok(records) => {
+            let key = records
+                .iter()
+                .find_map(|record| tag(record, "p"))
+                .filter(|key| !key.is_empty());
+            if key.is_some() {
+                report.push(
+                    "DKIM",
+                    Status::Pass,
+                    format!("Public key published on {name}"),
+                );
+            } else {
+                report.push(
+                    "DKIM",
+                    Status::Fail,
+                    format!("No DKIM public key found on {name}"),
+                );
+            }
+        }
+        Err(e) => report.push("DKIM", Status::Fail, format!("{e:#}")),
+    }
+}
+
+async fn check_dmarc(report: &mut Report, resolver: &TxtResolver, domain: &str) {
+    // Without a record on the domain itself, the one of the organizational
+    // domain applies. Approximate it with the parent domain.
+    let mut candidates = vec![domain.to_owned()];
+    if domain.matches('.').count() >= 2 {
+        if let Some((_, parent)) = domain.split_once('.') {
+            candidates.push(parent.to_owned());
+        }
+    }
+
+    for candidate in candidates {
+        let name = format!("_dmarc.{candidate}");
+        let records = match resolver.lookup(&name).await {
+            Ok(records) => records,
+            Err(e) => {
+                report.push("DMARC", Status::Fail, format!("{e:#}"));
+                return;
+            }
+        };
+
+        let Some(record) = records.iter().find(|record| record.starts_with("v=DMARC1")) else {
+            continue;
+        };
+
+        match tag(record, "p") {
+            Some("quarantine" | "reject") => {
+                report.push("DMARC", Status::Pass, format!("{name}: {record}"));
+            }
+            _ => report.push(
+                "DMARC",
+                Status::Warn,
+                format!(
+                    "{name} only monitors failures, consider a `quarantine` or `reject` policy once SPF and DKIM pass: {record}"
+                ),
+            ),
+        }
+        return;
+    }
+
+    report.push(
+        "DMARC",
+        Status::Warn,
+        format!("No DMARC record found on _dmarc.{domain}; some providers reject or flag emails from domains without one"),
+    );
+}
+
+#[allow(clippy::too_many_lines)]
+pub(super) async fn diagnose(
+    figment: &Figment,
+    to: Option<Address>,
+    dns_resolver: Url,
+    json: bool,
+) -> anyhow::Result<ExitCode> {
+    let _span = info_span!("cli.doctor.email").entered();
+    let config = RootConfig::extract(figment)?;
+    let mut report = Report::default();
+
+    let http_client = http_client_from_config(&config.http_client)?;
+    let resolver = TxtResolver {
+        http_client: http_client.clone(),
+        endpoint: dns_resolver,
+    };
+
+    let from: Mailbox = config
+        .email
+        .from
+        .parse()
+        .context("invalid email configuration: invalid 'from' address")?;
+    let from_domain = from.email.domain().to_owned();
+
+    let url_builder = UrlBuilder::new(
+        config.http.public_base.clone(),
+        config.http.issuer.clone(),
+        None,
+    );
+    let site_config = site_config_from_config(
+        &config.branding,
+        &config.matrix,
+        &config.experimental,
+        &config.passwords,
+        &config.account,
+        &config.captcha,
+        &config.certificate_login,
+        &config.session_limits,
+        &config.clients,
+        &config.upstream_oauth2,
+        &config.feature_flags,
+    )?;
+    let templates = templates_from_config(&config.templates, &site_config, &url_builder).await?;
+    let mailer = mailer_from_config(&config.email, &templates, http_client)?;
+
+    match config.email.transport() {
+        EmailTransportKind::Blackhole => report.push(
+            "transport",
+            Status::Fail,
+            "The `blackhole` transport discards all emails, set `email.transport`",
+        ),
+        transport => report.push(
+            "transport",
+            Status::Pass,
+            format!("Sending emails as {from} with the {transport:?} transport"),
+        ),
+    }
+
+    if matches!(config.email.transport(), EmailTransportKind::Smtp) {
+        match mailer.test_connection().await {
+            Ok(()) if matches!(config.email.mode(), Some(EmailSmtpMode::Plain)) => report.push(
+                "relay",
+                Status::Warn,
+                "Connected to the SMTP relay, but the connection is not encrypted; use the `tls` or `starttls` mode",
+            ),
+            Ok(()) => report.push(
+                "relay",
+                Status::Pass,
+                "Connected to the SMTP relay over TLS, with a valid certificate",
+            ),
+            Err(e) => report.push(
+                "relay",
+                Status::Fail,
+                format!("Could not connect to the SMTP relay: {e}"),
+            ),
+        }
+    } else {
+        report.push(
+            "relay",
+            Status::Skipped,
+            "Only checked with the SMTP transport",
+        );
+    }
+
+    check_spf(&mut report, &resolver, &from_domain).await;
+    check_dkim(&mut report, &resolver, &config.email, &from_domain).await;
+    check_dmarc(&mut report, &resolver, &from_domain).await;
+
+    if let Some(to) = to {
+        match mailer.send_test_email(Mailbox::new(None, to.clone())).await {
+            Ok(()) => report.push(
+                "test email",
+                Status::Pass,
+                format!(
+                    "Sent to {to}; check that it arrived, and its Authentication-Results header"
+                ),
+            ),
+            Err(e) => report.push(
+                "test email",
+                Status::Fail,
+                format!("Could not send to {to}: {e}"),
+            ),
+        }
+    } else {
+        report.push(
+            "test email",
+            Status::Skipped,
+            "Pass `--to` to send a test email",
+        );
+    }
+
+    if json {
+        println!("{:#}", report.to_json());
+    } else {
+        info!("Email diagnostics:\n{}", report.to_text());
+    }
+
+    if report.has_failures() {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_txt_strings() {
+        assert_eq!(join_txt_strings(r#""v=spf1 -all""#), "v=spf1 -all");
+        assert_eq!(
+            join_txt_strings(r#""v=DKIM1; k=rsa; p=MIIB" "IjANBg""#),
+            "v=DKIM1; k=rsa; p=MIIBIjANBg"
+        );
+        assert_eq!(join_txt_strings("v=spf1 -all"), "v=spf1 -all");
+    }
+
+    #[test]
+    fn test_relaxed_aligned() {
+        assert!(relaxed_aligned("example.com", "example.com"));
+        assert!(relaxed_aligned("mail.example.com", "example.com"));
+        assert!(relaxed_aligned("example.com.", "Mail.Example.com"));
+        assert!(!relaxed_aligned("example.org", "example.com"));
+        assert!(!relaxed_aligned("notexample.com", "example.com"));
+    }
+
+    #[test]
+    fn test_tag() {
+        let record = "v=DMARC1; p=reject; rua=mailto:dmarc@example.com";
+        assert_eq!(tag(record, "p"), Some("reject"));
+        assert_eq!(tag(record, "rua"), Some("mailto:dmarc@example.com"));
+        assert_eq!(tag(record, "sp"), None);
+    }
+}
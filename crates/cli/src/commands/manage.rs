@@ -6,33 +6,40 @@
 
 use std::{collections::BTreeMap, process::ExitCode};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::{ArgAction, CommandFactory, Parser};
 use console::{pad_str, style, Alignment, Style, Term};
 use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Password};
 use figment::Figment;
 use mas_config::{
-    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, MatrixConfig, PasswordsConfig,
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, FeatureFlagsConfig,
+    HttpClientConfig, HttpConfig, MatrixConfig, PasswordsConfig,
 };
-use mas_data_model::{Device, TokenType, Ulid, UpstreamOAuthProvider, User};
+use mas_data_model::{Client, Device, JwksOrJwksUri, TokenType, Ulid, UpstreamOAuthProvider, User};
 use mas_email::Address;
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use mas_matrix::HomeserverConnection;
-use mas_matrix_synapse::SynapseConnection;
+use mas_router::UrlBuilder;
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatSessionFilter, CompatSessionRepository},
+    email_queue::QueuedEmailFilter,
     job::{
         DeactivateUserJob, JobRepositoryExt, ProvisionUserJob, ReactivateUserJob, SyncDevicesJob,
     },
     oauth2::OAuth2SessionFilter,
     user::{BrowserSessionFilter, UserEmailRepository, UserPasswordRepository, UserRepository},
-    Clock, RepositoryAccess, SystemClock,
+    Clock, Pagination, RepositoryAccess, SystemClock,
 };
 use mas_storage_pg::{DatabaseError, PgRepository};
 use rand::{RngCore, SeedableRng};
 use sqlx::{types::Uuid, Acquire};
 use tracing::{error, info, info_span, warn};
+use url::Url;
 
-use crate::util::{database_connection_from_config, password_manager_from_config};
+use crate::util::{
+    database_connection_from_config, homeserver_backend_from_config, http_client_from_config,
+    password_manager_from_config,
+};
 
 const USER_ATTRIBUTES_HEADING: &str = "User attributes";
 
@@ -94,6 +101,26 @@ enum Subcommand {
     /// Trigger a provisioning job for all users
     ProvisionAllUsers,
 
+    /// Provision a user again on the homeserver
+    ///
+    /// This syncs the profile, devices and email addresses of the user to the
+    /// homeserver, and reactivates them there if they are not locked. This is
+    /// useful when the homeserver and MAS drifted apart, for example after an
+    /// outage.
+    ResyncUser {
+        /// User to provision again
+        username: String,
+    },
+
+    /// List the emails which failed to send too many times
+    ListFailedEmails,
+
+    /// Queue again an email which failed to send too many times
+    RetryEmail {
+        /// ID of the email to retry, as shown by `list-failed-emails`
+        id: Ulid,
+    },
+
     /// Kill all sessions for a user
     KillSessions {
         /// User for which to kill sessions
@@ -120,6 +147,25 @@ enum Subcommand {
         username: String,
     },
 
+    /// Delete a user
+    ///
+    /// The user is deactivated and permanently removed once the
+    /// `account.deleted_user_retention` period has elapsed. Until then, it can
+    /// be restored with the `restore-user` command.
+    DeleteUser {
+        /// User to delete
+        username: String,
+    },
+
+    /// Restore a user which was deleted but not purged yet
+    ///
+    /// The user stays locked, and must be unlocked with the `unlock-user`
+    /// command to be able to log in again.
+    RestoreUser {
+        /// User to restore
+        username: String,
+    },
+
     /// Register a user
     ///
     /// This will interactively prompt for the user's attributes unless the
@@ -169,6 +215,92 @@ enum Subcommand {
         #[clap(long)]
         ignore_password_complexity: bool,
     },
+
+    /// Prepare the move to a new issuer, e.g. when renaming the domain
+    ///
+    /// Sessions and grants don't store the issuer: tokens are opaque and ID
+    /// tokens are signed when they are issued, so existing sessions carry over
+    /// to the new issuer as is. This checks that the new issuer is the one
+    /// configured, and reports the clients and upstream providers which pin
+    /// the old one and need to be updated.
+    MigrateIssuer {
+        /// The issuer being migrated from
+        #[arg(long)]
+        from: Url,
+
+        /// The issuer being migrated to
+        #[arg(long)]
+        to: Url,
+    },
+
+    /// List the feature flags, with their configuration and overrides
+    ListFeatureFlags,
+
+    /// Override a feature flag, without restarting the service
+    ///
+    /// Running servers pick up the override within 30 seconds.
+    SetFeatureFlag {
+        /// Name of the flag
+        name: String,
+
+        /// Disable the flag for everyone
+        #[arg(long)]
+        disabled: bool,
+
+        /// Percentage of users for which the flag is enabled. Defaults to all
+        /// users.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        rollout_percentage: Option<u8>,
+    },
+
+    /// Remove the override of a feature flag, going back to its configuration
+    ClearFeatureFlag {
+        /// Name of the flag
+        name: String,
+    },
+}
+
+/// List the reasons why a client depends on the host of the old issuer
+fn issuer_pins(client: &Client, old_host: &str) -> Vec<&'static str> {
+    let on_old_host = |url: &Url| url.host_str() == Some(old_host);
+    let mut reasons = Vec::new();
+
+    if client.redirect_uris.iter().any(on_old_host) {
+        reasons.push("redirect URIs on the old domain");
+    }
+
+    if let Some(JwksOrJwksUri::JwksUri(jwks_uri)) = &client.jwks {
+        if on_old_host(jwks_uri) {
+            reasons.push("JWKS URI on the old domain");
+        }
+    }
+
+    if [
+        &client.client_uri,
+        &client.logo_uri,
+        &client.policy_uri,
+        &client.tos_uri,
+        &client.initiate_login_uri,
+    ]
+    .into_iter()
+    .flatten()
+    .any(on_old_host)
+    {
+        reasons.push("metadata URLs on the old domain");
+    }
+
+    // JWT client assertions are addressed to the token endpoint of the issuer
+    if matches!(
+        client.token_endpoint_auth_method,
+        Some(
+            OAuthClientAuthenticationMethod::ClientSecretJwt
+                | OAuthClientAuthenticationMethod::PrivateKeyJwt
+        )
+    ) {
+        reasons.push("JWT client assertions addressed to the old token endpoint");
+    }
+
+    reasons
 }
 
 impl Options {
@@ -202,9 +334,20 @@ impl Options {
                     .await?
                     .context("User not found")?;
 
-                if !ignore_complexity && !password_manager.is_password_complex_enough(&password)? {
-                    error!("That password is too weak.");
-                    return Ok(ExitCode::from(1));
+                if !ignore_complexity {
+                    let policy_class = repo.user().get_password_policy_class(&user).await?;
+                    let validation = password_manager
+                        .validate_new_password(
+                            policy_class.as_deref(),
+                            &user.username,
+                            &password,
+                            None,
+                        )
+                        .await?;
+                    if let Some(message) = validation.message() {
+                        error!("That password can't be used: {message}");
+                        return Ok(ExitCode::from(1));
+                    }
                 }
 
                 let password = password.into_bytes().into();
@@ -374,6 +517,59 @@ impl Options {
                 Ok(ExitCode::SUCCESS)
             }
 
+            SC::ResyncUser { username } => {
+                let _span =
+                    info_span!("cli.manage.resync_user", user.username = username).entered();
+                let http_client_config = HttpClientConfig::extract_or_default(figment)?;
+                let http_client = http_client_from_config(&http_client_config)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let matrix_config = MatrixConfig::extract(figment)?;
+
+                let homeserver =
+                    homeserver_backend_from_config(&matrix_config, http_client, &mut rng)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                let mxid = homeserver.mxid(&user.username);
+                if homeserver.is_localpart_available(&user.username).await? {
+                    info!("{mxid} doesn't exist on the homeserver, it will be created");
+                } else if user.locked_at.is_none()
+                    && homeserver.query_user(&mxid).await?.deactivated
+                {
+                    info!("{mxid} is deactivated on the homeserver, it will be reactivated");
+                    repo.job()
+                        .schedule_job(ReactivateUserJob::new(&user))
+                        .await?;
+                }
+
+                let emails = repo
+                    .user_email()
+                    .all(&user)
+                    .await?
+                    .into_iter()
+                    .filter(|email| email.confirmed_at.is_some())
+                    .count();
+                info!("{emails} confirmed email addresses will replace the ones on the homeserver");
+
+                // Provisioning the user also syncs their devices
+                repo.job()
+                    .schedule_job(ProvisionUserJob::new(&user))
+                    .await?;
+
+                repo.into_inner().commit().await?;
+
+                info!(%user.id, "Scheduled provisioning of {mxid} again on the homeserver");
+
+                Ok(ExitCode::SUCCESS)
+            }
+
             SC::KillSessions { username, dry_run } => {
                 let _span =
                     info_span!("cli.manage.kill_sessions", user.username = username).entered();
@@ -477,6 +673,69 @@ impl Options {
                 Ok(ExitCode::SUCCESS)
             }
 
+            SC::ListFailedEmails => {
+                let _span = info_span!("cli.manage.list_failed_emails").entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let mut cursor = Pagination::first(100);
+                let mut count = 0;
+                loop {
+                    let page = repo
+                        .email_queue()
+                        .list(QueuedEmailFilter::new().failed_only(), cursor)
+                        .await?;
+                    for email in page.edges {
+                        cursor = cursor.after(email.id);
+                        count += 1;
+                        println!(
+                            "{id}\t{recipient}\t{created_at}\t{attempts} attempts\t{error}",
+                            id = email.id,
+                            recipient = email.recipient,
+                            created_at = email.created_at,
+                            attempts = email.attempts,
+                            error = email.last_error.as_deref().unwrap_or("-"),
+                        );
+                    }
+
+                    if !page.has_next_page {
+                        break;
+                    }
+                }
+
+                repo.into_inner().rollback().await?;
+                info!("{count} failed emails");
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::RetryEmail { id } => {
+                let _span = info_span!("cli.manage.retry_email", queued_email.id = %id).entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let email = repo
+                    .email_queue()
+                    .lookup(id)
+                    .await?
+                    .context("Email not found")?;
+
+                if !email.state.is_failed() {
+                    bail!("Email is not in the dead-letter list");
+                }
+
+                repo.email_queue().retry(&clock, email).await?;
+                repo.into_inner().commit().await?;
+
+                info!(queued_email.id = %id, "Email queued again");
+
+                Ok(ExitCode::SUCCESS)
+            }
+
             SC::UnlockUser { username } => {
                 let _span = info_span!("cli.manage.lock_user", user.username = username).entered();
                 let config = DatabaseConfig::extract_or_default(figment)?;
@@ -500,6 +759,260 @@ impl Options {
                 Ok(ExitCode::SUCCESS)
             }
 
+            SC::DeleteUser { username } => {
+                let _span =
+                    info_span!("cli.manage.delete_user", user.username = username).entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                if user.deleted_at.is_some() {
+                    bail!("User is already deleted");
+                }
+
+                warn!(%user.id, "Deleting user");
+                let user = repo.user().delete(&clock, user).await?;
+
+                // Deactivate the user on the homeserver and end their sessions. The
+                // user will be removed for good by the purge job.
+                repo.job()
+                    .schedule_job(DeactivateUserJob::new(&user, false))
+                    .await?;
+
+                repo.into_inner().commit().await?;
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::RestoreUser { username } => {
+                let _span =
+                    info_span!("cli.manage.restore_user", user.username = username).entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                if user.deleted_at.is_none() {
+                    bail!("User is not deleted");
+                }
+
+                info!(%user.id, "Restoring user");
+                repo.user().restore(user).await?;
+
+                repo.into_inner().commit().await?;
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::MigrateIssuer { from, to } => {
+                let _span = info_span!("cli.manage.migrate_issuer", %from, %to).entered();
+                let http_config = HttpConfig::extract_or_default(figment)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+
+                // Check that the configuration matches the migration
+                let url_builder = UrlBuilder::new(
+                    http_config.public_base.clone(),
+                    http_config.issuer.clone(),
+                    None,
+                );
+                if url_builder.oidc_issuer() != to {
+                    warn!(
+                        configured = %url_builder.oidc_issuer(),
+                        "The new issuer is not the one configured in `http.issuer`"
+                    );
+                }
+
+                let old_issuer_served = http_config
+                    .additional_issuers
+                    .iter()
+                    .any(|issuer| issuer.issuer.as_ref().unwrap_or(&issuer.public_base) == &from);
+                if !old_issuer_served {
+                    warn!("The old issuer is not in `http.additional_issuers`, clients which did not migrate yet will be unable to reach it");
+                }
+
+                let mut repo = PgRepository::from_conn(txn);
+
+                let old_host = from.host_str().context("Old issuer has no host")?;
+                let mut pinned = 0;
+                let clients = repo.oauth2_client().all().await?;
+                for client in clients {
+                    let reasons = issuer_pins(&client, old_host);
+                    if reasons.is_empty() {
+                        continue;
+                    }
+
+                    let active_sessions = repo
+                        .oauth2_session()
+                        .count(OAuth2SessionFilter::new().for_client(&client).active_only())
+                        .await?;
+
+                    pinned += 1;
+                    println!(
+                        "client\t{client_id}\t{name}\t{active_sessions} active sessions\t{reasons}",
+                        client_id = client.client_id,
+                        name = client.client_name.as_deref().unwrap_or("-"),
+                        reasons = reasons.join(", "),
+                    );
+                }
+
+                // Upstream providers have the callback URL of the old issuer registered
+                for provider in repo.upstream_oauth_provider().all_enabled().await? {
+                    println!(
+                        "upstream\t{id}\t{issuer}\tregister the new redirect URI {redirect_uri}",
+                        id = provider.id,
+                        issuer = provider.issuer,
+                        redirect_uri = url_builder.upstream_oauth_callback(provider.id),
+                    );
+                }
+
+                let active_sessions = repo
+                    .oauth2_session()
+                    .count(OAuth2SessionFilter::new().active_only())
+                    .await?;
+                let active_compat_sessions = repo
+                    .compat_session()
+                    .count(CompatSessionFilter::new().active_only())
+                    .await?;
+
+                repo.into_inner().rollback().await?;
+
+                info!(
+                    "{active_sessions} OAuth 2.0 sessions and {active_compat_sessions} compatibility sessions carry over to the new issuer"
+                );
+                if pinned > 0 {
+                    warn!("{pinned} clients pin the old issuer and need to be updated");
+                }
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::ListFeatureFlags => {
+                let _span = info_span!("cli.manage.list_feature_flags").entered();
+                let feature_flags_config = FeatureFlagsConfig::extract_or_default(figment)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let overrides: BTreeMap<_, _> = repo
+                    .feature_flag()
+                    .all()
+                    .await?
+                    .into_iter()
+                    .map(|flag| (flag.name.clone(), flag))
+                    .collect();
+                repo.into_inner().rollback().await?;
+
+                let describe = |enabled: bool, rollout_percentage: Option<u8>| match (
+                    enabled,
+                    rollout_percentage,
+                ) {
+                    (false, _) => "disabled".to_owned(),
+                    (true, None) => "enabled".to_owned(),
+                    (true, Some(percentage)) => format!("{percentage}%"),
+                };
+
+                for flag in &feature_flags_config.flags {
+                    let overridden = overrides.get(&flag.name).map_or_else(
+                        || "-".to_owned(),
+                        |o| describe(o.enabled, o.rollout_percentage),
+                    );
+                    println!(
+                        "{name}	{configured}	{overridden}	{users} listed users",
+                        name = flag.name,
+                        configured = describe(flag.enabled, flag.rollout_percentage),
+                        users = flag.users.len(),
+                    );
+                }
+
+                // Overrides of flags which are not in the configuration
+                for flag in overrides.values() {
+                    if feature_flags_config
+                        .flags
+                        .iter()
+                        .any(|configured| configured.name == flag.name)
+                    {
+                        continue;
+                    }
+
+                    println!(
+                        "{name}	-	{overridden}	0 listed users",
+                        name = flag.name,
+                        overridden = describe(flag.enabled, flag.rollout_percentage),
+                    );
+                }
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::SetFeatureFlag {
+                name,
+                disabled,
+                rollout_percentage,
+            } => {
+                let _span =
+                    info_span!("cli.manage.set_feature_flag", feature_flag.name = name).entered();
+                let feature_flags_config = FeatureFlagsConfig::extract_or_default(figment)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let defined = feature_flags_config
+                    .flags
+                    .iter()
+                    .any(|flag| flag.name == name)
+                    || feature_flags_config
+                        .experiments
+                        .iter()
+                        .any(|experiment| experiment.name == name);
+                if !defined {
+                    warn!("The flag is not defined in the configuration");
+                }
+
+                repo.feature_flag()
+                    .set(&clock, name.clone(), !disabled, rollout_percentage)
+                    .await?;
+                repo.into_inner().commit().await?;
+
+                info!(feature_flag.name = name, "Feature flag overridden");
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::ClearFeatureFlag { name } => {
+                let _span =
+                    info_span!("cli.manage.clear_feature_flag", feature_flag.name = name).entered();
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                if !repo.feature_flag().remove(&name).await? {
+                    bail!("Feature flag is not overridden");
+                }
+                repo.into_inner().commit().await?;
+
+                info!(feature_flag.name = name, "Feature flag override removed");
+
+                Ok(ExitCode::SUCCESS)
+            }
+
             SC::RegisterUser {
                 username,
                 password,
@@ -511,31 +1024,19 @@ impl Options {
                 yes,
                 ignore_password_complexity,
             } => {
-                let http_client = mas_http::reqwest_client();
+                let http_client_config = HttpClientConfig::extract_or_default(figment)?;
+                let http_client = http_client_from_config(&http_client_config)?;
                 let password_config = PasswordsConfig::extract_or_default(figment)?;
                 let database_config = DatabaseConfig::extract_or_default(figment)?;
                 let matrix_config = MatrixConfig::extract(figment)?;
 
                 let password_manager = password_manager_from_config(&password_config).await?;
-                let homeserver = SynapseConnection::new(
-                    matrix_config.homeserver,
-                    matrix_config.endpoint,
-                    matrix_config.secret,
-                    http_client,
-                );
+                let homeserver =
+                    homeserver_backend_from_config(&matrix_config, http_client, &mut rng)?;
                 let mut conn = database_connection_from_config(&database_config).await?;
                 let txn = conn.begin().await?;
                 let mut repo = PgRepository::from_conn(txn);
 
-                if let Some(password) = &password {
-                    if !ignore_password_complexity
-                        && !password_manager.is_password_complex_enough(password)?
-                    {
-                        error!("That password is too weak.");
-                        return Ok(ExitCode::from(1));
-                    }
-                }
-
                 // If the username is provided, check if it's available and normalize it.
                 let localpart = if let Some(username) = username {
                     check_and_normalize_username(&username, &mut repo, &homeserver)
@@ -561,6 +1062,18 @@ impl Options {
                     }
                 };
 
+                if let Some(password) = &password {
+                    if !ignore_password_complexity {
+                        let validation = password_manager
+                            .validate_new_password(None, &localpart, password, None)
+                            .await?;
+                        if let Some(message) = validation.message() {
+                            error!("That password can't be used: {message}");
+                            return Ok(ExitCode::from(1));
+                        }
+                    }
+                }
+
                 // Load all the upstream providers
                 let upstream_providers: BTreeMap<_, _> = repo
                     .upstream_oauth_provider()
@@ -770,7 +1283,7 @@ impl std::fmt::Display for HumanReadable<&UpstreamOAuthProvider> {
 async fn check_and_normalize_username<'a>(
     localpart_or_mxid: &'a str,
     repo: &mut dyn RepositoryAccess<Error = DatabaseError>,
-    homeserver: &SynapseConnection,
+    homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
 ) -> anyhow::Result<&'a str> {
     // XXX: this is a very basic MXID to localpart conversion
     // Strip any leading '@'
@@ -852,7 +1365,11 @@ impl UserCreationRequest<'_> {
     }
 
     /// Show the user creation request in a human-readable format
-    fn show(&self, term: &Term, homeserver: &SynapseConnection) -> std::io::Result<()> {
+    fn show(
+        &self,
+        term: &Term,
+        homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
+    ) -> std::io::Result<()> {
         let value_style = Style::new().green();
         let key_style = Style::new().bold();
         let warning_style = Style::new().italic().red().bright();
@@ -8,17 +8,17 @@ use std::process::ExitCode;
 
 use camino::Utf8PathBuf;
 use clap::Parser;
-use figment::{
-    providers::{Env, Format, Yaml},
-    Figment,
-};
+use figment::{providers::Env, Figment};
 
 mod config;
+#[cfg(feature = "conformance")]
+pub(crate) mod conformance;
 mod database;
 mod debug;
 mod doctor;
 mod manage;
 mod server;
+mod stats;
 mod templates;
 mod worker;
 
@@ -48,14 +48,28 @@ enum Subcommand {
 
     /// Run diagnostics on the deployment
     Doctor(self::doctor::Options),
+
+    /// Print aggregate usage statistics, for capacity planning
+    Stats(self::stats::Options),
+
+    /// Support for running the OpenID Foundation conformance suite
+    #[cfg(feature = "conformance")]
+    #[clap(hide = true)]
+    Conformance(self::conformance::Options),
 }
 
 #[derive(Parser, Debug)]
 pub struct Options {
-    /// Path to the configuration file
+    /// Path to the configuration file, or to a directory of configuration
+    /// files
     #[arg(short, long, global = true, action = clap::ArgAction::Append)]
     config: Vec<Utf8PathBuf>,
 
+    /// Reject configuration files with keys which are not known, like typos in
+    /// the name of an option
+    #[arg(long, global = true)]
+    strict_config: bool,
+
     #[command(subcommand)]
     subcommand: Option<Subcommand>,
 }
@@ -63,6 +77,20 @@ pub struct Options {
 impl Options {
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
         use Subcommand as S;
+
+        if self.strict_config {
+            let unknown_keys = mas_config::unknown_keys(figment)?;
+            for unknown_key in &unknown_keys {
+                tracing::error!("{unknown_key}");
+            }
+            if !unknown_keys.is_empty() {
+                anyhow::bail!(
+                    "the configuration has {} unknown key(s)",
+                    unknown_keys.len()
+                );
+            }
+        }
+
         // We Box the futures for each subcommand so that we avoid this function being
         // big on the stack all the time
         match self.subcommand {
@@ -74,12 +102,19 @@ impl Options {
             Some(S::Templates(c)) => Box::pin(c.run(figment)).await,
             Some(S::Debug(c)) => Box::pin(c.run(figment)).await,
             Some(S::Doctor(c)) => Box::pin(c.run(figment)).await,
+            Some(S::Stats(c)) => Box::pin(c.run(figment)).await,
+            #[cfg(feature = "conformance")]
+            Some(S::Conformance(c)) => Box::pin(c.run(figment)).await,
             None => Box::pin(self::server::Options::default().run(figment)).await,
         }
     }
 
     /// Get a [`Figment`] instance with the configuration loaded
-    pub fn figment(&self) -> Figment {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration files could not be read
+    pub fn figment(&self) -> Result<Figment, figment::Error> {
         let configs = if self.config.is_empty() {
             // Read the MAS_CONFIG environment variable
             std::env::var("MAS_CONFIG")
@@ -94,8 +129,6 @@ impl Options {
         };
         let base = Figment::new().merge(Env::prefixed("MAS_").split("_"));
 
-        configs
-            .into_iter()
-            .fold(base, |f, path| f.admerge(Yaml::file(path)))
+        mas_config::merge_config_files(base, &configs)
     }
 }
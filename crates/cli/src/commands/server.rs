@@ -4,35 +4,46 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::{collections::BTreeSet, process::ExitCode, sync::Arc, time::Duration};
+use std::{collections::BTreeSet, num::NonZeroUsize, process::ExitCode, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use clap::Parser;
 use figment::Figment;
 use itertools::Itertools;
 use mas_config::{
-    AppConfig, ClientsConfig, ConfigurationSection, ConfigurationSectionExt, UpstreamOAuth2Config,
+    AppConfig, ClientsConfig, ConfigurationSection, ConfigurationSectionExt, TelemetryConfig,
+    UpstreamOAuth2Config,
+};
+use mas_handlers::{
+    ActivityTracker, AttestationChecker, CookieManager, DeviceProofReplayCache, Limiter,
+    MetadataCache, NetworkPolicy, RiskAssessor,
+};
+use mas_listener::{
+    limits::{ConnectionLimits, RequestLimitsLayer},
+    server::Server,
 };
-use mas_handlers::{ActivityTracker, CookieManager, Limiter, MetadataCache};
-use mas_listener::server::Server;
-use mas_matrix_synapse::SynapseConnection;
 use mas_router::UrlBuilder;
-use mas_storage::SystemClock;
-use mas_storage_pg::MIGRATOR;
+#[cfg(feature = "conformance")]
+use mas_storage::clock::MockClock;
+use mas_storage::{Clock, SystemClock};
+use mas_storage_pg::{TokenHasher, MIGRATOR};
 use rand::{
     distributions::{Alphanumeric, DistString},
     thread_rng,
 };
 use sqlx::migrate::Migrate;
+use tower::Layer;
 use tracing::{info, info_span, warn, Instrument};
 
 use crate::{
     app_state::AppState,
     shutdown::ShutdownManager,
     util::{
-        database_pool_from_config, mailer_from_config, password_manager_from_config,
-        policy_factory_from_config, register_sighup, site_config_from_config,
-        templates_from_config,
+        access_log_from_config, alerter_from_config, check_password_hashing_cost,
+        database_pool_from_config, homeserver_connection_from_config,
+        homeserver_discovery_from_config, http_client_from_config, mailer_from_config,
+        password_manager_from_config, policy_factory_from_config, probe_homeserver,
+        register_sighup, site_config_from_config, templates_from_config,
     },
 };
 
@@ -55,6 +66,18 @@ pub(super) struct Options {
     /// Do not sync the configuration with the database
     #[arg(long)]
     no_sync: bool,
+
+    /// Run in conformance testing mode, which relaxes the rate limits. Never
+    /// use this in production
+    #[cfg(feature = "conformance")]
+    #[arg(long)]
+    conformance: bool,
+
+    /// Freeze the clock used to serve requests at the given RFC 3339 time,
+    /// to get deterministic timestamps in conformance testing mode
+    #[cfg(feature = "conformance")]
+    #[arg(long, requires = "conformance")]
+    frozen_clock: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl Options {
@@ -63,6 +86,8 @@ impl Options {
         let span = info_span!("cli.run.init").entered();
         let shutdown = ShutdownManager::new()?;
         let config = AppConfig::extract(figment)?;
+        let clients_config = ClientsConfig::extract_or_default(figment)?;
+        let upstream_oauth2_config = UpstreamOAuth2Config::extract_or_default(figment)?;
 
         if self.migrate {
             warn!("The `--migrate` flag is deprecated and will be removed in a future release. Please use `--no-migrate` to disable automatic migrations on startup.");
@@ -84,26 +109,39 @@ impl Options {
             }
         } else {
             info!("Running pending database migrations");
+            let mut conn = pool.acquire().await?;
+
+            // The statement timeout of the pool doesn't apply to the migrations, which
+            // can take a while on large databases
+            sqlx::query("SET statement_timeout = 0")
+                .execute(&mut *conn)
+                .await?;
+
             MIGRATOR
-                .run(&pool)
+                .run(&mut *conn)
                 .instrument(info_span!("db.migrate"))
                 .await
                 .context("could not run database migrations")?;
+
+            sqlx::query("RESET statement_timeout")
+                .execute(&mut *conn)
+                .await?;
         }
 
         let encrypter = config.secrets.encrypter();
 
+        // The tokens are saved as a hash keyed with the encryption secret
+        let token_hasher = TokenHasher::new(&config.secrets.encryption);
+
         if self.no_sync {
             info!("Skipping configuration sync");
         } else {
             // Sync the configuration with the database
             let mut conn = pool.acquire().await?;
-            let clients_config = ClientsConfig::extract_or_default(figment)?;
-            let upstream_oauth2_config = UpstreamOAuth2Config::extract_or_default(figment)?;
 
             crate::sync::config_sync(
-                upstream_oauth2_config,
-                clients_config,
+                upstream_oauth2_config.clone(),
+                clients_config.clone(),
                 &mut conn,
                 &encrypter,
                 &SystemClock::default(),
@@ -123,6 +161,37 @@ impl Options {
         let cookie_manager =
             CookieManager::derive_from(config.http.public_base.clone(), &config.secrets.encryption);
 
+        // Each additional issuer gets its own URLs, cookies and signing keys
+        let mut additional_issuers = Vec::with_capacity(config.http.additional_issuers.len());
+        for issuer_config in &config.http.additional_issuers {
+            let key_store = config
+                .secrets
+                .key_store_with_kids(&issuer_config.keys)
+                .await
+                .with_context(|| {
+                    format!(
+                        "could not import keys for issuer {}",
+                        issuer_config.public_base
+                    )
+                })?;
+            let cookie_manager = CookieManager::derive_from(
+                issuer_config.public_base.clone(),
+                &config.secrets.encryption,
+            );
+            let url_builder = UrlBuilder::new(
+                issuer_config.public_base.clone(),
+                issuer_config.issuer.clone(),
+                None,
+            )
+            .with_error_documentation_base(config.http.error_documentation_base.clone());
+            additional_issuers.push((
+                issuer_config.public_base.clone(),
+                key_store,
+                cookie_manager,
+                url_builder,
+            ));
+        }
+
         // Load and compile the WASM policies (and fallback to the default embedded one)
         info!("Loading and compiling the policy module");
         let policy_factory = policy_factory_from_config(&config.policy).await?;
@@ -132,7 +201,8 @@ impl Options {
             config.http.public_base.clone(),
             config.http.issuer.clone(),
             None,
-        );
+        )
+        .with_error_documentation_base(config.http.error_documentation_base.clone());
 
         // Load the site configuration
         let site_config = site_config_from_config(
@@ -142,23 +212,57 @@ impl Options {
             &config.passwords,
             &config.account,
             &config.captcha,
+            &config.certificate_login,
+            &config.session_limits,
+            &clients_config,
+            &upstream_oauth2_config,
+            &config.feature_flags,
         )?;
 
+        // Load the overrides of the feature flags, and keep them up to date
+        crate::server::load_feature_flag_overrides(&pool, &site_config.feature_flags)
+            .await
+            .context("could not load the feature flag overrides")?;
+        shutdown
+            .task_tracker()
+            .spawn(crate::server::watch_feature_flags(
+                pool.clone(),
+                site_config.feature_flags.clone(),
+                shutdown.soft_shutdown_token(),
+            ));
+
         // Load and compile the templates
         let templates =
             templates_from_config(&config.templates, &site_config, &url_builder).await?;
 
-        let http_client = mas_http::reqwest_client();
+        let http_client = http_client_from_config(&config.http_client)?;
+
+        // Requests to other homeservers, for logins with their Matrix IDs, use a
+        // more restricted HTTP client
+        let homeserver_discovery = homeserver_discovery_from_config(&config.http_client)?;
+
+        // The external risk provider, if one is configured
+        let risk_assessor = RiskAssessor::from_config(&config.risk, http_client.clone());
+
+        // The verifier of the platform attestations of mobile clients, if one is
+        // configured
+        let attestation_checker =
+            AttestationChecker::from_config(&config.attestation, http_client.clone());
 
-        let homeserver_connection = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
+        // Where the admin API, logins and registrations can be used from
+        let network_policy = NetworkPolicy::from_config(&config.network_zones)
+            .context("network zones configuration is not valid")?;
+
+        #[allow(clippy::disallowed_methods)]
+        let homeserver_connection = homeserver_connection_from_config(
+            &config.matrix,
             http_client.clone(),
-        );
+            &mut thread_rng(),
+        )?;
+        probe_homeserver(&homeserver_connection).await;
 
         if !self.no_worker {
-            let mailer = mailer_from_config(&config.email, &templates)?;
+            let mailer = mailer_from_config(&config.email, &templates, http_client.clone())?;
             mailer.test_connection().await?;
 
             #[allow(clippy::disallowed_methods)]
@@ -172,6 +276,12 @@ impl Options {
                 &mailer,
                 homeserver_connection.clone(),
                 url_builder.clone(),
+                http_client.clone(),
+                token_hasher.clone(),
+                config.usage_stats.report_endpoint().cloned(),
+                alerter_from_config(&config.alerts),
+                config.account.deleted_user_retention,
+                config.account.login_history_retention,
             )
             .await?;
 
@@ -193,8 +303,41 @@ impl Options {
 
         let listeners_config = config.http.listeners.clone();
 
+        // Client certificates can only be used to log in on the listeners meant for
+        // it, which must verify them against a client CA
+        let certificate_login_listeners = if config.certificate_login.enabled {
+            for name in &config.certificate_login.listeners {
+                let listener = listeners_config
+                    .iter()
+                    .find(|listener| listener.name.as_ref() == Some(name))
+                    .with_context(|| {
+                        format!("certificate login listener {name:?} does not exist")
+                    })?;
+                let has_client_ca = listener
+                    .tls
+                    .as_ref()
+                    .is_some_and(|tls| tls.client_ca.is_some() || tls.client_ca_file.is_some());
+                if !has_client_ca {
+                    anyhow::bail!("certificate login listener {name:?} has no TLS client CA");
+                }
+            }
+
+            config.certificate_login.listeners.clone()
+        } else {
+            Vec::new()
+        };
+
         let password_manager = password_manager_from_config(&config.passwords).await?;
 
+        // Measure the cost of the hashing scheme in the background, to not delay
+        // the startup
+        {
+            let password_manager = password_manager.clone();
+            tokio::spawn(async move {
+                check_password_hashing_cost(&password_manager).await;
+            });
+        }
+
         // The upstream OIDC metadata cache
         let metadata_cache = MetadataCache::new();
 
@@ -207,19 +350,50 @@ impl Options {
             shutdown.soft_shutdown_token(),
         );
         let trusted_proxies = config.http.trusted_proxies.clone();
+        let email_webhook_secret = config.email.webhook_secret().map(ToOwned::to_owned);
+        let security_headers = crate::server::build_security_headers(
+            &config.http.security_headers,
+            &config.http.public_base,
+            site_config.captcha.as_ref(),
+        );
+
+        let telemetry_config = TelemetryConfig::extract_or_default(figment)?;
+        let access_log = access_log_from_config(&telemetry_config.access_log)?;
+        let compression = crate::server::build_compression(&config.http.compression);
+
+        let request_limits = RequestLimitsLayer::new(config.http.limits.max_request_body_size)
+            .with_body_read_timeout(config.http.limits.request_body_timeout);
+        let connection_limits = ConnectionLimits {
+            header_read_timeout: Some(config.http.limits.header_read_timeout),
+            max_concurrent_streams: Some(config.http.limits.max_concurrent_streams.get()),
+            max_connections: config.http.limits.max_connections.map(NonZeroUsize::get),
+        };
+
+        #[allow(unused_mut)]
+        let mut rate_limiting = config.rate_limiting.clone();
+        #[allow(unused_mut)]
+        let mut clock: Arc<dyn Clock + Send + Sync> = Arc::new(SystemClock::default());
+
+        #[cfg(feature = "conformance")]
+        if self.conformance {
+            warn!("Running in conformance testing mode, this must never be used in production!");
+            crate::commands::conformance::relax_rate_limits(&mut rate_limiting);
+
+            if let Some(frozen_clock) = self.frozen_clock {
+                info!(%frozen_clock, "Freezing the clock");
+                clock = Arc::new(MockClock::new(frozen_clock));
+            }
+        }
 
         // Build a rate limiter.
         // This should not raise an error here as the config should already have been
         // validated.
-        let limiter = Limiter::new(&config.rate_limiting)
-            .context("rate-limiting configuration is not valid")?;
+        let limiter =
+            Limiter::new(&rate_limiting).context("rate-limiting configuration is not valid")?;
 
         // Explicitly the config to properly zeroize secret keys
         drop(config);
 
-        // Listen for SIGHUP
-        register_sighup(&templates, &activity_tracker)?;
-
         limiter.start();
 
         let graphql_schema = mas_handlers::graphql_schema(
@@ -228,27 +402,38 @@ impl Options {
             homeserver_connection.clone(),
             site_config.clone(),
             password_manager.clone(),
+            limiter.clone(),
+            token_hasher.clone(),
         );
 
         let state = {
             let mut s = AppState {
                 pool,
-                templates,
+                templates: templates.clone(),
                 key_store,
                 cookie_manager,
                 encrypter,
+                token_hasher,
                 url_builder,
                 homeserver_connection,
                 policy_factory,
                 graphql_schema,
                 http_client,
+                homeserver_discovery,
                 password_manager,
                 metadata_cache,
                 site_config,
-                activity_tracker,
+                activity_tracker: activity_tracker.clone(),
                 trusted_proxies,
                 limiter,
+                risk_assessor,
+                attestation_checker,
+                device_proof_replay_cache: DeviceProofReplayCache::new(),
+                client_certificate_login: false,
+                network_policy,
+                email_webhook_secret,
                 conn_acquisition_histogram: None,
+                clock,
             };
             s.init_metrics()?;
             // XXX: this might panic
@@ -257,6 +442,7 @@ impl Options {
         };
 
         let mut fd_manager = listenfd::ListenFd::from_env();
+        let mut certificates = Vec::new();
 
         let servers: Vec<Server<_>> = listeners_config
             .into_iter()
@@ -266,20 +452,64 @@ impl Options {
 
                 // Load the TLS config
                 let tls_config = if let Some(tls_config) = config.tls.as_ref() {
-                    let tls_config = crate::server::build_tls_server_config(tls_config)?;
+                    let certificate =
+                        Arc::new(crate::server::ReloadableCertificate::load(tls_config)?);
+                    certificates.push(certificate.clone());
+                    let tls_config =
+                        crate::server::build_tls_server_config(tls_config, certificate)?;
                     Some(Arc::new(tls_config))
                 } else {
                     None
                 };
 
+                let state = AppState {
+                    client_certificate_login: config
+                        .name
+                        .as_ref()
+                        .is_some_and(|name| certificate_login_listeners.contains(name)),
+                    ..state.clone()
+                };
+
                 // and build the router
                 let router = crate::server::build_router(
                     state.clone(),
                     &config.resources,
                     config.prefix.as_deref(),
                     config.name.as_deref(),
+                    security_headers.as_ref(),
+                    access_log.as_ref(),
                 );
 
+                // Build a router for each additional issuer, with its own state
+                let issuer_routers = additional_issuers
+                    .iter()
+                    .map(|(public_base, key_store, cookie_manager, url_builder)| {
+                        let state = AppState {
+                            key_store: key_store.clone(),
+                            cookie_manager: cookie_manager.clone(),
+                            url_builder: url_builder.clone(),
+                            ..state.clone()
+                        };
+                        let prefix = crate::server::IssuerRouter::prefix(public_base)
+                            .or(config.prefix.as_deref());
+                        let router = crate::server::build_router(
+                            state,
+                            &config.resources,
+                            prefix,
+                            config.name.as_deref(),
+                            security_headers.as_ref(),
+                            access_log.as_ref(),
+                        );
+                        crate::server::IssuerRouter::new(public_base, router)
+                    })
+                    .collect();
+                let router = crate::server::build_multi_issuer_router(router, issuer_routers);
+                let router = if let Some(compression) = &compression {
+                    router.layer(compression.clone())
+                } else {
+                    router
+                };
+                let router = request_limits.layer(router);
 
                 // Display some informations about where we'll be serving connections
                 let proto = if config.tls.is_some() { "https" } else { "http" };
@@ -308,7 +538,8 @@ impl Options {
                 );
 
                 anyhow::Ok(listeners.into_iter().map(move |listener| {
-                    let mut server = Server::new(listener, router.clone());
+                    let mut server =
+                        Server::new(listener, router.clone()).with_limits(connection_limits);
                     if let Some(tls_config) = &tls_config {
                         server = server.with_tls(tls_config.clone());
                     }
@@ -323,6 +554,18 @@ impl Options {
 
         span.exit();
 
+        // Listen for SIGHUP
+        register_sighup(&templates, &activity_tracker, &certificates)?;
+
+        if !certificates.is_empty() {
+            shutdown
+                .task_tracker()
+                .spawn(crate::server::watch_certificates(
+                    certificates,
+                    shutdown.soft_shutdown_token(),
+                ));
+        }
+
         shutdown
             .task_tracker()
             .spawn(mas_listener::server::run_servers(
@@ -0,0 +1,42 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use figment::Figment;
+use mas_config::{ConfigurationSectionExt, DatabaseConfig};
+use mas_storage::SystemClock;
+use mas_storage_pg::PgRepository;
+use mas_tasks::UsageReport;
+use sqlx::Acquire;
+use tracing::info_span;
+
+use crate::util::database_connection_from_config;
+
+#[derive(Parser, Debug)]
+pub(super) struct Options {}
+
+impl Options {
+    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+        let _span = info_span!("cli.stats").entered();
+        let clock = SystemClock::default();
+
+        let database_config = DatabaseConfig::extract_or_default(figment)?;
+        let mut conn = database_connection_from_config(&database_config).await?;
+        let txn = conn.begin().await?;
+        let mut repo = PgRepository::from_conn(txn);
+
+        // This produces the same report as the one sent when usage statistics
+        // reporting is enabled
+        let report = UsageReport::collect(&mut repo, &clock).await?;
+        repo.into_inner().rollback().await?;
+
+        serde_json::to_writer_pretty(std::io::stdout(), &report)?;
+        println!();
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
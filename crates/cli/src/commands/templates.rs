@@ -4,17 +4,22 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::process::ExitCode;
+use std::{io::Write, process::ExitCode};
 
-use clap::Parser;
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use clap::{Parser, ValueEnum};
 use figment::Figment;
 use mas_config::{
-    AccountConfig, BrandingConfig, CaptchaConfig, ConfigurationSection, ConfigurationSectionExt,
-    ExperimentalConfig, MatrixConfig, PasswordsConfig, TemplatesConfig,
+    AccountConfig, BrandingConfig, CaptchaConfig, CertificateLoginConfig, ClientsConfig,
+    ConfigurationSection, ConfigurationSectionExt, ExperimentalConfig, FeatureFlagsConfig,
+    MatrixConfig, PasswordsConfig, SessionLimitsConfig, TemplatesConfig, UpstreamOAuth2Config,
 };
+use mas_i18n::DataLocale;
 use mas_storage::{Clock, SystemClock};
+use mas_templates::{EmailRecoveryContext, EmailVerificationContext, TemplateContext, Templates};
 use rand::SeedableRng;
-use tracing::info_span;
+use tracing::{info, info_span};
 
 use crate::util::{site_config_from_config, templates_from_config};
 
@@ -28,6 +33,81 @@ pub(super) struct Options {
 enum Subcommand {
     /// Check that the templates specified in the config are valid
     Check,
+
+    /// Render an email template with sample data, to preview it
+    RenderEmail {
+        /// The email template to render
+        #[arg(long, value_enum)]
+        template: EmailTemplate,
+
+        /// The locale to render the email in
+        #[arg(long, default_value = "en")]
+        locale: String,
+
+        /// Write the rendered emails in this directory instead of printing
+        /// them on the standard output
+        #[arg(long)]
+        out_dir: Option<Utf8PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EmailTemplate {
+    /// The email sent to verify an email address
+    Verification,
+
+    /// The email sent to recover an account
+    Recovery,
+}
+
+impl EmailTemplate {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Verification => "verification",
+            Self::Recovery => "recovery",
+        }
+    }
+}
+
+/// An email rendered from a sample context
+struct RenderedEmail {
+    subject: String,
+    text: String,
+    html: String,
+}
+
+/// Load the templates the same way the server does
+async fn load_templates(figment: &Figment) -> anyhow::Result<Templates> {
+    let template_config = TemplatesConfig::extract_or_default(figment)?;
+    let branding_config = BrandingConfig::extract_or_default(figment)?;
+    let matrix_config = MatrixConfig::extract(figment)?;
+    let experimental_config = ExperimentalConfig::extract_or_default(figment)?;
+    let password_config = PasswordsConfig::extract_or_default(figment)?;
+    let account_config = AccountConfig::extract_or_default(figment)?;
+    let captcha_config = CaptchaConfig::extract_or_default(figment)?;
+    let certificate_login_config = CertificateLoginConfig::extract_or_default(figment)?;
+    let session_limits_config = SessionLimitsConfig::extract_or_default(figment)?;
+    let clients_config = ClientsConfig::extract_or_default(figment)?;
+    let upstream_oauth2_config = UpstreamOAuth2Config::extract_or_default(figment)?;
+    let feature_flags_config = FeatureFlagsConfig::extract_or_default(figment)?;
+
+    let url_builder = mas_router::UrlBuilder::new("https://example.com/".parse()?, None, None);
+    let site_config = site_config_from_config(
+        &branding_config,
+        &matrix_config,
+        &experimental_config,
+        &password_config,
+        &account_config,
+        &captcha_config,
+        &certificate_login_config,
+        &session_limits_config,
+        &clients_config,
+        &upstream_oauth2_config,
+        &feature_flags_config,
+    )?;
+    let templates = templates_from_config(&template_config, &site_config, &url_builder).await?;
+
+    Ok(templates)
 }
 
 impl Options {
@@ -37,33 +117,88 @@ impl Options {
             SC::Check => {
                 let _span = info_span!("cli.templates.check").entered();
 
-                let template_config = TemplatesConfig::extract_or_default(figment)?;
-                let branding_config = BrandingConfig::extract_or_default(figment)?;
-                let matrix_config = MatrixConfig::extract(figment)?;
-                let experimental_config = ExperimentalConfig::extract_or_default(figment)?;
-                let password_config = PasswordsConfig::extract_or_default(figment)?;
-                let account_config = AccountConfig::extract_or_default(figment)?;
-                let captcha_config = CaptchaConfig::extract_or_default(figment)?;
-
                 let clock = SystemClock::default();
                 // XXX: we should disallow SeedableRng::from_entropy
                 let mut rng = rand_chacha::ChaChaRng::from_entropy();
-                let url_builder =
-                    mas_router::UrlBuilder::new("https://example.com/".parse()?, None, None);
-                let site_config = site_config_from_config(
-                    &branding_config,
-                    &matrix_config,
-                    &experimental_config,
-                    &password_config,
-                    &account_config,
-                    &captcha_config,
-                )?;
-                let templates =
-                    templates_from_config(&template_config, &site_config, &url_builder).await?;
+                let templates = load_templates(figment).await?;
                 templates.check_render(clock.now(), &mut rng)?;
 
                 Ok(ExitCode::SUCCESS)
             }
+
+            SC::RenderEmail {
+                template,
+                locale,
+                out_dir,
+            } => {
+                let _span = info_span!("cli.templates.render_email").entered();
+
+                let locale: DataLocale = locale
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid locale {locale:?}: {e}"))?;
+
+                let clock = SystemClock::default();
+                // XXX: we should disallow SeedableRng::from_entropy
+                let mut rng = rand_chacha::ChaChaRng::from_entropy();
+                let templates = load_templates(figment).await?;
+                let now = clock.now();
+
+                let rendered = match template {
+                    EmailTemplate::Verification => EmailVerificationContext::sample(now, &mut rng)
+                        .into_iter()
+                        .map(|context| {
+                            let context = context.with_language(locale.clone());
+                            Ok(RenderedEmail {
+                                subject: templates.render_email_verification_subject(&context)?,
+                                text: templates.render_email_verification_txt(&context)?,
+                                html: templates.render_email_verification_html(&context)?,
+                            })
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+
+                    EmailTemplate::Recovery => EmailRecoveryContext::sample(now, &mut rng)
+                        .into_iter()
+                        .map(|context| {
+                            let context = context.with_language(locale.clone());
+                            Ok(RenderedEmail {
+                                subject: templates.render_email_recovery_subject(&context)?,
+                                text: templates.render_email_recovery_txt(&context)?,
+                                html: templates.render_email_recovery_html(&context)?,
+                            })
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+                };
+
+                if let Some(out_dir) = &out_dir {
+                    std::fs::create_dir_all(out_dir)
+                        .with_context(|| format!("Could not create directory {out_dir}"))?;
+                }
+
+                let mut stdout = std::io::stdout().lock();
+                for (index, email) in rendered.into_iter().enumerate() {
+                    let Some(out_dir) = &out_dir else {
+                        writeln!(stdout, "==> Sample #{index}")?;
+                        writeln!(stdout, "Subject: {}", email.subject.trim())?;
+                        writeln!(stdout, "\n--- text ---\n{}", email.text)?;
+                        writeln!(stdout, "--- html ---\n{}", email.html)?;
+                        continue;
+                    };
+
+                    let name = template.name();
+                    for (extension, content) in [
+                        ("subject", &email.subject),
+                        ("txt", &email.text),
+                        ("html", &email.html),
+                    ] {
+                        let path = out_dir.join(format!("{name}-{index}.{extension}"));
+                        std::fs::write(&path, content)
+                            .with_context(|| format!("Could not write {path}"))?;
+                        info!(%path, "Wrote rendered email");
+                    }
+                }
+
+                Ok(ExitCode::SUCCESS)
+            }
         }
     }
 }
@@ -8,9 +8,11 @@ use std::process::ExitCode;
 
 use clap::Parser;
 use figment::Figment;
-use mas_config::{AppConfig, ConfigurationSection};
-use mas_matrix_synapse::SynapseConnection;
+use mas_config::{
+    AppConfig, ClientsConfig, ConfigurationSection, ConfigurationSectionExt, UpstreamOAuth2Config,
+};
 use mas_router::UrlBuilder;
+use mas_storage_pg::TokenHasher;
 use rand::{
     distributions::{Alphanumeric, DistString},
     thread_rng,
@@ -18,7 +20,9 @@ use rand::{
 use tracing::{info, info_span};
 
 use crate::util::{
-    database_pool_from_config, mailer_from_config, site_config_from_config, templates_from_config,
+    alerter_from_config, database_pool_from_config, homeserver_connection_from_config,
+    http_client_from_config, mailer_from_config, probe_homeserver, site_config_from_config,
+    templates_from_config,
 };
 
 #[derive(Parser, Debug, Default)]
@@ -28,6 +32,8 @@ impl Options {
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
         let span = info_span!("cli.worker.init").entered();
         let config = AppConfig::extract(figment)?;
+        let clients_config = ClientsConfig::extract_or_default(figment)?;
+        let upstream_oauth2_config = UpstreamOAuth2Config::extract_or_default(figment)?;
 
         // Connect to the database
         info!("Connecting to the database");
@@ -47,31 +53,57 @@ impl Options {
             &config.passwords,
             &config.account,
             &config.captcha,
+            &config.certificate_login,
+            &config.session_limits,
+            &clients_config,
+            &upstream_oauth2_config,
+            &config.feature_flags,
         )?;
 
         // Load and compile the templates
         let templates =
             templates_from_config(&config.templates, &site_config, &url_builder).await?;
 
-        let mailer = mailer_from_config(&config.email, &templates)?;
+        let http_client = http_client_from_config(&config.http_client)?;
+
+        let mailer = mailer_from_config(&config.email, &templates, http_client.clone())?;
         mailer.test_connection().await?;
 
-        let http_client = mas_http::reqwest_client();
-        let conn = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
-            http_client,
-        );
+        #[allow(clippy::disallowed_methods)]
+        let mut rng = thread_rng();
+
+        let conn =
+            homeserver_connection_from_config(&config.matrix, http_client.clone(), &mut rng)?;
+        probe_homeserver(&conn).await;
+
+        // The tokens are saved as a hash keyed with the encryption secret, which the
+        // backfills need
+        let token_hasher = TokenHasher::new(&config.secrets.encryption);
+
+        let usage_stats_endpoint = config.usage_stats.report_endpoint().cloned();
+        let alerter = alerter_from_config(&config.alerts);
+        let deleted_user_retention = config.account.deleted_user_retention;
+        let login_history_retention = config.account.login_history_retention;
 
         drop(config);
 
-        #[allow(clippy::disallowed_methods)]
-        let mut rng = thread_rng();
         let worker_name = Alphanumeric.sample_string(&mut rng, 10);
 
         info!(worker_name, "Starting task scheduler");
-        let monitor = mas_tasks::init(&worker_name, &pool, &mailer, conn, url_builder).await?;
+        let monitor = mas_tasks::init(
+            &worker_name,
+            &pool,
+            &mailer,
+            conn,
+            url_builder,
+            http_client,
+            token_hasher,
+            usage_stats_endpoint,
+            alerter,
+            deleted_user_retention,
+            login_history_retention,
+        )
+        .await?;
 
         span.exit();
 
@@ -87,7 +87,7 @@ async fn try_main() -> anyhow::Result<ExitCode> {
     let opts = self::commands::Options::parse();
 
     // Load the base configuration files
-    let figment = opts.figment();
+    let figment = opts.figment()?;
 
     // Telemetry config could fail to load, but that's probably OK, since the whole
     // config will be loaded afterwards, and crash if there is a problem.
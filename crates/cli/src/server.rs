@@ -5,29 +5,44 @@
 // Please see LICENSE in the repository root for full details.
 
 use std::{
+    convert::Infallible,
     future::ready,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs},
     os::unix::net::UnixListener,
+    sync::{Arc, Mutex},
+    time::SystemTime,
 };
 
 use anyhow::Context;
+use arc_swap::ArcSwap;
 use axum::{
+    body::Body,
     error_handling::HandleErrorLayer,
     extract::{FromRef, MatchedPath},
     Extension, Router,
 };
+use http::uri::Authority;
 use hyper::{
-    header::{HeaderValue, CACHE_CONTROL, USER_AGENT},
+    header::{HeaderValue, CACHE_CONTROL, HOST, USER_AGENT},
     Method, Request, Response, StatusCode, Version,
 };
 use listenfd::ListenFd;
-use mas_config::{HttpBindConfig, HttpResource, HttpTlsConfig, UnixOrTcp};
+use mas_axum_utils::security_headers::{
+    ContentSecurityPolicy, SecurityHeaders, SecurityHeadersLayer, StrictTransportSecurity,
+};
+use mas_config::{
+    HttpBindConfig, HttpCompressionConfig, HttpResource, HttpTlsConfig, SecurityHeadersConfig,
+    UnixOrTcp,
+};
+use mas_data_model::{CaptchaConfig, CaptchaService, FeatureFlags};
 use mas_listener::{unix_or_tcp::UnixOrTcpListener, ConnectionInfo};
 use mas_router::Route;
+use mas_storage::RepositoryAccess;
+use mas_storage_pg::PgRepository;
 use mas_templates::Templates;
 use mas_tower::{
-    make_span_fn, metrics_attributes_fn, DurationRecorderLayer, InFlightCounterLayer, TraceLayer,
-    KV,
+    make_span_fn, metrics_attributes_fn, AccessLogLayer, DurationRecorderLayer,
+    InFlightCounterLayer, TraceLayer, KV,
 };
 use opentelemetry::{Key, KeyValue};
 use opentelemetry_http::HeaderExtractor;
@@ -35,12 +50,26 @@ use opentelemetry_semantic_conventions::trace::{
     HTTP_REQUEST_METHOD, HTTP_RESPONSE_STATUS_CODE, HTTP_ROUTE, NETWORK_PROTOCOL_NAME,
     NETWORK_PROTOCOL_VERSION, URL_PATH, URL_QUERY, URL_SCHEME, USER_AGENT_ORIGINAL,
 };
-use rustls::ServerConfig;
+use rustls::{
+    crypto::CryptoProvider,
+    server::{danger::ClientCertVerifier, ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore, ServerConfig,
+};
 use sentry_tower::{NewSentryLayer, SentryHttpLayer};
-use tower::Layer;
-use tower_http::{services::ServeDir, set_header::SetResponseHeaderLayer};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use tower::{service_fn, Layer, ServiceExt};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, SizeAbove},
+        CompressionLayer, DefaultPredicate, Predicate,
+    },
+    services::ServeDir,
+};
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use url::Url;
 
 use crate::app_state::AppState;
 
@@ -81,6 +110,12 @@ fn otel_http_route<B>(request: &Request<B>) -> Option<&str> {
         .map(MatchedPath::as_str)
 }
 
+fn access_log_route(extensions: &axum::http::Extensions) -> Option<String> {
+    extensions
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+}
+
 fn otel_url_scheme<B>(request: &Request<B>) -> &'static str {
     // XXX: maybe we should panic if the connection info was not injected in the
     // request extensions
@@ -170,13 +205,145 @@ fn on_http_response_labels<B>(res: &Response<B>) -> Vec<KeyValue> {
     )]
 }
 
+/// Security header layers to apply on the different groups of routes
+#[derive(Clone)]
+pub struct SecurityHeadersLayers {
+    /// Applied on pages destined to be viewed by humans
+    human: SecurityHeadersLayer,
+
+    /// Applied on the OAuth 2.0, discovery and compatibility APIs
+    api: SecurityHeadersLayer,
+
+    /// Applied on everything else, which might serve third-party HTML (like the
+    /// GraphQL playground), so without a `Content-Security-Policy`
+    other: SecurityHeadersLayer,
+}
+
+/// Build the security header layers from the configuration
+///
+/// Returns `None` if security headers are disabled
+pub fn build_security_headers(
+    config: &SecurityHeadersConfig,
+    public_base: &Url,
+    captcha: Option<&CaptchaConfig>,
+) -> Option<SecurityHeadersLayers> {
+    if !config.enabled {
+        return None;
+    }
+
+    let mut base = SecurityHeaders::new().with_referrer_policy(&config.referrer_policy);
+
+    // Only send HSTS if we're actually served over HTTPS
+    if let Some(hsts) = &config.hsts {
+        if public_base.scheme() == "https" {
+            base = base.with_strict_transport_security(
+                StrictTransportSecurity::new(std::time::Duration::from_secs(hsts.max_age))
+                    .include_subdomains(hsts.include_subdomains)
+                    .preload(hsts.preload),
+            );
+        }
+    }
+
+    let mut csp = ContentSecurityPolicy::html()
+        .with_directive("frame-ancestors", config.frame_ancestors.iter().cloned())
+        .report_only(config.content_security_policy_report_only);
+
+    // CAPTCHA services load scripts and frames from their own domains
+    let captcha_sources: &[&str] = match captcha.map(|c| c.service) {
+        None => &[],
+        Some(CaptchaService::RecaptchaV2) => &[
+            "https://www.google.com/recaptcha/",
+            "https://www.gstatic.com/recaptcha/",
+        ],
+        Some(CaptchaService::CloudflareTurnstile) => &["https://challenges.cloudflare.com"],
+        Some(CaptchaService::HCaptcha) => &["https://hcaptcha.com", "https://*.hcaptcha.com"],
+    };
+    for directive in ["script-src", "frame-src", "connect-src", "style-src"] {
+        csp = csp.with_extra_sources(directive, captcha_sources.iter().copied());
+    }
+
+    for (directive, sources) in &config.content_security_policy_extra_sources {
+        csp = csp.with_extra_sources(directive, sources.iter().cloned());
+    }
+
+    Some(SecurityHeadersLayers {
+        human: SecurityHeadersLayer::new(base.clone().with_content_security_policy(csp)),
+        api: SecurityHeadersLayer::new(
+            base.clone()
+                .with_content_security_policy(ContentSecurityPolicy::api()),
+        ),
+        other: SecurityHeadersLayer::new(base),
+    })
+}
+
+/// Apply a security headers layer on a router, if enabled
+fn with_security_headers<S>(router: Router<S>, layer: Option<&SecurityHeadersLayer>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if let Some(layer) = layer {
+        router.layer(layer.clone())
+    } else {
+        router
+    }
+}
+
+/// Check whether a static asset has a content hash in its name, like
+/// `main-DnVeE3dY.js` or `main-DnVeE3dY.js.map`, as generated by Vite
+fn is_fingerprinted_asset(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let stem = file_name.split('.').next().unwrap_or_default().as_bytes();
+
+    // The hash is 8 characters long, and can itself contain dashes
+    let Some((name, hash)) = stem.len().checked_sub(8).map(|at| stem.split_at(at)) else {
+        return false;
+    };
+
+    name.len() > 1
+        && name.ends_with(b"-")
+        && hash
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_')
+}
+
+/// The compression layer applied on the listeners
+pub type HttpCompressionLayer = CompressionLayer<
+    tower_http::compression::predicate::And<
+        tower_http::compression::predicate::And<DefaultPredicate, SizeAbove>,
+        NotForContentType,
+    >,
+>;
+
+/// Build the response compression layer from the configuration
+///
+/// Returns `None` if compression is disabled
+pub fn build_compression(config: &HttpCompressionConfig) -> Option<HttpCompressionLayer> {
+    if !config.enabled {
+        return None;
+    }
+
+    // On top of the default predicate, which skips images, event streams and gRPC
+    // responses, skip fonts as they are already compressed
+    let predicate = DefaultPredicate::new()
+        .and(SizeAbove::new(config.min_size))
+        .and(NotForContentType::const_new("font/"));
+
+    Some(CompressionLayer::new().compress_when(predicate))
+}
+
+#[allow(clippy::too_many_lines)]
 pub fn build_router(
     state: AppState,
     resources: &[HttpResource],
     prefix: Option<&str>,
     name: Option<&str>,
+    security_headers: Option<&SecurityHeadersLayers>,
+    access_log: Option<&AccessLogLayer>,
 ) -> Router<()> {
     let templates = Templates::from_ref(&state);
+    let human_headers = security_headers.map(|s| &s.human);
+    let api_headers = security_headers.map(|s| &s.api);
+    let other_headers = security_headers.map(|s| &s.other);
     let mut router = Router::new();
 
     for resource in resources {
@@ -187,18 +354,20 @@ pub fn build_router(
             mas_config::HttpResource::Prometheus => {
                 router.route_service("/metrics", crate::telemetry::prometheus_service())
             }
-            mas_config::HttpResource::Discovery => {
-                router.merge(mas_handlers::discovery_router::<AppState>())
-            }
-            mas_config::HttpResource::Human => {
-                router.merge(mas_handlers::human_router::<AppState>(templates.clone()))
-            }
+            mas_config::HttpResource::Discovery => router.merge(with_security_headers(
+                mas_handlers::discovery_router::<AppState>(),
+                api_headers,
+            )),
+            mas_config::HttpResource::Human => router.merge(with_security_headers(
+                mas_handlers::human_router::<AppState>(templates.clone()),
+                human_headers,
+            )),
             mas_config::HttpResource::GraphQL {
                 playground,
                 undocumented_oauth2_access,
-            } => router.merge(mas_handlers::graphql_router::<AppState>(
-                *playground,
-                *undocumented_oauth2_access,
+            } => router.merge(with_security_headers(
+                mas_handlers::graphql_router::<AppState>(*playground, *undocumented_oauth2_access),
+                other_headers,
             )),
             mas_config::HttpResource::Assets { path } => {
                 let static_service = ServeDir::new(path)
@@ -210,23 +379,54 @@ pub fn build_router(
                 let error_layer =
                     HandleErrorLayer::new(|_e| ready(StatusCode::INTERNAL_SERVER_ERROR));
 
-                let cache_layer = SetResponseHeaderLayer::overriding(
-                    CACHE_CONTROL,
-                    HeaderValue::from_static("public, max-age=31536000, immutable"),
-                );
+                // Fingerprinted assets never change, so they can be cached forever. The
+                // other ones, like the manifest, must be revalidated on each use
+                let static_service = service_fn(move |request: Request<Body>| {
+                    let cache_control = if is_fingerprinted_asset(request.uri().path()) {
+                        HeaderValue::from_static("public, max-age=31536000, immutable")
+                    } else {
+                        HeaderValue::from_static("no-cache")
+                    };
+
+                    let response = static_service.clone().oneshot(request);
+                    async move {
+                        let mut response = response.await?;
+                        if response.status().is_success() || response.status().is_redirection() {
+                            response.headers_mut().insert(CACHE_CONTROL, cache_control);
+                        }
+                        Ok::<_, Infallible>(response)
+                    }
+                });
 
                 router.nest_service(
                     mas_router::StaticAsset::route(),
-                    (error_layer, cache_layer).layer(static_service),
+                    error_layer.layer(static_service),
                 )
             }
-            mas_config::HttpResource::OAuth => router.merge(mas_handlers::api_router::<AppState>()),
-            mas_config::HttpResource::Compat => {
-                router.merge(mas_handlers::compat_router::<AppState>())
-            }
+            mas_config::HttpResource::OAuth => router.merge(with_security_headers(
+                mas_handlers::api_router::<AppState>(templates.clone()),
+                api_headers,
+            )),
+            mas_config::HttpResource::Compat => router.merge(with_security_headers(
+                mas_handlers::compat_router::<AppState>(),
+                api_headers,
+            )),
             mas_config::HttpResource::AdminApi => {
                 let (_, api_router) = mas_handlers::admin_api_router::<AppState>();
-                router.merge(api_router)
+                router.merge(with_security_headers(api_router, other_headers))
+            }
+            mas_config::HttpResource::EmailWebhooks => {
+                if let Some(secret) = &state.email_webhook_secret {
+                    router.merge(with_security_headers(
+                        mas_handlers::email_webhooks_router::<AppState>(secret),
+                        api_headers,
+                    ))
+                } else {
+                    tracing::warn!(
+                        "The email webhooks resource is enabled, but `email.webhook_secret` is not set, not serving them"
+                    );
+                    router
+                }
             }
             // TODO: do a better handler here
             mas_config::HttpResource::ConnectionInfo => router.route(
@@ -245,6 +445,15 @@ pub fn build_router(
 
     router = router.fallback(mas_handlers::fallback);
 
+    if let Some(access_log) = access_log {
+        router = router.layer(
+            access_log
+                .clone()
+                .with_listener_name(name)
+                .with_route_fn(access_log_route),
+        );
+    }
+
     router
         .layer(
             InFlightCounterLayer::new("http.server.active_requests").on_request((
@@ -276,13 +485,315 @@ pub fn build_router(
         .with_state(state)
 }
 
-pub fn build_tls_server_config(config: &HttpTlsConfig) -> Result<ServerConfig, anyhow::Error> {
+/// The router of an additional issuer, along with the requests it serves
+#[derive(Clone)]
+pub struct IssuerRouter {
+    host: String,
+    port: Option<u16>,
+    path: String,
+    router: Router<()>,
+}
+
+impl IssuerRouter {
+    /// Serve the requests for the given public base with this router
+    ///
+    /// # Panics
+    ///
+    /// Panics if the public base does not have a host
+    pub fn new(public_base: &Url, router: Router<()>) -> Self {
+        Self {
+            host: public_base
+                .host_str()
+                .expect("public base must have a host")
+                .to_ascii_lowercase(),
+            port: public_base.port(),
+            path: public_base.path().trim_end_matches('/').to_owned(),
+            router,
+        }
+    }
+
+    /// The path prefix of the issuer, if any
+    pub fn prefix(public_base: &Url) -> Option<&str> {
+        let path = public_base.path().trim_end_matches('/');
+        if path.is_empty() {
+            None
+        } else {
+            Some(path)
+        }
+    }
+
+    fn matches<B>(&self, request: &Request<B>) -> bool {
+        // HTTP/2 requests carry the host in the URI instead of the Host header
+        let authority = request
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Authority>().ok())
+            .or_else(|| request.uri().authority().cloned());
+
+        let Some(authority) = authority else {
+            return false;
+        };
+
+        if !authority.host().eq_ignore_ascii_case(&self.host) {
+            return false;
+        }
+
+        if self.port.is_some() && authority.port_u16() != self.port {
+            return false;
+        }
+
+        let path = request.uri().path();
+        path == self.path || path.starts_with(&format!("{}/", self.path))
+    }
+}
+
+/// Dispatch the requests to the router of the issuer they are for, falling
+/// back to the router of the main issuer
+pub fn build_multi_issuer_router(main: Router<()>, issuers: Vec<IssuerRouter>) -> Router<()> {
+    if issuers.is_empty() {
+        return main;
+    }
+
+    let issuers = Arc::new(issuers);
+    let service = service_fn(move |request: Request<Body>| {
+        // Pick the most specific issuer matching the request
+        let router = issuers
+            .iter()
+            .filter(|issuer| issuer.matches(&request))
+            .max_by_key(|issuer| issuer.path.len())
+            .map_or_else(|| main.clone(), |issuer| issuer.router.clone());
+
+        router.oneshot(request)
+    });
+
+    Router::new().fallback_service(service)
+}
+
+/// A TLS certificate and its key, which can be reloaded from the configuration
+/// without restarting the server, for example after a certificate renewal
+#[derive(Debug)]
+pub struct ReloadableCertificate {
+    config: HttpTlsConfig,
+    current: ArcSwap<CertifiedKey>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl ReloadableCertificate {
+    /// Load the certificate and key from the configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate or key could not be loaded
+    pub fn load(config: &HttpTlsConfig) -> Result<Self, anyhow::Error> {
+        let modified = files_modified(config);
+        let current = load_certified_key(config)?;
+        Ok(Self {
+            config: config.clone(),
+            current: ArcSwap::from_pointee(current),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Reload the certificate and key from the configuration
+    ///
+    /// The current certificate is kept if the new one could not be loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate or key could not be loaded
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let modified = files_modified(&self.config);
+        let certified_key = load_certified_key(&self.config)?;
+        self.current.store(Arc::new(certified_key));
+        *self.modified.lock().unwrap() = modified;
+        Ok(())
+    }
+
+    /// Reload the certificate and key if one of their files changed since
+    /// they were last loaded
+    ///
+    /// Returns `true` if they were reloaded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate or key could not be loaded
+    pub fn reload_if_changed(&self) -> Result<bool, anyhow::Error> {
+        if *self.modified.lock().unwrap() == files_modified(&self.config) {
+            return Ok(false);
+        }
+
+        self.reload()?;
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+/// The last modification time of the files the certificate and key are loaded
+/// from
+fn files_modified(config: &HttpTlsConfig) -> Vec<Option<SystemTime>> {
+    [
+        &config.certificate_file,
+        &config.key_file,
+        &config.password_file,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+    .collect()
+}
+
+fn load_certified_key(config: &HttpTlsConfig) -> Result<CertifiedKey, anyhow::Error> {
     let (key, chain) = config.load()?;
 
-    let mut config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(chain, key)
-        .context("failed to build TLS server config")?;
+    let provider = CryptoProvider::get_default().context("no default crypto provider")?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .context("unsupported TLS private key")?;
+
+    Ok(CertifiedKey::new(chain, key))
+}
+
+/// How often the certificate files are checked for changes
+const CERTIFICATE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Periodically reload the certificates when their files change, until the
+/// cancellation token is cancelled
+pub async fn watch_certificates(
+    certificates: Vec<Arc<ReloadableCertificate>>,
+    cancellation_token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(CERTIFICATE_CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            () = cancellation_token.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        for certificate in &certificates {
+            match certificate.reload_if_changed() {
+                Ok(true) => tracing::info!("TLS certificate changed on disk, reloaded it"),
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::error!(
+                        ?err,
+                        "Could not reload the TLS certificate, keeping the current one"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// How often the overrides of the feature flags are reloaded from the database
+const FEATURE_FLAGS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Load the overrides of the feature flags from the database
+pub async fn load_feature_flag_overrides(
+    pool: &PgPool,
+    feature_flags: &FeatureFlags,
+) -> Result<(), anyhow::Error> {
+    let mut repo = PgRepository::from_pool(pool).await?.boxed();
+    let overrides = repo.feature_flag().all().await?;
+    repo.cancel().await?;
+
+    feature_flags.set_overrides(overrides);
+    Ok(())
+}
+
+/// Periodically reload the overrides of the feature flags, until the
+/// cancellation token is cancelled
+pub async fn watch_feature_flags(
+    pool: PgPool,
+    feature_flags: FeatureFlags,
+    cancellation_token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(FEATURE_FLAGS_REFRESH_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            () = cancellation_token.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(err) = load_feature_flag_overrides(&pool, &feature_flags).await {
+            tracing::error!(
+                ?err,
+                "Could not reload the feature flag overrides, keeping the current ones"
+            );
+        }
+    }
+}
+
+/// Build the verifier of the client certificates, if the listener has a client
+/// CA set
+fn build_client_cert_verifier(
+    config: &HttpTlsConfig,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>, anyhow::Error> {
+    let client_crls = config.load_client_crls()?;
+    let Some(client_ca) = config.load_client_ca()? else {
+        if client_crls.is_some() {
+            anyhow::bail!("TLS client CRLs are set without a client CA");
+        }
+
+        return Ok(None);
+    };
+
+    let mut roots = RootCertStore::empty();
+    for certificate in client_ca {
+        roots
+            .add(certificate)
+            .context("invalid TLS client CA certificate")?;
+    }
+
+    // Client certificates are optional: they are only used to log in, and
+    // clients without one must still be able to use the service
+    let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots)).allow_unauthenticated();
+
+    // Only the revocation of the client certificates themselves is checked, and
+    // the ones issued by an authority without a revocation list are refused
+    if let Some(client_crls) = client_crls {
+        verifier = verifier
+            .with_crls(client_crls)
+            .only_check_end_entity_revocation();
+    }
+
+    let verifier = verifier
+        .build()
+        .context("failed to build TLS client certificate verifier")?;
+
+    Ok(Some(verifier))
+}
+
+pub fn build_tls_server_config(
+    config: &HttpTlsConfig,
+    certificate: Arc<ReloadableCertificate>,
+) -> Result<ServerConfig, anyhow::Error> {
+    let builder = rustls::ServerConfig::builder();
+    let builder = if let Some(verifier) = build_client_cert_verifier(config)? {
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    let mut config = builder.with_cert_resolver(certificate);
     config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
     Ok(config)
@@ -356,3 +867,229 @@ pub fn build_listeners(
 
     Ok(listeners)
 }
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::{CertificateDer, UnixTime};
+
+    use super::*;
+
+    async fn served_by(router: &Router<()>, host: &str, path: &str) -> String {
+        let request = Request::get(path)
+            .header(HOST, host)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn named_router(name: &'static str) -> Router<()> {
+        Router::new().fallback(move || async move { name })
+    }
+
+    #[tokio::test]
+    async fn test_multi_issuer_router() {
+        let issuers = vec![
+            IssuerRouter::new(
+                &"https://legacy.example.com/".parse().unwrap(),
+                named_router("legacy"),
+            ),
+            IssuerRouter::new(
+                &"https://example.com:8443/auth/".parse().unwrap(),
+                named_router("prefixed"),
+            ),
+        ];
+        let router = build_multi_issuer_router(named_router("main"), issuers);
+
+        assert_eq!(served_by(&router, "auth.example.com", "/").await, "main");
+        assert_eq!(
+            served_by(&router, "LEGACY.example.com", "/oauth2/token").await,
+            "legacy"
+        );
+        assert_eq!(
+            served_by(&router, "legacy.example.com:443", "/").await,
+            "legacy"
+        );
+        assert_eq!(
+            served_by(&router, "example.com:8443", "/auth/oauth2/token").await,
+            "prefixed"
+        );
+        assert_eq!(
+            served_by(&router, "example.com:8443", "/auth").await,
+            "prefixed"
+        );
+        assert_eq!(
+            served_by(&router, "example.com:8443", "/authz").await,
+            "main"
+        );
+        assert_eq!(served_by(&router, "example.com", "/auth/").await, "main");
+    }
+
+    #[test]
+    fn test_reloadable_certificate() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let certs = camino::Utf8Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../listener/examples/demo/certs");
+        let dir = std::env::temp_dir().join(format!("mas-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certificate_file = camino::Utf8PathBuf::try_from(dir.join("cert.pem")).unwrap();
+        let key_file = camino::Utf8PathBuf::try_from(dir.join("key.pem")).unwrap();
+
+        let install = |name: &str, modified: SystemTime| {
+            std::fs::copy(certs.join(format!("{name}.pem")), &certificate_file).unwrap();
+            std::fs::copy(certs.join(format!("{name}-key.pem")), &key_file).unwrap();
+            for path in [&certificate_file, &key_file] {
+                std::fs::File::options()
+                    .write(true)
+                    .open(path)
+                    .unwrap()
+                    .set_modified(modified)
+                    .unwrap();
+            }
+        };
+
+        install("server", SystemTime::UNIX_EPOCH);
+        let config = HttpTlsConfig {
+            certificate: None,
+            certificate_file: Some(certificate_file.clone()),
+            key: None,
+            key_file: Some(key_file.clone()),
+            password: None,
+            password_file: None,
+            client_ca: None,
+            client_ca_file: None,
+            client_crl: None,
+            client_crl_file: None,
+        };
+        let certificate = ReloadableCertificate::load(&config).unwrap();
+        let initial = certificate.current.load_full();
+        assert!(!certificate.reload_if_changed().unwrap());
+
+        // Renew the certificate
+        install("client", SystemTime::now());
+        assert!(certificate.reload_if_changed().unwrap());
+        assert_ne!(certificate.current.load().cert, initial.cert);
+        assert!(!certificate.reload_if_changed().unwrap());
+
+        // A broken certificate is not loaded
+        std::fs::write(&certificate_file, "not a certificate").unwrap();
+        assert!(certificate.reload_if_changed().is_err());
+        assert_ne!(certificate.current.load().cert, initial.cert);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_client_certificate_revocation() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let certs =
+            camino::Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../handlers/tests/certs");
+        let pem = |name: &str| std::fs::read_to_string(certs.join(name)).unwrap();
+        let der = |name: &str| CertificateDer::from(std::fs::read(certs.join(name)).unwrap());
+        let now = UnixTime::now();
+
+        let mut config = HttpTlsConfig {
+            certificate: None,
+            certificate_file: None,
+            key: None,
+            key_file: None,
+            password: None,
+            password_file: None,
+            client_ca: Some(pem("ca.pem")),
+            client_ca_file: None,
+            client_crl: None,
+            client_crl_file: None,
+        };
+
+        // Without a revocation list, revoked certificates are still accepted
+        let verifier = build_client_cert_verifier(&config).unwrap().unwrap();
+        assert!(verifier
+            .verify_client_cert(&der("alice.der"), &[], now)
+            .is_ok());
+        assert!(verifier
+            .verify_client_cert(&der("bob.der"), &[], now)
+            .is_ok());
+
+        // With one, they are refused
+        config.client_crl = Some(pem("crl.pem"));
+        let verifier = build_client_cert_verifier(&config).unwrap().unwrap();
+        assert!(verifier
+            .verify_client_cert(&der("alice.der"), &[], now)
+            .is_ok());
+        assert!(matches!(
+            verifier.verify_client_cert(&der("bob.der"), &[], now),
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::Revoked
+            ))
+        ));
+
+        // Revocation lists are useless without a client CA
+        config.client_ca = None;
+        assert!(build_client_cert_verifier(&config).is_err());
+    }
+
+    #[test]
+    fn test_is_fingerprinted_asset() {
+        assert!(is_fingerprinted_asset("/main-DnVeE3dY.js"));
+        assert!(is_fingerprinted_asset("/main-DnVeE3dY.js.map"));
+        assert!(is_fingerprinted_asset(
+            "/inter-latin-400-normal-C38fXH4l.woff2"
+        ));
+        assert!(is_fingerprinted_asset("/templates-Bz_-3xA9.css"));
+        assert!(!is_fingerprinted_asset("/manifest.json"));
+        assert!(!is_fingerprinted_asset("/main.js"));
+        assert!(!is_fingerprinted_asset("/-DnVeE3dY.js"));
+        assert!(!is_fingerprinted_asset("/some-file.js"));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let layer = build_compression(&HttpCompressionConfig::default()).unwrap();
+        let router = Router::new()
+            .route("/small", axum::routing::get(|| async { "small" }))
+            .route(
+                "/large",
+                axum::routing::get(|| async { "large ".repeat(1000) }),
+            )
+            .route(
+                "/font",
+                axum::routing::get(|| async {
+                    (
+                        [(http::header::CONTENT_TYPE, "font/woff2")],
+                        "a".repeat(2000),
+                    )
+                }),
+            )
+            .layer(layer);
+
+        let encoding = |path: &'static str| {
+            let router = router.clone();
+            async move {
+                let request = Request::get(path)
+                    .header(http::header::ACCEPT_ENCODING, "br, gzip")
+                    .body(Body::empty())
+                    .unwrap();
+                let response = router.oneshot(request).await.unwrap();
+                response
+                    .headers()
+                    .get(http::header::CONTENT_ENCODING)
+                    .map(|value| value.to_str().unwrap().to_owned())
+            }
+        };
+
+        assert_eq!(encoding("/small").await, None);
+        assert_eq!(encoding("/large").await.as_deref(), Some("br"));
+        assert_eq!(encoding("/font").await, None);
+
+        let config = HttpCompressionConfig {
+            enabled: false,
+            ..HttpCompressionConfig::default()
+        };
+        assert!(build_compression(&config).is_none());
+    }
+}
@@ -4,25 +4,102 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::time::Duration;
+use std::{num::NonZeroU32, sync::Arc, time::Duration};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use mas_config::{
-    AccountConfig, BrandingConfig, CaptchaConfig, DatabaseConfig, EmailConfig, EmailSmtpMode,
-    EmailTransportKind, ExperimentalConfig, MatrixConfig, PasswordsConfig, PolicyConfig,
-    TemplatesConfig,
+    AccessLogConfig, AccountConfig, AlertEvent, AlertSeverity, AlertsConfig, BrandingConfig,
+    CaptchaConfig, CertificateLoginConfig, CertificateLookupConfig, ClientsConfig,
+    CodeBindingConfig, CompatPasswordLoginConfig, DatabaseConfig, DeviceIdAllocationConfig,
+    EmailConfig, EmailDkimAlgorithm, EmailSmtpMode, EmailTransportKind, ExperimentalConfig,
+    FeatureFlagsConfig, HashingCost, HomeserverKind, HttpClientConfig, MatrixConfig,
+    PasswordsConfig, PolicyConfig, RedirectUriValidationConfig, ScopeIconConfig,
+    SessionEvictionConfig, SessionLimitsConfig, TemplatesConfig, UpstreamOAuth2Config,
 };
-use mas_data_model::SiteConfig;
-use mas_email::{MailTransport, Mailer};
-use mas_handlers::{passwords::PasswordManager, ActivityTracker};
+use mas_data_model::{
+    AuthorizationCodeBinding, CertificateLookup, CompatPasswordLogin, CustomScope,
+    DeviceIdAllocation, Experiment, ExperimentVariant, FeatureFlag, FeatureFlags,
+    RedirectUriValidation, ScopeIcon, SessionEviction, SessionLimits, SiteConfig,
+};
+use mas_email::{
+    DkimConfig, DkimSigningAlgorithm, DkimSigningKey, MailTransport, Mailbox, Mailer,
+    SmtpPoolConfig,
+};
+use mas_handlers::{
+    passwords::{Hasher, PasswordManager, PasswordPolicy, PasswordValidationWebhook},
+    ActivityTracker, HomeserverDiscovery,
+};
+use mas_matrix::{
+    HomeserverConnection, HomeserverFeature, ResilienceOptions, ResilientHomeserverConnection,
+};
+#[cfg(feature = "conduit")]
+use mas_matrix_conduit::ConduitConnection;
+#[cfg(feature = "dendrite")]
+use mas_matrix_dendrite::DendriteConnection;
+use mas_matrix_synapse::SynapseConnection;
 use mas_policy::PolicyFactory;
 use mas_router::UrlBuilder;
 use mas_templates::{SiteConfigExt, TemplateLoadingError, Templates};
+use mas_tower::AccessLogLayer;
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions, PgConnection, PgPool,
 };
-use tracing::{error, info, log::LevelFilter};
+use tracing::{error, info, log::LevelFilter, warn};
+
+use crate::server::ReloadableCertificate;
+
+/// How long hashing a password should take, used as the default target of
+/// `mas-cli doctor hash-bench` and to detect mis-tuned hashing schemes
+pub const PASSWORD_HASHING_TARGET: Duration = Duration::from_millis(250);
+
+/// Warn if hashing a password with the current scheme is drastically faster or
+/// slower than [`PASSWORD_HASHING_TARGET`] on this host
+pub async fn check_password_hashing_cost(password_manager: &PasswordManager) {
+    if !password_manager.is_enabled() {
+        return;
+    }
+
+    let elapsed = match password_manager.benchmark().await {
+        Ok(elapsed) => elapsed,
+        Err(e) => {
+            warn!(
+                error = &*e as &dyn std::error::Error,
+                "Could not measure the cost of the password hashing scheme"
+            );
+            return;
+        }
+    };
+
+    if elapsed < PASSWORD_HASHING_TARGET / 4 {
+        warn!(
+            ?elapsed,
+            "Hashing a password is very fast on this host, which makes the hashes easier to brute-force. Consider tuning the password hashing scheme with `mas-cli doctor hash-bench`."
+        );
+    } else if elapsed > PASSWORD_HASHING_TARGET * 4 {
+        warn!(
+            ?elapsed,
+            "Hashing a password is very slow on this host, which makes logins slow and the server easier to overload. Consider tuning the password hashing scheme with `mas-cli doctor hash-bench`."
+        );
+    } else {
+        info!(?elapsed, "Password hashing cost checked");
+    }
+}
+
+/// Build the password hasher for the given algorithm and cost parameters
+pub fn hasher(
+    algorithm: mas_config::PasswordAlgorithm,
+    cost: HashingCost,
+    secret: Option<Vec<u8>>,
+) -> Hasher {
+    match algorithm {
+        mas_config::PasswordAlgorithm::Pbkdf2 => Hasher::pbkdf2(secret),
+        mas_config::PasswordAlgorithm::Bcrypt => Hasher::bcrypt(cost.cost, secret),
+        mas_config::PasswordAlgorithm::Argon2id => {
+            Hasher::argon2id_with_cost(cost.memory_cost, cost.time_cost, cost.parallelism, secret)
+        }
+    }
+}
 
 pub async fn password_manager_from_config(
     config: &PasswordsConfig,
@@ -35,25 +112,47 @@ pub async fn password_manager_from_config(
         .load()
         .await?
         .into_iter()
-        .map(|(version, algorithm, cost, secret)| {
-            use mas_handlers::passwords::Hasher;
-            let hasher = match algorithm {
-                mas_config::PasswordAlgorithm::Pbkdf2 => Hasher::pbkdf2(secret),
-                mas_config::PasswordAlgorithm::Bcrypt => Hasher::bcrypt(cost, secret),
-                mas_config::PasswordAlgorithm::Argon2id => Hasher::argon2id(secret),
+        .map(|(version, algorithm, cost, secret)| (version, hasher(algorithm, cost, secret)));
+
+    let policy_classes = config
+        .policy_classes()
+        .iter()
+        .map(|(name, class)| {
+            let policy = PasswordPolicy {
+                minimum_complexity: class
+                    .minimum_complexity
+                    .unwrap_or(config.minimum_complexity()),
+                minimum_length: class.minimum_length,
+                max_age: class.max_age,
             };
 
-            (version, hasher)
-        });
+            (name.clone(), policy)
+        })
+        .collect();
+
+    let mut password_manager = PasswordManager::new(config.minimum_complexity(), schemes)?
+        .with_policy_classes(config.max_age(), policy_classes)
+        .with_concurrency_limit(config.hashing_concurrency(), config.hashing_queue_timeout());
+
+    if let Some(webhook) = config.validation_webhook() {
+        password_manager =
+            password_manager.with_validation_webhook(PasswordValidationWebhook::new(
+                mas_http::reqwest_client(),
+                webhook.url.clone(),
+                webhook.timeout,
+                webhook.fail_open,
+            ));
+    }
 
-    PasswordManager::new(config.minimum_complexity(), schemes)
+    Ok(password_manager)
 }
 
 pub fn mailer_from_config(
     config: &EmailConfig,
     templates: &Templates,
+    http_client: reqwest::Client,
 ) -> Result<Mailer, anyhow::Error> {
-    let from = config
+    let from: Mailbox = config
         .from
         .parse()
         .context("invalid email configuration: invalid 'from' address")?;
@@ -90,13 +189,70 @@ pub fn mailer_from_config(
                 EmailSmtpMode::Tls => mas_email::SmtpMode::Tls,
             };
 
-            MailTransport::smtp(mode, hostname, config.port(), credentials)
+            let mut pool_config = SmtpPoolConfig::new();
+            if let Some(max_size) = config.pool_max_size() {
+                pool_config = pool_config.max_size(max_size.get());
+            }
+            if let Some(idle_timeout) = config.pool_idle_timeout() {
+                pool_config = pool_config.idle_timeout(idle_timeout);
+            }
+
+            MailTransport::smtp(mode, hostname, config.port(), credentials, pool_config)
                 .context("failed to build SMTP transport")?
         }
         EmailTransportKind::Sendmail => MailTransport::sendmail(config.command()),
+        EmailTransportKind::Http => {
+            // This should have been set ahead of time
+            let endpoint = config
+                .endpoint()
+                .context("invalid email configuration: missing endpoint")?;
+
+            MailTransport::http(
+                http_client,
+                endpoint.clone(),
+                config.token().map(ToOwned::to_owned),
+            )
+        }
     };
 
-    Ok(Mailer::new(templates.clone(), transport, from, reply_to))
+    let mut mailer = Mailer::new(templates.clone(), transport, from.clone(), reply_to);
+
+    if let Some(dkim) = config.dkim() {
+        let key = match (&dkim.key, &dkim.key_file) {
+            (Some(key), None) => key.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read the DKIM key from {path}"))?,
+            _ => bail!("invalid email configuration: DKIM needs one of `key` or `key_file`"),
+        };
+
+        let algorithm = match dkim.algorithm {
+            EmailDkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+            EmailDkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+        };
+
+        let key = DkimSigningKey::new(key.trim(), algorithm)
+            .map_err(|e| anyhow::anyhow!("invalid DKIM key: {e}"))?;
+
+        let domain = dkim
+            .domain
+            .clone()
+            .unwrap_or_else(|| from.email.domain().to_owned());
+
+        mailer = mailer.with_dkim(DkimConfig::default_config(
+            dkim.selector.clone(),
+            domain,
+            key,
+        ));
+    }
+
+    if let Some(limit) = config.domain_rate_limit() {
+        let quota = limit
+            .to_quota()
+            .context("invalid email configuration: invalid domain rate limit")?;
+        mailer = mailer.with_domain_rate_limit(quota);
+    }
+
+    Ok(mailer)
 }
 
 pub async fn policy_factory_from_config(
@@ -146,6 +302,7 @@ pub fn captcha_config_from_config(
     }))
 }
 
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
 pub fn site_config_from_config(
     branding_config: &BrandingConfig,
     matrix_config: &MatrixConfig,
@@ -153,11 +310,17 @@ pub fn site_config_from_config(
     password_config: &PasswordsConfig,
     account_config: &AccountConfig,
     captcha_config: &CaptchaConfig,
+    certificate_login_config: &CertificateLoginConfig,
+    session_limits_config: &SessionLimitsConfig,
+    clients_config: &ClientsConfig,
+    upstream_oauth2_config: &UpstreamOAuth2Config,
+    feature_flags_config: &FeatureFlagsConfig,
 ) -> Result<SiteConfig, anyhow::Error> {
     let captcha = captcha_config_from_config(captcha_config)?;
     Ok(SiteConfig {
         access_token_ttl: experimental_config.access_token_ttl,
         compat_token_ttl: experimental_config.compat_token_ttl,
+        jwt_leeway: upstream_oauth2_config.jwt_leeway,
         server_name: matrix_config.homeserver.clone(),
         policy_uri: branding_config.policy_uri.clone(),
         tos_uri: branding_config.tos_uri.clone(),
@@ -165,14 +328,156 @@ pub fn site_config_from_config(
         password_login_enabled: password_config.enabled(),
         password_registration_enabled: password_config.enabled()
             && account_config.password_registration_enabled,
+        compat_password_login: match password_config.compat_login() {
+            CompatPasswordLoginConfig::Allowed => CompatPasswordLogin::Allowed,
+            CompatPasswordLoginConfig::Deprecated => CompatPasswordLogin::Deprecated,
+            CompatPasswordLoginConfig::Disabled => CompatPasswordLogin::Disabled,
+        },
         email_change_allowed: account_config.email_change_allowed,
+        email_change_requires_reauthentication: account_config
+            .email_change_requires_reauthentication,
         displayname_change_allowed: account_config.displayname_change_allowed,
+        avatar_change_allowed: account_config.avatar_change_allowed,
+        displayname_locked_to_upstream: account_config.displayname_locked_to_upstream,
         password_change_allowed: password_config.enabled()
             && account_config.password_change_allowed,
         account_recovery_allowed: password_config.enabled()
             && account_config.password_recovery_enabled,
         captcha,
+        bot_detection: captcha_config.bot_detection.enabled.then_some(
+            mas_data_model::BotDetectionConfig {
+                captcha_threshold: captcha_config.bot_detection.captcha_threshold,
+                block_threshold: captcha_config.bot_detection.block_threshold,
+            },
+        ),
+        certificate_login: certificate_login_config.enabled.then(|| {
+            mas_data_model::CertificateLoginConfig {
+                template: certificate_login_config.template.clone(),
+                lookup: match certificate_login_config.lookup {
+                    CertificateLookupConfig::Username => CertificateLookup::Username,
+                    CertificateLookupConfig::Email => CertificateLookup::Email,
+                },
+            }
+        }),
+        matrix_id_login_enabled: account_config.matrix_id_login_enabled,
+        session_limits: SessionLimits {
+            max_sessions_per_user: session_limits_config
+                .max_sessions_per_user
+                .map(NonZeroU32::get),
+            client_overrides: clients_config
+                .iter()
+                .filter_map(|client| Some((client.client_id, client.max_sessions_per_user?.get())))
+                .collect(),
+            eviction: match session_limits_config.eviction {
+                SessionEvictionConfig::Reject => SessionEviction::Reject,
+                SessionEvictionConfig::RevokeOldest => SessionEviction::RevokeOldest,
+            },
+        },
         minimum_password_complexity: password_config.minimum_complexity(),
+        offline_access_required: experimental_config.offline_access_required,
+        offline_access_overrides: clients_config
+            .iter()
+            .filter_map(|client| Some((client.client_id, client.offline_access_required?)))
+            .collect(),
+        code_binding_overrides: clients_config
+            .iter()
+            .filter_map(|client| {
+                let binding = match client.code_binding {
+                    CodeBindingConfig::Off => return None,
+                    CodeBindingConfig::Lenient => AuthorizationCodeBinding::Lenient,
+                    CodeBindingConfig::Strict => AuthorizationCodeBinding::Strict,
+                };
+                Some((client.client_id, binding))
+            })
+            .collect(),
+        redirect_uri_validation_overrides: clients_config
+            .iter()
+            .filter_map(|client| {
+                let validation = match client.redirect_uri_validation? {
+                    RedirectUriValidationConfig::Default => RedirectUriValidation::Default,
+                    RedirectUriValidationConfig::Exact => RedirectUriValidation::Exact,
+                    RedirectUriValidationConfig::Native => RedirectUriValidation::Native,
+                };
+                Some((client.client_id, validation))
+            })
+            .collect(),
+        device_code_user_code_length: experimental_config.device_code_user_code_length,
+        device_code_user_code_charset: experimental_config
+            .device_code_user_code_charset
+            .chars()
+            .collect(),
+        custom_scopes: branding_config
+            .scopes
+            .iter()
+            .map(|(scope, config)| {
+                let icon = match config.icon {
+                    ScopeIconConfig::Info => ScopeIcon::Info,
+                    ScopeIconConfig::UserProfile => ScopeIcon::UserProfile,
+                    ScopeIconConfig::Chat => ScopeIcon::Chat,
+                    ScopeIconConfig::Send => ScopeIcon::Send,
+                    ScopeIconConfig::Computer => ScopeIcon::Computer,
+                    ScopeIconConfig::Devices => ScopeIcon::Devices,
+                    ScopeIconConfig::Time => ScopeIcon::Time,
+                    ScopeIconConfig::Key => ScopeIcon::Key,
+                    ScopeIconConfig::Email => ScopeIcon::Email,
+                    ScopeIconConfig::Settings => ScopeIcon::Settings,
+                    ScopeIconConfig::Admin => ScopeIcon::Admin,
+                    ScopeIconConfig::Lock => ScopeIcon::Lock,
+                    ScopeIconConfig::Warning => ScopeIcon::Warning,
+                    ScopeIconConfig::Error => ScopeIcon::Error,
+                };
+
+                let custom_scope = CustomScope {
+                    icon,
+                    description: config.description.clone(),
+                    translations: config.translations.clone(),
+                };
+                (scope.clone(), custom_scope)
+            })
+            .collect(),
+        device_id_allocation: match matrix_config.device_id_allocation {
+            DeviceIdAllocationConfig::ClientProvided => DeviceIdAllocation::ClientProvided,
+            DeviceIdAllocationConfig::Random => DeviceIdAllocation::Random,
+            DeviceIdAllocationConfig::Deterministic => DeviceIdAllocation::Deterministic,
+        },
+        reuse_devices: matrix_config.reuse_devices,
+        native_registration_clients: clients_config
+            .iter()
+            .filter(|client| client.native_registration)
+            .map(|client| client.client_id)
+            .collect(),
+        upstream_provider_hint_clients: clients_config
+            .iter()
+            .filter(|client| client.upstream_provider_hints)
+            .map(|client| client.client_id)
+            .collect(),
+        client_upstream_providers: clients_config
+            .iter()
+            .filter_map(|client| Some((client.client_id, client.upstream_provider?)))
+            .collect(),
+        feature_flags: FeatureFlags::new(
+            feature_flags_config.flags.iter().map(|flag| FeatureFlag {
+                name: flag.name.clone(),
+                enabled: flag.enabled,
+                rollout_percentage: flag.rollout_percentage,
+                users: flag.users.clone(),
+            }),
+            feature_flags_config
+                .experiments
+                .iter()
+                .map(|experiment| Experiment {
+                    name: experiment.name.clone(),
+                    enabled: experiment.enabled,
+                    variants: experiment
+                        .variants
+                        .iter()
+                        .map(|variant| ExperimentVariant {
+                            name: variant.name.clone(),
+                            weight: variant.weight,
+                        })
+                        .collect(),
+                }),
+        ),
     })
 }
 
@@ -186,6 +491,7 @@ pub async fn templates_from_config(
         url_builder.clone(),
         config.assets_manifest.clone(),
         config.translations_path.clone(),
+        config.email_overrides_path.clone(),
         site_config.templates_branding(),
         site_config.templates_features(),
     )
@@ -287,10 +593,20 @@ fn database_connect_options_from_config(
 #[tracing::instrument(name = "db.connect", skip_all, err(Debug))]
 pub async fn database_pool_from_config(config: &DatabaseConfig) -> Result<PgPool, anyhow::Error> {
     let options = database_connect_options_from_config(config)?;
+
+    // The statement timeout is only set on the pool, so that it doesn't apply to
+    // the migrations run with a single connection
+    let options = if let Some(timeout) = config.statement_timeout {
+        options.options([("statement_timeout", timeout.as_millis())])
+    } else {
+        options
+    };
+
+    mas_storage_pg::set_slow_query_threshold(config.slow_query_threshold);
     PgPoolOptions::new()
         .max_connections(config.max_connections.into())
         .min_connections(config.min_connections)
-        .acquire_timeout(config.connect_timeout)
+        .acquire_timeout(config.acquire_timeout.unwrap_or(config.connect_timeout))
         .idle_timeout(config.idle_timeout)
         .max_lifetime(config.max_lifetime)
         .connect_with(options)
@@ -309,16 +625,220 @@ pub async fn database_connection_from_config(
         .context("could not connect to the database")
 }
 
-/// Reload templates on SIGHUP
+fn http_client_options_from_config(
+    config: &HttpClientConfig,
+) -> Result<mas_http::ReqwestClientOptions, anyhow::Error> {
+    let extra_root_certificates = config
+        .load_ca_certificates()
+        .context("could not load the additional CA certificates")?;
+
+    Ok(mas_http::ReqwestClientOptions {
+        proxy: config.proxy.clone(),
+        https_proxy: config.https_proxy.clone(),
+        no_proxy: config.no_proxy.clone(),
+        extra_root_certificates,
+    })
+}
+
+/// Create the HTTP client used for outgoing requests from the configuration
+pub fn http_client_from_config(
+    config: &HttpClientConfig,
+) -> Result<reqwest::Client, anyhow::Error> {
+    let options = http_client_options_from_config(config)?;
+    mas_http::reqwest_client_with_options(&options).context("could not build the HTTP client")
+}
+
+/// Create the discovery of other homeservers from the configuration, with an
+/// HTTP client which only connects to public addresses
+pub fn homeserver_discovery_from_config(
+    config: &HttpClientConfig,
+) -> Result<HomeserverDiscovery, anyhow::Error> {
+    let options = http_client_options_from_config(config)?;
+    let http_client = mas_http::reqwest_public_client_with_options(&options)
+        .context("could not build the HTTP client")?;
+    Ok(HomeserverDiscovery::new(http_client))
+}
+
+/// A connection to any kind of homeserver
+pub type HomeserverBackend = Arc<dyn HomeserverConnection<Error = anyhow::Error>>;
+
+/// Create the connection to the homeserver from the configuration, depending
+/// on its kind
+///
+/// # Errors
+///
+/// Returns an error if this build doesn't support the kind of homeserver
+pub fn homeserver_backend_from_config(
+    config: &MatrixConfig,
+    http_client: reqwest::Client,
+    #[allow(unused_variables)] rng: &mut impl rand::RngCore,
+) -> anyhow::Result<HomeserverBackend> {
+    match config.kind {
+        HomeserverKind::Synapse => Ok(Arc::new(SynapseConnection::new(
+            config.homeserver.clone(),
+            config.endpoint.clone(),
+            config.secret.clone(),
+            http_client,
+        ))),
+
+        #[cfg(feature = "dendrite")]
+        HomeserverKind::Dendrite => Ok(Arc::new(DendriteConnection::new(
+            config.homeserver.clone(),
+            config.endpoint.clone(),
+            config.secret.clone(),
+            http_client,
+        ))),
+
+        #[cfg(feature = "conduit")]
+        HomeserverKind::Conduit => {
+            let admin_room = config
+                .admin_room
+                .clone()
+                .context("the admin room of the homeserver is required to connect to Conduit")?;
+
+            Ok(Arc::new(ConduitConnection::new(
+                config.homeserver.clone(),
+                config.endpoint.clone(),
+                config.secret.clone(),
+                admin_room,
+                http_client,
+                rng,
+            )))
+        }
+
+        #[allow(unreachable_patterns)]
+        kind => bail!(
+            "this build doesn't support {kind:?} homeservers, enable the corresponding feature"
+        ),
+    }
+}
+
+/// Create the connection to the homeserver from the configuration, with its
+/// timeouts, retries and concurrency limits
+///
+/// # Errors
+///
+/// Returns an error if this build doesn't support the kind of homeserver
+pub fn homeserver_connection_from_config(
+    config: &MatrixConfig,
+    http_client: reqwest::Client,
+    rng: &mut impl rand::RngCore,
+) -> anyhow::Result<ResilientHomeserverConnection<HomeserverBackend>> {
+    let backend = homeserver_backend_from_config(config, http_client, rng)?;
+
+    let options = ResilienceOptions {
+        timeout: config.connection.timeout,
+        max_retries: config.connection.max_retries,
+        retry_base_delay: config.connection.retry_base_delay,
+        max_concurrency: config.connection.max_concurrency,
+        queue_timeout: config.connection.queue_timeout,
+        failure_threshold: config.connection.circuit_breaker_threshold,
+        reset_after: config.connection.circuit_breaker_reset_after,
+    };
+
+    Ok(ResilientHomeserverConnection::new(backend, options, rng))
+}
+
+/// Probe the features supported by the homeserver, so that the connection uses
+/// the right admin API endpoints, and log the unsupported ones
+///
+/// Failures are only logged, as the homeserver may not be running yet, in
+/// which case all features are assumed to be available.
+pub async fn probe_homeserver(connection: &impl HomeserverConnection<Error = anyhow::Error>) {
+    if let Err(e) = connection.refresh_capabilities().await {
+        warn!(
+            error = &*e as &dyn std::error::Error,
+            "Failed to probe the homeserver capabilities, assuming it supports all features"
+        );
+        return;
+    }
+
+    let unsupported: Vec<&str> = connection
+        .capabilities()
+        .unsupported()
+        .map(HomeserverFeature::as_str)
+        .collect();
+    if !unsupported.is_empty() {
+        info!(
+            ?unsupported,
+            "The homeserver doesn't support some features, they will be skipped or fail"
+        );
+    }
+}
+
+/// Create the alerter used by the workers from the configuration
+pub fn alerter_from_config(config: &AlertsConfig) -> mas_tasks::Alerter {
+    fn severity(severity: AlertSeverity) -> mas_tasks::AlertSeverity {
+        match severity {
+            AlertSeverity::Info => mas_tasks::AlertSeverity::Info,
+            AlertSeverity::Warning => mas_tasks::AlertSeverity::Warning,
+            AlertSeverity::Critical => mas_tasks::AlertSeverity::Critical,
+        }
+    }
+
+    let Some(room_id) = &config.room_id else {
+        return mas_tasks::Alerter::disabled();
+    };
+
+    let mut alerter =
+        mas_tasks::Alerter::new(room_id.clone()).with_min_severity(severity(config.min_severity));
+
+    for (event, value) in &config.severities {
+        let event = match event {
+            AlertEvent::EmailDeadLettered => mas_tasks::AlertEvent::EmailDeadLettered,
+            AlertEvent::RegistrationSpike => mas_tasks::AlertEvent::RegistrationSpike,
+            AlertEvent::UpstreamProviderDown => mas_tasks::AlertEvent::UpstreamProviderDown,
+        };
+        alerter = alerter.with_severity(event, severity(*value));
+    }
+
+    if let Some(threshold) = config.registration_spike_threshold {
+        alerter = alerter.with_registration_spike_threshold(threshold as usize);
+    }
+
+    alerter
+}
+
+/// Create the access log layer from the configuration
+///
+/// Returns `None` if the access log is disabled
+pub fn access_log_from_config(
+    config: &AccessLogConfig,
+) -> Result<Option<AccessLogLayer>, anyhow::Error> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let layer = if let Some(path) = &config.path {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("could not open access log file {path}"))?;
+        AccessLogLayer::new(file)
+    } else {
+        AccessLogLayer::new(std::io::stdout())
+    };
+
+    let layer = layer
+        .with_redacted_query_parameters(config.redacted_query_parameters.iter().cloned())
+        .with_sample_ratio(config.sample_ratio);
+
+    Ok(Some(layer))
+}
+
+/// Reload templates and TLS certificates on SIGHUP
 pub fn register_sighup(
     templates: &Templates,
     activity_tracker: &ActivityTracker,
+    certificates: &[Arc<ReloadableCertificate>],
 ) -> anyhow::Result<()> {
     #[cfg(unix)]
     {
         let mut signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
         let templates = templates.clone();
         let activity_tracker = activity_tracker.clone();
+        let certificates = certificates.to_vec();
 
         tokio::spawn(async move {
             loop {
@@ -327,12 +847,19 @@ pub fn register_sighup(
                     break;
                 };
 
-                info!("SIGHUP received, reloading templates & flushing activity tracker");
+                info!(
+                    "SIGHUP received, reloading templates & TLS certificates, and flushing activity tracker"
+                );
 
                 activity_tracker.flush().await;
                 templates.clone().reload().await.unwrap_or_else(|err| {
                     error!(?err, "Error while reloading templates");
                 });
+                for certificate in &certificates {
+                    certificate.reload().unwrap_or_else(|err| {
+                        error!(?err, "Error while reloading TLS certificate");
+                    });
+                }
             }
         });
     }
@@ -4,15 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use schemars::gen::SchemaSettings;
-
 fn main() {
-    let settings = SchemaSettings::draft07().with(|s| {
-        s.option_nullable = false;
-        s.option_add_null_type = false;
-    });
-    let gen = settings.into_generator();
-    let schema = gen.into_root_schema_for::<mas_config::RootConfig>();
+    let schema = mas_config::json_schema();
 
     serde_json::to_writer_pretty(std::io::stdout(), &schema).expect("Failed to serialize schema");
 }
@@ -0,0 +1,238 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Loading of the configuration from multiple files
+
+use std::collections::BTreeSet;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use figment::{
+    providers::{Format, Yaml},
+    value::{Dict, Map},
+    Error, Figment, Metadata, Profile, Provider,
+};
+
+use crate::Interpolated;
+
+/// The key of the directive including other files
+const INCLUDE_KEY: &str = "include";
+
+/// A configuration file, with its values interpolated, as described in
+/// [`Interpolated`], and without its `include` directive
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    path: Utf8PathBuf,
+}
+
+impl ConfigFile {
+    fn provider(&self) -> Interpolated<figment::providers::Data<Yaml>> {
+        Interpolated::new(Yaml::file(&self.path))
+    }
+}
+
+impl Provider for ConfigFile {
+    fn metadata(&self) -> Metadata {
+        self.provider().metadata()
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        let mut data = self.provider().data()?;
+        for dict in data.values_mut() {
+            dict.remove(INCLUDE_KEY);
+        }
+        Ok(data)
+    }
+}
+
+/// Merge the given configuration files and directories into a [`Figment`]
+///
+/// The files are merged in the following order, later values taking
+/// precedence over earlier ones:
+///
+///  - the files in the order they are given;
+///  - for a directory, the `.yaml` and `.yml` files it contains, sorted by
+///    name;
+///  - for a file with an `include` directive, the included files, in the order
+///    they are listed, then the file itself. Relative paths are resolved from
+///    the directory of the including file.
+///
+/// Dictionaries are merged recursively, and arrays are concatenated. A file
+/// is only loaded once, even if it is included multiple times.
+///
+/// # Errors
+///
+/// Returns an error if a directory or an included file could not be read, or
+/// if files include each other
+pub fn merge_config_files(base: Figment, paths: &[Utf8PathBuf]) -> Result<Figment, Error> {
+    let mut files = Vec::new();
+    let mut loaded = BTreeSet::new();
+    for path in paths {
+        collect(path, false, &mut Vec::new(), &mut loaded, &mut files)?;
+    }
+
+    Ok(files
+        .into_iter()
+        .fold(base, |figment, path| figment.admerge(ConfigFile { path })))
+}
+
+/// Collect the files to load from the given path, in the order they should be
+/// merged
+fn collect(
+    path: &Utf8Path,
+    required: bool,
+    stack: &mut Vec<Utf8PathBuf>,
+    loaded: &mut BTreeSet<Utf8PathBuf>,
+    files: &mut Vec<Utf8PathBuf>,
+) -> Result<(), Error> {
+    if path.is_dir() {
+        let mut entries = Vec::new();
+        for entry in path
+            .read_dir_utf8()
+            .map_err(|e| Error::from(format!("could not read directory {path}: {e}")))?
+        {
+            let entry =
+                entry.map_err(|e| Error::from(format!("could not read directory {path}: {e}")))?;
+            let entry_path = entry.path();
+            if entry_path.is_file() && matches!(entry_path.extension(), Some("yaml" | "yml")) {
+                entries.push(entry_path.to_owned());
+            }
+        }
+        entries.sort();
+
+        for entry in entries {
+            collect(&entry, true, stack, loaded, files)?;
+        }
+
+        return Ok(());
+    }
+
+    if !path.exists() {
+        if required {
+            return Err(Error::from(format!("configuration file {path} not found")));
+        }
+
+        // Keep the previous behaviour of ignoring missing top-level files
+        files.push(path.to_owned());
+        return Ok(());
+    }
+
+    let canonical = path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned());
+    if stack.contains(&canonical) {
+        return Err(Error::from(format!(
+            "configuration file {path} includes itself"
+        )));
+    }
+    if !loaded.insert(canonical.clone()) {
+        return Ok(());
+    }
+
+    let file = Figment::from(Interpolated::new(Yaml::file(path)));
+    if file.contains(INCLUDE_KEY) {
+        let includes: Vec<Utf8PathBuf> = file.extract_inner(INCLUDE_KEY)?;
+        let directory = path.parent().unwrap_or(Utf8Path::new("."));
+
+        stack.push(canonical);
+        for include in includes {
+            collect(&directory.join(include), true, stack, loaded, files)?;
+        }
+        stack.pop();
+    }
+
+    files.push(path.to_owned());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn merge_files() {
+        Jail::expect_with(|jail| {
+            jail.create_dir("conf.d")?;
+            jail.create_file(
+                "conf.d/10-clients.yaml",
+                r"
+                  clients:
+                    - client_id: first
+                ",
+            )?;
+            jail.create_file(
+                "conf.d/20-clients.yml",
+                r"
+                  clients:
+                    - client_id: second
+                  http:
+                    public_base: https://from-conf-d.example.com/
+                ",
+            )?;
+            jail.create_file("conf.d/README.md", "not a configuration file")?;
+            jail.create_dir("secrets")?;
+            jail.create_file(
+                "secrets/secrets.yaml",
+                r"
+                  secrets:
+                    encryption: abc
+                  http:
+                    issuer: https://from-secrets.example.com/
+                ",
+            )?;
+            jail.create_file(
+                "config.yaml",
+                r"
+                  include:
+                    - secrets/secrets.yaml
+                  http:
+                    issuer: https://from-config.example.com/
+                ",
+            )?;
+
+            let figment =
+                merge_config_files(Figment::new(), &["config.yaml".into(), "conf.d".into()])?;
+
+            // Files in the directory are merged in order, after the config file
+            assert_eq!(
+                figment.find_value("http.public_base")?.as_str(),
+                Some("https://from-conf-d.example.com/")
+            );
+            let clients: Vec<Dict> = figment.extract_inner("clients")?;
+            assert_eq!(clients.len(), 2);
+            assert_eq!(clients[0]["client_id"].as_str(), Some("first"));
+            assert_eq!(clients[1]["client_id"].as_str(), Some("second"));
+
+            // The including file takes precedence over the included one
+            assert_eq!(
+                figment.find_value("secrets.encryption")?.as_str(),
+                Some("abc")
+            );
+            assert_eq!(
+                figment.find_value("http.issuer")?.as_str(),
+                Some("https://from-config.example.com/")
+            );
+            assert!(!figment.contains("include"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn include_errors() {
+        Jail::expect_with(|jail| {
+            jail.create_file("a.yaml", "include: [b.yaml]")?;
+            jail.create_file("b.yaml", "include: [a.yaml]")?;
+            jail.create_file("c.yaml", "include: [missing.yaml]")?;
+
+            let error = merge_config_files(Figment::new(), &["a.yaml".into()]).unwrap_err();
+            assert!(error.to_string().contains("includes itself"));
+
+            let error = merge_config_files(Figment::new(), &["c.yaml".into()]).unwrap_err();
+            assert!(error.to_string().contains("missing.yaml not found"));
+
+            Ok(())
+        });
+    }
+}
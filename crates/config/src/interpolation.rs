@@ -0,0 +1,199 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Interpolation of environment variables and files in configuration values
+
+use figment::{
+    value::{Dict, Map, Value},
+    Error, Metadata, Profile, Provider,
+};
+
+/// A [`Provider`] which interpolates the string values of another one
+///
+/// In every string value:
+///
+///  - `${NAME}` is replaced by the value of the `NAME` environment variable.
+///    Use `$${` to write a literal `${`.
+///  - a value of the form `file:///path/to/file` is replaced by the content of
+///    the file, without its trailing newline. This happens after the
+///    environment variables are replaced, so the path can contain some.
+///
+/// This makes it possible to keep secrets out of the configuration file, like
+/// when they are mounted from Kubernetes secrets.
+pub struct Interpolated<P> {
+    provider: P,
+}
+
+impl<P: Provider> Interpolated<P> {
+    /// Interpolate the values of the given provider
+    pub fn new(provider: P) -> Self {
+        Self { provider }
+    }
+}
+
+impl<P: Provider> Provider for Interpolated<P> {
+    fn metadata(&self) -> Metadata {
+        self.provider.metadata()
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        let mut data = self.provider.data()?;
+        for dict in data.values_mut() {
+            interpolate_dict(dict, "")?;
+        }
+        Ok(data)
+    }
+
+    fn profile(&self) -> Option<Profile> {
+        self.provider.profile()
+    }
+}
+
+fn interpolate_dict(dict: &mut Dict, path: &str) -> Result<(), Error> {
+    for (key, value) in dict.iter_mut() {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        interpolate_value(value, &path)?;
+    }
+
+    Ok(())
+}
+
+fn interpolate_value(value: &mut Value, path: &str) -> Result<(), Error> {
+    match value {
+        Value::String(_, string) => {
+            *string = interpolate(string)
+                .map_err(|message| Error::from(format!("in `{path}`: {message}")))?;
+        }
+        Value::Dict(_, dict) => interpolate_dict(dict, path)?,
+        Value::Array(_, items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{path}[{index}]"))?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Interpolate a single string value
+fn interpolate(value: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(variable) = rest.strip_prefix("${") {
+            let end = variable
+                .find('}')
+                .ok_or_else(|| "unterminated `${` in value".to_owned())?;
+            let name = &variable[..end];
+            let variable_value = std::env::var(name)
+                .map_err(|_| format!("environment variable `{name}` is not set"))?;
+            result.push_str(&variable_value);
+            rest = &variable[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    if let Some(file) = result.strip_prefix("file://") {
+        let content = std::fs::read_to_string(file)
+            .map_err(|err| format!("could not read file `{file}`: {err}"))?;
+        let content = content.strip_suffix('\n').unwrap_or(&content);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        return Ok(content.to_owned());
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn interpolate_values() {
+        Jail::expect_with(|jail| {
+            jail.set_env("SMTP_PASSWORD", "hunter2");
+            jail.set_env("SECRETS", jail.directory().display().to_string());
+            jail.create_file("client-secret", "s3cr3t\n")?;
+            jail.create_file(
+                "config.yaml",
+                r#"
+                  email:
+                    password: ${SMTP_PASSWORD}
+                    from: '"${SMTP_PASSWORD}" <$${not-a-variable}@example.com> costs $5'
+                  clients:
+                    - client_secret: file://${SECRETS}/client-secret
+                "#,
+            )?;
+
+            let figment = Figment::new().merge(Interpolated::new(Yaml::file("config.yaml")));
+
+            assert_eq!(
+                figment.find_value("email.password")?.as_str(),
+                Some("hunter2")
+            );
+            assert_eq!(
+                figment.find_value("email.from")?.as_str(),
+                Some(r#""hunter2" <${not-a-variable}@example.com> costs $5"#)
+            );
+            let clients = figment.find_value("clients")?.into_array().unwrap();
+            assert_eq!(
+                clients[0].find_ref("client_secret").unwrap().as_str(),
+                Some("s3cr3t")
+            );
+
+            // The metadata of the wrapped provider is kept
+            let metadata = figment.find_metadata("email.password").unwrap();
+            assert!(matches!(metadata.source, Some(figment::Source::File(_))));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn interpolation_errors() {
+        Jail::expect_with(|jail| {
+            jail.create_file("missing-variable.yaml", "secret: ${MISSING_VARIABLE}")?;
+            jail.create_file("missing-file.yaml", "secret: file:///does/not/exist")?;
+
+            let error = Figment::new()
+                .merge(Interpolated::new(Yaml::file("missing-variable.yaml")))
+                .find_value("secret")
+                .unwrap_err();
+            assert!(error
+                .to_string()
+                .contains("environment variable `MISSING_VARIABLE` is not set"));
+
+            let error = Figment::new()
+                .merge(Interpolated::new(Yaml::file("missing-file.yaml")))
+                .find_value("secret")
+                .unwrap_err();
+            assert!(error
+                .to_string()
+                .contains("could not read file `/does/not/exist`"));
+
+            Ok(())
+        });
+    }
+}
@@ -14,11 +14,20 @@
 #[cfg(all(feature = "docker", feature = "dist"))]
 compile_error!("Only one of the `docker` and `dist` features can be enabled at once");
 
+mod effective;
+mod files;
+mod interpolation;
 pub(crate) mod schema;
 mod sections;
+mod unknown_keys;
 pub(crate) mod util;
 
 pub use self::{
+    effective::{effective_config, EffectiveValue, ValueSource},
+    files::{merge_config_files, ConfigFile},
+    interpolation::Interpolated,
+    schema::json_schema,
     sections::*,
+    unknown_keys::{unknown_keys, UnknownKey},
     util::{ConfigurationSection, ConfigurationSectionExt},
 };
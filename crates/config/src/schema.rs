@@ -7,11 +7,24 @@
 //! Useful JSON Schema definitions
 
 use schemars::{
-    gen::SchemaGenerator,
-    schema::{InstanceType, Schema, SchemaObject},
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{InstanceType, RootSchema, Schema, SchemaObject},
     JsonSchema,
 };
 
+use crate::RootConfig;
+
+/// Generate the JSON Schema of the whole configuration
+#[must_use]
+pub fn json_schema() -> RootSchema {
+    let settings = SchemaSettings::draft07().with(|s| {
+        s.option_nullable = false;
+        s.option_add_null_type = false;
+    });
+    let gen = settings.into_generator();
+    gen.into_root_schema_for::<RootConfig>()
+}
+
 /// A network hostname
 pub struct Hostname;
 
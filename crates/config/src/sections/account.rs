@@ -4,8 +4,11 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::time::Duration;
+
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 
 use crate::ConfigurationSection;
 
@@ -27,8 +30,25 @@ const fn is_default_false(value: &bool) -> bool {
     *value == default_false()
 }
 
+const fn default_deleted_user_retention() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+fn is_default_deleted_user_retention(value: &Duration) -> bool {
+    *value == default_deleted_user_retention()
+}
+
+const fn default_login_history_retention() -> Duration {
+    Duration::from_secs(90 * 24 * 60 * 60)
+}
+
+fn is_default_login_history_retention(value: &Duration) -> bool {
+    *value == default_login_history_retention()
+}
+
 /// Configuration section to configure features related to account management
 #[allow(clippy::struct_excessive_bools)]
+#[serde_as]
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 pub struct AccountConfig {
     /// Whether users are allowed to change their email addresses. Defaults to
@@ -36,6 +56,12 @@ pub struct AccountConfig {
     #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
     pub email_change_allowed: bool,
 
+    /// Whether users must authenticate again before adding or removing email
+    /// addresses, if they have a password or an upstream account to do so.
+    /// Defaults to `false`.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub email_change_requires_reauthentication: bool,
+
     /// Whether users are allowed to change their display names. Defaults to
     /// `true`.
     ///
@@ -43,6 +69,20 @@ pub struct AccountConfig {
     #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
     pub displayname_change_allowed: bool,
 
+    /// Whether users are allowed to change their avatars. Defaults to `true`.
+    ///
+    /// This should be in sync with the policy in the homeserver configuration.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub avatar_change_allowed: bool,
+
+    /// Whether the display name of users linked to an upstream provider which
+    /// forces the display name is locked to the value it provides. Defaults to
+    /// `false`.
+    ///
+    /// Administrators can still change the display name of those users.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub displayname_locked_to_upstream: bool,
+
     /// Whether to enable self-service password registration. Defaults to
     /// `false` if password authentication is enabled.
     ///
@@ -61,16 +101,53 @@ pub struct AccountConfig {
     /// This has no effect if password login is disabled.
     #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
     pub password_recovery_enabled: bool,
+
+    /// Whether users can pick the upstream OAuth 2.0 provider to log in with
+    /// by entering the Matrix ID of an account on another homeserver. Defaults
+    /// to `false`.
+    ///
+    /// The homeserver is found from the Matrix ID through its client
+    /// well-known document, and must delegate its authentication to the issuer
+    /// of one of the configured upstream OAuth 2.0 providers. Other issuers are
+    /// not supported, as there is no dynamic client registration with them.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub matrix_id_login_enabled: bool,
+
+    /// How long deleted users are kept before being permanently purged, in
+    /// seconds. They can be restored until then. Defaults to 30 days.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_deleted_user_retention",
+        skip_serializing_if = "is_default_deleted_user_retention"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub deleted_user_retention: Duration,
+
+    /// How long the login attempts shown to users in their recent activity
+    /// are kept, in seconds. Defaults to 90 days.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_login_history_retention",
+        skip_serializing_if = "is_default_login_history_retention"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub login_history_retention: Duration,
 }
 
 impl Default for AccountConfig {
     fn default() -> Self {
         Self {
             email_change_allowed: default_true(),
+            email_change_requires_reauthentication: default_false(),
             displayname_change_allowed: default_true(),
+            avatar_change_allowed: default_true(),
+            displayname_locked_to_upstream: default_false(),
             password_registration_enabled: default_false(),
             password_change_allowed: default_true(),
             password_recovery_enabled: default_false(),
+            matrix_id_login_enabled: default_false(),
+            deleted_user_retention: default_deleted_user_retention(),
+            login_history_retention: default_login_history_retention(),
         }
     }
 }
@@ -80,9 +157,15 @@ impl AccountConfig {
     pub(crate) fn is_default(&self) -> bool {
         is_default_false(&self.password_registration_enabled)
             && is_default_true(&self.email_change_allowed)
+            && is_default_false(&self.email_change_requires_reauthentication)
             && is_default_true(&self.displayname_change_allowed)
+            && is_default_true(&self.avatar_change_allowed)
+            && is_default_false(&self.displayname_locked_to_upstream)
             && is_default_true(&self.password_change_allowed)
             && is_default_false(&self.password_recovery_enabled)
+            && is_default_false(&self.matrix_id_login_enabled)
+            && is_default_deleted_user_retention(&self.deleted_user_retention)
+            && is_default_login_history_retention(&self.login_history_retention)
     }
 }
 
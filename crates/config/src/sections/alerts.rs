@@ -0,0 +1,108 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+/// Severity of an alert
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    /// Informational alert, which doesn't require any action
+    Info,
+
+    /// Something went wrong and may need attention
+    #[default]
+    Warning,
+
+    /// Something is broken and needs immediate attention
+    Critical,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_severity(value: &AlertSeverity) -> bool {
+    *value == AlertSeverity::default()
+}
+
+/// An event which can trigger an alert
+#[derive(
+    Clone, Copy, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertEvent {
+    /// An email could not be sent after all retries, and was moved to the
+    /// dead-letter list. Defaults to `warning`.
+    EmailDeadLettered,
+
+    /// The number of registrations in a day went over the configured
+    /// threshold. Defaults to `warning`.
+    RegistrationSpike,
+
+    /// An upstream OAuth 2.0 provider started failing its health checks.
+    /// Defaults to `critical`.
+    UpstreamProviderDown,
+}
+
+/// Configuration of the alerts sent to the administrators in a Matrix room
+///
+/// Alerts are posted through the homeserver connection configured in the
+/// `matrix` section, as the user the admin token belongs to. That user must
+/// have joined the room.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Default)]
+pub struct AlertsConfig {
+    /// The ID of the room in which to post the alerts, like
+    /// `!abcdef:example.com`. Alerts are disabled when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
+
+    /// The minimum severity of the alerts to post. Defaults to `warning`.
+    #[serde(default, skip_serializing_if = "is_default_severity")]
+    pub min_severity: AlertSeverity,
+
+    /// Override the severity of individual events
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub severities: BTreeMap<AlertEvent, AlertSeverity>,
+
+    /// The number of registrations in a day above which a registration spike
+    /// alert is sent. Spike detection is disabled when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_spike_threshold: Option<u32>,
+}
+
+impl AlertsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.room_id.is_none()
+            && is_default_severity(&self.min_severity)
+            && self.severities.is_empty()
+            && self.registration_spike_threshold.is_none()
+    }
+}
+
+impl ConfigurationSection for AlertsConfig {
+    const PATH: Option<&'static str> = Some("alerts");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if let Some(room_id) = &self.room_id {
+            if !room_id.starts_with('!') || !room_id.contains(':') {
+                let mut error = figment::Error::from(format!(
+                    "Invalid room ID {room_id:?}, it should look like `!abcdef:example.com`"
+                ));
+                error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![Self::PATH.unwrap().to_owned(), "room_id".to_owned()];
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
+
+use crate::ConfigurationSection;
+
+const fn default_timeout() -> Duration {
+    Duration::from_millis(5000)
+}
+
+fn is_default_timeout(value: &Duration) -> bool {
+    *value == default_timeout()
+}
+
+/// How platform attestations of mobile clients are enforced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationEnforcement {
+    /// Attestations are ignored
+    #[default]
+    Disabled,
+
+    /// Attestations are verified and the result is logged, but clients without
+    /// a valid attestation are not rejected
+    Report,
+
+    /// Native clients without a valid attestation are rejected
+    Required,
+}
+
+impl AttestationEnforcement {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Configuration section to verify the platform attestations (Apple App
+/// Attest, Play Integrity) presented by mobile clients
+///
+/// Attestations are verified by an external service, which receives the
+/// attestation as JSON and replies with whether it is valid and the
+/// identifier of the attested application.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AttestationConfig {
+    /// Whether attestations are ignored, verified and logged, or required.
+    /// Defaults to `disabled`.
+    #[serde(default, skip_serializing_if = "AttestationEnforcement::is_default")]
+    pub enforcement: AttestationEnforcement,
+
+    /// URL of the attestation verification service. Required unless the
+    /// enforcement is `disabled`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<Url>,
+
+    /// Token sent to the verification service in the `Authorization` header,
+    /// as a bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// How long to wait for the verification service to reply, in
+    /// milliseconds. Defaults to 5 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_timeout",
+        skip_serializing_if = "is_default_timeout"
+    )]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// The iOS applications allowed to register, as App IDs like
+    /// `ABCDE12345.io.element.elementx`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apple_app_ids: Vec<String>,
+
+    /// The Android applications allowed to register, as package names like
+    /// `io.element.android.x`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub android_package_names: Vec<String>,
+}
+
+impl Default for AttestationConfig {
+    fn default() -> Self {
+        Self {
+            enforcement: AttestationEnforcement::default(),
+            endpoint: None,
+            token: None,
+            timeout: default_timeout(),
+            apple_app_ids: Vec::new(),
+            android_package_names: Vec::new(),
+        }
+    }
+}
+
+impl AttestationConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.enforcement.is_default()
+            && self.endpoint.is_none()
+            && self.token.is_none()
+            && is_default_timeout(&self.timeout)
+            && self.apple_app_ids.is_empty()
+            && self.android_package_names.is_empty()
+    }
+}
+
+impl ConfigurationSection for AttestationConfig {
+    const PATH: Option<&'static str> = Some("attestation");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on_field = |mut error: figment::error::Error, field: &'static str| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            error
+        };
+
+        if self.timeout.is_zero() {
+            return Err(error_on_field(
+                figment::error::Error::custom("timeout must be greater than 0"),
+                "timeout",
+            ));
+        }
+
+        if self.endpoint.is_none() && self.token.is_some() {
+            return Err(error_on_field(
+                figment::error::Error::custom("token requires an endpoint to be set"),
+                "token",
+            ));
+        }
+
+        if self.endpoint.is_none() && !self.enforcement.is_default() {
+            return Err(error_on_field(
+                figment::error::Error::custom("an endpoint is required to verify attestations"),
+                "endpoint",
+            ));
+        }
+
+        Ok(())
+    }
+}
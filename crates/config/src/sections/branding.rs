@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeMap;
+
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use url::Url;
@@ -37,6 +39,79 @@ pub struct BrandingConfig {
     /// Logo displayed in some web pages.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logo_uri: Option<Url>,
+
+    /// Human-readable descriptions of custom scopes, displayed on the consent
+    /// screen instead of the raw scope. Keys are the scopes, like
+    /// `urn:example:photos:read`.
+    ///
+    /// The scopes known by the service, like `openid` or the Matrix API
+    /// scopes, always use their built-in description.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scopes: BTreeMap<String, ScopeDescriptionConfig>,
+}
+
+/// The icon displayed next to a scope on the consent screen
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScopeIconConfig {
+    /// `info`: an information sign
+    #[default]
+    Info,
+
+    /// `user_profile`: a user profile
+    UserProfile,
+
+    /// `chat`: a chat bubble
+    Chat,
+
+    /// `send`: a paper plane
+    Send,
+
+    /// `computer`: a computer
+    Computer,
+
+    /// `devices`: a set of devices
+    Devices,
+
+    /// `time`: a clock
+    Time,
+
+    /// `key`: a key
+    Key,
+
+    /// `email`: an envelope
+    Email,
+
+    /// `settings`: a cog
+    Settings,
+
+    /// `admin`: an administrator badge
+    Admin,
+
+    /// `lock`: a padlock
+    Lock,
+
+    /// `warning`: a warning sign
+    Warning,
+
+    /// `error`: an error sign, used for the most sensitive scopes
+    Error,
+}
+
+/// How a custom scope is described on the consent screen
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ScopeDescriptionConfig {
+    /// The description of the scope, like "See your photos"
+    pub description: String,
+
+    /// Translations of the description, keyed by language, like `fr` or
+    /// `pt-BR`. The description is used for the languages not listed here.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub translations: BTreeMap<String, String>,
+
+    /// The icon displayed next to the description
+    #[serde(default)]
+    pub icon: ScopeIconConfig,
 }
 
 impl BrandingConfig {
@@ -47,9 +122,37 @@ impl BrandingConfig {
             && self.tos_uri.is_none()
             && self.imprint.is_none()
             && self.logo_uri.is_none()
+            && self.scopes.is_empty()
     }
 }
 
 impl ConfigurationSection for BrandingConfig {
     const PATH: Option<&'static str> = Some("branding");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        for scope in self.scopes.keys() {
+            // Scope tokens are non-empty and made of printable ASCII characters,
+            // except spaces, double quotes and backslashes
+            let valid = !scope.is_empty()
+                && scope
+                    .chars()
+                    .all(|c| matches!(c, '\x21' | '\x23'..='\x5B' | '\x5D'..='\x7E'));
+
+            if !valid {
+                let mut error = figment::Error::from(format!("invalid scope {scope:?}"));
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.scopes", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "scopes".to_owned(),
+                    scope.clone(),
+                ];
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
 }
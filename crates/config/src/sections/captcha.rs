@@ -25,6 +25,69 @@ pub enum CaptchaServiceKind {
     HCaptcha,
 }
 
+fn default_captcha_threshold() -> u32 {
+    3
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_captcha_threshold(value: &u32) -> bool {
+    *value == default_captcha_threshold()
+}
+
+fn default_block_threshold() -> u32 {
+    8
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_block_threshold(value: &u32) -> bool {
+    *value == default_block_threshold()
+}
+
+/// Configuration of the bot detection heuristics on the login and registration
+/// forms
+///
+/// Each suspicious signal (failed JavaScript challenge, anomalous headers,
+/// too many submissions from the same address) adds to a score, which is
+/// compared to the thresholds below.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+pub struct BotDetectionConfig {
+    /// Whether the bot detection is enabled
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Score from which a CAPTCHA is required, if a CAPTCHA service is
+    /// configured
+    #[serde(
+        default = "default_captcha_threshold",
+        skip_serializing_if = "is_default_captcha_threshold"
+    )]
+    pub captcha_threshold: u32,
+
+    /// Score from which the submission is rejected
+    #[serde(
+        default = "default_block_threshold",
+        skip_serializing_if = "is_default_block_threshold"
+    )]
+    pub block_threshold: u32,
+}
+
+impl Default for BotDetectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            captcha_threshold: default_captcha_threshold(),
+            block_threshold: default_block_threshold(),
+        }
+    }
+}
+
+impl BotDetectionConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// Configuration section to setup CAPTCHA protection on a few operations
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Default)]
 pub struct CaptchaConfig {
@@ -39,12 +102,20 @@ pub struct CaptchaConfig {
     /// The secret key to use
     #[serde(skip_serializing_if = "Option::is_none")]
     pub secret_key: Option<String>,
+
+    /// Bot detection on the login and registration forms. When enabled, the
+    /// CAPTCHA is only required on submissions which look suspicious
+    #[serde(default, skip_serializing_if = "BotDetectionConfig::is_default")]
+    pub bot_detection: BotDetectionConfig,
 }
 
 impl CaptchaConfig {
     /// Returns true if the configuration is the default one
     pub(crate) fn is_default(&self) -> bool {
-        self.service.is_none() && self.site_key.is_none() && self.secret_key.is_none()
+        self.service.is_none()
+            && self.site_key.is_none()
+            && self.secret_key.is_none()
+            && self.bot_detection.is_default()
     }
 }
 
@@ -75,6 +146,20 @@ impl ConfigurationSection for CaptchaConfig {
             }
         }
 
+        if self.bot_detection.captcha_threshold > self.bot_detection.block_threshold {
+            let mut error = figment::error::Error::custom(
+                "captcha_threshold must not be greater than block_threshold",
+            );
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "bot_detection".to_owned(),
+                "captcha_threshold".to_owned(),
+            ];
+            return Err(error);
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,123 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+fn default_template() -> String {
+    "{{ subject.cn }}".to_owned()
+}
+
+fn is_default_template(value: &str) -> bool {
+    value == default_template()
+}
+
+/// How the value rendered from the client certificate is matched to a user
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateLookupConfig {
+    /// The value is the username of the user
+    #[default]
+    Username,
+
+    /// The value is a verified email address of the user
+    Email,
+}
+
+impl CertificateLookupConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Configuration section to let users log in with a X.509 client certificate,
+/// for example from a smart card
+///
+/// Client certificates are requested by the listeners which have a
+/// `tls.client_ca` set, and only the ones presented to the `listeners` listed
+/// here can be used to log in.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CertificateLoginConfig {
+    /// Whether logging in with a client certificate is enabled
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Template rendering the value matched to a user from the certificate.
+    ///
+    /// The template has access to the `subject` of the certificate (`cn`,
+    /// `email`, `uid`, `o`, `ou`, `serial_number`), its subject alternative
+    /// names in `san` (`email`, `dns` and `upn` lists), and its SHA-256
+    /// `fingerprint`.
+    ///
+    /// Defaults to `{{ subject.cn }}`.
+    #[serde(
+        default = "default_template",
+        skip_serializing_if = "is_default_template"
+    )]
+    pub template: String,
+
+    /// How the rendered value is matched to a user
+    #[serde(default, skip_serializing_if = "CertificateLookupConfig::is_default")]
+    pub lookup: CertificateLookupConfig,
+
+    /// Names of the listeners on which client certificates can be used to log
+    /// in. Each of them must have a `tls.client_ca` set.
+    ///
+    /// Required if logging in with a client certificate is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<String>,
+}
+
+impl Default for CertificateLoginConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            template: default_template(),
+            lookup: CertificateLookupConfig::default(),
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl CertificateLoginConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled
+            && is_default_template(&self.template)
+            && self.lookup.is_default()
+            && self.listeners.is_empty()
+    }
+}
+
+impl ConfigurationSection for CertificateLoginConfig {
+    const PATH: Option<&'static str> = Some("certificate_login");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.template.trim().is_empty() {
+            let metadata = figment.find_metadata(Self::PATH.unwrap());
+            let mut error = figment::error::Error::custom("template must not be empty");
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "template".to_owned()];
+            return Err(error);
+        }
+
+        if self.enabled && self.listeners.is_empty() {
+            let metadata = figment.find_metadata(Self::PATH.unwrap());
+            let mut error = figment::error::Error::custom(
+                "listeners must be set when logging in with a client certificate is enabled",
+            );
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "listeners".to_owned()];
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::ops::Deref;
+use std::{num::NonZeroU32, ops::Deref};
 
 use figment::Figment;
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
@@ -65,6 +65,43 @@ impl std::fmt::Display for ClientAuthMethodConfig {
     }
 }
 
+/// How strictly authorization codes are bound to the context they were
+/// requested in
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeBindingConfig {
+    /// `off`: codes can be redeemed from any context
+    #[default]
+    Off,
+
+    /// `lenient`: codes are rejected if both the network and the user agent
+    /// changed
+    Lenient,
+
+    /// `strict`: codes are rejected if either the network or the user agent
+    /// changed
+    Strict,
+}
+
+/// How the redirect URIs of authorization requests are validated
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectUriValidationConfig {
+    /// `default`: redirect URIs must match the registered ones, except for the
+    /// port of loopback redirect URIs registered without one
+    Default,
+
+    /// `exact`: redirect URIs must exactly match the registered ones, and
+    /// wildcard redirect URIs are ignored
+    Exact,
+
+    /// `native`: redirect URIs follow the rules for native apps of RFC 8252.
+    /// Loopback redirect URIs match on any port, private-use schemes must be
+    /// the reverse domain of the `client_uri`, and claimed `https` redirect
+    /// URIs must match exactly
+    Native,
+}
+
 /// An OAuth 2.0 client configuration
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClientConfig {
@@ -97,6 +134,67 @@ pub struct ClientConfig {
     /// List of allowed redirect URIs
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub redirect_uris: Vec<Url>,
+
+    /// Whether the redirect URIs can have a wildcard as the first label of
+    /// their host, like `https://*.staging.example.com/callback`. Only meant
+    /// for development and staging clients. Defaults to `false`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wildcard_redirect_uris: bool,
+
+    /// Whether this client needs to be granted the `offline_access` scope to
+    /// get refresh tokens. Defaults to the
+    /// `experimental.offline_access_required` setting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offline_access_required: Option<bool>,
+
+    /// Whether authorization codes issued to this client can only be redeemed
+    /// from a context similar to the one they were requested in, comparing
+    /// the network (/24 for IPv4, /48 for IPv6) and the user agent. A part
+    /// which is missing when the code is redeemed counts as changed. This only
+    /// applies to public clients, as confidential clients redeem codes from
+    /// their backend. Defaults to `off`
+    #[serde(default, skip_serializing_if = "is_default_code_binding")]
+    pub code_binding: CodeBindingConfig,
+
+    /// How the redirect URIs of authorization requests are validated. Defaults
+    /// to `native` for native applications, and `default` otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_uri_validation: Option<RedirectUriValidationConfig>,
+
+    /// Maximum number of active sessions a user can have with this client at
+    /// the same time, for example `1` for a shared kiosk. No limit if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<u32>", range(min = 1))]
+    pub max_sessions_per_user: Option<NonZeroU32>,
+
+    /// Whether this client can register users through the registration API,
+    /// without going through the browser. Password registration must be
+    /// enabled. Defaults to `false`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub native_registration: bool,
+
+    /// Whether this client can send an `idp_hint` (or `kc_idp_hint`)
+    /// parameter in its authorization requests, with the ID or the brand name
+    /// of an upstream provider, to send users straight to it instead of the
+    /// login page. Defaults to `false`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub upstream_provider_hints: bool,
+
+    /// The ID of the upstream provider users are sent straight to when they
+    /// log in to this client without an `idp_hint`, for SSO-first
+    /// deployments. Users go through the login page if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        with = "Option<String>",
+        regex(pattern = r"^[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}$"),
+        description = "A ULID as per https://github.com/ulid/spec"
+    )]
+    pub upstream_provider: Option<Ulid>,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_code_binding(value: &CodeBindingConfig) -> bool {
+    *value == CodeBindingConfig::Off
 }
 
 impl ClientConfig {
@@ -174,6 +272,32 @@ impl ClientConfig {
             }
         }
 
+        for redirect_uri in &self.redirect_uris {
+            let Some(domain) = redirect_uri.host_str().filter(|host| host.contains('*')) else {
+                continue;
+            };
+
+            if !self.wildcard_redirect_uris {
+                let error = figment::error::Error::custom(format!(
+                    "wildcard redirect URI {redirect_uri} requires wildcard_redirect_uris to be enabled"
+                ));
+                return Err(error.with_path("redirect_uris"));
+            }
+
+            // Only a single wildcard is allowed, as the first label of an https URI, and it
+            // must not cover a whole top-level domain
+            let valid = redirect_uri.scheme() == "https"
+                && domain
+                    .strip_prefix("*.")
+                    .is_some_and(|domain| !domain.contains('*') && domain.contains('.'));
+            if !valid {
+                let error = figment::error::Error::custom(format!(
+                    "invalid wildcard redirect URI {redirect_uri}"
+                ));
+                return Err(error.with_path("redirect_uris"));
+            }
+        }
+
         Ok(())
     }
 
@@ -6,13 +6,20 @@
 
 #![allow(deprecated)]
 
-use std::{num::NonZeroU16, str::FromStr};
+use std::{
+    num::{NonZeroU16, NonZeroU32},
+    str::FromStr,
+    time::Duration,
+};
 
+use camino::Utf8PathBuf;
 use lettre::message::Mailbox;
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
 
-use super::ConfigurationSection;
+use super::{rate_limiting::RateLimiterConfiguration, ConfigurationSection};
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Credentials {
@@ -50,6 +57,48 @@ pub enum EmailTransportKind {
 
     /// Send emails by calling sendmail
     Sendmail,
+
+    /// Delegate sending emails to an external HTTP API
+    Http,
+}
+
+/// Algorithm used to sign emails with DKIM
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailDkimAlgorithm {
+    /// RSA with SHA-256
+    #[default]
+    Rsa,
+
+    /// Ed25519 with SHA-256
+    Ed25519,
+}
+
+/// Configuration to sign outgoing emails with DKIM
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EmailDkimConfig {
+    /// The selector under which the public key is published, as a TXT record
+    /// on `<selector>._domainkey.<domain>`
+    pub selector: String,
+
+    /// The signing domain. Defaults to the domain of the `from` address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<crate::schema::Hostname>")]
+    pub domain: Option<String>,
+
+    /// The signing algorithm. Defaults to `rsa`
+    #[serde(default)]
+    pub algorithm: EmailDkimAlgorithm,
+
+    /// The private key. RSA keys are in the PKCS#1 PEM format, Ed25519 keys
+    /// are the base64-encoded raw private key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    /// Path to a file containing the private key, in the same format as `key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub key_file: Option<Utf8PathBuf>,
 }
 
 fn default_email() -> String {
@@ -62,6 +111,7 @@ fn default_sendmail_command() -> Option<String> {
 }
 
 /// Configuration related to sending emails
+#[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct EmailConfig {
     /// Email address to use as From when sending emails
@@ -106,10 +156,49 @@ pub struct EmailConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     password: Option<String>,
 
+    /// SMTP transport: Maximum number of connections kept open to the relay,
+    /// to reuse them across emails. Default is 10
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool_max_size: Option<NonZeroU32>,
+
+    /// SMTP transport: How long an unused connection is kept open, in
+    /// seconds. Default is 60
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[schemars(with = "Option<u64>")]
+    pool_idle_timeout: Option<Duration>,
+
     /// Sendmail transport: Command to use to send emails
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(default = "default_sendmail_command")]
     command: Option<String>,
+
+    /// HTTP transport: URL of the API to which rendered emails are posted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<Url>,
+
+    /// HTTP transport: Token sent to the API in the `Authorization` header, as
+    /// a bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+
+    /// Sign outgoing emails with DKIM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dkim: Option<EmailDkimConfig>,
+
+    /// Limit how many emails are sent to each recipient domain. Emails over
+    /// the limit are kept in the queue until they can be sent.
+    ///
+    /// There is no limit if this is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain_rate_limit: Option<RateLimiterConfiguration>,
+
+    /// Secret token which the mail provider must pass in the `token` query
+    /// parameter when calling the bounce and complaint webhooks
+    ///
+    /// The webhooks are disabled if this is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_secret: Option<String>,
 }
 
 impl EmailConfig {
@@ -149,11 +238,53 @@ impl EmailConfig {
         self.password.as_deref()
     }
 
+    /// Maximum number of connections kept open to the SMTP server
+    #[must_use]
+    pub fn pool_max_size(&self) -> Option<NonZeroU32> {
+        self.pool_max_size
+    }
+
+    /// How long an unused connection to the SMTP server is kept open
+    #[must_use]
+    pub fn pool_idle_timeout(&self) -> Option<Duration> {
+        self.pool_idle_timeout
+    }
+
     /// Command to use to send emails
     #[must_use]
     pub fn command(&self) -> Option<&str> {
         self.command.as_deref()
     }
+
+    /// URL of the API to which rendered emails are posted
+    #[must_use]
+    pub fn endpoint(&self) -> Option<&Url> {
+        self.endpoint.as_ref()
+    }
+
+    /// Token used to authenticate against the email API
+    #[must_use]
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// DKIM signing configuration
+    #[must_use]
+    pub fn dkim(&self) -> Option<&EmailDkimConfig> {
+        self.dkim.as_ref()
+    }
+
+    /// Rate limit on the emails sent to each recipient domain
+    #[must_use]
+    pub fn domain_rate_limit(&self) -> Option<RateLimiterConfiguration> {
+        self.domain_rate_limit
+    }
+
+    /// Secret token used to authenticate the bounce and complaint webhooks
+    #[must_use]
+    pub fn webhook_secret(&self) -> Option<&str> {
+        self.webhook_secret.as_deref()
+    }
 }
 
 impl Default for EmailConfig {
@@ -167,7 +298,14 @@ impl Default for EmailConfig {
             port: None,
             username: None,
             password: None,
+            pool_max_size: None,
+            pool_idle_timeout: None,
             command: None,
+            endpoint: None,
+            token: None,
+            dkim: None,
+            domain_rate_limit: None,
+            webhook_secret: None,
         }
     }
 }
@@ -175,6 +313,7 @@ impl Default for EmailConfig {
 impl ConfigurationSection for EmailConfig {
     const PATH: Option<&'static str> = Some("email");
 
+    #[allow(clippy::too_many_lines)]
     fn validate(&self, figment: &figment::Figment) -> Result<(), figment::error::Error> {
         let metadata = figment.find_metadata(Self::PATH.unwrap());
 
@@ -196,10 +335,55 @@ impl ConfigurationSection for EmailConfig {
             )
         };
 
+        if let Some(dkim) = &self.dkim {
+            match (&dkim.key, &dkim.key_file) {
+                (None, None) => {
+                    return Err(error_on_field(
+                        figment::error::Error::custom("Missing `key` or `key_file`"),
+                        "dkim",
+                    ));
+                }
+                (Some(_), Some(_)) => {
+                    return Err(error_on_field(
+                        figment::error::Error::custom("Cannot specify both `key` and `key_file`"),
+                        "dkim",
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(limit) = &self.domain_rate_limit {
+            if limit.to_quota().is_none() {
+                return Err(error_on_field(
+                    figment::error::Error::custom(
+                        "`per_second` must be a number that is more than zero and less than 1_000_000_000 (1e9)",
+                    ),
+                    "domain_rate_limit",
+                ));
+            }
+        }
+
         match self.transport {
             EmailTransportKind::Blackhole => {}
 
             EmailTransportKind::Smtp => {
+                let expected_fields = &[
+                    "from",
+                    "reply_to",
+                    "transport",
+                    "mode",
+                    "hostname",
+                    "port",
+                    "username",
+                    "password",
+                    "pool_max_size",
+                    "pool_idle_timeout",
+                    "dkim",
+                    "domain_rate_limit",
+                    "webhook_secret",
+                ];
+
                 if let Err(e) = Mailbox::from_str(&self.from) {
                     return Err(error_on_field(figment::error::Error::custom(e), "from"));
                 }
@@ -227,24 +411,28 @@ impl ConfigurationSection for EmailConfig {
                 }
 
                 if self.command.is_some() {
-                    return Err(unexpected_field(
-                        "command",
-                        &[
-                            "from",
-                            "reply_to",
-                            "transport",
-                            "mode",
-                            "hostname",
-                            "port",
-                            "username",
-                            "password",
-                        ],
-                    ));
+                    return Err(unexpected_field("command", expected_fields));
+                }
+
+                if self.endpoint.is_some() {
+                    return Err(unexpected_field("endpoint", expected_fields));
+                }
+
+                if self.token.is_some() {
+                    return Err(unexpected_field("token", expected_fields));
                 }
             }
 
             EmailTransportKind::Sendmail => {
-                let expected_fields = &["from", "reply_to", "transport", "command"];
+                let expected_fields = &[
+                    "from",
+                    "reply_to",
+                    "transport",
+                    "command",
+                    "dkim",
+                    "domain_rate_limit",
+                    "webhook_secret",
+                ];
 
                 if let Err(e) = Mailbox::from_str(&self.from) {
                     return Err(error_on_field(figment::error::Error::custom(e), "from"));
@@ -277,6 +465,79 @@ impl ConfigurationSection for EmailConfig {
                 if self.password.is_some() {
                     return Err(unexpected_field("password", expected_fields));
                 }
+
+                if self.pool_max_size.is_some() {
+                    return Err(unexpected_field("pool_max_size", expected_fields));
+                }
+
+                if self.pool_idle_timeout.is_some() {
+                    return Err(unexpected_field("pool_idle_timeout", expected_fields));
+                }
+
+                if self.endpoint.is_some() {
+                    return Err(unexpected_field("endpoint", expected_fields));
+                }
+
+                if self.token.is_some() {
+                    return Err(unexpected_field("token", expected_fields));
+                }
+            }
+
+            EmailTransportKind::Http => {
+                let expected_fields = &[
+                    "from",
+                    "reply_to",
+                    "transport",
+                    "endpoint",
+                    "token",
+                    "dkim",
+                    "domain_rate_limit",
+                    "webhook_secret",
+                ];
+
+                if let Err(e) = Mailbox::from_str(&self.from) {
+                    return Err(error_on_field(figment::error::Error::custom(e), "from"));
+                }
+
+                if let Err(e) = Mailbox::from_str(&self.reply_to) {
+                    return Err(error_on_field(figment::error::Error::custom(e), "reply_to"));
+                }
+
+                if self.endpoint.is_none() {
+                    return Err(missing_field("endpoint"));
+                }
+
+                if self.mode.is_some() {
+                    return Err(unexpected_field("mode", expected_fields));
+                }
+
+                if self.hostname.is_some() {
+                    return Err(unexpected_field("hostname", expected_fields));
+                }
+
+                if self.port.is_some() {
+                    return Err(unexpected_field("port", expected_fields));
+                }
+
+                if self.username.is_some() {
+                    return Err(unexpected_field("username", expected_fields));
+                }
+
+                if self.password.is_some() {
+                    return Err(unexpected_field("password", expected_fields));
+                }
+
+                if self.pool_max_size.is_some() {
+                    return Err(unexpected_field("pool_max_size", expected_fields));
+                }
+
+                if self.pool_idle_timeout.is_some() {
+                    return Err(unexpected_field("pool_idle_timeout", expected_fields));
+                }
+
+                if self.command.is_some() {
+                    return Err(unexpected_field("command", expected_fields));
+                }
             }
         }
 
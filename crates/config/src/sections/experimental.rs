@@ -6,7 +6,7 @@
 
 use chrono::Duration;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Serialize};
 use serde_with::serde_as;
 
 use crate::ConfigurationSection;
@@ -19,6 +19,28 @@ fn is_default_token_ttl(value: &Duration) -> bool {
     *value == default_token_ttl()
 }
 
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_false(value: &bool) -> bool {
+    !*value
+}
+
+const fn default_device_code_user_code_length() -> usize {
+    6
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_device_code_user_code_length(value: &usize) -> bool {
+    *value == default_device_code_user_code_length()
+}
+
+fn default_device_code_user_code_charset() -> String {
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".to_owned()
+}
+
+fn is_default_device_code_user_code_charset(value: &String) -> bool {
+    *value == default_device_code_user_code_charset()
+}
+
 /// Configuration sections for experimental options
 ///
 /// Do not change these options unless you know what you are doing.
@@ -44,6 +66,34 @@ pub struct ExperimentalConfig {
     )]
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub compat_token_ttl: Duration,
+
+    /// Only issue refresh tokens to clients which were granted the
+    /// `offline_access` scope, as recommended by OpenID Connect. Defaults to
+    /// `false`, which issues refresh tokens to every client allowed to use
+    /// the `refresh_token` grant.
+    ///
+    /// This can be overridden for each client in the `clients` section.
+    #[serde(default, skip_serializing_if = "is_default_false")]
+    pub offline_access_required: bool,
+
+    /// Length of the user codes generated for the device authorization grant.
+    /// Defaults to 6.
+    #[schemars(range(min = 4, max = 16))]
+    #[serde(
+        default = "default_device_code_user_code_length",
+        skip_serializing_if = "is_default_device_code_user_code_length"
+    )]
+    pub device_code_user_code_length: usize,
+
+    /// Characters used to generate the user codes for the device
+    /// authorization grant. Only uppercase ASCII letters and digits are
+    /// allowed, as codes are entered case-insensitively. Defaults to all
+    /// uppercase letters and digits.
+    #[serde(
+        default = "default_device_code_user_code_charset",
+        skip_serializing_if = "is_default_device_code_user_code_charset"
+    )]
+    pub device_code_user_code_charset: String,
 }
 
 impl Default for ExperimentalConfig {
@@ -51,16 +101,66 @@ impl Default for ExperimentalConfig {
         Self {
             access_token_ttl: default_token_ttl(),
             compat_token_ttl: default_token_ttl(),
+            offline_access_required: false,
+            device_code_user_code_length: default_device_code_user_code_length(),
+            device_code_user_code_charset: default_device_code_user_code_charset(),
         }
     }
 }
 
 impl ExperimentalConfig {
     pub(crate) fn is_default(&self) -> bool {
-        is_default_token_ttl(&self.access_token_ttl) && is_default_token_ttl(&self.compat_token_ttl)
+        is_default_token_ttl(&self.access_token_ttl)
+            && is_default_token_ttl(&self.compat_token_ttl)
+            && is_default_false(&self.offline_access_required)
+            && is_default_device_code_user_code_length(&self.device_code_user_code_length)
+            && is_default_device_code_user_code_charset(&self.device_code_user_code_charset)
     }
 }
 
 impl ConfigurationSection for ExperimentalConfig {
     const PATH: Option<&'static str> = Some("experimental");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on_field = |mut error: figment::error::Error, field: &'static str| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            error
+        };
+
+        if !(4..=16).contains(&self.device_code_user_code_length) {
+            return Err(error_on_field(
+                figment::error::Error::custom("must be between 4 and 16"),
+                "device_code_user_code_length",
+            ));
+        }
+
+        let charset = &self.device_code_user_code_charset;
+        if !charset
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            return Err(error_on_field(
+                figment::error::Error::custom(
+                    "must only contain uppercase ASCII letters and digits",
+                ),
+                "device_code_user_code_charset",
+            ));
+        }
+
+        let mut unique: Vec<char> = charset.chars().collect();
+        unique.sort_unstable();
+        unique.dedup();
+        if unique.len() < 10 {
+            return Err(error_on_field(
+                figment::error::Error::custom("must contain at least 10 different characters"),
+                "device_code_user_code_charset",
+            ));
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,204 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+const fn default_true() -> bool {
+    true
+}
+
+const fn default_weight() -> u32 {
+    1
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_weight(value: &u32) -> bool {
+    *value == default_weight()
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value
+}
+
+/// A feature flag
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct FeatureFlagConfig {
+    /// The name of the flag, as checked by the code and the templates
+    pub name: String,
+
+    /// Whether the flag is enabled at all. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub enabled: bool,
+
+    /// The percentage of users for which the flag is enabled, between 0 and
+    /// 100. Users are assigned to the rollout consistently, based on their ID.
+    /// Anonymous visitors only get the flag once it is rolled out to everyone.
+    /// Defaults to all users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(max = 100))]
+    pub rollout_percentage: Option<u8>,
+
+    /// Usernames of the users for which the flag is enabled, whatever the
+    /// rollout percentage
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+/// A variant of an experiment
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ExperimentVariantConfig {
+    /// The name of the variant, as checked by the templates
+    pub name: String,
+
+    /// The relative weight of the variant, compared to the other variants of
+    /// the experiment. Defaults to 1.
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    pub weight: u32,
+}
+
+/// An A/B experiment on the login and registration pages
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ExperimentConfig {
+    /// The name of the experiment, as checked by the templates
+    pub name: String,
+
+    /// Whether the experiment is running. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub enabled: bool,
+
+    /// The variants visitors are assigned to, in proportion of their weight
+    pub variants: Vec<ExperimentVariantConfig>,
+}
+
+/// Configuration section to roll out features gradually
+///
+/// Flags and experiments can be overridden at runtime with the `mas-cli manage
+/// set-feature-flag` command, without restarting the service.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct FeatureFlagsConfig {
+    /// The feature flags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<FeatureFlagConfig>,
+
+    /// The A/B experiments. Visitors are assigned to a variant of each running
+    /// experiment, and keep it through a cookie.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<ExperimentConfig>,
+}
+
+impl FeatureFlagsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.flags.is_empty() && self.experiments.is_empty()
+    }
+}
+
+impl ConfigurationSection for FeatureFlagsConfig {
+    const PATH: Option<&'static str> = Some("feature_flags");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on = |mut error: figment::error::Error, field: &str, index: usize| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                field.to_owned(),
+                index.to_string(),
+            ];
+            error
+        };
+        let error_on_flag = |error, index| error_on(error, "flags", index);
+        let error_on_experiment = |error, index| error_on(error, "experiments", index);
+
+        let mut names = BTreeSet::new();
+        for (index, flag) in self.flags.iter().enumerate() {
+            if flag.name.is_empty() {
+                return Err(error_on_flag(
+                    figment::error::Error::custom("the name of a flag can't be empty"),
+                    index,
+                ));
+            }
+
+            if !names.insert(&flag.name) {
+                return Err(error_on_flag(
+                    figment::error::Error::custom(format!(
+                        "flag {:?} is defined more than once",
+                        flag.name
+                    )),
+                    index,
+                ));
+            }
+
+            if flag
+                .rollout_percentage
+                .is_some_and(|percentage| percentage > 100)
+            {
+                return Err(error_on_flag(
+                    figment::error::Error::custom("rollout_percentage must be at most 100"),
+                    index,
+                ));
+            }
+        }
+
+        for (index, experiment) in self.experiments.iter().enumerate() {
+            if experiment.name.is_empty() {
+                return Err(error_on_experiment(
+                    figment::error::Error::custom("the name of an experiment can't be empty"),
+                    index,
+                ));
+            }
+
+            // Flags and experiments share their overrides, so their names must not
+            // collide
+            if !names.insert(&experiment.name) {
+                return Err(error_on_experiment(
+                    figment::error::Error::custom(format!(
+                        "{:?} is defined more than once",
+                        experiment.name
+                    )),
+                    index,
+                ));
+            }
+
+            if experiment
+                .variants
+                .iter()
+                .all(|variant| variant.weight == 0)
+            {
+                return Err(error_on_experiment(
+                    figment::error::Error::custom(
+                        "an experiment needs at least one variant with a weight",
+                    ),
+                    index,
+                ));
+            }
+
+            let mut variants = BTreeSet::new();
+            if let Some(variant) = experiment
+                .variants
+                .iter()
+                .find(|variant| !variants.insert(&variant.name))
+            {
+                return Err(error_on_experiment(
+                    figment::error::Error::custom(format!(
+                        "variant {:?} is defined more than once",
+                        variant.name
+                    )),
+                    index,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -6,15 +6,24 @@
 
 #![allow(deprecated)]
 
-use std::{borrow::Cow, io::Cursor};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::Cursor,
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
 
 use anyhow::bail;
 use camino::Utf8PathBuf;
 use ipnetwork::IpNetwork;
 use mas_keystore::PrivateKey;
-use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls_pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, PrivatePkcs8KeyDer,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 use url::Url;
 
 use super::ConfigurationSection;
@@ -188,6 +197,46 @@ pub struct TlsConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(with = "Option<String>")]
     pub password_file: Option<Utf8PathBuf>,
+
+    /// PEM-encoded X509 certificates of the authorities issuing client
+    /// certificates
+    ///
+    /// When set, clients are asked for a certificate during the TLS
+    /// handshake, which can be used to log in if `certificate_login` is
+    /// enabled. Clients without a certificate can still connect.
+    ///
+    /// At most one of `client_ca` or `client_ca_file` can be set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<String>,
+
+    /// File containing the PEM-encoded X509 certificates of the authorities
+    /// issuing client certificates
+    ///
+    /// At most one of `client_ca` or `client_ca_file` can be set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub client_ca_file: Option<Utf8PathBuf>,
+
+    /// PEM-encoded certificate revocation lists of the authorities issuing
+    /// client certificates
+    ///
+    /// When set, client certificates which were revoked are refused, as well
+    /// as the ones issued by an authority which has no list here. Without
+    /// them, the revocation of client certificates is not checked.
+    ///
+    /// At most one of `client_crl` or `client_crl_file` can be set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_crl: Option<String>,
+
+    /// File containing the PEM-encoded certificate revocation lists of the
+    /// authorities issuing client certificates
+    ///
+    /// The file is only read on startup.
+    ///
+    /// At most one of `client_crl` or `client_crl_file` can be set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub client_crl_file: Option<Utf8PathBuf>,
 }
 
 impl TlsConfig {
@@ -262,6 +311,66 @@ impl TlsConfig {
 
         Ok((key, certificate_chain))
     }
+
+    /// Load the certificates of the authorities issuing client certificates
+    ///
+    /// Returns `None` if client certificates are not requested on this
+    /// listener.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if both `client_ca` and `client_ca_file` are set, if
+    /// the file could not be read, or if no certificate could be decoded
+    pub fn load_client_ca(&self) -> Result<Option<Vec<CertificateDer<'static>>>, anyhow::Error> {
+        let client_ca_pem = match (&self.client_ca, &self.client_ca_file) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                bail!("Only one of `client_ca` or `client_ca_file` can be set at a time")
+            }
+            (Some(client_ca), None) => Cow::Borrowed(client_ca),
+            (None, Some(path)) => Cow::Owned(std::fs::read_to_string(path)?),
+        };
+
+        let mut client_ca_reader = Cursor::new(client_ca_pem.as_bytes());
+        let client_ca: Result<Vec<_>, _> = rustls_pemfile::certs(&mut client_ca_reader).collect();
+        let client_ca = client_ca?;
+
+        if client_ca.is_empty() {
+            bail!("TLS client CA is empty (or invalid)")
+        }
+
+        Ok(Some(client_ca))
+    }
+
+    /// Load the revocation lists of the authorities issuing client
+    /// certificates, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if both `client_crl` and `client_crl_file` are set, if
+    /// the file could not be read, or if no revocation list could be decoded
+    pub fn load_client_crls(
+        &self,
+    ) -> Result<Option<Vec<CertificateRevocationListDer<'static>>>, anyhow::Error> {
+        let client_crl_pem = match (&self.client_crl, &self.client_crl_file) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                bail!("Only one of `client_crl` or `client_crl_file` can be set at a time")
+            }
+            (Some(client_crl), None) => Cow::Borrowed(client_crl),
+            (None, Some(path)) => Cow::Owned(std::fs::read_to_string(path)?),
+        };
+
+        let mut client_crl_reader = Cursor::new(client_crl_pem.as_bytes());
+        let client_crls: Result<Vec<_>, _> = rustls_pemfile::crls(&mut client_crl_reader).collect();
+        let client_crls = client_crls?;
+
+        if client_crls.is_empty() {
+            bail!("TLS client CRL is empty (or invalid)")
+        }
+
+        Ok(Some(client_crls))
+    }
 }
 
 /// HTTP resources to mount
@@ -311,6 +420,10 @@ pub enum Resource {
     /// Admin API, served at `/api/admin/v1`
     AdminApi,
 
+    /// Webhooks receiving bounce and complaint notifications from the mail
+    /// provider, served at `/email/webhooks/ses` and `/email/webhooks/sendgrid`
+    EmailWebhooks,
+
     /// Mount a "/connection-info" handler which helps debugging informations on
     /// the upstream connection
     #[serde(rename = "connection-info")]
@@ -344,6 +457,301 @@ pub struct ListenerConfig {
     pub tls: Option<TlsConfig>,
 }
 
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value == default_true()
+}
+
+/// Valid values for the `Referrer-Policy` header
+const REFERRER_POLICIES: [&str; 8] = [
+    "no-referrer",
+    "no-referrer-when-downgrade",
+    "origin",
+    "origin-when-cross-origin",
+    "same-origin",
+    "strict-origin",
+    "strict-origin-when-cross-origin",
+    "unsafe-url",
+];
+
+fn default_referrer_policy() -> String {
+    "strict-origin-when-cross-origin".to_owned()
+}
+
+fn is_default_referrer_policy(value: &String) -> bool {
+    *value == default_referrer_policy()
+}
+
+fn default_frame_ancestors() -> Vec<String> {
+    vec!["'none'".to_owned()]
+}
+
+fn is_default_frame_ancestors(value: &Vec<String>) -> bool {
+    *value == default_frame_ancestors()
+}
+
+const fn default_hsts_max_age() -> u64 {
+    // One year
+    365 * 24 * 60 * 60
+}
+
+/// Configuration of the `Strict-Transport-Security` header
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct HstsConfig {
+    /// How long browsers should remember that the service is only reachable
+    /// over HTTPS, in seconds. Defaults to one year.
+    #[serde(default = "default_hsts_max_age")]
+    pub max_age: u64,
+
+    /// Whether the policy also applies to subdomains
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_subdomains: bool,
+
+    /// Whether to allow the domain to be included in browsers' HSTS preload
+    /// lists
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preload: bool,
+}
+
+/// Configuration of the security headers set on HTTP responses
+///
+/// Pages destined to be viewed by humans get a strict
+/// `Content-Security-Policy` with per-response nonces for inline scripts, while
+/// API resources get a policy which doesn't allow loading anything.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SecurityHeadersConfig {
+    /// Whether to set the security headers on responses. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub enabled: bool,
+
+    /// Set the `Strict-Transport-Security` header on responses.
+    ///
+    /// This only has an effect if the `public_base` uses HTTPS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hsts: Option<HstsConfig>,
+
+    /// Value of the `Referrer-Policy` header. Defaults to
+    /// `strict-origin-when-cross-origin`.
+    #[serde(
+        default = "default_referrer_policy",
+        skip_serializing_if = "is_default_referrer_policy"
+    )]
+    pub referrer_policy: String,
+
+    /// Sources allowed to embed the human-facing pages in a frame, used in the
+    /// `frame-ancestors` directive of the `Content-Security-Policy`. Defaults
+    /// to `'none'`.
+    #[serde(
+        default = "default_frame_ancestors",
+        skip_serializing_if = "is_default_frame_ancestors"
+    )]
+    pub frame_ancestors: Vec<String>,
+
+    /// Additional sources to allow in the `Content-Security-Policy` of the
+    /// human-facing pages, keyed by directive name (e.g. `img-src`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub content_security_policy_extra_sources: BTreeMap<String, Vec<String>>,
+
+    /// Only report `Content-Security-Policy` violations instead of enforcing
+    /// the policy. Useful to test custom templates before enforcing it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub content_security_policy_report_only: bool,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            hsts: None,
+            referrer_policy: default_referrer_policy(),
+            frame_ancestors: default_frame_ancestors(),
+            content_security_policy_extra_sources: BTreeMap::new(),
+            content_security_policy_report_only: false,
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    pub(crate) fn is_default(&self) -> bool {
+        is_default_true(&self.enabled)
+            && self.hsts.is_none()
+            && is_default_referrer_policy(&self.referrer_policy)
+            && is_default_frame_ancestors(&self.frame_ancestors)
+            && self.content_security_policy_extra_sources.is_empty()
+            && !self.content_security_policy_report_only
+    }
+}
+
+const fn default_max_request_body_size() -> usize {
+    // 2 MiB
+    2 * 1024 * 1024
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_max_request_body_size(value: &usize) -> bool {
+    *value == default_max_request_body_size()
+}
+
+const fn default_request_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_request_timeout(value: &Duration) -> bool {
+    *value == default_request_timeout()
+}
+
+fn default_max_concurrent_streams() -> NonZeroU32 {
+    NonZeroU32::new(100).unwrap()
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_max_concurrent_streams(value: &NonZeroU32) -> bool {
+    *value == default_max_concurrent_streams()
+}
+
+/// Limits protecting the server against slow or abusive clients
+///
+/// Hitting any of those limits is reported in the `http.server.limits_hit`
+/// metric.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct HttpLimitsConfig {
+    /// Maximum size of a request body, in bytes. Defaults to 2 MiB.
+    #[serde(
+        default = "default_max_request_body_size",
+        skip_serializing_if = "is_default_max_request_body_size"
+    )]
+    pub max_request_body_size: usize,
+
+    /// Maximum time to receive a complete request body once its headers were
+    /// received, in seconds. Defaults to 10 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_request_timeout",
+        skip_serializing_if = "is_default_request_timeout"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub request_body_timeout: Duration,
+
+    /// Maximum time to receive the request headers on HTTP/1.1 connections, in
+    /// seconds. Defaults to 10 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_request_timeout",
+        skip_serializing_if = "is_default_request_timeout"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub header_read_timeout: Duration,
+
+    /// Maximum number of concurrent requests on a single HTTP/2 connection.
+    /// Defaults to 100.
+    #[serde(
+        default = "default_max_concurrent_streams",
+        skip_serializing_if = "is_default_max_concurrent_streams"
+    )]
+    pub max_concurrent_streams: NonZeroU32,
+
+    /// Maximum number of connections served at the same time by each
+    /// listener. New connections are dropped when the limit is reached. No
+    /// limit by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<NonZeroUsize>,
+}
+
+impl Default for HttpLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_request_body_size: default_max_request_body_size(),
+            request_body_timeout: default_request_timeout(),
+            header_read_timeout: default_request_timeout(),
+            max_concurrent_streams: default_max_concurrent_streams(),
+            max_connections: None,
+        }
+    }
+}
+
+impl HttpLimitsConfig {
+    pub(crate) fn is_default(&self) -> bool {
+        is_default_max_request_body_size(&self.max_request_body_size)
+            && is_default_request_timeout(&self.request_body_timeout)
+            && is_default_request_timeout(&self.header_read_timeout)
+            && is_default_max_concurrent_streams(&self.max_concurrent_streams)
+            && self.max_connections.is_none()
+    }
+}
+
+const fn default_compression_min_size() -> u16 {
+    1024
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_compression_min_size(value: &u16) -> bool {
+    *value == default_compression_min_size()
+}
+
+/// Compression of the HTTP responses
+///
+/// Responses are compressed with brotli or gzip, depending on what the client
+/// accepts. Images, fonts, event streams and gRPC responses are never
+/// compressed, as well as responses which are already compressed, like the
+/// pre-compressed static assets.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct HttpCompressionConfig {
+    /// Whether to compress responses. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub enabled: bool,
+
+    /// Minimum size of a response body to compress it, in bytes. Defaults to
+    /// 1024 bytes.
+    #[serde(
+        default = "default_compression_min_size",
+        skip_serializing_if = "is_default_compression_min_size"
+    )]
+    pub min_size: u16,
+}
+
+impl Default for HttpCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            min_size: default_compression_min_size(),
+        }
+    }
+}
+
+impl HttpCompressionConfig {
+    pub(crate) fn is_default(&self) -> bool {
+        is_default_true(&self.enabled) && is_default_compression_min_size(&self.min_size)
+    }
+}
+
+/// An additional issuer served by the same process, alongside the main one
+///
+/// This is useful during a domain migration, to keep serving the legacy issuer
+/// while clients move to the new one. Tokens are shared by all issuers, so
+/// tokens obtained through one of them are accepted by the others.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdditionalIssuerConfig {
+    /// Public URL base from where this issuer is reachable. Requests are routed
+    /// to this issuer when their `Host` header matches the host of this URL,
+    /// and their path starts with its path.
+    pub public_base: Url,
+
+    /// OIDC issuer URL. Defaults to `public_base` if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<Url>,
+
+    /// IDs of the keys from `secrets.keys` this issuer signs with and
+    /// advertises. Defaults to all the keys if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+}
+
 /// Configuration related to the web server
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HttpConfig {
@@ -362,6 +770,31 @@ pub struct HttpConfig {
     /// OIDC issuer URL. Defaults to `public_base` if not set.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub issuer: Option<Url>,
+
+    /// Additional issuers to serve from the same process, each with its own
+    /// discovery document and signing keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_issuers: Vec<AdditionalIssuerConfig>,
+
+    /// Security headers to set on responses
+    #[serde(default, skip_serializing_if = "SecurityHeadersConfig::is_default")]
+    pub security_headers: SecurityHeadersConfig,
+
+    /// Limits on requests and connections, protecting against slow or abusive
+    /// clients
+    #[serde(default, skip_serializing_if = "HttpLimitsConfig::is_default")]
+    pub limits: HttpLimitsConfig,
+
+    /// Compression of the responses
+    #[serde(default, skip_serializing_if = "HttpCompressionConfig::is_default")]
+    pub compression: HttpCompressionConfig,
+
+    /// Base URL of the documentation pages linked from the `error_uri` of the
+    /// OAuth 2.0 error responses, which gets the error code appended. Defaults
+    /// to the pages served by the service under `/errors/`. Must end with a
+    /// `/`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_documentation_base: Option<Url>,
 }
 
 impl Default for HttpConfig {
@@ -405,6 +838,11 @@ impl Default for HttpConfig {
             trusted_proxies: default_trusted_proxies(),
             issuer: Some(default_public_base()),
             public_base: default_public_base(),
+            additional_issuers: Vec::new(),
+            security_headers: SecurityHeadersConfig::default(),
+            limits: HttpLimitsConfig::default(),
+            compression: HttpCompressionConfig::default(),
+            error_documentation_base: None,
         }
     }
 }
@@ -412,7 +850,75 @@ impl Default for HttpConfig {
 impl ConfigurationSection for HttpConfig {
     const PATH: Option<&'static str> = Some("http");
 
+    #[allow(clippy::too_many_lines)]
     fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if !REFERRER_POLICIES.contains(&self.security_headers.referrer_policy.as_str()) {
+            let mut error = figment::Error::from("invalid referrer policy".to_owned());
+            error.metadata = figment
+                .find_metadata(&format!(
+                    "{root}.security_headers.referrer_policy",
+                    root = Self::PATH.unwrap()
+                ))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "security_headers".to_owned(),
+                "referrer_policy".to_owned(),
+            ];
+            return Err(error);
+        }
+
+        if let Some(base) = &self.error_documentation_base {
+            if !base.path().ends_with('/') {
+                let mut error = figment::Error::from(
+                    "`error_documentation_base` must end with a `/`".to_owned(),
+                );
+                error.metadata = figment
+                    .find_metadata(&format!(
+                        "{root}.error_documentation_base",
+                        root = Self::PATH.unwrap()
+                    ))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "error_documentation_base".to_owned(),
+                ];
+                return Err(error);
+            }
+        }
+
+        for (index, additional_issuer) in self.additional_issuers.iter().enumerate() {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!(
+                        "{root}.additional_issuers",
+                        root = Self::PATH.unwrap()
+                    ))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "additional_issuers".to_owned(),
+                    index.to_string(),
+                ];
+                Err(error)
+            };
+
+            if additional_issuer.public_base.host_str().is_none() {
+                return annotate(figment::Error::from(
+                    "`public_base` must have a host".to_owned(),
+                ));
+            }
+
+            if additional_issuer.public_base == self.public_base {
+                return annotate(figment::Error::from(
+                    "`public_base` must differ from the main `http.public_base`".to_owned(),
+                ));
+            }
+        }
+
         for (index, listener) in self.listeners.iter().enumerate() {
             let annotate = |mut error: figment::Error| {
                 error.metadata = figment
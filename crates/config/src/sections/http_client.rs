@@ -0,0 +1,106 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::io::Cursor;
+
+use anyhow::{bail, Context as _};
+use camino::Utf8PathBuf;
+use rustls_pki_types::CertificateDer;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// Configuration of the HTTP client used for all outgoing requests
+///
+/// This applies to the requests made to upstream OAuth 2.0 providers, to the
+/// homeserver, and to any other external service.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Default)]
+pub struct HttpClientConfig {
+    /// Proxy to use for all outgoing requests, both HTTP and HTTPS.
+    ///
+    /// When not set, the proxy set by the `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY` environment variables are used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Url>,
+
+    /// Proxy to use for outgoing HTTPS requests, overriding `proxy` for those
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<Url>,
+
+    /// Destinations which should be reached directly, without going through
+    /// the proxy.
+    ///
+    /// Each entry is either a domain name, which also matches its
+    /// subdomains, an IP address, or an IP range in the CIDR notation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+
+    /// Paths to PEM-encoded CA certificates to trust, in addition to the
+    /// system ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub ca_certificate_files: Vec<Utf8PathBuf>,
+}
+
+impl HttpClientConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.proxy.is_none()
+            && self.https_proxy.is_none()
+            && self.no_proxy.is_empty()
+            && self.ca_certificate_files.is_empty()
+    }
+
+    /// Load the additional trusted CA certificates from disk
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the files could not be read, could not be
+    /// decoded as PEM, or did not contain any certificate
+    pub fn load_ca_certificates(&self) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+        let mut certificates = Vec::new();
+        for path in &self.ca_certificate_files {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read CA certificate file {path}"))?;
+
+            let mut reader = Cursor::new(pem);
+            let loaded: Result<Vec<_>, _> = rustls_pemfile::certs(&mut reader).collect();
+            let loaded =
+                loaded.with_context(|| format!("Failed to decode CA certificate file {path}"))?;
+
+            if loaded.is_empty() {
+                bail!("CA certificate file {path} does not contain any certificate");
+            }
+
+            certificates.extend(loaded);
+        }
+
+        Ok(certificates)
+    }
+}
+
+impl ConfigurationSection for HttpClientConfig {
+    const PATH: Option<&'static str> = Some("http_client");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        for (field, proxy) in [("proxy", &self.proxy), ("https_proxy", &self.https_proxy)] {
+            let Some(proxy) = proxy else { continue };
+            if !matches!(proxy.scheme(), "http" | "https" | "socks5" | "socks5h") {
+                let mut error = figment::Error::from(format!(
+                    "Unsupported proxy scheme {:?}, expected one of http, https, socks5 or socks5h",
+                    proxy.scheme()
+                ));
+                error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
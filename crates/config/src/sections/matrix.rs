@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::{num::NonZeroUsize, time::Duration};
+
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
@@ -23,10 +25,164 @@ fn default_endpoint() -> Url {
     Url::parse("http://localhost:8008/").unwrap()
 }
 
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_retry_base_delay() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_max_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(32).unwrap()
+}
+
+fn default_queue_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_circuit_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_breaker_reset_after() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Timeouts, retries and concurrency limits of the calls to the homeserver
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct HomeserverConnectionConfig {
+    /// How long a call to the homeserver can take before it fails, in
+    /// seconds. Defaults to 10 seconds.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// How many times a failed call is retried. Defaults to 2.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// The delay before the first retry of a failed call, in milliseconds,
+    /// doubled for every other retry. A random delay up to that value is used,
+    /// so that calls don't all retry at the same time. Defaults to 100
+    /// milliseconds.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_retry_base_delay")]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub retry_base_delay: Duration,
+
+    /// Maximum number of calls to the homeserver made at the same time.
+    /// Defaults to 32.
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: NonZeroUsize,
+
+    /// How long a call can wait for the other ones when the maximum
+    /// concurrency is reached, in seconds, before failing. Defaults to 5
+    /// seconds.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_queue_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub queue_timeout: Duration,
+
+    /// After how many consecutive failed calls the homeserver is considered
+    /// unavailable, making the next calls fail right away. `0` disables this.
+    /// Defaults to 5.
+    #[serde(default = "default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: u32,
+
+    /// How long the homeserver is considered unavailable after too many
+    /// failed calls, in seconds. Defaults to 30 seconds.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_circuit_breaker_reset_after")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub circuit_breaker_reset_after: Duration,
+}
+
+impl Default for HomeserverConnectionConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+            max_retries: default_max_retries(),
+            retry_base_delay: default_retry_base_delay(),
+            max_concurrency: default_max_concurrency(),
+            queue_timeout: default_queue_timeout(),
+            circuit_breaker_threshold: default_circuit_breaker_threshold(),
+            circuit_breaker_reset_after: default_circuit_breaker_reset_after(),
+        }
+    }
+}
+
+impl HomeserverConnectionConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The kind of homeserver MAS is connected to
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeserverKind {
+    /// `synapse`: Synapse, managed through its admin API. `secret` is the
+    /// admin token.
+    #[default]
+    Synapse,
+
+    /// `dendrite`: Dendrite, whose users are created through the shared-secret
+    /// registration. `secret` is the registration shared secret.
+    Dendrite,
+
+    /// `conduit`: Conduit, managed through commands sent in its admin room.
+    /// `secret` is the access token of a member of the admin room.
+    Conduit,
+}
+
+impl HomeserverKind {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How the Matrix device IDs of new sessions are allocated
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceIdAllocationConfig {
+    /// `client_provided`: the device ID asked by the client is used, unless
+    /// another client uses it for the same user, in which case a random one is
+    /// allocated
+    #[default]
+    ClientProvided,
+
+    /// `random`: a random device ID is allocated, whatever the client asked for
+    Random,
+
+    /// `deterministic`: the device ID is derived from the client and the user,
+    /// so that a client always gets the same device for a user. Logins through
+    /// the compatibility layer get a random device ID.
+    Deterministic,
+}
+
+impl DeviceIdAllocationConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// Configuration related to the Matrix homeserver
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MatrixConfig {
+    /// The kind of homeserver. Defaults to `synapse`.
+    #[serde(default, skip_serializing_if = "HomeserverKind::is_default")]
+    pub kind: HomeserverKind,
+
     /// The server name of the homeserver.
     #[serde(default = "default_homeserver")]
     pub homeserver: String,
@@ -34,13 +190,52 @@ pub struct MatrixConfig {
     /// Shared secret to use for calls to the admin API
     pub secret: String,
 
+    /// The ID of the admin room of the homeserver, where the admin commands
+    /// are sent. Required for Conduit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_room: Option<String>,
+
     /// The base URL of the homeserver's client API
     #[serde(default = "default_endpoint")]
     pub endpoint: Url,
+
+    /// How the device IDs of new sessions are allocated. Only applies to the
+    /// clients asking for a device.
+    #[serde(default, skip_serializing_if = "DeviceIdAllocationConfig::is_default")]
+    pub device_id_allocation: DeviceIdAllocationConfig,
+
+    /// Whether a client authenticating again for a user reuses the device of
+    /// its previous session with that user, which is then ended. This avoids
+    /// creating a new device on the homeserver every time a web client loses
+    /// its local storage.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reuse_devices: bool,
+
+    /// Timeouts, retries and concurrency limits of the calls to the
+    /// homeserver
+    #[serde(
+        default,
+        skip_serializing_if = "HomeserverConnectionConfig::is_default"
+    )]
+    pub connection: HomeserverConnectionConfig,
 }
 
 impl ConfigurationSection for MatrixConfig {
     const PATH: Option<&'static str> = Some("matrix");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.kind == HomeserverKind::Conduit && self.admin_room.is_none() {
+            let mut error = figment::Error::from(
+                "The admin room of the homeserver is required to connect to Conduit".to_owned(),
+            );
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "admin_room".to_owned()];
+            return Err(error);
+        }
+
+        Ok(())
+    }
 }
 
 impl MatrixConfig {
@@ -49,17 +244,27 @@ impl MatrixConfig {
         R: Rng + Send,
     {
         Self {
+            kind: HomeserverKind::default(),
             homeserver: default_homeserver(),
             secret: Alphanumeric.sample_string(&mut rng, 32),
+            admin_room: None,
             endpoint: default_endpoint(),
+            device_id_allocation: DeviceIdAllocationConfig::default(),
+            reuse_devices: false,
+            connection: HomeserverConnectionConfig::default(),
         }
     }
 
     pub(crate) fn test() -> Self {
         Self {
+            kind: HomeserverKind::default(),
             homeserver: default_homeserver(),
             secret: "test".to_owned(),
+            admin_room: None,
             endpoint: default_endpoint(),
+            device_id_allocation: DeviceIdAllocationConfig::default(),
+            reuse_devices: false,
+            connection: HomeserverConnectionConfig::default(),
         }
     }
 }
@@ -89,6 +294,7 @@ mod tests {
                 .merge(Yaml::file("config.yaml"))
                 .extract_inner::<MatrixConfig>("matrix")?;
 
+            assert_eq!(config.kind, HomeserverKind::Synapse);
             assert_eq!(&config.homeserver, "matrix.org");
             assert_eq!(&config.secret, "test");
 
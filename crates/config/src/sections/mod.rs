@@ -9,42 +9,68 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 mod account;
+mod alerts;
+mod attestation;
 mod branding;
 mod captcha;
+mod certificate_login;
 mod clients;
 mod database;
 mod email;
 mod experimental;
+mod feature_flags;
 mod http;
+mod http_client;
 mod matrix;
+mod network_zones;
 mod passwords;
 mod policy;
 mod rate_limiting;
+mod risk;
 mod secrets;
+mod session_limits;
 mod telemetry;
 mod templates;
 mod upstream_oauth2;
+mod usage_stats;
 
 pub use self::{
     account::AccountConfig,
-    branding::BrandingConfig,
-    captcha::{CaptchaConfig, CaptchaServiceKind},
-    clients::{ClientAuthMethodConfig, ClientConfig, ClientsConfig},
+    alerts::{AlertEvent, AlertSeverity, AlertsConfig},
+    attestation::{AttestationConfig, AttestationEnforcement},
+    branding::{BrandingConfig, ScopeDescriptionConfig, ScopeIconConfig},
+    captcha::{BotDetectionConfig, CaptchaConfig, CaptchaServiceKind},
+    certificate_login::{CertificateLoginConfig, CertificateLookupConfig},
+    clients::{
+        ClientAuthMethodConfig, ClientConfig, ClientsConfig, CodeBindingConfig,
+        RedirectUriValidationConfig,
+    },
     database::{DatabaseConfig, PgSslMode},
-    email::{EmailConfig, EmailSmtpMode, EmailTransportKind},
+    email::{EmailConfig, EmailDkimAlgorithm, EmailDkimConfig, EmailSmtpMode, EmailTransportKind},
     experimental::ExperimentalConfig,
+    feature_flags::{
+        ExperimentConfig, ExperimentVariantConfig, FeatureFlagConfig, FeatureFlagsConfig,
+    },
     http::{
-        BindConfig as HttpBindConfig, HttpConfig, ListenerConfig as HttpListenerConfig,
-        Resource as HttpResource, TlsConfig as HttpTlsConfig, UnixOrTcp,
+        AdditionalIssuerConfig, BindConfig as HttpBindConfig, HstsConfig, HttpCompressionConfig,
+        HttpConfig, HttpLimitsConfig, ListenerConfig as HttpListenerConfig,
+        Resource as HttpResource, SecurityHeadersConfig, TlsConfig as HttpTlsConfig, UnixOrTcp,
+    },
+    http_client::HttpClientConfig,
+    matrix::{DeviceIdAllocationConfig, HomeserverConnectionConfig, HomeserverKind, MatrixConfig},
+    network_zones::{NetworkPolicyConfig, NetworkZoneConfig, NetworkZonesConfig},
+    passwords::{
+        Algorithm as PasswordAlgorithm, CompatPasswordLoginConfig, HashingCost,
+        PasswordPolicyClassConfig, PasswordValidationWebhookConfig, PasswordsConfig,
     },
-    matrix::MatrixConfig,
-    passwords::{Algorithm as PasswordAlgorithm, PasswordsConfig},
     policy::PolicyConfig,
     rate_limiting::RateLimitingConfig,
+    risk::RiskConfig,
     secrets::SecretsConfig,
+    session_limits::{SessionEvictionConfig, SessionLimitsConfig},
     telemetry::{
-        MetricsConfig, MetricsExporterKind, Propagator, TelemetryConfig, TracingConfig,
-        TracingExporterKind,
+        AccessLogConfig, MetricsConfig, MetricsExporterKind, Propagator, TelemetryConfig,
+        TracingConfig, TracingExporterKind,
     },
     templates::TemplatesConfig,
     upstream_oauth2::{
@@ -55,6 +81,7 @@ pub use self::{
         SetEmailVerification as UpstreamOAuth2SetEmailVerification,
         TokenAuthMethod as UpstreamOAuth2TokenAuthMethod, UpstreamOAuth2Config,
     },
+    usage_stats::UsageStatsConfig,
 };
 use crate::util::ConfigurationSection;
 
@@ -116,11 +143,53 @@ pub struct RootConfig {
     #[serde(default, skip_serializing_if = "CaptchaConfig::is_default")]
     pub captcha: CaptchaConfig,
 
+    /// Configuration section to consult an external risk provider on logins
+    /// and registrations
+    #[serde(default, skip_serializing_if = "RiskConfig::is_default")]
+    pub risk: RiskConfig,
+
+    /// Configuration section to verify the platform attestations presented by
+    /// mobile clients
+    #[serde(default, skip_serializing_if = "AttestationConfig::is_default")]
+    pub attestation: AttestationConfig,
+
+    /// Configuration section to let users log in with a X.509 client
+    /// certificate
+    #[serde(default, skip_serializing_if = "CertificateLoginConfig::is_default")]
+    pub certificate_login: CertificateLoginConfig,
+
+    /// Configuration section to limit the number of simultaneous sessions of
+    /// users
+    #[serde(default, skip_serializing_if = "SessionLimitsConfig::is_default")]
+    pub session_limits: SessionLimitsConfig,
+
+    /// Configuration section to restrict where the admin API, logins and
+    /// registrations can be used from
+    #[serde(default, skip_serializing_if = "NetworkZonesConfig::is_default")]
+    pub network_zones: NetworkZonesConfig,
+
     /// Configuration section to configure features related to account
     /// management
     #[serde(default, skip_serializing_if = "AccountConfig::is_default")]
     pub account: AccountConfig,
 
+    /// Configuration section for the opt-in reporting of anonymous usage
+    /// statistics
+    #[serde(default, skip_serializing_if = "UsageStatsConfig::is_default")]
+    pub usage_stats: UsageStatsConfig,
+
+    /// Configuration of the HTTP client used for outgoing requests
+    #[serde(default, skip_serializing_if = "HttpClientConfig::is_default")]
+    pub http_client: HttpClientConfig,
+
+    /// Configuration of the alerts sent to the administrators in a Matrix room
+    #[serde(default, skip_serializing_if = "AlertsConfig::is_default")]
+    pub alerts: AlertsConfig,
+
+    /// Configuration section to roll out features gradually
+    #[serde(default, skip_serializing_if = "FeatureFlagsConfig::is_default")]
+    pub feature_flags: FeatureFlagsConfig,
+
     /// Experimental configuration options
     #[serde(default, skip_serializing_if = "ExperimentalConfig::is_default")]
     pub experimental: ExperimentalConfig,
@@ -142,7 +211,16 @@ impl ConfigurationSection for RootConfig {
         self.upstream_oauth2.validate(figment)?;
         self.branding.validate(figment)?;
         self.captcha.validate(figment)?;
+        self.risk.validate(figment)?;
+        self.attestation.validate(figment)?;
+        self.certificate_login.validate(figment)?;
+        self.session_limits.validate(figment)?;
+        self.network_zones.validate(figment)?;
         self.account.validate(figment)?;
+        self.usage_stats.validate(figment)?;
+        self.http_client.validate(figment)?;
+        self.alerts.validate(figment)?;
+        self.feature_flags.validate(figment)?;
         self.experimental.validate(figment)?;
 
         Ok(())
@@ -174,7 +252,16 @@ impl RootConfig {
             upstream_oauth2: UpstreamOAuth2Config::default(),
             branding: BrandingConfig::default(),
             captcha: CaptchaConfig::default(),
+            risk: RiskConfig::default(),
+            attestation: AttestationConfig::default(),
+            certificate_login: CertificateLoginConfig::default(),
+            session_limits: SessionLimitsConfig::default(),
+            network_zones: NetworkZonesConfig::default(),
             account: AccountConfig::default(),
+            usage_stats: UsageStatsConfig::default(),
+            http_client: HttpClientConfig::default(),
+            alerts: AlertsConfig::default(),
+            feature_flags: FeatureFlagsConfig::default(),
             experimental: ExperimentalConfig::default(),
         })
     }
@@ -197,7 +284,16 @@ impl RootConfig {
             upstream_oauth2: UpstreamOAuth2Config::default(),
             branding: BrandingConfig::default(),
             captcha: CaptchaConfig::default(),
+            risk: RiskConfig::default(),
+            attestation: AttestationConfig::default(),
+            certificate_login: CertificateLoginConfig::default(),
+            session_limits: SessionLimitsConfig::default(),
+            network_zones: NetworkZonesConfig::default(),
             account: AccountConfig::default(),
+            usage_stats: UsageStatsConfig::default(),
+            http_client: HttpClientConfig::default(),
+            alerts: AlertsConfig::default(),
+            feature_flags: FeatureFlagsConfig::default(),
             experimental: ExperimentalConfig::default(),
         }
     }
@@ -238,9 +334,36 @@ pub struct AppConfig {
     #[serde(default)]
     pub captcha: CaptchaConfig,
 
+    #[serde(default)]
+    pub risk: RiskConfig,
+
+    #[serde(default)]
+    pub attestation: AttestationConfig,
+
+    #[serde(default)]
+    pub certificate_login: CertificateLoginConfig,
+
+    #[serde(default)]
+    pub session_limits: SessionLimitsConfig,
+
+    #[serde(default)]
+    pub network_zones: NetworkZonesConfig,
+
     #[serde(default)]
     pub account: AccountConfig,
 
+    #[serde(default)]
+    pub usage_stats: UsageStatsConfig,
+
+    #[serde(default)]
+    pub http_client: HttpClientConfig,
+
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+
+    #[serde(default)]
+    pub feature_flags: FeatureFlagsConfig,
+
     #[serde(default)]
     pub experimental: ExperimentalConfig,
 }
@@ -258,7 +381,16 @@ impl ConfigurationSection for AppConfig {
         self.rate_limiting.validate(figment)?;
         self.branding.validate(figment)?;
         self.captcha.validate(figment)?;
+        self.risk.validate(figment)?;
+        self.attestation.validate(figment)?;
+        self.certificate_login.validate(figment)?;
+        self.session_limits.validate(figment)?;
+        self.network_zones.validate(figment)?;
         self.account.validate(figment)?;
+        self.usage_stats.validate(figment)?;
+        self.http_client.validate(figment)?;
+        self.alerts.validate(figment)?;
+        self.feature_flags.validate(figment)?;
         self.experimental.validate(figment)?;
 
         Ok(())
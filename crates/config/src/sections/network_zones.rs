@@ -0,0 +1,227 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use ipnetwork::IpNetwork;
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+/// A named set of networks and countries
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct NetworkZoneConfig {
+    /// Name of the zone, used to reference it in the policies
+    pub name: String,
+
+    /// IP networks which are part of the zone, in CIDR notation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cidrs: Vec<IpNetwork>,
+
+    /// Countries which are part of the zone, as ISO 3166-1 alpha-2 codes.
+    ///
+    /// The country of a request is read from the `country_header`, which must
+    /// be set by a trusted reverse proxy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+}
+
+/// Which network zones can access a part of the service
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct NetworkPolicyConfig {
+    /// Only requests from those zones are allowed. When empty, requests from
+    /// anywhere are allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    /// Requests from those zones are denied, even if they are in an allowed
+    /// zone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl NetworkPolicyConfig {
+    fn is_default(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn zones(&self) -> impl Iterator<Item = &String> {
+        self.allow.iter().chain(self.deny.iter())
+    }
+}
+
+/// Configuration section to restrict where the admin API, logins and
+/// registrations can be used from
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct NetworkZonesConfig {
+    /// Name of the header set by the reverse proxy with the country of the
+    /// client, like `CF-IPCountry`. Required to use countries in the zones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_header: Option<String>,
+
+    /// The network zones, referenced by name in the policies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<NetworkZoneConfig>,
+
+    /// Where the admin API, and GraphQL with an admin scope, can be used from
+    #[serde(default, skip_serializing_if = "NetworkPolicyConfig::is_default")]
+    pub admin_api: NetworkPolicyConfig,
+
+    /// Where users can log in from
+    #[serde(default, skip_serializing_if = "NetworkPolicyConfig::is_default")]
+    pub login: NetworkPolicyConfig,
+
+    /// Where users can register from
+    #[serde(default, skip_serializing_if = "NetworkPolicyConfig::is_default")]
+    pub registration: NetworkPolicyConfig,
+}
+
+impl NetworkZonesConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.country_header.is_none()
+            && self.zones.is_empty()
+            && self.admin_api.is_default()
+            && self.login.is_default()
+            && self.registration.is_default()
+    }
+}
+
+impl ConfigurationSection for NetworkZonesConfig {
+    const PATH: Option<&'static str> = Some("network_zones");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on_field = |mut error: figment::error::Error, field: &str| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            error
+        };
+
+        let mut names = BTreeSet::new();
+        for zone in &self.zones {
+            if !names.insert(zone.name.as_str()) {
+                return Err(error_on_field(
+                    figment::error::Error::custom(format!(
+                        "zone {:?} is defined more than once",
+                        zone.name
+                    )),
+                    "zones",
+                ));
+            }
+
+            if zone.cidrs.is_empty() && zone.countries.is_empty() {
+                return Err(error_on_field(
+                    figment::error::Error::custom(format!(
+                        "zone {:?} has neither CIDRs nor countries",
+                        zone.name
+                    )),
+                    "zones",
+                ));
+            }
+
+            if let Some(country) = zone.countries.iter().find(|country| {
+                country.len() != 2 || !country.bytes().all(|b| b.is_ascii_alphabetic())
+            }) {
+                return Err(error_on_field(
+                    figment::error::Error::custom(format!(
+                        "{country:?} is not a two-letter country code"
+                    )),
+                    "zones",
+                ));
+            }
+
+            if !zone.countries.is_empty() && self.country_header.is_none() {
+                return Err(error_on_field(
+                    figment::error::Error::custom(
+                        "zones with countries require the country_header to be set",
+                    ),
+                    "country_header",
+                ));
+            }
+        }
+
+        for (field, policy) in [
+            ("admin_api", &self.admin_api),
+            ("login", &self.login),
+            ("registration", &self.registration),
+        ] {
+            if let Some(zone) = policy.zones().find(|zone| !names.contains(zone.as_str())) {
+                return Err(error_on_field(
+                    figment::error::Error::custom(format!("unknown zone {zone:?}")),
+                    field,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    network_zones:
+                      country_header: CF-IPCountry
+                      zones:
+                        - name: corp_vpn
+                          cidrs:
+                            - 10.8.0.0/16
+                            - fd00:8::/32
+                        - name: blocked
+                          countries: [KP]
+                      admin_api:
+                        allow: [corp_vpn]
+                      registration:
+                        deny: [blocked]
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = NetworkZonesConfig::extract(&figment)?;
+
+            assert_eq!(config.zones.len(), 2);
+            assert_eq!(config.zones[0].cidrs.len(), 2);
+            assert_eq!(config.admin_api.allow, ["corp_vpn"]);
+            assert!(config.login.is_default());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_unknown_zone() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    network_zones:
+                      login:
+                        deny: [nowhere]
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(NetworkZonesConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
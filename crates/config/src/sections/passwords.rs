@@ -4,12 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::cmp::Reverse;
+use std::{cmp::Reverse, collections::BTreeMap, num::NonZeroUsize, time::Duration};
 
 use anyhow::bail;
 use camino::Utf8PathBuf;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
 
 use crate::ConfigurationSection;
 
@@ -18,6 +20,9 @@ fn default_schemes() -> Vec<HashingScheme> {
         version: 1,
         algorithm: Algorithm::Argon2id,
         cost: None,
+        memory_cost: None,
+        time_cost: None,
+        parallelism: None,
         secret: None,
         secret_file: None,
     }]
@@ -31,7 +36,99 @@ fn default_minimum_complexity() -> u8 {
     3
 }
 
+fn default_validation_webhook_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_hashing_queue_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn is_default_hashing_queue_timeout(value: &Duration) -> bool {
+    *value == default_hashing_queue_timeout()
+}
+
+/// An external webhook validating the new passwords, on top of the complexity
+/// check
+///
+/// The webhook receives a `POST` request with a JSON body containing the
+/// `username`, the `password` and the `locale` of the user, and answers with a
+/// JSON body with an `allowed` boolean and an optional `reason`, which is
+/// shown to the user in their language when the password is rejected.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordValidationWebhookConfig {
+    /// URL of the webhook
+    pub url: Url,
+
+    /// Maximum time to wait for an answer from the webhook, in seconds.
+    /// Defaults to 5 seconds.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_validation_webhook_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// Whether to accept the password when the webhook fails or does not
+    /// answer in time. Defaults to `false`, rejecting the password.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+/// Password rules applying to a class of users, like service accounts, instead
+/// of the default ones
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordPolicyClassConfig {
+    /// Score between 0 and 4 determining the minimum allowed password
+    /// complexity. Defaults to the global `minimum_complexity`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_complexity: Option<u8>,
+
+    /// Minimum number of characters of the passwords. Defaults to 0.
+    #[serde(default, skip_serializing_if = "is_default_minimum_length")]
+    pub minimum_length: usize,
+
+    /// How long a password can be used before the user has to change it, in
+    /// seconds. Passwords of this class never expire if unset.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub max_age: Option<Duration>,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_default_minimum_length(value: &usize) -> bool {
+    *value == 0
+}
+
+/// How the legacy `m.login.password` login of the Matrix client-server API is
+/// handled, for clients which haven't moved to OAuth 2.0 yet
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatPasswordLoginConfig {
+    /// `allowed`: clients can log in with a password
+    #[default]
+    Allowed,
+
+    /// `deprecated`: clients can still log in with a password, but each login
+    /// is logged as a warning naming the client, to find the ones which still
+    /// rely on it
+    Deprecated,
+
+    /// `disabled`: the password login is no longer advertised, and clients
+    /// have to log in through the browser
+    Disabled,
+}
+
+impl CompatPasswordLoginConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// User password hashing config
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PasswordsConfig {
     /// Whether password-based authentication is enabled
@@ -52,6 +149,46 @@ pub struct PasswordsConfig {
     /// - 4: any more than that
     #[serde(default = "default_minimum_complexity")]
     minimum_complexity: u8,
+
+    /// An external webhook validating new passwords, for example against a
+    /// corporate password policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    validation_webhook: Option<PasswordValidationWebhookConfig>,
+
+    /// How long a password can be used before the user has to change it on
+    /// their next login, in seconds. This applies to users without a password
+    /// policy class. Passwords never expire if unset.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    max_age: Option<Duration>,
+
+    /// Classes of password rules which can be assigned to users through the
+    /// admin API, keyed by their name, like `service` for bot accounts
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    policy_classes: BTreeMap<String, PasswordPolicyClassConfig>,
+
+    /// Maximum number of passwords hashed or verified at the same time.
+    /// Defaults to the number of available CPU cores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hashing_concurrency: Option<NonZeroUsize>,
+
+    /// How long a login can wait for a password to be hashed when the
+    /// maximum concurrency is reached, in seconds, before failing with a
+    /// temporary error. Defaults to 5 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_hashing_queue_timeout",
+        skip_serializing_if = "is_default_hashing_queue_timeout"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    hashing_queue_timeout: Duration,
+
+    /// How the password login of the Matrix client-server API is handled.
+    /// Defaults to `allowed`. Has no effect if password-based authentication
+    /// is disabled.
+    #[serde(default, skip_serializing_if = "CompatPasswordLoginConfig::is_default")]
+    compat_login: CompatPasswordLoginConfig,
 }
 
 impl Default for PasswordsConfig {
@@ -60,6 +197,12 @@ impl Default for PasswordsConfig {
             enabled: default_enabled(),
             schemes: default_schemes(),
             minimum_complexity: default_minimum_complexity(),
+            validation_webhook: None,
+            max_age: None,
+            policy_classes: BTreeMap::new(),
+            hashing_concurrency: None,
+            hashing_queue_timeout: default_hashing_queue_timeout(),
+            compat_login: CompatPasswordLoginConfig::default(),
         }
     }
 }
@@ -112,6 +255,46 @@ impl PasswordsConfig {
         self.minimum_complexity
     }
 
+    /// The external webhook validating new passwords, if any
+    #[must_use]
+    pub fn validation_webhook(&self) -> Option<&PasswordValidationWebhookConfig> {
+        self.validation_webhook.as_ref()
+    }
+
+    /// How long the passwords of users without a password policy class can
+    /// be used, if they expire
+    #[must_use]
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The classes of password rules which can be assigned to users
+    #[must_use]
+    pub fn policy_classes(&self) -> &BTreeMap<String, PasswordPolicyClassConfig> {
+        &self.policy_classes
+    }
+
+    /// Maximum number of passwords hashed or verified at the same time
+    #[must_use]
+    pub fn hashing_concurrency(&self) -> usize {
+        self.hashing_concurrency
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    }
+
+    /// How long to wait for a password to be hashed when the maximum
+    /// concurrency is reached
+    #[must_use]
+    pub fn hashing_queue_timeout(&self) -> Duration {
+        self.hashing_queue_timeout
+    }
+
+    /// How the password login of the Matrix client-server API is handled
+    #[must_use]
+    pub fn compat_login(&self) -> CompatPasswordLoginConfig {
+        self.compat_login
+    }
+
     /// Load the password hashing schemes defined by the config
     ///
     /// # Errors
@@ -120,7 +303,7 @@ impl PasswordsConfig {
     /// not be read.
     pub async fn load(
         &self,
-    ) -> Result<Vec<(u16, Algorithm, Option<u32>, Option<Vec<u8>>)>, anyhow::Error> {
+    ) -> Result<Vec<(u16, Algorithm, HashingCost, Option<Vec<u8>>)>, anyhow::Error> {
         let mut schemes: Vec<&HashingScheme> = self.schemes.iter().collect();
         schemes.sort_unstable_by_key(|a| Reverse(a.version));
         schemes.dedup_by_key(|a| a.version);
@@ -147,7 +330,14 @@ impl PasswordsConfig {
                 (None, None) => None,
             };
 
-            mapped_result.push((scheme.version, scheme.algorithm, scheme.cost, secret));
+            let cost = HashingCost {
+                cost: scheme.cost,
+                memory_cost: scheme.memory_cost,
+                time_cost: scheme.time_cost,
+                parallelism: scheme.parallelism,
+            };
+
+            mapped_result.push((scheme.version, scheme.algorithm, cost, secret));
         }
 
         Ok(mapped_result)
@@ -165,6 +355,21 @@ pub struct HashingScheme {
     #[schemars(default = "default_bcrypt_cost")]
     cost: Option<u32>,
 
+    /// Memory cost for the argon2id algorithm, in KiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_argon2id_memory_cost")]
+    memory_cost: Option<u32>,
+
+    /// Number of iterations for the argon2id algorithm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_argon2id_time_cost")]
+    time_cost: Option<u32>,
+
+    /// Degree of parallelism for the argon2id algorithm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_argon2id_parallelism")]
+    parallelism: Option<u32>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     secret: Option<String>,
 
@@ -178,6 +383,38 @@ fn default_bcrypt_cost() -> Option<u32> {
     Some(12)
 }
 
+#[allow(clippy::unnecessary_wraps)]
+fn default_argon2id_memory_cost() -> Option<u32> {
+    Some(19 * 1024)
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn default_argon2id_time_cost() -> Option<u32> {
+    Some(2)
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn default_argon2id_parallelism() -> Option<u32> {
+    Some(1)
+}
+
+/// The cost parameters of a password hashing scheme. The ones which are not
+/// set use the defaults of the algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashingCost {
+    /// Cost for the bcrypt algorithm
+    pub cost: Option<u32>,
+
+    /// Memory cost for the argon2id algorithm, in KiB
+    pub memory_cost: Option<u32>,
+
+    /// Number of iterations for the argon2id algorithm
+    pub time_cost: Option<u32>,
+
+    /// Degree of parallelism for the argon2id algorithm
+    pub parallelism: Option<u32>,
+}
+
 /// A hashing algorithm
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
@@ -25,6 +25,26 @@ pub struct RateLimitingConfig {
     /// based on source address.
     #[serde(default = "default_registration")]
     pub registration: RateLimiterConfiguration,
+    /// Controls how many device code entries are permitted
+    /// based on source IP address.
+    /// This can protect against brute forcing the user codes of the device
+    /// authorization grant.
+    #[serde(default = "default_device_code_entry")]
+    pub device_code_entry: RateLimiterConfiguration,
+    /// Controls how many login and registration forms can be submitted
+    /// based on source IP address before the submissions are considered
+    /// suspicious by the bot detection.
+    /// This is not a hard limit, only a signal used by the bot detection.
+    #[serde(default = "default_form_submissions")]
+    pub form_submissions: RateLimiterConfiguration,
+    /// Controls how many logins with a Matrix ID of another homeserver are
+    /// permitted based on source IP address.
+    /// Each of them makes a request to the homeserver of that Matrix ID.
+    #[serde(default = "default_matrix_id_login")]
+    pub matrix_id_login: RateLimiterConfiguration,
+    /// Email verification-specific rate limits
+    #[serde(default)]
+    pub email_verification: EmailVerificationRateLimitingConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
@@ -67,6 +87,24 @@ pub struct AccountRecoveryRateLimitingConfig {
     pub per_address: RateLimiterConfiguration,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct EmailVerificationRateLimitingConfig {
+    /// Controls how many verification emails can be sent
+    /// to an email address.
+    /// This can protect against causing e-mail spam to one target.
+    ///
+    /// Note: this limit also applies to the first email sent after adding the
+    /// email address.
+    #[serde(default = "default_email_verification_resend")]
+    pub resend: RateLimiterConfiguration,
+    /// Controls how many verification codes can be submitted
+    /// for an email address, either by entering them or through the link in
+    /// the verification email.
+    /// This can protect against brute forcing the 6-digit codes.
+    #[serde(default = "default_email_verification_code_attempts")]
+    pub code_attempts: RateLimiterConfiguration,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct RateLimiterConfiguration {
     /// A one-off burst of actions that the user can perform
@@ -131,6 +169,29 @@ impl ConfigurationSection for RateLimitingConfig {
             return Err(error_on_field(error, "registration"));
         }
 
+        if let Some(error) = error_on_limiter(&self.device_code_entry) {
+            return Err(error_on_field(error, "device_code_entry"));
+        }
+
+        if let Some(error) = error_on_limiter(&self.form_submissions) {
+            return Err(error_on_field(error, "form_submissions"));
+        }
+
+        if let Some(error) = error_on_limiter(&self.matrix_id_login) {
+            return Err(error_on_field(error, "matrix_id_login"));
+        }
+
+        if let Some(error) = error_on_limiter(&self.email_verification.resend) {
+            return Err(error_on_nested_field(error, "email_verification", "resend"));
+        }
+        if let Some(error) = error_on_limiter(&self.email_verification.code_attempts) {
+            return Err(error_on_nested_field(
+                error,
+                "email_verification",
+                "code_attempts",
+            ));
+        }
+
         if let Some(error) = error_on_limiter(&self.login.per_ip) {
             return Err(error_on_nested_field(error, "login", "per_ip"));
         }
@@ -179,6 +240,27 @@ fn default_registration() -> RateLimiterConfiguration {
     }
 }
 
+fn default_device_code_entry() -> RateLimiterConfiguration {
+    RateLimiterConfiguration {
+        burst: NonZeroU32::new(10).unwrap(),
+        per_second: 10.0 / 600.0,
+    }
+}
+
+fn default_form_submissions() -> RateLimiterConfiguration {
+    RateLimiterConfiguration {
+        burst: NonZeroU32::new(5).unwrap(),
+        per_second: 5.0 / 60.0,
+    }
+}
+
+fn default_matrix_id_login() -> RateLimiterConfiguration {
+    RateLimiterConfiguration {
+        burst: NonZeroU32::new(10).unwrap(),
+        per_second: 10.0 / 600.0,
+    }
+}
+
 fn default_account_recovery_per_ip() -> RateLimiterConfiguration {
     RateLimiterConfiguration {
         burst: NonZeroU32::new(3).unwrap(),
@@ -193,12 +275,30 @@ fn default_account_recovery_per_address() -> RateLimiterConfiguration {
     }
 }
 
+fn default_email_verification_resend() -> RateLimiterConfiguration {
+    RateLimiterConfiguration {
+        burst: NonZeroU32::new(3).unwrap(),
+        per_second: 3.0 / 3600.0,
+    }
+}
+
+fn default_email_verification_code_attempts() -> RateLimiterConfiguration {
+    RateLimiterConfiguration {
+        burst: NonZeroU32::new(10).unwrap(),
+        per_second: 10.0 / 3600.0,
+    }
+}
+
 impl Default for RateLimitingConfig {
     fn default() -> Self {
         RateLimitingConfig {
             login: LoginRateLimitingConfig::default(),
             registration: default_registration(),
+            device_code_entry: default_device_code_entry(),
+            form_submissions: default_form_submissions(),
+            matrix_id_login: default_matrix_id_login(),
             account_recovery: AccountRecoveryRateLimitingConfig::default(),
+            email_verification: EmailVerificationRateLimitingConfig::default(),
         }
     }
 }
@@ -220,3 +320,12 @@ impl Default for AccountRecoveryRateLimitingConfig {
         }
     }
 }
+
+impl Default for EmailVerificationRateLimitingConfig {
+    fn default() -> Self {
+        EmailVerificationRateLimitingConfig {
+            resend: default_email_verification_resend(),
+            code_attempts: default_email_verification_code_attempts(),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
+
+use crate::ConfigurationSection;
+
+const fn default_timeout() -> Duration {
+    Duration::from_millis(2000)
+}
+
+fn is_default_timeout(value: &Duration) -> bool {
+    *value == default_timeout()
+}
+
+const fn default_cache_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn is_default_cache_ttl(value: &Duration) -> bool {
+    *value == default_cache_ttl()
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value == default_true()
+}
+
+/// Configuration section to consult an external risk provider on logins and
+/// registrations
+///
+/// The provider receives the context of the attempt as JSON, and replies with
+/// a verdict: `allow`, `challenge` (require a CAPTCHA) or `deny`.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RiskConfig {
+    /// URL of the risk provider endpoint. When not set, no risk provider is
+    /// consulted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<Url>,
+
+    /// Token sent to the risk provider in the `Authorization` header, as a
+    /// bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// How long to wait for the risk provider to reply, in milliseconds.
+    /// Defaults to 2 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_timeout",
+        skip_serializing_if = "is_default_timeout"
+    )]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// Whether to allow the attempt when the risk provider fails or doesn't
+    /// reply in time. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub fail_open: bool,
+
+    /// How long to reuse the verdict of the risk provider for identical
+    /// attempts, in seconds. Set to 0 to disable the cache. Defaults to 60
+    /// seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_cache_ttl",
+        skip_serializing_if = "is_default_cache_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub cache_ttl: Duration,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            token: None,
+            timeout: default_timeout(),
+            fail_open: default_true(),
+            cache_ttl: default_cache_ttl(),
+        }
+    }
+}
+
+impl RiskConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.endpoint.is_none()
+            && self.token.is_none()
+            && is_default_timeout(&self.timeout)
+            && is_default_true(&self.fail_open)
+            && is_default_cache_ttl(&self.cache_ttl)
+    }
+}
+
+impl ConfigurationSection for RiskConfig {
+    const PATH: Option<&'static str> = Some("risk");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on_field = |mut error: figment::error::Error, field: &'static str| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            error
+        };
+
+        if self.timeout.is_zero() {
+            return Err(error_on_field(
+                figment::error::Error::custom("timeout must be greater than 0"),
+                "timeout",
+            ));
+        }
+
+        if self.endpoint.is_none() && self.token.is_some() {
+            return Err(error_on_field(
+                figment::error::Error::custom("token requires an endpoint to be set"),
+                "token",
+            ));
+        }
+
+        Ok(())
+    }
+}
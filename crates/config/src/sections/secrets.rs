@@ -71,8 +71,36 @@ impl SecretsConfig {
     /// Returns an error when a key could not be imported
     #[tracing::instrument(name = "secrets.load", skip_all, err(Debug))]
     pub async fn key_store(&self) -> anyhow::Result<Keystore> {
-        let mut keys = Vec::with_capacity(self.keys.len());
-        for item in &self.keys {
+        Self::load_keys(&self.keys).await
+    }
+
+    /// Derive a signing and verifying keystore with only the keys with the
+    /// given IDs. All the keys are used if the list is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when a key could not be imported, or if no key has one
+    /// of the given IDs
+    #[tracing::instrument(name = "secrets.load_subset", skip_all, err(Debug))]
+    pub async fn key_store_with_kids(&self, kids: &[String]) -> anyhow::Result<Keystore> {
+        if kids.is_empty() {
+            return self.key_store().await;
+        }
+
+        let mut selected = Vec::with_capacity(kids.len());
+        for kid in kids {
+            let Some(item) = self.keys.iter().find(|item| &item.kid == kid) else {
+                bail!("No key with ID {kid:?} in `secrets.keys`");
+            };
+            selected.push(item.clone());
+        }
+
+        Self::load_keys(&selected).await
+    }
+
+    async fn load_keys(items: &[KeyConfig]) -> anyhow::Result<Keystore> {
+        let mut keys = Vec::with_capacity(items.len());
+        for item in items {
             let password = match (&item.password, &item.password_file) {
                 (None, None) => None,
                 (Some(_), Some(_)) => {
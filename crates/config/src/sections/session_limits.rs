@@ -0,0 +1,60 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::num::NonZeroU32;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+/// What happens when a new session would exceed a limit
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEvictionConfig {
+    /// The new session is rejected
+    #[default]
+    Reject,
+
+    /// The oldest sessions are ended to make room for the new one
+    RevokeOldest,
+}
+
+impl SessionEvictionConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Configuration section to limit the number of simultaneous sessions of
+/// users
+///
+/// Limits per client can be set with the `max_sessions_per_user` option of
+/// the clients, and limits per user can be overridden through the admin API.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct SessionLimitsConfig {
+    /// Maximum number of active sessions, compatibility and OAuth 2.0 ones,
+    /// a user can have at the same time. No limit if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<u32>", range(min = 1))]
+    pub max_sessions_per_user: Option<NonZeroU32>,
+
+    /// What happens when a new session would exceed a limit. Defaults to
+    /// `reject`
+    #[serde(default, skip_serializing_if = "SessionEvictionConfig::is_default")]
+    pub eviction: SessionEvictionConfig,
+}
+
+impl SessionLimitsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.max_sessions_per_user.is_none() && self.eviction.is_default()
+    }
+}
+
+impl ConfigurationSection for SessionLimitsConfig {
+    const PATH: Option<&'static str> = Some("session_limits");
+}
@@ -4,6 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use camino::Utf8PathBuf;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -131,6 +132,89 @@ impl SentryConfig {
     }
 }
 
+fn default_redacted_query_parameters() -> Vec<String> {
+    [
+        "access_token",
+        "refresh_token",
+        "id_token_hint",
+        "code",
+        "state",
+        "login_token",
+        "token",
+        "client_secret",
+    ]
+    .into_iter()
+    .map(ToOwned::to_owned)
+    .collect()
+}
+
+fn is_default_redacted_query_parameters(value: &Vec<String>) -> bool {
+    *value == default_redacted_query_parameters()
+}
+
+const fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref, clippy::float_cmp)]
+fn is_default_sample_ratio(value: &f64) -> bool {
+    *value == default_sample_ratio()
+}
+
+/// Configuration of the structured access log
+///
+/// When enabled, one JSON object is written per HTTP request, with the route,
+/// status, latency and the authenticated user and session when available.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AccessLogConfig {
+    /// Whether to write the access log. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// File to append the access log to. Defaults to the standard output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub path: Option<Utf8PathBuf>,
+
+    /// Query parameters whose values are replaced by `[REDACTED]` in the logs.
+    /// Defaults to the parameters which can carry tokens and authorization
+    /// codes.
+    #[serde(
+        default = "default_redacted_query_parameters",
+        skip_serializing_if = "is_default_redacted_query_parameters"
+    )]
+    pub redacted_query_parameters: Vec<String>,
+
+    /// Fraction of the successful requests to log, between `0.0` and `1.0`.
+    /// Requests resulting in an error are always logged. Defaults to `1.0`.
+    #[serde(
+        default = "default_sample_ratio",
+        skip_serializing_if = "is_default_sample_ratio"
+    )]
+    pub sample_ratio: f64,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            redacted_query_parameters: default_redacted_query_parameters(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+impl AccessLogConfig {
+    /// Returns true if all fields are at their default values
+    fn is_default(&self) -> bool {
+        !self.enabled
+            && self.path.is_none()
+            && is_default_redacted_query_parameters(&self.redacted_query_parameters)
+            && is_default_sample_ratio(&self.sample_ratio)
+    }
+}
+
 /// Configuration related to sending monitoring data
 #[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct TelemetryConfig {
@@ -145,15 +229,45 @@ pub struct TelemetryConfig {
     /// Configuration related to the Sentry integration
     #[serde(default, skip_serializing_if = "SentryConfig::is_default")]
     pub sentry: SentryConfig,
+
+    /// Configuration of the structured access log
+    #[serde(default, skip_serializing_if = "AccessLogConfig::is_default")]
+    pub access_log: AccessLogConfig,
 }
 
 impl TelemetryConfig {
     /// Returns true if all fields are at their default values
     pub(crate) fn is_default(&self) -> bool {
-        self.tracing.is_default() && self.metrics.is_default() && self.sentry.is_default()
+        self.tracing.is_default()
+            && self.metrics.is_default()
+            && self.sentry.is_default()
+            && self.access_log.is_default()
     }
 }
 
 impl ConfigurationSection for TelemetryConfig {
     const PATH: Option<&'static str> = Some("telemetry");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if !(0.0..=1.0).contains(&self.access_log.sample_ratio) {
+            let mut error = figment::Error::from(
+                "access log sample ratio must be between 0.0 and 1.0".to_owned(),
+            );
+            error.metadata = figment
+                .find_metadata(&format!(
+                    "{root}.access_log.sample_ratio",
+                    root = Self::PATH.unwrap()
+                ))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "access_log".to_owned(),
+                "sample_ratio".to_owned(),
+            ];
+            return Err(error);
+        }
+
+        Ok(())
+    }
 }
@@ -90,6 +90,16 @@ pub struct TemplatesConfig {
     )]
     #[schemars(with = "Option<String>")]
     pub translations_path: Utf8PathBuf,
+
+    /// Path to a folder holding overrides of the email templates.
+    ///
+    /// Files at the root of this folder (e.g. `verification.html`) override
+    /// the email templates for all languages, while files in a subfolder named
+    /// after a locale (e.g. `fr/verification.html`) only override them for
+    /// that language.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub email_overrides_path: Option<Utf8PathBuf>,
 }
 
 impl Default for TemplatesConfig {
@@ -98,6 +108,7 @@ impl Default for TemplatesConfig {
             path: default_path(),
             assets_manifest: default_assets_path(),
             translations_path: default_translations_path(),
+            email_overrides_path: None,
         }
     }
 }
@@ -108,6 +119,7 @@ impl TemplatesConfig {
         is_default_path(&self.path)
             && is_default_assets_path(&self.assets_manifest)
             && is_default_translations_path(&self.translations_path)
+            && self.email_overrides_path.is_none()
     }
 }
 
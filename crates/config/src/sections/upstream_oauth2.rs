@@ -6,26 +6,57 @@
 
 use std::collections::BTreeMap;
 
+use chrono::Duration;
 use mas_iana::jose::JsonWebSignatureAlg;
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
-use serde_with::skip_serializing_none;
+use serde_with::{serde_as, skip_serializing_none};
 use ulid::Ulid;
 use url::Url;
 
 use crate::ConfigurationSection;
 
+fn default_jwt_leeway() -> Duration {
+    mas_jose::claims::DEFAULT_LEEWAY
+}
+
+fn is_default_jwt_leeway(value: &Duration) -> bool {
+    *value == default_jwt_leeway()
+}
+
 /// Upstream OAuth 2.0 providers configuration
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UpstreamOAuth2Config {
+    /// How far apart the clocks of this service and of the issuers of the
+    /// tokens it checks can be, in seconds. This applies to the ID tokens of
+    /// the upstream providers and to the JWT assertions clients authenticate
+    /// with. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(max = 3600))]
+    #[serde(
+        default = "default_jwt_leeway",
+        skip_serializing_if = "is_default_jwt_leeway"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub jwt_leeway: Duration,
+
     /// List of OAuth 2.0 providers
     pub providers: Vec<Provider>,
 }
 
+impl Default for UpstreamOAuth2Config {
+    fn default() -> Self {
+        Self {
+            jwt_leeway: default_jwt_leeway(),
+            providers: Vec::new(),
+        }
+    }
+}
+
 impl UpstreamOAuth2Config {
     /// Returns true if the configuration is the default one
     pub(crate) fn is_default(&self) -> bool {
-        self.providers.is_empty()
+        is_default_jwt_leeway(&self.jwt_leeway) && self.providers.is_empty()
     }
 }
 
@@ -33,6 +64,14 @@ impl ConfigurationSection for UpstreamOAuth2Config {
     const PATH: Option<&'static str> = Some("upstream_oauth2");
 
     fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.jwt_leeway < Duration::zero() || self.jwt_leeway > Duration::hours(1) {
+            let mut error = figment::Error::custom("must be between 0 and 3600 seconds");
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "jwt_leeway".to_owned()];
+            return Err(error);
+        }
+
         for (index, provider) in self.providers.iter().enumerate() {
             let annotate = |mut error: figment::Error| {
                 error.metadata = figment
@@ -0,0 +1,60 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// Configuration section for the opt-in reporting of anonymous usage
+/// statistics
+///
+/// When enabled, the worker sends once a day aggregate counts (number of
+/// users, daily active users, logins and issued tokens) along with the version
+/// of the service to the configured endpoint. No personal information is sent.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Default)]
+pub struct UsageStatsConfig {
+    /// Whether to report usage statistics. Defaults to `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enabled: bool,
+
+    /// The endpoint to which the report is sent, as a JSON `POST` request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<Url>,
+}
+
+impl UsageStatsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled && self.endpoint.is_none()
+    }
+
+    /// Returns the endpoint to report to, if reporting is enabled
+    #[must_use]
+    pub fn report_endpoint(&self) -> Option<&Url> {
+        if self.enabled {
+            self.endpoint.as_ref()
+        } else {
+            None
+        }
+    }
+}
+
+impl ConfigurationSection for UsageStatsConfig {
+    const PATH: Option<&'static str> = Some("usage_stats");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.enabled && self.endpoint.is_none() {
+            let mut error = figment::Error::missing_field("endpoint");
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "endpoint".to_owned()];
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
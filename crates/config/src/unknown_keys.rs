@@ -0,0 +1,284 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Detection of the keys set in the configuration which are not known, like
+//! typos in the name of an option
+
+use figment::{
+    value::{Dict, Value},
+    Figment, Provider, Source,
+};
+use schemars::schema::{RootSchema, Schema, SchemaObject, SingleOrVec};
+
+use crate::json_schema;
+
+/// A key set in the configuration which is not known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    /// The path of the key, like `http.listners`
+    pub path: String,
+
+    /// The known key at the same level closest to this one, if any is close
+    /// enough
+    pub suggestion: Option<String>,
+
+    /// Where the key was set, like the path of a configuration file
+    pub source: Option<String>,
+}
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown configuration key `{}`", self.path)?;
+        if let Some(source) = &self.source {
+            write!(f, " in {source}")?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{suggestion}`?")?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the keys set in the configuration which are not part of its schema
+///
+/// Keys set through environment variables are ignored, as there is no way to
+/// tell the ones meant for the configuration from the others.
+///
+/// # Errors
+///
+/// Returns an error if the configuration could not be loaded
+pub fn unknown_keys(figment: &Figment) -> Result<Vec<UnknownKey>, figment::Error> {
+    let root = json_schema();
+    let root_schema = Schema::Object(root.schema.clone());
+    let mut checker = Checker {
+        root: &root,
+        figment,
+        unknown: Vec::new(),
+    };
+
+    for dict in figment.data()?.values() {
+        let schemas = checker.resolve([&root_schema]);
+        checker.check_dict("", dict, &schemas);
+    }
+
+    let mut unknown = checker.unknown;
+    unknown.sort_by(|a, b| a.path.cmp(&b.path));
+    unknown.dedup();
+    Ok(unknown)
+}
+
+struct Checker<'a> {
+    root: &'a RootSchema,
+    figment: &'a Figment,
+    unknown: Vec<UnknownKey>,
+}
+
+impl<'a> Checker<'a> {
+    /// Resolve the references and the combinations of the given schemas, to
+    /// get the list of schemas a value can match
+    fn resolve(&self, schemas: impl IntoIterator<Item = &'a Schema>) -> Vec<&'a Schema> {
+        let mut stack: Vec<&Schema> = schemas.into_iter().collect();
+        let mut resolved = Vec::new();
+
+        while let Some(schema) = stack.pop() {
+            let Schema::Object(object) = schema else {
+                resolved.push(schema);
+                continue;
+            };
+
+            if let Some(definition) = object
+                .reference
+                .as_deref()
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+                .and_then(|name| self.root.definitions.get(name))
+            {
+                stack.push(definition);
+            }
+
+            if let Some(subschemas) = &object.subschemas {
+                for list in [&subschemas.all_of, &subschemas.any_of, &subschemas.one_of]
+                    .into_iter()
+                    .flatten()
+                {
+                    stack.extend(list);
+                }
+            }
+
+            resolved.push(schema);
+        }
+
+        resolved
+    }
+
+    fn check_value(&mut self, path: &str, value: &Value, schemas: &[&'a Schema]) {
+        match value {
+            Value::Dict(_, dict) => self.check_dict(path, dict, schemas),
+            Value::Array(_, items) => {
+                let item_schemas = self.resolve(schemas.iter().filter_map(|schema| {
+                    match &as_object(schema)?.array.as_ref()?.items.as_ref()? {
+                        SingleOrVec::Single(schema) => Some(&**schema),
+                        // Tuples are not used in the configuration
+                        SingleOrVec::Vec(_) => None,
+                    }
+                }));
+
+                for (index, item) in items.iter().enumerate() {
+                    self.check_value(&format!("{path}[{index}]"), item, &item_schemas);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_dict(&mut self, path: &str, dict: &Dict, schemas: &[&'a Schema]) {
+        // Any key is allowed if one of the schemas accepts anything, or if none
+        // of them describes an object, which means we can't tell
+        let objects: Vec<&SchemaObject> = schemas.iter().filter_map(|s| as_object(s)).collect();
+        if schemas.iter().any(|s| matches!(s, Schema::Bool(true)))
+            || objects.iter().all(|object| object.object.is_none())
+        {
+            return;
+        }
+
+        // Keys outside of the known properties, like the ones of maps
+        let mut additional = Vec::new();
+        let mut open = false;
+        for object in objects.iter().filter_map(|object| object.object.as_ref()) {
+            if !object.pattern_properties.is_empty() {
+                open = true;
+            }
+
+            match object.additional_properties.as_deref() {
+                None | Some(Schema::Bool(false)) => {}
+                Some(Schema::Bool(true)) => open = true,
+                Some(schema) => additional.push(schema),
+            }
+        }
+
+        for (key, value) in dict {
+            let key_path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+
+            let properties: Vec<&Schema> = objects
+                .iter()
+                .filter_map(|object| object.object.as_ref()?.properties.get(key))
+                .collect();
+
+            if !properties.is_empty() {
+                let schemas = self.resolve(properties);
+                self.check_value(&key_path, value, &schemas);
+            } else if !additional.is_empty() {
+                let schemas = self.resolve(additional.iter().copied());
+                self.check_value(&key_path, value, &schemas);
+            } else if !open {
+                self.report(key_path, key, value, &objects);
+            }
+        }
+    }
+
+    fn report(&mut self, path: String, key: &str, value: &Value, objects: &[&SchemaObject]) {
+        let metadata = self.figment.get_metadata(value.tag());
+
+        // Values without a source come from the environment variables
+        let Some(source) = metadata.and_then(|metadata| metadata.source.as_ref()) else {
+            return;
+        };
+        let source = match source {
+            Source::File(path) => Some(path.display().to_string()),
+            _ => None,
+        };
+
+        let suggestion = objects
+            .iter()
+            .filter_map(|object| object.object.as_ref())
+            .flat_map(|object| object.properties.keys())
+            .map(|known| (strsim::jaro(key, known), known))
+            .filter(|(confidence, _)| *confidence > 0.7)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, known)| known.clone());
+
+        self.unknown.push(UnknownKey {
+            path,
+            suggestion,
+            source,
+        });
+    }
+}
+
+fn as_object(schema: &Schema) -> Option<&SchemaObject> {
+    match schema {
+        Schema::Object(object) => Some(object),
+        Schema::Bool(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Env, Format, Yaml},
+        Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn detect_unknown_keys() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                  http:
+                    public_base: https://example.com/
+                    listners: []
+                    security_headers:
+                      content_security_policy_extra_sources:
+                        script-src: [https://cdn.example.com]
+                  passwords:
+                    schemes:
+                      - version: 1
+                        algoritm: argon2id
+                  databse:
+                    uri: postgresql://localhost/mas
+                ",
+            )?;
+            jail.set_env("MAS_CONFIG", "config.yaml");
+
+            let figment = Figment::new()
+                .merge(Env::prefixed("MAS_").split("_"))
+                .merge(Yaml::file("config.yaml"));
+
+            let unknown = unknown_keys(&figment)?;
+            let summary: Vec<_> = unknown
+                .iter()
+                .map(|key| (key.path.as_str(), key.suggestion.as_deref()))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("databse", Some("database")),
+                    ("http.listners", Some("listeners")),
+                    ("passwords.schemes[0].algoritm", Some("algorithm")),
+                ]
+            );
+            assert!(unknown[0]
+                .source
+                .as_deref()
+                .unwrap()
+                .ends_with("config.yaml"));
+            assert_eq!(
+                unknown[1].to_string(),
+                format!(
+                    "unknown configuration key `http.listners` in {}, did you mean `listeners`?",
+                    unknown[1].source.as_deref().unwrap()
+                )
+            );
+
+            Ok(())
+        });
+    }
+}
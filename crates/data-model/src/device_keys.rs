@@ -0,0 +1,21 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use mas_jose::jwk::PublicJsonWebKey;
+use serde::Serialize;
+use ulid::Ulid;
+
+/// A public key registered by a client when logging in, bound to the session
+/// it got.
+///
+/// Once a session has a device key, refreshing its tokens requires a proof
+/// signed by the matching private key, which never leaves the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceKey {
+    pub id: Ulid,
+    pub public_key: PublicJsonWebKey,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,117 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use ulid::Ulid;
+
+/// The delivery state of a [`QueuedEmail`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuedEmailState {
+    /// The email is waiting to be sent, either for the first time or after a
+    /// failed attempt
+    Pending,
+
+    /// The email was handed over to the transport
+    Sent { sent_at: DateTime<Utc> },
+
+    /// Too many attempts failed, and the email was moved to the dead-letter
+    /// list
+    Failed { failed_at: DateTime<Utc> },
+}
+
+impl QueuedEmailState {
+    /// Returns `true` if the email is waiting to be sent
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Pending)
+    }
+
+    /// Returns `true` if the email was sent
+    #[must_use]
+    pub fn is_sent(&self) -> bool {
+        matches!(self, Self::Sent { .. })
+    }
+
+    /// Returns `true` if the email is in the dead-letter list
+    #[must_use]
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed { .. })
+    }
+}
+
+/// An email waiting in the outgoing queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedEmail {
+    pub id: Ulid,
+
+    /// The address the email is sent to
+    pub recipient: String,
+
+    /// The fully formatted message, headers included
+    pub message: Vec<u8>,
+
+    pub created_at: DateTime<Utc>,
+
+    /// The number of failed attempts so far
+    pub attempts: u32,
+
+    /// When the next attempt should happen
+    pub next_attempt_at: DateTime<Utc>,
+
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+
+    pub state: QueuedEmailState,
+}
+
+/// Why an email address was marked as undeliverable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndeliverableEmailReason {
+    /// The mail provider reported a permanent bounce
+    Bounce,
+
+    /// The recipient marked an email as spam
+    Complaint,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid undeliverable email reason {0:?}")]
+pub struct InvalidUndeliverableEmailReasonError(String);
+
+impl std::str::FromStr for UndeliverableEmailReason {
+    type Err = InvalidUndeliverableEmailReasonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounce" => Ok(Self::Bounce),
+            "complaint" => Ok(Self::Complaint),
+            s => Err(InvalidUndeliverableEmailReasonError(s.to_owned())),
+        }
+    }
+}
+
+impl UndeliverableEmailReason {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Bounce => "bounce",
+            Self::Complaint => "complaint",
+        }
+    }
+}
+
+/// An email address to which emails should not be sent anymore, as reported
+/// by the mail provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndeliverableEmailAddress {
+    pub email: String,
+    pub reason: UndeliverableEmailReason,
+    pub reported_at: DateTime<Utc>,
+
+    /// Additional information given by the mail provider, like a diagnostic
+    /// code
+    pub details: Option<String>,
+}
@@ -0,0 +1,410 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use ulid::Ulid;
+
+use crate::User;
+
+/// A feature flag, as defined in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureFlag {
+    /// The name of the flag
+    pub name: String,
+
+    /// Whether the flag is enabled at all
+    pub enabled: bool,
+
+    /// The percentage of users for which the flag is enabled. `None` means
+    /// all users.
+    pub rollout_percentage: Option<u8>,
+
+    /// Usernames of the users for which the flag is always enabled, as long as
+    /// it is enabled at all
+    pub users: Vec<String>,
+}
+
+/// A variant of an [`Experiment`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExperimentVariant {
+    /// The name of the variant
+    pub name: String,
+
+    /// The relative weight of the variant, compared to the other variants of
+    /// the experiment
+    pub weight: u32,
+}
+
+/// An A/B experiment, assigning visitors to one of its variants
+///
+/// Like feature flags, experiments can be disabled with an override.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Experiment {
+    /// The name of the experiment
+    pub name: String,
+
+    /// Whether the experiment is running
+    pub enabled: bool,
+
+    /// The variants visitors are assigned to
+    pub variants: Vec<ExperimentVariant>,
+}
+
+/// An override of a feature flag, stored in the database so that it can be
+/// changed without restarting the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureFlagOverride {
+    /// The name of the overridden flag
+    pub name: String,
+
+    /// Whether the flag is enabled at all
+    pub enabled: bool,
+
+    /// The percentage of users for which the flag is enabled. `None` means
+    /// all users.
+    pub rollout_percentage: Option<u8>,
+
+    /// When the override was last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    flags: BTreeMap<String, FeatureFlag>,
+    experiments: BTreeMap<String, Experiment>,
+    overrides: RwLock<BTreeMap<String, FeatureFlagOverride>>,
+}
+
+/// The feature flags of the instance, with their overrides
+///
+/// This is cheap to clone, and clones share the overrides, so that refreshing
+/// them is visible everywhere.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    inner: Arc<Inner>,
+}
+
+/// FNV-1a hash, which is stable across versions and platforms, unlike the
+/// hashers of the standard library
+///
+/// The result is mixed with the finalizer of `MurmurHash3`, as the low bits of
+/// FNV-1a are poorly distributed, and the hash is used modulo small numbers.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in *part {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        // Separate the parts, so that ("ab", "c") and ("a", "bc") differ
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash
+}
+
+impl FeatureFlags {
+    /// Create the feature flags and experiments from their definitions
+    #[must_use]
+    pub fn new(
+        flags: impl IntoIterator<Item = FeatureFlag>,
+        experiments: impl IntoIterator<Item = Experiment>,
+    ) -> Self {
+        let flags = flags
+            .into_iter()
+            .map(|flag| (flag.name.clone(), flag))
+            .collect();
+        let experiments = experiments
+            .into_iter()
+            .map(|experiment| (experiment.name.clone(), experiment))
+            .collect();
+
+        Self {
+            inner: Arc::new(Inner {
+                flags,
+                experiments,
+                overrides: RwLock::default(),
+            }),
+        }
+    }
+
+    /// Replace the overrides of the flags
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock on the overrides is poisoned
+    pub fn set_overrides(&self, overrides: impl IntoIterator<Item = FeatureFlagOverride>) {
+        let overrides = overrides
+            .into_iter()
+            .map(|flag| (flag.name.clone(), flag))
+            .collect();
+
+        *self.inner.overrides.write().unwrap() = overrides;
+    }
+
+    /// The names of the flags defined in the configuration or overridden
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock on the overrides is poisoned
+    #[must_use]
+    pub fn names(&self) -> Vec<String> {
+        let overrides = self.inner.overrides.read().unwrap();
+        let mut names: Vec<String> = self
+            .inner
+            .flags
+            .keys()
+            .chain(overrides.keys())
+            .cloned()
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Whether the flag is enabled for the given user, or for anonymous
+    /// visitors if no user is given
+    ///
+    /// Unknown flags are disabled.
+    #[must_use]
+    pub fn is_enabled(&self, name: &str, user: Option<&User>) -> bool {
+        self.is_enabled_for(name, user.map(|user| (user.id, user.username.as_str())))
+    }
+
+    /// Whether the flag is enabled for the user with the given ID and
+    /// username, or for anonymous visitors if no user is given
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock on the overrides is poisoned
+    #[must_use]
+    pub fn is_enabled_for(&self, name: &str, user: Option<(Ulid, &str)>) -> bool {
+        let flag = self.inner.flags.get(name);
+        let (enabled, rollout_percentage) =
+            if let Some(flag_override) = self.inner.overrides.read().unwrap().get(name) {
+                (flag_override.enabled, flag_override.rollout_percentage)
+            } else if let Some(flag) = flag {
+                (flag.enabled, flag.rollout_percentage)
+            } else {
+                return false;
+            };
+
+        if !enabled {
+            return false;
+        }
+
+        if let (Some(flag), Some((_, username))) = (flag, user) {
+            if flag.users.iter().any(|u| u == username) {
+                return true;
+            }
+        }
+
+        let Some(percentage) = rollout_percentage else {
+            return true;
+        };
+
+        if percentage >= 100 {
+            return true;
+        }
+
+        // Anonymous visitors can't be bucketed consistently, so they only get
+        // fully rolled out flags
+        let Some((user_id, _)) = user else {
+            return false;
+        };
+
+        let bucket = fnv1a(&[name.as_bytes(), &user_id.to_bytes()]) % 100;
+        bucket < u64::from(percentage)
+    }
+
+    /// The variant of the experiment the visitor is assigned to, if the
+    /// experiment is running
+    ///
+    /// Visitors are always assigned to the same variant, as long as the
+    /// variants of the experiment don't change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock on the overrides is poisoned
+    #[must_use]
+    pub fn experiment_variant(&self, name: &str, visitor_id: Ulid) -> Option<&str> {
+        let experiment = self.inner.experiments.get(name)?;
+        let enabled = self
+            .inner
+            .overrides
+            .read()
+            .unwrap()
+            .get(name)
+            .map_or(experiment.enabled, |flag_override| flag_override.enabled);
+        if !enabled {
+            return None;
+        }
+
+        let total: u64 = experiment
+            .variants
+            .iter()
+            .map(|variant| u64::from(variant.weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut bucket = fnv1a(&[name.as_bytes(), &visitor_id.to_bytes()]) % total;
+        for variant in &experiment.variants {
+            let weight = u64::from(variant.weight);
+            if bucket < weight {
+                return Some(&variant.name);
+            }
+            bucket -= weight;
+        }
+
+        None
+    }
+
+    /// The variants of all the running experiments the visitor is assigned
+    /// to, keyed by experiment
+    #[must_use]
+    pub fn experiment_variants(&self, visitor_id: Ulid) -> BTreeMap<String, String> {
+        self.inner
+            .experiments
+            .keys()
+            .filter_map(|name| {
+                let variant = self.experiment_variant(name, visitor_id)?;
+                Some((name.clone(), variant.to_owned()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag(name: &str, rollout_percentage: Option<u8>) -> FeatureFlag {
+        FeatureFlag {
+            name: name.to_owned(),
+            enabled: true,
+            rollout_percentage,
+            users: vec!["alice".to_owned()],
+        }
+    }
+
+    #[test]
+    fn evaluate_flags() {
+        let flags = FeatureFlags::new(
+            [
+                flag("on", None),
+                flag("half", Some(50)),
+                flag("none", Some(0)),
+                FeatureFlag {
+                    enabled: false,
+                    ..flag("off", None)
+                },
+            ],
+            [],
+        );
+
+        assert!(flags.is_enabled_for("on", None));
+        assert!(!flags.is_enabled_for("half", None));
+        assert!(!flags.is_enabled_for("off", None));
+        assert!(!flags.is_enabled_for("unknown", None));
+
+        // Listed users get the flag whatever the percentage, unless disabled
+        assert!(flags.is_enabled_for("none", Some((Ulid::nil(), "alice"))));
+        assert!(!flags.is_enabled_for("off", Some((Ulid::nil(), "alice"))));
+        assert!(!flags.is_enabled_for("none", Some((Ulid::nil(), "bob"))));
+
+        // Roughly half of the users get the flag, always the same ones
+        let enabled = (0..1000_u128)
+            .filter(|i| flags.is_enabled_for("half", Some((Ulid((*i << 64) | *i), "bob"))))
+            .count();
+        assert!((400..600).contains(&enabled), "{enabled}");
+        let user = Some((Ulid::from_parts(42, 42), "bob"));
+        assert_eq!(
+            flags.is_enabled_for("half", user),
+            flags.is_enabled_for("half", user)
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let flags = FeatureFlags::new([flag("feature", Some(0))], []);
+        let other = flags.clone();
+        assert!(!other.is_enabled_for("feature", Some((Ulid::nil(), "bob"))));
+
+        flags.set_overrides([
+            FeatureFlagOverride {
+                name: "feature".to_owned(),
+                enabled: true,
+                rollout_percentage: None,
+                updated_at: DateTime::UNIX_EPOCH,
+            },
+            FeatureFlagOverride {
+                name: "new".to_owned(),
+                enabled: true,
+                rollout_percentage: None,
+                updated_at: DateTime::UNIX_EPOCH,
+            },
+        ]);
+        assert!(other.is_enabled_for("feature", Some((Ulid::nil(), "bob"))));
+        assert!(other.is_enabled_for("new", None));
+        assert_eq!(other.names(), ["feature", "new"]);
+
+        flags.set_overrides([]);
+        assert!(!other.is_enabled_for("new", None));
+    }
+
+    #[test]
+    fn experiments() {
+        let variant = |name: &str, weight| ExperimentVariant {
+            name: name.to_owned(),
+            weight,
+        };
+        let flags = FeatureFlags::new(
+            [],
+            [Experiment {
+                name: "login_layout".to_owned(),
+                enabled: true,
+                variants: vec![variant("control", 3), variant("compact", 1)],
+            }],
+        );
+
+        let mut counts = BTreeMap::<&str, usize>::new();
+        for i in 0..1000_u128 {
+            let visitor = Ulid((i << 64) | i);
+            let variant = flags.experiment_variant("login_layout", visitor).unwrap();
+            // Visitors always get the same variant
+            assert_eq!(
+                flags.experiment_variant("login_layout", visitor),
+                Some(variant)
+            );
+            *counts.entry(variant).or_default() += 1;
+        }
+        assert!((650..850).contains(&counts["control"]), "{counts:?}");
+        assert!((150..350).contains(&counts["compact"]), "{counts:?}");
+
+        assert_eq!(flags.experiment_variant("unknown", Ulid::nil()), None);
+        assert_eq!(flags.experiment_variants(Ulid::nil()).len(), 1);
+
+        // Experiments can be stopped with an override
+        flags.set_overrides([FeatureFlagOverride {
+            name: "login_layout".to_owned(),
+            enabled: false,
+            rollout_percentage: None,
+            updated_at: DateTime::UNIX_EPOCH,
+        }]);
+        assert_eq!(flags.experiment_variant("login_layout", Ulid::nil()), None);
+        assert!(flags.experiment_variants(Ulid::nil()).is_empty());
+    }
+}
@@ -9,6 +9,9 @@
 use thiserror::Error;
 
 pub(crate) mod compat;
+pub(crate) mod device_keys;
+pub(crate) mod emails;
+mod feature_flags;
 pub mod oauth2;
 mod site_config;
 pub(crate) mod tokens;
@@ -26,13 +29,26 @@ pub use ulid::Ulid;
 pub use self::{
     compat::{
         CompatAccessToken, CompatRefreshToken, CompatRefreshTokenState, CompatSession,
-        CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
+        CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device, InvalidDeviceID,
+    },
+    device_keys::DeviceKey,
+    emails::{
+        InvalidUndeliverableEmailReasonError, QueuedEmail, QueuedEmailState,
+        UndeliverableEmailAddress, UndeliverableEmailReason,
+    },
+    feature_flags::{
+        Experiment, ExperimentVariant, FeatureFlag, FeatureFlagOverride, FeatureFlags,
     },
     oauth2::{
-        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, DeviceCodeGrant,
-        DeviceCodeGrantState, InvalidRedirectUriError, JwksOrJwksUri, Pkce, Session, SessionState,
+        AuthorizationCode, AuthorizationCodeBinding, AuthorizationGrant, AuthorizationGrantStage,
+        Client, ClientFingerprint, DeviceCodeGrant, DeviceCodeGrantState, FingerprintMismatch,
+        InvalidRedirectUriError, JwksOrJwksUri, Pkce, RedirectUriValidation, Session, SessionState,
+    },
+    site_config::{
+        BotDetectionConfig, CaptchaConfig, CaptchaService, CertificateLoginConfig,
+        CertificateLookup, CompatPasswordLogin, CustomScope, DeviceIdAllocation, ScopeIcon,
+        SessionEviction, SessionLimits, SiteConfig,
     },
-    site_config::{CaptchaConfig, CaptchaService, SiteConfig},
     tokens::{
         AccessToken, AccessTokenState, RefreshToken, RefreshTokenState, TokenFormatError, TokenType,
     },
@@ -40,13 +56,16 @@ pub use self::{
         UpsreamOAuthProviderSetEmailVerification, UpstreamOAuthAuthorizationSession,
         UpstreamOAuthAuthorizationSessionState, UpstreamOAuthLink, UpstreamOAuthProvider,
         UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderDiscoveryMode,
-        UpstreamOAuthProviderImportAction, UpstreamOAuthProviderImportPreference,
-        UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderResponseMode,
+        UpstreamOAuthProviderHealth, UpstreamOAuthProviderImportAction,
+        UpstreamOAuthProviderImportPreference, UpstreamOAuthProviderPkceMode,
+        UpstreamOAuthProviderResponseMode, UpstreamOAuthProviderStatus,
         UpstreamOAuthProviderSubjectPreference, UpstreamOAuthProviderTokenAuthMethod,
     },
-    user_agent::{DeviceType, UserAgent},
+    user_agent::{ClientApp, DeviceType, UserAgent, UserAgentDetails},
     users::{
-        Authentication, AuthenticationMethod, BrowserSession, Password, User, UserEmail,
-        UserEmailVerification, UserEmailVerificationState, UserRecoverySession, UserRecoveryTicket,
+        authentication_method_references, Authentication, AuthenticationMethod, BrowserSession,
+        InvalidLoginMethodError, LoginAttempt, LoginMethod, Password, RegistrationMetadata,
+        SessionElevation, User, UserEmail, UserEmailVerification, UserEmailVerificationState,
+        UserRecoverySession, UserRecoveryTicket,
     },
 };
@@ -4,9 +4,13 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::num::NonZeroU32;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    num::NonZeroU32,
+};
 
 use chrono::{DateTime, Duration, Utc};
+use crc::{Crc, CRC_32_ISO_HDLC};
 use mas_iana::oauth::PkceCodeChallengeMethod;
 use oauth2_types::{
     pkce::{CodeChallengeError, CodeChallengeMethodExt},
@@ -19,12 +23,123 @@ use rand::{
 };
 use ruma_common::{OwnedUserId, UserId};
 use serde::Serialize;
+use thiserror::Error;
 use ulid::Ulid;
 use url::Url;
 
 use super::session::Session;
 use crate::InvalidTransitionError;
 
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// A coarse fingerprint of the context in which an authorization code was
+/// requested or redeemed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientFingerprint {
+    /// The network the request came from: the /24 for IPv4 addresses, the /48
+    /// for IPv6 addresses
+    pub ip_network: Option<String>,
+
+    /// A hash of the raw user agent
+    pub user_agent_hash: Option<String>,
+}
+
+impl ClientFingerprint {
+    /// Compute the fingerprint of a request
+    #[must_use]
+    pub fn new(ip: Option<IpAddr>, user_agent: Option<&str>) -> Self {
+        let ip_network = ip.map(|ip| match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                format!("{}/24", Ipv4Addr::new(a, b, c, 0))
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, c, ..] = ip.segments();
+                format!("{}/48", Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+            }
+        });
+
+        let user_agent_hash =
+            user_agent.map(|user_agent| format!("{:08x}", CRC.checksum(user_agent.as_bytes())));
+
+        Self {
+            ip_network,
+            user_agent_hash,
+        }
+    }
+}
+
+/// How strictly an authorization code is bound to the context it was requested
+/// in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorizationCodeBinding {
+    /// Codes can be redeemed from any context
+    #[default]
+    Off,
+
+    /// Codes are rejected if both the network and the user agent changed
+    Lenient,
+
+    /// Codes are rejected if either the network or the user agent changed
+    Strict,
+}
+
+/// The context in which an authorization code was redeemed doesn't match the
+/// one it was requested in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("authorization code redeemed from a different context (network changed: {ip_network}, user agent changed: {user_agent})")]
+pub struct FingerprintMismatch {
+    /// Whether the network changed
+    pub ip_network: bool,
+
+    /// Whether the user agent changed
+    pub user_agent: bool,
+}
+
+impl AuthorizationCodeBinding {
+    /// Check that a code requested with the `requested` fingerprint can be
+    /// redeemed with the `redeemed` one
+    ///
+    /// Parts which were unknown when the code was requested are considered as
+    /// matching. Parts which were known then but are missing now are
+    /// considered as changed, so that leaving out a header doesn't get around
+    /// the binding.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fingerprints differ too much for this binding.
+    pub fn check(
+        self,
+        requested: &ClientFingerprint,
+        redeemed: &ClientFingerprint,
+    ) -> Result<(), FingerprintMismatch> {
+        fn changed(requested: Option<&String>, redeemed: Option<&String>) -> bool {
+            requested.is_some_and(|requested| redeemed != Some(requested))
+        }
+
+        let mismatch = FingerprintMismatch {
+            ip_network: changed(requested.ip_network.as_ref(), redeemed.ip_network.as_ref()),
+            user_agent: changed(
+                requested.user_agent_hash.as_ref(),
+                redeemed.user_agent_hash.as_ref(),
+            ),
+        };
+
+        let rejected = match self {
+            Self::Off => false,
+            Self::Lenient => mismatch.ip_network && mismatch.user_agent,
+            Self::Strict => mismatch.ip_network || mismatch.user_agent,
+        };
+
+        if rejected {
+            Err(mismatch)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Pkce {
     pub challenge_method: PkceCodeChallengeMethod,
@@ -290,6 +405,116 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn fingerprint_network() {
+        let first = ClientFingerprint::new(Some("192.0.2.1".parse().unwrap()), None);
+        let same_network = ClientFingerprint::new(Some("192.0.2.254".parse().unwrap()), None);
+        let other_network = ClientFingerprint::new(Some("198.51.100.1".parse().unwrap()), None);
+        let mapped = ClientFingerprint::new(Some("::ffff:192.0.2.42".parse().unwrap()), None);
+        assert_eq!(first.ip_network.as_deref(), Some("192.0.2.0/24"));
+        assert_eq!(first, same_network);
+        assert_ne!(first, other_network);
+        assert_eq!(first, mapped);
+
+        let ipv6 = ClientFingerprint::new(Some("2001:db8:1:2::1".parse().unwrap()), None);
+        assert_eq!(ipv6.ip_network.as_deref(), Some("2001:db8:1::/48"));
+    }
+
+    #[test]
+    fn code_binding() {
+        let requested = ClientFingerprint::new(Some("192.0.2.1".parse().unwrap()), Some("A"));
+        let other_ip = ClientFingerprint::new(Some("198.51.100.1".parse().unwrap()), Some("A"));
+        let other_both = ClientFingerprint::new(Some("198.51.100.1".parse().unwrap()), Some("B"));
+        let unknown = ClientFingerprint::new(None, None);
+
+        for binding in [
+            AuthorizationCodeBinding::Off,
+            AuthorizationCodeBinding::Lenient,
+            AuthorizationCodeBinding::Strict,
+        ] {
+            assert!(binding.check(&requested, &requested).is_ok());
+            // Parts unknown when the code was requested can't be compared
+            assert!(binding.check(&unknown, &other_both).is_ok());
+        }
+
+        assert!(AuthorizationCodeBinding::Off
+            .check(&requested, &other_both)
+            .is_ok());
+        assert!(AuthorizationCodeBinding::Off
+            .check(&requested, &unknown)
+            .is_ok());
+        assert!(AuthorizationCodeBinding::Lenient
+            .check(&requested, &other_ip)
+            .is_ok());
+        assert!(AuthorizationCodeBinding::Lenient
+            .check(&requested, &other_both)
+            .is_err());
+        assert!(AuthorizationCodeBinding::Strict
+            .check(&requested, &other_ip)
+            .is_err());
+    }
+
+    #[test]
+    fn code_binding_missing_user_agent() {
+        let requested = ClientFingerprint::new(Some("192.0.2.1".parse().unwrap()), Some("A"));
+        let same_ip = ClientFingerprint::new(Some("192.0.2.1".parse().unwrap()), None);
+        let other_ip = ClientFingerprint::new(Some("198.51.100.1".parse().unwrap()), None);
+
+        // A missing user agent counts as a changed one
+        assert_eq!(
+            AuthorizationCodeBinding::Strict.check(&requested, &same_ip),
+            Err(FingerprintMismatch {
+                ip_network: false,
+                user_agent: true,
+            })
+        );
+        assert!(AuthorizationCodeBinding::Lenient
+            .check(&requested, &same_ip)
+            .is_ok());
+
+        // So leaving it out doesn't let a code be redeemed from another network
+        assert_eq!(
+            AuthorizationCodeBinding::Lenient.check(&requested, &other_ip),
+            Err(FingerprintMismatch {
+                ip_network: true,
+                user_agent: true,
+            })
+        );
+        assert!(AuthorizationCodeBinding::Strict
+            .check(&requested, &other_ip)
+            .is_err());
+    }
+
+    #[test]
+    fn code_binding_missing_ip() {
+        let requested = ClientFingerprint::new(Some("192.0.2.1".parse().unwrap()), Some("A"));
+        let same_user_agent = ClientFingerprint::new(None, Some("A"));
+        let other_user_agent = ClientFingerprint::new(None, Some("B"));
+
+        // A missing address counts as a changed network
+        assert_eq!(
+            AuthorizationCodeBinding::Strict.check(&requested, &same_user_agent),
+            Err(FingerprintMismatch {
+                ip_network: true,
+                user_agent: false,
+            })
+        );
+        assert!(AuthorizationCodeBinding::Lenient
+            .check(&requested, &same_user_agent)
+            .is_ok());
+
+        assert_eq!(
+            AuthorizationCodeBinding::Lenient.check(&requested, &other_user_agent),
+            Err(FingerprintMismatch {
+                ip_network: true,
+                user_agent: true,
+            })
+        );
+        assert!(AuthorizationCodeBinding::Strict
+            .check(&requested, &other_user_agent)
+            .is_err());
+    }
+
     #[test]
     fn no_login_hint() {
         #[allow(clippy::disallowed_methods)]
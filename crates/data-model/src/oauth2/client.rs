@@ -85,6 +85,33 @@ pub struct Client {
     pub initiate_login_uri: Option<Url>,
 }
 
+/// How the redirect URI of an authorization request is validated against the
+/// ones registered by the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectUriValidation {
+    /// The redirect URI must match one of the registered ones, except for the
+    /// port of loopback redirect URIs registered without one
+    #[default]
+    Default,
+
+    /// The redirect URI must exactly match one of the registered ones, and
+    /// wildcard redirect URIs are ignored
+    Exact,
+
+    /// The redirect URI is validated following the rules for native apps of
+    /// [RFC 8252]:
+    ///
+    ///  - loopback redirect URIs use the `http` scheme and match the registered
+    ///    ones on any port,
+    ///  - private-use schemes must be the reverse domain of the `client_uri`
+    ///    host, and match the registered ones,
+    ///  - claimed `https` redirect URIs must match the registered ones.
+    ///
+    /// [RFC 8252]: https://www.rfc-editor.org/rfc/rfc8252#section-7
+    Native,
+}
+
 #[derive(Debug, Error)]
 pub enum InvalidRedirectUriError {
     #[error("redirect_uri is not allowed for this client")]
@@ -95,6 +122,9 @@ pub enum InvalidRedirectUriError {
 
     #[error("client has no redirect_uri registered")]
     NoneRegistered,
+
+    #[error("private-use scheme of the redirect_uri doesn't match the client_uri")]
+    PrivateUseSchemeMismatch,
 }
 
 impl Client {
@@ -104,19 +134,45 @@ impl Client {
     ///
     /// Returns an error if:
     ///
-    ///  - no URL was given but multiple redirect URIs are registered,
-    ///  - no URL was registered, or
-    ///  - the given URL is not registered
+    ///  - no URL was given but multiple redirect URIs, or a wildcard one, are
+    ///    registered,
+    ///  - no URL was registered,
+    ///  - the given URL is not registered, or
+    ///  - the given URL uses a private-use scheme which doesn't match the
+    ///    `client_uri`, when validating them as a native app
     pub fn resolve_redirect_uri<'a>(
         &'a self,
         redirect_uri: &'a Option<Url>,
+        validation: RedirectUriValidation,
     ) -> Result<&'a Url, InvalidRedirectUriError> {
-        match (&self.redirect_uris[..], redirect_uri) {
-            ([], _) => Err(InvalidRedirectUriError::NoneRegistered),
-            ([one], None) => Ok(one),
-            (_, None) => Err(InvalidRedirectUriError::MultipleRegistered),
-            (uris, Some(uri)) if uri_matches_one_of(uri, uris) => Ok(uri),
-            _ => Err(InvalidRedirectUriError::NotAllowed),
+        let uri = match (&self.redirect_uris[..], redirect_uri) {
+            ([], _) => return Err(InvalidRedirectUriError::NoneRegistered),
+            ([one], None) if !is_wildcard(one) => one,
+            (_, None) => return Err(InvalidRedirectUriError::MultipleRegistered),
+            (_, Some(uri)) => uri,
+        };
+
+        let matches = match validation {
+            RedirectUriValidation::Default => uri_matches_one_of(uri, &self.redirect_uris),
+            RedirectUriValidation::Exact => self.redirect_uris.contains(uri),
+            RedirectUriValidation::Native => {
+                if is_private_use_scheme(uri) {
+                    let client_host = self.client_uri.as_ref().and_then(Url::host_str);
+                    if let Some(host) = client_host {
+                        if !reverse_domain_matches(host, uri.scheme()) {
+                            return Err(InvalidRedirectUriError::PrivateUseSchemeMismatch);
+                        }
+                    }
+                }
+
+                native_uri_matches_one_of(uri, &self.redirect_uris)
+            }
+        };
+
+        if matches {
+            Ok(uri)
+        } else {
+            Err(InvalidRedirectUriError::NotAllowed)
         }
     }
 
@@ -237,7 +293,85 @@ fn uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
         }
     }
 
-    registered_uris.contains(uri)
+    registered_uris.contains(uri) || wildcard_matches_one_of(uri, registered_uris)
+}
+
+/// Whether the given URI is a wildcard redirect URI, like
+/// `https://*.staging.example.com/callback`.
+fn is_wildcard(uri: &Url) -> bool {
+    uri.host_str().is_some_and(|host| host.starts_with("*."))
+}
+
+/// Whether the given URI matches one of the registered wildcard URIs.
+///
+/// The wildcard only stands for a single label on the left of the host, and
+/// only on `https` URIs. Everything else must match exactly.
+fn wildcard_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
+    let Some((label, domain)) = uri.host_str().and_then(|host| host.split_once('.')) else {
+        return false;
+    };
+
+    if uri.scheme() != "https" || label.is_empty() || label == "*" {
+        return false;
+    }
+
+    registered_uris.iter().filter(|r| is_wildcard(r)).any(|r| {
+        r.scheme() == "https"
+            && r.host_str().and_then(|host| host.strip_prefix("*.")) == Some(domain)
+            && r.port() == uri.port()
+            && r.username() == uri.username()
+            && r.password() == uri.password()
+            && r.path() == uri.path()
+            && r.query() == uri.query()
+            && r.fragment() == uri.fragment()
+    })
+}
+
+/// Whether the given URI is a loopback redirect URI, as defined by RFC 8252.
+fn is_loopback(uri: &Url) -> bool {
+    uri.scheme() == "http" && LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default())
+}
+
+/// Whether the given URI uses a private-use scheme, as defined by RFC 8252.
+///
+/// Those are the schemes which contain a period, like `com.example.app:/`.
+fn is_private_use_scheme(uri: &Url) -> bool {
+    uri.scheme().contains('.')
+}
+
+/// Whether the given reverse domain scheme is the given host, or one of its
+/// subdomains, e.g. `com.example.app` for `example.com`.
+fn reverse_domain_matches(host: &str, scheme: &str) -> bool {
+    let mut scheme_parts = scheme.split('.');
+    host.split('.')
+        .rev()
+        .all(|part| scheme_parts.next() == Some(part))
+}
+
+/// Whether the given URI matches one of the registered URIs, following the
+/// rules for native apps of RFC 8252.
+///
+/// Loopback URIs match on any port, on both sides. Other URIs must use either a
+/// private-use scheme or `https`, and match exactly, or match a wildcard
+/// `https` URI.
+fn native_uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
+    if is_loopback(uri) {
+        let mut uri = uri.clone();
+        if uri.set_port(None).is_err() {
+            return false;
+        }
+
+        return registered_uris.iter().filter(|r| is_loopback(r)).any(|r| {
+            let mut registered = r.clone();
+            registered.set_port(None).is_ok() && registered == uri
+        });
+    }
+
+    if uri.scheme() != "https" && !is_private_use_scheme(uri) {
+        return false;
+    }
+
+    registered_uris.contains(uri) || wildcard_matches_one_of(uri, registered_uris)
 }
 
 #[cfg(test)]
@@ -277,4 +411,100 @@ mod tests {
             registered_uris
         ));
     }
+
+    #[test]
+    fn test_native_uri_matches_one_of() {
+        let registered_uris = &[
+            Url::parse("http://127.0.0.1:1234/callback").unwrap(),
+            Url::parse("http://[::1]/callback").unwrap(),
+            Url::parse("com.example.app:/callback").unwrap(),
+            Url::parse("https://app.example.com/callback").unwrap(),
+            Url::parse("http://example.com/callback").unwrap(),
+        ];
+
+        // Loopback redirects match on any port, even when registered with one
+        assert!(native_uri_matches_one_of(
+            &Url::parse("http://127.0.0.1:5678/callback").unwrap(),
+            registered_uris
+        ));
+        assert!(native_uri_matches_one_of(
+            &Url::parse("http://[::1]:5678/callback").unwrap(),
+            registered_uris
+        ));
+        assert!(!native_uri_matches_one_of(
+            &Url::parse("http://127.0.0.1:5678/other").unwrap(),
+            registered_uris
+        ));
+        assert!(!native_uri_matches_one_of(
+            &Url::parse("https://127.0.0.1:5678/callback").unwrap(),
+            registered_uris
+        ));
+
+        // Private-use schemes and claimed https URIs match exactly
+        assert!(native_uri_matches_one_of(
+            &Url::parse("com.example.app:/callback").unwrap(),
+            registered_uris
+        ));
+        assert!(!native_uri_matches_one_of(
+            &Url::parse("com.example.app:/other").unwrap(),
+            registered_uris
+        ));
+        assert!(native_uri_matches_one_of(
+            &Url::parse("https://app.example.com/callback").unwrap(),
+            registered_uris
+        ));
+
+        // Plain http is not allowed for anything else than loopback
+        assert!(!native_uri_matches_one_of(
+            &Url::parse("http://example.com/callback").unwrap(),
+            registered_uris
+        ));
+    }
+
+    #[test]
+    fn test_reverse_domain_matches() {
+        assert!(reverse_domain_matches("example.com", "com.example"));
+        assert!(reverse_domain_matches("example.com", "com.example.app"));
+        assert!(!reverse_domain_matches("example.com", "org.example"));
+        assert!(!reverse_domain_matches("app.example.com", "com.example"));
+    }
+
+    #[test]
+    fn test_wildcard_matches_one_of() {
+        let registered_uris = &[
+            Url::parse("https://*.staging.example.com/callback").unwrap(),
+            Url::parse("http://*.dev.example.com/callback").unwrap(),
+        ];
+
+        assert!(wildcard_matches_one_of(
+            &Url::parse("https://pr-123.staging.example.com/callback").unwrap(),
+            registered_uris
+        ));
+
+        // The wildcard only covers a single label
+        assert!(!wildcard_matches_one_of(
+            &Url::parse("https://a.b.staging.example.com/callback").unwrap(),
+            registered_uris
+        ));
+        assert!(!wildcard_matches_one_of(
+            &Url::parse("https://staging.example.com/callback").unwrap(),
+            registered_uris
+        ));
+
+        // The rest of the URI must match exactly
+        assert!(!wildcard_matches_one_of(
+            &Url::parse("https://pr-123.staging.example.com/other").unwrap(),
+            registered_uris
+        ));
+        assert!(!wildcard_matches_one_of(
+            &Url::parse("https://pr-123.staging.example.com:8443/callback").unwrap(),
+            registered_uris
+        ));
+
+        // Only https wildcards are honored
+        assert!(!wildcard_matches_one_of(
+            &Url::parse("http://pr-123.dev.example.com/callback").unwrap(),
+            registered_uris
+        ));
+    }
 }
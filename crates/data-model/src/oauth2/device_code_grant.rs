@@ -8,6 +8,7 @@ use std::net::IpAddr;
 
 use chrono::{DateTime, Utc};
 use oauth2_types::scope::Scope;
+use rand::{seq::SliceRandom, RngCore};
 use serde::Serialize;
 use ulid::Ulid;
 
@@ -204,6 +205,63 @@ impl std::ops::Deref for DeviceCodeGrant {
 }
 
 impl DeviceCodeGrant {
+    /// Generate a new user code of the given length, using the given set of
+    /// characters
+    ///
+    /// # Panics
+    ///
+    /// Panics if the set of characters is empty.
+    #[must_use]
+    pub fn generate_user_code(rng: &mut impl RngCore, length: usize, charset: &[char]) -> String {
+        (0..length)
+            .map(|_| *charset.choose(rng).expect("charset must not be empty"))
+            .collect()
+    }
+
+    /// Normalize a user code entered by a user, so that it can be looked up
+    ///
+    /// Users may type the code in lowercase, and with the separators it is
+    /// displayed with, so those are ignored.
+    #[must_use]
+    pub fn normalize_user_code(code: &str) -> String {
+        code.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    }
+
+    /// The user code formatted for display
+    ///
+    /// See [`Self::format_user_code`].
+    #[must_use]
+    pub fn formatted_user_code(&self) -> String {
+        Self::format_user_code(&self.user_code)
+    }
+
+    /// Format a user code for display, splitting it in groups of at most four
+    /// characters separated by dashes, e.g. `ABCD-EFGH`
+    #[must_use]
+    pub fn format_user_code(code: &str) -> String {
+        let chars: Vec<char> = code.chars().collect();
+        let groups = chars.len().div_ceil(4).max(1);
+        let base = chars.len() / groups;
+        let extra = chars.len() % groups;
+
+        let mut formatted = String::with_capacity(chars.len() + groups);
+        let mut rest = chars.as_slice();
+        for group in 0..groups {
+            let size = if group < extra { base + 1 } else { base };
+            let (head, tail) = rest.split_at(size);
+            if group > 0 {
+                formatted.push('-');
+            }
+            formatted.extend(head);
+            rest = tail;
+        }
+
+        formatted
+    }
+
     /// Mark this device code grant as fulfilled, returning the updated grant.
     ///
     /// # Errors
@@ -260,3 +318,55 @@ impl DeviceCodeGrant {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn grant(user_code: &str) -> DeviceCodeGrant {
+        #[allow(clippy::disallowed_methods)]
+        let now = Utc::now();
+        DeviceCodeGrant {
+            id: Ulid::nil(),
+            state: DeviceCodeGrantState::Pending,
+            client_id: Ulid::nil(),
+            scope: Scope::from_iter([]),
+            user_code: user_code.to_owned(),
+            device_code: "device_code".to_owned(),
+            created_at: now,
+            expires_at: now,
+            ip_address: None,
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn test_user_code_format() {
+        assert_eq!(grant("ABCDEF").formatted_user_code(), "ABC-DEF");
+        assert_eq!(grant("ABCDEFGH").formatted_user_code(), "ABCD-EFGH");
+        assert_eq!(grant("ABCDEFGHIJ").formatted_user_code(), "ABCD-EFG-HIJ");
+        assert_eq!(grant("ABCD").formatted_user_code(), "ABCD");
+    }
+
+    #[test]
+    fn test_user_code_normalize() {
+        assert_eq!(DeviceCodeGrant::normalize_user_code("abc-def"), "ABCDEF");
+        assert_eq!(
+            DeviceCodeGrant::normalize_user_code(" ABCD EFGH "),
+            "ABCDEFGH"
+        );
+        assert_eq!(DeviceCodeGrant::normalize_user_code("ABCDEF"), "ABCDEF");
+    }
+
+    #[test]
+    fn test_user_code_generate() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let charset: Vec<char> = "BCDFGHJKLMNPQRSTVWXZ".chars().collect();
+        let code = DeviceCodeGrant::generate_user_code(&mut rng, 8, &charset);
+        assert_eq!(code.len(), 8);
+        assert!(code.chars().all(|c| charset.contains(&c)));
+    }
+}
@@ -11,9 +11,10 @@ mod session;
 
 pub use self::{
     authorization_grant::{
-        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, LoginHint, Pkce,
+        AuthorizationCode, AuthorizationCodeBinding, AuthorizationGrant, AuthorizationGrantStage,
+        ClientFingerprint, FingerprintMismatch, LoginHint, Pkce,
     },
-    client::{Client, InvalidRedirectUriError, JwksOrJwksUri},
+    client::{Client, InvalidRedirectUriError, JwksOrJwksUri, RedirectUriValidation},
     device_code_grant::{DeviceCodeGrant, DeviceCodeGrantState},
     session::{Session, SessionState},
 };
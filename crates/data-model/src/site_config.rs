@@ -4,9 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+
 use chrono::Duration;
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use oauth2_types::oidc::ApplicationType;
+use ulid::Ulid;
 use url::Url;
 
+use crate::{AuthorizationCodeBinding, Client, FeatureFlags, RedirectUriValidation};
+
 /// Which Captcha service is being used
 #[derive(Debug, Clone, Copy)]
 pub enum CaptchaService {
@@ -28,6 +35,148 @@ pub struct CaptchaConfig {
     pub secret_key: String,
 }
 
+/// Thresholds of the bot detection on the login and registration forms
+#[derive(Debug, Clone, Copy)]
+pub struct BotDetectionConfig {
+    /// Score from which a CAPTCHA is required
+    pub captcha_threshold: u32,
+
+    /// Score from which the submission is rejected
+    pub block_threshold: u32,
+}
+
+/// How the value rendered from a client certificate is matched to a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificateLookup {
+    /// The value is the username of the user
+    Username,
+
+    /// The value is a verified email address of the user
+    Email,
+}
+
+/// Login with X.509 client certificates
+#[derive(Debug, Clone)]
+pub struct CertificateLoginConfig {
+    /// Template rendering the value matched to a user from the certificate
+    pub template: String,
+
+    /// How the rendered value is matched to a user
+    pub lookup: CertificateLookup,
+}
+
+/// What happens when a new session would exceed a limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionEviction {
+    /// The new session is rejected
+    #[default]
+    Reject,
+
+    /// The oldest sessions are ended to make room for the new one
+    RevokeOldest,
+}
+
+/// Limits on the number of simultaneous sessions of users
+#[derive(Debug, Clone, Default)]
+pub struct SessionLimits {
+    /// Maximum number of active sessions per user, unless overridden for the
+    /// user
+    pub max_sessions_per_user: Option<u32>,
+
+    /// Per-client maximum number of active sessions per user
+    pub client_overrides: HashMap<Ulid, u32>,
+
+    /// What happens when a new session would exceed a limit
+    pub eviction: SessionEviction,
+}
+
+/// How the Matrix device IDs of new sessions are allocated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceIdAllocation {
+    /// The device ID asked by the client is used, unless another client uses
+    /// it for the same user
+    #[default]
+    ClientProvided,
+
+    /// A new random device ID is allocated, whatever the client asked for
+    Random,
+
+    /// The device ID is derived from the client and the user, so that a
+    /// client always gets the same device for a user
+    Deterministic,
+}
+
+/// How the legacy password login of the Matrix client-server API is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompatPasswordLogin {
+    /// Clients can log in with a password
+    #[default]
+    Allowed,
+
+    /// Clients can still log in with a password, but each login is logged as
+    /// a warning
+    Deprecated,
+
+    /// The password login is neither advertised nor accepted
+    Disabled,
+}
+
+/// The icon displayed next to a scope on the consent screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScopeIcon {
+    #[default]
+    Info,
+    UserProfile,
+    Chat,
+    Send,
+    Computer,
+    Devices,
+    Time,
+    Key,
+    Email,
+    Settings,
+    Admin,
+    Lock,
+    Warning,
+    Error,
+}
+
+impl ScopeIcon {
+    /// The name of the icon, as used by the templates
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::UserProfile => "user_profile",
+            Self::Chat => "chat",
+            Self::Send => "send",
+            Self::Computer => "computer",
+            Self::Devices => "devices",
+            Self::Time => "time",
+            Self::Key => "key",
+            Self::Email => "email",
+            Self::Settings => "settings",
+            Self::Admin => "admin",
+            Self::Lock => "lock",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// How a scope unknown to the service is described on the consent screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomScope {
+    /// The icon displayed next to the description
+    pub icon: ScopeIcon,
+
+    /// The description of the scope
+    pub description: String,
+
+    /// Translations of the description, keyed by language
+    pub translations: BTreeMap<String, String>,
+}
+
 /// Random site configuration we want accessible in various places.
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone)]
@@ -38,6 +187,10 @@ pub struct SiteConfig {
     /// Time-to-live of compatibility access tokens.
     pub compat_token_ttl: Duration,
 
+    /// Tolerance for the clock differences with the issuers of the JWTs
+    /// checked by the service.
+    pub jwt_leeway: Duration,
+
     /// The server name, e.g. "matrix.org".
     pub server_name: String,
 
@@ -56,12 +209,25 @@ pub struct SiteConfig {
     /// Whether password registration is enabled.
     pub password_registration_enabled: bool,
 
+    /// How the password login of the Matrix client-server API is handled.
+    pub compat_password_login: CompatPasswordLogin,
+
     /// Whether users can change their email.
     pub email_change_allowed: bool,
 
+    /// Whether users must authenticate again before changing their email.
+    pub email_change_requires_reauthentication: bool,
+
     /// Whether users can change their display name.
     pub displayname_change_allowed: bool,
 
+    /// Whether users can change their avatar.
+    pub avatar_change_allowed: bool,
+
+    /// Whether the display name of users linked to an upstream provider
+    /// forcing it is locked to the value it provides.
+    pub displayname_locked_to_upstream: bool,
+
     /// Whether users can change their password.
     pub password_change_allowed: bool,
 
@@ -71,7 +237,112 @@ pub struct SiteConfig {
     /// Captcha configuration
     pub captcha: Option<CaptchaConfig>,
 
+    /// Bot detection on the login and registration forms, if enabled.
+    pub bot_detection: Option<BotDetectionConfig>,
+
+    /// Login with X.509 client certificates, if enabled.
+    pub certificate_login: Option<CertificateLoginConfig>,
+
+    /// Whether users can pick the upstream provider to log in with from the
+    /// Matrix ID of an account on another homeserver, if its authentication is
+    /// delegated to one of the configured providers.
+    pub matrix_id_login_enabled: bool,
+
+    /// Limits on the number of simultaneous sessions of users.
+    pub session_limits: SessionLimits,
+
     /// Minimum password complexity, between 0 and 4.
     /// This is a score from zxcvbn.
     pub minimum_password_complexity: u8,
+
+    /// Whether clients need to be granted the `offline_access` scope to get
+    /// refresh tokens.
+    pub offline_access_required: bool,
+
+    /// Per-client overrides of [`Self::offline_access_required`].
+    pub offline_access_overrides: HashMap<Ulid, bool>,
+
+    /// Per-client binding of authorization codes to the context they were
+    /// requested in. Clients not in this map don't bind their codes.
+    pub code_binding_overrides: HashMap<Ulid, AuthorizationCodeBinding>,
+
+    /// Per-client validation of redirect URIs. Clients not in this map use the
+    /// native app rules if they are native apps, and the default ones
+    /// otherwise.
+    pub redirect_uri_validation_overrides: HashMap<Ulid, RedirectUriValidation>,
+
+    /// Length of the user codes generated for the device authorization grant.
+    pub device_code_user_code_length: usize,
+
+    /// Characters used to generate the user codes for the device
+    /// authorization grant.
+    pub device_code_user_code_charset: Vec<char>,
+
+    /// Descriptions of the custom scopes, keyed by scope.
+    pub custom_scopes: BTreeMap<String, CustomScope>,
+
+    /// How the Matrix device IDs of new sessions are allocated.
+    pub device_id_allocation: DeviceIdAllocation,
+
+    /// Whether a client authenticating again for a user reuses the device of
+    /// its previous session with that user.
+    pub reuse_devices: bool,
+
+    /// The feature flags, to roll out features gradually.
+    pub feature_flags: FeatureFlags,
+
+    /// Clients allowed to register users through the registration API.
+    pub native_registration_clients: HashSet<Ulid>,
+
+    /// Clients allowed to send users straight to an upstream provider with an
+    /// `idp_hint`.
+    pub upstream_provider_hint_clients: HashSet<Ulid>,
+
+    /// The upstream provider users are sent straight to when logging in to
+    /// the given client.
+    pub client_upstream_providers: HashMap<Ulid, Ulid>,
+}
+
+impl SiteConfig {
+    /// Whether the given client needs to be granted the `offline_access` scope
+    /// to get refresh tokens.
+    #[must_use]
+    pub fn offline_access_required_for(&self, client_id: Ulid) -> bool {
+        self.offline_access_overrides
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.offline_access_required)
+    }
+
+    /// How strictly the authorization codes of the given client are bound to
+    /// the context they were requested in.
+    ///
+    /// Codes are never bound for the clients which authenticate at the token
+    /// endpoint, as they redeem them from their backend, which is on a
+    /// different network than the browser of the user.
+    #[must_use]
+    pub fn code_binding_for(&self, client: &Client) -> AuthorizationCodeBinding {
+        if client.token_endpoint_auth_method != Some(OAuthClientAuthenticationMethod::None) {
+            return AuthorizationCodeBinding::Off;
+        }
+
+        self.code_binding_overrides
+            .get(&client.id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// How the redirect URIs of the given client are validated.
+    #[must_use]
+    pub fn redirect_uri_validation_for(&self, client: &Client) -> RedirectUriValidation {
+        if let Some(validation) = self.redirect_uri_validation_overrides.get(&client.id) {
+            return *validation;
+        }
+
+        if client.application_type == Some(ApplicationType::Native) {
+            RedirectUriValidation::Native
+        } else {
+            RedirectUriValidation::Default
+        }
+    }
 }
@@ -52,7 +52,11 @@ pub struct AccessToken {
     pub id: Ulid,
     pub state: AccessTokenState,
     pub session_id: Ulid,
+
+    /// The value of the token, only known when it was just issued or looked up
+    /// by value, as the storage only keeps its hash. Empty otherwise.
     pub access_token: String,
+
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
 }
@@ -146,7 +150,11 @@ impl RefreshTokenState {
 pub struct RefreshToken {
     pub id: Ulid,
     pub state: RefreshTokenState,
+
+    /// The value of the token, only known when it was just issued or looked up
+    /// by value, as the storage only keeps its hash. Empty otherwise.
     pub refresh_token: String,
+
     pub session_id: Ulid,
     pub created_at: DateTime<Utc>,
     pub access_token_id: Option<Ulid>,
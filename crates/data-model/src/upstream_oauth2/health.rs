@@ -0,0 +1,79 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+/// The status of an upstream OAuth 2.0 provider, derived from its health
+/// checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamOAuthProviderStatus {
+    /// The last check succeeded
+    Up,
+
+    /// Some checks failed, but not enough yet to consider the provider down
+    Degraded,
+
+    /// Enough checks failed in a row for the provider to be considered down
+    Down,
+
+    /// The provider wasn't checked recently
+    Unknown,
+}
+
+/// The result of the periodic health checks of an upstream OAuth 2.0 provider
+///
+/// This acts as a circuit breaker: once enough checks failed in a row, the
+/// provider is considered down and users are not sent to it anymore, until a
+/// check succeeds again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamOAuthProviderHealth {
+    pub provider_id: Ulid,
+    pub checked_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl UpstreamOAuthProviderHealth {
+    /// Number of consecutive failed checks after which the provider is
+    /// considered down
+    pub const FAILURE_THRESHOLD: u32 = 3;
+
+    /// How long the result of a check stays relevant, in seconds. Past that,
+    /// for example if no worker is running the checks, the provider is
+    /// assumed to be up.
+    pub const VALIDITY_SECONDS: i64 = 10 * 60;
+
+    /// Returns `true` if the provider should be considered down at the given
+    /// instant
+    #[must_use]
+    pub fn is_down(&self, now: DateTime<Utc>) -> bool {
+        self.consecutive_failures >= Self::FAILURE_THRESHOLD && !self.is_stale(now)
+    }
+
+    /// Get the status of the provider at the given instant
+    #[must_use]
+    pub fn status(&self, now: DateTime<Utc>) -> UpstreamOAuthProviderStatus {
+        if self.is_stale(now) {
+            UpstreamOAuthProviderStatus::Unknown
+        } else if self.consecutive_failures >= Self::FAILURE_THRESHOLD {
+            UpstreamOAuthProviderStatus::Down
+        } else if self.consecutive_failures > 0 {
+            UpstreamOAuthProviderStatus::Degraded
+        } else {
+            UpstreamOAuthProviderStatus::Up
+        }
+    }
+
+    /// Returns `true` if the result of the last check is too old to be
+    /// relevant at the given instant
+    #[must_use]
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        now - self.checked_at >= Duration::seconds(Self::VALIDITY_SECONDS)
+    }
+}
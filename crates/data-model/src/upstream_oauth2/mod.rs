@@ -4,11 +4,13 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+mod health;
 mod link;
 mod provider;
 mod session;
 
 pub use self::{
+    health::{UpstreamOAuthProviderHealth, UpstreamOAuthProviderStatus},
     link::UpstreamOAuthLink,
     provider::{
         ClaimsImports as UpstreamOAuthProviderClaimsImports,
@@ -4,10 +4,25 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use serde::Serialize;
+//! Parse `User-Agent` headers to classify the device and the client
+//! application used for a session.
+//!
+//! The parsed [`UserAgent`] is used to display sessions, in audit events and
+//! when assessing the risk of a login attempt.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use woothee::{parser::Parser, woothee::VALUE_UNKNOWN};
 
-#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+static CUSTOM_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<name>[^/]+)/(?P<version>[^ ]+) \((?P<segments>.+)\)$").unwrap()
+});
+
+static ELECTRON_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)\w+/[\w.]+").unwrap());
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum DeviceType {
     Pc,
@@ -16,6 +31,62 @@ pub enum DeviceType {
     Unknown,
 }
 
+/// The kind of application which sent the request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientApp {
+    /// A web browser. This includes Element Web, which can't be told apart
+    /// from the browser it runs in.
+    Browser,
+
+    /// Element Desktop, the Electron application
+    ElementDesktop,
+
+    /// Element Android
+    ElementAndroid,
+
+    /// Element iOS
+    ElementIos,
+
+    /// Element X, on any platform
+    ElementX,
+
+    /// Another native application
+    OtherApp,
+
+    /// The application could not be detected
+    Unknown,
+}
+
+impl ClientApp {
+    /// A stable name of the application, suitable for logs and metrics
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Browser => "browser",
+            Self::ElementDesktop => "element_desktop",
+            Self::ElementAndroid => "element_android",
+            Self::ElementIos => "element_ios",
+            Self::ElementX => "element_x",
+            Self::OtherApp => "other_app",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// Classify a native application from its name and operating system, as
+    /// found in user-agents like `Element/1.6.10 (Google Pixel 7; Android 14)`
+    fn from_app(name: &str, os: Option<&str>) -> Self {
+        let normalized = name.to_ascii_lowercase();
+        match (normalized.as_str(), os) {
+            ("element x" | "elementx", _) => Self::ElementX,
+            ("element" | "riot.im", Some("Android")) => Self::ElementAndroid,
+            ("element" | "riot.im", Some("iOS" | "iPadOS")) => Self::ElementIos,
+            ("element" | "riot.im", _) => Self::ElementDesktop,
+            _ => Self::OtherApp,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone, PartialEq, Eq)]
 pub struct UserAgent {
     pub name: Option<String>,
@@ -24,9 +95,23 @@ pub struct UserAgent {
     pub os_version: Option<String>,
     pub model: Option<String>,
     pub device_type: DeviceType,
+    pub client_app: ClientApp,
     pub raw: String,
 }
 
+/// The fields parsed from a user-agent, which are saved alongside it so that
+/// sessions don't have to parse it again each time they are loaded
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UserAgentDetails {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub model: Option<String>,
+    pub device_type: DeviceType,
+    pub client_app: ClientApp,
+}
+
 impl std::ops::Deref for UserAgent {
     type Target = str;
 
@@ -37,10 +122,7 @@ impl std::ops::Deref for UserAgent {
 
 impl UserAgent {
     fn parse_custom(user_agent: &str) -> Option<(&str, &str, &str, &str, Option<&str>)> {
-        let regex = regex::Regex::new(r"^(?P<name>[^/]+)/(?P<version>[^ ]+) \((?P<segments>.+)\)$")
-            .unwrap();
-
-        let captures = regex.captures(user_agent)?;
+        let captures = CUSTOM_REGEX.captures(user_agent)?;
         let name = captures.name("name")?.as_str();
         let version = captures.name("version")?.as_str();
         let segments: Vec<&str> = captures
@@ -73,15 +155,62 @@ impl UserAgent {
     }
 
     fn parse_electron(user_agent: &str) -> Option<(&str, &str)> {
-        let regex = regex::Regex::new(r"(?m)\w+/[\w.]+").unwrap();
         let omit_keys = ["Mozilla", "AppleWebKit", "Chrome", "Electron", "Safari"];
-        return regex
+        return ELECTRON_REGEX
             .find_iter(user_agent)
             .map(|caps| caps.as_str().split_once('/').unwrap())
             .find(|pair| !omit_keys.contains(&pair.0));
     }
 
+    /// A short description of the client, like "Firefox on Linux", used to
+    /// name the sessions the user didn't name themselves
+    #[must_use]
+    pub fn summary(&self) -> Option<String> {
+        match (
+            self.name.as_deref(),
+            self.os.as_deref(),
+            self.model.as_deref(),
+        ) {
+            (Some(name), Some(os), _) => Some(format!("{name} on {os}")),
+            (_, _, Some(model)) => Some(model.to_owned()),
+            (Some(name), None, None) => Some(name.to_owned()),
+            (None, Some(os), None) => Some(os.to_owned()),
+            (None, None, None) => None,
+        }
+    }
+
+    /// The fields parsed from this user-agent, to save them alongside it
+    #[must_use]
+    pub fn details(&self) -> UserAgentDetails {
+        UserAgentDetails {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            os: self.os.clone(),
+            os_version: self.os_version.clone(),
+            model: self.model.clone(),
+            device_type: self.device_type.clone(),
+            client_app: self.client_app,
+        }
+    }
+
+    /// Rebuild a user-agent from its raw value and the fields which were
+    /// parsed from it when it was saved
+    #[must_use]
+    pub fn from_details(raw: String, details: UserAgentDetails) -> Self {
+        Self {
+            name: details.name,
+            version: details.version,
+            os: details.os,
+            os_version: details.os_version,
+            model: details.model,
+            device_type: details.device_type,
+            client_app: details.client_app,
+            raw,
+        }
+    }
+
     #[must_use]
+    #[allow(clippy::too_many_lines)]
     pub fn parse(user_agent: String) -> Self {
         if !user_agent.contains("Mozilla/") {
             if let Some((name, version, model, os, os_version)) =
@@ -106,6 +235,7 @@ impl UserAgent {
                     os_version: os_version.map(std::borrow::ToOwned::to_owned),
                     model: Some(model.to_owned()),
                     device_type,
+                    client_app: ClientApp::from_app(name, Some(os)),
                     raw: user_agent,
                 };
             }
@@ -121,6 +251,7 @@ impl UserAgent {
                 os_version: None,
                 model: None,
                 device_type: DeviceType::Unknown,
+                client_app: ClientApp::Unknown,
             };
         };
 
@@ -130,6 +261,12 @@ impl UserAgent {
             _ => DeviceType::Unknown,
         };
 
+        let mut client_app = if result.name != VALUE_UNKNOWN && device_type != DeviceType::Unknown {
+            ClientApp::Browser
+        } else {
+            ClientApp::Unknown
+        };
+
         // Special handling for Chrome user-agent reduction cases
         // https://www.chromium.org/updates/ua-reduction/
         match (result.os, &*result.os_version) {
@@ -204,6 +341,7 @@ impl UserAgent {
             if let Some(app) = UserAgent::parse_electron(&user_agent) {
                 result.name = app.0;
                 result.version = app.1;
+                client_app = ClientApp::from_app(app.0, None);
             }
         }
 
@@ -214,8 +352,63 @@ impl UserAgent {
             os_version: (result.os_version != VALUE_UNKNOWN)
                 .then(|| result.os_version.into_owned()),
             device_type,
+            client_app,
             model,
             raw: user_agent,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_app() {
+        let ua = UserAgent::parse(
+            "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) \
+             Chrome/100.0.0.0 Safari/537.36"
+                .to_owned(),
+        );
+        assert_eq!(ua.client_app, ClientApp::Browser);
+        assert_eq!(ua.device_type, DeviceType::Pc);
+        assert_eq!(ua.summary().as_deref(), Some("Chrome on Linux"));
+
+        let ua = UserAgent::parse(
+            "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) \
+             Element/1.11.57 Chrome/120.0.6099.291 Electron/28.2.3 Safari/537.36"
+                .to_owned(),
+        );
+        assert_eq!(ua.client_app, ClientApp::ElementDesktop);
+        assert_eq!(ua.name.as_deref(), Some("Element"));
+
+        let ua = UserAgent::parse(
+            "Element/1.6.10 (Google Pixel 7; Android 14; UQ1A.240205.004; Flavour GooglePlay; \
+             MatrixAndroidSdk2 1.6.10)"
+                .to_owned(),
+        );
+        assert_eq!(ua.client_app, ClientApp::ElementAndroid);
+        assert_eq!(ua.device_type, DeviceType::Mobile);
+        assert_eq!(ua.model.as_deref(), Some("Google Pixel 7"));
+
+        let ua = UserAgent::parse("Element/1.11.5 (iPhone; iOS 17.3; Scale/3.00)".to_owned());
+        assert_eq!(ua.client_app, ClientApp::ElementIos);
+
+        let ua = UserAgent::parse("Element X/1.5.6 (iPhone; iOS 17.3; Scale/3.00)".to_owned());
+        assert_eq!(ua.client_app, ClientApp::ElementX);
+
+        let ua = UserAgent::parse("FluffyChat/1.20.0 (Pixel 8; Android 14)".to_owned());
+        assert_eq!(ua.client_app, ClientApp::OtherApp);
+
+        let ua = UserAgent::parse("curl/8.5.0".to_owned());
+        assert_eq!(ua.client_app, ClientApp::Unknown);
+    }
+
+    #[test]
+    fn test_details_roundtrip() {
+        let ua = UserAgent::parse("Element X/1.5.6 (iPhone; iOS 17.3; Scale/3.00)".to_owned());
+        let details: UserAgentDetails =
+            serde_json::from_value(serde_json::to_value(ua.details()).unwrap()).unwrap();
+        assert_eq!(UserAgent::from_details(ua.raw.clone(), details), ua);
+    }
+}
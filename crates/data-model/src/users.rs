@@ -8,7 +8,8 @@ use std::{net::IpAddr, ops::Deref};
 
 use chrono::{DateTime, Duration, Utc};
 use rand::{Rng, SeedableRng};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use ulid::Ulid;
 
 use crate::UserAgent;
@@ -21,14 +22,15 @@ pub struct User {
     pub primary_user_email_id: Option<Ulid>,
     pub created_at: DateTime<Utc>,
     pub locked_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
     pub can_request_admin: bool,
 }
 
 impl User {
-    /// Returns `true` unless the user is locked.
+    /// Returns `true` unless the user is locked or deleted.
     #[must_use]
     pub fn is_valid(&self) -> bool {
-        self.locked_at.is_none()
+        self.locked_at.is_none() && self.deleted_at.is_none()
     }
 }
 
@@ -43,11 +45,32 @@ impl User {
             primary_user_email_id: None,
             created_at: now,
             locked_at: None,
+            deleted_at: None,
             can_request_admin: false,
         }]
     }
 }
 
+/// Where the registration of a user came from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistrationMetadata {
+    /// The OAuth 2.0 client the user was registering for, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Ulid>,
+
+    /// The upstream OAuth 2.0 provider the user registered with, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_provider_id: Option<Ulid>,
+
+    /// The invite token found in the registration link, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite_token: Option<String>,
+
+    /// The referral parameter found in the registration link, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referral: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Password {
     pub id: Ulid,
@@ -68,9 +91,117 @@ pub struct Authentication {
 pub enum AuthenticationMethod {
     Password { user_password_id: Ulid },
     UpstreamOAuth2 { upstream_oauth2_session_id: Ulid },
+    ClientCertificate { fingerprint: String },
     Unknown,
 }
 
+impl AuthenticationMethod {
+    /// The authentication method reference of this method, as used in the
+    /// `amr` claim of ID tokens
+    ///
+    /// Values are the ones registered in RFC 8176, except `fed` for logins
+    /// through an upstream provider, which is not registered but commonly used.
+    #[must_use]
+    pub fn amr(&self) -> Option<&'static str> {
+        match self {
+            Self::Password { .. } => Some("pwd"),
+            // Certificate logins are meant for smart cards
+            Self::ClientCertificate { .. } => Some("sc"),
+            Self::UpstreamOAuth2 { .. } => Some("fed"),
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// A short-lived elevation of a [`BrowserSession`], obtained by authenticating
+/// again, and required for sensitive actions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionElevation {
+    pub id: Ulid,
+    pub user_session_id: Ulid,
+
+    /// The authentication the elevation was obtained with
+    pub authentication_id: Ulid,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SessionElevation {
+    /// Whether the elevation can still be used at the given time
+    #[must_use]
+    pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+}
+
+/// How a user tried to log in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMethod {
+    Password,
+    UpstreamOAuth2,
+    ClientCertificate,
+}
+
+impl LoginMethod {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::UpstreamOAuth2 => "upstream_oauth2",
+            Self::ClientCertificate => "client_certificate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid login method {0:?}")]
+pub struct InvalidLoginMethodError(String);
+
+impl std::str::FromStr for LoginMethod {
+    type Err = InvalidLoginMethodError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "password" => Ok(Self::Password),
+            "upstream_oauth2" => Ok(Self::UpstreamOAuth2),
+            "client_certificate" => Ok(Self::ClientCertificate),
+            s => Err(InvalidLoginMethodError(s.to_owned())),
+        }
+    }
+}
+
+/// An attempt of a user to log in, successful or not, kept for a limited time
+/// so that users can review the recent activity on their account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoginAttempt {
+    pub id: Ulid,
+    pub user_id: Ulid,
+    pub method: LoginMethod,
+    pub succeeded: bool,
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<UserAgent>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The authentication method references of a list of authentications, without
+/// duplicates, in the order they were first used
+#[must_use]
+pub fn authentication_method_references(authentications: &[Authentication]) -> Vec<String> {
+    let mut amr: Vec<String> = Vec::new();
+    for method in authentications
+        .iter()
+        .filter_map(|authentication| authentication.authentication_method.amr())
+    {
+        if !amr.iter().any(|existing| existing == method) {
+            amr.push(method.to_owned());
+        }
+    }
+
+    amr
+}
+
 /// A session to recover a user if they have lost their credentials
 ///
 /// For each session intiated, there may be multiple [`UserRecoveryTicket`]s
@@ -241,3 +372,36 @@ impl UserEmailVerification {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authentication_method_references() {
+        let authentication = |authentication_method| Authentication {
+            id: Ulid::nil(),
+            created_at: DateTime::UNIX_EPOCH,
+            authentication_method,
+        };
+
+        let authentications = [
+            authentication(AuthenticationMethod::Password {
+                user_password_id: Ulid::nil(),
+            }),
+            authentication(AuthenticationMethod::Unknown),
+            authentication(AuthenticationMethod::ClientCertificate {
+                fingerprint: "abcd".to_owned(),
+            }),
+            authentication(AuthenticationMethod::Password {
+                user_password_id: Ulid::nil(),
+            }),
+        ];
+
+        assert_eq!(
+            authentication_method_references(&authentications),
+            vec!["pwd".to_owned(), "sc".to_owned()]
+        );
+        assert!(authentication_method_references(&[]).is_empty());
+    }
+}
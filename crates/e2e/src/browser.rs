@@ -0,0 +1,272 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A fake browser, keeping cookies around and following redirects
+
+use std::sync::{Arc, RwLock};
+
+use cookie_store::{CookieStore, RawCookie};
+use headers::{ContentType, HeaderMapExt};
+use hyper::{
+    header::{COOKIE, LOCATION, SET_COOKIE},
+    Request, Response, StatusCode,
+};
+use mas_router::Route;
+use serde::Serialize;
+use url::Url;
+
+use crate::{ResponseExt, TestServer};
+
+/// How many redirects to follow before giving up
+const MAX_REDIRECTS: usize = 10;
+
+/// A fake browser, with its own cookie jar, driving the human-facing pages of
+/// the in-process server
+#[derive(Clone)]
+pub struct Browser {
+    server: TestServer,
+    cookies: Arc<RwLock<CookieStore>>,
+}
+
+impl Browser {
+    pub(crate) fn new(server: TestServer) -> Self {
+        Self {
+            server,
+            cookies: Arc::default(),
+        }
+    }
+
+    /// Send a request with the cookies of the browser, and save the cookies
+    /// set by the response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cookies can't be serialized
+    pub async fn request(&self, mut request: Request<String>) -> Response<String> {
+        let url = self.server.absolute_url(&request.uri().to_string());
+        let value = {
+            let store = self.cookies.read().unwrap();
+            store
+                .get_request_values(&url)
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        request
+            .headers_mut()
+            .insert(COOKIE, value.parse().expect("Invalid cookie value"));
+
+        let response = self.server.request(request).await;
+
+        let mut store = self.cookies.write().unwrap();
+        store.store_response_cookies(
+            response
+                .headers()
+                .get_all(SET_COOKIE)
+                .iter()
+                .filter_map(|set_cookie| set_cookie.to_str().ok())
+                .filter_map(|set_cookie| RawCookie::parse(set_cookie.to_owned()).ok()),
+            &url,
+        );
+
+        response
+    }
+
+    /// Send a request and follow the redirects of the response, as long as
+    /// they point to the server
+    ///
+    /// Returns the path of the last page loaded, along with the first response
+    /// which isn't a redirect to the server. Forms on the pages don't have an
+    /// action, so the path is where they have to be submitted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are too many redirects
+    pub async fn navigate(&self, request: Request<String>) -> (String, Response<String>) {
+        let mut path = request.uri().to_string();
+        let mut response = self.request(request).await;
+
+        for _ in 0..MAX_REDIRECTS {
+            if !response.status().is_redirection() {
+                return (path, response);
+            }
+
+            let Some(location) = self.server_location(&response) else {
+                return (path, response);
+            };
+
+            let request = Request::get(&location).body(String::new()).unwrap();
+            response = self.request(request).await;
+            path = location;
+        }
+
+        panic!("Too many redirects");
+    }
+
+    /// Load a page, following redirects
+    ///
+    /// # Panics
+    ///
+    /// Panics if the final response is not a 200 OK
+    pub async fn get(&self, path: &str) -> Response<String> {
+        let request = Request::get(path).body(String::new()).unwrap();
+        let (_, response) = self.navigate(request).await;
+        response.assert_status(StatusCode::OK);
+        response
+    }
+
+    /// Submit the form on a page, adding the CSRF token from the page to the
+    /// fields, and following redirects
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page doesn't have a CSRF token
+    pub async fn submit_form<T: Serialize>(
+        &self,
+        path: &str,
+        page: &Response<String>,
+        fields: T,
+    ) -> Response<String> {
+        let csrf = csrf_token(page.body()).expect("No CSRF token on the page");
+
+        let mut form = serde_json::to_value(fields).unwrap();
+        form["csrf"] = csrf.into();
+
+        let mut request = Request::post(path)
+            .body(serde_urlencoded::to_string(&form).unwrap())
+            .unwrap();
+        request
+            .headers_mut()
+            .typed_insert(ContentType::form_url_encoded());
+
+        let (_, response) = self.navigate(request).await;
+        response
+    }
+
+    /// Register a new user with a password, and return the path to the page
+    /// where the email has to be verified
+    ///
+    /// # Panics
+    ///
+    /// Panics if the registration fails
+    pub async fn register(&self, username: &str, email: &str, password: &str) -> String {
+        let path = mas_router::Register::default().path_and_query();
+        let page = self.get(&path).await;
+
+        let csrf = csrf_token(page.body()).expect("No CSRF token on the page");
+        let mut request = Request::post(&*path)
+            .body(
+                serde_urlencoded::to_string([
+                    ("csrf", csrf.as_str()),
+                    ("username", username),
+                    ("email", email),
+                    ("password", password),
+                    ("password_confirm", password),
+                    ("accept_terms", "on"),
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+        request
+            .headers_mut()
+            .typed_insert(ContentType::form_url_encoded());
+
+        let response = self.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        self.server_location(&response)
+            .expect("Registration did not redirect to the server")
+    }
+
+    /// Verify an email address with the code sent to it, from the page the
+    /// registration redirected to
+    ///
+    /// # Panics
+    ///
+    /// Panics if the verification fails
+    pub async fn verify_email(&self, path: &str, code: &str) {
+        let page = self.get(path).await;
+        let response = self
+            .submit_form(path, &page, serde_json::json!({ "code": code }))
+            .await;
+        response.assert_status(StatusCode::OK);
+    }
+
+    /// Log in with a password
+    ///
+    /// # Panics
+    ///
+    /// Panics if the login fails
+    pub async fn login(&self, username: &str, password: &str) {
+        let path = mas_router::Login::default().path_and_query();
+        let page = self.get(&path).await;
+        let response = self
+            .submit_form(
+                &path,
+                &page,
+                serde_json::json!({ "username": username, "password": password }),
+            )
+            .await;
+        response.assert_status(StatusCode::OK);
+        assert!(
+            !response.body().contains("name=\"password\""),
+            "Login failed:\n{}",
+            response.body()
+        );
+    }
+
+    /// Go through an authorization code flow, giving consent if asked, and
+    /// return the URL the browser got redirected to on the client
+    ///
+    /// The browser must already be logged in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the flow doesn't end up redirecting to the client
+    pub async fn authorize(&self, authorization_url: &str) -> Url {
+        let request = Request::get(authorization_url).body(String::new()).unwrap();
+        let (path, mut response) = self.navigate(request).await;
+
+        // We landed on the consent page, accept it
+        if response.status() == StatusCode::OK && response.body().contains("name=\"csrf\"") {
+            response = self
+                .submit_form(&path, &response, serde_json::json!({}))
+                .await;
+        }
+
+        assert!(
+            response.status().is_redirection(),
+            "Authorization did not redirect to the client: {}\n{}",
+            response.status(),
+            response.body()
+        );
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        location.parse().expect("Invalid redirect URL")
+    }
+
+    /// The path and query of the location of a redirect, if it points to the
+    /// server
+    fn server_location(&self, response: &Response<String>) -> Option<String> {
+        let location = response.headers().get(LOCATION)?.to_str().ok()?;
+        let url = self.server.absolute_url(location);
+        if url.origin() != self.server.base_url().origin() {
+            return None;
+        }
+
+        Some(match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        })
+    }
+}
+
+/// Extract the CSRF token from a page
+fn csrf_token(body: &str) -> Option<String> {
+    let token = body
+        .split("name=\"csrf\" value=\"")
+        .nth(1)?
+        .split('"')
+        .next()?;
+    Some(token.to_owned())
+}
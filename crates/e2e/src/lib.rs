@@ -0,0 +1,524 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! End-to-end test harness for the Matrix Authentication Service
+//!
+//! This boots the full HTTP router in-process, against a temporary database
+//! schema, with a fake homeserver and a mail sink, alongside a task worker. A
+//! [`Browser`] can then drive complete flows, from the registration of a user
+//! to the introspection of the tokens a client got.
+//!
+//! Tests using it are regular `#[sqlx::test]` tests, which get a fresh
+//! database for each test.
+
+#![allow(clippy::missing_panics_doc)]
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Context as _;
+use axum::body::{Bytes, HttpBody};
+use chrono::Duration;
+use headers::{ContentType, HeaderMapExt};
+use hyper::{header::CONTENT_TYPE, Request, Response, StatusCode};
+use mas_config::RateLimitingConfig;
+use mas_data_model::{
+    CompatPasswordLogin, DeviceIdAllocation, FeatureFlags, SessionLimits, SiteConfig,
+};
+use mas_email::{MailTransport, Mailer};
+use mas_handlers::{
+    passwords::{Hasher, PasswordManager},
+    ActivityTracker, AttestationChecker, CookieManager, DeviceProofReplayCache,
+    HomeserverDiscovery, Limiter, MetadataCache, NetworkPolicy, RiskAssessor,
+};
+use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
+use mas_matrix::MockHomeserverConnection;
+use mas_policy::PolicyFactory;
+use mas_router::{SimpleRoute, UrlBuilder};
+use mas_storage::BoxRepository;
+use mas_storage_pg::{DatabaseError, PgRepository, TokenHasher};
+use mas_templates::{SiteConfigExt, Templates};
+use oauth2_types::{
+    registration::ClientRegistrationResponse,
+    requests::{AccessTokenResponse, IntrospectionResponse},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::PgPool;
+use tokio_util::{
+    sync::{CancellationToken, DropGuard},
+    task::TaskTracker,
+};
+use tower::{Service, ServiceExt};
+use url::Url;
+
+mod browser;
+mod mail;
+mod state;
+
+use self::state::State;
+pub use self::{browser::Browser, mail::MailSink};
+
+/// The redirect URI of the clients registered by
+/// [`TestServer::register_client`]
+pub const CLIENT_REDIRECT_URI: &str = "https://client.example.org/callback";
+
+/// Setup rustcrypto and tracing for tests.
+#[allow(unused_must_use)]
+pub fn setup() {
+    rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_test_writer()
+        .try_init();
+}
+
+fn workspace_root() -> camino::Utf8PathBuf {
+    camino::Utf8Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("..")
+        .canonicalize_utf8()
+        .unwrap()
+}
+
+/// The site config used by default by the test server
+#[must_use]
+pub fn test_site_config() -> SiteConfig {
+    SiteConfig {
+        access_token_ttl: Duration::try_minutes(5).unwrap(),
+        compat_token_ttl: Duration::try_minutes(5).unwrap(),
+        jwt_leeway: Duration::try_minutes(5).unwrap(),
+        server_name: "example.com".to_owned(),
+        policy_uri: Some("https://example.com/policy".parse().unwrap()),
+        tos_uri: Some("https://example.com/tos".parse().unwrap()),
+        imprint: None,
+        password_login_enabled: true,
+        password_registration_enabled: true,
+        compat_password_login: CompatPasswordLogin::default(),
+        email_change_allowed: true,
+        email_change_requires_reauthentication: false,
+        displayname_change_allowed: true,
+        avatar_change_allowed: true,
+        displayname_locked_to_upstream: false,
+        password_change_allowed: true,
+        account_recovery_allowed: true,
+        captcha: None,
+        bot_detection: None,
+        certificate_login: None,
+        matrix_id_login_enabled: false,
+        session_limits: SessionLimits::default(),
+        minimum_password_complexity: 1,
+        offline_access_required: false,
+        offline_access_overrides: HashMap::new(),
+        code_binding_overrides: HashMap::new(),
+        redirect_uri_validation_overrides: HashMap::new(),
+        device_code_user_code_length: 6,
+        device_code_user_code_charset: "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".chars().collect(),
+        custom_scopes: BTreeMap::new(),
+        device_id_allocation: DeviceIdAllocation::default(),
+        reuse_devices: false,
+        feature_flags: FeatureFlags::default(),
+        native_registration_clients: HashSet::new(),
+        upstream_provider_hint_clients: HashSet::new(),
+        client_upstream_providers: HashMap::new(),
+    }
+}
+
+/// A client registered on the test server
+#[derive(Debug, Clone)]
+pub struct TestClient {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: Url,
+}
+
+/// The full service running in-process, with a task worker in the background
+#[derive(Clone)]
+pub struct TestServer {
+    state: State,
+    mail_sink: MailSink,
+
+    // Cancels the worker and the activity tracker when the last clone is dropped
+    #[allow(dead_code)]
+    cancellation_drop_guard: Arc<DropGuard>,
+}
+
+impl TestServer {
+    /// Start a server on the given database, with the default site config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server or the worker failed to start
+    pub async fn start(pool: PgPool) -> Result<Self, anyhow::Error> {
+        Self::start_with_site_config(pool, test_site_config()).await
+    }
+
+    /// Start a server on the given database, with the given site config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server or the worker failed to start
+    #[allow(clippy::too_many_lines)]
+    pub async fn start_with_site_config(
+        pool: PgPool,
+        site_config: SiteConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let workspace_root = workspace_root();
+
+        let task_tracker = TaskTracker::new();
+        let shutdown_token = CancellationToken::new();
+
+        let url_builder = UrlBuilder::new("https://example.com/".parse()?, None, None);
+
+        let templates = Templates::load(
+            workspace_root.join("templates"),
+            url_builder.clone(),
+            workspace_root.join("frontend/dist/manifest.json"),
+            workspace_root.join("translations"),
+            None,
+            site_config.templates_branding(),
+            site_config.templates_features(),
+        )
+        .await?;
+
+        let http_client = mas_http::reqwest_client();
+
+        let rsa = PrivateKey::load_pem(include_str!("../../keystore/tests/keys/rsa.pkcs1.pem"))?;
+        let rsa = JsonWebKey::new(rsa).with_kid("test-rsa");
+        let key_store = Keystore::new(JsonWebKeySet::new(vec![rsa]));
+
+        let encrypter = Encrypter::new(&[0x42; 32]);
+        let token_hasher = TokenHasher::new(&[0x42; 32]);
+        let cookie_manager = CookieManager::derive_from(url_builder.http_base(), &[0x42; 32]);
+
+        let password_manager = PasswordManager::new(
+            site_config.minimum_password_complexity,
+            [(1, Hasher::argon2id(None))],
+        )?;
+
+        let file =
+            tokio::fs::File::open(workspace_root.join("policies").join("policy.wasm")).await?;
+        let entrypoints = mas_policy::Entrypoints {
+            register: "register/violation".to_owned(),
+            client_registration: "client_registration/violation".to_owned(),
+            authorization_grant: "authorization_grant/violation".to_owned(),
+            email: "email/violation".to_owned(),
+        };
+        let policy_factory =
+            Arc::new(PolicyFactory::load(file, serde_json::json!({}), entrypoints).await?);
+
+        let homeserver_connection =
+            Arc::new(MockHomeserverConnection::new(&site_config.server_name));
+
+        let limiter = Limiter::new(&RateLimitingConfig::default())
+            .context("Failed to create the rate limiter")?;
+
+        let graphql_schema = mas_handlers::graphql_schema(
+            &pool,
+            &policy_factory,
+            Arc::clone(&homeserver_connection),
+            site_config.clone(),
+            password_manager.clone(),
+            limiter.clone(),
+            token_hasher.clone(),
+        );
+
+        let activity_tracker = ActivityTracker::new(
+            pool.clone(),
+            std::time::Duration::from_secs(60),
+            &task_tracker,
+            shutdown_token.child_token(),
+        );
+
+        // Emails end up in the queue, where the mail sink reads them, before
+        // being dropped by the transport
+        let mailer = Mailer::new(
+            templates.clone(),
+            MailTransport::blackhole(),
+            "Matrix Authentication Service <root@localhost>".parse()?,
+            "root@localhost".parse()?,
+        );
+
+        let monitor = mas_tasks::init(
+            "e2e",
+            &pool,
+            &mailer,
+            Arc::clone(&homeserver_connection),
+            url_builder.clone(),
+            http_client.clone(),
+            token_hasher.clone(),
+            None,
+            mas_tasks::Alerter::disabled(),
+            std::time::Duration::from_secs(60 * 60 * 24 * 30),
+            std::time::Duration::from_secs(60 * 60 * 24 * 90),
+        )
+        .await?;
+
+        let worker_token = shutdown_token.child_token();
+        task_tracker.spawn(async move {
+            tokio::select! {
+                res = monitor.run() => {
+                    if let Err(e) = res {
+                        tracing::error!(error = &e as &dyn std::error::Error, "Worker failed");
+                    }
+                }
+                () = worker_token.cancelled() => {}
+            }
+        });
+
+        let state = State {
+            pool: pool.clone(),
+            templates,
+            key_store,
+            cookie_manager,
+            metadata_cache: MetadataCache::new(),
+            encrypter,
+            token_hasher,
+            url_builder,
+            homeserver_connection,
+            policy_factory,
+            graphql_schema,
+            password_manager,
+            site_config,
+            activity_tracker,
+            limiter,
+            attestation_checker: AttestationChecker::disabled(),
+            device_proof_replay_cache: DeviceProofReplayCache::new(),
+            network_policy: NetworkPolicy::disabled(),
+            risk_assessor: RiskAssessor::disabled(),
+            http_client,
+            homeserver_discovery: HomeserverDiscovery::new(mas_http::reqwest_public_client()),
+        };
+
+        Ok(Self {
+            state,
+            mail_sink: MailSink::new(pool),
+            cancellation_drop_guard: Arc::new(shutdown_token.drop_guard()),
+        })
+    }
+
+    /// Send a request to the server, without any cookie
+    pub async fn request<B>(&self, request: Request<B>) -> Response<String>
+    where
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        <B as HttpBody>::Error: std::error::Error + Send + Sync,
+        B::Error: std::error::Error + Send + Sync,
+        B::Data: Send,
+    {
+        let app = mas_handlers::healthcheck_router()
+            .merge(mas_handlers::discovery_router())
+            .merge(mas_handlers::api_router(self.state.templates.clone()))
+            .merge(mas_handlers::compat_router())
+            .merge(mas_handlers::human_router(self.state.templates.clone()))
+            .merge(mas_handlers::graphql_router(false, true))
+            .merge(mas_handlers::admin_api_router().1)
+            .merge(mas_handlers::email_webhooks_router("webhook-secret"))
+            .with_state(self.state.clone())
+            .into_service();
+
+        let Ok(mut service) = app.ready_oneshot().await;
+        let Ok(response) = service.call(request).await;
+
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        let body = std::str::from_utf8(&body)
+            .expect("Response body is not valid UTF-8")
+            .to_owned();
+
+        Response::from_parts(parts, body)
+    }
+
+    /// Get a new browser, with an empty cookie jar
+    #[must_use]
+    pub fn browser(&self) -> Browser {
+        Browser::new(self.clone())
+    }
+
+    /// The sink collecting the emails sent by the service
+    #[must_use]
+    pub fn mail_sink(&self) -> &MailSink {
+        &self.mail_sink
+    }
+
+    /// The fake homeserver the service talks to
+    #[must_use]
+    pub fn homeserver(&self) -> &MockHomeserverConnection {
+        &self.state.homeserver_connection
+    }
+
+    /// The public base URL of the server
+    #[must_use]
+    pub fn base_url(&self) -> Url {
+        self.state.url_builder.http_base()
+    }
+
+    /// Resolve a path or URL against the base URL of the server
+    #[must_use]
+    pub fn absolute_url(&self, url: &str) -> Url {
+        self.base_url().join(url).expect("Invalid URL")
+    }
+
+    /// Get a repository on the database of the server
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be reached
+    pub async fn repository(&self) -> Result<BoxRepository, DatabaseError> {
+        let repo = PgRepository::from_pool(&self.state.pool)
+            .await?
+            .with_token_hasher(self.state.token_hasher.clone());
+        Ok(repo.boxed())
+    }
+
+    /// Register a confidential client, authenticating with
+    /// `client_secret_post`, which can use the authorization code flow
+    pub async fn register_client(&self) -> TestClient {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://client.example.org/",
+                "redirect_uris": [CLIENT_REDIRECT_URI],
+                "contacts": ["contact@client.example.org"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+        let response = self.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let response: ClientRegistrationResponse = response.json();
+
+        TestClient {
+            client_id: response.client_id,
+            client_secret: response
+                .client_secret
+                .expect("Confidential clients have a secret"),
+            redirect_uri: CLIENT_REDIRECT_URI.parse().unwrap(),
+        }
+    }
+
+    /// The path of an authorization request for the given client and scope
+    #[must_use]
+    pub fn authorization_path(&self, client: &TestClient, scope: &str, state: &str) -> String {
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &client.client_id),
+            ("redirect_uri", client.redirect_uri.as_str()),
+            ("scope", scope),
+            ("state", state),
+        ])
+        .unwrap();
+        format!("{}?{query}", mas_router::OAuth2AuthorizationEndpoint::PATH)
+    }
+
+    /// Exchange an authorization code for tokens
+    pub async fn exchange_code(&self, client: &TestClient, code: &str) -> AccessTokenResponse {
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": client.redirect_uri,
+                "client_id": client.client_id,
+                "client_secret": client.client_secret,
+            }));
+        let response = self.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.json()
+    }
+
+    /// Introspect a token, authenticating as the given client
+    pub async fn introspect(&self, client: &TestClient, token: &str) -> IntrospectionResponse {
+        let request =
+            Request::post(mas_router::OAuth2Introspection::PATH).form(serde_json::json!({
+                "token": token,
+                "client_id": client.client_id,
+                "client_secret": client.client_secret,
+            }));
+        let response = self.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.json()
+    }
+}
+
+/// Helpers to build requests
+pub trait RequestBuilderExt {
+    /// Builds the request with the given JSON value as body.
+    fn json<T: Serialize>(self, body: T) -> Request<String>;
+
+    /// Builds the request with the given form value as body.
+    fn form<T: Serialize>(self, body: T) -> Request<String>;
+
+    /// Builds the request with an empty body.
+    fn empty(self) -> Request<String>;
+}
+
+impl RequestBuilderExt for hyper::http::request::Builder {
+    fn json<T: Serialize>(mut self, body: T) -> Request<String> {
+        self.headers_mut()
+            .unwrap()
+            .typed_insert(ContentType::json());
+
+        self.body(serde_json::to_string(&body).unwrap()).unwrap()
+    }
+
+    fn form<T: Serialize>(mut self, body: T) -> Request<String> {
+        self.headers_mut()
+            .unwrap()
+            .typed_insert(ContentType::form_url_encoded());
+
+        self.body(serde_urlencoded::to_string(&body).unwrap())
+            .unwrap()
+    }
+
+    fn empty(self) -> Request<String> {
+        self.body(String::new()).unwrap()
+    }
+}
+
+/// Helpers to check responses
+pub trait ResponseExt {
+    /// Asserts that the response has the given status code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the response has a different status code.
+    fn assert_status(&self, status: StatusCode);
+
+    /// Deserializes the JSON body of the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the response is not JSON, or doesn't match the type.
+    fn json<T: DeserializeOwned>(&self) -> T;
+}
+
+impl ResponseExt for Response<String> {
+    #[track_caller]
+    fn assert_status(&self, status: StatusCode) {
+        assert_eq!(
+            self.status(),
+            status,
+            "HTTP status code mismatch: got {}, expected {}. Body: {}",
+            self.status(),
+            status,
+            self.body()
+        );
+    }
+
+    #[track_caller]
+    fn json<T: DeserializeOwned>(&self) -> T {
+        assert_eq!(
+            self.headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+            Some("application/json"),
+            "Response is not JSON: {}",
+            self.body()
+        );
+        serde_json::from_str(self.body()).expect("Invalid JSON body")
+    }
+}
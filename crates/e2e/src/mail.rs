@@ -0,0 +1,126 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A sink collecting the emails sent by the service
+//!
+//! Every email goes through the email queue before being handed to the
+//! transport, so the sink reads them back from there. The transport itself is
+//! a blackhole.
+
+use std::time::Duration;
+
+use mas_storage::{email_queue::QueuedEmailFilter, Pagination, RepositoryAccess};
+use mas_storage_pg::PgRepository;
+use sqlx::PgPool;
+
+/// How long to wait for an email to be sent before giving up
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to look for new emails
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A sink collecting the emails sent by the service
+#[derive(Clone)]
+pub struct MailSink {
+    pool: PgPool,
+}
+
+impl MailSink {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Get all the emails sent to the given address so far, oldest first, as
+    /// fully formatted messages
+    ///
+    /// # Panics
+    ///
+    /// Panics if the database can't be queried
+    pub async fn emails_to(&self, recipient: &str) -> Vec<String> {
+        let mut repo = PgRepository::from_pool(&self.pool).await.unwrap().boxed();
+        let filter = QueuedEmailFilter::new().for_recipient(recipient);
+        let page = repo
+            .email_queue()
+            .list(filter, Pagination::first(100))
+            .await
+            .unwrap();
+        repo.cancel().await.unwrap();
+
+        page.edges
+            .into_iter()
+            .map(|email| String::from_utf8_lossy(&email.message).into_owned())
+            .collect()
+    }
+
+    /// Wait until at least `count` emails were sent to the given address, and
+    /// return them, oldest first
+    ///
+    /// # Panics
+    ///
+    /// Panics if the emails are not sent in time
+    pub async fn wait_for_emails_to(&self, recipient: &str, count: usize) -> Vec<String> {
+        let result = tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let emails = self.emails_to(recipient).await;
+                if emails.len() >= count {
+                    return emails;
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+        .await;
+
+        result.unwrap_or_else(|_| panic!("Timed out waiting for an email to {recipient}"))
+    }
+
+    /// Wait for the next verification email sent to the given address, and
+    /// extract the verification code from it
+    ///
+    /// `previous` is the number of emails sent to this address before the
+    /// one we're waiting for.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the email is not sent in time, or if it doesn't have a code
+    pub async fn wait_for_verification_code(&self, recipient: &str, previous: usize) -> String {
+        let emails = self.wait_for_emails_to(recipient, previous + 1).await;
+        let email = &emails[previous];
+        extract_code(email).unwrap_or_else(|| panic!("No verification code in email:\n{email}"))
+    }
+}
+
+/// Find the six-digit code in the plain text part of an email
+fn extract_code(message: &str) -> Option<String> {
+    let (_, part) = message.split_once("Content-Type: text/plain")?;
+    let (_, body) = part.split_once("\r\n\r\n")?;
+    let body = body.split("\r\n--").next()?;
+    // Undo the soft line breaks of the quoted-printable encoding
+    let body = body.replace("=\r\n", "");
+
+    body.split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == 6)
+        .map(ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_code;
+
+    #[test]
+    fn test_extract_code() {
+        let message = "From: MAS <root@localhost>\r\n\
+            Date: Mon, 01 Jan 2024 10:00:00 +0000\r\n\
+            Content-Type: multipart/alternative; boundary=\"abc\"\r\n\r\n\
+            --abc\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: 7bit\r\n\r\n\
+            Hello alice,\r\n\r\n\
+            Your verification code to confirm this email address is: 012345\r\n\
+            --abc--\r\n";
+
+        assert_eq!(extract_code(message).as_deref(), Some("012345"));
+        assert_eq!(extract_code("Content-Type: text/html\r\n\r\n123456"), None);
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! The state of the in-process server, which plugs the fakes in the handlers
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+};
+use mas_data_model::SiteConfig;
+use mas_handlers::{
+    passwords::PasswordManager, ActivityTracker, AttestationChecker, BoundActivityTracker,
+    ClientCertificate, CookieManager, DeviceProofReplayCache, ErrorWrapper, GraphQLSchema,
+    HomeserverDiscovery, Limiter, MetadataCache, NetworkPolicy, RequestOrigin,
+    RequesterFingerprint, RiskAssessor,
+};
+use mas_i18n::Translator;
+use mas_keystore::{Encrypter, Keystore};
+use mas_matrix::{BoxHomeserverConnection, CircuitBreaker, MockHomeserverConnection};
+use mas_policy::{Policy, PolicyFactory};
+use mas_router::UrlBuilder;
+use mas_storage::{BoxClock, BoxRepository, BoxRng, SystemClock};
+use mas_storage_pg::{PgRepository, TokenHasher};
+use mas_templates::Templates;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use sqlx::PgPool;
+
+#[derive(Clone)]
+pub(crate) struct State {
+    pub pool: PgPool,
+    pub templates: Templates,
+    pub key_store: Keystore,
+    pub cookie_manager: CookieManager,
+    pub metadata_cache: MetadataCache,
+    pub encrypter: Encrypter,
+    pub token_hasher: TokenHasher,
+    pub url_builder: UrlBuilder,
+    pub homeserver_connection: Arc<MockHomeserverConnection>,
+    pub policy_factory: Arc<PolicyFactory>,
+    pub graphql_schema: GraphQLSchema,
+    pub password_manager: PasswordManager,
+    pub site_config: SiteConfig,
+    pub activity_tracker: ActivityTracker,
+    pub limiter: Limiter,
+    pub attestation_checker: AttestationChecker,
+    pub device_proof_replay_cache: DeviceProofReplayCache,
+    pub network_policy: NetworkPolicy,
+    pub risk_assessor: RiskAssessor,
+    pub http_client: reqwest::Client,
+    pub homeserver_discovery: HomeserverDiscovery,
+}
+
+impl FromRef<State> for PgPool {
+    fn from_ref(input: &State) -> Self {
+        input.pool.clone()
+    }
+}
+
+impl FromRef<State> for GraphQLSchema {
+    fn from_ref(input: &State) -> Self {
+        input.graphql_schema.clone()
+    }
+}
+
+impl FromRef<State> for Templates {
+    fn from_ref(input: &State) -> Self {
+        input.templates.clone()
+    }
+}
+
+impl FromRef<State> for Arc<Translator> {
+    fn from_ref(input: &State) -> Self {
+        input.templates.translator()
+    }
+}
+
+impl FromRef<State> for Keystore {
+    fn from_ref(input: &State) -> Self {
+        input.key_store.clone()
+    }
+}
+
+impl FromRef<State> for Encrypter {
+    fn from_ref(input: &State) -> Self {
+        input.encrypter.clone()
+    }
+}
+
+impl FromRef<State> for UrlBuilder {
+    fn from_ref(input: &State) -> Self {
+        input.url_builder.clone()
+    }
+}
+
+impl FromRef<State> for reqwest::Client {
+    fn from_ref(input: &State) -> Self {
+        input.http_client.clone()
+    }
+}
+
+impl FromRef<State> for PasswordManager {
+    fn from_ref(input: &State) -> Self {
+        input.password_manager.clone()
+    }
+}
+
+impl FromRef<State> for CookieManager {
+    fn from_ref(input: &State) -> Self {
+        input.cookie_manager.clone()
+    }
+}
+
+impl FromRef<State> for MetadataCache {
+    fn from_ref(input: &State) -> Self {
+        input.metadata_cache.clone()
+    }
+}
+
+impl FromRef<State> for SiteConfig {
+    fn from_ref(input: &State) -> Self {
+        input.site_config.clone()
+    }
+}
+
+impl FromRef<State> for Limiter {
+    fn from_ref(input: &State) -> Self {
+        input.limiter.clone()
+    }
+}
+
+impl FromRef<State> for RiskAssessor {
+    fn from_ref(input: &State) -> Self {
+        input.risk_assessor.clone()
+    }
+}
+
+impl FromRef<State> for NetworkPolicy {
+    fn from_ref(input: &State) -> Self {
+        input.network_policy.clone()
+    }
+}
+
+impl FromRef<State> for HomeserverDiscovery {
+    fn from_ref(input: &State) -> Self {
+        input.homeserver_discovery.clone()
+    }
+}
+
+impl FromRef<State> for AttestationChecker {
+    fn from_ref(input: &State) -> Self {
+        input.attestation_checker.clone()
+    }
+}
+
+impl FromRef<State> for DeviceProofReplayCache {
+    fn from_ref(input: &State) -> Self {
+        input.device_proof_replay_cache.clone()
+    }
+}
+
+impl FromRef<State> for BoxHomeserverConnection {
+    fn from_ref(input: &State) -> Self {
+        Box::new(input.homeserver_connection.clone())
+    }
+}
+
+impl FromRef<State> for CircuitBreaker {
+    fn from_ref(_input: &State) -> Self {
+        // The fake homeserver never fails
+        CircuitBreaker::new(0, std::time::Duration::ZERO)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for BoxClock {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        _state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        // The worker uses the system clock, so the server has to use it as well
+        Ok(Box::new(SystemClock::default()))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for BoxRng {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        _state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        // This rng is used to source the local rng
+        #[allow(clippy::disallowed_methods)]
+        let rng = rand::thread_rng();
+
+        let rng = ChaChaRng::from_rng(rng).expect("Failed to seed RNG");
+        Ok(Box::new(rng))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for Policy {
+    type Rejection = ErrorWrapper<mas_policy::InstantiateError>;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        let policy = state.policy_factory.instantiate().await?;
+        Ok(policy)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for ActivityTracker {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(state.activity_tracker.clone())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for BoundActivityTracker {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(state.activity_tracker.clone().bind(None))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for RequesterFingerprint {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        _state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(RequesterFingerprint::EMPTY)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for ClientCertificate {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        // Tests can simulate a client certificate by adding it as an extension
+        Ok(parts
+            .extensions
+            .get::<ClientCertificate>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for RequestOrigin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        // Tests can simulate where the request comes from by adding it as an
+        // extension
+        Ok(parts
+            .extensions
+            .get::<RequestOrigin>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<State> for BoxRepository {
+    type Rejection = ErrorWrapper<mas_storage_pg::DatabaseError>;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &State,
+    ) -> Result<Self, Self::Rejection> {
+        let repo = PgRepository::from_pool(&state.pool)
+            .await?
+            .with_token_hasher(state.token_hasher.clone());
+        Ok(repo.boxed())
+    }
+}
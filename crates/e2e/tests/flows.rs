@@ -0,0 +1,50 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use mas_e2e::{setup, TestServer};
+use sqlx::PgPool;
+
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_register_login_and_authorize(pool: PgPool) {
+    setup();
+    let server = TestServer::start(pool).await.unwrap();
+
+    // Register a new user, and verify their email address
+    let browser = server.browser();
+    let verify_path = browser
+        .register("alice", "alice@example.com", "hunter2")
+        .await;
+    let code = server
+        .mail_sink()
+        .wait_for_verification_code("alice@example.com", 0)
+        .await;
+    browser.verify_email(&verify_path, &code).await;
+
+    // Log in from a new browser
+    let browser = server.browser();
+    browser.login("alice", "hunter2").await;
+
+    // Go through the authorization code flow with a new client
+    let client = server.register_client().await;
+    let authorization_path = server.authorization_path(&client, "openid", "some-state");
+    let callback = browser.authorize(&authorization_path).await;
+    assert_eq!(callback.origin(), client.redirect_uri.origin());
+    assert_eq!(callback.path(), client.redirect_uri.path());
+
+    let params: Vec<(String, String)> = callback.query_pairs().into_owned().collect();
+    let code = params
+        .iter()
+        .find_map(|(key, value)| (key == "code").then_some(value))
+        .expect("No code in the callback");
+    assert!(params
+        .iter()
+        .any(|(key, value)| key == "state" && value == "some-state"));
+
+    // Exchange the code and introspect the token
+    let tokens = server.exchange_code(&client, code).await;
+    let introspection = server.introspect(&client, &tokens.access_token).await;
+    assert!(introspection.active);
+    assert_eq!(introspection.username.as_deref(), Some("alice"));
+}
@@ -12,7 +12,14 @@ mod mailer;
 mod transport;
 
 pub use lettre::{
-    message::Mailbox, transport::smtp::authentication::Credentials as SmtpCredentials, Address,
+    message::{
+        dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+        Mailbox,
+    },
+    transport::smtp::{
+        authentication::Credentials as SmtpCredentials, PoolConfig as SmtpPoolConfig,
+    },
+    Address,
 };
 pub use mas_templates::EmailVerificationContext;
 
@@ -6,9 +6,13 @@
 
 //! Send emails to users
 
+use std::{sync::Arc, time::Duration};
+
+use governor::{clock::Clock as _, DefaultKeyedRateLimiter, Quota, RateLimiter};
 use lettre::{
-    message::{Mailbox, MessageBuilder, MultiPart},
-    AsyncTransport, Message,
+    address::Envelope,
+    message::{dkim::DkimConfig, header::ContentType, Mailbox, MessageBuilder, MultiPart},
+    Address, AsyncTransport, Message,
 };
 use mas_templates::{EmailRecoveryContext, EmailVerificationContext, Templates, WithLanguage};
 use thiserror::Error;
@@ -22,6 +26,8 @@ pub struct Mailer {
     transport: MailTransport,
     from: Mailbox,
     reply_to: Mailbox,
+    dkim: Option<Arc<DkimConfig>>,
+    domain_limiter: Option<Arc<DefaultKeyedRateLimiter<String>>>,
 }
 
 #[derive(Debug, Error)]
@@ -46,9 +52,33 @@ impl Mailer {
             transport,
             from,
             reply_to,
+            dkim: None,
+            domain_limiter: None,
         }
     }
 
+    /// Sign the rendered emails with DKIM
+    #[must_use]
+    pub fn with_dkim(mut self, config: DkimConfig) -> Self {
+        self.dkim = Some(Arc::new(config));
+        self
+    }
+
+    /// Limit how many emails are sent to each recipient domain
+    #[must_use]
+    pub fn with_domain_rate_limit(mut self, quota: Quota) -> Self {
+        self.domain_limiter = Some(Arc::new(RateLimiter::keyed(quota)));
+        self
+    }
+
+    fn format(&self, mut message: Message) -> Vec<u8> {
+        if let Some(dkim) = &self.dkim {
+            message.sign(dkim);
+        }
+
+        message.formatted()
+    }
+
     fn base_message(&self) -> MessageBuilder {
         Message::builder()
             .from(self.from.clone())
@@ -99,13 +129,15 @@ impl Mailer {
         Ok(message)
     }
 
-    /// Send the verification email to a user
+    /// Render the verification email to a user, to be queued for sending
+    ///
+    /// Returns the fully formatted message
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the email failed rendering or failed sending
+    /// Will return `Err` if the email failed rendering
     #[tracing::instrument(
-        name = "email.verification.send",
+        name = "email.verification.render",
         skip_all,
         fields(
             email.to = %to,
@@ -116,23 +148,24 @@ impl Mailer {
         ),
         err,
     )]
-    pub async fn send_verification_email(
+    pub fn render_verification_email(
         &self,
         to: Mailbox,
         context: &WithLanguage<EmailVerificationContext>,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<u8>, Error> {
         let message = self.prepare_verification_email(to, context)?;
-        self.transport.send(message).await?;
-        Ok(())
+        Ok(self.format(message))
     }
 
-    /// Send the recovery email to a user
+    /// Render the recovery email to a user, to be queued for sending
+    ///
+    /// Returns the fully formatted message
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the email failed rendering or failed sending
+    /// Will return `Err` if the email failed rendering
     #[tracing::instrument(
-        name = "email.recovery.send",
+        name = "email.recovery.render",
         skip_all,
         fields(
             email.to = %to,
@@ -142,16 +175,85 @@ impl Mailer {
         ),
         err,
     )]
-    pub async fn send_recovery_email(
+    pub fn render_recovery_email(
         &self,
         to: Mailbox,
         context: &WithLanguage<EmailRecoveryContext>,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<u8>, Error> {
         let message = self.prepare_recovery_email(to, context)?;
-        self.transport.send(message).await?;
+        Ok(self.format(message))
+    }
+
+    /// Send a message previously rendered by one of the `render_*` methods
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed sending
+    #[tracing::instrument(
+        name = "email.send",
+        skip_all,
+        fields(email.to = %to),
+        err,
+    )]
+    pub async fn send_rendered(&self, to: &Address, message: &[u8]) -> Result<(), Error> {
+        let envelope = Envelope::new(Some(self.from.email.clone()), vec![to.clone()])?;
+        self.transport.send_raw(&envelope, message).await?;
+        Ok(())
+    }
+
+    /// Send a test email to the given address right away, without going
+    /// through the queue
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering or sending
+    #[tracing::instrument(
+        name = "email.send_test",
+        skip_all,
+        fields(email.to = %to),
+        err,
+    )]
+    pub async fn send_test_email(&self, to: Mailbox) -> Result<(), Error> {
+        let envelope = Envelope::new(Some(self.from.email.clone()), vec![to.email.clone()])?;
+        let message = self
+            .base_message()
+            .subject("Test email from the Matrix Authentication Service")
+            .to(to)
+            .header(ContentType::TEXT_PLAIN)
+            .body(String::from(
+                "This is a test email, sent to check the email configuration of the Matrix Authentication Service.\n",
+            ))?;
+
+        self.transport
+            .send_raw(&envelope, &self.format(message))
+            .await?;
         Ok(())
     }
 
+    /// Check whether an email can be sent to the given address right now,
+    /// given the rate limit on its domain
+    ///
+    /// Returns how long to wait before sending it if the domain received too
+    /// many emails recently. Calling this counts as sending an email if it
+    /// returns `None`.
+    #[must_use]
+    pub fn throttle_delay(&self, to: &Address) -> Option<Duration> {
+        let limiter = self.domain_limiter.as_ref()?;
+        let domain = to.domain().to_ascii_lowercase();
+        limiter
+            .check_key(&domain)
+            .err()
+            .map(|not_until| not_until.wait_time_from(limiter.clock().now()))
+    }
+
+    /// Forget about the domains which did not receive emails recently, to
+    /// keep the rate limiter small
+    pub fn retain_recent_rate_limits(&self) {
+        if let Some(limiter) = &self.domain_limiter {
+            limiter.retain_recent();
+        }
+    }
+
     /// Test the connetion to the mail server
     ///
     /// # Errors
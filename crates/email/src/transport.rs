@@ -13,11 +13,14 @@ use lettre::{
     address::Envelope,
     transport::{
         sendmail::AsyncSendmailTransport,
-        smtp::{authentication::Credentials, AsyncSmtpTransport},
+        smtp::{authentication::Credentials, AsyncSmtpTransport, PoolConfig},
     },
     AsyncTransport, Tokio1Executor,
 };
+use mas_http::RequestBuilderExt as _;
+use serde::Serialize;
 use thiserror::Error;
+use url::Url;
 
 /// Encryption mode to use
 #[derive(Debug, Clone, Copy)]
@@ -40,6 +43,22 @@ enum TransportInner {
     Blackhole,
     Smtp(AsyncSmtpTransport<Tokio1Executor>),
     Sendmail(AsyncSendmailTransport<Tokio1Executor>),
+    Http(HttpTransport),
+}
+
+/// Delegates the delivery of emails to an external HTTP API
+struct HttpTransport {
+    http_client: reqwest::Client,
+    endpoint: Url,
+    token: Option<String>,
+}
+
+/// The body of the requests sent by the HTTP transport
+#[derive(Serialize)]
+struct HttpTransportRequest<'a> {
+    from: Option<&'a str>,
+    to: Vec<&'a str>,
+    message: &'a str,
 }
 
 impl Transport {
@@ -64,6 +83,7 @@ impl Transport {
         hostname: &str,
         port: Option<NonZeroU16>,
         credentials: Option<Credentials>,
+        pool_config: PoolConfig,
     ) -> Result<Self, lettre::transport::smtp::Error> {
         let mut t = match mode {
             SmtpMode::Plain => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(hostname),
@@ -79,6 +99,9 @@ impl Transport {
             t = t.port(port.into());
         }
 
+        // Connections are kept open and reused across emails
+        t = t.pool_config(pool_config);
+
         Ok(Self::new(TransportInner::Smtp(t.build())))
     }
 
@@ -92,6 +115,19 @@ impl Transport {
         };
         Self::new(TransportInner::Sendmail(transport))
     }
+
+    /// Construct a transport delegating the delivery of emails to an HTTP API
+    ///
+    /// The rendered message is sent in a JSON body, along with its envelope,
+    /// for an existing email service to deliver it.
+    #[must_use]
+    pub fn http(http_client: reqwest::Client, endpoint: Url, token: Option<String>) -> Self {
+        Self::new(TransportInner::Http(HttpTransport {
+            http_client,
+            endpoint,
+            token,
+        }))
+    }
 }
 
 impl Transport {
@@ -106,7 +142,7 @@ impl Transport {
             TransportInner::Smtp(t) => {
                 t.test_connection().await?;
             }
-            TransportInner::Blackhole | TransportInner::Sendmail(_) => {}
+            TransportInner::Blackhole | TransportInner::Sendmail(_) | TransportInner::Http(_) => {}
         }
 
         Ok(())
@@ -124,6 +160,7 @@ impl Default for TransportInner {
 pub enum Error {
     Smtp(#[from] lettre::transport::smtp::Error),
     Sendmail(#[from] lettre::transport::sendmail::Error),
+    Http(#[from] reqwest::Error),
 }
 
 #[async_trait]
@@ -144,8 +181,31 @@ impl AsyncTransport for Transport {
             TransportInner::Sendmail(t) => {
                 t.send_raw(envelope, email).await?;
             }
+            TransportInner::Http(t) => {
+                t.send_raw(envelope, email).await?;
+            }
         };
 
         Ok(())
     }
 }
+
+impl HttpTransport {
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<(), reqwest::Error> {
+        // Rendered messages only contain ASCII, as non-ASCII content is encoded
+        let message = String::from_utf8_lossy(email);
+        let body = HttpTransportRequest {
+            from: envelope.from().map(AsRef::as_ref),
+            to: envelope.to().iter().map(AsRef::as_ref).collect(),
+            message: &message,
+        };
+
+        let mut request = self.http_client.post(self.endpoint.clone()).json(&body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        request.send_traced().await?.error_for_status()?;
+        Ok(())
+    }
+}
@@ -8,6 +8,7 @@ use std::net::IpAddr;
 
 use mas_data_model::{BrowserSession, CompatSession, Session};
 use mas_storage::Clock;
+use mas_tower::AccessLogContext;
 
 use crate::activity_tracker::ActivityTracker;
 
@@ -16,13 +17,26 @@ use crate::activity_tracker::ActivityTracker;
 pub struct Bound {
     tracker: ActivityTracker,
     ip: Option<IpAddr>,
+    access_log: Option<AccessLogContext>,
 }
 
 impl Bound {
     /// Create a new bound activity tracker.
     #[must_use]
     pub fn new(tracker: ActivityTracker, ip: Option<IpAddr>) -> Self {
-        Self { tracker, ip }
+        Self {
+            tracker,
+            ip,
+            access_log: None,
+        }
+    }
+
+    /// Attach the access log context of the current request, so that the
+    /// sessions recorded by this tracker end up in the access log.
+    #[must_use]
+    pub fn with_access_log(mut self, access_log: Option<AccessLogContext>) -> Self {
+        self.access_log = access_log;
+        self
     }
 
     /// Get the IP address bound to this activity tracker.
@@ -33,6 +47,13 @@ impl Bound {
 
     /// Record activity in an OAuth 2.0 session.
     pub async fn record_oauth2_session(&self, clock: &dyn Clock, session: &Session) {
+        if let Some(access_log) = &self.access_log {
+            access_log.set_session_id(&session.id);
+            if let Some(user_id) = session.user_id {
+                access_log.set_user_id(&user_id);
+            }
+        }
+
         self.tracker
             .record_oauth2_session(clock, session, self.ip)
             .await;
@@ -40,6 +61,11 @@ impl Bound {
 
     /// Record activity in a compatibility session.
     pub async fn record_compat_session(&self, clock: &dyn Clock, session: &CompatSession) {
+        if let Some(access_log) = &self.access_log {
+            access_log.set_session_id(&session.id);
+            access_log.set_user_id(&session.user_id);
+        }
+
         self.tracker
             .record_compat_session(clock, session, self.ip)
             .await;
@@ -47,6 +73,11 @@ impl Bound {
 
     /// Record activity in a browser session.
     pub async fn record_browser_session(&self, clock: &dyn Clock, session: &BrowserSession) {
+        if let Some(access_log) = &self.access_log {
+            access_log.set_session_id(&session.id);
+            access_log.set_user_id(&session.user.id);
+        }
+
         self.tracker
             .record_browser_session(clock, session, self.ip)
             .await;
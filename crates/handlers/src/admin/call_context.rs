@@ -8,7 +8,7 @@ use std::convert::Infallible;
 
 use aide::OperationIo;
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRef, FromRequestParts},
     response::{IntoResponse, Response},
     Json,
 };
@@ -19,11 +19,18 @@ use mas_data_model::{Session, User};
 use mas_storage::{BoxClock, BoxRepository, RepositoryError};
 use ulid::Ulid;
 
-use super::response::ErrorResponse;
-use crate::BoundActivityTracker;
+use super::{
+    response::ErrorResponse,
+    role::{AdminRole, ADMIN_SCOPE, HELPDESK_SCOPE},
+};
+use crate::{BoundActivityTracker, NetworkPolicy, NetworkPolicyError, NetworkScope, RequestOrigin};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Rejection {
+    /// The request doesn't come from a network allowed to use the admin API
+    #[error("Access from this network is not allowed")]
+    NetworkPolicy(#[from] NetworkPolicyError),
+
     /// The authorization header is missing
     #[error("Missing authorization header")]
     MissingAuthorizationHeader,
@@ -64,14 +71,21 @@ pub enum Rejection {
     #[error("Failed to load user {0}")]
     LoadUser(Ulid),
 
-    /// The session does not have the `urn:mas:admin` scope
-    #[error("Missing urn:mas:admin scope")]
+    /// The session has neither the `urn:mas:admin` nor the
+    /// `urn:mas:admin:helpdesk` scope
+    #[error("Missing {ADMIN_SCOPE} scope")]
     MissingScope,
+
+    /// The session only has the helpdesk role, which is not allowed to call
+    /// this endpoint
+    #[error("The {HELPDESK_SCOPE} scope doesn't allow this operation")]
+    InsufficientRole,
 }
 
 impl Rejection {
     fn status_code(&self) -> StatusCode {
         match self {
+            Self::NetworkPolicy(_) | Self::InsufficientRole => StatusCode::FORBIDDEN,
             Self::InvalidAuthorizationHeader | Self::MissingAuthorizationHeader => {
                 StatusCode::BAD_REQUEST
             }
@@ -97,6 +111,9 @@ impl IntoResponse for Rejection {
 ///
 /// Because we need to load the database repository and the clock, we keep them
 /// in the context to avoid creating two instances for each request.
+///
+/// This requires the full admin role. Endpoints which the helpdesk role can
+/// call use the [`HelpdeskCallContext`] extractor instead.
 #[non_exhaustive]
 #[derive(OperationIo)]
 #[aide(input)]
@@ -105,24 +122,34 @@ pub struct CallContext {
     pub clock: BoxClock,
     pub user: Option<User>,
     pub session: Session,
+    pub role: AdminRole,
 }
 
-#[async_trait::async_trait]
-impl<S> FromRequestParts<S> for CallContext
-where
-    S: Send + Sync,
-    BoundActivityTracker: FromRequestParts<S, Rejection = Infallible>,
-    BoxRepository: FromRequestParts<S>,
-    BoxClock: FromRequestParts<S, Rejection = Infallible>,
-    <BoxRepository as FromRequestParts<S>>::Rejection:
-        Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
-{
-    type Rejection = Rejection;
+/// An extractor which authorizes the request, allowing both the full admin
+/// and the helpdesk roles
+#[derive(OperationIo)]
+#[aide(input)]
+pub struct HelpdeskCallContext(pub CallContext);
 
-    async fn from_request_parts(
+impl CallContext {
+    async fn authorize<S>(
         parts: &mut axum::http::request::Parts,
         state: &S,
-    ) -> Result<Self, Self::Rejection> {
+    ) -> Result<Self, Rejection>
+    where
+        S: Send + Sync,
+        NetworkPolicy: FromRef<S>,
+        RequestOrigin: FromRequestParts<S, Rejection = Infallible>,
+        BoundActivityTracker: FromRequestParts<S, Rejection = Infallible>,
+        BoxRepository: FromRequestParts<S>,
+        BoxClock: FromRequestParts<S, Rejection = Infallible>,
+        <BoxRepository as FromRequestParts<S>>::Rejection:
+            Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        // Check where the request comes from before anything else
+        let Ok(origin) = RequestOrigin::from_request_parts(parts, state).await;
+        NetworkPolicy::from_ref(state).check(NetworkScope::AdminApi, &origin)?;
+
         let Ok(activity_tracker) = BoundActivityTracker::from_request_parts(parts, state).await;
         let Ok(clock) = BoxClock::from_request_parts(parts, state).await;
 
@@ -193,17 +220,66 @@ where
             return Err(Rejection::TokenExpired);
         }
 
-        // For now, we only check that the session has the admin scope
-        // Later we might want to check other route-specific scopes
-        if !session.scope.contains("urn:mas:admin") {
-            return Err(Rejection::MissingScope);
-        }
+        // Check that the session has one of the admin roles. Whether the role
+        // is allowed on this route is checked by the extractors
+        let role = AdminRole::from_scope(&session.scope).ok_or(Rejection::MissingScope)?;
 
         Ok(Self {
             repo,
             clock,
             user,
             session,
+            role,
         })
     }
 }
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for CallContext
+where
+    S: Send + Sync,
+    NetworkPolicy: FromRef<S>,
+    RequestOrigin: FromRequestParts<S, Rejection = Infallible>,
+    BoundActivityTracker: FromRequestParts<S, Rejection = Infallible>,
+    BoxRepository: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S, Rejection = Infallible>,
+    <BoxRepository as FromRequestParts<S>>::Rejection:
+        Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let context = Self::authorize(parts, state).await?;
+
+        if !context.role.is_admin() {
+            return Err(Rejection::InsufficientRole);
+        }
+
+        Ok(context)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for HelpdeskCallContext
+where
+    S: Send + Sync,
+    NetworkPolicy: FromRef<S>,
+    RequestOrigin: FromRequestParts<S, Rejection = Infallible>,
+    BoundActivityTracker: FromRequestParts<S, Rejection = Infallible>,
+    BoxRepository: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S, Rejection = Infallible>,
+    <BoxRepository as FromRequestParts<S>>::Rejection:
+        Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        CallContext::authorize(parts, state).await.map(Self)
+    }
+}
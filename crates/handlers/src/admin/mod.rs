@@ -31,10 +31,15 @@ mod call_context;
 mod model;
 mod params;
 mod response;
+pub(crate) mod role;
 mod schema;
+pub(crate) mod snapshot;
 mod v1;
 
-use self::call_context::CallContext;
+use self::{
+    call_context::{CallContext, HelpdeskCallContext},
+    role::{ADMIN_SCOPE, HELPDESK_SCOPE},
+};
 use crate::passwords::PasswordManager;
 
 pub fn router<S>() -> (OpenApi, Router<S>)
@@ -44,6 +49,7 @@ where
     PasswordManager: FromRef<S>,
     BoxRng: FromRequestParts<S>,
     CallContext: FromRequestParts<S>,
+    HelpdeskCallContext: FromRequestParts<S>,
     Templates: FromRef<S>,
     UrlBuilder: FromRef<S>,
 {
@@ -61,6 +67,11 @@ where
                     description: Some("Manage OAuth2 sessions".to_owned()),
                     ..Tag::default()
                 })
+                .tag(Tag {
+                    name: "stats".to_owned(),
+                    description: Some("Statistics about the users".to_owned()),
+                    ..Tag::default()
+                })
                 .tag(Tag {
                     name: "user".to_owned(),
                     description: Some("Manage users".to_owned()),
@@ -73,19 +84,33 @@ where
                             client_credentials: Some(OAuth2Flow::ClientCredentials {
                                 refresh_url: Some(OAuth2TokenEndpoint::PATH.to_owned()),
                                 token_url: OAuth2TokenEndpoint::PATH.to_owned(),
-                                scopes: IndexMap::from([(
-                                    "urn:mas:admin".to_owned(),
-                                    "Grant access to the admin API".to_owned(),
-                                )]),
+                                scopes: IndexMap::from([
+                                    (
+                                        ADMIN_SCOPE.to_owned(),
+                                        "Grant access to the admin API".to_owned(),
+                                    ),
+                                    (
+                                        HELPDESK_SCOPE.to_owned(),
+                                        "Grant access to the helpdesk subset of the admin API"
+                                            .to_owned(),
+                                    ),
+                                ]),
                             }),
                             authorization_code: Some(OAuth2Flow::AuthorizationCode {
                                 authorization_url: OAuth2AuthorizationEndpoint::PATH.to_owned(),
                                 refresh_url: Some(OAuth2TokenEndpoint::PATH.to_owned()),
                                 token_url: OAuth2TokenEndpoint::PATH.to_owned(),
-                                scopes: IndexMap::from([(
-                                    "urn:mas:admin".to_owned(),
-                                    "Grant access to the admin API".to_owned(),
-                                )]),
+                                scopes: IndexMap::from([
+                                    (
+                                        ADMIN_SCOPE.to_owned(),
+                                        "Grant access to the admin API".to_owned(),
+                                    ),
+                                    (
+                                        HELPDESK_SCOPE.to_owned(),
+                                        "Grant access to the helpdesk subset of the admin API"
+                                            .to_owned(),
+                                    ),
+                                ]),
                             }),
                             implicit: None,
                             password: None,
@@ -94,7 +119,7 @@ where
                         extensions: IndexMap::default(),
                     },
                 )
-                .security_requirement_scopes("oauth2", ["urn:mas:admin"])
+                .security_requirement_scopes("oauth2", [ADMIN_SCOPE])
         });
 
     let router = router
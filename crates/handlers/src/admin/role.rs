@@ -0,0 +1,47 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use oauth2_types::scope::Scope;
+
+/// The scope granting full access to the admin APIs
+pub const ADMIN_SCOPE: &str = "urn:mas:admin";
+
+/// The scope granting the restricted helpdesk role
+pub const HELPDESK_SCOPE: &str = "urn:mas:admin:helpdesk";
+
+/// The administrative role granted to a session by its scope
+///
+/// This is shared between the admin REST API and the GraphQL API, so that the
+/// same session can do the same things on both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminRole {
+    /// Full access to the admin APIs
+    Admin,
+
+    /// Restricted access, limited to looking up users, setting their password
+    /// and unlocking them. It can't read sessions, nor lock, deactivate or
+    /// otherwise modify users.
+    Helpdesk,
+}
+
+impl AdminRole {
+    /// Get the role granted by the given scope, if any
+    #[must_use]
+    pub fn from_scope(scope: &Scope) -> Option<Self> {
+        if scope.contains(ADMIN_SCOPE) {
+            Some(Self::Admin)
+        } else if scope.contains(HELPDESK_SCOPE) {
+            Some(Self::Helpdesk)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this role has full access to the admin APIs
+    #[must_use]
+    pub fn is_admin(self) -> bool {
+        self == Self::Admin
+    }
+}
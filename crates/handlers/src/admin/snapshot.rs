@@ -0,0 +1,342 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Point-in-time snapshot of everything granting access to a user account,
+//! for security incident investigations
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuthenticationMethod, User};
+use mas_storage::{
+    compat::CompatSessionFilter, oauth2::OAuth2SessionFilter,
+    upstream_oauth2::UpstreamOAuthLinkFilter, user::BrowserSessionFilter, BoxRepository, Clock,
+    Pagination, RepositoryError,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+use ulid::Ulid;
+
+/// How many items are fetched at once when listing sessions and links
+const PAGE_SIZE: usize = 100;
+
+/// A snapshot of the sessions, tokens and upstream links of a user
+#[derive(Serialize, JsonSchema)]
+pub struct UserSnapshot {
+    /// When the snapshot was taken
+    generated_at: DateTime<Utc>,
+
+    /// The user
+    user: SnapshotUser,
+
+    /// The email addresses of the user
+    emails: Vec<SnapshotEmail>,
+
+    /// The active browser sessions of the user
+    browser_sessions: Vec<SnapshotBrowserSession>,
+
+    /// The active OAuth 2.0 sessions of the user, with their tokens
+    oauth2_sessions: Vec<SnapshotOAuth2Session>,
+
+    /// The active compatibility sessions of the user, with their tokens
+    compat_sessions: Vec<SnapshotCompatSession>,
+
+    /// The links to upstream identity providers of the user
+    upstream_links: Vec<SnapshotUpstreamLink>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotUser {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    username: String,
+    created_at: DateTime<Utc>,
+    locked_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    admin: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotEmail {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    email: String,
+    created_at: DateTime<Utc>,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// A token, without its value
+///
+/// Only hashes of the OAuth 2.0 tokens are saved, so their value isn't known.
+#[derive(Serialize, JsonSchema)]
+struct SnapshotToken {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotAuthentication {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    created_at: DateTime<Utc>,
+
+    /// Either `password`, `upstream_oauth2` or `unknown`
+    method: &'static str,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotBrowserSession {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    created_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    last_active_at: Option<DateTime<Utc>>,
+    last_active_ip: Option<IpAddr>,
+
+    /// The last time the user authenticated in this session
+    last_authentication: Option<SnapshotAuthentication>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotOAuth2Session {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    #[schemars(with = "super::schema::Ulid")]
+    client_id: Ulid,
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    user_session_id: Option<Ulid>,
+    scope: String,
+    created_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    last_active_at: Option<DateTime<Utc>>,
+    last_active_ip: Option<IpAddr>,
+    access_tokens: Vec<SnapshotToken>,
+    refresh_tokens: Vec<SnapshotToken>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotCompatSession {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    device_id: String,
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    user_session_id: Option<Ulid>,
+    created_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    last_active_at: Option<DateTime<Utc>>,
+    last_active_ip: Option<IpAddr>,
+    access_tokens: Vec<SnapshotToken>,
+    refresh_tokens: Vec<SnapshotToken>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SnapshotUpstreamLink {
+    #[schemars(with = "super::schema::Ulid")]
+    id: Ulid,
+    #[schemars(with = "super::schema::Ulid")]
+    provider_id: Ulid,
+    subject: String,
+    human_account_name: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// Take a snapshot of the sessions, tokens and upstream links of a user
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+#[allow(clippy::too_many_lines)]
+pub async fn user_snapshot(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    user: &User,
+) -> Result<UserSnapshot, RepositoryError> {
+    let emails = repo
+        .user_email()
+        .all(user)
+        .await?
+        .into_iter()
+        .map(|email| SnapshotEmail {
+            id: email.id,
+            email: email.email,
+            created_at: email.created_at,
+            confirmed_at: email.confirmed_at,
+        })
+        .collect();
+
+    let mut browser_sessions = Vec::new();
+    let filter = BrowserSessionFilter::new().for_user(user).active_only();
+    let mut pagination = Pagination::first(PAGE_SIZE);
+    loop {
+        let page = repo.browser_session().list(filter, pagination).await?;
+        for session in page.edges {
+            pagination = pagination.after(session.id);
+            let last_authentication = repo
+                .browser_session()
+                .get_last_authentication(&session)
+                .await?
+                .map(|authentication| SnapshotAuthentication {
+                    id: authentication.id,
+                    created_at: authentication.created_at,
+                    method: match authentication.authentication_method {
+                        AuthenticationMethod::Password { .. } => "password",
+                        AuthenticationMethod::UpstreamOAuth2 { .. } => "upstream_oauth2",
+                        AuthenticationMethod::ClientCertificate { .. } => "client_certificate",
+                        AuthenticationMethod::Unknown => "unknown",
+                    },
+                });
+
+            browser_sessions.push(SnapshotBrowserSession {
+                id: session.id,
+                created_at: session.created_at,
+                user_agent: session.user_agent.map(|ua| ua.raw),
+                last_active_at: session.last_active_at,
+                last_active_ip: session.last_active_ip,
+                last_authentication,
+            });
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let mut oauth2_sessions = Vec::new();
+    let filter = OAuth2SessionFilter::new().for_user(user).active_only();
+    let mut pagination = Pagination::first(PAGE_SIZE);
+    loop {
+        let page = repo.oauth2_session().list(filter, pagination).await?;
+        for session in page.edges {
+            pagination = pagination.after(session.id);
+            let access_tokens = repo
+                .oauth2_access_token()
+                .list_active(clock, &session)
+                .await?
+                .into_iter()
+                .map(|token| SnapshotToken {
+                    id: token.id,
+                    created_at: token.created_at,
+                    expires_at: token.expires_at,
+                })
+                .collect();
+            let refresh_tokens = repo
+                .oauth2_refresh_token()
+                .list_active(&session)
+                .await?
+                .into_iter()
+                .map(|token| SnapshotToken {
+                    id: token.id,
+                    created_at: token.created_at,
+                    expires_at: None,
+                })
+                .collect();
+
+            oauth2_sessions.push(SnapshotOAuth2Session {
+                id: session.id,
+                client_id: session.client_id,
+                user_session_id: session.user_session_id,
+                scope: session.scope.to_string(),
+                created_at: session.created_at,
+                user_agent: session.user_agent.map(|ua| ua.raw),
+                last_active_at: session.last_active_at,
+                last_active_ip: session.last_active_ip,
+                access_tokens,
+                refresh_tokens,
+            });
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let mut compat_sessions = Vec::new();
+    let filter = CompatSessionFilter::new().for_user(user).active_only();
+    let mut pagination = Pagination::first(PAGE_SIZE);
+    loop {
+        let page = repo.compat_session().list(filter, pagination).await?;
+        for (session, _sso_login) in page.edges {
+            pagination = pagination.after(session.id);
+            let access_tokens = repo
+                .compat_access_token()
+                .list_active(clock, &session)
+                .await?
+                .into_iter()
+                .map(|token| SnapshotToken {
+                    id: token.id,
+                    created_at: token.created_at,
+                    expires_at: token.expires_at,
+                })
+                .collect();
+            let refresh_tokens = repo
+                .compat_refresh_token()
+                .list_active(&session)
+                .await?
+                .into_iter()
+                .map(|token| SnapshotToken {
+                    id: token.id,
+                    created_at: token.created_at,
+                    expires_at: None,
+                })
+                .collect();
+
+            compat_sessions.push(SnapshotCompatSession {
+                id: session.id,
+                device_id: session.device.as_str().to_owned(),
+                user_session_id: session.user_session_id,
+                created_at: session.created_at,
+                user_agent: session.user_agent.map(|ua| ua.raw),
+                last_active_at: session.last_active_at,
+                last_active_ip: session.last_active_ip,
+                access_tokens,
+                refresh_tokens,
+            });
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let mut upstream_links = Vec::new();
+    let filter = UpstreamOAuthLinkFilter::new().for_user(user);
+    let mut pagination = Pagination::first(PAGE_SIZE);
+    loop {
+        let page = repo.upstream_oauth_link().list(filter, pagination).await?;
+        for link in page.edges {
+            pagination = pagination.after(link.id);
+            upstream_links.push(SnapshotUpstreamLink {
+                id: link.id,
+                provider_id: link.provider_id,
+                subject: link.subject,
+                human_account_name: link.human_account_name,
+                created_at: link.created_at,
+            });
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    Ok(UserSnapshot {
+        generated_at: clock.now(),
+        user: SnapshotUser {
+            id: user.id,
+            username: user.username.clone(),
+            created_at: user.created_at,
+            locked_at: user.locked_at,
+            deleted_at: user.deleted_at,
+            admin: user.can_request_admin,
+        },
+        emails,
+        browser_sessions,
+        oauth2_sessions,
+        compat_sessions,
+        upstream_links,
+    })
+}
@@ -12,10 +12,11 @@ use axum::extract::{FromRef, FromRequestParts};
 use mas_matrix::BoxHomeserverConnection;
 use mas_storage::BoxRng;
 
-use super::call_context::CallContext;
+use super::call_context::{CallContext, HelpdeskCallContext};
 use crate::passwords::PasswordManager;
 
 mod oauth2_sessions;
+mod stats;
 mod users;
 
 pub fn router<S>() -> ApiRouter<S>
@@ -25,6 +26,7 @@ where
     PasswordManager: FromRef<S>,
     BoxRng: FromRequestParts<S>,
     CallContext: FromRequestParts<S>,
+    HelpdeskCallContext: FromRequestParts<S>,
 {
     ApiRouter::<S>::new()
         .api_route(
@@ -35,6 +37,10 @@ where
             "/oauth2-sessions/:id",
             get_with(self::oauth2_sessions::get, self::oauth2_sessions::get_doc),
         )
+        .api_route(
+            "/stats/registrations",
+            get_with(self::stats::registrations, self::stats::registrations_doc),
+        )
         .api_route(
             "/users",
             get_with(self::users::list, self::users::list_doc)
@@ -48,6 +54,13 @@ where
             "/users/:id/set-password",
             post_with(self::users::set_password, self::users::set_password_doc),
         )
+        .api_route(
+            "/users/:id/set-password-policy-class",
+            post_with(
+                self::users::set_password_policy_class,
+                self::users::set_password_policy_class_doc,
+            ),
+        )
         .api_route(
             "/users/by-username/:username",
             get_with(self::users::by_username, self::users::by_username_doc),
@@ -56,6 +69,17 @@ where
             "/users/:id/set-admin",
             post_with(self::users::set_admin, self::users::set_admin_doc),
         )
+        .api_route(
+            "/users/:id/set-session-limit",
+            post_with(
+                self::users::set_session_limit,
+                self::users::set_session_limit_doc,
+            ),
+        )
+        .api_route(
+            "/users/:id/set-timezone",
+            post_with(self::users::set_timezone, self::users::set_timezone_doc),
+        )
         .api_route(
             "/users/:id/deactivate",
             post_with(self::users::deactivate, self::users::deactivate_doc),
@@ -68,4 +92,8 @@ where
             "/users/:id/unlock",
             post_with(self::users::unlock, self::users::unlock_doc),
         )
+        .api_route(
+            "/users/:id/snapshot",
+            get_with(self::users::snapshot, self::users::snapshot_doc),
+        )
 }
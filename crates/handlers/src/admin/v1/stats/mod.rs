@@ -0,0 +1,8 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+mod registrations;
+
+pub use self::registrations::{doc as registrations_doc, handler as registrations};
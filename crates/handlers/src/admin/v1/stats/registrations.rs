@@ -0,0 +1,210 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+/// How far back registrations are counted by default
+const DEFAULT_PERIOD_DAYS: i64 = 30;
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Invalid query parameters")]
+    InvalidParams(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidParams(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "RegistrationStatsParams")]
+#[aide(input_with = "Query<Params>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct Params {
+    /// Only count the users registered after this date
+    ///
+    /// Defaults to 30 days ago.
+    since: Option<DateTime<Utc>>,
+}
+
+/// A number of registrations sharing the same value
+#[derive(Serialize, JsonSchema)]
+struct Count<T> {
+    value: T,
+    count: usize,
+}
+
+impl<T> From<(T, usize)> for Count<T> {
+    fn from((value, count): (T, usize)) -> Self {
+        Self { value, count }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(transparent)]
+struct UlidValue(#[schemars(with = "crate::admin::schema::Ulid")] Ulid);
+
+/// How many users registered since a given date, broken down by where their
+/// registration came from
+///
+/// Each breakdown is sorted by descending count, and keeps the 100 most common
+/// values.
+#[derive(Serialize, JsonSchema)]
+pub struct RegistrationStats {
+    /// The date since which registrations are counted
+    since: DateTime<Utc>,
+
+    /// The total number of registrations
+    total: usize,
+
+    /// Registrations per OAuth 2.0 client ID
+    by_client: Vec<Count<UlidValue>>,
+
+    /// Registrations per upstream OAuth 2.0 provider ID
+    by_upstream_provider: Vec<Count<UlidValue>>,
+
+    /// Registrations per invite token, from the `invite` parameter of the
+    /// registration link
+    by_invite_token: Vec<Count<String>>,
+
+    /// Registrations per referral, from the `ref` parameter of the registration
+    /// link
+    by_referral: Vec<Count<String>>,
+}
+
+impl RegistrationStats {
+    fn new(since: DateTime<Utc>, stats: mas_storage::user::RegistrationStats) -> Self {
+        let ids = |counts: Vec<(Ulid, usize)>| {
+            counts
+                .into_iter()
+                .map(|(id, count)| Count::from((UlidValue(id), count)))
+                .collect()
+        };
+        let values = |counts: Vec<(String, usize)>| counts.into_iter().map(Count::from).collect();
+
+        Self {
+            since,
+            total: stats.total,
+            by_client: ids(stats.by_client),
+            by_upstream_provider: ids(stats.by_upstream_provider),
+            by_invite_token: values(stats.by_invite_token),
+            by_referral: values(stats.by_referral),
+        }
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("registrationStats")
+        .summary("Count registrations by source")
+        .description("Counts the users registered since a given date, broken down by the client they registered for, the upstream provider they registered with, and the invite token and referral parameter of their registration link.")
+        .tag("stats")
+        .response_with::<200, Json<RegistrationStats>, _>(|t| {
+            t.description("Registrations by source")
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.stats.registrations", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo, clock, ..
+    }: CallContext,
+    params: Params,
+) -> Result<Json<RegistrationStats>, RouteError> {
+    let since = params
+        .since
+        .unwrap_or_else(|| clock.now() - Duration::try_days(DEFAULT_PERIOD_DAYS).unwrap());
+
+    let stats = repo.user().registration_stats(since).await?;
+
+    Ok(Json(RegistrationStats::new(since, stats)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::RegistrationMetadata;
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_registration_stats(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        for (username, referral) in [("alice", "spring"), ("bob", "spring"), ("charlie", "blog")] {
+            let user = repo
+                .user()
+                .add(&mut rng, &state.clock, username.to_owned())
+                .await
+                .unwrap();
+            let metadata = RegistrationMetadata {
+                referral: Some(referral.to_owned()),
+                ..RegistrationMetadata::default()
+            };
+            repo.user()
+                .set_registration_metadata(&user, &metadata)
+                .await
+                .unwrap();
+        }
+        // Users provisioned without metadata are still counted in the total
+        repo.user()
+            .add(&mut rng, &state.clock, "dave".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/stats/registrations")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+
+        assert_eq!(body["total"], 4);
+        assert_eq!(
+            body["by_referral"],
+            serde_json::json!([
+                { "value": "spring", "count": 2 },
+                { "value": "blog", "count": 1 },
+            ])
+        );
+        assert_eq!(body["by_client"], serde_json::json!([]));
+    }
+}
@@ -12,7 +12,7 @@ use serde::Deserialize;
 
 use crate::{
     admin::{
-        call_context::CallContext,
+        call_context::{CallContext, HelpdeskCallContext},
         model::User,
         response::{ErrorResponse, SingleResponse},
     },
@@ -67,7 +67,7 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.by_username", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    HelpdeskCallContext(CallContext { mut repo, .. }): HelpdeskCallContext,
     Path(UsernamePathParam { username }): Path<UsernamePathParam>,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
     let self_path = format!("/api/admin/v1/users/by-username/{username}");
@@ -11,7 +11,7 @@ use ulid::Ulid;
 
 use crate::{
     admin::{
-        call_context::CallContext,
+        call_context::{CallContext, HelpdeskCallContext},
         model::User,
         params::UlidPathParam,
         response::{ErrorResponse, SingleResponse},
@@ -60,7 +60,7 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.get", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    HelpdeskCallContext(CallContext { mut repo, .. }): HelpdeskCallContext,
     id: UlidPathParam,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
     let user = repo
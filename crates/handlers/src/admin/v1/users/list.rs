@@ -18,7 +18,7 @@ use serde::Deserialize;
 
 use crate::{
     admin::{
-        call_context::CallContext,
+        call_context::{CallContext, HelpdeskCallContext},
         model::{Resource, User},
         params::Pagination,
         response::{ErrorResponse, PaginatedResponse},
@@ -124,7 +124,7 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.list", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    HelpdeskCallContext(CallContext { mut repo, .. }): HelpdeskCallContext,
     Pagination(pagination): Pagination,
     params: FilterParams,
 ) -> Result<Json<PaginatedResponse<User>>, RouteError> {
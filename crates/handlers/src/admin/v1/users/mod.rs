@@ -12,6 +12,10 @@ mod list;
 mod lock;
 mod set_admin;
 mod set_password;
+mod set_password_policy_class;
+mod set_session_limit;
+mod set_timezone;
+mod snapshot;
 mod unlock;
 
 pub use self::{
@@ -23,5 +27,11 @@ pub use self::{
     lock::{doc as lock_doc, handler as lock},
     set_admin::{doc as set_admin_doc, handler as set_admin},
     set_password::{doc as set_password_doc, handler as set_password},
+    set_password_policy_class::{
+        doc as set_password_policy_class_doc, handler as set_password_policy_class,
+    },
+    set_session_limit::{doc as set_session_limit_doc, handler as set_session_limit},
+    set_timezone::{doc as set_timezone_doc, handler as set_timezone},
+    snapshot::{doc as snapshot_doc, handler as snapshot},
     unlock::{doc as unlock_doc, handler as unlock},
 };
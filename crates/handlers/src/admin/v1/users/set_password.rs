@@ -14,9 +14,13 @@ use ulid::Ulid;
 use zeroize::Zeroizing;
 
 use crate::{
-    admin::{call_context::CallContext, params::UlidPathParam, response::ErrorResponse},
+    admin::{
+        call_context::{CallContext, HelpdeskCallContext},
+        params::UlidPathParam,
+        response::ErrorResponse,
+    },
     impl_from_error_for_route,
-    passwords::PasswordManager,
+    passwords::{PasswordManager, PasswordValidation},
 };
 
 #[derive(Debug, thiserror::Error, OperationIo)]
@@ -28,6 +32,9 @@ pub enum RouteError {
     #[error("Password is too weak")]
     PasswordTooWeak,
 
+    #[error("Password was rejected by the password policy: {0}")]
+    PasswordRejected(String),
+
     #[error("Password auth is disabled")]
     PasswordAuthDisabled,
 
@@ -36,6 +43,12 @@ pub enum RouteError {
 
     #[error("User ID {0} not found")]
     NotFound(Ulid),
+
+    #[error("The helpdesk role can't set the password of a user who can request admin")]
+    AdminUser,
+
+    #[error("Only the admin role can skip the password check")]
+    SkipPasswordCheckNotAllowed,
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -45,8 +58,10 @@ impl IntoResponse for RouteError {
         let error = ErrorResponse::from_error(&self);
         let status = match self {
             Self::Internal(_) | Self::Password(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::PasswordAuthDisabled => StatusCode::FORBIDDEN,
-            Self::PasswordTooWeak => StatusCode::BAD_REQUEST,
+            Self::PasswordAuthDisabled | Self::AdminUser | Self::SkipPasswordCheckNotAllowed => {
+                StatusCode::FORBIDDEN
+            }
+            Self::PasswordTooWeak | Self::PasswordRejected(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
         };
         (status, Json(error)).into_response()
@@ -65,7 +80,8 @@ pub struct Request {
     #[schemars(example = "password_example")]
     password: String,
 
-    /// Skip the password complexity check
+    /// Skip the password complexity check, and the password validation
+    /// webhook. This requires the full admin role.
     skip_password_check: Option<bool>,
 }
 
@@ -81,8 +97,11 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
         })
         .response_with::<403, RouteError, _>(|t| {
             let response = ErrorResponse::from_error(&RouteError::PasswordAuthDisabled);
-            t.description("Password auth is disabled in the server configuration")
-                .example(response)
+            t.description(
+                "Password auth is disabled in the server configuration, or the helpdesk role \
+                 tried to skip the password check or to set the password of an admin",
+            )
+            .example(response)
         })
         .response_with::<404, RouteError, _>(|t| {
             let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
@@ -92,9 +111,12 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.set_password", skip_all, err)]
 pub async fn handler(
-    CallContext {
-        mut repo, clock, ..
-    }: CallContext,
+    HelpdeskCallContext(CallContext {
+        mut repo,
+        clock,
+        role,
+        ..
+    }): HelpdeskCallContext,
     NoApi(mut rng): NoApi<BoxRng>,
     State(password_manager): State<PasswordManager>,
     id: UlidPathParam,
@@ -110,14 +132,37 @@ pub async fn handler(
         .await?
         .ok_or(RouteError::NotFound(*id))?;
 
+    // The helpdesk could otherwise take over an admin account, and get the admin
+    // role through it
+    if !role.is_admin() && user.can_request_admin {
+        return Err(RouteError::AdminUser);
+    }
+
     let skip_password_check = params.skip_password_check.unwrap_or(false);
+    if skip_password_check && !role.is_admin() {
+        return Err(RouteError::SkipPasswordCheckNotAllowed);
+    }
     tracing::info!(skip_password_check, "skip_password_check");
-    if !skip_password_check
-        && !password_manager
-            .is_password_complex_enough(&params.password)
-            .unwrap_or(false)
-    {
-        return Err(RouteError::PasswordTooWeak);
+    if !skip_password_check {
+        let policy_class = repo.user().get_password_policy_class(&user).await?;
+        let validation = password_manager
+            .validate_new_password(
+                policy_class.as_deref(),
+                &user.username,
+                &params.password,
+                None,
+            )
+            .await
+            .map_err(RouteError::Password)?;
+        match validation {
+            PasswordValidation::Valid => {}
+            PasswordValidation::TooWeak => return Err(RouteError::PasswordTooWeak),
+            PasswordValidation::TooShort { .. } | PasswordValidation::Rejected { .. } => {
+                return Err(RouteError::PasswordRejected(
+                    validation.message().unwrap_or_default(),
+                ))
+            }
+        }
     }
 
     let password = Zeroizing::new(params.password.into_bytes());
@@ -253,6 +298,69 @@ mod tests {
             .unwrap();
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_helpdesk(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin:helpdesk").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let bob = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "bob".to_owned())
+            .await
+            .unwrap();
+        let bob = repo.user().set_can_request_admin(bob, true).await.unwrap();
+        repo.save().await.unwrap();
+
+        // The helpdesk can set the password of regular users
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-password", alice.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "password": "this is a good enough password",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+
+        // But it can't skip the password check
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-password", alice.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "password": "password",
+                "skip_password_check": true,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "Only the admin role can skip the password check"
+        );
+
+        // Nor set the password of users who can request admin
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-password", bob.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "password": "this is a good enough password",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "The helpdesk role can't set the password of a user who can request admin"
+        );
+
+        let mut repo = state.repository().await.unwrap();
+        let user_password = repo.user_password().active(&bob).await.unwrap();
+        assert!(user_password.is_none());
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unknown_user(pool: PgPool) {
         setup();
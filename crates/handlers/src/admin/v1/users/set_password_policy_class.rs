@@ -0,0 +1,209 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{extract::State, response::IntoResponse, Json};
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, User},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+    passwords::PasswordManager,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    NotFound(Ulid),
+
+    #[error("Unknown password policy class {0:?}")]
+    UnknownPolicyClass(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::UnknownPolicyClass(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/users/:id/set-password-policy-class` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "UserSetPasswordPolicyClassRequest")]
+pub struct Request {
+    /// The name of the password policy class of the user, as defined in the
+    /// `passwords.policy_classes` section of the configuration. Set to `null`
+    /// to apply the default password policy.
+    #[schemars(example = "policy_class_example")]
+    policy_class: Option<String>,
+}
+
+fn policy_class_example() -> &'static str {
+    "service"
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("userSetPasswordPolicyClass")
+        .summary("Set the password policy class of a user")
+        .description("The password policy class determines the minimum length and complexity of the new passwords of the user, and how long they can be used. This can be used to give service accounts long random passwords which never expire.")
+        .tag("user")
+        .response_with::<200, Json<SingleResponse<User>>, _>(|t| {
+            let [sample, ..] = User::samples();
+            let id = sample.id();
+            let response = SingleResponse::new(
+                sample,
+                format!("/api/admin/v1/users/{id}/set-password-policy-class"),
+            );
+            t.description("The password policy class of the user was set")
+                .example(response)
+        })
+        .response_with::<400, RouteError, _>(|t| {
+            let response =
+                ErrorResponse::from_error(&RouteError::UnknownPolicyClass("robots".to_owned()));
+            t.description("The password policy class is not configured")
+                .example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("User ID not found").example(response)
+        })
+}
+
+#[tracing::instrument(
+    name = "handler.admin.v1.users.set_password_policy_class",
+    skip_all,
+    err
+)]
+pub async fn handler(
+    CallContext {
+        mut repo, clock, ..
+    }: CallContext,
+    State(password_manager): State<PasswordManager>,
+    id: UlidPathParam,
+    Json(params): Json<Request>,
+) -> Result<Json<SingleResponse<User>>, RouteError> {
+    let id = *id;
+    let user = repo
+        .user()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    if let Some(policy_class) = params.policy_class.as_deref() {
+        if !password_manager.has_policy_class(policy_class) {
+            return Err(RouteError::UnknownPolicyClass(policy_class.to_owned()));
+        }
+    }
+
+    repo.user()
+        .set_password_policy_class(&clock, &user, params.policy_class.as_deref())
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        User::from(user),
+        format!("/api/admin/v1/users/{id}/set-password-policy-class"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hyper::{Request, StatusCode};
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::{
+        passwords::PasswordPolicy,
+        test_utils::{setup, RequestBuilderExt, ResponseExt, TestState},
+    };
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_set_password_policy_class(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.password_manager = state.password_manager.clone().with_policy_classes(
+            None,
+            HashMap::from([(
+                "service".to_owned(),
+                PasswordPolicy {
+                    minimum_complexity: 4,
+                    minimum_length: 32,
+                    max_age: None,
+                },
+            )]),
+        );
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let uri = format!("/api/admin/v1/users/{}/set-password-policy-class", user.id);
+        let request = Request::post(&uri).bearer(&token).json(serde_json::json!({
+            "policy_class": "service",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let policy_class = repo.user().get_password_policy_class(&user).await.unwrap();
+        assert_eq!(policy_class.as_deref(), Some("service"));
+        repo.save().await.unwrap();
+
+        // Unknown classes are rejected
+        let request = Request::post(&uri).bearer(&token).json(serde_json::json!({
+            "policy_class": "robots",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "Unknown password policy class \"robots\""
+        );
+
+        // Go back to the default policy
+        let request = Request::post(&uri).bearer(&token).json(serde_json::json!({
+            "policy_class": null,
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let policy_class = repo.user().get_password_policy_class(&user).await.unwrap();
+        assert_eq!(policy_class, None);
+        repo.save().await.unwrap();
+    }
+}
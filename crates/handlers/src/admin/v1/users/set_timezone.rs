@@ -0,0 +1,195 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{response::IntoResponse, Json};
+use chrono_tz::Tz;
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, User},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    NotFound(Ulid),
+
+    #[error("Unknown timezone {0:?}")]
+    UnknownTimezone(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::UnknownTimezone(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/users/:id/set-timezone` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "UserSetTimezoneRequest")]
+pub struct Request {
+    /// The IANA timezone of the user, like `Europe/Paris`, used to evaluate
+    /// time-based policies. Set to `null` to fall back to UTC.
+    #[schemars(example = "timezone_example")]
+    timezone: Option<String>,
+}
+
+fn timezone_example() -> &'static str {
+    "Europe/Paris"
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("userSetTimezone")
+        .summary("Set the timezone of a user")
+        .description(
+            "The timezone is used by the policy to evaluate time-based rules, like access windows.",
+        )
+        .tag("user")
+        .response_with::<200, Json<SingleResponse<User>>, _>(|t| {
+            let [sample, ..] = User::samples();
+            let id = sample.id();
+            let response =
+                SingleResponse::new(sample, format!("/api/admin/v1/users/{id}/set-timezone"));
+            t.description("The timezone of the user was set")
+                .example(response)
+        })
+        .response_with::<400, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::UnknownTimezone(
+                "Mars/Olympus_Mons".to_owned(),
+            ));
+            t.description("The timezone is not known").example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("User ID not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.users.set_timezone", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo, clock, ..
+    }: CallContext,
+    id: UlidPathParam,
+    Json(params): Json<Request>,
+) -> Result<Json<SingleResponse<User>>, RouteError> {
+    let id = *id;
+    let user = repo
+        .user()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    // Store the canonical name of the timezone
+    let timezone = params
+        .timezone
+        .map(|timezone| {
+            timezone
+                .parse::<Tz>()
+                .map(Tz::name)
+                .map_err(|_| RouteError::UnknownTimezone(timezone))
+        })
+        .transpose()?;
+
+    repo.user().set_timezone(&clock, &user, timezone).await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        User::from(user),
+        format!("/api/admin/v1/users/{id}/set-timezone"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_set_timezone(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-timezone", user.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "timezone": "Europe/Paris",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let timezone = repo.user().get_timezone(&user).await.unwrap();
+        assert_eq!(timezone.as_deref(), Some("Europe/Paris"));
+        repo.save().await.unwrap();
+
+        // Unknown timezones are rejected
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-timezone", user.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "timezone": "Mars/Olympus_Mons",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "Unknown timezone \"Mars/Olympus_Mons\""
+        );
+
+        // Remove the timezone
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-timezone", user.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "timezone": null,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let timezone = repo.user().get_timezone(&user).await.unwrap();
+        assert_eq!(timezone, None);
+        repo.save().await.unwrap();
+    }
+}
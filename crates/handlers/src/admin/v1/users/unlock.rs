@@ -12,7 +12,7 @@ use ulid::Ulid;
 
 use crate::{
     admin::{
-        call_context::CallContext,
+        call_context::{CallContext, HelpdeskCallContext},
         model::{Resource, User},
         params::UlidPathParam,
         response::{ErrorResponse, SingleResponse},
@@ -66,7 +66,7 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.unlock", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    HelpdeskCallContext(CallContext { mut repo, .. }): HelpdeskCallContext,
     State(homeserver): State<BoxHomeserverConnection>,
     id: UlidPathParam,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
@@ -141,6 +141,48 @@ mod tests {
         );
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_unlock_user_helpdesk(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin:helpdesk").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let user = repo.user().lock(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(&mxid, &user.sub))
+            .await
+            .unwrap();
+
+        // The helpdesk role can unlock users
+        let request = Request::post(format!("/api/admin/v1/users/{}/unlock", user.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // But it can't lock them
+        let request = Request::post(format!("/api/admin/v1/users/{}/lock", user.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "The urn:mas:admin:helpdesk scope doesn't allow this operation"
+        );
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unlock_deactivated_user(pool: PgPool) {
         setup();
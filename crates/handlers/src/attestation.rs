@@ -0,0 +1,621 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Platform attestations of mobile clients, to keep clones of the official
+//! applications from registering and getting tokens.
+//!
+//! Mobile clients can send an Apple App Attest or a Play Integrity attestation
+//! in the `MAS-Client-Attestation` header when registering or exchanging an
+//! authorization code, prefixed by the platform:
+//!
+//! ```text
+//! MAS-Client-Attestation: apple-app-attest <attestation>
+//! MAS-Client-Attestation: play-integrity <integrity token>
+//! ```
+//!
+//! Verifying those attestations needs credentials and APIs specific to each
+//! platform, so it is delegated to an [`AttestationVerifier`]. The
+//! [`HttpAttestationVerifier`] is a reference implementation which sends the
+//! attestation as JSON to an HTTP endpoint.
+//!
+//! Only native clients are checked, and only when dynamically registering or
+//! exchanging an authorization code as a public client: refreshing tokens
+//! doesn't need a new attestation.
+//!
+//! Attestations are bound to the request they are sent with: the verifier gets
+//! a `request_hash`, the base64url-encoded SHA-256 of the registration body or
+//! of the `code_verifier` (of the `code` if the client doesn't use PKCE), which
+//! the client must have set as the nonce of the attestation. An attestation
+//! is also only accepted once: the ones seen in the last ten minutes are
+//! rejected, so the verifier must refuse older ones. Seen attestations are
+//! kept in memory, so each instance of the service only knows about its own.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName},
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use mas_config::{AttestationConfig, AttestationEnforcement};
+use mas_http::RequestBuilderExt as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+/// The header in which the attestation is sent
+pub(crate) static CLIENT_ATTESTATION_HEADER: HeaderName =
+    HeaderName::from_static("mas-client-attestation");
+
+/// How long attestations are remembered, to reject them if they are replayed
+const REPLAY_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// The platform which issued an attestation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationPlatform {
+    /// Apple App Attest
+    AppleAppAttest,
+
+    /// Google Play Integrity
+    PlayIntegrity,
+}
+
+impl AttestationPlatform {
+    fn from_scheme(scheme: &str) -> Option<Self> {
+        match scheme {
+            "apple-app-attest" => Some(Self::AppleAppAttest),
+            "play-integrity" => Some(Self::PlayIntegrity),
+            _ => None,
+        }
+    }
+}
+
+/// When the attestation was presented
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationEvent {
+    /// The client is registering through dynamic client registration
+    Registration,
+
+    /// The client is exchanging an authorization code for tokens
+    TokenIssuance,
+}
+
+/// The attestation sent to the verifier
+#[derive(Debug, Clone, Serialize)]
+pub struct AttestationRequest {
+    /// When the attestation was presented
+    pub event: AttestationEvent,
+
+    /// The platform which issued the attestation
+    pub platform: AttestationPlatform,
+
+    /// The attestation itself, as sent by the client
+    pub attestation: String,
+
+    /// The base64url-encoded SHA-256 of the request the attestation is sent
+    /// with, which the attestation must have as its nonce
+    pub request_hash: String,
+
+    /// The ID of the client, on token issuance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+/// What the verifier found out about an attestation
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AttestationVerdict {
+    /// Whether the attestation is genuine
+    pub valid: bool,
+
+    /// The identifier of the attested application: the App ID on iOS, the
+    /// package name on Android
+    #[serde(default)]
+    pub app_id: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum AttestationError {
+    #[error("The attestation verifier did not reply in time")]
+    Timeout,
+
+    #[error("The request to the attestation verifier failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Why a client was rejected
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum AttestationRejection {
+    #[error("a platform attestation is required")]
+    Missing,
+
+    #[error("the platform attestation is malformed")]
+    Malformed,
+
+    #[error("the platform attestation is invalid")]
+    Invalid,
+
+    #[error("the attested application is not allowed")]
+    AppNotAllowed,
+
+    #[error("the platform attestation could not be verified")]
+    Unverified,
+
+    #[error("the platform attestation was already used")]
+    Replayed,
+}
+
+/// Compute the hash of the request an attestation is bound to
+pub(crate) fn request_hash(request: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(request))
+}
+
+/// A service verifying platform attestations
+#[async_trait]
+pub trait AttestationVerifier: Send + Sync {
+    /// Verify the given attestation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the verifier could not reach a verdict
+    async fn verify(
+        &self,
+        request: &AttestationRequest,
+    ) -> Result<AttestationVerdict, AttestationError>;
+}
+
+/// An [`AttestationVerifier`] which POSTs the attestation as JSON to an HTTP
+/// endpoint
+///
+/// The endpoint must check that the attestation has the `request_hash` as its
+/// nonce, and reply with a JSON object like
+/// `{"valid": true, "app_id": "io.element.android.x"}`.
+pub struct HttpAttestationVerifier {
+    http_client: reqwest::Client,
+    endpoint: Url,
+    token: Option<String>,
+}
+
+impl HttpAttestationVerifier {
+    /// Create a new verifier sending requests to the given endpoint
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, endpoint: Url, token: Option<String>) -> Self {
+        Self {
+            http_client,
+            endpoint,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl AttestationVerifier for HttpAttestationVerifier {
+    async fn verify(
+        &self,
+        request: &AttestationRequest,
+    ) -> Result<AttestationVerdict, AttestationError> {
+        let mut builder = self.http_client.post(self.endpoint.clone()).json(request);
+        if let Some(token) = &self.token {
+            builder = builder.bearer_auth(token);
+        }
+
+        let verdict = builder
+            .send_traced()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(verdict)
+    }
+}
+
+/// The attestation sent with a request, if any
+pub(crate) struct ClientAttestation {
+    value: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientAttestation {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let value = parts
+            .headers
+            .get(&CLIENT_ATTESTATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        Ok(Self { value })
+    }
+}
+
+impl ClientAttestation {
+    fn parse(&self) -> Option<Result<(AttestationPlatform, &str), AttestationRejection>> {
+        let value = self.value.as_deref()?;
+        let parsed = value
+            .split_once(' ')
+            .and_then(|(scheme, attestation)| {
+                let platform = AttestationPlatform::from_scheme(scheme)?;
+                let attestation = attestation.trim();
+                (!attestation.is_empty()).then_some((platform, attestation))
+            })
+            .ok_or(AttestationRejection::Malformed);
+        Some(parsed)
+    }
+}
+
+struct Inner {
+    verifier: Arc<dyn AttestationVerifier>,
+    enforcement: AttestationEnforcement,
+    timeout: Duration,
+    apple_app_ids: Vec<String>,
+    android_package_names: Vec<String>,
+    seen: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+/// Checks the attestations of native clients, according to the configured
+/// enforcement
+#[derive(Clone, Default)]
+pub struct AttestationChecker {
+    inner: Option<Arc<Inner>>,
+}
+
+impl AttestationChecker {
+    /// A checker which ignores attestations
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Create a checker using the given verifier
+    #[must_use]
+    pub fn new(
+        verifier: Arc<dyn AttestationVerifier>,
+        enforcement: AttestationEnforcement,
+        timeout: Duration,
+        apple_app_ids: Vec<String>,
+        android_package_names: Vec<String>,
+    ) -> Self {
+        if enforcement == AttestationEnforcement::Disabled {
+            return Self::disabled();
+        }
+
+        Self {
+            inner: Some(Arc::new(Inner {
+                verifier,
+                enforcement,
+                timeout,
+                apple_app_ids,
+                android_package_names,
+                seen: Mutex::new(HashMap::new()),
+            })),
+        }
+    }
+
+    /// Create a checker from the configuration, using the HTTP endpoint if one
+    /// is set
+    #[must_use]
+    pub fn from_config(config: &AttestationConfig, http_client: reqwest::Client) -> Self {
+        let Some(endpoint) = &config.endpoint else {
+            return Self::disabled();
+        };
+
+        let verifier =
+            HttpAttestationVerifier::new(http_client, endpoint.clone(), config.token.clone());
+        Self::new(
+            Arc::new(verifier),
+            config.enforcement,
+            config.timeout,
+            config.apple_app_ids.clone(),
+            config.android_package_names.clone(),
+        )
+    }
+
+    /// Check the attestation sent by a native client with the request of the
+    /// given hash
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if attestations are required and the one sent by the
+    /// client is missing or not valid. When attestations are only reported,
+    /// the result is logged and this never fails.
+    #[tracing::instrument(name = "attestation.check", skip_all, fields(attestation.event = ?event))]
+    pub(crate) async fn check(
+        &self,
+        attestation: &ClientAttestation,
+        event: AttestationEvent,
+        client_id: Option<&str>,
+        request_hash: String,
+    ) -> Result<(), AttestationRejection> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+
+        let result = inner
+            .check(attestation, event, client_id, request_hash)
+            .await;
+        if let Err(e) = &result {
+            tracing::warn!(
+                error = e as &dyn std::error::Error,
+                enforcement = ?inner.enforcement,
+                client.id = client_id,
+                "Client did not present a valid platform attestation"
+            );
+        }
+
+        if inner.enforcement == AttestationEnforcement::Required {
+            result
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Inner {
+    async fn check(
+        &self,
+        attestation: &ClientAttestation,
+        event: AttestationEvent,
+        client_id: Option<&str>,
+        request_hash: String,
+    ) -> Result<(), AttestationRejection> {
+        let (platform, attestation) =
+            attestation.parse().ok_or(AttestationRejection::Missing)??;
+
+        // Remember the attestation before verifying it, so that concurrent replays
+        // are caught as well
+        if !self.remember(attestation) {
+            return Err(AttestationRejection::Replayed);
+        }
+
+        let request = AttestationRequest {
+            event,
+            platform,
+            attestation: attestation.to_owned(),
+            request_hash,
+            client_id: client_id.map(ToOwned::to_owned),
+        };
+
+        let verdict = tokio::time::timeout(self.timeout, self.verifier.verify(&request))
+            .await
+            .unwrap_or(Err(AttestationError::Timeout))
+            .map_err(|e| {
+                tracing::error!(
+                    error = &e as &dyn std::error::Error,
+                    "Could not verify the platform attestation"
+                );
+                AttestationRejection::Unverified
+            })?;
+
+        if !verdict.valid {
+            return Err(AttestationRejection::Invalid);
+        }
+
+        let allowed = match platform {
+            AttestationPlatform::AppleAppAttest => &self.apple_app_ids,
+            AttestationPlatform::PlayIntegrity => &self.android_package_names,
+        };
+
+        // An empty list allows any genuine application
+        if !allowed.is_empty()
+            && !verdict
+                .app_id
+                .as_ref()
+                .is_some_and(|app_id| allowed.contains(app_id))
+        {
+            return Err(AttestationRejection::AppNotAllowed);
+        }
+
+        Ok(())
+    }
+
+    /// Remember an attestation, returning `false` if it was already seen
+    fn remember(&self, attestation: &str) -> bool {
+        let digest: [u8; 32] = Sha256::digest(attestation.as_bytes()).into();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_at| seen_at.elapsed() < REPLAY_WINDOW);
+        seen.insert(digest, Instant::now()).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Attestations are `genuine.<nonce>` in these tests
+    struct MockVerifier {
+        verdict: Option<AttestationVerdict>,
+    }
+
+    #[async_trait]
+    impl AttestationVerifier for MockVerifier {
+        async fn verify(
+            &self,
+            request: &AttestationRequest,
+        ) -> Result<AttestationVerdict, AttestationError> {
+            if request.attestation != format!("genuine.{}", request.request_hash) {
+                return Ok(AttestationVerdict {
+                    valid: false,
+                    app_id: None,
+                });
+            }
+
+            self.verdict
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("verifier is down").into())
+        }
+    }
+
+    fn new_checker(
+        verdict: Option<AttestationVerdict>,
+        enforcement: AttestationEnforcement,
+    ) -> AttestationChecker {
+        AttestationChecker::new(
+            Arc::new(MockVerifier { verdict }),
+            enforcement,
+            Duration::from_secs(1),
+            Vec::new(),
+            vec!["io.element.android.x".to_owned()],
+        )
+    }
+
+    fn attestation(value: Option<&str>) -> ClientAttestation {
+        ClientAttestation {
+            value: value.map(ToOwned::to_owned),
+        }
+    }
+
+    /// A genuine attestation for the given request
+    fn genuine(platform: &str, request: &[u8]) -> String {
+        format!("{platform} genuine.{}", request_hash(request))
+    }
+
+    #[tokio::test]
+    async fn test_required() {
+        let genuine_verdict = AttestationVerdict {
+            valid: true,
+            app_id: Some("io.element.android.x".to_owned()),
+        };
+        let checker = new_checker(Some(genuine_verdict), AttestationEnforcement::Required);
+        let check = |value: Option<String>, request: &'static [u8]| {
+            let checker = checker.clone();
+            async move {
+                checker
+                    .check(
+                        &attestation(value.as_deref()),
+                        AttestationEvent::Registration,
+                        None,
+                        request_hash(request),
+                    )
+                    .await
+            }
+        };
+
+        assert_eq!(
+            check(Some(genuine("play-integrity", b"first")), b"first").await,
+            Ok(())
+        );
+        // Any iOS application is allowed, as the list is empty
+        assert_eq!(
+            check(Some(genuine("apple-app-attest", b"second")), b"second").await,
+            Ok(())
+        );
+        assert_eq!(
+            check(None, b"third").await,
+            Err(AttestationRejection::Missing)
+        );
+        assert_eq!(
+            check(Some("play-integrity".to_owned()), b"third").await,
+            Err(AttestationRejection::Malformed)
+        );
+        assert_eq!(
+            check(Some(genuine("safetynet", b"third")), b"third").await,
+            Err(AttestationRejection::Malformed)
+        );
+        assert_eq!(
+            check(Some("play-integrity cloned".to_owned()), b"third").await,
+            Err(AttestationRejection::Invalid)
+        );
+        // The attestation is bound to another request
+        assert_eq!(
+            check(Some(genuine("play-integrity", b"fourth")), b"fifth").await,
+            Err(AttestationRejection::Invalid)
+        );
+
+        let clone = AttestationVerdict {
+            valid: true,
+            app_id: Some("com.example.clone".to_owned()),
+        };
+        let checker = new_checker(Some(clone), AttestationEnforcement::Required);
+        assert_eq!(
+            checker
+                .check(
+                    &attestation(Some(&genuine("play-integrity", b"code"))),
+                    AttestationEvent::TokenIssuance,
+                    Some("client"),
+                    request_hash(b"code"),
+                )
+                .await,
+            Err(AttestationRejection::AppNotAllowed)
+        );
+
+        let checker = new_checker(None, AttestationEnforcement::Required);
+        assert_eq!(
+            checker
+                .check(
+                    &attestation(Some(&genuine("play-integrity", b"body"))),
+                    AttestationEvent::Registration,
+                    None,
+                    request_hash(b"body"),
+                )
+                .await,
+            Err(AttestationRejection::Unverified)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let verdict = AttestationVerdict {
+            valid: true,
+            app_id: Some("io.element.android.x".to_owned()),
+        };
+        let checker = new_checker(Some(verdict), AttestationEnforcement::Required);
+        let attestation = attestation(Some(&genuine("play-integrity", b"body")));
+        let check = || {
+            checker.check(
+                &attestation,
+                AttestationEvent::Registration,
+                None,
+                request_hash(b"body"),
+            )
+        };
+
+        assert_eq!(check().await, Ok(()));
+        // The same attestation can't be used twice, even for the same request
+        assert_eq!(check().await, Err(AttestationRejection::Replayed));
+    }
+
+    #[tokio::test]
+    async fn test_report_and_disabled() {
+        let checker = new_checker(None, AttestationEnforcement::Report);
+        assert_eq!(
+            checker
+                .check(
+                    &attestation(None),
+                    AttestationEvent::Registration,
+                    None,
+                    request_hash(b"body"),
+                )
+                .await,
+            Ok(())
+        );
+
+        let checker = new_checker(None, AttestationEnforcement::Disabled);
+        assert!(checker.inner.is_none());
+        assert_eq!(
+            checker
+                .check(
+                    &attestation(None),
+                    AttestationEvent::Registration,
+                    None,
+                    request_hash(b"body"),
+                )
+                .await,
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Description of the login and registration forms, so that alternative
+//! frontends and native applications can render them without hardcoding the
+//! configuration of the instance.
+//!
+//! The document is versioned through its path: fields may be added to a
+//! version, but never removed or changed.
+
+use axum::{extract::State, response::IntoResponse, Json};
+use mas_axum_utils::FancyError;
+use mas_data_model::{CaptchaService, SiteConfig};
+use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
+use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, BoxRepository};
+use serde::Serialize;
+use ulid::Ulid;
+use url::Url;
+
+use crate::passwords::PasswordManager;
+
+/// The version of the document served by this handler
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+struct AuthForms {
+    version: u32,
+    login: LoginForm,
+    registration: RegistrationForm,
+    password_policy: Option<PasswordPolicy>,
+    captcha: Option<Captcha>,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginForm {
+    /// Whether users can log in with a username and a password
+    password: bool,
+
+    /// Whether users can log in with a client certificate
+    certificate: bool,
+
+    /// The upstream providers users can log in with
+    upstream_providers: Vec<UpstreamProvider>,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamProvider {
+    id: Ulid,
+    human_name: Option<String>,
+    brand_name: Option<String>,
+    authorization_url: Url,
+}
+
+#[derive(Debug, Serialize)]
+struct RegistrationForm {
+    /// Whether users can register with a username and a password
+    enabled: bool,
+
+    /// The fields of the form, in the order they should be shown
+    fields: Vec<FormField>,
+
+    /// The terms of service users have to accept
+    tos_uri: Option<Url>,
+}
+
+#[derive(Debug, Serialize)]
+struct FormField {
+    name: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    required: bool,
+}
+
+impl FormField {
+    const fn required(name: &'static str, kind: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PasswordPolicy {
+    /// Minimum complexity score, between 0 and 4, as evaluated by zxcvbn
+    minimum_complexity: u8,
+
+    /// Minimum number of characters
+    minimum_length: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CaptchaRequirement {
+    /// The CAPTCHA has to be solved on every submission
+    Always,
+
+    /// The CAPTCHA is only asked for once the bot detection finds a
+    /// submission suspicious
+    OnSuspicion,
+}
+
+#[derive(Debug, Serialize)]
+struct Captcha {
+    service: &'static str,
+    site_key: String,
+
+    /// The name of the form field holding the response of the CAPTCHA
+    response_field: &'static str,
+    required: CaptchaRequirement,
+}
+
+#[tracing::instrument(name = "handlers.auth_forms.get", skip_all, err)]
+pub(crate) async fn get(
+    State(site_config): State<SiteConfig>,
+    State(url_builder): State<UrlBuilder>,
+    State(password_manager): State<PasswordManager>,
+    mut repo: BoxRepository,
+) -> Result<impl IntoResponse, FancyError> {
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+    repo.cancel().await?;
+
+    let upstream_providers = providers
+        .into_iter()
+        .map(|provider| UpstreamProvider {
+            id: provider.id,
+            authorization_url: url_builder
+                .absolute_url_for(&UpstreamOAuth2Authorize::new(provider.id)),
+            human_name: provider.human_name,
+            brand_name: provider.brand_name,
+        })
+        .collect();
+
+    let password_enabled = password_manager.is_enabled();
+    let registration_enabled = password_enabled && site_config.password_registration_enabled;
+
+    let mut fields = Vec::new();
+    if registration_enabled {
+        fields.extend([
+            FormField::required("username", "text"),
+            FormField::required("email", "email"),
+            FormField::required("password", "password"),
+            FormField::required("password_confirm", "password"),
+        ]);
+        if site_config.tos_uri.is_some() {
+            fields.push(FormField::required("accept_terms", "checkbox"));
+        }
+    }
+
+    let password_policy = password_manager
+        .policy(None)
+        .ok()
+        .map(|policy| PasswordPolicy {
+            minimum_complexity: policy.minimum_complexity,
+            minimum_length: policy.minimum_length,
+        });
+
+    let captcha = site_config.captcha.as_ref().map(|captcha| {
+        let (service, response_field) = match captcha.service {
+            CaptchaService::RecaptchaV2 => ("recaptcha_v2", "g-recaptcha-response"),
+            CaptchaService::CloudflareTurnstile => {
+                ("cloudflare_turnstile", "cf-turnstile-response")
+            }
+            CaptchaService::HCaptcha => ("hcaptcha", "h-captcha-response"),
+        };
+
+        Captcha {
+            service,
+            site_key: captcha.site_key.clone(),
+            response_field,
+            required: if site_config.bot_detection.is_some() {
+                CaptchaRequirement::OnSuspicion
+            } else {
+                CaptchaRequirement::Always
+            },
+        }
+    });
+
+    Ok(Json(AuthForms {
+        version: VERSION,
+        login: LoginForm {
+            password: password_enabled && site_config.password_login_enabled,
+            certificate: site_config.certificate_login.is_some(),
+            upstream_providers,
+        },
+        registration: RegistrationForm {
+            enabled: registration_enabled,
+            fields,
+            tos_uri: site_config.tos_uri.clone(),
+        },
+        password_policy,
+        captcha,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_auth_forms(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get(mas_router::AuthForms::PATH).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let forms: serde_json::Value = response.json();
+        assert_eq!(forms["version"], 1);
+        assert_eq!(forms["login"]["password"], true);
+        assert_eq!(forms["login"]["upstream_providers"], serde_json::json!([]));
+        assert_eq!(forms["registration"]["enabled"], true);
+        assert_eq!(forms["registration"]["fields"][0]["name"], "username");
+        assert!(forms["captcha"].is_null());
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Lightweight heuristics to detect automated submissions of the login and
+//! registration forms.
+//!
+//! Each suspicious signal adds to a score, which is compared to the thresholds
+//! of the [`BotDetectionConfig`] to decide whether the submission should be
+//! allowed, require a CAPTCHA, or be rejected.
+
+use hyper::header::{HeaderMap, ACCEPT_LANGUAGE, USER_AGENT};
+use mas_data_model::BotDetectionConfig;
+use serde::Deserialize;
+
+use crate::{Limiter, RequesterFingerprint};
+
+/// The response to the JavaScript challenge was missing or wrong
+const CHALLENGE_WEIGHT: u32 = 3;
+
+/// There was no `User-Agent` header
+const MISSING_USER_AGENT_WEIGHT: u32 = 3;
+
+/// The `User-Agent` header is one of a known automation tool
+const AUTOMATION_USER_AGENT_WEIGHT: u32 = 4;
+
+/// There was no `Accept-Language` header, which all browsers send
+const MISSING_ACCEPT_LANGUAGE_WEIGHT: u32 = 1;
+
+/// The form was submitted from another site
+const CROSS_SITE_WEIGHT: u32 = 2;
+
+/// Too many forms were submitted from the same address
+const VELOCITY_WEIGHT: u32 = 3;
+
+/// Lowercase fragments of the `User-Agent` header of common automation tools
+const AUTOMATION_USER_AGENTS: &[&str] = &[
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "go-http-client",
+    "node-fetch",
+    "axios/",
+    "headlesschrome",
+    "phantomjs",
+    "selenium",
+    "scrapy",
+];
+
+/// The fields added to the forms by the JavaScript challenge
+#[derive(Debug, Deserialize, Default)]
+pub struct Form {
+    bot_challenge: Option<String>,
+}
+
+/// What should happen to a form submission
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    /// The submission looks legitimate
+    Allow,
+
+    /// The submission is suspicious, and should require a CAPTCHA
+    Captcha,
+
+    /// The submission is most likely automated, and should be rejected
+    Block,
+}
+
+/// The suspicious signals found on a form submission
+#[derive(Debug, Default)]
+pub struct Signals {
+    score: u32,
+    reasons: Vec<&'static str>,
+}
+
+impl Signals {
+    fn add(&mut self, weight: u32, reason: &'static str) {
+        self.score += weight;
+        self.reasons.push(reason);
+    }
+
+    /// Collect the signals from a form submission
+    ///
+    /// # Parameters
+    ///
+    /// * `headers`: The headers of the request
+    /// * `form`: The fields filled by the JavaScript challenge
+    /// * `challenge`: The challenge which was given to the page, which is the
+    ///   CSRF token of the form
+    /// * `velocity_exceeded`: Whether too many forms were submitted from the
+    ///   same address
+    #[must_use]
+    pub fn collect(
+        headers: &HeaderMap,
+        form: &Form,
+        challenge: &str,
+        velocity_exceeded: bool,
+    ) -> Self {
+        let mut signals = Self::default();
+
+        if form.bot_challenge.as_deref() != Some(&expected_challenge_response(challenge)) {
+            signals.add(CHALLENGE_WEIGHT, "challenge");
+        }
+
+        match headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+        {
+            None => signals.add(MISSING_USER_AGENT_WEIGHT, "missing_user_agent"),
+            Some(user_agent) => {
+                let user_agent = user_agent.to_lowercase();
+                if AUTOMATION_USER_AGENTS
+                    .iter()
+                    .any(|fragment| user_agent.contains(fragment))
+                {
+                    signals.add(AUTOMATION_USER_AGENT_WEIGHT, "automation_user_agent");
+                }
+            }
+        }
+
+        if !headers.contains_key(ACCEPT_LANGUAGE) {
+            signals.add(MISSING_ACCEPT_LANGUAGE_WEIGHT, "missing_accept_language");
+        }
+
+        if headers
+            .get("sec-fetch-site")
+            .is_some_and(|value| value == "cross-site")
+        {
+            signals.add(CROSS_SITE_WEIGHT, "cross_site");
+        }
+
+        if velocity_exceeded {
+            signals.add(VELOCITY_WEIGHT, "velocity");
+        }
+
+        signals
+    }
+
+    /// The total score of the signals
+    #[must_use]
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Decide what should happen to the submission
+    #[must_use]
+    pub fn verdict(&self, config: BotDetectionConfig) -> Verdict {
+        if self.score >= config.block_threshold {
+            Verdict::Block
+        } else if self.score >= config.captcha_threshold {
+            Verdict::Captcha
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// The response the JavaScript challenge computes for the given challenge
+///
+/// This must be kept in sync with the script in
+/// `templates/components/bot_challenge.html`
+fn expected_challenge_response(challenge: &str) -> String {
+    challenge.chars().rev().collect()
+}
+
+/// Evaluate the bot detection signals of a login or registration form
+/// submission
+///
+/// Always allows the submission if the bot detection is disabled.
+pub(crate) fn evaluate(
+    config: Option<&BotDetectionConfig>,
+    limiter: &Limiter,
+    requester: RequesterFingerprint,
+    headers: &HeaderMap,
+    form: &Form,
+    challenge: &str,
+) -> Verdict {
+    let Some(config) = config else {
+        return Verdict::Allow;
+    };
+
+    let velocity_exceeded = limiter.check_form_submission(requester).is_err();
+    let signals = Signals::collect(headers, form, challenge, velocity_exceeded);
+    let verdict = signals.verdict(*config);
+
+    if verdict != Verdict::Allow {
+        tracing::warn!(
+            %requester,
+            score = signals.score(),
+            reasons = ?signals.reasons,
+            ?verdict,
+            "Suspicious form submission"
+        );
+    }
+
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    const CONFIG: BotDetectionConfig = BotDetectionConfig {
+        captcha_threshold: 3,
+        block_threshold: 8,
+    };
+
+    fn browser_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Firefox/130.0"),
+        );
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-GB,en;q=0.5"));
+        headers.insert("sec-fetch-site", HeaderValue::from_static("same-origin"));
+        headers
+    }
+
+    #[test]
+    fn test_browser_submission() {
+        let form = Form {
+            bot_challenge: Some("cba".to_owned()),
+        };
+
+        let signals = Signals::collect(&browser_headers(), &form, "abc", false);
+        assert_eq!(signals.score(), 0);
+        assert_eq!(signals.verdict(CONFIG), Verdict::Allow);
+
+        // Submitting too many forms alone escalates to a CAPTCHA
+        let signals = Signals::collect(&browser_headers(), &form, "abc", true);
+        assert_eq!(signals.verdict(CONFIG), Verdict::Captcha);
+    }
+
+    #[test]
+    fn test_failed_challenge() {
+        let form = Form {
+            bot_challenge: Some("abc".to_owned()),
+        };
+
+        let signals = Signals::collect(&browser_headers(), &form, "abc", false);
+        assert_eq!(signals.score(), CHALLENGE_WEIGHT);
+        assert_eq!(signals.verdict(CONFIG), Verdict::Captcha);
+    }
+
+    #[test]
+    fn test_automation_tool() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("curl/8.5.0"));
+
+        let signals = Signals::collect(&headers, &Form::default(), "abc", false);
+        assert_eq!(
+            signals.score(),
+            CHALLENGE_WEIGHT + AUTOMATION_USER_AGENT_WEIGHT + MISSING_ACCEPT_LANGUAGE_WEIGHT
+        );
+        assert_eq!(signals.verdict(CONFIG), Verdict::Block);
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Identify users from the X.509 client certificate they presented during the
+//! TLS handshake.
+//!
+//! The certificate chain is verified by the listener against its configured
+//! client CA, so this only has to extract the identity out of the leaf
+//! certificate, and map it to a user with the configured template.
+
+use mas_data_model::{CertificateLoginConfig, CertificateLookup, User};
+use mas_storage::{
+    user::{UserEmailFilter, UserEmailRepository, UserRepository},
+    BoxRepository, Pagination, RepositoryAccess,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use x509_cert::{
+    der::{
+        asn1::{Any, Ia5StringRef, ObjectIdentifier, PrintableStringRef, Utf8StringRef},
+        Decode, Tag, Tagged,
+    },
+    ext::pkix::{name::GeneralName, SubjectAltName},
+    Certificate,
+};
+
+use crate::upstream_oauth2::template::environment;
+
+const OID_CN: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+const OID_SERIAL_NUMBER: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.5");
+const OID_O: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.10");
+const OID_OU: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.11");
+const OID_UID: ObjectIdentifier = ObjectIdentifier::new_unwrap("0.9.2342.19200300.100.1.1");
+const OID_EMAIL_ADDRESS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.1");
+
+/// The Microsoft User Principal Name, found in the SAN of smart card
+/// certificates
+const OID_UPN: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.20.2.3");
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No client certificate was presented")]
+    Missing,
+
+    #[error("The client certificate could not be decoded")]
+    Decode(#[from] x509_cert::der::Error),
+
+    #[error("Failed to render the certificate mapping template")]
+    Template(#[from] minijinja::Error),
+
+    #[error("The certificate mapping template rendered an empty value")]
+    EmptyValue,
+
+    #[error("No user matches the client certificate")]
+    NoMatchingUser,
+
+    #[error("More than one user matches the client certificate")]
+    AmbiguousUser,
+
+    #[error(transparent)]
+    Repository(#[from] mas_storage::RepositoryError),
+}
+
+/// The leaf certificate the client presented during the TLS handshake, if any
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificate {
+    der: Option<Vec<u8>>,
+}
+
+impl ClientCertificate {
+    /// Wrap the DER-encoded leaf certificate presented by the client
+    #[must_use]
+    pub fn new(der: Option<Vec<u8>>) -> Self {
+        Self { der }
+    }
+
+    /// Drop the certificate if logging in with a client certificate is not
+    /// enabled on the listener it was presented to
+    ///
+    /// Other listeners may verify client certificates against authorities
+    /// which are not trusted to log in.
+    #[must_use]
+    pub fn on_listener(self, certificate_login: bool) -> Self {
+        if certificate_login {
+            self
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Extract the identity out of the certificate
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no certificate was presented, or if it could not
+    /// be decoded
+    pub fn identity(&self) -> Result<CertificateIdentity, Error> {
+        let der = self.der.as_deref().ok_or(Error::Missing)?;
+        CertificateIdentity::from_der(der)
+    }
+}
+
+/// Attributes of the subject of a certificate
+#[derive(Debug, Default, Serialize)]
+pub struct CertificateSubject {
+    cn: Option<String>,
+    email: Option<String>,
+    uid: Option<String>,
+    o: Option<String>,
+    ou: Option<String>,
+    serial_number: Option<String>,
+}
+
+/// Subject alternative names of a certificate
+#[derive(Debug, Default, Serialize)]
+pub struct SubjectAltNames {
+    email: Vec<String>,
+    dns: Vec<String>,
+    upn: Vec<String>,
+}
+
+/// The identity found in a certificate, exposed to the mapping template
+#[derive(Debug, Serialize)]
+pub struct CertificateIdentity {
+    subject: CertificateSubject,
+    san: SubjectAltNames,
+
+    /// Hex-encoded SHA-256 fingerprint of the DER-encoded certificate
+    fingerprint: String,
+}
+
+impl CertificateIdentity {
+    fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let certificate = Certificate::from_der(der)?;
+        let tbs = &certificate.tbs_certificate;
+
+        let mut subject = CertificateSubject::default();
+        for attribute in tbs.subject.0.iter().flat_map(|rdn| rdn.0.iter()) {
+            let field = match attribute.oid {
+                OID_CN => &mut subject.cn,
+                OID_EMAIL_ADDRESS => &mut subject.email,
+                OID_UID => &mut subject.uid,
+                OID_O => &mut subject.o,
+                OID_OU => &mut subject.ou,
+                OID_SERIAL_NUMBER => &mut subject.serial_number,
+                _ => continue,
+            };
+
+            // Keep the first occurence of each attribute
+            if field.is_none() {
+                *field = any_to_string(&attribute.value);
+            }
+        }
+
+        let mut san = SubjectAltNames::default();
+        if let Some((_critical, names)) = tbs.get::<SubjectAltName>()? {
+            for name in names.0 {
+                match name {
+                    GeneralName::Rfc822Name(email) => san.email.push(email.to_string()),
+                    GeneralName::DnsName(dns) => san.dns.push(dns.to_string()),
+                    GeneralName::OtherName(other) if other.type_id == OID_UPN => {
+                        san.upn.extend(any_to_string(&other.value));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let fingerprint = format!("{:x}", Sha256::digest(der));
+
+        Ok(Self {
+            subject,
+            san,
+            fingerprint,
+        })
+    }
+
+    /// Hex-encoded SHA-256 fingerprint of the certificate
+    #[must_use]
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
+fn any_to_string(value: &Any) -> Option<String> {
+    let value = match value.tag() {
+        Tag::Utf8String => value.decode_as::<Utf8StringRef>().ok()?.to_string(),
+        Tag::PrintableString => value.decode_as::<PrintableStringRef>().ok()?.to_string(),
+        Tag::Ia5String => value.decode_as::<Ia5StringRef>().ok()?.to_string(),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Find the user matching the identity of a certificate, as configured
+///
+/// # Errors
+///
+/// Returns an error if the template fails to render, or if not exactly one
+/// active user matches
+pub(crate) async fn find_user(
+    repo: &mut BoxRepository,
+    config: &CertificateLoginConfig,
+    identity: &CertificateIdentity,
+) -> Result<User, Error> {
+    let value = environment().render_str(&config.template, identity)?;
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(Error::EmptyValue);
+    }
+
+    let user = match config.lookup {
+        CertificateLookup::Username => repo.user().find_by_username(value).await?,
+
+        CertificateLookup::Email => {
+            let filter = UserEmailFilter::new().for_email(value).verified_only();
+            let emails = repo.user_email().list(filter, Pagination::first(2)).await?;
+
+            let [email] = &emails.edges[..] else {
+                return Err(if emails.edges.is_empty() {
+                    Error::NoMatchingUser
+                } else {
+                    Error::AmbiguousUser
+                });
+            };
+
+            repo.user().lookup(email.user_id).await?
+        }
+    };
+
+    user.filter(User::is_valid).ok_or(Error::NoMatchingUser)
+}
@@ -4,13 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::sync::LazyLock;
+
 use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::typed_header::TypedHeader;
 use chrono::Duration;
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
 use mas_data_model::{
-    CompatSession, CompatSsoLoginState, Device, SiteConfig, TokenType, User, UserAgent,
+    ClientApp, CompatPasswordLogin, CompatSession, CompatSsoLoginState, Device, InvalidDeviceID,
+    LoginMethod, SiteConfig, TokenType, User, UserAgent,
 };
 use mas_matrix::BoxHomeserverConnection;
 use mas_storage::{
@@ -18,9 +21,11 @@ use mas_storage::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
-    user::{UserPasswordRepository, UserRepository},
+    device_key::DeviceKeyRepository,
+    user::{UserLoginHistoryRepository, UserPasswordRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
+use opentelemetry::{metrics::Counter, KeyValue};
 use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, skip_serializing_none, DurationMilliSeconds};
@@ -29,10 +34,29 @@ use zeroize::Zeroizing;
 
 use super::MatrixError;
 use crate::{
-    impl_from_error_for_route, passwords::PasswordManager, rate_limit::PasswordCheckLimitedError,
-    BoundActivityTracker, Limiter, RequesterFingerprint,
+    device_allocation,
+    device_proof::{DeviceProof, DeviceProofError},
+    impl_from_error_for_route,
+    passwords::{PasswordHashingBusyError, PasswordManager},
+    rate_limit::PasswordCheckLimitedError,
+    session_limits::{self, SessionLimitError},
+    BoundActivityTracker, Limiter, NetworkPolicy, NetworkPolicyError, NetworkScope, RequestOrigin,
+    RequesterFingerprint,
 };
 
+static PASSWORD_LOGINS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.compat.password_login")
+    .with_description("The number of successful password logins through the Matrix login API")
+    .with_unit("{login}")
+    .init()
+});
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 enum LoginType {
@@ -65,8 +89,11 @@ struct LoginTypes {
 }
 
 #[tracing::instrument(name = "handlers.compat.login.get", skip_all)]
-pub(crate) async fn get(State(password_manager): State<PasswordManager>) -> impl IntoResponse {
-    let flows = if password_manager.is_enabled() {
+pub(crate) async fn get(
+    State(password_manager): State<PasswordManager>,
+    State(site_config): State<SiteConfig>,
+) -> impl IntoResponse {
+    let flows = if password_login_allowed(&password_manager, &site_config) {
         vec![
             LoginType::Password,
             LoginType::Sso {
@@ -90,6 +117,12 @@ pub(crate) async fn get(State(password_manager): State<PasswordManager>) -> impl
     Json(res)
 }
 
+/// Whether clients can log in with a password through the Matrix login API
+fn password_login_allowed(password_manager: &PasswordManager, site_config: &SiteConfig) -> bool {
+    password_manager.is_enabled()
+        && site_config.compat_password_login != CompatPasswordLogin::Disabled
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RequestBody {
     #[serde(flatten)]
@@ -97,6 +130,10 @@ pub struct RequestBody {
 
     #[serde(default)]
     refresh_token: bool,
+
+    /// The device the client wants to log in with, to reuse an existing one
+    #[serde(default)]
+    device_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -157,6 +194,12 @@ pub enum RouteError {
     #[error("password verification failed")]
     PasswordVerificationFailed(#[source] anyhow::Error),
 
+    #[error("the user has to change their password")]
+    PasswordChangeRequired,
+
+    #[error("too many passwords are being hashed")]
+    PasswordHashingBusy(#[from] PasswordHashingBusyError),
+
     #[error("request rate limited")]
     RateLimited(#[from] PasswordCheckLimitedError),
 
@@ -168,10 +211,31 @@ pub enum RouteError {
 
     #[error("failed to provision device")]
     ProvisionDeviceFailed(#[source] anyhow::Error),
+
+    #[error("invalid device proof")]
+    DeviceProof(#[from] DeviceProofError),
+
+    #[error("the user has too many active sessions")]
+    SessionLimitExceeded,
+
+    #[error("denied by the network policy")]
+    NetworkPolicy(#[from] NetworkPolicyError),
+
+    #[error("invalid device ID")]
+    InvalidDeviceId(#[from] InvalidDeviceID),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 
+impl From<SessionLimitError> for RouteError {
+    fn from(e: SessionLimitError) -> Self {
+        match e {
+            SessionLimitError::Exceeded => Self::SessionLimitExceeded,
+            SessionLimitError::Repository(e) => Self::Internal(Box::new(e)),
+        }
+    }
+}
+
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
@@ -188,6 +252,11 @@ impl IntoResponse for RouteError {
                 error: "Too many login attempts",
                 status: StatusCode::TOO_MANY_REQUESTS,
             },
+            Self::PasswordHashingBusy(_) => MatrixError {
+                errcode: "M_LIMIT_EXCEEDED",
+                error: "Too many logins in progress, try again later",
+                status: StatusCode::TOO_MANY_REQUESTS,
+            },
             Self::Unsupported => MatrixError {
                 errcode: "M_UNRECOGNIZED",
                 error: "Invalid login type",
@@ -200,6 +269,11 @@ impl IntoResponse for RouteError {
                     status: StatusCode::FORBIDDEN,
                 }
             }
+            Self::PasswordChangeRequired => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "The password must be changed, log in through the browser to do so",
+                status: StatusCode::FORBIDDEN,
+            },
             Self::LoginTookTooLong => MatrixError {
                 errcode: "M_FORBIDDEN",
                 error: "Login token expired",
@@ -210,6 +284,26 @@ impl IntoResponse for RouteError {
                 error: "Invalid login token",
                 status: StatusCode::FORBIDDEN,
             },
+            Self::DeviceProof(_) => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Invalid device proof",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::SessionLimitExceeded => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Too many active sessions",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::NetworkPolicy(_) => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Logins are not allowed from this network",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::InvalidDeviceId(_) => MatrixError {
+                errcode: "M_INVALID_PARAM",
+                error: "Invalid device ID",
+                status: StatusCode::BAD_REQUEST,
+            },
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -226,12 +320,26 @@ pub(crate) async fn post(
     State(homeserver): State<BoxHomeserverConnection>,
     State(site_config): State<SiteConfig>,
     State(limiter): State<Limiter>,
+    State(network_policy): State<NetworkPolicy>,
     requester: RequesterFingerprint,
+    origin: RequestOrigin,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    device_proof: DeviceProof,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
+    // Check that logins are allowed from where the request comes from
+    network_policy.check(NetworkScope::Login, &origin)?;
+
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
-    let (mut session, user) = match (password_manager.is_enabled(), input.credentials) {
+
+    // Check the device proof before doing anything, so that a bad proof doesn't
+    // consume a login token
+    let device_key = device_proof.verify_new_key(clock.now())?;
+
+    let device = input.device_id.map(Device::try_from).transpose()?;
+
+    let password_allowed = password_login_allowed(&password_manager, &site_config);
+    let (mut session, user) = match (password_allowed, input.credentials) {
         (
             true,
             Credentials::Password {
@@ -239,7 +347,7 @@ pub(crate) async fn post(
                 password,
             },
         ) => {
-            user_password_login(
+            let (session, user) = user_password_login(
                 &mut rng,
                 &clock,
                 &password_manager,
@@ -247,10 +355,31 @@ pub(crate) async fn post(
                 requester,
                 &mut repo,
                 &homeserver,
+                &site_config,
                 user,
                 password,
+                device,
             )
-            .await?
+            .await?;
+
+            // Keep track of the clients still logging in with a password, so
+            // that operators know when they can turn it off
+            let client_app = user_agent
+                .as_ref()
+                .map_or(ClientApp::Unknown, |ua| ua.client_app);
+            PASSWORD_LOGINS.add(1, &[KeyValue::new("client_app", client_app.as_str())]);
+
+            if site_config.compat_password_login == CompatPasswordLogin::Deprecated {
+                tracing::warn!(
+                    %user.id,
+                    user_agent.name = user_agent.as_ref().and_then(|ua| ua.name.as_deref()),
+                    user_agent.version = user_agent.as_ref().and_then(|ua| ua.version.as_deref()),
+                    user_agent.client_app = client_app.as_str(),
+                    "A client logged in with the deprecated password login of the Matrix API"
+                );
+            }
+
+            (session, user)
         }
 
         (_, Credentials::Token { token }) => token_login(&mut repo, &clock, &token).await?,
@@ -260,6 +389,15 @@ pub(crate) async fn post(
         }
     };
 
+    tracing::info!(
+        audit.event = "compat_session.created",
+        %session.id,
+        %session.user_id,
+        user_agent.device_type = ?user_agent.as_ref().map(|ua| &ua.device_type),
+        user_agent.client_app = ?user_agent.as_ref().map(|ua| ua.client_app),
+        "Started a compatibility session"
+    );
+
     if let Some(user_agent) = user_agent {
         session = repo
             .compat_session()
@@ -267,6 +405,19 @@ pub(crate) async fn post(
             .await?;
     }
 
+    if let Some(public_key) = device_key {
+        if repo
+            .device_key()
+            .find_for_compat_session(&session)
+            .await?
+            .is_none()
+        {
+            repo.device_key()
+                .add_for_compat_session(&mut rng, &clock, &session, public_key)
+                .await?;
+        }
+    }
+
     let user_id = homeserver.mxid(&user.username);
 
     // If the client asked for a refreshable token, make it expire
@@ -383,8 +534,10 @@ async fn user_password_login(
     requester: RequesterFingerprint,
     repo: &mut BoxRepository,
     homeserver: &BoxHomeserverConnection,
+    site_config: &SiteConfig,
     username: String,
     password: String,
+    device: Option<Device>,
 ) -> Result<(CompatSession, User), RouteError> {
     // Find the user
     let user = repo
@@ -415,7 +568,29 @@ async fn user_password_login(
             user_password.hashed_password.clone(),
         )
         .await
-        .map_err(RouteError::PasswordVerificationFailed)?;
+        .map_err(|e| match e.downcast::<PasswordHashingBusyError>() {
+            Ok(e) => RouteError::PasswordHashingBusy(e),
+            Err(e) => RouteError::PasswordVerificationFailed(e),
+        })?;
+
+    // If an admin reset the password, the user has to change it through the
+    // browser before they can log in with it
+    if repo.user().is_password_change_required(&user).await? {
+        return Err(RouteError::PasswordChangeRequired);
+    }
+
+    // The same goes for passwords past the maximum age of the user's policy
+    let policy_class = repo.user().get_password_policy_class(&user).await?;
+    let expired = password_manager
+        .is_password_expired(
+            policy_class.as_deref(),
+            user_password.created_at,
+            clock.now(),
+        )
+        .map_err(|e| RouteError::Internal(Box::new(e)))?;
+    if expired {
+        return Err(RouteError::PasswordChangeRequired);
+    }
 
     if let Some((version, hashed_password)) = new_password_hash {
         // Save the upgraded password if needed
@@ -434,8 +609,13 @@ async fn user_password_login(
     // Lock the user sync to make sure we don't get into a race condition
     repo.user().acquire_lock_for_sync(&user).await?;
 
+    // Make room for the new session, if the user has too many of them
+    session_limits::enforce(repo, clock, &site_config.session_limits, &user, None).await?;
+
     // Now that the user credentials have been verified, start a new compat session
-    let device = Device::generate(&mut rng);
+    let device =
+        device_allocation::allocate_compat(&mut rng, clock, repo, site_config, &user, device)
+            .await?;
     let mxid = homeserver.mxid(&user.username);
     homeserver
         .create_device(&mxid, device.as_str())
@@ -447,18 +627,33 @@ async fn user_password_login(
         .add(&mut rng, clock, &user, device, None, false)
         .await?;
 
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            clock,
+            &user,
+            LoginMethod::Password,
+            true,
+            requester.ip(),
+            None,
+        )
+        .await?;
+
     Ok((session, user))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use hyper::Request;
     use mas_matrix::{HomeserverConnection, ProvisionRequest};
+    use mas_storage::compat::CompatSessionFilter;
     use rand::distributions::{Alphanumeric, DistString};
     use sqlx::PgPool;
 
     use super::*;
-    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+    use crate::test_utils::{setup, test_site_config, RequestBuilderExt, ResponseExt, TestState};
 
     /// Test that the server advertises the right login flows.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -539,6 +734,43 @@ mod tests {
         assert_eq!(body["errcode"], "M_UNRECOGNIZED");
     }
 
+    /// Test that the password login is neither advertised nor accepted once it
+    /// is turned off for the Matrix API
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_compat_password_login_disabled(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                compat_password_login: CompatPasswordLogin::Disabled,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        let request = Request::get("/_matrix/client/v3/login").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        let flows = body["flows"].as_array().unwrap();
+        assert!(flows.iter().all(|flow| flow["type"] != "m.login.password"));
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_UNRECOGNIZED");
+    }
+
     /// Test that a user can login with a password using the Matrix
     /// compatibility API.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -627,6 +859,49 @@ mod tests {
         assert!(body.refresh_token.is_some());
         assert!(body.expires_in_ms.is_some());
 
+        // Ask for a specific device, twice: the second login reuses the device and
+        // ends the first session
+        for _ in 0..2 {
+            let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "alice",
+                },
+                "password": "password",
+                "device_id": "MYDEVICE",
+            }));
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+
+            let body: ResponseBody = response.json();
+            assert_eq!(body.device_id.as_str(), "MYDEVICE");
+        }
+
+        let mut repo = state.repository().await.unwrap();
+        let device = Device::try_from("MYDEVICE".to_owned()).unwrap();
+        let filter = CompatSessionFilter::new()
+            .for_user(&user)
+            .for_device(&device)
+            .active_only();
+        assert_eq!(repo.compat_session().count(filter).await.unwrap(), 1);
+        repo.save().await.unwrap();
+
+        // Device IDs with invalid characters are rejected
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+            "device_id": "MY DEVICE",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
         // Try to login with a wrong password.
         let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
             "type": "m.login.password",
@@ -662,6 +937,155 @@ mod tests {
         assert_eq!(body, old_body);
     }
 
+    /// Test that a user who has to change their password can't log in with it
+    /// using the Matrix compatibility API.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_change_required(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(mxid, &user.sub))
+            .await
+            .unwrap();
+
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(
+                &mut state.rng(),
+                Zeroizing::new("password".to_owned().into_bytes()),
+            )
+            .await
+            .unwrap();
+
+        repo.user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.user()
+            .set_password_change_required(&state.clock, &user, true)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+        assert_eq!(
+            body["error"],
+            "The password must be changed, log in through the browser to do so"
+        );
+
+        // No session was started
+        let mut repo = state.repository().await.unwrap();
+        let filter = CompatSessionFilter::new().for_user(&user);
+        assert_eq!(repo.compat_session().count(filter).await.unwrap(), 0);
+        repo.save().await.unwrap();
+    }
+
+    /// Test that a user whose password expired can't log in with it using the
+    /// Matrix compatibility API.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_expired_password(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.password_manager = state.password_manager.clone().with_policy_classes(
+            Some(std::time::Duration::from_secs(90 * 24 * 60 * 60)),
+            HashMap::new(),
+        );
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(mxid, &user.sub))
+            .await
+            .unwrap();
+
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(
+                &mut state.rng(),
+                Zeroizing::new("password".to_owned().into_bytes()),
+            )
+            .await
+            .unwrap();
+
+        repo.user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        // The password is still fresh, so the login works
+        let response = state.request(request.clone()).await;
+        response.assert_status(StatusCode::OK);
+
+        // Once it gets too old, the user has to change it through the browser
+        state.clock.advance(Duration::try_days(91).unwrap());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+        assert_eq!(
+            body["error"],
+            "The password must be changed, log in through the browser to do so"
+        );
+    }
+
     /// Test that password logins are rate limited.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_password_login_rate_limit(pool: PgPool) {
@@ -713,6 +1137,45 @@ mod tests {
         assert_eq!(body["error"], "Too many login attempts");
     }
 
+    /// Test that logins are denied from outside the allowed network zones.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_login_network_policy(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.network_policy = NetworkPolicy::from_config(&mas_config::NetworkZonesConfig {
+            zones: vec![mas_config::NetworkZoneConfig {
+                name: "office".to_owned(),
+                cidrs: vec!["192.0.2.0/24".parse().unwrap()],
+                countries: Vec::new(),
+            }],
+            login: mas_config::NetworkPolicyConfig {
+                allow: vec!["office".to_owned()],
+                deny: Vec::new(),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+        request.extensions_mut().insert(RequestOrigin::new(
+            Some("198.51.100.1".parse().unwrap()),
+            None,
+        ));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+        assert_eq!(body["error"], "Logins are not allowed from this network");
+    }
+
     /// Test the response of an unsupported login flow.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_login(pool: PgPool) {
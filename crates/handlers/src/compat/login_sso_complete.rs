@@ -17,7 +17,7 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::Device;
+use mas_data_model::{Device, SiteConfig};
 use mas_matrix::BoxHomeserverConnection;
 use mas_router::{CompatLoginSsoAction, PostAuthAction, UrlBuilder};
 use mas_storage::{
@@ -28,7 +28,10 @@ use mas_templates::{CompatSsoContext, ErrorContext, TemplateContext, Templates};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
-use crate::PreferredLanguage;
+use crate::{
+    session_limits::{self, SessionLimitError},
+    PreferredLanguage,
+};
 
 #[derive(Serialize)]
 struct AllParams<'s> {
@@ -80,6 +83,15 @@ pub async fn get(
         return Ok((cookie_jar, url).into_response());
     };
 
+    if repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        let destination = mas_router::PasswordChangeRequired::and_continue_compat_sso_login(id);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
     // TODO: make that more generic, check that the email has been confirmed
     if session.user.primary_user_email_id.is_none() {
         let destination = mas_router::AccountAddEmail::default()
@@ -128,6 +140,7 @@ pub async fn post(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     Path(id): Path<Ulid>,
     Query(params): Query<Params>,
@@ -152,6 +165,15 @@ pub async fn post(
         return Ok((cookie_jar, url).into_response());
     };
 
+    if repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        let destination = mas_router::PasswordChangeRequired::and_continue_compat_sso_login(id);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
     // TODO: make that more generic
     if session.user.primary_user_email_id.is_none() {
         let destination = mas_router::AccountAddEmail::default()
@@ -196,6 +218,29 @@ pub async fn post(
     // Lock the user sync to make sure we don't get into a race condition
     repo.user().acquire_lock_for_sync(&session.user).await?;
 
+    // Make room for the new session, if the user has too many of them
+    match session_limits::enforce(
+        &mut repo,
+        &clock,
+        &site_config.session_limits,
+        &session.user,
+        None,
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(SessionLimitError::Exceeded) => {
+            let ctx = ErrorContext::new()
+                .with_code("compat_sso_login_session_limit")
+                .with_description("You have too many active sessions.".to_owned())
+                .with_language(&locale);
+
+            let content = templates.render_error(&ctx)?;
+            return Ok((cookie_jar, Html(content)).into_response());
+        }
+        Err(SessionLimitError::Repository(e)) => return Err(e.into()),
+    }
+
     let device = Device::generate(&mut rng);
     let mxid = homeserver.mxid(&session.user.username);
     homeserver
@@ -11,6 +11,7 @@ use mas_axum_utils::sentry::SentryEventID;
 use mas_data_model::{SiteConfig, TokenFormatError, TokenType};
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
+    device_key::DeviceKeyRepository,
     BoxClock, BoxRepository, BoxRng, Clock,
 };
 use serde::{Deserialize, Serialize};
@@ -18,7 +19,10 @@ use serde_with::{serde_as, DurationMilliSeconds};
 use thiserror::Error;
 
 use super::MatrixError;
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{
+    device_proof::{DeviceProof, DeviceProofError},
+    impl_from_error_for_route, BoundActivityTracker,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct RequestBody {
@@ -41,6 +45,9 @@ pub enum RouteError {
 
     #[error("unknown session")]
     UnknownSession,
+
+    #[error("invalid device proof")]
+    DeviceProof(#[from] DeviceProofError),
 }
 
 impl IntoResponse for RouteError {
@@ -57,6 +64,11 @@ impl IntoResponse for RouteError {
                 error: "Invalid refresh token",
                 status: StatusCode::UNAUTHORIZED,
             },
+            Self::DeviceProof(_) => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Invalid device proof",
+                status: StatusCode::FORBIDDEN,
+            },
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -87,6 +99,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(site_config): State<SiteConfig>,
+    device_proof: DeviceProof,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
     let token_type = TokenType::check(&input.refresh_token)?;
@@ -115,6 +128,12 @@ pub(crate) async fn post(
         return Err(RouteError::InvalidSession);
     }
 
+    // If the session is bound to a device key, the client must prove it still
+    // holds it
+    if let Some(device_key) = repo.device_key().find_for_compat_session(&session).await? {
+        device_proof.verify_bound_key(clock.now(), &device_key.public_key)?;
+    }
+
     activity_tracker
         .record_compat_session(&clock, &session)
         .await;
@@ -0,0 +1,274 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Allocate the Matrix device IDs of new sessions.
+//!
+//! Depending on the configured [`DeviceIdAllocation`], the device asked by the
+//! client is kept, replaced by a random one, or replaced by one derived from
+//! the client and the user. When reusing devices is enabled, a client
+//! authenticating again for a user gets the device of its previous session,
+//! which is then ended, so that web clients losing their local storage don't
+//! create a new device on the homeserver every time.
+
+use mas_data_model::{Client, Device, DeviceIdAllocation, SiteConfig, User};
+use mas_storage::{
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    BoxRepository, Clock, Pagination, RepositoryAccess, RepositoryError,
+};
+use oauth2_types::scope::Scope;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// How many of the latest sessions of a client are looked at to find a device
+/// to reuse
+const REUSE_LOOKBACK: usize = 20;
+
+/// Characters used in the device IDs derived from the client and the user
+const DETERMINISTIC_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Length of the device IDs derived from the client and the user
+const DETERMINISTIC_LENGTH: usize = 10;
+
+/// Derive a stable device ID from the client and the user
+fn deterministic_device(client: &Client, user: &User) -> Device {
+    let digest = Sha256::new()
+        .chain_update(client.id.to_bytes())
+        .chain_update(user.id.to_bytes())
+        .finalize();
+
+    let id: String = digest
+        .iter()
+        .take(DETERMINISTIC_LENGTH)
+        .map(|byte| {
+            char::from(DETERMINISTIC_CHARSET[usize::from(*byte) % DETERMINISTIC_CHARSET.len()])
+        })
+        .collect();
+
+    // SAFETY: the ID only has alphanumeric characters
+    let Ok(device) = Device::try_from(id) else {
+        unreachable!()
+    };
+    device
+}
+
+/// Find the device of the latest active session of the client with the user
+async fn latest_device(
+    repo: &mut BoxRepository,
+    client: &Client,
+    user: &User,
+) -> Result<Option<Device>, RepositoryError> {
+    let filter = OAuth2SessionFilter::new()
+        .for_user(user)
+        .for_client(client)
+        .active_only();
+
+    let page = repo
+        .oauth2_session()
+        .list(filter, Pagination::last(REUSE_LOOKBACK))
+        .await?;
+
+    Ok(page
+        .edges
+        .iter()
+        .rev()
+        .find_map(|session| session.scope.iter().find_map(Device::from_scope_token)))
+}
+
+/// Whether another client has an active session with the given device for the
+/// user
+async fn used_by_another_client(
+    repo: &mut BoxRepository,
+    client: &Client,
+    user: &User,
+    device: &Device,
+) -> Result<bool, RepositoryError> {
+    let filter = OAuth2SessionFilter::new()
+        .for_user(user)
+        .for_device(device)
+        .active_only();
+
+    let page = repo
+        .oauth2_session()
+        .list(filter, Pagination::first(REUSE_LOOKBACK))
+        .await?;
+
+    if page
+        .edges
+        .iter()
+        .any(|session| session.client_id != client.id)
+    {
+        return Ok(true);
+    }
+
+    // Compatibility sessions don't have a client
+    let filter = CompatSessionFilter::new()
+        .for_user(user)
+        .for_device(device)
+        .active_only();
+
+    Ok(repo.compat_session().count(filter).await? > 0)
+}
+
+/// Allocate the device of a new OAuth 2.0 session of the client with the
+/// user, and return the scope of the session with that device
+///
+/// Scopes which don't ask for a device are left untouched. When the device
+/// of an existing session of the client is reused, that session is ended.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn allocate(
+    rng: &mut (impl RngCore + CryptoRng + Send),
+    clock: &dyn Clock,
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    client: &Client,
+    user: &User,
+    scope: Scope,
+) -> Result<Scope, RepositoryError> {
+    let Some(requested) = scope.iter().find_map(Device::from_scope_token) else {
+        return Ok(scope);
+    };
+
+    let reused = if site_config.reuse_devices {
+        latest_device(repo, client, user).await?
+    } else {
+        None
+    };
+
+    let (device, take_over) = if let Some(device) = reused {
+        (device, true)
+    } else {
+        match site_config.device_id_allocation {
+            DeviceIdAllocation::ClientProvided => {
+                if used_by_another_client(repo, client, user, &requested).await? {
+                    tracing::warn!(
+                        %client.id,
+                        %user.id,
+                        device.id = requested.as_str(),
+                        "The device asked by the client is used by another client, allocating a new one"
+                    );
+                    (Device::generate(rng), false)
+                } else {
+                    (requested, false)
+                }
+            }
+            DeviceIdAllocation::Random => (Device::generate(rng), false),
+            DeviceIdAllocation::Deterministic => (deterministic_device(client, user), true),
+        }
+    };
+
+    if take_over {
+        // The previous sessions of the client with that device are replaced by the
+        // new one. The device itself stays on the homeserver, as the new session
+        // uses it.
+        let filter = OAuth2SessionFilter::new()
+            .for_user(user)
+            .for_client(client)
+            .for_device(&device)
+            .active_only();
+        let ended = repo.oauth2_session().finish_bulk(clock, filter).await?;
+        if ended > 0 {
+            tracing::info!(
+                %client.id,
+                %user.id,
+                device.id = device.as_str(),
+                "Ended {ended} previous sessions of the client to reuse their device"
+            );
+        }
+    }
+
+    Ok(scope
+        .iter()
+        .filter(|token| Device::from_scope_token(token).is_none())
+        .cloned()
+        .chain(std::iter::once(device.to_scope_token()))
+        .collect())
+}
+
+/// Allocate the device of a new compatibility session of the user, given the
+/// device ID the client asked for, if any
+///
+/// With the [`DeviceIdAllocation::ClientProvided`] strategy, the asked device
+/// is used unless an OAuth 2.0 session uses it, and the existing compatibility
+/// sessions with that device are ended, as the Matrix specification requires.
+/// Other strategies always allocate a random device, as there is no client to
+/// derive a device from.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn allocate_compat(
+    rng: &mut (impl RngCore + CryptoRng + Send),
+    clock: &dyn Clock,
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    user: &User,
+    requested: Option<Device>,
+) -> Result<Device, RepositoryError> {
+    let Some(requested) = requested else {
+        return Ok(Device::generate(rng));
+    };
+
+    if site_config.device_id_allocation != DeviceIdAllocation::ClientProvided {
+        return Ok(Device::generate(rng));
+    }
+
+    let filter = OAuth2SessionFilter::new()
+        .for_user(user)
+        .for_device(&requested)
+        .active_only();
+    if repo.oauth2_session().count(filter).await? > 0 {
+        tracing::warn!(
+            %user.id,
+            device.id = requested.as_str(),
+            "The device asked by the client is used by an OAuth 2.0 session, allocating a new one"
+        );
+        return Ok(Device::generate(rng));
+    }
+
+    let filter = CompatSessionFilter::new()
+        .for_user(user)
+        .for_device(&requested)
+        .active_only();
+    repo.compat_session().finish_bulk(clock, filter).await?;
+
+    Ok(requested)
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::clock::MockClock;
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    fn test_deterministic_device() {
+        let now = MockClock::default().now();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let client = Client::samples(now, &mut rng).remove(0);
+        let user = User::samples(now, &mut rng).remove(0);
+
+        let device = deterministic_device(&client, &user);
+        assert_eq!(device.as_str().len(), DETERMINISTIC_LENGTH);
+        assert!(device.as_str().chars().all(|c| c.is_ascii_alphanumeric()));
+
+        // The device is stable for the same client and user
+        assert_eq!(device, deterministic_device(&client, &user));
+
+        // But differs for another user
+        let other_user = User {
+            id: Ulid::from_parts(0, 1),
+            ..user
+        };
+        assert_ne!(device, deterministic_device(&client, &other_user));
+    }
+}
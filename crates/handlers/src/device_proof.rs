@@ -0,0 +1,313 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Proofs of possession of a device key, binding sessions to a device.
+//!
+//! Mobile clients can generate a key pair on the device and send a proof signed
+//! with its private key when logging in. The public key embedded in that proof
+//! is then bound to the session, and every refresh of the tokens of the
+//! session needs a new proof signed with the same key. This way, tokens
+//! exfiltrated from the device can't be refreshed elsewhere.
+//!
+//! A proof is a JWT sent in the `MAS-Device-Proof` header, modelled after
+//! [DPoP]. Its type is `device-proof+jwt`, the proof sent when logging in has
+//! the public key in its `jwk` header, and its payload has:
+//!
+//!  - `htm`, the method of the request
+//!  - `htu`, the URL of the request. Only its path is checked, as the
+//!    compatibility endpoints are usually served on the homeserver's domain
+//!  - `iat`, the time at which it was created, which must be within a minute of
+//!    the time of the server
+//!  - `jti`, a unique identifier. A proof is only accepted once: the identifiers
+//!    are remembered until the proof is too old to be accepted anyway. They are
+//!    kept in memory, so each instance of the service only knows about its own
+//!
+//! [DPoP]: https://www.rfc-editor.org/rfc/rfc9449
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, OriginalUri},
+    http::{request::Parts, HeaderName, Method},
+};
+use chrono::{DateTime, Duration, Utc};
+use mas_jose::{
+    jwa::AsymmetricVerifyingKey,
+    jwk::PublicJsonWebKey,
+    jwt::{Jwt, JwtDecodeError},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+/// The header in which the proof is sent
+pub(crate) static DEVICE_PROOF_HEADER: HeaderName = HeaderName::from_static("mas-device-proof");
+
+/// The type of the proof JWTs
+const PROOF_TYPE: &str = "device-proof+jwt";
+
+/// How far the `iat` of a proof can be from the time of the server
+const MAX_CLOCK_SKEW: Duration = Duration::seconds(60);
+
+#[derive(Debug, Error)]
+pub(crate) enum DeviceProofError {
+    #[error("missing device proof")]
+    Missing,
+
+    #[error("could not decode device proof")]
+    Decode(#[from] JwtDecodeError),
+
+    #[error("device proof has the wrong type")]
+    WrongType,
+
+    #[error("device proof does not embed a public key")]
+    MissingKey,
+
+    #[error("device proof signature is invalid")]
+    InvalidSignature,
+
+    #[error("device proof is for another request")]
+    WrongRequest,
+
+    #[error("device proof is too old or in the future")]
+    Stale,
+
+    #[error("device proof was already used")]
+    Replayed,
+}
+
+#[derive(Deserialize)]
+struct ProofClaims {
+    htm: String,
+    htu: Url,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    iat: DateTime<Utc>,
+    jti: String,
+}
+
+/// The device proofs which were recently accepted, to reject them if they are
+/// replayed
+#[derive(Debug, Clone, Default)]
+pub struct DeviceProofReplayCache {
+    seen: Arc<Mutex<HashMap<[u8; 32], DateTime<Utc>>>>,
+}
+
+impl DeviceProofReplayCache {
+    /// Create an empty cache
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the proof with the given identifier, signed by the given key,
+    /// until it expires. Returns `false` if it was already seen.
+    fn remember(
+        &self,
+        key: &PublicJsonWebKey,
+        jti: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> bool {
+        // The identifiers are scoped to the key, as they are chosen by the clients
+        let key = serde_json::to_vec(key).unwrap_or_default();
+        let digest: [u8; 32] = Sha256::new()
+            .chain_update(&key)
+            .chain_update([0])
+            .chain_update(jti.as_bytes())
+            .finalize()
+            .into();
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expires_at| *expires_at > now);
+        seen.insert(digest, expires_at).is_none()
+    }
+}
+
+/// The device proof sent with a request, if any, along with the request it has
+/// to be bound to
+pub(crate) struct DeviceProof {
+    proof: Option<String>,
+    method: Method,
+    path: String,
+    replay_cache: DeviceProofReplayCache,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DeviceProof
+where
+    S: Send + Sync,
+    DeviceProofReplayCache: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let proof = parts
+            .headers
+            .get(&DEVICE_PROOF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        // The router may have stripped a prefix from the URI
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(&parts.uri, |OriginalUri(uri)| uri)
+            .path()
+            .to_owned();
+
+        Ok(Self {
+            proof,
+            method: parts.method.clone(),
+            path,
+            replay_cache: DeviceProofReplayCache::from_ref(state),
+        })
+    }
+}
+
+impl DeviceProof {
+    /// Verify the proof sent when logging in, if any, and return the public key
+    /// which should be bound to the new session
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a proof was sent but is invalid
+    pub fn verify_new_key(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Option<PublicJsonWebKey>, DeviceProofError> {
+        if self.proof.is_none() {
+            return Ok(None);
+        }
+
+        self.verify(now, None).map(Some)
+    }
+
+    /// Verify the proof sent when refreshing the tokens of a session bound to
+    /// the given key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no proof was sent, or if it is invalid or not signed
+    /// with the given key
+    pub fn verify_bound_key(
+        &self,
+        now: DateTime<Utc>,
+        key: &PublicJsonWebKey,
+    ) -> Result<(), DeviceProofError> {
+        self.verify(now, Some(key))?;
+        Ok(())
+    }
+
+    fn verify(
+        &self,
+        now: DateTime<Utc>,
+        key: Option<&PublicJsonWebKey>,
+    ) -> Result<PublicJsonWebKey, DeviceProofError> {
+        let proof = self.proof.as_deref().ok_or(DeviceProofError::Missing)?;
+        let jwt: Jwt<'_, ProofClaims> = Jwt::try_from(proof)?;
+
+        if jwt.header().typ() != Some(PROOF_TYPE) {
+            return Err(DeviceProofError::WrongType);
+        }
+
+        let key = match key {
+            Some(key) => key,
+            None => jwt.header().jwk().ok_or(DeviceProofError::MissingKey)?,
+        };
+
+        let verifying_key =
+            AsymmetricVerifyingKey::from_jwk_and_alg(key.params(), jwt.header().alg())
+                .map_err(|_| DeviceProofError::InvalidSignature)?;
+        jwt.verify(&verifying_key)
+            .map_err(|_| DeviceProofError::InvalidSignature)?;
+
+        let claims = jwt.payload();
+        if claims.htm != self.method.as_str() || claims.htu.path() != self.path {
+            return Err(DeviceProofError::WrongRequest);
+        }
+
+        if claims.iat < now - MAX_CLOCK_SKEW || claims.iat > now + MAX_CLOCK_SKEW {
+            return Err(DeviceProofError::Stale);
+        }
+
+        // The proof can't be accepted anymore once its `iat` is too far in the past
+        if !self
+            .replay_cache
+            .remember(key, &claims.jti, now, claims.iat + MAX_CLOCK_SKEW)
+        {
+            return Err(DeviceProofError::Replayed);
+        }
+
+        Ok(key.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::{DateTime, Utc};
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{
+        jwk::{JsonWebKey, JsonWebKeySet, PublicJsonWebKey},
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_keystore::{Keystore, PrivateKey};
+    use rand::{CryptoRng, RngCore};
+
+    /// A key pair generated on a device, to sign device proofs in tests
+    pub(crate) struct DeviceKeyPair {
+        keystore: Keystore,
+        proofs: AtomicUsize,
+    }
+
+    impl DeviceKeyPair {
+        pub fn generate(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+            let key = JsonWebKey::new(PrivateKey::generate_ec_p256(rng));
+            Self {
+                keystore: Keystore::new(JsonWebKeySet::new(vec![key])),
+                proofs: AtomicUsize::new(0),
+            }
+        }
+
+        pub fn public_key(&self) -> PublicJsonWebKey {
+            self.keystore.public_jwks()[0].clone()
+        }
+
+        /// Sign a proof for a request, with a new identifier
+        pub fn proof(
+            &self,
+            now: DateTime<Utc>,
+            method: &str,
+            url: &str,
+            embed_key: bool,
+        ) -> String {
+            let alg = JsonWebSignatureAlg::Es256;
+            let key = self.keystore.signing_key_for_algorithm(&alg).unwrap();
+            let signer = key.params().signing_key_for_alg(&alg).unwrap();
+
+            let mut header =
+                JsonWebSignatureHeader::new(alg).with_typ(super::PROOF_TYPE.to_owned());
+            if embed_key {
+                header = header.with_jwk(self.public_key());
+            }
+
+            let claims = serde_json::json!({
+                "htm": method,
+                "htu": url,
+                "iat": now.timestamp(),
+                "jti": format!("proof-{}", self.proofs.fetch_add(1, Ordering::Relaxed)),
+            });
+
+            Jwt::sign(header, claims, &signer).unwrap().into_string()
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Verify the email addresses of users, with a code sent to them.
+//!
+//! The code can either be typed in by the user, or submitted by clicking the
+//! link in the verification email. Both ways go through [`verify_code`], so
+//! that they share the same attempt limits and expiry handling, and sending
+//! codes is throttled by [`send_code`].
+
+use anyhow::Context;
+use mas_data_model::{UserEmail, UserEmailVerificationState};
+use mas_storage::{
+    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    user::{UserEmailRepository, UserRepository},
+    BoxRepository, Clock, RepositoryAccess,
+};
+use thiserror::Error;
+
+use crate::{rate_limit::EmailVerificationLimitedError, Limiter};
+
+#[derive(Debug, Error)]
+pub(crate) enum SendCodeError {
+    #[error(transparent)]
+    RateLimited(#[from] EmailVerificationLimitedError),
+
+    #[error(transparent)]
+    Repository(#[from] mas_storage::RepositoryError),
+}
+
+/// The result of submitting a verification code
+#[derive(Debug)]
+pub(crate) enum VerificationOutcome {
+    /// The email address was just verified
+    Verified(UserEmail),
+
+    /// The email address was already verified before
+    AlreadyVerified(UserEmail),
+
+    /// The code doesn't exist, or was already used
+    InvalidCode,
+
+    /// The code has expired, a new one has to be sent
+    ExpiredCode,
+
+    /// Too many codes were submitted for this email address recently
+    TooManyAttempts,
+}
+
+/// Schedule a job sending a new verification code to an email address
+///
+/// The caller is responsible for saving the repository.
+///
+/// # Errors
+///
+/// Returns an error if too many codes were sent to this email address
+/// recently, or if the job could not be scheduled
+pub(crate) async fn send_code(
+    repo: &mut BoxRepository,
+    limiter: &Limiter,
+    user_email: &UserEmail,
+    language: Option<String>,
+) -> Result<(), SendCodeError> {
+    limiter.check_email_verification_resend(user_email)?;
+
+    let job = VerifyEmailJob::new(user_email);
+    let job = if let Some(language) = language {
+        job.with_language(language)
+    } else {
+        job
+    };
+
+    repo.job().schedule_job(job).await?;
+
+    Ok(())
+}
+
+/// Check a verification code submitted for an email address, and mark the
+/// address as verified if it is valid
+///
+/// The caller is responsible for saving the repository.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn verify_code(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    limiter: &Limiter,
+    user_email: UserEmail,
+    code: &str,
+) -> Result<VerificationOutcome, anyhow::Error> {
+    if user_email.confirmed_at.is_some() {
+        return Ok(VerificationOutcome::AlreadyVerified(user_email));
+    }
+
+    if let Err(e) = limiter.check_email_verification_attempt(&user_email) {
+        tracing::warn!(error = &e as &dyn std::error::Error);
+        return Ok(VerificationOutcome::TooManyAttempts);
+    }
+
+    let verification = repo
+        .user_email()
+        .find_verification_code(clock, &user_email, code.trim())
+        .await?;
+
+    let Some(verification) = verification else {
+        return Ok(VerificationOutcome::InvalidCode);
+    };
+
+    match verification.state {
+        UserEmailVerificationState::Valid => {}
+        UserEmailVerificationState::AlreadyUsed { .. } => {
+            return Ok(VerificationOutcome::InvalidCode)
+        }
+        UserEmailVerificationState::Expired { .. } => return Ok(VerificationOutcome::ExpiredCode),
+    }
+
+    repo.user_email()
+        .consume_verification_code(clock, verification)
+        .await?;
+
+    let user = repo
+        .user()
+        .lookup(user_email.user_id)
+        .await?
+        .context("Failed to load user")?;
+
+    if user.primary_user_email_id.is_none() {
+        repo.user_email().set_as_primary(&user_email).await?;
+    }
+
+    let user_email = repo
+        .user_email()
+        .mark_as_verified(clock, user_email)
+        .await?;
+
+    repo.job()
+        .schedule_job(ProvisionUserJob::new(&user))
+        .await?;
+
+    Ok(VerificationOutcome::Verified(user_email))
+}
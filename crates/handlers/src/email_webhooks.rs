@@ -0,0 +1,425 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Webhooks receiving bounce and complaint notifications from the mail
+//! provider
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::UndeliverableEmailReason;
+use mas_storage::{BoxClock, BoxRepository};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::impl_from_error_for_route;
+
+/// The secret token the mail provider must pass to call the webhooks
+#[derive(Clone)]
+pub(crate) struct WebhookSecret(pub Arc<str>);
+
+#[derive(Deserialize)]
+pub(crate) struct Params {
+    token: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Missing or invalid token")]
+    InvalidToken,
+
+    #[error("Invalid payload")]
+    InvalidPayload(#[source] serde_json::Error),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+        };
+
+        (status, SentryEventID::from(event_id), self.to_string()).into_response()
+    }
+}
+
+/// Compare the given token with the expected one, in constant time
+fn check_token(secret: &WebhookSecret, params: &Params) -> Result<(), RouteError> {
+    let expected = secret.0.as_bytes();
+    let given = params
+        .token
+        .as_deref()
+        .ok_or(RouteError::InvalidToken)?
+        .as_bytes();
+
+    let diff = expected
+        .iter()
+        .zip(given)
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+    if expected.len() == given.len() && diff == 0 {
+        Ok(())
+    } else {
+        Err(RouteError::InvalidToken)
+    }
+}
+
+/// The SNS envelope in which SES notifications are delivered
+#[derive(Deserialize)]
+#[serde(tag = "Type")]
+enum SnsMessage {
+    SubscriptionConfirmation {
+        #[serde(rename = "SubscribeURL")]
+        subscribe_url: String,
+    },
+
+    Notification {
+        #[serde(rename = "Message")]
+        message: String,
+    },
+
+    UnsubscribeConfirmation {},
+}
+
+/// An SES notification, either from the legacy notifications or from event
+/// publishing
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesNotification {
+    #[serde(alias = "eventType")]
+    notification_type: String,
+
+    #[serde(default)]
+    bounce: Option<SesBounce>,
+
+    #[serde(default)]
+    complaint: Option<SesComplaint>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesBounce {
+    bounce_type: String,
+    bounced_recipients: Vec<SesRecipient>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesComplaint {
+    complained_recipients: Vec<SesRecipient>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesRecipient {
+    email_address: String,
+
+    #[serde(default)]
+    diagnostic_code: Option<String>,
+}
+
+/// Receive SES bounce and complaint notifications, delivered through SNS
+#[tracing::instrument(name = "handlers.email_webhooks.ses", skip_all, err)]
+pub(crate) async fn ses(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    Extension(secret): Extension<WebhookSecret>,
+    Query(params): Query<Params>,
+    // SNS sends its payloads with a text/plain content type
+    body: String,
+) -> Result<StatusCode, RouteError> {
+    check_token(&secret, &params)?;
+
+    let message: SnsMessage = serde_json::from_str(&body).map_err(RouteError::InvalidPayload)?;
+    let message = match message {
+        SnsMessage::SubscriptionConfirmation { subscribe_url } => {
+            warn!(
+                %subscribe_url,
+                "Received an SNS subscription confirmation request, visit the URL to confirm it"
+            );
+            return Ok(StatusCode::OK);
+        }
+        SnsMessage::UnsubscribeConfirmation {} => return Ok(StatusCode::OK),
+        SnsMessage::Notification { message } => message,
+    };
+
+    let notification: SesNotification =
+        serde_json::from_str(&message).map_err(RouteError::InvalidPayload)?;
+
+    let undeliverable: Vec<_> = match notification.notification_type.as_str() {
+        // Transient bounces are retried by SES itself
+        "Bounce" => notification
+            .bounce
+            .filter(|b| b.bounce_type == "Permanent")
+            .map(|b| {
+                b.bounced_recipients
+                    .into_iter()
+                    .map(|r| (r, UndeliverableEmailReason::Bounce))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        "Complaint" => notification
+            .complaint
+            .map(|c| {
+                c.complained_recipients
+                    .into_iter()
+                    .map(|r| (r, UndeliverableEmailReason::Complaint))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    for (recipient, reason) in undeliverable {
+        info!(
+            email = recipient.email_address,
+            reason = reason.as_str(),
+            "Marking email address as undeliverable"
+        );
+        repo.email_queue()
+            .mark_undeliverable(
+                &clock,
+                &recipient.email_address,
+                reason,
+                recipient.diagnostic_code,
+            )
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// An event from the `SendGrid` Event Webhook
+#[derive(Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub(crate) enum SendgridEvent {
+    Bounce {
+        email: String,
+
+        /// Either `bounce` or `blocked`. Blocked messages are temporary
+        /// failures, which don't make the address undeliverable.
+        #[serde(default, rename = "type")]
+        kind: Option<String>,
+
+        #[serde(default)]
+        reason: Option<String>,
+    },
+
+    #[serde(rename = "spamreport")]
+    SpamReport { email: String },
+
+    #[serde(other)]
+    Other,
+}
+
+/// Receive events from the `SendGrid` Event Webhook
+#[tracing::instrument(name = "handlers.email_webhooks.sendgrid", skip_all, err)]
+pub(crate) async fn sendgrid(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    Extension(secret): Extension<WebhookSecret>,
+    Query(params): Query<Params>,
+    Json(events): Json<Vec<SendgridEvent>>,
+) -> Result<StatusCode, RouteError> {
+    check_token(&secret, &params)?;
+
+    for event in events {
+        let (email, reason, details) = match event {
+            SendgridEvent::Bounce {
+                email,
+                kind,
+                reason,
+            } if kind.as_deref() != Some("blocked") => {
+                (email, UndeliverableEmailReason::Bounce, reason)
+            }
+            SendgridEvent::SpamReport { email } => {
+                (email, UndeliverableEmailReason::Complaint, None)
+            }
+            SendgridEvent::Bounce { .. } | SendgridEvent::Other => continue,
+        };
+
+        info!(
+            email,
+            reason = reason.as_str(),
+            "Marking email address as undeliverable"
+        );
+        repo.email_queue()
+            .mark_undeliverable(&clock, &email, reason, details)
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::UndeliverableEmailReason;
+    use mas_router::SimpleRoute;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_sendgrid(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let events = serde_json::json!([
+            { "email": "bounced@example.com", "event": "bounce", "type": "bounce", "reason": "550 5.1.1 User unknown" },
+            { "email": "blocked@example.com", "event": "bounce", "type": "blocked" },
+            { "email": "spam@example.com", "event": "spamreport" },
+            { "email": "delivered@example.com", "event": "delivered" },
+        ]);
+
+        // Without the token
+        let request = Request::post(mas_router::EmailWebhookSendgrid::PATH).json(&events);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // With a wrong token
+        let request = Request::post(format!(
+            "{}?token=wrong",
+            mas_router::EmailWebhookSendgrid::PATH
+        ))
+        .json(&events);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        let request = Request::post(format!(
+            "{}?token=webhook-secret",
+            mas_router::EmailWebhookSendgrid::PATH
+        ))
+        .json(&events);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let bounced = repo
+            .email_queue()
+            .find_undeliverable("bounced@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bounced.reason, UndeliverableEmailReason::Bounce);
+        assert_eq!(bounced.details.as_deref(), Some("550 5.1.1 User unknown"));
+
+        let spam = repo
+            .email_queue()
+            .find_undeliverable("spam@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(spam.reason, UndeliverableEmailReason::Complaint);
+
+        for email in ["blocked@example.com", "delivered@example.com"] {
+            assert!(repo
+                .email_queue()
+                .find_undeliverable(email)
+                .await
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_ses(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let uri = format!("{}?token=webhook-secret", mas_router::EmailWebhookSes::PATH);
+
+        // Subscription confirmations are acknowledged
+        let request = Request::post(&uri)
+            .body(
+                serde_json::json!({
+                    "Type": "SubscriptionConfirmation",
+                    "SubscribeURL": "https://sns.example.com/confirm",
+                })
+                .to_string(),
+            )
+            .unwrap();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let bounce = serde_json::json!({
+            "notificationType": "Bounce",
+            "bounce": {
+                "bounceType": "Permanent",
+                "bouncedRecipients": [
+                    { "emailAddress": "bounced@example.com", "diagnosticCode": "smtp; 550 5.1.1 User unknown" },
+                ],
+            },
+        });
+        let transient = serde_json::json!({
+            "notificationType": "Bounce",
+            "bounce": {
+                "bounceType": "Transient",
+                "bouncedRecipients": [{ "emailAddress": "full@example.com" }],
+            },
+        });
+        let complaint = serde_json::json!({
+            "eventType": "Complaint",
+            "complaint": {
+                "complainedRecipients": [{ "emailAddress": "spam@example.com" }],
+            },
+        });
+
+        for message in [bounce, transient, complaint] {
+            let request = Request::post(&uri)
+                .body(
+                    serde_json::json!({
+                        "Type": "Notification",
+                        "Message": message.to_string(),
+                    })
+                    .to_string(),
+                )
+                .unwrap();
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+        }
+
+        let mut repo = state.repository().await.unwrap();
+        let bounced = repo
+            .email_queue()
+            .find_undeliverable("bounced@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bounced.reason, UndeliverableEmailReason::Bounce);
+
+        let spam = repo
+            .email_queue()
+            .find_undeliverable("spam@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(spam.reason, UndeliverableEmailReason::Complaint);
+
+        assert!(repo
+            .email_queue()
+            .find_undeliverable("full@example.com")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Assign visitors of the login and registration pages to the variants of the
+//! A/B experiments, and record their exposures and conversions.
+//!
+//! Visitors are identified by a random ID kept in a cookie, so that anonymous
+//! visitors keep the same variants until they log in or register.
+
+use std::collections::BTreeMap;
+
+use mas_axum_utils::cookies::CookieJar;
+use mas_data_model::FeatureFlags;
+use mas_storage::{Clock, RepositoryAccess};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+/// Name of the cookie holding the ID of the visitor
+static COOKIE_NAME: &str = "visitor";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Visitor {
+    id: Ulid,
+}
+
+/// The variants of the running experiments a visitor is assigned to
+#[derive(Debug)]
+pub(crate) struct Experiments {
+    visitor_id: Ulid,
+    variants: BTreeMap<String, String>,
+}
+
+impl Experiments {
+    /// Assign the visitor to the variants of the running experiments
+    ///
+    /// A visitor ID is generated and saved in a cookie if the visitor doesn't
+    /// have one yet, unless no experiment is running.
+    pub fn load(
+        cookie_jar: CookieJar,
+        clock: &impl Clock,
+        rng: &mut (impl RngCore + Send),
+        feature_flags: &FeatureFlags,
+    ) -> (Self, CookieJar) {
+        let visitor = match cookie_jar.load::<Visitor>(COOKIE_NAME) {
+            Ok(visitor) => visitor,
+            Err(e) => {
+                tracing::warn!("Invalid visitor cookie: {}", e);
+                None
+            }
+        };
+
+        let (visitor_id, new) = match visitor {
+            Some(visitor) => (visitor.id, false),
+            None => (
+                Ulid::from_datetime_with_source(clock.now().into(), rng),
+                true,
+            ),
+        };
+
+        let variants = feature_flags.experiment_variants(visitor_id);
+        let cookie_jar = if new && !variants.is_empty() {
+            cookie_jar.save(COOKIE_NAME, &Visitor { id: visitor_id }, true)
+        } else {
+            cookie_jar
+        };
+
+        (
+            Self {
+                visitor_id,
+                variants,
+            },
+            cookie_jar,
+        )
+    }
+
+    /// The variants the visitor is assigned to, keyed by experiment
+    pub fn variants(&self) -> BTreeMap<String, String> {
+        self.variants.clone()
+    }
+
+    /// Record that the visitor was shown their variants
+    pub async fn record_exposures<E>(
+        &self,
+        repo: &mut impl RepositoryAccess<Error = E>,
+        clock: &impl Clock,
+    ) -> Result<(), E> {
+        for (experiment, variant) in &self.variants {
+            repo.stats()
+                .record_experiment_exposure(clock, self.visitor_id, experiment, variant)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Record that the visitor logged in or registered
+    pub async fn record_conversion<E>(
+        &self,
+        repo: &mut impl RepositoryAccess<Error = E>,
+        clock: &impl Clock,
+    ) -> Result<(), E> {
+        if self.variants.is_empty() {
+            return Ok(());
+        }
+
+        repo.stats()
+            .record_experiment_conversion(clock, self.visitor_id)
+            .await
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Batch the loading of the objects referenced by a page of results, so that
+//! resolving a page of sessions doesn't run a query per session for their
+//! user, client or browser session.
+//!
+//! The loaders are shared by all requests, so they don't cache anything: they
+//! only group the lookups happening at the same time in a single query.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use mas_data_model::{Authentication, BrowserSession, Client, User};
+use mas_storage::{BoxRepository, RepositoryAccess, RepositoryError};
+use mas_storage_pg::PgRepository;
+use sqlx::PgPool;
+use ulid::Ulid;
+
+async fn repository(pool: &PgPool) -> Result<BoxRepository, RepositoryError> {
+    let repo = PgRepository::from_pool(pool)
+        .await
+        .map_err(RepositoryError::from_error)?;
+
+    Ok(repo.boxed())
+}
+
+/// Load [`User`]s by their ID
+pub struct UserLoader(PgPool);
+
+impl Loader<Ulid> for UserLoader {
+    type Value = User;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = repository(&self.0).await?;
+        let users = repo
+            .user()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(users.into_iter().collect())
+    }
+}
+
+/// Load OAuth 2.0 [`Client`]s by their ID
+pub struct OAuth2ClientLoader(PgPool);
+
+impl Loader<Ulid> for OAuth2ClientLoader {
+    type Value = Client;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = repository(&self.0).await?;
+        let clients = repo
+            .oauth2_client()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(clients.into_iter().collect())
+    }
+}
+
+/// Load [`BrowserSession`]s by their ID
+pub struct BrowserSessionLoader(PgPool);
+
+impl Loader<Ulid> for BrowserSessionLoader {
+    type Value = BrowserSession;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = repository(&self.0).await?;
+        let sessions = repo
+            .browser_session()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(sessions.into_iter().collect())
+    }
+}
+
+/// Load the last [`Authentication`] of [`BrowserSession`]s, by session ID
+pub struct LastAuthenticationLoader(PgPool);
+
+impl Loader<Ulid> for LastAuthenticationLoader {
+    type Value = Authentication;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = repository(&self.0).await?;
+        let authentications = repo
+            .browser_session()
+            .load_last_authentications(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(authentications.into_iter().collect())
+    }
+}
+
+/// The data loaders available to the resolvers
+pub struct Loaders {
+    pub users: DataLoader<UserLoader>,
+    pub oauth2_clients: DataLoader<OAuth2ClientLoader>,
+    pub browser_sessions: DataLoader<BrowserSessionLoader>,
+    pub last_authentications: DataLoader<LastAuthenticationLoader>,
+}
+
+impl Loaders {
+    #[must_use]
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            users: DataLoader::new(UserLoader(pool.clone()), tokio::spawn),
+            oauth2_clients: DataLoader::new(OAuth2ClientLoader(pool.clone()), tokio::spawn),
+            browser_sessions: DataLoader::new(BrowserSessionLoader(pool.clone()), tokio::spawn),
+            last_authentications: DataLoader::new(
+                LastAuthenticationLoader(pool.clone()),
+                tokio::spawn,
+            ),
+        }
+    }
+}
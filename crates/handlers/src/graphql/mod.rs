@@ -33,7 +33,7 @@ use mas_data_model::{BrowserSession, Session, SiteConfig, User};
 use mas_matrix::HomeserverConnection;
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
 use mas_storage::{BoxClock, BoxRepository, BoxRng, Clock, RepositoryError, SystemClock};
-use mas_storage_pg::PgRepository;
+use mas_storage_pg::{PgRepository, TokenHasher};
 use opentelemetry_semantic_conventions::trace::{GRAPHQL_DOCUMENT, GRAPHQL_OPERATION_NAME};
 use rand::{thread_rng, SeedableRng};
 use rand_chacha::ChaChaRng;
@@ -41,22 +41,34 @@ use sqlx::PgPool;
 use tracing::{info_span, Instrument};
 use ulid::Ulid;
 
+mod loaders;
 mod model;
 mod mutations;
 mod query;
 mod state;
 
-pub use self::state::{BoxState, State};
+pub use self::{
+    loaders::Loaders,
+    state::{BoxState, State},
+};
 use self::{
     model::{CreationEvent, Node},
     mutations::Mutation,
     query::Query,
 };
-use crate::{impl_from_error_for_route, passwords::PasswordManager, BoundActivityTracker};
+use crate::{
+    admin::role::AdminRole, impl_from_error_for_route, passwords::PasswordManager,
+    BoundActivityTracker, Limiter, NetworkPolicy, NetworkPolicyError, NetworkScope, RequestOrigin,
+    RequesterFingerprint,
+};
 
 #[cfg(test)]
 mod tests;
 
+/// The maximum size of files uploaded through multipart requests. Mutations
+/// accepting files may enforce a lower limit.
+const MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+
 /// Extra parameters we get from the listener configuration, because they are
 /// per-listener options. We pass them through request extensions.
 #[derive(Debug, Clone)]
@@ -70,6 +82,8 @@ struct GraphQLState {
     policy_factory: Arc<PolicyFactory>,
     site_config: SiteConfig,
     password_manager: PasswordManager,
+    limiter: Limiter,
+    token_hasher: TokenHasher,
 }
 
 #[async_trait]
@@ -77,7 +91,8 @@ impl state::State for GraphQLState {
     async fn repository(&self) -> Result<BoxRepository, RepositoryError> {
         let repo = PgRepository::from_pool(&self.pool)
             .await
-            .map_err(RepositoryError::from_error)?;
+            .map_err(RepositoryError::from_error)?
+            .with_token_hasher(self.token_hasher.clone());
 
         Ok(repo.boxed())
     }
@@ -94,6 +109,10 @@ impl state::State for GraphQLState {
         &self.site_config
     }
 
+    fn limiter(&self) -> &Limiter {
+        &self.limiter
+    }
+
     fn homeserver_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error> {
         self.homeserver_connection.as_ref()
     }
@@ -119,17 +138,26 @@ pub fn schema(
     homeserver_connection: impl HomeserverConnection<Error = anyhow::Error> + 'static,
     site_config: SiteConfig,
     password_manager: PasswordManager,
+    limiter: Limiter,
+    token_hasher: TokenHasher,
 ) -> Schema {
+    let loaders = Loaders::new(pool);
     let state = GraphQLState {
         pool: pool.clone(),
         policy_factory: Arc::clone(policy_factory),
         homeserver_connection: Arc::new(homeserver_connection),
         site_config,
         password_manager,
+        limiter,
+        token_hasher,
     };
     let state: BoxState = Box::new(state);
 
-    schema_builder().extension(Tracing).data(state).finish()
+    schema_builder()
+        .extension(Tracing)
+        .data(state)
+        .data(loaders)
+        .finish()
 }
 
 fn span_for_graphql_request(request: &async_graphql::Request) -> tracing::Span {
@@ -163,6 +191,9 @@ pub enum RouteError {
     #[error("Missing scope")]
     MissingScope,
 
+    #[error(transparent)]
+    NetworkPolicy(#[from] NetworkPolicyError),
+
     #[error(transparent)]
     ParseRequest(#[from] async_graphql::ParseRequestError),
 }
@@ -201,6 +232,15 @@ impl IntoResponse for RouteError {
                     .into_response()
             }
 
+            Self::NetworkPolicy(e) => {
+                let error = async_graphql::Error::new_with_source(e);
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(serde_json::json!({"errors": [error]})),
+                )
+                    .into_response()
+            }
+
             Self::ParseRequest(e) => {
                 let error = async_graphql::Error::new_with_source(e);
                 (
@@ -219,6 +259,7 @@ async fn get_requester(
     undocumented_oauth2_access: bool,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    (network_policy, origin): (&NetworkPolicy, &RequestOrigin),
     mut repo: BoxRepository,
     session_info: SessionInfo,
     token: Option<&str>,
@@ -268,6 +309,11 @@ async fn get_requester(
             return Err(RouteError::MissingScope);
         }
 
+        // Admin access through GraphQL is restricted like the admin API
+        if AdminRole::from_scope(&session.scope).is_some() {
+            network_policy.check(NetworkScope::AdminApi, origin)?;
+        }
+
         Requester::OAuth2Session(Box::new((session, user)))
     } else {
         let maybe_session = session_info.load_session(&mut repo).await?;
@@ -292,6 +338,8 @@ pub async fn post(
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    requester_fingerprint: RequesterFingerprint,
+    (AxumState(network_policy), origin): (AxumState<NetworkPolicy>, RequestOrigin),
     cookie_jar: CookieJar,
     content_type: Option<TypedHeader<ContentType>>,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
@@ -306,6 +354,7 @@ pub async fn post(
         undocumented_oauth2_access,
         &clock,
         &activity_tracker,
+        (&network_policy, &origin),
         repo,
         session_info,
         token,
@@ -318,10 +367,13 @@ pub async fn post(
         content_type,
         body.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
             .into_async_read(),
-        MultipartOptions::default(),
+        MultipartOptions::default()
+            .max_file_size(MAX_UPLOAD_SIZE)
+            .max_num_files(1),
     )
     .await?
-    .data(requester); // XXX: this should probably return another error response?
+    .data(requester) // XXX: this should probably return another error response?
+    .data(requester_fingerprint);
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
@@ -345,6 +397,8 @@ pub async fn get(
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    requester_fingerprint: RequesterFingerprint,
+    (AxumState(network_policy), origin): (AxumState<NetworkPolicy>, RequestOrigin),
     cookie_jar: CookieJar,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
     RawQuery(query): RawQuery,
@@ -357,14 +411,16 @@ pub async fn get(
         undocumented_oauth2_access,
         &clock,
         &activity_tracker,
+        (&network_policy, &origin),
         repo,
         session_info,
         token,
     )
     .await?;
 
-    let request =
-        async_graphql::http::parse_query_string(&query.unwrap_or_default())?.data(requester);
+    let request = async_graphql::http::parse_query_string(&query.unwrap_or_default())?
+        .data(requester)
+        .data(requester_fingerprint);
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
@@ -501,16 +557,32 @@ impl Requester {
         user.id == owner_id
     }
 
-    fn is_admin(&self) -> bool {
+    /// Returns true if the requester can look up the user owning the
+    /// resource, set their password or unlock them.
+    ///
+    /// This is allowed to the helpdesk role, which is otherwise not an admin.
+    fn is_owner_or_helpdesk(&self, resource: &impl OwnerId) -> bool {
+        self.is_helpdesk() || self.is_owner_or_admin(resource)
+    }
+
+    /// The admin role granted to the requester by its scope, if any.
+    fn admin_role(&self) -> Option<AdminRole> {
         match self {
-            Self::OAuth2Session(tuple) => {
-                // TODO: is this the right scope?
-                // This has to be in sync with the policy
-                tuple.0.scope.contains("urn:mas:admin")
-            }
-            Self::BrowserSession(_) | Self::Anonymous => false,
+            // This has to be in sync with the policy
+            Self::OAuth2Session(tuple) => AdminRole::from_scope(&tuple.0.scope),
+            Self::BrowserSession(_) | Self::Anonymous => None,
         }
     }
+
+    fn is_admin(&self) -> bool {
+        self.admin_role().is_some_and(AdminRole::is_admin)
+    }
+
+    /// Returns true if the requester has either the helpdesk or the full admin
+    /// role.
+    fn is_helpdesk(&self) -> bool {
+        self.admin_role().is_some()
+    }
 }
 
 impl From<BrowserSession> for Requester {
@@ -9,7 +9,7 @@ use async_graphql::{
     Context, Description, Object, ID,
 };
 use chrono::{DateTime, Utc};
-use mas_data_model::Device;
+use mas_data_model::{authentication_method_references, Device};
 use mas_storage::{
     app_session::AppSessionFilter, user::BrowserSessionRepository, Pagination, RepositoryAccess,
 };
@@ -47,17 +47,28 @@ impl BrowserSession {
         &self,
         ctx: &Context<'_>,
     ) -> Result<Option<Authentication>, async_graphql::Error> {
+        let last_authentication = ctx
+            .loaders()
+            .last_authentications
+            .load_one(self.0.id)
+            .await?;
+
+        Ok(last_authentication.map(Authentication))
+    }
+
+    /// The methods which authenticated this session, as authentication method
+    /// references from RFC 8176, like `pwd`.
+    ///
+    /// Those are the same as the ones in the `amr` claim of the ID tokens.
+    async fn amr(&self, ctx: &Context<'_>) -> Result<Vec<String>, async_graphql::Error> {
         let state = ctx.state();
         let mut repo = state.repository().await?;
 
-        let last_authentication = repo
-            .browser_session()
-            .get_last_authentication(&self.0)
-            .await?;
+        let authentications = repo.browser_session().list_authentications(&self.0).await?;
 
         repo.cancel().await?;
 
-        Ok(last_authentication.map(Authentication))
+        Ok(authentication_method_references(&authentications))
     }
 
     /// When the object was created.
@@ -202,4 +213,10 @@ impl Authentication {
     pub async fn created_at(&self) -> DateTime<Utc> {
         self.0.created_at
     }
+
+    /// The method used for this authentication, as an authentication method
+    /// reference from RFC 8176, like `pwd`.
+    pub async fn amr(&self) -> Option<&'static str> {
+        self.0.authentication_method.amr()
+    }
 }
@@ -7,7 +7,7 @@
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, Object, ID};
 use chrono::{DateTime, Utc};
-use mas_storage::{compat::CompatSessionRepository, user::UserRepository};
+use mas_storage::compat::CompatSessionRepository;
 use url::Url;
 
 use super::{BrowserSession, NodeType, SessionState, User, UserAgent};
@@ -68,14 +68,12 @@ impl CompatSession {
 
     /// The user authorized for this session.
     async fn user(&self, ctx: &Context<'_>) -> Result<User, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let user = repo
-            .user()
-            .lookup(self.session.user_id)
+        let user = ctx
+            .loaders()
+            .users
+            .load_one(self.session.user_id)
             .await?
             .context("Could not load user")?;
-        repo.cancel().await?;
 
         Ok(User(user))
     }
@@ -100,6 +98,25 @@ impl CompatSession {
         self.session.user_agent.clone().map(UserAgent::from)
     }
 
+    /// The name of the session, as set by the user, or derived from the
+    /// user-agent, like "Firefox on Linux".
+    pub async fn human_name(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<String>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let human_name = repo.compat_session().human_name(&self.session).await?;
+        repo.cancel().await?;
+
+        Ok(human_name.or_else(|| {
+            self.session
+                .user_agent
+                .as_ref()
+                .and_then(mas_data_model::UserAgent::summary)
+        }))
+    }
+
     /// The associated SSO login, if any.
     pub async fn sso_login(
         &self,
@@ -131,14 +148,12 @@ impl CompatSession {
             return Ok(None);
         };
 
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let browser_session = repo
-            .browser_session()
-            .lookup(user_session_id)
+        let browser_session = ctx
+            .loaders()
+            .browser_sessions
+            .load_one(user_session_id)
             .await?
             .context("Could not load browser session")?;
-        repo.cancel().await?;
 
         Ok(Some(BrowserSession(browser_session)))
     }
@@ -0,0 +1,328 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use chrono::{Duration, NaiveDate};
+use mas_storage::stats;
+
+use super::OAuth2Client;
+use crate::graphql::state::ContextExt;
+
+/// The default number of days covered by the dashboard statistics
+const DEFAULT_DAYS: u32 = 30;
+
+/// The maximum number of days covered by the dashboard statistics. This
+/// matches the window of the precomputed aggregates.
+const MAX_DAYS: u32 = 90;
+
+/// Aggregate statistics about the service, for the admin dashboard.
+///
+/// Those are computed periodically in the background, so they may be a few
+/// minutes out of date.
+pub struct Dashboard;
+
+/// Get the first day covered by a statistic over the given number of days
+fn since(ctx: &Context<'_>, days: Option<u32>) -> NaiveDate {
+    let days = days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let today = ctx.state().clock().now().date_naive();
+    today - Duration::try_days(i64::from(days - 1)).unwrap()
+}
+
+#[Object]
+impl Dashboard {
+    /// Number of users registered per day. Days without any registration are
+    /// omitted.
+    async fn registrations_per_day(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Number of days to cover, up to 90. Defaults to 30.")] days: Option<u32>,
+    ) -> Result<Vec<DailyCount>, async_graphql::Error> {
+        let since = since(ctx, days);
+        let mut repo = ctx.state().repository().await?;
+        let counts = repo.stats().registrations_per_day(since).await?;
+        repo.cancel().await?;
+
+        Ok(counts.into_iter().map(DailyCount::from).collect())
+    }
+
+    /// Number of sessions which are currently active.
+    async fn active_sessions(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<ActiveSessionCounts, async_graphql::Error> {
+        let mut repo = ctx.state().repository().await?;
+        let counts = repo.stats().active_sessions().await?;
+        repo.cancel().await?;
+
+        Ok(ActiveSessionCounts {
+            browser: counts.browser,
+            oauth2: counts.oauth2,
+            compat: counts.compat,
+            total: counts.browser + counts.oauth2 + counts.compat,
+        })
+    }
+
+    /// Number of browser logins per day and per authentication method.
+    async fn logins_per_day(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Number of days to cover, up to 90. Defaults to 30.")] days: Option<u32>,
+    ) -> Result<Vec<DailyLoginCount>, async_graphql::Error> {
+        let since = since(ctx, days);
+        let mut repo = ctx.state().repository().await?;
+        let counts = repo.stats().logins_per_day(since).await?;
+        repo.cancel().await?;
+
+        Ok(counts
+            .into_iter()
+            .map(|c| DailyLoginCount {
+                day: c.day,
+                method: c.method.into(),
+                count: c.count,
+            })
+            .collect())
+    }
+
+    /// Number of failed and successful password logins per day, along with
+    /// the rate of failures. Days without any password login attempt are
+    /// omitted.
+    async fn password_login_failures_per_day(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Number of days to cover, up to 90. Defaults to 30.")] days: Option<u32>,
+    ) -> Result<Vec<DailyLoginFailures>, async_graphql::Error> {
+        let since = since(ctx, days);
+        let mut repo = ctx.state().repository().await?;
+        let failures = repo.stats().login_failures_per_day(since).await?;
+        let logins = repo.stats().logins_per_day(since).await?;
+        repo.cancel().await?;
+
+        // Merge both series on the day, as (failed, succeeded)
+        let mut days: BTreeMap<NaiveDate, (usize, usize)> = BTreeMap::new();
+        for failure in failures {
+            days.entry(failure.day).or_default().0 += failure.count;
+        }
+        for login in logins {
+            if login.method == stats::LoginMethod::Password {
+                days.entry(login.day).or_default().1 += login.count;
+            }
+        }
+
+        Ok(days
+            .into_iter()
+            .map(|(day, (failed, succeeded))| DailyLoginFailures::new(day, failed, succeeded))
+            .collect())
+    }
+
+    /// OAuth 2.0 clients which were issued the most access tokens, in
+    /// descending order.
+    async fn top_clients_by_access_tokens(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Number of days to cover, up to 90. Defaults to 30.")] days: Option<u32>,
+        #[graphql(desc = "Number of clients to return, up to 100. Defaults to 10.")] first: Option<
+            u32,
+        >,
+    ) -> Result<Vec<ClientAccessTokenCount>, async_graphql::Error> {
+        let since = since(ctx, days);
+        let limit = first.unwrap_or(10).clamp(1, 100) as usize;
+        let mut repo = ctx.state().repository().await?;
+        let counts = repo
+            .stats()
+            .top_clients_by_access_tokens(since, limit)
+            .await?;
+
+        let ids: BTreeSet<_> = counts.iter().map(|c| c.client_id).collect();
+        let mut clients = repo.oauth2_client().load_batch(ids).await?;
+        repo.cancel().await?;
+
+        Ok(counts
+            .into_iter()
+            .filter_map(|c| {
+                let client = clients.remove(&c.client_id)?;
+                Some(ClientAccessTokenCount {
+                    client: OAuth2Client(client),
+                    count: c.count,
+                })
+            })
+            .collect())
+    }
+
+    /// Exposures and conversions of each variant of the A/B experiments, for
+    /// the visitors first shown the experiment in the period. A conversion is
+    /// a visitor logging in or registering after being shown the variant.
+    async fn experiments(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Number of days to cover, up to 90. Defaults to 30.")] days: Option<u32>,
+    ) -> Result<Vec<ExperimentVariantResult>, async_graphql::Error> {
+        let since = since(ctx, days);
+        let mut repo = ctx.state().repository().await?;
+        let results = repo.stats().experiment_results(since).await?;
+        repo.cancel().await?;
+
+        Ok(results
+            .into_iter()
+            .map(ExperimentVariantResult::from)
+            .collect())
+    }
+}
+
+/// A number of events which happened on a given day.
+#[derive(SimpleObject)]
+pub struct DailyCount {
+    /// The day, in UTC.
+    day: NaiveDate,
+
+    /// The number of events on that day.
+    count: usize,
+}
+
+impl From<stats::DailyCount> for DailyCount {
+    fn from(value: stats::DailyCount) -> Self {
+        Self {
+            day: value.day,
+            count: value.count,
+        }
+    }
+}
+
+/// The method used to authenticate a browser session.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum LoginMethod {
+    /// Authenticated with a password.
+    Password,
+
+    /// Authenticated through an upstream OAuth 2.0 provider.
+    UpstreamOauth,
+
+    /// Any other authentication method.
+    Other,
+}
+
+impl From<stats::LoginMethod> for LoginMethod {
+    fn from(value: stats::LoginMethod) -> Self {
+        match value {
+            stats::LoginMethod::Password => Self::Password,
+            stats::LoginMethod::UpstreamOAuth => Self::UpstreamOauth,
+            stats::LoginMethod::Other => Self::Other,
+        }
+    }
+}
+
+/// A number of browser logins with a given method on a given day.
+#[derive(SimpleObject)]
+pub struct DailyLoginCount {
+    /// The day, in UTC.
+    day: NaiveDate,
+
+    /// The authentication method used.
+    method: LoginMethod,
+
+    /// The number of logins on that day with that method.
+    count: usize,
+}
+
+/// The number of failed and successful password logins on a given day.
+#[derive(SimpleObject)]
+pub struct DailyLoginFailures {
+    /// The day, in UTC.
+    day: NaiveDate,
+
+    /// The number of failed password logins.
+    failed: usize,
+
+    /// The number of successful password logins.
+    succeeded: usize,
+
+    /// The ratio of failed password logins over all password login
+    /// attempts, between 0 and 1.
+    failure_rate: f64,
+}
+
+impl DailyLoginFailures {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(day: NaiveDate, failed: usize, succeeded: usize) -> Self {
+        let attempts = failed + succeeded;
+        let failure_rate = if attempts == 0 {
+            0.0
+        } else {
+            failed as f64 / attempts as f64
+        };
+
+        Self {
+            day,
+            failed,
+            succeeded,
+            failure_rate,
+        }
+    }
+}
+
+/// The number of sessions which are currently active.
+#[derive(SimpleObject)]
+pub struct ActiveSessionCounts {
+    /// The number of active browser sessions.
+    browser: usize,
+
+    /// The number of active OAuth 2.0 sessions.
+    oauth2: usize,
+
+    /// The number of active compatibility sessions.
+    compat: usize,
+
+    /// The total number of active sessions.
+    total: usize,
+}
+
+/// The number of access tokens issued to an OAuth 2.0 client.
+#[derive(SimpleObject)]
+pub struct ClientAccessTokenCount {
+    /// The OAuth 2.0 client.
+    client: OAuth2Client,
+
+    /// The number of access tokens issued to this client.
+    count: usize,
+}
+
+/// The exposures and conversions of a variant of an A/B experiment.
+#[derive(SimpleObject)]
+pub struct ExperimentVariantResult {
+    /// The name of the experiment.
+    experiment: String,
+
+    /// The name of the variant.
+    variant: String,
+
+    /// The number of visitors who were shown this variant.
+    exposures: usize,
+
+    /// The number of those visitors who then logged in or registered.
+    conversions: usize,
+
+    /// The ratio of conversions over exposures, between 0 and 1.
+    conversion_rate: f64,
+}
+
+impl From<stats::ExperimentVariantStats> for ExperimentVariantResult {
+    #[allow(clippy::cast_precision_loss)]
+    fn from(value: stats::ExperimentVariantStats) -> Self {
+        let conversion_rate = if value.exposures == 0 {
+            0.0
+        } else {
+            value.conversions as f64 / value.exposures as f64
+        };
+
+        Self {
+            experiment: value.experiment,
+            variant: value.variant,
+            exposures: value.exposures,
+            conversions: value.conversions,
+            conversion_rate,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{Description, Enum, Object, ID};
+use chrono::{DateTime, Utc};
+use mas_data_model::QueuedEmailState as DataQueuedEmailState;
+
+use super::NodeType;
+
+/// The delivery state of a queued email.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum QueuedEmailState {
+    /// The email is waiting to be sent.
+    Pending,
+
+    /// The email was sent.
+    Sent,
+
+    /// Too many attempts failed, and the email was moved to the dead-letter
+    /// list.
+    Failed,
+}
+
+/// An email in the outgoing queue
+#[derive(Description)]
+pub struct QueuedEmail(pub mas_data_model::QueuedEmail);
+
+#[Object(use_type_description)]
+impl QueuedEmail {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::QueuedEmail.id(self.0.id)
+    }
+
+    /// The address the email is sent to.
+    async fn recipient(&self) -> &str {
+        &self.0.recipient
+    }
+
+    /// When the object was created.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// The number of failed attempts so far.
+    async fn attempts(&self) -> u32 {
+        self.0.attempts
+    }
+
+    /// When the next attempt should happen.
+    async fn next_attempt_at(&self) -> DateTime<Utc> {
+        self.0.next_attempt_at
+    }
+
+    /// The error of the last failed attempt.
+    async fn last_error(&self) -> Option<&str> {
+        self.0.last_error.as_deref()
+    }
+
+    /// The delivery state of the email.
+    async fn state(&self) -> QueuedEmailState {
+        match self.0.state {
+            DataQueuedEmailState::Pending => QueuedEmailState::Pending,
+            DataQueuedEmailState::Sent { .. } => QueuedEmailState::Sent,
+            DataQueuedEmailState::Failed { .. } => QueuedEmailState::Failed,
+        }
+    }
+
+    /// When the email was sent.
+    async fn sent_at(&self) -> Option<DateTime<Utc>> {
+        match self.0.state {
+            DataQueuedEmailState::Sent { sent_at } => Some(sent_at),
+            _ => None,
+        }
+    }
+
+    /// When the email was moved to the dead-letter list.
+    async fn failed_at(&self) -> Option<DateTime<Utc>> {
+        match self.0.state {
+            DataQueuedEmailState::Failed { failed_at } => Some(failed_at),
+            _ => None,
+        }
+    }
+}
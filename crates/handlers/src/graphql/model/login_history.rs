@@ -0,0 +1,72 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{Description, Enum, Object, ID};
+use chrono::{DateTime, Utc};
+use mas_data_model::LoginMethod as DataLoginMethod;
+
+use super::{NodeType, UserAgent};
+
+/// How a user tried to log in.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum LoginAttemptMethod {
+    /// With their username and password.
+    Password,
+
+    /// Through an upstream OAuth 2.0 provider.
+    #[graphql(name = "UPSTREAM_OAUTH2")]
+    UpstreamOAuth2,
+
+    /// With a client certificate.
+    ClientCertificate,
+}
+
+impl From<DataLoginMethod> for LoginAttemptMethod {
+    fn from(method: DataLoginMethod) -> Self {
+        match method {
+            DataLoginMethod::Password => Self::Password,
+            DataLoginMethod::UpstreamOAuth2 => Self::UpstreamOAuth2,
+            DataLoginMethod::ClientCertificate => Self::ClientCertificate,
+        }
+    }
+}
+
+/// An attempt of a user to log in, successful or not, kept for a limited time
+/// so that the user can review the recent activity on their account.
+#[derive(Description)]
+pub struct LoginAttempt(pub mas_data_model::LoginAttempt);
+
+#[Object(use_type_description)]
+impl LoginAttempt {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::LoginAttempt.id(self.0.id)
+    }
+
+    /// When the object was created.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// How the user tried to log in.
+    async fn method(&self) -> LoginAttemptMethod {
+        self.0.method.into()
+    }
+
+    /// Whether the attempt succeeded.
+    async fn succeeded(&self) -> bool {
+        self.0.succeeded
+    }
+
+    /// The IP address the attempt came from, if known.
+    async fn ip_address(&self) -> Option<String> {
+        self.0.ip_address.map(|ip| ip.to_string())
+    }
+
+    /// The user-agent of the device the attempt came from, if known.
+    async fn user_agent(&self) -> Option<UserAgent> {
+        self.0.user_agent.clone().map(UserAgent::from)
+    }
+}
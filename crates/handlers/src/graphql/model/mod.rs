@@ -10,9 +10,13 @@ use chrono::{DateTime, Utc};
 mod browser_sessions;
 mod compat_sessions;
 mod cursor;
+mod dashboard;
+mod email_queue;
+mod login_history;
 mod matrix;
 mod node;
 mod oauth;
+mod security_checkup;
 mod site_config;
 mod upstream_oauth;
 mod users;
@@ -22,8 +26,12 @@ pub use self::{
     browser_sessions::{Authentication, BrowserSession},
     compat_sessions::{CompatSession, CompatSsoLogin},
     cursor::{Cursor, NodeCursor},
+    dashboard::Dashboard,
+    email_queue::{QueuedEmail, QueuedEmailState},
+    login_history::LoginAttempt,
     node::{Node, NodeType},
     oauth::{OAuth2Client, OAuth2Session},
+    security_checkup::SecurityCheckup,
     site_config::{SiteConfig, SITE_CONFIG_ID},
     upstream_oauth::{UpstreamOAuth2Link, UpstreamOAuth2Provider},
     users::{AppSession, User, UserEmail},
@@ -45,6 +53,8 @@ pub enum CreationEvent {
     UpstreamOAuth2Provider(Box<UpstreamOAuth2Provider>),
     UpstreamOAuth2Link(Box<UpstreamOAuth2Link>),
     OAuth2Session(Box<OAuth2Session>),
+    QueuedEmail(Box<QueuedEmail>),
+    LoginAttempt(Box<LoginAttempt>),
 }
 
 pub struct PreloadedTotalCount(pub Option<usize>);
@@ -95,6 +105,46 @@ impl From<mas_data_model::DeviceType> for DeviceType {
     }
 }
 
+/// The kind of application which created a session
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ClientApp {
+    /// A web browser. This includes Element Web, which can't be told apart
+    /// from the browser it runs in.
+    Browser,
+
+    /// Element Desktop, the Electron application
+    ElementDesktop,
+
+    /// Element Android
+    ElementAndroid,
+
+    /// Element iOS
+    ElementIos,
+
+    /// Element X, on any platform
+    ElementX,
+
+    /// Another native application
+    OtherApp,
+
+    /// The application could not be detected
+    Unknown,
+}
+
+impl From<mas_data_model::ClientApp> for ClientApp {
+    fn from(client_app: mas_data_model::ClientApp) -> Self {
+        match client_app {
+            mas_data_model::ClientApp::Browser => Self::Browser,
+            mas_data_model::ClientApp::ElementDesktop => Self::ElementDesktop,
+            mas_data_model::ClientApp::ElementAndroid => Self::ElementAndroid,
+            mas_data_model::ClientApp::ElementIos => Self::ElementIos,
+            mas_data_model::ClientApp::ElementX => Self::ElementX,
+            mas_data_model::ClientApp::OtherApp => Self::OtherApp,
+            mas_data_model::ClientApp::Unknown => Self::Unknown,
+        }
+    }
+}
+
 /// A parsed user agent string
 #[derive(SimpleObject)]
 pub struct UserAgent {
@@ -118,6 +168,9 @@ pub struct UserAgent {
 
     /// The device type
     pub device_type: DeviceType,
+
+    /// The kind of application
+    pub client_app: ClientApp,
 }
 
 impl From<mas_data_model::UserAgent> for UserAgent {
@@ -130,6 +183,7 @@ impl From<mas_data_model::UserAgent> for UserAgent {
             os_version: ua.os_version,
             model: ua.model,
             device_type: ua.device_type.into(),
+            client_app: ua.client_app.into(),
         }
     }
 }
@@ -10,8 +10,9 @@ use thiserror::Error;
 use ulid::Ulid;
 
 use super::{
-    Anonymous, Authentication, BrowserSession, CompatSession, CompatSsoLogin, OAuth2Client,
-    OAuth2Session, SiteConfig, UpstreamOAuth2Link, UpstreamOAuth2Provider, User, UserEmail,
+    Anonymous, Authentication, BrowserSession, CompatSession, CompatSsoLogin, LoginAttempt,
+    OAuth2Client, OAuth2Session, QueuedEmail, SiteConfig, UpstreamOAuth2Link,
+    UpstreamOAuth2Provider, User, UserEmail,
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -20,8 +21,10 @@ pub enum NodeType {
     BrowserSession,
     CompatSession,
     CompatSsoLogin,
+    LoginAttempt,
     OAuth2Client,
     OAuth2Session,
+    QueuedEmail,
     UpstreamOAuth2Provider,
     UpstreamOAuth2Link,
     User,
@@ -44,8 +47,10 @@ impl NodeType {
             NodeType::BrowserSession => "browser_session",
             NodeType::CompatSession => "compat_session",
             NodeType::CompatSsoLogin => "compat_sso_login",
+            NodeType::LoginAttempt => "login_attempt",
             NodeType::OAuth2Client => "oauth2_client",
             NodeType::OAuth2Session => "oauth2_session",
+            NodeType::QueuedEmail => "queued_email",
             NodeType::UpstreamOAuth2Provider => "upstream_oauth2_provider",
             NodeType::UpstreamOAuth2Link => "upstream_oauth2_link",
             NodeType::User => "user",
@@ -59,8 +64,10 @@ impl NodeType {
             "browser_session" => Some(NodeType::BrowserSession),
             "compat_session" => Some(NodeType::CompatSession),
             "compat_sso_login" => Some(NodeType::CompatSsoLogin),
+            "login_attempt" => Some(NodeType::LoginAttempt),
             "oauth2_client" => Some(NodeType::OAuth2Client),
             "oauth2_session" => Some(NodeType::OAuth2Session),
+            "queued_email" => Some(NodeType::QueuedEmail),
             "upstream_oauth2_provider" => Some(NodeType::UpstreamOAuth2Provider),
             "upstream_oauth2_link" => Some(NodeType::UpstreamOAuth2Link),
             "user" => Some(NodeType::User),
@@ -113,8 +120,10 @@ pub enum Node {
     BrowserSession(Box<BrowserSession>),
     CompatSession(Box<CompatSession>),
     CompatSsoLogin(Box<CompatSsoLogin>),
+    LoginAttempt(Box<LoginAttempt>),
     OAuth2Client(Box<OAuth2Client>),
     OAuth2Session(Box<OAuth2Session>),
+    QueuedEmail(Box<QueuedEmail>),
     SiteConfig(Box<SiteConfig>),
     UpstreamOAuth2Provider(Box<UpstreamOAuth2Provider>),
     UpstreamOAuth2Link(Box<UpstreamOAuth2Link>),
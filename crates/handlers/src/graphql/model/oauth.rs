@@ -7,7 +7,7 @@
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, Object, ID};
 use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2ClientRepository, user::BrowserSessionRepository};
+use mas_storage::oauth2::{OAuth2ClientRepository, OAuth2SessionRepository};
 use oauth2_types::{oidc::ApplicationType, scope::Scope};
 use ulid::Ulid;
 use url::Url;
@@ -29,14 +29,12 @@ impl OAuth2Session {
 
     /// OAuth 2.0 client used by this session.
     pub async fn client(&self, ctx: &Context<'_>) -> Result<OAuth2Client, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let client = repo
-            .oauth2_client()
-            .lookup(self.0.client_id)
+        let client = ctx
+            .loaders()
+            .oauth2_clients
+            .load_one(self.0.client_id)
             .await?
             .context("Could not load client")?;
-        repo.cancel().await?;
 
         Ok(OAuth2Client(client))
     }
@@ -64,6 +62,25 @@ impl OAuth2Session {
         self.0.user_agent.clone().map(UserAgent::from)
     }
 
+    /// The name of the session, as set by the user, or derived from the
+    /// user-agent, like "Firefox on Linux".
+    pub async fn human_name(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<String>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let human_name = repo.oauth2_session().human_name(&self.0).await?;
+        repo.cancel().await?;
+
+        Ok(human_name.or_else(|| {
+            self.0
+                .user_agent
+                .as_ref()
+                .and_then(mas_data_model::UserAgent::summary)
+        }))
+    }
+
     /// The state of the session.
     pub async fn state(&self) -> SessionState {
         match &self.0.state {
@@ -81,21 +98,18 @@ impl OAuth2Session {
             return Ok(None);
         };
 
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let browser_session = repo
-            .browser_session()
-            .lookup(user_session_id)
+        let browser_session = ctx
+            .loaders()
+            .browser_sessions
+            .load_one(user_session_id)
             .await?
             .context("Could not load browser session")?;
-        repo.cancel().await?;
 
         Ok(Some(BrowserSession(browser_session)))
     }
 
     /// User authorized for this session.
     pub async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>, async_graphql::Error> {
-        let state = ctx.state();
         let Some(user_id) = self.0.user_id else {
             return Ok(None);
         };
@@ -104,13 +118,12 @@ impl OAuth2Session {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
-        let mut repo = state.repository().await?;
-        let user = repo
-            .user()
-            .lookup(user_id)
+        let user = ctx
+            .loaders()
+            .users
+            .load_one(user_id)
             .await?
             .context("Could not load user")?;
-        repo.cancel().await?;
 
         Ok(Some(User(user)))
     }
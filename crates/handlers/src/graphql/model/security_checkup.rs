@@ -0,0 +1,280 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{collections::HashSet, net::IpAddr};
+
+use async_graphql::{Context, Enum, Object};
+use chrono::{DateTime, Duration, Utc};
+use mas_storage::{
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
+        UserPasswordRepository, UserRepository,
+    },
+    Pagination, RepositoryAccess,
+};
+
+use super::{BrowserSession, OAuth2Session};
+use crate::graphql::state::ContextExt;
+
+/// Passwords which weren't changed for that many days are reported as old
+const PASSWORD_MAX_AGE_DAYS: i64 = 365;
+
+/// Logins more recent than that many days are checked for unusual ones
+const RECENT_LOGINS_DAYS: i64 = 30;
+
+/// How many of the latest sessions are looked at
+const MAX_SESSIONS: usize = 100;
+
+/// Scopes giving a broad access to the account
+const BROAD_SCOPES: [&str; 4] = [
+    "urn:matrix:org.matrix.msc2967.client:api:*",
+    "urn:synapse:admin:*",
+    "urn:mas:graphql:*",
+    "urn:mas:admin",
+];
+
+/// Something the user should look at to secure their account
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SecurityCheckupIssue {
+    /// The password wasn't changed for more than a year.
+    OldPassword,
+
+    /// An administrator reset the password, and it has to be changed.
+    PasswordChangeRequired,
+
+    /// No second authentication factor is set up.
+    NoSecondFactor,
+
+    /// There is no way to recover the account, like a verified email address.
+    NoRecoveryOption,
+
+    /// There were recent logins from an unusual location and device.
+    UnusualRecentLogins,
+
+    /// Some applications have a broad access to the account.
+    BroadScopeApplications,
+}
+
+/// An overview of the security of a user account, to render a security
+/// checkup page.
+pub struct SecurityCheckup {
+    password_changed_at: Option<DateTime<Utc>>,
+    password_change_required: bool,
+    verified_emails: usize,
+    upstream_oauth2_links: usize,
+    account_recovery_enabled: bool,
+    unusual_recent_sessions: Vec<mas_data_model::BrowserSession>,
+    broad_scope_sessions: Vec<mas_data_model::Session>,
+    now: DateTime<Utc>,
+}
+
+impl SecurityCheckup {
+    pub(super) async fn load(
+        ctx: &Context<'_>,
+        user: &mas_data_model::User,
+    ) -> Result<Self, async_graphql::Error> {
+        let state = ctx.state();
+        let now = state.clock().now();
+        let mut repo = state.repository().await?;
+
+        let password_changed_at = repo
+            .user_password()
+            .active(user)
+            .await?
+            .map(|password| password.created_at);
+        let password_change_required = repo.user().is_password_change_required(user).await?;
+
+        let verified_emails = repo
+            .user_email()
+            .count(UserEmailFilter::new().for_user(user).verified_only())
+            .await?;
+        let upstream_oauth2_links = repo
+            .upstream_oauth_link()
+            .count(UpstreamOAuthLinkFilter::new().for_user(user))
+            .await?;
+
+        let browser_sessions = repo
+            .browser_session()
+            .list(
+                BrowserSessionFilter::new().for_user(user),
+                Pagination::last(MAX_SESSIONS),
+            )
+            .await?;
+
+        let oauth2_sessions = repo
+            .oauth2_session()
+            .list(
+                OAuth2SessionFilter::new().for_user(user).active_only(),
+                Pagination::last(MAX_SESSIONS),
+            )
+            .await?;
+
+        repo.cancel().await?;
+
+        let recent_since = now - Duration::try_days(RECENT_LOGINS_DAYS).unwrap();
+        let unusual_recent_sessions = unusual_sessions(browser_sessions.edges, recent_since);
+
+        let broad_scope_sessions = oauth2_sessions
+            .edges
+            .into_iter()
+            .filter(|session| {
+                BROAD_SCOPES
+                    .iter()
+                    .any(|scope| session.scope.contains(scope))
+            })
+            .collect();
+
+        Ok(Self {
+            password_changed_at,
+            password_change_required,
+            verified_emails,
+            upstream_oauth2_links,
+            account_recovery_enabled: state.site_config().account_recovery_allowed
+                && verified_emails > 0,
+            unusual_recent_sessions,
+            broad_scope_sessions,
+            now,
+        })
+    }
+
+    fn password_age_in_days(&self) -> Option<i64> {
+        self.password_changed_at
+            .map(|changed_at| (self.now - changed_at).num_days())
+    }
+}
+
+/// Find the sessions created since the given date, from both an IP address and
+/// a browser which weren't used by an earlier session.
+///
+/// Sessions must be in chronological order.
+fn unusual_sessions(
+    sessions: Vec<mas_data_model::BrowserSession>,
+    since: DateTime<Utc>,
+) -> Vec<mas_data_model::BrowserSession> {
+    let mut known_ips: HashSet<IpAddr> = HashSet::new();
+    let mut known_browsers: HashSet<(Option<String>, Option<String>)> = HashSet::new();
+    let mut unusual = Vec::new();
+
+    for session in sessions {
+        let ip = session.last_active_ip;
+        let browser = session
+            .user_agent
+            .as_ref()
+            .map(|user_agent| (user_agent.name.clone(), user_agent.os.clone()));
+
+        // The very first session can't be compared to anything
+        let has_history = !known_ips.is_empty() || !known_browsers.is_empty();
+        let new_ip = ip.is_some_and(|ip| !known_ips.contains(&ip));
+        let new_browser = browser
+            .as_ref()
+            .is_some_and(|browser| !known_browsers.contains(browser));
+
+        known_ips.extend(ip);
+        known_browsers.extend(browser);
+
+        if session.created_at >= since && has_history && new_ip && new_browser {
+            unusual.push(session);
+        }
+    }
+
+    unusual
+}
+
+#[Object]
+impl SecurityCheckup {
+    /// When the current password was set, if the user has one.
+    async fn password_changed_at(&self) -> Option<DateTime<Utc>> {
+        self.password_changed_at
+    }
+
+    /// How many days ago the current password was set, if the user has one.
+    async fn password_age_days(&self) -> Option<i64> {
+        self.password_age_in_days()
+    }
+
+    /// Whether the user has to change their password on their next login.
+    async fn password_change_required(&self) -> bool {
+        self.password_change_required
+    }
+
+    /// Whether a second authentication factor is set up.
+    ///
+    /// Second factors aren't supported yet, so this is always `false`.
+    async fn mfa_enabled(&self) -> bool {
+        false
+    }
+
+    /// Number of verified email addresses, which can be used to recover the
+    /// account.
+    async fn verified_emails(&self) -> usize {
+        self.verified_emails
+    }
+
+    /// Number of accounts on upstream identity providers linked to this
+    /// account.
+    async fn upstream_oauth2_links(&self) -> usize {
+        self.upstream_oauth2_links
+    }
+
+    /// Whether the user can recover their account by email if they lose their
+    /// password.
+    async fn account_recovery_enabled(&self) -> bool {
+        self.account_recovery_enabled
+    }
+
+    /// Browser sessions created in the last 30 days, from both an IP address
+    /// and a browser which weren't used by earlier sessions.
+    async fn unusual_recent_sessions(&self) -> Vec<BrowserSession> {
+        self.unusual_recent_sessions
+            .iter()
+            .cloned()
+            .map(BrowserSession)
+            .collect()
+    }
+
+    /// Active OAuth 2.0 sessions with a broad access to the account, like the
+    /// full Matrix client API.
+    async fn broad_scope_oauth2_sessions(&self) -> Vec<OAuth2Session> {
+        self.broad_scope_sessions
+            .iter()
+            .cloned()
+            .map(OAuth2Session)
+            .collect()
+    }
+
+    /// Things the user should look at to secure their account.
+    async fn issues(&self) -> Vec<SecurityCheckupIssue> {
+        let mut issues = Vec::new();
+
+        if self
+            .password_age_in_days()
+            .is_some_and(|days| days > PASSWORD_MAX_AGE_DAYS)
+        {
+            issues.push(SecurityCheckupIssue::OldPassword);
+        }
+
+        if self.password_change_required {
+            issues.push(SecurityCheckupIssue::PasswordChangeRequired);
+        }
+
+        issues.push(SecurityCheckupIssue::NoSecondFactor);
+
+        if !self.account_recovery_enabled && self.upstream_oauth2_links == 0 {
+            issues.push(SecurityCheckupIssue::NoRecoveryOption);
+        }
+
+        if !self.unusual_recent_sessions.is_empty() {
+            issues.push(SecurityCheckupIssue::UnusualRecentLogins);
+        }
+
+        if !self.broad_scope_sessions.is_empty() {
+            issues.push(SecurityCheckupIssue::BroadScopeApplications);
+        }
+
+        issues
+    }
+}
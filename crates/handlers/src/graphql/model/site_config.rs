@@ -37,6 +37,9 @@ pub struct SiteConfig {
     /// Whether users can change their display name.
     display_name_change_allowed: bool,
 
+    /// Whether users can change their avatar.
+    avatar_change_allowed: bool,
+
     /// Whether passwords are enabled for login.
     password_login_enabled: bool,
 
@@ -90,6 +93,7 @@ impl SiteConfig {
             imprint: data_model.imprint.clone(),
             email_change_allowed: data_model.email_change_allowed,
             display_name_change_allowed: data_model.displayname_change_allowed,
+            avatar_change_allowed: data_model.avatar_change_allowed,
             password_login_enabled: data_model.password_login_enabled,
             password_change_allowed: data_model.password_change_allowed,
             password_registration_enabled: data_model.password_registration_enabled,
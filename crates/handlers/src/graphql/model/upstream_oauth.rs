@@ -5,7 +5,7 @@
 // Please see LICENSE in the repository root for full details.
 
 use anyhow::Context as _;
-use async_graphql::{Context, Object, ID};
+use async_graphql::{Context, Enum, Object, SimpleObject, ID};
 use chrono::{DateTime, Utc};
 use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, user::UserRepository};
 
@@ -45,6 +45,85 @@ impl UpstreamOAuth2Provider {
     pub async fn client_id(&self) -> &str {
         &self.provider.client_id
     }
+
+    /// The result of the last health checks of this provider, or null if it
+    /// was never checked.
+    ///
+    /// This is only available to administrators.
+    pub async fn health(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<UpstreamOAuth2ProviderHealth>, async_graphql::Error> {
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let health = repo
+            .upstream_oauth_provider()
+            .health(&self.provider)
+            .await?;
+        repo.cancel().await?;
+
+        let now = state.clock().now();
+        Ok(health.map(|health| UpstreamOAuth2ProviderHealth {
+            status: health.status(now).into(),
+            checked_at: health.checked_at,
+            last_success_at: health.last_success_at,
+            consecutive_failures: health.consecutive_failures,
+            last_error: health.last_error,
+        }))
+    }
+}
+
+/// The status of an upstream OAuth 2.0 provider.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum UpstreamOAuth2ProviderStatus {
+    /// The last check succeeded.
+    Up,
+
+    /// Some checks failed, but not enough yet to consider the provider down.
+    Degraded,
+
+    /// Enough checks failed in a row for the provider to be considered down.
+    /// Users can't log in with it until it is back up.
+    Down,
+
+    /// The provider wasn't checked recently.
+    Unknown,
+}
+
+impl From<mas_data_model::UpstreamOAuthProviderStatus> for UpstreamOAuth2ProviderStatus {
+    fn from(value: mas_data_model::UpstreamOAuthProviderStatus) -> Self {
+        match value {
+            mas_data_model::UpstreamOAuthProviderStatus::Up => Self::Up,
+            mas_data_model::UpstreamOAuthProviderStatus::Degraded => Self::Degraded,
+            mas_data_model::UpstreamOAuthProviderStatus::Down => Self::Down,
+            mas_data_model::UpstreamOAuthProviderStatus::Unknown => Self::Unknown,
+        }
+    }
+}
+
+/// The result of the periodic health checks of an upstream OAuth 2.0
+/// provider.
+#[derive(SimpleObject)]
+pub struct UpstreamOAuth2ProviderHealth {
+    /// The status of the provider.
+    status: UpstreamOAuth2ProviderStatus,
+
+    /// When the provider was last checked.
+    checked_at: DateTime<Utc>,
+
+    /// When the provider was last successfully checked.
+    last_success_at: Option<DateTime<Utc>>,
+
+    /// How many checks failed since the last successful one.
+    consecutive_failures: u32,
+
+    /// The error of the last check, if it failed.
+    last_error: Option<String>,
 }
 
 impl UpstreamOAuth2Link {
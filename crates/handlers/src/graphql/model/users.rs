@@ -16,15 +16,18 @@ use mas_storage::{
     compat::{CompatSessionFilter, CompatSsoLoginFilter, CompatSsoLoginRepository},
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
     upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
-    user::{BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository},
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
+        UserLoginHistoryRepository,
+    },
     Pagination, RepositoryAccess,
 };
 
 use super::{
     compat_sessions::{CompatSessionType, CompatSsoLogin},
     matrix::MatrixUser,
-    BrowserSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session,
-    PreloadedTotalCount, SessionState, UpstreamOAuth2Link,
+    BrowserSession, CompatSession, Cursor, LoginAttempt, NodeCursor, NodeType, OAuth2Session,
+    PreloadedTotalCount, SecurityCheckup, SessionState, UpstreamOAuth2Link,
 };
 use crate::graphql::{state::ContextExt, DateFilter};
 
@@ -44,6 +47,18 @@ impl From<mas_data_model::BrowserSession> for User {
     }
 }
 
+impl User {
+    /// The helpdesk can look up users, but not their sessions, so those are
+    /// only visible to the user themselves and to admins
+    fn ensure_can_see_sessions(&self, ctx: &Context<'_>) -> Result<(), async_graphql::Error> {
+        if ctx.requester().is_owner_or_admin(&self.0) {
+            Ok(())
+        } else {
+            Err(async_graphql::Error::new("Unauthorized"))
+        }
+    }
+}
+
 #[Object(use_type_description)]
 impl User {
     /// ID of the object.
@@ -103,6 +118,7 @@ impl User {
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, CompatSsoLogin, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let mut repo = state.repository().await?;
 
@@ -176,6 +192,7 @@ impl User {
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, CompatSession, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let mut repo = state.repository().await?;
         let last_active = last_active.unwrap_or_default();
@@ -268,6 +285,7 @@ impl User {
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, BrowserSession, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let mut repo = state.repository().await?;
         let last_active = last_active.unwrap_or_default();
@@ -331,6 +349,16 @@ impl User {
         .await
     }
 
+    /// An overview of the security of the account, to render a security
+    /// checkup page.
+    async fn security_checkup(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<SecurityCheckup, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
+        SecurityCheckup::load(ctx, &self.0).await
+    }
+
     /// Get the list of emails, chronologically sorted
     async fn emails(
         &self,
@@ -400,6 +428,69 @@ impl User {
         .await
     }
 
+    /// Get the recent login attempts of the user, successful or not,
+    /// chronologically sorted. Attempts are only kept for a limited time.
+    async fn login_history(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, LoginAttempt, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::LoginAttempt))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::LoginAttempt))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let page = repo.user_login_history().list(&self.0, pagination).await?;
+
+                // Preload the total count if requested
+                let count = if ctx.look_ahead().field("totalCount").exists() {
+                    Some(repo.user_login_history().count(&self.0).await?)
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+                connection
+                    .edges
+                    .extend(page.edges.into_iter().map(|attempt| {
+                        Edge::new(
+                            OpaqueCursor(NodeCursor(NodeType::LoginAttempt, attempt.id)),
+                            LoginAttempt(attempt),
+                        )
+                    }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+
     /// Get the list of OAuth 2.0 sessions, chronologically sorted
     #[allow(clippy::too_many_arguments)]
     async fn oauth2_sessions(
@@ -424,6 +515,7 @@ impl User {
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, OAuth2Session, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let mut repo = state.repository().await?;
         let last_active = last_active.unwrap_or_default();
@@ -520,6 +612,7 @@ impl User {
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, UpstreamOAuth2Link, PreloadedTotalCount>, async_graphql::Error>
     {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let mut repo = state.repository().await?;
 
@@ -606,6 +699,7 @@ impl User {
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, AppSession, PreloadedTotalCount>, async_graphql::Error> {
+        self.ensure_can_see_sessions(ctx)?;
         let state = ctx.state();
         let requester = ctx.requester();
         let mut repo = state.repository().await?;
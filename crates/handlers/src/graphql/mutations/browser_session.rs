@@ -5,11 +5,19 @@
 // Please see LICENSE in the repository root for full details.
 
 use async_graphql::{Context, Enum, InputObject, Object, ID};
-use mas_storage::RepositoryAccess;
+use chrono::{DateTime, Utc};
+use mas_storage::{
+    user::{BrowserSessionRepository, UserPasswordRepository},
+    RepositoryAccess,
+};
+use zeroize::Zeroizing;
 
-use crate::graphql::{
-    model::{BrowserSession, NodeType},
-    state::ContextExt,
+use crate::{
+    graphql::{
+        model::{BrowserSession, NodeType},
+        state::ContextExt,
+    },
+    session_elevation,
 };
 
 #[derive(Default)]
@@ -59,6 +67,74 @@ impl EndBrowserSessionPayload {
     }
 }
 
+/// The input of the `elevateSession` mutation.
+#[derive(InputObject)]
+pub struct ElevateSessionInput {
+    /// The password of the user. If not given, the session is elevated only if
+    /// it was authenticated in the last few minutes, for example by going
+    /// through the upstream provider again.
+    password: Option<String>,
+}
+
+/// The status of the `elevateSession` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum ElevateSessionStatus {
+    /// The session was elevated.
+    Elevated,
+
+    /// The password is wrong.
+    WrongPassword,
+
+    /// The user must authenticate again, either with their password or
+    /// through their upstream provider.
+    ReauthenticationRequired,
+
+    /// The mutation can only be called from a browser session.
+    NotAllowed,
+
+    /// Too many passwords were tried recently, try again later.
+    RateLimited,
+}
+
+/// The payload of the `elevateSession` mutation.
+pub enum ElevateSessionPayload {
+    Elevated(mas_data_model::SessionElevation),
+    WrongPassword,
+    ReauthenticationRequired,
+    NotAllowed,
+    RateLimited,
+}
+
+#[Object]
+impl ElevateSessionPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> ElevateSessionStatus {
+        match self {
+            Self::Elevated(_) => ElevateSessionStatus::Elevated,
+            Self::WrongPassword => ElevateSessionStatus::WrongPassword,
+            Self::ReauthenticationRequired => ElevateSessionStatus::ReauthenticationRequired,
+            Self::NotAllowed => ElevateSessionStatus::NotAllowed,
+            Self::RateLimited => ElevateSessionStatus::RateLimited,
+        }
+    }
+
+    /// The token to give to sensitive mutations, as `elevationToken`.
+    async fn token(&self) -> Option<&str> {
+        match self {
+            Self::Elevated(elevation) => Some(&elevation.token),
+            _ => None,
+        }
+    }
+
+    /// When the token expires.
+    async fn expires_at(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Elevated(elevation) => Some(elevation.expires_at),
+            _ => None,
+        }
+    }
+}
+
 #[Object]
 impl BrowserSessionMutations {
     async fn end_browser_session(
@@ -90,4 +166,96 @@ impl BrowserSessionMutations {
 
         Ok(EndBrowserSessionPayload::Ended(Box::new(session)))
     }
+
+    /// Elevate the current browser session for a few minutes, to allow
+    /// sensitive actions like resetting the cross-signing keys or changing
+    /// email addresses.
+    async fn elevate_session(
+        &self,
+        ctx: &Context<'_>,
+        input: ElevateSessionInput,
+    ) -> Result<ElevateSessionPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+        let requester_fingerprint = ctx.requester_fingerprint();
+
+        let Some(session) = requester.browser_session() else {
+            return Ok(ElevateSessionPayload::NotAllowed);
+        };
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let authentication = if let Some(password) = input.password {
+            let password_manager = state.password_manager();
+            let user_password = if state.site_config().password_login_enabled {
+                repo.user_password().active(&session.user).await?
+            } else {
+                None
+            };
+
+            let Some(user_password) = user_password else {
+                repo.cancel().await?;
+                return Ok(ElevateSessionPayload::ReauthenticationRequired);
+            };
+
+            if let Err(e) = state
+                .limiter()
+                .check_password(requester_fingerprint, &session.user)
+            {
+                tracing::warn!(error = &e as &dyn std::error::Error);
+                repo.cancel().await?;
+                return Ok(ElevateSessionPayload::RateLimited);
+            }
+
+            if password_manager
+                .verify(
+                    user_password.version,
+                    Zeroizing::new(password.into_bytes()),
+                    user_password.hashed_password.clone(),
+                )
+                .await
+                .is_err()
+            {
+                repo.cancel().await?;
+                return Ok(ElevateSessionPayload::WrongPassword);
+            }
+
+            repo.browser_session()
+                .authenticate_with_password(&mut rng, &clock, session, &user_password)
+                .await?
+        } else {
+            let last_authentication = repo
+                .browser_session()
+                .get_last_authentication(session)
+                .await?
+                .filter(|authentication| {
+                    clock.now() - authentication.created_at
+                        < session_elevation::REAUTHENTICATION_WINDOW
+                });
+
+            let Some(authentication) = last_authentication else {
+                repo.cancel().await?;
+                return Ok(ElevateSessionPayload::ReauthenticationRequired);
+            };
+
+            authentication
+        };
+
+        let elevation =
+            session_elevation::elevate(&mut repo, &mut rng, &clock, session, &authentication)
+                .await?;
+
+        repo.save().await?;
+
+        tracing::info!(
+            audit.event = "browser_session.elevated",
+            %session.id,
+            %session.user.id,
+            "Elevated the browser session"
+        );
+
+        Ok(ElevateSessionPayload::Elevated(elevation))
+    }
 }
@@ -4,14 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use anyhow::Context as _;
 use async_graphql::{Context, Enum, InputObject, Object, ID};
 use mas_storage::{
     compat::CompatSessionRepository,
-    job::{JobRepositoryExt, SyncDevicesJob},
+    job::{JobRepositoryExt, RevokeDeviceJob},
     RepositoryAccess,
 };
 
+use super::validate_session_name;
 use crate::graphql::{
     model::{CompatSession, NodeType},
     state::ContextExt,
@@ -64,6 +64,58 @@ impl EndCompatSessionPayload {
     }
 }
 
+/// The input of the `setCompatSessionName` mutation.
+#[derive(InputObject)]
+pub struct SetCompatSessionNameInput {
+    /// The ID of the session to name.
+    compat_session_id: ID,
+
+    /// The name to give to the session, like "Work laptop". If `None` or
+    /// empty, the name is removed, and a default one is derived from the
+    /// user-agent.
+    human_name: Option<String>,
+}
+
+/// The payload of the `setCompatSessionName` mutation.
+pub enum SetCompatSessionNamePayload {
+    NotFound,
+    Invalid,
+    Updated(Box<mas_data_model::CompatSession>),
+}
+
+/// The status of the `setCompatSessionName` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SetCompatSessionNameStatus {
+    /// The session was named.
+    Updated,
+
+    /// The name is too long.
+    Invalid,
+
+    /// The session was not found.
+    NotFound,
+}
+
+#[Object]
+impl SetCompatSessionNamePayload {
+    /// The status of the mutation.
+    async fn status(&self) -> SetCompatSessionNameStatus {
+        match self {
+            Self::Updated(_) => SetCompatSessionNameStatus::Updated,
+            Self::Invalid => SetCompatSessionNameStatus::Invalid,
+            Self::NotFound => SetCompatSessionNameStatus::NotFound,
+        }
+    }
+
+    /// Returns the named session.
+    async fn compat_session(&self) -> Option<CompatSession> {
+        match self {
+            Self::Updated(session) => Some(CompatSession::new(*session.clone())),
+            Self::Invalid | Self::NotFound => None,
+        }
+    }
+}
+
 #[Object]
 impl CompatSessionMutations {
     async fn end_compat_session(
@@ -87,14 +139,10 @@ impl CompatSessionMutations {
             return Ok(EndCompatSessionPayload::NotFound);
         }
 
-        let user = repo
-            .user()
-            .lookup(session.user_id)
-            .await?
-            .context("Could not load user")?;
-
-        // Schedule a job to sync the devices of the user with the homeserver
-        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+        // Schedule a job to delete the device from the homeserver right away
+        repo.job()
+            .schedule_job(RevokeDeviceJob::new(session.user_id, &session.device))
+            .await?;
 
         let session = repo.compat_session().finish(&clock, session).await?;
 
@@ -102,4 +150,38 @@ impl CompatSessionMutations {
 
         Ok(EndCompatSessionPayload::Ended(Box::new(session)))
     }
+
+    /// Give a name to a compatibility session, like "Work laptop".
+    async fn set_compat_session_name(
+        &self,
+        ctx: &Context<'_>,
+        input: SetCompatSessionNameInput,
+    ) -> Result<SetCompatSessionNamePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let compat_session_id = NodeType::CompatSession.extract_ulid(&input.compat_session_id)?;
+        let requester = ctx.requester();
+
+        let Ok(human_name) = validate_session_name(input.human_name.as_deref()) else {
+            return Ok(SetCompatSessionNamePayload::Invalid);
+        };
+
+        let mut repo = state.repository().await?;
+
+        let session = repo.compat_session().lookup(compat_session_id).await?;
+        let Some(session) = session else {
+            return Ok(SetCompatSessionNamePayload::NotFound);
+        };
+
+        if !requester.is_owner_or_admin(&session) {
+            return Ok(SetCompatSessionNamePayload::NotFound);
+        }
+
+        repo.compat_session()
+            .set_human_name(&session, human_name)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetCompatSessionNamePayload::Updated(Box::new(session)))
+    }
 }
@@ -4,8 +4,24 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::io::{Cursor, Read as _};
+
 use anyhow::Context as _;
-use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use async_graphql::{Context, Description, Enum, InputObject, Object, Upload, ID};
+use image::{imageops::FilterType, ImageFormat, ImageReader, Limits};
+use mas_data_model::Device;
+use mas_matrix::HomeserverFeature;
+use mas_storage::{
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{JobRepositoryExt, ProvisionUserJob, ReactivateUserJob, SyncDevicesJob},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    upstream_oauth2::{
+        UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
+    },
+    user::UserEmailRepository,
+    BoxRepository, Pagination, RepositoryAccess,
+};
+use tracing::info;
 
 use crate::graphql::{
     model::{NodeType, User},
@@ -35,6 +51,8 @@ pub enum SetDisplayNameStatus {
     Set,
     /// The display name is invalid
     Invalid,
+    /// The display name is locked to the one provided by an upstream provider
+    Locked,
 }
 
 /// The payload of the `setDisplayName` mutation
@@ -42,6 +60,7 @@ pub enum SetDisplayNameStatus {
 enum SetDisplayNamePayload {
     Set(User),
     Invalid,
+    Locked,
 }
 
 #[Object(use_type_description)]
@@ -51,6 +70,7 @@ impl SetDisplayNamePayload {
         match self {
             SetDisplayNamePayload::Set(_) => SetDisplayNameStatus::Set,
             SetDisplayNamePayload::Invalid => SetDisplayNameStatus::Invalid,
+            SetDisplayNamePayload::Locked => SetDisplayNameStatus::Locked,
         }
     }
 
@@ -58,7 +78,357 @@ impl SetDisplayNamePayload {
     async fn user(&self) -> Option<&User> {
         match self {
             SetDisplayNamePayload::Set(user) => Some(user),
-            SetDisplayNamePayload::Invalid => None,
+            SetDisplayNamePayload::Invalid | SetDisplayNamePayload::Locked => None,
+        }
+    }
+}
+
+/// The input for the `setAvatar` mutation
+#[derive(InputObject)]
+struct SetAvatarInput {
+    /// The ID of the user to set the avatar of
+    user_id: ID,
+
+    /// The `mxc://` URL of the avatar to set. If `None`, the avatar will be
+    /// removed.
+    avatar_url: Option<String>,
+}
+
+/// The status of the `setAvatar` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SetAvatarStatus {
+    /// The avatar was set
+    Set,
+    /// The avatar URL is invalid
+    Invalid,
+}
+
+/// The payload of the `setAvatar` mutation
+#[derive(Description)]
+enum SetAvatarPayload {
+    Set(User),
+    Invalid,
+}
+
+#[Object(use_type_description)]
+impl SetAvatarPayload {
+    /// Status of the operation
+    async fn status(&self) -> SetAvatarStatus {
+        match self {
+            SetAvatarPayload::Set(_) => SetAvatarStatus::Set,
+            SetAvatarPayload::Invalid => SetAvatarStatus::Invalid,
+        }
+    }
+
+    /// The user that was updated
+    async fn user(&self) -> Option<&User> {
+        match self {
+            SetAvatarPayload::Set(user) => Some(user),
+            SetAvatarPayload::Invalid => None,
+        }
+    }
+}
+
+/// The input for the `uploadAvatar` mutation
+#[derive(InputObject)]
+struct UploadAvatarInput {
+    /// The ID of the user to set the avatar of
+    user_id: ID,
+
+    /// The image to use as avatar
+    file: Upload,
+}
+
+/// The status of the `uploadAvatar` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum UploadAvatarStatus {
+    /// The avatar was uploaded and set
+    Uploaded,
+    /// The file is not a supported image
+    Invalid,
+    /// The file is too large
+    TooLarge,
+}
+
+/// The payload of the `uploadAvatar` mutation
+#[derive(Description)]
+enum UploadAvatarPayload {
+    Uploaded { user: User, avatar_url: String },
+    Invalid,
+    TooLarge,
+}
+
+#[Object(use_type_description)]
+impl UploadAvatarPayload {
+    /// Status of the operation
+    async fn status(&self) -> UploadAvatarStatus {
+        match self {
+            UploadAvatarPayload::Uploaded { .. } => UploadAvatarStatus::Uploaded,
+            UploadAvatarPayload::Invalid => UploadAvatarStatus::Invalid,
+            UploadAvatarPayload::TooLarge => UploadAvatarStatus::TooLarge,
+        }
+    }
+
+    /// The user that was updated
+    async fn user(&self) -> Option<&User> {
+        match self {
+            UploadAvatarPayload::Uploaded { user, .. } => Some(user),
+            UploadAvatarPayload::Invalid | UploadAvatarPayload::TooLarge => None,
+        }
+    }
+
+    /// The `mxc://` URL of the uploaded avatar
+    async fn avatar_url(&self) -> Option<&str> {
+        match self {
+            UploadAvatarPayload::Uploaded { avatar_url, .. } => Some(avatar_url),
+            UploadAvatarPayload::Invalid | UploadAvatarPayload::TooLarge => None,
+        }
+    }
+}
+
+/// The maximum size of an uploaded avatar, before processing
+const MAX_AVATAR_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The maximum width and height of an avatar, larger images are scaled down
+const AVATAR_DIMENSION: u32 = 512;
+
+/// The maximum width and height of an uploaded image, to avoid decoding huge
+/// images
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
+/// Decode an uploaded avatar, scale it down if needed, and encode it again as
+/// PNG
+///
+/// Encoding the image again also strips any metadata it carries, like the
+/// location embedded in photos.
+fn process_avatar(data: &[u8]) -> Result<Vec<u8>, image::ImageError> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    reader.limits(limits);
+
+    let mut image = reader.decode()?;
+    if image.width() > AVATAR_DIMENSION || image.height() > AVATAR_DIMENSION {
+        image = image.resize(AVATAR_DIMENSION, AVATAR_DIMENSION, FilterType::Lanczos3);
+    }
+
+    let mut output = Cursor::new(Vec::new());
+    image.write_to(&mut output, ImageFormat::Png)?;
+    Ok(output.into_inner())
+}
+
+/// Check that an avatar URL is a valid `mxc://` URI, pointing to media
+/// uploaded on a homeserver
+fn is_valid_avatar_url(avatar_url: &str) -> bool {
+    let Some((server_name, media_id)) = avatar_url
+        .strip_prefix("mxc://")
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return false;
+    };
+
+    avatar_url.len() <= 255
+        && !server_name.is_empty()
+        && !media_id.is_empty()
+        && media_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Whether the user is linked to an upstream provider which forces their
+/// display name
+async fn displayname_locked_to_upstream(
+    repo: &mut BoxRepository,
+    user: &mas_data_model::User,
+) -> Result<bool, async_graphql::Error> {
+    let filter = UpstreamOAuthLinkFilter::new().for_user(user);
+    let links = repo
+        .upstream_oauth_link()
+        .list(filter, Pagination::first(100))
+        .await?;
+
+    for link in links.edges {
+        let provider = repo
+            .upstream_oauth_provider()
+            .lookup(link.provider_id)
+            .await?;
+        if provider.is_some_and(|provider| provider.claims_imports.displayname.is_forced()) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The input for the `renameDevice` mutation
+#[derive(InputObject)]
+struct RenameDeviceInput {
+    /// The ID of the user owning the device
+    user_id: ID,
+
+    /// The ID of the Matrix device to rename
+    device_id: String,
+
+    /// The new display name of the device
+    display_name: String,
+}
+
+/// The status of the `renameDevice` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum RenameDeviceStatus {
+    /// The device was renamed
+    Renamed,
+    /// The device was not found
+    NotFound,
+    /// The display name is invalid
+    Invalid,
+}
+
+/// The payload of the `renameDevice` mutation
+#[derive(Description)]
+enum RenameDevicePayload {
+    Renamed(User),
+    NotFound,
+    Invalid,
+}
+
+#[Object(use_type_description)]
+impl RenameDevicePayload {
+    /// Status of the operation
+    async fn status(&self) -> RenameDeviceStatus {
+        match self {
+            RenameDevicePayload::Renamed(_) => RenameDeviceStatus::Renamed,
+            RenameDevicePayload::NotFound => RenameDeviceStatus::NotFound,
+            RenameDevicePayload::Invalid => RenameDeviceStatus::Invalid,
+        }
+    }
+
+    /// The user owning the device
+    async fn user(&self) -> Option<&User> {
+        match self {
+            RenameDevicePayload::Renamed(user) => Some(user),
+            RenameDevicePayload::NotFound | RenameDevicePayload::Invalid => None,
+        }
+    }
+}
+
+/// The input for the `deleteDevice` mutation
+#[derive(InputObject)]
+struct DeleteDeviceInput {
+    /// The ID of the user owning the device
+    user_id: ID,
+
+    /// The ID of the Matrix device to delete
+    device_id: String,
+}
+
+/// The status of the `deleteDevice` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum DeleteDeviceStatus {
+    /// The device was deleted, and the sessions using it were ended
+    Deleted,
+    /// The device was not found
+    NotFound,
+}
+
+/// The payload of the `deleteDevice` mutation
+#[derive(Description)]
+enum DeleteDevicePayload {
+    Deleted(User),
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl DeleteDevicePayload {
+    /// Status of the operation
+    async fn status(&self) -> DeleteDeviceStatus {
+        match self {
+            DeleteDevicePayload::Deleted(_) => DeleteDeviceStatus::Deleted,
+            DeleteDevicePayload::NotFound => DeleteDeviceStatus::NotFound,
+        }
+    }
+
+    /// The user owning the device
+    async fn user(&self) -> Option<&User> {
+        match self {
+            DeleteDevicePayload::Deleted(user) => Some(user),
+            DeleteDevicePayload::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `resyncUser` mutation
+#[derive(InputObject)]
+struct ResyncUserInput {
+    /// The ID of the user to provision again on the homeserver
+    user_id: ID,
+}
+
+/// The status of the `resyncUser` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ResyncUserStatus {
+    /// Provisioning the user again was scheduled
+    Scheduled,
+    /// The user was not found
+    NotFound,
+}
+
+/// What provisioning a user again changes on the homeserver
+struct ResyncReport {
+    created: bool,
+    reactivated: bool,
+    emails: Vec<String>,
+}
+
+/// The payload of the `resyncUser` mutation
+#[derive(Description)]
+enum ResyncUserPayload {
+    Scheduled(User, ResyncReport),
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl ResyncUserPayload {
+    /// Status of the operation
+    async fn status(&self) -> ResyncUserStatus {
+        match self {
+            ResyncUserPayload::Scheduled(..) => ResyncUserStatus::Scheduled,
+            ResyncUserPayload::NotFound => ResyncUserStatus::NotFound,
+        }
+    }
+
+    /// The user provisioned again
+    async fn user(&self) -> Option<&User> {
+        match self {
+            ResyncUserPayload::Scheduled(user, _) => Some(user),
+            ResyncUserPayload::NotFound => None,
+        }
+    }
+
+    /// Whether the user was missing on the homeserver, and will be created
+    async fn created_on_homeserver(&self) -> Option<bool> {
+        match self {
+            ResyncUserPayload::Scheduled(_, report) => Some(report.created),
+            ResyncUserPayload::NotFound => None,
+        }
+    }
+
+    /// Whether the user was deactivated on the homeserver but not locked in
+    /// MAS, and will be reactivated
+    async fn reactivated_on_homeserver(&self) -> Option<bool> {
+        match self {
+            ResyncUserPayload::Scheduled(_, report) => Some(report.reactivated),
+            ResyncUserPayload::NotFound => None,
+        }
+    }
+
+    /// The confirmed email addresses of the user, which replace the ones on
+    /// the homeserver
+    async fn emails(&self) -> Option<&[String]> {
+        match self {
+            ResyncUserPayload::Scheduled(_, report) => Some(&report.emails),
+            ResyncUserPayload::NotFound => None,
         }
     }
 }
@@ -90,8 +460,17 @@ impl MatrixMutations {
             .lookup(id)
             .await?
             .context("Failed to lookup user")?;
+
+        // Administrators can override the display name imported from upstream
+        let locked = !requester.is_admin()
+            && state.site_config().displayname_locked_to_upstream
+            && displayname_locked_to_upstream(&mut repo, &user).await?;
         repo.cancel().await?;
 
+        if locked {
+            return Ok(SetDisplayNamePayload::Locked);
+        }
+
         let conn = state.homeserver_connection();
         let mxid = conn.mxid(&user.username);
 
@@ -116,4 +495,331 @@ impl MatrixMutations {
 
         Ok(SetDisplayNamePayload::Set(User(user.clone())))
     }
+
+    /// Set the avatar of a user, from media already uploaded to the
+    /// homeserver
+    async fn set_avatar(
+        &self,
+        ctx: &Context<'_>,
+        input: SetAvatarInput,
+    ) -> Result<SetAvatarPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        // Allow non-admins to change their avatar if the site config allows it
+        if !requester.is_admin() && !state.site_config().avatar_change_allowed {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+        let user = repo
+            .user()
+            .lookup(id)
+            .await?
+            .context("Failed to lookup user")?;
+        repo.cancel().await?;
+
+        let conn = state.homeserver_connection();
+        let mxid = conn.mxid(&user.username);
+
+        if let Some(avatar_url) = &input.avatar_url {
+            if !is_valid_avatar_url(avatar_url) {
+                return Ok(SetAvatarPayload::Invalid);
+            }
+
+            conn.set_avatar_url(&mxid, avatar_url)
+                .await
+                .context("Failed to set avatar URL")?;
+        } else {
+            conn.unset_avatar_url(&mxid)
+                .await
+                .context("Failed to unset avatar URL")?;
+        }
+
+        Ok(SetAvatarPayload::Set(User(user)))
+    }
+
+    /// Upload an image to the homeserver and set it as the avatar of a user
+    ///
+    /// The image is scaled down to 512 pixels at most, and converted to PNG.
+    /// The avatar is only changed once the image was uploaded.
+    async fn upload_avatar(
+        &self,
+        ctx: &Context<'_>,
+        input: UploadAvatarInput,
+    ) -> Result<UploadAvatarPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        // Allow non-admins to change their avatar if the site config allows it
+        if !requester.is_admin() && !state.site_config().avatar_change_allowed {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let conn = state.homeserver_connection();
+        if !conn.capabilities().supports(HomeserverFeature::ProfileSync) {
+            return Err(async_graphql::Error::new(
+                "The homeserver doesn't support changing avatars",
+            ));
+        }
+
+        let upload = input.file.value(ctx)?;
+        if upload.size()? > MAX_AVATAR_SIZE {
+            return Ok(UploadAvatarPayload::TooLarge);
+        }
+
+        let mut repo = state.repository().await?;
+        let user = repo
+            .user()
+            .lookup(id)
+            .await?
+            .context("Failed to lookup user")?;
+        repo.cancel().await?;
+
+        // Decoding and resizing the image is CPU-intensive
+        let avatar = tokio::task::spawn_blocking(move || {
+            let mut data = Vec::new();
+            upload.into_read().read_to_end(&mut data)?;
+            process_avatar(&data)
+        })
+        .await?;
+
+        let avatar = match avatar {
+            Ok(avatar) => avatar,
+            Err(e) => {
+                info!(
+                    error = &e as &dyn std::error::Error,
+                    "Rejected an invalid avatar"
+                );
+                return Ok(UploadAvatarPayload::Invalid);
+            }
+        };
+
+        let mxid = conn.mxid(&user.username);
+        let avatar_url = conn
+            .upload_media("image/png", "avatar.png", &avatar)
+            .await
+            .context("Failed to upload avatar")?;
+
+        conn.set_avatar_url(&mxid, &avatar_url)
+            .await
+            .context("Failed to set avatar URL")?;
+
+        info!(%user.id, %avatar_url, "Uploaded a new avatar for {mxid}");
+
+        Ok(UploadAvatarPayload::Uploaded {
+            user: User(user),
+            avatar_url,
+        })
+    }
+
+    /// Rename a Matrix device of a user
+    ///
+    /// Only devices used by an active session of the user can be renamed.
+    async fn rename_device(
+        &self,
+        ctx: &Context<'_>,
+        input: RenameDeviceInput,
+    ) -> Result<RenameDevicePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        // Same validation as for the display name of the user
+        if input.display_name.is_empty() || input.display_name.len() > 256 {
+            return Ok(RenameDevicePayload::Invalid);
+        }
+
+        let Ok(device) = Device::try_from(input.device_id) else {
+            return Ok(RenameDevicePayload::NotFound);
+        };
+
+        let mut repo = state.repository().await?;
+        let user = repo
+            .user()
+            .lookup(id)
+            .await?
+            .context("Failed to lookup user")?;
+
+        let filter = OAuth2SessionFilter::new()
+            .for_user(&user)
+            .for_device(&device)
+            .active_only();
+        let oauth2_sessions = repo.oauth2_session().count(filter).await?;
+
+        let filter = CompatSessionFilter::new()
+            .for_user(&user)
+            .for_device(&device)
+            .active_only();
+        let compat_sessions = repo.compat_session().count(filter).await?;
+        repo.cancel().await?;
+
+        if oauth2_sessions == 0 && compat_sessions == 0 {
+            return Ok(RenameDevicePayload::NotFound);
+        }
+
+        let conn = state.homeserver_connection();
+        let mxid = conn.mxid(&user.username);
+        conn.update_device_display_name(&mxid, device.as_str(), &input.display_name)
+            .await
+            .context("Failed to rename device")?;
+
+        Ok(RenameDevicePayload::Renamed(User(user)))
+    }
+
+    /// Delete a Matrix device of a user
+    ///
+    /// This ends the sessions using the device, which invalidates their
+    /// tokens, and deletes the device on the homeserver.
+    async fn delete_device(
+        &self,
+        ctx: &Context<'_>,
+        input: DeleteDeviceInput,
+    ) -> Result<DeleteDevicePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let Ok(device) = Device::try_from(input.device_id) else {
+            return Ok(DeleteDevicePayload::NotFound);
+        };
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+        let user = repo
+            .user()
+            .lookup(id)
+            .await?
+            .context("Failed to lookup user")?;
+
+        let filter = OAuth2SessionFilter::new()
+            .for_user(&user)
+            .for_device(&device)
+            .active_only();
+        let oauth2_sessions = repo.oauth2_session().finish_bulk(&clock, filter).await?;
+
+        let filter = CompatSessionFilter::new()
+            .for_user(&user)
+            .for_device(&device)
+            .active_only();
+        let compat_sessions = repo.compat_session().finish_bulk(&clock, filter).await?;
+
+        if oauth2_sessions == 0 && compat_sessions == 0 {
+            repo.cancel().await?;
+            return Ok(DeleteDevicePayload::NotFound);
+        }
+
+        // Schedule a job to sync the devices of the user with the homeserver, in
+        // case deleting the device right away fails
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+
+        repo.save().await?;
+
+        let conn = state.homeserver_connection();
+        let mxid = conn.mxid(&user.username);
+        if let Err(e) = conn.delete_device(&mxid, device.as_str()).await {
+            tracing::warn!(
+                error = &*e as &dyn std::error::Error,
+                "Failed to delete device {device} on the homeserver",
+                device = device.as_str(),
+            );
+        }
+
+        Ok(DeleteDevicePayload::Deleted(User(user)))
+    }
+
+    /// Provision a user again on the homeserver, syncing their profile,
+    /// devices and email addresses. This is useful when the homeserver and MAS
+    /// drifted apart, for example after an outage. This is only available to
+    /// administrators.
+    async fn resync_user(
+        &self,
+        ctx: &Context<'_>,
+        input: ResyncUserInput,
+    ) -> Result<ResyncUserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let mut repo = state.repository().await?;
+        let Some(user) = repo.user().lookup(id).await? else {
+            return Ok(ResyncUserPayload::NotFound);
+        };
+
+        let emails: Vec<String> = repo
+            .user_email()
+            .all(&user)
+            .await?
+            .into_iter()
+            .filter(|email| email.confirmed_at.is_some())
+            .map(|email| email.email)
+            .collect();
+
+        let conn = state.homeserver_connection();
+        let mxid = conn.mxid(&user.username);
+        let created = conn
+            .is_localpart_available(&user.username)
+            .await
+            .context("Failed to check whether the user exists on the homeserver")?;
+
+        let reactivated = if created || user.locked_at.is_some() {
+            false
+        } else {
+            let matrix_user = conn
+                .query_user(&mxid)
+                .await
+                .context("Failed to query the user on the homeserver")?;
+            matrix_user.deactivated
+        };
+
+        // Provisioning the user also syncs their devices
+        repo.job()
+            .schedule_job(ProvisionUserJob::new(&user))
+            .await?;
+        if reactivated {
+            repo.job()
+                .schedule_job(ReactivateUserJob::new(&user))
+                .await?;
+        }
+
+        repo.save().await?;
+
+        info!(
+            audit.event = "user.resynced",
+            %user.id,
+            created,
+            reactivated,
+            emails = emails.len(),
+            "Scheduled provisioning of {mxid} again on the homeserver"
+        );
+
+        let report = ResyncReport {
+            created,
+            reactivated,
+            emails,
+        };
+        Ok(ResyncUserPayload::Scheduled(User(user), report))
+    }
 }
@@ -30,3 +30,22 @@ impl Mutation {
         Self::default()
     }
 }
+
+/// The maximum length of a session name, in characters
+const MAX_SESSION_NAME_LENGTH: usize = 128;
+
+/// Validate a session name given by a user, trimming it.
+///
+/// Returns `Ok(None)` if the name is missing or empty, meaning the stored name
+/// should be removed, and `Err(())` if the name is too long.
+fn validate_session_name(human_name: Option<&str>) -> Result<Option<&str>, ()> {
+    let Some(human_name) = human_name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return Ok(None);
+    };
+
+    if human_name.chars().count() > MAX_SESSION_NAME_LENGTH {
+        return Err(());
+    }
+
+    Ok(Some(human_name))
+}
@@ -9,7 +9,7 @@ use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
 use chrono::Duration;
 use mas_data_model::{Device, TokenType};
 use mas_storage::{
-    job::{JobRepositoryExt, SyncDevicesJob},
+    job::{JobRepositoryExt, RevokeDeviceJob},
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
         OAuth2SessionRepository,
@@ -19,6 +19,7 @@ use mas_storage::{
 };
 use oauth2_types::scope::Scope;
 
+use super::validate_session_name;
 use crate::graphql::{
     model::{NodeType, OAuth2Session},
     state::ContextExt,
@@ -78,7 +79,7 @@ pub struct EndOAuth2SessionInput {
 /// The payload of the `endOauth2Session` mutation.
 pub enum EndOAuth2SessionPayload {
     NotFound,
-    Ended(mas_data_model::Session),
+    Ended(Box<mas_data_model::Session>),
 }
 
 /// The status of the `endOauth2Session` mutation.
@@ -104,12 +105,64 @@ impl EndOAuth2SessionPayload {
     /// Returns the ended session.
     async fn oauth2_session(&self) -> Option<OAuth2Session> {
         match self {
-            Self::Ended(session) => Some(OAuth2Session(session.clone())),
+            Self::Ended(session) => Some(OAuth2Session(*session.clone())),
             Self::NotFound => None,
         }
     }
 }
 
+/// The input of the `setOauth2SessionName` mutation.
+#[derive(InputObject)]
+pub struct SetOAuth2SessionNameInput {
+    /// The ID of the session to name.
+    oauth2_session_id: ID,
+
+    /// The name to give to the session, like "Work laptop". If `None` or
+    /// empty, the name is removed, and a default one is derived from the
+    /// user-agent.
+    human_name: Option<String>,
+}
+
+/// The payload of the `setOauth2SessionName` mutation.
+pub enum SetOAuth2SessionNamePayload {
+    NotFound,
+    Invalid,
+    Updated(Box<mas_data_model::Session>),
+}
+
+/// The status of the `setOauth2SessionName` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SetOAuth2SessionNameStatus {
+    /// The session was named.
+    Updated,
+
+    /// The name is too long.
+    Invalid,
+
+    /// The session was not found.
+    NotFound,
+}
+
+#[Object]
+impl SetOAuth2SessionNamePayload {
+    /// The status of the mutation.
+    async fn status(&self) -> SetOAuth2SessionNameStatus {
+        match self {
+            Self::Updated(_) => SetOAuth2SessionNameStatus::Updated,
+            Self::Invalid => SetOAuth2SessionNameStatus::Invalid,
+            Self::NotFound => SetOAuth2SessionNameStatus::NotFound,
+        }
+    }
+
+    /// Returns the named session.
+    async fn oauth2_session(&self) -> Option<OAuth2Session> {
+        match self {
+            Self::Updated(session) => Some(OAuth2Session(*session.clone())),
+            Self::Invalid | Self::NotFound => None,
+        }
+    }
+}
+
 #[Object]
 impl OAuth2SessionMutations {
     /// Create a new arbitrary OAuth 2.0 Session.
@@ -227,21 +280,55 @@ impl OAuth2SessionMutations {
             return Ok(EndOAuth2SessionPayload::NotFound);
         }
 
+        // Schedule jobs to delete the devices from the homeserver right away
         if let Some(user_id) = session.user_id {
-            let user = repo
-                .user()
-                .lookup(user_id)
-                .await?
-                .context("Could not load user")?;
-
-            // Schedule a job to sync the devices of the user with the homeserver
-            repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+            for scope in &*session.scope {
+                if let Some(device) = Device::from_scope_token(scope) {
+                    repo.job()
+                        .schedule_job(RevokeDeviceJob::new(user_id, &device))
+                        .await?;
+                }
+            }
         }
 
         let session = repo.oauth2_session().finish(&clock, session).await?;
 
         repo.save().await?;
 
-        Ok(EndOAuth2SessionPayload::Ended(session))
+        Ok(EndOAuth2SessionPayload::Ended(Box::new(session)))
+    }
+
+    /// Give a name to an OAuth 2.0 session, like "Work laptop".
+    async fn set_oauth2_session_name(
+        &self,
+        ctx: &Context<'_>,
+        input: SetOAuth2SessionNameInput,
+    ) -> Result<SetOAuth2SessionNamePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let oauth2_session_id = NodeType::OAuth2Session.extract_ulid(&input.oauth2_session_id)?;
+        let requester = ctx.requester();
+
+        let Ok(human_name) = validate_session_name(input.human_name.as_deref()) else {
+            return Ok(SetOAuth2SessionNamePayload::Invalid);
+        };
+
+        let mut repo = state.repository().await?;
+
+        let session = repo.oauth2_session().lookup(oauth2_session_id).await?;
+        let Some(session) = session else {
+            return Ok(SetOAuth2SessionNamePayload::NotFound);
+        };
+
+        if !requester.is_owner_or_admin(&session) {
+            return Ok(SetOAuth2SessionNamePayload::NotFound);
+        }
+
+        repo.oauth2_session()
+            .set_human_name(&session, human_name)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetOAuth2SessionNamePayload::Updated(Box::new(session)))
     }
 }
@@ -6,17 +6,22 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use chrono::{DateTime, Utc};
+use mas_data_model::UserAgent;
 use mas_storage::{
-    job::{DeactivateUserJob, JobRepositoryExt, ProvisionUserJob},
-    user::UserRepository,
+    job::{DeactivateUserJob, JobRepositoryExt, ProvisionUserJob, SendAccountRecoveryEmailsJob},
+    user::{UserPasswordRepository, UserRepository},
 };
 use tracing::{info, warn};
 use zeroize::Zeroizing;
 
-use crate::graphql::{
-    model::{NodeType, User},
-    state::ContextExt,
-    Requester, UserId,
+use crate::{
+    graphql::{
+        model::{NodeType, User},
+        state::ContextExt,
+        Requester, UserId,
+    },
+    session_elevation,
 };
 
 #[derive(Default)]
@@ -215,25 +220,69 @@ impl SetCanRequestAdminPayload {
 struct AllowUserCrossSigningResetInput {
     /// The ID of the user to update.
     user_id: ID,
+
+    /// The token returned by the `elevateSession` mutation, if the user did
+    /// not authenticate in the last few minutes.
+    elevation_token: Option<String>,
+}
+
+/// The status of the `allowUserCrossSigningReset` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum AllowUserCrossSigningResetStatus {
+    /// The user can reset their cross-signing keys.
+    Allowed,
+
+    /// The user was not found.
+    NotFound,
+
+    /// The user must authenticate again before resetting their cross-signing
+    /// keys.
+    ReauthenticationRequired,
 }
 
 /// The payload for the `allowUserCrossSigningReset` mutation.
 #[derive(Description)]
 enum AllowUserCrossSigningResetPayload {
     /// The user was updated.
-    Allowed(mas_data_model::User),
+    Allowed {
+        user: mas_data_model::User,
+        until: Option<DateTime<Utc>>,
+    },
 
     /// The user was not found.
     NotFound,
+
+    /// The user must authenticate again.
+    ReauthenticationRequired,
 }
 
 #[Object(use_type_description)]
 impl AllowUserCrossSigningResetPayload {
+    /// Status of the operation
+    async fn status(&self) -> AllowUserCrossSigningResetStatus {
+        match self {
+            Self::Allowed { .. } => AllowUserCrossSigningResetStatus::Allowed,
+            Self::NotFound => AllowUserCrossSigningResetStatus::NotFound,
+            Self::ReauthenticationRequired => {
+                AllowUserCrossSigningResetStatus::ReauthenticationRequired
+            }
+        }
+    }
+
     /// The user that was updated.
     async fn user(&self) -> Option<User> {
         match self {
-            Self::Allowed(user) => Some(User(user.clone())),
-            Self::NotFound => None,
+            Self::Allowed { user, .. } => Some(User(user.clone())),
+            Self::NotFound | Self::ReauthenticationRequired => None,
+        }
+    }
+
+    /// Until when the user can reset their cross-signing keys, if the
+    /// homeserver told it.
+    async fn allowed_until(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Allowed { until, .. } => *until,
+            Self::NotFound | Self::ReauthenticationRequired => None,
         }
     }
 }
@@ -270,6 +319,7 @@ struct SetPasswordByRecoveryInput {
 #[derive(Description)]
 struct SetPasswordPayload {
     status: SetPasswordStatus,
+    rejection_reason: Option<String>,
 }
 
 /// The status of the `setPassword` mutation.
@@ -321,6 +371,69 @@ impl SetPasswordPayload {
     async fn status(&self) -> SetPasswordStatus {
         self.status
     }
+
+    /// Why the new password was rejected, if the status is
+    /// `INVALID_NEW_PASSWORD`
+    async fn rejection_reason(&self) -> Option<&str> {
+        self.rejection_reason.as_deref()
+    }
+}
+
+/// The input for the `resetPassword` mutation.
+#[derive(InputObject)]
+struct ResetPasswordInput {
+    /// The ID of the user to reset the password of.
+    user_id: ID,
+
+    /// A temporary password to set on the account.
+    /// If not set, a recovery link is sent to the primary email address of
+    /// the user instead.
+    temporary_password: Option<String>,
+}
+
+/// The status of the `resetPassword` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum ResetPasswordStatus {
+    /// The temporary password was set.
+    TemporaryPasswordSet,
+
+    /// A recovery link was sent to the user.
+    RecoveryLinkSent,
+
+    /// The user was not found.
+    NotFound,
+
+    /// The user has no primary email address to send a recovery link to.
+    NoEmail,
+
+    /// Password support has been disabled.
+    /// This usually means that login is handled by an upstream identity
+    /// provider.
+    PasswordChangesDisabled,
+
+    /// The temporary password is invalid. For example, it may not meet
+    /// configured security requirements.
+    InvalidNewPassword,
+}
+
+/// The payload for the `resetPassword` mutation.
+#[derive(Description)]
+struct ResetPasswordPayload {
+    status: ResetPasswordStatus,
+    user: Option<mas_data_model::User>,
+}
+
+#[Object(use_type_description)]
+impl ResetPasswordPayload {
+    /// Status of the operation
+    async fn status(&self) -> ResetPasswordStatus {
+        self.status
+    }
+
+    /// The user whose password was reset.
+    async fn user(&self) -> Option<User> {
+        self.user.clone().map(User)
+    }
 }
 
 fn valid_username_character(c: char) -> bool {
@@ -445,7 +558,8 @@ impl UserMutations {
         Ok(LockUserPayload::Locked(user))
     }
 
-    /// Unlock a user. This is only available to administrators.
+    /// Unlock a user. This is only available to administrators and the
+    /// helpdesk.
     async fn unlock_user(
         &self,
         ctx: &Context<'_>,
@@ -455,7 +569,7 @@ impl UserMutations {
         let requester = ctx.requester();
         let matrix = state.homeserver_connection();
 
-        if !requester.is_admin() {
+        if !requester.is_helpdesk() {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -513,6 +627,12 @@ impl UserMutations {
     }
 
     /// Temporarily allow user to reset their cross-signing keys.
+    ///
+    /// Users who can authenticate again, with a password or through an
+    /// upstream provider, must have authenticated in the last few minutes
+    /// with their browser session, or give an elevation token, to reset their
+    /// own keys, like the homeserver would require through user-interactive
+    /// authentication. This is not required from administrators.
     async fn allow_user_cross_signing_reset(
         &self,
         ctx: &Context<'_>,
@@ -527,29 +647,59 @@ impl UserMutations {
         }
 
         let mut repo = state.repository().await?;
+        let clock = state.clock();
         let user = repo.user().lookup(user_id).await?;
-        repo.cancel().await?;
 
         let Some(user) = user else {
+            repo.cancel().await?;
             return Ok(AllowUserCrossSigningResetPayload::NotFound);
         };
 
+        if !requester.is_admin()
+            && session_elevation::can_reauthenticate(&mut repo, state.site_config(), &user).await?
+        {
+            let reauthenticated = session_elevation::has_reauthenticated(
+                &mut repo,
+                &clock,
+                requester.browser_session(),
+                input.elevation_token.as_deref(),
+            )
+            .await?;
+
+            if !reauthenticated {
+                repo.cancel().await?;
+                return Ok(AllowUserCrossSigningResetPayload::ReauthenticationRequired);
+            }
+        }
+
+        repo.cancel().await?;
+
         let conn = state.homeserver_connection();
         let mxid = conn.mxid(&user.username);
 
-        conn.allow_cross_signing_reset(&mxid)
+        let until = conn
+            .allow_cross_signing_reset(&mxid)
             .await
-            .context("Failed to allow cross-signing reset")?;
+            .context("Failed to allow cross-signing reset")?
+            .map(DateTime::<Utc>::from);
+
+        info!(
+            audit.event = "cross_signing_reset.allowed",
+            %user.id,
+            "Allowed the user to reset their cross-signing keys"
+        );
 
-        Ok(AllowUserCrossSigningResetPayload::Allowed(user))
+        Ok(AllowUserCrossSigningResetPayload::Allowed { user, until })
     }
 
     /// Set the password for a user.
     ///
     /// This can be used by server administrators to set any user's password,
-    /// or, provided the capability hasn't been disabled on this server,
-    /// by a user to change their own password as long as they know their
-    /// current password.
+    /// by the helpdesk to set the password of users who can't request admin,
+    /// or, provided the capability hasn't been disabled on this server, by a
+    /// user to change their own password as long as they know their current
+    /// password.
+    #[allow(clippy::too_many_lines)]
     async fn set_password(
         &self,
         ctx: &Context<'_>,
@@ -559,7 +709,7 @@ impl UserMutations {
         let user_id = NodeType::User.extract_ulid(&input.user_id)?;
         let requester = ctx.requester();
 
-        if !requester.is_owner_or_admin(&UserId(user_id)) {
+        if !requester.is_owner_or_helpdesk(&UserId(user_id)) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -570,6 +720,7 @@ impl UserMutations {
             // with a list of union of different error kinds.
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::InvalidNewPassword,
+                rejection_reason: None,
             });
         }
 
@@ -578,12 +729,7 @@ impl UserMutations {
         if !password_manager.is_enabled() {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::PasswordChangesDisabled,
-            });
-        }
-
-        if !password_manager.is_password_complex_enough(&input.new_password)? {
-            return Ok(SetPasswordPayload {
-                status: SetPasswordStatus::InvalidNewPassword,
+                rejection_reason: None,
             });
         }
 
@@ -591,17 +737,41 @@ impl UserMutations {
         let Some(user) = repo.user().lookup(user_id).await? else {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::NotFound,
+                rejection_reason: None,
             });
         };
 
-        if !requester.is_admin() {
-            // If the user isn't an admin, we:
+        // The helpdesk could otherwise take over an admin account, and get the
+        // admin role through it
+        if !requester.is_owner_or_admin(&user) && user.can_request_admin {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let policy_class = repo.user().get_password_policy_class(&user).await?;
+        let validation = password_manager
+            .validate_new_password(
+                policy_class.as_deref(),
+                &user.username,
+                &input.new_password,
+                None,
+            )
+            .await?;
+        if !validation.is_valid() {
+            return Ok(SetPasswordPayload {
+                status: SetPasswordStatus::InvalidNewPassword,
+                rejection_reason: validation.message(),
+            });
+        }
+
+        if !requester.is_helpdesk() {
+            // If the user isn't an admin or the helpdesk, we:
             // - check that password changes are enabled
             // - check that they know their current password
 
             if !state.site_config().password_change_allowed {
                 return Ok(SetPasswordPayload {
                     status: SetPasswordStatus::PasswordChangesDisabled,
+                    rejection_reason: None,
                 });
             }
 
@@ -612,6 +782,7 @@ impl UserMutations {
 
                 return Ok(SetPasswordPayload {
                     status: SetPasswordStatus::NoCurrentPassword,
+                    rejection_reason: None,
                 });
             };
 
@@ -631,6 +802,7 @@ impl UserMutations {
             {
                 return Ok(SetPasswordPayload {
                     status: SetPasswordStatus::WrongPassword,
+                    rejection_reason: None,
                 });
             }
         }
@@ -650,10 +822,19 @@ impl UserMutations {
             )
             .await?;
 
+        // Users changing their own password no longer have to change it on their
+        // next login
+        if requester.user().is_some_and(|u| u.id == user.id) {
+            repo.user()
+                .set_password_change_required(&state.clock(), &user, false)
+                .await?;
+        }
+
         repo.save().await?;
 
         Ok(SetPasswordPayload {
             status: SetPasswordStatus::Allowed,
+            rejection_reason: None,
         })
     }
 
@@ -677,12 +858,7 @@ impl UserMutations {
         if !password_manager.is_enabled() || !state.site_config().account_recovery_allowed {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::PasswordChangesDisabled,
-            });
-        }
-
-        if !password_manager.is_password_complex_enough(&input.new_password)? {
-            return Ok(SetPasswordPayload {
-                status: SetPasswordStatus::InvalidNewPassword,
+                rejection_reason: None,
             });
         }
 
@@ -691,6 +867,7 @@ impl UserMutations {
         let Some(ticket) = repo.user_recovery().find_ticket(&input.ticket).await? else {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::NoSuchRecoveryTicket,
+                rejection_reason: None,
             });
         };
 
@@ -703,12 +880,14 @@ impl UserMutations {
         if session.consumed_at.is_some() {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::RecoveryTicketAlreadyUsed,
+                rejection_reason: None,
             });
         }
 
         if !ticket.active(clock.now()) {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::ExpiredRecoveryTicket,
+                rejection_reason: None,
             });
         }
 
@@ -729,6 +908,23 @@ impl UserMutations {
         if !user.is_valid() {
             return Ok(SetPasswordPayload {
                 status: SetPasswordStatus::AccountLocked,
+                rejection_reason: None,
+            });
+        }
+
+        let policy_class = repo.user().get_password_policy_class(&user).await?;
+        let validation = password_manager
+            .validate_new_password(
+                policy_class.as_deref(),
+                &user.username,
+                &input.new_password,
+                Some(&session.locale),
+            )
+            .await?;
+        if !validation.is_valid() {
+            return Ok(SetPasswordPayload {
+                status: SetPasswordStatus::InvalidNewPassword,
+                rejection_reason: validation.message(),
             });
         }
 
@@ -747,6 +943,10 @@ impl UserMutations {
             )
             .await?;
 
+        repo.user()
+            .set_password_change_required(&clock, &user, false)
+            .await?;
+
         // Mark the session as consumed
         repo.user_recovery()
             .consume_ticket(&clock, ticket, session)
@@ -756,6 +956,124 @@ impl UserMutations {
 
         Ok(SetPasswordPayload {
             status: SetPasswordStatus::Allowed,
+            rejection_reason: None,
+        })
+    }
+
+    /// Reset the password of a user, either by setting a temporary password or
+    /// by sending them a recovery link, and require them to choose a new
+    /// password on their next login.
+    ///
+    /// This is only available to administrators and the helpdesk, which can't
+    /// reset the password of users who can request admin.
+    async fn reset_password(
+        &self,
+        ctx: &Context<'_>,
+        input: ResetPasswordInput,
+    ) -> Result<ResetPasswordPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        if !requester.is_helpdesk() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+
+        let password_manager = state.password_manager();
+        if !password_manager.is_enabled() {
+            return Ok(ResetPasswordPayload {
+                status: ResetPasswordStatus::PasswordChangesDisabled,
+                user: None,
+            });
+        }
+
+        let mut repo = state.repository().await?;
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(ResetPasswordPayload {
+                status: ResetPasswordStatus::NotFound,
+                user: None,
+            });
+        };
+
+        // Same as in `set_password`, the helpdesk can't take over admin accounts
+        if !requester.is_admin() && user.can_request_admin {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let status = if let Some(temporary_password) = input.temporary_password {
+            let policy_class = repo.user().get_password_policy_class(&user).await?;
+            if temporary_password.is_empty()
+                || !password_manager
+                    .validate_new_password(
+                        policy_class.as_deref(),
+                        &user.username,
+                        &temporary_password,
+                        None,
+                    )
+                    .await?
+                    .is_valid()
+            {
+                return Ok(ResetPasswordPayload {
+                    status: ResetPasswordStatus::InvalidNewPassword,
+                    user: Some(user),
+                });
+            }
+
+            let (version, hashed_password) = password_manager
+                .hash(&mut rng, Zeroizing::new(temporary_password.into_bytes()))
+                .await?;
+
+            repo.user_password()
+                .add(&mut rng, &clock, &user, version, hashed_password, None)
+                .await?;
+
+            info!("Temporary password set for user {}", user.id);
+            ResetPasswordStatus::TemporaryPasswordSet
+        } else {
+            let email = match user.primary_user_email_id {
+                Some(id) => repo.user_email().lookup(id).await?,
+                None => None,
+            };
+
+            let Some(email) = email else {
+                return Ok(ResetPasswordPayload {
+                    status: ResetPasswordStatus::NoEmail,
+                    user: Some(user),
+                });
+            };
+
+            let session = repo
+                .user_recovery()
+                .add_session(
+                    &mut rng,
+                    &clock,
+                    email.email,
+                    UserAgent::parse("mas-admin".to_owned()),
+                    None,
+                    "en".to_owned(),
+                )
+                .await?;
+
+            repo.job()
+                .schedule_job(SendAccountRecoveryEmailsJob::new(&session))
+                .await?;
+
+            info!("Sending a password recovery link to user {}", user.id);
+            ResetPasswordStatus::RecoveryLinkSent
+        };
+
+        repo.user()
+            .set_password_change_required(&clock, &user, true)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(ResetPasswordPayload {
+            status,
+            user: Some(user),
         })
     }
 }
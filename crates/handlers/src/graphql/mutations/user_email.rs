@@ -6,16 +6,21 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use mas_data_model::SiteConfig;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    job::{JobRepositoryExt, ProvisionUserJob},
     user::{UserEmailRepository, UserRepository},
-    RepositoryAccess,
+    BoxClock, BoxRepository, RepositoryAccess, RepositoryError,
 };
 
-use crate::graphql::{
-    model::{NodeType, User, UserEmail},
-    state::ContextExt,
-    UserId,
+use crate::{
+    email_verification::{self, SendCodeError, VerificationOutcome},
+    graphql::{
+        model::{NodeType, User, UserEmail},
+        state::ContextExt,
+        Requester, UserId,
+    },
+    session_elevation,
 };
 
 #[derive(Default)]
@@ -37,6 +42,11 @@ struct AddEmailInput {
 
     /// Skip the email address policy check. Only allowed for admins.
     skip_policy_check: Option<bool>,
+
+    /// The token returned by the `elevateSession` mutation, if the server
+    /// requires users to authenticate again before changing their email
+    /// addresses.
+    elevation_token: Option<String>,
 }
 
 /// The status of the `addEmail` mutation
@@ -50,6 +60,8 @@ pub enum AddEmailStatus {
     Invalid,
     /// The email address is not allowed by the policy
     Denied,
+    /// The user must authenticate again before adding an email address
+    ReauthenticationRequired,
 }
 
 /// The payload of the `addEmail` mutation
@@ -61,6 +73,7 @@ enum AddEmailPayload {
     Denied {
         violations: Vec<mas_policy::Violation>,
     },
+    ReauthenticationRequired,
 }
 
 #[Object(use_type_description)]
@@ -72,6 +85,7 @@ impl AddEmailPayload {
             AddEmailPayload::Exists(_) => AddEmailStatus::Exists,
             AddEmailPayload::Invalid => AddEmailStatus::Invalid,
             AddEmailPayload::Denied { .. } => AddEmailStatus::Denied,
+            AddEmailPayload::ReauthenticationRequired => AddEmailStatus::ReauthenticationRequired,
         }
     }
 
@@ -81,7 +95,9 @@ impl AddEmailPayload {
             AddEmailPayload::Added(email) | AddEmailPayload::Exists(email) => {
                 Some(UserEmail(email.clone()))
             }
-            AddEmailPayload::Invalid | AddEmailPayload::Denied { .. } => None,
+            AddEmailPayload::Invalid
+            | AddEmailPayload::Denied { .. }
+            | AddEmailPayload::ReauthenticationRequired => None,
         }
     }
 
@@ -92,7 +108,9 @@ impl AddEmailPayload {
 
         let user_id = match self {
             AddEmailPayload::Added(email) | AddEmailPayload::Exists(email) => email.user_id,
-            AddEmailPayload::Invalid | AddEmailPayload::Denied { .. } => return Ok(None),
+            AddEmailPayload::Invalid
+            | AddEmailPayload::Denied { .. }
+            | AddEmailPayload::ReauthenticationRequired => return Ok(None),
         };
 
         let user = repo
@@ -129,6 +147,8 @@ enum SendVerificationEmailStatus {
     Sent,
     /// The email address is already verified
     AlreadyVerified,
+    /// Too many verification emails were sent recently, try again later
+    RateLimited,
 }
 
 /// The payload of the `sendVerificationEmail` mutation
@@ -136,6 +156,7 @@ enum SendVerificationEmailStatus {
 enum SendVerificationEmailPayload {
     Sent(mas_data_model::UserEmail),
     AlreadyVerified(mas_data_model::UserEmail),
+    RateLimited(mas_data_model::UserEmail),
 }
 
 #[Object(use_type_description)]
@@ -147,6 +168,9 @@ impl SendVerificationEmailPayload {
             SendVerificationEmailPayload::AlreadyVerified(_) => {
                 SendVerificationEmailStatus::AlreadyVerified
             }
+            SendVerificationEmailPayload::RateLimited(_) => {
+                SendVerificationEmailStatus::RateLimited
+            }
         }
     }
 
@@ -154,7 +178,8 @@ impl SendVerificationEmailPayload {
     async fn email(&self) -> UserEmail {
         match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => UserEmail(email.clone()),
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email) => UserEmail(email.clone()),
         }
     }
 
@@ -165,7 +190,8 @@ impl SendVerificationEmailPayload {
 
         let user_id = match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => email.user_id,
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email) => email.user_id,
         };
 
         let user = repo
@@ -196,6 +222,10 @@ enum VerifyEmailStatus {
     AlreadyVerified,
     /// The verification code is invalid
     InvalidCode,
+    /// The verification code has expired, a new one has to be sent
+    ExpiredCode,
+    /// Too many codes were submitted recently, try again later
+    TooManyAttempts,
 }
 
 /// The payload of the `verifyEmail` mutation
@@ -204,6 +234,20 @@ enum VerifyEmailPayload {
     Verified(mas_data_model::UserEmail),
     AlreadyVerified(mas_data_model::UserEmail),
     InvalidCode,
+    ExpiredCode,
+    TooManyAttempts,
+}
+
+impl From<VerificationOutcome> for VerifyEmailPayload {
+    fn from(outcome: VerificationOutcome) -> Self {
+        match outcome {
+            VerificationOutcome::Verified(email) => Self::Verified(email),
+            VerificationOutcome::AlreadyVerified(email) => Self::AlreadyVerified(email),
+            VerificationOutcome::InvalidCode => Self::InvalidCode,
+            VerificationOutcome::ExpiredCode => Self::ExpiredCode,
+            VerificationOutcome::TooManyAttempts => Self::TooManyAttempts,
+        }
+    }
 }
 
 #[Object(use_type_description)]
@@ -214,6 +258,8 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(_) => VerifyEmailStatus::Verified,
             VerifyEmailPayload::AlreadyVerified(_) => VerifyEmailStatus::AlreadyVerified,
             VerifyEmailPayload::InvalidCode => VerifyEmailStatus::InvalidCode,
+            VerifyEmailPayload::ExpiredCode => VerifyEmailStatus::ExpiredCode,
+            VerifyEmailPayload::TooManyAttempts => VerifyEmailStatus::TooManyAttempts,
         }
     }
 
@@ -223,7 +269,9 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(email) | VerifyEmailPayload::AlreadyVerified(email) => {
                 Some(UserEmail(email.clone()))
             }
-            VerifyEmailPayload::InvalidCode => None,
+            VerifyEmailPayload::InvalidCode
+            | VerifyEmailPayload::ExpiredCode
+            | VerifyEmailPayload::TooManyAttempts => None,
         }
     }
 
@@ -236,7 +284,9 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(email) | VerifyEmailPayload::AlreadyVerified(email) => {
                 email.user_id
             }
-            VerifyEmailPayload::InvalidCode => return Ok(None),
+            VerifyEmailPayload::InvalidCode
+            | VerifyEmailPayload::ExpiredCode
+            | VerifyEmailPayload::TooManyAttempts => return Ok(None),
         };
 
         let user = repo
@@ -254,6 +304,11 @@ impl VerifyEmailPayload {
 struct RemoveEmailInput {
     /// The ID of the email address to remove
     user_email_id: ID,
+
+    /// The token returned by the `elevateSession` mutation, if the server
+    /// requires users to authenticate again before changing their email
+    /// addresses.
+    elevation_token: Option<String>,
 }
 
 /// The status of the `removeEmail` mutation
@@ -267,6 +322,9 @@ enum RemoveEmailStatus {
 
     /// The email address was not found
     NotFound,
+
+    /// The user must authenticate again before removing an email address
+    ReauthenticationRequired,
 }
 
 /// The payload of the `removeEmail` mutation
@@ -275,6 +333,7 @@ enum RemoveEmailPayload {
     Removed(mas_data_model::UserEmail),
     Primary(mas_data_model::UserEmail),
     NotFound,
+    ReauthenticationRequired,
 }
 
 #[Object(use_type_description)]
@@ -285,6 +344,9 @@ impl RemoveEmailPayload {
             RemoveEmailPayload::Removed(_) => RemoveEmailStatus::Removed,
             RemoveEmailPayload::Primary(_) => RemoveEmailStatus::Primary,
             RemoveEmailPayload::NotFound => RemoveEmailStatus::NotFound,
+            RemoveEmailPayload::ReauthenticationRequired => {
+                RemoveEmailStatus::ReauthenticationRequired
+            }
         }
     }
 
@@ -294,7 +356,7 @@ impl RemoveEmailPayload {
             RemoveEmailPayload::Removed(email) | RemoveEmailPayload::Primary(email) => {
                 Some(UserEmail(email.clone()))
             }
-            RemoveEmailPayload::NotFound => None,
+            RemoveEmailPayload::NotFound | RemoveEmailPayload::ReauthenticationRequired => None,
         }
     }
 
@@ -307,7 +369,9 @@ impl RemoveEmailPayload {
             RemoveEmailPayload::Removed(email) | RemoveEmailPayload::Primary(email) => {
                 email.user_id
             }
-            RemoveEmailPayload::NotFound => return Ok(None),
+            RemoveEmailPayload::NotFound | RemoveEmailPayload::ReauthenticationRequired => {
+                return Ok(None)
+            }
         };
 
         let user = repo
@@ -365,6 +429,34 @@ impl SetPrimaryEmailPayload {
     }
 }
 
+/// Whether the requester must authenticate again before changing the email
+/// addresses of the user
+async fn reauthentication_required(
+    repo: &mut BoxRepository,
+    clock: &BoxClock,
+    site_config: &SiteConfig,
+    requester: &Requester,
+    user: &mas_data_model::User,
+    elevation_token: Option<&str>,
+) -> Result<bool, RepositoryError> {
+    if requester.is_admin() || !site_config.email_change_requires_reauthentication {
+        return Ok(false);
+    }
+
+    if !session_elevation::can_reauthenticate(repo, site_config, user).await? {
+        return Ok(false);
+    }
+
+    let reauthenticated = session_elevation::has_reauthenticated(
+        repo,
+        clock,
+        requester.browser_session(),
+        elevation_token,
+    )
+    .await?;
+    Ok(!reauthenticated)
+}
+
 #[Object]
 impl UserEmailMutations {
     /// Add an email address to the specified user
@@ -404,6 +496,20 @@ impl UserEmailMutations {
             .await?
             .context("Failed to load user")?;
 
+        if reauthentication_required(
+            &mut repo,
+            &state.clock(),
+            state.site_config(),
+            requester,
+            &user,
+            input.elevation_token.as_deref(),
+        )
+        .await?
+        {
+            repo.cancel().await?;
+            return Ok(AddEmailPayload::ReauthenticationRequired);
+        }
+
         // XXX: this logic should be extracted somewhere else, since most of it is
         // duplicated in mas_handlers
 
@@ -447,9 +553,15 @@ impl UserEmailMutations {
                     .await?;
             } else {
                 // TODO: figure out the locale
-                repo.job()
-                    .schedule_job(VerifyEmailJob::new(&user_email))
-                    .await?;
+                match email_verification::send_code(&mut repo, state.limiter(), &user_email, None)
+                    .await
+                {
+                    Ok(()) => {}
+                    Err(SendCodeError::RateLimited(e)) => {
+                        tracing::warn!(error = &e as &dyn std::error::Error);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
             }
         }
 
@@ -485,23 +597,24 @@ impl UserEmailMutations {
             return Err(async_graphql::Error::new("User email not found"));
         }
 
-        // Schedule a job to verify the email address if needed
-        let needs_verification = user_email.confirmed_at.is_none();
-        if needs_verification {
-            // TODO: figure out the locale
-            repo.job()
-                .schedule_job(VerifyEmailJob::new(&user_email))
-                .await?;
+        if user_email.confirmed_at.is_some() {
+            return Ok(SendVerificationEmailPayload::AlreadyVerified(user_email));
+        }
+
+        // Schedule a job to verify the email address
+        // TODO: figure out the locale
+        match email_verification::send_code(&mut repo, state.limiter(), &user_email, None).await {
+            Ok(()) => {}
+            Err(SendCodeError::RateLimited(e)) => {
+                tracing::warn!(error = &e as &dyn std::error::Error);
+                return Ok(SendVerificationEmailPayload::RateLimited(user_email));
+            }
+            Err(e) => return Err(e.into()),
         }
 
         repo.save().await?;
 
-        let payload = if needs_verification {
-            SendVerificationEmailPayload::Sent(user_email)
-        } else {
-            SendVerificationEmailPayload::AlreadyVerified(user_email)
-        };
-        Ok(payload)
+        Ok(SendVerificationEmailPayload::Sent(user_email))
     }
 
     /// Submit a verification code for an email address
@@ -527,53 +640,18 @@ impl UserEmailMutations {
             return Err(async_graphql::Error::new("User email not found"));
         }
 
-        if user_email.confirmed_at.is_some() {
-            // Just return the email address if it's already verified
-            // XXX: should we return an error instead?
-            return Ok(VerifyEmailPayload::AlreadyVerified(user_email));
-        }
-
-        // XXX: this logic should be extracted somewhere else, since most of it is
-        // duplicated in mas_handlers
-
-        // Find the verification code
-        let verification = repo
-            .user_email()
-            .find_verification_code(&clock, &user_email, &input.code)
-            .await?
-            .filter(|v| v.is_valid());
-
-        let Some(verification) = verification else {
-            return Ok(VerifyEmailPayload::InvalidCode);
-        };
-
-        repo.user_email()
-            .consume_verification_code(&clock, verification)
-            .await?;
-
-        let user = repo
-            .user()
-            .lookup(user_email.user_id)
-            .await?
-            .context("Failed to load user")?;
-
-        // XXX: is this the right place to do this?
-        if user.primary_user_email_id.is_none() {
-            repo.user_email().set_as_primary(&user_email).await?;
-        }
-
-        let user_email = repo
-            .user_email()
-            .mark_as_verified(&clock, user_email)
-            .await?;
-
-        repo.job()
-            .schedule_job(ProvisionUserJob::new(&user))
-            .await?;
+        let outcome = email_verification::verify_code(
+            &mut repo,
+            &clock,
+            state.limiter(),
+            user_email,
+            &input.code,
+        )
+        .await?;
 
         repo.save().await?;
 
-        Ok(VerifyEmailPayload::Verified(user_email))
+        Ok(outcome.into())
     }
 
     /// Remove an email address
@@ -608,6 +686,20 @@ impl UserEmailMutations {
             .await?
             .context("Failed to load user")?;
 
+        if reauthentication_required(
+            &mut repo,
+            &state.clock(),
+            state.site_config(),
+            requester,
+            &user,
+            input.elevation_token.as_deref(),
+        )
+        .await?
+        {
+            repo.cancel().await?;
+            return Ok(RemoveEmailPayload::ReauthenticationRequired);
+        }
+
         if user.primary_user_email_id == Some(user_email.id) {
             // Prevent removing the primary email address
             return Ok(RemoveEmailPayload::Primary(user_email));
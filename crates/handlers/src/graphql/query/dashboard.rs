@@ -0,0 +1,26 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{Context, Object};
+
+use crate::graphql::{model::Dashboard, state::ContextExt as _};
+
+#[derive(Default)]
+pub struct DashboardQuery;
+
+#[Object]
+impl DashboardQuery {
+    /// Get aggregate statistics about the service.
+    ///
+    /// This is only available to administrators.
+    async fn dashboard(&self, ctx: &Context<'_>) -> Result<Dashboard, async_graphql::Error> {
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        Ok(Dashboard)
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{
+    connection::{query, Connection, Edge, OpaqueCursor},
+    Context, Object, ID,
+};
+use mas_storage::{email_queue::QueuedEmailFilter, Pagination, RepositoryAccess};
+
+use crate::graphql::{
+    model::{Cursor, NodeCursor, NodeType, PreloadedTotalCount, QueuedEmail, QueuedEmailState},
+    state::ContextExt,
+};
+
+#[derive(Default)]
+pub struct EmailQueueQuery;
+
+#[Object]
+impl EmailQueueQuery {
+    /// Fetch a queued email by its ID.
+    ///
+    /// This is only available to administrators.
+    pub async fn queued_email(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+    ) -> Result<Option<QueuedEmail>, async_graphql::Error> {
+        let id = NodeType::QueuedEmail.extract_ulid(&id)?;
+
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Ok(None);
+        }
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let email = repo.email_queue().lookup(id).await?;
+        repo.cancel().await?;
+
+        Ok(email.map(QueuedEmail))
+    }
+
+    /// Get a list of the emails in the outgoing queue.
+    ///
+    /// This is only available to administrators.
+    async fn queued_emails(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(name = "state", desc = "List only emails in the given state.")]
+        state_param: Option<QueuedEmailState>,
+
+        #[graphql(
+            name = "recipient",
+            desc = "List only emails sent to the given address."
+        )]
+        recipient_param: Option<String>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, QueuedEmail, PreloadedTotalCount>, async_graphql::Error> {
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::QueuedEmail))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::QueuedEmail))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                // Build the query filter
+                let filter = QueuedEmailFilter::new();
+                let filter = match &recipient_param {
+                    Some(recipient) => filter.for_recipient(recipient),
+                    None => filter,
+                };
+                let filter = match state_param {
+                    Some(QueuedEmailState::Pending) => filter.pending_only(),
+                    Some(QueuedEmailState::Sent) => filter.sent_only(),
+                    Some(QueuedEmailState::Failed) => filter.failed_only(),
+                    None => filter,
+                };
+
+                let page = repo.email_queue().list(filter, pagination).await?;
+
+                // Preload the total count if requested
+                let count = if ctx.look_ahead().field("totalCount").exists() {
+                    Some(repo.email_queue().count(filter).await?)
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+                connection.edges.extend(page.edges.into_iter().map(|email| {
+                    Edge::new(
+                        OpaqueCursor(NodeCursor(NodeType::QueuedEmail, email.id)),
+                        QueuedEmail(email),
+                    )
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
@@ -14,13 +14,16 @@ use crate::graphql::{
     state::ContextExt,
 };
 
+mod dashboard;
+mod email_queue;
 mod session;
 mod upstream_oauth;
 mod user;
 mod viewer;
 
 use self::{
-    session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, user::UserQuery, viewer::ViewerQuery,
+    dashboard::DashboardQuery, email_queue::EmailQueueQuery, session::SessionQuery,
+    upstream_oauth::UpstreamOAuthQuery, user::UserQuery, viewer::ViewerQuery,
 };
 
 /// The query root of the GraphQL interface.
@@ -31,6 +34,8 @@ pub struct Query(
     UpstreamOAuthQuery,
     SessionQuery,
     ViewerQuery,
+    DashboardQuery,
+    EmailQueueQuery,
 );
 
 impl Query {
@@ -199,7 +204,7 @@ impl BaseQuery {
 
         let ret = match node_type {
             // TODO
-            NodeType::Authentication | NodeType::CompatSsoLogin => None,
+            NodeType::Authentication | NodeType::CompatSsoLogin | NodeType::LoginAttempt => None,
 
             NodeType::UpstreamOAuth2Provider => UpstreamOAuthQuery
                 .upstream_oauth2_provider(ctx, id)
@@ -236,6 +241,11 @@ impl BaseQuery {
                 .await?
                 .map(|s| Node::BrowserSession(Box::new(s))),
 
+            NodeType::QueuedEmail => EmailQueueQuery
+                .queued_email(ctx, id)
+                .await?
+                .map(|e| Node::QueuedEmail(Box::new(e))),
+
             NodeType::User => UserQuery
                 .user(ctx, id)
                 .await?
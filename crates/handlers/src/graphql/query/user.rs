@@ -30,7 +30,7 @@ impl UserQuery {
         let id = NodeType::User.extract_ulid(&id)?;
 
         let requester = ctx.requester();
-        if !requester.is_owner_or_admin(&UserId(id)) {
+        if !requester.is_owner_or_helpdesk(&UserId(id)) {
             return Ok(None);
         }
 
@@ -61,8 +61,8 @@ impl UserQuery {
             return Ok(None);
         };
 
-        // Users can only see themselves, except for admins
-        if !requester.is_owner_or_admin(&user) {
+        // Users can only see themselves, except for admins and the helpdesk
+        if !requester.is_owner_or_helpdesk(&user) {
             return Ok(None);
         }
 
@@ -71,7 +71,7 @@ impl UserQuery {
 
     /// Get a list of users.
     ///
-    /// This is only available to administrators.
+    /// This is only available to administrators and the helpdesk.
     async fn users(
         &self,
         ctx: &Context<'_>,
@@ -93,7 +93,7 @@ impl UserQuery {
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, User, PreloadedTotalCount>, async_graphql::Error> {
         let requester = ctx.requester();
-        if !requester.is_admin() {
+        if !requester.is_helpdesk() {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
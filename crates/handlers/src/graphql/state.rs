@@ -9,7 +9,11 @@ use mas_matrix::HomeserverConnection;
 use mas_policy::Policy;
 use mas_storage::{BoxClock, BoxRepository, BoxRng, RepositoryError};
 
-use crate::{graphql::Requester, passwords::PasswordManager};
+use crate::{
+    graphql::{Loaders, Requester},
+    passwords::PasswordManager,
+    Limiter, RequesterFingerprint,
+};
 
 #[async_trait::async_trait]
 pub trait State {
@@ -20,6 +24,7 @@ pub trait State {
     fn clock(&self) -> BoxClock;
     fn rng(&self) -> BoxRng;
     fn site_config(&self) -> &SiteConfig;
+    fn limiter(&self) -> &Limiter;
 }
 
 pub type BoxState = Box<dyn State + Send + Sync + 'static>;
@@ -28,6 +33,10 @@ pub trait ContextExt {
     fn state(&self) -> &BoxState;
 
     fn requester(&self) -> &Requester;
+
+    fn requester_fingerprint(&self) -> RequesterFingerprint;
+
+    fn loaders(&self) -> &Loaders;
 }
 
 impl ContextExt for async_graphql::Context<'_> {
@@ -38,4 +47,12 @@ impl ContextExt for async_graphql::Context<'_> {
     fn requester(&self) -> &Requester {
         self.data_unchecked()
     }
+
+    fn requester_fingerprint(&self) -> RequesterFingerprint {
+        *self.data_unchecked()
+    }
+
+    fn loaders(&self) -> &Loaders {
+        self.data_unchecked()
+    }
 }
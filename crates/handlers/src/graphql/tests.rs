@@ -4,13 +4,24 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::io::Cursor;
+
 use axum::http::Request;
 use hyper::StatusCode;
-use mas_data_model::{AccessToken, Client, TokenType, User};
+use mas_data_model::{
+    AccessToken, Client, Device, LoginMethod, TokenType, UpstreamOAuthProviderClaimsImports,
+    UpstreamOAuthProviderDiscoveryMode, UpstreamOAuthProviderImportAction,
+    UpstreamOAuthProviderImportPreference, UpstreamOAuthProviderPkceMode,
+    UpstreamOAuthProviderResponseMode, UpstreamOAuthProviderTokenAuthMethod, User, UserAgent,
+};
 use mas_matrix::{HomeserverConnection, ProvisionRequest};
 use mas_router::SimpleRoute;
 use mas_storage::{
-    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository},
+    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2SessionRepository},
+    upstream_oauth2::{
+        UpstreamOAuthLinkRepository, UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository,
+    },
+    user::{UserLoginHistoryRepository, UserPasswordRepository},
     RepositoryAccess,
 };
 use oauth2_types::{
@@ -19,10 +30,12 @@ use oauth2_types::{
     scope::{Scope, ScopeToken, OPENID},
 };
 use sqlx::PgPool;
+use zeroize::Zeroizing;
 
 use crate::{
     test_utils,
-    test_utils::{setup, RequestBuilderExt, ResponseExt, TestState},
+    test_utils::{setup, test_site_config, RequestBuilderExt, ResponseExt, TestState},
+    NetworkPolicy, RequestOrigin,
 };
 
 async fn create_test_client(state: &TestState) -> Client {
@@ -111,6 +124,7 @@ async fn start_oauth_session(
 
 const GRAPHQL: ScopeToken = ScopeToken::from_static("urn:mas:graphql:*");
 const ADMIN: ScopeToken = ScopeToken::from_static("urn:mas:admin");
+const HELPDESK: ScopeToken = ScopeToken::from_static("urn:mas:admin:helpdesk");
 
 #[derive(serde::Deserialize)]
 struct GraphQLResponse {
@@ -222,6 +236,177 @@ async fn test_oauth2_viewer(pool: PgPool) {
     );
 }
 
+/// Test the security checkup of the viewer
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_security_checkup(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+
+    let req = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query {
+                    viewer {
+                        ... on User {
+                            securityCheckup {
+                                passwordChangedAt
+                                mfaEnabled
+                                verifiedEmails
+                                unusualRecentSessions {
+                                    id
+                                }
+                                broadScopeOauth2Sessions {
+                                    id
+                                }
+                                issues
+                            }
+                        }
+                    }
+                }
+            ",
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "securityCheckup": {
+                    "passwordChangedAt": null,
+                    "mfaEnabled": false,
+                    "verifiedEmails": 0,
+                    "unusualRecentSessions": [],
+                    // The session used to query the API has access to the GraphQL API
+                    "broadScopeOauth2Sessions": [{
+                        "id": format!("oauth2_session:{id}", id = access_token.session_id),
+                    }],
+                    "issues": [
+                        "NO_SECOND_FACTOR",
+                        "NO_RECOVERY_OPTION",
+                        "BROAD_SCOPE_APPLICATIONS",
+                    ],
+                },
+            },
+        })
+    );
+}
+
+/// Test that users can list their own recent login attempts, and only theirs
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_login_history(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    for (user, succeeded) in [(&alice, false), (&alice, true), (&bob, true)] {
+        repo.user_login_history()
+            .record(
+                &mut rng,
+                &state.clock,
+                user,
+                LoginMethod::Password,
+                succeeded,
+                Some("203.0.113.42".parse().unwrap()),
+                None,
+            )
+            .await
+            .unwrap();
+    }
+    repo.save().await.unwrap();
+
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+
+    let req = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query {
+                    viewer {
+                        ... on User {
+                            loginHistory(first: 10) {
+                                totalCount
+                                nodes {
+                                    method
+                                    succeeded
+                                    ipAddress
+                                }
+                            }
+                        }
+                    }
+                }
+            ",
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "loginHistory": {
+                    "totalCount": 2,
+                    "nodes": [
+                        {
+                            "method": "PASSWORD",
+                            "succeeded": false,
+                            "ipAddress": "203.0.113.42",
+                        },
+                        {
+                            "method": "PASSWORD",
+                            "succeeded": true,
+                            "ipAddress": "203.0.113.42",
+                        },
+                    ],
+                },
+            },
+        })
+    );
+
+    // Other users can't see the login history of alice
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let req = Request::post("/graphql")
+        .bearer(&bob_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query UserLoginHistory($id: ID!) {
+                    user(id: $id) {
+                        loginHistory(first: 10) {
+                            totalCount
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "id": format!("user:{id}", id = alice.id),
+            },
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(response.data, serde_json::json!({ "user": null }));
+}
+
 /// Test that the GraphQL endpoint requires the GraphQL scope.
 #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
 async fn test_oauth2_no_scope(pool: PgPool) {
@@ -346,6 +531,258 @@ async fn test_oauth2_admin(pool: PgPool) {
     );
 }
 
+/// Test that admin access through GraphQL is restricted by the network policy
+/// of the admin API, but regular access is not.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_admin_network_policy(pool: PgPool) {
+    setup();
+    let mut state = TestState::from_pool(pool).await.unwrap();
+    state.network_policy = NetworkPolicy::from_config(&mas_config::NetworkZonesConfig {
+        zones: vec![mas_config::NetworkZoneConfig {
+            name: "office".to_owned(),
+            cidrs: vec!["192.0.2.0/24".parse().unwrap()],
+            countries: Vec::new(),
+        }],
+        admin_api: mas_config::NetworkPolicyConfig {
+            allow: vec!["office".to_owned()],
+            deny: Vec::new(),
+        },
+        ..Default::default()
+    })
+    .unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let request = |token: &str, ip: &str| {
+        let mut request = Request::post("/graphql")
+            .bearer(token)
+            .json(serde_json::json!({
+                "query": "query { viewer { __typename } }",
+            }));
+        request
+            .extensions_mut()
+            .insert(RequestOrigin::new(Some(ip.parse().unwrap()), None));
+        request
+    };
+
+    // The regular token works from anywhere
+    let response = state.request(request(&access_token, "198.51.100.1")).await;
+    response.assert_status(StatusCode::OK);
+
+    // The admin token only works from the office
+    let response = state
+        .request(request(&access_token_admin, "198.51.100.1"))
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+
+    let response = state
+        .request(request(&access_token_admin, "192.0.2.1"))
+        .await;
+    response.assert_status(StatusCode::OK);
+}
+
+/// Test that the helpdesk scope can look up users, but not their sessions, and
+/// can't lock them.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_helpdesk(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token = start_oauth_session(
+        &state,
+        &client,
+        &user,
+        Scope::from_iter([GRAPHQL, HELPDESK]),
+    )
+    .await;
+    let access_token = access_token.access_token;
+
+    let user2 = create_test_user(&state, "bob").await;
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query UserQuery($id: ID) {
+                    user(id: $id) {
+                        id
+                        username
+                    }
+                }
+            ",
+            "variables": {
+                "id": format!("user:{id}", id = user2.id),
+            },
+        }));
+
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    // It should find the user
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "user": {
+                "id": format!("user:{id}", id = user2.id),
+                "username": "bob",
+            },
+        })
+    );
+
+    // But not their sessions
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query UserQuery($id: ID) {
+                    user(id: $id) {
+                        browserSessions(first: 10) {
+                            totalCount
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "id": format!("user:{id}", id = user2.id),
+            },
+        }));
+
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    // And it can't lock them
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r"
+                mutation LockUser($id: ID!) {
+                    lockUser(input: {userId: $id}) {
+                        status
+                    }
+                }
+            ",
+            "variables": {
+                "id": format!("user:{id}", id = user2.id),
+            },
+        }));
+
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+}
+
+/// Test that the helpdesk scope can set the password of regular users, but not
+/// of users who can request admin.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_helpdesk_set_password(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token = start_oauth_session(
+        &state,
+        &client,
+        &user,
+        Scope::from_iter([GRAPHQL, HELPDESK]),
+    )
+    .await;
+    let access_token = access_token.access_token;
+
+    let bob = create_test_user(&state, "bob").await;
+    let charlie = create_test_user(&state, "charlie").await;
+    let mut repo = state.repository().await.unwrap();
+    let charlie = repo
+        .user()
+        .set_can_request_admin(charlie, true)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let set_password = |user: &User| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r#"
+                    mutation SetPassword($id: ID!) {
+                        setPassword(input: {
+                            userId: $id,
+                            newPassword: "this is a good enough password"
+                        }) {
+                            status
+                        }
+                    }
+                "#,
+                "variables": {
+                    "id": format!("user:{id}", id = user.id),
+                },
+            }))
+    };
+
+    // The helpdesk can set the password of a regular user
+    let response = state.request(set_password(&bob)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setPassword": { "status": "ALLOWED" } })
+    );
+
+    // But not of a user who can request admin
+    let response = state.request(set_password(&charlie)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    // Nor reset it
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation ResetPassword($id: ID!) {
+                    resetPassword(input: {
+                        userId: $id,
+                        temporaryPassword: "this is a good enough password"
+                    }) {
+                        status
+                    }
+                }
+            "#,
+            "variables": {
+                "id": format!("user:{id}", id = charlie.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    let mut repo = state.repository().await.unwrap();
+    let password = repo.user_password().active(&charlie).await.unwrap();
+    assert!(password.is_none());
+}
+
 /// Test that we can query the GraphQL endpoint with a token from a
 /// client_credentials grant.
 #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -507,7 +944,7 @@ async fn test_oauth2_client_credentials(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
     let user_id = response.data["addUser"]["user"]["id"].as_str().unwrap();
 
     assert_eq!(
@@ -552,7 +989,7 @@ async fn test_oauth2_client_credentials(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
     assert!(response.data["createOauth2Session"]["refreshToken"].is_null());
     assert!(response.data["createOauth2Session"]["accessToken"].is_string());
 
@@ -632,7 +1069,7 @@ async fn test_add_user(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
     let user_id = &response.data["addUser"]["user"]["id"];
 
     assert_eq!(
@@ -667,7 +1104,7 @@ async fn test_add_user(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
 
     assert_eq!(
         response.data,
@@ -701,7 +1138,7 @@ async fn test_add_user(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
 
     assert_eq!(
         response.data,
@@ -731,7 +1168,7 @@ async fn test_add_user(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
 
     assert_eq!(
         response.data,
@@ -763,7 +1200,7 @@ async fn test_add_user(pool: PgPool) {
     let response = state.request(request).await;
     response.assert_status(StatusCode::OK);
     let response: GraphQLResponse = response.json();
-    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.errors.is_empty());
 
     assert_eq!(
         response.data,
@@ -775,3 +1212,685 @@ async fn test_add_user(pool: PgPool) {
         })
     );
 }
+
+/// Test renaming and deleting Matrix devices through the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_rename_and_delete_device(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+
+    let mxid = state.homeserver_connection.mxid("alice");
+    state
+        .homeserver_connection
+        .provision_user(&ProvisionRequest::new(mxid.clone(), user.sub.clone()))
+        .await
+        .unwrap();
+    state
+        .homeserver_connection
+        .create_device(&mxid, "ABCDEF")
+        .await
+        .unwrap();
+
+    // Start a session using the device
+    let device = Device::try_from("ABCDEF".to_owned()).unwrap();
+    let device_token = start_oauth_session(
+        &state,
+        &client,
+        &user,
+        Scope::from_iter([OPENID, device.to_scope_token()]),
+    )
+    .await;
+
+    let mutation = |name: &str, input: &str| {
+        Request::post("/graphql")
+            .bearer(&access_token.access_token)
+            .json(serde_json::json!({
+                "query": format!(
+                    "mutation {{ {name}(input: {{userId: \"user:{id}\", {input}}}) {{ status }} }}",
+                    id = user.id,
+                ),
+            }))
+    };
+
+    // The display name must not be empty
+    let response = state
+        .request(mutation(
+            "renameDevice",
+            r#"deviceId: "ABCDEF", displayName: """#,
+        ))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "renameDevice": { "status": "INVALID" } })
+    );
+
+    // Devices which aren't used by a session can't be renamed
+    let response = state
+        .request(mutation(
+            "renameDevice",
+            r#"deviceId: "UNKNOWN", displayName: "Phone""#,
+        ))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "renameDevice": { "status": "NOT_FOUND" } })
+    );
+
+    let response = state
+        .request(mutation(
+            "renameDevice",
+            r#"deviceId: "ABCDEF", displayName: "Phone""#,
+        ))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "renameDevice": { "status": "RENAMED" } })
+    );
+
+    // Deleting the device ends the session using it
+    let response = state
+        .request(mutation("deleteDevice", r#"deviceId: "ABCDEF""#))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "deleteDevice": { "status": "DELETED" } })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let session = repo
+        .oauth2_session()
+        .lookup(device_token.session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(session.is_finished());
+    repo.cancel().await.unwrap();
+
+    // The device was deleted on the homeserver
+    assert!(state
+        .homeserver_connection
+        .update_device_display_name(&mxid, "ABCDEF", "Phone")
+        .await
+        .is_err());
+
+    // It can't be deleted twice
+    let response = state
+        .request(mutation("deleteDevice", r#"deviceId: "ABCDEF""#))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "deleteDevice": { "status": "NOT_FOUND" } })
+    );
+}
+
+/// Test that users who can authenticate again must do so to reset their
+/// cross-signing keys
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_allow_cross_signing_reset(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let admin = create_test_user(&state, "admin").await;
+    let admin_token =
+        start_oauth_session(&state, &client, &admin, Scope::from_iter([GRAPHQL, ADMIN])).await;
+
+    let mxid = state.homeserver_connection.mxid("alice");
+    state
+        .homeserver_connection
+        .provision_user(&ProvisionRequest::new(mxid, user.sub.clone()))
+        .await
+        .unwrap();
+
+    let mutation = |access_token: &AccessToken| {
+        Request::post("/graphql")
+            .bearer(&access_token.access_token)
+            .json(serde_json::json!({
+                "query": format!(
+                    "mutation {{ allowUserCrossSigningReset(input: {{userId: \"user:{id}\"}}) {{ status }} }}",
+                    id = user.id,
+                ),
+            }))
+    };
+
+    // Users without a password can't authenticate again
+    let response = state.request(mutation(&access_token)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "allowUserCrossSigningReset": { "status": "ALLOWED" } })
+    );
+
+    let mut rng = state.rng();
+    let mut repo = state.repository().await.unwrap();
+    let (version, hash) = state
+        .password_manager
+        .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+        .await
+        .unwrap();
+    repo.user_password()
+        .add(&mut rng, &state.clock, &user, version, hash, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    // Once they have one, they must authenticate again with their browser
+    let response = state.request(mutation(&access_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "allowUserCrossSigningReset": { "status": "REAUTHENTICATION_REQUIRED" }
+        })
+    );
+
+    // Only browser sessions can be elevated
+    let request = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": "mutation { elevateSession(input: {password: \"hunter2\"}) { status token } }",
+        }));
+    let response = state.request(request).await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "elevateSession": { "status": "NOT_ALLOWED", "token": null }
+        })
+    );
+
+    // Administrators don't have to
+    let response = state.request(mutation(&admin_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "allowUserCrossSigningReset": { "status": "ALLOWED" } })
+    );
+}
+
+/// Test provisioning a user again on the homeserver through the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_resync_user(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let admin = create_test_user(&state, "admin").await;
+    let admin_token =
+        start_oauth_session(&state, &client, &admin, Scope::from_iter([GRAPHQL, ADMIN])).await;
+
+    let mutation = |access_token: &AccessToken| {
+        Request::post("/graphql")
+            .bearer(&access_token.access_token)
+            .json(serde_json::json!({
+                "query": format!(
+                    "mutation {{ resyncUser(input: {{userId: \"user:{id}\"}}) {{ status createdOnHomeserver reactivatedOnHomeserver emails }} }}",
+                    id = user.id,
+                ),
+            }))
+    };
+
+    // Only administrators can provision users again
+    let response = state.request(mutation(&access_token)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // The user is missing on the homeserver
+    let response = state.request(mutation(&admin_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "resyncUser": {
+                "status": "SCHEDULED",
+                "createdOnHomeserver": true,
+                "reactivatedOnHomeserver": false,
+                "emails": [],
+            }
+        })
+    );
+
+    // The user was deactivated on the homeserver, but is not locked in MAS
+    let mxid = state.homeserver_connection.mxid("alice");
+    state
+        .homeserver_connection
+        .provision_user(&ProvisionRequest::new(mxid.clone(), user.sub.clone()))
+        .await
+        .unwrap();
+    state
+        .homeserver_connection
+        .delete_user(&mxid, false)
+        .await
+        .unwrap();
+
+    let response = state.request(mutation(&admin_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "resyncUser": {
+                "status": "SCHEDULED",
+                "createdOnHomeserver": false,
+                "reactivatedOnHomeserver": true,
+                "emails": [],
+            }
+        })
+    );
+}
+
+/// Test editing the profile of a user through the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_edit_profile(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool_with_site_config(
+        pool,
+        mas_data_model::SiteConfig {
+            displayname_locked_to_upstream: true,
+            ..test_site_config()
+        },
+    )
+    .await
+    .unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let admin = create_test_user(&state, "admin").await;
+    let admin_token =
+        start_oauth_session(&state, &client, &admin, Scope::from_iter([GRAPHQL, ADMIN])).await;
+
+    let mxid = state.homeserver_connection.mxid("alice");
+    state
+        .homeserver_connection
+        .provision_user(&ProvisionRequest::new(mxid.clone(), user.sub.clone()))
+        .await
+        .unwrap();
+
+    let mutation = |access_token: &AccessToken, name: &str, input: &str| {
+        Request::post("/graphql")
+            .bearer(&access_token.access_token)
+            .json(serde_json::json!({
+                "query": format!(
+                    "mutation {{ {name}(input: {{userId: \"user:{id}\", {input}}}) {{ status }} }}",
+                    id = user.id,
+                ),
+            }))
+    };
+
+    // Avatars must be uploaded to the homeserver
+    let response = state
+        .request(mutation(
+            &access_token,
+            "setAvatar",
+            r#"avatarUrl: "https://example.com/avatar.png""#,
+        ))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setAvatar": { "status": "INVALID" } })
+    );
+
+    let response = state
+        .request(mutation(
+            &access_token,
+            "setAvatar",
+            r#"avatarUrl: "mxc://example.com/abcdef""#,
+        ))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setAvatar": { "status": "SET" } })
+    );
+
+    let matrix_user = state.homeserver_connection.query_user(&mxid).await.unwrap();
+    assert_eq!(
+        matrix_user.avatar_url.as_deref(),
+        Some("mxc://example.com/abcdef")
+    );
+
+    let response = state
+        .request(mutation(&access_token, "setAvatar", "avatarUrl: null"))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let matrix_user = state.homeserver_connection.query_user(&mxid).await.unwrap();
+    assert_eq!(matrix_user.avatar_url, None);
+
+    // Link the user to a provider which forces the display name
+    let mut rng = state.rng();
+    let mut repo = state.repository().await.unwrap();
+    let provider = repo
+        .upstream_oauth_provider()
+        .add(
+            &mut rng,
+            &state.clock,
+            UpstreamOAuthProviderParams {
+                issuer: "https://example.com/".to_owned(),
+                human_name: None,
+                brand_name: None,
+                scope: Scope::from_iter([OPENID]),
+                token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                token_endpoint_signing_alg: None,
+                client_id: "client".to_owned(),
+                encrypted_client_secret: None,
+                claims_imports: UpstreamOAuthProviderClaimsImports {
+                    displayname: UpstreamOAuthProviderImportPreference {
+                        action: UpstreamOAuthProviderImportAction::Force,
+                        template: None,
+                    },
+                    ..UpstreamOAuthProviderClaimsImports::default()
+                },
+                authorization_endpoint_override: None,
+                token_endpoint_override: None,
+                userinfo_endpoint_override: None,
+                fetch_userinfo: false,
+                jwks_uri_override: None,
+                discovery_mode: UpstreamOAuthProviderDiscoveryMode::Oidc,
+                pkce_mode: UpstreamOAuthProviderPkceMode::Auto,
+                response_mode: UpstreamOAuthProviderResponseMode::Query,
+                additional_authorization_parameters: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+    let link = repo
+        .upstream_oauth_link()
+        .add(&mut rng, &state.clock, &provider, "alice".to_owned(), None)
+        .await
+        .unwrap();
+    repo.upstream_oauth_link()
+        .associate_to_user(&link, &user)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    // The user can't change their display name anymore
+    let response = state
+        .request(mutation(
+            &access_token,
+            "setDisplayName",
+            r#"displayName: "Alice""#,
+        ))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setDisplayName": { "status": "LOCKED" } })
+    );
+
+    // But administrators can
+    let response = state
+        .request(mutation(
+            &admin_token,
+            "setDisplayName",
+            r#"displayName: "Alice""#,
+        ))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setDisplayName": { "status": "SET" } })
+    );
+}
+
+/// Build a multipart GraphQL request uploading a file as the `$file` variable
+fn upload_request(
+    access_token: &AccessToken,
+    query: &str,
+    file: &[u8],
+) -> Request<axum::body::Body> {
+    const BOUNDARY: &str = "mas-test-boundary";
+
+    let operations = serde_json::json!({
+        "query": query,
+        "variables": { "file": null },
+    });
+
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"operations\"\r\n\r\n\
+             {operations}\r\n\
+             --{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"map\"\r\n\r\n\
+             {{\"0\": [\"variables.file\"]}}\r\n\
+             --{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"0\"; filename=\"avatar\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(axum::body::Body::from(body))
+        .unwrap()
+}
+
+/// Test uploading an avatar through the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_upload_avatar(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+
+    let mxid = state.homeserver_connection.mxid("alice");
+    state
+        .homeserver_connection
+        .provision_user(&ProvisionRequest::new(mxid.clone(), user.sub.clone()))
+        .await
+        .unwrap();
+
+    let query = format!(
+        "mutation($file: Upload!) {{ uploadAvatar(input: {{userId: \"user:{id}\", file: $file}}) {{ status avatarUrl }} }}",
+        id = user.id,
+    );
+
+    // Files which aren't images are rejected
+    let response = state
+        .request(upload_request(&access_token, &query, b"not an image"))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "uploadAvatar": { "status": "INVALID", "avatarUrl": null } })
+    );
+
+    // Large images are scaled down
+    let mut image = Cursor::new(Vec::new());
+    image::DynamicImage::new_rgb8(1024, 768)
+        .write_to(&mut image, image::ImageFormat::Jpeg)
+        .unwrap();
+    let response = state
+        .request(upload_request(&access_token, &query, image.get_ref()))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["uploadAvatar"]["status"], "UPLOADED");
+    let avatar_url = response.data["uploadAvatar"]["avatarUrl"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let matrix_user = state.homeserver_connection.query_user(&mxid).await.unwrap();
+    assert_eq!(matrix_user.avatar_url.as_deref(), Some(avatar_url.as_str()));
+
+    let (content_type, data) = state
+        .homeserver_connection
+        .media(&avatar_url)
+        .await
+        .unwrap();
+    assert_eq!(content_type, "image/png");
+    let avatar = image::load_from_memory_with_format(&data, image::ImageFormat::Png).unwrap();
+    assert_eq!((avatar.width(), avatar.height()), (512, 384));
+}
+
+/// Test that users can name their sessions, and that a default name is derived
+/// from the user-agent
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_session_names(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+
+    let mut repo = state.repository().await.unwrap();
+    let session = repo
+        .oauth2_session()
+        .lookup(access_token.session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    repo.oauth2_session()
+        .record_user_agent(
+            session,
+            UserAgent::parse(
+                "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 Chrome/100.0.0.0 Safari/537.36"
+                    .to_owned(),
+            ),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let session_id = format!("oauth2_session:{}", access_token.session_id);
+    let query = |token: &str| {
+        Request::post("/graphql")
+            .bearer(token)
+            .json(serde_json::json!({
+                "query": format!(
+                    "query {{ node(id: \"{session_id}\") {{ ... on Oauth2Session {{ humanName }} }} }}"
+                ),
+            }))
+    };
+    let mutation = |token: &str, human_name: &str| {
+        Request::post("/graphql")
+            .bearer(token)
+            .json(serde_json::json!({
+                "query": "mutation($id: ID!, $name: String) { \
+                    setOauth2SessionName(input: {oauth2SessionId: $id, humanName: $name}) { status } \
+                }",
+                "variables": { "id": session_id, "name": human_name },
+            }))
+    };
+
+    // The default name is derived from the user-agent
+    let response = state.request(query(&access_token.access_token)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "node": { "humanName": "Chrome on Linux" } })
+    );
+
+    // Other users can't name the session
+    let response = state
+        .request(mutation(&bob_token.access_token, "Hacked"))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setOauth2SessionName": { "status": "NOT_FOUND" } })
+    );
+
+    // Names are limited in length
+    let response = state
+        .request(mutation(&access_token.access_token, &"a".repeat(129)))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setOauth2SessionName": { "status": "INVALID" } })
+    );
+
+    let response = state
+        .request(mutation(&access_token.access_token, "  Work laptop "))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setOauth2SessionName": { "status": "UPDATED" } })
+    );
+
+    let response = state.request(query(&access_token.access_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "node": { "humanName": "Work laptop" } })
+    );
+
+    // An empty name brings back the default one
+    let response = state
+        .request(mutation(&access_token.access_token, ""))
+        .await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setOauth2SessionName": { "status": "UPDATED" } })
+    );
+
+    let response = state.request(query(&access_token.access_token)).await;
+    let response: GraphQLResponse = response.json();
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "node": { "humanName": "Chrome on Linux" } })
+    );
+}
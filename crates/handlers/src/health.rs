@@ -4,12 +4,19 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use axum::{extract::State, response::IntoResponse};
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
 use mas_axum_utils::FancyError;
+use mas_data_model::UpstreamOAuthProviderStatus;
+use mas_matrix::{CircuitBreaker, CircuitState};
+use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, BoxClock, BoxRepository};
+use serde::Serialize;
 use sqlx::PgPool;
 use tracing::{info_span, Instrument};
+use ulid::Ulid;
 
-pub async fn get(State(pool): State<PgPool>) -> Result<impl IntoResponse, FancyError> {
+async fn check_database(pool: &PgPool) -> Result<(), sqlx::Error> {
     let mut conn = pool.acquire().await?;
 
     sqlx::query("SELECT $1")
@@ -18,12 +25,99 @@ pub async fn get(State(pool): State<PgPool>) -> Result<impl IntoResponse, FancyE
         .instrument(info_span!("DB health"))
         .await?;
 
+    Ok(())
+}
+
+pub async fn get(State(pool): State<PgPool>) -> Result<impl IntoResponse, FancyError> {
+    check_database(&pool).await?;
     Ok("ok")
 }
 
+#[derive(Serialize)]
+struct Readiness {
+    database: &'static str,
+    homeserver: &'static str,
+}
+
+/// Report whether the service can handle requests: the database must be
+/// reachable, and the homeserver must not have failed too many times in a
+/// row
+pub async fn get_ready(
+    State(pool): State<PgPool>,
+    State(circuit_breaker): State<CircuitBreaker>,
+) -> impl IntoResponse {
+    let database_ok = match check_database(&pool).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Database is not reachable"
+            );
+            false
+        }
+    };
+    let homeserver = circuit_breaker.state();
+
+    let status = if database_ok && homeserver != CircuitState::Open {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let readiness = Readiness {
+        database: if database_ok { "ok" } else { "unreachable" },
+        homeserver: homeserver.as_str(),
+    };
+
+    (status, Json(readiness))
+}
+
+#[derive(Serialize)]
+struct UpstreamHealth {
+    id: Ulid,
+    issuer: String,
+    human_name: Option<String>,
+    status: UpstreamOAuthProviderStatus,
+    checked_at: Option<DateTime<Utc>>,
+    last_success_at: Option<DateTime<Utc>>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+/// Report the status of the enabled upstream providers, as found by the
+/// periodic health checks
+pub async fn get_upstreams(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+) -> Result<impl IntoResponse, FancyError> {
+    let now = clock.now();
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+
+    let mut upstreams = Vec::with_capacity(providers.len());
+    for provider in providers {
+        let health = repo.upstream_oauth_provider().health(&provider).await?;
+        upstreams.push(UpstreamHealth {
+            id: provider.id,
+            status: health
+                .as_ref()
+                .map_or(UpstreamOAuthProviderStatus::Unknown, |h| h.status(now)),
+            checked_at: health.as_ref().map(|h| h.checked_at),
+            last_success_at: health.as_ref().and_then(|h| h.last_success_at),
+            consecutive_failures: health.as_ref().map_or(0, |h| h.consecutive_failures),
+            last_error: health.and_then(|h| h.last_error),
+            issuer: provider.issuer,
+            human_name: provider.human_name,
+        });
+    }
+
+    repo.cancel().await?;
+
+    Ok(Json(upstreams))
+}
+
 #[cfg(test)]
 mod tests {
-    use hyper::{Request, StatusCode};
+    use hyper::Request;
 
     use super::*;
     use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
@@ -38,4 +132,33 @@ mod tests {
         response.assert_status(StatusCode::OK);
         assert_eq!(response.body(), "ok");
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_get_readiness(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let request = Request::get("/health/ready").empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let readiness: serde_json::Value = response.json();
+        assert_eq!(
+            readiness,
+            serde_json::json!({
+                "database": "ok",
+                "homeserver": "closed",
+            })
+        );
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_get_upstreams_health(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let request = Request::get("/health/upstreams").empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.body(), "[]");
+    }
 }
@@ -0,0 +1,128 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Discovery of the authentication issuer of other homeservers, from the
+//! client well-known document of their server name.
+//!
+//! Server names are given by anonymous users, so the requests are only sent
+//! to hostnames which look public, with an HTTP client which refuses to
+//! connect to non-public addresses, doesn't follow redirects and has short
+//! timeouts.
+
+use std::net::IpAddr;
+
+use mas_http::RequestBuilderExt as _;
+use serde::Deserialize;
+
+/// The parts of the client well-known document of a homeserver we care about
+#[derive(Debug, Deserialize)]
+struct ClientWellKnown {
+    #[serde(rename = "org.matrix.msc2965.authentication")]
+    authentication: Option<WellKnownAuthentication>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WellKnownAuthentication {
+    issuer: String,
+}
+
+/// Finds the issuer other homeservers delegate their authentication to
+#[derive(Debug, Clone)]
+pub struct HomeserverDiscovery {
+    http_client: reqwest::Client,
+}
+
+impl HomeserverDiscovery {
+    /// Create a new [`HomeserverDiscovery`] using the given HTTP client
+    ///
+    /// The client should be built with
+    /// [`mas_http::reqwest_public_client_with_options`], so that it doesn't
+    /// connect to internal addresses.
+    #[must_use]
+    pub const fn new(http_client: reqwest::Client) -> Self {
+        Self { http_client }
+    }
+
+    /// Find the issuer a homeserver delegates its authentication to, from the
+    /// client well-known document of its server name
+    pub(crate) async fn discover_issuer(&self, server_name: &str) -> Option<String> {
+        // The well-known document is served on the default port of the hostname
+        let hostname = match server_name.rsplit_once(':') {
+            Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+            _ => server_name,
+        };
+
+        if !is_public_hostname(hostname) {
+            tracing::warn!(
+                server_name,
+                "Refusing to fetch the client well-known document of a non-public host"
+            );
+            return None;
+        }
+
+        let url = format!("https://{hostname}/.well-known/matrix/client");
+
+        let result = async {
+            self.http_client
+                .get(&url)
+                .send_traced()
+                .await?
+                .error_for_status()?
+                .json::<ClientWellKnown>()
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(well_known) => well_known
+                .authentication
+                .map(|authentication| authentication.issuer),
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    server_name,
+                    "Could not fetch the client well-known document of the homeserver"
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Whether a hostname looks like it belongs to a public host
+///
+/// IP literals, `localhost` and single-label names, which resolve through the
+/// local search domains, are rejected. The addresses the other names resolve
+/// to are checked by the HTTP client.
+fn is_public_hostname(hostname: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+
+    let is_ip_literal = hostname.starts_with('[') || hostname.parse::<IpAddr>().is_ok();
+    let is_localhost = hostname == "localhost" || hostname.ends_with(".localhost");
+    let is_single_label = !hostname.contains('.');
+
+    !(is_ip_literal || is_localhost || is_single_label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public_hostname;
+
+    #[test]
+    fn test_is_public_hostname() {
+        assert!(is_public_hostname("example.org"));
+        assert!(is_public_hostname("matrix.example.org"));
+
+        assert!(!is_public_hostname("localhost"));
+        assert!(!is_public_hostname("LOCALHOST."));
+        assert!(!is_public_hostname("foo.localhost"));
+        assert!(!is_public_hostname("intranet"));
+        assert!(!is_public_hostname("127.0.0.1"));
+        assert!(!is_public_hostname("169.254.169.254"));
+        assert!(!is_public_hostname("10.0.0.1"));
+        assert!(!is_public_hostname("[::1]"));
+        assert!(!is_public_hostname("[fe80::1]"));
+    }
+}
@@ -15,7 +15,7 @@
     clippy::let_with_type_underscore,
 )]
 
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
     extract::{FromRef, FromRequestParts, OriginalUri, RawQuery, State},
@@ -36,7 +36,7 @@ use mas_axum_utils::{cookies::CookieJar, FancyError};
 use mas_data_model::SiteConfig;
 use mas_http::CorsLayerExt;
 use mas_keystore::{Encrypter, Keystore};
-use mas_matrix::BoxHomeserverConnection;
+use mas_matrix::{BoxHomeserverConnection, CircuitBreaker};
 use mas_policy::Policy;
 use mas_router::{Route, UrlBuilder};
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
@@ -47,18 +47,33 @@ use tower::util::AndThenLayer;
 use tower_http::cors::{Any, CorsLayer};
 
 mod admin;
+mod auth_forms;
 mod compat;
+mod email_webhooks;
 mod graphql;
 mod health;
 mod oauth2;
 pub mod passwords;
+mod registration_api;
 pub mod upstream_oauth2;
 mod views;
 
 mod activity_tracker;
+mod attestation;
+mod bot_detection;
 mod captcha;
+mod client_certificate;
+mod device_allocation;
+mod device_proof;
+mod email_verification;
+mod experiments;
+mod homeserver_discovery;
+mod network_policy;
 mod preferred_language;
 mod rate_limit;
+mod risk;
+mod session_elevation;
+mod session_limits;
 #[cfg(test)]
 mod test_utils;
 
@@ -82,12 +97,27 @@ pub use mas_axum_utils::{cookies::CookieManager, ErrorWrapper};
 
 pub use self::{
     activity_tracker::{ActivityTracker, Bound as BoundActivityTracker},
-    admin::router as admin_api_router,
+    admin::{
+        router as admin_api_router,
+        snapshot::{user_snapshot, UserSnapshot},
+    },
+    attestation::{
+        AttestationChecker, AttestationError, AttestationEvent, AttestationPlatform,
+        AttestationRequest, AttestationVerdict, AttestationVerifier, HttpAttestationVerifier,
+    },
+    client_certificate::ClientCertificate,
+    device_proof::DeviceProofReplayCache,
     graphql::{
         schema as graphql_schema, schema_builder as graphql_schema_builder, Schema as GraphQLSchema,
     },
+    homeserver_discovery::HomeserverDiscovery,
+    network_policy::{NetworkPolicy, NetworkPolicyError, NetworkScope, RequestOrigin},
     preferred_language::PreferredLanguage,
     rate_limit::{Limiter, RequesterFingerprint},
+    risk::{
+        HttpRiskProvider, RiskAssessor, RiskContext, RiskError, RiskEvent, RiskProvider,
+        RiskVerdict,
+    },
     upstream_oauth2::cache::MetadataCache,
 };
 
@@ -95,15 +125,49 @@ pub fn healthcheck_router<S>() -> Router<S>
 where
     S: Clone + Send + Sync + 'static,
     PgPool: FromRef<S>,
+    CircuitBreaker: FromRef<S>,
+    BoxRepository: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S>,
+{
+    Router::new()
+        .route(mas_router::Healthcheck::route(), get(self::health::get))
+        .route(mas_router::Readiness::route(), get(self::health::get_ready))
+        .route(
+            mas_router::UpstreamHealthcheck::route(),
+            get(self::health::get_upstreams),
+        )
+}
+
+/// Routes receiving bounce and complaint notifications from the mail
+/// provider, authenticated with the given secret token
+pub fn email_webhooks_router<S>(secret: &str) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    BoxRepository: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S>,
 {
-    Router::new().route(mas_router::Healthcheck::route(), get(self::health::get))
+    Router::new()
+        .route(
+            mas_router::EmailWebhookSes::route(),
+            post(self::email_webhooks::ses),
+        )
+        .route(
+            mas_router::EmailWebhookSendgrid::route(),
+            post(self::email_webhooks::sendgrid),
+        )
+        .layer(Extension(self::email_webhooks::WebhookSecret(Arc::from(
+            secret,
+        ))))
 }
 
 pub fn graphql_router<S>(playground: bool, undocumented_oauth2_access: bool) -> Router<S>
 where
     S: Clone + Send + Sync + 'static,
     graphql::Schema: FromRef<S>,
+    NetworkPolicy: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
+    RequesterFingerprint: FromRequestParts<S>,
+    RequestOrigin: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     Encrypter: FromRef<S>,
@@ -148,6 +212,8 @@ where
     Keystore: FromRef<S>,
     SiteConfig: FromRef<S>,
     UrlBuilder: FromRef<S>,
+    PasswordManager: FromRef<S>,
+    BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
 {
@@ -160,6 +226,7 @@ where
             mas_router::Webfinger::route(),
             get(self::oauth2::webfinger::get),
         )
+        .route(mas_router::AuthForms::route(), get(self::auth_forms::get))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -175,7 +242,7 @@ where
         )
 }
 
-pub fn api_router<S>() -> Router<S>
+pub fn api_router<S>(templates: Templates) -> Router<S>
 where
     S: Clone + Send + Sync + 'static,
     Keystore: FromRef<S>,
@@ -186,7 +253,16 @@ where
     Encrypter: FromRef<S>,
     reqwest::Client: FromRef<S>,
     SiteConfig: FromRef<S>,
+    AttestationChecker: FromRef<S>,
+    DeviceProofReplayCache: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
+    PasswordManager: FromRef<S>,
+    Limiter: FromRef<S>,
+    RiskAssessor: FromRef<S>,
+    NetworkPolicy: FromRef<S>,
+    PreferredLanguage: FromRequestParts<S>,
+    RequesterFingerprint: FromRequestParts<S>,
+    RequestOrigin: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
     Policy: FromRequestParts<S>,
@@ -221,6 +297,27 @@ where
             mas_router::OAuth2DeviceAuthorizationEndpoint::route(),
             post(self::oauth2::device::authorize::post),
         )
+        .route(
+            mas_router::RegistrationApi::route(),
+            post(self::registration_api::register),
+        )
+        .route(
+            mas_router::RegistrationApiResendEmail::route(),
+            post(self::registration_api::resend_email),
+        )
+        .route(
+            mas_router::RegistrationApiVerifyEmail::route(),
+            post(self::registration_api::verify_email),
+        )
+        .layer(axum::middleware::from_fn(
+            move |request: axum::extract::Request, next: axum::middleware::Next| {
+                self::oauth2::localized_errors::localize_client_errors(
+                    templates.clone(),
+                    request,
+                    next,
+                )
+            },
+        ))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -245,8 +342,11 @@ where
     BoxHomeserverConnection: FromRef<S>,
     PasswordManager: FromRef<S>,
     Limiter: FromRef<S>,
+    NetworkPolicy: FromRef<S>,
+    DeviceProofReplayCache: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
     RequesterFingerprint: FromRequestParts<S>,
+    RequestOrigin: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
@@ -302,6 +402,8 @@ where
     CookieJar: FromRequestParts<S>,
     BoundActivityTracker: FromRequestParts<S>,
     RequesterFingerprint: FromRequestParts<S>,
+    RequestOrigin: FromRequestParts<S>,
+    ClientCertificate: FromRequestParts<S>,
     Encrypter: FromRef<S>,
     Templates: FromRef<S>,
     Keystore: FromRef<S>,
@@ -309,7 +411,10 @@ where
     MetadataCache: FromRef<S>,
     SiteConfig: FromRef<S>,
     Limiter: FromRef<S>,
+    RiskAssessor: FromRef<S>,
+    NetworkPolicy: FromRef<S>,
     reqwest::Client: FromRef<S>,
+    HomeserverDiscovery: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
@@ -353,7 +458,20 @@ where
             mas_router::Login::route(),
             get(self::views::login::get).post(self::views::login::post),
         )
+        .route(
+            mas_router::CertificateLogin::route(),
+            post(self::views::certificate_login::post),
+        )
+        .route(
+            mas_router::MatrixIdLogin::route(),
+            post(self::views::matrix_id_login::post),
+        )
         .route(mas_router::Logout::route(), post(self::views::logout::post))
+        .route(
+            mas_router::PasswordChangeRequired::route(),
+            get(self::views::password_change_required::get)
+                .post(self::views::password_change_required::post),
+        )
         .route(
             mas_router::Reauth::route(),
             get(self::views::reauth::get).post(self::views::reauth::post),
@@ -367,6 +485,10 @@ where
             get(self::views::account::emails::verify::get)
                 .post(self::views::account::emails::verify::post),
         )
+        .route(
+            mas_router::AccountVerifyEmailLink::route(),
+            get(self::views::account::emails::verify::link),
+        )
         .route(
             mas_router::AccountAddEmail::route(),
             get(self::views::account::emails::add::get)
@@ -413,6 +535,14 @@ where
             mas_router::DeviceCodeLink::route(),
             get(self::oauth2::device::link::get),
         )
+        .route(
+            mas_router::OAuth2ErrorDocumentation::route(),
+            get(self::oauth2::error_documentation::get),
+        )
+        .route(
+            mas_router::DeviceCodeLinkQr::route(),
+            get(self::oauth2::device::link::qr),
+        )
         .route(
             mas_router::DeviceCodeConsent::route(),
             get(self::oauth2::device::consent::get).post(self::oauth2::device::consent::post),
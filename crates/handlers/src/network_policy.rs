@@ -0,0 +1,340 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Restrict where parts of the service can be used from.
+//!
+//! Network zones are named sets of IP networks and countries. Each
+//! [`NetworkScope`] has a policy allowing requests only from some zones, and
+//! denying requests from others. Denied requests are logged as audit events.
+
+use std::{net::IpAddr, sync::Arc};
+
+use hyper::header::{HeaderMap, HeaderName, InvalidHeaderName};
+use ipnetwork::IpNetwork;
+use mas_config::{NetworkPolicyConfig, NetworkZonesConfig};
+use thiserror::Error;
+
+/// A part of the service restricted by the network policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkScope {
+    /// The admin API, and GraphQL requests with an admin scope
+    AdminApi,
+
+    /// Logging in, with a password, a client certificate or an upstream
+    /// provider
+    Login,
+
+    /// Registering a new account with a password
+    Registration,
+}
+
+impl NetworkScope {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::AdminApi => "admin_api",
+            Self::Login => "login",
+            Self::Registration => "registration",
+        }
+    }
+}
+
+/// Where a request comes from
+#[derive(Debug, Clone, Default)]
+pub struct RequestOrigin {
+    ip: Option<IpAddr>,
+    country: Option<String>,
+}
+
+impl RequestOrigin {
+    /// Create a new request origin, from the IP address and country of the
+    /// client, if known
+    #[must_use]
+    pub fn new(ip: Option<IpAddr>, country: Option<&str>) -> Self {
+        Self {
+            ip,
+            country: country.map(str::to_ascii_uppercase),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NetworkPolicyError {
+    #[error("Requests from the {0:?} network zone are not allowed")]
+    DeniedZone(String),
+
+    #[error("Requests are only allowed from specific network zones")]
+    NotInAllowedZone,
+}
+
+struct Zone {
+    name: String,
+    cidrs: Vec<IpNetwork>,
+    countries: Vec<String>,
+}
+
+impl Zone {
+    fn contains(&self, origin: &RequestOrigin) -> bool {
+        let in_cidrs = origin
+            .ip
+            .is_some_and(|ip| self.cidrs.iter().any(|network| network.contains(ip)));
+
+        let in_countries = origin
+            .country
+            .as_ref()
+            .is_some_and(|country| self.countries.contains(country));
+
+        in_cidrs || in_countries
+    }
+}
+
+/// The policy of a scope, with the indices of the zones it references
+#[derive(Default)]
+struct Rule {
+    allow: Vec<usize>,
+    deny: Vec<usize>,
+}
+
+struct Inner {
+    country_header: Option<HeaderName>,
+    zones: Vec<Zone>,
+    admin_api: Rule,
+    login: Rule,
+    registration: Rule,
+}
+
+impl Inner {
+    fn rule(&self, scope: NetworkScope) -> &Rule {
+        match scope {
+            NetworkScope::AdminApi => &self.admin_api,
+            NetworkScope::Login => &self.login,
+            NetworkScope::Registration => &self.registration,
+        }
+    }
+
+    fn evaluate(&self, rule: &Rule, origin: &RequestOrigin) -> Result<(), NetworkPolicyError> {
+        if let Some(&zone) = rule
+            .deny
+            .iter()
+            .find(|&&zone| self.zones[zone].contains(origin))
+        {
+            return Err(NetworkPolicyError::DeniedZone(
+                self.zones[zone].name.clone(),
+            ));
+        }
+
+        if !rule.allow.is_empty()
+            && !rule
+                .allow
+                .iter()
+                .any(|&zone| self.zones[zone].contains(origin))
+        {
+            return Err(NetworkPolicyError::NotInAllowedZone);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks requests against the network zones policies
+#[derive(Clone, Default)]
+pub struct NetworkPolicy {
+    inner: Option<Arc<Inner>>,
+}
+
+impl NetworkPolicy {
+    /// A policy which allows every request
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Create a policy from the configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the country header is not a valid header name
+    pub fn from_config(config: &NetworkZonesConfig) -> Result<Self, InvalidHeaderName> {
+        if config.zones.is_empty() {
+            return Ok(Self::disabled());
+        }
+
+        let country_header = config
+            .country_header
+            .as_deref()
+            .map(HeaderName::try_from)
+            .transpose()?;
+
+        let zones: Vec<Zone> = config
+            .zones
+            .iter()
+            .map(|zone| Zone {
+                name: zone.name.clone(),
+                cidrs: zone.cidrs.clone(),
+                countries: zone
+                    .countries
+                    .iter()
+                    .map(|country| country.to_ascii_uppercase())
+                    .collect(),
+            })
+            .collect();
+
+        let resolve = |names: &[String]| -> Vec<usize> {
+            names
+                .iter()
+                .filter_map(|name| zones.iter().position(|zone| &zone.name == name))
+                .collect()
+        };
+
+        let rule = |policy: &NetworkPolicyConfig| Rule {
+            allow: resolve(&policy.allow),
+            deny: resolve(&policy.deny),
+        };
+
+        let admin_api = rule(&config.admin_api);
+        let login = rule(&config.login);
+        let registration = rule(&config.registration);
+
+        Ok(Self {
+            inner: Some(Arc::new(Inner {
+                country_header,
+                zones,
+                admin_api,
+                login,
+                registration,
+            })),
+        })
+    }
+
+    /// Build the origin of a request, from the IP address of the client and
+    /// the request headers
+    #[must_use]
+    pub fn origin(&self, ip: Option<IpAddr>, headers: &HeaderMap) -> RequestOrigin {
+        let country = self
+            .inner
+            .as_ref()
+            .and_then(|inner| inner.country_header.as_ref())
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok());
+
+        RequestOrigin::new(ip, country)
+    }
+
+    /// Check whether a request from the given origin can access the given
+    /// scope
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request is not allowed by the policy of the
+    /// scope
+    pub fn check(
+        &self,
+        scope: NetworkScope,
+        origin: &RequestOrigin,
+    ) -> Result<(), NetworkPolicyError> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+
+        let result = inner.evaluate(inner.rule(scope), origin);
+
+        if let Err(e) = &result {
+            tracing::warn!(
+                audit.event = "network_policy.denied",
+                network_policy.scope = scope.as_str(),
+                client.address = ?origin.ip,
+                client.country = origin.country.as_deref(),
+                error = e as &dyn std::error::Error,
+                "Request denied by the network policy"
+            );
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_config::NetworkZoneConfig;
+
+    use super::*;
+
+    fn policy() -> NetworkPolicy {
+        NetworkPolicy::from_config(&NetworkZonesConfig {
+            country_header: Some("CF-IPCountry".to_owned()),
+            zones: vec![
+                NetworkZoneConfig {
+                    name: "corp_vpn".to_owned(),
+                    cidrs: vec!["10.8.0.0/16".parse().unwrap()],
+                    countries: Vec::new(),
+                },
+                NetworkZoneConfig {
+                    name: "blocked".to_owned(),
+                    cidrs: vec!["10.8.66.0/24".parse().unwrap()],
+                    countries: vec!["kp".to_owned()],
+                },
+            ],
+            admin_api: NetworkPolicyConfig {
+                allow: vec!["corp_vpn".to_owned()],
+                deny: vec!["blocked".to_owned()],
+            },
+            login: NetworkPolicyConfig::default(),
+            registration: NetworkPolicyConfig {
+                allow: Vec::new(),
+                deny: vec!["blocked".to_owned()],
+            },
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = policy();
+
+        let vpn = RequestOrigin::new(Some("10.8.1.2".parse().unwrap()), None);
+        let outside = RequestOrigin::new(Some("192.0.2.1".parse().unwrap()), None);
+        let unknown = RequestOrigin::default();
+
+        assert!(policy.check(NetworkScope::AdminApi, &vpn).is_ok());
+        assert!(matches!(
+            policy.check(NetworkScope::AdminApi, &outside),
+            Err(NetworkPolicyError::NotInAllowedZone)
+        ));
+        assert!(policy.check(NetworkScope::AdminApi, &unknown).is_err());
+
+        // Scopes without a policy allow everything
+        assert!(policy.check(NetworkScope::Login, &outside).is_ok());
+        assert!(policy.check(NetworkScope::Login, &unknown).is_ok());
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let policy = policy();
+
+        // Denied zones win over allowed ones
+        let blocked_vpn = RequestOrigin::new(Some("10.8.66.1".parse().unwrap()), None);
+        assert!(matches!(
+            policy.check(NetworkScope::AdminApi, &blocked_vpn),
+            Err(NetworkPolicyError::DeniedZone(zone)) if zone == "blocked"
+        ));
+
+        // Countries are matched case-insensitively, from the configured header
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-ipcountry", "KP".parse().unwrap());
+        let origin = policy.origin(Some("192.0.2.1".parse().unwrap()), &headers);
+        assert!(policy.check(NetworkScope::Registration, &origin).is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-ipcountry", "FR".parse().unwrap());
+        let origin = policy.origin(Some("192.0.2.1".parse().unwrap()), &headers);
+        assert!(policy.check(NetworkScope::Registration, &origin).is_ok());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let policy = NetworkPolicy::disabled();
+        let origin = RequestOrigin::default();
+        assert!(policy.check(NetworkScope::AdminApi, &origin).is_ok());
+    }
+}
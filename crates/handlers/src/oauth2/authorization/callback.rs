@@ -9,6 +9,7 @@
 use std::collections::HashMap;
 
 use axum::response::{Html, IntoResponse, Redirect, Response};
+use mas_axum_utils::security_headers::CspNonce;
 use mas_data_model::AuthorizationGrant;
 use mas_i18n::DataLocale;
 use mas_templates::{FormPostContext, Templates};
@@ -31,6 +32,7 @@ pub struct CallbackDestination {
     mode: CallbackDestinationMode,
     safe_redirect_uri: Url,
     state: Option<String>,
+    csp_nonce: Option<CspNonce>,
 }
 
 #[derive(Debug, Error)]
@@ -98,9 +100,18 @@ impl CallbackDestination {
             mode,
             safe_redirect_uri: redirect_uri,
             state,
+            csp_nonce: None,
         })
     }
 
+    /// Set the Content-Security-Policy nonce to use when rendering the
+    /// `form_post` response mode page
+    #[must_use]
+    pub fn with_csp_nonce(mut self, csp_nonce: CspNonce) -> Self {
+        self.csp_nonce = Some(csp_nonce);
+        self
+    }
+
     pub async fn go<T: Serialize + Send + Sync>(
         self,
         templates: &Templates,
@@ -121,6 +132,7 @@ impl CallbackDestination {
 
         let mut redirect_uri = self.safe_redirect_uri;
         let state = self.state;
+        let csp_nonce = self.csp_nonce;
 
         match self.mode {
             CallbackDestinationMode::Query { existing_params } => {
@@ -157,7 +169,9 @@ impl CallbackDestination {
                     state,
                     params,
                 };
-                let ctx = FormPostContext::new_for_url(redirect_uri, merged).with_language(locale);
+                let ctx = FormPostContext::new_for_url(redirect_uri, merged)
+                    .with_csp_nonce(csp_nonce)
+                    .with_language(locale);
                 let rendered = templates.render_form_post(&ctx)?;
                 Ok(Html(rendered).into_response())
             }
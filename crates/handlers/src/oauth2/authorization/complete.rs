@@ -9,10 +9,13 @@ use axum::{
     response::{Html, IntoResponse, Response},
 };
 use hyper::StatusCode;
-use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device};
+use mas_axum_utils::{
+    cookies::CookieJar, csrf::CsrfExt, security_headers::CspNonce, sentry::SentryEventID,
+    SessionInfoExt,
+};
+use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device, SiteConfig};
 use mas_keystore::Keystore;
-use mas_policy::{EvaluationResult, Policy};
+use mas_policy::{EvaluationResult, Policy, TimeInput};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2SessionRepository},
@@ -20,14 +23,20 @@ use mas_storage::{
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
-use oauth2_types::requests::AuthorizationResponse;
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::AuthorizationResponse,
+};
 use thiserror::Error;
 use tracing::warn;
 use ulid::Ulid;
 
 use super::callback::CallbackDestination;
 use crate::{
-    impl_from_error_for_route, oauth2::generate_id_token, BoundActivityTracker, PreferredLanguage,
+    device_allocation, impl_from_error_for_route,
+    oauth2::generate_id_token,
+    session_limits::{self, SessionLimitError},
+    BoundActivityTracker, PreferredLanguage,
 };
 
 #[derive(Debug, Error)]
@@ -87,10 +96,12 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
+    csp_nonce: CspNonce,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
@@ -103,7 +114,7 @@ pub(crate) async fn get(
         .await?
         .ok_or(RouteError::NotFound)?;
 
-    let callback_destination = CallbackDestination::try_from(&grant)?;
+    let callback_destination = CallbackDestination::try_from(&grant)?.with_csp_nonce(csp_nonce);
     let continue_grant = PostAuthAction::continue_grant(grant.id);
 
     let Some(session) = maybe_session else {
@@ -134,6 +145,7 @@ pub(crate) async fn get(
         key_store,
         policy,
         &url_builder,
+        &site_config,
         grant,
         &client,
         &session,
@@ -149,6 +161,13 @@ pub(crate) async fn get(
             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant)),
         )
             .into_response()),
+        Err(GrantCompletionError::RequiresPasswordChange) => Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::PasswordChangeRequired::and_then(
+                continue_grant,
+            )),
+        )
+            .into_response()),
         Err(GrantCompletionError::RequiresConsent) => {
             let next = mas_router::Consent(grant_id);
             Ok((cookie_jar, url_builder.redirect(&next)).into_response())
@@ -166,6 +185,16 @@ pub(crate) async fn get(
 
             Ok((cookie_jar, Html(content)).into_response())
         }
+        Err(GrantCompletionError::SessionLimitExceeded) => {
+            let res = callback_destination
+                .go(
+                    &templates,
+                    &locale,
+                    ClientError::new(ClientErrorCode::AccessDenied, "Too many active sessions"),
+                )
+                .await?;
+            Ok((cookie_jar, res).into_response())
+        }
         Err(GrantCompletionError::NotPending) => Err(RouteError::NotPending),
         Err(GrantCompletionError::Internal(e)) => Err(RouteError::Internal(e)),
     }
@@ -182,11 +211,17 @@ pub enum GrantCompletionError {
     #[error("user needs to reauthenticate")]
     RequiresReauth,
 
+    #[error("user needs to change their password")]
+    RequiresPasswordChange,
+
     #[error("client lacks consent")]
     RequiresConsent,
 
     #[error("denied by the policy")]
     PolicyViolation(AuthorizationGrant, EvaluationResult),
+
+    #[error("the user has too many active sessions")]
+    SessionLimitExceeded,
 }
 
 impl_from_error_for_route!(GrantCompletionError: mas_storage::RepositoryError);
@@ -195,6 +230,15 @@ impl_from_error_for_route!(GrantCompletionError: mas_policy::LoadError);
 impl_from_error_for_route!(GrantCompletionError: mas_policy::EvaluationError);
 impl_from_error_for_route!(GrantCompletionError: super::super::IdTokenSignatureError);
 
+impl From<SessionLimitError> for GrantCompletionError {
+    fn from(e: SessionLimitError) -> Self {
+        match e {
+            SessionLimitError::Exceeded => Self::SessionLimitExceeded,
+            SessionLimitError::Repository(e) => Self::Internal(Box::new(e)),
+        }
+    }
+}
+
 pub(crate) async fn complete(
     rng: &mut (impl rand::RngCore + rand::CryptoRng + Send),
     clock: &impl Clock,
@@ -203,6 +247,7 @@ pub(crate) async fn complete(
     key_store: Keystore,
     mut policy: Policy,
     url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
     grant: AuthorizationGrant,
     client: &Client,
     browser_session: &BrowserSession,
@@ -213,20 +258,38 @@ pub(crate) async fn complete(
     }
 
     // Check if the authentication is fresh enough
-    let authentication = repo
+    let authentications = repo
         .browser_session()
-        .get_last_authentication(browser_session)
+        .list_authentications(browser_session)
         .await?;
-    let authentication = authentication.filter(|auth| auth.created_at > grant.max_auth_time());
+    let fresh = authentications
+        .last()
+        .is_some_and(|auth| auth.created_at > grant.max_auth_time());
 
-    let Some(valid_authentication) = authentication else {
+    if !fresh {
         repo.save().await?;
         return Err(GrantCompletionError::RequiresReauth);
-    };
+    }
+
+    // The password was reset by an admin, it has to be changed first
+    if repo
+        .user()
+        .is_password_change_required(&browser_session.user)
+        .await?
+    {
+        repo.save().await?;
+        return Err(GrantCompletionError::RequiresPasswordChange);
+    }
 
     // Run through the policy
+    let timezone = repo.user().get_timezone(&browser_session.user).await?;
     let res = policy
-        .evaluate_authorization_grant(&grant, client, &browser_session.user)
+        .evaluate_authorization_grant(
+            &grant,
+            client,
+            &browser_session.user,
+            TimeInput::new(clock.now(), timezone.as_deref()),
+        )
         .await?;
 
     if !res.valid() {
@@ -250,10 +313,32 @@ pub(crate) async fn complete(
         return Err(GrantCompletionError::RequiresConsent);
     }
 
+    // Make room for the new session, if the user has too many of them
+    session_limits::enforce(
+        &mut repo,
+        clock,
+        &site_config.session_limits,
+        &browser_session.user,
+        Some(client),
+    )
+    .await?;
+
+    // Pick the device of the session, if it asks for one
+    let scope = device_allocation::allocate(
+        rng,
+        clock,
+        &mut repo,
+        site_config,
+        client,
+        &browser_session.user,
+        grant.scope.clone(),
+    )
+    .await?;
+
     // All good, let's start the session
     let session = repo
         .oauth2_session()
-        .add_from_browser_session(rng, clock, client, browser_session, grant.scope.clone())
+        .add_from_browser_session(rng, clock, client, browser_session, scope)
         .await?;
 
     let grant = repo
@@ -275,7 +360,7 @@ pub(crate) async fn complete(
             Some(&grant),
             browser_session,
             None,
-            Some(&valid_authentication),
+            &authentications,
         )?);
     }
 
@@ -286,6 +371,8 @@ pub(crate) async fn complete(
 
     repo.save().await?;
 
+    policy.audit_grant(&session.scope, client, Some(&browser_session.user));
+
     activity_tracker
         .record_oauth2_session(clock, &session)
         .await;
@@ -8,15 +8,23 @@ use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::typed_header::TypedHeader;
 use hyper::StatusCode;
-use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationCode, Pkce};
+use mas_axum_utils::{
+    cookies::CookieJar, csrf::CsrfExt, security_headers::CspNonce, sentry::SentryEventID,
+    SessionInfoExt,
+};
+use mas_data_model::{
+    oauth2::LoginHint, AuthorizationCode, Client, ClientFingerprint, Pkce, SiteConfig,
+    UpstreamOAuthProvider,
+};
 use mas_keystore::Keystore;
 use mas_policy::Policy;
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
-    BoxClock, BoxRepository, BoxRng,
+    upstream_oauth2::UpstreamOAuthProviderRepository,
+    BoxClock, BoxRepository, BoxRng, RepositoryError,
 };
 use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
 use oauth2_types::{
@@ -29,6 +37,7 @@ use rand::{distributions::Alphanumeric, Rng};
 use serde::Deserialize;
 use thiserror::Error;
 use tracing::warn;
+use ulid::Ulid;
 
 use self::{callback::CallbackDestination, complete::GrantCompletionError};
 use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
@@ -95,6 +104,50 @@ pub(crate) struct Params {
 
     #[serde(flatten)]
     pkce: Option<pkce::AuthorizationRequest>,
+
+    /// The ID or the brand name of the upstream provider to send the user
+    /// straight to, if the client is allowed to
+    #[serde(default, alias = "kc_idp_hint")]
+    idp_hint: Option<String>,
+}
+
+/// Find the upstream provider to send the user straight to, either the one
+/// hinted by the client if it is allowed to, or the one configured for it
+///
+/// Unknown or disabled providers are ignored, and the user goes through the
+/// login page instead.
+async fn upstream_provider_for(
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    client: &Client,
+    idp_hint: Option<&str>,
+) -> Result<Option<Ulid>, RepositoryError> {
+    let idp_hint = idp_hint.filter(|_| {
+        site_config
+            .upstream_provider_hint_clients
+            .contains(&client.id)
+    });
+    if let Some(idp_hint) = idp_hint {
+        let providers = repo.upstream_oauth_provider().all_enabled().await?;
+        let provider = providers.into_iter().find(|provider| {
+            provider.id.to_string() == idp_hint || provider.brand_name.as_deref() == Some(idp_hint)
+        });
+
+        if let Some(provider) = provider {
+            return Ok(Some(provider.id));
+        }
+    }
+
+    let Some(provider_id) = site_config.client_upstream_providers.get(&client.id) else {
+        return Ok(None);
+    };
+
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(*provider_id)
+        .await?
+        .filter(UpstreamOAuthProvider::enabled);
+    Ok(provider.map(|provider| provider.id))
 }
 
 /// Given a list of response types and an optional user-defined response mode,
@@ -135,10 +188,13 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
+    csp_nonce: CspNonce,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     Form(params): Form<Params>,
 ) -> Result<Response, RouteError> {
     // First, figure out what client it is
@@ -150,7 +206,10 @@ pub(crate) async fn get(
 
     // And resolve the redirect_uri and response_mode
     let redirect_uri = client
-        .resolve_redirect_uri(&params.auth.redirect_uri)?
+        .resolve_redirect_uri(
+            &params.auth.redirect_uri,
+            site_config.redirect_uri_validation_for(&client),
+        )?
         .clone();
     let response_type = params.auth.response_type;
     let response_mode = resolve_response_mode(&response_type, params.auth.response_mode)?;
@@ -160,7 +219,8 @@ pub(crate) async fn get(
         &response_mode,
         redirect_uri.clone(),
         params.auth.state.clone(),
-    )?;
+    )?
+    .with_csp_nonce(csp_nonce);
 
     // Get the session info from the cookie
     let (session_info, cookie_jar) = cookie_jar.session_info();
@@ -231,6 +291,20 @@ pub(crate) async fn get(
                     .await?);
             }
 
+            // prompt=none can't be combined with other values
+            if prompt.contains(&Prompt::None) && prompt.len() > 1 {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        &locale,
+                        ClientError::new(
+                            ClientErrorCode::InvalidRequest,
+                            "prompt=none can't be combined with other values",
+                        ),
+                    )
+                    .await?);
+            }
+
             // Fail early if prompt=none and there is no active session
             if prompt.contains(&Prompt::None) && maybe_session.is_none() {
                 return Ok(callback_destination
@@ -303,8 +377,28 @@ pub(crate) async fn get(
                     params.auth.login_hint,
                 )
                 .await?;
+
+            // Remember where the code was requested from, so that it can be bound to
+            // that context when it is redeemed
+            if grant.code.is_some() {
+                let fingerprint = ClientFingerprint::new(
+                    activity_tracker.ip(),
+                    user_agent.as_ref().map(|ua| ua.as_str()),
+                );
+                repo.oauth2_authorization_grant()
+                    .set_fingerprint(&grant, &fingerprint)
+                    .await?;
+            }
             let continue_grant = PostAuthAction::continue_grant(grant.id);
 
+            let login_hint = grant.parse_login_hint(&site_config.server_name);
+            let hints_other_user = match (&maybe_session, login_hint) {
+                (Some(session), LoginHint::MXID(mxid)) => {
+                    mxid.localpart() != session.user.username
+                }
+                _ => false,
+            };
+
             let res = match maybe_session {
                 // Cases where there is no active session, redirect to the relevant page
                 None if prompt.contains(&Prompt::None) => {
@@ -319,17 +413,49 @@ pub(crate) async fn get(
                         .into_response()
                 }
                 None => {
-                    // Other cases where we don't have a session, ask for a login
+                    // Other cases where we don't have a session, ask for a login, or go
+                    // straight to the upstream provider for this client
+                    let provider_id = upstream_provider_for(
+                        &mut repo,
+                        &site_config,
+                        &client,
+                        params.idp_hint.as_deref(),
+                    )
+                    .await?;
+
                     repo.save().await?;
 
-                    url_builder.redirect(&mas_router::Login::and_then(continue_grant))
+                    if let Some(provider_id) = provider_id {
+                        url_builder.redirect(
+                            &mas_router::UpstreamOAuth2Authorize::new(provider_id)
+                                .and_then(continue_grant),
+                        )
                         .into_response()
+                    } else {
+                        url_builder.redirect(&mas_router::Login::and_then(continue_grant))
+                            .into_response()
+                    }
+                }
+
+                // With prompt=none, we can't ask the user to switch accounts
+                Some(_) if prompt.contains(&Prompt::None) && hints_other_user => {
+                    repo.save().await?;
+
+                    callback_destination
+                        .go(
+                            &templates,
+                            &locale,
+                            ClientError::from(ClientErrorCode::LoginRequired),
+                        )
+                        .await?
                 }
 
-                // Special case when we already have a session but prompt=login|select_account
+                // Special case when we already have a session but prompt=login|select_account,
+                // or the client hinted at another user
                 Some(session)
                     if prompt.contains(&Prompt::Login)
-                        || prompt.contains(&Prompt::SelectAccount) =>
+                        || prompt.contains(&Prompt::SelectAccount)
+                        || hints_other_user =>
                 {
                     // TODO: better pages here
                     repo.save().await?;
@@ -353,6 +479,7 @@ pub(crate) async fn get(
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -370,6 +497,16 @@ pub(crate) async fn get(
                                 .await?
                         }
                         Err(GrantCompletionError::RequiresReauth) => {
+                            // The authentication is older than the max_age
+                            callback_destination
+                                .go(
+                                    &templates,
+                                    &locale,
+                                    ClientError::from(ClientErrorCode::LoginRequired),
+                                )
+                                .await?
+                        }
+                        Err(GrantCompletionError::RequiresPasswordChange) => {
                             callback_destination
                                 .go(
                                     &templates,
@@ -383,6 +520,15 @@ pub(crate) async fn get(
                                 .go(&templates, &locale, ClientError::from(ClientErrorCode::AccessDenied))
                                 .await?
                         }
+                        Err(GrantCompletionError::SessionLimitExceeded) => {
+                            callback_destination
+                                .go(
+                                    &templates,
+                                    &locale,
+                                    ClientError::new(ClientErrorCode::AccessDenied, "Too many active sessions"),
+                                )
+                                .await?
+                        }
                         Err(GrantCompletionError::Internal(e)) => {
                             return Err(RouteError::Internal(e))
                         }
@@ -405,6 +551,7 @@ pub(crate) async fn get(
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -430,6 +577,19 @@ pub(crate) async fn get(
                             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant))
                                 .into_response()
                         }
+                        Err(GrantCompletionError::RequiresPasswordChange) => {
+                            url_builder.redirect(&mas_router::PasswordChangeRequired::and_then(continue_grant))
+                                .into_response()
+                        }
+                        Err(GrantCompletionError::SessionLimitExceeded) => {
+                            callback_destination
+                                .go(
+                                    &templates,
+                                    &locale,
+                                    ClientError::new(ClientErrorCode::AccessDenied, "Too many active sessions"),
+                                )
+                                .await?
+                        }
                         Err(GrantCompletionError::Internal(e)) => {
                             return Err(RouteError::Internal(e))
                         }
@@ -462,3 +622,262 @@ pub(crate) async fn get(
 
     Ok((cookie_jar, response).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_axum_utils::SessionInfoExt;
+    use mas_data_model::{
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::{Route, SimpleRoute};
+    use mas_storage::{
+        oauth2::OAuth2ClientRepository,
+        upstream_oauth2::{UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository},
+        user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+    use ulid::Ulid;
+    use zeroize::Zeroizing;
+
+    use crate::test_utils::{
+        setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    const CLIENT_ID: Ulid = Ulid(42);
+
+    fn authorize(idp_hint: Option<&str>) -> Request<String> {
+        let mut uri = format!(
+            "{}?client_id={CLIENT_ID}&redirect_uri=https%3A%2F%2Fexample.com%2Fcallback&response_type=code&scope=openid",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        );
+        if let Some(idp_hint) = idp_hint {
+            uri.push_str("&kc_idp_hint=");
+            uri.push_str(idp_hint);
+        }
+        Request::get(uri).empty()
+    }
+
+    /// Build an authorization request for the test client, with the given
+    /// extra query parameters
+    fn authorize_with(extra: &str) -> Request<String> {
+        Request::get(format!(
+            "{}?client_id={CLIENT_ID}&redirect_uri=https%3A%2F%2Fexample.com%2Fcallback&response_type=code&scope=openid{extra}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty()
+    }
+
+    /// Test that clients can send users straight to an upstream provider
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_upstream_provider_hint(pool: PgPool) {
+        setup();
+        let mut site_config = test_site_config();
+        site_config.upstream_provider_hint_clients.insert(CLIENT_ID);
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .upsert_static(
+                CLIENT_ID,
+                OAuthClientAuthenticationMethod::None,
+                None,
+                None,
+                None,
+                vec!["https://example.com/callback".parse().unwrap()],
+            )
+            .await
+            .unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: Some("Example Ltd.".to_owned()),
+                    brand_name: Some("example".to_owned()),
+                    scope: [OPENID].into_iter().collect(),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    fetch_userinfo: false,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    userinfo_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    response_mode: mas_data_model::UpstreamOAuthProviderResponseMode::Query,
+                    additional_authorization_parameters: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let provider_path = mas_router::UpstreamOAuth2Authorize::new(provider.id).path();
+
+        // Without a hint, users go through the login page
+        let response = state.request(authorize(None)).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login"), "{location}");
+
+        // With the brand name of the provider
+        let response = state.request(authorize(Some("example"))).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(&*provider_path), "{location}");
+
+        // With its ID
+        let response = state
+            .request(authorize(Some(&provider.id.to_string())))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(&*provider_path), "{location}");
+
+        // Unknown providers are ignored
+        let response = state.request(authorize(Some("unknown"))).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login"), "{location}");
+    }
+
+    /// Test that authorization requests with `prompt=none` never show a page
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_none(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .upsert_static(
+                CLIENT_ID,
+                OAuthClientAuthenticationMethod::None,
+                None,
+                None,
+                None,
+                vec!["https://example.com/callback".parse().unwrap()],
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = |extra: &str| cookies.with_cookies(authorize_with(extra));
+
+        // Without a session
+        let response = state.request(request("&prompt=none")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=login_required"), "{redirect}");
+
+        // prompt=none must be alone
+        let response = state.request(request("&prompt=none%20login")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=invalid_request"), "{redirect}");
+
+        // With a session which was never authenticated
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        cookies.import(state.cookie_jar().set_session(&browser_session));
+
+        let response = state.request(request("&prompt=none")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=login_required"), "{redirect}");
+
+        // Once authenticated, the user still has to consent
+        let mut repo = state.repository().await.unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new(b"hunter2".to_vec()))
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = state.request(request("&prompt=none")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=consent_required"), "{redirect}");
+
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .lookup(CLIENT_ID)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_client()
+            .give_consent_for_user(
+                &mut rng,
+                &state.clock,
+                &client,
+                &user,
+                &Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The client hinted at another user
+        let response = state
+            .request(request("&prompt=none&login_hint=mxid:@bob:example.com"))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=login_required"), "{redirect}");
+
+        // The authentication is too old
+        state.clock.advance(Duration::minutes(10));
+        let response = state.request(request("&prompt=none&max_age=60")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("error=login_required"), "{redirect}");
+
+        // Else, the code is sent back straight away
+        let response = state.request(request("&prompt=none")).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let redirect = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(redirect.starts_with("https://example.com/callback?"));
+        assert!(redirect.contains("code="), "{redirect}");
+    }
+}
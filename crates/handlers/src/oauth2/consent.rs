@@ -16,7 +16,7 @@ use mas_axum_utils::{
     SessionInfoExt,
 };
 use mas_data_model::{AuthorizationGrantStage, Device};
-use mas_policy::Policy;
+use mas_policy::{Policy, TimeInput};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
@@ -108,10 +108,25 @@ pub(crate) async fn get(
             .record_browser_session(&clock, &session)
             .await;
 
+        if repo
+            .user()
+            .is_password_change_required(&session.user)
+            .await?
+        {
+            let destination = mas_router::PasswordChangeRequired::and_continue_grant(grant_id);
+            return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+        }
+
         let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
+        let timezone = repo.user().get_timezone(&session.user).await?;
         let res = policy
-            .evaluate_authorization_grant(&grant, &client, &session.user)
+            .evaluate_authorization_grant(
+                &grant,
+                &client,
+                &session.user,
+                TimeInput::new(clock.now(), timezone.as_deref()),
+            )
             .await?;
 
         if res.valid() {
@@ -178,14 +193,29 @@ pub(crate) async fn post(
         .record_browser_session(&clock, &session)
         .await;
 
+    if repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        let destination = mas_router::PasswordChangeRequired::and_then(next);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
     let client = repo
         .oauth2_client()
         .lookup(grant.client_id)
         .await?
         .ok_or(RouteError::NoSuchClient)?;
 
+    let timezone = repo.user().get_timezone(&session.user).await?;
     let res = policy
-        .evaluate_authorization_grant(&grant, &client, &session.user)
+        .evaluate_authorization_grant(
+            &grant,
+            &client,
+            &session.user,
+            TimeInput::new(clock.now(), timezone.as_deref()),
+        )
         .await?;
 
     if !res.valid() {
@@ -13,10 +13,11 @@ use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     sentry::SentryEventID,
 };
-use mas_data_model::UserAgent;
+use mas_data_model::{DeviceCodeGrant, SiteConfig, UserAgent};
+use mas_jose::claims::TimeOptions;
 use mas_keystore::Encrypter;
 use mas_router::UrlBuilder;
-use mas_storage::{oauth2::OAuth2DeviceCodeGrantParams, BoxClock, BoxRepository, BoxRng};
+use mas_storage::{oauth2::OAuth2DeviceCodeGrantParams, BoxClock, BoxRepository, BoxRng, Clock};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     requests::{DeviceAuthorizationRequest, DeviceAuthorizationResponse, GrantType},
@@ -82,6 +83,7 @@ pub(crate) async fn post(
     State(url_builder): State<UrlBuilder>,
     State(http_client): State<reqwest::Client>,
     State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
     client_authorization: ClientAuthorization<DeviceAuthorizationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -98,7 +100,13 @@ pub(crate) async fn post(
 
     client_authorization
         .credentials
-        .verify(&http_client, &encrypter, method, &client)
+        .verify(
+            &http_client,
+            &encrypter,
+            method,
+            &client,
+            &TimeOptions::new(clock.now()).leeway(site_config.jwt_leeway),
+        )
         .await?;
 
     if !client.grant_types.contains(&GrantType::DeviceCode) {
@@ -117,7 +125,11 @@ pub(crate) async fn post(
     let ip_address = activity_tracker.ip();
 
     let device_code = Alphanumeric.sample_string(&mut rng, 32);
-    let user_code = Alphanumeric.sample_string(&mut rng, 6).to_uppercase();
+    let user_code = DeviceCodeGrant::generate_user_code(
+        &mut rng,
+        site_config.device_code_user_code_length,
+        &site_config.device_code_user_code_charset,
+    );
 
     let device_code = repo
         .oauth2_device_code_grant()
@@ -16,7 +16,7 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_policy::Policy;
+use mas_policy::{Policy, TimeInput};
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
 use mas_templates::{DeviceConsentContext, PolicyViolationContext, TemplateContext, Templates};
@@ -64,6 +64,16 @@ pub(crate) async fn get(
         .record_browser_session(&clock, &session)
         .await;
 
+    if repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        let destination =
+            mas_router::PasswordChangeRequired::and_continue_device_code_grant(grant_id);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
     // TODO: better error handling
     let grant = repo
         .oauth2_device_code_grant()
@@ -82,8 +92,14 @@ pub(crate) async fn get(
         .context("Client not found")?;
 
     // Evaluate the policy
+    let timezone = repo.user().get_timezone(&session.user).await?;
     let res = policy
-        .evaluate_device_code_grant(&grant, &client, &session.user)
+        .evaluate_device_code_grant(
+            &grant,
+            &client,
+            &session.user,
+            TimeInput::new(clock.now(), timezone.as_deref()),
+        )
         .await?;
     if !res.valid() {
         warn!(violation = ?res, "Device code grant for client {} denied by policy", client.id);
@@ -139,6 +155,16 @@ pub(crate) async fn post(
         .record_browser_session(&clock, &session)
         .await;
 
+    if repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        let destination =
+            mas_router::PasswordChangeRequired::and_continue_device_code_grant(grant_id);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
     // TODO: better error handling
     let grant = repo
         .oauth2_device_code_grant()
@@ -157,8 +183,14 @@ pub(crate) async fn post(
         .context("Client not found")?;
 
     // Evaluate the policy
+    let timezone = repo.user().get_timezone(&session.user).await?;
     let res = policy
-        .evaluate_device_code_grant(&grant, &client, &session.user)
+        .evaluate_device_code_grant(
+            &grant,
+            &client,
+            &session.user,
+            TimeInput::new(clock.now(), timezone.as_deref()),
+        )
         .await?;
     if !res.valid() {
         warn!(violation = ?res, "Device code grant for client {} denied by policy", client.id);
@@ -177,9 +209,12 @@ pub(crate) async fn post(
     let grant = if grant.is_pending() {
         match form.action {
             Action::Consent => {
-                repo.oauth2_device_code_grant()
+                let grant = repo
+                    .oauth2_device_code_grant()
                     .fulfill(&clock, grant, &session)
-                    .await?
+                    .await?;
+                policy.audit_grant(&grant.scope, &client, Some(&session.user));
+                grant
             }
             Action::Reject => {
                 repo.oauth2_device_code_grant()
@@ -9,28 +9,40 @@ use axum::{
     response::IntoResponse,
 };
 use axum_extra::response::Html;
+use hyper::header::CONTENT_TYPE;
 use mas_axum_utils::{cookies::CookieJar, FancyError};
+use mas_data_model::{DeviceCodeGrant, SiteConfig};
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository};
 use mas_templates::{
-    DeviceLinkContext, DeviceLinkFormField, FieldError, FormState, TemplateContext, Templates,
+    DeviceLinkContext, DeviceLinkFormField, FieldError, FormError, FormState, TemplateContext,
+    Templates,
 };
+use qrcode::{render::svg, QrCode};
 use serde::{Deserialize, Serialize};
 
-use crate::PreferredLanguage;
+use crate::{Limiter, PreferredLanguage, RequesterFingerprint};
 
 #[derive(Serialize, Deserialize)]
 pub struct Params {
     code: String,
 }
 
+#[derive(Deserialize)]
+pub struct QrParams {
+    code: Option<String>,
+}
+
 #[tracing::instrument(name = "handlers.oauth2.device.link.get", skip_all, err)]
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn get(
     clock: BoxClock,
     mut repo: BoxRepository,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    (State(limiter), requester): (State<Limiter>, RequesterFingerprint),
     cookie_jar: CookieJar,
     query: Option<Query<Params>>,
 ) -> Result<impl IntoResponse, FancyError> {
@@ -41,30 +53,40 @@ pub(crate) async fn get(
         // Save the form state so that we echo back the code
         form_state = FormState::from_form(&params);
 
-        // Find the code in the database
-        let code = params.code.to_uppercase();
-        let grant = repo
-            .oauth2_device_code_grant()
-            .find_by_user_code(&code)
-            .await?
-            // XXX: We should have different error messages for already exchanged and expired
-            .filter(|grant| grant.is_pending())
-            .filter(|grant| grant.expires_at > clock.now());
-
-        if let Some(grant) = grant {
-            // This is a valid code, redirect to the consent page
-            // This will in turn redirect to the login page if the user is not logged in
-            let destination = url_builder.redirect(&mas_router::DeviceCodeConsent::new(grant.id));
-
-            return Ok((cookie_jar, destination).into_response());
-        }
+        // Check the rate limit before looking up the code, so that codes can't be
+        // brute-forced
+        if let Err(e) = limiter.check_device_code_entry(requester) {
+            tracing::warn!(error = &e as &dyn std::error::Error);
+            form_state.add_error_on_form(FormError::RateLimitExceeded);
+        } else {
+            // Find the code in the database
+            let code = DeviceCodeGrant::normalize_user_code(&params.code);
+            let grant = repo
+                .oauth2_device_code_grant()
+                .find_by_user_code(&code)
+                .await?
+                // XXX: We should have different error messages for already exchanged and expired
+                .filter(|grant| grant.is_pending())
+                .filter(|grant| grant.expires_at > clock.now());
+
+            if let Some(grant) = grant {
+                // This is a valid code, redirect to the consent page
+                // This will in turn redirect to the login page if the user is not logged in
+                let destination =
+                    url_builder.redirect(&mas_router::DeviceCodeConsent::new(grant.id));
 
-        // The code isn't valid, set an error on the form
-        form_state = form_state.with_error_on_field(DeviceLinkFormField::Code, FieldError::Invalid);
+                return Ok((cookie_jar, destination).into_response());
+            }
+
+            // The code isn't valid, set an error on the form
+            form_state =
+                form_state.with_error_on_field(DeviceLinkFormField::Code, FieldError::Invalid);
+        }
     };
 
     // Rendre the form
     let ctx = DeviceLinkContext::new()
+        .with_code_length(site_config.device_code_user_code_length)
         .with_form_state(form_state)
         .with_language(locale);
 
@@ -72,3 +94,102 @@ pub(crate) async fn get(
 
     Ok((cookie_jar, Html(content)).into_response())
 }
+
+/// Render a QR code of the link to this page, so that devices which can display
+/// images can show it next to the code
+#[tracing::instrument(name = "handlers.oauth2.device.link.qr", skip_all, err)]
+pub(crate) async fn qr(
+    State(url_builder): State<UrlBuilder>,
+    Query(params): Query<QrParams>,
+) -> Result<impl IntoResponse, FancyError> {
+    let url = match params.code {
+        Some(code) => {
+            url_builder.device_code_link_full(DeviceCodeGrant::normalize_user_code(&code))
+        }
+        None => url_builder.device_code_link(),
+    };
+
+    let image = QrCode::new(url.as_str())?
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build();
+
+    Ok(([(CONTENT_TYPE, "image/svg+xml")], image))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::CONTENT_TYPE, Request, StatusCode};
+    use mas_router::{Route, SimpleRoute};
+    use oauth2_types::{
+        registration::ClientRegistrationResponse, requests::DeviceAuthorizationResponse,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_device_code_link(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "none",
+                "grant_types": ["urn:ietf:params:oauth:grant-type:device_code"],
+                "response_types": [],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+
+        // Start a device code grant
+        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: DeviceAuthorizationResponse = response.json();
+
+        // The code can be entered in lowercase, formatted as it is displayed
+        let code = response.user_code.to_lowercase();
+        let formatted = format!("{}-{}", &code[..3], &code[3..]);
+        let link = |code: &str| {
+            Request::get(format!(
+                "{}?code={code}",
+                mas_router::DeviceCodeLink::route()
+            ))
+            .empty()
+        };
+
+        let response = state.request(link(&formatted)).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // The QR code of the complete verification URI can be rendered
+        let request = Request::get(format!(
+            "{}?code={formatted}",
+            mas_router::DeviceCodeLinkQr::route()
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "image/svg+xml");
+
+        // Entering wrong codes repeatedly gets rate limited
+        for _ in 0..9 {
+            let response = state.request(link("WRONG0")).await;
+            response.assert_status(StatusCode::OK);
+        }
+
+        // Even the right code is now refused
+        let response = state.request(link(&formatted)).await;
+        response.assert_status(StatusCode::OK);
+    }
+}
@@ -123,6 +123,7 @@ pub(crate) async fn get(
         "exp".to_owned(),
         "nonce".to_owned(),
         "auth_time".to_owned(),
+        "amr".to_owned(),
         "at_hash".to_owned(),
         "c_hash".to_owned(),
     ]);
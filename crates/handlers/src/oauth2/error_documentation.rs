@@ -0,0 +1,64 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use axum_extra::response::Html;
+use hyper::StatusCode;
+use mas_axum_utils::FancyError;
+use mas_templates::{OAuth2ErrorContext, TemplateContext, Templates};
+use oauth2_types::errors::ClientErrorCode;
+
+use super::localized_errors::localized_description;
+use crate::PreferredLanguage;
+
+/// Render the documentation page of an OAuth 2.0 error code, linked from the
+/// `error_uri` of the error responses
+#[tracing::instrument(name = "handlers.oauth2.error_documentation.get", skip_all, err)]
+pub(crate) async fn get(
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    Path(code): Path<String>,
+) -> Result<Response, FancyError> {
+    let Ok(code) = code.parse::<ClientErrorCode>();
+    let translator = templates.translator();
+
+    let Some((description, _)) = localized_description(&translator, locale.clone(), &code) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let ctx = OAuth2ErrorContext::new(code.to_string(), description).with_language(locale);
+    let content = templates.render_oauth2_error(&ctx)?;
+
+    Ok(Html(content).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_error_documentation(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get("/errors/invalid_grant").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("invalid_grant"));
+        assert!(response
+            .body()
+            .contains("The provided access grant is invalid, expired, or revoked."));
+
+        let request = Request::get("/errors/not_an_error").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -10,19 +10,23 @@ use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     sentry::SentryEventID,
 };
-use mas_data_model::{TokenFormatError, TokenType};
+use mas_data_model::{Device, SiteConfig, TokenFormatError, TokenType};
 use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
+use mas_jose::claims::TimeOptions;
 use mas_keystore::Encrypter;
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
-    oauth2::{OAuth2AccessTokenRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository},
+    oauth2::{
+        AccessTokenIntrospection, OAuth2AccessTokenRepository, OAuth2ClientRepository,
+        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+    },
     user::UserRepository,
     BoxClock, BoxRepository, Clock,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     requests::{IntrospectionRequest, IntrospectionResponse},
-    scope::ScopeToken,
+    scope::{Scope, ScopeToken},
 };
 use thiserror::Error;
 
@@ -152,11 +156,32 @@ const INACTIVE: IntrospectionResponse = IntrospectionResponse {
     aud: None,
     iss: None,
     jti: None,
+    offline_access: None,
+    session_id: None,
+    device_id: None,
+    client_name: None,
 };
 
 const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
 const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:admin:*");
 
+/// Whether the claims identifying the session of a token are returned for a
+/// token with the given scope.
+///
+/// They are only useful to the homeserver, so they are only returned for tokens
+/// giving access to the Matrix client-server API.
+fn has_session_claims(scope: &Scope) -> bool {
+    scope.contains(API_SCOPE.as_str())
+}
+
+/// Find the Matrix device ID of a session from its scope
+fn matrix_device_id(scope: &Scope) -> Option<String> {
+    scope
+        .iter()
+        .find_map(Device::from_scope_token)
+        .map(|device| device.as_str().to_owned())
+}
+
 #[tracing::instrument(
     name = "handlers.oauth2.introspection.post",
     fields(client.id = client_authorization.client_id()),
@@ -170,6 +195,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: ActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
     client_authorization: ClientAuthorization<IntrospectionRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -187,7 +213,13 @@ pub(crate) async fn post(
 
     client_authorization
         .credentials
-        .verify(&http_client, &encrypter, method, &client)
+        .verify(
+            &http_client,
+            &encrypter,
+            method,
+            &client,
+            &TimeOptions::new(clock.now()).leeway(site_config.jwt_leeway),
+        )
         .await?;
 
     let Some(form) = client_authorization.form else {
@@ -207,9 +239,17 @@ pub(crate) async fn post(
 
     let reply = match token_type {
         TokenType::AccessToken => {
-            let access_token = repo
+            // This is the hot path, so the token, its session and its user are all
+            // fetched in a single query
+            let AccessTokenIntrospection {
+                access_token,
+                session,
+                user,
+                client_name,
+                has_active_refresh_token: offline_access,
+            } = repo
                 .oauth2_access_token()
-                .find_by_token(token)
+                .find_for_introspection(token)
                 .await?
                 .ok_or(RouteError::UnknownToken(TokenType::AccessToken))?;
 
@@ -217,24 +257,14 @@ pub(crate) async fn post(
                 return Err(RouteError::InvalidToken(TokenType::AccessToken));
             }
 
-            let session = repo
-                .oauth2_session()
-                .lookup(access_token.session_id)
-                .await?
-                .ok_or(RouteError::InvalidOAuthSession)?;
-
             if !session.is_valid() {
                 return Err(RouteError::InvalidOAuthSession);
             }
 
             // The session might not have a user on it (for Client Credentials grants for
-            // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
-                let user = repo
-                    .user()
-                    .lookup(user_id)
-                    .await?
-                    .ok_or(RouteError::CantLoadUser)?;
+            // example)
+            let (sub, username) = if session.user_id.is_some() {
+                let user = user.ok_or(RouteError::CantLoadUser)?;
 
                 if !user.is_valid() {
                     return Err(RouteError::InvalidUser);
@@ -245,6 +275,16 @@ pub(crate) async fn post(
                 (None, None)
             };
 
+            let (session_id, device_id, client_name) = if has_session_claims(&session.scope) {
+                (
+                    Some(session.id.to_string()),
+                    matrix_device_id(&session.scope),
+                    client_name,
+                )
+            } else {
+                (None, None, None)
+            };
+
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
@@ -262,6 +302,10 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: Some(access_token.jti()),
+                offline_access: Some(offline_access),
+                session_id,
+                device_id,
+                client_name,
             }
         }
 
@@ -304,6 +348,17 @@ pub(crate) async fn post(
                 (None, None)
             };
 
+            let (session_id, device_id, client_name) = if has_session_claims(&session.scope) {
+                let client = repo.oauth2_client().lookup(session.client_id).await?;
+                (
+                    Some(session.id.to_string()),
+                    matrix_device_id(&session.scope),
+                    client.and_then(|client| client.client_name),
+                )
+            } else {
+                (None, None, None)
+            };
+
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
@@ -321,6 +376,10 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: Some(refresh_token.jti()),
+                offline_access: Some(true),
+                session_id,
+                device_id,
+                client_name,
             }
         }
 
@@ -363,6 +422,12 @@ pub(crate) async fn post(
                 .chain(synapse_admin)
                 .collect();
 
+            let offline_access = !repo
+                .compat_refresh_token()
+                .list_active(&session)
+                .await?
+                .is_empty();
+
             activity_tracker
                 .record_compat_session(&clock, &session, ip)
                 .await;
@@ -380,6 +445,10 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
+                offline_access: Some(offline_access),
+                session_id: Some(session.id.to_string()),
+                device_id: Some(session.device.as_str().to_owned()),
+                client_name: None,
             }
         }
 
@@ -439,6 +508,10 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
+                offline_access: Some(true),
+                session_id: Some(session.id.to_string()),
+                device_id: Some(session.device.as_str().to_owned()),
+                client_name: None,
             }
         }
     };
@@ -565,6 +638,12 @@ mod tests {
         assert_eq!(response.client_id, Some(client_id.clone()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
         assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        assert_eq!(response.offline_access, Some(true));
+        // The token doesn't give access to the Matrix API, so the session isn't
+        // identified
+        assert_eq!(response.session_id, None);
+        assert_eq!(response.device_id, None);
+        assert_eq!(response.client_name, None);
 
         // Do the same request, but with a token_type_hint
         let request = Request::post(OAuth2Introspection::PATH)
@@ -759,6 +838,8 @@ mod tests {
         assert_eq!(response.client_id, Some("legacy".to_owned()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
         assert_eq!(response.scope, Some(expected_scope.clone()));
+        assert!(response.session_id.is_some());
+        assert_eq!(response.device_id.as_deref(), Some(device_id));
 
         // Do the same request, but with a token_type_hint
         let request = Request::post(OAuth2Introspection::PATH)
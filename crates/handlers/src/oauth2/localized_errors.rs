@@ -0,0 +1,179 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Localization of the OAuth 2.0 error responses
+//!
+//! Error responses of the OAuth 2.0 endpoints get an `error_i18n_key` field
+//! with the translation key of their error code, so that clients can show
+//! their own message without matching on the English description, and an
+//! `error_uri` linking to the documentation page of the error code. When the
+//! description is the default one for the error code, it is also translated
+//! in the language negotiated through the `Accept-Language` header.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use headers::HeaderMapExt;
+use hyper::header::{CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE};
+use mas_axum_utils::language_detection::AcceptLanguage;
+use mas_i18n::{ArgumentList, DataLocale, Translator};
+use mas_templates::Templates;
+use oauth2_types::errors::{ClientError, ClientErrorCode};
+
+use crate::PreferredLanguage;
+
+/// The prefix of the translation keys of the OAuth 2.0 error codes
+const KEY_PREFIX: &str = "mas.oauth2_errors";
+
+/// The translation key of the description of an error code
+fn translation_key(code: &ClientErrorCode) -> String {
+    format!("{KEY_PREFIX}.{code}")
+}
+
+/// Get the description of an error code in the given language, along with the
+/// locale it was found in, which may be a fallback one
+///
+/// Returns `None` for the error codes which don't have a translation
+pub(crate) fn localized_description(
+    translator: &Translator,
+    locale: DataLocale,
+    code: &ClientErrorCode,
+) -> Option<(String, DataLocale)> {
+    let key = translation_key(code);
+    let (message, locale) = translator.message_with_fallback(locale, &key)?;
+
+    match message.format(&ArgumentList::default()) {
+        Ok(description) => Some((description, locale)),
+        Err(e) => {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Failed to format the translation of {key}"
+            );
+            None
+        }
+    }
+}
+
+/// Whether the response looks like an OAuth 2.0 error response
+fn is_json_error(response: &Response) -> bool {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+
+    (status.is_client_error() || status.is_server_error()) && is_json
+}
+
+/// A middleware adding the translation key, the documentation URI and a
+/// localized description to the OAuth 2.0 error responses
+pub(crate) async fn localize_client_errors(
+    templates: Templates,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accept_language = request.headers().typed_get::<AcceptLanguage>();
+    let response = next.run(request).await;
+
+    if !is_json_error(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // Error responses are small and fully buffered, so this can't really fail
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+
+    let Ok(mut error) = serde_json::from_slice::<ClientError>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let translator = templates.translator();
+    let PreferredLanguage(locale) =
+        PreferredLanguage::negotiate(&translator, accept_language.as_ref());
+
+    // Unknown error codes don't have a translation, nor a documentation page
+    let Some((description, locale)) = localized_description(&translator, locale, &error.error)
+    else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    // Custom descriptions give more details than the default one for the error
+    // code, so they are kept as-is
+    let has_default_description = error
+        .error_description
+        .as_deref()
+        .is_none_or(|description| description == error.error.default_description());
+
+    if has_default_description {
+        error.error_description = Some(description.into());
+        if let Ok(value) = locale.to_string().parse() {
+            parts.headers.insert(CONTENT_LANGUAGE, value);
+        }
+    }
+
+    if error.error_uri.is_none() {
+        let base = templates.url_builder().oauth_error_documentation_base();
+        error.error_uri = error.error.uri(&base);
+    }
+
+    error.error_i18n_key = Some(translation_key(&error.error).into());
+
+    parts.headers.remove(CONTENT_LENGTH);
+    (parts, Json(error)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::ACCEPT_LANGUAGE, Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use oauth2_types::errors::ClientErrorCode;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localized_client_errors(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": "a-code",
+                "client_id": "unknown-client",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        let error: serde_json::Value = response.json();
+        assert_eq!(error["error"], "invalid_client");
+        assert_eq!(error["error_i18n_key"], "mas.oauth2_errors.invalid_client");
+        assert_eq!(
+            error["error_description"],
+            ClientErrorCode::InvalidClient.default_description()
+        );
+        assert_eq!(
+            error["error_uri"],
+            "https://example.com/errors/invalid_client"
+        );
+
+        // Unknown languages fall back to the default one
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header(ACCEPT_LANGUAGE, "tlh")
+            .form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": "a-code",
+                "client_id": "unknown-client",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[hyper::header::CONTENT_LANGUAGE], "en");
+    }
+}
@@ -8,8 +8,8 @@ use std::collections::HashMap;
 
 use chrono::Duration;
 use mas_data_model::{
-    AccessToken, Authentication, AuthorizationGrant, BrowserSession, Client, RefreshToken, Session,
-    TokenType,
+    authentication_method_references, AccessToken, Authentication, AuthorizationGrant,
+    BrowserSession, Client, RefreshToken, Session, TokenType,
 };
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
@@ -26,8 +26,10 @@ pub mod authorization;
 pub mod consent;
 pub mod device;
 pub mod discovery;
+pub mod error_documentation;
 pub mod introspection;
 pub mod keys;
+pub mod localized_errors;
 pub mod registration;
 pub mod revoke;
 pub mod token;
@@ -54,7 +56,7 @@ pub(crate) fn generate_id_token(
     grant: Option<&AuthorizationGrant>,
     browser_session: &BrowserSession,
     access_token: Option<&AccessToken>,
-    last_authentication: Option<&Authentication>,
+    authentications: &[Authentication],
 ) -> Result<String, IdTokenSignatureError> {
     let mut claims = HashMap::new();
     let now = clock.now();
@@ -68,10 +70,17 @@ pub(crate) fn generate_id_token(
         claims::NONCE.insert(&mut claims, nonce)?;
     }
 
-    if let Some(last_authentication) = last_authentication {
+    if let Some(last_authentication) = authentications.last() {
         claims::AUTH_TIME.insert(&mut claims, last_authentication.created_at)?;
     }
 
+    // All the methods which authenticated the session, so that relying parties
+    // can enforce their own conditional access rules
+    let amr = authentication_method_references(authentications);
+    if !amr.is_empty() {
+        claims::AMR.insert(&mut claims, amr)?;
+    }
+
     let alg = client
         .id_token_signed_response_alg
         .clone()
@@ -4,7 +4,17 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::{BytesRejection, JsonRejection},
+        State,
+    },
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::TypedHeader;
+use headers::ContentType;
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
@@ -13,6 +23,7 @@ use mas_policy::{Policy, Violation};
 use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, BoxRng};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
+    oidc::ApplicationType,
     registration::{
         ClientMetadata, ClientMetadataVerificationError, ClientRegistrationResponse, Localized,
         VerifiedClientMetadata,
@@ -25,7 +36,12 @@ use thiserror::Error;
 use tracing::info;
 use url::Url;
 
-use crate::impl_from_error_for_route;
+use crate::{
+    attestation::{
+        request_hash, AttestationChecker, AttestationEvent, AttestationRejection, ClientAttestation,
+    },
+    impl_from_error_for_route,
+};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -33,7 +49,10 @@ pub(crate) enum RouteError {
     Internal(Box<dyn std::error::Error + Send + Sync>),
 
     #[error(transparent)]
-    JsonExtract(#[from] axum::extract::rejection::JsonRejection),
+    JsonExtract(#[from] JsonRejection),
+
+    #[error("request body is not JSON")]
+    NotJson,
 
     #[error("invalid client metadata")]
     InvalidClientMetadata(#[from] ClientMetadataVerificationError),
@@ -43,6 +62,9 @@ pub(crate) enum RouteError {
 
     #[error("denied by the policy: {0:?}")]
     PolicyDenied(Vec<Violation>),
+
+    #[error("client attestation rejected")]
+    AttestationRejected(#[source] AttestationRejection),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -74,7 +96,7 @@ impl IntoResponse for RouteError {
 
             // For all other JSON errors we return a `invalid_request` error, since this is
             // probably due to a malformed request.
-            Self::JsonExtract(_) => (
+            Self::JsonExtract(_) | Self::NotJson => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::InvalidRequest)),
             )
@@ -145,6 +167,17 @@ impl IntoResponse for RouteError {
                 )
                     .into_response()
             }
+
+            // Native clients which didn't present a valid platform attestation, when those are
+            // required
+            Self::AttestationRejected(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidClientMetadata)
+                        .with_description(e.to_string()),
+                ),
+            )
+                .into_response(),
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -196,10 +229,24 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     mut policy: Policy,
     State(encrypter): State<Encrypter>,
-    body: Result<Json<ClientMetadata>, axum::extract::rejection::JsonRejection>,
+    State(attestation_checker): State<AttestationChecker>,
+    attestation: ClientAttestation,
+    content_type: Option<TypedHeader<ContentType>>,
+    body: Result<Bytes, BytesRejection>,
 ) -> Result<impl IntoResponse, RouteError> {
+    // The body is read as bytes, as platform attestations are bound to it
+    let raw_body = body.map_err(JsonRejection::from)?;
+    let is_json = content_type.is_some_and(|TypedHeader(content_type)| {
+        let mime = mime::Mime::from(content_type);
+        mime.type_() == mime::APPLICATION
+            && (mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+    });
+    if !is_json {
+        return Err(RouteError::NotJson);
+    }
+
     // Propagate any JSON extraction error
-    let Json(body) = body?;
+    let Json(body) = Json::<ClientMetadata>::from_bytes(&raw_body)?;
 
     info!(?body, "Client registration");
 
@@ -249,6 +296,19 @@ pub(crate) async fn post(
         return Err(RouteError::PolicyDenied(res.violations));
     }
 
+    // Mobile clients may have to prove they are a genuine application
+    if metadata.application_type() == ApplicationType::Native {
+        attestation_checker
+            .check(
+                &attestation,
+                AttestationEvent::Registration,
+                None,
+                request_hash(&raw_body),
+            )
+            .await
+            .map_err(RouteError::AttestationRejected)?;
+    }
+
     let (client_secret, encrypted_client_secret) = match metadata.token_endpoint_auth_method {
         Some(
             OAuthClientAuthenticationMethod::ClientSecretJwt
@@ -313,7 +373,10 @@ pub(crate) async fn post(
 
 #[cfg(test)]
 mod tests {
+    use std::{sync::Arc, time::Duration};
+
     use hyper::{Request, StatusCode};
+    use mas_config::AttestationEnforcement;
     use mas_router::SimpleRoute;
     use oauth2_types::{
         errors::{ClientError, ClientErrorCode},
@@ -323,8 +386,11 @@ mod tests {
     use url::Url;
 
     use crate::{
+        attestation::request_hash,
         oauth2::registration::host_is_public_suffix,
         test_utils::{setup, RequestBuilderExt, ResponseExt, TestState},
+        AttestationChecker, AttestationError, AttestationRequest, AttestationVerdict,
+        AttestationVerifier,
     };
 
     #[test]
@@ -477,4 +543,89 @@ mod tests {
         let response: ClientRegistrationResponse = response.json();
         assert!(response.client_secret.is_some());
     }
+
+    struct MockVerifier;
+
+    #[async_trait::async_trait]
+    impl AttestationVerifier for MockVerifier {
+        async fn verify(
+            &self,
+            request: &AttestationRequest,
+        ) -> Result<AttestationVerdict, AttestationError> {
+            Ok(AttestationVerdict {
+                valid: request.attestation == format!("genuine.{}", request.request_hash),
+                app_id: Some("com.example.app".to_owned()),
+            })
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_registration_attestation(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.attestation_checker = AttestationChecker::new(
+            Arc::new(MockVerifier),
+            AttestationEnforcement::Required,
+            Duration::from_secs(1),
+            Vec::new(),
+            vec!["com.example.app".to_owned()],
+        );
+
+        let body = serde_json::json!({
+            "client_uri": "https://example.com/",
+            "application_type": "native",
+            "redirect_uris": ["com.example.app:/callback"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code"],
+            "token_endpoint_auth_method": "none",
+        });
+        // The attestation has to be bound to the body
+        let genuine = format!(
+            "play-integrity genuine.{}",
+            request_hash(body.to_string().as_bytes())
+        );
+
+        let request = |attestation: Option<&str>| {
+            let mut builder = Request::post(mas_router::OAuth2RegistrationEndpoint::PATH);
+            if let Some(attestation) = attestation {
+                builder = builder.header("MAS-Client-Attestation", attestation);
+            }
+
+            builder.json(&body)
+        };
+
+        // Native clients must present an attestation
+        let response = state.request(request(None)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidClientMetadata);
+
+        let response = state.request(request(Some("play-integrity cloned"))).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // An attestation bound to another request is rejected
+        let response = state
+            .request(request(Some("play-integrity genuine.c29tZXRoaW5nIGVsc2U")))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let response = state.request(request(Some(&genuine))).await;
+        response.assert_status(StatusCode::CREATED);
+
+        // The same attestation can't be replayed
+        let response = state.request(request(Some(&genuine))).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Web clients aren't checked
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+    }
 }
@@ -10,12 +10,13 @@ use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     sentry::SentryEventID,
 };
-use mas_data_model::TokenType;
+use mas_data_model::{SiteConfig, TokenType};
 use mas_iana::oauth::OAuthTokenTypeHint;
+use mas_jose::claims::TimeOptions;
 use mas_keystore::Encrypter;
 use mas_storage::{
     job::{JobRepositoryExt, SyncDevicesJob},
-    BoxClock, BoxRepository, RepositoryAccess,
+    BoxClock, BoxRepository, Clock, RepositoryAccess,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
@@ -114,6 +115,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
     client_authorization: ClientAuthorization<RevocationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -129,7 +131,13 @@ pub(crate) async fn post(
 
     client_authorization
         .credentials
-        .verify(&http_client, &encrypter, method, &client)
+        .verify(
+            &http_client,
+            &encrypter,
+            method,
+            &client,
+            &TimeOptions::new(clock.now()).leeway(site_config.jwt_leeway),
+        )
         .await?;
 
     let Some(form) = client_authorization.form else {
@@ -137,6 +145,7 @@ pub(crate) async fn post(
     };
 
     let token_type = TokenType::check(&form.token)?;
+    let revoke_related = form.revoke_related();
 
     // Find the ID of the session to end.
     let session_id = match (form.token_type_hint, token_type) {
@@ -212,6 +221,28 @@ pub(crate) async fn post(
         repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
     }
 
+    // Ending the session makes all its tokens unusable, but the tokens
+    // themselves stay marked as valid. If asked to, also revoke the sibling
+    // tokens, in the same transaction as the session end.
+    if revoke_related {
+        let access_tokens = repo
+            .oauth2_access_token()
+            .list_active(&clock, &session)
+            .await?;
+        for access_token in access_tokens {
+            repo.oauth2_access_token()
+                .revoke(&clock, access_token)
+                .await?;
+        }
+
+        let refresh_tokens = repo.oauth2_refresh_token().list_active(&session).await?;
+        for refresh_token in refresh_tokens {
+            repo.oauth2_refresh_token()
+                .consume(&clock, refresh_token)
+                .await?;
+        }
+    }
+
     // Now that we checked everything, we can end the session.
     repo.oauth2_session().finish(&clock, session).await?;
 
@@ -439,4 +470,109 @@ mod tests {
 
         assert!(!state.is_access_token_valid(&access_token).await);
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_revoke_related(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let client_registration: ClientRegistrationResponse = response.json();
+
+        let client_id = client_registration.client_id;
+        let client_secret = client_registration.client_secret.unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        // Issue two pairs of tokens on the same session
+        let (AccessToken { access_token, .. }, _) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+        generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Revoke one of the access tokens, along with its siblings
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+            "token": access_token,
+            "token_type_hint": "access_token",
+            "revoke_related": true,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // All the tokens of the session were revoked
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo
+            .oauth2_access_token()
+            .list_active(&state.clock, &session)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(repo
+            .oauth2_refresh_token()
+            .list_active(&session)
+            .await
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -14,14 +14,20 @@ use mas_axum_utils::{
     sentry::SentryEventID,
 };
 use mas_data_model::{
-    AuthorizationGrantStage, Client, Device, DeviceCodeGrantState, SiteConfig, TokenType, UserAgent,
+    AuthorizationCodeBinding, AuthorizationGrantStage, Client, ClientFingerprint, Device,
+    DeviceCodeGrantState, FingerprintMismatch, RefreshTokenState, Session, SiteConfig, TokenType,
+    UserAgent,
 };
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_jose::claims::TimeOptions;
 use mas_keystore::{Encrypter, Keystore};
 use mas_matrix::BoxHomeserverConnection;
 use mas_oidc_client::types::scope::ScopeToken;
-use mas_policy::Policy;
+use mas_policy::{Policy, TimeInput};
 use mas_router::UrlBuilder;
 use mas_storage::{
+    device_key::DeviceKeyRepository,
+    job::{JobRepositoryExt, RevokeDeviceJob},
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
         OAuth2RefreshTokenRepository, OAuth2SessionRepository,
@@ -31,19 +37,34 @@ use mas_storage::{
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
+    oidc::ApplicationType,
     pkce::CodeChallengeError,
     requests::{
         AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, ClientCredentialsGrant,
         DeviceCodeGrant, GrantType, RefreshTokenGrant,
     },
-    scope,
+    scope::{self, Scope},
 };
 use thiserror::Error;
 use tracing::debug;
 use ulid::Ulid;
 
 use super::{generate_id_token, generate_token_pair};
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{
+    attestation::{
+        request_hash, AttestationChecker, AttestationEvent, AttestationRejection, ClientAttestation,
+    },
+    device_allocation,
+    device_proof::{DeviceProof, DeviceProofError},
+    impl_from_error_for_route,
+    session_limits::{self, SessionLimitError},
+    BoundActivityTracker,
+};
+
+/// How long after a refresh token was consumed it can be presented again
+/// without ending its session, to let clients retry a request which failed on
+/// their side
+const CONSUMED_REFRESH_TOKEN_GRACE_PERIOD: Duration = Duration::seconds(30);
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -86,12 +107,18 @@ pub(crate) enum RouteError {
     #[error("policy denied the request")]
     DeniedByPolicy(Vec<mas_policy::Violation>),
 
+    #[error("requested scope is not a subset of the session scope")]
+    ScopeNotGranted,
+
     #[error("unsupported grant type")]
     UnsupportedGrantType,
 
     #[error("unauthorized client")]
     UnauthorizedClient,
 
+    #[error("client attestation rejected")]
+    AttestationRejected(#[source] AttestationRejection),
+
     #[error("failed to load browser session")]
     NoSuchBrowserSession,
 
@@ -112,9 +139,19 @@ pub(crate) enum RouteError {
 
     #[error("failed to provision device")]
     ProvisionDeviceFailed(#[source] anyhow::Error),
+
+    #[error("invalid device proof")]
+    DeviceProof(#[from] DeviceProofError),
+
+    #[error("authorization code binding check failed")]
+    CodeBinding(#[from] FingerprintMismatch),
+
+    #[error("the user has too many active sessions")]
+    SessionLimitExceeded,
 }
 
 impl IntoResponse for RouteError {
+    #[allow(clippy::too_many_lines)]
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
 
@@ -145,6 +182,13 @@ impl IntoResponse for RouteError {
                 StatusCode::UNAUTHORIZED,
                 Json(ClientError::from(ClientErrorCode::UnauthorizedClient)),
             ),
+            Self::AttestationRejected(e) => (
+                StatusCode::UNAUTHORIZED,
+                Json(
+                    ClientError::from(ClientErrorCode::UnauthorizedClient)
+                        .with_description(e.to_string()),
+                ),
+            ),
             Self::DeniedByPolicy(violations) => (
                 StatusCode::FORBIDDEN,
                 Json(
@@ -157,10 +201,25 @@ impl IntoResponse for RouteError {
                     ),
                 ),
             ),
+            Self::ScopeNotGranted => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidScope).with_description(
+                        "The requested scope exceeds the scope originally granted".to_owned(),
+                    ),
+                ),
+            ),
             Self::DeviceCodeRejected => (
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::AccessDenied)),
             ),
+            Self::SessionLimitExceeded => (
+                StatusCode::FORBIDDEN,
+                Json(ClientError::new(
+                    ClientErrorCode::AccessDenied,
+                    "Too many active sessions",
+                )),
+            ),
             Self::DeviceCodeExpired => (
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::ExpiredToken)),
@@ -179,6 +238,21 @@ impl IntoResponse for RouteError {
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::InvalidGrant)),
             ),
+            Self::DeviceProof(err) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidGrant)
+                        .with_description(format!("Device proof verification failed: {err}")),
+                ),
+            ),
+            Self::CodeBinding(_) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidGrant).with_description(
+                        "The authorization code was requested from a different context".to_owned(),
+                    ),
+                ),
+            ),
             Self::UnsupportedGrantType => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::UnsupportedGrantType)),
@@ -193,6 +267,15 @@ impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(super::IdTokenSignatureError);
 
+impl From<SessionLimitError> for RouteError {
+    fn from(e: SessionLimitError) -> Self {
+        match e {
+            SessionLimitError::Exceeded => Self::SessionLimitExceeded,
+            SessionLimitError::Repository(e) => Self::Internal(Box::new(e)),
+        }
+    }
+}
+
 #[tracing::instrument(
     name = "handlers.oauth2.token.post",
     fields(client.id = client_authorization.client_id()),
@@ -212,6 +295,8 @@ pub(crate) async fn post(
     State(encrypter): State<Encrypter>,
     policy: Policy,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    device_proof: DeviceProof,
+    (State(attestation_checker), attestation): (State<AttestationChecker>, ClientAttestation),
     client_authorization: ClientAuthorization<AccessTokenRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
@@ -228,11 +313,38 @@ pub(crate) async fn post(
 
     client_authorization
         .credentials
-        .verify(&http_client, &encrypter, method, &client)
+        .verify(
+            &http_client,
+            &encrypter,
+            method,
+            &client,
+            &TimeOptions::new(clock.now()).leeway(site_config.jwt_leeway),
+        )
         .await?;
 
     let form = client_authorization.form.ok_or(RouteError::BadRequest)?;
 
+    // Public mobile clients may have to prove they are a genuine application
+    // when exchanging an authorization code
+    if let AccessTokenRequest::AuthorizationCode(grant) = &form {
+        if client.application_type == Some(ApplicationType::Native)
+            && *method == OAuthClientAuthenticationMethod::None
+        {
+            // The attestation is bound to the code verifier, or to the code itself
+            // if the client doesn't use PKCE
+            let binding = grant.code_verifier.as_deref().unwrap_or(&grant.code);
+            attestation_checker
+                .check(
+                    &attestation,
+                    AttestationEvent::TokenIssuance,
+                    Some(&client.client_id),
+                    request_hash(binding.as_bytes()),
+                )
+                .await
+                .map_err(RouteError::AttestationRejected)?;
+        }
+    }
+
     let (reply, repo) = match form {
         AccessTokenRequest::AuthorizationCode(grant) => {
             authorization_code_grant(
@@ -247,6 +359,7 @@ pub(crate) async fn post(
                 repo,
                 &homeserver,
                 user_agent,
+                &device_proof,
             )
             .await?
         }
@@ -260,6 +373,7 @@ pub(crate) async fn post(
                 &site_config,
                 repo,
                 user_agent,
+                &device_proof,
             )
             .await?
         }
@@ -290,6 +404,7 @@ pub(crate) async fn post(
                 repo,
                 &homeserver,
                 user_agent,
+                &device_proof,
             )
             .await?
         }
@@ -307,6 +422,68 @@ pub(crate) async fn post(
     Ok((headers, Json(reply)))
 }
 
+/// Whether the refresh token issuance policy allows issuing a refresh token
+/// for a session with the given scope
+///
+/// If the server requires it for this client, the `offline_access` scope must
+/// have been granted.
+fn offline_access_allowed(site_config: &SiteConfig, client: &Client, scope: &Scope) -> bool {
+    !site_config.offline_access_required_for(client.id) || scope.contains(&scope::OFFLINE_ACCESS)
+}
+
+/// End a session whose tokens may have leaked, and revoke its devices on the
+/// homeserver right away, so that it stops accepting its access tokens without
+/// waiting for its introspection cache to expire
+async fn end_compromised_session(
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    session: Session,
+) -> Result<(), RouteError> {
+    if let Some(user_id) = session.user_id {
+        for scope in &*session.scope {
+            if let Some(device) = Device::from_scope_token(scope) {
+                repo.job()
+                    .schedule_job(RevokeDeviceJob::new(user_id, &device))
+                    .await?;
+            }
+        }
+    }
+
+    repo.oauth2_session().finish(clock, session).await?;
+
+    Ok(())
+}
+
+/// Bind the session to the device key sent in the device proof, if any
+///
+/// Sessions which are already bound to a key are left untouched.
+async fn bind_device_key(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    session: &mas_data_model::Session,
+    device_proof: &DeviceProof,
+) -> Result<(), RouteError> {
+    let Some(public_key) = device_proof.verify_new_key(clock.now())? else {
+        return Ok(());
+    };
+
+    if repo
+        .device_key()
+        .find_for_oauth2_session(session)
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    repo.device_key()
+        .add_for_oauth2_session(rng, clock, session, public_key)
+        .await?;
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)] // TODO: refactor some parts out
 async fn authorization_code_grant(
     mut rng: &mut BoxRng,
@@ -320,6 +497,7 @@ async fn authorization_code_grant(
     mut repo: BoxRepository,
     homeserver: &BoxHomeserverConnection,
     user_agent: Option<UserAgent>,
+    device_proof: &DeviceProof,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::AuthorizationCode) {
@@ -354,7 +532,7 @@ async fn authorization_code_grant(
                     .lookup(session_id)
                     .await?
                     .ok_or(RouteError::NoSuchOAuthSession)?;
-                repo.oauth2_session().finish(clock, session).await?;
+                end_compromised_session(clock, &mut repo, session).await?;
                 repo.save().await?;
             }
 
@@ -377,6 +555,31 @@ async fn authorization_code_grant(
         }
     };
 
+    // Check that the code is redeemed from a context similar to the one it was
+    // requested in, if the client is configured to do so
+    let binding = site_config.code_binding_for(client);
+    if binding != AuthorizationCodeBinding::Off {
+        let requested = repo
+            .oauth2_authorization_grant()
+            .get_fingerprint(&authz_grant)
+            .await?;
+
+        if let Some(requested) = requested {
+            let redeemed = ClientFingerprint::new(activity_tracker.ip(), user_agent.as_deref());
+            if let Err(mismatch) = binding.check(&requested, &redeemed) {
+                tracing::warn!(
+                    audit.event = "authorization_code.binding_rejected",
+                    client.id = %client.id,
+                    grant.id = %authz_grant.id,
+                    network_changed = mismatch.ip_network,
+                    user_agent_changed = mismatch.user_agent,
+                    "Rejected an authorization code redeemed from a different context",
+                );
+                return Err(mismatch.into());
+            }
+        }
+    }
+
     let mut session = repo
         .oauth2_session()
         .lookup(session_id)
@@ -390,6 +593,15 @@ async fn authorization_code_grant(
             .await?;
     }
 
+    tracing::info!(
+        audit.event = "oauth2_session.code_exchanged",
+        %session.id,
+        %session.client_id,
+        user_agent.device_type = ?session.user_agent.as_ref().map(|ua| &ua.device_type),
+        user_agent.client_app = ?session.user_agent.as_ref().map(|ua| ua.client_app),
+        "Exchanged an authorization code"
+    );
+
     // This should never happen, since we looked up in the database using the code
     let code = authz_grant.code.as_ref().ok_or(RouteError::InvalidGrant)?;
 
@@ -407,6 +619,8 @@ async fn authorization_code_grant(
         }
     };
 
+    bind_device_key(rng, clock, &mut repo, &session, device_proof).await?;
+
     let Some(user_session_id) = session.user_session_id else {
         tracing::warn!("No user session associated with this OAuth2 session");
         return Err(RouteError::InvalidGrant);
@@ -418,14 +632,28 @@ async fn authorization_code_grant(
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
-    let last_authentication = repo
+    let authentications = repo
         .browser_session()
-        .get_last_authentication(&browser_session)
+        .list_authentications(&browser_session)
         .await?;
 
     let ttl = site_config.access_token_ttl;
-    let (access_token, refresh_token) =
-        generate_token_pair(&mut rng, clock, &mut repo, &session, ttl).await?;
+    let access_token_str = TokenType::AccessToken.generate(&mut rng);
+    let access_token = repo
+        .oauth2_access_token()
+        .add(&mut rng, clock, &session, access_token_str, Some(ttl))
+        .await?;
+
+    let refresh_token = if offline_access_allowed(site_config, client, &session.scope) {
+        let refresh_token_str = TokenType::RefreshToken.generate(&mut rng);
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .add(&mut rng, clock, &session, &access_token, refresh_token_str)
+            .await?;
+        Some(refresh_token)
+    } else {
+        None
+    };
 
     let id_token = if session.scope.contains(&scope::OPENID) {
         Some(generate_id_token(
@@ -437,7 +665,7 @@ async fn authorization_code_grant(
             Some(&authz_grant),
             &browser_session,
             Some(&access_token),
-            last_authentication.as_ref(),
+            &authentications,
         )?)
     } else {
         None
@@ -445,9 +673,12 @@ async fn authorization_code_grant(
 
     let mut params = AccessTokenResponse::new(access_token.access_token)
         .with_expires_in(ttl)
-        .with_refresh_token(refresh_token.refresh_token)
         .with_scope(session.scope.clone());
 
+    if let Some(refresh_token) = refresh_token {
+        params = params.with_refresh_token(refresh_token.refresh_token);
+    }
+
     if let Some(id_token) = id_token {
         params = params.with_id_token(id_token);
     }
@@ -482,6 +713,7 @@ async fn authorization_code_grant(
     Ok((params, repo))
 }
 
+#[allow(clippy::too_many_lines)]
 async fn refresh_token_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
@@ -491,6 +723,7 @@ async fn refresh_token_grant(
     site_config: &SiteConfig,
     mut repo: BoxRepository,
     user_agent: Option<UserAgent>,
+    device_proof: &DeviceProof,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::RefreshToken) {
@@ -509,6 +742,16 @@ async fn refresh_token_grant(
         .await?
         .ok_or(RouteError::NoSuchOAuthSession)?;
 
+    // Check the client before anything else, so that another client can't end the
+    // session by replaying one of its consumed refresh tokens
+    if client.id != session.client_id {
+        // As per https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
+        return Err(RouteError::ClientIDMismatch {
+            expected: session.client_id,
+            actual: client.id,
+        });
+    }
+
     // Let's for now record the user agent on each refresh, that should be
     // responsive enough and not too much of a burden on the database.
     if let Some(user_agent) = user_agent {
@@ -518,7 +761,19 @@ async fn refresh_token_grant(
             .await?;
     }
 
-    if !refresh_token.is_valid() {
+    if let RefreshTokenState::Consumed { consumed_at } = refresh_token.state {
+        // A refresh token presented again after it was rotated is a strong sign
+        // that it leaked, so end the session after the grace period
+        if session.is_valid() && clock.now() - consumed_at > CONSUMED_REFRESH_TOKEN_GRACE_PERIOD {
+            tracing::warn!(
+                oauth2_session.id = %session.id,
+                oauth2_refresh_token.id = %refresh_token.id,
+                "Consumed refresh token was used again, ending potentially compromised session"
+            );
+            end_compromised_session(clock, &mut repo, session).await?;
+            repo.save().await?;
+        }
+
         return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
     }
 
@@ -526,21 +781,61 @@ async fn refresh_token_grant(
         return Err(RouteError::SessionInvalid(session.id));
     }
 
-    if client.id != session.client_id {
-        // As per https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
-        return Err(RouteError::ClientIDMismatch {
-            expected: session.client_id,
-            actual: client.id,
-        });
+    // If the session is bound to a device key, the client must prove it still
+    // holds it
+    if let Some(device_key) = repo.device_key().find_for_oauth2_session(&session).await? {
+        device_proof.verify_bound_key(clock.now(), &device_key.public_key)?;
+    }
+
+    // The client may ask for a narrower scope, which then replaces the scope of
+    // the session, as per https://datatracker.ietf.org/doc/html/rfc6749#section-6
+    if let Some(scope) = &grant.scope {
+        if !scope.is_subset(&session.scope) {
+            return Err(RouteError::ScopeNotGranted);
+        }
+
+        if *scope != session.scope {
+            // Remove the devices which are no longer in the scope from the homeserver
+            if let Some(user_id) = session.user_id {
+                for token in &*session.scope {
+                    if scope.contains(token) {
+                        continue;
+                    }
+
+                    if let Some(device) = Device::from_scope_token(token) {
+                        repo.job()
+                            .schedule_job(RevokeDeviceJob::new(user_id, &device))
+                            .await?;
+                    }
+                }
+            }
+
+            session = repo
+                .oauth2_session()
+                .set_scope(session, scope.clone())
+                .await?;
+        }
     }
 
     activity_tracker
         .record_oauth2_session(clock, &session)
         .await;
 
+    // The narrowed scope may not allow issuing a new refresh token anymore
     let ttl = site_config.access_token_ttl;
     let (new_access_token, new_refresh_token) =
-        generate_token_pair(rng, clock, &mut repo, &session, ttl).await?;
+        if offline_access_allowed(site_config, client, &session.scope) {
+            let (access_token, refresh_token) =
+                generate_token_pair(rng, clock, &mut repo, &session, ttl).await?;
+            (access_token, Some(refresh_token))
+        } else {
+            let access_token_str = TokenType::AccessToken.generate(rng);
+            let access_token = repo
+                .oauth2_access_token()
+                .add(rng, clock, &session, access_token_str, Some(ttl))
+                .await?;
+            (access_token, None)
+        };
 
     let refresh_token = repo
         .oauth2_refresh_token()
@@ -556,11 +851,14 @@ async fn refresh_token_grant(
         }
     }
 
-    let params = AccessTokenResponse::new(new_access_token.access_token)
+    let mut params = AccessTokenResponse::new(new_access_token.access_token)
         .with_expires_in(ttl)
-        .with_refresh_token(new_refresh_token.refresh_token)
         .with_scope(session.scope);
 
+    if let Some(new_refresh_token) = new_refresh_token {
+        params = params.with_refresh_token(new_refresh_token.refresh_token);
+    }
+
     Ok((params, repo))
 }
 
@@ -588,7 +886,7 @@ async fn client_credentials_grant(
 
     // Make the request go through the policy engine
     let res = policy
-        .evaluate_client_credentials_grant(&scope, client)
+        .evaluate_client_credentials_grant(&scope, client, TimeInput::new(clock.now(), None))
         .await?;
     if !res.valid() {
         return Err(RouteError::DeniedByPolicy(res.violations));
@@ -600,6 +898,8 @@ async fn client_credentials_grant(
         .add_from_client_credentials(rng, clock, client, scope)
         .await?;
 
+    policy.audit_grant(&session.scope, client, None);
+
     if let Some(user_agent) = user_agent {
         session = repo
             .oauth2_session()
@@ -632,6 +932,7 @@ async fn client_credentials_grant(
     Ok((params, repo))
 }
 
+#[allow(clippy::too_many_lines)]
 async fn device_code_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
@@ -644,6 +945,7 @@ async fn device_code_grant(
     mut repo: BoxRepository,
     homeserver: &BoxHomeserverConnection,
     user_agent: Option<UserAgent>,
+    device_proof: &DeviceProof,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::DeviceCode) {
@@ -689,12 +991,36 @@ async fn device_code_grant(
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
+    // Make room for the new session, if the user has too many of them
+    session_limits::enforce(
+        &mut repo,
+        clock,
+        &site_config.session_limits,
+        &browser_session.user,
+        Some(client),
+    )
+    .await?;
+
+    // Pick the device of the session, if it asks for one
+    let scope = device_allocation::allocate(
+        rng,
+        clock,
+        &mut repo,
+        site_config,
+        client,
+        &browser_session.user,
+        grant.scope,
+    )
+    .await?;
+
     // Start the session
     let mut session = repo
         .oauth2_session()
-        .add_from_browser_session(rng, clock, client, &browser_session, grant.scope)
+        .add_from_browser_session(rng, clock, client, &browser_session, scope)
         .await?;
 
+    bind_device_key(rng, clock, &mut repo, &session, device_proof).await?;
+
     // XXX: should we get the user agent from the device code grant instead?
     if let Some(user_agent) = user_agent {
         session = repo
@@ -715,8 +1041,10 @@ async fn device_code_grant(
         AccessTokenResponse::new(access_token.access_token.clone()).with_expires_in(ttl);
 
     // If the client uses the refresh token grant type, we also generate a refresh
-    // token
-    if client.grant_types.contains(&GrantType::RefreshToken) {
+    // token, as long as the refresh token issuance policy allows it
+    if client.grant_types.contains(&GrantType::RefreshToken)
+        && offline_access_allowed(site_config, client, &session.scope)
+    {
         let refresh_token_str = TokenType::RefreshToken.generate(rng);
 
         let refresh_token = repo
@@ -729,6 +1057,11 @@ async fn device_code_grant(
 
     // If the client asked for an ID token, we generate one
     if session.scope.contains(&scope::OPENID) {
+        let authentications = repo
+            .browser_session()
+            .list_authentications(&browser_session)
+            .await?;
+
         let id_token = generate_id_token(
             rng,
             clock,
@@ -738,7 +1071,7 @@ async fn device_code_grant(
             None,
             &browser_session,
             Some(&access_token),
-            None,
+            &authentications,
         )?;
 
         params = params.with_id_token(id_token);
@@ -777,18 +1110,21 @@ async fn device_code_grant(
 
 #[cfg(test)]
 mod tests {
-    use hyper::Request;
+    use hyper::{header::USER_AGENT, Request};
     use mas_data_model::{AccessToken, AuthorizationCode, RefreshToken};
     use mas_router::SimpleRoute;
     use oauth2_types::{
         registration::ClientRegistrationResponse,
         requests::{DeviceAuthorizationResponse, ResponseMode},
-        scope::{Scope, OPENID},
+        scope::{Scope, EMAIL, OPENID},
     };
     use sqlx::PgPool;
 
     use super::*;
-    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+    use crate::{
+        device_proof::test_utils::DeviceKeyPair,
+        test_utils::{setup, test_site_config, RequestBuilderExt, ResponseExt, TestState},
+    };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_auth_code_grant(pool: PgPool) {
@@ -1001,6 +1337,150 @@ mod tests {
         assert_eq!(error, ClientErrorCode::InvalidGrant);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_auth_code_grant_binding(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Bind the codes of this client strictly
+        state
+            .site_config
+            .code_binding_overrides
+            .insert(client.id, AuthorizationCodeBinding::Strict);
+
+        // Start a grant, requested from a specific user agent
+        let code = "thisisaverysecurecode";
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                "https://example.com/redirect".parse().unwrap(),
+                Scope::from_iter([OPENID]),
+                Some(AuthorizationCode {
+                    code: code.to_owned(),
+                    pkce: None,
+                }),
+                Some("state".to_owned()),
+                Some("nonce".to_owned()),
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.oauth2_authorization_grant()
+            .set_fingerprint(&grant, &ClientFingerprint::new(None, Some("Requester/1.0")))
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                grant.scope.clone(),
+            )
+            .await
+            .unwrap();
+
+        let grant = repo
+            .oauth2_authorization_grant()
+            .fulfill(&state.clock, &session, grant)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Redeeming the code from another user agent fails
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header(USER_AGENT, "Attacker/1.0")
+            .form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": grant.redirect_uri,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidGrant);
+        assert!(error.error_description.is_some());
+
+        // Leaving out the user agent doesn't get around the binding
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": grant.redirect_uri,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidGrant);
+
+        // Redeeming it from the same user agent works
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header(USER_AGENT, "Requester/1.0")
+            .form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": grant.redirect_uri,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let AccessTokenResponse { access_token, .. } = response.json();
+        assert!(state.is_access_token_valid(&access_token).await);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_refresh_token_grant(pool: PgPool) {
         setup();
@@ -1120,19 +1600,620 @@ mod tests {
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
-        let _: AccessTokenResponse = response.json();
-    }
-
-    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_client_credentials(pool: PgPool) {
-        setup();
-        let state = TestState::from_pool(pool).await.unwrap();
+        let response: AccessTokenResponse = response.json();
+        let access_token = response.access_token;
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
 
-        // Provision a client
+        // Using a consumed refresh token long after it was consumed means it
+        // leaked, which ends the session
+        state.clock.advance(Duration::try_minutes(1).unwrap());
         let request =
-            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
-                "client_uri": "https://example.com/",
-                "token_endpoint_auth_method": "client_secret_post",
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": old_refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        assert!(!state.is_access_token_valid(&access_token).await);
+
+        // So the latest refresh token can't be used anymore
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_reuse_from_other_client(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision two clients
+        let mut client_ids = Vec::new();
+        for _ in 0..2 {
+            let request = Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(
+                serde_json::json!({
+                    "client_uri": "https://example.com/",
+                    "redirect_uris": ["https://example.com/callback"],
+                    "token_endpoint_auth_method": "none",
+                    "response_types": ["code"],
+                    "grant_types": ["authorization_code", "refresh_token"],
+                }),
+            );
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::CREATED);
+
+            let ClientRegistrationResponse { client_id, .. } = response.json();
+            client_ids.push(client_id);
+        }
+        let [client_id, other_client_id] = client_ids.try_into().unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Consume the refresh token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let old_refresh_token = refresh_token;
+        let response: AccessTokenResponse = response.json();
+        let access_token = response.access_token;
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+
+        // Another client replays the consumed refresh token after the grace period
+        state.clock.advance(Duration::try_minutes(1).unwrap());
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": old_refresh_token,
+                "client_id": other_client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // The session of the first client is still valid
+        assert!(state.is_access_token_valid(&access_token).await);
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_down_scoping(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID, EMAIL]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Asking for a scope which wasn't granted should fail
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": "openid email profile",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // Asking for a narrower scope should work
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": "openid",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+
+        // The session scope should have been narrowed down
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.scope, Scope::from_iter([OPENID]));
+        repo.cancel().await.unwrap();
+
+        // So asking back for the original scope should fail
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": "openid email",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_down_scoping_offline_access(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                offline_access_required: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID, scope::OFFLINE_ACCESS]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Narrowing the scope down to drop offline_access gives an access token
+        // without a refresh token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": "openid",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        assert!(response.refresh_token.is_none());
+        assert!(state.is_access_token_valid(&response.access_token).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_down_scoping_device(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let device = Device::generate(&mut state.rng());
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID, device.to_scope_token()]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Narrowing the scope down to drop the device scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": "openid",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+
+        // It should have scheduled the removal of the device from the homeserver
+        // XXX: we don't have a good way to look for the job
+        let job: sqlx::types::Json<serde_json::Value> =
+            sqlx::query_scalar("SELECT job FROM apalis.jobs WHERE job_type = 'revoke-device'")
+                .fetch_one(&state.pool)
+                .await
+                .expect("Device revocation job to be scheduled");
+        assert_eq!(job["user_id"], serde_json::json!(user.id));
+        assert_eq!(job["device_id"], serde_json::json!(device.as_str()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_device_bound(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        // Bind the session to a key generated on the device
+        let device_key = DeviceKeyPair::generate(&mut state.rng());
+        repo.device_key()
+            .add_for_oauth2_session(
+                &mut state.rng(),
+                &state.clock,
+                &session,
+                device_key.public_key(),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        let token_url = format!(
+            "https://example.com{}",
+            mas_router::OAuth2TokenEndpoint::PATH
+        );
+
+        // Refreshing without a proof should fail
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // So should refreshing with a proof signed by another key
+        let other_key = DeviceKeyPair::generate(&mut state.rng());
+        let proof = other_key.proof(state.clock.now(), "POST", &token_url, false);
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("MAS-Device-Proof", proof)
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Or with a proof for another endpoint
+        let proof = device_key.proof(
+            state.clock.now(),
+            "POST",
+            "https://example.com/oauth2/revoke",
+            false,
+        );
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("MAS-Device-Proof", proof)
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // A proof signed by the device key should work
+        let proof = device_key.proof(state.clock.now(), "POST", &token_url, false);
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("MAS-Device-Proof", &proof)
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let AccessTokenResponse { refresh_token, .. } = response.json();
+
+        // But the same proof can't be used twice
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("MAS-Device-Proof", &proof)
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_credentials(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "client_secret_post",
                 "grant_types": ["client_credentials"],
             }));
 
@@ -1247,6 +2328,36 @@ mod tests {
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
+
+        // Restricting the admin scope to another client denies it again
+        let state = {
+            let mut state = state;
+            state.policy_factory = crate::test_utils::policy_factory(serde_json::json!({
+                "admin_clients": [client_id],
+                "restricted_scopes": {
+                    "urn:mas:admin": {
+                        "clients": ["another-client"],
+                    },
+                },
+            }))
+            .await
+            .unwrap();
+            state
+        };
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:admin"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -1434,6 +2545,86 @@ mod tests {
         assert_eq!(error, ClientErrorCode::AccessDenied);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_offline_access_required(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                offline_access_required: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "none",
+                "grant_types": ["urn:ietf:params:oauth:grant-type:device_code", "refresh_token"],
+                "response_types": [],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        for (scope, expect_refresh_token) in [("openid", false), ("openid offline_access", true)] {
+            let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+                serde_json::json!({
+                    "client_id": client_id,
+                    "scope": scope,
+                }),
+            );
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+            let device_grant: DeviceAuthorizationResponse = response.json();
+
+            let mut repo = state.repository().await.unwrap();
+            let grant = repo
+                .oauth2_device_code_grant()
+                .find_by_user_code(&device_grant.user_code)
+                .await
+                .unwrap()
+                .unwrap();
+            repo.oauth2_device_code_grant()
+                .fulfill(&state.clock, grant, &browser_session)
+                .await
+                .unwrap();
+            repo.save().await.unwrap();
+
+            let request =
+                Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                    "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                    "device_code": device_grant.device_code,
+                    "client_id": client_id,
+                }));
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+
+            let response: AccessTokenResponse = response.json();
+            assert!(state.is_access_token_valid(&response.access_token).await);
+            assert_eq!(response.refresh_token.is_some(), expect_refresh_token);
+        }
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_grant(pool: PgPool) {
         setup();
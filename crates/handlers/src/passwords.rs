@@ -4,14 +4,20 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use chrono::{DateTime, Utc};
 use futures_util::future::OptionFuture;
+use mas_http::RequestBuilderExt as _;
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
 use pbkdf2::Pbkdf2;
 use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
 use zeroize::Zeroizing;
 use zxcvbn::zxcvbn;
 
@@ -21,9 +27,259 @@ pub type SchemeVersion = u16;
 #[error("Password manager is disabled")]
 pub struct PasswordManagerDisabledError;
 
+/// Returned when too many passwords are already being hashed, and none of them
+/// finished in time
+#[derive(Debug, Error)]
+#[error("Too many passwords are being hashed, try again later")]
+pub struct PasswordHashingBusyError;
+
 #[derive(Clone)]
 pub struct PasswordManager {
     inner: Option<Arc<InnerPasswordManager>>,
+    validation_webhook: Option<Arc<PasswordValidationWebhook>>,
+    policy_classes: Option<Arc<PasswordPolicyClasses>>,
+    hashing_limiter: Option<Arc<HashingLimiter>>,
+}
+
+/// Bounds how many passwords are hashed or verified at the same time, so that
+/// a login storm doesn't exhaust the blocking thread pool
+struct HashingLimiter {
+    semaphore: Arc<Semaphore>,
+    queue_timeout: Duration,
+    queue_time: Histogram<u64>,
+    rejected: Counter<u64>,
+    active: UpDownCounter<i64>,
+}
+
+impl HashingLimiter {
+    fn new(max_concurrency: usize, queue_timeout: Duration) -> Self {
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+
+        let queue_time = meter
+            .u64_histogram("mas.passwords.hashing.queue_time")
+            .with_description("How long password hashes waited for a free slot")
+            .with_unit("ms")
+            .init();
+
+        let rejected = meter
+            .u64_counter("mas.passwords.hashing.rejected")
+            .with_description(
+                "The number of password hashes rejected because no slot freed up in time",
+            )
+            .with_unit("{hashes}")
+            .init();
+        rejected.add(0, &[]);
+
+        let active = meter
+            .i64_up_down_counter("mas.passwords.hashing.active")
+            .with_description("The number of passwords currently being hashed or verified")
+            .with_unit("{hashes}")
+            .init();
+
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrency)),
+            queue_timeout,
+            queue_time,
+            rejected,
+            active,
+        }
+    }
+
+    /// Wait for a free slot, up to the queue timeout
+    async fn acquire(&self) -> Result<HashingPermit, PasswordHashingBusyError> {
+        let start = std::time::Instant::now();
+        let permit =
+            tokio::time::timeout(self.queue_timeout, self.semaphore.clone().acquire_owned()).await;
+        let queue_time = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.queue_time.record(queue_time, &[]);
+
+        // The semaphore is never closed, so this only fails on timeouts
+        let Ok(Ok(permit)) = permit else {
+            self.rejected.add(1, &[]);
+            return Err(PasswordHashingBusyError);
+        };
+
+        self.active.add(1, &[]);
+        Ok(HashingPermit {
+            _permit: permit,
+            active: self.active.clone(),
+        })
+    }
+}
+
+/// A slot to hash a password, released when dropped
+struct HashingPermit {
+    _permit: OwnedSemaphorePermit,
+    active: UpDownCounter<i64>,
+}
+
+impl Drop for HashingPermit {
+    fn drop(&mut self) {
+        self.active.add(-1, &[]);
+    }
+}
+
+/// Rules applying to the passwords of a class of users
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Minimum complexity score of new passwords (between 0 and 4) as
+    /// evaluated by zxcvbn
+    pub minimum_complexity: u8,
+
+    /// Minimum number of characters of new passwords
+    pub minimum_length: usize,
+
+    /// How long a password can be used before it has to be changed, if it
+    /// expires
+    pub max_age: Option<Duration>,
+}
+
+struct PasswordPolicyClasses {
+    /// How long the passwords of users without a class can be used
+    default_max_age: Option<Duration>,
+
+    /// The policies of the classes which can be assigned to users, by name
+    classes: HashMap<String, PasswordPolicy>,
+}
+
+/// The outcome of the validation of a new password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordValidation {
+    /// The password can be used
+    Valid,
+
+    /// The password is not complex enough
+    TooWeak,
+
+    /// The password is shorter than what the policy of the user requires
+    TooShort {
+        /// The minimum number of characters
+        minimum_length: usize,
+    },
+
+    /// The password was rejected by the validation webhook
+    Rejected {
+        /// The reason given by the webhook, in the language of the user
+        reason: Option<String>,
+    },
+}
+
+impl PasswordValidation {
+    /// Whether the password can be used
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        matches!(self, Self::Valid)
+    }
+
+    /// A message explaining why the password can't be used, to show to the
+    /// user
+    #[must_use]
+    pub fn message(&self) -> Option<String> {
+        match self {
+            Self::Valid => None,
+            // TODO localise this error
+            Self::TooWeak => Some("Password is too weak".to_owned()),
+            Self::TooShort { minimum_length } => Some(format!(
+                "Password must be at least {minimum_length} characters long"
+            )),
+            Self::Rejected { reason } => {
+                Some(reason.clone().unwrap_or_else(|| {
+                    "Password does not comply with the password policy".to_owned()
+                }))
+            }
+        }
+    }
+}
+
+/// An external webhook validating new passwords
+pub struct PasswordValidationWebhook {
+    client: reqwest::Client,
+    url: Url,
+    timeout: Duration,
+    fail_open: bool,
+}
+
+#[derive(Serialize)]
+struct PasswordValidationRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+    locale: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct PasswordValidationResponse {
+    allowed: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl PasswordValidationWebhook {
+    /// Create a new validation webhook
+    ///
+    /// If `fail_open` is true, passwords are accepted when the webhook fails
+    /// or does not answer within the `timeout`, else they are rejected.
+    #[must_use]
+    pub fn new(client: reqwest::Client, url: Url, timeout: Duration, fail_open: bool) -> Self {
+        Self {
+            client,
+            url,
+            timeout,
+            fail_open,
+        }
+    }
+
+    #[tracing::instrument(name = "passwords.validation_webhook", skip_all, fields(url = %self.url))]
+    async fn validate(
+        &self,
+        username: &str,
+        password: &str,
+        locale: Option<&str>,
+    ) -> PasswordValidation {
+        let request = PasswordValidationRequest {
+            username,
+            password,
+            locale,
+        };
+
+        let response: Result<PasswordValidationResponse, reqwest::Error> = async {
+            self.client
+                .post(self.url.clone())
+                .timeout(self.timeout)
+                .json(&request)
+                .send_traced()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        }
+        .await;
+
+        match response {
+            Ok(response) if response.allowed => PasswordValidation::Valid,
+            Ok(response) => PasswordValidation::Rejected {
+                reason: response.reason,
+            },
+            Err(err) if self.fail_open => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "Password validation webhook failed, accepting the password"
+                );
+                PasswordValidation::Valid
+            }
+            Err(err) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "Password validation webhook failed, rejecting the password"
+                );
+                PasswordValidation::Rejected { reason: None }
+            }
+        }
+    }
 }
 
 struct InnerPasswordManager {
@@ -66,13 +322,137 @@ impl PasswordManager {
                 current_version,
                 other_hashers,
             })),
+            validation_webhook: None,
+            policy_classes: None,
+            hashing_limiter: None,
         })
     }
 
     /// Creates a new disabled password manager
     #[must_use]
     pub const fn disabled() -> Self {
-        Self { inner: None }
+        Self {
+            inner: None,
+            validation_webhook: None,
+            policy_classes: None,
+            hashing_limiter: None,
+        }
+    }
+
+    /// Validate the new passwords with an external webhook, on top of the
+    /// complexity check
+    #[must_use]
+    pub fn with_validation_webhook(mut self, webhook: PasswordValidationWebhook) -> Self {
+        self.validation_webhook = Some(Arc::new(webhook));
+        self
+    }
+
+    /// Set how long the passwords of users without a password policy class
+    /// can be used, and the classes of password rules which can be assigned
+    /// to users
+    #[must_use]
+    pub fn with_policy_classes(
+        mut self,
+        default_max_age: Option<Duration>,
+        classes: HashMap<String, PasswordPolicy>,
+    ) -> Self {
+        self.policy_classes = Some(Arc::new(PasswordPolicyClasses {
+            default_max_age,
+            classes,
+        }));
+        self
+    }
+
+    /// Limit how many passwords are hashed or verified at the same time.
+    /// Operations waiting longer than `queue_timeout` for a free slot fail
+    /// with a [`PasswordHashingBusyError`].
+    #[must_use]
+    pub fn with_concurrency_limit(
+        mut self,
+        max_concurrency: usize,
+        queue_timeout: Duration,
+    ) -> Self {
+        self.hashing_limiter = Some(Arc::new(HashingLimiter::new(
+            max_concurrency,
+            queue_timeout,
+        )));
+        self
+    }
+
+    /// Wait for a slot to hash a password, if the concurrency is limited
+    async fn acquire_hashing_permit(
+        &self,
+    ) -> Result<Option<HashingPermit>, PasswordHashingBusyError> {
+        let Some(limiter) = &self.hashing_limiter else {
+            return Ok(None);
+        };
+
+        limiter.acquire().await.map(Some)
+    }
+
+    /// Whether a password policy class with the given name exists
+    #[must_use]
+    pub fn has_policy_class(&self, name: &str) -> bool {
+        self.policy_classes
+            .as_ref()
+            .is_some_and(|policies| policies.classes.contains_key(name))
+    }
+
+    /// Get the rules applying to the passwords of users of the given class.
+    ///
+    /// Users without a class, or with a class which is not configured anymore,
+    /// get the default rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password manager is disabled
+    pub fn policy(
+        &self,
+        policy_class: Option<&str>,
+    ) -> Result<PasswordPolicy, PasswordManagerDisabledError> {
+        let inner = self.get_inner()?;
+        let policies = self.policy_classes.as_deref();
+
+        if let Some(name) = policy_class {
+            if let Some(policy) = policies.and_then(|policies| policies.classes.get(name)) {
+                return Ok(*policy);
+            }
+
+            tracing::warn!(
+                policy_class = name,
+                "Unknown password policy class, applying the default policy"
+            );
+        }
+
+        Ok(PasswordPolicy {
+            minimum_complexity: inner.minimum_complexity,
+            minimum_length: 0,
+            max_age: policies.and_then(|policies| policies.default_max_age),
+        })
+    }
+
+    /// Whether a password set at `created_at` has expired, according to the
+    /// policy of the given class
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password manager is disabled
+    pub fn is_password_expired(
+        &self,
+        policy_class: Option<&str>,
+        created_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, PasswordManagerDisabledError> {
+        let Some(max_age) = self.policy(policy_class)?.max_age else {
+            return Ok(false);
+        };
+
+        // A max age too large to be represented never expires
+        let Ok(max_age) = chrono::Duration::from_std(max_age) else {
+            return Ok(false);
+        };
+
+        Ok(created_at + max_age <= now)
     }
 
     /// Checks if the password manager is enabled or not
@@ -102,6 +482,44 @@ impl PasswordManager {
         Ok(u8::from(score.score()) >= inner.minimum_complexity)
     }
 
+    /// Validate a new password for the given user: check its length and
+    /// complexity against the policy of the user's class, and ask the
+    /// validation webhook, if there is one.
+    ///
+    /// The `locale` is passed to the webhook, so that it can explain why the
+    /// password was rejected in the language of the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password manager is disabled
+    #[tracing::instrument(name = "passwords.validate", skip_all)]
+    pub async fn validate_new_password(
+        &self,
+        policy_class: Option<&str>,
+        username: &str,
+        password: &str,
+        locale: Option<&str>,
+    ) -> Result<PasswordValidation, anyhow::Error> {
+        let policy = self.policy(policy_class)?;
+
+        if password.chars().count() < policy.minimum_length {
+            return Ok(PasswordValidation::TooShort {
+                minimum_length: policy.minimum_length,
+            });
+        }
+
+        let score = zxcvbn(password, &[]);
+        if u8::from(score.score()) < policy.minimum_complexity {
+            return Ok(PasswordValidation::TooWeak);
+        }
+
+        let Some(webhook) = &self.validation_webhook else {
+            return Ok(PasswordValidation::Valid);
+        };
+
+        Ok(webhook.validate(username, password, locale).await)
+    }
+
     /// Hash a password with the default hashing scheme.
     /// Returns the version of the hashing scheme used and the hashed password.
     ///
@@ -126,7 +544,12 @@ impl PasswordManager {
         // first
         let version = inner.current_version;
 
+        // The permit is moved in the blocking task, so that it is held until the
+        // hash is done, even if this future is dropped
+        let permit = self.acquire_hashing_permit().await?;
+
         let hashed = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
             span.in_scope(move || inner.current_hasher.hash_blocking(rng, &password))
         })
         .await??;
@@ -134,6 +557,24 @@ impl PasswordManager {
         Ok((version, hashed))
     }
 
+    /// Measure how long hashing a password takes with the current hashing
+    /// scheme on this host
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hashing failed or if the password manager is
+    /// disabled
+    #[tracing::instrument(name = "passwords.benchmark", skip_all)]
+    pub async fn benchmark(&self) -> Result<Duration, anyhow::Error> {
+        let inner = self.get_inner()?;
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(move || inner.current_hasher.benchmark_blocking())
+        })
+        .await?
+    }
+
     /// Verify a password hash for the given hashing scheme.
     ///
     /// # Errors
@@ -149,8 +590,10 @@ impl PasswordManager {
     ) -> Result<(), anyhow::Error> {
         let inner = self.get_inner()?;
         let span = tracing::Span::current();
+        let permit = self.acquire_hashing_permit().await?;
 
         tokio::task::spawn_blocking(move || {
+            let _permit = permit;
             span.in_scope(move || {
                 let hasher = if scheme == inner.current_version {
                     &inner.current_hasher
@@ -220,7 +663,24 @@ impl Hasher {
     /// Creates a new hashing scheme based on the argon2id algorithm
     #[must_use]
     pub const fn argon2id(pepper: Option<Vec<u8>>) -> Self {
-        let algorithm = Algorithm::Argon2id;
+        Self::argon2id_with_cost(None, None, None, pepper)
+    }
+
+    /// Creates a new hashing scheme based on the argon2id algorithm, with
+    /// custom cost parameters. The parameters which are not set use the
+    /// defaults recommended by OWASP.
+    #[must_use]
+    pub const fn argon2id_with_cost(
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+        pepper: Option<Vec<u8>>,
+    ) -> Self {
+        let algorithm = Algorithm::Argon2id {
+            memory_cost,
+            time_cost,
+            parallelism,
+        };
         Self { algorithm, pepper }
     }
 
@@ -244,12 +704,33 @@ impl Hasher {
         self.algorithm
             .verify_blocking(hashed_password, password, self.pepper.as_deref())
     }
+
+    /// Measure how long hashing a password takes with this scheme on this
+    /// host.
+    ///
+    /// This blocks the current thread for the duration of the hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hashing failed, like with invalid parameters
+    pub fn benchmark_blocking(&self) -> Result<Duration, anyhow::Error> {
+        let rng = rand_chacha::ChaChaRng::from_entropy();
+        let start = std::time::Instant::now();
+        self.hash_blocking(rng, b"correct horse battery staple")?;
+        Ok(start.elapsed())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 enum Algorithm {
-    Bcrypt { cost: Option<u32> },
-    Argon2id,
+    Bcrypt {
+        cost: Option<u32>,
+    },
+    Argon2id {
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+    },
     Pbkdf2,
 }
 
@@ -273,10 +754,19 @@ impl Algorithm {
                 Ok(hashed.format_for_version(bcrypt::Version::TwoB))
             }
 
-            Self::Argon2id => {
+            Self::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
                 let algorithm = argon2::Algorithm::default();
                 let version = argon2::Version::default();
-                let params = argon2::Params::default();
+                let params = argon2::Params::new(
+                    memory_cost.unwrap_or(argon2::Params::DEFAULT_M_COST),
+                    time_cost.unwrap_or(argon2::Params::DEFAULT_T_COST),
+                    parallelism.unwrap_or(argon2::Params::DEFAULT_P_COST),
+                    None,
+                )?;
 
                 let phf = if let Some(secret) = pepper {
                     Argon2::new_with_secret(secret, algorithm, version, params)?
@@ -319,7 +809,9 @@ impl Algorithm {
                 anyhow::ensure!(result, "wrong password");
             }
 
-            Algorithm::Argon2id => {
+            // The parameters are read from the hash, so the ones of the scheme
+            // don't matter here
+            Algorithm::Argon2id { .. } => {
                 let algorithm = argon2::Algorithm::default();
                 let version = argon2::Version::default();
                 let params = argon2::Params::default();
@@ -396,7 +888,11 @@ mod tests {
         let pepper = b"a-secret-pepper";
         let pepper2 = b"the-wrong-pepper";
 
-        let alg = Algorithm::Argon2id;
+        let alg = Algorithm::Argon2id {
+            memory_cost: None,
+            time_cost: None,
+            parallelism: None,
+        };
         // Hash with a pepper
         let hash = alg
             .hash_blocking(&mut rng, password, Some(pepper))
@@ -419,6 +915,38 @@ mod tests {
         assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_err());
     }
 
+    #[test]
+    fn hashing_argon2id_with_cost() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let password = b"hunter2";
+
+        let alg = Algorithm::Argon2id {
+            memory_cost: Some(8 * 1024),
+            time_cost: Some(3),
+            parallelism: Some(2),
+        };
+        let hash = alg
+            .hash_blocking(&mut rng, password, None)
+            .expect("Couldn't hash password");
+        assert!(hash.starts_with("$argon2id$v=19$m=8192,t=3,p=2$"));
+
+        // Hashes are verified with their own parameters
+        let default = Algorithm::Argon2id {
+            memory_cost: None,
+            time_cost: None,
+            parallelism: None,
+        };
+        assert!(default.verify_blocking(&hash, password, None).is_ok());
+
+        // Invalid parameters are reported
+        let invalid = Algorithm::Argon2id {
+            memory_cost: None,
+            time_cost: Some(0),
+            parallelism: None,
+        };
+        assert!(invalid.hash_blocking(&mut rng, password, None).is_err());
+    }
+
     #[test]
     #[ignore = "this is particularly slow (20s+ seconds)"]
     fn hashing_pbkdf2() {
@@ -620,4 +1148,180 @@ mod tests {
             .await
             .expect_err("Verification should have failed");
     }
+
+    #[tokio::test]
+    async fn validation_webhook() {
+        use wiremock::{
+            matchers::{body_partial_json, method},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        crate::test_utils::setup();
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(serde_json::json!({
+                "username": "alice",
+                "password": "correct horse battery staple",
+                "locale": "fr",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "allowed": false,
+                "reason": "Le mot de passe contient un mot du dictionnaire",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(serde_json::json!({ "username": "bob" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "allowed": true,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                serde_json::json!({ "username": "carol" }),
+            ))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let manager = |fail_open| {
+            PasswordManager::new(1, [(1, Hasher::argon2id(None))])
+                .unwrap()
+                .with_validation_webhook(PasswordValidationWebhook::new(
+                    mas_http::reqwest_client(),
+                    server.uri().parse().unwrap(),
+                    Duration::from_secs(5),
+                    fail_open,
+                ))
+        };
+        let password = "correct horse battery staple";
+
+        // The complexity is checked first
+        assert_eq!(
+            manager(false)
+                .validate_new_password(None, "bob", "password", None)
+                .await
+                .unwrap(),
+            PasswordValidation::TooWeak
+        );
+
+        let validation = manager(false)
+            .validate_new_password(None, "alice", password, Some("fr"))
+            .await
+            .unwrap();
+        assert_eq!(
+            validation.message().as_deref(),
+            Some("Le mot de passe contient un mot du dictionnaire")
+        );
+
+        assert!(manager(false)
+            .validate_new_password(None, "bob", password, None)
+            .await
+            .unwrap()
+            .is_valid());
+
+        // Failures of the webhook reject the password, unless it fails open
+        assert_eq!(
+            manager(false)
+                .validate_new_password(None, "carol", password, None)
+                .await
+                .unwrap(),
+            PasswordValidation::Rejected { reason: None }
+        );
+        assert!(manager(true)
+            .validate_new_password(None, "carol", password, None)
+            .await
+            .unwrap()
+            .is_valid());
+    }
+
+    #[tokio::test]
+    async fn policy_classes() {
+        let manager = PasswordManager::new(3, [(1, Hasher::argon2id(None))])
+            .unwrap()
+            .with_policy_classes(
+                Some(Duration::from_secs(90 * 24 * 60 * 60)),
+                HashMap::from([(
+                    "service".to_owned(),
+                    PasswordPolicy {
+                        minimum_complexity: 4,
+                        minimum_length: 32,
+                        max_age: None,
+                    },
+                )]),
+            );
+
+        assert!(manager.has_policy_class("service"));
+        assert!(!manager.has_policy_class("human"));
+
+        // Users without a class, or with an unknown one, get the default policy
+        let default = PasswordPolicy {
+            minimum_complexity: 3,
+            minimum_length: 0,
+            max_age: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+        };
+        assert_eq!(manager.policy(None).unwrap(), default);
+        assert_eq!(manager.policy(Some("human")).unwrap(), default);
+
+        let password = "correct horse battery staple";
+        assert!(manager
+            .validate_new_password(None, "alice", password, None)
+            .await
+            .unwrap()
+            .is_valid());
+        assert_eq!(
+            manager
+                .validate_new_password(Some("service"), "bot", password, None)
+                .await
+                .unwrap(),
+            PasswordValidation::TooShort { minimum_length: 32 }
+        );
+        assert!(manager
+            .validate_new_password(
+                Some("service"),
+                "bot",
+                "bR4xJ9vQ2mT7kW1zN6pL3sD8fG5hY0cE",
+                None,
+            )
+            .await
+            .unwrap()
+            .is_valid());
+
+        // The passwords of service accounts never expire
+        #[allow(clippy::disallowed_methods)]
+        let now = Utc::now();
+        let created_at = now - chrono::Duration::days(91);
+        assert!(manager.is_password_expired(None, created_at, now).unwrap());
+        assert!(!manager
+            .is_password_expired(None, now - chrono::Duration::days(89), now)
+            .unwrap());
+        assert!(!manager
+            .is_password_expired(Some("service"), created_at, now)
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn hashing_concurrency_limit() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let password = Zeroizing::new(b"hunter2".to_vec());
+        let manager = PasswordManager::new(0, [(1, Hasher::argon2id(None))])
+            .unwrap()
+            .with_concurrency_limit(1, Duration::from_millis(10));
+
+        // Take the only slot, so that the next hashes have to wait for it
+        let permit = manager.acquire_hashing_permit().await.unwrap();
+        assert!(permit.is_some());
+
+        let error = manager
+            .hash(&mut rng, password.clone())
+            .await
+            .expect_err("Hashing should time out while the slot is taken");
+        assert!(error.is::<PasswordHashingBusyError>());
+
+        // Once the slot is released, hashing works again
+        drop(permit);
+        let (version, hash) = manager.hash(&mut rng, password.clone()).await.unwrap();
+        manager.verify(version, password, hash).await.unwrap();
+    }
 }
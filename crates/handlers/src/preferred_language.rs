@@ -17,22 +17,16 @@ use mas_i18n::{locale, DataLocale, Translator};
 
 pub struct PreferredLanguage(pub DataLocale);
 
-#[async_trait]
-impl<S> FromRequestParts<S> for PreferredLanguage
-where
-    S: Send + Sync,
-    Arc<Translator>: FromRef<S>,
-{
-    type Rejection = Infallible;
-
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let translator: Arc<Translator> = FromRef::from_ref(state);
-        let accept_language: Option<TypedHeader<AcceptLanguage>> =
-            FromRequestParts::from_request_parts(parts, state).await?;
-
+impl PreferredLanguage {
+    /// Choose the best available locale for the given `Accept-Language`
+    /// header
+    pub(crate) fn negotiate(
+        translator: &Translator,
+        accept_language: Option<&AcceptLanguage>,
+    ) -> Self {
         let iter = accept_language
-            .iter()
-            .flat_map(|TypedHeader(accept_language)| accept_language.iter())
+            .into_iter()
+            .flat_map(AcceptLanguage::iter)
             .flat_map(|lang| {
                 let lang = DataLocale::from(lang);
                 // XXX: this is hacky as we may want to actually maintain proper language
@@ -46,8 +40,28 @@ where
                 }
             });
 
-        let locale = translator.choose_locale(iter);
+        PreferredLanguage(translator.choose_locale(iter))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PreferredLanguage
+where
+    S: Send + Sync,
+    Arc<Translator>: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let translator: Arc<Translator> = FromRef::from_ref(state);
+        let accept_language: Option<TypedHeader<AcceptLanguage>> =
+            FromRequestParts::from_request_parts(parts, state).await?;
 
-        Ok(PreferredLanguage(locale))
+        Ok(Self::negotiate(
+            &translator,
+            accept_language
+                .as_ref()
+                .map(|TypedHeader(accept_language)| accept_language),
+        ))
     }
 }
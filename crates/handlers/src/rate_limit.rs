@@ -8,7 +8,7 @@ use std::{net::IpAddr, sync::Arc, time::Duration};
 
 use governor::{clock::QuantaClock, state::keyed::DashMapStateStore, RateLimiter};
 use mas_config::RateLimitingConfig;
-use mas_data_model::User;
+use mas_data_model::{User, UserEmail};
 use ulid::Ulid;
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -35,6 +35,33 @@ pub enum RegistrationLimitedError {
     Requester(RequesterFingerprint),
 }
 
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DeviceCodeEntryLimitedError {
+    #[error("Too many device code entries for requester {0}")]
+    Requester(RequesterFingerprint),
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum FormSubmissionLimitedError {
+    #[error("Too many form submissions for requester {0}")]
+    Requester(RequesterFingerprint),
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MatrixIdLoginLimitedError {
+    #[error("Too many Matrix ID logins for requester {0}")]
+    Requester(RequesterFingerprint),
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+pub enum EmailVerificationLimitedError {
+    #[error("Too many verification emails sent to user email {0}")]
+    Resend(Ulid),
+
+    #[error("Too many verification codes submitted for user email {0}")]
+    CodeAttempts(Ulid),
+}
+
 /// Key used to rate limit requests per requester
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RequesterFingerprint {
@@ -61,6 +88,12 @@ impl RequesterFingerprint {
     pub const fn new(ip: IpAddr) -> Self {
         Self { ip: Some(ip) }
     }
+
+    /// The IP address of the requester, if known
+    #[must_use]
+    pub const fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
 }
 
 /// Rate limiters for the different operations
@@ -78,6 +111,11 @@ struct LimiterInner {
     password_check_for_requester: KeyedRateLimiter<RequesterFingerprint>,
     password_check_for_user: KeyedRateLimiter<Ulid>,
     registration_per_requester: KeyedRateLimiter<RequesterFingerprint>,
+    device_code_entry_per_requester: KeyedRateLimiter<RequesterFingerprint>,
+    form_submissions_per_requester: KeyedRateLimiter<RequesterFingerprint>,
+    matrix_id_login_per_requester: KeyedRateLimiter<RequesterFingerprint>,
+    email_verification_resend_per_email: KeyedRateLimiter<Ulid>,
+    email_verification_code_attempts_per_email: KeyedRateLimiter<Ulid>,
 }
 
 impl LimiterInner {
@@ -92,6 +130,17 @@ impl LimiterInner {
             password_check_for_requester: RateLimiter::keyed(config.login.per_ip.to_quota()?),
             password_check_for_user: RateLimiter::keyed(config.login.per_account.to_quota()?),
             registration_per_requester: RateLimiter::keyed(config.registration.to_quota()?),
+            device_code_entry_per_requester: RateLimiter::keyed(
+                config.device_code_entry.to_quota()?,
+            ),
+            form_submissions_per_requester: RateLimiter::keyed(config.form_submissions.to_quota()?),
+            matrix_id_login_per_requester: RateLimiter::keyed(config.matrix_id_login.to_quota()?),
+            email_verification_resend_per_email: RateLimiter::keyed(
+                config.email_verification.resend.to_quota()?,
+            ),
+            email_verification_code_attempts_per_email: RateLimiter::keyed(
+                config.email_verification.code_attempts.to_quota()?,
+            ),
         })
     }
 }
@@ -127,6 +176,15 @@ impl Limiter {
                 this.inner.password_check_for_requester.retain_recent();
                 this.inner.password_check_for_user.retain_recent();
                 this.inner.registration_per_requester.retain_recent();
+                this.inner.device_code_entry_per_requester.retain_recent();
+                this.inner.form_submissions_per_requester.retain_recent();
+                this.inner.matrix_id_login_per_requester.retain_recent();
+                this.inner
+                    .email_verification_resend_per_email
+                    .retain_recent();
+                this.inner
+                    .email_verification_code_attempts_per_email
+                    .retain_recent();
 
                 interval.tick().await;
             }
@@ -199,6 +257,93 @@ impl Limiter {
 
         Ok(())
     }
+
+    /// Check if a device code can be entered
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited.
+    pub fn check_device_code_entry(
+        &self,
+        requester: RequesterFingerprint,
+    ) -> Result<(), DeviceCodeEntryLimitedError> {
+        self.inner
+            .device_code_entry_per_requester
+            .check_key(&requester)
+            .map_err(|_| DeviceCodeEntryLimitedError::Requester(requester))?;
+
+        Ok(())
+    }
+
+    /// Check if a login or registration form can be submitted without being
+    /// considered suspicious
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited.
+    pub fn check_form_submission(
+        &self,
+        requester: RequesterFingerprint,
+    ) -> Result<(), FormSubmissionLimitedError> {
+        self.inner
+            .form_submissions_per_requester
+            .check_key(&requester)
+            .map_err(|_| FormSubmissionLimitedError::Requester(requester))?;
+
+        Ok(())
+    }
+
+    /// Check if a login with a Matrix ID of another homeserver can be
+    /// attempted
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited.
+    pub fn check_matrix_id_login(
+        &self,
+        requester: RequesterFingerprint,
+    ) -> Result<(), MatrixIdLoginLimitedError> {
+        self.inner
+            .matrix_id_login_per_requester
+            .check_key(&requester)
+            .map_err(|_| MatrixIdLoginLimitedError::Requester(requester))?;
+
+        Ok(())
+    }
+
+    /// Check if a verification email can be sent to an email address
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited.
+    pub fn check_email_verification_resend(
+        &self,
+        user_email: &UserEmail,
+    ) -> Result<(), EmailVerificationLimitedError> {
+        self.inner
+            .email_verification_resend_per_email
+            .check_key(&user_email.id)
+            .map_err(|_| EmailVerificationLimitedError::Resend(user_email.id))?;
+
+        Ok(())
+    }
+
+    /// Check if a verification code can be submitted for an email address
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited.
+    pub fn check_email_verification_attempt(
+        &self,
+        user_email: &UserEmail,
+    ) -> Result<(), EmailVerificationLimitedError> {
+        self.inner
+            .email_verification_code_attempts_per_email
+            .check_key(&user_email.id)
+            .map_err(|_| EmailVerificationLimitedError::CodeAttempts(user_email.id))?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -230,6 +375,7 @@ mod tests {
             primary_user_email_id: None,
             created_at: now,
             locked_at: None,
+            deleted_at: None,
             can_request_admin: false,
         };
 
@@ -240,6 +386,7 @@ mod tests {
             primary_user_email_id: None,
             created_at: now,
             locked_at: None,
+            deleted_at: None,
             can_request_admin: false,
         };
 
@@ -0,0 +1,571 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Registration API, for native applications registering users without
+//! embedding a browser.
+//!
+//! It applies the same checks as the registration form, and only accepts
+//! clients which are allowed to use it in the configuration. Once registered,
+//! the user verifies their email address with the code sent to them, and the
+//! application then signs them in with the usual authorization flow.
+
+use std::str::FromStr;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::typed_header::TypedHeader;
+use hyper::StatusCode;
+use lettre::Address;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{RegistrationMetadata, SiteConfig, UserAgent};
+use mas_matrix::BoxHomeserverConnection;
+use mas_policy::Policy;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    job::{JobRepositoryExt, ProvisionUserJob},
+    user::{UserEmailRepository, UserPasswordRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ulid::Ulid;
+use zeroize::Zeroizing;
+
+use crate::{
+    captcha::Form as CaptchaForm,
+    email_verification::{self, SendCodeError, VerificationOutcome},
+    impl_from_error_for_route,
+    passwords::PasswordManager,
+    rate_limit::{EmailVerificationLimitedError, RegistrationLimitedError},
+    BoundActivityTracker, Limiter, NetworkPolicy, NetworkPolicyError, NetworkScope,
+    PreferredLanguage, RequestOrigin, RequesterFingerprint, RiskAssessor, RiskContext, RiskEvent,
+    RiskVerdict,
+};
+
+/// A field of the registration request which was rejected
+#[derive(Debug, Serialize)]
+pub(crate) struct FieldViolation {
+    /// The field, or `None` if the violation is about the whole request
+    field: Option<&'static str>,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl FieldViolation {
+    fn new(field: &'static str, code: &'static str) -> Self {
+        Self {
+            field: Some(field),
+            code,
+            message: None,
+        }
+    }
+
+    fn with_message(mut self, message: String) -> Self {
+        self.message = Some(message);
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("password registration is disabled")]
+    RegistrationDisabled,
+
+    #[error("client {0} is not allowed to use the registration API")]
+    ClientNotAllowed(Ulid),
+
+    #[error("denied by the network policy")]
+    NetworkPolicy(#[from] NetworkPolicyError),
+
+    #[error("denied by the risk provider")]
+    RiskDenied,
+
+    #[error("invalid CAPTCHA response")]
+    Captcha,
+
+    #[error("invalid registration request")]
+    InvalidFields(Vec<FieldViolation>),
+
+    #[error(transparent)]
+    RegistrationLimited(#[from] RegistrationLimitedError),
+
+    #[error(transparent)]
+    EmailVerificationLimited(#[from] EmailVerificationLimitedError),
+
+    #[error("email {0} not found")]
+    EmailNotFound(Ulid),
+
+    #[error("invalid verification code")]
+    InvalidCode,
+
+    #[error("expired verification code")]
+    ExpiredCode,
+
+    #[error("too many verification attempts")]
+    TooManyAttempts,
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_policy::EvaluationError);
+
+impl From<anyhow::Error> for RouteError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+impl From<SendCodeError> for RouteError {
+    fn from(e: SendCodeError) -> Self {
+        match e {
+            SendCodeError::RateLimited(e) => Self::EmailVerificationLimited(e),
+            SendCodeError::Repository(e) => e.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    violations: Vec<FieldViolation>,
+}
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let (status, error) = match &self {
+            Self::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+            Self::RegistrationDisabled => (StatusCode::FORBIDDEN, "registration_disabled"),
+            Self::ClientNotAllowed(_) => (StatusCode::FORBIDDEN, "client_not_allowed"),
+            Self::NetworkPolicy(_) => (StatusCode::FORBIDDEN, "network_denied"),
+            Self::RiskDenied => (StatusCode::FORBIDDEN, "denied"),
+            Self::Captcha => (StatusCode::BAD_REQUEST, "invalid_captcha"),
+            Self::InvalidFields(_) => (StatusCode::BAD_REQUEST, "invalid_request"),
+            Self::RegistrationLimited(_) | Self::EmailVerificationLimited(_) => {
+                (StatusCode::TOO_MANY_REQUESTS, "rate_limited")
+            }
+            Self::EmailNotFound(_) => (StatusCode::NOT_FOUND, "not_found"),
+            Self::InvalidCode => (StatusCode::BAD_REQUEST, "invalid_code"),
+            Self::ExpiredCode => (StatusCode::BAD_REQUEST, "expired_code"),
+            Self::TooManyAttempts => (StatusCode::TOO_MANY_REQUESTS, "too_many_attempts"),
+        };
+
+        let violations = match self {
+            Self::InvalidFields(violations) => violations,
+            _ => Vec::new(),
+        };
+
+        let response = (status, Json(ErrorResponse { error, violations }));
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Check that registrations are enabled, and that the client is allowed to
+/// use the registration API
+fn check_client(site_config: &SiteConfig, client_id: Ulid) -> Result<(), RouteError> {
+    if !site_config.password_registration_enabled {
+        return Err(RouteError::RegistrationDisabled);
+    }
+
+    if !site_config.native_registration_clients.contains(&client_id) {
+        return Err(RouteError::ClientNotAllowed(client_id));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RegisterRequest {
+    client_id: Ulid,
+    username: String,
+    email: String,
+    password: String,
+    #[serde(default)]
+    accept_terms: bool,
+
+    /// The CAPTCHA response, in the field named after the service, as
+    /// advertised by the forms description
+    #[serde(flatten)]
+    captcha: CaptchaForm,
+}
+
+#[derive(Serialize)]
+struct RegisterResponse {
+    user_id: Ulid,
+    username: String,
+
+    /// The ID of the email address to verify with the code sent to it
+    email_id: Ulid,
+}
+
+#[tracing::instrument(
+    name = "handlers.registration_api.register",
+    fields(client.id = %request.client_id),
+    skip_all,
+    err,
+)]
+#[allow(clippy::too_many_lines, clippy::too_many_arguments)]
+pub(crate) async fn register(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(password_manager): State<PasswordManager>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    State(http_client): State<reqwest::Client>,
+    (State(limiter), State(risk_assessor), State(network_policy), requester, origin): (
+        State<Limiter>,
+        State<RiskAssessor>,
+        State<NetworkPolicy>,
+        RequesterFingerprint,
+        RequestOrigin,
+    ),
+    mut policy: Policy,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<impl IntoResponse, RouteError> {
+    check_client(&site_config, request.client_id)?;
+    network_policy.check(NetworkScope::Registration, &origin)?;
+
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+
+    let risk = risk_assessor
+        .assess(
+            RiskContext {
+                event: RiskEvent::Registration,
+                username: request.username.clone(),
+                email: Some(request.email.clone()).filter(|email| !email.is_empty()),
+                ip: activity_tracker.ip(),
+                user_agent: None,
+                device_type: None,
+                client_app: None,
+            }
+            .with_user_agent(user_agent.as_ref()),
+        )
+        .await;
+    if risk == RiskVerdict::Deny {
+        return Err(RouteError::RiskDenied);
+    }
+
+    // There is no bot detection outside of the browser, so the CAPTCHA is always
+    // required if one is configured
+    request
+        .captcha
+        .verify(
+            &activity_tracker,
+            &http_client,
+            url_builder.public_hostname(),
+            site_config.captcha.as_ref(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::warn!(error = &e as &dyn std::error::Error, "Invalid CAPTCHA");
+            RouteError::Captcha
+        })?;
+
+    let mut violations = Vec::new();
+
+    if request.username.is_empty() {
+        violations.push(FieldViolation::new("username", "required"));
+    } else if repo.user().exists(&request.username).await?
+        || !homeserver.is_localpart_available(&request.username).await?
+    {
+        violations.push(FieldViolation::new("username", "exists"));
+    }
+
+    if request.email.is_empty() {
+        violations.push(FieldViolation::new("email", "required"));
+    } else if Address::from_str(&request.email).is_err() {
+        violations.push(FieldViolation::new("email", "invalid"));
+    }
+
+    if request.password.is_empty() {
+        violations.push(FieldViolation::new("password", "required"));
+    } else {
+        let validation = password_manager
+            .validate_new_password(
+                None,
+                &request.username,
+                &request.password,
+                Some(&locale.to_string()),
+            )
+            .await?;
+        if let Some(message) = validation.message() {
+            violations.push(FieldViolation::new("password", "policy").with_message(message));
+        }
+    }
+
+    if site_config.tos_uri.is_some() && !request.accept_terms {
+        violations.push(FieldViolation::new("accept_terms", "required"));
+    }
+
+    let res = policy
+        .evaluate_register(&request.username, &request.email)
+        .await?;
+    for violation in res.violations {
+        let field = match violation.field.as_deref() {
+            Some("email") => Some("email"),
+            Some("username") => Some("username"),
+            Some("password") => Some("password"),
+            _ => None,
+        };
+        violations.push(FieldViolation {
+            field,
+            code: "policy",
+            message: Some(violation.msg),
+        });
+    }
+
+    if !violations.is_empty() {
+        return Err(RouteError::InvalidFields(violations));
+    }
+
+    limiter.check_registration(requester)?;
+
+    let user = repo.user().add(&mut rng, &clock, request.username).await?;
+
+    repo.user()
+        .set_registration_metadata(
+            &user,
+            &RegistrationMetadata {
+                client_id: Some(request.client_id),
+                upstream_provider_id: None,
+                invite_token: None,
+                referral: None,
+            },
+        )
+        .await?;
+
+    if let Some(tos_uri) = &site_config.tos_uri {
+        repo.user_terms()
+            .accept_terms(&mut rng, &clock, &user, tos_uri.clone())
+            .await?;
+    }
+
+    let password = Zeroizing::new(request.password.into_bytes());
+    let (version, hashed_password) = password_manager.hash(&mut rng, password).await?;
+    repo.user_password()
+        .add(&mut rng, &clock, &user, version, hashed_password, None)
+        .await?;
+
+    let user_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, request.email)
+        .await?;
+
+    // The address was just added, so sending the code can't be rate limited
+    email_verification::send_code(&mut repo, &limiter, &user_email, Some(locale.to_string()))
+        .await?;
+
+    repo.job()
+        .schedule_job(ProvisionUserJob::new(&user))
+        .await?;
+
+    repo.save().await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(RegisterResponse {
+            user_id: user.id,
+            username: user.username,
+            email_id: user_email.id,
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ResendEmailRequest {
+    client_id: Ulid,
+    email_id: Ulid,
+}
+
+#[tracing::instrument(
+    name = "handlers.registration_api.resend_email",
+    fields(client.id = %request.client_id, user_email.id = %request.email_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn resend_email(
+    PreferredLanguage(locale): PreferredLanguage,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
+    mut repo: BoxRepository,
+    Json(request): Json<ResendEmailRequest>,
+) -> Result<impl IntoResponse, RouteError> {
+    check_client(&site_config, request.client_id)?;
+
+    let user_email = repo
+        .user_email()
+        .lookup(request.email_id)
+        .await?
+        .ok_or(RouteError::EmailNotFound(request.email_id))?;
+
+    if user_email.confirmed_at.is_none() {
+        email_verification::send_code(&mut repo, &limiter, &user_email, Some(locale.to_string()))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VerifyEmailRequest {
+    client_id: Ulid,
+    email_id: Ulid,
+    code: String,
+}
+
+#[tracing::instrument(
+    name = "handlers.registration_api.verify_email",
+    fields(client.id = %request.client_id, user_email.id = %request.email_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn verify_email(
+    clock: BoxClock,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
+    mut repo: BoxRepository,
+    Json(request): Json<VerifyEmailRequest>,
+) -> Result<impl IntoResponse, RouteError> {
+    check_client(&site_config, request.client_id)?;
+
+    let user_email = repo
+        .user_email()
+        .lookup(request.email_id)
+        .await?
+        .ok_or(RouteError::EmailNotFound(request.email_id))?;
+
+    let outcome =
+        email_verification::verify_code(&mut repo, &clock, &limiter, user_email, &request.code)
+            .await?;
+
+    match outcome {
+        VerificationOutcome::Verified(_) | VerificationOutcome::AlreadyVerified(_) => {}
+        VerificationOutcome::InvalidCode => return Err(RouteError::InvalidCode),
+        VerificationOutcome::ExpiredCode => return Err(RouteError::ExpiredCode),
+        VerificationOutcome::TooManyAttempts => return Err(RouteError::TooManyAttempts),
+    }
+
+    repo.save().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use mas_storage::{
+        user::{UserEmailRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use crate::test_utils::{setup, test_site_config, RequestBuilderExt, ResponseExt, TestState};
+
+    const CLIENT_ID: Ulid = Ulid(42);
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_not_allowed(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::post(mas_router::RegistrationApi::PATH).json(serde_json::json!({
+            "client_id": CLIENT_ID,
+            "username": "john",
+            "email": "john@example.com",
+            "password": "correcthorsebatterystaple",
+            "accept_terms": true,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let error: serde_json::Value = response.json();
+        assert_eq!(error["error"], "client_not_allowed");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_register_and_verify_email(pool: PgPool) {
+        setup();
+        let mut site_config = test_site_config();
+        site_config.native_registration_clients.insert(CLIENT_ID);
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+        let mut rng = state.rng();
+
+        // Invalid fields are all reported at once
+        let request = Request::post(mas_router::RegistrationApi::PATH).json(serde_json::json!({
+            "client_id": CLIENT_ID,
+            "username": "",
+            "email": "not an email",
+            "password": "correcthorsebatterystaple",
+            "accept_terms": true,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = response.json();
+        assert_eq!(error["error"], "invalid_request");
+        assert_eq!(error["violations"][0]["field"], "username");
+        assert_eq!(error["violations"][1]["field"], "email");
+
+        let request = Request::post(mas_router::RegistrationApi::PATH).json(serde_json::json!({
+            "client_id": CLIENT_ID,
+            "username": "john",
+            "email": "john@example.com",
+            "password": "correcthorsebatterystaple",
+            "accept_terms": true,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let registration: serde_json::Value = response.json();
+        assert_eq!(registration["username"], "john");
+        let email_id: Ulid = registration["email_id"].as_str().unwrap().parse().unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().find_by_username("john").await.unwrap().unwrap();
+        let user_email = repo.user_email().lookup(email_id).await.unwrap().unwrap();
+        assert_eq!(user_email.user_id, user.id);
+        repo.user_email()
+            .add_verification_code(
+                &mut rng,
+                &state.clock,
+                &user_email,
+                Duration::try_hours(8).unwrap(),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let verify = |code: &str| {
+            Request::post(mas_router::RegistrationApiVerifyEmail::PATH).json(serde_json::json!({
+                "client_id": CLIENT_ID,
+                "email_id": email_id,
+                "code": code,
+            }))
+        };
+
+        let response = state.request(verify("000000")).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let response = state.request(verify("123456")).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo.user_email().lookup(email_id).await.unwrap().unwrap();
+        assert!(user_email.confirmed_at.is_some());
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Consult an external risk provider on logins and registrations.
+//!
+//! The [`RiskProvider`] trait is the extension point to plug a fraud detection
+//! stack, and [`HttpRiskProvider`] is a reference implementation which sends
+//! the context of the attempt as JSON to an HTTP endpoint.
+//!
+//! The [`RiskAssessor`] wraps a provider with a timeout, a fail-open or
+//! fail-closed behaviour, and a short-lived cache of the verdicts.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use mas_config::RiskConfig;
+use mas_data_model::{ClientApp, DeviceType, UserAgent};
+use mas_http::RequestBuilderExt as _;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+/// The kind of attempt being assessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskEvent {
+    /// A user is logging in with a password
+    Login,
+
+    /// A user is registering a new account with a password
+    Registration,
+}
+
+/// The context of an attempt, sent to the risk provider
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct RiskContext {
+    /// The kind of attempt
+    pub event: RiskEvent,
+
+    /// The username submitted in the form
+    pub username: String,
+
+    /// The email submitted in the form, on registrations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+
+    /// The IP address of the requester, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+
+    /// The `User-Agent` of the requester, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    /// The type of device, as parsed from the `User-Agent`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<DeviceType>,
+
+    /// The kind of application, as parsed from the `User-Agent`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_app: Option<ClientApp>,
+}
+
+impl RiskContext {
+    /// Fill the `User-Agent` related fields from the parsed `User-Agent`
+    #[must_use]
+    pub fn with_user_agent(mut self, user_agent: Option<&UserAgent>) -> Self {
+        self.user_agent = user_agent.map(|ua| ua.raw.clone());
+        self.device_type = user_agent.map(|ua| ua.device_type.clone());
+        self.client_app = user_agent.map(|ua| ua.client_app);
+        self
+    }
+}
+
+/// What the risk provider decided about an attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskVerdict {
+    /// The attempt can go through
+    Allow,
+
+    /// The attempt should require a CAPTCHA
+    Challenge,
+
+    /// The attempt should be rejected
+    Deny,
+}
+
+#[derive(Debug, Error)]
+pub enum RiskError {
+    #[error("The risk provider did not reply in time")]
+    Timeout,
+
+    #[error("The request to the risk provider failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// An external service deciding whether a login or a registration is risky
+#[async_trait]
+pub trait RiskProvider: Send + Sync {
+    /// Assess the risk of the given attempt
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the provider could not reach a verdict
+    async fn assess(&self, context: &RiskContext) -> Result<RiskVerdict, RiskError>;
+}
+
+#[derive(Debug, Deserialize)]
+struct HttpRiskResponse {
+    verdict: RiskVerdict,
+}
+
+/// A [`RiskProvider`] which POSTs the context as JSON to an HTTP endpoint
+///
+/// The endpoint must reply with a JSON object like `{"verdict": "allow"}`,
+/// where the verdict is one of `allow`, `challenge` or `deny`.
+pub struct HttpRiskProvider {
+    http_client: reqwest::Client,
+    endpoint: Url,
+    token: Option<String>,
+}
+
+impl HttpRiskProvider {
+    /// Create a new provider sending requests to the given endpoint
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, endpoint: Url, token: Option<String>) -> Self {
+        Self {
+            http_client,
+            endpoint,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl RiskProvider for HttpRiskProvider {
+    async fn assess(&self, context: &RiskContext) -> Result<RiskVerdict, RiskError> {
+        let mut request = self.http_client.post(self.endpoint.clone()).json(context);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response: HttpRiskResponse = request
+            .send_traced()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.verdict)
+    }
+}
+
+struct Inner {
+    provider: Arc<dyn RiskProvider>,
+    timeout: Duration,
+    fail_open: bool,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<RiskContext, (RiskVerdict, Instant)>>,
+}
+
+/// Consults a [`RiskProvider`], if one is configured, with a timeout and a
+/// cache
+#[derive(Clone, Default)]
+pub struct RiskAssessor {
+    inner: Option<Arc<Inner>>,
+}
+
+impl RiskAssessor {
+    /// An assessor which allows every attempt, without consulting any provider
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Create an assessor consulting the given provider
+    #[must_use]
+    pub fn new(
+        provider: Arc<dyn RiskProvider>,
+        timeout: Duration,
+        fail_open: bool,
+        cache_ttl: Duration,
+    ) -> Self {
+        Self {
+            inner: Some(Arc::new(Inner {
+                provider,
+                timeout,
+                fail_open,
+                cache_ttl,
+                cache: Mutex::new(HashMap::new()),
+            })),
+        }
+    }
+
+    /// Create an assessor from the configuration, consulting the HTTP
+    /// endpoint if one is set
+    #[must_use]
+    pub fn from_config(config: &RiskConfig, http_client: reqwest::Client) -> Self {
+        let Some(endpoint) = &config.endpoint else {
+            return Self::disabled();
+        };
+
+        let provider = HttpRiskProvider::new(http_client, endpoint.clone(), config.token.clone());
+        Self::new(
+            Arc::new(provider),
+            config.timeout,
+            config.fail_open,
+            config.cache_ttl,
+        )
+    }
+
+    /// Assess the risk of the given attempt
+    ///
+    /// If the provider fails or doesn't reply in time, the attempt is allowed
+    /// or denied depending on whether the assessor fails open.
+    #[tracing::instrument(name = "risk.assess", skip_all, fields(risk.event = ?context.event))]
+    pub async fn assess(&self, context: RiskContext) -> RiskVerdict {
+        let Some(inner) = &self.inner else {
+            return RiskVerdict::Allow;
+        };
+
+        if let Some(verdict) = inner.cached(&context) {
+            return verdict;
+        }
+
+        let result = tokio::time::timeout(inner.timeout, inner.provider.assess(&context))
+            .await
+            .unwrap_or(Err(RiskError::Timeout));
+
+        match result {
+            Ok(verdict) => {
+                if verdict != RiskVerdict::Allow {
+                    tracing::warn!(?verdict, "Risk provider flagged the attempt");
+                }
+
+                inner.store(context, verdict);
+                verdict
+            }
+
+            Err(e) => {
+                tracing::error!(
+                    error = &e as &dyn std::error::Error,
+                    fail_open = inner.fail_open,
+                    "Could not assess the risk of the attempt"
+                );
+
+                if inner.fail_open {
+                    RiskVerdict::Allow
+                } else {
+                    RiskVerdict::Deny
+                }
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn cached(&self, context: &RiskContext) -> Option<RiskVerdict> {
+        let cache = self.cache.lock().unwrap();
+        let (verdict, inserted_at) = cache.get(context)?;
+        (inserted_at.elapsed() < self.cache_ttl).then_some(*verdict)
+    }
+
+    fn store(&self, context: RiskContext, verdict: RiskVerdict) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, inserted_at)| inserted_at.elapsed() < self.cache_ttl);
+        cache.insert(context, (verdict, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct MockProvider {
+        verdict: Option<RiskVerdict>,
+        delay: Duration,
+        calls: AtomicUsize,
+    }
+
+    impl MockProvider {
+        fn new(verdict: Option<RiskVerdict>) -> Self {
+            Self {
+                verdict,
+                delay: Duration::ZERO,
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RiskProvider for MockProvider {
+        async fn assess(&self, _context: &RiskContext) -> Result<RiskVerdict, RiskError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.verdict
+                .ok_or_else(|| anyhow::anyhow!("provider is down").into())
+        }
+    }
+
+    fn context(username: &str) -> RiskContext {
+        RiskContext {
+            event: RiskEvent::Login,
+            username: username.to_owned(),
+            email: None,
+            ip: None,
+            user_agent: None,
+            device_type: None,
+            client_app: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let assessor = RiskAssessor::disabled();
+        assert_eq!(assessor.assess(context("alice")).await, RiskVerdict::Allow);
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        let provider = Arc::new(MockProvider::new(Some(RiskVerdict::Challenge)));
+        let assessor = RiskAssessor::new(
+            provider.clone(),
+            Duration::from_secs(1),
+            true,
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(
+            assessor.assess(context("alice")).await,
+            RiskVerdict::Challenge
+        );
+        assert_eq!(
+            assessor.assess(context("alice")).await,
+            RiskVerdict::Challenge
+        );
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+        // Another attempt isn't served from the cache
+        assessor.assess(context("bob")).await;
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let provider = Arc::new(MockProvider::new(None));
+        let assessor = RiskAssessor::new(
+            provider.clone(),
+            Duration::from_secs(1),
+            true,
+            Duration::ZERO,
+        );
+        assert_eq!(assessor.assess(context("alice")).await, RiskVerdict::Allow);
+
+        let assessor = RiskAssessor::new(provider, Duration::from_secs(1), false, Duration::ZERO);
+        assert_eq!(assessor.assess(context("alice")).await, RiskVerdict::Deny);
+
+        // A provider which is too slow is treated as a failure
+        let provider = Arc::new(MockProvider {
+            delay: Duration::from_secs(5),
+            ..MockProvider::new(Some(RiskVerdict::Allow))
+        });
+        let assessor =
+            RiskAssessor::new(provider, Duration::from_millis(10), false, Duration::ZERO);
+        assert_eq!(assessor.assess(context("alice")).await, RiskVerdict::Deny);
+    }
+}
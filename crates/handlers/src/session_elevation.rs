@@ -0,0 +1,110 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Elevation of browser sessions ("sudo mode") before sensitive actions.
+//!
+//! Users get an elevation token by authenticating again, either with their
+//! password, or by going through their upstream provider, which renews the
+//! authentication of their session. The token is short-lived, and tied to the
+//! session it was obtained in.
+
+use chrono::Duration;
+use mas_data_model::{Authentication, BrowserSession, SessionElevation, User};
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
+    user::{BrowserSessionRepository, UserPasswordRepository},
+    BoxRepository, Clock, RepositoryAccess, RepositoryError,
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    RngCore,
+};
+
+use crate::SiteConfig;
+
+/// How long an elevation lasts
+pub(crate) const ELEVATION_TTL: Duration = Duration::minutes(5);
+
+/// How recent the authentication of a session must be to elevate it without
+/// authenticating again
+pub(crate) const REAUTHENTICATION_WINDOW: Duration = Duration::minutes(5);
+
+/// Whether the user has a way to authenticate again, either with a password or
+/// through an upstream provider
+pub(crate) async fn can_reauthenticate(
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    user: &User,
+) -> Result<bool, RepositoryError> {
+    if site_config.password_login_enabled && repo.user_password().active(user).await?.is_some() {
+        return Ok(true);
+    }
+
+    let links = repo
+        .upstream_oauth_link()
+        .count(UpstreamOAuthLinkFilter::new().for_user(user))
+        .await?;
+    Ok(links > 0)
+}
+
+/// Elevate the session with the given authentication, which should have just
+/// happened
+///
+/// The caller is responsible for saving the repository.
+pub(crate) async fn elevate(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    session: &BrowserSession,
+    authentication: &Authentication,
+) -> Result<SessionElevation, RepositoryError> {
+    let token = Alphanumeric.sample_string(rng, 32);
+    repo.browser_session()
+        .elevate(rng, clock, session, authentication, token, ELEVATION_TTL)
+        .await
+}
+
+/// Whether the elevation token is valid for the session
+pub(crate) async fn is_elevated(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    session: &BrowserSession,
+    token: Option<&str>,
+) -> Result<bool, RepositoryError> {
+    let Some(token) = token else {
+        return Ok(false);
+    };
+
+    let elevation = repo
+        .browser_session()
+        .find_elevation(session, token)
+        .await?;
+    Ok(elevation.is_some_and(|elevation| elevation.is_valid(clock.now())))
+}
+
+/// Whether the session authenticated in the last few minutes, or the elevation
+/// token is valid for it
+pub(crate) async fn has_reauthenticated(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    session: Option<&BrowserSession>,
+    token: Option<&str>,
+) -> Result<bool, RepositoryError> {
+    let Some(session) = session else {
+        return Ok(false);
+    };
+
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(session)
+        .await?;
+    if last_authentication.is_some_and(|authentication| {
+        clock.now() - authentication.created_at < REAUTHENTICATION_WINDOW
+    }) {
+        return Ok(true);
+    }
+
+    is_elevated(repo, clock, session, token).await
+}
@@ -0,0 +1,271 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Enforce the limits on the number of simultaneous sessions of users.
+//!
+//! Limits are checked right before a new session is started. Depending on the
+//! configured [`SessionEviction`], a new session exceeding a limit is either
+//! rejected, or the oldest sessions are ended to make room for it.
+
+use mas_data_model::{Client, SessionEviction, SessionLimits, User};
+use mas_storage::{
+    app_session::{AppSession, AppSessionFilter, AppSessionRepository},
+    compat::CompatSessionRepository,
+    job::{JobRepositoryExt, SyncDevicesJob},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    user::UserRepository,
+    BoxRepository, Clock, Pagination, RepositoryAccess,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum SessionLimitError {
+    #[error("The user has too many active sessions")]
+    Exceeded,
+
+    #[error(transparent)]
+    Repository(#[from] mas_storage::RepositoryError),
+}
+
+/// How many sessions have to be ended before starting a new one, given the
+/// number of active sessions and the limit
+fn excess(active: usize, limit: u32) -> usize {
+    let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    (active + 1).saturating_sub(limit)
+}
+
+/// Make sure the user can start a new session, optionally with the given
+/// client
+///
+/// The limit per user is the one set by an admin for that user, or else the
+/// configured one. When evicting sessions, the ones with the same client are
+/// ended first, and the devices of the user are synced with the homeserver.
+///
+/// If a limit applies, the user is locked until the end of the transaction,
+/// so that concurrent logins can't both see room for one more session. The
+/// new session must then be started in the same transaction.
+///
+/// # Errors
+///
+/// Returns [`SessionLimitError::Exceeded`] if a limit is reached and new
+/// sessions are rejected, or an error if the repository fails
+pub(crate) async fn enforce(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    limits: &SessionLimits,
+    user: &User,
+    client: Option<&Client>,
+) -> Result<(), SessionLimitError> {
+    let client_limit =
+        client.and_then(|client| Some((client, *limits.client_overrides.get(&client.id)?)));
+    let user_limit = repo
+        .user()
+        .get_session_limit(user)
+        .await?
+        .or(limits.max_sessions_per_user);
+
+    if client_limit.is_none() && user_limit.is_none() {
+        return Ok(());
+    }
+
+    // Serialise the logins of the user until the new session is started
+    repo.user().acquire_lock_for_sync(user).await?;
+
+    let mut evicted = false;
+
+    // First, the limit of sessions with that client
+    if let Some((client, limit)) = client_limit {
+        let filter = OAuth2SessionFilter::new()
+            .for_user(user)
+            .for_client(client)
+            .active_only();
+
+        let excess = excess(repo.oauth2_session().count(filter).await?, limit);
+        if excess > 0 {
+            if limits.eviction == SessionEviction::Reject {
+                return Err(SessionLimitError::Exceeded);
+            }
+
+            let page = repo
+                .oauth2_session()
+                .list(filter, Pagination::first(excess))
+                .await?;
+
+            for session in page.edges {
+                repo.oauth2_session().finish(clock, session).await?;
+            }
+
+            evicted = true;
+        }
+    }
+
+    // Then the limit of sessions of the user, across all clients
+    if let Some(limit) = user_limit {
+        let filter = AppSessionFilter::new().for_user(user).active_only();
+
+        let mut excess = excess(repo.app_session().count(filter).await?, limit);
+        if excess > 0 {
+            if limits.eviction == SessionEviction::Reject {
+                return Err(SessionLimitError::Exceeded);
+            }
+
+            evicted = true;
+
+            // End the oldest sessions with the same client first
+            if let Some(client) = client {
+                let filter = OAuth2SessionFilter::new()
+                    .for_user(user)
+                    .for_client(client)
+                    .active_only();
+
+                let page = repo
+                    .oauth2_session()
+                    .list(filter, Pagination::first(excess))
+                    .await?;
+
+                excess -= page.edges.len();
+                for session in page.edges {
+                    repo.oauth2_session().finish(clock, session).await?;
+                }
+            }
+
+            // Then the oldest sessions across all clients
+            if excess > 0 {
+                let page = repo
+                    .app_session()
+                    .list(filter, Pagination::first(excess))
+                    .await?;
+
+                for session in page.edges {
+                    match session {
+                        AppSession::Compat(session) => {
+                            repo.compat_session().finish(clock, *session).await?;
+                        }
+                        AppSession::OAuth2(session) => {
+                            repo.oauth2_session().finish(clock, *session).await?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if evicted {
+        tracing::info!(%user.id, "Ended the oldest sessions of the user to respect the session limits");
+
+        // Remove the devices of the ended sessions from the homeserver
+        repo.job().schedule_job(SyncDevicesJob::new(user)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Duration;
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_storage::{
+        clock::MockClock, oauth2::OAuth2ClientRepository, user::BrowserSessionRepository,
+    };
+    use mas_storage_pg::PgRepository;
+    use oauth2_types::scope::{Scope, OPENID};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    fn test_excess() {
+        assert_eq!(excess(0, 1), 0);
+        assert_eq!(excess(1, 1), 1);
+        assert_eq!(excess(3, 10), 0);
+        assert_eq!(excess(9, 10), 0);
+        assert_eq!(excess(10, 10), 1);
+        assert_eq!(excess(12, 10), 3);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_evict_same_client_first(pool: PgPool) {
+        let clock = MockClock::default();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None)
+            .await
+            .unwrap();
+
+        let mut clients = Vec::new();
+        for id in [1, 2] {
+            let client = repo
+                .oauth2_client()
+                .upsert_static(
+                    Ulid(id),
+                    OAuthClientAuthenticationMethod::None,
+                    None,
+                    None,
+                    None,
+                    vec!["https://example.com/callback".parse().unwrap()],
+                )
+                .await
+                .unwrap();
+            clients.push(client);
+        }
+        let [client, other_client] = clients.try_into().unwrap();
+
+        // The session with the other client is the oldest one
+        let mut sessions = Vec::new();
+        for client in [&other_client, &client] {
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut rng,
+                    &clock,
+                    client,
+                    &browser_session,
+                    Scope::from_iter([OPENID]),
+                )
+                .await
+                .unwrap();
+            sessions.push(session);
+            clock.advance(Duration::try_minutes(1).unwrap());
+        }
+
+        let limits = SessionLimits {
+            max_sessions_per_user: Some(2),
+            client_overrides: HashMap::new(),
+            eviction: SessionEviction::RevokeOldest,
+        };
+        enforce(&mut repo, &clock, &limits, &user, Some(&client))
+            .await
+            .unwrap();
+
+        // The session with the same client was ended instead of the oldest one
+        let other_session = repo
+            .oauth2_session()
+            .lookup(sessions[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(other_session.is_valid());
+        let same_client_session = repo
+            .oauth2_session()
+            .lookup(sessions[1].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!same_client_session.is_valid());
+    }
+}
@@ -5,6 +5,7 @@
 // Please see LICENSE in the repository root for full details.
 
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     convert::Infallible,
     sync::{Arc, Mutex, RwLock},
     task::{Context, Poll},
@@ -29,14 +30,18 @@ use mas_axum_utils::{
     ErrorWrapper,
 };
 use mas_config::RateLimitingConfig;
-use mas_data_model::SiteConfig;
+use mas_data_model::{
+    CompatPasswordLogin, DeviceIdAllocation, FeatureFlags, SessionLimits, SiteConfig,
+};
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
-use mas_matrix::{BoxHomeserverConnection, HomeserverConnection, MockHomeserverConnection};
+use mas_matrix::{
+    BoxHomeserverConnection, CircuitBreaker, HomeserverConnection, MockHomeserverConnection,
+};
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
 use mas_router::{SimpleRoute, UrlBuilder};
 use mas_storage::{clock::MockClock, BoxClock, BoxRepository, BoxRng};
-use mas_storage_pg::{DatabaseError, PgRepository};
+use mas_storage_pg::{DatabaseError, PgRepository, TokenHasher};
 use mas_templates::{SiteConfigExt, Templates};
 use oauth2_types::{registration::ClientRegistrationResponse, requests::AccessTokenResponse};
 use rand::SeedableRng;
@@ -54,7 +59,9 @@ use crate::{
     graphql,
     passwords::{Hasher, PasswordManager},
     upstream_oauth2::cache::MetadataCache,
-    ActivityTracker, BoundActivityTracker, Limiter, RequesterFingerprint,
+    ActivityTracker, AttestationChecker, BoundActivityTracker, ClientCertificate,
+    DeviceProofReplayCache, HomeserverDiscovery, Limiter, NetworkPolicy, RequestOrigin,
+    RequesterFingerprint, RiskAssessor,
 };
 
 /// Setup rustcrypto and tracing for tests.
@@ -97,6 +104,7 @@ pub(crate) struct TestState {
     pub cookie_manager: CookieManager,
     pub metadata_cache: MetadataCache,
     pub encrypter: Encrypter,
+    pub token_hasher: TokenHasher,
     pub url_builder: UrlBuilder,
     pub homeserver_connection: Arc<MockHomeserverConnection>,
     pub policy_factory: Arc<PolicyFactory>,
@@ -105,9 +113,15 @@ pub(crate) struct TestState {
     pub site_config: SiteConfig,
     pub activity_tracker: ActivityTracker,
     pub limiter: Limiter,
+    pub risk_assessor: RiskAssessor,
+    pub attestation_checker: AttestationChecker,
+    pub device_proof_replay_cache: DeviceProofReplayCache,
+    pub client_certificate_login: bool,
+    pub network_policy: NetworkPolicy,
     pub clock: Arc<MockClock>,
     pub rng: Arc<Mutex<ChaChaRng>>,
     pub http_client: reqwest::Client,
+    pub homeserver_discovery: HomeserverDiscovery,
 
     #[allow(dead_code)] // It is used, as it will cancel the CancellationToken when dropped
     cancellation_drop_guard: Arc<DropGuard>,
@@ -125,18 +139,40 @@ pub fn test_site_config() -> SiteConfig {
     SiteConfig {
         access_token_ttl: Duration::try_minutes(5).unwrap(),
         compat_token_ttl: Duration::try_minutes(5).unwrap(),
+        jwt_leeway: Duration::try_minutes(5).unwrap(),
         server_name: "example.com".to_owned(),
         policy_uri: Some("https://example.com/policy".parse().unwrap()),
         tos_uri: Some("https://example.com/tos".parse().unwrap()),
         imprint: None,
         password_login_enabled: true,
         password_registration_enabled: true,
+        compat_password_login: CompatPasswordLogin::default(),
         email_change_allowed: true,
+        email_change_requires_reauthentication: false,
         displayname_change_allowed: true,
+        avatar_change_allowed: true,
+        displayname_locked_to_upstream: false,
         password_change_allowed: true,
         account_recovery_allowed: true,
         captcha: None,
+        bot_detection: None,
+        certificate_login: None,
+        matrix_id_login_enabled: false,
+        session_limits: SessionLimits::default(),
         minimum_password_complexity: 1,
+        offline_access_required: false,
+        offline_access_overrides: HashMap::new(),
+        code_binding_overrides: HashMap::new(),
+        redirect_uri_validation_overrides: HashMap::new(),
+        device_code_user_code_length: 6,
+        device_code_user_code_charset: "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".chars().collect(),
+        custom_scopes: BTreeMap::new(),
+        device_id_allocation: DeviceIdAllocation::default(),
+        reuse_devices: false,
+        feature_flags: FeatureFlags::default(),
+        native_registration_clients: HashSet::new(),
+        upstream_provider_hint_clients: HashSet::new(),
+        client_upstream_providers: HashMap::new(),
     }
 }
 
@@ -163,6 +199,7 @@ impl TestState {
             url_builder.clone(),
             workspace_root.join("frontend/dist/manifest.json"),
             workspace_root.join("translations"),
+            None,
             site_config.templates_branding(),
             site_config.templates_features(),
         )
@@ -179,6 +216,7 @@ impl TestState {
         let key_store = Keystore::new(jwks);
 
         let encrypter = Encrypter::new(&[0x42; 32]);
+        let token_hasher = TokenHasher::new(&[0x42; 32]);
         let cookie_manager = CookieManager::derive_from(url_builder.http_base(), &[0x42; 32]);
 
         let metadata_cache = MetadataCache::new();
@@ -200,6 +238,8 @@ impl TestState {
         let clock = Arc::new(MockClock::default());
         let rng = Arc::new(Mutex::new(ChaChaRng::seed_from_u64(42)));
 
+        let limiter = Limiter::new(&RateLimitingConfig::default()).unwrap();
+
         let graphql_state = TestGraphQLState {
             pool: pool.clone(),
             policy_factory: Arc::clone(&policy_factory),
@@ -208,10 +248,15 @@ impl TestState {
             rng: Arc::clone(&rng),
             clock: Arc::clone(&clock),
             password_manager: password_manager.clone(),
+            limiter: limiter.clone(),
+            token_hasher: token_hasher.clone(),
         };
         let state: crate::graphql::BoxState = Box::new(graphql_state);
 
-        let graphql_schema = graphql::schema_builder().data(state).finish();
+        let graphql_schema = graphql::schema_builder()
+            .data(state)
+            .data(graphql::Loaders::new(&pool))
+            .finish();
 
         let activity_tracker = ActivityTracker::new(
             pool.clone(),
@@ -220,8 +265,6 @@ impl TestState {
             shutdown_token.child_token(),
         );
 
-        let limiter = Limiter::new(&RateLimitingConfig::default()).unwrap();
-
         Ok(Self {
             pool,
             templates,
@@ -229,6 +272,7 @@ impl TestState {
             cookie_manager,
             metadata_cache,
             encrypter,
+            token_hasher,
             url_builder,
             homeserver_connection,
             policy_factory,
@@ -237,9 +281,15 @@ impl TestState {
             site_config,
             activity_tracker,
             limiter,
+            risk_assessor: RiskAssessor::disabled(),
+            attestation_checker: AttestationChecker::disabled(),
+            device_proof_replay_cache: DeviceProofReplayCache::new(),
+            client_certificate_login: true,
+            network_policy: NetworkPolicy::disabled(),
             clock,
             rng,
             http_client,
+            homeserver_discovery: HomeserverDiscovery::new(mas_http::reqwest_public_client()),
             cancellation_drop_guard: Arc::new(shutdown_token.drop_guard()),
         })
     }
@@ -253,13 +303,14 @@ impl TestState {
     {
         let app = crate::healthcheck_router()
             .merge(crate::discovery_router())
-            .merge(crate::api_router())
+            .merge(crate::api_router(self.templates.clone()))
             .merge(crate::compat_router())
             .merge(crate::human_router(self.templates.clone()))
             // We enable undocumented_oauth2_access for the tests, as it is easier to query the API
             // with it
             .merge(crate::graphql_router(false, true))
             .merge(crate::admin_api_router().1)
+            .merge(crate::email_webhooks_router("webhook-secret"))
             .with_state(self.clone())
             .into_service();
 
@@ -322,7 +373,9 @@ impl TestState {
     }
 
     pub async fn repository(&self) -> Result<BoxRepository, DatabaseError> {
-        let repo = PgRepository::from_pool(&self.pool).await?;
+        let repo = PgRepository::from_pool(&self.pool)
+            .await?
+            .with_token_hasher(self.token_hasher.clone());
         Ok(repo.boxed())
     }
 
@@ -370,6 +423,8 @@ struct TestGraphQLState {
     clock: Arc<MockClock>,
     rng: Arc<Mutex<ChaChaRng>>,
     password_manager: PasswordManager,
+    limiter: Limiter,
+    token_hasher: TokenHasher,
 }
 
 #[async_trait]
@@ -377,7 +432,8 @@ impl graphql::State for TestGraphQLState {
     async fn repository(&self) -> Result<BoxRepository, mas_storage::RepositoryError> {
         let repo = PgRepository::from_pool(&self.pool)
             .await
-            .map_err(mas_storage::RepositoryError::from_error)?;
+            .map_err(mas_storage::RepositoryError::from_error)?
+            .with_token_hasher(self.token_hasher.clone());
 
         Ok(repo.boxed())
     }
@@ -402,6 +458,10 @@ impl graphql::State for TestGraphQLState {
         &self.site_config
     }
 
+    fn limiter(&self) -> &Limiter {
+        &self.limiter
+    }
+
     fn rng(&self) -> BoxRng {
         let mut parent_rng = self.rng.lock().expect("Failed to lock RNG");
         let rng = ChaChaRng::from_rng(&mut *parent_rng).expect("Failed to seed RNG");
@@ -481,18 +541,55 @@ impl FromRef<TestState> for BoxHomeserverConnection {
     }
 }
 
+impl FromRef<TestState> for CircuitBreaker {
+    fn from_ref(_input: &TestState) -> Self {
+        // The mock homeserver never fails
+        CircuitBreaker::new(0, std::time::Duration::ZERO)
+    }
+}
+
 impl FromRef<TestState> for Limiter {
     fn from_ref(input: &TestState) -> Self {
         input.limiter.clone()
     }
 }
 
+impl FromRef<TestState> for AttestationChecker {
+    fn from_ref(input: &TestState) -> Self {
+        input.attestation_checker.clone()
+    }
+}
+
+impl FromRef<TestState> for DeviceProofReplayCache {
+    fn from_ref(input: &TestState) -> Self {
+        input.device_proof_replay_cache.clone()
+    }
+}
+
+impl FromRef<TestState> for RiskAssessor {
+    fn from_ref(input: &TestState) -> Self {
+        input.risk_assessor.clone()
+    }
+}
+
+impl FromRef<TestState> for NetworkPolicy {
+    fn from_ref(input: &TestState) -> Self {
+        input.network_policy.clone()
+    }
+}
+
 impl FromRef<TestState> for reqwest::Client {
     fn from_ref(input: &TestState) -> Self {
         input.http_client.clone()
     }
 }
 
+impl FromRef<TestState> for HomeserverDiscovery {
+    fn from_ref(input: &TestState) -> Self {
+        input.homeserver_discovery.clone()
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<TestState> for ActivityTracker {
     type Rejection = Infallible;
@@ -530,6 +627,42 @@ impl FromRequestParts<TestState> for RequesterFingerprint {
     }
 }
 
+#[async_trait]
+impl FromRequestParts<TestState> for RequestOrigin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &TestState,
+    ) -> Result<Self, Self::Rejection> {
+        // Tests can simulate where the request comes from by adding it as an
+        // extension
+        Ok(parts
+            .extensions
+            .get::<RequestOrigin>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<TestState> for ClientCertificate {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &TestState,
+    ) -> Result<Self, Self::Rejection> {
+        // Tests can simulate a client certificate by adding it as an extension
+        Ok(parts
+            .extensions
+            .get::<ClientCertificate>()
+            .cloned()
+            .unwrap_or_default()
+            .on_listener(state.client_certificate_login))
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<TestState> for BoxClock {
     type Rejection = Infallible;
@@ -564,7 +697,9 @@ impl FromRequestParts<TestState> for BoxRepository {
         _parts: &mut axum::http::request::Parts,
         state: &TestState,
     ) -> Result<Self, Self::Rejection> {
-        let repo = PgRepository::from_pool(&state.pool).await?;
+        let repo = PgRepository::from_pool(&state.pool)
+            .await?
+            .with_token_hasher(state.token_hasher.clone());
         Ok(repo.boxed())
     }
 }
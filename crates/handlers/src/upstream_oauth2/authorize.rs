@@ -6,7 +6,7 @@
 
 use axum::{
     extract::{Path, Query, State},
-    response::{IntoResponse, Redirect},
+    response::{Html, IntoResponse, Redirect, Response},
 };
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, sentry::SentryEventID};
@@ -15,15 +15,16 @@ use mas_oidc_client::requests::authorization_code::AuthorizationRequestData;
 use mas_router::UrlBuilder;
 use mas_storage::{
     upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
-    BoxClock, BoxRepository, BoxRng,
+    BoxClock, BoxRepository, BoxRng, Clock,
 };
+use mas_templates::{ErrorContext, Templates};
 use thiserror::Error;
 use ulid::Ulid;
 
 use super::{cache::LazyProviderInfos, UpstreamSessionsCookie};
 use crate::{
     impl_from_error_for_route, upstream_oauth2::cache::MetadataCache,
-    views::shared::OptionalPostAuthAction,
+    views::shared::OptionalPostAuthAction, PreferredLanguage,
 };
 
 #[derive(Debug, Error)]
@@ -38,6 +39,7 @@ pub(crate) enum RouteError {
 impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
 impl_from_error_for_route!(mas_oidc_client::error::AuthorizationError);
 impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_templates::TemplateError);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -60,14 +62,16 @@ impl IntoResponse for RouteError {
 pub(crate) async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
     State(metadata_cache): State<MetadataCache>,
+    State(templates): State<Templates>,
     mut repo: BoxRepository,
     State(url_builder): State<UrlBuilder>,
     State(http_client): State<reqwest::Client>,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
     Query(query): Query<OptionalPostAuthAction>,
-) -> Result<impl IntoResponse, RouteError> {
+) -> Result<Response, RouteError> {
     let provider = repo
         .upstream_oauth_provider()
         .lookup(provider_id)
@@ -75,6 +79,27 @@ pub(crate) async fn get(
         .filter(UpstreamOAuthProvider::enabled)
         .ok_or(RouteError::ProviderNotFound)?;
 
+    // If the health checks found the provider to be down, tell the user now
+    // instead of sending them to a provider which will likely fail
+    let health = repo.upstream_oauth_provider().health(&provider).await?;
+    if health.is_some_and(|health| health.is_down(clock.now())) {
+        let name = provider
+            .human_name
+            .as_deref()
+            .unwrap_or(provider.issuer.as_str());
+
+        // TODO: translate
+        let ctx = ErrorContext::new()
+            .with_code("upstream_provider_unavailable")
+            .with_description(format!(
+                "{name} is currently unavailable. Please try again later, or use another way to sign in."
+            ))
+            .with_language(&locale);
+
+        let content = templates.render_error(&ctx)?;
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, Html(content)).into_response());
+    }
+
     // First, discover the provider
     // This is done lazyly according to provider.discovery_mode and the various
     // endpoint overrides
@@ -130,5 +155,5 @@ pub(crate) async fn get(
 
     repo.save().await?;
 
-    Ok((cookie_jar, Redirect::temporary(url.as_str())))
+    Ok((cookie_jar, Redirect::temporary(url.as_str())).into_response())
 }
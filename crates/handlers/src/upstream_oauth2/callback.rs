@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::{collections::HashMap, sync::LazyLock};
+
 use axum::{
     extract::{Path, State},
     http::Method,
@@ -11,12 +13,13 @@ use axum::{
     Form,
 };
 use axum_extra::response::Html;
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
-use mas_axum_utils::{cookies::CookieJar, sentry::SentryEventID};
-use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderResponseMode};
-use mas_jose::claims::TokenHash;
+use mas_axum_utils::{cookies::CookieJar, security_headers::CspNonce, sentry::SentryEventID};
+use mas_data_model::{SiteConfig, UpstreamOAuthProvider, UpstreamOAuthProviderResponseMode};
+use mas_jose::{claims::TokenHash, jwt::Jwt};
 use mas_keystore::{Encrypter, Keystore};
-use mas_oidc_client::requests::jose::JwtVerificationData;
+use mas_oidc_client::requests::jose::{IdTokenValidationOptions, JwtVerificationData};
 use mas_router::UrlBuilder;
 use mas_storage::{
     upstream_oauth2::{
@@ -27,8 +30,9 @@ use mas_storage::{
 };
 use mas_templates::{FormPostContext, Templates};
 use oauth2_types::{errors::ClientErrorCode, requests::AccessTokenRequest};
+use opentelemetry::{metrics::Histogram, KeyValue};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use thiserror::Error;
 use ulid::Ulid;
 
@@ -40,6 +44,45 @@ use super::{
 };
 use crate::{impl_from_error_for_route, upstream_oauth2::cache::MetadataCache, PreferredLanguage};
 
+static CLOCK_SKEW: LazyLock<Histogram<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_histogram("mas.upstream_oauth2.clock_skew")
+    .with_description(
+        "How far the issuance time of the ID tokens of upstream providers is from the current time",
+    )
+    .with_unit("s")
+    .init()
+});
+
+/// Record how far the `iat` claim of an ID token is from the current time, to
+/// spot the upstream providers with a drifting clock
+///
+/// This is done before verifying the token, so that the tokens rejected
+/// because of the skew are recorded too.
+fn record_clock_skew(provider: &UpstreamOAuthProvider, id_token: &str, now: DateTime<Utc>) {
+    let Ok(jwt) = Jwt::<HashMap<String, Value>>::try_from(id_token) else {
+        return;
+    };
+
+    let Some(iat) = jwt.payload().get("iat").and_then(Value::as_i64) else {
+        return;
+    };
+
+    let skew = now.timestamp().abs_diff(iat);
+    CLOCK_SKEW.record(
+        skew,
+        &[KeyValue::new(
+            "upstream_oauth_provider.id",
+            provider.id.to_string(),
+        )],
+    );
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Params {
     state: String,
@@ -158,9 +201,11 @@ pub(crate) async fn handler(
     State(keystore): State<Keystore>,
     State(client): State<reqwest::Client>,
     State(templates): State<Templates>,
+    State(site_config): State<SiteConfig>,
     method: Method,
     PreferredLanguage(locale): PreferredLanguage,
     cookie_jar: CookieJar,
+    csp_nonce: CspNonce,
     Path(provider_id): Path<Ulid>,
     params: Option<Form<Params>>,
 ) -> Result<Response, RouteError> {
@@ -197,7 +242,9 @@ pub(crate) async fn handler(
                     did_mas_repost_to_itself: true,
                     ..params
                 };
-                let context = FormPostContext::new_for_current_url(params).with_language(&locale);
+                let context = FormPostContext::new_for_current_url(params)
+                    .with_csp_nonce(Some(csp_nonce))
+                    .with_language(&locale);
                 let html = templates.render_form_post(&context)?;
                 return Ok(Html(html).into_response());
             }
@@ -289,11 +336,16 @@ pub(crate) async fn handler(
             client_id: &provider.client_id,
         };
 
-        // Decode and verify the ID token
-        let id_token = mas_oidc_client::requests::jose::verify_id_token(
+        record_clock_skew(&provider, id_token, clock.now());
+
+        // Decode and verify the ID token. The nonce is checked below, along
+        // with the hashes.
+        let id_token = mas_oidc_client::requests::jose::verify_id_token_with_options(
             id_token,
             verification_data,
             None,
+            None,
+            &IdTokenValidationOptions::default().with_clock_skew(site_config.jwt_leeway),
             clock.now(),
         )?;
 
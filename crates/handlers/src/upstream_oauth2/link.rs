@@ -17,7 +17,7 @@ use mas_axum_utils::{
     sentry::SentryEventID,
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{User, UserAgent};
+use mas_data_model::{LoginMethod, RegistrationMetadata, User, UserAgent};
 use mas_jose::jwt::Jwt;
 use mas_matrix::BoxHomeserverConnection;
 use mas_policy::Policy;
@@ -25,7 +25,9 @@ use mas_router::UrlBuilder;
 use mas_storage::{
     job::{JobRepositoryExt, ProvisionUserJob},
     upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository},
-    user::{BrowserSessionRepository, UserEmailRepository, UserRepository},
+    user::{
+        BrowserSessionRepository, UserEmailRepository, UserLoginHistoryRepository, UserRepository,
+    },
     BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
 use mas_templates::{
@@ -43,7 +45,8 @@ use super::{
     UpstreamSessionsCookie,
 };
 use crate::{
-    impl_from_error_for_route, views::shared::OptionalPostAuthAction, PreferredLanguage, SiteConfig,
+    impl_from_error_for_route, views::shared::OptionalPostAuthAction, BoundActivityTracker,
+    NetworkPolicy, NetworkPolicyError, NetworkScope, PreferredLanguage, RequestOrigin, SiteConfig,
 };
 
 const DEFAULT_LOCALPART_TEMPLATE: &str = "{{ user.preferred_username }}";
@@ -94,6 +97,9 @@ pub(crate) enum RouteError {
     #[error("Homeserver connection error")]
     HomeserverConnection(#[source] anyhow::Error),
 
+    #[error(transparent)]
+    NetworkPolicy(#[from] NetworkPolicyError),
+
     #[error(transparent)]
     Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
@@ -110,6 +116,11 @@ impl IntoResponse for RouteError {
         let event_id = sentry::capture_error(&self);
         let response = match self {
             Self::LinkNotFound => (StatusCode::NOT_FOUND, "Link not found").into_response(),
+            Self::NetworkPolicy(_) => (
+                StatusCode::FORBIDDEN,
+                "Logins are not allowed from this network",
+            )
+                .into_response(),
             Self::Internal(e) => FancyError::from(e).into_response(),
             e => FancyError::from(e).into_response(),
         };
@@ -198,10 +209,15 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(homeserver): State<BoxHomeserverConnection>,
+    activity_tracker: BoundActivityTracker,
+    (State(network_policy), origin): (State<NetworkPolicy>, RequestOrigin),
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     Path(link_id): Path<Ulid>,
 ) -> Result<impl IntoResponse, RouteError> {
+    // Check that logins are allowed from where the request comes from
+    network_policy.check(NetworkScope::Login, &origin)?;
+
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
     let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
     let (session_id, post_auth_action) = sessions_cookie
@@ -251,6 +267,18 @@ pub(crate) async fn get(
                 .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
                 .await?;
 
+            repo.user_login_history()
+                .record(
+                    &mut rng,
+                    &clock,
+                    &session.user,
+                    LoginMethod::UpstreamOAuth2,
+                    true,
+                    activity_tracker.ip(),
+                    session.user_agent.clone(),
+                )
+                .await?;
+
             cookie_jar = cookie_jar.set_session(&session);
 
             repo.save().await?;
@@ -311,6 +339,18 @@ pub(crate) async fn get(
                 .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
                 .await?;
 
+            repo.user_login_history()
+                .record(
+                    &mut rng,
+                    &clock,
+                    &session.user,
+                    LoginMethod::UpstreamOAuth2,
+                    true,
+                    activity_tracker.ip(),
+                    session.user_agent.clone(),
+                )
+                .await?;
+
             cookie_jar = sessions_cookie
                 .consume_link(link_id)?
                 .save(cookie_jar, &clock);
@@ -493,9 +533,14 @@ pub(crate) async fn post(
     State(homeserver): State<BoxHomeserverConnection>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    (State(network_policy), origin): (State<NetworkPolicy>, RequestOrigin),
     Path(link_id): Path<Ulid>,
     Form(form): Form<ProtectedForm<FormData>>,
 ) -> Result<Response, RouteError> {
+    // Check that logins are allowed from where the request comes from
+    network_policy.check(NetworkScope::Login, &origin)?;
+
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
     let form = cookie_jar.verify_form(&clock, form)?;
 
@@ -782,6 +827,19 @@ pub(crate) async fn post(
             // Now we can create the user
             let user = repo.user().add(&mut rng, &clock, username).await?;
 
+            let client_id = post_auth_action
+                .client_id(&mut repo)
+                .await
+                .map_err(|e| RouteError::Internal(e.into()))?;
+            let metadata = RegistrationMetadata {
+                client_id,
+                upstream_provider_id: Some(provider.id),
+                ..RegistrationMetadata::default()
+            };
+            repo.user()
+                .set_registration_metadata(&user, &metadata)
+                .await?;
+
             if let Some(terms_url) = &site_config.tos_uri {
                 repo.user_terms()
                     .accept_terms(&mut rng, &clock, &user, terms_url.clone())
@@ -841,6 +899,18 @@ pub(crate) async fn post(
         .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
         .await?;
 
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &session.user,
+            LoginMethod::UpstreamOAuth2,
+            true,
+            activity_tracker.ip(),
+            session.user_agent.clone(),
+        )
+        .await?;
+
     let cookie_jar = sessions_cookie
         .consume_link(link_id)?
         .save(cookie_jar, &clock);
@@ -865,8 +935,50 @@ mod tests {
     use oauth2_types::scope::{Scope, OPENID};
     use sqlx::PgPool;
 
+    use ulid::Ulid;
+
     use super::UpstreamSessionsCookie;
-    use crate::test_utils::{setup, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+    use crate::{
+        test_utils::{setup, CookieHelper, RequestBuilderExt, ResponseExt, TestState},
+        NetworkPolicy, RequestOrigin,
+    };
+
+    /// Test that upstream logins are denied from outside the allowed network
+    /// zones.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_link_network_policy(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.network_policy = NetworkPolicy::from_config(&mas_config::NetworkZonesConfig {
+            zones: vec![mas_config::NetworkZoneConfig {
+                name: "blocked".to_owned(),
+                cidrs: vec!["198.51.100.0/24".parse().unwrap()],
+                countries: Vec::new(),
+            }],
+            login: mas_config::NetworkPolicyConfig {
+                allow: Vec::new(),
+                deny: vec!["blocked".to_owned()],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let path = mas_router::UpstreamOAuth2Link::new(Ulid::nil()).path();
+        let origin = RequestOrigin::new(Some("198.51.100.1".parse().unwrap()), None);
+
+        let mut request = Request::get(&*path).empty();
+        request.extensions_mut().insert(origin.clone());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let mut request = Request::post(&*path).form(serde_json::json!({
+            "csrf": "csrf",
+            "action": "link",
+        }));
+        request.extensions_mut().insert(origin);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_register(pool: PgPool) {
@@ -13,24 +13,43 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::SiteConfig;
+use mas_data_model::{BrowserSession, SiteConfig};
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
-use mas_storage::{
-    job::{JobRepositoryExt, VerifyEmailJob},
-    user::UserEmailRepository,
-    BoxClock, BoxRepository, BoxRng,
-};
+use mas_storage::{user::UserEmailRepository, BoxClock, BoxRepository, BoxRng, RepositoryError};
 use mas_templates::{EmailAddContext, ErrorContext, TemplateContext, Templates};
 use serde::Deserialize;
 
-use crate::{views::shared::OptionalPostAuthAction, BoundActivityTracker, PreferredLanguage};
+use crate::{
+    email_verification::{self, SendCodeError},
+    session_elevation,
+    views::shared::OptionalPostAuthAction,
+    BoundActivityTracker, Limiter, PreferredLanguage,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct EmailForm {
     email: String,
 }
 
+/// Whether the user must authenticate again before adding an email address
+async fn reauthentication_required(
+    repo: &mut BoxRepository,
+    clock: &BoxClock,
+    site_config: &SiteConfig,
+    session: &BrowserSession,
+) -> Result<bool, RepositoryError> {
+    if !site_config.email_change_requires_reauthentication
+        || !session_elevation::can_reauthenticate(repo, site_config, &session.user).await?
+    {
+        return Ok(false);
+    }
+
+    let reauthenticated =
+        session_elevation::has_reauthenticated(repo, clock, Some(session), None).await?;
+    Ok(!reauthenticated)
+}
+
 #[tracing::instrument(name = "handlers.views.account_email_add.get", skip_all, err)]
 pub(crate) async fn get(
     mut rng: BoxRng,
@@ -62,6 +81,14 @@ pub(crate) async fn get(
         ));
     }
 
+    if reauthentication_required(&mut repo, &clock, &site_config, &session).await? {
+        return Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::Reauth::default()),
+        )
+            .into_response());
+    }
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
@@ -86,6 +113,7 @@ pub(crate) async fn post(
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
     Form(form): Form<ProtectedForm<EmailForm>>,
@@ -109,6 +137,14 @@ pub(crate) async fn post(
         ));
     }
 
+    if reauthentication_required(&mut repo, &clock, &site_config, &session).await? {
+        return Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::Reauth::default()),
+        )
+            .into_response());
+    }
+
     // Validate the email address
     if form.email.parse::<lettre::Address>().is_err() {
         return Err(anyhow::anyhow!("Invalid email address").into());
@@ -137,9 +173,22 @@ pub(crate) async fn post(
     // If the email was not confirmed, send a confirmation email & redirect to the
     // verify page
     let next = if user_email.confirmed_at.is_none() {
-        repo.job()
-            .schedule_job(VerifyEmailJob::new(&user_email).with_language(locale.to_string()))
-            .await?;
+        // If too many codes were sent already, the user can still use the last
+        // one, or ask for a new one later from the verify page
+        match email_verification::send_code(
+            &mut repo,
+            &limiter,
+            &user_email,
+            Some(locale.to_string()),
+        )
+        .await
+        {
+            Ok(()) => {}
+            Err(SendCodeError::RateLimited(e)) => {
+                tracing::warn!(error = &e as &dyn std::error::Error);
+            }
+            Err(e) => return Err(e.into()),
+        }
 
         let next = mas_router::AccountVerifyEmail::new(user_email.id);
         let next = if let Some(action) = query.post_auth_action {
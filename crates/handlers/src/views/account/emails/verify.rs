@@ -14,23 +14,45 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_router::UrlBuilder;
-use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob},
-    user::UserEmailRepository,
-    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+use mas_router::{EmailVerificationCode, UrlBuilder};
+use mas_storage::{user::UserEmailRepository, BoxClock, BoxRepository, BoxRng, RepositoryAccess};
+use mas_templates::{
+    EmailVerificationFormField, EmailVerificationPageContext, FormError, TemplateContext,
+    Templates, ToFormState,
 };
-use mas_templates::{EmailVerificationPageContext, TemplateContext, Templates};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
-use crate::{views::shared::OptionalPostAuthAction, BoundActivityTracker, PreferredLanguage};
+use crate::{
+    email_verification::{self, VerificationOutcome},
+    views::shared::OptionalPostAuthAction,
+    BoundActivityTracker, Limiter, PreferredLanguage,
+};
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeFormAction {
+    /// Submit the code
+    #[default]
+    Verify,
 
-#[derive(Deserialize, Debug)]
+    /// Send a new code
+    Resend,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct CodeForm {
+    #[serde(default)]
+    action: CodeFormAction,
+
+    #[serde(default)]
     code: String,
 }
 
+impl ToFormState for CodeForm {
+    type Field = EmailVerificationFormField;
+}
+
 #[tracing::instrument(
     name = "handlers.views.account_email_verify.get",
     fields(user_email.id = %id),
@@ -93,16 +115,21 @@ pub(crate) async fn get(
     err,
 )]
 pub(crate) async fn post(
+    mut rng: BoxRng,
     clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(limiter): State<Limiter>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
-    State(url_builder): State<UrlBuilder>,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
     Path(id): Path<Ulid>,
     Form(form): Form<ProtectedForm<CodeForm>>,
 ) -> Result<Response, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
     let maybe_session = session_info.load_session(&mut repo).await?;
@@ -112,6 +139,10 @@ pub(crate) async fn post(
         return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
     };
 
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
     let user_email = repo
         .user_email()
         .lookup(id)
@@ -119,38 +150,265 @@ pub(crate) async fn post(
         .filter(|u| u.user_id == session.user.id)
         .context("Could not find user email")?;
 
-    // XXX: this logic should be extracted somewhere else, since most of it is
-    // duplicated in mas_graphql
+    let mut state = form.to_form_state();
+
+    match form.action {
+        CodeFormAction::Resend => {
+            if user_email.confirmed_at.is_none() {
+                match email_verification::send_code(
+                    &mut repo,
+                    &limiter,
+                    &user_email,
+                    Some(locale.to_string()),
+                )
+                .await
+                {
+                    Ok(()) => {}
+                    Err(email_verification::SendCodeError::RateLimited(e)) => {
+                        tracing::warn!(error = &e as &dyn std::error::Error);
+                        state.add_error_on_form(FormError::RateLimitExceeded);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        CodeFormAction::Verify => {
+            let outcome = email_verification::verify_code(
+                &mut repo,
+                &clock,
+                &limiter,
+                user_email.clone(),
+                &form.code,
+            )
+            .await?;
+
+            match outcome {
+                VerificationOutcome::Verified(_) | VerificationOutcome::AlreadyVerified(_) => {
+                    repo.save().await?;
+
+                    let destination =
+                        query.go_next_or_default(&url_builder, &mas_router::Account::default());
+                    return Ok((cookie_jar, destination).into_response());
+                }
+
+                VerificationOutcome::InvalidCode => {
+                    state.add_error_on_form(FormError::InvalidCode);
+                }
+
+                VerificationOutcome::ExpiredCode => {
+                    state.add_error_on_form(FormError::ExpiredCode);
+                }
+
+                VerificationOutcome::TooManyAttempts => {
+                    state.add_error_on_form(FormError::RateLimitExceeded);
+                }
+            }
+        }
+    }
+
+    repo.save().await?;
+
+    let ctx = EmailVerificationPageContext::new(user_email)
+        .with_form_state(state)
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_account_verify_email(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
 
-    let verification = repo
+/// Verify an email address from the link sent in the verification email
+///
+/// This doesn't need a session, as the code in the link is proof enough that
+/// the user received the email.
+#[tracing::instrument(
+    name = "handlers.views.account_email_verify.link",
+    fields(user_email.id = %id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn link(
+    clock: BoxClock,
+    State(url_builder): State<UrlBuilder>,
+    State(limiter): State<Limiter>,
+    mut repo: BoxRepository,
+    Path(id): Path<Ulid>,
+    Query(EmailVerificationCode { code }): Query<EmailVerificationCode>,
+) -> Result<Response, FancyError> {
+    let user_email = repo
         .user_email()
-        .find_verification_code(&clock, &user_email, &form.code)
+        .lookup(id)
         .await?
-        .context("Invalid code")?;
+        .context("Could not find user email")?;
+
+    let outcome =
+        email_verification::verify_code(&mut repo, &clock, &limiter, user_email, &code).await?;
+
+    repo.save().await?;
+
+    let response = match outcome {
+        VerificationOutcome::Verified(_) | VerificationOutcome::AlreadyVerified(_) => {
+            url_builder.redirect(&mas_router::Account::default())
+        }
 
-    // TODO: display nice errors if the code was already consumed or expired
-    repo.user_email()
-        .consume_verification_code(&clock, verification)
-        .await?;
+        // Let the user type in the code or ask for a new one
+        VerificationOutcome::InvalidCode
+        | VerificationOutcome::ExpiredCode
+        | VerificationOutcome::TooManyAttempts => {
+            url_builder.redirect(&mas_router::AccountVerifyEmail::new(id))
+        }
+    };
+
+    Ok(response.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_router::Route;
+    use mas_storage::{
+        user::{UserEmailRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_verify_email_link(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let user_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, "john@example.com".to_owned())
+            .await
+            .unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut rng,
+                &state.clock,
+                &user_email,
+                Duration::try_hours(8).unwrap(),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let verify_page = mas_router::AccountVerifyEmail::new(user_email.id).path_and_query();
+        let link = |code: &str| {
+            mas_router::AccountVerifyEmailLink::new(user_email.id, code.to_owned()).path_and_query()
+        };
 
-    if session.user.primary_user_email_id.is_none() {
-        repo.user_email().set_as_primary(&user_email).await?;
+        // A wrong code sends the user to the page to type in the code
+        let response = state.request(Request::get(&*link("000000")).empty()).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, &verify_page);
+
+        // So does an expired one
+        state.clock.advance(Duration::try_hours(9).unwrap());
+        let response = state.request(Request::get(&*link("123456")).empty()).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, &verify_page);
+
+        let mut repo = state.repository().await.unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut rng,
+                &state.clock,
+                &user_email,
+                Duration::try_hours(8).unwrap(),
+                "654321".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = state.request(Request::get(&*link("654321")).empty()).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, &mas_router::Account::default().path_and_query());
+
+        // The email is verified, and became the primary one
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .lookup(user_email.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user_email.confirmed_at.is_some());
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert_eq!(user.primary_user_email_id, Some(user_email.id));
     }
 
-    repo.user_email()
-        .mark_as_verified(&clock, user_email)
-        .await?;
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_verify_email_attempts_limit(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
 
-    repo.job()
-        .schedule_job(ProvisionUserJob::new(&session.user))
-        .await?;
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let user_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, "john@example.com".to_owned())
+            .await
+            .unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut rng,
+                &state.clock,
+                &user_email,
+                Duration::try_hours(8).unwrap(),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
 
-    repo.save().await?;
+        // Use up all the attempts with wrong codes
+        for _ in 0..10 {
+            let link = mas_router::AccountVerifyEmailLink::new(user_email.id, "000000".to_owned());
+            let response = state
+                .request(Request::get(&*link.path_and_query()).empty())
+                .await;
+            response.assert_status(StatusCode::SEE_OTHER);
+        }
 
-    activity_tracker
-        .record_browser_session(&clock, &session)
-        .await;
+        // Even the right code is now rejected
+        let link = mas_router::AccountVerifyEmailLink::new(user_email.id, "123456".to_owned());
+        let response = state
+            .request(Request::get(&*link.path_and_query()).empty())
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(
+            LOCATION,
+            &mas_router::AccountVerifyEmail::new(user_email.id).path_and_query(),
+        );
 
-    let destination = query.go_next_or_default(&url_builder, &mas_router::Account::default());
-    Ok((cookie_jar, destination).into_response())
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .lookup(user_email.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user_email.confirmed_at.is_none());
+    }
 }
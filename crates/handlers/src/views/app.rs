@@ -8,7 +8,7 @@ use axum::{
     extract::{Query, State},
     response::{Html, IntoResponse},
 };
-use mas_axum_utils::{cookies::CookieJar, FancyError, SessionInfoExt};
+use mas_axum_utils::{cookies::CookieJar, security_headers::CspNonce, FancyError, SessionInfoExt};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{BoxClock, BoxRepository};
 use mas_templates::{AppContext, TemplateContext, Templates};
@@ -25,6 +25,7 @@ pub async fn get(
     mut repo: BoxRepository,
     clock: BoxClock,
     cookie_jar: CookieJar,
+    csp_nonce: CspNonce,
 ) -> Result<impl IntoResponse, FancyError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
     let session = session_info.load_session(&mut repo).await?;
@@ -45,7 +46,9 @@ pub async fn get(
         .record_browser_session(&clock, &session)
         .await;
 
-    let ctx = AppContext::from_url_builder(&url_builder).with_language(locale);
+    let ctx = AppContext::from_url_builder(&url_builder)
+        .with_csp_nonce(Some(csp_nonce))
+        .with_language(locale);
     let content = templates.render_app(&ctx)?;
 
     Ok((cookie_jar, Html(content)).into_response())
@@ -59,8 +62,11 @@ pub async fn get_anonymous(
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    csp_nonce: CspNonce,
 ) -> Result<impl IntoResponse, FancyError> {
-    let ctx = AppContext::from_url_builder(&url_builder).with_language(locale);
+    let ctx = AppContext::from_url_builder(&url_builder)
+        .with_csp_nonce(Some(csp_nonce))
+        .with_language(locale);
     let content = templates.render_app(&ctx)?;
 
     Ok(Html(content).into_response())
@@ -0,0 +1,397 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::typed_header::TypedHeader;
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    security_headers::CspNonce,
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::{LoginMethod, UserAgent};
+use mas_matrix::BoxHomeserverConnection;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository,
+    user::{BrowserSessionRepository, UserLoginHistoryRepository},
+    BoxClock, BoxRepository, BoxRng,
+};
+use mas_templates::{FormError, FormState, LoginContext, LoginFormField, Templates};
+
+use super::{login::render, shared::OptionalPostAuthAction};
+use crate::{
+    client_certificate::{self, ClientCertificate},
+    BoundActivityTracker, NetworkPolicy, NetworkScope, PreferredLanguage, RequestOrigin,
+    SiteConfig,
+};
+
+#[tracing::instrument(name = "handlers.views.certificate_login.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(site_config): State<SiteConfig>,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    csp_nonce: CspNonce,
+    client_certificate: ClientCertificate,
+    (State(network_policy), origin): (State<NetworkPolicy>, RequestOrigin),
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Form(form): Form<ProtectedForm<()>>,
+) -> Result<Response, FancyError> {
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let Some(config) = &site_config.certificate_login else {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    };
+
+    cookie_jar.verify_form(&clock, form)?;
+
+    // Check that logins are allowed from where the request comes from
+    let result = if network_policy.check(NetworkScope::Login, &origin).is_err() {
+        Err(FormError::DeniedNetwork)
+    } else {
+        let result = match client_certificate.identity() {
+            Ok(identity) => client_certificate::find_user(&mut repo, config, &identity)
+                .await
+                .map(|user| (user, identity)),
+            Err(e) => Err(e),
+        };
+
+        result.map_err(|e| {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Could not log in with the client certificate"
+            );
+            FormError::ClientCertificate
+        })
+    };
+
+    let (user, identity) = match result {
+        Ok(found) => found,
+        Err(error) => {
+            let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+            let providers = repo.upstream_oauth_provider().all_enabled().await?;
+            let state = FormState::<LoginFormField>::default().with_error_on_form(error);
+
+            let content = render(
+                locale,
+                LoginContext::default()
+                    .with_form_state(state)
+                    .with_upstream_providers(providers),
+                query,
+                csrf_token,
+                csp_nonce,
+                None,
+                &mut repo,
+                &templates,
+                homeserver,
+            )
+            .await?;
+
+            return Ok((cookie_jar, Html(content)).into_response());
+        }
+    };
+
+    // Start a new session, authenticated by the certificate
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, user_agent.clone())
+        .await?;
+
+    repo.browser_session()
+        .authenticate_with_client_certificate(&mut rng, &clock, &session, identity.fingerprint())
+        .await?;
+
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &user,
+            LoginMethod::ClientCertificate,
+            true,
+            activity_tracker.ip(),
+            user_agent,
+        )
+        .await?;
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let cookie_jar = cookie_jar.set_session(&session);
+    let reply = query.go_next(&url_builder);
+    Ok((cookie_jar, reply).into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Request, Response, StatusCode};
+    use mas_data_model::{CertificateLoginConfig, CertificateLookup, User};
+    use mas_storage::{
+        user::{
+            BrowserSessionFilter, BrowserSessionRepository, UserEmailRepository, UserRepository,
+        },
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::{
+        client_certificate::ClientCertificate,
+        test_utils::{
+            setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+        },
+        SiteConfig,
+    };
+
+    /// Certificate for `CN=alice`, with the `alice@example.com` email in its SAN
+    const ALICE: &[u8] = include_bytes!("../../tests/certs/alice.der");
+
+    /// Certificate for `CN=bob`, with the `bob@example.com` email in its SAN
+    const BOB: &[u8] = include_bytes!("../../tests/certs/bob.der");
+
+    async fn state_with_lookup(
+        pool: PgPool,
+        template: &str,
+        lookup: CertificateLookup,
+    ) -> TestState {
+        TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                certificate_login: Some(CertificateLoginConfig {
+                    template: template.to_owned(),
+                    lookup,
+                }),
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Submit the certificate login form, as if the given certificate was
+    /// presented during the TLS handshake
+    async fn submit(state: &TestState, certificate: &[u8]) -> Response<String> {
+        let cookies = CookieHelper::new();
+        let request = cookies.with_cookies(Request::get("/login").empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let mut request = Request::post("/login/certificate").form(serde_json::json!({
+            "csrf": csrf_token,
+        }));
+        request
+            .extensions_mut()
+            .insert(ClientCertificate::new(Some(certificate.to_vec())));
+        let request = cookies.with_cookies(request);
+        state.request(request).await
+    }
+
+    fn assert_refused(response: &Response<String>) {
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("No valid certificate matching an account was presented"));
+    }
+
+    async fn add_user(state: &TestState, username: &str) -> User {
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, username.to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        user
+    }
+
+    async fn add_verified_email(state: &TestState, user: &User, email: &str) {
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .add(&mut state.rng(), &state.clock, user, email.to_owned())
+            .await
+            .unwrap();
+        repo.user_email()
+            .mark_as_verified(&state.clock, user_email)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+    }
+
+    async fn active_sessions(state: &TestState, user: &User) -> usize {
+        let mut repo = state.repository().await.unwrap();
+        let count = repo
+            .browser_session()
+            .count(BrowserSessionFilter::new().for_user(user).active_only())
+            .await
+            .unwrap();
+        repo.cancel().await.unwrap();
+        count
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                certificate_login: Some(CertificateLoginConfig {
+                    template: "{{ subject.cn }}".to_owned(),
+                    lookup: CertificateLookup::Username,
+                }),
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let cookies = CookieHelper::new();
+
+        // The login page offers to log in with a certificate
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("Continue with a certificate"));
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // Without a certificate, the login page is shown again with an error
+        let request = Request::post("/login/certificate").form(serde_json::json!({
+            "csrf": csrf_token,
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("No valid certificate matching an account was presented"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login_username(pool: PgPool) {
+        setup();
+        let state = state_with_lookup(pool, "{{ subject.cn }}", CertificateLookup::Username).await;
+
+        // No user matches the certificate yet
+        assert_refused(&submit(&state, ALICE).await);
+
+        let alice = add_user(&state, "alice").await;
+        let response = submit(&state, ALICE).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_eq!(active_sessions(&state, &alice).await, 1);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login_email(pool: PgPool) {
+        setup();
+        let state = state_with_lookup(pool, "{{ san.email[0] }}", CertificateLookup::Email).await;
+
+        // The email must be verified
+        let alice = add_user(&state, "alice").await;
+        let mut repo = state.repository().await.unwrap();
+        repo.user_email()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &alice,
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        assert_refused(&submit(&state, ALICE).await);
+
+        add_verified_email(&state, &alice, "alice@example.com").await;
+        let response = submit(&state, ALICE).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_eq!(active_sessions(&state, &alice).await, 1);
+
+        // Users sharing the same verified email can't be told apart
+        let bob = add_user(&state, "bob").await;
+        let robert = add_user(&state, "robert").await;
+        add_verified_email(&state, &bob, "bob@example.com").await;
+        add_verified_email(&state, &robert, "bob@example.com").await;
+        assert_refused(&submit(&state, BOB).await);
+        assert_eq!(active_sessions(&state, &bob).await, 0);
+        assert_eq!(active_sessions(&state, &robert).await, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login_inactive_user(pool: PgPool) {
+        setup();
+        let state = state_with_lookup(pool, "{{ subject.cn }}", CertificateLookup::Username).await;
+
+        let alice = add_user(&state, "alice").await;
+        let bob = add_user(&state, "bob").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo.user().lock(&state.clock, alice).await.unwrap();
+        let bob = repo.user().delete(&state.clock, bob).await.unwrap();
+        repo.save().await.unwrap();
+
+        // Locked users can't log in
+        assert_refused(&submit(&state, ALICE).await);
+        assert_eq!(active_sessions(&state, &alice).await, 0);
+
+        // Neither can deactivated ones
+        assert_refused(&submit(&state, BOB).await);
+        assert_eq!(active_sessions(&state, &bob).await, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login_other_listener(pool: PgPool) {
+        setup();
+        let mut state =
+            state_with_lookup(pool, "{{ subject.cn }}", CertificateLookup::Username).await;
+        let alice = add_user(&state, "alice").await;
+
+        // Certificates presented to a listener which isn't used to log in are ignored
+        state.client_certificate_login = false;
+        assert_refused(&submit(&state, ALICE).await);
+        assert_eq!(active_sessions(&state, &alice).await, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_certificate_login_disabled(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::post("/login/certificate").form(serde_json::json!({
+            "csrf": "csrf",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
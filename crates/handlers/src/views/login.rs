@@ -9,19 +9,23 @@ use axum::{
     response::{Html, IntoResponse, Response},
 };
 use axum_extra::typed_header::TypedHeader;
-use hyper::StatusCode;
+use hyper::{HeaderMap, StatusCode};
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    security_headers::CspNonce,
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{oauth2::LoginHint, BrowserSession, UserAgent};
+use mas_data_model::{oauth2::LoginHint, BrowserSession, CaptchaConfig, LoginMethod, UserAgent};
 use mas_i18n::DataLocale;
 use mas_matrix::BoxHomeserverConnection;
 use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
 use mas_storage::{
     upstream_oauth2::UpstreamOAuthProviderRepository,
-    user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
+    user::{
+        BrowserSessionRepository, UserLoginHistoryRepository, UserPasswordRepository,
+        UserRepository,
+    },
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{
@@ -34,14 +38,24 @@ use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
 use crate::{
-    passwords::PasswordManager, BoundActivityTracker, Limiter, PreferredLanguage,
-    RequesterFingerprint, SiteConfig,
+    bot_detection::{self, Form as BotDetectionForm, Verdict},
+    captcha::Form as CaptchaForm,
+    experiments::Experiments,
+    passwords::{PasswordHashingBusyError, PasswordManager},
+    BoundActivityTracker, Limiter, NetworkPolicy, NetworkScope, PreferredLanguage, RequestOrigin,
+    RequesterFingerprint, RiskAssessor, RiskContext, RiskEvent, RiskVerdict, SiteConfig,
 };
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct LoginForm {
     username: String,
     password: String,
+
+    #[serde(flatten, skip_serializing)]
+    bot_detection: BotDetectionForm,
+
+    #[serde(flatten, skip_serializing)]
+    captcha: CaptchaForm,
 }
 
 impl ToFormState for LoginForm {
@@ -59,6 +73,7 @@ pub(crate) async fn get(
     State(homeserver): State<BoxHomeserverConnection>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    csp_nonce: CspNonce,
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
@@ -72,6 +87,15 @@ pub(crate) async fn get(
             .record_browser_session(&clock, &session)
             .await;
 
+        if repo
+            .user()
+            .is_password_change_required(&session.user)
+            .await?
+        {
+            let destination = mas_router::PasswordChangeRequired::from(query.post_auth_action);
+            return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+        }
+
         let reply = query.go_next(&url_builder);
         return Ok((cookie_jar, reply).into_response());
     };
@@ -92,21 +116,32 @@ pub(crate) async fn get(
         return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
     };
 
+    let (experiments, cookie_jar) =
+        Experiments::load(cookie_jar, &clock, &mut rng, &site_config.feature_flags);
+    experiments.record_exposures(&mut repo, &clock).await?;
+
     let content = render(
         locale,
-        LoginContext::default().with_upstream_providers(providers),
+        LoginContext::default()
+            .with_upstream_providers(providers)
+            .with_experiments(experiments.variants()),
         query,
         csrf_token,
+        csp_nonce,
+        None,
         &mut repo,
         &templates,
         homeserver,
     )
     .await?;
 
+    repo.save().await?;
+
     Ok((cookie_jar, Html(content)).into_response())
 }
 
 #[tracing::instrument(name = "handlers.views.login.post", skip_all, err)]
+#[allow(clippy::too_many_lines)]
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
@@ -115,14 +150,20 @@ pub(crate) async fn post(
     State(site_config): State<SiteConfig>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
-    State(limiter): State<Limiter>,
+    (State(limiter), State(risk_assessor), State(network_policy), requester, origin): (
+        State<Limiter>,
+        State<RiskAssessor>,
+        State<NetworkPolicy>,
+        RequesterFingerprint,
+        RequestOrigin,
+    ),
     State(homeserver): State<BoxHomeserverConnection>,
+    State(http_client): State<reqwest::Client>,
     mut repo: BoxRepository,
-    activity_tracker: BoundActivityTracker,
-    requester: RequesterFingerprint,
+    (activity_tracker, csp_nonce): (BoundActivityTracker, CspNonce),
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
-    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    (user_agent, request_headers): (Option<TypedHeader<headers::UserAgent>>, HeaderMap),
     Form(form): Form<ProtectedForm<LoginForm>>,
 ) -> Result<Response, FancyError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
@@ -134,11 +175,76 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (experiments, cookie_jar) =
+        Experiments::load(cookie_jar, &clock, &mut rng, &site_config.feature_flags);
+
+    // Check that logins are allowed from where the request comes from
+    let network_allowed = network_policy.check(NetworkScope::Login, &origin).is_ok();
+
+    // Look for signs of an automated submission. The challenge was computed from
+    // the CSRF token the form was rendered with, which was just verified.
+    let verdict = bot_detection::evaluate(
+        site_config.bot_detection.as_ref(),
+        &limiter,
+        requester,
+        &request_headers,
+        &form.bot_detection,
+        &csrf_token.form_value(),
+    );
+
+    // Consult the external risk provider, unless the submission is already
+    // rejected
+    let risk = if !network_allowed || verdict == Verdict::Block || form.username.is_empty() {
+        RiskVerdict::Allow
+    } else {
+        risk_assessor
+            .assess(
+                RiskContext {
+                    event: RiskEvent::Login,
+                    username: form.username.clone(),
+                    email: None,
+                    ip: activity_tracker.ip(),
+                    user_agent: None,
+                    device_type: None,
+                    client_app: None,
+                }
+                .with_user_agent(user_agent.as_ref()),
+            )
+            .await
+    };
+
+    // Suspicious submissions require a CAPTCHA, if one is configured
+    let captcha = if verdict == Verdict::Captcha || risk == RiskVerdict::Challenge {
+        site_config.captcha.clone()
+    } else {
+        None
+    };
 
     // Validate the form
     let state = {
         let mut state = form.to_form_state();
 
+        if !network_allowed {
+            state.add_error_on_form(FormError::DeniedNetwork);
+        } else if verdict == Verdict::Block {
+            state.add_error_on_form(FormError::Suspicious);
+        } else if risk == RiskVerdict::Deny {
+            state.add_error_on_form(FormError::DeniedRisk);
+        } else if captcha.is_some()
+            && form
+                .captcha
+                .verify(
+                    &activity_tracker,
+                    &http_client,
+                    url_builder.public_hostname(),
+                    captcha.as_ref(),
+                )
+                .await
+                .is_err()
+        {
+            state.add_error_on_form(FormError::Captcha);
+        }
+
         if form.username.is_empty() {
             state.add_error_on_field(LoginFormField::Username, FieldError::Required);
         }
@@ -156,9 +262,12 @@ pub(crate) async fn post(
             locale,
             LoginContext::default()
                 .with_form_state(state)
-                .with_upstream_providers(providers),
+                .with_upstream_providers(providers)
+                .with_experiments(experiments.variants()),
             query,
             csrf_token,
+            csp_nonce,
+            captcha,
             &mut repo,
             &templates,
             homeserver,
@@ -182,6 +291,13 @@ pub(crate) async fn post(
     .await
     {
         Ok(session_info) => {
+            let password_change_required = repo
+                .user()
+                .is_password_change_required(&session_info.user)
+                .await?;
+
+            experiments.record_conversion(&mut repo, &clock).await?;
+
             repo.save().await?;
 
             activity_tracker
@@ -189,29 +305,52 @@ pub(crate) async fn post(
                 .await;
 
             let cookie_jar = cookie_jar.set_session(&session_info);
+
+            // If an admin reset the password, or if it expired, the user has to
+            // change it before anything else happens
+            if password_change_required {
+                let destination = mas_router::PasswordChangeRequired::from(query.post_auth_action);
+                return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+            }
+
             let reply = query.go_next(&url_builder);
             Ok((cookie_jar, reply).into_response())
         }
         Err(e) => {
+            // Count failed attempts for the admin dashboard
+            let failed = matches!(e, FormError::InvalidCredentials);
+            if failed {
+                repo.stats().record_login_failure(&clock).await?;
+            }
+
             let state = state.with_error_on_form(e);
 
             let content = render(
                 locale,
-                LoginContext::default().with_form_state(state),
+                LoginContext::default()
+                    .with_form_state(state)
+                    .with_experiments(experiments.variants()),
                 query,
                 csrf_token,
+                csp_nonce,
+                captcha,
                 &mut repo,
                 &templates,
                 homeserver,
             )
             .await?;
 
+            if failed {
+                repo.save().await?;
+            }
+
             Ok((cookie_jar, Html(content)).into_response())
         }
     }
 }
 
 // TODO: move that logic elsewhere?
+#[allow(clippy::too_many_lines)]
 async fn login(
     password_manager: PasswordManager,
     repo: &mut impl RepositoryAccess,
@@ -250,7 +389,7 @@ async fn login(
     let password = Zeroizing::new(password.as_bytes().to_vec());
 
     // Verify the password, and upgrade it on-the-fly if needed
-    let new_password_hash = password_manager
+    let new_password_hash = match password_manager
         .verify_and_upgrade(
             &mut rng,
             user_password.version,
@@ -258,7 +397,49 @@ async fn login(
             user_password.hashed_password.clone(),
         )
         .await
-        .map_err(|_| FormError::InvalidCredentials)?;
+    {
+        Ok(new_password_hash) => new_password_hash,
+        Err(e) if e.is::<PasswordHashingBusyError>() => {
+            return Err(FormError::TemporarilyUnavailable);
+        }
+        Err(_) => {
+            // Let the user know about the failed attempt in their login history
+            repo.user_login_history()
+                .record(
+                    &mut rng,
+                    clock,
+                    &user,
+                    LoginMethod::Password,
+                    false,
+                    requester.ip(),
+                    user_agent,
+                )
+                .await
+                .map_err(|_| FormError::Internal)?;
+            return Err(FormError::InvalidCredentials);
+        }
+    };
+
+    // Passwords past the maximum age of the user's policy have to be changed
+    // before the login completes
+    let policy_class = repo
+        .user()
+        .get_password_policy_class(&user)
+        .await
+        .map_err(|_| FormError::Internal)?;
+    let expired = password_manager
+        .is_password_expired(
+            policy_class.as_deref(),
+            user_password.created_at,
+            clock.now(),
+        )
+        .map_err(|_| FormError::Internal)?;
+    if expired {
+        repo.user()
+            .set_password_change_required(clock, &user, true)
+            .await
+            .map_err(|_| FormError::Internal)?;
+    }
 
     let user_password = if let Some((version, new_password_hash)) = new_password_hash {
         // Save the upgraded password
@@ -280,7 +461,7 @@ async fn login(
     // Start a new session
     let user_session = repo
         .browser_session()
-        .add(&mut rng, clock, &user, user_agent)
+        .add(&mut rng, clock, &user, user_agent.clone())
         .await
         .map_err(|_| FormError::Internal)?;
 
@@ -290,6 +471,19 @@ async fn login(
         .await
         .map_err(|_| FormError::Internal)?;
 
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            clock,
+            &user,
+            LoginMethod::Password,
+            true,
+            requester.ip(),
+            user_agent,
+        )
+        .await
+        .map_err(|_| FormError::Internal)?;
+
     Ok(user_session)
 }
 
@@ -314,11 +508,14 @@ fn handle_login_hint(
     }
 }
 
-async fn render(
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn render(
     locale: DataLocale,
     mut ctx: LoginContext,
     action: OptionalPostAuthAction,
     csrf_token: CsrfToken,
+    csp_nonce: CspNonce,
+    captcha_config: Option<CaptchaConfig>,
     repo: &mut impl RepositoryAccess,
     templates: &Templates,
     homeserver: BoxHomeserverConnection,
@@ -331,7 +528,11 @@ async fn render(
     } else {
         ctx
     };
-    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+    let ctx = ctx
+        .with_captcha(captcha_config)
+        .with_csp_nonce(Some(csp_nonce))
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
 
     let content = templates.render_login(&ctx)?;
     Ok(content)
@@ -339,12 +540,16 @@ async fn render(
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use hyper::{
-        header::{CONTENT_TYPE, LOCATION},
+        header::{ACCEPT_LANGUAGE, CONTENT_TYPE, LOCATION, USER_AGENT},
         Request, StatusCode,
     };
+    use mas_config::RiskConfig;
     use mas_data_model::{
-        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+        BotDetectionConfig, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderTokenAuthMethod,
     };
     use mas_router::Route;
     use mas_storage::{
@@ -354,13 +559,18 @@ mod test {
     use mas_templates::escape_html;
     use oauth2_types::scope::OPENID;
     use sqlx::PgPool;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
     use zeroize::Zeroizing;
 
     use crate::{
+        passwords::PasswordPolicy,
         test_utils::{
             setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
         },
-        SiteConfig,
+        RiskAssessor, SiteConfig,
     };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -537,6 +747,237 @@ mod test {
         assert!(response.body().contains("john"));
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_expired_password(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.password_manager = state.password_manager.clone().with_policy_classes(
+            Some(std::time::Duration::from_secs(90 * 24 * 60 * 60)),
+            HashMap::from([(
+                "service".to_owned(),
+                PasswordPolicy {
+                    minimum_complexity: 0,
+                    minimum_length: 0,
+                    max_age: None,
+                },
+            )]),
+        );
+        let mut rng = state.rng();
+
+        // Provision a human and a service account with passwords
+        let mut repo = state.repository().await.unwrap();
+        for username in ["john", "bot"] {
+            let user = repo
+                .user()
+                .add(&mut rng, &state.clock, username.to_owned())
+                .await
+                .unwrap();
+            let (version, hash) = state
+                .password_manager
+                .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+                .await
+                .unwrap();
+            repo.user_password()
+                .add(&mut rng, &state.clock, &user, version, hash, None)
+                .await
+                .unwrap();
+
+            if username == "bot" {
+                repo.user()
+                    .set_password_policy_class(&state.clock, &user, Some("service"))
+                    .await
+                    .unwrap();
+            }
+        }
+        repo.save().await.unwrap();
+
+        state.clock.advance(chrono::Duration::try_days(91).unwrap());
+
+        let login = |username: &'static str| {
+            let state = &state;
+            async move {
+                let cookies = CookieHelper::new();
+                let request = cookies.with_cookies(Request::get("/login").empty());
+                let response = state.request(request).await;
+                cookies.save_cookies(&response);
+                let csrf_token = response
+                    .body()
+                    .split("name=\"csrf\" value=\"")
+                    .nth(1)
+                    .unwrap()
+                    .split('\"')
+                    .next()
+                    .unwrap()
+                    .to_owned();
+
+                let request = Request::post("/login").form(serde_json::json!({
+                    "csrf": csrf_token,
+                    "username": username,
+                    "password": "hunter2",
+                }));
+                state.request(cookies.with_cookies(request)).await
+            }
+        };
+
+        // The password of the human expired, so they have to change it
+        let response = login("john").await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(
+            LOCATION,
+            &mas_router::PasswordChangeRequired::from(None).path_and_query(),
+        );
+
+        // The password of the service account never expires
+        let response = login("bot").await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_ne!(
+            response.headers().get(LOCATION).unwrap().to_str().unwrap(),
+            mas_router::PasswordChangeRequired::from(None).path_and_query()
+        );
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_bot_detection(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                bot_detection: Some(BotDetectionConfig {
+                    captcha_threshold: 3,
+                    block_threshold: 8,
+                }),
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // An automation tool which didn't run the JavaScript challenge is rejected
+        let request = Request::post("/login")
+            .header(USER_AGENT, "curl/8.5.0")
+            .form(serde_json::json!({
+                "csrf": csrf_token,
+                "username": "john",
+                "password": "hunter2",
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("looks automated"));
+
+        // A browser which ran the JavaScript challenge is let through
+        let challenge_response: String = csrf_token.chars().rev().collect();
+        let request = Request::post("/login")
+            .header(
+                USER_AGENT,
+                "Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Firefox/130.0",
+            )
+            .header(ACCEPT_LANGUAGE, "en")
+            .form(serde_json::json!({
+                "csrf": csrf_token,
+                "username": "john",
+                "password": "hunter2",
+                "bot_challenge": challenge_response,
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_risk_provider(pool: PgPool) {
+        setup();
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/assess"))
+            .and(body_partial_json(serde_json::json!({
+                "event": "login",
+                "username": "john",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "verdict": "deny",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.risk_assessor = RiskAssessor::from_config(
+            &RiskConfig {
+                endpoint: Some(format!("{}/assess", mock_server.uri()).parse().unwrap()),
+                ..RiskConfig::default()
+            },
+            state.http_client.clone(),
+        );
+        let cookies = CookieHelper::new();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // The risk provider denies the attempt
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("flagged as risky"));
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_password_login_rate_limit(pool: PgPool) {
         setup();
@@ -0,0 +1,312 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Pick the upstream provider to log in with from the Matrix ID of an account
+//! on another homeserver.
+//!
+//! The homeserver is found from the server name of the Matrix ID, through its
+//! client well-known document. If it delegates its authentication to the
+//! issuer of one of the configured upstream providers, the user is sent to
+//! that provider. Issuers which aren't configured are not supported: there is
+//! no dynamic client registration with them.
+//!
+//! Those lookups are rate limited per IP address, as anyone can trigger them.
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    security_headers::CspNonce,
+    FancyError,
+};
+use mas_matrix::BoxHomeserverConnection;
+use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository, BoxClock, BoxRepository, BoxRng,
+};
+use mas_templates::{FieldError, FormError, LoginContext, LoginFormField, Templates, ToFormState};
+use serde::{Deserialize, Serialize};
+
+use super::{login::render, shared::OptionalPostAuthAction};
+use crate::{
+    homeserver_discovery::HomeserverDiscovery, Limiter, NetworkPolicy, NetworkScope,
+    PreferredLanguage, RequestOrigin, RequesterFingerprint, SiteConfig,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct MatrixIdLoginForm {
+    mxid: String,
+}
+
+impl ToFormState for MatrixIdLoginForm {
+    type Field = LoginFormField;
+}
+
+/// Extract the server name of a Matrix ID, like `example.org:8448` from
+/// `@alice:example.org:8448`
+fn server_name(mxid: &str) -> Option<&str> {
+    let (localpart, server_name) = mxid.strip_prefix('@')?.split_once(':')?;
+    let valid = !localpart.is_empty()
+        && !server_name.is_empty()
+        && server_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+
+    valid.then_some(server_name)
+}
+
+#[tracing::instrument(name = "handlers.views.matrix_id_login.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(site_config): State<SiteConfig>,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    State(homeserver_discovery): State<HomeserverDiscovery>,
+    (State(limiter), requester): (State<Limiter>, RequesterFingerprint),
+    (State(network_policy), origin): (State<NetworkPolicy>, RequestOrigin),
+    mut repo: BoxRepository,
+    csp_nonce: CspNonce,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<MatrixIdLoginForm>>,
+) -> Result<Response, FancyError> {
+    if !site_config.matrix_id_login_enabled {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+
+    let state = form.to_form_state();
+    let result = match server_name(form.mxid.trim()) {
+        None => Err(state.with_error_on_field(LoginFormField::Mxid, FieldError::Invalid)),
+
+        // Check that logins are allowed from where the request comes from
+        Some(_) if network_policy.check(NetworkScope::Login, &origin).is_err() => {
+            Err(state.with_error_on_form(FormError::DeniedNetwork))
+        }
+
+        // Users of this homeserver log in with the other methods
+        Some(server_name) if server_name == site_config.server_name => {
+            Err(state.with_error_on_field(LoginFormField::Mxid, FieldError::Unsupported))
+        }
+
+        // Check the rate limit before making a request to the other homeserver
+        Some(_) if limiter.check_matrix_id_login(requester).is_err() => {
+            tracing::warn!(%requester, "Too many Matrix ID logins");
+            Err(state.with_error_on_form(FormError::RateLimitExceeded))
+        }
+
+        Some(server_name) => {
+            let issuer = homeserver_discovery.discover_issuer(server_name).await;
+
+            // Compare the issuers without their trailing slash, as homeservers
+            // and providers don't always agree on it
+            issuer
+                .and_then(|issuer| {
+                    let issuer = issuer.trim_end_matches('/');
+                    providers
+                        .iter()
+                        .find(|provider| provider.issuer.trim_end_matches('/') == issuer)
+                })
+                .map(|provider| provider.id)
+                .ok_or_else(|| {
+                    state.with_error_on_field(LoginFormField::Mxid, FieldError::Unsupported)
+                })
+        }
+    };
+
+    let provider_id = match result {
+        Ok(provider_id) => provider_id,
+        Err(state) => {
+            let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+            let content = render(
+                locale,
+                LoginContext::default()
+                    .with_form_state(state)
+                    .with_upstream_providers(providers),
+                query,
+                csrf_token,
+                csp_nonce,
+                None,
+                &mut repo,
+                &templates,
+                homeserver,
+            )
+            .await?;
+
+            return Ok((cookie_jar, Html(content)).into_response());
+        }
+    };
+
+    let mut destination = UpstreamOAuth2Authorize::new(provider_id);
+    if let Some(action) = query.post_auth_action {
+        destination = destination.and_then(action);
+    }
+
+    Ok((cookie_jar, url_builder.redirect(&destination)).into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Request, StatusCode};
+    use sqlx::PgPool;
+
+    use super::server_name;
+    use crate::{
+        test_utils::{
+            setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+        },
+        NetworkPolicy, RequestOrigin, SiteConfig,
+    };
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(server_name("@alice:example.org"), Some("example.org"));
+        assert_eq!(
+            server_name("@alice:example.org:8448"),
+            Some("example.org:8448")
+        );
+        assert_eq!(server_name("@alice:[::1]:8448"), Some("[::1]:8448"));
+        assert_eq!(server_name("alice:example.org"), None);
+        assert_eq!(server_name("@alice"), None);
+        assert_eq!(server_name("@:example.org"), None);
+        assert_eq!(server_name("@alice:example.org/path"), None);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_matrix_id_login(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                matrix_id_login_enabled: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let cookies = CookieHelper::new();
+
+        // The login page offers to log in with a Matrix ID
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("Continue with your Matrix ID"));
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // An invalid Matrix ID is rejected
+        let request = Request::post("/login/matrix-id").form(serde_json::json!({
+            "csrf": csrf_token,
+            "mxid": "alice",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("This is not a valid Matrix ID"));
+
+        // Users of this homeserver can't log in through another one
+        let request = Request::post("/login/matrix-id").form(serde_json::json!({
+            "csrf": csrf_token,
+            "mxid": "@alice:example.com",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("Logging in with an account on this homeserver is not supported"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_matrix_id_login_network_policy(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                matrix_id_login_enabled: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        state.network_policy = NetworkPolicy::from_config(&mas_config::NetworkZonesConfig {
+            zones: vec![mas_config::NetworkZoneConfig {
+                name: "blocked".to_owned(),
+                cidrs: vec!["198.51.100.0/24".parse().unwrap()],
+                countries: Vec::new(),
+            }],
+            login: mas_config::NetworkPolicyConfig {
+                allow: Vec::new(),
+                deny: vec!["blocked".to_owned()],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let cookies = CookieHelper::new();
+
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // The homeserver isn't looked up when logins are denied from the network
+        let mut request = Request::post("/login/matrix-id").form(serde_json::json!({
+            "csrf": csrf_token,
+            "mxid": "@alice:example.org",
+        }));
+        request.extensions_mut().insert(RequestOrigin::new(
+            Some("198.51.100.1".parse().unwrap()),
+            None,
+        ));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This is not allowed from your network"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_matrix_id_login_disabled(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::post("/login/matrix-id").form(serde_json::json!({
+            "csrf": "csrf",
+            "mxid": "@alice:example.org",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -6,9 +6,12 @@
 
 pub mod account;
 pub mod app;
+pub mod certificate_login;
 pub mod index;
 pub mod login;
 pub mod logout;
+pub mod matrix_id_login;
+pub mod password_change_required;
 pub mod reauth;
 pub mod recovery;
 pub mod register;
@@ -0,0 +1,360 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::BrowserSession;
+use mas_i18n::DataLocale;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    user::{UserPasswordRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+};
+use mas_templates::{
+    FieldError, PasswordChangeRequiredContext, PasswordChangeRequiredFormField, TemplateContext,
+    Templates, ToFormState,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::shared::OptionalPostAuthAction;
+use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct PasswordChangeForm {
+    new_password: String,
+    new_password_confirm: String,
+}
+
+impl ToFormState for PasswordChangeForm {
+    type Field = PasswordChangeRequiredFormField;
+}
+
+#[tracing::instrument(name = "handlers.views.password_change_required.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        // If there is no session, redirect to the login screen, keeping the
+        // PostAuthAction
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    // Nothing to do here if the user doesn't have to change their password
+    if !repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        return Ok((cookie_jar, query.go_next(&url_builder)).into_response());
+    }
+
+    let content = render(
+        locale,
+        PasswordChangeRequiredContext::default(),
+        query,
+        csrf_token,
+        session,
+        &mut repo,
+        &templates,
+    )
+    .await?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.password_change_required.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(password_manager): State<PasswordManager>,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<PasswordChangeForm>>,
+) -> Result<Response, FancyError> {
+    if !password_manager.is_enabled() {
+        // XXX: do something better here
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    if !repo
+        .user()
+        .is_password_change_required(&session.user)
+        .await?
+    {
+        return Ok((cookie_jar, query.go_next(&url_builder)).into_response());
+    }
+
+    let mut state = form.to_form_state();
+
+    if form.new_password.is_empty() {
+        state.add_error_on_field(
+            PasswordChangeRequiredFormField::NewPassword,
+            FieldError::Required,
+        );
+    }
+
+    if form.new_password != form.new_password_confirm {
+        state.add_error_on_field(
+            PasswordChangeRequiredFormField::NewPasswordConfirm,
+            FieldError::PasswordMismatch,
+        );
+    }
+
+    let policy_class = repo.user().get_password_policy_class(&session.user).await?;
+    let validation = password_manager
+        .validate_new_password(
+            policy_class.as_deref(),
+            &session.user.username,
+            &form.new_password,
+            Some(&locale.to_string()),
+        )
+        .await?;
+    if let Some(message) = validation.message() {
+        state.add_error_on_field(
+            PasswordChangeRequiredFormField::NewPassword,
+            FieldError::Policy { message },
+        );
+    }
+
+    // The point is to stop using the password set by the admin, so it can't be
+    // kept as is
+    if let Some(active_password) = repo.user_password().active(&session.user).await? {
+        let reused = password_manager
+            .verify(
+                active_password.version,
+                Zeroizing::new(form.new_password.as_bytes().to_vec()),
+                active_password.hashed_password,
+            )
+            .await
+            .is_ok();
+
+        if reused {
+            // TODO localise this error
+            state.add_error_on_field(
+                PasswordChangeRequiredFormField::NewPassword,
+                FieldError::Policy {
+                    message: "The new password must be different from the current one".to_owned(),
+                },
+            );
+        }
+    }
+
+    if !state.is_valid() {
+        let content = render(
+            locale,
+            PasswordChangeRequiredContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            session,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    let (version, hashed_password) = password_manager
+        .hash(&mut rng, Zeroizing::new(form.new_password.into_bytes()))
+        .await?;
+
+    repo.user_password()
+        .add(
+            &mut rng,
+            &clock,
+            &session.user,
+            version,
+            hashed_password,
+            None,
+        )
+        .await?;
+
+    repo.user()
+        .set_password_change_required(&clock, &session.user, false)
+        .await?;
+
+    repo.save().await?;
+
+    Ok((cookie_jar, query.go_next(&url_builder)).into_response())
+}
+
+async fn render(
+    locale: DataLocale,
+    ctx: PasswordChangeRequiredContext,
+    action: OptionalPostAuthAction,
+    csrf_token: CsrfToken,
+    session: BrowserSession,
+    repo: &mut impl RepositoryAccess,
+    templates: &Templates,
+) -> Result<String, FancyError> {
+    let next = action.load_context(repo).await?;
+    let ctx = if let Some(next) = next {
+        ctx.with_post_action(next)
+    } else {
+        ctx
+    };
+    let ctx = ctx
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_password_change_required(&ctx)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_router::Route;
+    use mas_storage::{
+        user::{UserPasswordRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+    use zeroize::Zeroizing;
+
+    use crate::test_utils::{setup, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+
+    fn extract_csrf_token(body: &str) -> String {
+        body.split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_change_required(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a temporary password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new(b"temporary".to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user()
+            .set_password_change_required(&state.clock, &user, true)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Log in with the temporary password
+        let request = cookies.with_cookies(Request::get("/login").empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        let csrf_token = extract_csrf_token(response.body());
+
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "temporary",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+
+        // The user is sent to the password change page instead of continuing
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(
+            LOCATION,
+            &mas_router::PasswordChangeRequired::from(None).path_and_query(),
+        );
+
+        let request = cookies.with_cookies(Request::get("/change-password").empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = extract_csrf_token(response.body());
+
+        // Keeping the temporary password is not allowed
+        let request = Request::post("/change-password").form(serde_json::json!({
+            "csrf": csrf_token,
+            "new_password": "temporary",
+            "new_password_confirm": "temporary",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = extract_csrf_token(response.body());
+
+        let request = Request::post("/change-password").form(serde_json::json!({
+            "csrf": csrf_token,
+            "new_password": "correcthorsebatterystaple",
+            "new_password_confirm": "correcthorsebatterystaple",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/");
+
+        // The flag is cleared
+        let mut repo = state.repository().await.unwrap();
+        assert!(!repo
+            .user()
+            .is_password_change_required(&user)
+            .await
+            .unwrap());
+    }
+}
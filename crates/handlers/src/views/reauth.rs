@@ -15,9 +15,10 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
+use mas_data_model::LoginMethod;
 use mas_router::UrlBuilder;
 use mas_storage::{
-    user::{BrowserSessionRepository, UserPasswordRepository},
+    user::{BrowserSessionRepository, UserLoginHistoryRepository, UserPasswordRepository},
     BoxClock, BoxRepository, BoxRng,
 };
 use mas_templates::{ReauthContext, TemplateContext, Templates};
@@ -25,7 +26,10 @@ use serde::Deserialize;
 use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage, SiteConfig};
+use crate::{
+    passwords::{PasswordHashingBusyError, PasswordManager},
+    BoundActivityTracker, PreferredLanguage, SiteConfig,
+};
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct ReauthForm {
@@ -92,6 +96,7 @@ pub(crate) async fn post(
     State(password_manager): State<PasswordManager>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
@@ -126,14 +131,36 @@ pub(crate) async fn post(
 
     // TODO: recover from errors
     // Verify the password, and upgrade it on-the-fly if needed
-    let new_password_hash = password_manager
+    let new_password_hash = match password_manager
         .verify_and_upgrade(
             &mut rng,
             user_password.version,
             password,
             user_password.hashed_password.clone(),
         )
-        .await?;
+        .await
+    {
+        Ok(new_password_hash) => new_password_hash,
+        Err(e) => {
+            if !e.is::<PasswordHashingBusyError>() {
+                // Let the user know about the failed attempt in their login history
+                repo.user_login_history()
+                    .record(
+                        &mut rng,
+                        &clock,
+                        &session.user,
+                        LoginMethod::Password,
+                        false,
+                        activity_tracker.ip(),
+                        session.user_agent.clone(),
+                    )
+                    .await?;
+                repo.save().await?;
+            }
+
+            return Err(e.into());
+        }
+    };
 
     let user_password = if let Some((version, new_password_hash)) = new_password_hash {
         // Save the upgraded password
@@ -156,6 +183,18 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &session.user,
+            LoginMethod::Password,
+            true,
+            activity_tracker.ip(),
+            session.user_agent.clone(),
+        )
+        .await?;
+
     let cookie_jar = cookie_jar.set_session(&session);
     repo.save().await?;
 
@@ -11,20 +11,21 @@ use axum::{
     response::{Html, IntoResponse, Response},
 };
 use axum_extra::typed_header::TypedHeader;
-use hyper::StatusCode;
+use hyper::{HeaderMap, StatusCode};
 use lettre::Address;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    security_headers::CspNonce,
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{CaptchaConfig, UserAgent};
+use mas_data_model::{CaptchaConfig, RegistrationMetadata, UserAgent};
 use mas_i18n::DataLocale;
 use mas_matrix::BoxHomeserverConnection;
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    job::{JobRepositoryExt, ProvisionUserJob},
     user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
@@ -33,12 +34,18 @@ use mas_templates::{
     ToFormState,
 };
 use serde::{Deserialize, Serialize};
+use ulid::Ulid;
 use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
 use crate::{
-    captcha::Form as CaptchaForm, passwords::PasswordManager, BoundActivityTracker, Limiter,
-    PreferredLanguage, RequesterFingerprint, SiteConfig,
+    bot_detection::{self, Form as BotDetectionForm, Verdict},
+    captcha::Form as CaptchaForm,
+    email_verification,
+    experiments::Experiments,
+    passwords::PasswordManager,
+    BoundActivityTracker, Limiter, NetworkPolicy, NetworkScope, PreferredLanguage, RequestOrigin,
+    RequesterFingerprint, RiskAssessor, RiskContext, RiskEvent, RiskVerdict, SiteConfig,
 };
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -50,6 +57,9 @@ pub(crate) struct RegisterForm {
     #[serde(default)]
     accept_terms: String,
 
+    #[serde(flatten, skip_serializing)]
+    bot_detection: BotDetectionForm,
+
     #[serde(flatten, skip_serializing)]
     captcha: CaptchaForm,
 }
@@ -58,6 +68,40 @@ impl ToFormState for RegisterForm {
     type Field = RegisterFormField;
 }
 
+/// Longest invite token or referral parameter kept from a registration link
+const MAX_SOURCE_PARAM_LENGTH: usize = 128;
+
+/// Parameters operators can add to registration links, to track where
+/// registrations come from
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct RegistrationSourceParams {
+    #[serde(default, rename = "ref")]
+    referral: Option<String>,
+
+    #[serde(default)]
+    invite: Option<String>,
+}
+
+impl RegistrationSourceParams {
+    fn sanitize(value: Option<&String>) -> Option<String> {
+        let value = value?.trim();
+        if value.is_empty() {
+            return None;
+        }
+
+        Some(value.chars().take(MAX_SOURCE_PARAM_LENGTH).collect())
+    }
+
+    fn metadata(&self, client_id: Option<Ulid>) -> RegistrationMetadata {
+        RegistrationMetadata {
+            client_id,
+            upstream_provider_id: None,
+            invite_token: Self::sanitize(self.invite.as_ref()),
+            referral: Self::sanitize(self.referral.as_ref()),
+        }
+    }
+}
+
 #[tracing::instrument(name = "handlers.views.register.get", skip_all, err)]
 pub(crate) async fn get(
     mut rng: BoxRng,
@@ -67,6 +111,7 @@ pub(crate) async fn get(
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
+    csp_nonce: CspNonce,
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
@@ -87,17 +132,32 @@ pub(crate) async fn get(
             .into_response());
     }
 
+    // With the bot detection enabled, the CAPTCHA is only shown once a submission
+    // looks suspicious
+    let captcha = if site_config.bot_detection.is_none() {
+        site_config.captcha.clone()
+    } else {
+        None
+    };
+
+    let (experiments, cookie_jar) =
+        Experiments::load(cookie_jar, &clock, &mut rng, &site_config.feature_flags);
+    experiments.record_exposures(&mut repo, &clock).await?;
+
     let content = render(
         locale,
-        RegisterContext::default(),
+        RegisterContext::default().with_experiments(experiments.variants()),
         query,
         csrf_token,
+        csp_nonce,
         &mut repo,
         &templates,
-        site_config.captcha.clone(),
+        captcha,
     )
     .await?;
 
+    repo.save().await?;
+
     Ok((cookie_jar, Html(content)).into_response())
 }
 
@@ -113,14 +173,25 @@ pub(crate) async fn post(
     State(site_config): State<SiteConfig>,
     State(homeserver): State<BoxHomeserverConnection>,
     State(http_client): State<reqwest::Client>,
-    (State(limiter), requester): (State<Limiter>, RequesterFingerprint),
+    (State(limiter), State(risk_assessor), State(network_policy), requester, origin): (
+        State<Limiter>,
+        State<RiskAssessor>,
+        State<NetworkPolicy>,
+        RequesterFingerprint,
+        RequestOrigin,
+    ),
     mut policy: Policy,
     mut repo: BoxRepository,
-    (user_agent, activity_tracker): (
+    (user_agent, activity_tracker, request_headers, csp_nonce): (
         Option<TypedHeader<headers::UserAgent>>,
         BoundActivityTracker,
+        HeaderMap,
+        CspNonce,
+    ),
+    (Query(query), Query(source)): (
+        Query<OptionalPostAuthAction>,
+        Query<RegistrationSourceParams>,
     ),
-    Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
     Form(form): Form<ProtectedForm<RegisterForm>>,
 ) -> Result<Response, FancyError> {
@@ -132,24 +203,88 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (experiments, cookie_jar) =
+        Experiments::load(cookie_jar, &clock, &mut rng, &site_config.feature_flags);
+
+    // Check that registrations are allowed from where the request comes from
+    let network_allowed = network_policy
+        .check(NetworkScope::Registration, &origin)
+        .is_ok();
+
+    // Look for signs of an automated submission. The challenge was computed from
+    // the CSRF token the form was rendered with, which was just verified.
+    let verdict = bot_detection::evaluate(
+        site_config.bot_detection.as_ref(),
+        &limiter,
+        requester,
+        &request_headers,
+        &form.bot_detection,
+        &csrf_token.form_value(),
+    );
+
+    // Consult the external risk provider, unless the submission is already
+    // rejected
+    let risk = if !network_allowed || verdict == Verdict::Block || form.username.is_empty() {
+        RiskVerdict::Allow
+    } else {
+        risk_assessor
+            .assess(
+                RiskContext {
+                    event: RiskEvent::Registration,
+                    username: form.username.clone(),
+                    email: Some(form.email.clone()).filter(|email| !email.is_empty()),
+                    ip: activity_tracker.ip(),
+                    user_agent: None,
+                    device_type: None,
+                    client_app: None,
+                }
+                .with_user_agent(user_agent.as_ref()),
+            )
+            .await
+    };
+
+    // Without the bot detection, the CAPTCHA is always required. With it, it is
+    // only required on suspicious submissions, or when the risk provider asks for
+    // it
+    let captcha = if site_config.bot_detection.is_none()
+        || verdict == Verdict::Captcha
+        || risk == RiskVerdict::Challenge
+    {
+        site_config.captcha.clone()
+    } else {
+        None
+    };
 
     // Validate the captcha
     // TODO: display a nice error message to the user
-    let passed_captcha = form
-        .captcha
-        .verify(
-            &activity_tracker,
-            &http_client,
-            url_builder.public_hostname(),
-            site_config.captcha.as_ref(),
-        )
-        .await
-        .is_ok();
+    let passed_captcha = captcha.is_none()
+        || form
+            .captcha
+            .verify(
+                &activity_tracker,
+                &http_client,
+                url_builder.public_hostname(),
+                captcha.as_ref(),
+            )
+            .await
+            .is_ok();
 
     // Validate the form
     let state = {
         let mut state = form.to_form_state();
 
+        if !network_allowed {
+            state.add_error_on_form(FormError::DeniedNetwork);
+        }
+
+        if verdict == Verdict::Block {
+            state.add_error_on_form(FormError::Suspicious);
+        }
+
+        if risk == RiskVerdict::Deny {
+            state.add_error_on_form(FormError::DeniedRisk);
+        }
+
         if !passed_captcha {
             state.add_error_on_form(FormError::Captcha);
         }
@@ -192,14 +327,16 @@ pub(crate) async fn post(
             );
         }
 
-        if !password_manager.is_password_complex_enough(&form.password)? {
-            // TODO localise this error
-            state.add_error_on_field(
-                RegisterFormField::Password,
-                FieldError::Policy {
-                    message: "Password is too weak".to_owned(),
-                },
-            );
+        let validation = password_manager
+            .validate_new_password(
+                None,
+                &form.username,
+                &form.password,
+                Some(&locale.to_string()),
+            )
+            .await?;
+        if let Some(message) = validation.message() {
+            state.add_error_on_field(RegisterFormField::Password, FieldError::Policy { message });
         }
 
         // If the site has terms of service, the user must accept them
@@ -251,12 +388,15 @@ pub(crate) async fn post(
     if !state.is_valid() {
         let content = render(
             locale,
-            RegisterContext::default().with_form_state(state),
+            RegisterContext::default()
+                .with_form_state(state)
+                .with_experiments(experiments.variants()),
             query,
             csrf_token,
+            csp_nonce,
             &mut repo,
             &templates,
-            site_config.captcha.clone(),
+            captcha,
         )
         .await?;
 
@@ -265,6 +405,11 @@ pub(crate) async fn post(
 
     let user = repo.user().add(&mut rng, &clock, form.username).await?;
 
+    let client_id = query.client_id(&mut repo).await?;
+    repo.user()
+        .set_registration_metadata(&user, &source.metadata(client_id))
+        .await?;
+
     if let Some(tos_uri) = &site_config.tos_uri {
         repo.user_terms()
             .accept_terms(&mut rng, &clock, &user, tos_uri.clone())
@@ -294,14 +439,16 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
-    repo.job()
-        .schedule_job(VerifyEmailJob::new(&user_email).with_language(locale.to_string()))
+    // The address was just added, so sending the code can't be rate limited
+    email_verification::send_code(&mut repo, &limiter, &user_email, Some(locale.to_string()))
         .await?;
 
     repo.job()
         .schedule_job(ProvisionUserJob::new(&user))
         .await?;
 
+    experiments.record_conversion(&mut repo, &clock).await?;
+
     repo.save().await?;
 
     activity_tracker
@@ -312,11 +459,13 @@ pub(crate) async fn post(
     Ok((cookie_jar, url_builder.redirect(&next)).into_response())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn render(
     locale: DataLocale,
     ctx: RegisterContext,
     action: OptionalPostAuthAction,
     csrf_token: CsrfToken,
+    csp_nonce: CspNonce,
     repo: &mut impl RepositoryAccess,
     templates: &Templates,
     captcha_config: Option<CaptchaConfig>,
@@ -329,6 +478,7 @@ async fn render(
     };
     let ctx = ctx
         .with_captcha(captcha_config)
+        .with_csp_nonce(Some(csp_nonce))
         .with_csrf(csrf_token.form_value())
         .with_language(locale);
 
@@ -14,6 +14,7 @@ use mas_storage::{
 };
 use mas_templates::{PostAuthContext, PostAuthContextInner};
 use serde::{Deserialize, Serialize};
+use ulid::Ulid;
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub(crate) struct OptionalPostAuthAction {
@@ -37,6 +38,36 @@ impl OptionalPostAuthAction {
         self.go_next_or_default(url_builder, &mas_router::Index)
     }
 
+    /// The ID of the OAuth 2.0 client the user is authenticating for, if any
+    pub async fn client_id(
+        &self,
+        repo: &mut impl RepositoryAccess,
+    ) -> anyhow::Result<Option<Ulid>> {
+        let client_id = match self.post_auth_action {
+            Some(PostAuthAction::ContinueAuthorizationGrant { id }) => {
+                let grant = repo
+                    .oauth2_authorization_grant()
+                    .lookup(id)
+                    .await?
+                    .context("Failed to load authorization grant")?;
+                Some(grant.client_id)
+            }
+
+            Some(PostAuthAction::ContinueDeviceCodeGrant { id }) => {
+                let grant = repo
+                    .oauth2_device_code_grant()
+                    .lookup(id)
+                    .await?
+                    .context("Failed to load device code grant")?;
+                Some(grant.client_id)
+            }
+
+            _ => None,
+        };
+
+        Ok(client_id)
+    }
+
     pub async fn load_context<'a>(
         &'a self,
         repo: &'a mut impl RepositoryAccess,
@@ -0,0 +1,53 @@
+#!/bin/sh
+
+# Script to regenerate the client certificates used to test logging in with a
+# certificate, and a revocation list revoking the one of bob
+
+set -eux
+
+cd "$(dirname "$0")"
+rm -f ./*.pem ./*.der
+
+WORK="$(mktemp -d)"
+trap 'rm -rf "${WORK}"' EXIT
+
+cat > "${WORK}/ca.cnf" <<CNF
+[ca]
+default_ca = test_ca
+
+[test_ca]
+database = ${WORK}/index.txt
+crlnumber = ${WORK}/crlnumber
+default_md = sha256
+default_crl_days = 36500
+
+[client]
+basicConstraints = critical, CA:FALSE
+keyUsage = critical, digitalSignature
+extendedKeyUsage = clientAuth
+subjectAltName = email:\${ENV::EMAIL}
+CNF
+touch "${WORK}/index.txt"
+echo 01 > "${WORK}/crlnumber"
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+  -keyout ca-key.pem -out ca.pem -days 36500 -subj "/CN=Test client CA" \
+  -addext "basicConstraints=critical,CA:TRUE" \
+  -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+client() {
+  NAME=$1
+  export EMAIL=$2
+  openssl req -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+    -keyout "${WORK}/${NAME}-key.pem" -out "${WORK}/${NAME}.csr" -subj "/CN=${NAME}"
+  openssl x509 -req -in "${WORK}/${NAME}.csr" -CA ca.pem -CAkey ca-key.pem \
+    -CAcreateserial -CAserial "${WORK}/ca.srl" -days 36500 \
+    -extfile "${WORK}/ca.cnf" -extensions client -out "${NAME}.pem"
+  openssl x509 -in "${NAME}.pem" -outform der -out "${NAME}.der"
+}
+
+client alice alice@example.com
+client bob bob@example.com
+
+openssl ca -config "${WORK}/ca.cnf" -keyfile ca-key.pem -cert ca.pem -revoke bob.pem
+openssl ca -config "${WORK}/ca.cnf" -keyfile ca-key.pem -cert ca.pem -gencrl -out crl.pem
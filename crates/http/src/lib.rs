@@ -16,7 +16,12 @@ mod reqwest;
 
 pub use self::{
     ext::{set_propagator, CorsLayerExt},
-    reqwest::{client as reqwest_client, RequestBuilderExt},
+    reqwest::{
+        client as reqwest_client, client_with_options as reqwest_client_with_options,
+        public_client as reqwest_public_client,
+        public_client_with_options as reqwest_public_client_with_options,
+        ClientOptions as ReqwestClientOptions, RequestBuilderExt,
+    },
 };
 
 static METER: LazyLock<opentelemetry::metrics::Meter> = LazyLock::new(|| {
@@ -5,6 +5,7 @@
 
 use std::{
     future::Future,
+    net::IpAddr,
     str::FromStr,
     sync::{Arc, LazyLock},
     time::Duration,
@@ -30,6 +31,7 @@ use opentelemetry_semantic_conventions::{
         NETWORK_TYPE, SERVER_ADDRESS, SERVER_PORT, URL_FULL, URL_SCHEME, USER_AGENT_ORIGINAL,
     },
 };
+use rustls::pki_types::CertificateDer;
 use tokio::time::Instant;
 use tower::{BoxError, Service as _};
 use tracing::Instrument;
@@ -83,6 +85,84 @@ impl reqwest::dns::Resolve for TracingResolver {
     }
 }
 
+/// Resolver which refuses to resolve names to addresses which are not
+/// publicly routable, like loopback, private or link-local ones
+struct PublicResolver {
+    inner: TracingResolver,
+}
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let name_str = name.as_str().to_owned();
+        Box::pin(self.inner.resolve(name).map(move |result| {
+            let addrs: Vec<_> = result?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                tracing::warn!(
+                    name = name_str,
+                    address = %addr.ip(),
+                    "Refusing to connect to a non-public address"
+                );
+                return Err(format!("{name_str} resolves to a non-public address").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        }))
+    }
+}
+
+/// Whether an IP address is publicly routable
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space, used for carrier-grade NAT
+                || (a == 100 && (b & 0b1100_0000) == 64)
+                // Reserved for future use
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local addresses
+                || (first & 0xfe00) == 0xfc00
+                // Link-local addresses
+                || (first & 0xffc0) == 0xfe80
+                // Documentation addresses
+                || first == 0x2001 && ip.segments()[1] == 0x0db8)
+        }
+    }
+}
+
+/// Options used to build a [`reqwest::Client`]
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Proxy used for all requests. If neither this nor `https_proxy` is set,
+    /// the proxy configured in the environment is used.
+    pub proxy: Option<reqwest::Url>,
+
+    /// Proxy used for HTTPS requests, taking precedence over `proxy`
+    pub https_proxy: Option<reqwest::Url>,
+
+    /// Hosts, domains and IP ranges which should not go through the proxy
+    pub no_proxy: Vec<String>,
+
+    /// Additional trusted root certificates, on top of the platform ones
+    pub extra_root_certificates: Vec<CertificateDer<'static>>,
+}
+
 /// Create a new [`reqwest::Client`] with sane parameters
 ///
 /// # Panics
@@ -90,16 +170,103 @@ impl reqwest::dns::Resolve for TracingResolver {
 /// Panics if the client fails to build, which should never happen
 #[must_use]
 pub fn client() -> reqwest::Client {
+    client_with_options(&ClientOptions::default()).expect("failed to create HTTP client")
+}
+
+/// Create a new [`reqwest::Client`] with sane parameters, using the given
+/// proxy and TLS options
+///
+/// # Errors
+///
+/// Returns an error if one of the proxy URLs is invalid, or if the client
+/// fails to build
+pub fn client_with_options(options: &ClientOptions) -> Result<reqwest::Client, reqwest::Error> {
+    builder_with_options(options, Arc::new(TracingResolver::new()))?.build()
+}
+
+/// Create a new [`reqwest::Client`] to make requests to hosts given by end
+/// users
+///
+/// # Panics
+///
+/// Panics if the client fails to build, which should never happen
+#[must_use]
+pub fn public_client() -> reqwest::Client {
+    public_client_with_options(&ClientOptions::default()).expect("failed to create HTTP client")
+}
+
+/// Create a new [`reqwest::Client`] to make requests to hosts given by end
+/// users, using the given proxy and TLS options
+///
+/// This client only connects to publicly routable addresses, doesn't follow
+/// redirects and has short timeouts. Requests going through a proxy are
+/// resolved by the proxy, so the addresses aren't checked for them.
+///
+/// # Errors
+///
+/// Returns an error if one of the proxy URLs is invalid, or if the client
+/// fails to build
+pub fn public_client_with_options(
+    options: &ClientOptions,
+) -> Result<reqwest::Client, reqwest::Error> {
+    let resolver = PublicResolver {
+        inner: TracingResolver::new(),
+    };
+
+    builder_with_options(options, Arc::new(resolver))?
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(10))
+        .connect_timeout(Duration::from_secs(5))
+        .read_timeout(Duration::from_secs(5))
+        .build()
+}
+
+fn builder_with_options(
+    options: &ClientOptions,
+    resolver: Arc<impl reqwest::dns::Resolve + 'static>,
+) -> Result<reqwest::ClientBuilder, reqwest::Error> {
+    let tls_config = if options.extra_root_certificates.is_empty() {
+        rustls_platform_verifier::tls_config()
+    } else {
+        let mut extra_roots = rustls::RootCertStore::empty();
+        let (_, invalid) =
+            extra_roots.add_parsable_certificates(options.extra_root_certificates.iter().cloned());
+        if invalid > 0 {
+            tracing::warn!(
+                invalid,
+                "Ignoring extra root certificates which could not be parsed"
+            );
+        }
+        let verifier = rustls_platform_verifier::Verifier::new_with_extra_roots(extra_roots.roots);
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth()
+    };
+
     // TODO: can/should we limit in-flight requests?
-    reqwest::Client::builder()
-        .dns_resolver(Arc::new(TracingResolver::new()))
-        .use_preconfigured_tls(rustls_platform_verifier::tls_config())
+    let mut builder = reqwest::Client::builder()
+        .dns_resolver(resolver)
+        .use_preconfigured_tls(tls_config)
         .user_agent(USER_AGENT)
         .timeout(Duration::from_secs(60))
         .connect_timeout(Duration::from_secs(30))
-        .read_timeout(Duration::from_secs(30))
-        .build()
-        .expect("failed to create HTTP client")
+        .read_timeout(Duration::from_secs(30));
+
+    let no_proxy = reqwest::NoProxy::from_string(&options.no_proxy.join(","));
+
+    // Setting any proxy disables the ones from the environment, so the HTTPS
+    // one has to be added first to take precedence
+    if let Some(https_proxy) = &options.https_proxy {
+        builder =
+            builder.proxy(reqwest::Proxy::https(https_proxy.clone())?.no_proxy(no_proxy.clone()));
+    }
+
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?.no_proxy(no_proxy));
+    }
+
+    Ok(builder)
 }
 
 async fn send_traced(
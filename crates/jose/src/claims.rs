@@ -184,6 +184,10 @@ where
     }
 }
 
+/// The default tolerance for the clock differences between the issuer and the
+/// verifier of a token, when checking its time-based claims
+pub const DEFAULT_LEEWAY: chrono::Duration = chrono::Duration::minutes(5);
+
 #[derive(Debug, Clone)]
 pub struct TimeOptions {
     when: chrono::DateTime<chrono::Utc>,
@@ -195,7 +199,7 @@ impl TimeOptions {
     pub fn new(when: chrono::DateTime<chrono::Utc>) -> Self {
         Self {
             when,
-            leeway: chrono::Duration::microseconds(5 * 60 * 1000 * 1000),
+            leeway: DEFAULT_LEEWAY,
         }
     }
 
@@ -495,6 +499,9 @@ mod oidc_core {
     use super::{Claim, Equality, Timestamp, TokenHash};
 
     pub const AUTH_TIME: Claim<Timestamp> = Claim::new("auth_time");
+    pub const ACR: Claim<String> = Claim::new("acr");
+    pub const AMR: Claim<Vec<String>> = Claim::new("amr");
+    pub const AZP: Claim<String> = Claim::new("azp");
     pub const NONCE: Claim<String, Equality<str>> = Claim::new("nonce");
     pub const AT_HASH: Claim<String, TokenHash> = Claim::new("at_hash");
     pub const C_HASH: Claim<String, TokenHash> = Claim::new("c_hash");
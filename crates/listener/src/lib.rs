@@ -12,6 +12,7 @@
 
 use self::{maybe_tls::TlsStreamInfo, proxy_protocol::ProxyProtocolV1Info};
 
+pub mod limits;
 pub mod maybe_tls;
 pub mod proxy_protocol;
 pub mod rewind;
@@ -0,0 +1,262 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Protections against slow or abusive clients: limits on the size of request
+//! bodies, on the time it takes to receive them, and on the number of
+//! concurrent connections and streams.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::LazyLock,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::future::Either;
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{LengthLimitError, Limited};
+use hyper::{header::CONTENT_LENGTH, Request, Response, StatusCode};
+use opentelemetry::{metrics::Counter, KeyValue};
+use pin_project_lite::pin_project;
+use thiserror::Error;
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+static LIMITS_HIT: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("http.server.limits_hit")
+    .with_description("The number of times a request or connection hit a server limit")
+    .with_unit("{hit}")
+    .init()
+});
+
+/// The kind of limit which was hit, used as a metric attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LimitKind {
+    RequestBodySize,
+    RequestBodyTimeout,
+    HeaderReadTimeout,
+    MaxConnections,
+}
+
+impl LimitKind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::RequestBodySize => "request_body_size",
+            Self::RequestBodyTimeout => "request_body_timeout",
+            Self::HeaderReadTimeout => "header_read_timeout",
+            Self::MaxConnections => "max_connections",
+        }
+    }
+
+    pub(crate) fn record(self) {
+        LIMITS_HIT.add(1, &[KeyValue::new("limit", self.as_str())]);
+    }
+}
+
+/// Limits applied on each connection accepted by a [`Server`]
+///
+/// [`Server`]: crate::server::Server
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// Maximum time to receive the request headers on HTTP/1.1 connections
+    pub header_read_timeout: Option<Duration>,
+
+    /// Maximum number of concurrent streams on a single HTTP/2 connection
+    pub max_concurrent_streams: Option<u32>,
+
+    /// Maximum number of connections served at the same time by a listener
+    pub max_connections: Option<usize>,
+}
+
+/// A [`Layer`] which limits the size of request bodies and the time it takes
+/// to receive them
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimitsLayer {
+    max_body_size: usize,
+    body_read_timeout: Option<Duration>,
+}
+
+impl RequestLimitsLayer {
+    /// Create a new layer limiting request bodies to `max_body_size` bytes
+    #[must_use]
+    pub const fn new(max_body_size: usize) -> Self {
+        Self {
+            max_body_size,
+            body_read_timeout: None,
+        }
+    }
+
+    /// Set the maximum time allowed to receive a complete request body,
+    /// starting from when the headers were received
+    #[must_use]
+    pub const fn with_body_read_timeout(mut self, timeout: Duration) -> Self {
+        self.body_read_timeout = Some(timeout);
+        self
+    }
+}
+
+impl<S> Layer<S> for RequestLimitsLayer {
+    type Service = RequestLimitsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestLimitsService {
+            inner,
+            max_body_size: self.max_body_size,
+            body_read_timeout: self.body_read_timeout,
+        }
+    }
+}
+
+/// Service created by the [`RequestLimitsLayer`]
+#[derive(Debug, Clone)]
+pub struct RequestLimitsService<S> {
+    inner: S,
+    max_body_size: usize,
+    body_read_timeout: Option<Duration>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestLimitsService<S>
+where
+    S: Service<Request<LimitedBody<ReqBody>>, Response = Response<ResBody>>,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<std::future::Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // Reject early if the client announced a body which is too large
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        if content_length.is_some_and(|length| length > self.max_body_size) {
+            LimitKind::RequestBodySize.record();
+            let mut response = Response::new(ResBody::default());
+            *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+            return Either::Left(std::future::ready(Ok(response)));
+        }
+
+        let max_body_size = self.max_body_size;
+        let body_read_timeout = self.body_read_timeout;
+        let req = req.map(|body| LimitedBody::new(body, max_body_size, body_read_timeout));
+        Either::Right(self.inner.call(req))
+    }
+}
+
+/// Error returned by a [`LimitedBody`] when reading it took too long
+#[derive(Debug, Error)]
+#[error("timed out while reading the request body")]
+pub struct BodyTimeoutError;
+
+pin_project! {
+    /// A request body with a size limit and a deadline
+    pub struct LimitedBody<B> {
+        #[pin]
+        inner: Limited<B>,
+        deadline: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+impl<B> LimitedBody<B> {
+    fn new(inner: B, max_size: usize, timeout: Option<Duration>) -> Self {
+        Self {
+            inner: Limited::new(inner, max_size),
+            deadline: timeout
+                .map(|timeout| Box::pin(tokio::time::sleep_until(Instant::now() + timeout))),
+        }
+    }
+}
+
+impl<B> Body for LimitedBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = B::Data;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        if let Some(deadline) = this.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                LimitKind::RequestBodyTimeout.record();
+                return Poll::Ready(Some(Err(BodyTimeoutError.into())));
+            }
+        }
+
+        match std::task::ready!(this.inner.poll_frame(cx)) {
+            Some(Err(e)) => {
+                if e.downcast_ref::<LengthLimitError>().is_some() {
+                    LimitKind::RequestBodySize.record();
+                }
+                Poll::Ready(Some(Err(e)))
+            }
+            Some(Ok(frame)) => Poll::Ready(Some(Ok(frame))),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full, StreamBody};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let body = LimitedBody::new(Full::new(Bytes::from_static(b"hello")), 4, None);
+        let res = body.collect().await;
+        assert!(res.is_err());
+
+        let body = LimitedBody::new(Full::new(Bytes::from_static(b"hello")), 5, None);
+        let res = body.collect().await.unwrap().to_bytes();
+        assert_eq!(res, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_body_timeout() {
+        // A body which never finishes
+        let stream = futures_util::stream::pending::<Result<Frame<Bytes>, std::io::Error>>();
+        let body = LimitedBody::new(
+            StreamBody::new(stream),
+            1024,
+            Some(Duration::from_millis(10)),
+        );
+        let err = body.collect().await.unwrap_err();
+        assert!(err.downcast_ref::<BodyTimeoutError>().is_some());
+    }
+}
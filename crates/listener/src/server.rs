@@ -15,12 +15,13 @@ use std::{
 use futures_util::{stream::SelectAll, StreamExt};
 use hyper::{Request, Response};
 use hyper_util::{
-    rt::{TokioExecutor, TokioIo},
+    rt::{TokioExecutor, TokioIo, TokioTimer},
     server::conn::auto::Connection,
     service::TowerToHyperService,
 };
 use pin_project_lite::pin_project;
 use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio_rustls::rustls::ServerConfig;
 use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
 use tower::Service;
@@ -28,6 +29,7 @@ use tower_http::add_extension::AddExtension;
 use tracing::Instrument;
 
 use crate::{
+    limits::{ConnectionLimits, LimitKind},
     maybe_tls::{MaybeTlsAcceptor, MaybeTlsStream, TlsStreamInfo},
     proxy_protocol::{MaybeProxyAcceptor, ProxyAcceptError},
     rewind::Rewind,
@@ -42,6 +44,7 @@ pub struct Server<S> {
     tls: Option<Arc<ServerConfig>>,
     proxy: bool,
     listener: UnixOrTcpListener,
+    limits: ConnectionLimits,
     service: S,
 }
 
@@ -57,6 +60,7 @@ impl<S> Server<S> {
             tls: None,
             proxy: false,
             listener: listener.try_into()?,
+            limits: ConnectionLimits::default(),
             service,
         })
     }
@@ -67,6 +71,7 @@ impl<S> Server<S> {
             tls: None,
             proxy: false,
             listener: listener.into(),
+            limits: ConnectionLimits::default(),
             service,
         }
     }
@@ -83,6 +88,13 @@ impl<S> Server<S> {
         self
     }
 
+    /// Set the limits applied on connections accepted by this server
+    #[must_use]
+    pub const fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     /// Run a single server
     pub async fn run<B>(
         self,
@@ -170,6 +182,7 @@ impl AcceptError {
 async fn accept<S, B>(
     maybe_proxy_acceptor: &MaybeProxyAcceptor,
     maybe_tls_acceptor: &MaybeTlsAcceptor,
+    limits: &ConnectionLimits,
     peer_addr: SocketAddr,
     stream: UnixOrTcpConnection,
     service: S,
@@ -231,6 +244,17 @@ where
         }
         builder.http1().keep_alive(true);
 
+        if let Some(timeout) = limits.header_read_timeout {
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(timeout);
+        }
+
+        if let Some(max) = limits.max_concurrent_streams {
+            builder.http2().max_concurrent_streams(max);
+        }
+
         let service = TowerToHyperService::new(AddExtension::new(service, info));
 
         let conn = builder
@@ -301,6 +325,29 @@ where
     }
 }
 
+/// Serve a connection until it finishes, holding the connection permit (if
+/// any) for its whole lifetime
+async fn serve_connection<C>(connection: C, permit: Option<OwnedSemaphorePermit>) -> C::Output
+where
+    C: Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+{
+    let res = connection.await;
+    drop(permit);
+
+    if let Err(e) = &res {
+        // Hyper reports connections which didn't send their headers in time as
+        // timeout errors
+        let is_timeout = e
+            .downcast_ref::<hyper::Error>()
+            .is_some_and(hyper::Error::is_timeout);
+        if is_timeout {
+            LimitKind::HeaderReadTimeout.record();
+        }
+    }
+
+    res
+}
+
 #[allow(clippy::too_many_lines)]
 pub async fn run_servers<S, B>(
     listeners: impl IntoIterator<Item = Server<S>>,
@@ -320,18 +367,41 @@ pub async fn run_servers<S, B>(
         .map(|server| {
             let maybe_proxy_acceptor = MaybeProxyAcceptor::new(server.proxy);
             let maybe_tls_acceptor = MaybeTlsAcceptor::new(server.tls);
-            futures_util::stream::poll_fn(move |cx| {
-                let res =
-                    std::task::ready!(server.listener.poll_accept(cx)).map(|(addr, stream)| {
-                        (
-                            maybe_proxy_acceptor,
-                            maybe_tls_acceptor.clone(),
-                            server.service.clone(),
-                            addr,
-                            stream,
-                        )
-                    });
-                Poll::Ready(Some(res))
+            let limits = server.limits;
+            let semaphore = limits
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max)));
+            futures_util::stream::poll_fn(move |cx| loop {
+                let res = std::task::ready!(server.listener.poll_accept(cx));
+
+                // Acquire a permit for the connection if the number of concurrent
+                // connections is limited. If none are available, the connection is
+                // dropped right away
+                let permit = match (&semaphore, &res) {
+                    (Some(semaphore), Ok(_)) => {
+                        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                            Some(permit)
+                        } else {
+                            LimitKind::MaxConnections.record();
+                            tracing::warn!("Too many concurrent connections, dropping connection");
+                            continue;
+                        }
+                    }
+                    _ => None,
+                };
+
+                let res = res.map(|(addr, stream)| {
+                    (
+                        maybe_proxy_acceptor,
+                        maybe_tls_acceptor.clone(),
+                        limits,
+                        permit,
+                        server.service.clone(),
+                        addr,
+                        stream,
+                    )
+                });
+                return Poll::Ready(Some(res));
             })
         })
         .collect();
@@ -354,10 +424,10 @@ pub async fn run_servers<S, B>(
             // Poll on the JoinSet to collect connections to serve
             res = accept_tasks.join_next(), if !accept_tasks.is_empty() => {
                 match res {
-                    Some(Ok(Ok(connection))) => {
+                    Some(Ok(Ok((connection, permit)))) => {
                         tracing::trace!("Accepted connection");
                         let conn = AbortableConnection::new(connection, soft_shutdown_token.child_token());
-                        connection_tasks.spawn(conn);
+                        connection_tasks.spawn(serve_connection(conn, permit));
                     },
                     Some(Ok(Err(_e))) => { /* Connection did not finish handshake, error should be logged in `accept` */ },
                     Some(Err(e)) => tracing::error!("Join error: {e}"),
@@ -383,9 +453,10 @@ pub async fn run_servers<S, B>(
                 // accept the next connection. This allows us to keep track of active connections
                 // and waiting on them for a graceful shutdown
                 accept_tasks.spawn(async move {
-                    let (maybe_proxy_acceptor, maybe_tls_acceptor, service, peer_addr, stream) = res
+                    let (maybe_proxy_acceptor, maybe_tls_acceptor, limits, permit, service, peer_addr, stream) = res
                         .map_err(AcceptError::socket)?;
-                    accept(&maybe_proxy_acceptor, &maybe_tls_acceptor, peer_addr, stream, service).await
+                    let connection = accept(&maybe_proxy_acceptor, &maybe_tls_acceptor, &limits, peer_addr, stream, service).await?;
+                    Ok::<_, AcceptError>((connection, permit))
                 });
             },
         };
@@ -406,10 +477,10 @@ pub async fn run_servers<S, B>(
                 // Poll on the JoinSet to collect connections to serve
                 res = accept_tasks.join_next(), if !accept_tasks.is_empty() => {
                     match res {
-                        Some(Ok(Ok(connection))) => {
+                        Some(Ok(Ok((connection, permit)))) => {
                             tracing::trace!("Accepted connection");
                             let conn = AbortableConnection::new(connection, soft_shutdown_token.child_token());
-                            connection_tasks.spawn(conn);
+                            connection_tasks.spawn(serve_connection(conn, permit));
                         }
                         Some(Ok(Err(_e))) => { /* Connection did not finish handshake, error should be logged in `accept` */ },
                         Some(Err(e)) => tracing::error!("Join error: {e}"),
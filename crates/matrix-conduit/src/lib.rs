@@ -0,0 +1,474 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A [`HomeserverConnection`] for Conduit
+//!
+//! Conduit has no admin API over HTTP. It is administered through commands
+//! sent as messages in its admin room, which this connection does as the user
+//! it is authenticated as, who must be a member of that room. Commands are
+//! handled asynchronously by Conduit, so their outcome isn't known.
+//!
+//! Users can be created and deactivated, and notices can be sent. The profile
+//! and devices of users are left for Conduit to manage, and reactivating users
+//! and allowing cross-signing resets are not supported.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context};
+use error::MatrixResponseExt;
+use http::{Method, StatusCode};
+use mas_http::RequestBuilderExt as _;
+use mas_matrix::{
+    HomeserverCapabilities, HomeserverConnection, HomeserverFeature, MatrixUser, ProvisionRequest,
+    UnsupportedError,
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    RngCore,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use url::Url;
+
+mod error;
+
+/// The name of the backend, in errors
+const BACKEND: &str = "Conduit";
+
+/// Encountered when trying to register a user ID which has been taken.
+/// — <https://spec.matrix.org/v1.10/client-server-api/#other-error-codes>
+const M_USER_IN_USE: &str = "M_USER_IN_USE";
+/// Encountered when trying to register a user ID which is not valid.
+/// — <https://spec.matrix.org/v1.10/client-server-api/#other-error-codes>
+const M_INVALID_USERNAME: &str = "M_INVALID_USERNAME";
+
+#[derive(Clone)]
+pub struct ConduitConnection {
+    homeserver: String,
+    endpoint: Url,
+    access_token: String,
+    admin_room: String,
+    http_client: reqwest::Client,
+
+    /// Prefix of the transaction IDs of the commands, so that they don't clash
+    /// with the ones of a previous run
+    txn_prefix: String,
+    txn_counter: Arc<AtomicU64>,
+}
+
+impl ConduitConnection {
+    /// Create a connection sending commands to the given admin room, as the
+    /// user authenticated by the access token
+    #[must_use]
+    pub fn new(
+        homeserver: String,
+        endpoint: Url,
+        access_token: String,
+        admin_room: String,
+        http_client: reqwest::Client,
+        rng: &mut impl RngCore,
+    ) -> Self {
+        Self {
+            homeserver,
+            endpoint,
+            access_token,
+            admin_room,
+            http_client,
+            txn_prefix: Alphanumeric.sample_string(rng, 16),
+            txn_counter: Arc::default(),
+        }
+    }
+
+    fn builder(&self, method: Method, url: &str) -> reqwest::RequestBuilder {
+        self.http_client
+            .request(
+                method,
+                self.endpoint
+                    .join(url)
+                    .map(String::from)
+                    .unwrap_or_default(),
+            )
+            .bearer_auth(&self.access_token)
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.builder(Method::GET, url)
+    }
+
+    fn put(&self, url: &str) -> reqwest::RequestBuilder {
+        self.builder(Method::PUT, url)
+    }
+
+    /// The message running the given command in the admin room
+    fn command_body(&self, command: &str) -> String {
+        format!("@conduit:{}: {command}", self.homeserver)
+    }
+
+    /// Send a message to a room, as the user the connection is authenticated
+    /// as
+    async fn send_message(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        content: &MessageContent<'_>,
+    ) -> Result<(), anyhow::Error> {
+        let room_id = urlencoding::encode(room_id);
+        let txn_id = urlencoding::encode(txn_id);
+
+        let response = self
+            .put(&format!(
+                "_matrix/client/v3/rooms/{room_id}/send/m.room.message/{txn_id}"
+            ))
+            .json(content)
+            .send_traced()
+            .await
+            .context("Failed to send message to the room")?;
+
+        let response = response
+            .error_for_matrix_error()
+            .await
+            .context("Unexpected HTTP response while sending message to the room")?;
+
+        if response.status() != StatusCode::OK {
+            bail!(
+                "Unexpected HTTP code while sending message to the room: {}",
+                response.status(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Run a command in the admin room
+    async fn run_command(&self, command: &str) -> Result<(), anyhow::Error> {
+        let counter = self.txn_counter.fetch_add(1, Ordering::Relaxed);
+        let txn_id = format!("mas-{}-{counter}", self.txn_prefix);
+        let body = self.command_body(command);
+
+        self.send_message(
+            &self.admin_room,
+            &txn_id,
+            &MessageContent {
+                msgtype: "m.text",
+                body: &body,
+            },
+        )
+        .await
+        .with_context(|| format!("Failed to run the {command:?} command in Conduit"))
+    }
+}
+
+/// Content of an `m.room.message` event
+#[derive(Serialize)]
+struct MessageContent<'a> {
+    msgtype: &'static str,
+    body: &'a str,
+}
+
+/// Response body of `/_matrix/client/v3/profile/{userId}`
+#[derive(Deserialize)]
+struct ProfileResponse {
+    #[serde(default)]
+    displayname: Option<String>,
+
+    #[serde(default)]
+    avatar_url: Option<String>,
+}
+
+/// Response body of `/_matrix/client/v3/register/available`
+#[derive(Deserialize)]
+struct UsernameAvailableResponse {
+    available: bool,
+}
+
+#[async_trait::async_trait]
+impl HomeserverConnection for ConduitConnection {
+    type Error = anyhow::Error;
+
+    fn homeserver(&self) -> &str {
+        &self.homeserver
+    }
+
+    fn capabilities(&self) -> HomeserverCapabilities {
+        HomeserverCapabilities::none()
+            .with(HomeserverFeature::Deactivation)
+            .with(HomeserverFeature::Notices)
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.query_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Debug),
+    )]
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        let mxid = urlencoding::encode(mxid);
+
+        let response = self
+            .get(&format!("_matrix/client/v3/profile/{mxid}"))
+            .send_traced()
+            .await
+            .context("Failed to query user from Conduit")?;
+
+        let response = response
+            .error_for_matrix_error()
+            .await
+            .context("Unexpected HTTP response while querying user from Conduit")?;
+
+        let body: ProfileResponse = response
+            .json()
+            .await
+            .context("Failed to deserialize response while querying user from Conduit")?;
+
+        // Conduit doesn't tell whether the user is deactivated
+        Ok(MatrixUser {
+            displayname: body.displayname,
+            avatar_url: body.avatar_url,
+            deactivated: false,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.provision_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = request.mxid(),
+            user.id = request.sub(),
+        ),
+        err(Debug),
+    )]
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        let localpart = request
+            .mxid()
+            .strip_prefix('@')
+            .and_then(|mxid| mxid.strip_suffix(&format!(":{}", self.homeserver)))
+            .with_context(|| format!("{} is not a user of {}", request.mxid(), self.homeserver))?;
+
+        if !self.is_localpart_available(localpart).await? {
+            debug!("User already exists in Conduit, leaving its profile as-is");
+            return Ok(false);
+        }
+
+        // Conduit sets a random password, which is never used as users
+        // authenticate through MAS
+        self.run_command(&format!("create-user {localpart}"))
+            .await?;
+
+        Ok(true)
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.is_localpart_available",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.localpart = localpart,
+        ),
+        err(Debug),
+    )]
+    async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error> {
+        let localpart = urlencoding::encode(localpart);
+
+        let response = self
+            .get(&format!(
+                "_matrix/client/v3/register/available?username={localpart}"
+            ))
+            .send_traced()
+            .await
+            .context("Failed to query localpart availability from Conduit")?;
+
+        match response.error_for_matrix_error().await {
+            Ok(resp) => {
+                let response: UsernameAvailableResponse = resp.json().await.context(
+                    "Unexpected response while querying localpart availability from Conduit",
+                )?;
+
+                Ok(response.available)
+            }
+
+            Err(err)
+                if err.errcode() == Some(M_INVALID_USERNAME)
+                    || err.errcode() == Some(M_USER_IN_USE) =>
+            {
+                debug!(
+                    error = &err as &dyn std::error::Error,
+                    "Localpart is not available"
+                );
+                Ok(false)
+            }
+
+            Err(err) => Err(err).context("Failed to query localpart availability from Conduit"),
+        }
+    }
+
+    async fn create_device(&self, _mxid: &str, _device_id: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages its devices itself, not creating the device");
+        Ok(())
+    }
+
+    async fn delete_device(&self, _mxid: &str, _device_id: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages its devices itself, not deleting the device");
+        Ok(())
+    }
+
+    async fn update_device_display_name(
+        &self,
+        _mxid: &str,
+        _device_id: &str,
+        _display_name: &str,
+    ) -> Result<(), Self::Error> {
+        debug!("Conduit manages its devices itself, not renaming the device");
+        Ok(())
+    }
+
+    async fn sync_devices(
+        &self,
+        _mxid: &str,
+        _devices: HashSet<String>,
+    ) -> Result<(), Self::Error> {
+        debug!("Conduit manages its devices itself, not syncing the devices");
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.delete_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            erase = erase,
+        ),
+        err(Debug),
+    )]
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        if erase {
+            warn!("Conduit can't erase users, only deactivating the user");
+        }
+
+        self.run_command(&format!("deactivate-user {mxid}")).await
+    }
+
+    async fn reactivate_user(&self, _mxid: &str) -> Result<(), Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::Reactivation,
+        }
+        .into())
+    }
+
+    async fn set_displayname(&self, _mxid: &str, _displayname: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages the profile of users itself, not setting the display name");
+        Ok(())
+    }
+
+    async fn unset_displayname(&self, _mxid: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages the profile of users itself, not unsetting the display name");
+        Ok(())
+    }
+
+    async fn set_avatar_url(&self, _mxid: &str, _avatar_url: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages the profile of users itself, not setting the avatar");
+        Ok(())
+    }
+
+    async fn unset_avatar_url(&self, _mxid: &str) -> Result<(), Self::Error> {
+        debug!("Conduit manages the profile of users itself, not unsetting the avatar");
+        Ok(())
+    }
+
+    async fn upload_media(
+        &self,
+        _content_type: &str,
+        _filename: &str,
+        _data: &[u8],
+    ) -> Result<String, Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::ProfileSync,
+        }
+        .into())
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        _mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::CrossSigningReset,
+        }
+        .into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.send_notice",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.room_id = room_id,
+        ),
+        err(Debug),
+    )]
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        self.send_message(
+            room_id,
+            txn_id,
+            &MessageContent {
+                msgtype: "m.notice",
+                body,
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    use super::*;
+
+    #[test]
+    fn test_command_body() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let connection = ConduitConnection::new(
+            "example.com".to_owned(),
+            Url::parse("http://localhost:6167/").unwrap(),
+            "access_token".to_owned(),
+            "!admins:example.com".to_owned(),
+            mas_http::reqwest_client(),
+            &mut rng,
+        );
+
+        assert_eq!(
+            connection.command_body("create-user alice"),
+            "@conduit:example.com: create-user alice"
+        );
+        assert!(connection
+            .capabilities()
+            .supports(HomeserverFeature::Notices));
+        assert!(!connection
+            .capabilities()
+            .supports(HomeserverFeature::Devices));
+    }
+}
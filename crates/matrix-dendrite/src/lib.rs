@@ -0,0 +1,452 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A [`HomeserverConnection`] for Dendrite
+//!
+//! Dendrite has no admin API to manage users the way Synapse does. Users are
+//! created through the shared-secret registration endpoint, and their profile
+//! and devices are left for Dendrite to manage. Deactivating users, sending
+//! notices and allowing cross-signing resets are not supported.
+
+use std::{collections::HashSet, time::SystemTime};
+
+use anyhow::Context;
+use error::MatrixResponseExt;
+use hmac::{Hmac, Mac};
+use http::Method;
+use mas_http::RequestBuilderExt as _;
+use mas_matrix::{
+    HomeserverCapabilities, HomeserverConnection, HomeserverFeature, MatrixUser, ProvisionRequest,
+    UnsupportedError,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use tracing::debug;
+use url::Url;
+
+mod error;
+
+/// The name of the backend, in errors
+const BACKEND: &str = "Dendrite";
+
+/// Encountered when trying to register a user ID which has been taken.
+/// — <https://spec.matrix.org/v1.10/client-server-api/#other-error-codes>
+const M_USER_IN_USE: &str = "M_USER_IN_USE";
+/// Encountered when trying to register a user ID which is not valid.
+/// — <https://spec.matrix.org/v1.10/client-server-api/#other-error-codes>
+const M_INVALID_USERNAME: &str = "M_INVALID_USERNAME";
+
+#[derive(Clone)]
+pub struct DendriteConnection {
+    homeserver: String,
+    endpoint: Url,
+    registration_shared_secret: String,
+    http_client: reqwest::Client,
+}
+
+impl DendriteConnection {
+    #[must_use]
+    pub fn new(
+        homeserver: String,
+        endpoint: Url,
+        registration_shared_secret: String,
+        http_client: reqwest::Client,
+    ) -> Self {
+        Self {
+            homeserver,
+            endpoint,
+            registration_shared_secret,
+            http_client,
+        }
+    }
+
+    fn builder(&self, method: Method, url: &str) -> reqwest::RequestBuilder {
+        self.http_client.request(
+            method,
+            self.endpoint
+                .join(url)
+                .map(String::from)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.builder(Method::POST, url)
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.builder(Method::GET, url)
+    }
+
+    /// Compute the hex-encoded HMAC-SHA1 of the given parts, separated by NUL
+    /// bytes, with the registration shared secret
+    fn mac(&self, parts: &[&str]) -> String {
+        // HMAC can take keys of any size
+        let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(self.registration_shared_secret.as_bytes())
+        else {
+            unreachable!()
+        };
+
+        for (index, part) in parts.iter().enumerate() {
+            if index > 0 {
+                mac.update(b"\0");
+            }
+            mac.update(part.as_bytes());
+        }
+
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Get the localpart of a Matrix ID on this homeserver
+    fn localpart<'a>(&self, mxid: &'a str) -> Result<&'a str, anyhow::Error> {
+        mxid.strip_prefix('@')
+            .and_then(|mxid| mxid.strip_suffix(&format!(":{}", self.homeserver)))
+            .with_context(|| format!("{mxid} is not a user of {}", self.homeserver))
+    }
+}
+
+/// Response body of `/_synapse/admin/v1/register`, when getting a nonce
+#[derive(Deserialize)]
+struct NonceResponse {
+    nonce: String,
+}
+
+/// Request body of `/_synapse/admin/v1/register`
+#[derive(Serialize)]
+struct SharedSecretRegistrationRequest<'a> {
+    nonce: &'a str,
+    username: &'a str,
+    password: &'a str,
+    admin: bool,
+    mac: &'a str,
+}
+
+/// Response body of `/_synapse/admin/v1/register`, when registering a user
+#[derive(Deserialize)]
+struct SharedSecretRegistrationResponse {
+    access_token: String,
+}
+
+/// Response body of `/_matrix/client/v3/profile/{userId}`
+#[derive(Deserialize)]
+struct ProfileResponse {
+    #[serde(default)]
+    displayname: Option<String>,
+
+    #[serde(default)]
+    avatar_url: Option<String>,
+}
+
+/// Response body of `/_matrix/client/v3/register/available`
+#[derive(Deserialize)]
+struct UsernameAvailableResponse {
+    available: bool,
+}
+
+#[async_trait::async_trait]
+impl HomeserverConnection for DendriteConnection {
+    type Error = anyhow::Error;
+
+    fn homeserver(&self) -> &str {
+        &self.homeserver
+    }
+
+    fn capabilities(&self) -> HomeserverCapabilities {
+        HomeserverCapabilities::none()
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.query_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Debug),
+    )]
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        let mxid = urlencoding::encode(mxid);
+
+        let response = self
+            .get(&format!("_matrix/client/v3/profile/{mxid}"))
+            .send_traced()
+            .await
+            .context("Failed to query user from Dendrite")?;
+
+        let response = response
+            .error_for_matrix_error()
+            .await
+            .context("Unexpected HTTP response while querying user from Dendrite")?;
+
+        let body: ProfileResponse = response
+            .json()
+            .await
+            .context("Failed to deserialize response while querying user from Dendrite")?;
+
+        // Dendrite doesn't tell whether the user is deactivated
+        Ok(MatrixUser {
+            displayname: body.displayname,
+            avatar_url: body.avatar_url,
+            deactivated: false,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.provision_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = request.mxid(),
+            user.id = request.sub(),
+        ),
+        err(Debug),
+    )]
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        let username = self.localpart(request.mxid())?;
+
+        let response = self
+            .get("_synapse/admin/v1/register")
+            .send_traced()
+            .await
+            .context("Failed to get a registration nonce from Dendrite")?;
+
+        let response = response
+            .error_for_matrix_error()
+            .await
+            .context("Unexpected HTTP response while getting a registration nonce from Dendrite")?;
+
+        let NonceResponse { nonce } = response
+            .json()
+            .await
+            .context("Failed to deserialize response while getting a registration nonce")?;
+
+        // Users authenticate through MAS, so the password is never used. It is
+        // derived from the shared secret, so that nobody else can guess it.
+        let password = self.mac(&["password", &nonce, username]);
+        let mac = self.mac(&[&nonce, username, &password, "notadmin"]);
+
+        let response = self
+            .post("_synapse/admin/v1/register")
+            .json(&SharedSecretRegistrationRequest {
+                nonce: &nonce,
+                username,
+                password: &password,
+                admin: false,
+                mac: &mac,
+            })
+            .send_traced()
+            .await
+            .context("Failed to provision user in Dendrite")?;
+
+        let response = match response.error_for_matrix_error().await {
+            Ok(response) => response,
+            Err(err) if err.errcode() == Some(M_USER_IN_USE) => {
+                debug!("User already exists in Dendrite, leaving its profile as-is");
+                return Ok(false);
+            }
+            Err(err) => {
+                return Err(err)
+                    .context("Unexpected HTTP response while provisioning user in Dendrite")
+            }
+        };
+
+        let body: SharedSecretRegistrationResponse = response
+            .json()
+            .await
+            .context("Failed to deserialize response while provisioning user in Dendrite")?;
+
+        // The registration logs the user in, which isn't needed
+        let response = self
+            .post("_matrix/client/v3/logout")
+            .bearer_auth(&body.access_token)
+            .send_traced()
+            .await
+            .context("Failed to log out the provisioned user from Dendrite")?;
+
+        response
+            .error_for_matrix_error()
+            .await
+            .context("Unexpected HTTP response while logging out the provisioned user")?;
+
+        Ok(true)
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.is_localpart_available",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.localpart = localpart,
+        ),
+        err(Debug),
+    )]
+    async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error> {
+        let localpart = urlencoding::encode(localpart);
+
+        let response = self
+            .get(&format!(
+                "_matrix/client/v3/register/available?username={localpart}"
+            ))
+            .send_traced()
+            .await
+            .context("Failed to query localpart availability from Dendrite")?;
+
+        match response.error_for_matrix_error().await {
+            Ok(resp) => {
+                let response: UsernameAvailableResponse = resp.json().await.context(
+                    "Unexpected response while querying localpart availability from Dendrite",
+                )?;
+
+                Ok(response.available)
+            }
+
+            Err(err)
+                if err.errcode() == Some(M_INVALID_USERNAME)
+                    || err.errcode() == Some(M_USER_IN_USE) =>
+            {
+                debug!(
+                    error = &err as &dyn std::error::Error,
+                    "Localpart is not available"
+                );
+                Ok(false)
+            }
+
+            Err(err) => Err(err).context("Failed to query localpart availability from Dendrite"),
+        }
+    }
+
+    async fn create_device(&self, _mxid: &str, _device_id: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages its devices itself, not creating the device");
+        Ok(())
+    }
+
+    async fn delete_device(&self, _mxid: &str, _device_id: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages its devices itself, not deleting the device");
+        Ok(())
+    }
+
+    async fn update_device_display_name(
+        &self,
+        _mxid: &str,
+        _device_id: &str,
+        _display_name: &str,
+    ) -> Result<(), Self::Error> {
+        debug!("Dendrite manages its devices itself, not renaming the device");
+        Ok(())
+    }
+
+    async fn sync_devices(
+        &self,
+        _mxid: &str,
+        _devices: HashSet<String>,
+    ) -> Result<(), Self::Error> {
+        debug!("Dendrite manages its devices itself, not syncing the devices");
+        Ok(())
+    }
+
+    async fn delete_user(&self, _mxid: &str, _erase: bool) -> Result<(), Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::Deactivation,
+        }
+        .into())
+    }
+
+    async fn reactivate_user(&self, _mxid: &str) -> Result<(), Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::Reactivation,
+        }
+        .into())
+    }
+
+    async fn set_displayname(&self, _mxid: &str, _displayname: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages the profile of users itself, not setting the display name");
+        Ok(())
+    }
+
+    async fn unset_displayname(&self, _mxid: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages the profile of users itself, not unsetting the display name");
+        Ok(())
+    }
+
+    async fn set_avatar_url(&self, _mxid: &str, _avatar_url: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages the profile of users itself, not setting the avatar");
+        Ok(())
+    }
+
+    async fn unset_avatar_url(&self, _mxid: &str) -> Result<(), Self::Error> {
+        debug!("Dendrite manages the profile of users itself, not unsetting the avatar");
+        Ok(())
+    }
+
+    async fn upload_media(
+        &self,
+        _content_type: &str,
+        _filename: &str,
+        _data: &[u8],
+    ) -> Result<String, Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::ProfileSync,
+        }
+        .into())
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        _mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::CrossSigningReset,
+        }
+        .into())
+    }
+
+    async fn send_notice(
+        &self,
+        _room_id: &str,
+        _txn_id: &str,
+        _body: &str,
+    ) -> Result<(), Self::Error> {
+        Err(UnsupportedError {
+            backend: BACKEND,
+            feature: HomeserverFeature::Notices,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_secret_mac() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let connection = DendriteConnection::new(
+            "example.com".to_owned(),
+            Url::parse("http://localhost:8008/").unwrap(),
+            "shared_secret".to_owned(),
+            mas_http::reqwest_client(),
+        );
+
+        let mac = connection.mac(&["nonce", "alice", "password", "notadmin"]);
+        assert_eq!(mac.len(), 40);
+        assert_eq!(
+            mac,
+            connection.mac(&["nonce", "alice", "password", "notadmin"])
+        );
+        assert_ne!(
+            mac,
+            connection.mac(&["nonce", "alice", "password", "admin"])
+        );
+
+        assert_eq!(connection.localpart("@alice:example.com").unwrap(), "alice");
+        assert!(connection.localpart("@alice:example.org").is_err());
+        assert!(connection.localpart("alice").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Features of the Synapse admin API which depend on the Synapse version
+//!
+//! The version is probed through `/_synapse/admin/v1/server_version` when the
+//! service starts. When it is unknown, because the probe failed or the
+//! version couldn't be parsed, all features are assumed to be available.
+
+use serde::Deserialize;
+
+/// A feature of the Synapse admin API which isn't available on all versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynapseFeature {
+    /// Deleting multiple devices of a user in a single request
+    BulkDeviceDeletion,
+
+    /// Allowing a user to replace their cross-signing keys without
+    /// user-interactive authentication
+    CrossSigningReset,
+}
+
+impl SynapseFeature {
+    /// All the known features
+    pub const ALL: [Self; 2] = [Self::BulkDeviceDeletion, Self::CrossSigningReset];
+
+    /// A short name of the feature, for logs and diagnostics
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::BulkDeviceDeletion => "bulk_device_deletion",
+            Self::CrossSigningReset => "cross_signing_reset",
+        }
+    }
+
+    /// The first Synapse version with this feature, as a major and minor
+    /// version
+    #[must_use]
+    pub const fn min_version(self) -> (u32, u32) {
+        match self {
+            Self::BulkDeviceDeletion => (1, 15),
+            Self::CrossSigningReset => (1, 97),
+        }
+    }
+}
+
+/// Response body of `/_synapse/admin/v1/server_version`
+#[derive(Deserialize)]
+pub(crate) struct ServerVersionResponse {
+    pub(crate) server_version: String,
+}
+
+/// The capabilities of a Synapse homeserver, derived from its version
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SynapseCapabilities {
+    server_version: Option<String>,
+    version: Option<(u32, u32)>,
+}
+
+impl SynapseCapabilities {
+    /// Derive the capabilities from the version reported by Synapse, like
+    /// `1.110.0` or `1.111.0rc1 (b=develop,abc123)`
+    #[must_use]
+    pub fn from_server_version(server_version: String) -> Self {
+        let version = parse_version(&server_version);
+        Self {
+            server_version: Some(server_version),
+            version,
+        }
+    }
+
+    /// The version reported by Synapse, if it was probed
+    #[must_use]
+    pub fn server_version(&self) -> Option<&str> {
+        self.server_version.as_deref()
+    }
+
+    /// Whether the homeserver supports the given feature
+    ///
+    /// Returns `true` if the version of the homeserver is unknown.
+    #[must_use]
+    pub fn supports(&self, feature: SynapseFeature) -> bool {
+        self.version
+            .is_none_or(|version| version >= feature.min_version())
+    }
+}
+
+/// Parse the major and minor version out of the version reported by Synapse
+fn parse_version(server_version: &str) -> Option<(u32, u32)> {
+    let version = server_version.split_whitespace().next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()?
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("1.110.0"), Some((1, 110)));
+        assert_eq!(
+            parse_version("1.111.0rc1 (b=develop,abc123)"),
+            Some((1, 111))
+        );
+        assert_eq!(parse_version("2.0"), Some((2, 0)));
+        assert_eq!(parse_version("1.98rc1"), Some((1, 98)));
+        assert_eq!(parse_version("unknown"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn test_supports() {
+        let unknown = SynapseCapabilities::default();
+        assert!(unknown.supports(SynapseFeature::CrossSigningReset));
+
+        let old = SynapseCapabilities::from_server_version("1.90.0".to_owned());
+        assert_eq!(old.server_version(), Some("1.90.0"));
+        assert!(old.supports(SynapseFeature::BulkDeviceDeletion));
+        assert!(!old.supports(SynapseFeature::CrossSigningReset));
+
+        let recent = SynapseCapabilities::from_server_version("1.110.0".to_owned());
+        assert!(recent.supports(SynapseFeature::CrossSigningReset));
+
+        let unparsable = SynapseCapabilities::from_server_version("custom".to_owned());
+        assert!(unparsable.supports(SynapseFeature::CrossSigningReset));
+    }
+}
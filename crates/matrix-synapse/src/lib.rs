@@ -4,15 +4,22 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{bail, Context};
 use error::SynapseResponseExt;
-use http::{Method, StatusCode};
+use http::{header::CONTENT_TYPE, Method, StatusCode};
 use mas_http::RequestBuilderExt as _;
-use mas_matrix::{HomeserverConnection, MatrixUser, ProvisionRequest};
+use mas_matrix::{
+    HomeserverCapabilities, HomeserverConnection, HomeserverFeature, MatrixUser, ProvisionRequest,
+    UnsupportedError,
+};
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use tracing::{debug, info};
 use url::Url;
 
 static SYNAPSE_AUTH_PROVIDER: &str = "oauth-delegated";
@@ -24,14 +31,19 @@ const M_USER_IN_USE: &str = "M_USER_IN_USE";
 /// — <https://spec.matrix.org/v1.10/client-server-api/#other-error-codes>
 const M_INVALID_USERNAME: &str = "M_INVALID_USERNAME";
 
+mod capabilities;
 mod error;
 
+use self::capabilities::ServerVersionResponse;
+pub use self::capabilities::{SynapseCapabilities, SynapseFeature};
+
 #[derive(Clone)]
 pub struct SynapseConnection {
     homeserver: String,
     endpoint: Url,
     access_token: String,
     http_client: reqwest::Client,
+    capabilities: Arc<RwLock<SynapseCapabilities>>,
 }
 
 impl SynapseConnection {
@@ -47,9 +59,71 @@ impl SynapseConnection {
             endpoint,
             access_token,
             http_client,
+            capabilities: Arc::default(),
         }
     }
 
+    /// Probe the version of Synapse, and cache the features it supports
+    ///
+    /// Until this is called, or if it fails, all features are assumed to be
+    /// available.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver could not be reached or replied with
+    /// an unexpected response
+    #[tracing::instrument(
+        name = "homeserver.probe",
+        skip_all,
+        fields(matrix.homeserver = self.homeserver),
+        err(Debug),
+    )]
+    pub async fn probe(&self) -> Result<SynapseCapabilities, anyhow::Error> {
+        let response = self
+            .get("_synapse/admin/v1/server_version")
+            .send_traced()
+            .await
+            .context("Failed to query the Synapse version")?;
+
+        let response = response
+            .error_for_synapse_error()
+            .await
+            .context("Unexpected HTTP response while querying the Synapse version")?;
+
+        let body: ServerVersionResponse = response
+            .json()
+            .await
+            .context("Failed to parse response while querying the Synapse version")?;
+
+        let capabilities = SynapseCapabilities::from_server_version(body.server_version);
+        let unsupported: Vec<&str> = SynapseFeature::ALL
+            .into_iter()
+            .filter(|feature| !capabilities.supports(*feature))
+            .map(SynapseFeature::as_str)
+            .collect();
+        info!(
+            synapse.version = capabilities.server_version(),
+            ?unsupported,
+            "Probed the homeserver capabilities"
+        );
+
+        *self
+            .capabilities
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = capabilities.clone();
+
+        Ok(capabilities)
+    }
+
+    /// The capabilities of the homeserver, as of the last probe
+    #[must_use]
+    pub fn synapse_capabilities(&self) -> SynapseCapabilities {
+        self.capabilities
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
     fn builder(&self, method: Method, url: &str) -> reqwest::RequestBuilder {
         self.http_client
             .request(
@@ -140,6 +214,21 @@ struct SetDisplayNameRequest<'a> {
     displayname: &'a str,
 }
 
+#[derive(Serialize)]
+struct SetAvatarUrlRequest<'a> {
+    avatar_url: &'a str,
+}
+
+#[derive(Deserialize)]
+struct UploadMediaResponse {
+    content_uri: String,
+}
+
+#[derive(Serialize)]
+struct UpdateDeviceRequest<'a> {
+    display_name: &'a str,
+}
+
 #[derive(Serialize)]
 struct SynapseDeactivateUserRequest {
     erase: bool,
@@ -148,6 +237,18 @@ struct SynapseDeactivateUserRequest {
 #[derive(Serialize)]
 struct SynapseAllowCrossSigningResetRequest {}
 
+#[derive(Deserialize)]
+struct SynapseAllowCrossSigningResetResponse {
+    updatable_without_uia_before_ms: Option<u64>,
+}
+
+/// Content of an `m.notice` message event
+#[derive(Serialize)]
+struct NoticeContent<'a> {
+    msgtype: &'static str,
+    body: &'a str,
+}
+
 /// Response body of
 /// `/_synapse/admin/v1/username_available?username={localpart}`
 #[derive(Deserialize)]
@@ -163,6 +264,23 @@ impl HomeserverConnection for SynapseConnection {
         &self.homeserver
     }
 
+    fn capabilities(&self) -> HomeserverCapabilities {
+        let capabilities = HomeserverCapabilities::all();
+        if self
+            .synapse_capabilities()
+            .supports(SynapseFeature::CrossSigningReset)
+        {
+            capabilities
+        } else {
+            capabilities.without(HomeserverFeature::CrossSigningReset)
+        }
+    }
+
+    async fn refresh_capabilities(&self) -> Result<(), Self::Error> {
+        self.probe().await?;
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "homeserver.query_user",
         skip_all,
@@ -375,6 +493,49 @@ impl HomeserverConnection for SynapseConnection {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "homeserver.update_device_display_name",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.device_id = device_id,
+        ),
+        err(Debug),
+    )]
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        let mxid = urlencoding::encode(mxid);
+        let device_id = urlencoding::encode(device_id);
+
+        let response = self
+            .put(&format!(
+                "_synapse/admin/v2/users/{mxid}/devices/{device_id}"
+            ))
+            .json(&UpdateDeviceRequest { display_name })
+            .send_traced()
+            .await
+            .context("Failed to update device in Synapse")?;
+
+        let response = response
+            .error_for_synapse_error()
+            .await
+            .context("Unexpected HTTP response while updating device in Synapse")?;
+
+        if response.status() != StatusCode::OK {
+            bail!(
+                "Unexpected HTTP code while updating device in Synapse: {}",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "homeserver.sync_devices",
         skip_all,
@@ -412,27 +573,37 @@ impl HomeserverConnection for SynapseConnection {
             body.devices.into_iter().map(|d| d.device_id).collect();
 
         // First, delete all the devices that are not needed anymore
-        let to_delete = existing_devices.difference(&devices).cloned().collect();
-
-        let response = self
-            .post(&format!(
-                "_synapse/admin/v2/users/{mxid_url}/delete_devices"
-            ))
-            .json(&SynapseDeleteDevicesRequest { devices: to_delete })
-            .send_traced()
-            .await
-            .context("Failed to delete devices from Synapse")?;
-
-        let response = response
-            .error_for_synapse_error()
-            .await
-            .context("Unexpected HTTP response while deleting devices from Synapse")?;
-
-        if response.status() != StatusCode::OK {
-            bail!(
-                "Unexpected HTTP code while deleting devices from Synapse: {}",
-                response.status()
-            );
+        let to_delete: Vec<String> = existing_devices.difference(&devices).cloned().collect();
+
+        if self
+            .synapse_capabilities()
+            .supports(SynapseFeature::BulkDeviceDeletion)
+        {
+            let response = self
+                .post(&format!(
+                    "_synapse/admin/v2/users/{mxid_url}/delete_devices"
+                ))
+                .json(&SynapseDeleteDevicesRequest { devices: to_delete })
+                .send_traced()
+                .await
+                .context("Failed to delete devices from Synapse")?;
+
+            let response = response
+                .error_for_synapse_error()
+                .await
+                .context("Unexpected HTTP response while deleting devices from Synapse")?;
+
+            if response.status() != StatusCode::OK {
+                bail!(
+                    "Unexpected HTTP code while deleting devices from Synapse: {}",
+                    response.status()
+                );
+            }
+        } else {
+            // Older versions can only delete devices one by one
+            for device_id in &to_delete {
+                self.delete_device(mxid, device_id).await?;
+            }
         }
 
         // Then, create the devices that are missing. There is no batching API to do
@@ -558,6 +729,91 @@ impl HomeserverConnection for SynapseConnection {
         self.set_displayname(mxid, "").await
     }
 
+    #[tracing::instrument(
+        name = "homeserver.set_avatar_url",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.avatar_url = avatar_url,
+        ),
+        err(Debug),
+    )]
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error> {
+        let mxid = urlencoding::encode(mxid);
+        let response = self
+            .put(&format!("_matrix/client/v3/profile/{mxid}/avatar_url"))
+            .json(&SetAvatarUrlRequest { avatar_url })
+            .send_traced()
+            .await
+            .context("Failed to set avatar URL in Synapse")?;
+
+        let response = response
+            .error_for_synapse_error()
+            .await
+            .context("Unexpected HTTP response while setting avatar URL in Synapse")?;
+
+        if response.status() != StatusCode::OK {
+            bail!(
+                "Unexpected HTTP code while setting avatar URL in Synapse: {}",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.unset_avatar_url",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Display),
+    )]
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.set_avatar_url(mxid, "").await
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.upload_media",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            media.content_type = content_type,
+            media.size = data.len(),
+        ),
+        err(Debug),
+    )]
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error> {
+        let filename = urlencoding::encode(filename);
+        let response = self
+            .post(&format!("_matrix/media/v3/upload?filename={filename}"))
+            .header(CONTENT_TYPE, content_type)
+            .body(data.to_vec())
+            .send_traced()
+            .await
+            .context("Failed to upload media to Synapse")?;
+
+        let response = response
+            .error_for_synapse_error()
+            .await
+            .context("Unexpected HTTP response while uploading media to Synapse")?;
+
+        let body: UploadMediaResponse = response
+            .json()
+            .await
+            .context("Failed to deserialize response while uploading media to Synapse")?;
+
+        Ok(body.content_uri)
+    }
+
     #[tracing::instrument(
         name = "homeserver.allow_cross_signing_reset",
         skip_all,
@@ -567,7 +823,23 @@ impl HomeserverConnection for SynapseConnection {
         ),
         err(Debug),
     )]
-    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
+        let capabilities = self.synapse_capabilities();
+        if !capabilities.supports(SynapseFeature::CrossSigningReset) {
+            let (major, minor) = SynapseFeature::CrossSigningReset.min_version();
+            let error = UnsupportedError {
+                backend: "Synapse",
+                feature: HomeserverFeature::CrossSigningReset,
+            };
+            return Err(anyhow::Error::new(error).context(format!(
+                "Synapse {} doesn't support allowing cross-signing resets, version {major}.{minor} or later is required",
+                capabilities.server_version().unwrap_or("(unknown)"),
+            )));
+        }
+
         let mxid = urlencoding::encode(mxid);
 
         let response = self
@@ -591,6 +863,57 @@ impl HomeserverConnection for SynapseConnection {
             );
         }
 
+        let body: SynapseAllowCrossSigningResetResponse = response.json().await.context(
+            "Failed to deserialize response while allowing cross-signing reset in Synapse",
+        )?;
+
+        Ok(body
+            .updatable_without_uia_before_ms
+            .map(|ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms)))
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.send_notice",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.room_id = room_id,
+        ),
+        err(Debug),
+    )]
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        let room_id = urlencoding::encode(room_id);
+        let txn_id = urlencoding::encode(txn_id);
+
+        let response = self
+            .put(&format!(
+                "_matrix/client/v3/rooms/{room_id}/send/m.room.message/{txn_id}"
+            ))
+            .json(&NoticeContent {
+                msgtype: "m.notice",
+                body,
+            })
+            .send_traced()
+            .await
+            .context("Failed to send notice to the room")?;
+
+        let response = response
+            .error_for_synapse_error()
+            .await
+            .context("Unexpected HTTP response while sending notice to the room")?;
+
+        if response.status() != StatusCode::OK {
+            bail!(
+                "Unexpected HTTP code while sending notice to the room: {}",
+                response.status(),
+            );
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,175 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! What each homeserver backend supports
+//!
+//! Not all homeservers have an admin API as complete as Synapse's. Operations
+//! on an unsupported feature either do nothing, for the ones mirroring on the
+//! homeserver some state MAS manages, like devices or display names, or fail
+//! with an [`UnsupportedError`], for the ones expected to have an effect, like
+//! deactivating a user.
+
+/// A feature of the homeserver admin API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomeserverFeature {
+    /// Updating the profile of existing users: display name, avatar and email
+    /// addresses
+    ProfileSync,
+
+    /// Creating, renaming and deleting the devices of users
+    Devices,
+
+    /// Deactivating users
+    Deactivation,
+
+    /// Reactivating deactivated users
+    Reactivation,
+
+    /// Allowing users to reset their cross-signing keys
+    CrossSigningReset,
+
+    /// Sending notices to rooms
+    Notices,
+}
+
+impl HomeserverFeature {
+    /// All the known features
+    pub const ALL: [Self; 6] = [
+        Self::ProfileSync,
+        Self::Devices,
+        Self::Deactivation,
+        Self::Reactivation,
+        Self::CrossSigningReset,
+        Self::Notices,
+    ];
+
+    /// A short name of the feature, for logs and diagnostics
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ProfileSync => "profile_sync",
+            Self::Devices => "devices",
+            Self::Deactivation => "deactivation",
+            Self::Reactivation => "reactivation",
+            Self::CrossSigningReset => "cross_signing_reset",
+            Self::Notices => "notices",
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl std::fmt::Display for HomeserverFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The set of features supported by a homeserver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HomeserverCapabilities {
+    features: u8,
+}
+
+impl Default for HomeserverCapabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl HomeserverCapabilities {
+    /// A homeserver supporting all the features
+    #[must_use]
+    pub const fn all() -> Self {
+        Self::none()
+            .with(HomeserverFeature::ProfileSync)
+            .with(HomeserverFeature::Devices)
+            .with(HomeserverFeature::Deactivation)
+            .with(HomeserverFeature::Reactivation)
+            .with(HomeserverFeature::CrossSigningReset)
+            .with(HomeserverFeature::Notices)
+    }
+
+    /// A homeserver supporting none of the features
+    #[must_use]
+    pub const fn none() -> Self {
+        Self { features: 0 }
+    }
+
+    /// Add a feature to the set
+    #[must_use]
+    pub const fn with(self, feature: HomeserverFeature) -> Self {
+        Self {
+            features: self.features | feature.bit(),
+        }
+    }
+
+    /// Remove a feature from the set
+    #[must_use]
+    pub const fn without(self, feature: HomeserverFeature) -> Self {
+        Self {
+            features: self.features & !feature.bit(),
+        }
+    }
+
+    /// Whether the homeserver supports the given feature
+    #[must_use]
+    pub const fn supports(self, feature: HomeserverFeature) -> bool {
+        self.features & feature.bit() != 0
+    }
+
+    /// The features not supported by the homeserver
+    pub fn unsupported(self) -> impl Iterator<Item = HomeserverFeature> {
+        HomeserverFeature::ALL
+            .into_iter()
+            .filter(move |feature| !self.supports(*feature))
+    }
+}
+
+/// An operation was asked on a feature the homeserver doesn't support
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{backend} doesn't support the {feature} feature")]
+pub struct UnsupportedError {
+    /// The name of the homeserver backend
+    pub backend: &'static str,
+
+    /// The unsupported feature
+    pub feature: HomeserverFeature,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let all = HomeserverCapabilities::all();
+        assert!(HomeserverFeature::ALL
+            .into_iter()
+            .all(|feature| all.supports(feature)));
+        assert_eq!(all.unsupported().count(), 0);
+
+        let capabilities = all.without(HomeserverFeature::Devices);
+        assert!(!capabilities.supports(HomeserverFeature::Devices));
+        assert!(capabilities.supports(HomeserverFeature::Notices));
+        assert_eq!(
+            capabilities.unsupported().collect::<Vec<_>>(),
+            vec![HomeserverFeature::Devices]
+        );
+
+        let error = UnsupportedError {
+            backend: "Test",
+            feature: HomeserverFeature::Devices,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Test doesn't support the devices feature"
+        );
+
+        assert_eq!(HomeserverCapabilities::none().unsupported().count(), 6);
+    }
+}
@@ -4,11 +4,20 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+mod capabilities;
 mod mock;
+mod resilient;
 
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, sync::Arc, time::SystemTime};
 
-pub use self::mock::HomeserverConnection as MockHomeserverConnection;
+pub use self::{
+    capabilities::{HomeserverCapabilities, HomeserverFeature, UnsupportedError},
+    mock::HomeserverConnection as MockHomeserverConnection,
+    resilient::{
+        CircuitBreaker, CircuitState, ResilienceError, ResilienceOptions,
+        ResilientHomeserverConnection,
+    },
+};
 
 // TODO: this should probably be another error type by default
 pub type BoxHomeserverConnection<Error = anyhow::Error> =
@@ -184,6 +193,23 @@ pub trait HomeserverConnection: Send + Sync {
     /// Get the homeserver URL.
     fn homeserver(&self) -> &str;
 
+    /// Get the features supported by the homeserver.
+    ///
+    /// Defaults to all the features.
+    fn capabilities(&self) -> HomeserverCapabilities {
+        HomeserverCapabilities::all()
+    }
+
+    /// Detect again the features supported by the homeserver, for the
+    /// backends which depend on its version.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable.
+    async fn refresh_capabilities(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Get the Matrix ID of the user with the given localpart.
     ///
     /// # Parameters
@@ -255,6 +281,25 @@ pub trait HomeserverConnection: Send + Sync {
     /// not be deleted.
     async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error>;
 
+    /// Set the display name of a device of a user on the homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `mxid` - The Matrix ID of the user owning the device.
+    /// * `device_id` - The device ID to rename.
+    /// * `display_name` - The new display name of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the device could
+    /// not be renamed.
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error>;
+
     /// Sync the list of devices of a user with the homeserver.
     ///
     /// # Parameters
@@ -318,8 +363,57 @@ pub trait HomeserverConnection: Send + Sync {
     /// could not be unset.
     async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error>;
 
+    /// Set the avatar URL of a user on the homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `mxid` - The Matrix ID of the user to set the avatar URL for.
+    /// * `avatar_url` - The `mxc://` URL of the avatar to set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the avatar URL
+    /// could not be set.
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error>;
+
+    /// Unset the avatar URL of a user on the homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `mxid` - The Matrix ID of the user to unset the avatar URL for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the avatar URL
+    /// could not be unset.
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error>;
+
+    /// Upload a file to the media repository of the homeserver.
+    ///
+    /// Returns the `mxc://` URI of the uploaded media.
+    ///
+    /// # Parameters
+    ///
+    /// * `content_type` - The MIME type of the file.
+    /// * `filename` - The name of the file.
+    /// * `data` - The content of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the file could not
+    /// be uploaded.
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error>;
+
     /// Temporarily allow a user to reset their cross-signing keys.
     ///
+    /// Returns the time until which the user can reset their keys without
+    /// authenticating again, if the homeserver tells it.
+    ///
     /// # Parameters
     ///
     /// * `mxid` - The Matrix ID of the user to allow cross-signing key reset
@@ -328,7 +422,26 @@ pub trait HomeserverConnection: Send + Sync {
     ///
     /// Returns an error if the homeserver is unreachable or the cross-signing
     /// reset could not be allowed.
-    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error>;
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error>;
+
+    /// Send a notice message to a room, as the user the connection is
+    /// authenticated as.
+    ///
+    /// # Parameters
+    ///
+    /// * `room_id` - The ID of the room to send the notice to.
+    /// * `txn_id` - A unique transaction ID, used to deduplicate retries.
+    /// * `body` - The text of the notice.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the notice could
+    /// not be sent.
+    async fn send_notice(&self, room_id: &str, txn_id: &str, body: &str)
+        -> Result<(), Self::Error>;
 }
 
 #[async_trait::async_trait]
@@ -339,6 +452,14 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
         (**self).homeserver()
     }
 
+    fn capabilities(&self) -> HomeserverCapabilities {
+        (**self).capabilities()
+    }
+
+    async fn refresh_capabilities(&self) -> Result<(), Self::Error> {
+        (**self).refresh_capabilities().await
+    }
+
     async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
         (**self).query_user(mxid).await
     }
@@ -359,6 +480,17 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
         (**self).delete_device(mxid, device_id).await
     }
 
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .update_device_display_name(mxid, device_id, display_name)
+            .await
+    }
+
     async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
         (**self).sync_devices(mxid, devices).await
     }
@@ -379,9 +511,38 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
         (**self).unset_displayname(mxid).await
     }
 
-    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error> {
+        (**self).set_avatar_url(mxid, avatar_url).await
+    }
+
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).unset_avatar_url(mxid).await
+    }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error> {
+        (**self).upload_media(content_type, filename, data).await
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
         (**self).allow_cross_signing_reset(mxid).await
     }
+
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        (**self).send_notice(room_id, txn_id, body).await
+    }
 }
 
 // Implement for Arc<T> where T: HomeserverConnection
@@ -393,6 +554,14 @@ impl<T: HomeserverConnection + ?Sized> HomeserverConnection for Arc<T> {
         (**self).homeserver()
     }
 
+    fn capabilities(&self) -> HomeserverCapabilities {
+        (**self).capabilities()
+    }
+
+    async fn refresh_capabilities(&self) -> Result<(), Self::Error> {
+        (**self).refresh_capabilities().await
+    }
+
     async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
         (**self).query_user(mxid).await
     }
@@ -413,6 +582,17 @@ impl<T: HomeserverConnection + ?Sized> HomeserverConnection for Arc<T> {
         (**self).delete_device(mxid, device_id).await
     }
 
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .update_device_display_name(mxid, device_id, display_name)
+            .await
+    }
+
     async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
         (**self).sync_devices(mxid, devices).await
     }
@@ -433,7 +613,36 @@ impl<T: HomeserverConnection + ?Sized> HomeserverConnection for Arc<T> {
         (**self).unset_displayname(mxid).await
     }
 
-    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error> {
+        (**self).set_avatar_url(mxid, avatar_url).await
+    }
+
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).unset_avatar_url(mxid).await
+    }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error> {
+        (**self).upload_media(content_type, filename, data).await
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
         (**self).allow_cross_signing_reset(mxid).await
     }
+
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        (**self).send_notice(room_id, txn_id, body).await
+    }
 }
@@ -4,7 +4,10 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
 
 use anyhow::Context;
 use async_trait::async_trait;
@@ -28,6 +31,8 @@ pub struct HomeserverConnection {
     homeserver: String,
     users: RwLock<HashMap<String, MockUser>>,
     reserved_localparts: RwLock<HashSet<&'static str>>,
+    notices: RwLock<HashMap<String, Vec<String>>>,
+    media: RwLock<HashMap<String, (String, Vec<u8>)>>,
 }
 
 impl HomeserverConnection {
@@ -40,12 +45,29 @@ impl HomeserverConnection {
             homeserver: homeserver.into(),
             users: RwLock::new(HashMap::new()),
             reserved_localparts: RwLock::new(HashSet::new()),
+            notices: RwLock::new(HashMap::new()),
+            media: RwLock::new(HashMap::new()),
         }
     }
 
     pub async fn reserve_localpart(&self, localpart: &'static str) {
         self.reserved_localparts.write().await.insert(localpart);
     }
+
+    /// Get the notices which were sent to the given room.
+    pub async fn notices(&self, room_id: &str) -> Vec<String> {
+        self.notices
+            .read()
+            .await
+            .get(room_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get the content type and content of an uploaded media.
+    pub async fn media(&self, mxc: &str) -> Option<(String, Vec<u8>)> {
+        self.media.read().await.get(mxc).cloned()
+    }
 }
 
 #[async_trait]
@@ -123,6 +145,18 @@ impl crate::HomeserverConnection for HomeserverConnection {
         Ok(())
     }
 
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        _display_name: &str,
+    ) -> Result<(), Self::Error> {
+        let users = self.users.read().await;
+        let user = users.get(mxid).context("User not found")?;
+        user.devices.get(device_id).context("Device not found")?;
+        Ok(())
+    }
+
     async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
@@ -166,10 +200,53 @@ impl crate::HomeserverConnection for HomeserverConnection {
         Ok(())
     }
 
-    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error> {
+        let mut users = self.users.write().await;
+        let user = users.get_mut(mxid).context("User not found")?;
+        user.avatar_url = Some(avatar_url.to_owned());
+        Ok(())
+    }
+
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error> {
+        let mut users = self.users.write().await;
+        let user = users.get_mut(mxid).context("User not found")?;
+        user.avatar_url = None;
+        Ok(())
+    }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        _filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error> {
+        let mut media = self.media.write().await;
+        let mxc = format!("mxc://{}/media{}", self.homeserver, media.len());
+        media.insert(mxc.clone(), (content_type.to_owned(), data.to_vec()));
+        Ok(mxc)
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
         user.cross_signing_reset_allowed = true;
+        Ok(None)
+    }
+
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        _txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        let mut notices = self.notices.write().await;
+        notices
+            .entry(room_id.to_owned())
+            .or_default()
+            .push(body.to_owned());
         Ok(())
     }
 }
@@ -216,6 +293,29 @@ mod tests {
         let user = conn.query_user(mxid).await.unwrap();
         assert_eq!(user.displayname, None);
 
+        // Set and unset the avatar URL
+        assert!(conn
+            .set_avatar_url(mxid, "mxc://example.org/abcdef")
+            .await
+            .is_ok());
+        let user = conn.query_user(mxid).await.unwrap();
+        assert_eq!(user.avatar_url, Some("mxc://example.org/abcdef".into()));
+
+        assert!(conn.unset_avatar_url(mxid).await.is_ok());
+        let user = conn.query_user(mxid).await.unwrap();
+        assert_eq!(user.avatar_url, None);
+
+        // Upload some media
+        let mxc = conn
+            .upload_media("image/png", "avatar.png", b"PNG")
+            .await
+            .unwrap();
+        assert!(mxc.starts_with("mxc://example.org/"));
+        assert_eq!(
+            conn.media(&mxc).await,
+            Some(("image/png".to_owned(), b"PNG".to_vec()))
+        );
+
         // Deleting a non-existent device should not fail
         assert!(conn.delete_device(mxid, device).await.is_ok());
 
@@ -224,10 +324,22 @@ mod tests {
         // Create the same device again
         assert!(conn.create_device(mxid, device).await.is_ok());
 
+        // Rename the device
+        assert!(conn
+            .update_device_display_name(mxid, device, "My phone")
+            .await
+            .is_ok());
+
         // XXX: there is no API to query devices yet in the trait
         // Delete the device
         assert!(conn.delete_device(mxid, device).await.is_ok());
 
+        // Deleted devices can't be renamed
+        assert!(conn
+            .update_device_display_name(mxid, device, "My phone")
+            .await
+            .is_err());
+
         // The user we just created should be not available
         assert!(!conn.is_localpart_available("test").await.unwrap());
         // But another user should be
@@ -236,5 +348,11 @@ mod tests {
         // Reserve the localpart, it should not be available anymore
         conn.reserve_localpart("alice").await;
         assert!(!conn.is_localpart_available("alice").await.unwrap());
+
+        // Send a notice to a room
+        let room_id = "!room:example.org";
+        assert!(conn.notices(room_id).await.is_empty());
+        assert!(conn.send_notice(room_id, "txn", "Hello").await.is_ok());
+        assert_eq!(conn.notices(room_id).await, vec!["Hello".to_owned()]);
     }
 }
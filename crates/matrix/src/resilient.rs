@@ -0,0 +1,582 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A [`HomeserverConnection`] wrapper which bounds the time and concurrency of
+//! the calls to the homeserver
+//!
+//! Every call gets a timeout, is retried with an exponential backoff and
+//! jitter when it fails, and waits for a slot in a bulkhead limiting how many
+//! calls run at the same time. After too many consecutive failures, a circuit
+//! breaker opens and calls fail right away until the homeserver had some time
+//! to recover, so that a slow homeserver can't stall the login handlers.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+use tokio::{
+    sync::Semaphore,
+    time::{timeout, Instant},
+};
+
+use crate::{
+    HomeserverCapabilities, HomeserverConnection, MatrixUser, ProvisionRequest, UnsupportedError,
+};
+
+static METER: LazyLock<Meter> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+});
+
+static CALL_DURATION: LazyLock<Histogram<u64>> = LazyLock::new(|| {
+    METER
+        .u64_histogram("homeserver.call.duration")
+        .with_unit("ms")
+        .with_description("Duration of the calls to the homeserver, including retries")
+        .init()
+});
+
+static CALL_RETRIES: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    METER
+        .u64_counter("homeserver.call.retries")
+        .with_unit("{retries}")
+        .with_description("Number of retried calls to the homeserver")
+        .init()
+});
+
+/// The errors returned by a [`ResilientHomeserverConnection`] on top of the
+/// ones of the wrapped connection
+#[derive(Debug, thiserror::Error)]
+pub enum ResilienceError {
+    /// The call did not complete in time
+    #[error("the call to the homeserver timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// Too many calls were waiting for the homeserver
+    #[error("too many calls to the homeserver are in progress")]
+    Overloaded,
+
+    /// The circuit breaker is open after too many failures
+    #[error("the homeserver is unavailable after too many failures")]
+    CircuitOpen,
+}
+
+/// Options of a [`ResilientHomeserverConnection`]
+#[derive(Debug, Clone, Copy)]
+pub struct ResilienceOptions {
+    /// How long a single attempt of a call can take
+    pub timeout: Duration,
+
+    /// How many times a failed call is retried
+    pub max_retries: u32,
+
+    /// The delay before the first retry, doubled for every other retry. The
+    /// actual delay is a random duration up to that value.
+    pub retry_base_delay: Duration,
+
+    /// How many calls can run at the same time
+    pub max_concurrency: NonZeroUsize,
+
+    /// How long a call can wait for one of the other calls to complete when
+    /// the maximum concurrency is reached
+    pub queue_timeout: Duration,
+
+    /// After how many consecutive failures the circuit breaker opens. `0`
+    /// disables the circuit breaker.
+    pub failure_threshold: u32,
+
+    /// How long the circuit breaker stays open before letting calls through
+    /// again
+    pub reset_after: Duration,
+}
+
+impl Default for ResilienceOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 2,
+            retry_base_delay: Duration::from_millis(100),
+            max_concurrency: NonZeroUsize::new(32).unwrap(),
+            queue_timeout: Duration::from_secs(5),
+            failure_threshold: 5,
+            reset_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The state of a [`CircuitBreaker`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+
+    /// Calls fail right away, as the homeserver failed too many times
+    Open,
+
+    /// Calls go through again after the circuit was open, and the next
+    /// failure opens it again
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// The name of the state, as reported in the readiness checks
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CircuitInner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Tracks the failures of the calls to the homeserver, and stops the calls
+/// when there are too many of them
+///
+/// This is cheap to clone, and clones share their state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Mutex<CircuitInner>>,
+    failure_threshold: u32,
+    reset_after: Duration,
+}
+
+impl CircuitBreaker {
+    /// Create a new, closed, circuit breaker
+    #[must_use]
+    pub fn new(failure_threshold: u32, reset_after: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            failure_threshold,
+            reset_after,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CircuitInner> {
+        // The state stays consistent even if a thread panicked while holding the
+        // lock
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// The current state of the circuit breaker
+    #[must_use]
+    pub fn state(&self) -> CircuitState {
+        match self.lock().opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.reset_after => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut inner = self.lock();
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+    }
+
+    fn record_failure(&self) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+
+        let mut inner = self.lock();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        if inner.consecutive_failures >= self.failure_threshold {
+            if inner.opened_at.is_none() {
+                tracing::warn!(
+                    failures = inner.consecutive_failures,
+                    "Too many failed calls to the homeserver, opening the circuit breaker"
+                );
+            }
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// A [`HomeserverConnection`] which bounds the time and concurrency of the
+/// calls made through another one
+///
+/// This is cheap to clone, and clones share their bulkhead and circuit
+/// breaker.
+#[derive(Clone)]
+pub struct ResilientHomeserverConnection<C> {
+    inner: C,
+    options: ResilienceOptions,
+    bulkhead: Arc<Semaphore>,
+    circuit_breaker: CircuitBreaker,
+    rng: Arc<Mutex<ChaChaRng>>,
+}
+
+impl<C> ResilientHomeserverConnection<C> {
+    /// Wrap a connection with the given options
+    ///
+    /// The random number generator is used to seed the one which computes the
+    /// jitter of the retries.
+    pub fn new(inner: C, options: ResilienceOptions, rng: &mut impl RngCore) -> Self {
+        Self {
+            inner,
+            bulkhead: Arc::new(Semaphore::new(options.max_concurrency.get())),
+            circuit_breaker: CircuitBreaker::new(options.failure_threshold, options.reset_after),
+            rng: Arc::new(Mutex::new(ChaChaRng::seed_from_u64(rng.next_u64()))),
+            options,
+        }
+    }
+
+    /// The wrapped connection
+    #[must_use]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// The circuit breaker of the connection, to check its state
+    #[must_use]
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    /// A random delay before the given retry
+    fn retry_delay(&self, retry: u32) -> Duration {
+        let max = self
+            .options
+            .retry_base_delay
+            .saturating_mul(2_u32.saturating_pow(retry.saturating_sub(1)));
+        let max_ms = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+        if max_ms == 0 {
+            return Duration::ZERO;
+        }
+
+        let mut rng = self
+            .rng
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Duration::from_millis(rng.gen_range(0..=max_ms))
+    }
+
+    /// Make a call to the homeserver, with the timeout, the retries, the
+    /// bulkhead and the circuit breaker
+    async fn call<T, F, Fut>(&self, method: &'static str, call: F) -> Result<T, anyhow::Error>
+    where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = Result<T, anyhow::Error>> + Send,
+        T: Send,
+    {
+        let start = Instant::now();
+        let result = self.call_inner(method, call).await;
+
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(e) => match e.downcast_ref::<ResilienceError>() {
+                Some(ResilienceError::Timeout(_)) => "timeout",
+                Some(ResilienceError::Overloaded) => "overloaded",
+                Some(ResilienceError::CircuitOpen) => "circuit_open",
+                None if e.is::<UnsupportedError>() => "unsupported",
+                None => "failure",
+            },
+        };
+        let duration_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        CALL_DURATION.record(
+            duration_ms,
+            &[
+                KeyValue::new("homeserver.method", method),
+                KeyValue::new("homeserver.outcome", outcome),
+            ],
+        );
+
+        result
+    }
+
+    async fn call_inner<T, F, Fut>(&self, method: &'static str, call: F) -> Result<T, anyhow::Error>
+    where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = Result<T, anyhow::Error>> + Send,
+        T: Send,
+    {
+        if self.circuit_breaker.state() == CircuitState::Open {
+            return Err(ResilienceError::CircuitOpen.into());
+        }
+
+        let _permit = timeout(self.options.queue_timeout, self.bulkhead.acquire())
+            .await
+            .map_err(|_| ResilienceError::Overloaded)?
+            .map_err(|_| ResilienceError::Overloaded)?;
+
+        let mut retry = 0;
+        loop {
+            let error = match timeout(self.options.timeout, call()).await {
+                Ok(Ok(value)) => {
+                    self.circuit_breaker.record_success();
+                    return Ok(value);
+                }
+                // The homeserver didn't fail, it just can't do that
+                Ok(Err(e)) if e.is::<UnsupportedError>() => return Err(e),
+                Ok(Err(e)) => e,
+                Err(_) => ResilienceError::Timeout(self.options.timeout).into(),
+            };
+
+            self.circuit_breaker.record_failure();
+
+            if retry >= self.options.max_retries
+                || self.circuit_breaker.state() == CircuitState::Open
+            {
+                return Err(error);
+            }
+
+            retry += 1;
+            let delay = self.retry_delay(retry);
+            tracing::warn!(
+                homeserver.method = method,
+                retry,
+                ?delay,
+                error = &*error as &dyn std::error::Error,
+                "Call to the homeserver failed, retrying"
+            );
+            CALL_RETRIES.add(1, &[KeyValue::new("homeserver.method", method)]);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C> HomeserverConnection for ResilientHomeserverConnection<C>
+where
+    C: HomeserverConnection<Error = anyhow::Error>,
+{
+    type Error = anyhow::Error;
+
+    fn homeserver(&self) -> &str {
+        self.inner.homeserver()
+    }
+
+    fn mxid(&self, localpart: &str) -> String {
+        self.inner.mxid(localpart)
+    }
+
+    fn capabilities(&self) -> HomeserverCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn refresh_capabilities(&self) -> Result<(), Self::Error> {
+        self.call("refresh_capabilities", || self.inner.refresh_capabilities())
+            .await
+    }
+
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        self.call("query_user", || self.inner.query_user(mxid))
+            .await
+    }
+
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        self.call("provision_user", || self.inner.provision_user(request))
+            .await
+    }
+
+    async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error> {
+        self.call("is_localpart_available", || {
+            self.inner.is_localpart_available(localpart)
+        })
+        .await
+    }
+
+    async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.call("create_device", || {
+            self.inner.create_device(mxid, device_id)
+        })
+        .await
+    }
+
+    async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.call("delete_device", || {
+            self.inner.delete_device(mxid, device_id)
+        })
+        .await
+    }
+
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        self.call("update_device_display_name", || {
+            self.inner
+                .update_device_display_name(mxid, device_id, display_name)
+        })
+        .await
+    }
+
+    async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
+        self.call("sync_devices", || {
+            self.inner.sync_devices(mxid, devices.clone())
+        })
+        .await
+    }
+
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        self.call("delete_user", || self.inner.delete_user(mxid, erase))
+            .await
+    }
+
+    async fn reactivate_user(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.call("reactivate_user", || self.inner.reactivate_user(mxid))
+            .await
+    }
+
+    async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        self.call("set_displayname", || {
+            self.inner.set_displayname(mxid, displayname)
+        })
+        .await
+    }
+
+    async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.call("unset_displayname", || self.inner.unset_displayname(mxid))
+            .await
+    }
+
+    async fn set_avatar_url(&self, mxid: &str, avatar_url: &str) -> Result<(), Self::Error> {
+        self.call("set_avatar_url", || {
+            self.inner.set_avatar_url(mxid, avatar_url)
+        })
+        .await
+    }
+
+    async fn unset_avatar_url(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.call("unset_avatar_url", || self.inner.unset_avatar_url(mxid))
+            .await
+    }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, Self::Error> {
+        self.call("upload_media", || {
+            self.inner.upload_media(content_type, filename, data)
+        })
+        .await
+    }
+
+    async fn allow_cross_signing_reset(
+        &self,
+        mxid: &str,
+    ) -> Result<Option<SystemTime>, Self::Error> {
+        self.call("allow_cross_signing_reset", || {
+            self.inner.allow_cross_signing_reset(mxid)
+        })
+        .await
+    }
+
+    async fn send_notice(
+        &self,
+        room_id: &str,
+        txn_id: &str,
+        body: &str,
+    ) -> Result<(), Self::Error> {
+        // The transaction ID makes retrying the notice safe
+        self.call("send_notice", || {
+            self.inner.send_notice(room_id, txn_id, body)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::MockHomeserverConnection;
+
+    fn options() -> ResilienceOptions {
+        ResilienceOptions {
+            timeout: Duration::from_millis(50),
+            max_retries: 2,
+            retry_base_delay: Duration::from_millis(1),
+            max_concurrency: NonZeroUsize::new(1).unwrap(),
+            queue_timeout: Duration::from_millis(50),
+            failure_threshold: 3,
+            reset_after: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_circuit_breaker() {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let conn = ResilientHomeserverConnection::new(
+            MockHomeserverConnection::new("example.com"),
+            options(),
+            &mut rng,
+        );
+
+        // Calls which eventually succeed are retried
+        let attempts = AtomicU32::new(0);
+        let result = conn
+            .call("test", || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    anyhow::bail!("failed");
+                }
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(conn.circuit_breaker().state(), CircuitState::Closed);
+
+        // Slow calls time out
+        let error = conn
+            .call("test", || async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok::<_, anyhow::Error>(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ResilienceError>(),
+            Some(ResilienceError::Timeout(_))
+        ));
+
+        // The failures of both calls open the circuit breaker
+        let attempts = AtomicU32::new(0);
+        let error = conn
+            .call("test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, anyhow::Error>(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ResilienceError>(),
+            Some(ResilienceError::CircuitOpen)
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+        assert_eq!(conn.circuit_breaker().state(), CircuitState::Open);
+
+        // Calls still go through the wrapped connection
+        assert_eq!(conn.mxid("alice"), "@alice:example.com");
+    }
+}
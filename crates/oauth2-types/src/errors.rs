@@ -10,6 +10,7 @@ use std::borrow::Cow;
 
 use serde::{Deserialize, Serialize};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
+use url::Url;
 
 /// A client error returned by an authorization server.
 ///
@@ -23,6 +24,18 @@ pub struct ClientError {
     /// A human-readable description of the error.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_description: Option<Cow<'static, str>>,
+
+    /// A URL of a web page with more information about the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_uri: Option<Url>,
+
+    /// A key identifying the message of this error in the translations of the
+    /// authorization server, so that clients can show their own localized
+    /// message.
+    ///
+    /// This is a non-standard extension.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_i18n_key: Option<Cow<'static, str>>,
 }
 
 impl ClientError {
@@ -32,9 +45,19 @@ impl ClientError {
         Self {
             error,
             error_description: Some(Cow::Borrowed(error_description)),
+            error_uri: None,
+            error_i18n_key: None,
         }
     }
 
+    /// Changes the URI of the web page with more information about this
+    /// `ClientError`.
+    #[must_use]
+    pub fn with_uri(mut self, uri: Url) -> Self {
+        self.error_uri = Some(uri);
+        self
+    }
+
     /// Changes the description of this `ClientError` with the given `String`.
     #[must_use]
     pub fn with_description(mut self, description: String) -> Self {
@@ -265,6 +288,31 @@ pub enum ClientErrorCode {
     /// From [RFC7009](https://www.rfc-editor.org/rfc/rfc7009#section-2.2.1).
     UnsupportedTokenType,
 
+    /// `invalid_authorization_details`
+    ///
+    /// The `authorization_details` parameter is malformed, uses an unknown
+    /// authorization details type, or asks for more than the client is
+    /// allowed to.
+    ///
+    /// From [RFC9396](https://www.rfc-editor.org/rfc/rfc9396#section-5).
+    InvalidAuthorizationDetails,
+
+    /// `invalid_dpop_proof`
+    ///
+    /// The DPoP proof JWT sent in the `DPoP` header is missing, malformed or
+    /// invalid.
+    ///
+    /// From [RFC9449](https://www.rfc-editor.org/rfc/rfc9449#section-5).
+    InvalidDpopProof,
+
+    /// `use_dpop_nonce`
+    ///
+    /// The authorization server requires a nonce in the DPoP proof. The
+    /// nonce to use is given in the `DPoP-Nonce` header of the response.
+    ///
+    /// From [RFC9449](https://www.rfc-editor.org/rfc/rfc9449#section-8).
+    UseDpopNonce,
+
     /// Another error code.
     Unknown(String),
 }
@@ -297,6 +345,11 @@ impl core::fmt::Display for ClientErrorCode {
             ClientErrorCode::SlowDown => f.write_str("slow_down"),
             ClientErrorCode::ExpiredToken => f.write_str("expired_token"),
             ClientErrorCode::UnsupportedTokenType => f.write_str("unsupported_token_type"),
+            ClientErrorCode::InvalidAuthorizationDetails => {
+                f.write_str("invalid_authorization_details")
+            }
+            ClientErrorCode::InvalidDpopProof => f.write_str("invalid_dpop_proof"),
+            ClientErrorCode::UseDpopNonce => f.write_str("use_dpop_nonce"),
             ClientErrorCode::Unknown(value) => f.write_str(value),
         }
     }
@@ -332,6 +385,9 @@ impl core::str::FromStr for ClientErrorCode {
             "slow_down" => Ok(ClientErrorCode::SlowDown),
             "expired_token" => Ok(ClientErrorCode::ExpiredToken),
             "unsupported_token_type" => Ok(ClientErrorCode::UnsupportedTokenType),
+            "invalid_authorization_details" => Ok(ClientErrorCode::InvalidAuthorizationDetails),
+            "invalid_dpop_proof" => Ok(ClientErrorCode::InvalidDpopProof),
+            "use_dpop_nonce" => Ok(ClientErrorCode::UseDpopNonce),
             _ => Ok(ClientErrorCode::Unknown(s.to_owned())),
         }
     }
@@ -423,9 +479,71 @@ impl ClientErrorCode {
             ClientErrorCode::UnsupportedTokenType => {
                 "The authorization server does not support the revocation of the presented token type."
             },
+            ClientErrorCode::InvalidAuthorizationDetails => {
+                "The authorization details are invalid, or exceed what the client is allowed to request"
+            }
+            ClientErrorCode::InvalidDpopProof => "The DPoP proof is missing or invalid",
+            ClientErrorCode::UseDpopNonce => {
+                "The authorization server requires a nonce in the DPoP proof"
+            }
             ClientErrorCode::Unknown(_) => "",
         }
     }
+
+    /// Get the URL of the documentation page of this `ClientErrorCode`, under
+    /// the given base URL.
+    ///
+    /// The base URL should end with a `/`, as the error code is joined to it.
+    /// Returns `None` for [`ClientErrorCode::Unknown`], which are not
+    /// documented.
+    #[must_use]
+    pub fn uri(&self, base: &Url) -> Option<Url> {
+        if matches!(self, ClientErrorCode::Unknown(_)) {
+            return None;
+        }
+
+        base.join(&self.to_string()).ok()
+    }
+
+    /// Get the HTTP status code to use when returning this `ClientErrorCode`
+    /// in a JSON response, like from the token endpoint.
+    ///
+    /// Errors which are meant to be returned in a redirection to the client,
+    /// like [`ClientErrorCode::AccessDenied`], map to `400 Bad Request`.
+    #[must_use]
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            ClientErrorCode::InvalidClient => http::StatusCode::UNAUTHORIZED,
+            ClientErrorCode::ServerError => http::StatusCode::INTERNAL_SERVER_ERROR,
+            ClientErrorCode::TemporarilyUnavailable => http::StatusCode::SERVICE_UNAVAILABLE,
+            ClientErrorCode::InvalidRequest
+            | ClientErrorCode::InvalidGrant
+            | ClientErrorCode::UnauthorizedClient
+            | ClientErrorCode::UnsupportedGrantType
+            | ClientErrorCode::AccessDenied
+            | ClientErrorCode::UnsupportedResponseType
+            | ClientErrorCode::InvalidScope
+            | ClientErrorCode::InteractionRequired
+            | ClientErrorCode::LoginRequired
+            | ClientErrorCode::AccountSelectionRequired
+            | ClientErrorCode::ConsentRequired
+            | ClientErrorCode::InvalidRequestUri
+            | ClientErrorCode::InvalidRequestObject
+            | ClientErrorCode::RequestNotSupported
+            | ClientErrorCode::RequestUriNotSupported
+            | ClientErrorCode::RegistrationNotSupported
+            | ClientErrorCode::InvalidRedirectUri
+            | ClientErrorCode::InvalidClientMetadata
+            | ClientErrorCode::AuthorizationPending
+            | ClientErrorCode::SlowDown
+            | ClientErrorCode::ExpiredToken
+            | ClientErrorCode::UnsupportedTokenType
+            | ClientErrorCode::InvalidAuthorizationDetails
+            | ClientErrorCode::InvalidDpopProof
+            | ClientErrorCode::UseDpopNonce
+            | ClientErrorCode::Unknown(_) => http::StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -433,6 +551,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn serialize_client_error_code() {
         assert_eq!(
             serde_json::to_string(&ClientErrorCode::InvalidRequest).unwrap(),
@@ -518,6 +637,30 @@ mod tests {
             serde_json::to_string(&ClientErrorCode::InvalidClientMetadata).unwrap(),
             "\"invalid_client_metadata\""
         );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::AuthorizationPending).unwrap(),
+            "\"authorization_pending\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::SlowDown).unwrap(),
+            "\"slow_down\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::ExpiredToken).unwrap(),
+            "\"expired_token\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::InvalidAuthorizationDetails).unwrap(),
+            "\"invalid_authorization_details\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::InvalidDpopProof).unwrap(),
+            "\"invalid_dpop_proof\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::UseDpopNonce).unwrap(),
+            "\"use_dpop_nonce\""
+        );
 
         assert_eq!(
             serde_json::to_string(&ClientErrorCode::Unknown("unknown_error_code".to_owned()))
@@ -527,6 +670,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn deserialize_client_error_code() {
         assert_eq!(
             serde_json::from_str::<ClientErrorCode>("\"invalid_request\"").unwrap(),
@@ -612,10 +756,67 @@ mod tests {
             serde_json::from_str::<ClientErrorCode>("\"invalid_client_metadata\"").unwrap(),
             ClientErrorCode::InvalidClientMetadata
         );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"authorization_pending\"").unwrap(),
+            ClientErrorCode::AuthorizationPending
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"slow_down\"").unwrap(),
+            ClientErrorCode::SlowDown
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"expired_token\"").unwrap(),
+            ClientErrorCode::ExpiredToken
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"invalid_authorization_details\"").unwrap(),
+            ClientErrorCode::InvalidAuthorizationDetails
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"invalid_dpop_proof\"").unwrap(),
+            ClientErrorCode::InvalidDpopProof
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"use_dpop_nonce\"").unwrap(),
+            ClientErrorCode::UseDpopNonce
+        );
 
         assert_eq!(
             serde_json::from_str::<ClientErrorCode>("\"unknown_error_code\"").unwrap(),
             ClientErrorCode::Unknown("unknown_error_code".to_owned())
         );
     }
+
+    #[test]
+    fn client_error_code_status() {
+        assert_eq!(
+            ClientErrorCode::InvalidGrant.status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            ClientErrorCode::InvalidClient.status_code(),
+            http::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            ClientErrorCode::TemporarilyUnavailable.status_code(),
+            http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            ClientErrorCode::UseDpopNonce.status_code(),
+            http::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn client_error_code_uri() {
+        let base = Url::parse("https://example.com/errors/").unwrap();
+        assert_eq!(
+            ClientErrorCode::InvalidGrant.uri(&base).unwrap().as_str(),
+            "https://example.com/errors/invalid_grant"
+        );
+        assert_eq!(
+            ClientErrorCode::Unknown("custom_error".to_owned()).uri(&base),
+            None
+        );
+    }
 }
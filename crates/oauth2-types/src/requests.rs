@@ -786,12 +786,38 @@ pub struct IntrospectionResponse {
 
     /// String identifier for the token.
     pub jti: Option<String>,
+
+    /// Whether the session of the token can be refreshed, i.e. whether it has
+    /// a usable refresh token.
+    ///
+    /// This is a non-standard extension.
+    pub offline_access: Option<bool>,
+
+    /// ID of the session the token belongs to.
+    ///
+    /// This is a non-standard extension, only returned for tokens giving
+    /// access to the Matrix client-server API.
+    pub session_id: Option<String>,
+
+    /// Matrix device ID of the session the token belongs to, if it has one.
+    ///
+    /// This is a non-standard extension, only returned for tokens giving
+    /// access to the Matrix client-server API.
+    pub device_id: Option<String>,
+
+    /// Human-readable name of the client the token was issued to, if it has
+    /// one.
+    ///
+    /// This is a non-standard extension, only returned for tokens giving
+    /// access to the Matrix client-server API.
+    pub client_name: Option<String>,
 }
 
 /// A request to the [Revocation Endpoint].
 ///
 /// [Revocation Endpoint]: https://www.rfc-editor.org/rfc/rfc7009#section-2
 #[skip_serializing_none]
+#[serde_as]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct RevocationRequest {
     /// The value of the token.
@@ -799,12 +825,56 @@ pub struct RevocationRequest {
 
     /// A hint about the type of the token submitted for introspection.
     pub token_type_hint: Option<OAuthTokenTypeHint>,
+
+    /// Whether the other access and refresh tokens of the same session should
+    /// be revoked along with this token.
+    ///
+    /// This is an extension to [RFC 7009].
+    ///
+    /// [RFC 7009]: https://www.rfc-editor.org/rfc/rfc7009
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub revoke_related: Option<bool>,
+}
+
+impl RevocationRequest {
+    /// Creates a new `RevocationRequest` for the given token.
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            token_type_hint: None,
+            revoke_related: None,
+        }
+    }
+
+    /// Adds a token type hint to a `RevocationRequest`.
+    #[must_use]
+    pub fn with_token_type_hint(mut self, token_type_hint: OAuthTokenTypeHint) -> Self {
+        self.token_type_hint = Some(token_type_hint);
+        self
+    }
+
+    /// Sets whether the related tokens should be revoked along with the token
+    /// of a `RevocationRequest`.
+    #[must_use]
+    pub fn with_revoke_related(mut self, revoke_related: bool) -> Self {
+        self.revoke_related = Some(revoke_related);
+        self
+    }
+
+    /// Whether the related tokens should be revoked along with the token.
+    #[must_use]
+    pub fn revoke_related(&self) -> bool {
+        self.revoke_related.unwrap_or(false)
+    }
 }
 
 impl fmt::Debug for RevocationRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RevocationRequest")
             .field("token_type_hint", &self.token_type_hint)
+            .field("revoke_related", &self.revoke_related)
             .finish_non_exhaustive()
     }
 }
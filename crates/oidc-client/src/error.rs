@@ -6,7 +6,6 @@
 
 //! The error types used in this crate.
 
-use async_trait::async_trait;
 use mas_jose::{
     claims::ClaimError,
     jwa::InvalidAlgorithm,
@@ -16,6 +15,8 @@ use oauth2_types::{oidc::ProviderMetadataVerificationError, pkce::CodeChallengeE
 use serde::Deserialize;
 use thiserror::Error;
 
+use crate::http_client::{HttpError, HttpResponse};
+
 /// All possible errors when using this crate.
 #[derive(Debug, Error)]
 #[error(transparent)]
@@ -38,8 +39,14 @@ pub enum Error {
     /// An error occurred refreshing an access token.
     TokenRefresh(#[from] TokenRefreshError),
 
+    /// An error occurred revoking a token.
+    TokenRevoke(#[from] TokenRevokeError),
+
     /// An error occurred requesting user info.
     UserInfo(#[from] UserInfoError),
+
+    /// An error occurred getting the tokens of a session.
+    Session(#[from] SessionError),
 }
 
 /// All possible errors when fetching provider metadata.
@@ -49,8 +56,8 @@ pub enum DiscoveryError {
     /// An error occurred building the request's URL.
     IntoUrl(#[from] url::ParseError),
 
-    /// The server returned an HTTP error status code.
-    Http(#[from] reqwest::Error),
+    /// An error occurred sending the request.
+    Http(#[from] HttpError),
 
     /// An error occurred validating the metadata.
     Validation(#[from] ProviderMetadataVerificationError),
@@ -76,7 +83,7 @@ pub enum AuthorizationError {
 #[error("Request to the token endpoint failed")]
 pub enum TokenRequestError {
     /// The HTTP client returned an error.
-    Http(#[from] reqwest::Error),
+    Http(#[from] HttpError),
 
     /// The server returned an error
     OAuth2(#[from] OAuth2Error),
@@ -109,9 +116,27 @@ pub enum TokenRefreshError {
     IdToken(#[from] IdTokenError),
 }
 
+/// All possible errors when revoking a token.
+#[derive(Debug, Error)]
+#[error("Request to the revocation endpoint failed")]
+pub enum TokenRevokeError {
+    /// The HTTP client returned an error.
+    Http(#[from] HttpError),
+
+    /// The server returned an error
+    OAuth2(#[from] OAuth2Error),
+
+    /// Error while injecting the client credentials into the request.
+    Credentials(#[from] CredentialsError),
+}
+
 /// All possible errors when requesting user info.
 #[derive(Debug, Error)]
 pub enum UserInfoError {
+    /// The access token can't be used in an `Authorization` header.
+    #[error("invalid access token")]
+    InvalidAccessToken,
+
     /// The content-type header is missing from the response.
     #[error("missing response content-type")]
     MissingResponseContentType,
@@ -135,11 +160,80 @@ pub enum UserInfoError {
 
     /// An error occurred sending the request.
     #[error(transparent)]
-    Http(#[from] reqwest::Error),
+    Http(#[from] HttpError),
 
     /// The server returned an error
     #[error(transparent)]
     OAuth2(#[from] OAuth2Error),
+
+    /// An error occurred resolving the aggregated or distributed claims.
+    #[error(transparent)]
+    ClaimSource(#[from] ClaimSourceError),
+}
+
+/// All possible errors when resolving aggregated and distributed claims.
+#[derive(Debug, Error)]
+pub enum ClaimSourceError {
+    /// The `_claim_names` or `_claim_sources` members are invalid.
+    #[error("invalid claim sources")]
+    Invalid(#[source] serde_json::Error),
+
+    /// A claim references a source that doesn't exist.
+    #[error("claim {claim:?} references unknown source {name:?}")]
+    UnknownSource {
+        /// The name of the claim.
+        claim: String,
+        /// The name of the source.
+        name: String,
+    },
+
+    /// The JWT of a source couldn't be decoded.
+    #[error("invalid JWT in claim source {name:?}")]
+    Jwt {
+        /// The name of the source.
+        name: String,
+        /// The decoding error.
+        #[source]
+        error: JwtDecodeError,
+    },
+
+    /// The request to the endpoint of a distributed source failed.
+    #[error("failed to fetch claim source {name:?}")]
+    Fetch {
+        /// The name of the source.
+        name: String,
+        /// The HTTP error.
+        #[source]
+        error: HttpError,
+    },
+}
+
+/// All possible errors when getting the tokens of an
+/// [`OidcSession`](crate::session::OidcSession).
+#[derive(Debug, Error)]
+pub enum SessionError {
+    /// The session doesn't have any tokens yet.
+    #[error("the session has no tokens")]
+    NoTokens,
+
+    /// The access token needs to be refreshed, but the session doesn't have a
+    /// refresh token.
+    #[error("the session has no refresh token")]
+    NoRefreshToken,
+
+    /// An error occurred refreshing the access token.
+    #[error(transparent)]
+    Refresh(#[from] TokenRefreshError),
+
+    /// An error occurred loading or saving the tokens.
+    #[error("failed to access the token store")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl SessionError {
+    pub(crate) fn store(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self::Store(Box::new(error))
+    }
 }
 
 /// All possible errors when requesting a JWKS.
@@ -147,7 +241,7 @@ pub enum UserInfoError {
 #[error("Failed to fetch JWKS")]
 pub enum JwksError {
     /// An error occurred sending the request.
-    Http(#[from] reqwest::Error),
+    Http(#[from] HttpError),
 }
 
 /// All possible errors when verifying a JWT.
@@ -200,6 +294,48 @@ pub enum IdTokenError {
     /// one we got before.
     #[error("wrong authentication time")]
     WrongAuthTime,
+
+    /// The ID Token has expired, even with the allowed clock skew.
+    #[error("ID token has expired")]
+    Expired,
+
+    /// The ID Token was issued in the future, even with the allowed clock
+    /// skew.
+    #[error("ID token was issued in the future")]
+    IssuedInTheFuture,
+
+    /// The `nonce` claim is missing although it is required.
+    #[error("missing nonce")]
+    MissingNonce,
+
+    /// The `nonce` claim doesn't match the one sent in the authorization
+    /// request.
+    #[error("wrong nonce")]
+    WrongNonce,
+
+    /// The `acr` claim is missing or is not one of the allowed values.
+    #[error("authentication context class reference {got:?} is not allowed")]
+    AcrNotAllowed {
+        /// The `acr` claim of the ID Token, if any.
+        got: Option<String>,
+    },
+
+    /// The `auth_time` claim is missing although a maximum authentication age
+    /// is set.
+    #[error("missing authentication time")]
+    MissingAuthTime,
+
+    /// The end-user authenticated longer ago than the maximum authentication
+    /// age.
+    #[error("authentication is too old")]
+    AuthTimeTooOld,
+
+    /// The `azp` claim is missing or is not the expected authorized party.
+    #[error("wrong authorized party {got:?}")]
+    WrongAuthorizedParty {
+        /// The `azp` claim of the ID Token, if any.
+        got: Option<String>,
+    },
 }
 
 /// All errors that can occur when adding client credentials to the request.
@@ -229,6 +365,10 @@ pub enum CredentialsError {
     /// An error occurred when signing the JWT.
     #[error(transparent)]
     JwtSignature(#[from] JwtSignatureError),
+
+    /// An error occurred when building the request.
+    #[error(transparent)]
+    Request(#[from] HttpError),
 }
 
 #[derive(Debug, Deserialize)]
@@ -260,7 +400,7 @@ pub struct OAuth2Error {
     error: Option<OAuth2ErrorResponse>,
 
     #[source]
-    inner: reqwest::Error,
+    inner: HttpError,
 }
 
 impl std::fmt::Display for OAuth2Error {
@@ -276,32 +416,24 @@ impl std::fmt::Display for OAuth2Error {
     }
 }
 
-impl From<reqwest::Error> for OAuth2Error {
-    fn from(inner: reqwest::Error) -> Self {
+impl From<HttpError> for OAuth2Error {
+    fn from(inner: HttpError) -> Self {
         Self { error: None, inner }
     }
 }
 
-/// An extension trait to deal with error responses from the OAuth 2.0 provider
-#[async_trait]
-pub(crate) trait ResponseExt {
-    async fn error_from_oauth2_error_response(self) -> Result<Self, OAuth2Error>
-    where
-        Self: Sized;
-}
-
-#[async_trait]
-impl ResponseExt for reqwest::Response {
-    async fn error_from_oauth2_error_response(self) -> Result<Self, OAuth2Error> {
-        let Err(inner) = self.error_for_status_ref() else {
-            return Ok(self);
-        };
-
-        let error: OAuth2ErrorResponse = self.json().await?;
+impl OAuth2Error {
+    /// Return an error if the response has an HTTP error status code, with
+    /// the OAuth 2.0 error from the body if there is one.
+    pub(crate) fn from_response(response: HttpResponse) -> Result<HttpResponse, Self> {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(response);
+        }
 
-        Err(OAuth2Error {
-            error: Some(error),
-            inner,
+        Err(Self {
+            error: serde_json::from_slice(response.body()).ok(),
+            inner: HttpError::Status(status),
         })
     }
 }
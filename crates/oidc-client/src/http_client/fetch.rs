@@ -0,0 +1,103 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! An [`HttpClient`] using the `fetch` API of the browser.
+
+use async_trait::async_trait;
+use http::Response;
+use js_sys::{Array, Uint8Array};
+use thiserror::Error;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, Request, RequestInit, Window, WorkerGlobalScope};
+
+use super::{HttpClient, HttpRequest, HttpResponse};
+
+/// An [`HttpClient`] sending requests with the `fetch` API of the browser.
+///
+/// It works both in windows and in web workers. Redirects are followed by the
+/// browser.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FetchHttpClient;
+
+/// An error returned by the `fetch` API.
+#[derive(Debug, Error)]
+#[error("fetch request failed: {0}")]
+pub struct FetchError(String);
+
+impl From<JsValue> for FetchError {
+    fn from(value: JsValue) -> Self {
+        let message = value
+            .dyn_ref::<js_sys::Error>()
+            .map(|error| String::from(error.message()))
+            .or_else(|| value.as_string())
+            .unwrap_or_else(|| format!("{value:?}"));
+        Self(message)
+    }
+}
+
+impl From<http::Error> for FetchError {
+    fn from(error: http::Error) -> Self {
+        Self(error.to_string())
+    }
+}
+
+impl From<http::header::ToStrError> for FetchError {
+    fn from(error: http::header::ToStrError) -> Self {
+        Self(error.to_string())
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpClient for FetchHttpClient {
+    type Error = FetchError;
+
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
+        let (parts, body) = request.into_parts();
+
+        let headers = Headers::new()?;
+        for (name, value) in &parts.headers {
+            headers.append(name.as_str(), value.to_str()?)?;
+        }
+
+        let init = RequestInit::new();
+        init.set_method(parts.method.as_str());
+        init.set_headers(&headers);
+        if !body.is_empty() {
+            init.set_body(&Uint8Array::from(body.as_slice()));
+        }
+
+        let request = Request::new_with_str_and_init(&parts.uri.to_string(), &init)?;
+
+        // `fetch` is available on the global scope of both windows and workers
+        let global = js_sys::global();
+        let promise = if let Some(window) = global.dyn_ref::<Window>() {
+            window.fetch_with_request(&request)
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            worker.fetch_with_request(&request)
+        } else {
+            return Err(FetchError("no fetch API in this environment".to_owned()));
+        };
+
+        let response: web_sys::Response = JsFuture::from(promise).await?.dyn_into()?;
+
+        let mut builder = Response::builder().status(response.status());
+        if let Some(entries) = js_sys::try_iter(&response.headers())? {
+            for entry in entries {
+                let entry: Array = entry?.unchecked_into();
+                if let (Some(name), Some(value)) =
+                    (entry.get(0).as_string(), entry.get(1).as_string())
+                {
+                    builder = builder.header(name, value);
+                }
+            }
+        }
+
+        let body = JsFuture::from(response.array_buffer()?).await?;
+        let body = Uint8Array::new(&body).to_vec();
+
+        Ok(builder.body(body)?)
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! The HTTP backend used to send requests.
+//!
+//! All the requests of this crate go through the [`HttpClient`] trait, so that
+//! consumers can plug the HTTP stack of their choice, or a test double.
+//!
+//! With the `reqwest` feature, which is enabled by default, it is implemented
+//! for [`reqwest::Client`]. With the `fetch` feature, on the
+//! `wasm32-unknown-unknown` target, the `FetchHttpClient` uses the `fetch`
+//! API of the browser.
+//!
+//! On WASM targets, the futures of the backends don't need to be [`Send`].
+
+use async_trait::async_trait;
+use headers::{ContentType, HeaderMapExt};
+use http::{request::Builder, Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+use url::Url;
+
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))]
+mod fetch;
+#[cfg(feature = "reqwest")]
+mod reqwest_client;
+
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))]
+pub use self::fetch::{FetchError, FetchHttpClient};
+
+/// An HTTP request sent by this crate.
+pub type HttpRequest = Request<Vec<u8>>;
+
+/// An HTTP response received by this crate.
+pub type HttpResponse = Response<Vec<u8>>;
+
+/// A backend sending HTTP requests.
+///
+/// Implementations must not error on HTTP error status codes: the response is
+/// returned as is.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait HttpClient: Send + Sync {
+    /// The error type of the backend.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Send the request, and read the full response.
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error>;
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T: HttpClient + ?Sized> HttpClient for &T {
+    type Error = T::Error;
+
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
+        (**self).send(request).await
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T: HttpClient + ?Sized> HttpClient for std::sync::Arc<T> {
+    type Error = T::Error;
+
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
+        (**self).send(request).await
+    }
+}
+
+/// All possible errors when sending a request with an [`HttpClient`].
+#[derive(Debug, Error)]
+pub enum HttpError {
+    /// The request could not be built.
+    #[error("failed to build the request")]
+    Request(#[from] http::Error),
+
+    /// The body of the request could not be serialized.
+    #[error("failed to serialize the request")]
+    UrlEncoded(#[from] serde_urlencoded::ser::Error),
+
+    /// The backend failed to send the request or read the response.
+    #[error("failed to send the request")]
+    Backend(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// The server returned an HTTP error status code.
+    #[error("the server returned an error status code: {0}")]
+    Status(StatusCode),
+
+    /// The body of the response could not be deserialized.
+    #[error("failed to deserialize the response")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Start building a `GET` request to the given URL.
+pub(crate) fn get(url: &Url) -> Builder {
+    Request::get(url.as_str())
+}
+
+/// Start building a `POST` request to the given URL.
+pub(crate) fn post(url: &Url) -> Builder {
+    Request::post(url.as_str())
+}
+
+/// Finish building a request with a form body.
+pub(crate) fn with_form<T: Serialize>(
+    mut request: Builder,
+    form: &T,
+) -> Result<HttpRequest, HttpError> {
+    let body = serde_urlencoded::to_string(form)?;
+    if let Some(headers) = request.headers_mut() {
+        headers.typed_insert(ContentType::form_url_encoded());
+    }
+    Ok(request.body(body.into_bytes())?)
+}
+
+/// Send a request with the given client, mapping the error of the backend.
+pub(crate) async fn send(
+    http_client: &impl HttpClient,
+    request: HttpRequest,
+) -> Result<HttpResponse, HttpError> {
+    http_client
+        .send(request)
+        .await
+        .map_err(|e| HttpError::Backend(Box::new(e)))
+}
+
+/// Return an error if the response has an HTTP error status code.
+pub(crate) fn error_for_status(response: HttpResponse) -> Result<HttpResponse, HttpError> {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(HttpError::Status(status));
+    }
+
+    Ok(response)
+}
+
+/// Deserialize the JSON body of a response.
+pub(crate) fn json<T: DeserializeOwned>(response: &HttpResponse) -> Result<T, HttpError> {
+    Ok(serde_json::from_slice(response.body())?)
+}
@@ -0,0 +1,42 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! An [`HttpClient`] using `reqwest`.
+
+use async_trait::async_trait;
+use http::Response;
+use mas_http::RequestBuilderExt;
+
+use super::{HttpClient, HttpRequest, HttpResponse};
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl HttpClient for reqwest::Client {
+    type Error = reqwest::Error;
+
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
+        let request = reqwest::Request::try_from(request)?;
+        let response = reqwest::RequestBuilder::from_parts(self.clone(), request)
+            .send_traced()
+            .await?;
+
+        let mut builder = Response::builder()
+            .status(response.status())
+            .version(response.version());
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(
+                response
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+        }
+
+        let body = response.bytes().await?;
+        Ok(builder
+            .body(body.to_vec())
+            .expect("the response parts are valid"))
+    }
+}
@@ -24,8 +24,13 @@
 //!   - [Client Credentials](https://www.rfc-editor.org/rfc/rfc6749#section-4.4)
 //!   - [Device Code](https://www.rfc-editor.org/rfc/rfc8628) (TBD)
 //!   - [Refresh Token](https://openid.net/specs/openid-connect-core-1_0.html#RefreshTokens)
+//! - [Token Revocation](https://www.rfc-editor.org/rfc/rfc7009)
 //! - [User Info](https://openid.net/specs/openid-connect-core-1_0.html#UserInfo)
+//!   - [Aggregated and Distributed Claims](https://openid.net/specs/openid-connect-core-1_0.html#AggregatedDistributedClaims)
 //! - [PKCE](https://www.rfc-editor.org/rfc/rfc7636)
+//! - Automatic refresh and persistence of the tokens of a session
+//! - Pluggable HTTP backend, with implementations for `reqwest` and the
+//!   browser's `fetch` API
 //!
 //! # Matrix features
 //!
@@ -33,18 +38,32 @@
 //! - Login
 //! - Matrix API Scopes
 //! - Logout
+//! - Account management deep links ([MSC4191])
+//!
+//! # WASM support
+//!
+//! This crate can be built for the `wasm32-unknown-unknown` target, to be used
+//! in a browser or a web worker. Disable the default features, enable the
+//! `fetch` feature and use the `FetchHttpClient` as the HTTP backend.
+//!
+//! The randomness used for PKCE, nonces and states is then provided by the
+//! browser, so [`rand::thread_rng`] can be passed as the random number
+//! generator.
 //!
 //! [OpenID Connect]: https://openid.net/connect/
 //! [Matrix]: https://matrix.org/
 //! [Matrix Authentication Service]: https://github.com/element-hq/matrix-authentication-service
 //! [MSC3861]: https://github.com/matrix-org/matrix-spec-proposals/pull/3861
+//! [MSC4191]: https://github.com/matrix-org/matrix-spec-proposals/pull/4191
 //! [OAuth 2.0]: https://oauth.net/2/
 
 #![deny(missing_docs)]
 #![allow(clippy::module_name_repetitions, clippy::implicit_hasher)]
 
 pub mod error;
+pub mod http_client;
 pub mod requests;
+pub mod session;
 pub mod types;
 
 use std::fmt;
@@ -0,0 +1,116 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Methods to link to the account management page of the issuer.
+//!
+//! The URL of this page and the actions it supports are advertised in the
+//! provider metadata, as defined in [MSC4191].
+//!
+//! [MSC4191]: https://github.com/matrix-org/matrix-spec-proposals/pull/4191
+
+use oauth2_types::oidc::AccountManagementAction;
+use serde::Serialize;
+use url::Url;
+
+/// An action that the user wishes to take on the account management page, with
+/// its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountManagementActionFull {
+    /// The user wishes to view their profile (name, avatar, contact details).
+    Profile,
+
+    /// The user wishes to view a list of their sessions.
+    SessionsList,
+
+    /// The user wishes to view the details of a specific session.
+    SessionView {
+        /// The ID of the device of the session.
+        device_id: String,
+    },
+
+    /// The user wishes to end/log out of a specific session.
+    SessionEnd {
+        /// The ID of the device of the session.
+        device_id: String,
+    },
+
+    /// The user wishes to deactivate their account.
+    AccountDeactivate,
+
+    /// The user wishes to reset their cross-signing keys.
+    CrossSigningReset,
+}
+
+impl AccountManagementActionFull {
+    /// The action, without its parameters.
+    ///
+    /// It can be checked against the `account_management_actions_supported`
+    /// field of the provider metadata.
+    #[must_use]
+    pub fn action(&self) -> AccountManagementAction {
+        match self {
+            Self::Profile => AccountManagementAction::Profile,
+            Self::SessionsList => AccountManagementAction::SessionsList,
+            Self::SessionView { .. } => AccountManagementAction::SessionView,
+            Self::SessionEnd { .. } => AccountManagementAction::SessionEnd,
+            Self::AccountDeactivate => AccountManagementAction::AccountDeactivate,
+            Self::CrossSigningReset => AccountManagementAction::CrossSigningReset,
+        }
+    }
+
+    fn device_id(&self) -> Option<&str> {
+        match self {
+            Self::SessionView { device_id } | Self::SessionEnd { device_id } => Some(device_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AccountManagementQuery<'a> {
+    action: AccountManagementAction,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<&'a str>,
+}
+
+/// Build the URL to open the account management page with the given action.
+///
+/// # Arguments
+///
+/// * `account_management_uri` - The URL of the account management page, as
+///   found in the `account_management_uri` field of the provider metadata.
+///
+/// * `action` - The action that the user wishes to take, if any.
+///
+/// # Errors
+///
+/// Returns an error if serializing the query fails.
+pub fn build_account_management_url(
+    account_management_uri: Url,
+    action: Option<&AccountManagementActionFull>,
+) -> Result<Url, serde_urlencoded::ser::Error> {
+    let Some(action) = action else {
+        return Ok(account_management_uri);
+    };
+
+    let query = serde_urlencoded::to_string(AccountManagementQuery {
+        action: action.action(),
+        device_id: action.device_id(),
+    })?;
+
+    let mut url = account_management_uri;
+
+    // Add our parameters to the query, because the URL might already have one.
+    let mut full_query = url.query().map(ToOwned::to_owned).unwrap_or_default();
+    if !full_query.is_empty() {
+        full_query.push('&');
+    }
+    full_query.push_str(&query);
+
+    url.set_query(Some(&full_query));
+
+    Ok(url)
+}
@@ -31,10 +31,11 @@ use rand::{
 use serde::Serialize;
 use url::Url;
 
-use super::jose::JwtVerificationData;
+use super::jose::{IdTokenValidationOptions, JwtVerificationData};
 use crate::{
     error::{AuthorizationError, IdTokenError, TokenAuthorizationCodeError},
-    requests::{jose::verify_id_token, token::request_access_token},
+    http_client::HttpClient,
+    requests::{jose::verify_id_token_with_options, token::request_access_token},
     types::{client_credentials::ClientCredentials, IdToken},
 };
 
@@ -359,7 +360,7 @@ pub fn build_authorization_url(
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `client_credentials` - The credentials obtained when registering the
 ///   client.
@@ -381,6 +382,8 @@ pub fn build_authorization_url(
 ///   If it is not provided, the ID Token won't be verified. Note that in the
 ///   OpenID Connect specification, this verification is required.
 ///
+/// * `id_token_options` - The options of the checks performed on the ID Token.
+///
 /// * `now` - The current time.
 ///
 /// * `rng` - A random number generator.
@@ -392,12 +395,13 @@ pub fn build_authorization_url(
 #[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip_all, fields(token_endpoint))]
 pub async fn access_token_with_authorization_code(
-    http_client: &reqwest::Client,
+    http_client: &impl HttpClient,
     client_credentials: ClientCredentials,
     token_endpoint: &Url,
     code: String,
     validation_data: AuthorizationValidationData,
     id_token_verification_data: Option<JwtVerificationData<'_>>,
+    id_token_options: &IdTokenValidationOptions,
     now: DateTime<Utc>,
     rng: &mut impl Rng,
 ) -> Result<(AccessTokenResponse, Option<IdToken<'static>>), TokenAuthorizationCodeError> {
@@ -425,7 +429,14 @@ pub async fn access_token_with_authorization_code(
             .as_deref()
             .ok_or(IdTokenError::MissingIdToken)?;
 
-        let id_token = verify_id_token(id_token, verification_data, None, now)?;
+        let id_token = verify_id_token_with_options(
+            id_token,
+            verification_data,
+            None,
+            Some(&validation_data.nonce),
+            id_token_options,
+            now,
+        )?;
 
         let mut claims = id_token.payload().clone();
 
@@ -442,11 +453,6 @@ pub async fn access_token_with_authorization_code(
             .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, &code))
             .map_err(IdTokenError::from)?;
 
-        // Nonce must match.
-        claims::NONCE
-            .extract_required_with_options(&mut claims, validation_data.nonce.as_str())
-            .map_err(IdTokenError::from)?;
-
         Some(id_token.into_owned())
     } else {
         None
@@ -8,15 +8,17 @@
 //!
 //! [Discovery]: https://openid.net/specs/openid-connect-discovery-1_0.html
 
-use mas_http::RequestBuilderExt;
 use oauth2_types::oidc::{ProviderMetadata, VerifiedProviderMetadata};
 use url::Url;
 
-use crate::error::DiscoveryError;
+use crate::{
+    error::DiscoveryError,
+    http_client::{self, HttpClient},
+};
 
 /// Fetch the provider metadata.
 async fn discover_inner(
-    client: &reqwest::Client,
+    client: &impl HttpClient,
     issuer: Url,
 ) -> Result<ProviderMetadata, DiscoveryError> {
     tracing::debug!("Fetching provider metadata...");
@@ -33,13 +35,12 @@ async fn discover_inner(
 
     let config_url = config_url.join(".well-known/openid-configuration")?;
 
-    let response = client
-        .get(config_url.as_str())
-        .send_traced()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let request = http_client::get(&config_url)
+        .body(Vec::new())
+        .map_err(http_client::HttpError::from)?;
+    let response = http_client::send(client, request).await?;
+    let response = http_client::error_for_status(response)?;
+    let response = http_client::json(&response)?;
 
     tracing::debug!(?response);
 
@@ -53,7 +54,7 @@ async fn discover_inner(
 /// Returns an error if the request fails or if the data is invalid.
 #[tracing::instrument(skip_all, fields(issuer))]
 pub async fn discover(
-    client: &reqwest::Client,
+    client: &impl HttpClient,
     issuer: &str,
 ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
     let provider_metadata = discover_inner(client, issuer.parse()?).await?;
@@ -69,7 +70,7 @@ pub async fn discover(
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `issuer` - The URL of the OpenID Connect Provider to fetch metadata for.
 ///
@@ -85,7 +86,7 @@ pub async fn discover(
 /// [provider metadata]: https://openid.net/specs/openid-connect-discovery-1_0.html
 #[tracing::instrument(skip_all, fields(issuer))]
 pub async fn insecure_discover(
-    client: &reqwest::Client,
+    client: &impl HttpClient,
     issuer: &str,
 ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
     let provider_metadata = discover_inner(client, issuer.parse()?).await?;
@@ -8,11 +8,10 @@
 
 use std::collections::HashMap;
 
-use chrono::{DateTime, Utc};
-use mas_http::RequestBuilderExt;
+use chrono::{DateTime, Duration, Utc};
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
-    claims::{self, TimeOptions},
+    claims::{self, ClaimError, TimeOptions},
     jwk::PublicJsonWebKeySet,
     jwt::Jwt,
 };
@@ -21,6 +20,7 @@ use url::Url;
 
 use crate::{
     error::{IdTokenError, JwksError, JwtVerificationError},
+    http_client::{self, HttpClient},
     types::IdToken,
 };
 
@@ -28,7 +28,7 @@ use crate::{
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `jwks_uri` - The URL where the JWKS can be retrieved.
 ///
@@ -37,18 +37,17 @@ use crate::{
 /// Returns an error if the request fails or if the data is invalid.
 #[tracing::instrument(skip_all, fields(jwks_uri))]
 pub async fn fetch_jwks(
-    client: &reqwest::Client,
+    client: &impl HttpClient,
     jwks_uri: &Url,
 ) -> Result<PublicJsonWebKeySet, JwksError> {
     tracing::debug!("Fetching JWKS...");
 
-    let response: PublicJsonWebKeySet = client
-        .get(jwks_uri.as_str())
-        .send_traced()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let request = http_client::get(jwks_uri)
+        .body(Vec::new())
+        .map_err(http_client::HttpError::from)?;
+    let response = http_client::send(client, request).await?;
+    let response = http_client::error_for_status(response)?;
+    let response: PublicJsonWebKeySet = http_client::json(&response)?;
 
     Ok(response)
 }
@@ -131,6 +130,137 @@ pub fn verify_signed_jwt<'a>(
     Ok(jwt)
 }
 
+/// How the `nonce` claim of an ID Token is checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoncePolicy {
+    /// The claim must be present and match the nonce sent in the authorization
+    /// request.
+    #[default]
+    Required,
+
+    /// If the claim is present, it must match the nonce sent in the
+    /// authorization request.
+    IfPresent,
+
+    /// The claim is not checked.
+    Ignore,
+}
+
+/// The options of the checks performed on an ID Token.
+///
+/// The default options only allow for a clock skew of 5 minutes, and require
+/// the nonce to be present when one was sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdTokenValidationOptions {
+    /// The allowed difference between the clocks of the client and the issuer,
+    /// when checking the time-based claims.
+    pub clock_skew: Duration,
+
+    /// The values allowed for the `acr` claim.
+    ///
+    /// If this is set, the claim must be present and be one of these values.
+    pub acr_values: Option<Vec<String>>,
+
+    /// The maximum time since the end-user last authenticated.
+    ///
+    /// If this is set, the `auth_time` claim must be present and not older
+    /// than this.
+    pub max_auth_age: Option<Duration>,
+
+    /// The expected authorized party.
+    ///
+    /// If this is set, the `azp` claim must be present and match it.
+    pub authorized_party: Option<String>,
+
+    /// How the `nonce` claim is checked.
+    pub nonce_policy: NoncePolicy,
+}
+
+impl Default for IdTokenValidationOptions {
+    fn default() -> Self {
+        Self {
+            clock_skew: claims::DEFAULT_LEEWAY,
+            acr_values: None,
+            max_auth_age: None,
+            authorized_party: None,
+            nonce_policy: NoncePolicy::default(),
+        }
+    }
+}
+
+impl IdTokenValidationOptions {
+    /// Set the allowed clock skew.
+    #[must_use]
+    pub fn with_clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Set the values allowed for the `acr` claim.
+    #[must_use]
+    pub fn with_acr_values(mut self, acr_values: impl IntoIterator<Item = String>) -> Self {
+        self.acr_values = Some(acr_values.into_iter().collect());
+        self
+    }
+
+    /// Set the maximum time since the end-user last authenticated.
+    #[must_use]
+    pub fn with_max_auth_age(mut self, max_auth_age: Duration) -> Self {
+        self.max_auth_age = Some(max_auth_age);
+        self
+    }
+
+    /// Set the expected authorized party.
+    #[must_use]
+    pub fn with_authorized_party(mut self, authorized_party: String) -> Self {
+        self.authorized_party = Some(authorized_party);
+        self
+    }
+
+    /// Set how the `nonce` claim is checked.
+    #[must_use]
+    pub fn with_nonce_policy(mut self, nonce_policy: NoncePolicy) -> Self {
+        self.nonce_policy = nonce_policy;
+        self
+    }
+}
+
+/// Decode and verify an ID Token, with the default
+/// [`IdTokenValidationOptions`].
+///
+/// See [`verify_id_token_with_options()`] for the checks that are performed.
+///
+/// # Arguments
+///
+/// * `id_token` - The serialized ID Token to decode and verify.
+///
+/// * `verification_data` - The data necessary to verify the ID Token.
+///
+/// * `auth_id_token` - If the ID Token is not verified during an authorization
+///   request, the ID token that was returned from the latest authorization
+///   request.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the data is invalid or verification fails.
+pub fn verify_id_token<'a>(
+    id_token: &'a str,
+    verification_data: JwtVerificationData<'_>,
+    auth_id_token: Option<&IdToken<'_>>,
+    now: DateTime<Utc>,
+) -> Result<IdToken<'a>, IdTokenError> {
+    verify_id_token_with_options(
+        id_token,
+        verification_data,
+        auth_id_token,
+        None,
+        &IdTokenValidationOptions::default(),
+        now,
+    )
+}
+
 /// Decode and verify an ID Token.
 ///
 /// Besides the checks of [`verify_signed_jwt()`], the following checks are
@@ -142,6 +272,12 @@ pub fn verify_signed_jwt<'a>(
 ///
 /// * The `sub` claim must be present.
 ///
+/// * The `nonce` claim must match the expected nonce, according to the
+///   [`NoncePolicy`].
+///
+/// * If set in the options, the `acr`, `auth_time` and `azp` claims must be
+///   present and valid.
+///
 /// If an authorization ID token is provided, these extra checks are performed:
 ///
 /// * The `sub` claims must match.
@@ -158,29 +294,91 @@ pub fn verify_signed_jwt<'a>(
 ///   request, the ID token that was returned from the latest authorization
 ///   request.
 ///
+/// * `nonce` - The nonce sent in the authorization request, if the ID Token is
+///   verified during one.
+///
+/// * `options` - The options of the checks.
+///
+/// * `now` - The current time.
+///
 /// # Errors
 ///
 /// Returns an error if the data is invalid or verification fails.
-pub fn verify_id_token<'a>(
+pub fn verify_id_token_with_options<'a>(
     id_token: &'a str,
     verification_data: JwtVerificationData<'_>,
     auth_id_token: Option<&IdToken<'_>>,
+    nonce: Option<&str>,
+    options: &IdTokenValidationOptions,
     now: DateTime<Utc>,
 ) -> Result<IdToken<'a>, IdTokenError> {
     let id_token = verify_signed_jwt(id_token, verification_data)?;
 
     let mut claims = id_token.payload().clone();
 
-    let time_options = TimeOptions::new(now);
+    let time_options = TimeOptions::new(now).leeway(options.clock_skew);
     // Must not have expired.
-    claims::EXP.extract_required_with_options(&mut claims, &time_options)?;
+    claims::EXP
+        .extract_required_with_options(&mut claims, &time_options)
+        .map_err(|e| match e {
+            ClaimError::ValidationError { .. } => IdTokenError::Expired,
+            e => e.into(),
+        })?;
 
     // `iat` claim must be present.
-    claims::IAT.extract_required_with_options(&mut claims, time_options)?;
+    claims::IAT
+        .extract_required_with_options(&mut claims, time_options)
+        .map_err(|e| match e {
+            ClaimError::ValidationError { .. } => IdTokenError::IssuedInTheFuture,
+            e => e.into(),
+        })?;
 
     // Subject identifier must be present.
     let sub = claims::SUB.extract_required(&mut claims)?;
 
+    // Nonce must match the one we sent.
+    if let Some(nonce) = nonce {
+        let claim = match options.nonce_policy {
+            NoncePolicy::Required | NoncePolicy::IfPresent => claims::NONCE
+                .extract_optional_with_options(&mut claims, nonce)
+                .map_err(|e| match e {
+                    ClaimError::ValidationError { .. } => IdTokenError::WrongNonce,
+                    e => e.into(),
+                })?,
+            NoncePolicy::Ignore => None,
+        };
+
+        if claim.is_none() && options.nonce_policy == NoncePolicy::Required {
+            return Err(IdTokenError::MissingNonce);
+        }
+    }
+
+    // Authentication context must be one of the allowed ones.
+    if let Some(acr_values) = &options.acr_values {
+        let acr = claims::ACR.extract_optional(&mut claims)?;
+        if !acr.as_ref().is_some_and(|acr| acr_values.contains(acr)) {
+            return Err(IdTokenError::AcrNotAllowed { got: acr });
+        }
+    }
+
+    // Authentication must be recent enough.
+    if let Some(max_auth_age) = options.max_auth_age {
+        let auth_time = claims::AUTH_TIME
+            .extract_optional(&mut claims.clone())?
+            .ok_or(IdTokenError::MissingAuthTime)?;
+        if now - *auth_time > max_auth_age + options.clock_skew {
+            return Err(IdTokenError::AuthTimeTooOld);
+        }
+    }
+
+    // Authorized party must be the expected one.
+    if let Some(authorized_party) = &options.authorized_party {
+        let azp = claims::AZP.extract_optional(&mut claims)?;
+        if azp.as_ref() != Some(authorized_party) {
+            return Err(IdTokenError::WrongAuthorizedParty { got: azp });
+        }
+    }
+
     // More checks if there is a previous ID token.
     if let Some(auth_id_token) = auth_id_token {
         let mut auth_claims = auth_id_token.payload().clone();
@@ -6,10 +6,12 @@
 
 //! Methods to interact with OpenID Connect and OAuth2.0 endpoints.
 
+pub mod account_management;
 pub mod authorization_code;
 pub mod client_credentials;
 pub mod discovery;
 pub mod jose;
 pub mod refresh_token;
+pub mod revocation;
 pub mod token;
 pub mod userinfo;
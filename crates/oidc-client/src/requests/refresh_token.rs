@@ -17,10 +17,11 @@ use oauth2_types::{
 use rand::Rng;
 use url::Url;
 
-use super::jose::JwtVerificationData;
+use super::jose::{IdTokenValidationOptions, JwtVerificationData};
 use crate::{
     error::{IdTokenError, TokenRefreshError},
-    requests::{jose::verify_id_token, token::request_access_token},
+    http_client::HttpClient,
+    requests::{jose::verify_id_token_with_options, token::request_access_token},
     types::{client_credentials::ClientCredentials, IdToken},
 };
 
@@ -31,7 +32,7 @@ use crate::{
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `client_credentials` - The credentials obtained when registering the
 ///   client.
@@ -53,6 +54,8 @@ use crate::{
 ///
 ///   If it is not provided, the ID Token won't be verified.
 ///
+/// * `id_token_options` - The options of the checks performed on the ID Token.
+///
 /// * `auth_id_token` - If an ID Token is expected in the response, the ID token
 ///   that was returned from the latest authorization request.
 ///
@@ -67,12 +70,13 @@ use crate::{
 #[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip_all, fields(token_endpoint))]
 pub async fn refresh_access_token(
-    http_client: &reqwest::Client,
+    http_client: &impl HttpClient,
     client_credentials: ClientCredentials,
     token_endpoint: &Url,
     refresh_token: String,
     scope: Option<Scope>,
     id_token_verification_data: Option<JwtVerificationData<'_>>,
+    id_token_options: &IdTokenValidationOptions,
     auth_id_token: Option<&IdToken<'_>>,
     now: DateTime<Utc>,
     rng: &mut impl Rng,
@@ -98,7 +102,14 @@ pub async fn refresh_access_token(
         let auth_id_token = auth_id_token.ok_or(IdTokenError::MissingAuthIdToken)?;
         let signing_alg = verification_data.signing_algorithm;
 
-        let id_token = verify_id_token(id_token, verification_data, Some(auth_id_token), now)?;
+        let id_token = verify_id_token_with_options(
+            id_token,
+            verification_data,
+            Some(auth_id_token),
+            None,
+            id_token_options,
+            now,
+        )?;
 
         let mut claims = id_token.payload().clone();
 
@@ -0,0 +1,63 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Requests for the [Revocation endpoint].
+//!
+//! [Revocation endpoint]: https://www.rfc-editor.org/rfc/rfc7009
+
+use chrono::{DateTime, Utc};
+use oauth2_types::requests::RevocationRequest;
+use rand::Rng;
+use url::Url;
+
+use crate::{
+    error::{OAuth2Error, TokenRevokeError},
+    http_client::{self, HttpClient},
+    types::client_credentials::ClientCredentials,
+};
+
+/// Revoke a token.
+///
+/// Use [`RevocationRequest::with_revoke_related`] to also revoke the other
+/// tokens of the same session, if the server supports it.
+///
+/// # Arguments
+///
+/// * `http_client` - The client to use for making HTTP requests.
+///
+/// * `client_credentials` - The credentials obtained when registering the
+///   client.
+///
+/// * `revocation_endpoint` - The URL of the issuer's Revocation endpoint.
+///
+/// * `request` - The request to make at the Revocation endpoint.
+///
+/// * `now` - The current time.
+///
+/// * `rng` - A random number generator.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the server returns an error.
+#[tracing::instrument(skip_all, fields(revocation_endpoint, request))]
+pub async fn revoke_token(
+    http_client: &impl HttpClient,
+    client_credentials: ClientCredentials,
+    revocation_endpoint: &Url,
+    request: RevocationRequest,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<(), TokenRevokeError> {
+    tracing::debug!(?request, "Revoking token...");
+
+    let revocation_request = http_client::post(revocation_endpoint);
+    let revocation_request =
+        client_credentials.authenticated_form(revocation_request, &request, now, rng)?;
+
+    let response = http_client::send(http_client, revocation_request).await?;
+    OAuth2Error::from_response(response)?;
+
+    Ok(())
+}
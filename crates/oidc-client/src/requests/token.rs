@@ -8,14 +8,14 @@
 
 use chrono::{DateTime, Utc};
 use http::header::ACCEPT;
-use mas_http::RequestBuilderExt;
 use mime::APPLICATION_JSON;
 use oauth2_types::requests::{AccessTokenRequest, AccessTokenResponse};
 use rand::Rng;
 use url::Url;
 
 use crate::{
-    error::{ResponseExt, TokenRequestError},
+    error::{OAuth2Error, TokenRequestError},
+    http_client::{self, HttpClient},
     types::client_credentials::ClientCredentials,
 };
 
@@ -23,7 +23,7 @@ use crate::{
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `client_credentials` - The credentials obtained when registering the
 ///   client.
@@ -41,7 +41,7 @@ use crate::{
 /// Returns an error if the request fails or the response is invalid.
 #[tracing::instrument(skip_all, fields(token_endpoint, request))]
 pub async fn request_access_token(
-    http_client: &reqwest::Client,
+    http_client: &impl HttpClient,
     client_credentials: ClientCredentials,
     token_endpoint: &Url,
     request: AccessTokenRequest,
@@ -50,18 +50,12 @@ pub async fn request_access_token(
 ) -> Result<AccessTokenResponse, TokenRequestError> {
     tracing::debug!(?request, "Requesting access token...");
 
-    let token_request = http_client
-        .post(token_endpoint.as_str())
-        .header(ACCEPT, APPLICATION_JSON.as_ref());
+    let token_request = http_client::post(token_endpoint).header(ACCEPT, APPLICATION_JSON.as_ref());
+    let token_request = client_credentials.authenticated_form(token_request, &request, now, rng)?;
 
-    let token_response = client_credentials
-        .authenticated_form(token_request, &request, now, rng)?
-        .send_traced()
-        .await?
-        .error_from_oauth2_error_response()
-        .await?
-        .json()
-        .await?;
+    let response = http_client::send(http_client, token_request).await?;
+    let response = OAuth2Error::from_response(response)?;
+    let token_response = http_client::json(&response)?;
 
     Ok(token_response)
 }
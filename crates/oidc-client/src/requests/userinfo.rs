@@ -6,20 +6,26 @@
 
 //! Requests for obtaining [Claims] about an end-user.
 //!
+//! [Aggregated and distributed claims] can be resolved with
+//! [`resolve_claim_sources()`].
+//!
 //! [Claims]: https://openid.net/specs/openid-connect-core-1_0.html#Claims
+//! [Aggregated and distributed claims]: https://openid.net/specs/openid-connect-core-1_0.html#AggregatedDistributedClaims
 
 use std::collections::HashMap;
 
-use headers::{ContentType, HeaderMapExt, HeaderValue};
+use headers::{Authorization, ContentType, HeaderMapExt, HeaderValue};
 use http::header::ACCEPT;
-use mas_http::RequestBuilderExt;
+use mas_jose::jwt::Jwt;
 use mime::Mime;
+use serde::Deserialize;
 use serde_json::Value;
 use url::Url;
 
 use super::jose::JwtVerificationData;
 use crate::{
-    error::{IdTokenError, ResponseExt, UserInfoError},
+    error::{ClaimSourceError, IdTokenError, OAuth2Error, UserInfoError},
+    http_client::{self, HttpClient, HttpError},
     requests::jose::verify_signed_jwt,
 };
 
@@ -30,7 +36,7 @@ use crate::{
 ///
 /// # Arguments
 ///
-/// * `http_client` - The reqwest client to use for making HTTP requests.
+/// * `http_client` - The client to use for making HTTP requests.
 ///
 /// * `userinfo_endpoint` - The URL of the issuer's User Info endpoint.
 ///
@@ -53,7 +59,7 @@ use crate::{
 /// [`Claim`]: mas_jose::claims::Claim
 #[tracing::instrument(skip_all, fields(userinfo_endpoint))]
 pub async fn fetch_userinfo(
-    http_client: &reqwest::Client,
+    http_client: &impl HttpClient,
     userinfo_endpoint: &Url,
     access_token: &str,
     jwt_verification_data: Option<JwtVerificationData<'_>>,
@@ -66,16 +72,17 @@ pub async fn fetch_userinfo(
         mime::APPLICATION_JSON.as_ref()
     };
 
-    let userinfo_request = http_client
-        .get(userinfo_endpoint.as_str())
-        .bearer_auth(access_token)
+    let mut userinfo_request = http_client::get(userinfo_endpoint)
         .header(ACCEPT, HeaderValue::from_static(expected_content_type));
+    if let Some(headers) = userinfo_request.headers_mut() {
+        let authorization =
+            Authorization::bearer(access_token).map_err(|_| UserInfoError::InvalidAccessToken)?;
+        headers.typed_insert(authorization);
+    }
+    let userinfo_request = userinfo_request.body(Vec::new()).map_err(HttpError::from)?;
 
-    let userinfo_response = userinfo_request
-        .send_traced()
-        .await?
-        .error_from_oauth2_error_response()
-        .await?;
+    let userinfo_response = http_client::send(http_client, userinfo_request).await?;
+    let userinfo_response = OAuth2Error::from_response(userinfo_response)?;
 
     let content_type: Mime = userinfo_response
         .headers()
@@ -92,13 +99,273 @@ pub async fn fetch_userinfo(
     }
 
     let claims = if let Some(verification_data) = jwt_verification_data {
-        let response_body = userinfo_response.text().await?;
+        let response_body = String::from_utf8_lossy(userinfo_response.body());
         verify_signed_jwt(&response_body, verification_data)
             .map_err(IdTokenError::from)?
             .into_parts()
             .1
     } else {
-        userinfo_response.json().await?
+        http_client::json(&userinfo_response)?
+    };
+
+    Ok(claims)
+}
+
+/// Which distributed claims sources are fetched when resolving the claims of a
+/// User Info response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClaimSourcesPolicy {
+    /// Distributed claims are not fetched, only aggregated claims are
+    /// resolved.
+    #[default]
+    AggregatedOnly,
+
+    /// Distributed claims are fetched from any endpoint.
+    FetchAll,
+
+    /// Distributed claims are only fetched from endpoints on these hosts.
+    FetchFromHosts(Vec<String>),
+}
+
+impl ClaimSourcesPolicy {
+    fn allows(&self, endpoint: &Url) -> bool {
+        match self {
+            Self::AggregatedOnly => false,
+            Self::FetchAll => true,
+            Self::FetchFromHosts(hosts) => endpoint
+                .host_str()
+                .is_some_and(|host| hosts.iter().any(|h| h == host)),
+        }
+    }
+}
+
+/// Where the value of a claim comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimProvenance {
+    /// The claim was in the User Info response itself.
+    UserInfo,
+
+    /// The claim was in a JWT embedded in the User Info response.
+    Aggregated {
+        /// The name of the source.
+        source: String,
+
+        /// The issuer of the JWT, if any.
+        issuer: Option<String>,
+    },
+
+    /// The claim was fetched from the endpoint of a claims provider.
+    Distributed {
+        /// The name of the source.
+        source: String,
+
+        /// The endpoint the claim was fetched from.
+        endpoint: Url,
+
+        /// The issuer of the JWT, if any.
+        issuer: Option<String>,
+    },
+}
+
+/// The claims of a User Info response, with the aggregated and distributed
+/// claims resolved.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedClaims {
+    /// The claims with their value.
+    pub claims: HashMap<String, Value>,
+
+    /// Where the value of each claim comes from.
+    pub provenance: HashMap<String, ClaimProvenance>,
+
+    /// The claims whose source was not resolved, because the policy doesn't
+    /// allow fetching it or because the source doesn't contain them, sorted by
+    /// name.
+    pub unresolved: Vec<String>,
+}
+
+/// A source of claims, as found in the `_claim_sources` member.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClaimSource {
+    Aggregated {
+        #[serde(rename = "JWT")]
+        jwt: String,
+    },
+    Distributed {
+        endpoint: Url,
+        access_token: Option<String>,
+    },
+}
+
+/// Resolve the aggregated and distributed claims referenced in the
+/// `_claim_names` and `_claim_sources` members of claims obtained from the
+/// User Info endpoint.
+///
+/// The JWTs of the sources are signed by the claims providers, whose keys are
+/// not known, so their signature is not verified. The provenance of the claims
+/// can be used to decide whether to trust them.
+///
+/// # Arguments
+///
+/// * `http_client` - The client to use for making HTTP requests.
+///
+/// * `claims` - The claims returned by [`fetch_userinfo()`].
+///
+/// * `policy` - Which distributed claims sources should be fetched.
+///
+/// # Errors
+///
+/// Returns an error if the claim sources are invalid, or if fetching a
+/// distributed claims source fails.
+#[tracing::instrument(skip_all)]
+pub async fn resolve_claim_sources(
+    http_client: &impl HttpClient,
+    mut claims: HashMap<String, Value>,
+    policy: &ClaimSourcesPolicy,
+) -> Result<ResolvedClaims, UserInfoError> {
+    let claim_names: HashMap<String, String> = claims
+        .remove("_claim_names")
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(ClaimSourceError::Invalid)?
+        .unwrap_or_default();
+    let claim_sources: HashMap<String, ClaimSource> = claims
+        .remove("_claim_sources")
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(ClaimSourceError::Invalid)?
+        .unwrap_or_default();
+
+    let mut resolved = ResolvedClaims {
+        provenance: claims
+            .keys()
+            .map(|claim| (claim.clone(), ClaimProvenance::UserInfo))
+            .collect(),
+        claims,
+        unresolved: Vec::new(),
+    };
+
+    // Group the claims by source, so that each source is resolved only once
+    let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+    for (claim, source) in claim_names {
+        if !claim_sources.contains_key(&source) {
+            return Err(ClaimSourceError::UnknownSource {
+                claim,
+                name: source,
+            }
+            .into());
+        }
+        sources.entry(source).or_default().push(claim);
+    }
+
+    for (name, source_claims) in sources {
+        let (mut values, provenance) = match &claim_sources[&name] {
+            ClaimSource::Aggregated { jwt } => {
+                let payload = decode_claims_jwt(&name, jwt)?;
+                let provenance = ClaimProvenance::Aggregated {
+                    source: name.clone(),
+                    issuer: payload
+                        .get("iss")
+                        .and_then(Value::as_str)
+                        .map(ToOwned::to_owned),
+                };
+                (payload, provenance)
+            }
+
+            ClaimSource::Distributed {
+                endpoint,
+                access_token,
+            } => {
+                if !policy.allows(endpoint) {
+                    tracing::debug!(source = %name, %endpoint, "Skipping distributed claims source");
+                    resolved.unresolved.extend(source_claims);
+                    continue;
+                }
+
+                let payload =
+                    fetch_distributed_claims(http_client, &name, endpoint, access_token.as_deref())
+                        .await?;
+                let provenance = ClaimProvenance::Distributed {
+                    source: name.clone(),
+                    endpoint: endpoint.clone(),
+                    issuer: payload
+                        .get("iss")
+                        .and_then(Value::as_str)
+                        .map(ToOwned::to_owned),
+                };
+                (payload, provenance)
+            }
+        };
+
+        for claim in source_claims {
+            if let Some(value) = values.remove(&claim) {
+                resolved.claims.insert(claim.clone(), value);
+                resolved.provenance.insert(claim, provenance.clone());
+            } else {
+                resolved.unresolved.push(claim);
+            }
+        }
+    }
+
+    resolved.unresolved.sort();
+
+    Ok(resolved)
+}
+
+/// Decode the claims of a JWT from a claims source, without verifying its
+/// signature.
+fn decode_claims_jwt(name: &str, jwt: &str) -> Result<HashMap<String, Value>, ClaimSourceError> {
+    let jwt: Jwt<'_, HashMap<String, Value>> =
+        jwt.try_into().map_err(|error| ClaimSourceError::Jwt {
+            name: name.to_owned(),
+            error,
+        })?;
+    Ok(jwt.into_parts().1)
+}
+
+/// Fetch the claims of a distributed claims source.
+async fn fetch_distributed_claims(
+    http_client: &impl HttpClient,
+    name: &str,
+    endpoint: &Url,
+    access_token: Option<&str>,
+) -> Result<HashMap<String, Value>, UserInfoError> {
+    tracing::debug!(source = name, %endpoint, "Fetching distributed claims…");
+
+    let fetch_error = |error: HttpError| ClaimSourceError::Fetch {
+        name: name.to_owned(),
+        error,
+    };
+
+    let mut request =
+        http_client::get(endpoint).header(ACCEPT, HeaderValue::from_static("application/jwt"));
+    if let (Some(headers), Some(access_token)) = (request.headers_mut(), access_token) {
+        let authorization =
+            Authorization::bearer(access_token).map_err(|_| UserInfoError::InvalidAccessToken)?;
+        headers.typed_insert(authorization);
+    }
+    let request = request
+        .body(Vec::new())
+        .map_err(|e| fetch_error(HttpError::from(e)))?;
+
+    let response = http_client::send(http_client, request)
+        .await
+        .map_err(fetch_error)?;
+    let response = http_client::error_for_status(response).map_err(fetch_error)?;
+
+    // The response should be a JWT, but some providers return plain JSON
+    let is_json = response
+        .headers()
+        .typed_get::<ContentType>()
+        .is_some_and(|content_type| {
+            Mime::from(content_type).essence_str() == mime::APPLICATION_JSON.as_ref()
+        });
+
+    let claims = if is_json {
+        http_client::json(&response).map_err(fetch_error)?
+    } else {
+        let body = String::from_utf8_lossy(response.body());
+        decode_claims_jwt(name, body.trim())?
     };
 
     Ok(claims)
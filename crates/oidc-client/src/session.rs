@@ -0,0 +1,339 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A higher-level session owning the tokens of a client.
+//!
+//! An [`OidcSession`] keeps the latest tokens obtained from the provider,
+//! refreshes the access token before it expires, and persists the tokens
+//! through a [`TokenStore`] every time they change.
+//!
+//! Refreshes are single-flight: when the access token needs to be refreshed,
+//! concurrent callers wait for the same refresh instead of each sending their
+//! own request, which would make the provider invalidate all but one of the
+//! new refresh tokens.
+
+use std::{convert::Infallible, sync::Mutex as SyncMutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::lock::Mutex;
+use oauth2_types::{requests::AccessTokenResponse, scope::Scope};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    error::SessionError,
+    http_client::HttpClient,
+    requests::{jose::IdTokenValidationOptions, refresh_token::refresh_access_token},
+    types::client_credentials::ClientCredentials,
+};
+
+/// The tokens of a session, as persisted by a [`TokenStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTokens {
+    /// The current access token.
+    pub access_token: String,
+
+    /// The current refresh token, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+
+    /// When the access token expires, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// The scope granted to the access token, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+}
+
+impl SessionTokens {
+    /// Build the tokens from a response of the Token endpoint.
+    ///
+    /// If the response doesn't have a new refresh token, the previous one is
+    /// kept, as allowed by the [OAuth 2.0 specification].
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response of the Token endpoint.
+    ///
+    /// * `previous_refresh_token` - The refresh token used for the request, if
+    ///   any.
+    ///
+    /// * `now` - The time at which the request was made.
+    ///
+    /// [OAuth 2.0 specification]: https://www.rfc-editor.org/rfc/rfc6749#section-6
+    #[must_use]
+    pub fn from_response(
+        response: AccessTokenResponse,
+        previous_refresh_token: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.or(previous_refresh_token),
+            expires_at: response.expires_in.map(|expires_in| now + expires_in),
+            scope: response.scope,
+        }
+    }
+}
+
+/// A storage for the tokens of an [`OidcSession`].
+///
+/// The session saves the tokens every time they are refreshed, so that they
+/// survive a restart of the application.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait TokenStore: Send + Sync {
+    /// The error type of the storage.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Load the saved tokens, if any.
+    async fn load(&self) -> Result<Option<SessionTokens>, Self::Error>;
+
+    /// Save the given tokens, replacing the previous ones.
+    async fn save(&self, tokens: &SessionTokens) -> Result<(), Self::Error>;
+}
+
+/// A [`TokenStore`] which only keeps the tokens in memory.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: SyncMutex<Option<SessionTokens>>,
+}
+
+impl MemoryTokenStore {
+    /// Create a new store, with the given initial tokens.
+    #[must_use]
+    pub fn new(tokens: Option<SessionTokens>) -> Self {
+        Self {
+            tokens: SyncMutex::new(tokens),
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl TokenStore for MemoryTokenStore {
+    type Error = Infallible;
+
+    async fn load(&self) -> Result<Option<SessionTokens>, Self::Error> {
+        Ok(self.tokens.lock().unwrap().clone())
+    }
+
+    async fn save(&self, tokens: &SessionTokens) -> Result<(), Self::Error> {
+        *self.tokens.lock().unwrap() = Some(tokens.clone());
+        Ok(())
+    }
+}
+
+/// The state of a session, guarded by the refresh lock.
+struct SessionState {
+    tokens: Option<SessionTokens>,
+
+    /// When the access token should be refreshed, including the jitter.
+    refresh_at: Option<DateTime<Utc>>,
+}
+
+/// A session owning the tokens of a client, and refreshing them before they
+/// expire.
+pub struct OidcSession<S, C> {
+    http_client: C,
+    client_credentials: ClientCredentials,
+    token_endpoint: Url,
+    store: S,
+    refresh_margin: Duration,
+    refresh_jitter: Duration,
+    state: Mutex<SessionState>,
+}
+
+impl<S: TokenStore, C: HttpClient> OidcSession<S, C> {
+    /// Create a new session, using the tokens saved in the store, if any.
+    ///
+    /// By default, the access token is refreshed between 30 and 60 seconds
+    /// before it expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `http_client` - The client to use for making HTTP requests.
+    ///
+    /// * `client_credentials` - The credentials obtained when registering the
+    ///   client.
+    ///
+    /// * `token_endpoint` - The URL of the issuer's Token endpoint.
+    ///
+    /// * `store` - The storage to load the tokens from, and save them to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens couldn't be loaded from the store.
+    pub async fn load(
+        http_client: C,
+        client_credentials: ClientCredentials,
+        token_endpoint: Url,
+        store: S,
+    ) -> Result<Self, SessionError> {
+        let tokens = store.load().await.map_err(SessionError::store)?;
+
+        Ok(Self {
+            http_client,
+            client_credentials,
+            token_endpoint,
+            store,
+            refresh_margin: Duration::seconds(30),
+            refresh_jitter: Duration::seconds(30),
+            state: Mutex::new(SessionState {
+                tokens,
+                refresh_at: None,
+            }),
+        })
+    }
+
+    /// Set how long before the expiration of the access token it should be
+    /// refreshed, at least.
+    #[must_use]
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Set the maximum random delay added to the refresh margin, so that many
+    /// clients sharing the same expiration don't all refresh at once.
+    #[must_use]
+    pub fn with_refresh_jitter(mut self, refresh_jitter: Duration) -> Self {
+        self.refresh_jitter = refresh_jitter;
+        self
+    }
+
+    /// Get the underlying token store.
+    #[must_use]
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Replace the tokens of the session, for example after a new login, and
+    /// save them to the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens couldn't be saved to the store.
+    pub async fn set_tokens(&self, tokens: SessionTokens) -> Result<(), SessionError> {
+        let mut state = self.state.lock().await;
+        self.store
+            .save(&tokens)
+            .await
+            .map_err(SessionError::store)?;
+        state.tokens = Some(tokens);
+        state.refresh_at = None;
+        Ok(())
+    }
+
+    /// Get the current tokens, without refreshing them.
+    pub async fn tokens(&self) -> Option<SessionTokens> {
+        self.state.lock().await.tokens.clone()
+    }
+
+    /// Get a valid access token, refreshing it first if it is about to
+    /// expire.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    ///
+    /// * `rng` - A random number generator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session has no tokens, if the refresh failed,
+    /// or if the new tokens couldn't be saved.
+    pub async fn access_token(
+        &self,
+        now: DateTime<Utc>,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<String, SessionError> {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        let tokens = state.tokens.as_ref().ok_or(SessionError::NoTokens)?;
+
+        let Some(expires_at) = tokens.expires_at else {
+            // We don't know when the token expires, so it is refreshed only on demand
+            return Ok(tokens.access_token.clone());
+        };
+
+        let refresh_at = *state.refresh_at.get_or_insert_with(|| {
+            let jitter = self.refresh_jitter.num_milliseconds().max(0);
+            let jitter = Duration::try_milliseconds(rng.gen_range(0..=jitter)).unwrap_or_default();
+            expires_at - self.refresh_margin - jitter
+        });
+
+        if now < refresh_at {
+            return Ok(tokens.access_token.clone());
+        }
+
+        let tokens = self.refresh_locked(state, now, rng).await?;
+        Ok(tokens.access_token.clone())
+    }
+
+    /// Refresh the access token now, regardless of its expiration.
+    ///
+    /// This is useful when the resource server rejected the current access
+    /// token.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    ///
+    /// * `rng` - A random number generator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session has no refresh token, if the refresh
+    /// failed, or if the new tokens couldn't be saved.
+    pub async fn refresh(
+        &self,
+        now: DateTime<Utc>,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<SessionTokens, SessionError> {
+        let mut state = self.state.lock().await;
+        let tokens = self.refresh_locked(&mut state, now, rng).await?;
+        Ok(tokens.clone())
+    }
+
+    async fn refresh_locked<'a>(
+        &self,
+        state: &'a mut SessionState,
+        now: DateTime<Utc>,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<&'a SessionTokens, SessionError> {
+        let tokens = state.tokens.as_ref().ok_or(SessionError::NoTokens)?;
+        let refresh_token = tokens
+            .refresh_token
+            .clone()
+            .ok_or(SessionError::NoRefreshToken)?;
+
+        let (response, _id_token) = refresh_access_token(
+            &self.http_client,
+            self.client_credentials.clone(),
+            &self.token_endpoint,
+            refresh_token.clone(),
+            None,
+            None,
+            &IdTokenValidationOptions::default(),
+            None,
+            now,
+            rng,
+        )
+        .await?;
+
+        let tokens = SessionTokens::from_response(response, Some(refresh_token), now);
+        self.store
+            .save(&tokens)
+            .await
+            .map_err(SessionError::store)?;
+
+        state.refresh_at = None;
+        Ok(state.tokens.insert(tokens))
+    }
+}
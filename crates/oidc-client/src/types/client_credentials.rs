@@ -10,6 +10,8 @@ use std::{collections::HashMap, fmt};
 
 use base64ct::{Base64UrlUnpadded, Encoding};
 use chrono::{DateTime, Duration, Utc};
+use headers::{Authorization, HeaderMapExt};
+use http::request::Builder;
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::{
     claims::{self, ClaimError},
@@ -23,7 +25,10 @@ use serde::Serialize;
 use serde_json::Value;
 use url::Url;
 
-use crate::error::CredentialsError;
+use crate::{
+    error::CredentialsError,
+    http_client::{with_form, HttpRequest},
+};
 
 /// The supported authentication methods of this library.
 ///
@@ -136,19 +141,22 @@ impl ClientCredentials {
     #[allow(clippy::too_many_lines)]
     pub(crate) fn authenticated_form<T: Serialize>(
         &self,
-        request: reqwest::RequestBuilder,
+        mut request: Builder,
         form: &T,
         now: DateTime<Utc>,
         rng: &mut impl Rng,
-    ) -> Result<reqwest::RequestBuilder, CredentialsError> {
+    ) -> Result<HttpRequest, CredentialsError> {
         let request = match self {
-            ClientCredentials::None { client_id } => request.form(&RequestWithClientCredentials {
-                body: form,
-                client_id,
-                client_secret: None,
-                client_assertion: None,
-                client_assertion_type: None,
-            }),
+            ClientCredentials::None { client_id } => with_form(
+                request,
+                &RequestWithClientCredentials {
+                    body: form,
+                    client_id,
+                    client_secret: None,
+                    client_assertion: None,
+                    client_assertion_type: None,
+                },
+            )?,
 
             ClientCredentials::ClientSecretBasic {
                 client_id,
@@ -158,27 +166,35 @@ impl ClientCredentials {
                     form_urlencoded::byte_serialize(client_id.as_bytes()).collect::<String>();
                 let password =
                     form_urlencoded::byte_serialize(client_secret.as_bytes()).collect::<String>();
-                request
-                    .basic_auth(username, Some(password))
-                    .form(&RequestWithClientCredentials {
+                if let Some(headers) = request.headers_mut() {
+                    headers.typed_insert(Authorization::basic(&username, &password));
+                }
+
+                with_form(
+                    request,
+                    &RequestWithClientCredentials {
                         body: form,
                         client_id,
                         client_secret: None,
                         client_assertion: None,
                         client_assertion_type: None,
-                    })
+                    },
+                )?
             }
 
             ClientCredentials::ClientSecretPost {
                 client_id,
                 client_secret,
-            } => request.form(&RequestWithClientCredentials {
-                body: form,
-                client_id,
-                client_secret: Some(client_secret),
-                client_assertion: None,
-                client_assertion_type: None,
-            }),
+            } => with_form(
+                request,
+                &RequestWithClientCredentials {
+                    body: form,
+                    client_id,
+                    client_secret: Some(client_secret),
+                    client_assertion: None,
+                    client_assertion_type: None,
+                },
+            )?,
 
             ClientCredentials::ClientSecretJwt {
                 client_id,
@@ -196,13 +212,16 @@ impl ClientCredentials {
 
                 let jwt = Jwt::sign(header, claims, &key)?;
 
-                request.form(&RequestWithClientCredentials {
-                    body: form,
-                    client_id,
-                    client_secret: None,
-                    client_assertion: Some(jwt.as_str()),
-                    client_assertion_type: Some(JwtBearerClientAssertionType),
-                })
+                with_form(
+                    request,
+                    &RequestWithClientCredentials {
+                        body: form,
+                        client_id,
+                        client_secret: None,
+                        client_assertion: Some(jwt.as_str()),
+                        client_assertion_type: Some(JwtBearerClientAssertionType),
+                    },
+                )?
             }
 
             ClientCredentials::PrivateKeyJwt {
@@ -229,13 +248,16 @@ impl ClientCredentials {
 
                 let client_assertion = Jwt::sign(header, claims, &signer)?;
 
-                request.form(&RequestWithClientCredentials {
-                    body: form,
-                    client_id,
-                    client_secret: None,
-                    client_assertion: Some(client_assertion.as_str()),
-                    client_assertion_type: Some(JwtBearerClientAssertionType),
-                })
+                with_form(
+                    request,
+                    &RequestWithClientCredentials {
+                        body: form,
+                        client_id,
+                        client_secret: None,
+                        client_assertion: Some(client_assertion.as_str()),
+                        client_assertion_type: Some(JwtBearerClientAssertionType),
+                    },
+                )?
             }
 
             ClientCredentials::SignInWithApple {
@@ -262,13 +284,16 @@ impl ClientCredentials {
 
                 let client_secret = Jwt::sign(header, claims, &signer)?;
 
-                request.form(&RequestWithClientCredentials {
-                    body: form,
-                    client_id,
-                    client_secret: Some(client_secret.as_str()),
-                    client_assertion: None,
-                    client_assertion_type: None,
-                })
+                with_form(
+                    request,
+                    &RequestWithClientCredentials {
+                        body: form,
+                        client_id,
+                        client_secret: Some(client_secret.as_str()),
+                        client_assertion: None,
+                        client_assertion_type: None,
+                    },
+                )?
             }
         };
 
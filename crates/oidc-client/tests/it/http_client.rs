@@ -0,0 +1,77 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{convert::Infallible, sync::Mutex};
+
+use assert_matches::assert_matches;
+use async_trait::async_trait;
+use http::{Method, Response, StatusCode};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_oidc_client::{
+    error::JwksError,
+    http_client::{HttpClient, HttpError, HttpRequest, HttpResponse},
+    requests::jose::fetch_jwks,
+};
+use url::Url;
+
+use crate::keystore;
+
+/// A backend always returning the same response, and recording the requests
+struct StaticHttpClient {
+    status: StatusCode,
+    body: Vec<u8>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl StaticHttpClient {
+    fn new(status: StatusCode, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            body,
+            requests: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for StaticHttpClient {
+    type Error = Infallible;
+
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
+        self.requests.lock().unwrap().push(request);
+        Ok(Response::builder()
+            .status(self.status)
+            .header("content-type", "application/json")
+            .body(self.body.clone())
+            .unwrap())
+    }
+}
+
+#[tokio::test]
+async fn pass_custom_http_client() {
+    let jwks = keystore(&JsonWebSignatureAlg::Es256).public_jwks();
+    let http_client = StaticHttpClient::new(StatusCode::OK, serde_json::to_vec(&jwks).unwrap());
+    let jwks_uri = Url::parse("https://example.com/jwks").unwrap();
+
+    let fetched = fetch_jwks(&http_client, &jwks_uri).await.unwrap();
+    assert_eq!(fetched, jwks);
+
+    let requests = http_client.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method(), Method::GET);
+    assert_eq!(requests[0].uri(), "https://example.com/jwks");
+}
+
+#[tokio::test]
+async fn fail_custom_http_client_error_status() {
+    let http_client = StaticHttpClient::new(StatusCode::NOT_FOUND, Vec::new());
+    let jwks_uri = Url::parse("https://example.com/jwks").unwrap();
+
+    let error = fetch_jwks(&http_client, &jwks_uri).await.unwrap_err();
+    assert_matches!(
+        error,
+        JwksError::Http(HttpError::Status(StatusCode::NOT_FOUND))
+    );
+}
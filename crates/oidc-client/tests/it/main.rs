@@ -23,7 +23,9 @@ use rand::{
 use url::Url;
 use wiremock::MockServer;
 
+mod http_client;
 mod requests;
+mod session;
 mod types;
 
 const REDIRECT_URI: &str = "http://localhost/";
@@ -0,0 +1,52 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use mas_oidc_client::requests::account_management::{
+    build_account_management_url, AccountManagementActionFull,
+};
+use url::Url;
+
+#[test]
+fn build_account_management_urls() {
+    let account_management_uri = Url::parse("https://auth.example.com/account/").unwrap();
+
+    let url = build_account_management_url(account_management_uri.clone(), None).unwrap();
+    assert_eq!(url.as_str(), "https://auth.example.com/account/");
+
+    let url = build_account_management_url(
+        account_management_uri.clone(),
+        Some(&AccountManagementActionFull::Profile),
+    )
+    .unwrap();
+    assert_eq!(
+        url.as_str(),
+        "https://auth.example.com/account/?action=org.matrix.profile"
+    );
+
+    let url = build_account_management_url(
+        account_management_uri,
+        Some(&AccountManagementActionFull::SessionEnd {
+            device_id: "ABC DEF".to_owned(),
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        url.as_str(),
+        "https://auth.example.com/account/?action=org.matrix.session_end&device_id=ABC+DEF"
+    );
+
+    // Existing query parameters are kept
+    let url = build_account_management_url(
+        Url::parse("https://auth.example.com/account?lang=fr").unwrap(),
+        Some(&AccountManagementActionFull::SessionView {
+            device_id: "ABCDEF".to_owned(),
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        url.as_str(),
+        "https://auth.example.com/account?lang=fr&action=org.matrix.session_view&device_id=ABCDEF"
+    );
+}
@@ -10,7 +10,7 @@ use assert_matches::assert_matches;
 use mas_iana::oauth::{
     OAuthAccessTokenType, OAuthClientAuthenticationMethod, PkceCodeChallengeMethod,
 };
-use mas_jose::{claims::ClaimError, jwk::PublicJsonWebKeySet};
+use mas_jose::jwk::PublicJsonWebKeySet;
 use mas_oidc_client::{
     error::{IdTokenError, TokenAuthorizationCodeError},
     requests::{
@@ -18,7 +18,7 @@ use mas_oidc_client::{
             access_token_with_authorization_code, build_authorization_url,
             AuthorizationRequestData, AuthorizationValidationData,
         },
-        jose::JwtVerificationData,
+        jose::{IdTokenValidationOptions, JwtVerificationData},
     },
 };
 use oauth2_types::{
@@ -222,6 +222,7 @@ async fn pass_access_token_with_authorization_code() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        &IdTokenValidationOptions::default(),
         now(),
         &mut rng,
     )
@@ -280,6 +281,7 @@ async fn fail_access_token_with_authorization_code_wrong_nonce() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        &IdTokenValidationOptions::default(),
         now(),
         &mut rng,
     )
@@ -288,10 +290,7 @@ async fn fail_access_token_with_authorization_code_wrong_nonce() {
 
     assert_matches!(
         error,
-        TokenAuthorizationCodeError::IdToken(IdTokenError::Claim(ClaimError::ValidationError {
-            claim: "nonce",
-            ..
-        }))
+        TokenAuthorizationCodeError::IdToken(IdTokenError::WrongNonce)
     );
 }
 
@@ -341,6 +340,7 @@ async fn fail_access_token_with_authorization_code_no_id_token() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        &IdTokenValidationOptions::default(),
         now(),
         &mut rng,
     )
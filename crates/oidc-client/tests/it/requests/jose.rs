@@ -17,16 +17,20 @@ use mas_jose::{
 };
 use mas_oidc_client::{
     error::{IdTokenError, JwtVerificationError},
-    requests::jose::{verify_id_token, JwtVerificationData},
+    requests::jose::{
+        verify_id_token, verify_id_token_with_options, IdTokenValidationOptions,
+        JwtVerificationData, NoncePolicy,
+    },
     types::IdToken,
 };
 
-use crate::{keystore, now, CLIENT_ID, ID_TOKEN_SIGNING_ALG, SUBJECT_IDENTIFIER};
+use crate::{keystore, now, CLIENT_ID, ID_TOKEN_SIGNING_ALG, NONCE, SUBJECT_IDENTIFIER};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum IdTokenFlag {
     WrongExpiration,
     WrongSubject,
+    WithExtraClaims,
 }
 
 /// Generate an ID token with the given settings.
@@ -72,6 +76,16 @@ fn id_token(
         claims::AUTH_TIME.insert(&mut claims, auth_time).unwrap();
     }
 
+    if flag == Some(IdTokenFlag::WithExtraClaims) {
+        claims::NONCE.insert(&mut claims, NONCE.to_owned()).unwrap();
+        claims::ACR
+            .insert(&mut claims, "urn:mace:incommon:iap:silver".to_owned())
+            .unwrap();
+        claims::AZP
+            .insert(&mut claims, CLIENT_ID.to_owned())
+            .unwrap();
+    }
+
     let key = keystore.signing_key_for_algorithm(&signing_alg).unwrap();
     let signer = key.params().signing_key_for_alg(&signing_alg).unwrap();
     let header = JsonWebSignatureHeader::new(signing_alg).with_kid(key.kid().unwrap());
@@ -188,7 +202,7 @@ async fn fail_verify_id_token_wrong_expiration() {
 
     let error = verify_id_token(id_token.as_str(), verification_data, None, now).unwrap_err();
 
-    assert_matches!(error, IdTokenError::Claim(_));
+    assert_matches!(error, IdTokenError::Expired);
 }
 
 #[tokio::test]
@@ -240,3 +254,154 @@ async fn fail_verify_id_token_wrong_auth_time() {
 
     assert_matches!(error, IdTokenError::WrongAuthTime);
 }
+
+#[tokio::test]
+async fn pass_verify_id_token_with_options() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (id_token, jwks) = id_token(
+        issuer,
+        Some(IdTokenFlag::WithExtraClaims),
+        Some(now - Duration::try_minutes(10).unwrap()),
+    );
+
+    let verification_data = JwtVerificationData {
+        issuer,
+        jwks: &jwks,
+        client_id: &CLIENT_ID.to_owned(),
+        signing_algorithm: &ID_TOKEN_SIGNING_ALG,
+    };
+
+    let options = IdTokenValidationOptions::default()
+        .with_acr_values(["urn:mace:incommon:iap:silver".to_owned()])
+        .with_max_auth_age(Duration::try_hours(1).unwrap())
+        .with_authorized_party(CLIENT_ID.to_owned());
+
+    verify_id_token_with_options(
+        id_token.as_str(),
+        verification_data,
+        None,
+        Some(NONCE),
+        &options,
+        now,
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn fail_verify_id_token_with_options() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (id_token_without_claims, _) = id_token(issuer, None, None);
+    let (id_token, jwks) = id_token(
+        issuer,
+        Some(IdTokenFlag::WithExtraClaims),
+        Some(now - Duration::try_hours(2).unwrap()),
+    );
+
+    let verification_data = JwtVerificationData {
+        issuer,
+        jwks: &jwks,
+        client_id: &CLIENT_ID.to_owned(),
+        signing_algorithm: &ID_TOKEN_SIGNING_ALG,
+    };
+
+    let verify = |id_token: &IdToken<'_>, nonce, options| {
+        verify_id_token_with_options(
+            id_token.as_str(),
+            verification_data,
+            None,
+            nonce,
+            &options,
+            now,
+        )
+        .unwrap_err()
+    };
+
+    // Wrong nonce
+    let error = verify(
+        &id_token,
+        Some("wrong_nonce"),
+        IdTokenValidationOptions::default(),
+    );
+    assert_matches!(error, IdTokenError::WrongNonce);
+
+    // Missing nonce, unless it is optional
+    let error = verify(
+        &id_token_without_claims,
+        Some(NONCE),
+        IdTokenValidationOptions::default(),
+    );
+    assert_matches!(error, IdTokenError::MissingNonce);
+    verify_id_token_with_options(
+        id_token_without_claims.as_str(),
+        verification_data,
+        None,
+        Some(NONCE),
+        &IdTokenValidationOptions::default().with_nonce_policy(NoncePolicy::IfPresent),
+        now,
+    )
+    .unwrap();
+
+    // Wrong acr
+    let error = verify(
+        &id_token,
+        None,
+        IdTokenValidationOptions::default()
+            .with_acr_values(["urn:mace:incommon:iap:gold".to_owned()]),
+    );
+    assert_matches!(error, IdTokenError::AcrNotAllowed { got: Some(_) });
+
+    // Authentication too old
+    let error = verify(
+        &id_token,
+        None,
+        IdTokenValidationOptions::default().with_max_auth_age(Duration::try_hours(1).unwrap()),
+    );
+    assert_matches!(error, IdTokenError::AuthTimeTooOld);
+
+    // Missing auth_time
+    let error = verify(
+        &id_token_without_claims,
+        None,
+        IdTokenValidationOptions::default().with_max_auth_age(Duration::try_hours(1).unwrap()),
+    );
+    assert_matches!(error, IdTokenError::MissingAuthTime);
+
+    // Wrong azp
+    let error = verify(
+        &id_token,
+        None,
+        IdTokenValidationOptions::default().with_authorized_party("other_client".to_owned()),
+    );
+    assert_matches!(error, IdTokenError::WrongAuthorizedParty { got: Some(_) });
+}
+
+#[tokio::test]
+async fn verify_id_token_clock_skew() {
+    let issuer = "http://localhost/";
+    let (id_token, jwks) = id_token(issuer, None, None);
+    // The token expires in an hour
+    let now = now() + Duration::try_minutes(62).unwrap();
+
+    let verification_data = JwtVerificationData {
+        issuer,
+        jwks: &jwks,
+        client_id: &CLIENT_ID.to_owned(),
+        signing_algorithm: &ID_TOKEN_SIGNING_ALG,
+    };
+
+    // Allowed with the default skew of 5 minutes
+    verify_id_token(id_token.as_str(), verification_data, None, now).unwrap();
+
+    let error = verify_id_token_with_options(
+        id_token.as_str(),
+        verification_data,
+        None,
+        None,
+        &IdTokenValidationOptions::default().with_clock_skew(Duration::zero()),
+        now,
+    )
+    .unwrap_err();
+    assert_matches!(error, IdTokenError::Expired);
+}
@@ -4,9 +4,11 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+mod account_management;
 mod authorization_code;
 mod client_credentials;
 mod discovery;
 mod jose;
 mod refresh_token;
+mod revocation;
 mod userinfo;
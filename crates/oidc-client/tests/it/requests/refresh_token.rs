@@ -8,7 +8,9 @@ use std::collections::HashMap;
 
 use assert_matches::assert_matches;
 use mas_iana::oauth::{OAuthAccessTokenType, OAuthClientAuthenticationMethod};
-use mas_oidc_client::requests::refresh_token::refresh_access_token;
+use mas_oidc_client::requests::{
+    jose::IdTokenValidationOptions, refresh_token::refresh_access_token,
+};
 use oauth2_types::requests::AccessTokenResponse;
 use rand::SeedableRng;
 use wiremock::{
@@ -77,6 +79,7 @@ async fn pass_refresh_access_token() {
         REFRESH_TOKEN.to_owned(),
         None,
         None,
+        &IdTokenValidationOptions::default(),
         None,
         now(),
         &mut rng,
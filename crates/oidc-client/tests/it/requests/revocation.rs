@@ -0,0 +1,118 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::HashMap;
+
+use assert_matches::assert_matches;
+use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
+use mas_oidc_client::{error::TokenRevokeError, requests::revocation::revoke_token};
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::RevocationRequest,
+};
+use rand::SeedableRng;
+use wiremock::{
+    matchers::{method, path},
+    Mock, Request, ResponseTemplate,
+};
+
+use crate::{client_credentials, init_test, now, CLIENT_ID, REFRESH_TOKEN};
+
+#[tokio::test]
+async fn pass_revoke_token() {
+    let (http_client, mock_server, issuer) = init_test().await;
+    let client_credentials = client_credentials(&OAuthClientAuthenticationMethod::None, &issuer);
+    let revocation_endpoint = issuer.join("revoke").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    Mock::given(method("POST"))
+        .and(path("/revoke"))
+        .and(|req: &Request| {
+            let query_pairs = form_urlencoded::parse(&req.body).collect::<HashMap<_, _>>();
+
+            if query_pairs
+                .get("token")
+                .filter(|s| *s == REFRESH_TOKEN)
+                .is_none()
+            {
+                println!("Wrong or missing token");
+                return false;
+            }
+            if query_pairs
+                .get("token_type_hint")
+                .filter(|s| *s == "refresh_token")
+                .is_none()
+            {
+                println!("Wrong or missing token type hint");
+                return false;
+            }
+            if query_pairs
+                .get("revoke_related")
+                .filter(|s| *s == "true")
+                .is_none()
+            {
+                println!("Wrong or missing revoke_related");
+                return false;
+            }
+            if query_pairs
+                .get("client_id")
+                .filter(|s| *s == CLIENT_ID)
+                .is_none()
+            {
+                println!("Wrong or missing client ID");
+                return false;
+            }
+
+            true
+        })
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    let request = RevocationRequest::new(REFRESH_TOKEN.to_owned())
+        .with_token_type_hint(OAuthTokenTypeHint::RefreshToken)
+        .with_revoke_related(true);
+
+    revoke_token(
+        &http_client,
+        client_credentials,
+        &revocation_endpoint,
+        request,
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn fail_revoke_token() {
+    let (http_client, mock_server, issuer) = init_test().await;
+    let client_credentials = client_credentials(&OAuthClientAuthenticationMethod::None, &issuer);
+    let revocation_endpoint = issuer.join("revoke").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    Mock::given(method("POST"))
+        .and(path("/revoke"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .set_body_json(ClientError::from(ClientErrorCode::UnsupportedTokenType)),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let error = revoke_token(
+        &http_client,
+        client_credentials,
+        &revocation_endpoint,
+        RevocationRequest::new(REFRESH_TOKEN.to_owned()),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap_err();
+
+    assert_matches!(error, TokenRevokeError::OAuth2(_));
+}
@@ -4,14 +4,38 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use mas_oidc_client::requests::userinfo::fetch_userinfo;
-use serde_json::json;
+use std::collections::HashMap;
+
+use assert_matches::assert_matches;
+use mas_jose::{
+    constraints::Constrainable,
+    jwt::{JsonWebSignatureHeader, Jwt},
+};
+use mas_oidc_client::{
+    error::{ClaimSourceError, UserInfoError},
+    requests::userinfo::{
+        fetch_userinfo, resolve_claim_sources, ClaimProvenance, ClaimSourcesPolicy,
+    },
+};
+use serde_json::{json, Value};
 use wiremock::{
     matchers::{header, method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::{init_test, ACCESS_TOKEN, SUBJECT_IDENTIFIER};
+use crate::{init_test, keystore, ACCESS_TOKEN, ID_TOKEN_SIGNING_ALG, SUBJECT_IDENTIFIER};
+
+/// Sign the given claims, as a claims provider would.
+fn claims_jwt(claims: Value) -> String {
+    let signing_alg = ID_TOKEN_SIGNING_ALG;
+    let keystore = keystore(&signing_alg);
+    let claims: HashMap<String, Value> = serde_json::from_value(claims).unwrap();
+
+    let key = keystore.signing_key_for_algorithm(&signing_alg).unwrap();
+    let signer = key.params().signing_key_for_alg(&signing_alg).unwrap();
+    let header = JsonWebSignatureHeader::new(signing_alg).with_kid(key.kid().unwrap());
+    Jwt::sign(header, claims, &signer).unwrap().into_string()
+}
 
 #[tokio::test]
 async fn pass_fetch_userinfo() {
@@ -37,3 +61,95 @@ async fn pass_fetch_userinfo() {
 
     assert_eq!(claims.get("email").unwrap(), "janedoe@example.com");
 }
+
+#[tokio::test]
+async fn pass_resolve_claim_sources() {
+    let (http_client, mock_server, issuer) = init_test().await;
+    let distributed_endpoint = issuer.join("claims").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/claims"))
+        .and(header("authorization", "Bearer DistributedToken"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "application/jwt")
+                .set_body_string(claims_jwt(json!({
+                    "iss": "https://bank.example.com/",
+                    "payment_info": "Some Card",
+                }))),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let claims = serde_json::from_value(json!({
+        "sub": SUBJECT_IDENTIFIER,
+        "_claim_names": {
+            "address": "src1",
+            "payment_info": "src2",
+            "credit_score": "src3",
+            "phone_number": "src1",
+        },
+        "_claim_sources": {
+            "src1": { "JWT": claims_jwt(json!({
+                "iss": "https://address.example.com/",
+                "address": { "country": "France" },
+            })) },
+            "src2": {
+                "endpoint": distributed_endpoint,
+                "access_token": "DistributedToken",
+            },
+            "src3": { "endpoint": "https://credit.example.com/claims" },
+        },
+    }))
+    .unwrap();
+
+    let policy = ClaimSourcesPolicy::FetchFromHosts(vec![issuer.host_str().unwrap().to_owned()]);
+    let resolved = resolve_claim_sources(&http_client, claims, &policy)
+        .await
+        .unwrap();
+
+    assert_eq!(resolved.claims.len(), 3);
+    assert_eq!(resolved.claims["sub"], SUBJECT_IDENTIFIER);
+    assert_eq!(resolved.claims["address"], json!({ "country": "France" }));
+    assert_eq!(resolved.claims["payment_info"], "Some Card");
+    assert_eq!(resolved.unresolved, ["credit_score", "phone_number"]);
+
+    assert_eq!(resolved.provenance["sub"], ClaimProvenance::UserInfo);
+    assert_eq!(
+        resolved.provenance["address"],
+        ClaimProvenance::Aggregated {
+            source: "src1".to_owned(),
+            issuer: Some("https://address.example.com/".to_owned()),
+        }
+    );
+    assert_eq!(
+        resolved.provenance["payment_info"],
+        ClaimProvenance::Distributed {
+            source: "src2".to_owned(),
+            endpoint: distributed_endpoint,
+            issuer: Some("https://bank.example.com/".to_owned()),
+        }
+    );
+}
+
+#[tokio::test]
+async fn fail_resolve_claim_sources_unknown_source() {
+    let (http_client, _mock_server, _issuer) = init_test().await;
+
+    let claims = serde_json::from_value(json!({
+        "sub": SUBJECT_IDENTIFIER,
+        "_claim_names": { "address": "src1" },
+        "_claim_sources": {},
+    }))
+    .unwrap();
+
+    let error = resolve_claim_sources(&http_client, claims, &ClaimSourcesPolicy::default())
+        .await
+        .unwrap_err();
+
+    assert_matches!(
+        error,
+        UserInfoError::ClaimSource(ClaimSourceError::UnknownSource { .. })
+    );
+}
@@ -0,0 +1,117 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use assert_matches::assert_matches;
+use chrono::Duration;
+use mas_iana::oauth::{OAuthAccessTokenType, OAuthClientAuthenticationMethod};
+use mas_oidc_client::{
+    error::SessionError,
+    session::{MemoryTokenStore, OidcSession, SessionTokens, TokenStore},
+};
+use oauth2_types::requests::AccessTokenResponse;
+use rand::SeedableRng;
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::{client_credentials, init_test, now, ACCESS_TOKEN, REFRESH_TOKEN};
+
+const NEW_ACCESS_TOKEN: &str = "AccessToken2";
+const NEW_REFRESH_TOKEN: &str = "RefreshToken2";
+
+#[tokio::test]
+async fn pass_access_token_not_expired() {
+    let (http_client, _mock_server, issuer) = init_test().await;
+    let client_credentials = client_credentials(&OAuthClientAuthenticationMethod::None, &issuer);
+    let token_endpoint = issuer.join("token").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let store = MemoryTokenStore::new(Some(SessionTokens {
+        access_token: ACCESS_TOKEN.to_owned(),
+        refresh_token: Some(REFRESH_TOKEN.to_owned()),
+        expires_at: Some(now() + Duration::try_hours(1).unwrap()),
+        scope: None,
+    }));
+    let session = OidcSession::load(http_client, client_credentials, token_endpoint, store)
+        .await
+        .unwrap();
+
+    // No request is mounted on the mock server, so this would fail if it tried
+    // to refresh the token
+    let access_token = session.access_token(now(), &mut rng).await.unwrap();
+    assert_eq!(access_token, ACCESS_TOKEN);
+}
+
+#[tokio::test]
+async fn pass_access_token_refresh_single_flight() {
+    let (http_client, mock_server, issuer) = init_test().await;
+    let client_credentials = client_credentials(&OAuthClientAuthenticationMethod::None, &issuer);
+    let token_endpoint = issuer.join("token").unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(body_string_contains(format!(
+            "refresh_token={REFRESH_TOKEN}"
+        )))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(AccessTokenResponse {
+                access_token: NEW_ACCESS_TOKEN.to_owned(),
+                refresh_token: Some(NEW_REFRESH_TOKEN.to_owned()),
+                id_token: None,
+                token_type: OAuthAccessTokenType::Bearer,
+                expires_in: Some(Duration::try_hours(1).unwrap()),
+                scope: None,
+            }),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    // The token expires in 10 seconds, which is within the refresh margin
+    let store = MemoryTokenStore::new(Some(SessionTokens {
+        access_token: ACCESS_TOKEN.to_owned(),
+        refresh_token: Some(REFRESH_TOKEN.to_owned()),
+        expires_at: Some(now() + Duration::try_seconds(10).unwrap()),
+        scope: None,
+    }));
+    let session = OidcSession::load(http_client, client_credentials, token_endpoint, store)
+        .await
+        .unwrap();
+
+    let mut rng1 = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+    let mut rng2 = rand_chacha::ChaCha8Rng::seed_from_u64(43);
+    let (token1, token2) = tokio::join!(
+        session.access_token(now(), &mut rng1),
+        session.access_token(now(), &mut rng2),
+    );
+    assert_eq!(token1.unwrap(), NEW_ACCESS_TOKEN);
+    assert_eq!(token2.unwrap(), NEW_ACCESS_TOKEN);
+
+    // The new tokens were persisted
+    let saved = session.store().load().await.unwrap().unwrap();
+    assert_eq!(saved.access_token, NEW_ACCESS_TOKEN);
+    assert_eq!(saved.refresh_token.as_deref(), Some(NEW_REFRESH_TOKEN));
+}
+
+#[tokio::test]
+async fn fail_access_token_no_tokens() {
+    let (http_client, _mock_server, issuer) = init_test().await;
+    let client_credentials = client_credentials(&OAuthClientAuthenticationMethod::None, &issuer);
+    let token_endpoint = issuer.join("token").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let session = OidcSession::load(
+        http_client,
+        client_credentials,
+        token_endpoint,
+        MemoryTokenStore::default(),
+    )
+    .await
+    .unwrap();
+
+    let error = session.access_token(now(), &mut rng).await.unwrap_err();
+    assert_matches!(error, SessionError::NoTokens);
+}
@@ -6,6 +6,8 @@
 
 pub mod model;
 
+use std::{collections::BTreeSet, sync::Arc};
+
 use mas_data_model::{AuthorizationGrant, Client, DeviceCodeGrant, User};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
 use opa_wasm::{
@@ -16,7 +18,7 @@ use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
 use self::model::{AuthorizationGrantInput, ClientRegistrationInput, EmailInput, RegisterInput};
-pub use self::model::{EvaluationResult, Violation};
+pub use self::model::{EvaluationResult, TimeInput, Violation};
 use crate::model::GrantType;
 
 #[derive(Debug, Error)]
@@ -74,6 +76,7 @@ pub struct PolicyFactory {
     module: Module,
     data: serde_json::Value,
     entrypoints: Entrypoints,
+    restricted_scopes: Arc<BTreeSet<String>>,
 }
 
 impl PolicyFactory {
@@ -100,11 +103,19 @@ impl PolicyFactory {
         .await?
         .map_err(LoadError::Compilation)?;
 
+        // Keep track of the restricted scopes, so that granting them can be audited
+        let restricted_scopes = data
+            .get("restricted_scopes")
+            .and_then(serde_json::Value::as_object)
+            .map(|scopes| scopes.keys().cloned().collect())
+            .unwrap_or_default();
+
         let factory = Self {
             engine,
             module,
             data,
             entrypoints,
+            restricted_scopes: Arc::new(restricted_scopes),
         };
 
         // Try to instantiate
@@ -143,6 +154,7 @@ impl PolicyFactory {
             store,
             instance,
             entrypoints: self.entrypoints.clone(),
+            restricted_scopes: self.restricted_scopes.clone(),
         })
     }
 }
@@ -151,6 +163,7 @@ pub struct Policy {
     store: Store<()>,
     instance: opa_wasm::Policy<opa_wasm::DefaultContext>,
     entrypoints: Entrypoints,
+    restricted_scopes: Arc<BTreeSet<String>>,
 }
 
 #[derive(Debug, Error)]
@@ -161,6 +174,28 @@ pub enum EvaluationError {
 }
 
 impl Policy {
+    /// Record an audit event for each restricted scope of a scope which was
+    /// granted to a client, on behalf of a user if any.
+    ///
+    /// Restricted scopes are the ones listed in the `restricted_scopes` of the
+    /// policy data.
+    pub fn audit_grant(&self, scope: &Scope, client: &Client, user: Option<&User>) {
+        for token in scope
+            .iter()
+            .filter(|token| self.restricted_scopes.contains(token.as_str()))
+        {
+            tracing::info!(
+                audit.event = "restricted_scope.granted",
+                scope = %token,
+                client.id = %client.id,
+                client.client_id = %client.client_id,
+                user.id = user.map(|user| tracing::field::display(user.id)),
+                user.username = user.map(|user| user.username.as_str()),
+                "Restricted scope granted",
+            );
+        }
+    }
+
     #[tracing::instrument(
         name = "policy.evaluate_email",
         skip_all,
@@ -268,12 +303,14 @@ impl Policy {
         authorization_grant: &AuthorizationGrant,
         client: &Client,
         user: &User,
+        time: TimeInput,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = AuthorizationGrantInput {
             user: Some(user),
             client,
             scope: &authorization_grant.scope,
             grant_type: GrantType::AuthorizationCode,
+            time,
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -301,12 +338,14 @@ impl Policy {
         &mut self,
         scope: &Scope,
         client: &Client,
+        time: TimeInput,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = AuthorizationGrantInput {
             user: None,
             client,
             scope,
             grant_type: GrantType::ClientCredentials,
+            time,
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -337,12 +376,14 @@ impl Policy {
         device_code_grant: &DeviceCodeGrant,
         client: &Client,
         user: &User,
+        time: TimeInput,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = AuthorizationGrantInput {
             user: Some(user),
             client,
             scope: &device_code_grant.scope,
             grant_type: GrantType::DeviceCode,
+            time,
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -360,8 +401,35 @@ impl Policy {
 
 #[cfg(test)]
 mod tests {
+    use chrono::{TimeZone, Utc};
+
     use super::*;
 
+    #[test]
+    fn test_time_input() {
+        // A Monday, at 05:30 UTC
+        let now = Utc.with_ymd_and_hms(2024, 12, 16, 5, 30, 0).unwrap();
+
+        let time = TimeInput::new(now, None);
+        assert_eq!(time.timezone, "UTC");
+        assert_eq!(time.weekday, "monday");
+        assert_eq!((time.hour, time.minute), (5, 30));
+
+        let time = TimeInput::new(now, Some("Europe/Paris"));
+        assert_eq!(time.timezone, "Europe/Paris");
+        assert_eq!((time.hour, time.minute), (6, 30));
+
+        // It is still Sunday evening in Los Angeles
+        let time = TimeInput::new(now, Some("America/Los_Angeles"));
+        assert_eq!(time.weekday, "sunday");
+        assert_eq!(time.date, "2024-12-15");
+        assert_eq!((time.hour, time.minute), (21, 30));
+
+        // Unknown timezones fall back to UTC
+        let time = TimeInput::new(now, Some("Mars/Olympus_Mons"));
+        assert_eq!(time.timezone, "UTC");
+    }
+
     #[tokio::test]
     async fn test_register() {
         let data = serde_json::json!({
@@ -9,6 +9,8 @@
 //! This is useful to generate JSON schemas for each input type, which can then
 //! be type-checked by Open Policy Agent.
 
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
 use mas_data_model::{Client, User};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
 use serde::{Deserialize, Serialize};
@@ -91,6 +93,52 @@ pub enum GrantType {
     DeviceCode,
 }
 
+/// The time at which a policy is evaluated, in the timezone of the user.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct TimeInput {
+    /// The current time, as given by the server clock
+    #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+    pub now: DateTime<Utc>,
+
+    /// The IANA timezone the other fields are expressed in
+    pub timezone: String,
+
+    /// The day of the week, in lowercase, like `monday`
+    pub weekday: String,
+
+    /// The hour of the day, from 0 to 23
+    pub hour: u32,
+
+    /// The minute of the hour, from 0 to 59
+    pub minute: u32,
+
+    /// The date, like `2024-12-18`
+    pub date: String,
+}
+
+impl TimeInput {
+    /// Express the given time in the given timezone, falling back to UTC if
+    /// the timezone is not set or not known
+    #[must_use]
+    pub fn new(now: DateTime<Utc>, timezone: Option<&str>) -> Self {
+        let timezone = timezone
+            .and_then(|timezone| timezone.parse::<Tz>().ok())
+            .unwrap_or(Tz::UTC);
+        let local = now.with_timezone(&timezone);
+
+        Self {
+            now,
+            timezone: timezone.name().to_owned(),
+            weekday: local.format("%A").to_string().to_lowercase(),
+            hour: local.hour(),
+            minute: local.minute(),
+            date: local.date_naive().to_string(),
+        }
+    }
+}
+
 /// Input for the authorization grant policy.
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -112,6 +160,8 @@ pub struct AuthorizationGrantInput<'a> {
     pub scope: &'a Scope,
 
     pub grant_type: GrantType,
+
+    pub time: TimeInput,
 }
 
 /// Input for the email add policy.
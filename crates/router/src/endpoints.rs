@@ -173,6 +173,38 @@ impl SimpleRoute for Healthcheck {
     const PATH: &'static str = "/health";
 }
 
+/// `GET /health/ready`
+#[derive(Default, Debug, Clone)]
+pub struct Readiness;
+
+impl SimpleRoute for Readiness {
+    const PATH: &'static str = "/health/ready";
+}
+
+/// `GET /health/upstreams`
+#[derive(Default, Debug, Clone)]
+pub struct UpstreamHealthcheck;
+
+impl SimpleRoute for UpstreamHealthcheck {
+    const PATH: &'static str = "/health/upstreams";
+}
+
+/// `POST /email/webhooks/ses`
+#[derive(Default, Debug, Clone)]
+pub struct EmailWebhookSes;
+
+impl SimpleRoute for EmailWebhookSes {
+    const PATH: &'static str = "/email/webhooks/ses";
+}
+
+/// `POST /email/webhooks/sendgrid`
+#[derive(Default, Debug, Clone)]
+pub struct EmailWebhookSendgrid;
+
+impl SimpleRoute for EmailWebhookSendgrid {
+    const PATH: &'static str = "/email/webhooks/sendgrid";
+}
+
 /// `GET|POST /login`
 #[derive(Default, Debug, Clone)]
 pub struct Login {
@@ -247,6 +279,54 @@ impl From<Option<PostAuthAction>> for Login {
     }
 }
 
+/// `POST /login/certificate`
+#[derive(Default, Debug, Clone)]
+pub struct CertificateLogin {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl Route for CertificateLogin {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/login/certificate"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for CertificateLogin {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
+/// `POST /login/matrix-id`
+#[derive(Default, Debug, Clone)]
+pub struct MatrixIdLogin {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl Route for MatrixIdLogin {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/login/matrix-id"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for MatrixIdLogin {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
 /// `POST /logout`
 #[derive(Default, Debug, Clone)]
 pub struct Logout;
@@ -315,6 +395,63 @@ impl From<Option<PostAuthAction>> for Reauth {
     }
 }
 
+/// `GET|POST /change-password`
+///
+/// Where users are sent when they must change their password before their
+/// login completes
+#[derive(Default, Debug, Clone)]
+pub struct PasswordChangeRequired {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl PasswordChangeRequired {
+    #[must_use]
+    pub const fn and_then(action: PostAuthAction) -> Self {
+        Self {
+            post_auth_action: Some(action),
+        }
+    }
+
+    #[must_use]
+    pub const fn and_continue_grant(id: Ulid) -> Self {
+        Self {
+            post_auth_action: Some(PostAuthAction::continue_grant(id)),
+        }
+    }
+
+    #[must_use]
+    pub const fn and_continue_device_code_grant(id: Ulid) -> Self {
+        Self {
+            post_auth_action: Some(PostAuthAction::continue_device_code_grant(id)),
+        }
+    }
+
+    #[must_use]
+    pub const fn and_continue_compat_sso_login(id: Ulid) -> Self {
+        Self {
+            post_auth_action: Some(PostAuthAction::continue_compat_sso_login(id)),
+        }
+    }
+}
+
+impl Route for PasswordChangeRequired {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/change-password"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for PasswordChangeRequired {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
 /// `GET|POST /register`
 #[derive(Default, Debug, Clone)]
 pub struct Register {
@@ -419,6 +556,44 @@ impl Route for AccountVerifyEmail {
     }
 }
 
+/// The code in the link sent in the verification emails
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailVerificationCode {
+    pub code: String,
+}
+
+/// `GET /verify-email/:id/link?code=:code`
+#[derive(Debug, Clone)]
+pub struct AccountVerifyEmailLink {
+    id: Ulid,
+    code: EmailVerificationCode,
+}
+
+impl AccountVerifyEmailLink {
+    #[must_use]
+    pub fn new(id: Ulid, code: String) -> Self {
+        Self {
+            id,
+            code: EmailVerificationCode { code },
+        }
+    }
+}
+
+impl Route for AccountVerifyEmailLink {
+    type Query = EmailVerificationCode;
+    fn route() -> &'static str {
+        "/verify-email/:id/link"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        Some(&self.code)
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/verify-email/{}/link", self.id).into()
+    }
+}
+
 /// `GET /add-email`
 #[derive(Default, Debug, Clone)]
 pub struct AccountAddEmail {
@@ -736,6 +911,33 @@ impl Route for DeviceCodeLink {
     }
 }
 
+/// `GET /link/qr.svg`
+///
+/// A QR code of the link to the device code page, with the code pre-filled if
+/// one is given.
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceCodeLinkQr {
+    code: Option<String>,
+}
+
+impl DeviceCodeLinkQr {
+    #[must_use]
+    pub fn with_code(code: String) -> Self {
+        Self { code: Some(code) }
+    }
+}
+
+impl Route for DeviceCodeLinkQr {
+    type Query = DeviceCodeLinkQr;
+    fn route() -> &'static str {
+        "/link/qr.svg"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        Some(self)
+    }
+}
+
 /// `GET|POST /device/:device_code_id`
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct DeviceCodeConsent {
@@ -768,6 +970,30 @@ impl SimpleRoute for OAuth2DeviceAuthorizationEndpoint {
     const PATH: &'static str = "/oauth2/device";
 }
 
+/// `GET /errors/:code`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct OAuth2ErrorDocumentation {
+    code: String,
+}
+
+impl OAuth2ErrorDocumentation {
+    #[must_use]
+    pub fn new(code: String) -> Self {
+        Self { code }
+    }
+}
+
+impl Route for OAuth2ErrorDocumentation {
+    type Query = ();
+    fn route() -> &'static str {
+        "/errors/:code"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/errors/{}", self.code).into()
+    }
+}
+
 /// `GET|POST /recover`
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct AccountRecoveryStart;
@@ -863,6 +1089,38 @@ impl SimpleRoute for GraphQLPlayground {
     const PATH: &'static str = "/graphql/playground";
 }
 
+/// `GET /api/forms/v1`
+#[derive(Default, Debug, Clone)]
+pub struct AuthForms;
+
+impl SimpleRoute for AuthForms {
+    const PATH: &'static str = "/api/forms/v1";
+}
+
+/// `POST /api/register/v1`
+#[derive(Default, Debug, Clone)]
+pub struct RegistrationApi;
+
+impl SimpleRoute for RegistrationApi {
+    const PATH: &'static str = "/api/register/v1";
+}
+
+/// `POST /api/register/v1/resend-email`
+#[derive(Default, Debug, Clone)]
+pub struct RegistrationApiResendEmail;
+
+impl SimpleRoute for RegistrationApiResendEmail {
+    const PATH: &'static str = "/api/register/v1/resend-email";
+}
+
+/// `POST /api/register/v1/verify-email`
+#[derive(Default, Debug, Clone)]
+pub struct RegistrationApiVerifyEmail;
+
+impl SimpleRoute for RegistrationApiVerifyEmail {
+    const PATH: &'static str = "/api/register/v1/verify-email";
+}
+
 /// `GET /api/spec.json`
 pub struct ApiSpec;
 
@@ -17,6 +17,7 @@ pub struct UrlBuilder {
     prefix: String,
     assets_base: String,
     issuer: Url,
+    error_documentation_base: Option<Url>,
 }
 
 impl UrlBuilder {
@@ -103,9 +104,18 @@ impl UrlBuilder {
             prefix,
             assets_base,
             issuer,
+            error_documentation_base: None,
         }
     }
 
+    /// Use documentation pages of the OAuth 2.0 errors hosted somewhere else
+    /// than on this service
+    #[must_use]
+    pub fn with_error_documentation_base(mut self, base: Option<Url>) -> Self {
+        self.error_documentation_base = base;
+        self
+    }
+
     /// Site public hostname
     ///
     /// # Panics
@@ -172,6 +182,15 @@ impl UrlBuilder {
         self.absolute_url_for(&crate::endpoints::OAuth2DeviceAuthorizationEndpoint)
     }
 
+    /// Base URL of the documentation pages of the OAuth 2.0 errors, to be
+    /// joined with an error code
+    #[must_use]
+    pub fn oauth_error_documentation_base(&self) -> Url {
+        self.error_documentation_base.clone().unwrap_or_else(|| {
+            self.absolute_url_for(&crate::endpoints::OAuth2ErrorDocumentation::default())
+        })
+    }
+
     /// OAuth 2.0 device code link
     #[must_use]
     pub fn device_code_link(&self) -> Url {
@@ -237,6 +256,15 @@ impl UrlBuilder {
     pub fn account_recovery_link(&self, ticket: String) -> Url {
         self.absolute_url_for(&crate::endpoints::AccountRecoveryFinish::new(ticket))
     }
+
+    /// Email verification link
+    #[must_use]
+    pub fn email_verification_link(&self, user_email_id: Ulid, code: String) -> Url {
+        self.absolute_url_for(&crate::endpoints::AccountVerifyEmailLink::new(
+            user_email_id,
+            code,
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -302,4 +330,25 @@ mod tests {
         let uri = builder.absolute_url_for(&crate::endpoints::OAuth2AuthorizationEndpoint);
         assert_eq!(uri.as_str(), "https://example.com/foo/authorize");
     }
+
+    #[test]
+    fn test_error_documentation_base() {
+        let builder = super::UrlBuilder::new(
+            url::Url::parse("https://example.com/foo/").unwrap(),
+            None,
+            None,
+        );
+        assert_eq!(
+            builder.oauth_error_documentation_base().as_str(),
+            "https://example.com/foo/errors/"
+        );
+
+        let builder = builder.with_error_documentation_base(Some(
+            url::Url::parse("https://docs.example.com/errors/").unwrap(),
+        ));
+        assert_eq!(
+            builder.oauth_error_documentation_base().as_str(),
+            "https://docs.example.com/errors/"
+        );
+    }
 }
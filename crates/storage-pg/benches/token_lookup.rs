@@ -0,0 +1,218 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Benchmark of the lookup of OAuth 2.0 access tokens by their value, which
+//! happens on every request authenticated with a token.
+//!
+//! It needs a scratch PostgreSQL database, given by the `DATABASE_URL`
+//! environment variable, on which the migrations are run and which is filled
+//! with tokens:
+//!
+//! ```sh
+//! DATABASE_URL=postgresql://localhost/mas_bench cargo bench -p mas-storage-pg
+//! ```
+
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mas_storage::{clock::SystemClock, Clock, RepositoryAccess};
+use mas_storage_pg::{PgRepository, TokenHasher, MIGRATOR};
+use oauth2_types::{
+    requests::GrantType,
+    scope::{Scope, OPENID},
+};
+use rand::{distributions::Alphanumeric, Rng, SeedableRng};
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+/// How many tokens of each kind are saved before running the benchmark
+const TOKENS: usize = 10_000;
+
+/// The secret from which the key of the token hashes is derived
+const TOKEN_HASH_SECRET: &[u8] = b"mas-bench-secret";
+
+/// The tokens to look up
+struct Tokens {
+    /// A token saved as a hash
+    hashed: String,
+
+    /// A token saved before the hashes were introduced, and not backfilled yet
+    legacy: String,
+}
+
+async fn setup(pool: &PgPool) -> Tokens {
+    let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+    let clock = SystemClock::default();
+    MIGRATOR.run(pool).await.unwrap();
+
+    let mut repo = PgRepository::from_pool(pool)
+        .await
+        .unwrap()
+        .with_token_hasher(TokenHasher::new(TOKEN_HASH_SECRET))
+        .boxed();
+    let client = repo
+        .oauth2_client()
+        .add(
+            &mut rng,
+            &clock,
+            Vec::new(),
+            None,
+            None,
+            vec![GrantType::ClientCredentials],
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let session = repo
+        .oauth2_session()
+        .add_from_client_credentials(&mut rng, &clock, &client, Scope::from_iter([OPENID]))
+        .await
+        .unwrap();
+
+    let mut tokens = Vec::with_capacity(TOKENS);
+    for _ in 0..TOKENS {
+        let value: String = (&mut rng)
+            .sample_iter(&Alphanumeric)
+            .take(30)
+            .map(char::from)
+            .collect();
+        let token = repo
+            .oauth2_access_token()
+            .add(&mut rng, &clock, &session, value, None)
+            .await
+            .unwrap();
+        tokens.push(token.access_token);
+    }
+    repo.save().await.unwrap();
+
+    let mut legacy = Vec::with_capacity(TOKENS);
+    for _ in 0..TOKENS {
+        let value: String = (&mut rng)
+            .sample_iter(&Alphanumeric)
+            .take(30)
+            .map(char::from)
+            .collect();
+        let id = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+        legacy.push((Uuid::from(id), value));
+    }
+    let (ids, values): (Vec<Uuid>, Vec<String>) = legacy.into_iter().unzip();
+    sqlx::query!(
+        r#"
+            INSERT INTO oauth2_access_tokens
+                (oauth2_access_token_id, oauth2_session_id, access_token, created_at)
+            SELECT id, $2, access_token, $3
+            FROM UNNEST($1::uuid[], $4::text[]) AS t(id, access_token)
+        "#,
+        &ids,
+        Uuid::from(session.id),
+        clock.now(),
+        &values,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    sqlx::query!("ANALYZE oauth2_access_tokens")
+        .execute(pool)
+        .await
+        .unwrap();
+
+    Tokens {
+        hashed: tokens.swap_remove(TOKENS / 2),
+        legacy: values[TOKENS / 2].clone(),
+    }
+}
+
+/// Run `iters` lookups of the token in a single repository, and measure how
+/// long they took
+async fn find_by_token(pool: PgPool, token: String, iters: u64) -> Duration {
+    let mut repo = PgRepository::from_pool(&pool)
+        .await
+        .unwrap()
+        .with_token_hasher(TokenHasher::new(TOKEN_HASH_SECRET))
+        .boxed();
+
+    let start = Instant::now();
+    for _ in 0..iters {
+        let found = repo
+            .oauth2_access_token()
+            .find_by_token(&token)
+            .await
+            .unwrap();
+        assert!(black_box(found).is_some());
+    }
+    let elapsed = start.elapsed();
+
+    repo.cancel().await.unwrap();
+    elapsed
+}
+
+async fn find_for_introspection(pool: PgPool, token: String, iters: u64) -> Duration {
+    let mut repo = PgRepository::from_pool(&pool)
+        .await
+        .unwrap()
+        .with_token_hasher(TokenHasher::new(TOKEN_HASH_SECRET))
+        .boxed();
+
+    let start = Instant::now();
+    for _ in 0..iters {
+        let found = repo
+            .oauth2_access_token()
+            .find_for_introspection(&token)
+            .await
+            .unwrap();
+        assert!(black_box(found).is_some());
+    }
+    let elapsed = start.elapsed();
+
+    repo.cancel().await.unwrap();
+    elapsed
+}
+
+fn token_lookup(c: &mut Criterion) {
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set, skipping the token lookup benchmark");
+        return;
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let pool = runtime.block_on(PgPool::connect(&url)).unwrap();
+    let tokens = runtime.block_on(setup(&pool));
+
+    let mut group = c.benchmark_group("oauth2_access_token");
+
+    group.bench_function("find_by_token/hashed", |b| {
+        b.to_async(&runtime)
+            .iter_custom(|iters| find_by_token(pool.clone(), tokens.hashed.clone(), iters));
+    });
+
+    group.bench_function("find_by_token/legacy", |b| {
+        b.to_async(&runtime)
+            .iter_custom(|iters| find_by_token(pool.clone(), tokens.legacy.clone(), iters));
+    });
+
+    group.bench_function("find_for_introspection/hashed", |b| {
+        b.to_async(&runtime).iter_custom(|iters| {
+            find_for_introspection(pool.clone(), tokens.hashed.clone(), iters)
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, token_lookup);
+criterion_main!(benches);
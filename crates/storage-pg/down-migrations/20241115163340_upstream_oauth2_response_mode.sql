@@ -0,0 +1,7 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+ALTER TABLE "upstream_oauth_providers"
+  DROP COLUMN "response_mode";
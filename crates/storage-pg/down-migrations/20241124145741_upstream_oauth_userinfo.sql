@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+ALTER TABLE "upstream_oauth_authorization_sessions"
+  DROP COLUMN "userinfo";
+
+ALTER TABLE "upstream_oauth_providers"
+  DROP COLUMN "userinfo_endpoint_override",
+  DROP COLUMN "fetch_userinfo";
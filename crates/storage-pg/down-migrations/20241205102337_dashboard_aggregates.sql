@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+DROP MATERIALIZED VIEW "dashboard_active_sessions";
+DROP MATERIALIZED VIEW "dashboard_access_tokens_per_day";
+DROP MATERIALIZED VIEW "dashboard_logins_per_day";
+DROP MATERIALIZED VIEW "dashboard_registrations_per_day";
+DROP TABLE "user_login_failures_per_day";
@@ -0,0 +1,6 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+DROP TABLE "upstream_oauth_provider_health";
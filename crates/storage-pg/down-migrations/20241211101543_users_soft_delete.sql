@@ -0,0 +1,19 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Users which were soft-deleted would become active again, so this refuses to
+-- revert the migration until they are purged
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM "users" WHERE "deleted_at" IS NOT NULL) THEN
+    RAISE EXCEPTION 'Some users are soft-deleted, purge them before reverting';
+  END IF;
+END
+$$;
+
+DROP INDEX "users_deleted_at_idx";
+
+ALTER TABLE "users"
+  DROP COLUMN "deleted_at";
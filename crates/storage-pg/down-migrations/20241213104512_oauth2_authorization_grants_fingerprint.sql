@@ -0,0 +1,8 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+ALTER TABLE "oauth2_authorization_grants"
+  DROP COLUMN "fingerprint_user_agent_hash",
+  DROP COLUMN "fingerprint_ip_network";
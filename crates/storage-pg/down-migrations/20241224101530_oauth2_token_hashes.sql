@@ -0,0 +1,22 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The value of the tokens which were only saved as a hash can't be recovered,
+-- so they are removed. The clients holding them will have to log in again.
+DELETE FROM "oauth2_refresh_tokens"
+  WHERE "refresh_token" IS NULL;
+
+DELETE FROM "oauth2_access_tokens"
+  WHERE "access_token" IS NULL;
+
+DROP INDEX "oauth2_refresh_tokens_refresh_token_hash_idx";
+ALTER TABLE "oauth2_refresh_tokens"
+  DROP COLUMN "refresh_token_hash",
+  ALTER COLUMN "refresh_token" SET NOT NULL;
+
+DROP INDEX "oauth2_access_tokens_access_token_hash_idx";
+ALTER TABLE "oauth2_access_tokens"
+  DROP COLUMN "access_token_hash",
+  ALTER COLUMN "access_token" SET NOT NULL;
@@ -0,0 +1,84 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Copy the rows of the partitioned tables back into regular tables
+DROP MATERIALIZED VIEW "dashboard_access_tokens_per_day";
+
+ALTER TABLE "oauth2_refresh_tokens"
+  DROP CONSTRAINT "oauth2_refresh_tokens_oauth2_access_token_id_fkey";
+DROP INDEX "oauth2_refresh_tokens_oauth2_access_token_id_idx";
+
+CREATE TABLE "oauth2_access_tokens_unpartitioned" (
+  LIKE "oauth2_access_tokens" INCLUDING DEFAULTS
+);
+INSERT INTO "oauth2_access_tokens_unpartitioned"
+  SELECT * FROM "oauth2_access_tokens";
+DROP TABLE "oauth2_access_tokens";
+DROP FUNCTION "oauth2_access_tokens_check_unique"();
+ALTER TABLE "oauth2_access_tokens_unpartitioned"
+  RENAME TO "oauth2_access_tokens";
+
+ALTER TABLE "oauth2_access_tokens"
+  ADD CONSTRAINT "oauth2_access_tokens_pkey"
+    PRIMARY KEY ("oauth2_access_token_id"),
+  ADD CONSTRAINT "oauth2_access_tokens_oauth2_session_id_fkey"
+    FOREIGN KEY ("oauth2_session_id")
+    REFERENCES "oauth2_sessions" ("oauth2_session_id"),
+  ADD CONSTRAINT "oauth2_access_tokens_unique"
+    UNIQUE ("access_token");
+
+CREATE UNIQUE INDEX "oauth2_access_tokens_access_token_hash_idx"
+  ON "oauth2_access_tokens" ("access_token_hash")
+  INCLUDE ("oauth2_access_token_id", "oauth2_session_id", "created_at", "expires_at", "revoked_at");
+
+-- The refresh tokens don't point to access tokens which were dropped, but
+-- check anyway before adding back the constraint on the regular table
+UPDATE "oauth2_refresh_tokens" r
+  SET "oauth2_access_token_id" = NULL
+  WHERE NOT EXISTS (
+    SELECT 1 FROM "oauth2_access_tokens" t
+    WHERE t."oauth2_access_token_id" = r."oauth2_access_token_id"
+  );
+
+ALTER TABLE "oauth2_refresh_tokens"
+  ADD CONSTRAINT "oauth2_refresh_tokens_oauth2_access_token_id_fkey"
+    FOREIGN KEY ("oauth2_access_token_id")
+    REFERENCES "oauth2_access_tokens" ("oauth2_access_token_id")
+    ON DELETE SET NULL;
+
+CREATE TABLE "oauth2_authorization_grants_unpartitioned" (
+  LIKE "oauth2_authorization_grants" INCLUDING DEFAULTS
+);
+INSERT INTO "oauth2_authorization_grants_unpartitioned"
+  SELECT * FROM "oauth2_authorization_grants";
+DROP TABLE "oauth2_authorization_grants";
+DROP FUNCTION "oauth2_authorization_grants_check_unique"();
+ALTER TABLE "oauth2_authorization_grants_unpartitioned"
+  RENAME TO "oauth2_authorization_grants";
+
+ALTER TABLE "oauth2_authorization_grants"
+  ADD CONSTRAINT "oauth2_authorization_grants_pkey"
+    PRIMARY KEY ("oauth2_authorization_grant_id"),
+  ADD CONSTRAINT "tbl_oauth2_client_fkey"
+    FOREIGN KEY ("oauth2_client_id")
+    REFERENCES "oauth2_clients" ("oauth2_client_id"),
+  ADD CONSTRAINT "tbl_oauth2_session_fkey"
+    FOREIGN KEY ("oauth2_session_id")
+    REFERENCES "oauth2_sessions" ("oauth2_session_id"),
+  ADD CONSTRAINT "oauth2_authorization_grants_authorization_code_unique"
+    UNIQUE ("authorization_code");
+
+CREATE MATERIALIZED VIEW "dashboard_access_tokens_per_day" AS
+  SELECT
+    (date_trunc('day', t."created_at" AT TIME ZONE 'UTC'))::DATE AS "day",
+    s."oauth2_client_id",
+    COUNT(*) AS "count"
+  FROM "oauth2_access_tokens" t
+  INNER JOIN "oauth2_sessions" s USING ("oauth2_session_id")
+  WHERE t."created_at" >= now() - INTERVAL '90 days'
+  GROUP BY 1, 2;
+
+CREATE UNIQUE INDEX "dashboard_access_tokens_per_day_day_client_idx"
+  ON "dashboard_access_tokens_per_day" ("day", "oauth2_client_id");
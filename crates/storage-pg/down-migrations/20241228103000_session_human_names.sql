@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+ALTER TABLE "oauth2_sessions"
+  DROP COLUMN "human_name";
+
+ALTER TABLE "compat_sessions"
+  DROP COLUMN "human_name";
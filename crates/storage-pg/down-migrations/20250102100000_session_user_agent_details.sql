@@ -0,0 +1,13 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+ALTER TABLE "oauth2_sessions"
+  DROP COLUMN "user_agent_details";
+
+ALTER TABLE "compat_sessions"
+  DROP COLUMN "user_agent_details";
+
+ALTER TABLE "user_sessions"
+  DROP COLUMN "user_agent_details";
@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+DELETE FROM "user_session_elevations";
+
+ALTER TABLE "user_session_elevations"
+  DROP COLUMN "token_hash",
+  ADD COLUMN "token" TEXT NOT NULL
+    UNIQUE;
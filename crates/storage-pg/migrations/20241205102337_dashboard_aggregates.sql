@@ -0,0 +1,76 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Aggregates backing the admin dashboard. Those are materialized views which
+-- are periodically refreshed by a background job, so that the dashboard
+-- doesn't have to scan the whole tables on each request. They only cover the
+-- last 90 days.
+
+-- Count of failed password logins per day. Failures aren't recorded
+-- anywhere else, so this is a plain table incremented on each failure.
+CREATE TABLE "user_login_failures_per_day" (
+  "day" DATE NOT NULL
+    CONSTRAINT "user_login_failures_per_day_pkey"
+    PRIMARY KEY,
+
+  "count" BIGINT NOT NULL
+);
+
+-- Number of users registered per day
+CREATE MATERIALIZED VIEW "dashboard_registrations_per_day" AS
+  SELECT
+    (date_trunc('day', "created_at" AT TIME ZONE 'UTC'))::DATE AS "day",
+    COUNT(*) AS "count"
+  FROM "users"
+  WHERE "created_at" >= now() - INTERVAL '90 days'
+  GROUP BY 1;
+
+CREATE UNIQUE INDEX "dashboard_registrations_per_day_day_idx"
+  ON "dashboard_registrations_per_day" ("day");
+
+-- Number of browser logins per day, by authentication method
+CREATE MATERIALIZED VIEW "dashboard_logins_per_day" AS
+  SELECT
+    (date_trunc('day', "created_at" AT TIME ZONE 'UTC'))::DATE AS "day",
+    CASE
+      WHEN "user_password_id" IS NOT NULL THEN 'password'
+      WHEN "upstream_oauth_authorization_session_id" IS NOT NULL THEN 'upstream_oauth'
+      ELSE 'other'
+    END AS "method",
+    COUNT(*) AS "count"
+  FROM "user_session_authentications"
+  WHERE "created_at" >= now() - INTERVAL '90 days'
+  GROUP BY 1, 2;
+
+CREATE UNIQUE INDEX "dashboard_logins_per_day_day_method_idx"
+  ON "dashboard_logins_per_day" ("day", "method");
+
+-- Number of OAuth 2.0 access tokens issued per day, by client
+CREATE MATERIALIZED VIEW "dashboard_access_tokens_per_day" AS
+  SELECT
+    (date_trunc('day', t."created_at" AT TIME ZONE 'UTC'))::DATE AS "day",
+    s."oauth2_client_id",
+    COUNT(*) AS "count"
+  FROM "oauth2_access_tokens" t
+  INNER JOIN "oauth2_sessions" s USING ("oauth2_session_id")
+  WHERE t."created_at" >= now() - INTERVAL '90 days'
+  GROUP BY 1, 2;
+
+CREATE UNIQUE INDEX "dashboard_access_tokens_per_day_day_client_idx"
+  ON "dashboard_access_tokens_per_day" ("day", "oauth2_client_id");
+
+-- Number of sessions which are currently active, by session type
+CREATE MATERIALIZED VIEW "dashboard_active_sessions" AS
+  SELECT 'browser' AS "session_type", COUNT(*) AS "count"
+    FROM "user_sessions" WHERE "finished_at" IS NULL
+  UNION ALL
+  SELECT 'oauth2' AS "session_type", COUNT(*) AS "count"
+    FROM "oauth2_sessions" WHERE "finished_at" IS NULL
+  UNION ALL
+  SELECT 'compat' AS "session_type", COUNT(*) AS "count"
+    FROM "compat_sessions" WHERE "finished_at" IS NULL;
+
+CREATE UNIQUE INDEX "dashboard_active_sessions_session_type_idx"
+  ON "dashboard_active_sessions" ("session_type");
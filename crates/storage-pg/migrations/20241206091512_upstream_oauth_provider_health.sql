@@ -0,0 +1,26 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Result of the periodic health checks of upstream OAuth 2.0 providers
+CREATE TABLE "upstream_oauth_provider_health" (
+  "upstream_oauth_provider_id" UUID NOT NULL
+    CONSTRAINT "upstream_oauth_provider_health_pkey"
+    PRIMARY KEY
+    CONSTRAINT "upstream_oauth_provider_health_provider_fkey"
+    REFERENCES "upstream_oauth_providers" ("upstream_oauth_provider_id")
+    ON DELETE CASCADE,
+
+  -- When the last check happened
+  "checked_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the last successful check happened
+  "last_success_at" TIMESTAMP WITH TIME ZONE,
+
+  -- How many checks failed since the last successful one
+  "consecutive_failures" INTEGER NOT NULL DEFAULT 0,
+
+  -- The error of the last check, if it failed
+  "last_error" TEXT
+);
@@ -0,0 +1,49 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Outgoing emails, sent by a background job with retries. Emails which failed
+-- too many times have `failed_at` set, and form the dead-letter list.
+CREATE TABLE "queued_emails" (
+  "queued_email_id" UUID NOT NULL
+    CONSTRAINT "queued_emails_pkey"
+    PRIMARY KEY,
+
+  "recipient" TEXT NOT NULL,
+
+  -- The fully formatted message, headers included
+  "message" BYTEA NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "attempts" INTEGER NOT NULL DEFAULT 0,
+  "next_attempt_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "last_error" TEXT,
+
+  "sent_at" TIMESTAMP WITH TIME ZONE,
+  "failed_at" TIMESTAMP WITH TIME ZONE
+);
+
+-- Used to find the next emails to send
+CREATE INDEX "queued_emails_pending_next_attempt_at_idx"
+  ON "queued_emails" ("next_attempt_at")
+  WHERE "sent_at" IS NULL AND "failed_at" IS NULL;
+
+-- Used to list the dead-letter list
+CREATE INDEX "queued_emails_failed_idx"
+  ON "queued_emails" ("queued_email_id")
+  WHERE "failed_at" IS NOT NULL;
+
+-- Email addresses reported as undeliverable by the mail provider, either
+-- because of a permanent bounce or a spam complaint
+CREATE TABLE "undeliverable_email_addresses" (
+  -- Lowercased email address
+  "email" TEXT NOT NULL
+    CONSTRAINT "undeliverable_email_addresses_pkey"
+    PRIMARY KEY,
+
+  "reason" TEXT NOT NULL,
+  "reported_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "details" TEXT
+);
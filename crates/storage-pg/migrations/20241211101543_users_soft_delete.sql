@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Users are first soft-deleted by setting this timestamp, and then purged by a
+-- background job once the retention period is over
+ALTER TABLE "users"
+  ADD COLUMN "deleted_at" TIMESTAMP WITH TIME ZONE;
+
+-- Used by the purge job to find the users to purge
+CREATE INDEX "users_deleted_at_idx"
+  ON "users" ("deleted_at")
+  WHERE "deleted_at" IS NOT NULL;
@@ -0,0 +1,38 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Public keys registered by clients when logging in, bound to either an OAuth
+-- 2.0 session or a compatibility session. Refreshing the tokens of a session
+-- with a device key requires a proof signed by the matching private key.
+CREATE TABLE "device_keys" (
+  "device_key_id" UUID NOT NULL
+    CONSTRAINT "device_keys_pkey"
+    PRIMARY KEY,
+
+  "oauth2_session_id" UUID
+    CONSTRAINT "device_keys_oauth2_session_id_fkey"
+    REFERENCES "oauth2_sessions" ("oauth2_session_id")
+    ON DELETE CASCADE,
+
+  "compat_session_id" UUID
+    CONSTRAINT "device_keys_compat_session_id_fkey"
+    REFERENCES "compat_sessions" ("compat_session_id")
+    ON DELETE CASCADE,
+
+  -- The public key, as a JWK
+  "public_key" JSONB NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "device_keys_session_check"
+    CHECK (num_nonnulls("oauth2_session_id", "compat_session_id") = 1)
+);
+
+-- A session has at most one device key
+CREATE UNIQUE INDEX "device_keys_oauth2_session_id_idx"
+  ON "device_keys" ("oauth2_session_id");
+
+CREATE UNIQUE INDEX "device_keys_compat_session_id_idx"
+  ON "device_keys" ("compat_session_id");
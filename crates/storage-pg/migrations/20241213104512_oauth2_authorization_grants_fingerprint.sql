@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- A coarse fingerprint of the context in which an authorization grant was
+-- requested, used to reject codes redeemed from a very different context
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "fingerprint_ip_network" TEXT,
+  ADD COLUMN "fingerprint_user_agent_hash" TEXT;
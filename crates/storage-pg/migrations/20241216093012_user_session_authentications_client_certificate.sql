@@ -0,0 +1,9 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record the SHA-256 fingerprint of the client certificate used to
+-- authenticate a user_session, when logging in with a X.509 certificate
+ALTER TABLE "user_session_authentications"
+  ADD COLUMN "client_certificate_fingerprint" TEXT;
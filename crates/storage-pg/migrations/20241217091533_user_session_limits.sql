@@ -0,0 +1,17 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Per-user overrides of the maximum number of simultaneous sessions, set by
+-- admins
+CREATE TABLE "user_session_limits" (
+  "user_id" UUID NOT NULL
+    PRIMARY KEY
+    REFERENCES "users" ("user_id") ON DELETE CASCADE,
+
+  "max_sessions" INTEGER NOT NULL
+    CHECK ("max_sessions" > 0),
+
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,15 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The IANA timezone of users, used to evaluate time-based policies
+CREATE TABLE "user_timezones" (
+  "user_id" UUID NOT NULL
+    PRIMARY KEY
+    REFERENCES "users" ("user_id") ON DELETE CASCADE,
+
+  "timezone" TEXT NOT NULL,
+
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
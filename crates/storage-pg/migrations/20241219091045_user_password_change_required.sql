@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Users which must change their password on their next login, for example
+-- after an admin set a temporary password for them
+CREATE TABLE "user_password_change_required" (
+  "user_id" UUID NOT NULL
+    PRIMARY KEY
+    REFERENCES "users" ("user_id") ON DELETE CASCADE,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
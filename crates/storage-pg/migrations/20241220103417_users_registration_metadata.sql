@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Where the registration of a user came from: the client they registered for,
+-- the upstream provider they registered with, and the invite token and
+-- referral parameter of the registration link
+ALTER TABLE "users"
+  ADD COLUMN "registration_metadata" JSONB;
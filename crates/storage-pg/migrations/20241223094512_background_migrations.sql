@@ -0,0 +1,24 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Progress of the backfills running in the background, between the migration
+-- adding a new schema and the one removing the old one
+CREATE TABLE "background_migrations" (
+  "name" TEXT NOT NULL
+    CONSTRAINT "background_migrations_pkey"
+    PRIMARY KEY,
+
+  -- The key of the last row processed
+  "cursor" UUID,
+
+  "processed_rows" BIGINT NOT NULL DEFAULT 0,
+
+  -- The estimated number of rows in the table when the backfill started
+  "estimated_rows" BIGINT NOT NULL,
+
+  "started_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "completed_at" TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,32 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Store a keyed hash of the OAuth 2.0 tokens instead of their value.
+--
+-- The hash is an HMAC-SHA256 computed by the application, with a key derived
+-- from the `secrets.encryption` configuration, so that it can't be used to
+-- check guessed tokens without the key.
+--
+-- New tokens are only saved as a hash. The existing tokens are hashed by the
+-- `oauth2_access_tokens_hash` and `oauth2_refresh_tokens_hash` background
+-- backfills, which also clear their value. The value columns will be dropped
+-- once the backfills had time to complete.
+ALTER TABLE "oauth2_access_tokens"
+  ADD COLUMN "access_token_hash" BYTEA,
+  ALTER COLUMN "access_token" DROP NOT NULL;
+
+-- This covers the columns needed to load an access token by its hash, so that
+-- lookups can be answered from the index alone
+CREATE UNIQUE INDEX "oauth2_access_tokens_access_token_hash_idx"
+  ON "oauth2_access_tokens" ("access_token_hash")
+  INCLUDE ("oauth2_access_token_id", "oauth2_session_id", "created_at", "expires_at", "revoked_at");
+
+ALTER TABLE "oauth2_refresh_tokens"
+  ADD COLUMN "refresh_token_hash" BYTEA,
+  ALTER COLUMN "refresh_token" DROP NOT NULL;
+
+CREATE UNIQUE INDEX "oauth2_refresh_tokens_refresh_token_hash_idx"
+  ON "oauth2_refresh_tokens" ("refresh_token_hash")
+  INCLUDE ("oauth2_refresh_token_id", "oauth2_session_id", "oauth2_access_token_id", "created_at", "consumed_at");
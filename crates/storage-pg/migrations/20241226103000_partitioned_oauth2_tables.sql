@@ -0,0 +1,211 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Partition the OAuth 2.0 access tokens and authorization grants by day, so
+-- that they are cleaned up by dropping whole partitions instead of deleting
+-- rows one by one.
+--
+-- They are partitioned on their ULID primary key, which starts with their
+-- creation time, so that the primary key stays the same. The existing rows are
+-- kept in a `_legacy` partition, which covers everything until the day after
+-- tomorrow (UTC). The partitions for the following days are created ahead of
+-- time by the `maintain-partitions` job, which also drops the old ones. Rows
+-- which don't fit in any of them, if the job didn't run in time, go to a
+-- `_default` partition.
+
+-- This depends on the access tokens table, and is created again below
+DROP MATERIALIZED VIEW "dashboard_access_tokens_per_day";
+
+-- The foreign key from the refresh tokens is added back below, on the
+-- partitioned table
+ALTER TABLE "oauth2_refresh_tokens"
+  DROP CONSTRAINT "oauth2_refresh_tokens_oauth2_access_token_id_fkey";
+
+ALTER TABLE "oauth2_access_tokens"
+  RENAME TO "oauth2_access_tokens_legacy";
+ALTER INDEX "oauth2_access_tokens_pkey"
+  RENAME TO "oauth2_access_tokens_legacy_pkey";
+
+-- The unique indexes can't be kept on the partitioned table, as they don't
+-- include the partition key. They are replaced by regular indexes, which are
+-- built on the existing rows when the legacy partition is attached, and the
+-- uniqueness is checked by a trigger instead.
+ALTER TABLE "oauth2_access_tokens_legacy"
+  DROP CONSTRAINT "oauth2_access_tokens_unique";
+DROP INDEX "oauth2_access_tokens_access_token_hash_idx";
+
+CREATE TABLE "oauth2_access_tokens" (
+  LIKE "oauth2_access_tokens_legacy" INCLUDING DEFAULTS,
+
+  CONSTRAINT "oauth2_access_tokens_pkey"
+    PRIMARY KEY ("oauth2_access_token_id"),
+
+  CONSTRAINT "oauth2_access_tokens_oauth2_session_id_fkey"
+    FOREIGN KEY ("oauth2_session_id")
+    REFERENCES "oauth2_sessions" ("oauth2_session_id")
+) PARTITION BY RANGE ("oauth2_access_token_id");
+
+CREATE INDEX "oauth2_access_tokens_access_token_hash_idx"
+  ON "oauth2_access_tokens" ("access_token_hash")
+  INCLUDE ("oauth2_access_token_id", "oauth2_session_id", "created_at", "expires_at", "revoked_at");
+
+CREATE INDEX "oauth2_access_tokens_access_token_idx"
+  ON "oauth2_access_tokens" ("access_token");
+
+-- Used by the cleanup of the expired tokens in the partitions which can't be
+-- dropped
+CREATE INDEX "oauth2_access_tokens_expires_at_idx"
+  ON "oauth2_access_tokens" ("expires_at");
+
+-- The bound is the smallest ULID of the day after tomorrow
+ALTER TABLE "oauth2_access_tokens"
+  ATTACH PARTITION "oauth2_access_tokens_legacy"
+  FOR VALUES FROM (MINVALUE) TO ((
+    lpad(to_hex((extract(epoch FROM date_trunc('day', now() AT TIME ZONE 'UTC') + INTERVAL '2 days') * 1000)::BIGINT), 12, '0')
+    || repeat('0', 20)
+  )::UUID);
+
+CREATE TABLE "oauth2_access_tokens_default"
+  PARTITION OF "oauth2_access_tokens" DEFAULT;
+
+-- Checks that no other row has the same token, in any partition. Concurrent
+-- inserts of the same token are serialised by a lock on the token, held until
+-- the end of the transaction, so that they see each other's rows.
+CREATE FUNCTION "oauth2_access_tokens_check_unique"()
+  RETURNS TRIGGER
+  LANGUAGE plpgsql
+AS $$
+BEGIN
+  IF NEW."access_token_hash" IS NOT NULL THEN
+    PERFORM pg_advisory_xact_lock(hashtextextended(
+      'oauth2_access_tokens.access_token_hash:' || encode(NEW."access_token_hash", 'hex'), 0));
+
+    IF EXISTS (
+      SELECT 1 FROM "oauth2_access_tokens"
+      WHERE "access_token_hash" = NEW."access_token_hash"
+        AND "oauth2_access_token_id" <> NEW."oauth2_access_token_id"
+    ) THEN
+      RAISE unique_violation USING
+        MESSAGE = 'duplicate key value violates unique constraint "oauth2_access_tokens_access_token_hash_unique"',
+        CONSTRAINT = 'oauth2_access_tokens_access_token_hash_unique';
+    END IF;
+  END IF;
+
+  IF NEW."access_token" IS NOT NULL THEN
+    PERFORM pg_advisory_xact_lock(hashtextextended(
+      'oauth2_access_tokens.access_token:' || NEW."access_token", 0));
+
+    IF EXISTS (
+      SELECT 1 FROM "oauth2_access_tokens"
+      WHERE "access_token" = NEW."access_token"
+        AND "oauth2_access_token_id" <> NEW."oauth2_access_token_id"
+    ) THEN
+      RAISE unique_violation USING
+        MESSAGE = 'duplicate key value violates unique constraint "oauth2_access_tokens_unique"',
+        CONSTRAINT = 'oauth2_access_tokens_unique';
+    END IF;
+  END IF;
+
+  RETURN NEW;
+END;
+$$;
+
+CREATE TRIGGER "oauth2_access_tokens_check_unique"
+  BEFORE INSERT OR UPDATE OF "access_token_hash", "access_token"
+  ON "oauth2_access_tokens"
+  FOR EACH ROW
+  EXECUTE FUNCTION "oauth2_access_tokens_check_unique"();
+
+-- Foreign keys to a partitioned table are supported, but its partitions can't
+-- be dropped while they are referenced: the references are cleared first, like
+-- `ON DELETE SET NULL` would do. This index makes that cheap.
+CREATE INDEX "oauth2_refresh_tokens_oauth2_access_token_id_idx"
+  ON "oauth2_refresh_tokens" ("oauth2_access_token_id");
+
+ALTER TABLE "oauth2_refresh_tokens"
+  ADD CONSTRAINT "oauth2_refresh_tokens_oauth2_access_token_id_fkey"
+    FOREIGN KEY ("oauth2_access_token_id")
+    REFERENCES "oauth2_access_tokens" ("oauth2_access_token_id")
+    ON DELETE SET NULL;
+
+ALTER TABLE "oauth2_authorization_grants"
+  RENAME TO "oauth2_authorization_grants_legacy";
+ALTER INDEX "oauth2_authorization_grants_pkey"
+  RENAME TO "oauth2_authorization_grants_legacy_pkey";
+ALTER TABLE "oauth2_authorization_grants_legacy"
+  DROP CONSTRAINT "oauth2_authorization_grants_authorization_code_unique";
+
+CREATE TABLE "oauth2_authorization_grants" (
+  LIKE "oauth2_authorization_grants_legacy" INCLUDING DEFAULTS,
+
+  CONSTRAINT "oauth2_authorization_grants_pkey"
+    PRIMARY KEY ("oauth2_authorization_grant_id"),
+
+  CONSTRAINT "tbl_oauth2_client_fkey"
+    FOREIGN KEY ("oauth2_client_id")
+    REFERENCES "oauth2_clients" ("oauth2_client_id"),
+
+  CONSTRAINT "tbl_oauth2_session_fkey"
+    FOREIGN KEY ("oauth2_session_id")
+    REFERENCES "oauth2_sessions" ("oauth2_session_id")
+) PARTITION BY RANGE ("oauth2_authorization_grant_id");
+
+CREATE INDEX "oauth2_authorization_grants_authorization_code_idx"
+  ON "oauth2_authorization_grants" ("authorization_code");
+
+ALTER TABLE "oauth2_authorization_grants"
+  ATTACH PARTITION "oauth2_authorization_grants_legacy"
+  FOR VALUES FROM (MINVALUE) TO ((
+    lpad(to_hex((extract(epoch FROM date_trunc('day', now() AT TIME ZONE 'UTC') + INTERVAL '2 days') * 1000)::BIGINT), 12, '0')
+    || repeat('0', 20)
+  )::UUID);
+
+CREATE TABLE "oauth2_authorization_grants_default"
+  PARTITION OF "oauth2_authorization_grants" DEFAULT;
+
+-- Same as in `oauth2_access_tokens_check_unique`
+CREATE FUNCTION "oauth2_authorization_grants_check_unique"()
+  RETURNS TRIGGER
+  LANGUAGE plpgsql
+AS $$
+BEGIN
+  IF NEW."authorization_code" IS NOT NULL THEN
+    PERFORM pg_advisory_xact_lock(hashtextextended(
+      'oauth2_authorization_grants.authorization_code:' || NEW."authorization_code", 0));
+
+    IF EXISTS (
+      SELECT 1 FROM "oauth2_authorization_grants"
+      WHERE "authorization_code" = NEW."authorization_code"
+        AND "oauth2_authorization_grant_id" <> NEW."oauth2_authorization_grant_id"
+    ) THEN
+      RAISE unique_violation USING
+        MESSAGE = 'duplicate key value violates unique constraint "oauth2_authorization_grants_authorization_code_unique"',
+        CONSTRAINT = 'oauth2_authorization_grants_authorization_code_unique';
+    END IF;
+  END IF;
+
+  RETURN NEW;
+END;
+$$;
+
+CREATE TRIGGER "oauth2_authorization_grants_check_unique"
+  BEFORE INSERT OR UPDATE OF "authorization_code"
+  ON "oauth2_authorization_grants"
+  FOR EACH ROW
+  EXECUTE FUNCTION "oauth2_authorization_grants_check_unique"();
+
+-- Same as in the `dashboard_aggregates` migration
+CREATE MATERIALIZED VIEW "dashboard_access_tokens_per_day" AS
+  SELECT
+    (date_trunc('day', t."created_at" AT TIME ZONE 'UTC'))::DATE AS "day",
+    s."oauth2_client_id",
+    COUNT(*) AS "count"
+  FROM "oauth2_access_tokens" t
+  INNER JOIN "oauth2_sessions" s USING ("oauth2_session_id")
+  WHERE t."created_at" >= now() - INTERVAL '90 days'
+  GROUP BY 1, 2;
+
+CREATE UNIQUE INDEX "dashboard_access_tokens_per_day_day_client_idx"
+  ON "dashboard_access_tokens_per_day" ("day", "oauth2_client_id");
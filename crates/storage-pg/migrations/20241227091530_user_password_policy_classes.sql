@@ -0,0 +1,16 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The password policy class assigned to users, like for service accounts
+-- which have different password rules than humans
+CREATE TABLE "user_password_policy_classes" (
+  "user_id" UUID NOT NULL
+    PRIMARY KEY
+    REFERENCES "users" ("user_id") ON DELETE CASCADE,
+
+  "policy_class" TEXT NOT NULL,
+
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The names users give to their sessions, like "Work laptop"
+ALTER TABLE "compat_sessions"
+  ADD COLUMN "human_name" TEXT;
+
+ALTER TABLE "oauth2_sessions"
+  ADD COLUMN "human_name" TEXT;
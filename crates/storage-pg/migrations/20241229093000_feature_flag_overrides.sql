@@ -0,0 +1,18 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Overrides of the feature flags defined in the configuration, so that they
+-- can be toggled without restarting the service
+CREATE TABLE "feature_flag_overrides" (
+  "name" TEXT NOT NULL
+    PRIMARY KEY,
+
+  "enabled" BOOLEAN NOT NULL,
+
+  "rollout_percentage" SMALLINT
+    CHECK ("rollout_percentage" BETWEEN 0 AND 100),
+
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
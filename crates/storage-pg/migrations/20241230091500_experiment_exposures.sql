@@ -0,0 +1,25 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The variants of the A/B experiments shown to visitors, and whether they
+-- logged in or registered afterwards
+CREATE TABLE "experiment_exposures" (
+  -- The visitor ID is random and stored in a cookie, so that anonymous
+  -- visitors keep their variant
+  "visitor_id" UUID NOT NULL,
+
+  "experiment" TEXT NOT NULL,
+
+  "variant" TEXT NOT NULL,
+
+  "exposed_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "converted_at" TIMESTAMP WITH TIME ZONE,
+
+  PRIMARY KEY ("visitor_id", "experiment")
+);
+
+CREATE INDEX "experiment_exposures_exposed_at_idx"
+  ON "experiment_exposures" ("exposed_at");
@@ -0,0 +1,29 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Short-lived elevations of browser sessions, obtained by authenticating
+-- again, and required for sensitive actions
+CREATE TABLE "user_session_elevations" (
+  "user_session_elevation_id" UUID NOT NULL
+    PRIMARY KEY,
+
+  "user_session_id" UUID NOT NULL
+    REFERENCES "user_sessions" ("user_session_id")
+    ON DELETE CASCADE,
+
+  "user_session_authentication_id" UUID NOT NULL
+    REFERENCES "user_session_authentications" ("user_session_authentication_id")
+    ON DELETE CASCADE,
+
+  "token" TEXT NOT NULL
+    UNIQUE,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX "user_session_elevations_user_session_id_idx"
+  ON "user_session_elevations" ("user_session_id");
@@ -0,0 +1,33 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Attempts of users to log in, successful or not, kept for a limited time so
+-- that users can review the recent activity on their account
+CREATE TABLE "user_login_attempts" (
+  "user_login_attempt_id" UUID NOT NULL
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- One of 'password', 'upstream_oauth2' or 'client_certificate'
+  "method" TEXT NOT NULL,
+
+  "succeeded" BOOLEAN NOT NULL,
+
+  "ip_address" INET,
+
+  "user_agent" TEXT,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX "user_login_attempts_user_id_idx"
+  ON "user_login_attempts" ("user_id", "user_login_attempt_id");
+
+-- Used to purge the attempts past the retention period
+CREATE INDEX "user_login_attempts_created_at_idx"
+  ON "user_login_attempts" ("created_at");
@@ -0,0 +1,20 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The fields parsed from the user-agent of the sessions, so that they don't
+-- have to be parsed again each time a session is loaded.
+--
+-- The existing sessions are filled by the `user_sessions_user_agent_details`,
+-- `compat_sessions_user_agent_details` and `oauth2_sessions_user_agent_details`
+-- background backfills. Until they complete, the sessions without details
+-- have their user-agent parsed when they are loaded.
+ALTER TABLE "user_sessions"
+  ADD COLUMN "user_agent_details" JSONB;
+
+ALTER TABLE "compat_sessions"
+  ADD COLUMN "user_agent_details" JSONB;
+
+ALTER TABLE "oauth2_sessions"
+  ADD COLUMN "user_agent_details" JSONB;
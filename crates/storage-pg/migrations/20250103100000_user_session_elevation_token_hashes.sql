@@ -0,0 +1,17 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Store a keyed hash of the session elevation tokens instead of their value,
+-- like the OAuth 2.0 tokens.
+--
+-- Elevations only last a few minutes, so the existing ones are dropped instead
+-- of being hashed: users will have to authenticate again before their next
+-- sensitive action.
+DELETE FROM "user_session_elevations";
+
+ALTER TABLE "user_session_elevations"
+  DROP COLUMN "token",
+  ADD COLUMN "token_hash" BYTEA NOT NULL
+    UNIQUE;
@@ -0,0 +1,910 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Backfills running in the background, to change the schema of large tables
+//! without downtime.
+//!
+//! Such schema changes are done in three steps:
+//!
+//!  1. an *expand* migration adds the new schema next to the old one, without
+//!     rewriting the table, like a nullable column. The application writes to
+//!     both from that point.
+//!  2. a [`Backfill`], registered in [`BACKFILLS`], fills the new schema for
+//!     the existing rows. It is run in small batches by a background job, which
+//!     saves its progress in the `background_migrations` table, so that it
+//!     resumes where it stopped after a restart.
+//!  3. a *contract* migration, shipped in a later release, removes the old
+//!     schema and adds the constraints on the new one. It should first check
+//!     that no row is left to backfill, and fail otherwise, as a backfill may
+//!     need the application to compute the new values.
+
+use chrono::{DateTime, Utc};
+use futures_util::{future::BoxFuture, FutureExt};
+use mas_data_model::{UserAgent, UserAgentDetails};
+use mas_storage::Clock;
+use sqlx::{types::Json, Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{ExecuteExt, TokenHasher};
+
+/// A function backfilling a batch of rows, with keys greater than the first
+/// [`Uuid`] and lower or equal to the second one. It returns the number of rows
+/// it updated. The [`TokenHasher`] is there for backfills which need to hash
+/// tokens.
+///
+/// It must be idempotent, as a batch could run again if the job is
+/// interrupted.
+pub type BatchFn = for<'c> fn(
+    &'c mut PgConnection,
+    &'c TokenHasher,
+    Uuid,
+    Uuid,
+) -> BoxFuture<'c, Result<u64, sqlx::Error>>;
+
+/// A backfill of the rows of a table, processed in batches of keys
+#[derive(Debug, Clone, Copy)]
+pub struct Backfill {
+    /// A unique name for the backfill, used to save its progress
+    pub name: &'static str,
+
+    /// The table to backfill
+    pub table: &'static str,
+
+    /// The `UUID` primary key of the table
+    pub key: &'static str,
+
+    /// The function backfilling a batch of rows
+    pub batch: BatchFn,
+}
+
+/// The backfills to run in the background
+pub static BACKFILLS: &[Backfill] = &[
+    Backfill {
+        name: "oauth2_access_tokens_hash",
+        table: "oauth2_access_tokens",
+        key: "oauth2_access_token_id",
+        batch: |conn, token_hasher, lower, upper| {
+            hash_access_tokens(conn, token_hasher, lower, upper).boxed()
+        },
+    },
+    Backfill {
+        name: "oauth2_refresh_tokens_hash",
+        table: "oauth2_refresh_tokens",
+        key: "oauth2_refresh_token_id",
+        batch: |conn, token_hasher, lower, upper| {
+            hash_refresh_tokens(conn, token_hasher, lower, upper).boxed()
+        },
+    },
+    Backfill {
+        name: "user_sessions_user_agent_details",
+        table: "user_sessions",
+        key: "user_session_id",
+        batch: |conn, _, lower, upper| parse_user_session_user_agents(conn, lower, upper).boxed(),
+    },
+    Backfill {
+        name: "compat_sessions_user_agent_details",
+        table: "compat_sessions",
+        key: "compat_session_id",
+        batch: |conn, _, lower, upper| parse_compat_session_user_agents(conn, lower, upper).boxed(),
+    },
+    Backfill {
+        name: "oauth2_sessions_user_agent_details",
+        table: "oauth2_sessions",
+        key: "oauth2_session_id",
+        batch: |conn, _, lower, upper| parse_oauth2_session_user_agents(conn, lower, upper).boxed(),
+    },
+];
+
+/// Replace the value of the access tokens by their hash. The hash is keyed with
+/// a secret which the database doesn't know, so it is computed here.
+async fn hash_access_tokens(
+    conn: &mut PgConnection,
+    token_hasher: &TokenHasher,
+    lower: Uuid,
+    upper: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT oauth2_access_token_id
+                 , access_token AS "access_token!"
+            FROM oauth2_access_tokens
+            WHERE oauth2_access_token_id > $1
+              AND oauth2_access_token_id <= $2
+              AND access_token IS NOT NULL
+            FOR UPDATE
+        "#,
+        lower,
+        upper,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (ids, hashes): (Vec<Uuid>, Vec<Vec<u8>>) = rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.oauth2_access_token_id,
+                token_hasher.hash(&row.access_token),
+            )
+        })
+        .unzip();
+
+    let res = sqlx::query!(
+        r#"
+            UPDATE oauth2_access_tokens
+            SET access_token_hash = t.access_token_hash
+              , access_token = NULL
+            FROM UNNEST($1::uuid[], $2::bytea[]) AS t(oauth2_access_token_id, access_token_hash)
+            WHERE oauth2_access_tokens.oauth2_access_token_id = t.oauth2_access_token_id
+        "#,
+        &ids,
+        &hashes,
+    )
+    .traced()
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Replace the value of the refresh tokens by their hash
+async fn hash_refresh_tokens(
+    conn: &mut PgConnection,
+    token_hasher: &TokenHasher,
+    lower: Uuid,
+    upper: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT oauth2_refresh_token_id
+                 , refresh_token AS "refresh_token!"
+            FROM oauth2_refresh_tokens
+            WHERE oauth2_refresh_token_id > $1
+              AND oauth2_refresh_token_id <= $2
+              AND refresh_token IS NOT NULL
+            FOR UPDATE
+        "#,
+        lower,
+        upper,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (ids, hashes): (Vec<Uuid>, Vec<Vec<u8>>) = rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.oauth2_refresh_token_id,
+                token_hasher.hash(&row.refresh_token),
+            )
+        })
+        .unzip();
+
+    let res = sqlx::query!(
+        r#"
+            UPDATE oauth2_refresh_tokens
+            SET refresh_token_hash = t.refresh_token_hash
+              , refresh_token = NULL
+            FROM UNNEST($1::uuid[], $2::bytea[]) AS t(oauth2_refresh_token_id, refresh_token_hash)
+            WHERE oauth2_refresh_tokens.oauth2_refresh_token_id = t.oauth2_refresh_token_id
+        "#,
+        &ids,
+        &hashes,
+    )
+    .traced()
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Save the fields parsed from the user-agents of the browser sessions, which
+/// are computed by the application
+async fn parse_user_session_user_agents(
+    conn: &mut PgConnection,
+    lower: Uuid,
+    upper: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT user_session_id
+                 , user_agent AS "user_agent!"
+            FROM user_sessions
+            WHERE user_session_id > $1
+              AND user_session_id <= $2
+              AND user_agent IS NOT NULL
+              AND user_agent_details IS NULL
+            FOR UPDATE
+        "#,
+        lower,
+        upper,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (ids, details): (Vec<Uuid>, Vec<Json<UserAgentDetails>>) = rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.user_session_id,
+                Json(UserAgent::parse(row.user_agent).details()),
+            )
+        })
+        .unzip();
+
+    let res = sqlx::query!(
+        r#"
+            UPDATE user_sessions
+            SET user_agent_details = t.user_agent_details
+            FROM UNNEST($1::uuid[], $2::jsonb[]) AS t(user_session_id, user_agent_details)
+            WHERE user_sessions.user_session_id = t.user_session_id
+        "#,
+        &ids,
+        &details as _,
+    )
+    .traced()
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Save the fields parsed from the user-agents of the compatibility sessions
+async fn parse_compat_session_user_agents(
+    conn: &mut PgConnection,
+    lower: Uuid,
+    upper: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT compat_session_id
+                 , user_agent AS "user_agent!"
+            FROM compat_sessions
+            WHERE compat_session_id > $1
+              AND compat_session_id <= $2
+              AND user_agent IS NOT NULL
+              AND user_agent_details IS NULL
+            FOR UPDATE
+        "#,
+        lower,
+        upper,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (ids, details): (Vec<Uuid>, Vec<Json<UserAgentDetails>>) = rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.compat_session_id,
+                Json(UserAgent::parse(row.user_agent).details()),
+            )
+        })
+        .unzip();
+
+    let res = sqlx::query!(
+        r#"
+            UPDATE compat_sessions
+            SET user_agent_details = t.user_agent_details
+            FROM UNNEST($1::uuid[], $2::jsonb[]) AS t(compat_session_id, user_agent_details)
+            WHERE compat_sessions.compat_session_id = t.compat_session_id
+        "#,
+        &ids,
+        &details as _,
+    )
+    .traced()
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Save the fields parsed from the user-agents of the OAuth 2.0 sessions
+async fn parse_oauth2_session_user_agents(
+    conn: &mut PgConnection,
+    lower: Uuid,
+    upper: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT oauth2_session_id
+                 , user_agent AS "user_agent!"
+            FROM oauth2_sessions
+            WHERE oauth2_session_id > $1
+              AND oauth2_session_id <= $2
+              AND user_agent IS NOT NULL
+              AND user_agent_details IS NULL
+            FOR UPDATE
+        "#,
+        lower,
+        upper,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (ids, details): (Vec<Uuid>, Vec<Json<UserAgentDetails>>) = rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.oauth2_session_id,
+                Json(UserAgent::parse(row.user_agent).details()),
+            )
+        })
+        .unzip();
+
+    let res = sqlx::query!(
+        r#"
+            UPDATE oauth2_sessions
+            SET user_agent_details = t.user_agent_details
+            FROM UNNEST($1::uuid[], $2::jsonb[]) AS t(oauth2_session_id, user_agent_details)
+            WHERE oauth2_sessions.oauth2_session_id = t.oauth2_session_id
+        "#,
+        &ids,
+        &details as _,
+    )
+    .traced()
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// The outcome of a single backfill batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// Another worker is running a batch of this backfill
+    Busy,
+
+    /// A batch was processed, updating that many rows
+    Progressed {
+        /// The number of rows updated by the batch
+        affected_rows: u64,
+    },
+
+    /// There is no row left to backfill
+    Completed,
+}
+
+/// The progress of a backfill
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillProgress {
+    /// The name of the backfill
+    pub name: String,
+
+    /// How many rows were updated so far
+    pub processed_rows: i64,
+
+    /// The estimated number of rows in the table when the backfill started
+    pub estimated_rows: i64,
+
+    /// When the backfill started
+    pub started_at: DateTime<Utc>,
+
+    /// When the last batch was processed
+    pub updated_at: DateTime<Utc>,
+
+    /// When the backfill completed, if it did
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl BackfillProgress {
+    /// The estimated completion, between 0 and 100
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn percent(&self) -> f64 {
+        if self.completed_at.is_some() {
+            return 100.0;
+        }
+
+        if self.estimated_rows <= 0 {
+            return 0.0;
+        }
+
+        (self.processed_rows as f64 * 100.0 / self.estimated_rows as f64).min(99.9)
+    }
+}
+
+/// Process the next batch of a backfill, in its own transaction
+///
+/// # Errors
+///
+/// Returns an error if the database fails, or if the backfill function fails
+#[tracing::instrument(
+    name = "db.backfill.run_batch",
+    skip_all,
+    fields(
+        db.query.text,
+        backfill.name = backfill.name,
+        backfill.batch_size = batch_size,
+    ),
+    err,
+)]
+pub async fn run_batch(
+    conn: &mut PgConnection,
+    clock: &dyn Clock,
+    token_hasher: &TokenHasher,
+    backfill: &Backfill,
+    batch_size: i64,
+) -> Result<BatchOutcome, sqlx::Error> {
+    let now = clock.now();
+    let mut txn = conn.begin().await?;
+
+    // Save the size of the table the first time the backfill runs, to be able to
+    // report the progress
+    sqlx::query!(
+        r#"
+            INSERT INTO background_migrations
+                ( name
+                , estimated_rows
+                , started_at
+                , updated_at
+                )
+            SELECT $1
+                 , COALESCE(
+                     (SELECT GREATEST(reltuples, 0)::BIGINT
+                      FROM pg_class
+                      WHERE oid = to_regclass($2)),
+                     0
+                   )
+                 , $3
+                 , $3
+            ON CONFLICT (name) DO NOTHING
+        "#,
+        backfill.name,
+        backfill.table,
+        now,
+    )
+    .execute(&mut *txn)
+    .await?;
+
+    // Skip it if another worker is processing a batch
+    let progress = sqlx::query!(
+        r#"
+            SELECT cursor, completed_at
+            FROM background_migrations
+            WHERE name = $1
+            FOR UPDATE SKIP LOCKED
+        "#,
+        backfill.name,
+    )
+    .fetch_optional(&mut *txn)
+    .await?;
+
+    let Some(progress) = progress else {
+        return Ok(BatchOutcome::Busy);
+    };
+
+    if progress.completed_at.is_some() {
+        return Ok(BatchOutcome::Completed);
+    }
+
+    let lower = progress.cursor.unwrap_or_else(Uuid::nil);
+
+    // Find the key ending this batch, or the last one if less than a batch is left
+    let upper: Option<Uuid> = sqlx::query_scalar(&format!(
+        r#"
+            SELECT "{key}"
+            FROM "{table}"
+            WHERE "{key}" > $1
+            ORDER BY "{key}"
+            OFFSET $2 - 1
+            LIMIT 1
+        "#,
+        key = backfill.key,
+        table = backfill.table,
+    ))
+    .bind(lower)
+    .bind(batch_size)
+    .fetch_optional(&mut *txn)
+    .await?;
+
+    let upper =
+        match upper {
+            Some(upper) => Some(upper),
+            None => sqlx::query_scalar(&format!(
+                r#"SELECT "{key}" FROM "{table}" WHERE "{key}" > $1 ORDER BY "{key}" DESC LIMIT 1"#,
+                key = backfill.key,
+                table = backfill.table,
+            ))
+            .bind(lower)
+            .fetch_optional(&mut *txn)
+            .await?,
+        };
+
+    let Some(upper) = upper else {
+        sqlx::query!(
+            r#"
+                UPDATE background_migrations
+                SET completed_at = $2
+                  , updated_at = $2
+                WHERE name = $1
+            "#,
+            backfill.name,
+            now,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+        return Ok(BatchOutcome::Completed);
+    };
+
+    let affected_rows = (backfill.batch)(&mut txn, token_hasher, lower, upper).await?;
+
+    sqlx::query!(
+        r#"
+            UPDATE background_migrations
+            SET cursor = $2
+              , processed_rows = processed_rows + $3
+              , updated_at = $4
+            WHERE name = $1
+        "#,
+        backfill.name,
+        upper,
+        i64::try_from(affected_rows).unwrap_or(i64::MAX),
+        now,
+    )
+    .execute(&mut *txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(BatchOutcome::Progressed { affected_rows })
+}
+
+/// List the progress of the backfills which started
+///
+/// # Errors
+///
+/// Returns an error if the database fails
+#[tracing::instrument(name = "db.backfill.list_progress", skip_all, fields(db.query.text), err)]
+pub async fn list_progress(conn: &mut PgConnection) -> Result<Vec<BackfillProgress>, sqlx::Error> {
+    sqlx::query_as!(
+        BackfillProgress,
+        r#"
+            SELECT name
+                 , processed_rows
+                 , estimated_rows
+                 , started_at
+                 , updated_at
+                 , completed_at
+            FROM background_migrations
+            ORDER BY started_at, name
+        "#,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::{clock::MockClock, RepositoryAccess};
+    use oauth2_types::{
+        requests::GrantType,
+        scope::{Scope, OPENID},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_backfill(pool: PgPool) {
+        let clock = MockClock::default();
+        let token_hasher = TokenHasher::new(b"secret");
+        let mut conn = pool.acquire().await.unwrap();
+
+        sqlx::query(
+            r#"
+                CREATE TABLE backfill_test (
+                  id UUID NOT NULL PRIMARY KEY,
+                  name TEXT NOT NULL,
+                  name_lower TEXT
+                )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        for i in 0..25 {
+            sqlx::query("INSERT INTO backfill_test (id, name) VALUES ($1, $2)")
+                .bind(Uuid::from_u128(i + 1))
+                .bind(format!("User{i}"))
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+        }
+
+        let backfill = Backfill {
+            name: "backfill_test_name_lower",
+            table: "backfill_test",
+            key: "id",
+            batch: |conn, _, lower, upper| {
+                async move {
+                    let res = sqlx::query(
+                        r#"
+                            UPDATE backfill_test
+                            SET name_lower = LOWER(name)
+                            WHERE id > $1 AND id <= $2 AND name_lower IS NULL
+                        "#,
+                    )
+                    .bind(lower)
+                    .bind(upper)
+                    .execute(conn)
+                    .await?;
+                    Ok(res.rows_affected())
+                }
+                .boxed()
+            },
+        };
+
+        let mut outcomes = Vec::new();
+        loop {
+            let outcome = run_batch(&mut conn, &clock, &token_hasher, &backfill, 10)
+                .await
+                .unwrap();
+            outcomes.push(outcome);
+            if outcome == BatchOutcome::Completed {
+                break;
+            }
+        }
+
+        assert_eq!(
+            outcomes,
+            vec![
+                BatchOutcome::Progressed { affected_rows: 10 },
+                BatchOutcome::Progressed { affected_rows: 10 },
+                BatchOutcome::Progressed { affected_rows: 5 },
+                BatchOutcome::Completed,
+            ]
+        );
+
+        let remaining: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM backfill_test WHERE name_lower IS NULL")
+                .fetch_one(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(remaining, 0);
+
+        // Rows added afterwards are not touched anymore, as the application writes
+        // them in the new schema
+        assert_eq!(
+            run_batch(&mut conn, &clock, &token_hasher, &backfill, 10)
+                .await
+                .unwrap(),
+            BatchOutcome::Completed
+        );
+
+        let progress = list_progress(&mut conn).await.unwrap();
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].name, "backfill_test_name_lower");
+        assert_eq!(progress[0].processed_rows, 25);
+        assert!(progress[0].completed_at.is_some());
+        assert!((progress[0].percent() - 100.0).abs() < f64::EPSILON);
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_token_hash_backfill(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let token_hasher = TokenHasher::new(b"secret");
+        let mut repo = PgRepository::from_pool(&pool)
+            .await
+            .unwrap()
+            .with_token_hasher(token_hasher.clone())
+            .boxed();
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                Vec::new(),
+                None,
+                None,
+                vec![GrantType::ClientCredentials],
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .add_from_client_credentials(&mut rng, &clock, &client, Scope::from_iter([OPENID]))
+            .await
+            .unwrap();
+        let new_token = repo
+            .oauth2_access_token()
+            .add(&mut rng, &clock, &session, "new".to_owned(), None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // A token saved before the hashes were introduced
+        let mut conn = pool.acquire().await.unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO oauth2_access_tokens
+                    (oauth2_access_token_id, oauth2_session_id, access_token, created_at)
+                VALUES ($1, $2, 'old', $3)
+            "#,
+            Uuid::from(Ulid::from_datetime_with_source(
+                clock.now().into(),
+                &mut rng,
+            )),
+            Uuid::from(session.id),
+            clock.now(),
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        // Both tokens can be found, before and after the backfill
+        let backfill = BACKFILLS
+            .iter()
+            .find(|b| b.name == "oauth2_access_tokens_hash")
+            .unwrap();
+        for backfilled in [false, true] {
+            if backfilled {
+                while run_batch(&mut conn, &clock, &token_hasher, backfill, 10)
+                    .await
+                    .unwrap()
+                    != BatchOutcome::Completed
+                {}
+            }
+
+            let mut repo = PgRepository::from_pool(&pool)
+                .await
+                .unwrap()
+                .with_token_hasher(token_hasher.clone())
+                .boxed();
+            for value in ["old", "new"] {
+                let token = repo
+                    .oauth2_access_token()
+                    .find_by_token(value)
+                    .await
+                    .unwrap()
+                    .expect("token not found");
+                assert_eq!(token.access_token, value);
+            }
+            repo.cancel().await.unwrap();
+        }
+
+        // Only the hash is known when loading a token by ID
+        let mut repo = PgRepository::from_pool(&pool)
+            .await
+            .unwrap()
+            .with_token_hasher(token_hasher.clone())
+            .boxed();
+        let token = repo
+            .oauth2_access_token()
+            .lookup(new_token.id)
+            .await
+            .unwrap()
+            .expect("token not found");
+        assert_eq!(token.access_token, "");
+        repo.cancel().await.unwrap();
+
+        // No token value is left in the database
+        let plaintext = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM oauth2_access_tokens
+                WHERE access_token IS NOT NULL
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(plaintext, 0);
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_user_agent_details_backfill(pool: PgPool) {
+        const RAW: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like \
+                           Gecko) Chrome/100.0.0.0 Safari/537.36";
+        let token_hasher = TokenHasher::new(b"secret");
+
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+        let new_session = repo
+            .browser_session()
+            .add(
+                &mut rng,
+                &clock,
+                &user,
+                Some(UserAgent::parse(RAW.to_owned())),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // A session saved before the parsed fields were introduced
+        let mut conn = pool.acquire().await.unwrap();
+        let old_session_id = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+        sqlx::query!(
+            r#"
+                INSERT INTO user_sessions (user_session_id, user_id, user_agent, created_at)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(old_session_id),
+            Uuid::from(user.id),
+            RAW,
+            clock.now(),
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        // Both sessions have their user-agent, before and after the backfill
+        let backfill = BACKFILLS
+            .iter()
+            .find(|b| b.name == "user_sessions_user_agent_details")
+            .unwrap();
+        for backfilled in [false, true] {
+            if backfilled {
+                while run_batch(&mut conn, &clock, &token_hasher, backfill, 10)
+                    .await
+                    .unwrap()
+                    != BatchOutcome::Completed
+                {}
+            }
+
+            let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+            for id in [old_session_id, new_session.id] {
+                let session = repo
+                    .browser_session()
+                    .lookup(id)
+                    .await
+                    .unwrap()
+                    .expect("session not found");
+                let user_agent = session.user_agent.expect("user-agent not loaded");
+                assert_eq!(user_agent.raw, RAW);
+                assert_eq!(user_agent.summary().as_deref(), Some("Chrome on Linux"));
+            }
+            repo.cancel().await.unwrap();
+        }
+
+        // Every session now has its parsed fields
+        let missing = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM user_sessions
+                WHERE user_agent_details IS NULL
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(missing, 0);
+    }
+}
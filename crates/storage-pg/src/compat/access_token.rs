@@ -123,6 +123,46 @@ impl<'c> CompatAccessTokenRepository for PgCompatAccessTokenRepository<'c> {
         Ok(Some(res.into()))
     }
 
+    #[tracing::instrument(
+        name = "db.compat_access_token.list_active",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+        ),
+        err,
+    )]
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatAccessToken>, Self::Error> {
+        let res = sqlx::query_as!(
+            CompatAccessTokenLookup,
+            r#"
+                SELECT compat_access_token_id
+                     , access_token
+                     , created_at
+                     , expires_at
+                     , compat_session_id
+
+                FROM compat_access_tokens
+
+                WHERE compat_session_id = $1
+                  AND (expires_at IS NULL OR expires_at > $2)
+
+                ORDER BY compat_access_token_id
+            "#,
+            Uuid::from(compat_session.id),
+            clock.now(),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res.into_iter().map(Into::into).collect())
+    }
+
     #[tracing::instrument(
         name = "db.compat_access_token.add",
         skip_all,
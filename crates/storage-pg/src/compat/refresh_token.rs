@@ -134,6 +134,45 @@ impl<'c> CompatRefreshTokenRepository for PgCompatRefreshTokenRepository<'c> {
         Ok(Some(res.into()))
     }
 
+    #[tracing::instrument(
+        name = "db.compat_refresh_token.list_active",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+        ),
+        err,
+    )]
+    async fn list_active(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatRefreshToken>, Self::Error> {
+        let res = sqlx::query_as!(
+            CompatRefreshTokenLookup,
+            r#"
+                SELECT compat_refresh_token_id
+                     , refresh_token
+                     , created_at
+                     , consumed_at
+                     , compat_session_id
+                     , compat_access_token_id
+
+                FROM compat_refresh_tokens
+
+                WHERE compat_session_id = $1
+                  AND consumed_at IS NULL
+
+                ORDER BY compat_refresh_token_id
+            "#,
+            Uuid::from(compat_session.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res.into_iter().map(Into::into).collect())
+    }
+
     #[tracing::instrument(
         name = "db.compat_refresh_token.add",
         skip_all,
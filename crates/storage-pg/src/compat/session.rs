@@ -10,7 +10,7 @@ use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
     BrowserSession, CompatSession, CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
-    User, UserAgent,
+    User, UserAgent, UserAgentDetails,
 };
 use mas_storage::{
     compat::{CompatSessionFilter, CompatSessionRepository},
@@ -19,7 +19,7 @@ use mas_storage::{
 use rand::RngCore;
 use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use url::Url;
 use uuid::Uuid;
@@ -29,6 +29,7 @@ use crate::{
     iden::{CompatSessions, CompatSsoLogins},
     pagination::QueryBuilderExt,
     tracing::ExecuteExt,
+    user_agent::load_user_agent,
     DatabaseError, DatabaseInconsistencyError,
 };
 
@@ -54,6 +55,7 @@ struct CompatSessionLookup {
     finished_at: Option<DateTime<Utc>>,
     is_synapse_admin: bool,
     user_agent: Option<String>,
+    user_agent_details: Option<Json<UserAgentDetails>>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
 }
@@ -83,7 +85,7 @@ impl TryFrom<CompatSessionLookup> for CompatSession {
             device,
             created_at: value.created_at,
             is_synapse_admin: value.is_synapse_admin,
-            user_agent: value.user_agent.map(UserAgent::parse),
+            user_agent: load_user_agent(value.user_agent, value.user_agent_details),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
         };
@@ -103,6 +105,7 @@ struct CompatSessionAndSsoLoginLookup {
     finished_at: Option<DateTime<Utc>>,
     is_synapse_admin: bool,
     user_agent: Option<String>,
+    user_agent_details: Option<Json<UserAgentDetails>>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
     compat_sso_login_id: Option<Uuid>,
@@ -138,7 +141,7 @@ impl TryFrom<CompatSessionAndSsoLoginLookup> for (CompatSession, Option<CompatSs
             user_session_id: value.user_session_id.map(Ulid::from),
             created_at: value.created_at,
             is_synapse_admin: value.is_synapse_admin,
-            user_agent: value.user_agent.map(UserAgent::parse),
+            user_agent: load_user_agent(value.user_agent, value.user_agent_details),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
         };
@@ -284,6 +287,7 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
                      , finished_at
                      , is_synapse_admin
                      , user_agent
+                     , user_agent_details AS "user_agent_details: Json<UserAgentDetails>"
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
                 FROM compat_sessions
@@ -469,6 +473,10 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
                 Expr::col((CompatSessions::Table, CompatSessions::UserAgent)),
                 CompatSessionAndSsoLoginLookupIden::UserAgent,
             )
+            .expr_as(
+                Expr::col((CompatSessions::Table, CompatSessions::UserAgentDetails)),
+                CompatSessionAndSsoLoginLookupIden::UserAgentDetails,
+            )
             .expr_as(
                 Expr::col((CompatSessions::Table, CompatSessions::LastActiveAt)),
                 CompatSessionAndSsoLoginLookupIden::LastActiveAt,
@@ -614,10 +622,12 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
             r#"
             UPDATE compat_sessions
             SET user_agent = $2
+              , user_agent_details = $3
             WHERE compat_session_id = $1
         "#,
             Uuid::from(compat_session.id),
             &*user_agent,
+            Json(user_agent.details()) as _,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -629,4 +639,65 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
 
         Ok(compat_session)
     }
+
+    #[tracing::instrument(
+        name = "db.compat_session.human_name",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+        ),
+        err,
+    )]
+    async fn human_name(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Option<String>, Self::Error> {
+        let human_name = sqlx::query_scalar!(
+            r#"
+                SELECT human_name
+                FROM compat_sessions
+                WHERE compat_session_id = $1
+            "#,
+            Uuid::from(compat_session.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(human_name)
+    }
+
+    #[tracing::instrument(
+        name = "db.compat_session.set_human_name",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+            compat_session.human_name = human_name,
+        ),
+        err,
+    )]
+    async fn set_human_name(
+        &mut self,
+        compat_session: &CompatSession,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE compat_sessions
+                SET human_name = $2
+                WHERE compat_session_id = $1
+            "#,
+            Uuid::from(compat_session.id),
+            human_name,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,25 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Run the storage conformance test suite against the PostgreSQL backend
+
+use mas_storage::BoxRepository;
+use sqlx::PgPool;
+
+use crate::PgRepository;
+
+struct TestBackend(PgPool);
+
+#[async_trait::async_trait]
+impl mas_storage_testkit::Backend for TestBackend {
+    async fn repository(&self) -> BoxRepository {
+        PgRepository::from_pool(&self.0).await.unwrap().boxed()
+    }
+}
+
+mas_storage_testkit::conformance_tests! {
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    (pool: PgPool) => TestBackend(pool)
+}
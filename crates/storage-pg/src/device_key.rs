@@ -0,0 +1,209 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`DeviceKeyRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{CompatSession, DeviceKey, Session};
+use mas_jose::jwk::PublicJsonWebKey;
+use mas_storage::{device_key::DeviceKeyRepository, Clock};
+use rand::RngCore;
+use sqlx::{types::Json, PgConnection};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`DeviceKeyRepository`] for a PostgreSQL connection
+pub struct PgDeviceKeyRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgDeviceKeyRepository<'c> {
+    /// Create a new [`PgDeviceKeyRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct DeviceKeyLookup {
+    device_key_id: Uuid,
+    public_key: Json<PublicJsonWebKey>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<DeviceKeyLookup> for DeviceKey {
+    fn from(value: DeviceKeyLookup) -> Self {
+        DeviceKey {
+            id: value.device_key_id.into(),
+            public_key: value.public_key.0,
+            created_at: value.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceKeyRepository for PgDeviceKeyRepository<'_> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.device_key.find_for_oauth2_session",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn find_for_oauth2_session(
+        &mut self,
+        session: &Session,
+    ) -> Result<Option<DeviceKey>, Self::Error> {
+        let res = sqlx::query_as!(
+            DeviceKeyLookup,
+            r#"
+                SELECT device_key_id
+                     , public_key AS "public_key: Json<PublicJsonWebKey>"
+                     , created_at
+                FROM device_keys
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.device_key.find_for_compat_session",
+        skip_all,
+        fields(
+            db.query.text,
+            compat_session.id = %session.id,
+        ),
+        err,
+    )]
+    async fn find_for_compat_session(
+        &mut self,
+        session: &CompatSession,
+    ) -> Result<Option<DeviceKey>, Self::Error> {
+        let res = sqlx::query_as!(
+            DeviceKeyLookup,
+            r#"
+                SELECT device_key_id
+                     , public_key AS "public_key: Json<PublicJsonWebKey>"
+                     , created_at
+                FROM device_keys
+                WHERE compat_session_id = $1
+            "#,
+            Uuid::from(session.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.device_key.add_for_oauth2_session",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            device_key.id,
+        ),
+        err,
+    )]
+    async fn add_for_oauth2_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("device_key.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO device_keys
+                    ( device_key_id
+                    , oauth2_session_id
+                    , public_key
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(session.id),
+            Json(&public_key) as _,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(DeviceKey {
+            id,
+            public_key,
+            created_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.device_key.add_for_compat_session",
+        skip_all,
+        fields(
+            db.query.text,
+            compat_session.id = %session.id,
+            device_key.id,
+        ),
+        err,
+    )]
+    async fn add_for_compat_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &CompatSession,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("device_key.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO device_keys
+                    ( device_key_id
+                    , compat_session_id
+                    , public_key
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(session.id),
+            Json(&public_key) as _,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(DeviceKey {
+            id,
+            public_key,
+            created_at,
+        })
+    }
+}
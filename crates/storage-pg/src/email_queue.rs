@@ -0,0 +1,612 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`EmailQueueRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    QueuedEmail, QueuedEmailState, UndeliverableEmailAddress, UndeliverableEmailReason,
+};
+use mas_storage::{
+    email_queue::{EmailQueueRepository, QueuedEmailFilter, QueuedEmailStateFilter},
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    errors::DatabaseInconsistencyError,
+    filter::{Filter, StatementExt},
+    iden::QueuedEmails,
+    pagination::QueryBuilderExt,
+    tracing::ExecuteExt,
+    DatabaseError,
+};
+
+/// An implementation of [`EmailQueueRepository`] for a PostgreSQL connection
+pub struct PgEmailQueueRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgEmailQueueRepository<'c> {
+    /// Create a new [`PgEmailQueueRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+impl Filter for QueuedEmailFilter<'_> {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all()
+            .add_option(self.recipient().map(|recipient| {
+                Expr::col((QueuedEmails::Table, QueuedEmails::Recipient)).eq(recipient)
+            }))
+            .add_option(self.state().map(|state| {
+                match state {
+                    QueuedEmailStateFilter::Pending => sea_query::Condition::all()
+                        .add(Expr::col((QueuedEmails::Table, QueuedEmails::SentAt)).is_null())
+                        .add(Expr::col((QueuedEmails::Table, QueuedEmails::FailedAt)).is_null()),
+                    QueuedEmailStateFilter::Sent => sea_query::Condition::all()
+                        .add(Expr::col((QueuedEmails::Table, QueuedEmails::SentAt)).is_not_null()),
+                    QueuedEmailStateFilter::Failed => sea_query::Condition::all().add(
+                        Expr::col((QueuedEmails::Table, QueuedEmails::FailedAt)).is_not_null(),
+                    ),
+                }
+            }))
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct QueuedEmailLookup {
+        pub(super) queued_email_id: Uuid,
+        pub(super) recipient: String,
+        pub(super) message: Vec<u8>,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) attempts: i32,
+        pub(super) next_attempt_at: DateTime<Utc>,
+        pub(super) last_error: Option<String>,
+        pub(super) sent_at: Option<DateTime<Utc>>,
+        pub(super) failed_at: Option<DateTime<Utc>>,
+    }
+}
+
+use priv_::{QueuedEmailLookup, QueuedEmailLookupIden};
+
+impl TryFrom<QueuedEmailLookup> for QueuedEmail {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: QueuedEmailLookup) -> Result<Self, Self::Error> {
+        let id = value.queued_email_id.into();
+        let attempts = value.attempts.try_into().map_err(|e| {
+            DatabaseInconsistencyError::on("queued_emails")
+                .column("attempts")
+                .row(id)
+                .source(e)
+        })?;
+
+        let state = match (value.sent_at, value.failed_at) {
+            (None, None) => QueuedEmailState::Pending,
+            (Some(sent_at), None) => QueuedEmailState::Sent { sent_at },
+            (None, Some(failed_at)) => QueuedEmailState::Failed { failed_at },
+            (Some(_), Some(_)) => {
+                return Err(DatabaseInconsistencyError::on("queued_emails")
+                    .column("failed_at")
+                    .row(id));
+            }
+        };
+
+        Ok(QueuedEmail {
+            id,
+            recipient: value.recipient,
+            message: value.message,
+            created_at: value.created_at,
+            attempts,
+            next_attempt_at: value.next_attempt_at,
+            last_error: value.last_error,
+            state,
+        })
+    }
+}
+
+struct UndeliverableLookup {
+    email: String,
+    reason: String,
+    reported_at: DateTime<Utc>,
+    details: Option<String>,
+}
+
+impl TryFrom<UndeliverableLookup> for UndeliverableEmailAddress {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: UndeliverableLookup) -> Result<Self, Self::Error> {
+        let reason = value.reason.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("undeliverable_email_addresses")
+                .column("reason")
+                .source(e)
+        })?;
+
+        Ok(UndeliverableEmailAddress {
+            email: value.email,
+            reason,
+            reported_at: value.reported_at,
+            details: value.details,
+        })
+    }
+}
+
+#[async_trait]
+impl EmailQueueRepository for PgEmailQueueRepository<'_> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.email_queue.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<QueuedEmail>, Self::Error> {
+        let res = sqlx::query_as!(
+            QueuedEmailLookup,
+            r#"
+                SELECT queued_email_id
+                     , recipient
+                     , message
+                     , created_at
+                     , attempts
+                     , next_attempt_at
+                     , last_error
+                     , sent_at
+                     , failed_at
+                FROM queued_emails
+                WHERE queued_email_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.enqueue",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id,
+            queued_email.recipient = recipient,
+        ),
+        err,
+    )]
+    async fn enqueue(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        recipient: String,
+        message: Vec<u8>,
+    ) -> Result<QueuedEmail, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("queued_email.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO queued_emails
+                    ( queued_email_id
+                    , recipient
+                    , message
+                    , created_at
+                    , next_attempt_at
+                    )
+                VALUES ($1, $2, $3, $4, $4)
+            "#,
+            Uuid::from(id),
+            &recipient,
+            &message,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(QueuedEmail {
+            id,
+            recipient,
+            message,
+            created_at,
+            attempts: 0,
+            next_attempt_at: created_at,
+            last_error: None,
+            state: QueuedEmailState::Pending,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.next_due",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn next_due(&mut self, clock: &dyn Clock) -> Result<Option<QueuedEmail>, Self::Error> {
+        let res = sqlx::query_as!(
+            QueuedEmailLookup,
+            r#"
+                SELECT queued_email_id
+                     , recipient
+                     , message
+                     , created_at
+                     , attempts
+                     , next_attempt_at
+                     , last_error
+                     , sent_at
+                     , failed_at
+                FROM queued_emails
+                WHERE sent_at IS NULL
+                  AND failed_at IS NULL
+                  AND next_attempt_at <= $1
+                ORDER BY next_attempt_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.mark_as_sent",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id = %email.id,
+        ),
+        err,
+    )]
+    async fn mark_as_sent(
+        &mut self,
+        clock: &dyn Clock,
+        mut email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error> {
+        let sent_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE queued_emails
+                SET sent_at = $2
+                WHERE queued_email_id = $1
+            "#,
+            Uuid::from(email.id),
+            sent_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        email.state = QueuedEmailState::Sent { sent_at };
+        Ok(email)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.record_failure",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id = %email.id,
+            queued_email.dead_letter = retry_at.is_none(),
+        ),
+        err,
+    )]
+    async fn record_failure(
+        &mut self,
+        clock: &dyn Clock,
+        mut email: QueuedEmail,
+        error: String,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<QueuedEmail, Self::Error> {
+        let now = clock.now();
+        let failed_at = retry_at.is_none().then_some(now);
+        let next_attempt_at = retry_at.unwrap_or(now);
+        let attempts = email.attempts + 1;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE queued_emails
+                SET attempts = $2
+                  , next_attempt_at = $3
+                  , last_error = $4
+                  , failed_at = $5
+                WHERE queued_email_id = $1
+            "#,
+            Uuid::from(email.id),
+            i32::try_from(attempts).map_err(DatabaseError::to_invalid_operation)?,
+            next_attempt_at,
+            &error,
+            failed_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        email.attempts = attempts;
+        email.next_attempt_at = next_attempt_at;
+        email.last_error = Some(error);
+        if let Some(failed_at) = failed_at {
+            email.state = QueuedEmailState::Failed { failed_at };
+        }
+        Ok(email)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.postpone",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id = %email.id,
+        ),
+        err,
+    )]
+    async fn postpone(
+        &mut self,
+        mut email: QueuedEmail,
+        until: DateTime<Utc>,
+    ) -> Result<QueuedEmail, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE queued_emails
+                SET next_attempt_at = $2
+                WHERE queued_email_id = $1
+            "#,
+            Uuid::from(email.id),
+            until,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        email.next_attempt_at = until;
+        Ok(email)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: QueuedEmailFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<QueuedEmail>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::QueuedEmailId)),
+                QueuedEmailLookupIden::QueuedEmailId,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::Recipient)),
+                QueuedEmailLookupIden::Recipient,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::Message)),
+                QueuedEmailLookupIden::Message,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::CreatedAt)),
+                QueuedEmailLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::Attempts)),
+                QueuedEmailLookupIden::Attempts,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::NextAttemptAt)),
+                QueuedEmailLookupIden::NextAttemptAt,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::LastError)),
+                QueuedEmailLookupIden::LastError,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::SentAt)),
+                QueuedEmailLookupIden::SentAt,
+            )
+            .expr_as(
+                Expr::col((QueuedEmails::Table, QueuedEmails::FailedAt)),
+                QueuedEmailLookupIden::FailedAt,
+            )
+            .from(QueuedEmails::Table)
+            .apply_filter(filter)
+            .generate_pagination(
+                (QueuedEmails::Table, QueuedEmails::QueuedEmailId),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<QueuedEmailLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).try_map(QueuedEmail::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(&mut self, filter: QueuedEmailFilter<'_>) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(Expr::col((QueuedEmails::Table, QueuedEmails::QueuedEmailId)).count())
+            .from(QueuedEmails::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.retry",
+        skip_all,
+        fields(
+            db.query.text,
+            queued_email.id = %email.id,
+        ),
+        err,
+    )]
+    async fn retry(
+        &mut self,
+        clock: &dyn Clock,
+        mut email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error> {
+        let next_attempt_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE queued_emails
+                SET attempts = 0
+                  , next_attempt_at = $2
+                  , failed_at = NULL
+                WHERE queued_email_id = $1
+                  AND sent_at IS NULL
+            "#,
+            Uuid::from(email.id),
+            next_attempt_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        email.attempts = 0;
+        email.next_attempt_at = next_attempt_at;
+        email.state = QueuedEmailState::Pending;
+        Ok(email)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.mark_undeliverable",
+        skip_all,
+        fields(
+            db.query.text,
+            email = email,
+            reason = reason.as_str(),
+        ),
+        err,
+    )]
+    async fn mark_undeliverable(
+        &mut self,
+        clock: &dyn Clock,
+        email: &str,
+        reason: UndeliverableEmailReason,
+        details: Option<String>,
+    ) -> Result<UndeliverableEmailAddress, Self::Error> {
+        let res = sqlx::query_as!(
+            UndeliverableLookup,
+            r#"
+                INSERT INTO undeliverable_email_addresses
+                    (email, reason, reported_at, details)
+                VALUES (LOWER($1), $2, $3, $4)
+                ON CONFLICT (email) DO UPDATE
+                SET reason = EXCLUDED.reason
+                  , reported_at = EXCLUDED.reported_at
+                  , details = EXCLUDED.details
+                RETURNING email, reason, reported_at, details
+            "#,
+            email,
+            reason.as_str(),
+            clock.now(),
+            details,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(res.try_into()?)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_queue.find_undeliverable",
+        skip_all,
+        fields(
+            db.query.text,
+            email = email,
+        ),
+        err,
+    )]
+    async fn find_undeliverable(
+        &mut self,
+        email: &str,
+    ) -> Result<Option<UndeliverableEmailAddress>, Self::Error> {
+        let res = sqlx::query_as!(
+            UndeliverableLookup,
+            r#"
+                SELECT email, reason, reported_at, details
+                FROM undeliverable_email_addresses
+                WHERE email = LOWER($1)
+            "#,
+            email,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+}
@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use sqlx::postgres::PgQueryResult;
+use sqlx::{error::ErrorKind, postgres::PgQueryResult};
 use thiserror::Error;
 use ulid::Ulid;
 
@@ -65,6 +65,29 @@ impl DatabaseError {
     pub(crate) const fn invalid_operation() -> Self {
         Self::InvalidOperation { source: None }
     }
+
+    /// A short name for the kind of error, used as a metric attribute
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Driver { source } => match source {
+                sqlx::Error::PoolTimedOut => "pool_timeout",
+                sqlx::Error::PoolClosed | sqlx::Error::Io(_) | sqlx::Error::Tls(_) => "connection",
+                // The `query_canceled` error code, which is what a statement timeout raises
+                sqlx::Error::Database(e) if e.code().as_deref() == Some("57014") => {
+                    "statement_timeout"
+                }
+                sqlx::Error::Database(e) if !matches!(e.kind(), ErrorKind::Other) => {
+                    "constraint_violation"
+                }
+                sqlx::Error::Database(_) => "database",
+                _ => "driver",
+            },
+            Self::Inconsistency(_) => "inconsistency",
+            Self::InvalidOperation { .. } => "invalid_operation",
+            Self::RowsAffected { .. } => "rows_affected",
+        }
+    }
 }
 
 /// An error which occured while converting the data from the database
@@ -0,0 +1,169 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`FeatureFlagRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::FeatureFlagOverride;
+use mas_storage::{feature_flag::FeatureFlagRepository, Clock};
+use sqlx::PgConnection;
+
+use crate::{errors::DatabaseInconsistencyError, tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`FeatureFlagRepository`] for a PostgreSQL connection
+pub struct PgFeatureFlagRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgFeatureFlagRepository<'c> {
+    /// Create a new [`PgFeatureFlagRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct FeatureFlagOverrideLookup {
+    name: String,
+    enabled: bool,
+    rollout_percentage: Option<i16>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<FeatureFlagOverrideLookup> for FeatureFlagOverride {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: FeatureFlagOverrideLookup) -> Result<Self, Self::Error> {
+        let rollout_percentage = value
+            .rollout_percentage
+            .map(u8::try_from)
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("feature_flag_overrides")
+                    .column("rollout_percentage")
+                    .source(e)
+            })?;
+
+        Ok(FeatureFlagOverride {
+            name: value.name,
+            enabled: value.enabled,
+            rollout_percentage,
+            updated_at: value.updated_at,
+        })
+    }
+}
+
+#[async_trait]
+impl FeatureFlagRepository for PgFeatureFlagRepository<'_> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.feature_flag.all",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn all(&mut self) -> Result<Vec<FeatureFlagOverride>, Self::Error> {
+        let res = sqlx::query_as!(
+            FeatureFlagOverrideLookup,
+            r#"
+                SELECT name
+                     , enabled
+                     , rollout_percentage
+                     , updated_at
+                FROM feature_flag_overrides
+                ORDER BY name
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let overrides = res
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, DatabaseInconsistencyError>>()?;
+
+        Ok(overrides)
+    }
+
+    #[tracing::instrument(
+        name = "db.feature_flag.set",
+        skip_all,
+        fields(
+            db.query.text,
+            feature_flag.name = %name,
+            feature_flag.enabled = enabled,
+        ),
+        err,
+    )]
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        name: String,
+        enabled: bool,
+        rollout_percentage: Option<u8>,
+    ) -> Result<FeatureFlagOverride, Self::Error> {
+        let updated_at = clock.now();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO feature_flag_overrides
+                    ( name
+                    , enabled
+                    , rollout_percentage
+                    , updated_at
+                    )
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (name) DO UPDATE
+                SET enabled = EXCLUDED.enabled
+                  , rollout_percentage = EXCLUDED.rollout_percentage
+                  , updated_at = EXCLUDED.updated_at
+            "#,
+            &name,
+            enabled,
+            rollout_percentage.map(i16::from),
+            updated_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(FeatureFlagOverride {
+            name,
+            enabled,
+            rollout_percentage,
+            updated_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.feature_flag.remove",
+        skip_all,
+        fields(
+            db.query.text,
+            feature_flag.name = %name,
+        ),
+        err,
+    )]
+    async fn remove(&mut self, name: &str) -> Result<bool, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM feature_flag_overrides
+                WHERE name = $1
+            "#,
+            name,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
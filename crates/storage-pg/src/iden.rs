@@ -14,6 +14,7 @@ pub enum UserSessions {
     CreatedAt,
     FinishedAt,
     UserAgent,
+    UserAgentDetails,
     LastActiveAt,
     LastActiveIp,
 }
@@ -26,6 +27,7 @@ pub enum Users {
     PrimaryUserEmailId,
     CreatedAt,
     LockedAt,
+    DeletedAt,
     CanRequestAdmin,
 }
 
@@ -39,6 +41,18 @@ pub enum UserEmails {
     ConfirmedAt,
 }
 
+#[derive(sea_query::Iden)]
+pub enum UserLoginAttempts {
+    Table,
+    UserLoginAttemptId,
+    UserId,
+    Method,
+    Succeeded,
+    IpAddress,
+    UserAgent,
+    CreatedAt,
+}
+
 #[derive(sea_query::Iden)]
 pub enum CompatSessions {
     Table,
@@ -50,6 +64,7 @@ pub enum CompatSessions {
     FinishedAt,
     IsSynapseAdmin,
     UserAgent,
+    UserAgentDetails,
     LastActiveAt,
     LastActiveIp,
 }
@@ -80,6 +95,7 @@ pub enum OAuth2Sessions {
     CreatedAt,
     FinishedAt,
     UserAgent,
+    UserAgentDetails,
     LastActiveAt,
     LastActiveIp,
 }
@@ -125,3 +141,17 @@ pub enum UpstreamOAuthLinks {
     HumanAccountName,
     CreatedAt,
 }
+
+#[derive(sea_query::Iden)]
+pub enum QueuedEmails {
+    Table,
+    QueuedEmailId,
+    Recipient,
+    Message,
+    CreatedAt,
+    Attempts,
+    NextAttemptAt,
+    LastError,
+    SentAt,
+    FailedAt,
+}
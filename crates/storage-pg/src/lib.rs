@@ -163,21 +163,37 @@
 use sqlx::migrate::Migrator;
 
 pub mod app_session;
+pub mod backfill;
 pub mod compat;
+pub mod device_key;
+pub mod email_queue;
+pub mod feature_flag;
 pub mod job;
+pub mod migrations;
 pub mod oauth2;
+pub mod partitions;
+pub mod stats;
 pub mod upstream_oauth2;
 pub mod user;
 
+#[cfg(test)]
+mod conformance;
 mod errors;
 pub(crate) mod filter;
 pub(crate) mod iden;
 pub(crate) mod pagination;
 pub(crate) mod repository;
+mod token_hash;
 pub(crate) mod tracing;
+pub(crate) mod user_agent;
 
 pub(crate) use self::errors::DatabaseInconsistencyError;
-pub use self::{errors::DatabaseError, repository::PgRepository, tracing::ExecuteExt};
+pub use self::{
+    errors::DatabaseError,
+    repository::{set_slow_query_threshold, PgRepository},
+    token_hash::TokenHasher,
+    tracing::ExecuteExt,
+};
 
 /// Embedded migrations, allowing them to run on startup
 pub static MIGRATOR: Migrator = {
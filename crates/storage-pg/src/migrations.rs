@@ -0,0 +1,523 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Tools around the database migrations: listing the pending ones, estimating
+//! how long they would lock large tables, and reverting the most recent ones.
+//!
+//! Down migrations live in the `down-migrations` directory, next to the
+//! `migrations` one, with the same file names. They are only provided for
+//! the migrations added since the last release.
+
+use std::borrow::Cow;
+
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migration, MigrationType},
+    PgConnection,
+};
+use thiserror::Error;
+
+use crate::MIGRATOR;
+
+/// Tables with more rows than this are considered large when estimating the
+/// impact of a migration
+pub const LARGE_TABLE_ROWS: i64 = 100_000;
+
+/// A migration reverting one of the [`MIGRATOR`] migrations
+#[derive(Debug, Clone, Copy)]
+pub struct DownMigration {
+    /// The version of the migration it reverts
+    pub version: i64,
+
+    /// The description of the migration it reverts
+    pub description: &'static str,
+
+    /// The SQL reverting the migration
+    pub sql: &'static str,
+}
+
+macro_rules! down_migration {
+    ($version:literal, $description:literal) => {
+        DownMigration {
+            version: $version,
+            description: $description,
+            sql: include_str!(concat!(
+                "../down-migrations/",
+                stringify!($version),
+                "_",
+                $description,
+                ".sql"
+            )),
+        }
+    };
+}
+
+/// The down migrations, oldest first
+// The versions are spelled like the migration file names
+#[allow(clippy::unreadable_literal)]
+pub static DOWN_MIGRATIONS: &[DownMigration] = &[
+    down_migration!(20241115163340, "upstream_oauth2_response_mode"),
+    down_migration!(20241118115314, "upstream_oauth2_extra_query_params"),
+    down_migration!(20241124145741, "upstream_oauth_userinfo"),
+    down_migration!(20241129091057, "upstream_oauth2_link_account_name"),
+    down_migration!(20241205102337, "dashboard_aggregates"),
+    down_migration!(20241206091512, "upstream_oauth_provider_health"),
+    down_migration!(20241209143022, "email_queue"),
+    down_migration!(20241211101543, "users_soft_delete"),
+    down_migration!(20241212093041, "device_keys"),
+    down_migration!(20241213104512, "oauth2_authorization_grants_fingerprint"),
+    down_migration!(
+        20241216093012,
+        "user_session_authentications_client_certificate"
+    ),
+    down_migration!(20241217091533, "user_session_limits"),
+    down_migration!(20241218102204, "user_timezones"),
+    down_migration!(20241219091045, "user_password_change_required"),
+    down_migration!(20241220103417, "users_registration_metadata"),
+    down_migration!(20241223094512, "background_migrations"),
+    down_migration!(20241224101530, "oauth2_token_hashes"),
+    down_migration!(20241226103000, "partitioned_oauth2_tables"),
+    down_migration!(20241227091530, "user_password_policy_classes"),
+    down_migration!(20241228103000, "session_human_names"),
+    down_migration!(20241229093000, "feature_flag_overrides"),
+    down_migration!(20241230091500, "experiment_exposures"),
+    down_migration!(20241231100000, "user_session_elevations"),
+    down_migration!(20250101100000, "user_login_attempts"),
+    down_migration!(20250102100000, "session_user_agent_details"),
+    down_migration!(20250103100000, "user_session_elevation_token_hashes"),
+];
+
+/// An error which happened while reverting migrations
+#[derive(Debug, Error)]
+pub enum RevertError {
+    /// A migration to revert has no down migration
+    #[error("Migration {0} can't be reverted, as it has no down migration")]
+    Unsupported(i64),
+
+    /// The target version is not one of the known migrations
+    #[error("Migration {0} is not a known migration")]
+    UnknownTarget(i64),
+
+    /// The database failed
+    #[error(transparent)]
+    Migrate(#[from] MigrateError),
+}
+
+/// List the versions of the migrations applied to the database
+async fn applied_versions(conn: &mut PgConnection) -> Result<Vec<i64>, MigrateError> {
+    // Don't create the migrations table if it doesn't exist, as this is also
+    // used in dry runs
+    let exists =
+        sqlx::query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "exists!""#)
+            .fetch_one(&mut *conn)
+            .await?;
+
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+/// List the migrations which are not applied to the database yet, oldest
+/// first
+///
+/// # Errors
+///
+/// Returns an error if the applied migrations could not be listed
+pub async fn pending_migrations(
+    conn: &mut PgConnection,
+) -> Result<Vec<&'static Migration>, MigrateError> {
+    let applied = applied_versions(conn).await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .collect())
+}
+
+/// List the down migrations needed to go back to the given version, newest
+/// first
+///
+/// # Errors
+///
+/// Returns an error if the target is not a known migration, or if one of the
+/// migrations to revert has no down migration
+pub async fn down_migrations_to(
+    conn: &mut PgConnection,
+    target: i64,
+) -> Result<Vec<&'static DownMigration>, RevertError> {
+    if !MIGRATOR.iter().any(|m| m.version == target) {
+        return Err(RevertError::UnknownTarget(target));
+    }
+
+    let mut applied = applied_versions(conn).await?;
+    applied.retain(|version| *version > target);
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    applied
+        .into_iter()
+        .map(|version| {
+            DOWN_MIGRATIONS
+                .iter()
+                .find(|down| down.version == version)
+                .ok_or(RevertError::Unsupported(version))
+        })
+        .collect()
+}
+
+/// Revert the migrations applied after the given version, newest first
+///
+/// Nothing is reverted if one of them has no down migration. Each migration
+/// is reverted in its own transaction.
+///
+/// # Errors
+///
+/// Returns an error if a migration can't be reverted, or if the database
+/// fails
+pub async fn revert_to(
+    conn: &mut PgConnection,
+    target: i64,
+) -> Result<Vec<&'static DownMigration>, RevertError> {
+    let downs = down_migrations_to(conn, target).await?;
+
+    conn.lock().await?;
+
+    for down in &downs {
+        tracing::info!(
+            version = down.version,
+            description = down.description,
+            "Reverting migration"
+        );
+
+        let migration = Migration::new(
+            down.version,
+            Cow::Borrowed(down.description),
+            MigrationType::ReversibleDown,
+            Cow::Borrowed(down.sql),
+            false,
+        );
+
+        if let Err(e) = conn.revert(&migration).await {
+            conn.unlock().await?;
+            return Err(e.into());
+        }
+    }
+
+    conn.unlock().await?;
+
+    Ok(downs)
+}
+
+/// How a statement locks a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// An exclusive lock is held for a short time, only the catalog is
+    /// updated
+    Brief,
+
+    /// An exclusive lock is held while the whole table is scanned
+    ScansTable,
+
+    /// An exclusive lock is held while the whole table is rewritten
+    RewritesTable,
+
+    /// Writes are blocked while an index is built
+    BuildsIndex,
+
+    /// Every row is updated or deleted
+    TouchesAllRows,
+}
+
+impl LockKind {
+    /// Whether the lock duration depends on the size of the table
+    #[must_use]
+    pub fn depends_on_size(self) -> bool {
+        !matches!(self, Self::Brief)
+    }
+
+    /// A human readable description of the lock
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Brief => "brief exclusive lock, only the catalog is updated",
+            Self::ScansTable => "exclusive lock while the whole table is scanned",
+            Self::RewritesTable => "exclusive lock while the whole table is rewritten",
+            Self::BuildsIndex => "writes blocked while the index is built",
+            Self::TouchesAllRows => "row locks on every updated or deleted row",
+        }
+    }
+}
+
+/// The estimated impact of a migration statement on an existing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockImpact {
+    /// The table locked by the statement
+    pub table: String,
+
+    /// How the table is locked
+    pub kind: LockKind,
+
+    /// The estimated number of rows in the table
+    pub estimated_rows: i64,
+
+    /// The size of the table and its indexes, in bytes
+    pub total_bytes: i64,
+}
+
+impl LockImpact {
+    /// Whether the lock is likely to be held long enough to disrupt the
+    /// service
+    #[must_use]
+    pub fn is_concerning(&self) -> bool {
+        self.kind.depends_on_size() && self.estimated_rows > LARGE_TABLE_ROWS
+    }
+}
+
+/// Split some SQL in statements, leaving out the comments
+///
+/// This doesn't try to understand the quoting, which is good enough for
+/// migrations.
+fn statements(sql: &str) -> Vec<String> {
+    let sql: String = sql
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    sql.split(';')
+        .map(|statement| {
+            statement
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_uppercase()
+        })
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Get the name of the table following the given keyword in a statement
+fn table_after<'a>(statement: &'a str, keyword: &str) -> Option<&'a str> {
+    let (_, rest) = statement.split_once(keyword)?;
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix("IF EXISTS ").unwrap_or(rest);
+    let rest = rest.strip_prefix("ONLY ").unwrap_or(rest);
+    let table = rest.split([' ', '(']).next()?;
+    Some(table.trim_matches('"'))
+}
+
+/// Find which table a statement locks, and how
+fn analyze_statement(statement: &str) -> Option<(String, LockKind)> {
+    if statement.starts_with("ALTER TABLE ") {
+        let table = table_after(statement, "ALTER TABLE ")?;
+        let kind = if statement.contains(" TYPE ") {
+            LockKind::RewritesTable
+        } else if statement.contains("SET NOT NULL")
+            || ((statement.contains("ADD CONSTRAINT")
+                || statement.contains("FOREIGN KEY")
+                || statement.contains("CHECK"))
+                && !statement.contains("NOT VALID"))
+        {
+            LockKind::ScansTable
+        } else {
+            LockKind::Brief
+        };
+
+        return Some((table.to_lowercase(), kind));
+    }
+
+    if statement.starts_with("CREATE INDEX ") || statement.starts_with("CREATE UNIQUE INDEX ") {
+        if statement.contains(" CONCURRENTLY ") {
+            return None;
+        }
+
+        let table = table_after(statement, " ON ")?;
+        return Some((table.to_lowercase(), LockKind::BuildsIndex));
+    }
+
+    if statement.starts_with("UPDATE ") {
+        let table = table_after(statement, "UPDATE ")?;
+        return Some((table.to_lowercase(), LockKind::TouchesAllRows));
+    }
+
+    if statement.starts_with("DELETE FROM ") {
+        let table = table_after(statement, "DELETE FROM ")?;
+        return Some((table.to_lowercase(), LockKind::TouchesAllRows));
+    }
+
+    None
+}
+
+/// Estimate how the statements of a migration lock the existing tables
+///
+/// Tables which don't exist yet, like the ones created earlier in the same
+/// migration, are left out.
+///
+/// # Errors
+///
+/// Returns an error if the table statistics could not be fetched
+// The only panic is in the type-checking code generated by `sqlx::query!`
+#[allow(clippy::missing_panics_doc)]
+pub async fn lock_impact(
+    conn: &mut PgConnection,
+    sql: &str,
+) -> Result<Vec<LockImpact>, sqlx::Error> {
+    let mut impacts = Vec::new();
+
+    for statement in statements(sql) {
+        let Some((table, kind)) = analyze_statement(&statement) else {
+            continue;
+        };
+
+        let stats = sqlx::query!(
+            r#"
+                SELECT GREATEST(c.reltuples, 0)::BIGINT AS "estimated_rows!"
+                     , pg_total_relation_size(c.oid) AS "total_bytes!"
+                FROM pg_class c
+                WHERE c.oid = to_regclass($1)
+            "#,
+            &table,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let Some(stats) = stats else {
+            continue;
+        };
+
+        impacts.push(LockImpact {
+            table,
+            kind,
+            estimated_rows: stats.estimated_rows,
+            total_bytes: stats.total_bytes,
+        });
+    }
+
+    Ok(impacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[test]
+    fn test_down_migrations_match() {
+        let ups: Vec<_> = MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .collect();
+
+        // Every down migration reverts an existing migration
+        for down in DOWN_MIGRATIONS {
+            let up = ups
+                .iter()
+                .find(|up| up.version == down.version)
+                .unwrap_or_else(|| panic!("No migration {}", down.version));
+            assert_eq!(up.description, down.description.replace('_', " "));
+        }
+
+        // Every migration more recent than the oldest down migration has one
+        let oldest = DOWN_MIGRATIONS.first().unwrap().version;
+        for up in ups.iter().filter(|up| up.version >= oldest) {
+            assert!(
+                DOWN_MIGRATIONS
+                    .iter()
+                    .any(|down| down.version == up.version),
+                "Migration {} has no down migration",
+                up.version
+            );
+        }
+    }
+
+    #[test]
+    fn test_analyze_statement() {
+        let analyze = |sql: &str| {
+            statements(sql)
+                .iter()
+                .filter_map(|statement| analyze_statement(statement))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            analyze(
+                r#"
+                -- Add a column
+                ALTER TABLE "user_passwords"
+                  ADD COLUMN "expires_at" TIMESTAMP WITH TIME ZONE;
+                "#
+            ),
+            vec![("user_passwords".to_owned(), LockKind::Brief)]
+        );
+
+        assert_eq!(
+            analyze(r#"ALTER TABLE ONLY "users" ALTER COLUMN "username" SET NOT NULL"#),
+            vec![("users".to_owned(), LockKind::ScansTable)]
+        );
+
+        assert_eq!(
+            analyze(r#"ALTER TABLE "users" ALTER COLUMN "sub" TYPE BYTEA"#),
+            vec![("users".to_owned(), LockKind::RewritesTable)]
+        );
+
+        assert_eq!(
+            analyze(
+                r#"
+                CREATE INDEX "users_deleted_at_idx" ON "users" ("deleted_at");
+                CREATE INDEX CONCURRENTLY "users_locked_at_idx" ON "users" ("locked_at");
+                CREATE TABLE "foo" ("id" UUID);
+                UPDATE "user_emails" SET "email" = LOWER("email");
+                "#
+            ),
+            vec![
+                ("users".to_owned(), LockKind::BuildsIndex),
+                ("user_emails".to_owned(), LockKind::TouchesAllRows),
+            ]
+        );
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_revert(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(pending_migrations(&mut conn).await.unwrap().is_empty());
+
+        // Reverting past the oldest down migration is refused
+        let too_old = MIGRATOR.iter().next().unwrap().version;
+        assert!(matches!(
+            revert_to(&mut conn, too_old).await,
+            Err(RevertError::Unsupported(_))
+        ));
+
+        // Revert everything which can be reverted
+        let oldest = DOWN_MIGRATIONS.first().unwrap().version;
+        let target = MIGRATOR
+            .iter()
+            .map(|m| m.version)
+            .filter(|version| *version < oldest)
+            .max()
+            .unwrap();
+        let reverted = revert_to(&mut conn, target).await.unwrap();
+        assert_eq!(reverted.len(), DOWN_MIGRATIONS.len());
+
+        let pending = pending_migrations(&mut conn).await.unwrap();
+        assert_eq!(pending.len(), DOWN_MIGRATIONS.len());
+
+        // The large tables are checked before migrating again
+        let migration = pending
+            .iter()
+            .find(|m| m.version == 20_241_220_103_417)
+            .unwrap();
+        let impact = lock_impact(&mut conn, &migration.sql).await.unwrap();
+        assert_eq!(impact.len(), 1);
+        assert_eq!(impact[0].table, "users");
+        assert!(!impact[0].is_concerning());
+
+        // And the migrations can be applied again
+        MIGRATOR.run(&mut conn).await.unwrap();
+        assert!(pending_migrations(&mut conn).await.unwrap().is_empty());
+    }
+}
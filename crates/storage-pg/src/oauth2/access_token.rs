@@ -4,35 +4,45 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::net::IpAddr;
+
 use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
-use mas_data_model::{AccessToken, AccessTokenState, Session};
-use mas_storage::{oauth2::OAuth2AccessTokenRepository, Clock};
+use mas_data_model::{AccessToken, AccessTokenState, Session, UserAgentDetails};
+use mas_storage::{
+    oauth2::{AccessTokenIntrospection, OAuth2AccessTokenRepository},
+    Clock,
+};
 use rand::RngCore;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use super::session::OAuthSessionLookup;
+use crate::{
+    token_hash::hash_token, tracing::ExecuteExt, user::UserLookup, DatabaseError, TokenHasher,
+};
 
 /// An implementation of [`OAuth2AccessTokenRepository`] for a PostgreSQL
 /// connection
 pub struct PgOAuth2AccessTokenRepository<'c> {
     conn: &'c mut PgConnection,
+    token_hasher: Option<&'c TokenHasher>,
 }
 
 impl<'c> PgOAuth2AccessTokenRepository<'c> {
     /// Create a new [`PgOAuth2AccessTokenRepository`] from an active PostgreSQL
     /// connection
-    pub fn new(conn: &'c mut PgConnection) -> Self {
-        Self { conn }
+    pub fn new(conn: &'c mut PgConnection, token_hasher: Option<&'c TokenHasher>) -> Self {
+        Self { conn, token_hasher }
     }
 }
 
+#[derive(sqlx::FromRow)]
 struct OAuth2AccessTokenLookup {
     oauth2_access_token_id: Uuid,
     oauth2_session_id: Uuid,
-    access_token: String,
+    access_token: Option<String>,
     created_at: DateTime<Utc>,
     expires_at: Option<DateTime<Utc>>,
     revoked_at: Option<DateTime<Utc>>,
@@ -49,13 +59,105 @@ impl From<OAuth2AccessTokenLookup> for AccessToken {
             id: value.oauth2_access_token_id.into(),
             state,
             session_id: value.oauth2_session_id.into(),
-            access_token: value.access_token,
+            // Only the hash of the new tokens is saved
+            access_token: value.access_token.unwrap_or_default(),
             created_at: value.created_at,
             expires_at: value.expires_at,
         }
     }
 }
 
+/// The access token, its session and its user, fetched in a single query for
+/// introspection
+struct IntrospectionLookup {
+    oauth2_access_token_id: Uuid,
+    access_token_created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+
+    oauth2_session_id: Uuid,
+    user_id: Option<Uuid>,
+    user_session_id: Option<Uuid>,
+    oauth2_client_id: Uuid,
+    scope_list: Vec<String>,
+    session_created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    user_agent: Option<String>,
+    user_agent_details: Option<Json<UserAgentDetails>>,
+    last_active_at: Option<DateTime<Utc>>,
+    last_active_ip: Option<IpAddr>,
+
+    username: Option<String>,
+    primary_user_email_id: Option<Uuid>,
+    user_created_at: Option<DateTime<Utc>>,
+    locked_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    can_request_admin: Option<bool>,
+
+    client_name: Option<String>,
+
+    has_active_refresh_token: bool,
+}
+
+impl TryFrom<IntrospectionLookup> for AccessTokenIntrospection {
+    type Error = DatabaseError;
+
+    fn try_from(value: IntrospectionLookup) -> Result<Self, Self::Error> {
+        let access_token = OAuth2AccessTokenLookup {
+            oauth2_access_token_id: value.oauth2_access_token_id,
+            oauth2_session_id: value.oauth2_session_id,
+            access_token: None,
+            created_at: value.access_token_created_at,
+            expires_at: value.expires_at,
+            revoked_at: value.revoked_at,
+        };
+
+        // The users table is left-joined, so the user columns are all null if the
+        // session has no user, or if the user doesn't exist anymore
+        let user = match (
+            value.user_id,
+            value.username,
+            value.user_created_at,
+            value.can_request_admin,
+        ) {
+            (Some(user_id), Some(username), Some(created_at), Some(can_request_admin)) => {
+                Some(UserLookup {
+                    user_id,
+                    username,
+                    primary_user_email_id: value.primary_user_email_id,
+                    created_at,
+                    locked_at: value.locked_at,
+                    deleted_at: value.deleted_at,
+                    can_request_admin,
+                })
+            }
+            _ => None,
+        };
+
+        let session = OAuthSessionLookup {
+            oauth2_session_id: value.oauth2_session_id,
+            user_id: value.user_id,
+            user_session_id: value.user_session_id,
+            oauth2_client_id: value.oauth2_client_id,
+            scope_list: value.scope_list,
+            created_at: value.session_created_at,
+            finished_at: value.finished_at,
+            user_agent: value.user_agent,
+            user_agent_details: value.user_agent_details,
+            last_active_at: value.last_active_at,
+            last_active_ip: value.last_active_ip,
+        };
+
+        Ok(Self {
+            access_token: access_token.into(),
+            session: session.try_into()?,
+            user: user.map(Into::into),
+            client_name: value.client_name,
+            has_active_refresh_token: value.has_active_refresh_token,
+        })
+    }
+}
+
 #[async_trait]
 impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
     type Error = DatabaseError;
@@ -97,6 +199,158 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
         &mut self,
         access_token: &str,
     ) -> Result<Option<AccessToken>, Self::Error> {
+        // The first part is answered from the covering index on the hash. The
+        // second one only runs if it found nothing, for the tokens which weren't
+        // hashed yet by the background backfill.
+        let res = sqlx::query_as!(
+            OAuth2AccessTokenLookup,
+            r#"
+                (
+                    SELECT oauth2_access_token_id AS "oauth2_access_token_id!"
+                         , NULL::TEXT AS access_token
+                         , created_at AS "created_at!"
+                         , expires_at
+                         , revoked_at
+                         , oauth2_session_id AS "oauth2_session_id!"
+                    FROM oauth2_access_tokens
+                    WHERE access_token_hash = $1
+                )
+                UNION ALL
+                (
+                    SELECT oauth2_access_token_id
+                         , NULL::TEXT AS access_token
+                         , created_at
+                         , expires_at
+                         , revoked_at
+                         , oauth2_session_id
+                    FROM oauth2_access_tokens
+                    WHERE access_token = $2
+                )
+                LIMIT 1
+            "#,
+            hash_token(self.token_hasher, access_token),
+            access_token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(AccessToken {
+            access_token: access_token.to_owned(),
+            ..res.into()
+        }))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.find_for_introspection",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn find_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<AccessTokenIntrospection>, Self::Error> {
+        // This is a single round-trip to the database. The statement is prepared
+        // once per connection and then cached by sqlx, so only the parameter has
+        // to be sent afterwards.
+        let res = sqlx::query_as!(
+            IntrospectionLookup,
+            r#"
+                SELECT t.oauth2_access_token_id AS "oauth2_access_token_id!"
+                     , t.created_at AS "access_token_created_at!"
+                     , t.expires_at
+                     , t.revoked_at
+
+                     , s.oauth2_session_id
+                     , s.user_id
+                     , s.user_session_id
+                     , s.oauth2_client_id
+                     , s.scope_list
+                     , s.created_at AS session_created_at
+                     , s.finished_at
+                     , s.user_agent
+                     , s.user_agent_details AS "user_agent_details: Json<UserAgentDetails>"
+                     , s.last_active_at
+                     , s.last_active_ip AS "last_active_ip: IpAddr"
+
+                     , u.username AS "username?"
+                     , u.primary_user_email_id
+                     , u.created_at AS "user_created_at?"
+                     , u.locked_at
+                     , u.deleted_at
+                     , u.can_request_admin AS "can_request_admin?"
+
+                     , c.client_name
+
+                     , EXISTS (
+                         SELECT 1
+                         FROM oauth2_refresh_tokens r
+                         WHERE r.oauth2_session_id = s.oauth2_session_id
+                           AND r.consumed_at IS NULL
+                       ) AS "has_active_refresh_token!"
+
+                FROM (
+                    (
+                        SELECT oauth2_access_token_id
+                             , oauth2_session_id
+                             , created_at
+                             , expires_at
+                             , revoked_at
+                        FROM oauth2_access_tokens
+                        WHERE access_token_hash = $1
+                    )
+                    UNION ALL
+                    (
+                        SELECT oauth2_access_token_id
+                             , oauth2_session_id
+                             , created_at
+                             , expires_at
+                             , revoked_at
+                        FROM oauth2_access_tokens
+                        WHERE access_token = $2
+                    )
+                    LIMIT 1
+                ) t
+                INNER JOIN oauth2_sessions s
+                  USING (oauth2_session_id)
+                LEFT JOIN users u
+                  ON u.user_id = s.user_id
+                INNER JOIN oauth2_clients c
+                  ON c.oauth2_client_id = s.oauth2_client_id
+            "#,
+            hash_token(self.token_hasher, access_token),
+            access_token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        let mut res: AccessTokenIntrospection = res.try_into()?;
+        access_token.clone_into(&mut res.access_token.access_token);
+        Ok(Some(res))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.list_active",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        session: &Session,
+    ) -> Result<Vec<AccessToken>, Self::Error> {
         let res = sqlx::query_as!(
             OAuth2AccessTokenLookup,
             r#"
@@ -109,16 +363,20 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
                 FROM oauth2_access_tokens
 
-                WHERE access_token = $1
+                WHERE oauth2_session_id = $1
+                  AND revoked_at IS NULL
+                  AND (expires_at IS NULL OR expires_at > $2)
+
+                ORDER BY oauth2_access_token_id
             "#,
-            access_token,
+            Uuid::from(session.id),
+            clock.now(),
         )
-        .fetch_optional(&mut *self.conn)
+        .traced()
+        .fetch_all(&mut *self.conn)
         .await?;
 
-        let Some(res) = res else { return Ok(None) };
-
-        Ok(Some(res.into()))
+        Ok(res.into_iter().map(Into::into).collect())
     }
 
     #[tracing::instrument(
@@ -146,20 +404,21 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
         tracing::Span::current().record("access_token.id", tracing::field::display(id));
 
+        // Only a hash of the token is saved
         sqlx::query!(
             r#"
                 INSERT INTO oauth2_access_tokens
-                    (oauth2_access_token_id, oauth2_session_id, access_token, created_at, expires_at)
+                    (oauth2_access_token_id, oauth2_session_id, access_token_hash, created_at, expires_at)
                 VALUES
                     ($1, $2, $3, $4, $5)
             "#,
             Uuid::from(id),
             Uuid::from(session.id),
-            &access_token,
+            hash_token(self.token_hasher, &access_token),
             created_at,
             expires_at,
         )
-            .traced()
+        .traced()
         .execute(&mut *self.conn)
         .await?;
 
@@ -199,7 +458,9 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
     }
 
     async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
-        // Cleanup token which expired more than 15 minutes ago
+        // Cleanup token which expired more than 15 minutes ago. Most of them are
+        // removed along with their partition, but this covers the partitions
+        // which can't be dropped yet, like the legacy and default ones.
         let threshold = clock.now() - Duration::microseconds(15 * 60 * 1000 * 1000);
         let res = sqlx::query!(
             r#"
@@ -9,7 +9,8 @@ use std::num::NonZeroU32;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, Pkce, Session,
+    AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, ClientFingerprint,
+    Pkce, Session,
 };
 use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_storage::{oauth2::OAuth2AuthorizationGrantRepository, Clock};
@@ -513,4 +514,79 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
 
         Ok(grant)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.set_fingerprint",
+        skip_all,
+        fields(
+            db.query.text,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn set_fingerprint(
+        &mut self,
+        grant: &AuthorizationGrant,
+        fingerprint: &ClientFingerprint,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants
+                SET fingerprint_ip_network = $2
+                  , fingerprint_user_agent_hash = $3
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+            fingerprint.ip_network.as_deref(),
+            fingerprint.user_agent_hash.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.get_fingerprint",
+        skip_all,
+        fields(
+            db.query.text,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn get_fingerprint(
+        &mut self,
+        grant: &AuthorizationGrant,
+    ) -> Result<Option<ClientFingerprint>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT fingerprint_ip_network
+                     , fingerprint_user_agent_hash
+                FROM oauth2_authorization_grants
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let fingerprint = res.and_then(|row| {
+            match (row.fingerprint_ip_network, row.fingerprint_user_agent_hash) {
+                (None, None) => None,
+                (ip_network, user_agent_hash) => Some(ClientFingerprint {
+                    ip_network,
+                    user_agent_hash,
+                }),
+            }
+        });
+
+        Ok(fingerprint)
+    }
 }
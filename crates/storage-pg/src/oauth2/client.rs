@@ -637,6 +637,50 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             .collect()
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_client.all",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn all(&mut self) -> Result<Vec<Client>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2ClientLookup,
+            r#"
+                SELECT oauth2_client_id
+                     , encrypted_client_secret
+                     , application_type
+                     , redirect_uris
+                     , grant_type_authorization_code
+                     , grant_type_refresh_token
+                     , grant_type_client_credentials
+                     , grant_type_device_code
+                     , client_name
+                     , logo_uri
+                     , client_uri
+                     , policy_uri
+                     , tos_uri
+                     , jwks_uri
+                     , jwks
+                     , id_token_signed_response_alg
+                     , userinfo_signed_response_alg
+                     , token_endpoint_auth_method
+                     , token_endpoint_auth_signing_alg
+                     , initiate_login_uri
+                FROM oauth2_clients c
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| r.try_into().map_err(DatabaseError::from))
+            .collect()
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_client.get_consent_for_user",
         skip_all,
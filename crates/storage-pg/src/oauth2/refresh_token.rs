@@ -13,25 +13,27 @@ use sqlx::PgConnection;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use crate::{token_hash::hash_token, tracing::ExecuteExt, DatabaseError, TokenHasher};
 
 /// An implementation of [`OAuth2RefreshTokenRepository`] for a PostgreSQL
 /// connection
 pub struct PgOAuth2RefreshTokenRepository<'c> {
     conn: &'c mut PgConnection,
+    token_hasher: Option<&'c TokenHasher>,
 }
 
 impl<'c> PgOAuth2RefreshTokenRepository<'c> {
     /// Create a new [`PgOAuth2RefreshTokenRepository`] from an active
     /// PostgreSQL connection
-    pub fn new(conn: &'c mut PgConnection) -> Self {
-        Self { conn }
+    pub fn new(conn: &'c mut PgConnection, token_hasher: Option<&'c TokenHasher>) -> Self {
+        Self { conn, token_hasher }
     }
 }
 
+#[derive(sqlx::FromRow)]
 struct OAuth2RefreshTokenLookup {
     oauth2_refresh_token_id: Uuid,
-    refresh_token: String,
+    refresh_token: Option<String>,
     created_at: DateTime<Utc>,
     consumed_at: Option<DateTime<Utc>>,
     oauth2_access_token_id: Option<Uuid>,
@@ -49,7 +51,8 @@ impl From<OAuth2RefreshTokenLookup> for RefreshToken {
             id: value.oauth2_refresh_token_id.into(),
             state,
             session_id: value.oauth2_session_id.into(),
-            refresh_token: value.refresh_token,
+            // Only the hash of the new tokens is saved
+            refresh_token: value.refresh_token.unwrap_or_default(),
             created_at: value.created_at,
             access_token_id: value.oauth2_access_token_id.map(Ulid::from),
         }
@@ -85,6 +88,7 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
             "#,
             Uuid::from(id),
         )
+        .traced()
         .fetch_optional(&mut *self.conn)
         .await?;
 
@@ -105,6 +109,60 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         &mut self,
         refresh_token: &str,
     ) -> Result<Option<RefreshToken>, Self::Error> {
+        // The first part is answered from the covering index on the hash. The
+        // second one only runs if it found nothing, for the tokens which weren't
+        // hashed yet by the background backfill.
+        let res = sqlx::query_as!(
+            OAuth2RefreshTokenLookup,
+            r#"
+                (
+                    SELECT oauth2_refresh_token_id AS "oauth2_refresh_token_id!"
+                         , NULL::TEXT AS refresh_token
+                         , created_at AS "created_at!"
+                         , consumed_at
+                         , oauth2_access_token_id
+                         , oauth2_session_id AS "oauth2_session_id!"
+                    FROM oauth2_refresh_tokens
+                    WHERE refresh_token_hash = $1
+                )
+                UNION ALL
+                (
+                    SELECT oauth2_refresh_token_id
+                         , NULL::TEXT AS refresh_token
+                         , created_at
+                         , consumed_at
+                         , oauth2_access_token_id
+                         , oauth2_session_id
+                    FROM oauth2_refresh_tokens
+                    WHERE refresh_token = $2
+                )
+                LIMIT 1
+            "#,
+            hash_token(self.token_hasher, refresh_token),
+            refresh_token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(RefreshToken {
+            refresh_token: refresh_token.to_owned(),
+            ..res.into()
+        }))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_refresh_token.list_active",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn list_active(&mut self, session: &Session) -> Result<Vec<RefreshToken>, Self::Error> {
         let res = sqlx::query_as!(
             OAuth2RefreshTokenLookup,
             r#"
@@ -116,17 +174,18 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
                      , oauth2_session_id
                 FROM oauth2_refresh_tokens
 
-                WHERE refresh_token = $1
+                WHERE oauth2_session_id = $1
+                  AND consumed_at IS NULL
+
+                ORDER BY oauth2_refresh_token_id
             "#,
-            refresh_token,
+            Uuid::from(session.id),
         )
         .traced()
-        .fetch_optional(&mut *self.conn)
+        .fetch_all(&mut *self.conn)
         .await?;
 
-        let Some(res) = res else { return Ok(None) };
-
-        Ok(Some(res.into()))
+        Ok(res.into_iter().map(Into::into).collect())
     }
 
     #[tracing::instrument(
@@ -156,14 +215,14 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
             r#"
                 INSERT INTO oauth2_refresh_tokens
                     (oauth2_refresh_token_id, oauth2_session_id, oauth2_access_token_id,
-                     refresh_token, created_at)
+                     refresh_token_hash, created_at)
                 VALUES
                     ($1, $2, $3, $4, $5)
             "#,
             Uuid::from(id),
             Uuid::from(session.id),
             Uuid::from(access_token.id),
-            refresh_token,
+            hash_token(self.token_hasher, &refresh_token),
             created_at,
         )
         .traced()
@@ -8,7 +8,9 @@ use std::net::IpAddr;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{BrowserSession, Client, Session, SessionState, User, UserAgent};
+use mas_data_model::{
+    BrowserSession, Client, Session, SessionState, User, UserAgent, UserAgentDetails,
+};
 use mas_storage::{
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
     Clock, Page, Pagination,
@@ -17,7 +19,7 @@ use oauth2_types::scope::{Scope, ScopeToken};
 use rand::RngCore;
 use sea_query::{enum_def, extension::postgres::PgExpr, Expr, PgFunc, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use uuid::Uuid;
 
@@ -26,6 +28,7 @@ use crate::{
     iden::OAuth2Sessions,
     pagination::QueryBuilderExt,
     tracing::ExecuteExt,
+    user_agent::load_user_agent,
     DatabaseError, DatabaseInconsistencyError,
 };
 
@@ -44,17 +47,18 @@ impl<'c> PgOAuth2SessionRepository<'c> {
 
 #[derive(sqlx::FromRow)]
 #[enum_def]
-struct OAuthSessionLookup {
-    oauth2_session_id: Uuid,
-    user_id: Option<Uuid>,
-    user_session_id: Option<Uuid>,
-    oauth2_client_id: Uuid,
-    scope_list: Vec<String>,
-    created_at: DateTime<Utc>,
-    finished_at: Option<DateTime<Utc>>,
-    user_agent: Option<String>,
-    last_active_at: Option<DateTime<Utc>>,
-    last_active_ip: Option<IpAddr>,
+pub(super) struct OAuthSessionLookup {
+    pub(super) oauth2_session_id: Uuid,
+    pub(super) user_id: Option<Uuid>,
+    pub(super) user_session_id: Option<Uuid>,
+    pub(super) oauth2_client_id: Uuid,
+    pub(super) scope_list: Vec<String>,
+    pub(super) created_at: DateTime<Utc>,
+    pub(super) finished_at: Option<DateTime<Utc>>,
+    pub(super) user_agent: Option<String>,
+    pub(super) user_agent_details: Option<Json<UserAgentDetails>>,
+    pub(super) last_active_at: Option<DateTime<Utc>>,
+    pub(super) last_active_ip: Option<IpAddr>,
 }
 
 impl TryFrom<OAuthSessionLookup> for Session {
@@ -87,7 +91,7 @@ impl TryFrom<OAuthSessionLookup> for Session {
             user_id: value.user_id.map(Ulid::from),
             user_session_id: value.user_session_id.map(Ulid::from),
             scope,
-            user_agent: value.user_agent.map(UserAgent::parse),
+            user_agent: load_user_agent(value.user_agent, value.user_agent_details),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
         })
@@ -161,6 +165,7 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                      , created_at
                      , finished_at
                      , user_agent
+                     , user_agent_details AS "user_agent_details: Json<UserAgentDetails>"
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
                 FROM oauth2_sessions
@@ -352,6 +357,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserAgent)),
                 OAuthSessionLookupIden::UserAgent,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserAgentDetails)),
+                OAuthSessionLookupIden::UserAgentDetails,
+            )
             .expr_as(
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveAt)),
                 OAuthSessionLookupIden::LastActiveAt,
@@ -471,10 +480,12 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             r#"
                 UPDATE oauth2_sessions
                 SET user_agent = $2
+                  , user_agent_details = $3
                 WHERE oauth2_session_id = $1
             "#,
             Uuid::from(session.id),
             &*user_agent,
+            Json(user_agent.details()) as _,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -486,4 +497,100 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
 
         Ok(session)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.set_scope",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            session.old_scope = %session.scope,
+            session.scope = %scope,
+            client.id = %session.client_id,
+        ),
+        err,
+    )]
+    async fn set_scope(
+        &mut self,
+        mut session: Session,
+        scope: Scope,
+    ) -> Result<Session, Self::Error> {
+        let scope_list: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET scope_list = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            &scope_list,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        session.scope = scope;
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.human_name",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn human_name(&mut self, session: &Session) -> Result<Option<String>, Self::Error> {
+        let human_name = sqlx::query_scalar!(
+            r#"
+                SELECT human_name
+                FROM oauth2_sessions
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(human_name)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.set_human_name",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            session.human_name = human_name,
+        ),
+        err,
+    )]
+    async fn set_human_name(
+        &mut self,
+        session: &Session,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET human_name = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            human_name,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,540 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Tables partitioned by day, so that their old rows are removed by dropping
+//! whole partitions instead of deleting them one by one.
+//!
+//! The tables are partitioned on their ULID primary key, which starts with the
+//! creation time of the row, so that each partition covers a day (UTC). The
+//! partitions are created ahead of time and dropped once they are past their
+//! retention by a background job, with [`create_partitions`] and
+//! [`drop_partitions`]. Rows which don't fit in any partition, if the job
+//! didn't run in time, go to a default partition, which is never dropped.
+//!
+//! The statements creating and dropping partitions can't be checked at compile
+//! time, as the tables they work on are only known at runtime.
+
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use mas_storage::Clock;
+use sqlx::{Acquire, PgConnection};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::ExecuteExt;
+
+/// A table partitioned by day
+#[derive(Debug, Clone, Copy)]
+pub struct PartitionedTable {
+    /// The name of the partitioned table
+    pub table: &'static str,
+
+    /// The ULID column the table is partitioned on
+    pub key: &'static str,
+
+    /// How long a partition is kept after the end of the day it covers
+    pub retention: std::time::Duration,
+
+    /// A query checking whether a partition still has rows to keep after its
+    /// retention, with the current time as `$1`. `{partition}` is replaced by
+    /// the name of the partition.
+    pub in_use: Option<&'static str>,
+
+    /// The tables and columns referencing the table with `ON DELETE SET NULL`.
+    /// The references to a partition are cleared before dropping it, as
+    /// dropping it doesn't trigger the foreign key actions.
+    pub referenced_by: &'static [(&'static str, &'static str)],
+}
+
+/// The partitioned tables
+pub static PARTITIONED_TABLES: &[PartitionedTable] = &[
+    PartitionedTable {
+        table: "oauth2_access_tokens",
+        key: "oauth2_access_token_id",
+        retention: std::time::Duration::ZERO,
+        // Tokens are kept until 15 minutes after they expired, and some never
+        // expire
+        in_use: Some(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM "{partition}"
+                    WHERE expires_at IS NULL
+                       OR expires_at >= $1 - INTERVAL '15 minutes'
+                )
+            "#,
+        ),
+        referenced_by: &[("oauth2_refresh_tokens", "oauth2_access_token_id")],
+    },
+    PartitionedTable {
+        table: "oauth2_authorization_grants",
+        key: "oauth2_authorization_grant_id",
+        // The grants are only useful until the code is exchanged, which is
+        // done within minutes
+        retention: std::time::Duration::from_secs(7 * 24 * 60 * 60),
+        in_use: None,
+        referenced_by: &[],
+    },
+];
+
+/// A partition of a [`PartitionedTable`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// The name of the partition
+    pub name: String,
+
+    /// The start of the time range covered by the partition, `None` if it
+    /// has no lower bound
+    pub from: Option<DateTime<Utc>>,
+
+    /// The end of the time range covered by the partition
+    pub to: DateTime<Utc>,
+}
+
+/// The smallest ULID of the given time, used as a partition bound
+fn bound(time: DateTime<Utc>) -> Uuid {
+    let millis = u64::try_from(time.timestamp_millis()).unwrap_or_default();
+    Ulid::from_parts(millis, 0).into()
+}
+
+fn bound_time(bound: Uuid) -> DateTime<Utc> {
+    Ulid::from(bound).datetime().into()
+}
+
+/// List the partitions of a table, ordered by time range
+///
+/// # Errors
+///
+/// Returns an error if the database fails
+#[tracing::instrument(
+    name = "db.partitions.list",
+    skip_all,
+    fields(
+        db.query.text,
+        partitioned_table.name = table.table,
+    ),
+    err,
+)]
+pub async fn list_partitions(
+    conn: &mut PgConnection,
+    table: &PartitionedTable,
+) -> Result<Vec<Partition>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT c.relname::TEXT AS "name!"
+                 , substring(pg_get_expr(c.relpartbound, c.oid) FROM 'FROM \(''([0-9a-f-]+)''\)')::UUID AS "from?"
+                 , substring(pg_get_expr(c.relpartbound, c.oid) FROM 'TO \(''([0-9a-f-]+)''\)')::UUID AS "to?"
+            FROM pg_inherits i
+            INNER JOIN pg_class c
+              ON c.oid = i.inhrelid
+            WHERE i.inhparent = to_regclass($1)
+        "#,
+        table.table,
+    )
+    .traced()
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // The default partition has no bounds, and is left out
+    let mut partitions: Vec<Partition> = rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Partition {
+                name: row.name,
+                from: row.from.map(bound_time),
+                to: bound_time(row.to?),
+            })
+        })
+        .collect();
+    partitions.sort_by_key(|partition| partition.to);
+
+    Ok(partitions)
+}
+
+/// Create the partitions of a table for the days up to `days_ahead` days from
+/// now, and return their names
+///
+/// If rows went to the default partition past the last partition, the
+/// partitions start on the day after the last of them, as a partition can't be
+/// created over rows of the default partition.
+///
+/// # Errors
+///
+/// Returns an error if the database fails
+#[tracing::instrument(
+    name = "db.partitions.create",
+    skip_all,
+    fields(
+        db.query.text,
+        partitioned_table.name = table.table,
+    ),
+    err,
+)]
+pub async fn create_partitions(
+    conn: &mut PgConnection,
+    clock: &dyn Clock,
+    table: &PartitionedTable,
+    days_ahead: u32,
+) -> Result<Vec<String>, sqlx::Error> {
+    let horizon = clock.now() + Duration::try_days(days_ahead.into()).unwrap();
+    let mut txn = conn.begin().await?;
+
+    // Creating a partition locks the table, so give up instead of waiting
+    // behind long queries and blocking everything else
+    sqlx::query!("SET LOCAL lock_timeout = '5s'")
+        .execute(&mut *txn)
+        .await?;
+
+    let partitions = list_partitions(&mut txn, table).await?;
+    let Some(last) = partitions.last() else {
+        // The table is not partitioned
+        return Ok(Vec::new());
+    };
+
+    // Rows past the last partition are in the default partition
+    let in_default: Option<Uuid> = sqlx::query_scalar(&format!(
+        r#"SELECT "{key}" FROM "{table}" WHERE "{key}" >= $1 ORDER BY "{key}" DESC LIMIT 1"#,
+        key = table.key,
+        table = table.table,
+    ))
+    .bind(bound(last.to))
+    .traced()
+    .fetch_optional(&mut *txn)
+    .await?;
+
+    let mut from = last.to;
+    if let Some(in_default) = in_default {
+        let day = bound_time(in_default).date_naive().and_time(NaiveTime::MIN);
+        from = from.max(day.and_utc() + Duration::try_days(1).unwrap());
+    }
+
+    let mut created = Vec::new();
+    while from < horizon {
+        let to = from + Duration::try_days(1).unwrap();
+        let name = format!("{}_p{}", table.table, from.format("%Y%m%d"));
+
+        sqlx::query(&format!(
+            r#"CREATE TABLE "{name}" PARTITION OF "{table}" FOR VALUES FROM ('{from}') TO ('{to}')"#,
+            table = table.table,
+            from = bound(from),
+            to = bound(to),
+        ))
+        .traced()
+        .execute(&mut *txn)
+        .await?;
+
+        created.push(name);
+        from = to;
+    }
+
+    txn.commit().await?;
+
+    Ok(created)
+}
+
+/// Drop the partitions of a table which are past their retention, and return
+/// their names
+///
+/// # Errors
+///
+/// Returns an error if the database fails
+#[tracing::instrument(
+    name = "db.partitions.drop",
+    skip_all,
+    fields(
+        db.query.text,
+        partitioned_table.name = table.table,
+    ),
+    err,
+)]
+pub async fn drop_partitions(
+    conn: &mut PgConnection,
+    clock: &dyn Clock,
+    table: &PartitionedTable,
+) -> Result<Vec<String>, sqlx::Error> {
+    let now = clock.now();
+    let threshold = now - Duration::from_std(table.retention).unwrap();
+    let mut txn = conn.begin().await?;
+
+    sqlx::query!("SET LOCAL lock_timeout = '5s'")
+        .execute(&mut *txn)
+        .await?;
+
+    let partitions = list_partitions(&mut txn, table).await?;
+
+    // The last partition is always kept, as the next ones are created after it
+    let count = partitions.len().saturating_sub(1);
+
+    let mut dropped = Vec::new();
+    for partition in partitions.into_iter().take(count) {
+        // Partitions are ordered by time range, so the next ones are not past
+        // their retention either
+        if partition.to > threshold {
+            break;
+        }
+
+        if let Some(in_use) = table.in_use {
+            let in_use: bool = sqlx::query_scalar(&in_use.replace("{partition}", &partition.name))
+                .bind(now)
+                .traced()
+                .fetch_one(&mut *txn)
+                .await?;
+
+            if in_use {
+                continue;
+            }
+        }
+
+        // A partition can't be detached while rows reference it
+        for (referencing, column) in table.referenced_by {
+            sqlx::query(&format!(
+                r#"UPDATE "{referencing}" SET "{column}" = NULL WHERE "{column}" >= $1 AND "{column}" < $2"#,
+            ))
+            .bind(partition.from.map_or(Uuid::nil(), bound))
+            .bind(bound(partition.to))
+            .traced()
+            .execute(&mut *txn)
+            .await?;
+        }
+
+        sqlx::query(&format!(
+            r#"ALTER TABLE "{table}" DETACH PARTITION "{partition}""#,
+            table = table.table,
+            partition = partition.name,
+        ))
+        .traced()
+        .execute(&mut *txn)
+        .await?;
+
+        sqlx::query(&format!(r#"DROP TABLE "{}""#, partition.name))
+            .traced()
+            .execute(&mut *txn)
+            .await?;
+
+        dropped.push(partition.name);
+    }
+
+    txn.commit().await?;
+
+    Ok(dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::{clock::MockClock, RepositoryAccess};
+    use oauth2_types::{
+        requests::GrantType,
+        scope::{Scope, OPENID},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_partitions(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        // The partitions are created relative to the current date
+        #[allow(clippy::disallowed_methods)]
+        let clock = MockClock::new(Utc::now());
+        let mut conn = pool.acquire().await.unwrap();
+        let table = &PARTITIONED_TABLES[0];
+
+        // Only the legacy partition exists after the migration
+        let partitions = list_partitions(&mut conn, table).await.unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!(partitions[0].name, "oauth2_access_tokens_legacy");
+        assert_eq!(partitions[0].from, None);
+        assert!(partitions[0].to > clock.now() + Duration::try_days(1).unwrap());
+
+        let created = create_partitions(&mut conn, &clock, table, 7)
+            .await
+            .unwrap();
+        assert!(!created.is_empty());
+        let partitions = list_partitions(&mut conn, table).await.unwrap();
+        assert_eq!(partitions.len(), created.len() + 1);
+        assert!(partitions.last().unwrap().to >= clock.now() + Duration::try_days(7).unwrap());
+        for pair in partitions.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from.unwrap());
+        }
+
+        // Running it again doesn't create anything
+        let created = create_partitions(&mut conn, &clock, table, 7)
+            .await
+            .unwrap();
+        assert!(created.is_empty());
+
+        // Create two tokens in the partitions, one which never expires
+        clock.advance(Duration::try_days(3).unwrap());
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                Vec::new(),
+                None,
+                None,
+                vec![GrantType::ClientCredentials],
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .add_from_client_credentials(&mut rng, &clock, &client, Scope::from_iter([OPENID]))
+            .await
+            .unwrap();
+        let permanent = repo
+            .oauth2_access_token()
+            .add(&mut rng, &clock, &session, "permanent".to_owned(), None)
+            .await
+            .unwrap();
+        clock.advance(Duration::try_days(1).unwrap());
+        let expiring = repo
+            .oauth2_access_token()
+            .add(
+                &mut rng,
+                &clock,
+                &session,
+                "expiring".to_owned(),
+                Some(Duration::try_minutes(5).unwrap()),
+            )
+            .await
+            .unwrap();
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .add(&mut rng, &clock, &session, &expiring, "refresh".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Once past their retention, the partitions are dropped, except the one
+        // with the token which never expires
+        clock.advance(Duration::try_days(10).unwrap());
+        let dropped = drop_partitions(&mut conn, &clock, table).await.unwrap();
+        assert!(dropped.contains(&"oauth2_access_tokens_legacy".to_owned()));
+        // The last partition is kept as well, to create the next ones after it
+        let partitions = list_partitions(&mut conn, table).await.unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert!(partitions[0].from.unwrap() <= permanent.created_at);
+        assert!(partitions[0].to > permanent.created_at);
+
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        let token = repo
+            .oauth2_access_token()
+            .lookup(permanent.id)
+            .await
+            .unwrap();
+        assert!(token.is_some());
+        let token = repo
+            .oauth2_access_token()
+            .lookup(expiring.id)
+            .await
+            .unwrap();
+        assert!(token.is_none());
+
+        // The refresh token doesn't point to the dropped access token anymore
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .lookup(refresh_token.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(refresh_token.access_token_id, None);
+        repo.cancel().await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_default_partition(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        // The partitions are created relative to the current date
+        #[allow(clippy::disallowed_methods)]
+        let clock = MockClock::new(Utc::now());
+        let mut conn = pool.acquire().await.unwrap();
+        let table = &PARTITIONED_TABLES[0];
+
+        // Without the partitions of the next days, the tokens go to the default
+        // partition
+        clock.advance(Duration::try_days(5).unwrap());
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                Vec::new(),
+                None,
+                None,
+                vec![GrantType::ClientCredentials],
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .add_from_client_credentials(&mut rng, &clock, &client, Scope::from_iter([OPENID]))
+            .await
+            .unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .add(&mut rng, &clock, &session, "token".to_owned(), None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The tokens are still unique across partitions
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        clock.advance(Duration::try_days(1).unwrap());
+        let res = repo
+            .oauth2_access_token()
+            .add(&mut rng, &clock, &session, "token".to_owned(), None)
+            .await;
+        assert!(res.is_err());
+        repo.cancel().await.unwrap();
+
+        // The partitions start after the rows of the default partition
+        create_partitions(&mut conn, &clock, table, 7)
+            .await
+            .unwrap();
+        let partitions = list_partitions(&mut conn, table).await.unwrap();
+        assert_eq!(partitions[0].name, "oauth2_access_tokens_legacy");
+        assert!(partitions[0].to < token.created_at);
+        assert!(partitions[1].from.unwrap() > token.created_at);
+        assert!(partitions.last().unwrap().to >= clock.now() + Duration::try_days(7).unwrap());
+
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        let found = repo
+            .oauth2_access_token()
+            .find_by_token("token")
+            .await
+            .unwrap();
+        assert_eq!(found.map(|t| t.id), Some(token.id));
+        repo.cancel().await.unwrap();
+    }
+}
@@ -4,7 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
 
 use futures_util::{future::BoxFuture, FutureExt, TryFutureExt};
 use mas_storage::{
@@ -13,17 +20,26 @@ use mas_storage::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
+    device_key::DeviceKeyRepository,
+    email_queue::EmailQueueRepository,
+    feature_flag::FeatureFlagRepository,
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
         OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
+    stats::StatsRepository,
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
     user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
-    BoxRepository, MapErr, Repository, RepositoryAccess, RepositoryError, RepositoryTransaction,
+    BoxRepository, Instrumented, MapErr, Repository, RepositoryAccess, RepositoryError,
+    RepositoryMetrics, RepositoryTransaction,
+};
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
 };
 use sqlx::{PgConnection, PgPool, Postgres, Transaction};
 use tracing::Instrument;
@@ -34,27 +50,77 @@ use crate::{
         PgCompatAccessTokenRepository, PgCompatRefreshTokenRepository, PgCompatSessionRepository,
         PgCompatSsoLoginRepository,
     },
+    device_key::PgDeviceKeyRepository,
+    email_queue::PgEmailQueueRepository,
+    feature_flag::PgFeatureFlagRepository,
     job::PgJobRepository,
     oauth2::{
         PgOAuth2AccessTokenRepository, PgOAuth2AuthorizationGrantRepository,
         PgOAuth2ClientRepository, PgOAuth2DeviceCodeGrantRepository,
         PgOAuth2RefreshTokenRepository, PgOAuth2SessionRepository,
     },
+    stats::PgStatsRepository,
     upstream_oauth2::{
         PgUpstreamOAuthLinkRepository, PgUpstreamOAuthProviderRepository,
         PgUpstreamOAuthSessionRepository,
     },
     user::{
-        PgBrowserSessionRepository, PgUserEmailRepository, PgUserPasswordRepository,
-        PgUserRecoveryRepository, PgUserRepository, PgUserTermsRepository,
+        PgBrowserSessionRepository, PgUserEmailRepository, PgUserLoginHistoryRepository,
+        PgUserPasswordRepository, PgUserRecoveryRepository, PgUserRepository,
+        PgUserTermsRepository,
     },
-    DatabaseError,
+    DatabaseError, TokenHasher,
 };
 
+static METER: LazyLock<Meter> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+});
+
+static DATABASE_ERRORS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    METER
+        .u64_counter("db.client.errors")
+        .with_unit("{errors}")
+        .with_description("Number of database errors, including pool acquisition timeouts")
+        .init()
+});
+
+/// Count the error in the metrics, by kind
+fn record_error(error: DatabaseError) -> DatabaseError {
+    DATABASE_ERRORS.add(1, &[KeyValue::new("error.type", error.kind())]);
+    error
+}
+
+/// The threshold above which repository calls are logged as slow, in
+/// milliseconds. Zero means slow calls are not logged.
+static SLOW_QUERY_THRESHOLD_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Set the threshold above which repository calls are logged as slow
+///
+/// This applies to all the repositories created through
+/// [`PgRepository::boxed`] afterwards. Passing `None` disables the logging.
+pub fn set_slow_query_threshold(threshold: Option<Duration>) {
+    let threshold_ms = threshold.map_or(0, |t| u64::try_from(t.as_millis()).unwrap_or(u64::MAX));
+    SLOW_QUERY_THRESHOLD_MS.store(threshold_ms, Ordering::Relaxed);
+}
+
+fn repository_metrics() -> RepositoryMetrics {
+    let metrics = RepositoryMetrics::new();
+    match SLOW_QUERY_THRESHOLD_MS.load(Ordering::Relaxed) {
+        0 => metrics,
+        threshold_ms => metrics.with_slow_threshold(Duration::from_millis(threshold_ms)),
+    }
+}
+
 /// An implementation of the [`Repository`] trait backed by a PostgreSQL
 /// transaction.
 pub struct PgRepository<C = Transaction<'static, Postgres>> {
     conn: C,
+    token_hasher: Option<TokenHasher>,
 }
 
 impl PgRepository {
@@ -65,13 +131,21 @@ impl PgRepository {
     ///
     /// Returns a [`DatabaseError`] if the transaction could not be started.
     pub async fn from_pool(pool: &PgPool) -> Result<Self, DatabaseError> {
-        let txn = pool.begin().await?;
+        let txn = pool.begin().await.map_err(|e| record_error(e.into()))?;
         Ok(Self::from_conn(txn))
     }
 
     /// Transform the repository into a type-erased [`BoxRepository`]
+    ///
+    /// The calls made through the returned repository are timed and counted
+    /// in the metrics, and logged if they are slower than the threshold set
+    /// with [`set_slow_query_threshold`].
+    #[must_use]
     pub fn boxed(self) -> BoxRepository {
-        Box::new(MapErr::new(self, RepositoryError::from_error))
+        Box::new(Instrumented::new(
+            MapErr::new(self, |e| RepositoryError::from_error(record_error(e))),
+            repository_metrics(),
+        ))
     }
 }
 
@@ -79,7 +153,19 @@ impl<C> PgRepository<C> {
     /// Create a new [`PgRepository`] from an existing PostgreSQL connection
     /// with a transaction
     pub fn from_conn(conn: C) -> Self {
-        PgRepository { conn }
+        PgRepository {
+            conn,
+            token_hasher: None,
+        }
+    }
+
+    /// Set the [`TokenHasher`] used to save and look up the OAuth 2.0 tokens
+    ///
+    /// Saving or looking up a token through a repository without one panics.
+    #[must_use]
+    pub fn with_token_hasher(mut self, token_hasher: TokenHasher) -> Self {
+        self.token_hasher = Some(token_hasher);
+        self
     }
 
     /// Consume this [`PgRepository`], returning the underlying connection.
@@ -188,10 +274,19 @@ where
         Box::new(PgUserTermsRepository::new(self.conn.as_mut()))
     }
 
+    fn user_login_history<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserLoginHistoryRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserLoginHistoryRepository::new(self.conn.as_mut()))
+    }
+
     fn browser_session<'c>(
         &'c mut self,
     ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
-        Box::new(PgBrowserSessionRepository::new(self.conn.as_mut()))
+        Box::new(PgBrowserSessionRepository::new(
+            self.conn.as_mut(),
+            self.token_hasher.as_ref(),
+        ))
     }
 
     fn app_session<'c>(&'c mut self) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
@@ -221,13 +316,19 @@ where
     fn oauth2_access_token<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2AccessTokenRepository<Error = Self::Error> + 'c> {
-        Box::new(PgOAuth2AccessTokenRepository::new(self.conn.as_mut()))
+        Box::new(PgOAuth2AccessTokenRepository::new(
+            self.conn.as_mut(),
+            self.token_hasher.as_ref(),
+        ))
     }
 
     fn oauth2_refresh_token<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2RefreshTokenRepository<Error = Self::Error> + 'c> {
-        Box::new(PgOAuth2RefreshTokenRepository::new(self.conn.as_mut()))
+        Box::new(PgOAuth2RefreshTokenRepository::new(
+            self.conn.as_mut(),
+            self.token_hasher.as_ref(),
+        ))
     }
 
     fn oauth2_device_code_grant<'c>(
@@ -260,6 +361,22 @@ where
         Box::new(PgCompatRefreshTokenRepository::new(self.conn.as_mut()))
     }
 
+    fn stats<'c>(&'c mut self) -> Box<dyn StatsRepository<Error = Self::Error> + 'c> {
+        Box::new(PgStatsRepository::new(self.conn.as_mut()))
+    }
+
+    fn device_key<'c>(&'c mut self) -> Box<dyn DeviceKeyRepository<Error = Self::Error> + 'c> {
+        Box::new(PgDeviceKeyRepository::new(self.conn.as_mut()))
+    }
+
+    fn email_queue<'c>(&'c mut self) -> Box<dyn EmailQueueRepository<Error = Self::Error> + 'c> {
+        Box::new(PgEmailQueueRepository::new(self.conn.as_mut()))
+    }
+
+    fn feature_flag<'c>(&'c mut self) -> Box<dyn FeatureFlagRepository<Error = Self::Error> + 'c> {
+        Box::new(PgFeatureFlagRepository::new(self.conn.as_mut()))
+    }
+
     fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
         Box::new(PgJobRepository::new(self.conn.as_mut()))
     }
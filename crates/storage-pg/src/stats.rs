@@ -0,0 +1,526 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`StatsRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use mas_storage::{
+    stats::{
+        ActiveSessionCounts, ClientTokenIssuance, DailyCount, DailyLoginCount,
+        ExperimentVariantStats, LoginMethod, StatsRepository,
+    },
+    Clock,
+};
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{errors::DatabaseInconsistencyError, DatabaseError, ExecuteExt};
+
+fn count_to_usize(count: i64) -> Result<usize, DatabaseError> {
+    count
+        .try_into()
+        .map_err(DatabaseError::to_invalid_operation)
+}
+
+struct DailyCountLookup {
+    day: NaiveDate,
+    count: i64,
+}
+
+impl TryFrom<DailyCountLookup> for DailyCount {
+    type Error = DatabaseError;
+
+    fn try_from(value: DailyCountLookup) -> Result<Self, Self::Error> {
+        Ok(DailyCount {
+            day: value.day,
+            count: count_to_usize(value.count)?,
+        })
+    }
+}
+
+/// An implementation of [`StatsRepository`] for a PostgreSQL connection
+pub struct PgStatsRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgStatsRepository<'c> {
+    /// Create a new [`PgStatsRepository`] from an active PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl<'c> StatsRepository for PgStatsRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.stats.count_active_users",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn count_active_users(&mut self, since: DateTime<Utc>) -> Result<usize, Self::Error> {
+        // A distinct union de-duplicates users active in multiple sessions
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM (
+                    SELECT user_id
+                    FROM user_sessions
+                    WHERE last_active_at >= $1
+                  UNION
+                    SELECT user_id
+                    FROM oauth2_sessions
+                    WHERE user_id IS NOT NULL
+                      AND last_active_at >= $1
+                  UNION
+                    SELECT user_id
+                    FROM compat_sessions
+                    WHERE last_active_at >= $1
+                ) AS rows
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count_to_usize(count)
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.count_authentications",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn count_authentications(&mut self, since: DateTime<Utc>) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM user_session_authentications
+                WHERE created_at >= $1
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count_to_usize(count)
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.count_issued_access_tokens",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn count_issued_access_tokens(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM (
+                    SELECT oauth2_access_token_id
+                    FROM oauth2_access_tokens
+                    WHERE created_at >= $1
+                  UNION ALL
+                    SELECT compat_access_token_id
+                    FROM compat_access_tokens
+                    WHERE created_at >= $1
+                ) AS rows
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count_to_usize(count)
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.record_login_failure",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn record_login_failure(&mut self, clock: &dyn Clock) -> Result<(), Self::Error> {
+        let day = clock.now().date_naive();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_login_failures_per_day (day, count)
+                VALUES ($1, 1)
+                ON CONFLICT (day) DO UPDATE
+                SET count = user_login_failures_per_day.count + 1
+            "#,
+            day,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.refresh_dashboard",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn refresh_dashboard(&mut self) -> Result<(), Self::Error> {
+        // Refreshing concurrently doesn't lock out readers of the views while
+        // they are being recomputed
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY dashboard_registrations_per_day")
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY dashboard_logins_per_day")
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY dashboard_access_tokens_per_day")
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY dashboard_active_sessions")
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.registrations_per_day",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn registrations_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error> {
+        let res = sqlx::query_as!(
+            DailyCountLookup,
+            r#"
+                SELECT day AS "day!"
+                     , count AS "count!"
+                FROM dashboard_registrations_per_day
+                WHERE day >= $1
+                ORDER BY day ASC
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter().map(TryInto::try_into).collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.logins_per_day",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn logins_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyLoginCount>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT day AS "day!"
+                     , method AS "method!"
+                     , count AS "count!"
+                FROM dashboard_logins_per_day
+                WHERE day >= $1
+                ORDER BY day ASC, method ASC
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|row| {
+                let method = match row.method.as_str() {
+                    "password" => LoginMethod::Password,
+                    "upstream_oauth" => LoginMethod::UpstreamOAuth,
+                    "other" => LoginMethod::Other,
+                    _ => {
+                        return Err(DatabaseInconsistencyError::on("dashboard_logins_per_day")
+                            .column("method")
+                            .into())
+                    }
+                };
+
+                Ok(DailyLoginCount {
+                    day: row.day,
+                    method,
+                    count: count_to_usize(row.count)?,
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.login_failures_per_day",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn login_failures_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error> {
+        let res = sqlx::query_as!(
+            DailyCountLookup,
+            r#"
+                SELECT day
+                     , count
+                FROM user_login_failures_per_day
+                WHERE day >= $1
+                ORDER BY day ASC
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter().map(TryInto::try_into).collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.active_sessions",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn active_sessions(&mut self) -> Result<ActiveSessionCounts, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT session_type AS "session_type!"
+                     , count AS "count!"
+                FROM dashboard_active_sessions
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let mut counts = ActiveSessionCounts::default();
+        for row in res {
+            let count = count_to_usize(row.count)?;
+            match row.session_type.as_str() {
+                "browser" => counts.browser = count,
+                "oauth2" => counts.oauth2 = count,
+                "compat" => counts.compat = count,
+                _ => {
+                    return Err(DatabaseInconsistencyError::on("dashboard_active_sessions")
+                        .column("session_type")
+                        .into())
+                }
+            }
+        }
+
+        Ok(counts)
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.top_clients_by_access_tokens",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn top_clients_by_access_tokens(
+        &mut self,
+        since: NaiveDate,
+        limit: usize,
+    ) -> Result<Vec<ClientTokenIssuance>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let res = sqlx::query!(
+            r#"
+                SELECT oauth2_client_id AS "oauth2_client_id!"
+                     , SUM(count)::BIGINT AS "count!"
+                FROM dashboard_access_tokens_per_day
+                WHERE day >= $1
+                GROUP BY oauth2_client_id
+                ORDER BY 2 DESC
+                LIMIT $2
+            "#,
+            since,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|row| {
+                Ok(ClientTokenIssuance {
+                    client_id: row.oauth2_client_id.into(),
+                    count: count_to_usize(row.count)?,
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.record_experiment_exposure",
+        skip_all,
+        fields(
+            db.query.text,
+            visitor.id = %visitor_id,
+            experiment.name = experiment,
+            experiment.variant = variant,
+        ),
+        err,
+    )]
+    async fn record_experiment_exposure(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+        experiment: &str,
+        variant: &str,
+    ) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                INSERT INTO experiment_exposures
+                    ( visitor_id
+                    , experiment
+                    , variant
+                    , exposed_at
+                    )
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (visitor_id, experiment) DO NOTHING
+            "#,
+            Uuid::from(visitor_id),
+            experiment,
+            variant,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.record_experiment_conversion",
+        skip_all,
+        fields(
+            db.query.text,
+            visitor.id = %visitor_id,
+        ),
+        err,
+    )]
+    async fn record_experiment_conversion(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+    ) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE experiment_exposures
+                SET converted_at = $2
+                WHERE visitor_id = $1
+                  AND converted_at IS NULL
+            "#,
+            Uuid::from(visitor_id),
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.stats.experiment_results",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn experiment_results(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<ExperimentVariantStats>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT experiment
+                     , variant
+                     , COUNT(*) AS "exposures!"
+                     , COUNT(converted_at) AS "conversions!"
+                FROM experiment_exposures
+                WHERE exposed_at >= $1
+                GROUP BY experiment, variant
+                ORDER BY experiment, variant
+            "#,
+            since.and_time(chrono::NaiveTime::MIN).and_utc(),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|row| {
+                Ok(ExperimentVariantStats {
+                    experiment: row.experiment,
+                    variant: row.variant,
+                    exposures: count_to_usize(row.exposures)?,
+                    conversions: count_to_usize(row.conversions)?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Keyed hashes of the OAuth 2.0 and session elevation tokens, which are
+//! saved instead of their value.
+//!
+//! The hash is an HMAC-SHA256 with a key derived from a secret of the
+//! configuration, so that someone with read access to the database can't check
+//! guessed tokens against the saved hashes.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Computes the hashes under which the tokens are saved
+#[derive(Clone)]
+pub struct TokenHasher {
+    key: HmacSha256,
+}
+
+impl std::fmt::Debug for TokenHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenHasher").finish_non_exhaustive()
+    }
+}
+
+impl TokenHasher {
+    /// Create a new [`TokenHasher`], with a key derived from the given secret
+    ///
+    /// Changing the secret makes all the existing tokens invalid.
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)] // HMAC accepts keys of any size
+    pub fn new(secret: &[u8]) -> Self {
+        // Derive a dedicated key, so that the secret isn't used as-is by different
+        // algorithms
+        let key = HmacSha256::new_from_slice(secret)
+            .expect("HMAC accepts keys of any size")
+            .chain_update(b"oauth2-token-hash")
+            .finalize()
+            .into_bytes();
+
+        let key = HmacSha256::new_from_slice(&key).expect("HMAC accepts keys of any size");
+        Self { key }
+    }
+
+    /// Compute the hash under which a token is saved
+    #[must_use]
+    pub fn hash(&self, token: &str) -> Vec<u8> {
+        self.key
+            .clone()
+            .chain_update(token.as_bytes())
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+}
+
+/// Compute the hash of a token with the hasher of a repository
+///
+/// The tests of this crate fall back to a hasher with a fixed secret.
+///
+/// # Panics
+///
+/// Panics if the repository wasn't given a [`TokenHasher`].
+pub(crate) fn hash_token(token_hasher: Option<&TokenHasher>, token: &str) -> Vec<u8> {
+    #[cfg(test)]
+    let token_hasher = token_hasher.or(Some(&*TEST_TOKEN_HASHER));
+
+    token_hasher
+        .expect("the repository needs a token hasher to save or look up tokens")
+        .hash(token)
+}
+
+#[cfg(test)]
+static TEST_TOKEN_HASHER: std::sync::LazyLock<TokenHasher> =
+    std::sync::LazyLock::new(|| TokenHasher::new(b"mas-storage-pg-test-secret"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_token() {
+        let hasher = TokenHasher::new(b"one");
+        let hash = hasher.hash("mat_token");
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, hasher.hash("mat_token"));
+        assert_ne!(hash, hasher.hash("mat_other"));
+
+        // The hash depends on the secret
+        assert_ne!(hash, TokenHasher::new(b"two").hash("mat_token"));
+    }
+}
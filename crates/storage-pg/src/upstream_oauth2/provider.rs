@@ -6,7 +6,9 @@
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports};
+use mas_data_model::{
+    UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderHealth,
+};
 use mas_storage::{
     upstream_oauth2::{
         UpstreamOAuthProviderFilter, UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository,
@@ -208,6 +210,36 @@ impl Filter for UpstreamOAuthProviderFilter<'_> {
     }
 }
 
+struct HealthLookup {
+    upstream_oauth_provider_id: Uuid,
+    checked_at: DateTime<Utc>,
+    last_success_at: Option<DateTime<Utc>>,
+    consecutive_failures: i32,
+    last_error: Option<String>,
+}
+
+impl TryFrom<HealthLookup> for UpstreamOAuthProviderHealth {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: HealthLookup) -> Result<Self, Self::Error> {
+        let provider_id = value.upstream_oauth_provider_id.into();
+        let consecutive_failures = value.consecutive_failures.try_into().map_err(|e| {
+            DatabaseInconsistencyError::on("upstream_oauth_provider_health")
+                .column("consecutive_failures")
+                .row(provider_id)
+                .source(e)
+        })?;
+
+        Ok(UpstreamOAuthProviderHealth {
+            provider_id,
+            checked_at: value.checked_at,
+            last_success_at: value.last_success_at,
+            consecutive_failures,
+            last_error: value.last_error,
+        })
+    }
+}
+
 #[async_trait]
 impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'c> {
     type Error = DatabaseError;
@@ -862,4 +894,106 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         let res: Result<Vec<_>, _> = res.into_iter().map(TryInto::try_into).collect();
         Ok(res?)
     }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.record_health_check",
+        skip_all,
+        fields(
+            db.query.text,
+            upstream_oauth_provider.id = %provider.id,
+            upstream_oauth_provider.healthy = error.is_none(),
+        ),
+        err,
+    )]
+    async fn record_health_check(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>,
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error> {
+        let checked_at = clock.now();
+
+        // A successful check resets the failure counter, a failed one
+        // increments it
+        let res = sqlx::query_as!(
+            HealthLookup,
+            r#"
+                INSERT INTO upstream_oauth_provider_health
+                    ( upstream_oauth_provider_id
+                    , checked_at
+                    , last_success_at
+                    , consecutive_failures
+                    , last_error
+                    )
+                VALUES
+                    ( $1
+                    , $2
+                    , CASE WHEN $3::TEXT IS NULL THEN $2::TIMESTAMPTZ END
+                    , CASE WHEN $3::TEXT IS NULL THEN 0 ELSE 1 END
+                    , $3
+                    )
+                ON CONFLICT (upstream_oauth_provider_id) DO UPDATE
+                SET checked_at = EXCLUDED.checked_at
+                  , last_success_at = COALESCE(
+                        EXCLUDED.last_success_at,
+                        upstream_oauth_provider_health.last_success_at
+                    )
+                  , consecutive_failures = CASE
+                        WHEN EXCLUDED.last_error IS NULL THEN 0
+                        ELSE upstream_oauth_provider_health.consecutive_failures + 1
+                    END
+                  , last_error = EXCLUDED.last_error
+                RETURNING
+                    upstream_oauth_provider_id,
+                    checked_at,
+                    last_success_at,
+                    consecutive_failures,
+                    last_error
+            "#,
+            Uuid::from(provider.id),
+            checked_at,
+            error,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(res.try_into()?)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.health",
+        skip_all,
+        fields(
+            db.query.text,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error> {
+        let res = sqlx::query_as!(
+            HealthLookup,
+            r#"
+                SELECT
+                    upstream_oauth_provider_id,
+                    checked_at,
+                    last_success_at,
+                    consecutive_failures,
+                    last_error
+                FROM upstream_oauth_provider_health
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
 }
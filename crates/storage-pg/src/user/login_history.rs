@@ -0,0 +1,269 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{LoginAttempt, LoginMethod, User, UserAgent};
+use mas_storage::{user::UserLoginHistoryRepository, Clock, Page, Pagination};
+use rand::RngCore;
+use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    iden::UserLoginAttempts, pagination::QueryBuilderExt, tracing::ExecuteExt, DatabaseError,
+    DatabaseInconsistencyError,
+};
+
+/// An implementation of [`UserLoginHistoryRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserLoginHistoryRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserLoginHistoryRepository<'c> {
+    /// Create a new [`PgUserLoginHistoryRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+#[enum_def]
+struct LoginAttemptLookup {
+    user_login_attempt_id: Uuid,
+    user_id: Uuid,
+    method: String,
+    succeeded: bool,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<LoginAttemptLookup> for LoginAttempt {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: LoginAttemptLookup) -> Result<Self, Self::Error> {
+        let id = value.user_login_attempt_id.into();
+        let method = value.method.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("user_login_attempts")
+                .column("method")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(LoginAttempt {
+            id,
+            user_id: value.user_id.into(),
+            method,
+            succeeded: value.succeeded,
+            ip_address: value.ip_address,
+            user_agent: value.user_agent.map(UserAgent::parse),
+            created_at: value.created_at,
+        })
+    }
+}
+
+#[async_trait]
+impl UserLoginHistoryRepository for PgUserLoginHistoryRepository<'_> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_login_history.record",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user_login_attempt.id,
+            user_login_attempt.method = method.as_str(),
+            user_login_attempt.succeeded = succeeded,
+        ),
+        err,
+    )]
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        method: LoginMethod,
+        succeeded: bool,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<LoginAttempt, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_login_attempt.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_login_attempts
+                    ( user_login_attempt_id
+                    , user_id
+                    , method
+                    , succeeded
+                    , ip_address
+                    , user_agent
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            method.as_str(),
+            succeeded,
+            ip_address as Option<IpAddr>,
+            user_agent.as_deref(),
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(LoginAttempt {
+            id,
+            user_id: user.id,
+            method,
+            succeeded,
+            ip_address,
+            user_agent,
+            created_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_login_history.list",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<LoginAttempt>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((
+                    UserLoginAttempts::Table,
+                    UserLoginAttempts::UserLoginAttemptId,
+                )),
+                LoginAttemptLookupIden::UserLoginAttemptId,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::UserId)),
+                LoginAttemptLookupIden::UserId,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::Method)),
+                LoginAttemptLookupIden::Method,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::Succeeded)),
+                LoginAttemptLookupIden::Succeeded,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::IpAddress)),
+                LoginAttemptLookupIden::IpAddress,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::UserAgent)),
+                LoginAttemptLookupIden::UserAgent,
+            )
+            .expr_as(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::CreatedAt)),
+                LoginAttemptLookupIden::CreatedAt,
+            )
+            .from(UserLoginAttempts::Table)
+            .and_where(
+                Expr::col((UserLoginAttempts::Table, UserLoginAttempts::UserId))
+                    .eq(Uuid::from(user.id)),
+            )
+            .generate_pagination(
+                (
+                    UserLoginAttempts::Table,
+                    UserLoginAttempts::UserLoginAttemptId,
+                ),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<LoginAttemptLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).try_map(LoginAttempt::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_login_history.count",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM user_login_attempts
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_login_history.purge",
+        skip_all,
+        fields(
+            db.query.text,
+            %before,
+        ),
+        err,
+    )]
+    async fn purge(&mut self, before: DateTime<Utc>, limit: usize) -> Result<usize, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM user_login_attempts
+                WHERE user_login_attempt_id IN (
+                    SELECT user_login_attempt_id
+                    FROM user_login_attempts
+                    WHERE created_at < $1
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+            "#,
+            before,
+            limit,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
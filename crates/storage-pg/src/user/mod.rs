@@ -7,16 +7,19 @@
 //! A module containing the PostgreSQL implementation of the user-related
 //! repositories
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use async_trait::async_trait;
-use mas_data_model::User;
+use chrono::{DateTime, Utc};
+use mas_data_model::{RegistrationMetadata, User};
 use mas_storage::{
-    user::{UserFilter, UserRepository},
+    user::{RegistrationStats, UserFilter, UserRepository, UserState},
     Clock,
 };
 use rand::RngCore;
 use sea_query::{Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use uuid::Uuid;
 
@@ -25,10 +28,11 @@ use crate::{
     iden::Users,
     pagination::QueryBuilderExt,
     tracing::ExecuteExt,
-    DatabaseError,
+    DatabaseError, DatabaseInconsistencyError,
 };
 
 mod email;
+mod login_history;
 mod password;
 mod recovery;
 mod session;
@@ -38,9 +42,9 @@ mod terms;
 mod tests;
 
 pub use self::{
-    email::PgUserEmailRepository, password::PgUserPasswordRepository,
-    recovery::PgUserRecoveryRepository, session::PgBrowserSessionRepository,
-    terms::PgUserTermsRepository,
+    email::PgUserEmailRepository, login_history::PgUserLoginHistoryRepository,
+    password::PgUserPasswordRepository, recovery::PgUserRecoveryRepository,
+    session::PgBrowserSessionRepository, terms::PgUserTermsRepository,
 };
 
 /// An implementation of [`UserRepository`] for a PostgreSQL connection
@@ -53,6 +57,43 @@ impl<'c> PgUserRepository<'c> {
     pub fn new(conn: &'c mut PgConnection) -> Self {
         Self { conn }
     }
+
+    /// Count the users registered since the given date per value of a key of
+    /// their registration metadata, keeping the 100 most common values
+    async fn count_by_metadata_key(
+        &mut self,
+        key: &'static str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(String, usize)>, DatabaseError> {
+        let counts = sqlx::query!(
+            r#"
+                SELECT registration_metadata ->> $1::TEXT AS "value!"
+                     , COUNT(*) AS "count!"
+                FROM users
+                WHERE created_at >= $2
+                  AND registration_metadata ->> $1::TEXT IS NOT NULL
+                GROUP BY 1
+                ORDER BY 2 DESC, 1
+                LIMIT 100
+            "#,
+            key,
+            since,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        counts
+            .into_iter()
+            .map(|row| {
+                let count = row
+                    .count
+                    .try_into()
+                    .map_err(DatabaseError::to_invalid_operation)?;
+                Ok((row.value, count))
+            })
+            .collect()
+    }
 }
 
 mod priv_ {
@@ -66,17 +107,19 @@ mod priv_ {
 
     #[derive(Debug, Clone, sqlx::FromRow)]
     #[enum_def]
-    pub(super) struct UserLookup {
-        pub(super) user_id: Uuid,
-        pub(super) username: String,
-        pub(super) primary_user_email_id: Option<Uuid>,
-        pub(super) created_at: DateTime<Utc>,
-        pub(super) locked_at: Option<DateTime<Utc>>,
-        pub(super) can_request_admin: bool,
+    pub(crate) struct UserLookup {
+        pub(crate) user_id: Uuid,
+        pub(crate) username: String,
+        pub(crate) primary_user_email_id: Option<Uuid>,
+        pub(crate) created_at: DateTime<Utc>,
+        pub(crate) locked_at: Option<DateTime<Utc>>,
+        pub(crate) deleted_at: Option<DateTime<Utc>>,
+        pub(crate) can_request_admin: bool,
     }
 }
 
-use priv_::{UserLookup, UserLookupIden};
+pub(crate) use priv_::UserLookup;
+use priv_::UserLookupIden;
 
 impl From<UserLookup> for User {
     fn from(value: UserLookup) -> Self {
@@ -88,6 +131,7 @@ impl From<UserLookup> for User {
             primary_user_email_id: value.primary_user_email_id.map(Into::into),
             created_at: value.created_at,
             locked_at: value.locked_at,
+            deleted_at: value.deleted_at,
             can_request_admin: value.can_request_admin,
         }
     }
@@ -97,10 +141,15 @@ impl Filter for UserFilter<'_> {
     fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
         sea_query::Condition::all()
             .add_option(self.state().map(|state| {
-                if state.is_locked() {
-                    Expr::col((Users::Table, Users::LockedAt)).is_not_null()
-                } else {
-                    Expr::col((Users::Table, Users::LockedAt)).is_null()
+                match state {
+                    UserState::Locked => sea_query::Condition::all()
+                        .add(Expr::col((Users::Table, Users::LockedAt)).is_not_null())
+                        .add(Expr::col((Users::Table, Users::DeletedAt)).is_null()),
+                    UserState::Active => sea_query::Condition::all()
+                        .add(Expr::col((Users::Table, Users::LockedAt)).is_null())
+                        .add(Expr::col((Users::Table, Users::DeletedAt)).is_null()),
+                    UserState::Deleted => sea_query::Condition::all()
+                        .add(Expr::col((Users::Table, Users::DeletedAt)).is_not_null()),
                 }
             }))
             .add_option(self.can_request_admin().map(|can_request_admin| {
@@ -131,6 +180,7 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , deleted_at
                      , can_request_admin
                 FROM users
                 WHERE user_id = $1
@@ -146,6 +196,47 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         Ok(Some(res.into()))
     }
 
+    #[tracing::instrument(
+        name = "db.user.load_batch",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, User>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query_as!(
+            UserLookup,
+            r#"
+                SELECT user_id
+                     , username
+                     , primary_user_email_id
+                     , created_at
+                     , locked_at
+                     , deleted_at
+                     , can_request_admin
+                FROM users
+                WHERE user_id = ANY($1::uuid[])
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .map(|r| {
+                let user = User::from(r);
+                (user.id, user)
+            })
+            .collect())
+    }
+
     #[tracing::instrument(
         name = "db.user.find_by_username",
         skip_all,
@@ -164,6 +255,7 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , deleted_at
                      , can_request_admin
                 FROM users
                 WHERE username = $1
@@ -224,6 +316,7 @@ impl<'c> UserRepository for PgUserRepository<'c> {
             primary_user_email_id: None,
             created_at,
             locked_at: None,
+            deleted_at: None,
             can_request_admin: false,
         })
     }
@@ -322,28 +415,61 @@ impl<'c> UserRepository for PgUserRepository<'c> {
     }
 
     #[tracing::instrument(
-        name = "db.user.set_can_request_admin",
+        name = "db.user.delete",
         skip_all,
         fields(
             db.query.text,
             %user.id,
-            user.can_request_admin = can_request_admin,
         ),
         err,
     )]
-    async fn set_can_request_admin(
-        &mut self,
-        mut user: User,
-        can_request_admin: bool,
-    ) -> Result<User, Self::Error> {
+    async fn delete(&mut self, clock: &dyn Clock, mut user: User) -> Result<User, Self::Error> {
+        if user.deleted_at.is_some() {
+            return Ok(user);
+        }
+
+        let deleted_at = clock.now();
         let res = sqlx::query!(
             r#"
                 UPDATE users
-                SET can_request_admin = $2
+                SET deleted_at = $1
+                WHERE user_id = $2
+            "#,
+            deleted_at,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.deleted_at = Some(deleted_at);
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.restore",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn restore(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.deleted_at.is_none() {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET deleted_at = NULL
                 WHERE user_id = $1
             "#,
             Uuid::from(user.id),
-            can_request_admin,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -351,44 +477,811 @@ impl<'c> UserRepository for PgUserRepository<'c> {
 
         DatabaseError::ensure_affected_rows(&res, 1)?;
 
-        user.can_request_admin = can_request_admin;
+        user.deleted_at = None;
 
         Ok(user)
     }
 
     #[tracing::instrument(
-        name = "db.user.list",
+        name = "db.user.purge_deleted",
         skip_all,
         fields(
             db.query.text,
+            %deleted_before,
         ),
         err,
     )]
-    async fn list(
+    async fn purge_deleted(
         &mut self,
-        filter: UserFilter<'_>,
-        pagination: mas_storage::Pagination,
-    ) -> Result<mas_storage::Page<User>, Self::Error> {
-        let (sql, arguments) = Query::select()
-            .expr_as(
-                Expr::col((Users::Table, Users::UserId)),
-                UserLookupIden::UserId,
-            )
-            .expr_as(
-                Expr::col((Users::Table, Users::Username)),
-                UserLookupIden::Username,
-            )
-            .expr_as(
-                Expr::col((Users::Table, Users::PrimaryUserEmailId)),
-                UserLookupIden::PrimaryUserEmailId,
-            )
-            .expr_as(
-                Expr::col((Users::Table, Users::CreatedAt)),
-                UserLookupIden::CreatedAt,
-            )
-            .expr_as(
-                Expr::col((Users::Table, Users::LockedAt)),
-                UserLookupIden::LockedAt,
+        deleted_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<usize, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        // Lock the users to purge, skipping the ones locked by a concurrent
+        // purge, so that multiple workers can purge in parallel
+        let user_ids = sqlx::query_scalar!(
+            r#"
+                SELECT user_id
+                FROM users
+                WHERE deleted_at < $1
+                ORDER BY deleted_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            "#,
+            deleted_before,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        if user_ids.is_empty() {
+            return Ok(0);
+        }
+
+        // Remove the data related to the users, in an order which satisfies the
+        // foreign key constraints
+        sqlx::query!(
+            r#"
+                UPDATE users
+                SET primary_user_email_id = NULL
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_email_confirmation_codes
+                WHERE user_email_id IN (
+                    SELECT user_email_id FROM user_emails WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_emails
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_refresh_tokens
+                WHERE oauth2_session_id IN (
+                    SELECT oauth2_session_id FROM oauth2_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_access_tokens
+                WHERE oauth2_session_id IN (
+                    SELECT oauth2_session_id FROM oauth2_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_authorization_grants
+                WHERE oauth2_session_id IN (
+                    SELECT oauth2_session_id FROM oauth2_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_device_code_grant
+                WHERE user_session_id IN (
+                    SELECT user_session_id FROM user_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_sessions
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM oauth2_consents
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM compat_refresh_tokens
+                WHERE compat_session_id IN (
+                    SELECT compat_session_id FROM compat_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM compat_access_tokens
+                WHERE compat_session_id IN (
+                    SELECT compat_session_id FROM compat_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM compat_sso_logins
+                WHERE compat_session_id IN (
+                    SELECT compat_session_id FROM compat_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM compat_sessions
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_session_authentications
+                WHERE user_session_id IN (
+                    SELECT user_session_id FROM user_sessions WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_sessions
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM upstream_oauth_authorization_sessions
+                WHERE upstream_oauth_link_id IN (
+                    SELECT upstream_oauth_link_id FROM upstream_oauth_links WHERE user_id = ANY($1)
+                )
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM upstream_oauth_links
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_passwords
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_terms
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_login_attempts
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM users
+                WHERE user_id = ANY($1)
+            "#,
+            &user_ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(user_ids.len())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_can_request_admin",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.can_request_admin = can_request_admin,
+        ),
+        err,
+    )]
+    async fn set_can_request_admin(
+        &mut self,
+        mut user: User,
+        can_request_admin: bool,
+    ) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET can_request_admin = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            can_request_admin,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.can_request_admin = can_request_admin;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.get_session_limit",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn get_session_limit(&mut self, user: &User) -> Result<Option<u32>, Self::Error> {
+        let max_sessions = sqlx::query_scalar!(
+            r#"
+                SELECT max_sessions
+                FROM user_session_limits
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(max_sessions) = max_sessions else {
+            return Ok(None);
+        };
+
+        let max_sessions = u32::try_from(max_sessions).map_err(|e| {
+            DatabaseInconsistencyError::on("user_session_limits")
+                .column("max_sessions")
+                .row(user.id)
+                .source(e)
+        })?;
+
+        Ok(Some(max_sessions))
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_session_limit",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.max_sessions = max_sessions,
+        ),
+        err,
+    )]
+    async fn set_session_limit(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        max_sessions: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        let Some(max_sessions) = max_sessions else {
+            sqlx::query!(
+                r#"
+                    DELETE FROM user_session_limits
+                    WHERE user_id = $1
+                "#,
+                Uuid::from(user.id),
+            )
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+            return Ok(());
+        };
+
+        let max_sessions = i32::try_from(max_sessions).unwrap_or(i32::MAX);
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_limits (user_id, max_sessions, updated_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE
+                SET max_sessions = EXCLUDED.max_sessions
+                  , updated_at = EXCLUDED.updated_at
+            "#,
+            Uuid::from(user.id),
+            max_sessions,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.get_timezone",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn get_timezone(&mut self, user: &User) -> Result<Option<String>, Self::Error> {
+        let timezone = sqlx::query_scalar!(
+            r#"
+                SELECT timezone
+                FROM user_timezones
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(timezone)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_timezone",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.timezone = timezone,
+        ),
+        err,
+    )]
+    async fn set_timezone(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        timezone: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let Some(timezone) = timezone else {
+            sqlx::query!(
+                r#"
+                    DELETE FROM user_timezones
+                    WHERE user_id = $1
+                "#,
+                Uuid::from(user.id),
+            )
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+            return Ok(());
+        };
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_timezones (user_id, timezone, updated_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE
+                SET timezone = EXCLUDED.timezone
+                  , updated_at = EXCLUDED.updated_at
+            "#,
+            Uuid::from(user.id),
+            timezone,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.get_password_policy_class",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn get_password_policy_class(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<String>, Self::Error> {
+        let policy_class = sqlx::query_scalar!(
+            r#"
+                SELECT policy_class
+                FROM user_password_policy_classes
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(policy_class)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_password_policy_class",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.password_policy_class = policy_class,
+        ),
+        err,
+    )]
+    async fn set_password_policy_class(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        policy_class: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let Some(policy_class) = policy_class else {
+            sqlx::query!(
+                r#"
+                    DELETE FROM user_password_policy_classes
+                    WHERE user_id = $1
+                "#,
+                Uuid::from(user.id),
+            )
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+
+            return Ok(());
+        };
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_password_policy_classes (user_id, policy_class, updated_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE
+                SET policy_class = EXCLUDED.policy_class
+                  , updated_at = EXCLUDED.updated_at
+            "#,
+            Uuid::from(user.id),
+            policy_class,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.is_password_change_required",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn is_password_change_required(&mut self, user: &User) -> Result<bool, Self::Error> {
+        let required = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM user_password_change_required
+                    WHERE user_id = $1
+                ) AS "required!"
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(required)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_password_change_required",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.password_change_required = required,
+        ),
+        err,
+    )]
+    async fn set_password_change_required(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        required: bool,
+    ) -> Result<(), Self::Error> {
+        if required {
+            sqlx::query!(
+                r#"
+                    INSERT INTO user_password_change_required (user_id, created_at)
+                    VALUES ($1, $2)
+                    ON CONFLICT (user_id) DO NOTHING
+                "#,
+                Uuid::from(user.id),
+                clock.now(),
+            )
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"
+                    DELETE FROM user_password_change_required
+                    WHERE user_id = $1
+                "#,
+                Uuid::from(user.id),
+            )
+            .traced()
+            .execute(&mut *self.conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_registration_metadata",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn set_registration_metadata(
+        &mut self,
+        user: &User,
+        metadata: &RegistrationMetadata,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET registration_metadata = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            Json(metadata) as _,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.get_registration_metadata",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn get_registration_metadata(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<RegistrationMetadata>, Self::Error> {
+        let metadata = sqlx::query_scalar!(
+            r#"
+                SELECT registration_metadata AS "registration_metadata: Json<RegistrationMetadata>"
+                FROM users
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?
+        .flatten();
+
+        Ok(metadata.map(|Json(metadata)| metadata))
+    }
+
+    #[tracing::instrument(
+        name = "db.user.registration_stats",
+        skip_all,
+        fields(
+            db.query.text,
+            %since,
+        ),
+        err,
+    )]
+    async fn registration_stats(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<RegistrationStats, Self::Error> {
+        let total = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "total!"
+                FROM users
+                WHERE created_at >= $1
+            "#,
+            since,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let total = total
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let by_client = self.count_by_metadata_key("client_id", since).await?;
+        let by_upstream_provider = self
+            .count_by_metadata_key("upstream_provider_id", since)
+            .await?;
+        let by_invite_token = self.count_by_metadata_key("invite_token", since).await?;
+        let by_referral = self.count_by_metadata_key("referral", since).await?;
+
+        let parse_ids = |counts: Vec<(String, usize)>| {
+            counts
+                .into_iter()
+                .map(|(id, count)| {
+                    let id = id.parse::<Ulid>().map_err(|e| {
+                        DatabaseInconsistencyError::on("users")
+                            .column("registration_metadata")
+                            .source(e)
+                    })?;
+                    Ok::<_, DatabaseInconsistencyError>((id, count))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(RegistrationStats {
+            total,
+            by_client: parse_ids(by_client)?,
+            by_upstream_provider: parse_ids(by_upstream_provider)?,
+            by_invite_token,
+            by_referral,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: UserFilter<'_>,
+        pagination: mas_storage::Pagination,
+    ) -> Result<mas_storage::Page<User>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((Users::Table, Users::UserId)),
+                UserLookupIden::UserId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::Username)),
+                UserLookupIden::Username,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::PrimaryUserEmailId)),
+                UserLookupIden::PrimaryUserEmailId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::CreatedAt)),
+                UserLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::LockedAt)),
+                UserLookupIden::LockedAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeletedAt)),
+                UserLookupIden::DeletedAt,
             )
             .expr_as(
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
@@ -4,13 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    Authentication, AuthenticationMethod, BrowserSession, Password,
-    UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, AuthenticationMethod, BrowserSession, Password, SessionElevation,
+    UpstreamOAuthAuthorizationSession, User, UserAgent, UserAgentDetails,
 };
 use mas_storage::{
     user::{BrowserSessionFilter, BrowserSessionRepository},
@@ -19,7 +22,7 @@ use mas_storage::{
 use rand::RngCore;
 use sea_query::{Expr, PostgresQueryBuilder};
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use uuid::Uuid;
 
@@ -27,21 +30,27 @@ use crate::{
     filter::StatementExt,
     iden::{UserSessions, Users},
     pagination::QueryBuilderExt,
+    token_hash::hash_token,
     tracing::ExecuteExt,
-    DatabaseError, DatabaseInconsistencyError,
+    user_agent::load_user_agent,
+    DatabaseError, DatabaseInconsistencyError, TokenHasher,
 };
 
 /// An implementation of [`BrowserSessionRepository`] for a PostgreSQL
 /// connection
 pub struct PgBrowserSessionRepository<'c> {
     conn: &'c mut PgConnection,
+    token_hasher: Option<&'c TokenHasher>,
 }
 
 impl<'c> PgBrowserSessionRepository<'c> {
     /// Create a new [`PgBrowserSessionRepository`] from an active PostgreSQL
     /// connection
-    pub fn new(conn: &'c mut PgConnection) -> Self {
-        Self { conn }
+    ///
+    /// The [`TokenHasher`] is needed to elevate sessions and find their
+    /// elevations.
+    pub fn new(conn: &'c mut PgConnection, token_hasher: Option<&'c TokenHasher>) -> Self {
+        Self { conn, token_hasher }
     }
 }
 
@@ -53,6 +62,7 @@ struct SessionLookup {
     user_session_created_at: DateTime<Utc>,
     user_session_finished_at: Option<DateTime<Utc>>,
     user_session_user_agent: Option<String>,
+    user_session_user_agent_details: Option<Json<UserAgentDetails>>,
     user_session_last_active_at: Option<DateTime<Utc>>,
     user_session_last_active_ip: Option<IpAddr>,
     user_id: Uuid,
@@ -60,6 +70,7 @@ struct SessionLookup {
     user_primary_user_email_id: Option<Uuid>,
     user_created_at: DateTime<Utc>,
     user_locked_at: Option<DateTime<Utc>>,
+    user_deleted_at: Option<DateTime<Utc>>,
     user_can_request_admin: bool,
 }
 
@@ -75,6 +86,7 @@ impl TryFrom<SessionLookup> for BrowserSession {
             primary_user_email_id: value.user_primary_user_email_id.map(Into::into),
             created_at: value.user_created_at,
             locked_at: value.user_locked_at,
+            deleted_at: value.user_deleted_at,
             can_request_admin: value.user_can_request_admin,
         };
 
@@ -83,7 +95,10 @@ impl TryFrom<SessionLookup> for BrowserSession {
             user,
             created_at: value.user_session_created_at,
             finished_at: value.user_session_finished_at,
-            user_agent: value.user_session_user_agent.map(UserAgent::parse),
+            user_agent: load_user_agent(
+                value.user_session_user_agent,
+                value.user_session_user_agent_details,
+            ),
             last_active_at: value.user_session_last_active_at,
             last_active_ip: value.user_session_last_active_ip,
         })
@@ -95,6 +110,29 @@ struct AuthenticationLookup {
     created_at: DateTime<Utc>,
     user_password_id: Option<Uuid>,
     upstream_oauth_authorization_session_id: Option<Uuid>,
+    client_certificate_fingerprint: Option<String>,
+}
+
+struct SessionElevationLookup {
+    user_session_elevation_id: Uuid,
+    user_session_id: Uuid,
+    user_session_authentication_id: Uuid,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl SessionElevationLookup {
+    /// Only a hash of the token is saved, so the caller has to give it back
+    fn into_elevation(self, token: String) -> SessionElevation {
+        SessionElevation {
+            id: self.user_session_elevation_id.into(),
+            user_session_id: self.user_session_id.into(),
+            authentication_id: self.user_session_authentication_id.into(),
+            token,
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+        }
+    }
 }
 
 impl TryFrom<AuthenticationLookup> for Authentication {
@@ -107,12 +145,20 @@ impl TryFrom<AuthenticationLookup> for Authentication {
             value
                 .upstream_oauth_authorization_session_id
                 .map(Into::into),
+            value.client_certificate_fingerprint,
         ) {
-            (Some(user_password_id), None) => AuthenticationMethod::Password { user_password_id },
-            (None, Some(upstream_oauth2_session_id)) => AuthenticationMethod::UpstreamOAuth2 {
-                upstream_oauth2_session_id,
-            },
-            (None, None) => AuthenticationMethod::Unknown,
+            (Some(user_password_id), None, None) => {
+                AuthenticationMethod::Password { user_password_id }
+            }
+            (None, Some(upstream_oauth2_session_id), None) => {
+                AuthenticationMethod::UpstreamOAuth2 {
+                    upstream_oauth2_session_id,
+                }
+            }
+            (None, None, Some(fingerprint)) => {
+                AuthenticationMethod::ClientCertificate { fingerprint }
+            }
+            (None, None, None) => AuthenticationMethod::Unknown,
             _ => {
                 return Err(DatabaseInconsistencyError::on("user_session_authentications").row(id));
             }
@@ -169,6 +215,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , s.created_at            AS "user_session_created_at"
                      , s.finished_at           AS "user_session_finished_at"
                      , s.user_agent            AS "user_session_user_agent"
+                     , s.user_agent_details    AS "user_session_user_agent_details: Json<UserAgentDetails>"
                      , s.last_active_at        AS "user_session_last_active_at"
                      , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
                      , u.user_id
@@ -176,6 +223,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , u.primary_user_email_id AS "user_primary_user_email_id"
                      , u.created_at            AS "user_created_at"
                      , u.locked_at             AS "user_locked_at"
+                     , u.deleted_at            AS "user_deleted_at"
                      , u.can_request_admin     AS "user_can_request_admin"
                 FROM user_sessions s
                 INNER JOIN users u
@@ -193,6 +241,55 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(Some(res.try_into()?))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.load_batch",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query_as!(
+            SessionLookup,
+            r#"
+                SELECT s.user_session_id
+                     , s.created_at            AS "user_session_created_at"
+                     , s.finished_at           AS "user_session_finished_at"
+                     , s.user_agent            AS "user_session_user_agent"
+                     , s.user_agent_details    AS "user_session_user_agent_details: Json<UserAgentDetails>"
+                     , s.last_active_at        AS "user_session_last_active_at"
+                     , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
+                     , u.user_id
+                     , u.username              AS "user_username"
+                     , u.primary_user_email_id AS "user_primary_user_email_id"
+                     , u.created_at            AS "user_created_at"
+                     , u.locked_at             AS "user_locked_at"
+                     , u.deleted_at            AS "user_deleted_at"
+                     , u.can_request_admin     AS "user_can_request_admin"
+                FROM user_sessions s
+                INNER JOIN users u
+                    USING (user_id)
+                WHERE s.user_session_id = ANY($1::uuid[])
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| -> Result<_, DatabaseError> {
+                let session = BrowserSession::try_from(r)?;
+                Ok((session.id, session))
+            })
+            .collect()
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.add",
         skip_all,
@@ -216,13 +313,15 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
 
         sqlx::query!(
             r#"
-                INSERT INTO user_sessions (user_session_id, user_id, created_at, user_agent)
-                VALUES ($1, $2, $3, $4)
+                INSERT INTO user_sessions
+                    (user_session_id, user_id, created_at, user_agent, user_agent_details)
+                VALUES ($1, $2, $3, $4, $5)
             "#,
             Uuid::from(id),
             Uuid::from(user.id),
             created_at,
             user_agent.as_deref(),
+            user_agent.as_ref().map(|ua| Json(ua.details())) as _,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -335,6 +434,10 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((UserSessions::Table, UserSessions::UserAgent)),
                 SessionLookupIden::UserSessionUserAgent,
             )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::UserAgentDetails)),
+                SessionLookupIden::UserSessionUserAgentDetails,
+            )
             .expr_as(
                 Expr::col((UserSessions::Table, UserSessions::LastActiveAt)),
                 SessionLookupIden::UserSessionLastActiveAt,
@@ -363,6 +466,10 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((Users::Table, Users::LockedAt)),
                 SessionLookupIden::UserLockedAt,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeletedAt)),
+                SessionLookupIden::UserDeletedAt,
+            )
             .expr_as(
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
                 SessionLookupIden::UserCanRequestAdmin,
@@ -515,6 +622,54 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         })
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.authenticate_with_client_certificate",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+            user_session_authentication.id,
+        ),
+        err,
+    )]
+    async fn authenticate_with_client_certificate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        fingerprint: &str,
+    ) -> Result<Authentication, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "user_session_authentication.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_authentications
+                    (user_session_authentication_id, user_session_id, created_at, client_certificate_fingerprint)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_session.id),
+            created_at,
+            fingerprint,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Authentication {
+            id,
+            created_at,
+            authentication_method: AuthenticationMethod::ClientCertificate {
+                fingerprint: fingerprint.to_owned(),
+            },
+        })
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.get_last_authentication",
         skip_all,
@@ -535,6 +690,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , created_at
                      , user_password_id
                      , upstream_oauth_authorization_session_id
+                     , client_certificate_fingerprint
                 FROM user_session_authentications
                 WHERE user_session_id = $1
                 ORDER BY created_at DESC
@@ -554,6 +710,92 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(Some(authentication))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.load_last_authentications",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn load_last_authentications(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query!(
+            r#"
+                SELECT DISTINCT ON (user_session_id)
+                       user_session_id
+                     , user_session_authentication_id
+                     , created_at
+                     , user_password_id
+                     , upstream_oauth_authorization_session_id
+                     , client_certificate_fingerprint
+                FROM user_session_authentications
+                WHERE user_session_id = ANY($1::uuid[])
+                ORDER BY user_session_id, created_at DESC
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| -> Result<_, DatabaseError> {
+                let authentication = Authentication::try_from(AuthenticationLookup {
+                    user_session_authentication_id: r.user_session_authentication_id,
+                    created_at: r.created_at,
+                    user_password_id: r.user_password_id,
+                    upstream_oauth_authorization_session_id: r
+                        .upstream_oauth_authorization_session_id,
+                    client_certificate_fingerprint: r.client_certificate_fingerprint,
+                })?;
+                Ok((r.user_session_id.into(), authentication))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.list_authentications",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+        ),
+        err,
+    )]
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Vec<Authentication>, Self::Error> {
+        let authentications = sqlx::query_as!(
+            AuthenticationLookup,
+            r#"
+                SELECT user_session_authentication_id
+                     , created_at
+                     , user_password_id
+                     , upstream_oauth_authorization_session_id
+                     , client_certificate_fingerprint
+                FROM user_session_authentications
+                WHERE user_session_id = $1
+                ORDER BY created_at ASC
+            "#,
+            Uuid::from(user_session.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let authentications = authentications
+            .into_iter()
+            .map(Authentication::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(authentications)
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.record_batch_activity",
         skip_all,
@@ -600,4 +842,98 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
 
         Ok(())
     }
+
+    #[tracing::instrument(
+        name = "db.browser_session.elevate",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+            %authentication.id,
+            user_session_elevation.id,
+        ),
+        err,
+    )]
+    async fn elevate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        authentication: &Authentication,
+        token: String,
+        ttl: chrono::Duration,
+    ) -> Result<SessionElevation, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + ttl;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_session_elevation.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_elevations
+                    ( user_session_elevation_id
+                    , user_session_id
+                    , user_session_authentication_id
+                    , token_hash
+                    , created_at
+                    , expires_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_session.id),
+            Uuid::from(authentication.id),
+            hash_token(self.token_hasher, &token),
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(SessionElevation {
+            id,
+            user_session_id: user_session.id,
+            authentication_id: authentication.id,
+            token,
+            created_at,
+            expires_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.find_elevation",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+        ),
+        err,
+    )]
+    async fn find_elevation(
+        &mut self,
+        user_session: &BrowserSession,
+        token: &str,
+    ) -> Result<Option<SessionElevation>, Self::Error> {
+        let elevation = sqlx::query_as!(
+            SessionElevationLookup,
+            r#"
+                SELECT user_session_elevation_id
+                     , user_session_id
+                     , user_session_authentication_id
+                     , created_at
+                     , expires_at
+                FROM user_session_elevations
+                WHERE user_session_id = $1
+                  AND token_hash = $2
+            "#,
+            Uuid::from(user_session.id),
+            hash_token(self.token_hasher, token),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(elevation.map(|elevation| elevation.into_elevation(token.to_owned())))
+    }
 }
@@ -0,0 +1,25 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Load the user-agents saved on the sessions, with the fields parsed from
+//! them when they were saved
+
+use mas_data_model::{UserAgent, UserAgentDetails};
+use sqlx::types::Json;
+
+/// Rebuild the user-agent of a session from its columns
+///
+/// The sessions saved before the parsed fields were stored, and not yet
+/// processed by the backfill, have their user-agent parsed again.
+pub(crate) fn load_user_agent(
+    raw: Option<String>,
+    details: Option<Json<UserAgentDetails>>,
+) -> Option<UserAgent> {
+    let raw = raw?;
+    Some(match details {
+        Some(Json(details)) => UserAgent::from_details(raw, details),
+        None => UserAgent::parse(raw),
+    })
+}
@@ -0,0 +1,242 @@
+// Copyright 2024 New Vector Ltd.
+// Copyright 2023, 2024 The Matrix.org Foundation C.I.C.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::Duration;
+use mas_data_model::Device;
+use mas_storage::{
+    app_session::{AppSession, AppSessionFilter},
+    clock::MockClock,
+    oauth2::OAuth2SessionRepository,
+    Pagination, RepositoryAccess,
+};
+use oauth2_types::{
+    requests::GrantType,
+    scope::{Scope, OPENID},
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+use crate::Backend;
+
+/// Test the app session repository, which lists both OAuth 2.0 and
+/// compatibility sessions
+pub async fn app_repo(backend: &impl Backend) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let mut repo = backend.repository().await;
+
+    // Create a user
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let all = AppSessionFilter::new().for_user(&user);
+    let active = all.active_only();
+    let finished = all.finished_only();
+    let pagination = Pagination::first(10);
+
+    assert_eq!(repo.app_session().count(all).await.unwrap(), 0);
+    assert_eq!(repo.app_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.app_session().count(finished).await.unwrap(), 0);
+
+    let full_list = repo.app_session().list(all, pagination).await.unwrap();
+    assert!(full_list.edges.is_empty());
+    let active_list = repo.app_session().list(active, pagination).await.unwrap();
+    assert!(active_list.edges.is_empty());
+    let finished_list = repo.app_session().list(finished, pagination).await.unwrap();
+    assert!(finished_list.edges.is_empty());
+
+    // Start a compat session for that user
+    let device = Device::generate(&mut rng);
+    let compat_session = repo
+        .compat_session()
+        .add(&mut rng, &clock, &user, device.clone(), None, false)
+        .await
+        .unwrap();
+
+    assert_eq!(repo.app_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.app_session().count(active).await.unwrap(), 1);
+    assert_eq!(repo.app_session().count(finished).await.unwrap(), 0);
+
+    let full_list = repo.app_session().list(all, pagination).await.unwrap();
+    assert_eq!(full_list.edges.len(), 1);
+    assert_eq!(
+        full_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    let active_list = repo.app_session().list(active, pagination).await.unwrap();
+    assert_eq!(active_list.edges.len(), 1);
+    assert_eq!(
+        active_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    let finished_list = repo.app_session().list(finished, pagination).await.unwrap();
+    assert!(finished_list.edges.is_empty());
+
+    // Finish the session
+    let compat_session = repo
+        .compat_session()
+        .finish(&clock, compat_session)
+        .await
+        .unwrap();
+
+    assert_eq!(repo.app_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.app_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.app_session().count(finished).await.unwrap(), 1);
+
+    let full_list = repo.app_session().list(all, pagination).await.unwrap();
+    assert_eq!(full_list.edges.len(), 1);
+    assert_eq!(
+        full_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    let active_list = repo.app_session().list(active, pagination).await.unwrap();
+    assert!(active_list.edges.is_empty());
+    let finished_list = repo.app_session().list(finished, pagination).await.unwrap();
+    assert_eq!(finished_list.edges.len(), 1);
+    assert_eq!(
+        finished_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+
+    // Start an OAuth2 session
+    let client = repo
+        .oauth2_client()
+        .add(
+            &mut rng,
+            &clock,
+            vec!["https://example.com/redirect".parse().unwrap()],
+            None,
+            None,
+            vec![GrantType::AuthorizationCode],
+            Some("First client".to_owned()),
+            Some("https://example.com/logo.png".parse().unwrap()),
+            Some("https://example.com/".parse().unwrap()),
+            Some("https://example.com/policy".parse().unwrap()),
+            Some("https://example.com/tos".parse().unwrap()),
+            Some("https://example.com/jwks.json".parse().unwrap()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some("https://example.com/login".parse().unwrap()),
+        )
+        .await
+        .unwrap();
+
+    let device2 = Device::generate(&mut rng);
+    let scope = Scope::from_iter([OPENID, device2.to_scope_token()]);
+
+    // We're moving the clock forward by 1 minute between each session to ensure
+    // we're getting consistent ordering in lists.
+    clock.advance(Duration::try_minutes(1).unwrap());
+
+    let oauth_session = repo
+        .oauth2_session()
+        .add(&mut rng, &clock, &client, Some(&user), None, scope)
+        .await
+        .unwrap();
+
+    assert_eq!(repo.app_session().count(all).await.unwrap(), 2);
+    assert_eq!(repo.app_session().count(active).await.unwrap(), 1);
+    assert_eq!(repo.app_session().count(finished).await.unwrap(), 1);
+
+    let full_list = repo.app_session().list(all, pagination).await.unwrap();
+    assert_eq!(full_list.edges.len(), 2);
+    assert_eq!(
+        full_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    assert_eq!(
+        full_list.edges[1],
+        AppSession::OAuth2(Box::new(oauth_session.clone()))
+    );
+
+    let active_list = repo.app_session().list(active, pagination).await.unwrap();
+    assert_eq!(active_list.edges.len(), 1);
+    assert_eq!(
+        active_list.edges[0],
+        AppSession::OAuth2(Box::new(oauth_session.clone()))
+    );
+
+    let finished_list = repo.app_session().list(finished, pagination).await.unwrap();
+    assert_eq!(finished_list.edges.len(), 1);
+    assert_eq!(
+        finished_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+
+    // Finish the session
+    let oauth_session = repo
+        .oauth2_session()
+        .finish(&clock, oauth_session)
+        .await
+        .unwrap();
+
+    assert_eq!(repo.app_session().count(all).await.unwrap(), 2);
+    assert_eq!(repo.app_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.app_session().count(finished).await.unwrap(), 2);
+
+    let full_list = repo.app_session().list(all, pagination).await.unwrap();
+    assert_eq!(full_list.edges.len(), 2);
+    assert_eq!(
+        full_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    assert_eq!(
+        full_list.edges[1],
+        AppSession::OAuth2(Box::new(oauth_session.clone()))
+    );
+
+    let active_list = repo.app_session().list(active, pagination).await.unwrap();
+    assert!(active_list.edges.is_empty());
+
+    let finished_list = repo.app_session().list(finished, pagination).await.unwrap();
+    assert_eq!(finished_list.edges.len(), 2);
+    assert_eq!(
+        finished_list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+    assert_eq!(
+        full_list.edges[1],
+        AppSession::OAuth2(Box::new(oauth_session.clone()))
+    );
+
+    // Query by device
+    let filter = AppSessionFilter::new().for_device(&device);
+    assert_eq!(repo.app_session().count(filter).await.unwrap(), 1);
+    let list = repo.app_session().list(filter, pagination).await.unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(
+        list.edges[0],
+        AppSession::Compat(Box::new(compat_session.clone()))
+    );
+
+    let filter = AppSessionFilter::new().for_device(&device2);
+    assert_eq!(repo.app_session().count(filter).await.unwrap(), 1);
+    let list = repo.app_session().list(filter, pagination).await.unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(
+        list.edges[0],
+        AppSession::OAuth2(Box::new(oauth_session.clone()))
+    );
+
+    // Create a second user
+    let user2 = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    // If we list/count for this user, we should get nothing
+    let filter = AppSessionFilter::new().for_user(&user2);
+    assert_eq!(repo.app_session().count(filter).await.unwrap(), 0);
+    let list = repo.app_session().list(filter, pagination).await.unwrap();
+    assert!(list.edges.is_empty());
+}
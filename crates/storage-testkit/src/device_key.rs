@@ -0,0 +1,147 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use mas_data_model::Device;
+use mas_jose::jwk::PublicJsonWebKey;
+use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+use oauth2_types::{
+    requests::GrantType,
+    scope::{Scope, OPENID},
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+use crate::Backend;
+
+/// The P-256 public key from RFC 7515, appendix A.3
+fn public_key() -> PublicJsonWebKey {
+    serde_json::from_value(serde_json::json!({
+        "kty": "EC",
+        "crv": "P-256",
+        "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+        "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+    }))
+    .unwrap()
+}
+
+/// Test the device key repository, binding keys to both OAuth 2.0 and
+/// compatibility sessions
+pub async fn device_key_repository(backend: &impl Backend) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let mut repo = backend.repository().await;
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let device = Device::generate(&mut rng);
+    let compat_session = repo
+        .compat_session()
+        .add(&mut rng, &clock, &user, device, None, false)
+        .await
+        .unwrap();
+
+    let client = repo
+        .oauth2_client()
+        .add(
+            &mut rng,
+            &clock,
+            vec!["https://example.com/redirect".parse().unwrap()],
+            None,
+            None,
+            vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
+            Some("Mobile client".to_owned()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let oauth2_session = repo
+        .oauth2_session()
+        .add(
+            &mut rng,
+            &clock,
+            &client,
+            Some(&user),
+            None,
+            Scope::from_iter([OPENID]),
+        )
+        .await
+        .unwrap();
+
+    // Sessions don't have a device key by default
+    assert!(repo
+        .device_key()
+        .find_for_compat_session(&compat_session)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repo
+        .device_key()
+        .find_for_oauth2_session(&oauth2_session)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Bind a key to the compat session
+    let compat_key = repo
+        .device_key()
+        .add_for_compat_session(&mut rng, &clock, &compat_session, public_key())
+        .await
+        .unwrap();
+    assert_eq!(compat_key.public_key, public_key());
+    assert_eq!(compat_key.created_at, clock.now());
+
+    let found = repo
+        .device_key()
+        .find_for_compat_session(&compat_session)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found, compat_key);
+
+    // It is not bound to the OAuth 2.0 session
+    assert!(repo
+        .device_key()
+        .find_for_oauth2_session(&oauth2_session)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Bind a key to the OAuth 2.0 session
+    let oauth2_key = repo
+        .device_key()
+        .add_for_oauth2_session(&mut rng, &clock, &oauth2_session, public_key())
+        .await
+        .unwrap();
+    assert_ne!(oauth2_key.id, compat_key.id);
+
+    let found = repo
+        .device_key()
+        .find_for_oauth2_session(&oauth2_session)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found, oauth2_key);
+
+    // A session can't have two device keys
+    assert!(repo
+        .device_key()
+        .add_for_oauth2_session(&mut rng, &clock, &oauth2_session, public_key())
+        .await
+        .is_err());
+}
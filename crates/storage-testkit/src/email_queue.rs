@@ -0,0 +1,188 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::Duration;
+use mas_data_model::UndeliverableEmailReason;
+use mas_storage::{
+    clock::MockClock, email_queue::QueuedEmailFilter, Clock, Pagination, RepositoryAccess,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+use crate::Backend;
+
+/// Test the email queue, by enqueuing, sending, failing and retrying emails
+pub async fn email_queue(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    assert!(repo.email_queue().next_due(&clock).await.unwrap().is_none());
+
+    let email = repo
+        .email_queue()
+        .enqueue(
+            &mut rng,
+            &clock,
+            "alice@example.com".to_owned(),
+            b"Subject: Hello\r\n\r\nHello".to_vec(),
+        )
+        .await
+        .unwrap();
+    assert!(email.state.is_pending());
+    assert_eq!(email.attempts, 0);
+
+    // The email is due right away
+    let due = repo.email_queue().next_due(&clock).await.unwrap().unwrap();
+    assert_eq!(due, email);
+
+    // Record a failure, with a retry in a minute
+    let retry_at = clock.now() + Duration::try_minutes(1).unwrap();
+    let email = repo
+        .email_queue()
+        .record_failure(&clock, due, "Connection refused".to_owned(), Some(retry_at))
+        .await
+        .unwrap();
+    assert!(email.state.is_pending());
+    assert_eq!(email.attempts, 1);
+    assert_eq!(email.last_error.as_deref(), Some("Connection refused"));
+
+    // It's not due anymore, until a minute passes
+    assert!(repo.email_queue().next_due(&clock).await.unwrap().is_none());
+    clock.advance(Duration::try_minutes(1).unwrap());
+    let due = repo.email_queue().next_due(&clock).await.unwrap().unwrap();
+    assert_eq!(due.id, email.id);
+
+    // Postponing it doesn't count as an attempt
+    let until = clock.now() + Duration::try_seconds(30).unwrap();
+    let email = repo.email_queue().postpone(due, until).await.unwrap();
+    assert!(email.state.is_pending());
+    assert_eq!(email.attempts, 1);
+    assert_eq!(email.next_attempt_at, until);
+    assert!(repo.email_queue().next_due(&clock).await.unwrap().is_none());
+    clock.advance(Duration::try_seconds(30).unwrap());
+    let due = repo.email_queue().next_due(&clock).await.unwrap().unwrap();
+    assert_eq!(due.id, email.id);
+
+    // Give up on it, it should land in the dead-letter list
+    let email = repo
+        .email_queue()
+        .record_failure(&clock, due, "Connection refused".to_owned(), None)
+        .await
+        .unwrap();
+    assert!(email.state.is_failed());
+    assert!(repo.email_queue().next_due(&clock).await.unwrap().is_none());
+
+    let failed = QueuedEmailFilter::new().failed_only();
+    let page = repo
+        .email_queue()
+        .list(failed, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0], email);
+    assert_eq!(repo.email_queue().count(failed).await.unwrap(), 1);
+    assert_eq!(
+        repo.email_queue()
+            .count(QueuedEmailFilter::new().pending_only())
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        repo.email_queue()
+            .count(QueuedEmailFilter::new().for_recipient("alice@example.com"))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        repo.email_queue()
+            .count(QueuedEmailFilter::new().for_recipient("bob@example.com"))
+            .await
+            .unwrap(),
+        0
+    );
+
+    // Retry it, and send it this time
+    let email = repo.email_queue().retry(&clock, email).await.unwrap();
+    assert!(email.state.is_pending());
+    assert_eq!(email.attempts, 0);
+    let page = repo
+        .email_queue()
+        .list(failed, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(page.edges.is_empty());
+
+    let due = repo.email_queue().next_due(&clock).await.unwrap().unwrap();
+    let email = repo.email_queue().mark_as_sent(&clock, due).await.unwrap();
+    assert!(email.state.is_sent());
+
+    let email = repo.email_queue().lookup(email.id).await.unwrap().unwrap();
+    assert!(email.state.is_sent());
+    assert!(repo.email_queue().next_due(&clock).await.unwrap().is_none());
+    assert_eq!(
+        repo.email_queue()
+            .count(QueuedEmailFilter::new().sent_only())
+            .await
+            .unwrap(),
+        1
+    );
+
+    repo.save().await.unwrap();
+}
+
+/// Test the tracking of undeliverable email addresses
+pub async fn undeliverable_addresses(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let clock = MockClock::default();
+
+    assert!(repo
+        .email_queue()
+        .find_undeliverable("alice@example.com")
+        .await
+        .unwrap()
+        .is_none());
+
+    let address = repo
+        .email_queue()
+        .mark_undeliverable(
+            &clock,
+            "Alice@Example.com",
+            UndeliverableEmailReason::Bounce,
+            Some("550 5.1.1 User unknown".to_owned()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(address.email, "alice@example.com");
+    assert_eq!(address.reason, UndeliverableEmailReason::Bounce);
+
+    // Lookups are case-insensitive
+    let found = repo
+        .email_queue()
+        .find_undeliverable("ALICE@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found, address);
+
+    // Marking it again updates the reason
+    clock.advance(Duration::try_minutes(1).unwrap());
+    let address = repo
+        .email_queue()
+        .mark_undeliverable(
+            &clock,
+            "alice@example.com",
+            UndeliverableEmailReason::Complaint,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(address.reason, UndeliverableEmailReason::Complaint);
+    assert_eq!(address.reported_at, clock.now());
+
+    repo.save().await.unwrap();
+}
@@ -0,0 +1,56 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::Duration;
+use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+
+use crate::Backend;
+
+/// Test the feature flag repository, setting, replacing and removing overrides
+pub async fn feature_flag_repository(backend: &impl Backend) {
+    let clock = MockClock::default();
+    let mut repo = backend.repository().await;
+
+    assert!(repo.feature_flag().all().await.unwrap().is_empty());
+
+    let passkeys = repo
+        .feature_flag()
+        .set(&clock, "passkeys".to_owned(), true, Some(10))
+        .await
+        .unwrap();
+    assert_eq!(passkeys.rollout_percentage, Some(10));
+    assert_eq!(passkeys.updated_at, clock.now());
+
+    repo.feature_flag()
+        .set(&clock, "new_registration".to_owned(), false, None)
+        .await
+        .unwrap();
+
+    let overrides = repo.feature_flag().all().await.unwrap();
+    assert_eq!(overrides.len(), 2);
+    // Ordered by name
+    assert_eq!(overrides[0].name, "new_registration");
+    assert!(!overrides[0].enabled);
+    assert_eq!(overrides[1], passkeys);
+
+    // Setting the flag again replaces the override
+    clock.advance(Duration::try_minutes(1).unwrap());
+    let passkeys = repo
+        .feature_flag()
+        .set(&clock, "passkeys".to_owned(), true, None)
+        .await
+        .unwrap();
+    let overrides = repo.feature_flag().all().await.unwrap();
+    assert_eq!(overrides.len(), 2);
+    assert_eq!(overrides[1], passkeys);
+    assert_eq!(overrides[1].rollout_percentage, None);
+
+    assert!(repo.feature_flag().remove("passkeys").await.unwrap());
+    assert!(!repo.feature_flag().remove("passkeys").await.unwrap());
+
+    let overrides = repo.feature_flag().all().await.unwrap();
+    assert_eq!(overrides.len(), 1);
+    assert_eq!(overrides[0].name, "new_registration");
+}
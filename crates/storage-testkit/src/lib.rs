@@ -0,0 +1,144 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Conformance test suite for the storage backends.
+//!
+//! This crate exercises the contracts of the repository traits defined in
+//! [`mas_storage`] against any implementation of them. A backend proves its
+//! compatibility by implementing the [`Backend`] trait and invoking the
+//! [`conformance_tests!`] macro in its test suite:
+//!
+//! ```ignore
+//! struct TestBackend(MyPool);
+//!
+//! #[async_trait::async_trait]
+//! impl mas_storage_testkit::Backend for TestBackend {
+//!     async fn repository(&self) -> mas_storage::BoxRepository {
+//!         MyRepository::from_pool(&self.0).await.unwrap().boxed()
+//!     }
+//! }
+//!
+//! mas_storage_testkit::conformance_tests! {
+//!     #[my_backend::test]
+//!     (pool: MyPool) => TestBackend(pool)
+//! }
+//! ```
+//!
+//! Each test expects a fresh, empty backend.
+
+#![deny(missing_docs)]
+#![allow(clippy::too_many_lines, clippy::missing_panics_doc)]
+
+use mas_storage::BoxRepository;
+
+/// Tests for the application sessions listing
+pub mod app_session;
+/// Tests for the compatibility layer repositories
+pub mod compat;
+/// Tests for the device key repository
+pub mod device_key;
+/// Tests for the email queue repository
+pub mod email_queue;
+/// Tests for the feature flag repository
+pub mod feature_flag;
+/// Tests for the OAuth 2.0 repositories
+pub mod oauth2;
+/// Tests for the statistics repository
+pub mod stats;
+/// Tests for the upstream OAuth 2.0 repositories
+pub mod upstream_oauth2;
+/// Tests for the user repositories
+pub mod user;
+
+/// A storage backend under test
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync {
+    /// Open a new repository on the backend.
+    ///
+    /// Tests may open multiple repositories on the same backend, to check
+    /// that the changes saved in one are visible in the next ones.
+    async fn repository(&self) -> BoxRepository;
+}
+
+/// Generate a test for each contract of the repository traits.
+///
+/// It takes the attribute used to mark the tests, the arguments the tests
+/// receive from that attribute, and an expression building the [`Backend`]
+/// from those arguments. The tests are grouped in modules named after the
+/// modules of this crate, which have access to the items of the module in
+/// which the macro is invoked.
+#[macro_export]
+macro_rules! conformance_tests {
+    (
+        #[$attr:meta]
+        $args:tt => $backend:expr $(,)?
+    ) => {
+        $crate::conformance_tests! {
+            @tests #[$attr] $args => $backend;
+            user {
+                user_repo,
+                user_email_repo,
+                user_password_repo,
+                user_session,
+                user_terms,
+                user_soft_delete,
+                user_login_history,
+            }
+            compat {
+                session_repository,
+                access_token_repository,
+                refresh_token_repository,
+                sso_login_repository,
+            }
+            oauth2 {
+                repositories,
+                list_sessions,
+                device_code_grant_repository,
+            }
+            upstream_oauth2 {
+                repository,
+                provider_repository_pagination,
+            }
+            app_session {
+                app_repo,
+            }
+            device_key {
+                device_key_repository,
+            }
+            email_queue {
+                email_queue,
+                undeliverable_addresses,
+            }
+            feature_flag {
+                feature_flag_repository,
+            }
+            stats {
+                stats,
+                dashboard,
+                experiments,
+            }
+        }
+    };
+
+    (
+        @tests #[$attr:meta] $args:tt => $backend:expr;
+        $( $module:ident { $( $test:ident ),* $(,)? } )*
+    ) => {
+        $(
+            mod $module {
+                #[allow(unused_imports)]
+                use super::*;
+
+                $(
+                    #[$attr]
+                    async fn $test $args {
+                        let backend = $backend;
+                        $crate::$module::$test(&backend).await;
+                    }
+                )*
+            }
+        )*
+    };
+}
@@ -0,0 +1,166 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::Duration;
+use mas_storage::{clock::MockClock, stats::ExperimentVariantStats, Clock, RepositoryAccess};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use ulid::Ulid;
+
+use crate::Backend;
+
+/// Test the usage statistics on a database with a single active user
+pub async fn stats(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let since = clock.now() - Duration::try_days(1).unwrap();
+
+    assert_eq!(repo.stats().count_active_users(since).await.unwrap(), 0);
+    assert_eq!(repo.stats().count_authentications(since).await.unwrap(), 0);
+    assert_eq!(
+        repo.stats()
+            .count_issued_access_tokens(since)
+            .await
+            .unwrap(),
+        0
+    );
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // Two active sessions for the same user count as one active user
+    for _ in 0..2 {
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .record_batch_activity(vec![(session.id, clock.now(), None)])
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(repo.stats().count_active_users(since).await.unwrap(), 1);
+
+    // Activity older than the window isn't counted
+    clock.advance(Duration::try_days(2).unwrap());
+    let since = clock.now() - Duration::try_days(1).unwrap();
+    assert_eq!(repo.stats().count_active_users(since).await.unwrap(), 0);
+}
+
+/// Test the dashboard aggregates
+pub async fn dashboard(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let today = clock.now().date_naive();
+
+    assert!(repo
+        .stats()
+        .login_failures_per_day(today)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Failures on the same day are counted together
+    repo.stats().record_login_failure(&clock).await.unwrap();
+    repo.stats().record_login_failure(&clock).await.unwrap();
+    clock.advance(Duration::try_days(1).unwrap());
+    repo.stats().record_login_failure(&clock).await.unwrap();
+
+    let failures = repo.stats().login_failures_per_day(today).await.unwrap();
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0].day, today);
+    assert_eq!(failures[0].count, 2);
+    assert_eq!(failures[1].count, 1);
+
+    // Aggregates are only updated when refreshed
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    repo.browser_session()
+        .add(&mut rng, &clock, &user, None)
+        .await
+        .unwrap();
+
+    let active = repo.stats().active_sessions().await.unwrap();
+    assert_eq!(active.browser, 0);
+
+    repo.stats().refresh_dashboard().await.unwrap();
+    let active = repo.stats().active_sessions().await.unwrap();
+    assert_eq!(active.browser, 1);
+    assert_eq!(active.oauth2, 0);
+    assert_eq!(active.compat, 0);
+}
+
+/// Test the exposures and conversions of the A/B experiments
+pub async fn experiments(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let today = clock.now().date_naive();
+
+    let alice = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+    let bob = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+
+    for (visitor, variant) in [(alice, "control"), (bob, "compact")] {
+        repo.stats()
+            .record_experiment_exposure(&clock, visitor, "login_layout", variant)
+            .await
+            .unwrap();
+    }
+
+    // Only the first exposure counts, even if the variant changed since
+    repo.stats()
+        .record_experiment_exposure(&clock, alice, "login_layout", "compact")
+        .await
+        .unwrap();
+
+    // Conversions of visitors which were not exposed are ignored
+    let carol = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+    repo.stats()
+        .record_experiment_conversion(&clock, carol)
+        .await
+        .unwrap();
+    repo.stats()
+        .record_experiment_conversion(&clock, bob)
+        .await
+        .unwrap();
+
+    let results = repo.stats().experiment_results(today).await.unwrap();
+    assert_eq!(
+        results,
+        vec![
+            ExperimentVariantStats {
+                experiment: "login_layout".to_owned(),
+                variant: "compact".to_owned(),
+                exposures: 1,
+                conversions: 1,
+            },
+            ExperimentVariantStats {
+                experiment: "login_layout".to_owned(),
+                variant: "control".to_owned(),
+                exposures: 1,
+                conversions: 0,
+            },
+        ]
+    );
+
+    // Exposures before the requested day are not counted
+    let tomorrow = today + Duration::try_days(1).unwrap();
+    assert!(repo
+        .stats()
+        .experiment_results(tomorrow)
+        .await
+        .unwrap()
+        .is_empty());
+}
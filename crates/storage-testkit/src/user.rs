@@ -0,0 +1,1110 @@
+// Copyright 2024 New Vector Ltd.
+// Copyright 2023, 2024 The Matrix.org Foundation C.I.C.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use chrono::Duration;
+use mas_data_model::{LoginMethod, RegistrationMetadata, UserAgent};
+use mas_storage::{
+    clock::MockClock,
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
+        UserFilter, UserLoginHistoryRepository, UserPasswordRepository, UserRepository,
+    },
+    Clock, Pagination, RepositoryAccess,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use ulid::Ulid;
+
+use crate::Backend;
+
+/// Test the user repository, by adding and looking up a user
+pub async fn user_repo(backend: &impl Backend) {
+    const USERNAME: &str = "john";
+
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let all = UserFilter::new();
+    let admin = all.can_request_admin_only();
+    let non_admin = all.cannot_request_admin_only();
+    let active = all.active_only();
+    let locked = all.locked_only();
+
+    // Initially, the user shouldn't exist
+    assert!(!repo.user().exists(USERNAME).await.unwrap());
+    assert!(repo
+        .user()
+        .find_by_username(USERNAME)
+        .await
+        .unwrap()
+        .is_none());
+
+    assert_eq!(repo.user().count(all).await.unwrap(), 0);
+    assert_eq!(repo.user().count(admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(non_admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(active).await.unwrap(), 0);
+    assert_eq!(repo.user().count(locked).await.unwrap(), 0);
+
+    // Adding the user should work
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .unwrap();
+
+    // And now it should exist
+    assert!(repo.user().exists(USERNAME).await.unwrap());
+    assert!(repo
+        .user()
+        .find_by_username(USERNAME)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(repo.user().lookup(user.id).await.unwrap().is_some());
+
+    // Loading it in a batch skips the users which don't exist
+    let users = repo
+        .user()
+        .load_batch(BTreeSet::from([user.id, Ulid::nil()]))
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[&user.id], user);
+
+    assert_eq!(repo.user().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user().count(admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(non_admin).await.unwrap(), 1);
+    assert_eq!(repo.user().count(active).await.unwrap(), 1);
+    assert_eq!(repo.user().count(locked).await.unwrap(), 0);
+
+    // Adding a second time should give a conflict
+    // It should not poison the transaction though
+    assert!(repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .is_err());
+
+    // Try locking a user
+    assert!(user.is_valid());
+    let user = repo.user().lock(&clock, user).await.unwrap();
+    assert!(!user.is_valid());
+
+    assert_eq!(repo.user().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user().count(admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(non_admin).await.unwrap(), 1);
+    assert_eq!(repo.user().count(active).await.unwrap(), 0);
+    assert_eq!(repo.user().count(locked).await.unwrap(), 1);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_valid());
+
+    // Locking a second time should not fail
+    let user = repo.user().lock(&clock, user).await.unwrap();
+    assert!(!user.is_valid());
+
+    // Try unlocking a user
+    let user = repo.user().unlock(user).await.unwrap();
+    assert!(user.is_valid());
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_valid());
+
+    // Unlocking a second time should not fail
+    let user = repo.user().unlock(user).await.unwrap();
+    assert!(user.is_valid());
+
+    // Set the can_request_admin flag
+    let user = repo.user().set_can_request_admin(user, true).await.unwrap();
+    assert!(user.can_request_admin);
+
+    assert_eq!(repo.user().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user().count(admin).await.unwrap(), 1);
+    assert_eq!(repo.user().count(non_admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(active).await.unwrap(), 1);
+    assert_eq!(repo.user().count(locked).await.unwrap(), 0);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.can_request_admin);
+
+    // Unset the can_request_admin flag
+    let user = repo
+        .user()
+        .set_can_request_admin(user, false)
+        .await
+        .unwrap();
+    assert!(!user.can_request_admin);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.can_request_admin);
+
+    assert_eq!(repo.user().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user().count(admin).await.unwrap(), 0);
+    assert_eq!(repo.user().count(non_admin).await.unwrap(), 1);
+    assert_eq!(repo.user().count(active).await.unwrap(), 1);
+    assert_eq!(repo.user().count(locked).await.unwrap(), 0);
+
+    // There is no session limit override by default
+    assert_eq!(repo.user().get_session_limit(&user).await.unwrap(), None);
+
+    // Set, update and remove the session limit override
+    repo.user()
+        .set_session_limit(&clock, &user, Some(3))
+        .await
+        .unwrap();
+    assert_eq!(repo.user().get_session_limit(&user).await.unwrap(), Some(3));
+
+    repo.user()
+        .set_session_limit(&clock, &user, Some(1))
+        .await
+        .unwrap();
+    assert_eq!(repo.user().get_session_limit(&user).await.unwrap(), Some(1));
+
+    repo.user()
+        .set_session_limit(&clock, &user, None)
+        .await
+        .unwrap();
+    assert_eq!(repo.user().get_session_limit(&user).await.unwrap(), None);
+
+    // Set, update and remove the timezone
+    assert_eq!(repo.user().get_timezone(&user).await.unwrap(), None);
+    repo.user()
+        .set_timezone(&clock, &user, Some("Europe/Paris"))
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user().get_timezone(&user).await.unwrap().as_deref(),
+        Some("Europe/Paris")
+    );
+
+    repo.user()
+        .set_timezone(&clock, &user, Some("America/New_York"))
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user().get_timezone(&user).await.unwrap().as_deref(),
+        Some("America/New_York")
+    );
+
+    repo.user().set_timezone(&clock, &user, None).await.unwrap();
+    assert_eq!(repo.user().get_timezone(&user).await.unwrap(), None);
+
+    // Assign and remove a password policy class
+    assert_eq!(
+        repo.user().get_password_policy_class(&user).await.unwrap(),
+        None
+    );
+    repo.user()
+        .set_password_policy_class(&clock, &user, Some("service"))
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user()
+            .get_password_policy_class(&user)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("service")
+    );
+
+    repo.user()
+        .set_password_policy_class(&clock, &user, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user().get_password_policy_class(&user).await.unwrap(),
+        None
+    );
+
+    // Require and clear a password change
+    assert!(!repo
+        .user()
+        .is_password_change_required(&user)
+        .await
+        .unwrap());
+    repo.user()
+        .set_password_change_required(&clock, &user, true)
+        .await
+        .unwrap();
+    assert!(repo
+        .user()
+        .is_password_change_required(&user)
+        .await
+        .unwrap());
+
+    // Setting it twice is fine
+    repo.user()
+        .set_password_change_required(&clock, &user, true)
+        .await
+        .unwrap();
+    assert!(repo
+        .user()
+        .is_password_change_required(&user)
+        .await
+        .unwrap());
+
+    repo.user()
+        .set_password_change_required(&clock, &user, false)
+        .await
+        .unwrap();
+    assert!(!repo
+        .user()
+        .is_password_change_required(&user)
+        .await
+        .unwrap());
+
+    // Record where the registration came from
+    assert_eq!(
+        repo.user().get_registration_metadata(&user).await.unwrap(),
+        None
+    );
+    let metadata = RegistrationMetadata {
+        referral: Some("spring-campaign".to_owned()),
+        ..RegistrationMetadata::default()
+    };
+    repo.user()
+        .set_registration_metadata(&user, &metadata)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user().get_registration_metadata(&user).await.unwrap(),
+        Some(metadata)
+    );
+
+    let stats = repo
+        .user()
+        .registration_stats(clock.now() - Duration::try_days(1).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(stats.total, 1);
+    assert!(stats.by_client.is_empty());
+    assert_eq!(stats.by_referral, vec![("spring-campaign".to_owned(), 1)]);
+
+    // Users registered before the given date are not counted
+    let stats = repo
+        .user()
+        .registration_stats(clock.now() + Duration::try_days(1).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(stats.total, 0);
+    assert!(stats.by_referral.is_empty());
+
+    // Check the list method
+    let list = repo.user().list(all, Pagination::first(10)).await.unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(list.edges[0].id, user.id);
+
+    let list = repo
+        .user()
+        .list(admin, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list.edges.len(), 0);
+
+    let list = repo
+        .user()
+        .list(non_admin, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(list.edges[0].id, user.id);
+
+    let list = repo
+        .user()
+        .list(active, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(list.edges[0].id, user.id);
+
+    let list = repo
+        .user()
+        .list(locked, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list.edges.len(), 0);
+
+    repo.save().await.unwrap();
+}
+
+/// Test the user email repository, by trying out most of its methods
+pub async fn user_email_repo(backend: &impl Backend) {
+    const USERNAME: &str = "john";
+    const CODE: &str = "012345";
+    const CODE2: &str = "543210";
+    const EMAIL: &str = "john@example.com";
+
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .unwrap();
+
+    // The user email should not exist yet
+    assert!(repo
+        .user_email()
+        .find(&user, EMAIL)
+        .await
+        .unwrap()
+        .is_none());
+
+    let all = UserEmailFilter::new().for_user(&user);
+    let pending = all.pending_only();
+    let verified = all.verified_only();
+
+    // Check the counts
+    assert_eq!(repo.user_email().count(all).await.unwrap(), 0);
+    assert_eq!(repo.user_email().count(pending).await.unwrap(), 0);
+    assert_eq!(repo.user_email().count(verified).await.unwrap(), 0);
+
+    let user_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, EMAIL.to_owned())
+        .await
+        .unwrap();
+
+    assert_eq!(user_email.user_id, user.id);
+    assert_eq!(user_email.email, EMAIL);
+    assert!(user_email.confirmed_at.is_none());
+
+    // Check the counts
+    assert_eq!(repo.user_email().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user_email().count(pending).await.unwrap(), 1);
+    assert_eq!(repo.user_email().count(verified).await.unwrap(), 0);
+
+    assert!(repo
+        .user_email()
+        .find(&user, EMAIL)
+        .await
+        .unwrap()
+        .is_some());
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email.id)
+        .await
+        .unwrap()
+        .expect("user email was not found");
+
+    assert_eq!(user_email.user_id, user.id);
+    assert_eq!(user_email.email, EMAIL);
+
+    let verification = repo
+        .user_email()
+        .add_verification_code(
+            &mut rng,
+            &clock,
+            &user_email,
+            Duration::try_hours(8).unwrap(),
+            CODE.to_owned(),
+        )
+        .await
+        .unwrap();
+
+    let verification_id = verification.id;
+    assert_eq!(verification.user_email_id, user_email.id);
+    assert_eq!(verification.code, CODE);
+
+    // A single user email can have multiple verification at the same time
+    let _verification2 = repo
+        .user_email()
+        .add_verification_code(
+            &mut rng,
+            &clock,
+            &user_email,
+            Duration::try_hours(8).unwrap(),
+            CODE2.to_owned(),
+        )
+        .await
+        .unwrap();
+
+    let verification = repo
+        .user_email()
+        .find_verification_code(&clock, &user_email, CODE)
+        .await
+        .unwrap()
+        .expect("user email verification was not found");
+
+    assert_eq!(verification.id, verification_id);
+    assert_eq!(verification.user_email_id, user_email.id);
+    assert_eq!(verification.code, CODE);
+
+    // Consuming the verification code
+    repo.user_email()
+        .consume_verification_code(&clock, verification)
+        .await
+        .unwrap();
+
+    // Mark the email as verified
+    repo.user_email()
+        .mark_as_verified(&clock, user_email)
+        .await
+        .unwrap();
+
+    // Check the counts
+    assert_eq!(repo.user_email().count(all).await.unwrap(), 1);
+    assert_eq!(repo.user_email().count(pending).await.unwrap(), 0);
+    assert_eq!(repo.user_email().count(verified).await.unwrap(), 1);
+
+    // Reload the user_email
+    let user_email = repo
+        .user_email()
+        .find(&user, EMAIL)
+        .await
+        .unwrap()
+        .expect("user email was not found");
+
+    // The email should be marked as verified now
+    assert!(user_email.confirmed_at.is_some());
+
+    // Reload the verification
+    let verification = repo
+        .user_email()
+        .find_verification_code(&clock, &user_email, CODE)
+        .await
+        .unwrap()
+        .expect("user email verification was not found");
+
+    // Consuming a second time should not work
+    assert!(repo
+        .user_email()
+        .consume_verification_code(&clock, verification)
+        .await
+        .is_err());
+
+    // The user shouldn't have a primary email yet
+    assert!(repo
+        .user_email()
+        .get_primary(&user)
+        .await
+        .unwrap()
+        .is_none());
+
+    repo.user_email().set_as_primary(&user_email).await.unwrap();
+
+    // Reload the user
+    let user = repo
+        .user()
+        .lookup(user.id)
+        .await
+        .unwrap()
+        .expect("user was not found");
+
+    // Now it should have one
+    assert!(repo
+        .user_email()
+        .get_primary(&user)
+        .await
+        .unwrap()
+        .is_some());
+
+    // Listing the user emails should work
+    let emails = repo
+        .user_email()
+        .list(all, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!emails.has_next_page);
+    assert_eq!(emails.edges.len(), 1);
+    assert_eq!(emails.edges[0], user_email);
+
+    let emails = repo
+        .user_email()
+        .list(verified, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!emails.has_next_page);
+    assert_eq!(emails.edges.len(), 1);
+    assert_eq!(emails.edges[0], user_email);
+
+    let emails = repo
+        .user_email()
+        .list(pending, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!emails.has_next_page);
+    assert!(emails.edges.is_empty());
+
+    // Listing emails from the email address should work
+    let emails = repo
+        .user_email()
+        .list(all.for_email(EMAIL), Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!emails.has_next_page);
+    assert_eq!(emails.edges.len(), 1);
+    assert_eq!(emails.edges[0], user_email);
+
+    // Filtering on another email should not return anything
+    let emails = repo
+        .user_email()
+        .list(all.for_email("hello@example.com"), Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!emails.has_next_page);
+    assert!(emails.edges.is_empty());
+
+    // Counting also works with the email filter
+    assert_eq!(
+        repo.user_email().count(all.for_email(EMAIL)).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        repo.user_email()
+            .count(all.for_email("hello@example.com"))
+            .await
+            .unwrap(),
+        0
+    );
+
+    // Deleting the user email should work
+    repo.user_email().remove(user_email).await.unwrap();
+    assert_eq!(repo.user_email().count(all).await.unwrap(), 0);
+    assert_eq!(repo.user_email().count(pending).await.unwrap(), 0);
+    assert_eq!(repo.user_email().count(verified).await.unwrap(), 0);
+
+    // Reload the user
+    let user = repo
+        .user()
+        .lookup(user.id)
+        .await
+        .unwrap()
+        .expect("user was not found");
+
+    // The primary user email should be gone
+    assert!(repo
+        .user_email()
+        .get_primary(&user)
+        .await
+        .unwrap()
+        .is_none());
+
+    repo.save().await.unwrap();
+}
+
+/// Test the user password repository implementation.
+pub async fn user_password_repo(backend: &impl Backend) {
+    const USERNAME: &str = "john";
+    const FIRST_PASSWORD_HASH: &str = "doesntmatter";
+    const SECOND_PASSWORD_HASH: &str = "alsodoesntmatter";
+
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .unwrap();
+
+    // User should have no active password
+    assert!(repo.user_password().active(&user).await.unwrap().is_none());
+
+    // Insert a first password
+    let first_password = repo
+        .user_password()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            1,
+            FIRST_PASSWORD_HASH.to_owned(),
+            None,
+        )
+        .await
+        .unwrap();
+
+    // User should now have an active password
+    let first_password_lookup = repo
+        .user_password()
+        .active(&user)
+        .await
+        .unwrap()
+        .expect("user should have an active password");
+
+    assert_eq!(first_password.id, first_password_lookup.id);
+    assert_eq!(first_password_lookup.hashed_password, FIRST_PASSWORD_HASH);
+    assert_eq!(first_password_lookup.version, 1);
+    assert_eq!(first_password_lookup.upgraded_from_id, None);
+
+    // Getting the last inserted password is based on the clock, so we need to
+    // advance it
+    clock.advance(Duration::microseconds(10 * 1000 * 1000));
+
+    let second_password = repo
+        .user_password()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            2,
+            SECOND_PASSWORD_HASH.to_owned(),
+            Some(&first_password),
+        )
+        .await
+        .unwrap();
+
+    // User should now have an active password
+    let second_password_lookup = repo
+        .user_password()
+        .active(&user)
+        .await
+        .unwrap()
+        .expect("user should have an active password");
+
+    assert_eq!(second_password.id, second_password_lookup.id);
+    assert_eq!(second_password_lookup.hashed_password, SECOND_PASSWORD_HASH);
+    assert_eq!(second_password_lookup.version, 2);
+    assert_eq!(
+        second_password_lookup.upgraded_from_id,
+        Some(first_password.id)
+    );
+
+    repo.save().await.unwrap();
+}
+
+/// Test the browser session repository
+pub async fn user_session(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    let all = BrowserSessionFilter::default();
+    let active = all.active_only();
+    let finished = all.finished_only();
+
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 0);
+
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    assert_eq!(session.user.id, alice.id);
+    assert!(session.finished_at.is_none());
+
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 0);
+
+    // The session should be in the list of active sessions
+    let session_list = repo
+        .browser_session()
+        .list(active, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!session_list.has_next_page);
+    assert_eq!(session_list.edges.len(), 1);
+    assert_eq!(session_list.edges[0], session);
+
+    let session_lookup = repo
+        .browser_session()
+        .lookup(session.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+
+    assert_eq!(session_lookup.id, session.id);
+    assert_eq!(session_lookup.user.id, alice.id);
+    assert!(session_lookup.finished_at.is_none());
+
+    // Load it in a batch, along with one which doesn't exist
+    let sessions = repo
+        .browser_session()
+        .load_batch(BTreeSet::from([session.id, Ulid::nil()]))
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[&session.id].user.id, alice.id);
+
+    // The session has no authentication yet
+    let authentications = repo
+        .browser_session()
+        .load_last_authentications(BTreeSet::from([session.id]))
+        .await
+        .unwrap();
+    assert!(authentications.is_empty());
+
+    let password = repo
+        .user_password()
+        .add(&mut rng, &clock, &alice, 1, "hash".to_owned(), None)
+        .await
+        .unwrap();
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session_lookup, &password)
+        .await
+        .unwrap();
+    clock.advance(Duration::try_seconds(10).unwrap());
+    let last_authentication = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session_lookup, &password)
+        .await
+        .unwrap();
+
+    let authentications = repo
+        .browser_session()
+        .load_last_authentications(BTreeSet::from([session.id, Ulid::nil()]))
+        .await
+        .unwrap();
+    assert_eq!(authentications.len(), 1);
+    assert_eq!(authentications[&session.id], last_authentication);
+
+    // Elevate the session with the last authentication
+    let elevation = repo
+        .browser_session()
+        .elevate(
+            &mut rng,
+            &clock,
+            &session_lookup,
+            &last_authentication,
+            "elevation-token".to_owned(),
+            Duration::try_minutes(5).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(elevation.authentication_id, last_authentication.id);
+    assert!(elevation.is_valid(clock.now()));
+
+    let elevation_lookup = repo
+        .browser_session()
+        .find_elevation(&session_lookup, "elevation-token")
+        .await
+        .unwrap()
+        .expect("elevation not found");
+    assert_eq!(elevation_lookup, elevation);
+    assert!(repo
+        .browser_session()
+        .find_elevation(&session_lookup, "other-token")
+        .await
+        .unwrap()
+        .is_none());
+
+    clock.advance(Duration::try_minutes(6).unwrap());
+    assert!(!elevation_lookup.is_valid(clock.now()));
+
+    // Finish the session
+    repo.browser_session()
+        .finish(&clock, session_lookup)
+        .await
+        .unwrap();
+
+    // The active session counter should be 0, and the finished one should be 1
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 1);
+
+    // The session should not be in the list of active sessions anymore
+    let session_list = repo
+        .browser_session()
+        .list(active, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!session_list.has_next_page);
+    assert!(session_list.edges.is_empty());
+
+    // Reload the session
+    let session_lookup = repo
+        .browser_session()
+        .lookup(session.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+
+    assert_eq!(session_lookup.id, session.id);
+    assert_eq!(session_lookup.user.id, alice.id);
+    // This time the session is finished
+    assert!(session_lookup.finished_at.is_some());
+
+    // Create a bunch of other sessions
+    for _ in 0..5 {
+        for user in &[&alice, &bob] {
+            repo.browser_session()
+                .add(&mut rng, &clock, user, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    let all_alice = BrowserSessionFilter::new().for_user(&alice);
+    let active_alice = BrowserSessionFilter::new().for_user(&alice).active_only();
+    let all_bob = BrowserSessionFilter::new().for_user(&bob);
+    let active_bob = BrowserSessionFilter::new().for_user(&bob).active_only();
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 11);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 10);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(all_alice).await.unwrap(), 6);
+    assert_eq!(repo.browser_session().count(active_alice).await.unwrap(), 5);
+    assert_eq!(repo.browser_session().count(all_bob).await.unwrap(), 5);
+    assert_eq!(repo.browser_session().count(active_bob).await.unwrap(), 5);
+
+    // Finish all the sessions for alice
+    let affected = repo
+        .browser_session()
+        .finish_bulk(&clock, active_alice)
+        .await
+        .unwrap();
+    assert_eq!(affected, 5);
+    assert_eq!(repo.browser_session().count(all_alice).await.unwrap(), 6);
+    assert_eq!(repo.browser_session().count(active_alice).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 6);
+
+    // Finish all the sessions for bob
+    let affected = repo
+        .browser_session()
+        .finish_bulk(&clock, active_bob)
+        .await
+        .unwrap();
+    assert_eq!(affected, 5);
+    assert_eq!(repo.browser_session().count(all_bob).await.unwrap(), 5);
+    assert_eq!(repo.browser_session().count(active_bob).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 11);
+}
+
+/// Test the user terms repository
+pub async fn user_terms(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // Accepting the terms should work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Accepting a second time should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Accepting a different terms should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms?v=2".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    repo.save().await.unwrap();
+}
+
+/// Test soft-deleting, restoring and purging users
+pub async fn user_soft_delete(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let active = UserFilter::new().active_only();
+    let deleted = UserFilter::new().deleted_only();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    let email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.com".to_owned())
+        .await
+        .unwrap();
+    repo.user_email().set_as_primary(&email).await.unwrap();
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None)
+        .await
+        .unwrap();
+
+    // Delete the user
+    let user = repo.user().delete(&clock, user).await.unwrap();
+    assert!(user.deleted_at.is_some());
+    assert!(!user.is_valid());
+    assert_eq!(repo.user().count(active).await.unwrap(), 0);
+    assert_eq!(repo.user().count(deleted).await.unwrap(), 1);
+
+    // The user can still be looked up, and the username stays reserved
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.deleted_at.is_some());
+    assert!(repo.user().exists("john").await.unwrap());
+
+    // Restore it
+    let user = repo.user().restore(user).await.unwrap();
+    assert!(user.deleted_at.is_none());
+    assert!(user.is_valid());
+    assert_eq!(repo.user().count(active).await.unwrap(), 1);
+    assert_eq!(repo.user().count(deleted).await.unwrap(), 0);
+
+    // Restoring a second time should not fail
+    let user = repo.user().restore(user).await.unwrap();
+
+    // Users are only purged once they were deleted before the given date
+    let user = repo.user().delete(&clock, user).await.unwrap();
+    let purged = repo
+        .user()
+        .purge_deleted(clock.now() - Duration::try_days(1).unwrap(), 100)
+        .await
+        .unwrap();
+    assert_eq!(purged, 0);
+
+    clock.advance(Duration::try_days(2).unwrap());
+    let purged = repo
+        .user()
+        .purge_deleted(clock.now() - Duration::try_days(1).unwrap(), 100)
+        .await
+        .unwrap();
+    assert_eq!(purged, 1);
+
+    // The user and its related data are gone
+    assert!(repo.user().lookup(user.id).await.unwrap().is_none());
+    assert!(!repo.user().exists("john").await.unwrap());
+    assert!(repo.user_email().lookup(email.id).await.unwrap().is_none());
+    assert!(repo
+        .browser_session()
+        .lookup(session.id)
+        .await
+        .unwrap()
+        .is_none());
+
+    repo.save().await.unwrap();
+}
+
+/// Test recording, listing and purging login attempts
+pub async fn user_login_history(backend: &impl Backend) {
+    let mut repo = backend.repository().await;
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    // Nothing was recorded yet
+    assert_eq!(repo.user_login_history().count(&alice).await.unwrap(), 0);
+
+    let failure = repo
+        .user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &alice,
+            LoginMethod::Password,
+            false,
+            Some("203.0.113.42".parse().unwrap()),
+            Some(UserAgent::parse("Mozilla/5.0".to_owned())),
+        )
+        .await
+        .unwrap();
+    assert!(!failure.succeeded);
+    assert_eq!(failure.user_id, alice.id);
+
+    clock.advance(Duration::try_days(2).unwrap());
+    let success = repo
+        .user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &alice,
+            LoginMethod::UpstreamOAuth2,
+            true,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    repo.user_login_history()
+        .record(
+            &mut rng,
+            &clock,
+            &bob,
+            LoginMethod::ClientCertificate,
+            true,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Attempts are listed per user, oldest first
+    assert_eq!(repo.user_login_history().count(&alice).await.unwrap(), 2);
+    let page = repo
+        .user_login_history()
+        .list(&alice, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(!page.has_next_page);
+    assert_eq!(page.edges, vec![failure, success.clone()]);
+
+    // Only the attempts past the retention period are purged
+    let purged = repo
+        .user_login_history()
+        .purge(clock.now() - Duration::try_days(1).unwrap(), 100)
+        .await
+        .unwrap();
+    assert_eq!(purged, 1);
+
+    let page = repo
+        .user_login_history()
+        .list(&alice, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges, vec![success]);
+    assert_eq!(repo.user_login_history().count(&bob).await.unwrap(), 1);
+
+    repo.save().await.unwrap();
+}
@@ -48,6 +48,23 @@ pub trait CompatAccessTokenRepository: Send + Sync {
         access_token: &str,
     ) -> Result<Option<CompatAccessToken>, Self::Error>;
 
+    /// List the compat access tokens of a compat session which are still
+    /// active, i.e. not expired
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    /// * `compat_session`: The compat session to list the access tokens of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatAccessToken>, Self::Error>;
+
     /// Add a new compat access token to the database
     ///
     /// Returns the newly created compat access token
@@ -96,6 +113,12 @@ repository_impl!(CompatAccessTokenRepository:
         access_token: &str,
     ) -> Result<Option<CompatAccessToken>, Self::Error>;
 
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatAccessToken>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -47,6 +47,21 @@ pub trait CompatRefreshTokenRepository: Send + Sync {
         refresh_token: &str,
     ) -> Result<Option<CompatRefreshToken>, Self::Error>;
 
+    /// List the compat refresh tokens of a compat session which were not
+    /// consumed yet
+    ///
+    /// # Parameters
+    ///
+    /// * `compat_session`: The compat session to list the refresh tokens of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_active(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatRefreshToken>, Self::Error>;
+
     /// Add a new compat refresh token to the database
     ///
     /// Returns the newly created compat refresh token
@@ -96,6 +111,11 @@ repository_impl!(CompatRefreshTokenRepository:
         refresh_token: &str,
     ) -> Result<Option<CompatRefreshToken>, Self::Error>;
 
+    async fn list_active(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Vec<CompatRefreshToken>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -324,6 +324,36 @@ pub trait CompatSessionRepository: Send + Sync {
         compat_session: CompatSession,
         user_agent: UserAgent,
     ) -> Result<CompatSession, Self::Error>;
+
+    /// Get the name the user gave to a compat session, if any
+    ///
+    /// # Parameters
+    ///
+    /// * `compat_session`: The compat session to get the name of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn human_name(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Option<String>, Self::Error>;
+
+    /// Set or remove the name the user gave to a compat session
+    ///
+    /// # Parameters
+    ///
+    /// * `compat_session`: The compat session to name
+    /// * `human_name`: The name to set, or `None` to remove it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_human_name(
+        &mut self,
+        compat_session: &CompatSession,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error>;
 }
 
 repository_impl!(CompatSessionRepository:
@@ -369,4 +399,15 @@ repository_impl!(CompatSessionRepository:
         compat_session: CompatSession,
         user_agent: UserAgent,
     ) -> Result<CompatSession, Self::Error>;
+
+    async fn human_name(
+        &mut self,
+        compat_session: &CompatSession,
+    ) -> Result<Option<String>, Self::Error>;
+
+    async fn set_human_name(
+        &mut self,
+        compat_session: &CompatSession,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error>;
 );
@@ -0,0 +1,128 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the device keys bound to sessions
+
+use async_trait::async_trait;
+use mas_data_model::{CompatSession, DeviceKey, Session};
+use mas_jose::jwk::PublicJsonWebKey;
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`DeviceKeyRepository`] helps interacting with the [`DeviceKey`] bound to
+/// OAuth 2.0 and compatibility sessions
+#[async_trait]
+pub trait DeviceKeyRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Find the [`DeviceKey`] bound to an OAuth 2.0 [`Session`]
+    ///
+    /// Returns `None` if the session has no device key
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The OAuth 2.0 session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_for_oauth2_session(
+        &mut self,
+        session: &Session,
+    ) -> Result<Option<DeviceKey>, Self::Error>;
+
+    /// Find the [`DeviceKey`] bound to a [`CompatSession`]
+    ///
+    /// Returns `None` if the session has no device key
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The compatibility session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_for_compat_session(
+        &mut self,
+        session: &CompatSession,
+    ) -> Result<Option<DeviceKey>, Self::Error>;
+
+    /// Bind a device key to an OAuth 2.0 [`Session`]
+    ///
+    /// Returns the newly bound [`DeviceKey`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator used to generate IDs
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session`: The OAuth 2.0 session, which must not have a device key yet
+    /// * `public_key`: The public key of the device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// session already has a device key
+    async fn add_for_oauth2_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error>;
+
+    /// Bind a device key to a [`CompatSession`]
+    ///
+    /// Returns the newly bound [`DeviceKey`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator used to generate IDs
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session`: The compatibility session, which must not have a device key
+    ///   yet
+    /// * `public_key`: The public key of the device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// session already has a device key
+    async fn add_for_compat_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &CompatSession,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error>;
+}
+
+repository_impl!(DeviceKeyRepository:
+    async fn find_for_oauth2_session(
+        &mut self,
+        session: &Session,
+    ) -> Result<Option<DeviceKey>, Self::Error>;
+
+    async fn find_for_compat_session(
+        &mut self,
+        session: &CompatSession,
+    ) -> Result<Option<DeviceKey>, Self::Error>;
+
+    async fn add_for_oauth2_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error>;
+
+    async fn add_for_compat_session(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &CompatSession,
+        public_key: PublicJsonWebKey,
+    ) -> Result<DeviceKey, Self::Error>;
+);
@@ -0,0 +1,350 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repository to manage the queue of outgoing emails, and the addresses which
+//! should not receive emails anymore
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{QueuedEmail, UndeliverableEmailAddress, UndeliverableEmailReason};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock, Page, Pagination};
+
+/// The delivery state to filter queued emails on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuedEmailStateFilter {
+    /// The email is waiting to be sent
+    Pending,
+
+    /// The email was sent
+    Sent,
+
+    /// The email is in the dead-letter list
+    Failed,
+}
+
+/// Filter parameters for listing queued emails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct QueuedEmailFilter<'a> {
+    recipient: Option<&'a str>,
+    state: Option<QueuedEmailStateFilter>,
+}
+
+impl<'a> QueuedEmailFilter<'a> {
+    /// Create a new [`QueuedEmailFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter for emails sent to a specific address
+    #[must_use]
+    pub fn for_recipient(mut self, recipient: &'a str) -> Self {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    /// Get the recipient filter
+    ///
+    /// Returns [`None`] if no recipient filter is set
+    #[must_use]
+    pub fn recipient(&self) -> Option<&str> {
+        self.recipient
+    }
+
+    /// Filter for emails waiting to be sent
+    #[must_use]
+    pub fn pending_only(mut self) -> Self {
+        self.state = Some(QueuedEmailStateFilter::Pending);
+        self
+    }
+
+    /// Filter for emails which were sent
+    #[must_use]
+    pub fn sent_only(mut self) -> Self {
+        self.state = Some(QueuedEmailStateFilter::Sent);
+        self
+    }
+
+    /// Filter for emails in the dead-letter list
+    #[must_use]
+    pub fn failed_only(mut self) -> Self {
+        self.state = Some(QueuedEmailStateFilter::Failed);
+        self
+    }
+
+    /// Get the state filter
+    ///
+    /// Returns [`None`] if no state filter is set
+    #[must_use]
+    pub fn state(&self) -> Option<QueuedEmailStateFilter> {
+        self.state
+    }
+}
+
+/// An [`EmailQueueRepository`] helps interacting with the queue of outgoing
+/// emails saved in the storage backend
+#[async_trait]
+pub trait EmailQueueRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a queued email by its ID
+    ///
+    /// Returns `None` if no email was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the email to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<QueuedEmail>, Self::Error>;
+
+    /// Add an email to the queue, to be sent as soon as possible
+    ///
+    /// Returns the newly queued email
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `recipient`: The address the email is sent to
+    /// * `message`: The fully formatted message
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn enqueue(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        recipient: String,
+        message: Vec<u8>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    /// Get the next pending email which is due to be sent, and lock it until
+    /// the end of the transaction, so that concurrent workers skip it
+    ///
+    /// Returns `None` if no email is due
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn next_due(&mut self, clock: &dyn Clock) -> Result<Option<QueuedEmail>, Self::Error>;
+
+    /// Mark a queued email as sent
+    ///
+    /// Returns the updated email
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `email`: The email to mark as sent
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_as_sent(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    /// Record a failed attempt to send a queued email
+    ///
+    /// Returns the updated email
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `email`: The email which failed to send
+    /// * `error`: The error which happened
+    /// * `retry_at`: When to try again. If `None`, the email is moved to the
+    ///   dead-letter list
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_failure(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+        error: String,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    /// Postpone sending a queued email, without counting it as a failed
+    /// attempt
+    ///
+    /// Returns the updated email
+    ///
+    /// # Parameters
+    ///
+    /// * `email`: The email to postpone
+    /// * `until`: When to try sending it again
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn postpone(
+        &mut self,
+        email: QueuedEmail,
+        until: DateTime<Utc>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    /// List queued emails matching the given filter, with the given
+    /// pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: QueuedEmailFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<QueuedEmail>, Self::Error>;
+
+    /// Count the queued emails matching the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: QueuedEmailFilter<'_>) -> Result<usize, Self::Error>;
+
+    /// Move an email out of the dead-letter list, so that it is sent again
+    /// as soon as possible
+    ///
+    /// Returns the updated email
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    /// * `email`: The email to retry
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn retry(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    /// Mark an email address as undeliverable, as reported by the mail
+    /// provider. If the address was already marked, the reason and details
+    /// are updated.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `email`: The email address
+    /// * `reason`: Why the address is undeliverable
+    /// * `details`: Additional information given by the mail provider
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_undeliverable(
+        &mut self,
+        clock: &dyn Clock,
+        email: &str,
+        reason: UndeliverableEmailReason,
+        details: Option<String>,
+    ) -> Result<UndeliverableEmailAddress, Self::Error>;
+
+    /// Check whether an email address was marked as undeliverable
+    ///
+    /// Returns `None` if the address can receive emails
+    ///
+    /// # Parameters
+    ///
+    /// * `email`: The email address to check
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_undeliverable(
+        &mut self,
+        email: &str,
+    ) -> Result<Option<UndeliverableEmailAddress>, Self::Error>;
+}
+
+repository_impl!(EmailQueueRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<QueuedEmail>, Self::Error>;
+
+    async fn enqueue(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        recipient: String,
+        message: Vec<u8>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    async fn next_due(&mut self, clock: &dyn Clock) -> Result<Option<QueuedEmail>, Self::Error>;
+
+    async fn mark_as_sent(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    async fn record_failure(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+        error: String,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    async fn postpone(
+        &mut self,
+        email: QueuedEmail,
+        until: DateTime<Utc>,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: QueuedEmailFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<QueuedEmail>, Self::Error>;
+
+    async fn count(&mut self, filter: QueuedEmailFilter<'_>) -> Result<usize, Self::Error>;
+
+    async fn retry(
+        &mut self,
+        clock: &dyn Clock,
+        email: QueuedEmail,
+    ) -> Result<QueuedEmail, Self::Error>;
+
+    async fn mark_undeliverable(
+        &mut self,
+        clock: &dyn Clock,
+        email: &str,
+        reason: UndeliverableEmailReason,
+        details: Option<String>,
+    ) -> Result<UndeliverableEmailAddress, Self::Error>;
+
+    async fn find_undeliverable(
+        &mut self,
+        email: &str,
+    ) -> Result<Option<UndeliverableEmailAddress>, Self::Error>;
+);
@@ -0,0 +1,77 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the overrides of the feature flags
+
+use async_trait::async_trait;
+use mas_data_model::FeatureFlagOverride;
+
+use crate::{repository_impl, Clock};
+
+/// A [`FeatureFlagRepository`] helps interacting with the
+/// [`FeatureFlagOverride`] saved in the storage backend
+#[async_trait]
+pub trait FeatureFlagRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// List all the [`FeatureFlagOverride`], ordered by name
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn all(&mut self) -> Result<Vec<FeatureFlagOverride>, Self::Error>;
+
+    /// Override a feature flag, replacing any previous override of the same
+    /// flag
+    ///
+    /// Returns the saved [`FeatureFlagOverride`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `name`: The name of the flag
+    /// * `enabled`: Whether the flag is enabled at all
+    /// * `rollout_percentage`: The percentage of users for which the flag is
+    ///   enabled, or `None` for all users
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        name: String,
+        enabled: bool,
+        rollout_percentage: Option<u8>,
+    ) -> Result<FeatureFlagOverride, Self::Error>;
+
+    /// Remove the override of a feature flag
+    ///
+    /// Returns `true` if the flag was overridden
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: The name of the flag
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove(&mut self, name: &str) -> Result<bool, Self::Error>;
+}
+
+repository_impl!(FeatureFlagRepository:
+    async fn all(&mut self) -> Result<Vec<FeatureFlagOverride>, Self::Error>;
+
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        name: String,
+        enabled: bool,
+        rollout_percentage: Option<u8>,
+    ) -> Result<FeatureFlagOverride, Self::Error>;
+
+    async fn remove(&mut self, name: &str) -> Result<bool, Self::Error>;
+);
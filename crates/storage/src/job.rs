@@ -408,6 +408,45 @@ mod jobs {
         const NAME: &'static str = "sync-devices";
     }
 
+    /// A job which immediately deletes a revoked device from the homeserver,
+    /// so that it stops accepting its tokens without waiting for its
+    /// introspection cache to expire
+    ///
+    /// The device is kept if another active session of the user still uses it.
+    /// Failures are retried by the job queue.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct RevokeDeviceJob {
+        user_id: Ulid,
+        device_id: String,
+    }
+
+    impl RevokeDeviceJob {
+        /// Create a new job to revoke a device of a user on the homeserver
+        #[must_use]
+        pub fn new(user_id: Ulid, device: &Device) -> Self {
+            Self {
+                user_id,
+                device_id: device.as_str().to_owned(),
+            }
+        }
+
+        /// The ID of the user owning the device
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// The ID of the device to revoke
+        #[must_use]
+        pub fn device_id(&self) -> &str {
+            &self.device_id
+        }
+    }
+
+    impl Job for RevokeDeviceJob {
+        const NAME: &'static str = "revoke-device";
+    }
+
     /// A job to deactivate and lock a user
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct DeactivateUserJob {
@@ -510,5 +549,5 @@ mod jobs {
 
 pub use self::jobs::{
     DeactivateUserJob, DeleteDeviceJob, ProvisionDeviceJob, ProvisionUserJob, ReactivateUserJob,
-    SendAccountRecoveryEmailsJob, SyncDevicesJob, VerifyEmailJob,
+    RevokeDeviceJob, SendAccountRecoveryEmailsJob, SyncDevicesJob, VerifyEmailJob,
 };
@@ -118,8 +118,12 @@ mod utils;
 
 pub mod app_session;
 pub mod compat;
+pub mod device_key;
+pub mod email_queue;
+pub mod feature_flag;
 pub mod job;
 pub mod oauth2;
+pub mod stats;
 pub mod upstream_oauth2;
 pub mod user;
 
@@ -129,5 +133,5 @@ pub use self::{
     repository::{
         BoxRepository, Repository, RepositoryAccess, RepositoryError, RepositoryTransaction,
     },
-    utils::{BoxClock, BoxRng, MapErr},
+    utils::{BoxClock, BoxRng, Instrumented, MapErr, RepositoryMetrics},
 };
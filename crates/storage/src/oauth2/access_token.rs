@@ -6,12 +6,32 @@
 
 use async_trait::async_trait;
 use chrono::Duration;
-use mas_data_model::{AccessToken, Session};
+use mas_data_model::{AccessToken, Session, User};
 use rand_core::RngCore;
 use ulid::Ulid;
 
 use crate::{repository_impl, Clock};
 
+/// An access token along with everything needed to introspect it, as returned
+/// by [`OAuth2AccessTokenRepository::find_for_introspection`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessTokenIntrospection {
+    /// The access token
+    pub access_token: AccessToken,
+
+    /// The session the access token belongs to
+    pub session: Session,
+
+    /// The user of the session, if the session has one and it still exists
+    pub user: Option<User>,
+
+    /// The human-readable name of the client of the session, if it has one
+    pub client_name: Option<String>,
+
+    /// Whether the session has a refresh token which wasn't consumed yet
+    pub has_active_refresh_token: bool,
+}
+
 /// An [`OAuth2AccessTokenRepository`] helps interacting with [`AccessToken`]
 /// saved in the storage backend
 #[async_trait]
@@ -48,6 +68,43 @@ pub trait OAuth2AccessTokenRepository: Send + Sync {
         access_token: &str,
     ) -> Result<Option<AccessToken>, Self::Error>;
 
+    /// Find an access token by its token, along with its session, the user of
+    /// the session, and whether the session has an active refresh token
+    ///
+    /// This is on the hot path of the token introspection, so implementations
+    /// should fetch everything at once instead of doing several lookups.
+    ///
+    /// Returns `None` if the access token or its session doesn't exist
+    ///
+    /// # Parameters
+    ///
+    /// * `access_token`: The token of the access token to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<AccessTokenIntrospection>, Self::Error>;
+
+    /// List the access tokens of a session which are still active, i.e. not
+    /// revoked nor expired
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    /// * `session`: The session to list the access tokens of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        session: &Session,
+    ) -> Result<Vec<AccessToken>, Self::Error>;
+
     /// Add a new access token to the database
     ///
     /// Returns the newly created access token
@@ -113,6 +170,17 @@ repository_impl!(OAuth2AccessTokenRepository:
         access_token: &str,
     ) -> Result<Option<AccessToken>, Self::Error>;
 
+    async fn find_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<AccessTokenIntrospection>, Self::Error>;
+
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        session: &Session,
+    ) -> Result<Vec<AccessToken>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
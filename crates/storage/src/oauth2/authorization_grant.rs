@@ -7,7 +7,7 @@
 use std::num::NonZeroU32;
 
 use async_trait::async_trait;
-use mas_data_model::{AuthorizationCode, AuthorizationGrant, Client, Session};
+use mas_data_model::{AuthorizationCode, AuthorizationGrant, Client, ClientFingerprint, Session};
 use oauth2_types::{requests::ResponseMode, scope::Scope};
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -147,6 +147,40 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Record the fingerprint of the context in which an authorization grant
+    /// was requested
+    ///
+    /// # Parameters
+    ///
+    /// * `authorization_grant`: The authorization grant to update
+    /// * `fingerprint`: The fingerprint of the request
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_fingerprint(
+        &mut self,
+        authorization_grant: &AuthorizationGrant,
+        fingerprint: &ClientFingerprint,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the fingerprint of the context in which an authorization grant was
+    /// requested
+    ///
+    /// Returns `None` if no fingerprint was recorded
+    ///
+    /// # Parameters
+    ///
+    /// * `authorization_grant`: The authorization grant to look up
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_fingerprint(
+        &mut self,
+        authorization_grant: &AuthorizationGrant,
+    ) -> Result<Option<ClientFingerprint>, Self::Error>;
 }
 
 repository_impl!(OAuth2AuthorizationGrantRepository:
@@ -189,4 +223,15 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn set_fingerprint(
+        &mut self,
+        authorization_grant: &AuthorizationGrant,
+        fingerprint: &ClientFingerprint,
+    ) -> Result<(), Self::Error>;
+
+    async fn get_fingerprint(
+        &mut self,
+        authorization_grant: &AuthorizationGrant,
+    ) -> Result<Option<ClientFingerprint>, Self::Error>;
 );
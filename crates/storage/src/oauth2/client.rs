@@ -152,6 +152,13 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
+    /// List all clients, static or not
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn all(&mut self) -> Result<Vec<Client>, Self::Error>;
+
     /// Get the list of scopes that the user has given consent for the given
     /// client
     ///
@@ -260,6 +267,8 @@ repository_impl!(OAuth2ClientRepository:
 
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
+    async fn all(&mut self) -> Result<Vec<Client>, Self::Error>;
+
     async fn delete(&mut self, client: Client) -> Result<(), Self::Error>;
 
     async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error>;
@@ -14,7 +14,7 @@ mod refresh_token;
 mod session;
 
 pub use self::{
-    access_token::OAuth2AccessTokenRepository,
+    access_token::{AccessTokenIntrospection, OAuth2AccessTokenRepository},
     authorization_grant::OAuth2AuthorizationGrantRepository,
     client::OAuth2ClientRepository,
     device_code_grant::{OAuth2DeviceCodeGrantParams, OAuth2DeviceCodeGrantRepository},
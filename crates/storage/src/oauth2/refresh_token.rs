@@ -47,6 +47,17 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
         refresh_token: &str,
     ) -> Result<Option<RefreshToken>, Self::Error>;
 
+    /// List the refresh tokens of a session which were not consumed yet
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The session to list the refresh tokens of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_active(&mut self, session: &Session) -> Result<Vec<RefreshToken>, Self::Error>;
+
     /// Add a new refresh token to the database
     ///
     /// Returns the newly created [`RefreshToken`]
@@ -100,6 +111,8 @@ repository_impl!(OAuth2RefreshTokenRepository:
         refresh_token: &str,
     ) -> Result<Option<RefreshToken>, Self::Error>;
 
+    async fn list_active(&mut self, session: &Session) -> Result<Vec<RefreshToken>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -372,6 +372,50 @@ pub trait OAuth2SessionRepository: Send + Sync {
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    /// Replace the scope of a [`Session`]
+    ///
+    /// This is used to narrow down the scope of a session when a client
+    /// requests a smaller scope while refreshing a token.
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to update
+    /// * `scope`: The new [`Scope`] of the [`Session`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_scope(&mut self, session: Session, scope: Scope) -> Result<Session, Self::Error>;
+
+    /// Get the name the user gave to a [`Session`], if any
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to get the name of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn human_name(&mut self, session: &Session) -> Result<Option<String>, Self::Error>;
+
+    /// Set or remove the name the user gave to a [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to name
+    /// * `human_name`: The name to set, or `None` to remove it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_human_name(
+        &mut self,
+        session: &Session,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error>;
 }
 
 repository_impl!(OAuth2SessionRepository:
@@ -431,4 +475,14 @@ repository_impl!(OAuth2SessionRepository:
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    async fn set_scope(&mut self, session: Session, scope: Scope) -> Result<Session, Self::Error>;
+
+    async fn human_name(&mut self, session: &Session) -> Result<Option<String>, Self::Error>;
+
+    async fn set_human_name(
+        &mut self,
+        session: &Session,
+        human_name: Option<&str>,
+    ) -> Result<(), Self::Error>;
 );
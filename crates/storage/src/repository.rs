@@ -13,18 +13,22 @@ use crate::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
+    device_key::DeviceKeyRepository,
+    email_queue::EmailQueueRepository,
+    feature_flag::FeatureFlagRepository,
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
         OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
+    stats::StatsRepository,
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
     user::{
-        BrowserSessionRepository, UserEmailRepository, UserPasswordRepository,
-        UserRecoveryRepository, UserRepository, UserTermsRepository,
+        BrowserSessionRepository, UserEmailRepository, UserLoginHistoryRepository,
+        UserPasswordRepository, UserRecoveryRepository, UserRepository, UserTermsRepository,
     },
 };
 
@@ -132,6 +136,11 @@ pub trait RepositoryAccess: Send {
     /// Get an [`UserTermsRepository`]
     fn user_terms<'c>(&'c mut self) -> Box<dyn UserTermsRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`UserLoginHistoryRepository`]
+    fn user_login_history<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserLoginHistoryRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`BrowserSessionRepository`]
     fn browser_session<'c>(
         &'c mut self,
@@ -189,12 +198,25 @@ pub trait RepositoryAccess: Send {
         &'c mut self,
     ) -> Box<dyn CompatRefreshTokenRepository<Error = Self::Error> + 'c>;
 
+    /// Get a [`StatsRepository`]
+    fn stats<'c>(&'c mut self) -> Box<dyn StatsRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`DeviceKeyRepository`]
+    fn device_key<'c>(&'c mut self) -> Box<dyn DeviceKeyRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`EmailQueueRepository`]
+    fn email_queue<'c>(&'c mut self) -> Box<dyn EmailQueueRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`FeatureFlagRepository`]
+    fn feature_flag<'c>(&'c mut self) -> Box<dyn FeatureFlagRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`JobRepository`]
     fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c>;
 }
 
 /// Implementations of the [`RepositoryAccess`], [`RepositoryTransaction`] and
-/// [`Repository`] for the [`crate::MapErr`] wrapper and [`Box<R>`]
+/// [`Repository`] for the [`crate::MapErr`] and [`crate::Instrumented`]
+/// wrappers and [`Box<R>`]
 mod impls {
     use futures_util::{future::BoxFuture, FutureExt, TryFutureExt};
 
@@ -205,12 +227,16 @@ mod impls {
             CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
             CompatSsoLoginRepository,
         },
+        device_key::DeviceKeyRepository,
+        email_queue::EmailQueueRepository,
+        feature_flag::FeatureFlagRepository,
         job::JobRepository,
         oauth2::{
             OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
             OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository,
             OAuth2SessionRepository,
         },
+        stats::StatsRepository,
         upstream_oauth2::{
             UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
             UpstreamOAuthSessionRepository,
@@ -219,7 +245,7 @@ mod impls {
             BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository,
             UserTermsRepository,
         },
-        MapErr, Repository, RepositoryTransaction,
+        Instrumented, MapErr, Repository, RepositoryTransaction,
     };
 
     // --- Repository ---
@@ -312,6 +338,15 @@ mod impls {
             Box::new(MapErr::new(self.inner.user_terms(), &mut self.mapper))
         }
 
+        fn user_login_history<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserLoginHistoryRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_login_history(),
+                &mut self.mapper,
+            ))
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -402,6 +437,26 @@ mod impls {
             ))
         }
 
+        fn stats<'c>(&'c mut self) -> Box<dyn StatsRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.stats(), &mut self.mapper))
+        }
+
+        fn device_key<'c>(&'c mut self) -> Box<dyn DeviceKeyRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.device_key(), &mut self.mapper))
+        }
+
+        fn email_queue<'c>(
+            &'c mut self,
+        ) -> Box<dyn EmailQueueRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.email_queue(), &mut self.mapper))
+        }
+
+        fn feature_flag<'c>(
+            &'c mut self,
+        ) -> Box<dyn FeatureFlagRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.feature_flag(), &mut self.mapper))
+        }
+
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             Box::new(MapErr::new(self.inner.job(), &mut self.mapper))
         }
@@ -452,6 +507,12 @@ mod impls {
             (**self).user_terms()
         }
 
+        fn user_login_history<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserLoginHistoryRepository<Error = Self::Error> + 'c> {
+            (**self).user_login_history()
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -524,8 +585,251 @@ mod impls {
             (**self).compat_refresh_token()
         }
 
+        fn stats<'c>(&'c mut self) -> Box<dyn StatsRepository<Error = Self::Error> + 'c> {
+            (**self).stats()
+        }
+
+        fn device_key<'c>(&'c mut self) -> Box<dyn DeviceKeyRepository<Error = Self::Error> + 'c> {
+            (**self).device_key()
+        }
+
+        fn email_queue<'c>(
+            &'c mut self,
+        ) -> Box<dyn EmailQueueRepository<Error = Self::Error> + 'c> {
+            (**self).email_queue()
+        }
+
+        fn feature_flag<'c>(
+            &'c mut self,
+        ) -> Box<dyn FeatureFlagRepository<Error = Self::Error> + 'c> {
+            (**self).feature_flag()
+        }
+
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             (**self).job()
         }
     }
+
+    // --- Repository ---
+    impl<R, E> Repository<E> for Instrumented<R>
+    where
+        R: Repository<E> + RepositoryAccess<Error = E> + RepositoryTransaction<Error = E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+    }
+
+    // --- RepositoryTransaction --
+    impl<R> RepositoryTransaction for Instrumented<R>
+    where
+        R: RepositoryTransaction,
+        R::Error: 'static,
+    {
+        type Error = R::Error;
+
+        fn save(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
+            let metrics = self.metrics;
+            let save = Box::new(self.inner).save();
+            async move { metrics.record("RepositoryTransaction::save", save).await }.boxed()
+        }
+
+        fn cancel(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
+            let metrics = self.metrics;
+            let cancel = Box::new(self.inner).cancel();
+            async move {
+                metrics
+                    .record("RepositoryTransaction::cancel", cancel)
+                    .await
+            }
+            .boxed()
+        }
+    }
+
+    // --- RepositoryAccess --
+    impl<R> RepositoryAccess for Instrumented<R>
+    where
+        R: RepositoryAccess,
+        R::Error: 'static,
+    {
+        type Error = R::Error;
+
+        fn upstream_oauth_link<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthLinkRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.upstream_oauth_link(),
+                self.metrics,
+            ))
+        }
+
+        fn upstream_oauth_provider<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthProviderRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.upstream_oauth_provider(),
+                self.metrics,
+            ))
+        }
+
+        fn upstream_oauth_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthSessionRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.upstream_oauth_session(),
+                self.metrics,
+            ))
+        }
+
+        fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.user(), self.metrics))
+        }
+
+        fn user_email<'c>(&'c mut self) -> Box<dyn UserEmailRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.user_email(), self.metrics))
+        }
+
+        fn user_password<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserPasswordRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.user_password(), self.metrics))
+        }
+
+        fn user_recovery<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserRecoveryRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.user_recovery(), self.metrics))
+        }
+
+        fn user_terms<'c>(&'c mut self) -> Box<dyn UserTermsRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.user_terms(), self.metrics))
+        }
+
+        fn user_login_history<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserLoginHistoryRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.user_login_history(),
+                self.metrics,
+            ))
+        }
+
+        fn browser_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.browser_session(),
+                self.metrics,
+            ))
+        }
+
+        fn app_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.app_session(), self.metrics))
+        }
+
+        fn oauth2_client<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.oauth2_client(), self.metrics))
+        }
+
+        fn oauth2_authorization_grant<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.oauth2_authorization_grant(),
+                self.metrics,
+            ))
+        }
+
+        fn oauth2_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2SessionRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.oauth2_session(), self.metrics))
+        }
+
+        fn oauth2_access_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2AccessTokenRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.oauth2_access_token(),
+                self.metrics,
+            ))
+        }
+
+        fn oauth2_refresh_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2RefreshTokenRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.oauth2_refresh_token(),
+                self.metrics,
+            ))
+        }
+
+        fn oauth2_device_code_grant<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2DeviceCodeGrantRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.oauth2_device_code_grant(),
+                self.metrics,
+            ))
+        }
+
+        fn compat_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.compat_session(), self.metrics))
+        }
+
+        fn compat_sso_login<'c>(
+            &'c mut self,
+        ) -> Box<dyn CompatSsoLoginRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.compat_sso_login(),
+                self.metrics,
+            ))
+        }
+
+        fn compat_access_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn CompatAccessTokenRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.compat_access_token(),
+                self.metrics,
+            ))
+        }
+
+        fn compat_refresh_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn CompatRefreshTokenRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(
+                self.inner.compat_refresh_token(),
+                self.metrics,
+            ))
+        }
+
+        fn stats<'c>(&'c mut self) -> Box<dyn StatsRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.stats(), self.metrics))
+        }
+
+        fn device_key<'c>(&'c mut self) -> Box<dyn DeviceKeyRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.device_key(), self.metrics))
+        }
+
+        fn email_queue<'c>(
+            &'c mut self,
+        ) -> Box<dyn EmailQueueRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.email_queue(), self.metrics))
+        }
+
+        fn feature_flag<'c>(
+            &'c mut self,
+        ) -> Box<dyn FeatureFlagRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.feature_flag(), self.metrics))
+        }
+
+        fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
+            Box::new(Instrumented::new(self.inner.job(), self.metrics))
+        }
+    }
 }
@@ -0,0 +1,325 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repository to compute aggregate statistics about the service usage
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A number of events which happened on a given day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyCount {
+    /// The day, in UTC
+    pub day: NaiveDate,
+
+    /// The number of events on that day
+    pub count: usize,
+}
+
+/// The method used to authenticate a browser session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LoginMethod {
+    /// Authenticated with a password
+    Password,
+
+    /// Authenticated through an upstream OAuth 2.0 provider
+    UpstreamOAuth,
+
+    /// Any other authentication method
+    Other,
+}
+
+/// A number of browser logins with a given method on a given day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyLoginCount {
+    /// The day, in UTC
+    pub day: NaiveDate,
+
+    /// The authentication method used
+    pub method: LoginMethod,
+
+    /// The number of logins on that day with that method
+    pub count: usize,
+}
+
+/// The number of sessions which are currently active, by type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ActiveSessionCounts {
+    /// The number of active browser sessions
+    pub browser: usize,
+
+    /// The number of active OAuth 2.0 sessions
+    pub oauth2: usize,
+
+    /// The number of active compatibility sessions
+    pub compat: usize,
+}
+
+/// The number of access tokens issued to a given OAuth 2.0 client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientTokenIssuance {
+    /// The ID of the OAuth 2.0 client
+    pub client_id: Ulid,
+
+    /// The number of access tokens issued to this client
+    pub count: usize,
+}
+
+/// The exposures and conversions of a variant of an A/B experiment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExperimentVariantStats {
+    /// The name of the experiment
+    pub experiment: String,
+
+    /// The name of the variant
+    pub variant: String,
+
+    /// The number of visitors who were shown this variant
+    pub exposures: usize,
+
+    /// The number of those visitors who then logged in or registered
+    pub conversions: usize,
+}
+
+/// A [`StatsRepository`] helps computing aggregate statistics over the whole
+/// database, used for capacity planning and usage reporting
+#[async_trait]
+pub trait StatsRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Count the number of distinct users who had activity in any of their
+    /// sessions (browser, OAuth 2.0 or compatibility) since the given instant
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The instant from which to count activity
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count_active_users(&mut self, since: DateTime<Utc>) -> Result<usize, Self::Error>;
+
+    /// Count the number of times users authenticated in a browser session
+    /// since the given instant
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The instant from which to count authentications
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count_authentications(&mut self, since: DateTime<Utc>) -> Result<usize, Self::Error>;
+
+    /// Count the number of access tokens issued since the given instant, both
+    /// through OAuth 2.0 and the compatibility layer
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The instant from which to count issued tokens
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count_issued_access_tokens(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<usize, Self::Error>;
+
+    /// Record a failed password login attempt, counted on the current day
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to determine the current day
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_login_failure(&mut self, clock: &dyn Clock) -> Result<(), Self::Error>;
+
+    /// Refresh the precomputed aggregates backing the admin dashboard
+    ///
+    /// The other dashboard methods read from those aggregates, so their
+    /// results are only as fresh as the last refresh.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn refresh_dashboard(&mut self) -> Result<(), Self::Error>;
+
+    /// Get the number of users registered per day, from the given day onward
+    ///
+    /// Days without any registration are omitted.
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The first day to include
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn registrations_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error>;
+
+    /// Get the number of browser logins per day and per authentication
+    /// method, from the given day onward
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The first day to include
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn logins_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyLoginCount>, Self::Error>;
+
+    /// Get the number of failed password logins per day, from the given day
+    /// onward
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The first day to include
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn login_failures_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error>;
+
+    /// Get the number of sessions which are currently active
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn active_sessions(&mut self) -> Result<ActiveSessionCounts, Self::Error>;
+
+    /// Get the OAuth 2.0 clients which were issued the most access tokens
+    /// from the given day onward, in descending order
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The first day to include
+    /// * `limit`: The maximum number of clients to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn top_clients_by_access_tokens(
+        &mut self,
+        since: NaiveDate,
+        limit: usize,
+    ) -> Result<Vec<ClientTokenIssuance>, Self::Error>;
+
+    /// Record that a visitor was shown a variant of an experiment
+    ///
+    /// Only the first exposure of a visitor to an experiment is recorded.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `visitor_id`: The ID of the visitor, from their cookie
+    /// * `experiment`: The name of the experiment
+    /// * `variant`: The name of the variant the visitor was shown
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_experiment_exposure(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+        experiment: &str,
+        variant: &str,
+    ) -> Result<(), Self::Error>;
+
+    /// Record that a visitor logged in or registered, converting all the
+    /// experiments they were exposed to
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `visitor_id`: The ID of the visitor, from their cookie
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_experiment_conversion(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the exposures and conversions of each variant of the experiments,
+    /// for the visitors exposed from the given day onward
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: The first day to include
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn experiment_results(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<ExperimentVariantStats>, Self::Error>;
+}
+
+repository_impl!(StatsRepository:
+    async fn count_active_users(&mut self, since: DateTime<Utc>) -> Result<usize, Self::Error>;
+    async fn count_authentications(&mut self, since: DateTime<Utc>)
+        -> Result<usize, Self::Error>;
+    async fn count_issued_access_tokens(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<usize, Self::Error>;
+    async fn record_login_failure(&mut self, clock: &dyn Clock) -> Result<(), Self::Error>;
+    async fn refresh_dashboard(&mut self) -> Result<(), Self::Error>;
+    async fn registrations_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error>;
+    async fn logins_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyLoginCount>, Self::Error>;
+    async fn login_failures_per_day(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyCount>, Self::Error>;
+    async fn active_sessions(&mut self) -> Result<ActiveSessionCounts, Self::Error>;
+    async fn top_clients_by_access_tokens(
+        &mut self,
+        since: NaiveDate,
+        limit: usize,
+    ) -> Result<Vec<ClientTokenIssuance>, Self::Error>;
+    async fn record_experiment_exposure(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+        experiment: &str,
+        variant: &str,
+    ) -> Result<(), Self::Error>;
+    async fn record_experiment_conversion(
+        &mut self,
+        clock: &dyn Clock,
+        visitor_id: Ulid,
+    ) -> Result<(), Self::Error>;
+    async fn experiment_results(
+        &mut self,
+        since: NaiveDate,
+    ) -> Result<Vec<ExperimentVariantStats>, Self::Error>;
+);
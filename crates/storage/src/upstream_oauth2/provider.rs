@@ -9,7 +9,7 @@ use std::marker::PhantomData;
 use async_trait::async_trait;
 use mas_data_model::{
     UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderDiscoveryMode,
-    UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderResponseMode,
+    UpstreamOAuthProviderHealth, UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderResponseMode,
     UpstreamOAuthProviderTokenAuthMethod,
 };
 use mas_iana::jose::JsonWebSignatureAlg;
@@ -261,6 +261,43 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn all_enabled(&mut self) -> Result<Vec<UpstreamOAuthProvider>, Self::Error>;
+
+    /// Record the result of a health check of an upstream OAuth provider
+    ///
+    /// Returns the updated health of the provider
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider`: The provider which was checked
+    /// * `error`: The error which made the check fail, or `None` if it
+    ///   succeeded
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_health_check(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>,
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error>;
+
+    /// Get the health of an upstream OAuth provider
+    ///
+    /// Returns `None` if the provider was never checked
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to get the health of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error>;
 }
 
 repository_impl!(UpstreamOAuthProviderRepository:
@@ -302,4 +339,16 @@ repository_impl!(UpstreamOAuthProviderRepository:
     ) -> Result<usize, Self::Error>;
 
     async fn all_enabled(&mut self) -> Result<Vec<UpstreamOAuthProvider>, Self::Error>;
+
+    async fn record_health_check(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error>;
+
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error>;
 );
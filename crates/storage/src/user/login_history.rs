@@ -0,0 +1,111 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{LoginAttempt, LoginMethod, User, UserAgent};
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock, Page, Pagination};
+
+/// A [`UserLoginHistoryRepository`] helps interacting with the
+/// [`LoginAttempt`]s of a [`User`] saved in the storage backend
+#[async_trait]
+pub trait UserLoginHistoryRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record an attempt of a [`User`] to log in
+    ///
+    /// Returns the newly recorded [`LoginAttempt`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The user who tried to log in
+    /// * `method`: How the user tried to log in
+    /// * `succeeded`: Whether the attempt succeeded
+    /// * `ip_address`: The IP address the attempt came from, if known
+    /// * `user_agent`: The user agent of the browser, if known
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    #[allow(clippy::too_many_arguments)]
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        method: LoginMethod,
+        succeeded: bool,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<LoginAttempt, Self::Error>;
+
+    /// List the [`LoginAttempt`]s of a [`User`], with the given pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The user to list the login attempts of
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<LoginAttempt>, Self::Error>;
+
+    /// Count the [`LoginAttempt`]s of a [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The user to count the login attempts of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    /// Remove the [`LoginAttempt`]s recorded before the given date
+    ///
+    /// Returns the number of login attempts removed
+    ///
+    /// # Parameters
+    ///
+    /// * `before`: Only remove login attempts recorded before this date
+    /// * `limit`: The maximum number of login attempts to remove in one go
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn purge(&mut self, before: DateTime<Utc>, limit: usize) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(UserLoginHistoryRepository:
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        method: LoginMethod,
+        succeeded: bool,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<LoginAttempt, Self::Error>;
+    async fn list(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<LoginAttempt>, Self::Error>;
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error>;
+    async fn purge(&mut self, before: DateTime<Utc>, limit: usize) -> Result<usize, Self::Error>;
+);
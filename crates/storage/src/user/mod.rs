@@ -6,14 +6,18 @@
 
 //! Repositories to interact with entities related to user accounts
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use async_trait::async_trait;
-use mas_data_model::User;
+use chrono::{DateTime, Utc};
+use mas_data_model::{RegistrationMetadata, User};
 use rand_core::RngCore;
 use ulid::Ulid;
 
 use crate::{repository_impl, Clock, Page, Pagination};
 
 mod email;
+mod login_history;
 mod password;
 mod recovery;
 mod session;
@@ -21,6 +25,7 @@ mod terms;
 
 pub use self::{
     email::{UserEmailFilter, UserEmailRepository},
+    login_history::UserLoginHistoryRepository,
     password::UserPasswordRepository,
     recovery::UserRecoveryRepository,
     session::{BrowserSessionFilter, BrowserSessionRepository},
@@ -35,6 +40,10 @@ pub enum UserState {
 
     /// The account is active
     Active,
+
+    /// The account was deleted, it has the `deleted_at` timestamp set. It can
+    /// still be restored until it gets purged.
+    Deleted,
 }
 
 impl UserState {
@@ -53,6 +62,14 @@ impl UserState {
     pub fn is_active(&self) -> bool {
         matches!(self, Self::Active)
     }
+
+    /// Returns `true` if the user state is [`Deleted`].
+    ///
+    /// [`Deleted`]: UserState::Deleted
+    #[must_use]
+    pub fn is_deleted(&self) -> bool {
+        matches!(self, Self::Deleted)
+    }
 }
 
 /// Filter parameters for listing users
@@ -84,6 +101,13 @@ impl<'a> UserFilter<'a> {
         self
     }
 
+    /// Filter for deleted users, which were not purged yet
+    #[must_use]
+    pub fn deleted_only(mut self) -> Self {
+        self.state = Some(UserState::Deleted);
+        self
+    }
+
     /// Filter for users that can request admin privileges
     #[must_use]
     pub fn can_request_admin_only(mut self) -> Self {
@@ -115,6 +139,29 @@ impl<'a> UserFilter<'a> {
     }
 }
 
+/// How many users registered since a given date, broken down by where their
+/// registration came from
+///
+/// Each breakdown is sorted by descending count. Users without a value for a
+/// breakdown are not part of it.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct RegistrationStats {
+    /// The total number of registrations
+    pub total: usize,
+
+    /// Registrations per OAuth 2.0 client
+    pub by_client: Vec<(Ulid, usize)>,
+
+    /// Registrations per upstream OAuth 2.0 provider
+    pub by_upstream_provider: Vec<(Ulid, usize)>,
+
+    /// Registrations per invite token
+    pub by_invite_token: Vec<(String, usize)>,
+
+    /// Registrations per referral parameter
+    pub by_referral: Vec<(String, usize)>,
+}
+
 /// A [`UserRepository`] helps interacting with [`User`] saved in the storage
 /// backend
 #[async_trait]
@@ -135,6 +182,23 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn lookup(&mut self, id: Ulid) -> Result<Option<User>, Self::Error>;
 
+    /// Load a batch of [`User`]s by their IDs
+    ///
+    /// Returns a map of user IDs to users. If a user does not exist, it is not
+    /// present in the map.
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the users to load
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, User>, Self::Error>;
+
     /// Find a [`User`] by its username
     ///
     /// Returns `None` if no [`User`] was found
@@ -208,6 +272,55 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
 
+    /// Soft-delete a [`User`]
+    ///
+    /// The user is kept in the database and can be restored with
+    /// [`Self::restore`], until it gets purged with [`Self::purge_deleted`].
+    ///
+    /// Returns the deleted [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn delete(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+
+    /// Restore a soft-deleted [`User`]
+    ///
+    /// Returns the restored [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to restore
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn restore(&mut self, user: User) -> Result<User, Self::Error>;
+
+    /// Permanently remove users which were deleted before the given date,
+    /// along with all their related data
+    ///
+    /// Returns the number of users purged
+    ///
+    /// # Parameters
+    ///
+    /// * `deleted_before`: Only purge users deleted before this date
+    /// * `limit`: The maximum number of users to purge in one go
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn purge_deleted(
+        &mut self,
+        deleted_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<usize, Self::Error>;
+
     /// Set whether a [`User`] can request admin
     ///
     /// Returns the [`User`] with the new `can_request_admin` value
@@ -225,6 +338,179 @@ pub trait UserRepository: Send + Sync {
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
 
+    /// Get the maximum number of simultaneous sessions of a [`User`], if an
+    /// admin overrode it
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the limit of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_session_limit(&mut self, user: &User) -> Result<Option<u32>, Self::Error>;
+
+    /// Override the maximum number of simultaneous sessions of a [`User`], or
+    /// remove the override
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to set the limit of
+    /// * `max_sessions`: The new limit, or `None` to remove the override
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_session_limit(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        max_sessions: Option<u32>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the IANA timezone of a [`User`], if one was set
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the timezone of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_timezone(&mut self, user: &User) -> Result<Option<String>, Self::Error>;
+
+    /// Set the IANA timezone of a [`User`], or remove it
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to set the timezone of
+    /// * `timezone`: The new timezone, like `Europe/Paris`, or `None` to remove
+    ///   it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_timezone(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        timezone: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the password policy class of a [`User`], if one was assigned
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the password policy class of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_password_policy_class(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<String>, Self::Error>;
+
+    /// Assign a password policy class to a [`User`], or remove it
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to assign the password policy class to
+    /// * `policy_class`: The name of the password policy class, like `service`,
+    ///   or `None` to apply the default policy
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_password_policy_class(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        policy_class: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
+    /// Check whether a [`User`] must change their password before their
+    /// next login completes
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to check
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn is_password_change_required(&mut self, user: &User) -> Result<bool, Self::Error>;
+
+    /// Set or clear the flag forcing a [`User`] to change their password on
+    /// their next login
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to flag
+    /// * `required`: Whether the user must change their password
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_password_change_required(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        required: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Record where the registration of a [`User`] came from
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] who just registered
+    /// * `metadata`: Where the registration came from
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_registration_metadata(
+        &mut self,
+        user: &User,
+        metadata: &RegistrationMetadata,
+    ) -> Result<(), Self::Error>;
+
+    /// Get where the registration of a [`User`] came from
+    ///
+    /// Returns `None` if nothing was recorded, for example for users which
+    /// were provisioned through the admin API.
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to look up
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_registration_metadata(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<RegistrationMetadata>, Self::Error>;
+
+    /// Count the users registered since the given date, broken down by where
+    /// their registration came from
+    ///
+    /// # Parameters
+    ///
+    /// * `since`: Only count users created after this date
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn registration_stats(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<RegistrationStats, Self::Error>;
+
     /// List [`User`] with the given filter and pagination
     ///
     /// # Parameters
@@ -268,6 +554,8 @@ pub trait UserRepository: Send + Sync {
 
 repository_impl!(UserRepository:
     async fn lookup(&mut self, id: Ulid) -> Result<Option<User>, Self::Error>;
+    async fn load_batch(&mut self, ids: BTreeSet<Ulid>)
+        -> Result<BTreeMap<Ulid, User>, Self::Error>;
     async fn find_by_username(&mut self, username: &str) -> Result<Option<User>, Self::Error>;
     async fn add(
         &mut self,
@@ -278,11 +566,62 @@ repository_impl!(UserRepository:
     async fn exists(&mut self, username: &str) -> Result<bool, Self::Error>;
     async fn lock(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn delete(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+    async fn restore(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn purge_deleted(
+        &mut self,
+        deleted_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<usize, Self::Error>;
     async fn set_can_request_admin(
         &mut self,
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+    async fn get_session_limit(&mut self, user: &User) -> Result<Option<u32>, Self::Error>;
+    async fn set_session_limit(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        max_sessions: Option<u32>,
+    ) -> Result<(), Self::Error>;
+    async fn get_timezone(&mut self, user: &User) -> Result<Option<String>, Self::Error>;
+    async fn set_timezone(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        timezone: Option<&str>,
+    ) -> Result<(), Self::Error>;
+    async fn get_password_policy_class(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<String>, Self::Error>;
+    async fn set_password_policy_class(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        policy_class: Option<&str>,
+    ) -> Result<(), Self::Error>;
+    async fn is_password_change_required(&mut self, user: &User) -> Result<bool, Self::Error>;
+    async fn set_password_change_required(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        required: bool,
+    ) -> Result<(), Self::Error>;
+    async fn set_registration_metadata(
+        &mut self,
+        user: &User,
+        metadata: &RegistrationMetadata,
+    ) -> Result<(), Self::Error>;
+    async fn get_registration_metadata(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<RegistrationMetadata>, Self::Error>;
+    async fn registration_stats(
+        &mut self,
+        since: DateTime<Utc>,
+    ) -> Result<RegistrationStats, Self::Error>;
     async fn list(
         &mut self,
         filter: UserFilter<'_>,
@@ -4,12 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    Authentication, BrowserSession, Password, UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, BrowserSession, Password, SessionElevation, UpstreamOAuthAuthorizationSession,
+    User, UserAgent,
 };
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -132,6 +136,23 @@ pub trait BrowserSessionRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
 
+    /// Load a batch of [`BrowserSession`]s by their IDs
+    ///
+    /// Returns a map of session IDs to sessions. If a session does not exist,
+    /// it is not present in the map.
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the sessions to load
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error>;
+
     /// Create a new [`BrowserSession`] for a [`User`]
     ///
     /// Returns the newly created [`BrowserSession`]
@@ -259,6 +280,27 @@ pub trait BrowserSessionRepository: Send + Sync {
         upstream_oauth_session: &UpstreamOAuthAuthorizationSession,
     ) -> Result<Authentication, Self::Error>;
 
+    /// Authenticate a [`BrowserSession`] with a X.509 client certificate
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The session to authenticate
+    /// * `fingerprint`: The SHA-256 fingerprint of the certificate which was
+    ///   used to authenticate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn authenticate_with_client_certificate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        fingerprint: &str,
+    ) -> Result<Authentication, Self::Error>;
+
     /// Get the last successful authentication for a [`BrowserSession`]
     ///
     /// # Params
@@ -273,6 +315,39 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    /// Get the last successful authentication of a batch of
+    /// [`BrowserSession`]s
+    ///
+    /// Returns a map of session IDs to their last authentication. Sessions
+    /// without authentication are not present in the map.
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the sessions
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn load_last_authentications(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
+    /// List all the successful authentications of a [`BrowserSession`], in
+    /// chronological order
+    ///
+    /// # Params
+    ///
+    /// * `user_session`: The session for which to list the authentications
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Vec<Authentication>, Self::Error>;
+
     /// Record a batch of [`BrowserSession`] activity
     ///
     /// # Parameters
@@ -287,10 +362,60 @@ pub trait BrowserSessionRepository: Send + Sync {
         &mut self,
         activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
     ) -> Result<(), Self::Error>;
+
+    /// Elevate a [`BrowserSession`] for a short time, after the user
+    /// authenticated again
+    ///
+    /// Returns the newly created [`SessionElevation`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The session to elevate
+    /// * `authentication`: The authentication the elevation is obtained with
+    /// * `token`: The token identifying the elevation
+    /// * `ttl`: How long the elevation lasts
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn elevate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        authentication: &Authentication,
+        token: String,
+        ttl: chrono::Duration,
+    ) -> Result<SessionElevation, Self::Error>;
+
+    /// Find an elevation of a [`BrowserSession`] by its token
+    ///
+    /// Returns `None` if no elevation of this session has this token. The
+    /// elevation may have expired.
+    ///
+    /// # Parameters
+    ///
+    /// * `user_session`: The session the elevation belongs to
+    /// * `token`: The token of the elevation
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_elevation(
+        &mut self,
+        user_session: &BrowserSession,
+        token: &str,
+    ) -> Result<Option<SessionElevation>, Self::Error>;
 }
 
 repository_impl!(BrowserSessionRepository:
     async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error>;
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -334,13 +459,47 @@ repository_impl!(BrowserSessionRepository:
         upstream_oauth_session: &UpstreamOAuthAuthorizationSession,
     ) -> Result<Authentication, Self::Error>;
 
+    async fn authenticate_with_client_certificate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        fingerprint: &str,
+    ) -> Result<Authentication, Self::Error>;
+
     async fn get_last_authentication(
         &mut self,
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    async fn load_last_authentications(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Vec<Authentication>, Self::Error>;
+
     async fn record_batch_activity(
         &mut self,
         activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
     ) -> Result<(), Self::Error>;
+
+    async fn elevate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        authentication: &Authentication,
+        token: String,
+        ttl: chrono::Duration,
+    ) -> Result<SessionElevation, Self::Error>;
+
+    async fn find_elevation(
+        &mut self,
+        user_session: &BrowserSession,
+        token: &str,
+    ) -> Result<Option<SessionElevation>, Self::Error>;
 );
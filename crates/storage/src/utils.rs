@@ -6,10 +6,45 @@
 
 //! Wrappers and useful type aliases
 
+use std::{
+    future::Future,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
 use rand_core::CryptoRngCore;
 
 use crate::Clock;
 
+static METER: LazyLock<Meter> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+});
+
+static REPOSITORY_CALL_DURATION: LazyLock<Histogram<u64>> = LazyLock::new(|| {
+    METER
+        .u64_histogram("db.repository.call.duration")
+        .with_unit("ms")
+        .with_description("Duration of repository method calls")
+        .init()
+});
+
+static REPOSITORY_CALL_ERRORS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    METER
+        .u64_counter("db.repository.call.errors")
+        .with_unit("{errors}")
+        .with_description("Number of repository method calls which returned an error")
+        .init()
+});
+
 /// A wrapper which is used to map the error type of a repository to another
 pub struct MapErr<R, F> {
     pub(crate) inner: R,
@@ -30,14 +65,104 @@ impl<R, F> MapErr<R, F> {
     }
 }
 
+/// Records metrics about the calls made through an [`Instrumented`]
+/// repository
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepositoryMetrics {
+    slow_threshold: Option<Duration>,
+}
+
+impl RepositoryMetrics {
+    /// Create a new [`RepositoryMetrics`], which doesn't log slow calls
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            slow_threshold: None,
+        }
+    }
+
+    /// Log a warning for every call which takes longer than the given
+    /// threshold
+    #[must_use]
+    pub const fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Time the given repository call, recording its duration and whether it
+    /// failed
+    ///
+    /// # Parameters
+    ///
+    /// * `method`: The name of the repository method being called
+    /// * `call`: The future of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the underlying call, if it failed
+    pub async fn record<T, E>(
+        &self,
+        method: &'static str,
+        call: impl Future<Output = Result<T, E>> + Send,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let result = call.await;
+        let elapsed = start.elapsed();
+
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        let attributes = [
+            KeyValue::new("db.repository.method", method),
+            KeyValue::new("db.repository.outcome", outcome),
+        ];
+        let duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        REPOSITORY_CALL_DURATION.record(duration_ms, &attributes);
+        if result.is_err() {
+            REPOSITORY_CALL_ERRORS.add(1, &attributes[..1]);
+        }
+
+        if self
+            .slow_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            tracing::warn!(
+                db.repository.method = method,
+                duration_ms,
+                "Slow repository call"
+            );
+        }
+
+        result
+    }
+}
+
+/// A wrapper which records metrics about the calls made to a repository
+pub struct Instrumented<R> {
+    pub(crate) inner: R,
+    pub(crate) metrics: RepositoryMetrics,
+    _private: (),
+}
+
+impl<R> Instrumented<R> {
+    /// Create a new [`Instrumented`] wrapper from an inner repository and the
+    /// metrics to record
+    #[must_use]
+    pub fn new(inner: R, metrics: RepositoryMetrics) -> Self {
+        Self {
+            inner,
+            metrics,
+            _private: (),
+        }
+    }
+}
+
 /// A boxed [`Clock`]
 pub type BoxClock = Box<dyn Clock + Send>;
 
 /// A boxed random number generator
 pub type BoxRng = Box<dyn CryptoRngCore + Send>;
 
-/// A macro to implement a repository trait for the [`MapErr`] wrapper and for
-/// [`Box<R>`]
+/// A macro to implement a repository trait for the [`MapErr`] and
+/// [`Instrumented`] wrappers and for [`Box<R>`]
 #[macro_export]
 macro_rules! repository_impl {
     ($repo_trait:ident:
@@ -77,5 +202,24 @@ macro_rules! repository_impl {
                 }
             )*
         }
+
+        #[::async_trait::async_trait]
+        impl<R> $repo_trait for $crate::Instrumented<R>
+        where
+            R: $repo_trait,
+        {
+            type Error = <R as $repo_trait>::Error;
+
+            $(
+                async fn $method (&mut self $(, $arg: $arg_ty)*) -> Result<$ret_ty, Self::Error> {
+                    self.metrics
+                        .record(
+                            concat!(stringify!($repo_trait), "::", stringify!($method)),
+                            self.inner.$method ( $($arg),* ),
+                        )
+                        .await
+                }
+            )*
+        }
     };
 }
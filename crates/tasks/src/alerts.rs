@@ -0,0 +1,274 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Alerts sent to the administrators in a Matrix room
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, NaiveDate, Utc};
+use mas_matrix::HomeserverConnection;
+use mas_storage::{Clock, RepositoryAccess};
+use rand::Rng;
+use tracing::{debug, warn};
+use ulid::Ulid;
+
+use crate::{
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// Severity of an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertSeverity {
+    /// Informational alert, which doesn't require any action
+    Info,
+
+    /// Something went wrong and may need attention
+    Warning,
+
+    /// Something is broken and needs immediate attention
+    Critical,
+}
+
+impl AlertSeverity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "INFO",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+        }
+    }
+}
+
+/// An event which can trigger an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertEvent {
+    /// An email could not be sent after all retries
+    EmailDeadLettered,
+
+    /// The number of registrations in a day went over the threshold
+    RegistrationSpike,
+
+    /// An upstream OAuth 2.0 provider started failing its health checks
+    UpstreamProviderDown,
+}
+
+impl AlertEvent {
+    /// The severity of this event, unless overridden in the configuration
+    #[must_use]
+    pub const fn default_severity(self) -> AlertSeverity {
+        match self {
+            Self::EmailDeadLettered | Self::RegistrationSpike => AlertSeverity::Warning,
+            Self::UpstreamProviderDown => AlertSeverity::Critical,
+        }
+    }
+}
+
+/// Posts alerts to a Matrix room through the homeserver connection
+#[derive(Debug, Clone)]
+pub struct Alerter {
+    room_id: Option<String>,
+    min_severity: AlertSeverity,
+    severities: HashMap<AlertEvent, AlertSeverity>,
+    registration_spike_threshold: Option<usize>,
+
+    /// The last day for which a registration spike was reported, to only
+    /// report it once a day
+    last_registration_spike: Arc<Mutex<Option<NaiveDate>>>,
+}
+
+impl Default for Alerter {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl Alerter {
+    /// Create an alerter posting to the given room
+    #[must_use]
+    pub fn new(room_id: String) -> Self {
+        Self {
+            room_id: Some(room_id),
+            ..Self::disabled()
+        }
+    }
+
+    /// Create an alerter which doesn't post anything
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            room_id: None,
+            min_severity: AlertSeverity::Warning,
+            severities: HashMap::new(),
+            registration_spike_threshold: None,
+            last_registration_spike: Arc::default(),
+        }
+    }
+
+    /// Set the minimum severity of the alerts to post
+    #[must_use]
+    pub fn with_min_severity(mut self, min_severity: AlertSeverity) -> Self {
+        self.min_severity = min_severity;
+        self
+    }
+
+    /// Override the severity of an event
+    #[must_use]
+    pub fn with_severity(mut self, event: AlertEvent, severity: AlertSeverity) -> Self {
+        self.severities.insert(event, severity);
+        self
+    }
+
+    /// Set the number of registrations in a day above which a registration
+    /// spike is reported
+    #[must_use]
+    pub fn with_registration_spike_threshold(mut self, threshold: usize) -> Self {
+        self.registration_spike_threshold = Some(threshold);
+        self
+    }
+
+    fn severity(&self, event: AlertEvent) -> AlertSeverity {
+        self.severities
+            .get(&event)
+            .copied()
+            .unwrap_or(event.default_severity())
+    }
+
+    /// Post an alert for the given event, if its severity is high enough
+    ///
+    /// Failures to post the alert are logged and otherwise ignored, so that
+    /// they don't interfere with the job which raised the alert.
+    pub async fn send(
+        &self,
+        homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
+        clock: &dyn Clock,
+        rng: &mut (impl Rng + Send),
+        event: AlertEvent,
+        message: &str,
+    ) {
+        let Some(room_id) = &self.room_id else {
+            return;
+        };
+
+        let severity = self.severity(event);
+        if severity < self.min_severity {
+            debug!(?event, ?severity, "Alert severity too low, not posting it");
+            return;
+        }
+
+        let txn_id = Ulid::from_datetime_with_source(clock.now().into(), rng).to_string();
+        let body = format!("[{}] {message}", severity.as_str());
+        if let Err(e) = homeserver.send_notice(room_id, &txn_id, &body).await {
+            warn!(?event, error = %e, "Failed to post alert to the Matrix room");
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CheckRegistrationSpikeJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CheckRegistrationSpikeJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CheckRegistrationSpikeJob {
+    const NAME: &'static str = "check-registration-spike";
+}
+
+impl TracedJob for CheckRegistrationSpikeJob {}
+
+pub async fn check_registration_spike(
+    job: CheckRegistrationSpikeJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "check registration spike job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let alerter = state.alerter();
+    let Some(threshold) = alerter.registration_spike_threshold else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let today = clock.now().date_naive();
+    let mut repo = state.repository().await?;
+    let registrations: usize = repo
+        .stats()
+        .registrations_per_day(today)
+        .await?
+        .into_iter()
+        .filter(|c| c.day == today)
+        .map(|c| c.count)
+        .sum();
+    repo.cancel().await?;
+
+    if registrations <= threshold {
+        return Ok(());
+    }
+
+    {
+        let mut last = alerter.last_registration_spike.lock().unwrap();
+        if *last == Some(today) {
+            return Ok(());
+        }
+        *last = Some(today);
+    }
+
+    warn!(registrations, threshold, "Registration spike detected");
+    state
+        .alert(
+            AlertEvent::RegistrationSpike,
+            &format!(
+                "{registrations} users registered today, which is above the threshold of {threshold}"
+            ),
+        )
+        .await;
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let alerter = state.alerter();
+    if alerter.room_id.is_none() || alerter.registration_spike_threshold.is_none() {
+        return monitor;
+    }
+
+    // The registration counts come from the dashboard aggregates, which are
+    // refreshed every 15 minutes
+    let schedule = apalis_cron::Schedule::from_str("0 5/15 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CheckRegistrationSpikeJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(check_registration_spike);
+
+    monitor.register(worker)
+}
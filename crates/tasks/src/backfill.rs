@@ -0,0 +1,128 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Run the backfills of the online schema changes in the background
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_storage_pg::backfill::{run_batch, BatchOutcome, BACKFILLS};
+use tracing::{debug, info};
+
+use crate::{
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many rows are backfilled in a single transaction
+const BATCH_SIZE: i64 = 1000;
+
+/// How long to pause between two batches, to leave room for other queries
+const BATCH_PAUSE: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How long a single run of the job can go on, so that runs don't overlap
+const RUN_BUDGET: std::time::Duration = std::time::Duration::from_secs(50);
+
+#[derive(Default, Clone)]
+pub struct RunBackfillsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RunBackfillsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RunBackfillsJob {
+    const NAME: &'static str = "run-backfills";
+}
+
+impl TracedJob for RunBackfillsJob {}
+
+/// Job to process the pending backfills in batches, until they complete or the
+/// run budget is spent. The next run resumes where this one stopped.
+pub async fn run_backfills(
+    job: RunBackfillsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("run backfills job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let started = std::time::Instant::now();
+    let mut conn = state.pool().acquire().await?;
+
+    for backfill in BACKFILLS {
+        let mut total = 0;
+        loop {
+            if started.elapsed() > RUN_BUDGET {
+                info!(
+                    backfill.name = backfill.name,
+                    affected_rows = total,
+                    "backfill paused until the next run"
+                );
+                return Ok(());
+            }
+
+            match run_batch(
+                &mut conn,
+                &clock,
+                state.token_hasher(),
+                backfill,
+                BATCH_SIZE,
+            )
+            .await?
+            {
+                BatchOutcome::Busy => {
+                    debug!(backfill.name = backfill.name, "backfill running elsewhere");
+                    break;
+                }
+                BatchOutcome::Completed => {
+                    if total > 0 {
+                        info!(
+                            backfill.name = backfill.name,
+                            affected_rows = total,
+                            "backfill completed"
+                        );
+                    }
+                    break;
+                }
+                BatchOutcome::Progressed { affected_rows } => {
+                    total += affected_rows;
+                    tokio::time::sleep(BATCH_PAUSE).await;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let schedule = apalis_cron::Schedule::from_str("0 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = RunBackfillsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(run_backfills);
+
+    monitor.register(worker)
+}
@@ -18,7 +18,8 @@ use apalis_core::{
 };
 use apalis_cron::CronStream;
 use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2AccessTokenRepository, RepositoryAccess};
+use mas_storage::{oauth2::OAuth2AccessTokenRepository, stats::StatsRepository, RepositoryAccess};
+use mas_storage_pg::partitions::{create_partitions, drop_partitions, PARTITIONED_TABLES};
 use tracing::{debug, info};
 
 use crate::{
@@ -26,6 +27,9 @@ use crate::{
     JobContextExt, State,
 };
 
+/// How many days of partitions are created ahead of time
+const PARTITIONS_AHEAD_DAYS: u32 = 7;
+
 #[derive(Default, Clone)]
 pub struct CleanupExpiredTokensJob {
     scheduled: DateTime<Utc>,
@@ -43,6 +47,8 @@ impl Job for CleanupExpiredTokensJob {
 
 impl TracedJob for CleanupExpiredTokensJob {}
 
+/// Job to delete the expired tokens which are in partitions that can't be
+/// dropped yet
 pub async fn cleanup_expired_tokens(
     job: CleanupExpiredTokensJob,
     ctx: JobContext,
@@ -65,6 +71,94 @@ pub async fn cleanup_expired_tokens(
     Ok(())
 }
 
+#[derive(Default, Clone)]
+pub struct MaintainPartitionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for MaintainPartitionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for MaintainPartitionsJob {
+    const NAME: &'static str = "maintain-partitions";
+}
+
+impl TracedJob for MaintainPartitionsJob {}
+
+/// Job to create the partitions of the next days, and drop the ones past their
+/// retention, which cleans up the expired tokens and old authorization grants
+pub async fn maintain_partitions(
+    job: MaintainPartitionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("maintain partitions job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut conn = state.pool().acquire().await?;
+
+    for table in PARTITIONED_TABLES {
+        let created = create_partitions(&mut conn, &clock, table, PARTITIONS_AHEAD_DAYS).await?;
+        if !created.is_empty() {
+            info!(
+                partitioned_table.name = table.table,
+                ?created,
+                "created partitions"
+            );
+        }
+
+        let dropped = drop_partitions(&mut conn, &clock, table).await?;
+        if dropped.is_empty() {
+            debug!(partitioned_table.name = table.table, "no partition to drop");
+        } else {
+            info!(
+                partitioned_table.name = table.table,
+                ?dropped,
+                "dropped partitions"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct RefreshDashboardJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RefreshDashboardJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RefreshDashboardJob {
+    const NAME: &'static str = "refresh-dashboard";
+}
+
+impl TracedJob for RefreshDashboardJob {}
+
+pub async fn refresh_dashboard(
+    job: RefreshDashboardJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("refresh dashboard job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+
+    repo.stats().refresh_dashboard().await?;
+    repo.save().await?;
+
+    debug!("refreshed dashboard aggregates");
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -78,6 +172,28 @@ pub(crate) fn register(
         .layer(metrics_layer())
         .layer(trace_layer())
         .build_fn(cleanup_expired_tokens);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 */15 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = MaintainPartitionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(maintain_partitions);
+    let monitor = monitor.register(worker);
+
+    // The dashboard aggregates are expensive to compute, so they are only
+    // refreshed every 15 minutes
+    let schedule = apalis_cron::Schedule::from_str("0 */15 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = RefreshDashboardJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(refresh_dashboard);
 
     monitor.register(worker)
 }
@@ -4,17 +4,58 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::str::FromStr;
+
 use anyhow::Context;
-use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
-use chrono::Duration;
-use mas_email::{Address, Mailbox};
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::QueuedEmail;
+use mas_email::{Address, Mailbox, Mailer};
 use mas_i18n::locale;
 use mas_storage::job::{JobWithSpanContext, VerifyEmailJob};
 use mas_templates::{EmailVerificationContext, TemplateContext};
 use rand::{distributions::Uniform, Rng};
-use tracing::info;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    alerts::AlertEvent,
+    storage::PostgresStorageFactory,
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many times sending an email is attempted before it is moved to the
+/// dead-letter list
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Delay before the first retry, doubled on each subsequent attempt
+const BASE_RETRY_DELAY_SECONDS: i64 = 30;
+
+/// Upper bound of the delay between two attempts
+const MAX_RETRY_DELAY_SECONDS: i64 = 60 * 60;
+
+/// Maximum number of emails sent in a single run of the job
+const BATCH_SIZE: usize = 100;
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+/// Compute when to retry sending an email, given how many attempts already
+/// failed. Returns `None` if we should give up.
+fn retry_delay(failed_attempts: u32) -> Option<Duration> {
+    if failed_attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    let exponent = failed_attempts.saturating_sub(1).min(16);
+    let seconds = (BASE_RETRY_DELAY_SECONDS << exponent).min(MAX_RETRY_DELAY_SECONDS);
+    Duration::try_seconds(seconds)
+}
 
 #[tracing::instrument(
     name = "job.verify_email",
@@ -51,6 +92,21 @@ async fn verify_email(
         .await?
         .context("User not found")?;
 
+    // Don't send anything to addresses the mail provider reported as
+    // undeliverable
+    if let Some(undeliverable) = repo
+        .email_queue()
+        .find_undeliverable(&user_email.email)
+        .await?
+    {
+        warn!(
+            email.id = %user_email.id,
+            reason = undeliverable.reason.as_str(),
+            "Email address is undeliverable, not sending a verification email"
+        );
+        return Ok(());
+    }
+
     // Generate a verification code
     let range = Uniform::<u32>::from(0..1_000_000);
     let code = rng.sample(range);
@@ -70,17 +126,26 @@ async fn verify_email(
         )
         .await?;
 
-    // And send the verification email
-    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+    // And queue the verification email
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    // The link lets the user verify the address without typing the code
+    let verification_link = state
+        .url_builder()
+        .email_verification_link(user_email.id, verification.code.clone());
 
     let context =
-        EmailVerificationContext::new(user.clone(), verification.clone()).with_language(language);
+        EmailVerificationContext::new(user.clone(), verification.clone(), verification_link)
+            .with_language(language);
 
-    mailer.send_verification_email(mailbox, &context).await?;
+    let message = mailer.render_verification_email(mailbox, &context)?;
+    repo.email_queue()
+        .enqueue(&mut rng, &clock, address.to_string(), message)
+        .await?;
 
     info!(
         email.id = %user_email.id,
-        "Verification email sent"
+        "Verification email queued"
     );
 
     repo.save().await?;
@@ -88,6 +153,134 @@ async fn verify_email(
     Ok(())
 }
 
+#[derive(Default, Clone)]
+pub struct SendQueuedEmailsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for SendQueuedEmailsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for SendQueuedEmailsJob {
+    const NAME: &'static str = "send-queued-emails";
+}
+
+impl TracedJob for SendQueuedEmailsJob {}
+
+/// How long to wait before sending the email, if its recipient domain
+/// received too many emails recently
+fn throttle_delay(mailer: &Mailer, email: &QueuedEmail) -> Option<Duration> {
+    // Invalid addresses are not throttled, sending them will fail anyway
+    let to: Address = email.recipient.parse().ok()?;
+    let delay = mailer.throttle_delay(&to)?;
+    Duration::from_std(delay).ok()
+}
+
+async fn send(mailer: &Mailer, email: &QueuedEmail) -> Result<(), anyhow::Error> {
+    let to: Address = email.recipient.parse()?;
+    mailer.send_rendered(&to, &email.message).await?;
+    Ok(())
+}
+
+pub async fn send_queued_emails(
+    job: SendQueuedEmailsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("send queued emails job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mailer = state.mailer();
+
+    for _ in 0..BATCH_SIZE {
+        // Each email is handled in its own transaction, which keeps it locked
+        // while it is being sent, so that other workers skip it
+        let mut repo = state.repository().await?;
+        let Some(email) = repo.email_queue().next_due(&clock).await? else {
+            repo.cancel().await?;
+            break;
+        };
+
+        // Set when the email is moved to the dead-letter list after too many
+        // failures, to alert the administrators
+        let mut dead_lettered = None;
+
+        let undeliverable = repo
+            .email_queue()
+            .find_undeliverable(&email.recipient)
+            .await?;
+
+        if let Some(undeliverable) = undeliverable {
+            // The address was reported as undeliverable after the email was
+            // queued, don't even try
+            warn!(
+                queued_email.id = %email.id,
+                reason = undeliverable.reason.as_str(),
+                "Email address is undeliverable, moving email to the dead-letter list"
+            );
+            let error = format!(
+                "The address was reported as undeliverable ({})",
+                undeliverable.reason.as_str()
+            );
+            repo.email_queue()
+                .record_failure(&clock, email, error, None)
+                .await?;
+        } else if let Some(delay) = throttle_delay(mailer, &email) {
+            // Too many emails were sent to this domain recently, keep it in
+            // the queue without counting it as a failed attempt
+            let until = clock.now() + delay;
+            debug!(
+                queued_email.id = %email.id,
+                %until,
+                "Recipient domain is rate limited, postponing email"
+            );
+            repo.email_queue().postpone(email, until).await?;
+        } else if let Err(e) = send(mailer, &email).await {
+            let retry_at = retry_delay(email.attempts + 1).map(|delay| clock.now() + delay);
+            if let Some(retry_at) = retry_at {
+                warn!(
+                    queued_email.id = %email.id,
+                    error = %e,
+                    %retry_at,
+                    "Failed to send email, will retry"
+                );
+            } else {
+                error!(
+                    queued_email.id = %email.id,
+                    error = %e,
+                    "Failed to send email too many times, moving it to the dead-letter list"
+                );
+                dead_lettered = Some(email.id);
+            }
+
+            repo.email_queue()
+                .record_failure(&clock, email, format!("{e:#}"), retry_at)
+                .await?;
+        } else {
+            info!(queued_email.id = %email.id, "Email sent");
+            repo.email_queue().mark_as_sent(&clock, email).await?;
+        }
+
+        repo.save().await?;
+
+        if let Some(id) = dead_lettered {
+            state
+                .alert(
+                    AlertEvent::EmailDeadLettered,
+                    &format!("Email {id} could not be sent after {MAX_ATTEMPTS} attempts and was moved to the dead-letter list"),
+                )
+                .await;
+        }
+    }
+
+    mailer.retain_recent_rate_limits();
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -97,5 +290,16 @@ pub(crate) fn register(
     let verify_email_worker =
         crate::build!(VerifyEmailJob => verify_email, suffix, state, storage_factory);
 
-    monitor.register(verify_email_worker)
+    let schedule = apalis_cron::Schedule::from_str("*/5 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = SendQueuedEmailsJob::NAME);
+    let send_queued_emails_worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(send_queued_emails);
+
+    monitor
+        .register(verify_email_worker)
+        .register(send_queued_emails_worker)
 }
@@ -11,18 +11,27 @@ use mas_email::Mailer;
 use mas_matrix::HomeserverConnection;
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, SystemClock};
-use mas_storage_pg::{DatabaseError, PgRepository};
+use mas_storage_pg::{DatabaseError, PgRepository, TokenHasher};
 use rand::SeedableRng;
 use sqlx::{Pool, Postgres};
 use tracing::debug;
+use url::Url;
 
+pub use self::{
+    alerts::{AlertEvent, AlertSeverity, Alerter},
+    stats::UsageReport,
+};
 use crate::storage::PostgresStorageFactory;
 
+mod alerts;
+mod backfill;
 mod database;
 mod email;
 mod matrix;
 mod recovery;
+mod stats;
 mod storage;
+mod upstream_oauth2;
 mod user;
 mod utils;
 
@@ -33,15 +42,26 @@ struct State {
     clock: SystemClock,
     homeserver: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
     url_builder: UrlBuilder,
+    http_client: reqwest::Client,
+    token_hasher: TokenHasher,
+    alerter: Alerter,
+    deleted_user_retention: chrono::Duration,
+    login_history_retention: chrono::Duration,
 }
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pool: Pool<Postgres>,
         clock: SystemClock,
         mailer: Mailer,
         homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
         url_builder: UrlBuilder,
+        http_client: reqwest::Client,
+        token_hasher: TokenHasher,
+        alerter: Alerter,
+        deleted_user_retention: chrono::Duration,
+        login_history_retention: chrono::Duration,
     ) -> Self {
         Self {
             pool,
@@ -49,6 +69,11 @@ impl State {
             clock,
             homeserver: Arc::new(homeserver),
             url_builder,
+            http_client,
+            token_hasher,
+            alerter,
+            deleted_user_retention,
+            login_history_retention,
         }
     }
 
@@ -75,7 +100,10 @@ impl State {
     }
 
     pub async fn repository(&self) -> Result<BoxRepository, DatabaseError> {
-        let repo = PgRepository::from_pool(self.pool()).await?.boxed();
+        let repo = PgRepository::from_pool(self.pool())
+            .await?
+            .with_token_hasher(self.token_hasher.clone())
+            .boxed();
 
         Ok(repo)
     }
@@ -87,6 +115,35 @@ impl State {
     pub fn url_builder(&self) -> &UrlBuilder {
         &self.url_builder
     }
+
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    pub fn token_hasher(&self) -> &TokenHasher {
+        &self.token_hasher
+    }
+
+    pub fn alerter(&self) -> &Alerter {
+        &self.alerter
+    }
+
+    pub fn deleted_user_retention(&self) -> chrono::Duration {
+        self.deleted_user_retention
+    }
+
+    pub fn login_history_retention(&self) -> chrono::Duration {
+        self.login_history_retention
+    }
+
+    /// Post an alert to the administrators, if alerts are configured
+    pub async fn alert(&self, event: AlertEvent, message: &str) {
+        let clock = self.clock();
+        let mut rng = self.rng();
+        self.alerter
+            .send(self.matrix_connection(), &clock, &mut rng, event, message)
+            .await;
+    }
 }
 
 trait JobContextExt {
@@ -129,30 +186,65 @@ pub(crate) use build;
 
 /// Initialise the workers.
 ///
+/// The tokens saved by the jobs, like the ones hashed by the backfills, are
+/// hashed with the given [`TokenHasher`].
+///
+/// If `usage_stats_endpoint` is set, anonymous usage statistics are reported
+/// to it once a day. Alerts about critical events are posted using the given
+/// [`Alerter`]. Deleted users are purged once they were deleted for longer
+/// than `deleted_user_retention`, and login attempts once they are older than
+/// `login_history_retention`.
+///
 /// # Errors
 ///
 /// This function can fail if the database connection fails.
+#[allow(clippy::too_many_arguments)]
 pub async fn init(
     name: &str,
     pool: &Pool<Postgres>,
     mailer: &Mailer,
     homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
     url_builder: UrlBuilder,
+    http_client: reqwest::Client,
+    token_hasher: TokenHasher,
+    usage_stats_endpoint: Option<Url>,
+    alerter: Alerter,
+    deleted_user_retention: std::time::Duration,
+    login_history_retention: std::time::Duration,
 ) -> Result<Monitor<TokioExecutor>, sqlx::Error> {
+    // Cap the retentions to something representable, which is still way more
+    // than anyone would configure
+    let deleted_user_retention = chrono::Duration::from_std(deleted_user_retention)
+        .unwrap_or_else(|_| chrono::Duration::max_value());
+    let login_history_retention = chrono::Duration::from_std(login_history_retention)
+        .unwrap_or_else(|_| chrono::Duration::max_value());
     let state = State::new(
         pool.clone(),
         SystemClock::default(),
         mailer.clone(),
         homeserver,
         url_builder,
+        http_client,
+        token_hasher,
+        alerter,
+        deleted_user_retention,
+        login_history_retention,
     );
     let factory = PostgresStorageFactory::new(pool.clone());
     let monitor = Monitor::new().executor(TokioExecutor::new());
     let monitor = self::database::register(name, monitor, &state);
+    let monitor = self::backfill::register(name, monitor, &state);
     let monitor = self::email::register(name, monitor, &state, &factory);
     let monitor = self::matrix::register(name, monitor, &state, &factory);
     let monitor = self::user::register(name, monitor, &state, &factory);
     let monitor = self::recovery::register(name, monitor, &state, &factory);
+    let monitor = self::upstream_oauth2::register(name, monitor, &state);
+    let monitor = self::alerts::register(name, monitor, &state);
+    let monitor = if let Some(endpoint) = usage_stats_endpoint {
+        self::stats::register(name, monitor, &state, endpoint)
+    } else {
+        monitor
+    };
     // TODO: we might want to grab the join handle here
     factory.listen().await?;
     debug!(?monitor, "workers registered");
@@ -14,7 +14,7 @@ use mas_storage::{
     compat::CompatSessionFilter,
     job::{
         DeleteDeviceJob, JobRepositoryExt as _, JobWithSpanContext, ProvisionDeviceJob,
-        ProvisionUserJob, SyncDevicesJob,
+        ProvisionUserJob, RevokeDeviceJob, SyncDevicesJob,
     },
     oauth2::OAuth2SessionFilter,
     user::{UserEmailRepository, UserRepository},
@@ -224,6 +224,76 @@ async fn sync_devices(
     Ok(())
 }
 
+/// Job to delete a revoked device from the homeserver, so that its tokens stop
+/// working right away.
+///
+/// Errors are returned to the job queue, which retries the job.
+#[tracing::instrument(
+    name = "job.revoke_device"
+    fields(
+        user.id = %job.user_id(),
+        device.id = %job.device_id(),
+    ),
+    skip_all,
+    err(Debug),
+)]
+async fn revoke_device(
+    job: JobWithSpanContext<RevokeDeviceJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let matrix = state.matrix_connection();
+    let mut repo = state.repository().await?;
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    let device = Device::try_from(job.device_id().to_owned())?;
+
+    // Lock the user sync to make sure we don't race with a device sync
+    repo.user().acquire_lock_for_sync(&user).await?;
+
+    // The device may have been picked up by another session in the meantime,
+    // in which case it must stay on the homeserver
+    let active_compat_sessions = repo
+        .compat_session()
+        .count(
+            CompatSessionFilter::new()
+                .for_user(&user)
+                .for_device(&device)
+                .active_only(),
+        )
+        .await?;
+    let active_oauth2_sessions = repo
+        .oauth2_session()
+        .count(
+            OAuth2SessionFilter::new()
+                .for_user(&user)
+                .for_device(&device)
+                .active_only(),
+        )
+        .await?;
+
+    if active_compat_sessions > 0 || active_oauth2_sessions > 0 {
+        info!("Device is still in use, not revoking it");
+        repo.save().await?;
+        return Ok(());
+    }
+
+    let mxid = matrix.mxid(&user.username);
+    matrix.delete_device(&mxid, device.as_str()).await?;
+    info!(%mxid, "Device revoked on the homeserver");
+
+    // We kept the connection until now, so that we still hold the lock on the user
+    // throughout the deletion
+    repo.save().await?;
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -238,10 +308,13 @@ pub(crate) fn register(
         crate::build!(DeleteDeviceJob => delete_device, suffix, state, storage_factory);
     let sync_devices_worker =
         crate::build!(SyncDevicesJob => sync_devices, suffix, state, storage_factory);
+    let revoke_device_worker =
+        crate::build!(RevokeDeviceJob => revoke_device, suffix, state, storage_factory);
 
     monitor
         .register(provision_user_worker)
         .register(provision_device_worker)
         .register(delete_device_worker)
         .register(sync_devices_worker)
+        .register(revoke_device_worker)
 }
@@ -15,7 +15,7 @@ use mas_storage::{
 };
 use mas_templates::{EmailRecoveryContext, TemplateContext};
 use rand::distributions::{Alphanumeric, DistString};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{storage::PostgresStorageFactory, JobContextExt, State};
 
@@ -72,6 +72,21 @@ async fn send_account_recovery_email_job(
             .await?;
 
         for email in page.edges {
+            cursor = cursor.after(email.id);
+
+            if repo
+                .email_queue()
+                .find_undeliverable(&email.email)
+                .await?
+                .is_some()
+            {
+                warn!(
+                    email.id = %email.id,
+                    "Email address is undeliverable, not sending a recovery email"
+                );
+                continue;
+            }
+
             let ticket = Alphanumeric.sample_string(&mut rng, 32);
 
             let ticket = repo
@@ -94,21 +109,26 @@ async fn send_account_recovery_email_job(
             let url = url_builder.account_recovery_link(ticket.ticket);
 
             let address: Address = user_email.email.parse()?;
-            let mailbox = Mailbox::new(Some(user.username.clone()), address);
+            let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
 
-            info!("Sending recovery email to {}", mailbox);
+            info!("Queuing recovery email to {}", mailbox);
             let context =
                 EmailRecoveryContext::new(user, session.clone(), url).with_language(lang.clone());
 
-            // XXX: we only log if the email fails to send, to avoid stopping the loop
-            if let Err(e) = mailer.send_recovery_email(mailbox, &context).await {
-                error!(
-                    error = &e as &dyn std::error::Error,
-                    "Failed to send recovery email"
-                );
+            // XXX: we only log if the email fails to render, to avoid stopping the loop
+            match mailer.render_recovery_email(mailbox, &context) {
+                Ok(message) => {
+                    repo.email_queue()
+                        .enqueue(&mut rng, &clock, address.to_string(), message)
+                        .await?;
+                }
+                Err(e) => {
+                    error!(
+                        error = &e as &dyn std::error::Error,
+                        "Failed to render recovery email"
+                    );
+                }
             }
-
-            cursor = cursor.after(email.id);
         }
 
         if !page.has_next_page {
@@ -0,0 +1,160 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Anonymous usage statistics, periodically reported to a configurable
+//! endpoint when opted-in
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    layers::extensions::Extension,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Duration, Utc};
+use mas_http::RequestBuilderExt;
+use mas_storage::{user::UserFilter, Clock, RepositoryAccess};
+use serde::Serialize;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// Aggregate counts about the usage of the service, which don't contain any
+/// personal information
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    /// Version of the service
+    pub version: &'static str,
+
+    /// Number of active (not locked) users
+    pub total_users: usize,
+
+    /// Number of distinct users with activity in the last 24 hours
+    pub daily_active_users: usize,
+
+    /// Number of distinct users with activity in the last 30 days
+    pub monthly_active_users: usize,
+
+    /// Number of browser authentications in the last 24 hours
+    pub daily_logins: usize,
+
+    /// Number of access tokens issued in the last 24 hours
+    pub daily_issued_access_tokens: usize,
+}
+
+impl UsageReport {
+    /// Compute the usage report from the database
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying repository fails
+    pub async fn collect<R>(repo: &mut R, clock: &dyn Clock) -> Result<Self, R::Error>
+    where
+        R: RepositoryAccess + ?Sized,
+    {
+        let now = clock.now();
+        let last_day = now - Duration::days(1);
+        let last_month = now - Duration::days(30);
+
+        let total_users = repo.user().count(UserFilter::new().active_only()).await?;
+        let daily_active_users = repo.stats().count_active_users(last_day).await?;
+        let monthly_active_users = repo.stats().count_active_users(last_month).await?;
+        let daily_logins = repo.stats().count_authentications(last_day).await?;
+        let daily_issued_access_tokens = repo.stats().count_issued_access_tokens(last_day).await?;
+
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION"),
+            total_users,
+            daily_active_users,
+            monthly_active_users,
+            daily_logins,
+            daily_issued_access_tokens,
+        })
+    }
+}
+
+/// Where to send the usage reports
+#[derive(Clone)]
+struct UsageStatsReporter {
+    endpoint: Url,
+}
+
+#[derive(Default, Clone)]
+pub struct ReportUsageStatsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ReportUsageStatsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ReportUsageStatsJob {
+    const NAME: &'static str = "report-usage-stats";
+}
+
+impl TracedJob for ReportUsageStatsJob {}
+
+pub async fn report_usage_stats(
+    job: ReportUsageStatsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("report usage stats job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let reporter = ctx
+        .data_opt::<UsageStatsReporter>()
+        .expect("usage stats reporter not injected in job context")
+        .clone();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let report = UsageReport::collect(&mut repo, &clock).await?;
+    repo.cancel().await?;
+
+    state
+        .http_client()
+        .post(reporter.endpoint.clone())
+        .json(&report)
+        .send_traced()
+        .await?
+        .error_for_status()?;
+
+    info!(?report, "Reported usage statistics");
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+    endpoint: Url,
+) -> Monitor<TokioExecutor> {
+    let reporter = UsageStatsReporter { endpoint };
+
+    // Report once a day
+    let schedule = apalis_cron::Schedule::from_str("0 0 0 * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ReportUsageStatsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(Extension(reporter))
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(report_usage_stats);
+
+    monitor.register(worker)
+}
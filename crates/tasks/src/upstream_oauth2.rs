@@ -0,0 +1,172 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Health checks of the upstream OAuth 2.0 providers
+
+use std::{str::FromStr, time::Duration};
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderDiscoveryMode};
+use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess};
+use tracing::{debug, info, warn};
+
+use crate::{
+    alerts::AlertEvent,
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How long a single probe of a provider can take before it is considered
+/// failed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default, Clone)]
+pub struct CheckUpstreamProvidersJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CheckUpstreamProvidersJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CheckUpstreamProvidersJob {
+    const NAME: &'static str = "check-upstream-providers";
+}
+
+impl TracedJob for CheckUpstreamProvidersJob {}
+
+/// Check that the provider metadata and its JWKS can be fetched
+async fn probe(
+    client: &reqwest::Client,
+    provider: &UpstreamOAuthProvider,
+) -> Result<(), anyhow::Error> {
+    let discovered_jwks_uri = match provider.discovery_mode {
+        UpstreamOAuthProviderDiscoveryMode::Oidc => Some(
+            mas_oidc_client::requests::discovery::discover(client, &provider.issuer)
+                .await?
+                .jwks_uri()
+                .clone(),
+        ),
+        UpstreamOAuthProviderDiscoveryMode::Insecure => Some(
+            mas_oidc_client::requests::discovery::insecure_discover(client, &provider.issuer)
+                .await?
+                .jwks_uri()
+                .clone(),
+        ),
+        UpstreamOAuthProviderDiscoveryMode::Disabled => None,
+    };
+
+    // The JWKS isn't used by providers which don't do OIDC, in which case
+    // there is nothing to check
+    if let Some(jwks_uri) = provider
+        .jwks_uri_override
+        .as_ref()
+        .or(discovered_jwks_uri.as_ref())
+    {
+        mas_oidc_client::requests::jose::fetch_jwks(client, jwks_uri).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn check_upstream_providers(
+    job: CheckUpstreamProvidersJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "check upstream providers job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+    // Don't hold the connection while probing the providers
+    repo.cancel().await?;
+
+    for provider in providers {
+        let error = match tokio::time::timeout(PROBE_TIMEOUT, probe(state.http_client(), &provider))
+            .await
+        {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(format!("{e:#}")),
+            Err(_) => Some("Timed out".to_owned()),
+        };
+
+        let mut repo = state.repository().await?;
+        let previous = repo.upstream_oauth_provider().health(&provider).await?;
+        let health = repo
+            .upstream_oauth_provider()
+            .record_health_check(&clock, &provider, error)
+            .await?;
+        repo.save().await?;
+
+        let was_down = previous.is_some_and(|h| h.is_down(clock.now()));
+        let is_down = health.is_down(clock.now());
+        match (was_down, is_down) {
+            (false, true) => {
+                warn!(
+                    upstream_oauth_provider.id = %provider.id,
+                    upstream_oauth_provider.issuer = %provider.issuer,
+                    error = health.last_error.as_deref(),
+                    "Upstream provider is down"
+                );
+                state
+                    .alert(
+                        AlertEvent::UpstreamProviderDown,
+                        &format!(
+                            "Upstream provider {} ({}) is down: {}",
+                            provider.issuer,
+                            provider.id,
+                            health.last_error.as_deref().unwrap_or("unknown error"),
+                        ),
+                    )
+                    .await;
+            }
+            (true, false) => info!(
+                upstream_oauth_provider.id = %provider.id,
+                upstream_oauth_provider.issuer = %provider.issuer,
+                "Upstream provider is back up"
+            ),
+            _ => debug!(
+                upstream_oauth_provider.id = %provider.id,
+                consecutive_failures = health.consecutive_failures,
+                "Checked upstream provider"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let schedule = apalis_cron::Schedule::from_str("0 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CheckUpstreamProvidersJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(check_upstream_providers);
+
+    monitor.register(worker)
+}
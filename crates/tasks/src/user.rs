@@ -4,18 +4,37 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::str::FromStr;
+
 use anyhow::Context;
-use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_matrix::HomeserverFeature;
 use mas_storage::{
     compat::CompatSessionFilter,
     job::{DeactivateUserJob, JobWithSpanContext, ReactivateUserJob},
     oauth2::OAuth2SessionFilter,
-    user::{BrowserSessionFilter, UserRepository},
+    user::{BrowserSessionFilter, UserLoginHistoryRepository, UserRepository},
     RepositoryAccess,
 };
-use tracing::info;
+use tracing::{debug, info, warn};
+
+use crate::{
+    storage::PostgresStorageFactory,
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+/// How many users or login attempts are purged in a single transaction
+const PURGE_BATCH_SIZE: usize = 100;
 
 /// Job to deactivate a user, both locally and on the Matrix homeserver.
 #[tracing::instrument(
@@ -79,6 +98,14 @@ async fn deactivate_user(
     repo.save().await?;
 
     let mxid = matrix.mxid(&user.username);
+    if !matrix
+        .capabilities()
+        .supports(HomeserverFeature::Deactivation)
+    {
+        warn!("The homeserver can't deactivate users, {mxid} is only locked out of MAS");
+        return Ok(());
+    }
+
     info!("Deactivating user {} on homeserver", mxid);
     matrix.delete_user(&mxid, job.hs_erase()).await?;
 
@@ -107,8 +134,15 @@ pub async fn reactivate_user(
         .context("User not found")?;
 
     let mxid = matrix.mxid(&user.username);
-    info!("Reactivating user {} on homeserver", mxid);
-    matrix.reactivate_user(&mxid).await?;
+    if matrix
+        .capabilities()
+        .supports(HomeserverFeature::Reactivation)
+    {
+        info!("Reactivating user {} on homeserver", mxid);
+        matrix.reactivate_user(&mxid).await?;
+    } else {
+        warn!("The homeserver can't reactivate users, {mxid} is only unlocked in MAS");
+    }
 
     // We want to unlock the user from our side only once it has been reactivated on
     // the homeserver
@@ -118,6 +152,114 @@ pub async fn reactivate_user(
     Ok(())
 }
 
+#[derive(Default, Clone)]
+pub struct PurgeDeletedUsersJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for PurgeDeletedUsersJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for PurgeDeletedUsersJob {
+    const NAME: &'static str = "purge-deleted-users";
+}
+
+impl TracedJob for PurgeDeletedUsersJob {}
+
+/// Job to permanently remove the users which were deleted before the
+/// retention period.
+pub async fn purge_deleted_users(
+    job: PurgeDeletedUsersJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("purge deleted users job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let deleted_before = clock.now() - state.deleted_user_retention();
+
+    // Purge in batches, each in its own transaction, so that we don't hold
+    // locks on many rows for too long
+    let mut total = 0;
+    loop {
+        let mut repo = state.repository().await?;
+        let count = repo
+            .user()
+            .purge_deleted(deleted_before, PURGE_BATCH_SIZE)
+            .await?;
+        repo.save().await?;
+
+        total += count;
+        if count < PURGE_BATCH_SIZE {
+            break;
+        }
+    }
+
+    if total == 0 {
+        debug!("no deleted user to purge");
+    } else {
+        info!(count = total, "purged deleted users");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct PurgeLoginHistoryJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for PurgeLoginHistoryJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for PurgeLoginHistoryJob {
+    const NAME: &'static str = "purge-login-history";
+}
+
+impl TracedJob for PurgeLoginHistoryJob {}
+
+/// Job to remove the login attempts older than the retention period.
+pub async fn purge_login_history(
+    job: PurgeLoginHistoryJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("purge login history job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let before = clock.now() - state.login_history_retention();
+
+    let mut total = 0;
+    loop {
+        let mut repo = state.repository().await?;
+        let count = repo
+            .user_login_history()
+            .purge(before, PURGE_BATCH_SIZE)
+            .await?;
+        repo.save().await?;
+
+        total += count;
+        if count < PURGE_BATCH_SIZE {
+            break;
+        }
+    }
+
+    if total > 0 {
+        info!(
+            count = total,
+            "purged login attempts past the retention period"
+        );
+    }
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -130,7 +272,27 @@ pub(crate) fn register(
     let reactivate_user_worker =
         crate::build!(ReactivateUserJob => reactivate_user, suffix, state, storage_factory);
 
+    let schedule = apalis_cron::Schedule::from_str("0 30 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = PurgeDeletedUsersJob::NAME);
+    let purge_deleted_users_worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(purge_deleted_users);
+
+    let schedule = apalis_cron::Schedule::from_str("0 45 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = PurgeLoginHistoryJob::NAME);
+    let purge_login_history_worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(purge_login_history);
+
     monitor
         .register(deactivate_user_worker)
         .register(reactivate_user_worker)
+        .register(purge_deleted_users_worker)
+        .register(purge_login_history_worker)
 }
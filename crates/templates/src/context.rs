@@ -10,8 +10,10 @@ mod branding;
 mod captcha;
 mod ext;
 mod features;
+mod scopes;
 
 use std::{
+    collections::BTreeMap,
     fmt::Formatter,
     net::{IpAddr, Ipv4Addr},
 };
@@ -26,7 +28,7 @@ use mas_data_model::{
     UserEmail, UserEmailVerification, UserRecoverySession,
 };
 use mas_i18n::DataLocale;
-use mas_router::{Account, GraphQL, PostAuthAction, UrlBuilder};
+use mas_router::{Account, GraphQL, PostAuthAction, Reauth, UrlBuilder};
 use oauth2_types::scope::{Scope, OPENID};
 use rand::{
     distributions::{Alphanumeric, DistString},
@@ -38,8 +40,9 @@ use url::Url;
 
 pub use self::{
     branding::SiteBranding, captcha::WithCaptcha, ext::SiteConfigExt, features::SiteFeatures,
+    scopes::ScopeRegistry,
 };
-use crate::{FieldError, FormField, FormState};
+use crate::{FieldError, FormError, FormField, FormState};
 
 /// Helper trait to construct context wrappers
 pub trait TemplateContext: Serialize {
@@ -81,6 +84,19 @@ pub trait TemplateContext: Serialize {
         }
     }
 
+    /// Attach a Content-Security-Policy nonce to the template context, to be
+    /// used on inline scripts
+    fn with_csp_nonce<N>(self, csp_nonce: Option<N>) -> WithCspNonce<Self>
+    where
+        Self: Sized,
+        N: ToString,
+    {
+        WithCspNonce {
+            csp_nonce: csp_nonce.map(|n| n.to_string()),
+            inner: self,
+        }
+    }
+
     /// Attach a language to the template context
     fn with_language(self, lang: DataLocale) -> WithLanguage<Self>
     where
@@ -181,6 +197,30 @@ impl<T: TemplateContext> TemplateContext for WithCsrf<T> {
     }
 }
 
+/// Context with a Content-Security-Policy nonce in it
+#[derive(Serialize, Debug)]
+pub struct WithCspNonce<T> {
+    csp_nonce: Option<String>,
+
+    #[serde(flatten)]
+    inner: T,
+}
+
+impl<T: TemplateContext> TemplateContext for WithCspNonce<T> {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        T::sample(now, rng)
+            .into_iter()
+            .map(|inner| WithCspNonce {
+                csp_nonce: Some("fake_csp_nonce".into()),
+                inner,
+            })
+            .collect()
+    }
+}
+
 /// Context with a user session in it
 #[derive(Serialize)]
 pub struct WithSession<T> {
@@ -298,6 +338,7 @@ impl TemplateContext for IndexContext {
 pub struct AppConfig {
     root: String,
     graphql_endpoint: String,
+    reauth_endpoint: String,
 }
 
 /// Context used by the `app.html` template
@@ -312,10 +353,12 @@ impl AppContext {
     pub fn from_url_builder(url_builder: &UrlBuilder) -> Self {
         let root = url_builder.relative_url_for(&Account::default());
         let graphql_endpoint = url_builder.relative_url_for(&GraphQL);
+        let reauth_endpoint = url_builder.relative_url_for(&Reauth::default());
         Self {
             app_config: AppConfig {
                 root,
                 graphql_endpoint,
+                reauth_endpoint,
             },
         }
     }
@@ -369,12 +412,15 @@ pub enum LoginFormField {
 
     /// The password field
     Password,
+
+    /// The Matrix ID field, to log in with an account on another homeserver
+    Mxid,
 }
 
 impl FormField for LoginFormField {
     fn keep(&self) -> bool {
         match self {
-            Self::Username => true,
+            Self::Username | Self::Mxid => true,
             Self::Password => false,
         }
     }
@@ -436,6 +482,7 @@ pub struct LoginContext {
     form: FormState<LoginFormField>,
     next: Option<PostAuthContext>,
     providers: Vec<UpstreamOAuthProvider>,
+    experiments: BTreeMap<String, String>,
 }
 
 impl TemplateContext for LoginContext {
@@ -449,11 +496,13 @@ impl TemplateContext for LoginContext {
                 form: FormState::default(),
                 next: None,
                 providers: Vec::new(),
+                experiments: BTreeMap::new(),
             },
             LoginContext {
                 form: FormState::default(),
                 next: None,
                 providers: Vec::new(),
+                experiments: BTreeMap::new(),
             },
             LoginContext {
                 form: FormState::default()
@@ -466,12 +515,21 @@ impl TemplateContext for LoginContext {
                     ),
                 next: None,
                 providers: Vec::new(),
+                experiments: BTreeMap::new(),
             },
             LoginContext {
                 form: FormState::default()
                     .with_error_on_field(LoginFormField::Username, FieldError::Exists),
                 next: None,
                 providers: Vec::new(),
+                experiments: BTreeMap::new(),
+            },
+            LoginContext {
+                form: FormState::default()
+                    .with_error_on_field(LoginFormField::Mxid, FieldError::Unsupported),
+                next: None,
+                providers: Vec::new(),
+                experiments: BTreeMap::new(),
             },
         ]
     }
@@ -495,6 +553,16 @@ impl LoginContext {
         Self { providers, ..self }
     }
 
+    /// Set the variants of the A/B experiments the visitor is assigned to,
+    /// keyed by experiment
+    #[must_use]
+    pub fn with_experiments(self, experiments: BTreeMap<String, String>) -> Self {
+        Self {
+            experiments,
+            ..self
+        }
+    }
+
     /// Add a post authentication action to the context
     #[must_use]
     pub fn with_post_action(self, context: PostAuthContext) -> Self {
@@ -539,6 +607,7 @@ impl FormField for RegisterFormField {
 pub struct RegisterContext {
     form: FormState<RegisterFormField>,
     next: Option<PostAuthContext>,
+    experiments: BTreeMap<String, String>,
 }
 
 impl TemplateContext for RegisterContext {
@@ -550,6 +619,7 @@ impl TemplateContext for RegisterContext {
         vec![RegisterContext {
             form: FormState::default(),
             next: None,
+            experiments: BTreeMap::new(),
         }]
     }
 }
@@ -569,6 +639,16 @@ impl RegisterContext {
             ..self
         }
     }
+
+    /// Set the variants of the A/B experiments the visitor is assigned to,
+    /// keyed by experiment
+    #[must_use]
+    pub fn with_experiments(self, experiments: BTreeMap<String, String>) -> Self {
+        Self {
+            experiments,
+            ..self
+        }
+    }
 }
 
 /// Context used by the `consent.html` template
@@ -746,6 +826,62 @@ impl ReauthContext {
     }
 }
 
+/// Fields of the required password change form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordChangeRequiredFormField {
+    /// The new password
+    NewPassword,
+
+    /// The new password confirmation
+    NewPasswordConfirm,
+}
+
+impl FormField for PasswordChangeRequiredFormField {
+    fn keep(&self) -> bool {
+        false
+    }
+}
+
+/// Context used by the `pages/password_change_required.html` template
+#[derive(Serialize, Default)]
+pub struct PasswordChangeRequiredContext {
+    form: FormState<PasswordChangeRequiredFormField>,
+    next: Option<PostAuthContext>,
+}
+
+impl TemplateContext for PasswordChangeRequiredContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            Self::default(),
+            Self::default().with_form_state(FormState::default().with_error_on_field(
+                PasswordChangeRequiredFormField::NewPasswordConfirm,
+                FieldError::PasswordMismatch,
+            )),
+        ]
+    }
+}
+
+impl PasswordChangeRequiredContext {
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<PasswordChangeRequiredFormField>) -> Self {
+        Self { form, ..self }
+    }
+
+    /// Add a post authentication action to the context
+    #[must_use]
+    pub fn with_post_action(self, next: PostAuthContext) -> Self {
+        Self {
+            next: Some(next),
+            ..self
+        }
+    }
+}
+
 /// Context used by the `sso.html` template
 #[derive(Serialize)]
 pub struct CompatSsoContext {
@@ -839,13 +975,18 @@ impl TemplateContext for EmailRecoveryContext {
 pub struct EmailVerificationContext {
     user: User,
     verification: UserEmailVerification,
+    verification_link: Url,
 }
 
 impl EmailVerificationContext {
     /// Constructs a context for the verification email
     #[must_use]
-    pub fn new(user: User, verification: UserEmailVerification) -> Self {
-        Self { user, verification }
+    pub fn new(user: User, verification: UserEmailVerification, verification_link: Url) -> Self {
+        Self {
+            user,
+            verification,
+            verification_link,
+        }
     }
 
     /// Get the user to which this email is being sent
@@ -885,7 +1026,14 @@ impl TemplateContext for EmailVerificationContext {
                     state: mas_data_model::UserEmailVerificationState::Valid,
                 };
 
-                Self { user, verification }
+                let verification_link = format!(
+                    "https://example.com/verify-email/{}/link?code=123456",
+                    email.id
+                )
+                .parse()
+                .unwrap();
+
+                Self::new(user, verification, verification_link)
             })
             .collect()
     }
@@ -1434,13 +1582,23 @@ impl FormField for DeviceLinkFormField {
 #[derive(Serialize, Default, Debug)]
 pub struct DeviceLinkContext {
     form_state: FormState<DeviceLinkFormField>,
+    code_length: usize,
+    code_placeholder: String,
 }
 
 impl DeviceLinkContext {
     /// Constructs a new context with an existing linked user
     #[must_use]
     pub fn new() -> Self {
-        Self::default()
+        Self::default().with_code_length(6)
+    }
+
+    /// Set the length of the user codes, used to format the input field
+    #[must_use]
+    pub fn with_code_length(mut self, code_length: usize) -> Self {
+        self.code_length = code_length;
+        self.code_placeholder = DeviceCodeGrant::format_user_code(&"X".repeat(code_length));
+        self
     }
 
     /// Set the form state
@@ -1462,6 +1620,9 @@ impl TemplateContext for DeviceLinkContext {
                 FormState::default()
                     .with_error_on_field(DeviceLinkFormField::Code, FieldError::Required),
             ),
+            Self::new().with_code_length(8).with_form_state(
+                FormState::default().with_error_on_form(FormError::RateLimitExceeded),
+            ),
         ]
     }
 }
@@ -1470,6 +1631,7 @@ impl TemplateContext for DeviceLinkContext {
 #[derive(Serialize, Debug)]
 pub struct DeviceConsentContext {
     grant: DeviceCodeGrant,
+    formatted_user_code: String,
     client: Client,
 }
 
@@ -1477,7 +1639,12 @@ impl DeviceConsentContext {
     /// Constructs a new context with an existing linked user
     #[must_use]
     pub fn new(grant: DeviceCodeGrant, client: Client) -> Self {
-        Self { grant, client }
+        let formatted_user_code = grant.formatted_user_code();
+        Self {
+            grant,
+            formatted_user_code,
+            client,
+        }
     }
 }
 
@@ -1501,7 +1668,7 @@ impl TemplateContext for DeviceConsentContext {
                     ip_address: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
                     user_agent: Some(UserAgent::parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.0.0 Safari/537.36".to_owned())),
                 };
-                Self { grant, client }
+                Self::new(grant, client)
             })
             .collect()
     }
@@ -1512,6 +1679,7 @@ impl TemplateContext for DeviceConsentContext {
 pub struct FormPostContext<T> {
     redirect_uri: Option<Url>,
     params: T,
+    csp_nonce: Option<String>,
 }
 
 impl<T: TemplateContext> TemplateContext for FormPostContext<T> {
@@ -1525,6 +1693,7 @@ impl<T: TemplateContext> TemplateContext for FormPostContext<T> {
             .map(|params| FormPostContext {
                 redirect_uri: "https://example.com/callback".parse().ok(),
                 params,
+                csp_nonce: Some("fake_csp_nonce".into()),
             })
             .collect()
     }
@@ -1537,6 +1706,7 @@ impl<T> FormPostContext<T> {
         Self {
             redirect_uri: Some(redirect_uri),
             params,
+            csp_nonce: None,
         }
     }
 
@@ -1546,9 +1716,18 @@ impl<T> FormPostContext<T> {
         Self {
             redirect_uri: None,
             params,
+            csp_nonce: None,
         }
     }
 
+    /// Set the Content-Security-Policy nonce used on the inline script which
+    /// submits the form
+    #[must_use]
+    pub fn with_csp_nonce<N: ToString>(mut self, csp_nonce: Option<N>) -> Self {
+        self.csp_nonce = csp_nonce.map(|n| n.to_string());
+        self
+    }
+
     /// Add the language to the context
     ///
     /// This is usually implemented by the [`TemplateContext`] trait, but it is
@@ -1693,3 +1872,37 @@ impl TemplateContext for NotFoundContext {
         ]
     }
 }
+
+/// Context used by the `pages/oauth2_error.html` template, documenting an
+/// OAuth 2.0 error code
+#[derive(Serialize)]
+pub struct OAuth2ErrorContext {
+    code: String,
+    description: String,
+    version: &'static str,
+}
+
+impl OAuth2ErrorContext {
+    /// Constructs a context for the documentation page of an OAuth 2.0 error
+    /// code, with its localized description
+    #[must_use]
+    pub fn new(code: String, description: String) -> Self {
+        Self {
+            code,
+            description,
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+impl TemplateContext for OAuth2ErrorContext {
+    fn sample(_now: DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![Self::new(
+            "invalid_grant".to_owned(),
+            "The provided access grant is invalid, expired, or revoked.".to_owned(),
+        )]
+    }
+}
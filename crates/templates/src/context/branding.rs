@@ -11,6 +11,8 @@ use minijinja::{
     Value,
 };
 
+use super::ScopeRegistry;
+
 /// Site branding information.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SiteBranding {
@@ -18,6 +20,7 @@ pub struct SiteBranding {
     policy_uri: Option<Arc<str>>,
     tos_uri: Option<Arc<str>>,
     imprint: Option<Arc<str>>,
+    scopes: ScopeRegistry,
 }
 
 impl SiteBranding {
@@ -29,6 +32,7 @@ impl SiteBranding {
             policy_uri: None,
             tos_uri: None,
             imprint: None,
+            scopes: ScopeRegistry::default(),
         }
     }
 
@@ -52,6 +56,13 @@ impl SiteBranding {
         self.imprint = Some(imprint.into());
         self
     }
+
+    /// Set the registry describing the scopes on the consent screens.
+    #[must_use]
+    pub fn with_scopes(mut self, scopes: ScopeRegistry) -> Self {
+        self.scopes = scopes;
+        self
+    }
 }
 
 impl Object for SiteBranding {
@@ -61,11 +72,12 @@ impl Object for SiteBranding {
             "policy_uri" => self.policy_uri.clone().map(Value::from),
             "tos_uri" => self.tos_uri.clone().map(Value::from),
             "imprint" => self.imprint.clone().map(Value::from),
+            "scopes" => Some(Value::from_object(self.scopes.clone())),
             _ => None,
         }
     }
 
     fn enumerate(self: &Arc<Self>) -> Enumerator {
-        Enumerator::Str(&["server_name", "policy_uri", "tos_uri", "imprint"])
+        Enumerator::Str(&["server_name", "policy_uri", "tos_uri", "imprint", "scopes"])
     }
 }
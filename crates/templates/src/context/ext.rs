@@ -6,7 +6,7 @@
 
 use mas_data_model::SiteConfig;
 
-use super::{SiteBranding, SiteFeatures};
+use super::{ScopeRegistry, SiteBranding, SiteFeatures};
 
 mod private {
     pub trait Sealed {}
@@ -25,7 +25,8 @@ pub trait SiteConfigExt: private::Sealed {
 
 impl SiteConfigExt for SiteConfig {
     fn templates_branding(&self) -> SiteBranding {
-        let mut branding = SiteBranding::new(self.server_name.clone());
+        let mut branding = SiteBranding::new(self.server_name.clone())
+            .with_scopes(ScopeRegistry::new(self.custom_scopes.clone()));
 
         if let Some(policy_uri) = &self.policy_uri {
             branding = branding.with_policy_uri(policy_uri.as_str());
@@ -47,6 +48,9 @@ impl SiteConfigExt for SiteConfig {
             password_registration: self.password_registration_enabled,
             password_login: self.password_login_enabled,
             account_recovery: self.account_recovery_allowed,
+            certificate_login: self.certificate_login.is_some(),
+            matrix_id_login: self.matrix_id_login_enabled,
+            flags: self.feature_flags.clone(),
         }
     }
 }
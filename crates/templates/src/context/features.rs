@@ -6,13 +6,16 @@
 
 use std::sync::Arc;
 
+use mas_data_model::FeatureFlags;
 use minijinja::{
-    value::{Enumerator, Object},
-    Value,
+    value::{from_args, Enumerator, Object},
+    Error, ErrorKind, State, Value,
 };
+use ulid::Ulid;
 
 /// Site features information.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct SiteFeatures {
     /// Whether local password-based registration is enabled.
     pub password_registration: bool,
@@ -22,6 +25,16 @@ pub struct SiteFeatures {
 
     /// Whether email-based account recovery is enabled.
     pub account_recovery: bool,
+
+    /// Whether login with a X.509 client certificate is enabled.
+    pub certificate_login: bool,
+
+    /// Whether login with the Matrix ID of an account on another homeserver is
+    /// enabled.
+    pub matrix_id_login: bool,
+
+    /// The feature flags, checked with `features.flag("name", user)`.
+    pub flags: FeatureFlags,
 }
 
 impl Object for SiteFeatures {
@@ -30,6 +43,8 @@ impl Object for SiteFeatures {
             "password_registration" => Some(Value::from(self.password_registration)),
             "password_login" => Some(Value::from(self.password_login)),
             "account_recovery" => Some(Value::from(self.account_recovery)),
+            "certificate_login" => Some(Value::from(self.certificate_login)),
+            "matrix_id_login" => Some(Value::from(self.matrix_id_login)),
             _ => None,
         }
     }
@@ -39,6 +54,49 @@ impl Object for SiteFeatures {
             "password_registration",
             "password_login",
             "account_recovery",
+            "certificate_login",
+            "matrix_id_login",
         ])
     }
+
+    fn call_method(
+        self: &Arc<Self>,
+        _state: &State,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        match name {
+            "flag" => {
+                let (flag, user): (&str, Option<Value>) = from_args(args)?;
+
+                // The user is the serialized `User`, or none for anonymous visitors
+                let user = match user {
+                    Some(user) if !user.is_none() && !user.is_undefined() => {
+                        let id = user.get_attr("id")?;
+                        let id: Ulid = id.as_str().unwrap_or_default().parse().map_err(|e| {
+                            Error::new(
+                                ErrorKind::InvalidOperation,
+                                "Invalid user while calling method `flag`",
+                            )
+                            .with_source(e)
+                        })?;
+                        let username = user.get_attr("username")?;
+                        Some((id, username.as_str().unwrap_or_default().to_owned()))
+                    }
+                    _ => None,
+                };
+
+                let enabled = self.flags.is_enabled_for(
+                    flag,
+                    user.as_ref().map(|(id, username)| (*id, username.as_str())),
+                );
+                Ok(Value::from(enabled))
+            }
+
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "Invalid method on features",
+            )),
+        }
+    }
 }
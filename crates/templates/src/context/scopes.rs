@@ -0,0 +1,176 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use mas_data_model::{CustomScope, ScopeIcon};
+use minijinja::{
+    value::{from_args, Object},
+    Error, ErrorKind, State, Value,
+};
+use serde::Serialize;
+
+/// Prefix of the scopes requesting a specific Matrix device ID
+const MATRIX_DEVICE_SCOPE_PREFIX: &str = "urn:matrix:org.matrix.msc2967.client:device:";
+
+/// What the scopes known by the service let clients do, as a list of
+/// translation keys under `mas.scope` with their icon
+fn builtin(scope: &str) -> Option<&'static [(&'static str, ScopeIcon)]> {
+    let descriptions: &'static [(&'static str, ScopeIcon)] = match scope {
+        "openid" => &[("view_profile", ScopeIcon::UserProfile)],
+        "urn:mas:graphql:*" => &[
+            ("edit_profile", ScopeIcon::Info),
+            ("manage_sessions", ScopeIcon::Computer),
+        ],
+        "urn:matrix:org.matrix.msc2967.client:api:*" => &[
+            ("view_messages", ScopeIcon::Chat),
+            ("send_messages", ScopeIcon::Send),
+        ],
+        "urn:synapse:admin:*" => &[("synapse_admin", ScopeIcon::Error)],
+        "urn:mas:admin" => &[("mas_admin", ScopeIcon::Error)],
+        "offline_access" => &[("offline_access", ScopeIcon::Time)],
+        // The device ID is not something users should care about
+        scope if scope.starts_with(MATRIX_DEVICE_SCOPE_PREFIX) => &[],
+        _ => return None,
+    };
+
+    Some(descriptions)
+}
+
+/// A line of the list of scopes displayed on the consent screen
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct ScopeDescription {
+    /// The translation key of the description under `mas.scope`, `custom` for
+    /// the custom scopes and `unknown` for the other ones
+    kind: &'static str,
+
+    /// The name of the icon
+    icon: &'static str,
+
+    /// The scope being described
+    scope: String,
+
+    /// The description of a custom scope, in the requested language
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// Registry of the human-readable descriptions of the scopes, used by the
+/// consent screens.
+///
+/// It knows about the scopes used by the service, and the custom scopes
+/// configured by the operator. The other scopes are displayed as-is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeRegistry {
+    custom: BTreeMap<String, CustomScope>,
+}
+
+impl ScopeRegistry {
+    /// Create a registry with the given custom scopes
+    #[must_use]
+    pub fn new(custom: BTreeMap<String, CustomScope>) -> Self {
+        Self { custom }
+    }
+
+    /// Describe the space-separated list of scopes in the given language
+    fn describe(&self, scopes: &str, lang: &str) -> Vec<ScopeDescription> {
+        let mut descriptions = Vec::new();
+        for scope in scopes.split(' ').filter(|scope| !scope.is_empty()) {
+            if let Some(builtin) = builtin(scope) {
+                descriptions.extend(builtin.iter().map(|(kind, icon)| ScopeDescription {
+                    kind,
+                    icon: icon.as_str(),
+                    scope: scope.to_owned(),
+                    description: None,
+                }));
+            } else if let Some(custom) = self.custom.get(scope) {
+                descriptions.push(ScopeDescription {
+                    kind: "custom",
+                    icon: custom.icon.as_str(),
+                    scope: scope.to_owned(),
+                    description: Some(localize(custom, lang).to_owned()),
+                });
+            } else {
+                descriptions.push(ScopeDescription {
+                    kind: "unknown",
+                    icon: ScopeIcon::Info.as_str(),
+                    scope: scope.to_owned(),
+                    description: None,
+                });
+            }
+        }
+
+        descriptions
+    }
+}
+
+/// Get the description of a custom scope in the given language, falling back
+/// to the language without its region, then to the untranslated description
+fn localize<'a>(custom: &'a CustomScope, lang: &str) -> &'a str {
+    let language = lang.split('-').next().unwrap_or(lang);
+    custom
+        .translations
+        .get(lang)
+        .or_else(|| custom.translations.get(language))
+        .unwrap_or(&custom.description)
+}
+
+impl Object for ScopeRegistry {
+    fn call_method(
+        self: &Arc<Self>,
+        _state: &State,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        match name {
+            "describe" => {
+                let (scopes, lang): (&str, &str) = from_args(args)?;
+                Ok(Value::from_serialize(self.describe(scopes, lang)))
+            }
+
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "Invalid method on scope registry",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_scopes() {
+        let registry = ScopeRegistry::new(BTreeMap::from([(
+            "urn:example:photos".to_owned(),
+            CustomScope {
+                icon: ScopeIcon::Email,
+                description: "See your photos".to_owned(),
+                translations: BTreeMap::from([("fr".to_owned(), "Voir vos photos".to_owned())]),
+            },
+        )]));
+
+        let descriptions = registry.describe(
+            "openid urn:matrix:org.matrix.msc2967.client:device:ABCDEF urn:example:photos \
+             urn:example:unknown",
+            "fr-CA",
+        );
+        let kinds: Vec<_> = descriptions.iter().map(|d| d.kind).collect();
+        assert_eq!(kinds, ["view_profile", "custom", "unknown"]);
+        assert_eq!(descriptions[1].icon, "email");
+        assert_eq!(
+            descriptions[1].description.as_deref(),
+            Some("Voir vos photos")
+        );
+        assert_eq!(descriptions[2].scope, "urn:example:unknown");
+
+        let descriptions = registry.describe("urn:example:photos", "de");
+        assert_eq!(
+            descriptions[0].description.as_deref(),
+            Some("See your photos")
+        );
+    }
+}
@@ -34,6 +34,9 @@ pub enum FieldError {
     /// That value already exists
     Exists,
 
+    /// The value is valid, but not supported by this service
+    Unsupported,
+
     /// Denied by the policy
     Policy {
         /// Message for this policy violation
@@ -65,6 +68,27 @@ pub enum FormError {
 
     /// Failed to validate CAPTCHA
     Captcha,
+
+    /// The submission looks automated
+    Suspicious,
+
+    /// Denied by the external risk provider
+    DeniedRisk,
+
+    /// Denied because of the network the request comes from
+    DeniedNetwork,
+
+    /// No valid client certificate matching a user was presented
+    ClientCertificate,
+
+    /// The verification code is invalid
+    InvalidCode,
+
+    /// The verification code has expired
+    ExpiredCode,
+
+    /// The server is too busy to handle the request right now
+    TemporarilyUnavailable,
 }
 
 #[derive(Debug, Default, Serialize)]
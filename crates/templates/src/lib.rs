@@ -14,7 +14,7 @@ use std::{collections::HashSet, sync::Arc};
 use anyhow::Context as _;
 use arc_swap::ArcSwap;
 use camino::{Utf8Path, Utf8PathBuf};
-use mas_i18n::Translator;
+use mas_i18n::{DataLocale, Translator};
 use mas_router::UrlBuilder;
 use mas_spa::ViteManifest;
 use minijinja::Value;
@@ -22,7 +22,7 @@ use rand::Rng;
 use serde::Serialize;
 use thiserror::Error;
 use tokio::task::JoinError;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use walkdir::DirEntry;
 
 mod context;
@@ -36,14 +36,17 @@ pub use self::{
     context::{
         ApiDocContext, AppContext, CompatSsoContext, ConsentContext, DeviceConsentContext,
         DeviceLinkContext, DeviceLinkFormField, EmailAddContext, EmailRecoveryContext,
-        EmailVerificationContext, EmailVerificationPageContext, EmptyContext, ErrorContext,
-        FormPostContext, IndexContext, LoginContext, LoginFormField, NotFoundContext,
-        PolicyViolationContext, PostAuthContext, PostAuthContextInner, ReauthContext,
-        ReauthFormField, RecoveryExpiredContext, RecoveryFinishContext, RecoveryFinishFormField,
-        RecoveryProgressContext, RecoveryStartContext, RecoveryStartFormField, RegisterContext,
-        RegisterFormField, SiteBranding, SiteConfigExt, SiteFeatures, TemplateContext,
+        EmailVerificationContext, EmailVerificationFormField, EmailVerificationPageContext,
+        EmptyContext, ErrorContext, FormPostContext, IndexContext, LoginContext, LoginFormField,
+        NotFoundContext, OAuth2ErrorContext, PasswordChangeRequiredContext,
+        PasswordChangeRequiredFormField, PolicyViolationContext, PostAuthContext,
+        PostAuthContextInner, ReauthContext, ReauthFormField, RecoveryExpiredContext,
+        RecoveryFinishContext, RecoveryFinishFormField, RecoveryProgressContext,
+        RecoveryStartContext, RecoveryStartFormField, RegisterContext, RegisterFormField,
+        ScopeRegistry, SiteBranding, SiteConfigExt, SiteFeatures, TemplateContext,
         UpstreamExistingLinkContext, UpstreamRegister, UpstreamRegisterFormField,
-        UpstreamSuggestLink, WithCaptcha, WithCsrf, WithLanguage, WithOptionalSession, WithSession,
+        UpstreamSuggestLink, WithCaptcha, WithCspNonce, WithCsrf, WithLanguage,
+        WithOptionalSession, WithSession,
     },
     forms::{FieldError, FormError, FormField, FormState, ToFormState},
 };
@@ -67,6 +70,7 @@ pub struct Templates {
     features: SiteFeatures,
     vite_manifest_path: Utf8PathBuf,
     translations_path: Utf8PathBuf,
+    email_overrides_path: Option<Utf8PathBuf>,
     path: Utf8PathBuf,
 }
 
@@ -130,6 +134,97 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .is_some_and(|s| s.starts_with('.'))
 }
 
+/// Prefix under which the email template overrides are registered in the
+/// environment. It can't clash with a template loaded from the filesystem.
+const EMAIL_OVERRIDES_PREFIX: &str = "@overrides";
+
+/// Register the email template overrides found in the given folder
+///
+/// Files at the root of the folder are registered as
+/// `@overrides/emails/{file}`, and files in a locale subfolder as
+/// `@overrides/{locale}/emails/{file}`.
+fn load_email_overrides(
+    env: &mut minijinja::Environment<'static>,
+    path: &Utf8Path,
+) -> Result<(), TemplateLoadingError> {
+    let root = path.canonicalize_utf8()?;
+    info!(%root, "Loading email template overrides from filesystem");
+    for entry in walkdir::WalkDir::new(&root)
+        .min_depth(1)
+        .max_depth(2)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = Utf8PathBuf::try_from(entry.into_path())?;
+        let relative = path.strip_prefix(&root)?;
+        let Some(file_name) = relative.file_name() else {
+            continue;
+        };
+
+        let template = format!("emails/{file_name}");
+        if !TEMPLATES.contains(&template.as_str()) {
+            warn!(%relative, "Ignoring unknown email template override");
+            continue;
+        }
+
+        let name = match relative.parent().map(Utf8Path::as_str) {
+            Some("") | None => format!("{EMAIL_OVERRIDES_PREFIX}/{template}"),
+            Some(locale) => {
+                let Ok(locale) = locale.parse::<DataLocale>() else {
+                    warn!(%relative, "Ignoring email template override in an invalid locale folder");
+                    continue;
+                };
+                format!("{EMAIL_OVERRIDES_PREFIX}/{locale}/{template}")
+            }
+        };
+
+        debug!(%relative, %name, "Registering email template override");
+        let source = std::fs::read_to_string(&path)?;
+        env.add_template_owned(name, source)?;
+    }
+
+    Ok(())
+}
+
+/// Get the template to render, taking the email template overrides into
+/// account
+///
+/// For email templates, the override for the exact language of the context is
+/// preferred, then the one for the language without its region, then the one
+/// for all languages, before falling back to the built-in template.
+fn pick_template<'env>(
+    env: &'env minijinja::Environment<'static>,
+    template: &'static str,
+    context: &Value,
+) -> Result<minijinja::Template<'env, 'env>, minijinja::Error> {
+    if template.starts_with("emails/") {
+        let lang = context.get_attr("lang").ok();
+        let lang = lang.as_ref().and_then(Value::as_str);
+
+        let mut candidates = Vec::with_capacity(3);
+        if let Some(lang) = lang {
+            candidates.push(format!("{EMAIL_OVERRIDES_PREFIX}/{lang}/{template}"));
+            if let Some((language, _)) = lang.split_once('-') {
+                candidates.push(format!("{EMAIL_OVERRIDES_PREFIX}/{language}/{template}"));
+            }
+        }
+        candidates.push(format!("{EMAIL_OVERRIDES_PREFIX}/{template}"));
+
+        for candidate in candidates {
+            if let Ok(tmpl) = env.get_template(&candidate) {
+                return Ok(tmpl);
+            }
+        }
+    }
+
+    env.get_template(template)
+}
+
 impl Templates {
     /// Load the templates from the given config
     #[tracing::instrument(
@@ -143,6 +238,7 @@ impl Templates {
         url_builder: UrlBuilder,
         vite_manifest_path: Utf8PathBuf,
         translations_path: Utf8PathBuf,
+        email_overrides_path: Option<Utf8PathBuf>,
         branding: SiteBranding,
         features: SiteFeatures,
     ) -> Result<Self, TemplateLoadingError> {
@@ -151,8 +247,9 @@ impl Templates {
             url_builder.clone(),
             &vite_manifest_path,
             &translations_path,
+            email_overrides_path.as_deref(),
             branding.clone(),
-            features,
+            features.clone(),
         )
         .await?;
         Ok(Self {
@@ -162,6 +259,7 @@ impl Templates {
             url_builder,
             vite_manifest_path,
             translations_path,
+            email_overrides_path,
             branding,
             features,
         })
@@ -172,10 +270,12 @@ impl Templates {
         url_builder: UrlBuilder,
         vite_manifest_path: &Utf8Path,
         translations_path: &Utf8Path,
+        email_overrides_path: Option<&Utf8Path>,
         branding: SiteBranding,
         features: SiteFeatures,
     ) -> Result<(Arc<Translator>, Arc<minijinja::Environment<'static>>), TemplateLoadingError> {
         let path = path.to_owned();
+        let email_overrides_path = email_overrides_path.map(ToOwned::to_owned);
         let span = tracing::Span::current();
 
         // Read the assets manifest from disk
@@ -223,6 +323,10 @@ impl Templates {
                     }
                 }
 
+                if let Some(email_overrides_path) = email_overrides_path {
+                    load_email_overrides(&mut env, &email_overrides_path)?;
+                }
+
                 Ok::<_, TemplateLoadingError>((loaded, env))
             })
         })
@@ -264,8 +368,9 @@ impl Templates {
             self.url_builder.clone(),
             &self.vite_manifest_path,
             &self.translations_path,
+            self.email_overrides_path.as_deref(),
             self.branding.clone(),
-            self.features,
+            self.features.clone(),
         )
         .await?;
 
@@ -281,6 +386,12 @@ impl Templates {
     pub fn translator(&self) -> Arc<Translator> {
         self.translator.load_full()
     }
+
+    /// Get the URL builder used by the templates
+    #[must_use]
+    pub fn url_builder(&self) -> &UrlBuilder {
+        &self.url_builder
+    }
 }
 
 /// Failed to render a template
@@ -313,8 +424,11 @@ register_templates! {
     /// Render the not found fallback page
     pub fn render_not_found(WithLanguage<NotFoundContext>) { "pages/404.html" }
 
+    /// Render the documentation page of an OAuth 2.0 error
+    pub fn render_oauth2_error(WithLanguage<OAuth2ErrorContext>) { "pages/oauth2_error.html" }
+
     /// Render the frontend app
-    pub fn render_app(WithLanguage<AppContext>) { "app.html" }
+    pub fn render_app(WithLanguage<WithCspNonce<AppContext>>) { "app.html" }
 
     /// Render the Swagger API reference
     pub fn render_swagger(ApiDocContext) { "swagger/doc.html" }
@@ -323,10 +437,10 @@ register_templates! {
     pub fn render_swagger_callback(ApiDocContext) { "swagger/oauth2-redirect.html" }
 
     /// Render the login page
-    pub fn render_login(WithLanguage<WithCsrf<LoginContext>>) { "pages/login.html" }
+    pub fn render_login(WithLanguage<WithCsrf<WithCspNonce<WithCaptcha<LoginContext>>>>) { "pages/login.html" }
 
     /// Render the registration page
-    pub fn render_register(WithLanguage<WithCsrf<WithCaptcha<RegisterContext>>>) { "pages/register.html" }
+    pub fn render_register(WithLanguage<WithCsrf<WithCspNonce<WithCaptcha<RegisterContext>>>>) { "pages/register.html" }
 
     /// Render the client consent page
     pub fn render_consent(WithLanguage<WithCsrf<WithSession<ConsentContext>>>) { "pages/consent.html" }
@@ -367,6 +481,9 @@ register_templates! {
     /// Render the re-authentication form
     pub fn render_reauth(WithLanguage<WithCsrf<WithSession<ReauthContext>>>) { "pages/reauth.html" }
 
+    /// Render the required password change form
+    pub fn render_password_change_required(WithLanguage<WithCsrf<WithSession<PasswordChangeRequiredContext>>>) { "pages/password_change_required.html" }
+
     /// Render the form used by the form_post response mode
     pub fn render_form_post<T: Serialize>(WithLanguage<FormPostContext<T>>) { "form_post.html" }
 
@@ -420,6 +537,7 @@ impl Templates {
         rng: &mut impl Rng,
     ) -> anyhow::Result<()> {
         check::render_not_found(self, now, rng)?;
+        check::render_oauth2_error(self, now, rng)?;
         check::render_app(self, now, rng)?;
         check::render_swagger(self, now, rng)?;
         check::render_swagger_callback(self, now, rng)?;
@@ -438,6 +556,7 @@ impl Templates {
         check::render_recovery_consumed(self, now, rng)?;
         check::render_recovery_disabled(self, now, rng)?;
         check::render_reauth(self, now, rng)?;
+        check::render_password_change_required(self, now, rng)?;
         check::render_form_post::<EmptyContext>(self, now, rng)?;
         check::render_error(self, now, rng)?;
         check::render_email_verification_txt(self, now, rng)?;
@@ -468,6 +587,9 @@ mod tests {
             password_login: true,
             password_registration: true,
             account_recovery: true,
+            certificate_login: true,
+            matrix_id_login: true,
+            flags: mas_data_model::FeatureFlags::default(),
         };
         let vite_manifest_path =
             Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../frontend/dist/manifest.json");
@@ -478,6 +600,7 @@ mod tests {
             url_builder,
             vite_manifest_path,
             translations_path,
+            None,
             branding,
             features,
         )
@@ -57,7 +57,7 @@ macro_rules! register_templates {
                     let ctx = ::minijinja::value::Value::from_serialize(context);
 
                     let env = self.environment.load();
-                    let tmpl = env.get_template($template)
+                    let tmpl = pick_template(&env, $template, &ctx)
                         .map_err(|source| TemplateError::Missing { template: $template, source })?;
                     tmpl.render(ctx)
                         .map_err(|source| TemplateError::Render { template: $template, source })
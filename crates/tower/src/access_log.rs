@@ -0,0 +1,348 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A structured access log, writing one JSON object per request.
+//!
+//! This is separate from the tracing spans, and is meant for operators who
+//! ship logs rather than traces.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    io::Write,
+    sync::{Arc, Mutex},
+    task::ready,
+    time::Instant,
+};
+
+use http::{header::USER_AGENT, Extensions, Request, Response};
+use pin_project_lite::pin_project;
+use rand::Rng;
+use serde::Serialize;
+use tower::{Layer, Service};
+
+/// The value which replaces redacted query parameters
+const REDACTED: &str = "[REDACTED]";
+
+/// Per-request context, inserted in the request extensions by the
+/// [`AccessLogService`], which handlers can use to attach the authenticated
+/// user and session to the access log entry.
+#[derive(Clone, Debug, Default)]
+pub struct AccessLogContext {
+    inner: Arc<Mutex<Identity>>,
+}
+
+#[derive(Debug, Default)]
+struct Identity {
+    user_id: Option<String>,
+    session_id: Option<String>,
+}
+
+impl AccessLogContext {
+    /// Set the ID of the user who made the request
+    pub fn set_user_id(&self, user_id: &impl ToString) {
+        if let Ok(mut identity) = self.inner.lock() {
+            identity.user_id = Some(user_id.to_string());
+        }
+    }
+
+    /// Set the ID of the session used to make the request
+    pub fn set_session_id(&self, session_id: &impl ToString) {
+        if let Ok(mut identity) = self.inner.lock() {
+            identity.session_id = Some(session_id.to_string());
+        }
+    }
+
+    fn take(&self) -> Identity {
+        self.inner
+            .lock()
+            .map(|mut identity| std::mem::take(&mut *identity))
+            .unwrap_or_default()
+    }
+}
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A [`Layer`] which writes a structured access log entry for each request.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    writer: SharedWriter,
+    listener: Option<Arc<str>>,
+    redacted_query_parameters: Arc<HashSet<String>>,
+    sample_ratio: f64,
+    route: fn(&Extensions) -> Option<String>,
+}
+
+impl std::fmt::Debug for AccessLogLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLogLayer")
+            .field("listener", &self.listener)
+            .field("redacted_query_parameters", &self.redacted_query_parameters)
+            .field("sample_ratio", &self.sample_ratio)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AccessLogLayer {
+    /// Create a new [`AccessLogLayer`] writing entries to the given writer.
+    ///
+    /// All requests are logged and no query parameter is redacted by default.
+    #[must_use]
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            listener: None,
+            redacted_query_parameters: Arc::new(HashSet::new()),
+            sample_ratio: 1.0,
+            route: |_| None,
+        }
+    }
+
+    /// Set the name of the listener, added to each entry
+    #[must_use]
+    pub fn with_listener_name(mut self, name: Option<&str>) -> Self {
+        self.listener = name.map(Arc::from);
+        self
+    }
+
+    /// Set the query parameters whose values should not end up in the logs,
+    /// like tokens and authorization codes
+    #[must_use]
+    pub fn with_redacted_query_parameters<I>(mut self, parameters: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.redacted_query_parameters = Arc::new(parameters.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Only log a fraction of the successful requests. Requests which resulted
+    /// in a client or server error are always logged.
+    #[must_use]
+    pub fn with_sample_ratio(mut self, ratio: f64) -> Self {
+        self.sample_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the function used to extract the route template from the request
+    /// extensions
+    #[must_use]
+    pub fn with_route_fn(mut self, route: fn(&Extensions) -> Option<String>) -> Self {
+        self.route = route;
+        self
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A middleware which writes a structured access log entry for each request.
+#[derive(Clone, Debug)]
+pub struct AccessLogService<S> {
+    inner: S,
+    layer: AccessLogLayer,
+}
+
+/// Informations about the request, gathered before calling the inner service
+struct PendingEntry {
+    start: Instant,
+    method: String,
+    route: Option<String>,
+    path: String,
+    query: Option<String>,
+    protocol: &'static str,
+    user_agent: Option<String>,
+    context: AccessLogContext,
+}
+
+#[derive(Serialize)]
+struct AccessLogEntry<'a> {
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listener: Option<&'a str>,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route: Option<&'a str>,
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<&'a str>,
+    protocol: &'static str,
+    status: u16,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<&'a str>,
+}
+
+/// Replace the values of the redacted parameters in a query string
+fn redact_query(query: &str, redacted: &HashSet<String>) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if redacted.contains(key) => format!("{key}={REDACTED}"),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn protocol<B>(request: &Request<B>) -> &'static str {
+    match request.version() {
+        http::Version::HTTP_09 => "HTTP/0.9",
+        http::Version::HTTP_10 => "HTTP/1.0",
+        http::Version::HTTP_11 => "HTTP/1.1",
+        http::Version::HTTP_2 => "HTTP/2.0",
+        http::Version::HTTP_3 => "HTTP/3.0",
+        _ => "_OTHER",
+    }
+}
+
+impl AccessLogLayer {
+    fn write<B>(&self, entry: &PendingEntry, response: &Response<B>) {
+        let status = response.status();
+
+        // Sample successful requests
+        if !status.is_client_error() && !status.is_server_error() && self.sample_ratio < 1.0 {
+            // The sampling decision doesn't need to be reproducible
+            #[allow(clippy::disallowed_methods)]
+            let keep = rand::thread_rng().gen_bool(self.sample_ratio);
+            if !keep {
+                return;
+            }
+        }
+
+        let identity = entry.context.take();
+
+        // The access log is about wall-clock time, not the application clock
+        #[allow(clippy::disallowed_methods)]
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+
+        let record = AccessLogEntry {
+            timestamp,
+            listener: self.listener.as_deref(),
+            method: &entry.method,
+            route: entry.route.as_deref(),
+            path: &entry.path,
+            query: entry.query.as_deref(),
+            protocol: entry.protocol,
+            status: status.as_u16(),
+            latency_ms: entry.start.elapsed().as_secs_f64() * 1000.0,
+            user_agent: entry.user_agent.as_deref(),
+            user_id: identity.user_id.as_deref(),
+            session_id: identity.session_id.as_deref(),
+        };
+
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Failed to serialize access log entry"
+                );
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let Ok(mut writer) = self.writer.lock() else {
+            return;
+        };
+
+        if let Err(e) = writer.write_all(&line).and_then(|()| writer.flush()) {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Failed to write access log entry"
+            );
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`AccessLogService`]
+    pub struct AccessLogFuture<F> {
+        #[pin]
+        inner: F,
+        layer: AccessLogLayer,
+        entry: Option<PendingEntry>,
+    }
+}
+
+impl<F, ResBody, E> Future for AccessLogFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+
+        if let (Ok(response), Some(entry)) = (&result, this.entry.take()) {
+            this.layer.write(&entry, response);
+        }
+
+        std::task::Poll::Ready(result)
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessLogService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = AccessLogFuture<S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let context = AccessLogContext::default();
+        req.extensions_mut().insert(context.clone());
+
+        let entry = PendingEntry {
+            start: Instant::now(),
+            method: req.method().to_string(),
+            route: (self.layer.route)(req.extensions()),
+            path: req.uri().path().to_owned(),
+            query: req
+                .uri()
+                .query()
+                .map(|query| redact_query(query, &self.layer.redacted_query_parameters)),
+            protocol: protocol(&req),
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned),
+            context,
+        };
+
+        AccessLogFuture {
+            inner: self.inner.call(req),
+            layer: self.layer.clone(),
+            entry: Some(entry),
+        }
+    }
+}
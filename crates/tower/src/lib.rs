@@ -6,12 +6,13 @@
 
 #![allow(clippy::module_name_repetitions)]
 
+mod access_log;
 mod metrics;
 mod trace_context;
 mod tracing;
 mod utils;
 
-pub use self::{metrics::*, trace_context::*, tracing::*, utils::*};
+pub use self::{access_log::*, metrics::*, trace_context::*, tracing::*, utils::*};
 
 fn meter() -> opentelemetry::metrics::Meter {
     opentelemetry::global::meter_with_version(
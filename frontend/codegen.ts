@@ -23,6 +23,7 @@ const config: CodegenConfig = {
         maybeValue: "T | null | undefined",
         scalars: {
           DateTime: "string",
+          NaiveDate: "string",
           Url: "string",
         },
       },
@@ -164,7 +164,8 @@ const UserGreeting: React.FC<Props> = ({ user, siteConfig }) => {
               <Form.Field
                 name="displayname"
                 serverInvalid={
-                  setDisplayName.data?.setDisplayName.status === "INVALID"
+                  setDisplayName.data?.setDisplayName.status === "INVALID" ||
+                  setDisplayName.data?.setDisplayName.status === "LOCKED"
                 }
               >
                 <Form.Label>
@@ -189,6 +190,12 @@ const UserGreeting: React.FC<Props> = ({ user, siteConfig }) => {
                 <Form.HelpMessage>
                   {t("frontend.account.edit_profile.display_name_help")}
                 </Form.HelpMessage>
+
+                {setDisplayName.data?.setDisplayName.status === "LOCKED" && (
+                  <Form.ErrorMessage>
+                    {t("frontend.account.edit_profile.display_name_locked")}
+                  </Form.ErrorMessage>
+                )}
               </Form.Field>
 
               <Form.Field name="mxid">
@@ -21,10 +21,34 @@ export type Scalars = {
    * The input/output is a string in RFC3339 format.
    */
   DateTime: { input: string; output: string; }
+  /**
+   * ISO 8601 calendar date without timezone.
+   * Format: %Y-%m-%d
+   *
+   * # Examples
+   *
+   * * `1994-11-13`
+   * * `2000-02-24`
+   */
+  NaiveDate: { input: string; output: string; }
+  Upload: { input: any; output: any; }
   /** URL is a String implementing the [URL Standard](http://url.spec.whatwg.org/) */
   Url: { input: string; output: string; }
 };
 
+/** The number of sessions which are currently active. */
+export type ActiveSessionCounts = {
+  __typename?: 'ActiveSessionCounts';
+  /** The number of active browser sessions. */
+  browser: Scalars['Int']['output'];
+  /** The number of active compatibility sessions. */
+  compat: Scalars['Int']['output'];
+  /** The number of active OAuth 2.0 sessions. */
+  oauth2: Scalars['Int']['output'];
+  /** The total number of active sessions. */
+  total: Scalars['Int']['output'];
+};
+
 /** The input for the `addEmail` mutation */
 export type AddEmailInput = {
   /** The email address to add */
@@ -104,10 +128,29 @@ export type AllowUserCrossSigningResetInput = {
 /** The payload for the `allowUserCrossSigningReset` mutation. */
 export type AllowUserCrossSigningResetPayload = {
   __typename?: 'AllowUserCrossSigningResetPayload';
+  /**
+   * Until when the user can reset their cross-signing keys, if the
+   * homeserver told it.
+   */
+  allowedUntil?: Maybe<Scalars['DateTime']['output']>;
+  /** Status of the operation */
+  status: AllowUserCrossSigningResetStatus;
   /** The user that was updated. */
   user?: Maybe<User>;
 };
 
+/** The status of the `allowUserCrossSigningReset` mutation. */
+export type AllowUserCrossSigningResetStatus =
+  /** The user can reset their cross-signing keys. */
+  | 'ALLOWED'
+  /** The user was not found. */
+  | 'NOT_FOUND'
+  /**
+   * The user must authenticate again before resetting their cross-signing
+   * keys.
+   */
+  | 'REAUTHENTICATION_REQUIRED';
+
 export type Anonymous = Node & {
   __typename?: 'Anonymous';
   id: Scalars['ID']['output'];
@@ -143,6 +186,11 @@ export type AppSessionEdge = {
  */
 export type Authentication = CreationEvent & Node & {
   __typename?: 'Authentication';
+  /**
+   * The method used for this authentication, as an authentication method
+   * reference from RFC 8176, like `pwd`.
+   */
+  amr?: Maybe<Scalars['String']['output']>;
   /** When the object was created. */
   createdAt: Scalars['DateTime']['output'];
   /** ID of the object. */
@@ -152,6 +200,13 @@ export type Authentication = CreationEvent & Node & {
 /** A browser session represents a logged in user in a browser. */
 export type BrowserSession = CreationEvent & Node & {
   __typename?: 'BrowserSession';
+  /**
+   * The methods which authenticated this session, as authentication method
+   * references from RFC 8176, like `pwd`.
+   *
+   * Those are the same as the ones in the `amr` claim of the ID tokens.
+   */
+  amr: Array<Scalars['String']['output']>;
   /**
    * Get the list of both compat and OAuth 2.0 sessions started by this
    * browser session, chronologically sorted
@@ -224,6 +279,35 @@ export type CaptchaService =
   | 'H_CAPTCHA'
   | 'RECAPTCHA_V2';
 
+/** The number of access tokens issued to an OAuth 2.0 client. */
+export type ClientAccessTokenCount = {
+  __typename?: 'ClientAccessTokenCount';
+  /** The OAuth 2.0 client. */
+  client: Oauth2Client;
+  /** The number of access tokens issued to this client. */
+  count: Scalars['Int']['output'];
+};
+
+/** The kind of application which created a session */
+export type ClientApp =
+  /**
+   * A web browser. This includes Element Web, which can't be told apart
+   * from the browser it runs in.
+   */
+  | 'BROWSER'
+  /** Element Android */
+  | 'ELEMENT_ANDROID'
+  /** Element Desktop, the Electron application */
+  | 'ELEMENT_DESKTOP'
+  /** Element iOS */
+  | 'ELEMENT_IOS'
+  /** Element X, on any platform */
+  | 'ELEMENT_X'
+  /** Another native application */
+  | 'OTHER_APP'
+  /** The application could not be detected */
+  | 'UNKNOWN';
+
 /**
  * A compat session represents a client session which used the legacy Matrix
  * login API.
@@ -238,6 +322,11 @@ export type CompatSession = CreationEvent & Node & {
   deviceId: Scalars['String']['output'];
   /** When the session ended. */
   finishedAt?: Maybe<Scalars['DateTime']['output']>;
+  /**
+   * The name of the session, as set by the user, or derived from the
+   * user-agent, like "Firefox on Linux".
+   */
+  humanName?: Maybe<Scalars['String']['output']>;
   /** ID of the object. */
   id: Scalars['ID']['output'];
   /** The last time the session was active. */
@@ -353,6 +442,117 @@ export type CreationEvent = {
   createdAt: Scalars['DateTime']['output'];
 };
 
+/** A number of events which happened on a given day. */
+export type DailyCount = {
+  __typename?: 'DailyCount';
+  /** The number of events on that day. */
+  count: Scalars['Int']['output'];
+  /** The day, in UTC. */
+  day: Scalars['NaiveDate']['output'];
+};
+
+/** A number of browser logins with a given method on a given day. */
+export type DailyLoginCount = {
+  __typename?: 'DailyLoginCount';
+  /** The number of logins on that day with that method. */
+  count: Scalars['Int']['output'];
+  /** The day, in UTC. */
+  day: Scalars['NaiveDate']['output'];
+  /** The authentication method used. */
+  method: LoginMethod;
+};
+
+/** The number of failed and successful password logins on a given day. */
+export type DailyLoginFailures = {
+  __typename?: 'DailyLoginFailures';
+  /** The day, in UTC. */
+  day: Scalars['NaiveDate']['output'];
+  /** The number of failed password logins. */
+  failed: Scalars['Int']['output'];
+  /**
+   * The ratio of failed password logins over all password login
+   * attempts, between 0 and 1.
+   */
+  failureRate: Scalars['Float']['output'];
+  /** The number of successful password logins. */
+  succeeded: Scalars['Int']['output'];
+};
+
+/**
+ * Aggregate statistics about the service, for the admin dashboard.
+ *
+ * Those are computed periodically in the background, so they may be a few
+ * minutes out of date.
+ */
+export type Dashboard = {
+  __typename?: 'Dashboard';
+  /** Number of sessions which are currently active. */
+  activeSessions: ActiveSessionCounts;
+  /** Number of browser logins per day and per authentication method. */
+  loginsPerDay: Array<DailyLoginCount>;
+  /**
+   * Number of failed and successful password logins per day, along with
+   * the rate of failures. Days without any password login attempt are
+   * omitted.
+   */
+  passwordLoginFailuresPerDay: Array<DailyLoginFailures>;
+  /**
+   * Number of users registered per day. Days without any registration are
+   * omitted.
+   */
+  registrationsPerDay: Array<DailyCount>;
+  /**
+   * OAuth 2.0 clients which were issued the most access tokens, in
+   * descending order.
+   */
+  topClientsByAccessTokens: Array<ClientAccessTokenCount>;
+};
+
+
+/**
+ * Aggregate statistics about the service, for the admin dashboard.
+ *
+ * Those are computed periodically in the background, so they may be a few
+ * minutes out of date.
+ */
+export type DashboardLoginsPerDayArgs = {
+  days?: InputMaybe<Scalars['Int']['input']>;
+};
+
+
+/**
+ * Aggregate statistics about the service, for the admin dashboard.
+ *
+ * Those are computed periodically in the background, so they may be a few
+ * minutes out of date.
+ */
+export type DashboardPasswordLoginFailuresPerDayArgs = {
+  days?: InputMaybe<Scalars['Int']['input']>;
+};
+
+
+/**
+ * Aggregate statistics about the service, for the admin dashboard.
+ *
+ * Those are computed periodically in the background, so they may be a few
+ * minutes out of date.
+ */
+export type DashboardRegistrationsPerDayArgs = {
+  days?: InputMaybe<Scalars['Int']['input']>;
+};
+
+
+/**
+ * Aggregate statistics about the service, for the admin dashboard.
+ *
+ * Those are computed periodically in the background, so they may be a few
+ * minutes out of date.
+ */
+export type DashboardTopClientsByAccessTokensArgs = {
+  days?: InputMaybe<Scalars['Int']['input']>;
+  first?: InputMaybe<Scalars['Int']['input']>;
+};
+
 /** A filter for dates, with a lower bound and an upper bound */
 export type DateFilter = {
   /** The lower bound of the date range */
@@ -361,6 +561,30 @@ export type DateFilter = {
   before?: InputMaybe<Scalars['DateTime']['input']>;
 };
 
+/** The input for the `deleteDevice` mutation */
+export type DeleteDeviceInput = {
+  /** The ID of the Matrix device to delete */
+  deviceId: Scalars['String']['input'];
+  /** The ID of the user owning the device */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload of the `deleteDevice` mutation */
+export type DeleteDevicePayload = {
+  __typename?: 'DeleteDevicePayload';
+  /** Status of the operation */
+  status: DeleteDeviceStatus;
+  /** The user owning the device */
+  user?: Maybe<User>;
+};
+
+/** The status of the `deleteDevice` mutation */
+export type DeleteDeviceStatus =
+  /** The device was deleted, and the sessions using it were ended */
+  | 'DELETED'
+  /** The device was not found */
+  | 'NOT_FOUND';
+
 /** The type of a user agent */
 export type DeviceType =
   /** A mobile phone. Can also sometimes be a tablet. */
@@ -459,6 +683,65 @@ export type LockUserStatus =
   /** The user was not found. */
   | 'NOT_FOUND';
 
+/**
+ * An attempt of a user to log in, successful or not, kept for a limited time
+ * so that the user can review the recent activity on their account.
+ */
+export type LoginAttempt = CreationEvent & Node & {
+  __typename?: 'LoginAttempt';
+  /** When the object was created. */
+  createdAt: Scalars['DateTime']['output'];
+  /** ID of the object. */
+  id: Scalars['ID']['output'];
+  /** The IP address the attempt came from, if known. */
+  ipAddress?: Maybe<Scalars['String']['output']>;
+  /** How the user tried to log in. */
+  method: LoginAttemptMethod;
+  /** Whether the attempt succeeded. */
+  succeeded: Scalars['Boolean']['output'];
+  /** The user-agent of the device the attempt came from, if known. */
+  userAgent?: Maybe<UserAgent>;
+};
+
+export type LoginAttemptConnection = {
+  __typename?: 'LoginAttemptConnection';
+  /** A list of edges. */
+  edges: Array<LoginAttemptEdge>;
+  /** A list of nodes. */
+  nodes: Array<LoginAttempt>;
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** Identifies the total count of items in the connection. */
+  totalCount: Scalars['Int']['output'];
+};
+
+/** An edge in a connection. */
+export type LoginAttemptEdge = {
+  __typename?: 'LoginAttemptEdge';
+  /** A cursor for use in pagination */
+  cursor: Scalars['String']['output'];
+  /** The item at the end of the edge */
+  node: LoginAttempt;
+};
+
+/** How a user tried to log in. */
+export type LoginAttemptMethod =
+  /** With a client certificate. */
+  | 'CLIENT_CERTIFICATE'
+  /** With their username and password. */
+  | 'PASSWORD'
+  /** Through an upstream OAuth 2.0 provider. */
+  | 'UPSTREAM_OAUTH2';
+
+/** The method used to authenticate a browser session. */
+export type LoginMethod =
+  /** Any other authentication method. */
+  | 'OTHER'
+  /** Authenticated with a password. */
+  | 'PASSWORD'
+  /** Authenticated through an upstream OAuth 2.0 provider. */
+  | 'UPSTREAM_OAUTH';
+
 export type MatrixUser = {
   __typename?: 'MatrixUser';
   /** The avatar URL of the user, if any. */
@@ -478,7 +761,14 @@ export type Mutation = {
   addEmail: AddEmailPayload;
   /** Add a user. This is only available to administrators. */
   addUser: AddUserPayload;
-  /** Temporarily allow user to reset their cross-signing keys. */
+  /**
+   * Temporarily allow user to reset their cross-signing keys.
+   *
+   * Users with a password must have authenticated in the last few minutes
+   * with their browser session to reset their own keys, like the homeserver
+   * would require through user-interactive authentication. This is not
+   * required from administrators.
+   */
   allowUserCrossSigningReset: AllowUserCrossSigningResetPayload;
   /**
    * Create a new arbitrary OAuth 2.0 Session.
@@ -486,6 +776,13 @@ export type Mutation = {
    * Only available for administrators.
    */
   createOauth2Session: CreateOAuth2SessionPayload;
+  /**
+   * Delete a Matrix device of a user
+   *
+   * This ends the sessions using the device, which invalidates their
+   * tokens, and deletes the device on the homeserver.
+   */
+  deleteDevice: DeleteDevicePayload;
   endBrowserSession: EndBrowserSessionPayload;
   endCompatSession: EndCompatSessionPayload;
   endOauth2Session: EndOAuth2SessionPayload;
@@ -493,30 +790,72 @@ export type Mutation = {
   lockUser: LockUserPayload;
   /** Remove an email address */
   removeEmail: RemoveEmailPayload;
+  /**
+   * Rename a Matrix device of a user
+   *
+   * Only devices used by an active session of the user can be renamed.
+   */
+  renameDevice: RenameDevicePayload;
+  /**
+   * Reset the password of a user, either by setting a temporary password or
+   * by sending them a recovery link, and require them to choose a new
+   * password on their next login.
+   *
+   * This is only available to administrators and the helpdesk, which can't
+   * reset the password of users who can request admin.
+   */
+  resetPassword: ResetPasswordPayload;
+  /**
+   * Provision a user again on the homeserver, syncing their profile,
+   * devices and email addresses. This is useful when the homeserver and MAS
+   * drifted apart, for example after an outage. This is only available to
+   * administrators.
+   */
+  resyncUser: ResyncUserPayload;
   /** Send a verification code for an email address */
   sendVerificationEmail: SendVerificationEmailPayload;
+  /**
+   * Set the avatar of a user, from media already uploaded to the
+   * homeserver
+   */
+  setAvatar: SetAvatarPayload;
   /**
    * Set whether a user can request admin. This is only available to
    * administrators.
    */
   setCanRequestAdmin: SetCanRequestAdminPayload;
+  /** Give a name to a compatibility session, like "Work laptop". */
+  setCompatSessionName: SetCompatSessionNamePayload;
   /** Set the display name of a user */
   setDisplayName: SetDisplayNamePayload;
+  /** Give a name to an OAuth 2.0 session, like "Work laptop". */
+  setOauth2SessionName: SetOAuth2SessionNamePayload;
   /**
    * Set the password for a user.
    *
    * This can be used by server administrators to set any user's password,
-   * or, provided the capability hasn't been disabled on this server,
-   * by a user to change their own password as long as they know their
-   * current password.
+   * by the helpdesk to set the password of users who can't request admin,
+   * or, provided the capability hasn't been disabled on this server, by a
+   * user to change their own password as long as they know their current
+   * password.
    */
   setPassword: SetPasswordPayload;
   /** Set the password for yourself, using a recovery ticket sent by e-mail. */
   setPasswordByRecovery: SetPasswordPayload;
   /** Set an email address as primary */
   setPrimaryEmail: SetPrimaryEmailPayload;
-  /** Unlock a user. This is only available to administrators. */
+  /**
+   * Unlock a user. This is only available to administrators and the
+   * helpdesk.
+   */
   unlockUser: UnlockUserPayload;
+  /**
+   * Upload an image to the homeserver and set it as the avatar of a user
+   *
+   * The image is scaled down to 512 pixels at most, and converted to PNG.
+   * The avatar is only changed once the image was uploaded.
+   */
+  uploadAvatar: UploadAvatarPayload;
   /** Submit a verification code for an email address */
   verifyEmail: VerifyEmailPayload;
 };
@@ -546,6 +885,12 @@ export type MutationCreateOauth2SessionArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationDeleteDeviceArgs = {
+  input: DeleteDeviceInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationEndBrowserSessionArgs = {
   input: EndBrowserSessionInput;
@@ -576,24 +921,60 @@ export type MutationRemoveEmailArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationRenameDeviceArgs = {
+  input: RenameDeviceInput;
+};
+
+
+/** The mutations root of the GraphQL interface. */
+export type MutationResetPasswordArgs = {
+  input: ResetPasswordInput;
+};
+
+
+/** The mutations root of the GraphQL interface. */
+export type MutationResyncUserArgs = {
+  input: ResyncUserInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSendVerificationEmailArgs = {
   input: SendVerificationEmailInput;
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationSetAvatarArgs = {
+  input: SetAvatarInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSetCanRequestAdminArgs = {
   input: SetCanRequestAdminInput;
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationSetCompatSessionNameArgs = {
+  input: SetCompatSessionNameInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSetDisplayNameArgs = {
   input: SetDisplayNameInput;
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationSetOauth2SessionNameArgs = {
+  input: SetOAuth2SessionNameInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSetPasswordArgs = {
   input: SetPasswordInput;
@@ -618,6 +999,12 @@ export type MutationUnlockUserArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationUploadAvatarArgs = {
+  input: UploadAvatarInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationVerifyEmailArgs = {
   input: VerifyEmailInput;
@@ -673,6 +1060,11 @@ export type Oauth2Session = CreationEvent & Node & {
   createdAt: Scalars['DateTime']['output'];
   /** When the session ended. */
   finishedAt?: Maybe<Scalars['DateTime']['output']>;
+  /**
+   * The name of the session, as set by the user, or derived from the
+   * user-agent, like "Firefox on Linux".
+   */
+  humanName?: Maybe<Scalars['String']['output']>;
   /** ID of the object. */
   id: Scalars['ID']['output'];
   /** The last time the session was active. */
@@ -740,12 +1132,30 @@ export type Query = {
    * @deprecated Use `viewer` instead.
    */
   currentUser?: Maybe<User>;
+  /**
+   * Get aggregate statistics about the service.
+   *
+   * This is only available to administrators.
+   */
+  dashboard: Dashboard;
   /** Fetches an object given its ID. */
   node?: Maybe<Node>;
   /** Fetch an OAuth 2.0 client by its ID. */
   oauth2Client?: Maybe<Oauth2Client>;
   /** Fetch an OAuth 2.0 session by its ID. */
   oauth2Session?: Maybe<Oauth2Session>;
+  /**
+   * Fetch a queued email by its ID.
+   *
+   * This is only available to administrators.
+   */
+  queuedEmail?: Maybe<QueuedEmail>;
+  /**
+   * Get a list of the emails in the outgoing queue.
+   *
+   * This is only available to administrators.
+   */
+  queuedEmails: QueuedEmailConnection;
   /** Lookup a compat or OAuth 2.0 session */
   session?: Maybe<Session>;
   /** Get the current site configuration */
@@ -765,7 +1175,7 @@ export type Query = {
   /**
    * Get a list of users.
    *
-   * This is only available to administrators.
+   * This is only available to administrators and the helpdesk.
    */
   users: UserConnection;
   /** Get the viewer */
@@ -805,6 +1215,23 @@ export type QueryOauth2SessionArgs = {
 };
 
 
+/** The query root of the GraphQL interface. */
+export type QueryQueuedEmailArgs = {
+  id: Scalars['ID']['input'];
+};
+
+
+/** The query root of the GraphQL interface. */
+export type QueryQueuedEmailsArgs = {
+  after?: InputMaybe<Scalars['String']['input']>;
+  before?: InputMaybe<Scalars['String']['input']>;
+  first?: InputMaybe<Scalars['Int']['input']>;
+  last?: InputMaybe<Scalars['Int']['input']>;
+  recipient?: InputMaybe<Scalars['String']['input']>;
+  state?: InputMaybe<QueuedEmailState>;
+};
+
+
 /** The query root of the GraphQL interface. */
 export type QuerySessionArgs = {
   deviceId: Scalars['String']['input'];
@@ -861,6 +1288,59 @@ export type QueryUsersArgs = {
   state?: InputMaybe<UserState>;
 };
 
+/** An email in the outgoing queue */
+export type QueuedEmail = CreationEvent & Node & {
+  __typename?: 'QueuedEmail';
+  /** The number of failed attempts so far. */
+  attempts: Scalars['Int']['output'];
+  /** When the object was created. */
+  createdAt: Scalars['DateTime']['output'];
+  /** When the email was moved to the dead-letter list. */
+  failedAt?: Maybe<Scalars['DateTime']['output']>;
+  /** ID of the object. */
+  id: Scalars['ID']['output'];
+  /** The error of the last failed attempt. */
+  lastError?: Maybe<Scalars['String']['output']>;
+  /** When the next attempt should happen. */
+  nextAttemptAt: Scalars['DateTime']['output'];
+  /** The address the email is sent to. */
+  recipient: Scalars['String']['output'];
+  /** When the email was sent. */
+  sentAt?: Maybe<Scalars['DateTime']['output']>;
+  /** The delivery state of the email. */
+  state: QueuedEmailState;
+};
+
+export type QueuedEmailConnection = {
+  __typename?: 'QueuedEmailConnection';
+  /** A list of edges. */
+  edges: Array<QueuedEmailEdge>;
+  /** A list of nodes. */
+  nodes: Array<QueuedEmail>;
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** Identifies the total count of items in the connection. */
+  totalCount: Scalars['Int']['output'];
+};
+
+/** An edge in a connection. */
+export type QueuedEmailEdge = {
+  __typename?: 'QueuedEmailEdge';
+  /** A cursor for use in pagination */
+  cursor: Scalars['String']['output'];
+  /** The item at the end of the edge */
+  node: QueuedEmail;
+};
+
+/** The delivery state of a queued email. */
+export type QueuedEmailState =
+  /** Too many attempts failed, and the email was moved to the dead-letter list. */
+  | 'FAILED'
+  /** The email is waiting to be sent. */
+  | 'PENDING'
+  /** The email was sent. */
+  | 'SENT';
+
 /** The input for the `removeEmail` mutation */
 export type RemoveEmailInput = {
   /** The ID of the email address to remove */
@@ -887,6 +1367,173 @@ export type RemoveEmailStatus =
   /** The email address was removed */
   | 'REMOVED';
 
+/** The input for the `renameDevice` mutation */
+export type RenameDeviceInput = {
+  /** The ID of the Matrix device to rename */
+  deviceId: Scalars['String']['input'];
+  /** The new display name of the device */
+  displayName: Scalars['String']['input'];
+  /** The ID of the user owning the device */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload of the `renameDevice` mutation */
+export type RenameDevicePayload = {
+  __typename?: 'RenameDevicePayload';
+  /** Status of the operation */
+  status: RenameDeviceStatus;
+  /** The user owning the device */
+  user?: Maybe<User>;
+};
+
+/** The status of the `renameDevice` mutation */
+export type RenameDeviceStatus =
+  /** The display name is invalid */
+  | 'INVALID'
+  /** The device was not found */
+  | 'NOT_FOUND'
+  /** The device was renamed */
+  | 'RENAMED';
+
+/** The input for the `resetPassword` mutation. */
+export type ResetPasswordInput = {
+  /**
+   * A temporary password to set on the account.
+   * If not set, a recovery link is sent to the primary email address of
+   * the user instead.
+   */
+  temporaryPassword?: InputMaybe<Scalars['String']['input']>;
+  /** The ID of the user to reset the password of. */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload for the `resetPassword` mutation. */
+export type ResetPasswordPayload = {
+  __typename?: 'ResetPasswordPayload';
+  /** Status of the operation */
+  status: ResetPasswordStatus;
+  /** The user whose password was reset. */
+  user?: Maybe<User>;
+};
+
+/** The status of the `resetPassword` mutation. */
+export type ResetPasswordStatus =
+  /**
+   * The temporary password is invalid. For example, it may not meet
+   * configured security requirements.
+   */
+  | 'INVALID_NEW_PASSWORD'
+  /** The user has no primary email address to send a recovery link to. */
+  | 'NO_EMAIL'
+  /** The user was not found. */
+  | 'NOT_FOUND'
+  /**
+   * Password support has been disabled.
+   * This usually means that login is handled by an upstream identity
+   * provider.
+   */
+  | 'PASSWORD_CHANGES_DISABLED'
+  /** A recovery link was sent to the user. */
+  | 'RECOVERY_LINK_SENT'
+  /** The temporary password was set. */
+  | 'TEMPORARY_PASSWORD_SET';
+
+/** The input for the `resyncUser` mutation */
+export type ResyncUserInput = {
+  /** The ID of the user to provision again on the homeserver */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload of the `resyncUser` mutation */
+export type ResyncUserPayload = {
+  __typename?: 'ResyncUserPayload';
+  /** Whether the user was missing on the homeserver, and will be created */
+  createdOnHomeserver?: Maybe<Scalars['Boolean']['output']>;
+  /**
+   * The confirmed email addresses of the user, which replace the ones on
+   * the homeserver
+   */
+  emails?: Maybe<Array<Scalars['String']['output']>>;
+  /**
+   * Whether the user was deactivated on the homeserver but not locked in
+   * MAS, and will be reactivated
+   */
+  reactivatedOnHomeserver?: Maybe<Scalars['Boolean']['output']>;
+  /** Status of the operation */
+  status: ResyncUserStatus;
+  /** The user provisioned again */
+  user?: Maybe<User>;
+};
+
+/** The status of the `resyncUser` mutation */
+export type ResyncUserStatus =
+  /** The user was not found */
+  | 'NOT_FOUND'
+  /** Provisioning the user again was scheduled */
+  | 'SCHEDULED';
+
+/**
+ * An overview of the security of a user account, to render a security
+ * checkup page.
+ */
+export type SecurityCheckup = {
+  __typename?: 'SecurityCheckup';
+  /**
+   * Whether the user can recover their account by email if they lose their
+   * password.
+   */
+  accountRecoveryEnabled: Scalars['Boolean']['output'];
+  /**
+   * Active OAuth 2.0 sessions with a broad access to the account, like the
+   * full Matrix client API.
+   */
+  broadScopeOauth2Sessions: Array<Oauth2Session>;
+  /** Things the user should look at to secure their account. */
+  issues: Array<SecurityCheckupIssue>;
+  /**
+   * Whether a second authentication factor is set up.
+   *
+   * Second factors aren't supported yet, so this is always `false`.
+   */
+  mfaEnabled: Scalars['Boolean']['output'];
+  /** How many days ago the current password was set, if the user has one. */
+  passwordAgeDays?: Maybe<Scalars['Int']['output']>;
+  /** Whether the user has to change their password on their next login. */
+  passwordChangeRequired: Scalars['Boolean']['output'];
+  /** When the current password was set, if the user has one. */
+  passwordChangedAt?: Maybe<Scalars['DateTime']['output']>;
+  /**
+   * Browser sessions created in the last 30 days, from both an IP address
+   * and a browser which weren't used by earlier sessions.
+   */
+  unusualRecentSessions: Array<BrowserSession>;
+  /**
+   * Number of accounts on upstream identity providers linked to this
+   * account.
+   */
+  upstreamOauth2Links: Scalars['Int']['output'];
+  /**
+   * Number of verified email addresses, which can be used to recover the
+   * account.
+   */
+  verifiedEmails: Scalars['Int']['output'];
+};
+
+/** Something the user should look at to secure their account */
+export type SecurityCheckupIssue =
+  /** Some applications have a broad access to the account. */
+  | 'BROAD_SCOPE_APPLICATIONS'
+  /** There is no way to recover the account, like a verified email address. */
+  | 'NO_RECOVERY_OPTION'
+  /** No second authentication factor is set up. */
+  | 'NO_SECOND_FACTOR'
+  /** The password wasn't changed for more than a year. */
+  | 'OLD_PASSWORD'
+  /** An administrator reset the password, and it has to be changed. */
+  | 'PASSWORD_CHANGE_REQUIRED'
+  /** There were recent logins from an unusual location and device. */
+  | 'UNUSUAL_RECENT_LOGINS';
+
 /** The input for the `sendVerificationEmail` mutation */
 export type SendVerificationEmailInput = {
   /** The ID of the email address to verify */
@@ -908,6 +1555,8 @@ export type SendVerificationEmailPayload = {
 export type SendVerificationEmailStatus =
   /** The email address is already verified */
   | 'ALREADY_VERIFIED'
+  /** Too many verification emails were sent recently, try again later */
+  | 'RATE_LIMITED'
   /** The verification email was sent */
   | 'SENT';
 
@@ -921,6 +1570,33 @@ export type SessionState =
   /** The session is no longer active. */
   | 'FINISHED';
 
+/** The input for the `setAvatar` mutation */
+export type SetAvatarInput = {
+  /**
+   * The `mxc://` URL of the avatar to set. If `None`, the avatar will be
+   * removed.
+   */
+  avatarUrl?: InputMaybe<Scalars['String']['input']>;
+  /** The ID of the user to set the avatar of */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload of the `setAvatar` mutation */
+export type SetAvatarPayload = {
+  __typename?: 'SetAvatarPayload';
+  /** Status of the operation */
+  status: SetAvatarStatus;
+  /** The user that was updated */
+  user?: Maybe<User>;
+};
+
+/** The status of the `setAvatar` mutation */
+export type SetAvatarStatus =
+  /** The avatar URL is invalid */
+  | 'INVALID'
+  /** The avatar was set */
+  | 'SET';
+
 /** The input for the `setCanRequestAdmin` mutation. */
 export type SetCanRequestAdminInput = {
   /** Whether the user can request admin. */
@@ -936,6 +1612,36 @@ export type SetCanRequestAdminPayload = {
   user?: Maybe<User>;
 };
 
+/** The input of the `setCompatSessionName` mutation. */
+export type SetCompatSessionNameInput = {
+  /** The ID of the session to name. */
+  compatSessionId: Scalars['ID']['input'];
+  /**
+   * The name to give to the session, like "Work laptop". If `None` or
+   * empty, the name is removed, and a default one is derived from the
+   * user-agent.
+   */
+  humanName?: InputMaybe<Scalars['String']['input']>;
+};
+
+/** The payload of the `setCompatSessionName` mutation. */
+export type SetCompatSessionNamePayload = {
+  __typename?: 'SetCompatSessionNamePayload';
+  /** Returns the named session. */
+  compatSession?: Maybe<CompatSession>;
+  /** The status of the mutation. */
+  status: SetCompatSessionNameStatus;
+};
+
+/** The status of the `setCompatSessionName` mutation. */
+export type SetCompatSessionNameStatus =
+  /** The name is too long. */
+  | 'INVALID'
+  /** The session was not found. */
+  | 'NOT_FOUND'
+  /** The session was named. */
+  | 'UPDATED';
+
 /** The input for the `addEmail` mutation */
 export type SetDisplayNameInput = {
   /** The display name to set. If `None`, the display name will be removed. */
@@ -957,9 +1663,41 @@ export type SetDisplayNamePayload = {
 export type SetDisplayNameStatus =
   /** The display name is invalid */
   | 'INVALID'
+  /** The display name is locked to the one provided by an upstream provider */
+  | 'LOCKED'
   /** The display name was set */
   | 'SET';
 
+/** The input of the `setOauth2SessionName` mutation. */
+export type SetOAuth2SessionNameInput = {
+  /** The ID of the session to name. */
+  oauth2SessionId: Scalars['ID']['input'];
+  /**
+   * The name to give to the session, like "Work laptop". If `None` or
+   * empty, the name is removed, and a default one is derived from the
+   * user-agent.
+   */
+  humanName?: InputMaybe<Scalars['String']['input']>;
+};
+
+/** The payload of the `setOauth2SessionName` mutation. */
+export type SetOAuth2SessionNamePayload = {
+  __typename?: 'SetOAuth2SessionNamePayload';
+  /** Returns the named session. */
+  oauth2Session?: Maybe<Oauth2Session>;
+  /** The status of the mutation. */
+  status: SetOAuth2SessionNameStatus;
+};
+
+/** The status of the `setOauth2SessionName` mutation. */
+export type SetOAuth2SessionNameStatus =
+  /** The name is too long. */
+  | 'INVALID'
+  /** The session was not found. */
+  | 'NOT_FOUND'
+  /** The session was named. */
+  | 'UPDATED';
+
 /** The input for the `setPasswordByRecovery` mutation. */
 export type SetPasswordByRecoveryInput = {
   /** The new password for the user. */
@@ -992,6 +1730,11 @@ export type SetPasswordInput = {
 /** The return type for the `setPassword` mutation. */
 export type SetPasswordPayload = {
   __typename?: 'SetPasswordPayload';
+  /**
+   * Why the new password was rejected, if the status is
+   * `INVALID_NEW_PASSWORD`
+   */
+  rejectionReason?: Maybe<Scalars['String']['output']>;
   /** Status of the operation */
   status: SetPasswordStatus;
 };
@@ -1060,6 +1803,8 @@ export type SetPrimaryEmailStatus =
 
 export type SiteConfig = Node & {
   __typename?: 'SiteConfig';
+  /** Whether users can change their avatar. */
+  avatarChangeAllowed: Scalars['Boolean']['output'];
   /** The configuration of CAPTCHA provider. */
   captchaConfig?: Maybe<CaptchaConfig>;
   /** Whether users can change their display name. */
@@ -1112,6 +1857,34 @@ export type UnlockUserStatus =
   /** The user was unlocked. */
   | 'UNLOCKED';
 
+/** The input for the `uploadAvatar` mutation */
+export type UploadAvatarInput = {
+  /** The image to use as avatar */
+  file: Scalars['Upload']['input'];
+  /** The ID of the user to set the avatar of */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload of the `uploadAvatar` mutation */
+export type UploadAvatarPayload = {
+  __typename?: 'UploadAvatarPayload';
+  /** The `mxc://` URL of the uploaded avatar */
+  avatarUrl?: Maybe<Scalars['String']['output']>;
+  /** Status of the operation */
+  status: UploadAvatarStatus;
+  /** The user that was updated */
+  user?: Maybe<User>;
+};
+
+/** The status of the `uploadAvatar` mutation */
+export type UploadAvatarStatus =
+  /** The file is not a supported image */
+  | 'INVALID'
+  /** The file is too large */
+  | 'TOO_LARGE'
+  /** The avatar was uploaded and set */
+  | 'UPLOADED';
+
 export type UpstreamOAuth2Link = CreationEvent & Node & {
   __typename?: 'UpstreamOAuth2Link';
   /** When the object was created. */
@@ -1153,6 +1926,13 @@ export type UpstreamOAuth2Provider = CreationEvent & Node & {
   clientId: Scalars['String']['output'];
   /** When the object was created. */
   createdAt: Scalars['DateTime']['output'];
+  /**
+   * The result of the last health checks of this provider, or null if it
+   * was never checked.
+   *
+   * This is only available to administrators.
+   */
+  health?: Maybe<UpstreamOAuth2ProviderHealth>;
   /** ID of the object. */
   id: Scalars['ID']['output'];
   /** OpenID Connect issuer URL. */
@@ -1180,6 +1960,38 @@ export type UpstreamOAuth2ProviderEdge = {
   node: UpstreamOAuth2Provider;
 };
 
+/**
+ * The result of the periodic health checks of an upstream OAuth 2.0
+ * provider.
+ */
+export type UpstreamOAuth2ProviderHealth = {
+  __typename?: 'UpstreamOAuth2ProviderHealth';
+  /** When the provider was last checked. */
+  checkedAt: Scalars['DateTime']['output'];
+  /** How many checks failed since the last successful one. */
+  consecutiveFailures: Scalars['Int']['output'];
+  /** The error of the last check, if it failed. */
+  lastError?: Maybe<Scalars['String']['output']>;
+  /** When the provider was last successfully checked. */
+  lastSuccessAt?: Maybe<Scalars['DateTime']['output']>;
+  /** The status of the provider. */
+  status: UpstreamOAuth2ProviderStatus;
+};
+
+/** The status of an upstream OAuth 2.0 provider. */
+export type UpstreamOAuth2ProviderStatus =
+  /** Some checks failed, but not enough yet to consider the provider down. */
+  | 'DEGRADED'
+  /**
+   * Enough checks failed in a row for the provider to be considered down.
+   * Users can't log in with it until it is back up.
+   */
+  | 'DOWN'
+  /** The provider wasn't checked recently. */
+  | 'UNKNOWN'
+  /** The last check succeeded. */
+  | 'UP';
+
 /** A user is an individual's account. */
 export type User = Node & {
   __typename?: 'User';
@@ -1204,12 +2016,22 @@ export type User = Node & {
   id: Scalars['ID']['output'];
   /** When the user was locked out. */
   lockedAt?: Maybe<Scalars['DateTime']['output']>;
+  /**
+   * Get the recent login attempts of the user, successful or not,
+   * chronologically sorted. Attempts are only kept for a limited time.
+   */
+  loginHistory: LoginAttemptConnection;
   /** Access to the user's Matrix account information. */
   matrix: MatrixUser;
   /** Get the list of OAuth 2.0 sessions, chronologically sorted */
   oauth2Sessions: Oauth2SessionConnection;
   /** Primary email address of the user. */
   primaryEmail?: Maybe<UserEmail>;
+  /**
+   * An overview of the security of the account, to render a security
+   * checkup page.
+   */
+  securityCheckup: SecurityCheckup;
   /** Get the list of upstream OAuth 2.0 links */
   upstreamOauth2Links: UpstreamOAuth2LinkConnection;
   /** Username chosen by the user. */
@@ -1272,6 +2094,15 @@ export type UserEmailsArgs = {
 };
 
 
+/** A user is an individual's account. */
+export type UserLoginHistoryArgs = {
+  after?: InputMaybe<Scalars['String']['input']>;
+  before?: InputMaybe<Scalars['String']['input']>;
+  first?: InputMaybe<Scalars['Int']['input']>;
+  last?: InputMaybe<Scalars['Int']['input']>;
+};
+
+
 /** A user is an individual's account. */
 export type UserOauth2SessionsArgs = {
   after?: InputMaybe<Scalars['String']['input']>;
@@ -1295,6 +2126,8 @@ export type UserUpstreamOauth2LinksArgs = {
 /** A parsed user agent string */
 export type UserAgent = {
   __typename?: 'UserAgent';
+  /** The kind of application */
+  clientApp: ClientApp;
   /** The device type */
   deviceType: DeviceType;
   /** The device model */
@@ -1406,8 +2239,12 @@ export type VerifyEmailPayload = {
 export type VerifyEmailStatus =
   /** The email address was already verified before */
   | 'ALREADY_VERIFIED'
+  /** The verification code has expired, a new one has to be sent */
+  | 'EXPIRED_CODE'
   /** The verification code is invalid */
   | 'INVALID_CODE'
+  /** Too many codes were submitted recently, try again later */
+  | 'TOO_MANY_ATTEMPTS'
   /** The email address was just verified */
   | 'VERIFIED';
 
@@ -1558,6 +2395,16 @@ export type ResendVerificationEmailMutation = { __typename?: 'Mutation', sendVer
       & { ' $fragmentRefs'?: { 'UserEmail_EmailFragment': UserEmail_EmailFragment } }
     ) } };
 
+export type RecentActivityQueryVariables = Exact<{
+  first?: InputMaybe<Scalars['Int']['input']>;
+  after?: InputMaybe<Scalars['String']['input']>;
+  last?: InputMaybe<Scalars['Int']['input']>;
+  before?: InputMaybe<Scalars['String']['input']>;
+}>;
+
+
+export type RecentActivityQuery = { __typename?: 'Query', viewer: { __typename: 'Anonymous' } | { __typename: 'User', id: string, loginHistory: { __typename?: 'LoginAttemptConnection', totalCount: number, edges: Array<{ __typename?: 'LoginAttemptEdge', cursor: string, node: { __typename?: 'LoginAttempt', id: string, createdAt: string, method: LoginAttemptMethod, succeeded: boolean, ipAddress?: string | null, userAgent?: { __typename?: 'UserAgent', name?: string | null, os?: string | null, model?: string | null, deviceType: DeviceType } | null } }>, pageInfo: { __typename?: 'PageInfo', hasNextPage: boolean, hasPreviousPage: boolean, startCursor?: string | null, endCursor?: string | null } } } };
+
 export type UserProfileQueryVariables = Exact<{ [key: string]: never; }>;
 
 
@@ -1709,7 +2556,7 @@ export type AllowCrossSigningResetMutationVariables = Exact<{
 }>;
 
 
-export type AllowCrossSigningResetMutation = { __typename?: 'Mutation', allowUserCrossSigningReset: { __typename?: 'AllowUserCrossSigningResetPayload', user?: { __typename?: 'User', id: string } | null } };
+export type AllowCrossSigningResetMutation = { __typename?: 'Mutation', allowUserCrossSigningReset: { __typename?: 'AllowUserCrossSigningResetPayload', status: AllowUserCrossSigningResetStatus, user?: { __typename?: 'User', id: string } | null } };
 
 export class TypedDocumentString<TResult, TVariables>
   extends String
@@ -2115,6 +2962,41 @@ export const ResendVerificationEmailDocument = new TypedDocumentString(`
   email
   confirmedAt
 }`) as unknown as TypedDocumentString<ResendVerificationEmailMutation, ResendVerificationEmailMutationVariables>;
+export const RecentActivityDocument = new TypedDocumentString(`
+    query RecentActivity($first: Int, $after: String, $last: Int, $before: String) {
+  viewer {
+    __typename
+    ... on User {
+      id
+      loginHistory(first: $first, after: $after, last: $last, before: $before) {
+        totalCount
+        edges {
+          cursor
+          node {
+            id
+            createdAt
+            method
+            succeeded
+            ipAddress
+            userAgent {
+              name
+              os
+              model
+              deviceType
+            }
+          }
+        }
+        pageInfo {
+          hasNextPage
+          hasPreviousPage
+          startCursor
+          endCursor
+        }
+      }
+    }
+  }
+}
+    `) as unknown as TypedDocumentString<RecentActivityQuery, RecentActivityQueryVariables>;
 export const UserProfileDocument = new TypedDocumentString(`
     query UserProfile {
   viewer {
@@ -2486,6 +3368,7 @@ export const PasswordRecoveryDocument = new TypedDocumentString(`
 export const AllowCrossSigningResetDocument = new TypedDocumentString(`
     mutation AllowCrossSigningReset($userId: ID!) {
   allowUserCrossSigningReset(input: {userId: $userId}) {
+    status
     user {
       id
     }
@@ -2734,6 +3617,28 @@ export const mockResendVerificationEmailMutation = (resolver: GraphQLResponseRes
     options
   )
 
+/**
+ * @param resolver A function that accepts [resolver arguments](https://mswjs.io/docs/api/graphql#resolver-argument) and must always return the instruction on what to do with the intercepted request. ([see more](https://mswjs.io/docs/concepts/response-resolver#resolver-instructions))
+ * @param options Options object to customize the behavior of the mock. ([see more](https://mswjs.io/docs/api/graphql#handler-options))
+ * @see https://mswjs.io/docs/basics/response-resolver
+ * @example
+ * mockRecentActivityQuery(
+ *   ({ query, variables }) => {
+ *     const { first, after, last, before } = variables;
+ *     return HttpResponse.json({
+ *       data: { viewer }
+ *     })
+ *   },
+ *   requestOptions
+ * )
+ */
+export const mockRecentActivityQuery = (resolver: GraphQLResponseResolver<RecentActivityQuery, RecentActivityQueryVariables>, options?: RequestHandlerOptions) =>
+  graphql.query<RecentActivityQuery, RecentActivityQueryVariables>(
+    'RecentActivity',
+    resolver,
+    options
+  )
+
 /**
  * @param resolver A function that accepts [resolver arguments](https://mswjs.io/docs/api/graphql#resolver-argument) and must always return the instruction on what to do with the intercepted request. ([see more](https://mswjs.io/docs/concepts/response-resolver#resolver-instructions))
  * @param options Options object to customize the behavior of the mock. ([see more](https://mswjs.io/docs/api/graphql#handler-options))
@@ -17,6 +17,7 @@ import { Route as ResetCrossSigningImport } from './routes/reset-cross-signing'
 import { Route as AccountImport } from './routes/_account'
 import { Route as ResetCrossSigningIndexImport } from './routes/reset-cross-signing.index'
 import { Route as AccountIndexImport } from './routes/_account.index'
+import { Route as AccountActivityImport } from './routes/_account.activity'
 import { Route as ResetCrossSigningSuccessImport } from './routes/reset-cross-signing.success'
 import { Route as ResetCrossSigningCancelledImport } from './routes/reset-cross-signing.cancelled'
 import { Route as DevicesSplatImport } from './routes/devices.$'
@@ -61,6 +62,14 @@ const AccountIndexRoute = AccountIndexImport.update({
   import('./routes/_account.index.lazy').then((d) => d.Route),
 )
 
+const AccountActivityRoute = AccountActivityImport.update({
+  id: '/activity',
+  path: '/activity',
+  getParentRoute: () => AccountRoute,
+} as any).lazy(() =>
+  import('./routes/_account.activity.lazy').then((d) => d.Route),
+)
+
 const ResetCrossSigningSuccessRoute = ResetCrossSigningSuccessImport.update({
   id: '/success',
   path: '/success',
@@ -175,6 +184,13 @@ declare module '@tanstack/react-router' {
       preLoaderRoute: typeof DevicesSplatImport
       parentRoute: typeof rootRoute
     }
+    '/_account/activity': {
+      id: '/_account/activity'
+      path: '/activity'
+      fullPath: '/activity'
+      preLoaderRoute: typeof AccountActivityImport
+      parentRoute: typeof AccountImport
+    }
     '/reset-cross-signing/cancelled': {
       id: '/reset-cross-signing/cancelled'
       path: '/cancelled'
@@ -258,6 +274,7 @@ declare module '@tanstack/react-router' {
 // Create and export the route tree
 
 interface AccountRouteChildren {
+  AccountActivityRoute: typeof AccountActivityRoute
   AccountIndexRoute: typeof AccountIndexRoute
   AccountSessionsIdRoute: typeof AccountSessionsIdRoute
   AccountSessionsBrowsersRoute: typeof AccountSessionsBrowsersRoute
@@ -265,6 +282,7 @@ interface AccountRouteChildren {
 }
 
 const AccountRouteChildren: AccountRouteChildren = {
+  AccountActivityRoute: AccountActivityRoute,
   AccountIndexRoute: AccountIndexRoute,
   AccountSessionsIdRoute: AccountSessionsIdRoute,
   AccountSessionsBrowsersRoute: AccountSessionsBrowsersRoute,
@@ -294,6 +312,7 @@ export interface FileRoutesByFullPath {
   '/reset-cross-signing': typeof ResetCrossSigningRouteWithChildren
   '/clients/$id': typeof ClientsIdRoute
   '/devices/$': typeof DevicesSplatRoute
+  '/activity': typeof AccountActivityRoute
   '/reset-cross-signing/cancelled': typeof ResetCrossSigningCancelledRoute
   '/reset-cross-signing/success': typeof ResetCrossSigningSuccessRoute
   '/': typeof AccountIndexRoute
@@ -310,6 +329,7 @@ export interface FileRoutesByFullPath {
 export interface FileRoutesByTo {
   '/clients/$id': typeof ClientsIdRoute
   '/devices/$': typeof DevicesSplatRoute
+  '/activity': typeof AccountActivityRoute
   '/reset-cross-signing/cancelled': typeof ResetCrossSigningCancelledRoute
   '/reset-cross-signing/success': typeof ResetCrossSigningSuccessRoute
   '/': typeof AccountIndexRoute
@@ -329,6 +349,7 @@ export interface FileRoutesById {
   '/reset-cross-signing': typeof ResetCrossSigningRouteWithChildren
   '/clients/$id': typeof ClientsIdRoute
   '/devices/$': typeof DevicesSplatRoute
+  '/_account/activity': typeof AccountActivityRoute
   '/reset-cross-signing/cancelled': typeof ResetCrossSigningCancelledRoute
   '/reset-cross-signing/success': typeof ResetCrossSigningSuccessRoute
   '/_account/': typeof AccountIndexRoute
@@ -349,6 +370,7 @@ export interface FileRouteTypes {
     | '/reset-cross-signing'
     | '/clients/$id'
     | '/devices/$'
+    | '/activity'
     | '/reset-cross-signing/cancelled'
     | '/reset-cross-signing/success'
     | '/'
@@ -364,6 +386,7 @@ export interface FileRouteTypes {
   to:
     | '/clients/$id'
     | '/devices/$'
+    | '/activity'
     | '/reset-cross-signing/cancelled'
     | '/reset-cross-signing/success'
     | '/'
@@ -381,6 +404,7 @@ export interface FileRouteTypes {
     | '/reset-cross-signing'
     | '/clients/$id'
     | '/devices/$'
+    | '/_account/activity'
     | '/reset-cross-signing/cancelled'
     | '/reset-cross-signing/success'
     | '/_account/'
@@ -440,6 +464,7 @@ export const routeTree = rootRoute
     "/_account": {
       "filePath": "_account.tsx",
       "children": [
+        "/_account/activity",
         "/_account/",
         "/_account/sessions/$id",
         "/_account/sessions/browsers",
@@ -460,6 +485,10 @@ export const routeTree = rootRoute
     "/devices/$": {
       "filePath": "devices.$.tsx"
     },
+    "/_account/activity": {
+      "filePath": "_account.activity.tsx",
+      "parent": "/_account"
+    },
     "/reset-cross-signing/cancelled": {
       "filePath": "reset-cross-signing.cancelled.tsx",
       "parent": "/reset-cross-signing"
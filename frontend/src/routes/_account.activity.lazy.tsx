@@ -0,0 +1,131 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+import { useSuspenseQuery } from "@tanstack/react-query";
+import { createLazyFileRoute, notFound } from "@tanstack/react-router";
+import { Badge, H5, Text } from "@vector-im/compound-web";
+import { parseISO } from "date-fns";
+import { useTranslation } from "react-i18next";
+
+import BlockList from "../components/BlockList";
+import { ButtonLink } from "../components/ButtonLink";
+import DateTime from "../components/DateTime";
+import EmptyState from "../components/EmptyState";
+import * as Card from "../components/SessionCard";
+import { usePages } from "../pagination";
+
+import { query } from "./_account.activity";
+
+const PAGE_SIZE = 10;
+
+export const Route = createLazyFileRoute("/_account/activity")({
+  component: RecentActivity,
+});
+
+function RecentActivity(): React.ReactElement {
+  const { t } = useTranslation();
+  const { pagination } = Route.useLoaderDeps();
+
+  const {
+    data: { viewer },
+  } = useSuspenseQuery(query(pagination));
+  if (viewer.__typename !== "User") throw notFound();
+
+  const [backwardPage, forwardPage] = usePages(
+    pagination,
+    viewer.loginHistory.pageInfo,
+    PAGE_SIZE,
+  );
+
+  // We reverse the list as we are paginating backwards
+  const edges = [...viewer.loginHistory.edges].reverse();
+  return (
+    <BlockList>
+      <H5>{t("frontend.recent_activity.heading")}</H5>
+      <Text size="md">{t("frontend.recent_activity.description")}</Text>
+
+      {edges.map(({ cursor, node }) => (
+        <Card.Root key={cursor}>
+          <Card.Body>
+            <Card.Header type={node.userAgent?.deviceType ?? "UNKNOWN"}>
+              <Card.Name
+                name={
+                  node.userAgent?.model ??
+                  node.userAgent?.name ??
+                  t("frontend.session.unknown_browser")
+                }
+              />
+              {node.userAgent?.os && <Card.Client name={node.userAgent.os} />}
+            </Card.Header>
+
+            <Card.Metadata>
+              <Card.Info label={t("frontend.recent_activity.date_label")}>
+                <DateTime datetime={parseISO(node.createdAt)} />
+              </Card.Info>
+
+              <Card.Info label={t("frontend.recent_activity.method_label")}>
+                {node.method === "PASSWORD" &&
+                  t("frontend.recent_activity.method.password")}
+                {node.method === "UPSTREAM_OAUTH2" &&
+                  t("frontend.recent_activity.method.upstream_oauth2")}
+                {node.method === "CLIENT_CERTIFICATE" &&
+                  t("frontend.recent_activity.method.client_certificate")}
+              </Card.Info>
+
+              {node.ipAddress && (
+                <Card.Info label={t("frontend.session.ip_label")}>
+                  <code>{node.ipAddress}</code>
+                </Card.Info>
+              )}
+
+              <Badge
+                kind={node.succeeded ? "green" : "red"}
+                className="self-center"
+              >
+                {node.succeeded
+                  ? t("frontend.recent_activity.succeeded")
+                  : t("frontend.recent_activity.failed")}
+              </Badge>
+            </Card.Metadata>
+          </Card.Body>
+        </Card.Root>
+      ))}
+
+      {viewer.loginHistory.totalCount === 0 && (
+        <EmptyState>{t("frontend.recent_activity.empty")}</EmptyState>
+      )}
+
+      {/* Only show the pagination buttons if there are pages to go to */}
+      {(forwardPage || backwardPage) && (
+        <div className="flex *:flex-1">
+          <ButtonLink
+            kind="secondary"
+            size="sm"
+            disabled={!forwardPage}
+            to="/activity"
+            search={forwardPage || pagination}
+            resetScroll
+          >
+            {t("common.previous")}
+          </ButtonLink>
+
+          {/* Spacer */}
+          <div />
+
+          <ButtonLink
+            kind="secondary"
+            size="sm"
+            disabled={!backwardPage}
+            to="/activity"
+            search={backwardPage || pagination}
+            resetScroll
+          >
+            {t("common.next")}
+          </ButtonLink>
+        </div>
+      )}
+    </BlockList>
+  );
+}
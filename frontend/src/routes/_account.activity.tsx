@@ -0,0 +1,89 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+import { queryOptions } from "@tanstack/react-query";
+import { createFileRoute } from "@tanstack/react-router";
+import { zodSearchValidator } from "@tanstack/router-zod-adapter";
+
+import { graphql } from "../gql";
+import { graphqlRequest } from "../graphql";
+import {
+  type AnyPagination,
+  anyPaginationSchema,
+  normalizePagination,
+} from "../pagination";
+
+const PAGE_SIZE = 10;
+
+const QUERY = graphql(/* GraphQL */ `
+  query RecentActivity(
+    $first: Int
+    $after: String
+    $last: Int
+    $before: String
+  ) {
+    viewer {
+      __typename
+      ... on User {
+        id
+
+        loginHistory(
+          first: $first
+          after: $after
+          last: $last
+          before: $before
+        ) {
+          totalCount
+
+          edges {
+            cursor
+            node {
+              id
+              createdAt
+              method
+              succeeded
+              ipAddress
+              userAgent {
+                name
+                os
+                model
+                deviceType
+              }
+            }
+          }
+
+          pageInfo {
+            hasNextPage
+            hasPreviousPage
+            startCursor
+            endCursor
+          }
+        }
+      }
+    }
+  }
+`);
+
+export const query = (pagination: AnyPagination) =>
+  queryOptions({
+    queryKey: ["recentActivity", pagination],
+    queryFn: ({ signal }) =>
+      graphqlRequest({
+        query: QUERY,
+        variables: pagination,
+        signal,
+      }),
+  });
+
+export const Route = createFileRoute("/_account/activity")({
+  validateSearch: zodSearchValidator(anyPaginationSchema),
+
+  loaderDeps: ({ search }) => ({
+    pagination: normalizePagination(search, PAGE_SIZE, "backward"),
+  }),
+
+  loader: ({ context, deps: { pagination } }) =>
+    context.queryClient.ensureQueryData(query(pagination)),
+});
@@ -49,6 +49,7 @@ function Account(): React.ReactElement {
         <NavBar>
           <NavItem to="/">{t("frontend.nav.settings")}</NavItem>
           <NavItem to="/sessions">{t("frontend.nav.devices")}</NavItem>
+          <NavItem to="/activity">{t("frontend.nav.activity")}</NavItem>
         </NavBar>
       </div>
 
@@ -22,6 +22,7 @@ import {
   VisualList,
   VisualListItem,
 } from "../components/VisualList/VisualList";
+import appConfig from "../config";
 import { graphql } from "../gql";
 import { graphqlRequest } from "../graphql";
 
@@ -64,6 +65,7 @@ declare global {
 const ALLOW_CROSS_SIGING_RESET_MUTATION = graphql(/* GraphQL */ `
   mutation AllowCrossSigningReset($userId: ID!) {
     allowUserCrossSigningReset(input: { userId: $userId }) {
+      status
       user {
         id
       }
@@ -90,7 +92,20 @@ function ResetCrossSigning(): React.ReactNode {
         },
       }),
 
-    onSuccess: () => {
+    onSuccess: (data) => {
+      // Users have to authenticate again before resetting their keys, after
+      // which they are sent back here
+      if (
+        data.allowUserCrossSigningReset.status === "REAUTHENTICATION_REQUIRED"
+      ) {
+        const params = new URLSearchParams({
+          kind: "manage_account",
+          action: "org.matrix.cross_signing_reset",
+        });
+        window.location.assign(`${appConfig.reauthEndpoint}?${params}`);
+        return;
+      }
+
       setTimeout(() => {
         // Synapse may fling the user here via UIA fallback,
         // this is part of the API to signal completion to the calling client
@@ -47,6 +47,7 @@ describe("Reset cross signing", () => {
         return HttpResponse.json({
           data: {
             allowUserCrossSigningReset: {
+              status: "ALLOWED",
               user: {
                 id: "user-id",
               },
@@ -60,6 +60,14 @@ export default defineConfig((env) => ({
         resolve(__dirname, "src/templates.css"),
         resolve(__dirname, "src/swagger.tsx"),
       ],
+
+      // The server caches assets with a hash in their name forever, so make
+      // sure all of them have one
+      output: {
+        assetFileNames: "[name]-[hash][extname]",
+        chunkFileNames: "[name]-[hash].js",
+        entryFileNames: "[name]-[hash].js",
+      },
     },
   },
 
@@ -23,6 +23,17 @@ can_request_admin(user) {
 	user.can_request_admin
 }
 
+# Users can request the helpdesk scope if they can request admin scopes, or
+# if they are in the helpdesk_users list
+can_request_helpdesk(user) {
+	can_request_admin(user)
+}
+
+can_request_helpdesk(user) {
+	some helpdesk_user in data.helpdesk_users
+	user.username == helpdesk_user
+}
+
 interactive_grant_type("authorization_code") = true
 
 interactive_grant_type("urn:ietf:params:oauth:grant-type:device_code") = true
@@ -34,6 +45,9 @@ allowed_scope("openid") = true
 
 allowed_scope("email") = true
 
+# Whether refresh tokens are issued without it is up to the server
+allowed_scope("offline_access") = true
+
 # This grants access to Synapse's admin API endpoints
 allowed_scope("urn:synapse:admin:*") {
 	# Synapse doesn't support user-less tokens yet, so access to the admin API
@@ -59,6 +73,26 @@ allowed_scope("urn:mas:admin") {
 	input.client.id == client
 }
 
+# This grants a restricted subset of the admin scope, which can look up users,
+# set their password and unlock them, in both the GraphQL and the admin API
+allowed_scope("urn:mas:admin:helpdesk") {
+	interactive_grant_type(input.grant_type)
+	can_request_helpdesk(input.user)
+}
+
+allowed_scope("urn:mas:admin:helpdesk") {
+	input.grant_type == "client_credentials"
+	some client in data.helpdesk_clients
+	input.client.id == client
+}
+
+# Clients allowed to get the admin scope can also get the helpdesk scope
+allowed_scope("urn:mas:admin:helpdesk") {
+	input.grant_type == "client_credentials"
+	some client in data.admin_clients
+	input.client.id == client
+}
+
 allowed_scope(scope) {
 	# Grant access to the C-S API only if there is a user
 	interactive_grant_type(input.grant_type)
@@ -76,7 +110,144 @@ violation[{"msg": msg}] {
 	msg := sprintf("scope '%s' not allowed", [scope])
 }
 
+# Restricted scopes can only be granted to the clients and users allowed in
+# data.restricted_scopes, which maps scopes to who can get them:
+#  - `clients`: the IDs of the clients which can get the scope
+#  - `users`: the usernames of the users who can get the scope
+#  - `roles`: the roles of the users who can get the scope, `admin` or
+#    `helpdesk`
+# Leaving out `clients`, or both `users` and `roles`, allows any client, or
+# any user. As there is no user in client_credentials grants, the client has to
+# be listed for them.
+user_has_role(user, "admin") {
+	can_request_admin(user)
+}
+
+user_has_role(user, "helpdesk") {
+	can_request_helpdesk(user)
+}
+
+restricted_scope_client_allowed(restriction) {
+	not restriction.clients
+}
+
+restricted_scope_client_allowed(restriction) {
+	some client_id in restriction.clients
+	input.client.id == client_id
+}
+
+restricted_scope_user_allowed(restriction) {
+	not restriction.users
+	not restriction.roles
+}
+
+restricted_scope_user_allowed(restriction) {
+	some username in restriction.users
+	input.user.username == username
+}
+
+restricted_scope_user_allowed(restriction) {
+	some role in restriction.roles
+	user_has_role(input.user, role)
+}
+
+restricted_scope_allowed(restriction) {
+	interactive_grant_type(input.grant_type)
+	restricted_scope_client_allowed(restriction)
+	restricted_scope_user_allowed(restriction)
+}
+
+restricted_scope_allowed(restriction) {
+	input.grant_type == "client_credentials"
+	some client_id in restriction.clients
+	input.client.id == client_id
+}
+
+violation[{"msg": msg}] {
+	some scope in split(input.scope, " ")
+	restriction := data.restricted_scopes[scope]
+	not restricted_scope_allowed(restriction)
+	msg := sprintf("scope '%s' is restricted", [scope])
+}
+
 violation[{"msg": "only one device scope is allowed at a time"}] {
 	scope_list := split(input.scope, " ")
 	count({key | scope_list[key]; startswith(scope_list[key], "urn:matrix:org.matrix.msc2967.client:device:")}) > 1
 }
+
+# Access windows restrict when some users or clients can be authorized, like
+# contractor accounts limited to business hours.
+# A window applies to the users and clients it lists, or to everyone if it
+# doesn't list any. If some windows apply, the current time, in the timezone
+# of the user, must be within one of them.
+window_matches_user(window) {
+	not window.users
+}
+
+window_matches_user(window) {
+	some username in window.users
+	input.user.username == username
+}
+
+window_matches_client(window) {
+	not window.clients
+}
+
+window_matches_client(window) {
+	some client_id in window.clients
+	input.client.id == client_id
+}
+
+window_applies(window) {
+	window_matches_user(window)
+	window_matches_client(window)
+}
+
+window_days_match(window) {
+	not window.days
+}
+
+window_days_match(window) {
+	input.time.weekday in window.days
+}
+
+# Parse a "HH:MM" time into minutes since midnight
+minutes(time) := value {
+	parts := split(time, ":")
+	value := (to_number(parts[0]) * 60) + to_number(parts[1])
+}
+
+# Windows ending before they start span over midnight
+in_hours(now, start, end) {
+	start <= end
+	now >= start
+	now < end
+}
+
+in_hours(now, start, end) {
+	start > end
+	now >= start
+}
+
+in_hours(now, start, end) {
+	start > end
+	now < end
+}
+
+window_open(window) {
+	window_days_match(window)
+	now := (input.time.hour * 60) + input.time.minute
+	in_hours(now, minutes(window.start), minutes(window.end))
+}
+
+within_access_window {
+	some window in data.access_windows
+	window_applies(window)
+	window_open(window)
+}
+
+violation[{"msg": "access is not allowed at this time"}] {
+	some window in object.get(data, "access_windows", [])
+	window_applies(window)
+	not within_access_window
+}
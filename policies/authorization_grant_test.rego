@@ -21,6 +21,10 @@ test_standard_scopes {
 		with input.client as client
 		with input.scope as "openid email"
 
+	allow with input.user as user
+		with input.client as client
+		with input.scope as "openid offline_access"
+
 	# Not supported yet
 	not allow with input.user as user
 		with input.client as client
@@ -155,3 +159,163 @@ test_mas_scopes {
 		with input.grant_type as "authorization_code"
 		with input.scope as "urn:mas:admin"
 }
+
+test_helpdesk_scope {
+	allow with input.user as user
+		with input.client as client
+		with data.helpdesk_users as ["john"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin:helpdesk"
+
+	# Admins can also request the helpdesk scope
+	allow with input.user as user
+		with input.client as client
+		with data.admin_users as ["john"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin:helpdesk"
+
+	# Helpdesk users can't request the full admin scope
+	not allow with input.user as user
+		with input.client as client
+		with data.admin_users as []
+		with data.helpdesk_users as ["john"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin"
+
+	not allow with input.user as user
+		with input.client as client
+		with data.helpdesk_users as []
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin:helpdesk"
+
+	allow with input.client as {"id": "client"}
+		with data.helpdesk_clients as ["client"]
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin:helpdesk"
+
+	not allow with input.client as {"id": "client"}
+		with data.helpdesk_clients as ["client"]
+		with data.admin_clients as []
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin"
+}
+
+contractor := {"username": "contractor"}
+
+access_windows := [{
+	"users": ["contractor"],
+	"days": ["monday", "tuesday", "wednesday", "thursday", "friday"],
+	"start": "09:00",
+	"end": "18:00",
+}]
+
+monday_morning := {"timezone": "Europe/Paris", "weekday": "monday", "hour": 9, "minute": 30}
+
+monday_night := {"timezone": "Europe/Paris", "weekday": "monday", "hour": 22, "minute": 0}
+
+saturday_morning := {"timezone": "Europe/Paris", "weekday": "saturday", "hour": 9, "minute": 30}
+
+test_access_windows {
+	allow with input.user as contractor
+		with input.client as client
+		with input.scope as "openid"
+		with input.time as monday_morning
+		with data.access_windows as access_windows
+
+	not allow with input.user as contractor
+		with input.client as client
+		with input.scope as "openid"
+		with input.time as monday_night
+		with data.access_windows as access_windows
+
+	not allow with input.user as contractor
+		with input.client as client
+		with input.scope as "openid"
+		with input.time as saturday_morning
+		with data.access_windows as access_windows
+
+	# Other users are not restricted
+	allow with input.user as user
+		with input.client as client
+		with input.scope as "openid"
+		with input.time as monday_night
+		with data.access_windows as access_windows
+}
+
+test_access_windows_clients {
+	kiosk := {"id": "kiosk"}
+	windows := [{"clients": ["kiosk"], "start": "22:00", "end": "06:00"}]
+
+	# Windows can span over midnight
+	allow with input.user as user
+		with input.client as kiosk
+		with input.scope as "openid"
+		with input.time as monday_night
+		with data.access_windows as windows
+
+	not allow with input.user as user
+		with input.client as kiosk
+		with input.scope as "openid"
+		with input.time as monday_morning
+		with data.access_windows as windows
+
+	allow with input.user as user
+		with input.client as client
+		with input.scope as "openid"
+		with input.time as monday_morning
+		with data.access_windows as windows
+}
+
+test_restricted_scopes {
+	admin_client := {"id": "admin-client"}
+	restricted := {"urn:synapse:admin:*": {"clients": ["admin-client"], "roles": ["admin"]}}
+
+	allow with input.user as user
+		with input.user.can_request_admin as true
+		with input.client as admin_client
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:synapse:admin:*"
+		with data.restricted_scopes as restricted
+
+	# The client is not allowed
+	not allow with input.user as user
+		with input.user.can_request_admin as true
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:synapse:admin:*"
+		with data.restricted_scopes as restricted
+
+	# The user doesn't have the role
+	not allow with input.user as user
+		with data.helpdesk_users as ["john"]
+		with input.client as admin_client
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:synapse:admin:*"
+		with data.restricted_scopes as restricted
+
+	# Users can be allowed by name
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+		with data.restricted_scopes as {"openid": {"users": ["john"]}}
+
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+		with data.restricted_scopes as {"openid": {"users": ["jane"]}}
+
+	# Client credentials grants need the client to be listed
+	allow with input.client as admin_client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin"
+		with data.admin_clients as ["admin-client"]
+		with data.restricted_scopes as {"urn:mas:admin": {"clients": ["admin-client"]}}
+
+	not allow with input.client as admin_client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin"
+		with data.admin_clients as ["admin-client"]
+		with data.restricted_scopes as {"urn:mas:admin": {"roles": ["admin"]}}
+}
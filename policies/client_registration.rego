@@ -13,7 +13,7 @@ allow {
 
 parse_uri(url) = obj {
 	is_string(url)
-	[matches] := regex.find_all_string_submatch_n("^(?P<scheme>[a-z][a-z0-9+.-]*):(?://(?P<host>((?:(?:[a-z0-9]|[a-z0-9][a-z0-9-]*[a-z0-9])\\.)*(?:[a-z0-9]|[a-z0-9][a-z0-9-]*[a-z0-9])|127.0.0.1|0.0.0.0|\\[::1\\])(?::(?P<port>[0-9]+))?))?(?P<path>/[A-Za-z0-9/.-]*)$", url, 1)
+	[matches] := regex.find_all_string_submatch_n("^(?P<scheme>[a-z][a-z0-9+.-]*):(?://(?P<host>((?:\\*\\.)?(?:(?:[a-z0-9]|[a-z0-9][a-z0-9-]*[a-z0-9])\\.)*(?:[a-z0-9]|[a-z0-9][a-z0-9-]*[a-z0-9])|127.0.0.1|0.0.0.0|\\[::1\\])(?::(?P<port>[0-9]+))?))?(?P<path>/[A-Za-z0-9/.-]*)$", url, 1)
 	obj := {"scheme": matches[1], "authority": matches[2], "host": matches[3], "port": matches[4], "path": matches[5]}
 }
 
@@ -217,3 +217,29 @@ violation[{"msg": "invalid redirect_uri", "redirect_uri": redirect_uri}] {
 	some redirect_uri in input.client_metadata.redirect_uris
 	not valid_redirect_uri(redirect_uri)
 }
+
+# Wildcard redirect URIs, like https://*.staging.example.com/callback, are only
+# allowed under the domains explicitly listed in the policy data
+is_wildcard_uri(x) {
+	url := parse_uri(x)
+	startswith(url.host, "*.")
+}
+
+allowed_wildcard_uri(x) {
+	url := parse_uri(x)
+	url.scheme == "https"
+
+	domain := substring(url.host, 2, -1)
+
+	# The wildcard must not cover a whole top-level domain
+	count(split(domain, ".")) >= 2
+
+	some allowed in data.client_registration.allowed_wildcard_redirect_uri_domains
+	is_subdomain(allowed, domain)
+}
+
+violation[{"msg": "wildcard redirect_uri not allowed", "redirect_uri": redirect_uri}] {
+	some redirect_uri in input.client_metadata.redirect_uris
+	is_wildcard_uri(redirect_uri)
+	not allowed_wildcard_uri(redirect_uri)
+}
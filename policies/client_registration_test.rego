@@ -364,3 +364,54 @@ test_reverse_dns_match {
 	not reverse_dns_match("example.com", "org.example")
 	not reverse_dns_match("test.com", "com.example")
 }
+
+test_wildcard_redirect_uri {
+	# Wildcards are not allowed by default
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://*.staging.example.com/callback"],
+	}
+
+	# Allowed under the domains listed in the config
+	allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://*.staging.example.com/callback"],
+	}
+		with data.client_registration.allowed_wildcard_redirect_uri_domains as ["staging.example.com"]
+
+	# Not allowed under other domains
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://*.example.com/callback"],
+	}
+		with data.client_registration.allowed_wildcard_redirect_uri_domains as ["staging.example.com"]
+
+	# Wildcard URIs must use https
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://*.staging.example.com/callback"],
+	}
+		with data.client_registration.allowed_wildcard_redirect_uri_domains as ["staging.example.com"]
+		with data.client_registration.allow_insecure_uris as true
+
+	# Wildcards can't cover a whole top-level domain
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://*.com/callback"],
+	}
+		with data.client_registration.allowed_wildcard_redirect_uri_domains as ["com"]
+		with data.client_registration.allow_host_mismatch as true
+
+	# Wildcards must still be on the same host as the client_uri
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://*.staging.example.org/callback"],
+	}
+		with data.client_registration.allowed_wildcard_redirect_uri_domains as ["staging.example.org"]
+}